@@ -97,6 +97,9 @@ IRQ 12
 IRQ 13
 IRQ 14
 IRQ 15
+IRQ 16
+IRQ 17
+IRQ 31
 
 .macro STORE_REGS
     push fs
@@ -97,6 +97,9 @@ IRQ 12
 IRQ 13
 IRQ 14
 IRQ 15
+IRQ 16
+IRQ 17
+IRQ 31
 
 .macro STORE_REGS
     push fs
@@ -149,12 +152,22 @@ IRQ 15
 .type idle_task, @function
 
 int_common:
+	# If interrupting userspace, swap to the kernel's `gs` base, which points to the core-local
+	# storage
+	test qword ptr [rsp + 24], 3
+	jz 1f
+	swapgs
+1:
 STORE_REGS
 	cld
 	mov rdi, rsp
 	call interrupt_handler
 LOAD_REGS
 	add rsp, 16
+	test qword ptr [rsp + 8], 3
+	jz 1f
+	swapgs
+1:
 	iretq
 
 init_ctx:
@@ -213,10 +226,9 @@ LOAD_REGS
     sysretq
 
 idle_task:
-    # Lazy cleanup
+    # Lazy cleanup. `gs` is not cleared since it would clear the base of the core-local storage
     xor ax, ax
     mov fs, ax
-    mov gs, ax
 0:
     sti
     hlt
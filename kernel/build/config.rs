@@ -40,6 +40,14 @@ struct ConfigDebug {
 	///
 	/// **Warning**: this options slows down the system significantly.
 	malloc_check: bool,
+	/// If enabled, the kernel surrounds memory allocations with redzones and fills freed memory
+	/// with a poison pattern.
+	#[serde(default)]
+	malloc_poison: bool,
+	/// If enabled, the kernel panics when a corrupted redzone is detected. Else, it only logs
+	/// the corruption.
+	#[serde(default)]
+	malloc_poison_panic: bool,
 }
 
 /// The compilation configuration.
@@ -82,6 +90,12 @@ impl Config {
 			if self.debug.malloc_check {
 				println!("cargo:rustc-cfg=config_debug_malloc_check");
 			}
+			if self.debug.malloc_poison {
+				println!("cargo:rustc-cfg=config_debug_malloc_poison");
+			}
+			if self.debug.malloc_poison_panic {
+				println!("cargo:rustc-cfg=config_debug_malloc_poison_panic");
+			}
 		}
 	}
 }
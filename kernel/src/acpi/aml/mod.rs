@@ -209,7 +209,12 @@ macro_rules! impl_aml_parseable_primitive {
 
 				let mut n: $type = Default::default();
 				unsafe {
-					core::ptr::copy_nonoverlapping(&b[0], (&mut n) as *mut _ as *mut u8, len);
+					utils::fortify::memcpy_chk(
+						(&mut n) as *mut _ as *mut u8,
+						&b[0],
+						len,
+						size_of::<$type>(),
+					);
 				}
 
 				Ok(Some((n, len)))
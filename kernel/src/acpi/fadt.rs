@@ -18,8 +18,8 @@
 
 //! This module handles ACPI's Fixed ACPI Description Table (FADT).
 
-use super::{Table, TableHdr, dsdt::Dsdt};
-use core::{ptr, slice};
+use super::{Table, TableHdr, dsdt::Dsdt, get_phys};
+use core::slice;
 
 /// TODO doc
 pub struct GenericAddr {
@@ -107,20 +107,18 @@ impl Fadt {
 		} else {
 			self.dsdt as _
 		};
-		let dsdt: *const TableHdr = ptr::with_exposed_provenance(dsdt as _);
-		if !dsdt.is_null() {
-			let dsdt = unsafe {
-				let len = (*dsdt).length as usize;
-				let dsdt_slice = slice::from_raw_parts(dsdt as *const u8, len);
-				&*(dsdt_slice as *const [_] as *const [()] as *const Dsdt)
-			};
-			if !dsdt.hdr().check::<Dsdt>() {
-				panic!("Invalid ACPI structure!");
-			}
-			Some(dsdt)
-		} else {
-			None
+		if dsdt == 0 {
+			return None;
+		}
+		let dsdt = unsafe {
+			let hdr: &TableHdr = get_phys(dsdt as _)?;
+			let dsdt_slice = slice::from_raw_parts(hdr as *const _ as *const u8, hdr.length as _);
+			&*(dsdt_slice as *const [_] as *const [()] as *const Dsdt)
+		};
+		if !dsdt.hdr().check::<Dsdt>() {
+			panic!("Invalid ACPI structure!");
 		}
+		Some(dsdt)
 	}
 }
 
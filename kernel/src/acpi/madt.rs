@@ -56,6 +56,9 @@ impl Table for Madt {
 	const SIGNATURE: &'static [u8; 4] = b"APIC";
 }
 
+/// [`LocalApic`] flag: the processor is enabled.
+pub const LOCAL_APIC_ENABLED: u32 = 0b1;
+
 /// Represents an MADT entry header.
 #[repr(C)]
 #[derive(Debug)]
@@ -66,6 +69,25 @@ pub struct EntryHeader {
 	pub length: u8,
 }
 
+/// MADT entry describing a processor and its Local APIC.
+#[repr(C)]
+#[derive(Debug)]
+pub struct LocalApic {
+	/// The entry's header.
+	pub header: EntryHeader,
+	/// The ACPI ID of the processor.
+	pub processor_id: u8,
+	/// The ID of the processor's Local APIC.
+	pub apic_id: u8,
+	/// Processor flags.
+	pub flags: u32,
+}
+
+impl LocalApic {
+	/// The type of the entry.
+	pub const TYPE: u8 = 0;
+}
+
 /// Iterator over MADT entries.
 pub struct EntriesIterator<'m> {
 	madt: &'m Madt,
@@ -80,7 +102,7 @@ impl<'m> Iterator for EntriesIterator<'m> {
 		let entries_len = self.madt.header.length as usize - ENTRIES_OFF;
 		if likely(self.cursor < entries_len) {
 			let entry = unsafe {
-				let ptr = (self as *const _ as *const c_void).add(ENTRIES_OFF + self.cursor)
+				let ptr = (self.madt as *const _ as *const c_void).add(ENTRIES_OFF + self.cursor)
 					as *const EntryHeader;
 				&*ptr
 			};
@@ -24,16 +24,21 @@
 //!   available tables.
 //! - TODO
 
-use crate::{acpi::rsdt::Rsdt, memory};
+use crate::{
+	acpi::rsdt::Rsdt,
+	memory,
+	memory::{PhysAddr, memmap::PHYS_MAP},
+};
 use core::{
 	hint::{likely, unlikely},
 	mem::{align_of, size_of},
-	ptr, slice,
+	slice,
 	sync::{atomic, atomic::AtomicBool},
 };
 use dsdt::Dsdt;
 use fadt::Fadt;
-use madt::Madt;
+use madt::{LocalApic, Madt};
+use utils::{collections::vec::Vec, errno::AllocResult, limits::PAGE_SIZE};
 
 mod aml;
 mod dsdt;
@@ -46,6 +51,22 @@ mod rsdt;
 /// The signature of the RSDP.
 const RSDP_SIGNATURE: &[u8] = b"RSD PTR ";
 
+/// Returns a reference to the object of type `T` at the physical address `addr`.
+///
+/// If the object is not in the kernel's mapping of physical memory, the function returns `None`.
+///
+/// # Safety
+///
+/// The memory at `addr` must contain a valid instance of `T`.
+unsafe fn get_phys<T>(addr: usize) -> Option<&'static T> {
+	let end = addr.checked_add(size_of::<T>())?;
+	if unlikely(end > PHYS_MAP.memory_size * PAGE_SIZE) {
+		return None;
+	}
+	let ptr = PhysAddr(addr).kernel_to_virtual()?.as_ptr::<T>();
+	Some(&*ptr)
+}
+
 /// Checks the checksum for `obj`.
 ///
 /// `len` is the size of the object in bytes.
@@ -92,11 +113,13 @@ impl Rsdp {
 
 	/// Returns the [`Rsdt`].
 	///
+	/// If the table is not accessible, the function returns `None`.
+	///
 	/// # Safety
 	///
 	/// This function is safe only if [`check`] returns `true`.
-	pub unsafe fn get_rsdt(&self) -> &Rsdt {
-		&*ptr::with_exposed_provenance(self.rsdt_address as _)
+	pub unsafe fn get_rsdt(&self) -> Option<&'static Rsdt> {
+		get_phys(self.rsdt_address as _)
 	}
 }
 
@@ -170,8 +193,8 @@ pub trait Table {
 
 /// Finds the [`Rsdp`] and returns a reference to it.
 unsafe fn find_rsdp() -> Option<&'static Rsdp> {
-	let begin = (memory::PROCESS_END + 0xe0000).as_ptr();
-	let end = (memory::PROCESS_END + 0xfffff).as_ptr();
+	let begin = (memory::KERNEL_BEGIN + 0xe0000).as_ptr();
+	let end = (memory::KERNEL_BEGIN + 0xfffff).as_ptr();
 	let mut ptr = begin;
 	while ptr < end {
 		let signature_slice = slice::from_raw_parts::<u8>(ptr, RSDP_SIGNATURE.len());
@@ -191,28 +214,43 @@ pub fn is_century_register_present() -> bool {
 	CENTURY_REGISTER.load(atomic::Ordering::Relaxed)
 }
 
-/// Initializes ACPI.
-///
-/// This function must be called only once, at boot.
-pub(crate) fn init() {
-	let rsdp = unsafe { find_rsdp() };
-	let Some(rsdp) = rsdp else {
-		return;
-	};
+/// Returns the [`Rsdt`], if present.
+fn get_rsdt() -> Option<&'static Rsdt> {
+	let rsdp = unsafe { find_rsdp() }?;
 	if unlikely(!rsdp.check()) {
 		panic!("ACPI: invalid RSDP checksum");
 	}
 	// Safe because `check` returned `true`
-	let rsdt = unsafe { rsdp.get_rsdt() };
-	// Read MADT
-	if let Some(madt) = rsdt.get_table::<Madt>() {
-		// Register CPU cores
-		for e in madt.entries() {
-			if e.entry_type == 0 {
-				// TODO Register a new CPU
-			}
+	unsafe { rsdp.get_rsdt() }
+}
+
+/// Returns the Local APIC IDs of the enabled CPU cores, as listed by the MADT.
+///
+/// If the MADT is not present, the function returns an empty list.
+pub fn local_apic_ids() -> AllocResult<Vec<u8>> {
+	let mut ids = Vec::new();
+	let Some(madt) = get_rsdt().and_then(Rsdt::get_table::<Madt>) else {
+		return Ok(ids);
+	};
+	for e in madt.entries() {
+		if e.entry_type != LocalApic::TYPE {
+			continue;
+		}
+		let ent = unsafe { &*(e as *const _ as *const LocalApic) };
+		if ent.flags & madt::LOCAL_APIC_ENABLED != 0 {
+			ids.push(ent.apic_id)?;
 		}
 	}
+	Ok(ids)
+}
+
+/// Initializes ACPI.
+///
+/// This function must be called only once, at boot.
+pub(crate) fn init() {
+	let Some(rsdt) = get_rsdt() else {
+		return;
+	};
 	// Read FADT
 	let fadt = rsdt.get_table::<Fadt>();
 	if let Some(fadt) = fadt {
@@ -18,7 +18,7 @@
 
 //! This module handles ACPI's Root System Description Table (RSDT).
 
-use super::{Table, TableHdr};
+use super::{Table, TableHdr, get_phys};
 use core::{mem::size_of, ptr, ptr::Pointee, slice};
 
 /// The Root System Description Table.
@@ -40,7 +40,7 @@ impl Rsdt {
 			let entries_start = (self as *const Self).add(1) as *const u32;
 			slice::from_raw_parts(entries_start, entries_count)
 				.iter()
-				.map(|p| &*ptr::with_exposed_provenance(*p as usize))
+				.filter_map(|p| get_phys(*p as _))
 		}
 	}
 
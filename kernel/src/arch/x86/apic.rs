@@ -0,0 +1,177 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The Local APIC (Advanced Programmable Interrupt Controller) is the interrupt controller of each
+//! CPU core.
+//!
+//! It is used to send Inter-Processor Interrupts (IPI), which allow cores to start each other and
+//! to exchange messages.
+//!
+//! External interrupts are still received through the PIC, on the bootstrap core only.
+
+use crate::{
+	arch::x86::{cpuid, idt, rdmsr, wrmsr},
+	memory::{PhysAddr, mmio::MMIO},
+	sync::once::OnceInit,
+};
+use core::{
+	hint,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{Acquire, Release},
+	},
+};
+use utils::errno::AllocResult;
+
+/// MSR: APIC base address and state
+const IA32_APIC_BASE: u32 = 0x1b;
+/// `IA32_APIC_BASE` flag: the APIC is enabled
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// Mask of the physical address in `IA32_APIC_BASE`
+const APIC_BASE_ADDR_MASK: u64 = 0xfffff000;
+
+/// Register: Local APIC ID
+const REG_ID: usize = 0x20;
+/// Register: Task Priority
+const REG_TPR: usize = 0x80;
+/// Register: End Of Interrupt
+const REG_EOI: usize = 0xb0;
+/// Register: Spurious Interrupt Vector
+const REG_SVR: usize = 0xf0;
+/// Register: Interrupt Command, low half
+const REG_ICR_LOW: usize = 0x300;
+/// Register: Interrupt Command, high half
+const REG_ICR_HIGH: usize = 0x310;
+
+/// Spurious Interrupt Vector flag: enables the APIC
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// Interrupt Command delivery mode: INIT
+const ICR_INIT: u32 = 0b101 << 8;
+/// Interrupt Command delivery mode: Start-Up
+const ICR_STARTUP: u32 = 0b110 << 8;
+/// Interrupt Command flag: the previous command has not been delivered yet
+const ICR_PENDING: u32 = 1 << 12;
+/// Interrupt Command flag: assert the level of the interrupt
+const ICR_ASSERT: u32 = 1 << 14;
+/// Interrupt Command flag: level-triggered interrupt
+const ICR_LEVEL: u32 = 1 << 15;
+
+/// The interrupt vector of spurious interrupts. The lowest four bits must be set.
+pub const SPURIOUS_VECTOR: u8 = 0x3f;
+
+/// The mapping of the registers of the Local APIC.
+///
+/// The registers are at the same physical address on every core, each core accessing its own.
+static REGS: OnceInit<MMIO> = unsafe { OnceInit::new() };
+/// Tells whether [`REGS`] is initialized.
+static MAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Tells whether the CPU has a Local APIC.
+pub fn is_present() -> bool {
+	cpuid(1, 0, 0, 0).3 & (1 << 9) != 0
+}
+
+/// Reads the register at offset `reg`.
+fn read(reg: usize) -> u32 {
+	unsafe { REGS.as_ptr().add(reg).cast::<u32>().read_volatile() }
+}
+
+/// Writes `val` to the register at offset `reg`.
+fn write(reg: usize, val: u32) {
+	unsafe {
+		REGS.as_ptr().add(reg).cast::<u32>().write_volatile(val);
+	}
+}
+
+/// Maps the registers of the Local APIC, then enables it on the current core.
+///
+/// This function must be called once, on the bootstrap core. If the CPU has no Local APIC, the
+/// function does nothing.
+pub(crate) fn init() -> AllocResult<()> {
+	if !is_present() {
+		return Ok(());
+	}
+	let base = PhysAddr((rdmsr(IA32_APIC_BASE) & APIC_BASE_ADDR_MASK) as _);
+	let regs = MMIO::new(base, 1, false)?;
+	unsafe {
+		OnceInit::init(&REGS, regs);
+	}
+	MAPPED.store(true, Release);
+	init_core();
+	Ok(())
+}
+
+/// Enables the Local APIC of the current core.
+pub(crate) fn init_core() {
+	if !MAPPED.load(Acquire) {
+		return;
+	}
+	let base = rdmsr(IA32_APIC_BASE);
+	wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+	// Accept all interrupts
+	write(REG_TPR, 0);
+	write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Returns the ID of the Local APIC of the current core.
+///
+/// If the Local APIC is not initialized, the function returns `0`.
+pub fn id() -> u8 {
+	if !MAPPED.load(Acquire) {
+		return 0;
+	}
+	(read(REG_ID) >> 24) as _
+}
+
+/// Signals the end of the handling of the current interrupt to the Local APIC.
+pub fn end_of_interrupt() {
+	write(REG_EOI, 0);
+}
+
+/// Sends the interrupt command `cmd` to the core with the APIC ID `dest`, then waits until it
+/// has been delivered.
+fn send(dest: u8, cmd: u32) {
+	// Interrupts are disabled so that the command cannot be overwritten before being sent
+	idt::wrap_disable_interrupts(|| {
+		write(REG_ICR_HIGH, (dest as u32) << 24);
+		write(REG_ICR_LOW, cmd);
+		while read(REG_ICR_LOW) & ICR_PENDING != 0 {
+			hint::spin_loop();
+		}
+	});
+}
+
+/// Sends an interrupt with vector `vector` to the core with the APIC ID `dest`.
+pub fn send_ipi(dest: u8, vector: u8) {
+	send(dest, vector as u32);
+}
+
+/// Sends an INIT IPI to the core with the APIC ID `dest`, resetting it.
+///
+/// The core then waits for a Start-Up IPI.
+pub fn send_init(dest: u8) {
+	send(dest, ICR_INIT | ICR_LEVEL | ICR_ASSERT);
+	send(dest, ICR_INIT | ICR_LEVEL);
+}
+
+/// Sends a Start-Up IPI to the core with the APIC ID `dest`, making it start executing in real
+/// mode at the beginning of the physical page `page`.
+pub fn send_startup(dest: u8, page: u8) {
+	send(dest, ICR_STARTUP | page as u32);
+}
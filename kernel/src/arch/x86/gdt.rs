@@ -23,14 +23,24 @@
 //! handle protection rings and load the Task State Segment (TSS).
 
 use crate::{
-	boot::{GDT_VIRT_ADDR, InitGdt},
+	boot::InitGdt,
 	memory::{PhysAddr, VirtAddr},
+	process::scheduler::{MAX_CPUS, core_id},
+};
+use core::{
+	arch::asm,
+	fmt, mem, ptr,
+	ptr::{addr_of, addr_of_mut},
 };
-use core::{arch::asm, fmt, ptr};
 
-/// The address in physical memory to the beginning of the GDT.
+/// The address in physical memory to the beginning of the GDT set up at boot.
 const PHYS_PTR: PhysAddr = PhysAddr(0x800);
 
+/// The GDT of each core.
+///
+/// Each core has its own GDT, since the TSS and TLS entries are specific to the core.
+static mut GDT: [InitGdt; MAX_CPUS] = unsafe { mem::zeroed() };
+
 /// The offset of the kernel code segment.
 pub const KERNEL_CS: usize = 8;
 /// The offset of the kernel data segment.
@@ -171,16 +181,14 @@ impl fmt::Debug for Entry {
 	}
 }
 
-/// Returns the pointer to the segment at offset `offset`.
+/// Returns the pointer to the segment at offset `offset` in the GDT of the current core.
 ///
 /// # Safety
 ///
 /// The caller must ensure the given `offset` is in bounds of the GDT.
 pub unsafe fn get_segment_ptr(offset: usize) -> *mut Entry {
-	PHYS_PTR
-		.kernel_to_virtual()
-		.unwrap()
-		.as_ptr::<Entry>()
+	addr_of_mut!(GDT[core_id()])
+		.cast::<Entry>()
 		.byte_add(offset)
 }
 
@@ -193,14 +201,25 @@ struct Gdt {
 	addr: VirtAddr,
 }
 
-/// Refreshes the GDT's cache.
+/// Refreshes the GDT's cache on the current core.
 #[inline(always)]
 pub fn flush() {
 	let gdt = Gdt {
 		size: (size_of::<InitGdt>() - 1) as _,
-		addr: GDT_VIRT_ADDR,
+		addr: VirtAddr::from(unsafe { addr_of!(GDT[core_id()]) }),
 	};
 	unsafe {
 		asm!("lgdt [{}]", in(reg) &gdt);
 	}
 }
+
+/// Copies the GDT set up at boot to the GDT of the current core, then loads it.
+///
+/// This function must be called once on each core, after its core-local storage is set up.
+pub(crate) fn init() {
+	unsafe {
+		let boot_gdt = PHYS_PTR.kernel_to_virtual().unwrap().as_ptr::<InitGdt>();
+		GDT[core_id()] = *boot_gdt;
+	}
+	flush();
+}
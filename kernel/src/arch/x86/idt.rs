@@ -23,7 +23,7 @@
 use crate::{
 	arch::{
 		x86,
-		x86::{DEFAULT_FLAGS, cli, gdt, pic, sti},
+		x86::{DEFAULT_FLAGS, apic, cli, gdt, pic, smp, sti},
 	},
 	syscall::syscall_int,
 };
@@ -321,6 +321,9 @@ unsafe extern "C" {
 	fn irq13();
 	fn irq14();
 	fn irq15();
+	fn irq16();
+	fn irq17();
+	fn irq31();
 }
 
 /// The list of IDT entries.
@@ -424,15 +427,30 @@ pub fn init() {
 		IDT_ENTRIES[0x2d] = InterruptDescriptor::new(irq13 as _, 0x8, 0x8e);
 		IDT_ENTRIES[0x2e] = InterruptDescriptor::new(irq14 as _, 0x8, 0x8e);
 		IDT_ENTRIES[0x2f] = InterruptDescriptor::new(irq15 as _, 0x8, 0x8e);
+		// Local APIC
+		IDT_ENTRIES[smp::IPI_RESCHEDULE as usize] =
+			InterruptDescriptor::new(irq16 as _, 0x8, 0x8e);
+		IDT_ENTRIES[smp::IPI_TLB_SHOOTDOWN as usize] =
+			InterruptDescriptor::new(irq17 as _, 0x8, 0x8e);
+		IDT_ENTRIES[apic::SPURIOUS_VECTOR as usize] =
+			InterruptDescriptor::new(irq31 as _, 0x8, 0x8e);
 		// System calls
 		IDT_ENTRIES[SYSCALL_ENTRY] = InterruptDescriptor::new(syscall_int as _, 0x8, 0xee);
-		// Load
-		let idt = InterruptDescriptorTable {
-			size: (size_of::<InterruptDescriptor>() * ENTRIES_COUNT - 1) as u16,
-			offset: addr_of!(IDT_ENTRIES) as _,
-		};
+	}
+	load();
+}
+
+/// Loads the IDT on the current core.
+///
+/// The IDT is shared by all cores. It must have been initialized with [`init`] beforehand.
+pub fn load() {
+	let idt = InterruptDescriptorTable {
+		size: (size_of::<InterruptDescriptor>() * ENTRIES_COUNT - 1) as u16,
+		offset: addr_of!(IDT_ENTRIES) as _,
+	};
+	unsafe {
 		asm!("lidt [{}]", in(reg) &idt);
-		#[cfg(target_arch = "x86_64")]
-		enable_syscall_inst();
 	}
+	#[cfg(target_arch = "x86_64")]
+	enable_syscall_inst();
 }
@@ -18,13 +18,17 @@
 
 //! x86-specific code.
 
+pub mod apic;
 pub mod gdt;
 #[macro_use]
 pub mod idt;
 pub mod io;
 pub mod paging;
 pub mod pic;
+pub mod smp;
 pub mod tss;
+#[cfg(target_arch = "x86_64")]
+pub mod vmx;
 
 use core::arch::asm;
 
@@ -113,6 +117,21 @@ pub fn hlt() {
 	}
 }
 
+/// Returns the value of the Time Stamp Counter of the current core.
+#[inline]
+pub fn rdtsc() -> u64 {
+	let (lo, hi): (u32, u32);
+	unsafe {
+		asm!(
+			"rdtsc",
+			out("eax") lo,
+			out("edx") hi,
+			options(nomem, nostack),
+		);
+	}
+	((hi as u64) << 32) | lo as u64
+}
+
 /// Calls the CPUID instruction.
 #[inline]
 pub fn cpuid(mut eax: u32, mut ebx: u32, mut ecx: u32, mut edx: u32) -> (u32, u32, u32, u32) {
@@ -0,0 +1,409 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Symmetric MultiProcessing (SMP) allows the kernel to run on several CPU cores at once.
+//!
+//! At boot, only the bootstrap core is running. The other cores (Application Processors) are
+//! started by the bootstrap core through the Local APIC. They begin in real mode, executing a
+//! trampoline which switches them to long mode before entering the kernel.
+//!
+//! Cores communicate with Inter-Processor Interrupts (IPI), to request rescheduling or the
+//! invalidation of TLB entries (shootdown).
+//!
+//! Starting other cores is supported only on `x86_64`.
+
+use crate::{
+	arch::x86::apic,
+	event,
+	event::CallbackResult,
+	memory::VirtAddr,
+	process::scheduler::{CpuMask, MAX_CPUS, core_id, iter_cpus, online_cpus},
+};
+use core::{
+	mem::ManuallyDrop,
+	sync::atomic::{AtomicU8, Ordering::Relaxed},
+};
+use utils::errno::AllocResult;
+
+/// IPI: requests the core to tick its scheduler.
+pub const IPI_RESCHEDULE: u8 = 0x30;
+/// IPI: requests the core to invalidate TLB entries.
+pub const IPI_TLB_SHOOTDOWN: u8 = 0x31;
+
+/// The Local APIC ID of each core.
+static APIC_IDS: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(0) }; MAX_CPUS];
+
+/// Sends the interrupt `vector` to the core with ID `core`.
+///
+/// If the core is the current one, or is not online, the function does nothing.
+pub fn send_ipi(core: usize, vector: u8) {
+	if online_cpus() & (1 << core) == 0 || core == core_id() {
+		return;
+	}
+	apic::send_ipi(APIC_IDS[core].load(Relaxed), vector);
+}
+
+/// Sends the interrupt `vector` to every online core, except the current one.
+pub fn send_ipi_others(vector: u8) {
+	let others = online_cpus() & !(1 << core_id());
+	for core in iter_cpus(others) {
+		apic::send_ipi(APIC_IDS[core].load(Relaxed), vector);
+	}
+}
+
+/// Invalidates the range of `pages` pages starting at `addr` in the TLB of the cores in
+/// `targets`, then waits until they are done.
+///
+/// The current core and cores which are not online are ignored. The caller is responsible for
+/// invalidating the range on the current core.
+#[inline]
+pub fn shootdown(targets: CpuMask, addr: VirtAddr, pages: usize) {
+	#[cfg(target_arch = "x86_64")]
+	tlb::shootdown(targets, addr, pages);
+	#[cfg(not(target_arch = "x86_64"))]
+	let _ = (targets, addr, pages);
+}
+
+/// Invalidates the range requested by the current TLB shootdown on the current core, if the core
+/// is targeted.
+///
+/// Cores waiting with interrupts disabled must call this function, since a shootdown cannot
+/// complete until every targeted core has handled it.
+#[inline]
+pub fn handle_shootdown() {
+	#[cfg(target_arch = "x86_64")]
+	tlb::handle_shootdown();
+}
+
+#[cfg(target_arch = "x86_64")]
+mod tlb {
+	use super::{APIC_IDS, IPI_TLB_SHOOTDOWN};
+	use crate::{
+		arch::x86::{apic, idt},
+		memory::{KERNEL_BEGIN, VirtAddr, vmem},
+		process::scheduler::{CpuMask, core_id, iter_cpus, online_cpus},
+	};
+	use core::{
+		hint,
+		sync::atomic::{
+			AtomicBool, AtomicU64, AtomicUsize,
+			Ordering::{Acquire, Relaxed, Release},
+		},
+	};
+	use utils::limits::PAGE_SIZE;
+
+	/// Above this number of userspace pages, a shootdown flushes the whole TLB instead of
+	/// invalidating pages one by one.
+	const FLUSH_THRESHOLD: usize = 64;
+
+	/// Tells whether a TLB shootdown is in progress.
+	static LOCK: AtomicBool = AtomicBool::new(false);
+	/// The beginning of the range to invalidate.
+	static ADDR: AtomicUsize = AtomicUsize::new(0);
+	/// The number of pages in the range to invalidate.
+	static PAGES: AtomicUsize = AtomicUsize::new(0);
+	/// The set of cores which have not yet invalidated the range.
+	static PENDING: AtomicU64 = AtomicU64::new(0);
+
+	pub(super) fn shootdown(targets: CpuMask, addr: VirtAddr, pages: usize) {
+		// Check online cores first, since the ID of the current core is not available early at
+		// boot
+		let targets = targets & online_cpus();
+		if targets == 0 {
+			return;
+		}
+		let targets = targets & !(1 << core_id());
+		if targets == 0 {
+			return;
+		}
+		while LOCK.swap(true, Acquire) {
+			// The core holding the lock may be waiting for this one
+			handle_shootdown();
+			hint::spin_loop();
+		}
+		ADDR.store(addr.0, Relaxed);
+		PAGES.store(pages, Relaxed);
+		PENDING.store(targets, Release);
+		for core in iter_cpus(targets) {
+			apic::send_ipi(APIC_IDS[core].load(Relaxed), IPI_TLB_SHOOTDOWN);
+		}
+		while PENDING.load(Acquire) != 0 {
+			hint::spin_loop();
+		}
+		LOCK.store(false, Release);
+	}
+
+	pub(super) fn handle_shootdown() {
+		// Fast path, without reading the ID of the current core
+		if PENDING.load(Acquire) == 0 {
+			return;
+		}
+		// Disable interrupts so that the shootdown cannot be handled again meanwhile
+		idt::wrap_disable_interrupts(|| {
+			let bit = 1 << core_id();
+			if PENDING.load(Acquire) & bit == 0 {
+				return;
+			}
+			let addr = VirtAddr(ADDR.load(Relaxed));
+			let pages = PAGES.load(Relaxed);
+			// Kernel mappings are global, so they are not flushed along with the TLB
+			if addr < KERNEL_BEGIN && pages > FLUSH_THRESHOLD {
+				vmem::flush_current();
+			} else {
+				for i in 0..pages {
+					vmem::invalidate_page_current(addr + i * PAGE_SIZE);
+				}
+			}
+			PENDING.fetch_and(!bit, Release);
+		});
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+mod boot {
+	use super::APIC_IDS;
+	use crate::{
+		acpi,
+		arch::{
+			x86,
+			x86::{apic, gdt, idt, io::outb, paging, tss},
+		},
+		memory::{PhysAddr, VirtAddr, vmem::KERNEL_VMEM},
+		println,
+		process::{scheduler, scheduler::switch::idle_task},
+	};
+	use core::{
+		arch::global_asm,
+		hint, ptr,
+		ptr::addr_of,
+		sync::atomic::{
+			AtomicUsize,
+			Ordering::{Relaxed, Release},
+		},
+	};
+	use utils::{errno::AllocResult, limits::PAGE_SIZE};
+
+	/// The physical address at which the trampoline is copied. It must be page-aligned and below
+	/// 1 MiB, since the core starts in real mode.
+	const TRAMPOLINE_ADDR: PhysAddr = PhysAddr(0x8000);
+	/// The number of attempts at waiting for a started core to come online, each lasting 1 ms.
+	const START_TIMEOUT: usize = 200;
+
+	/// The physical address of the kernel's page table, loaded by the starting core.
+	static AP_CR3: AtomicUsize = AtomicUsize::new(0);
+	/// The stack of the starting core.
+	static AP_STACK: AtomicUsize = AtomicUsize::new(0);
+	/// The ID of the starting core.
+	static AP_CORE: AtomicUsize = AtomicUsize::new(0);
+
+	unsafe extern "C" {
+		/// The beginning of the trampoline.
+		static ap_trampoline: u8;
+		/// The end of the trampoline.
+		static ap_trampoline_end: u8;
+	}
+
+	// The trampoline is executed from `TRAMPOLINE_ADDR`, so addresses inside it are computed from
+	// there. The identity mapping of `REMAP` is used until jumping to the kernel
+	global_asm!(r#"
+.section .text
+
+.global ap_trampoline
+.global ap_trampoline_end
+
+.code16
+ap_trampoline:
+	cli
+	cld
+	xor ax, ax
+	mov ds, ax
+	lgdt [{TRAMPOLINE} + (ap_gdtr - ap_trampoline)]
+
+	# Enable protected mode
+	mov eax, cr0
+	or eax, 1
+	mov cr0, eax
+
+	# Far jump to 32 bit code segment
+	.byte 0x66, 0xea
+	.long {TRAMPOLINE} + (ap_protected - ap_trampoline)
+	.word 0x18
+
+.code32
+ap_protected:
+	mov ax, 0x10
+	mov ds, ax
+	mov es, ax
+	mov ss, ax
+
+	mov eax, offset REMAP
+	mov cr3, eax
+
+	# Enable PSE and PAE
+	mov eax, cr4
+	or eax, 0x30
+	mov cr4, eax
+
+	# Enable LME
+	mov ecx, 0xc0000080 # EFER
+	rdmsr
+	or eax, 0x901
+	wrmsr
+
+	# Enable paging and write protect
+	mov eax, cr0
+	or eax, 0x80010000
+	mov cr0, eax
+
+	# Far jump to 64 bit code segment
+	.byte 0xea
+	.long {TRAMPOLINE} + (ap_long - ap_trampoline)
+	.word 0x8
+
+.code64
+ap_long:
+	movabs rax, offset ap_entry
+	jmp rax
+
+.align 8
+ap_gdt:
+	.quad 0
+	.quad 0x00af9a000000ffff # 64 bit code
+	.quad 0x00cf92000000ffff # data
+	.quad 0x00cf9a000000ffff # 32 bit code
+ap_gdtr:
+	.word 4 * 8 - 1
+	.long {TRAMPOLINE} + (ap_gdt - ap_trampoline)
+ap_trampoline_end:
+
+ap_entry:
+	# Switch to the kernel's page table, in which the stack is mapped
+	mov rax, [rip + {AP_CR3}]
+	mov cr3, rax
+	mov rsp, [rip + {AP_STACK}]
+	xor rbp, rbp
+	mov rdi, [rip + {AP_CORE}]
+	call {ap_main}
+	# cannot return
+	ud2
+"#,
+		TRAMPOLINE = const TRAMPOLINE_ADDR.0,
+		AP_CR3 = sym AP_CR3,
+		AP_STACK = sym AP_STACK,
+		AP_CORE = sym AP_CORE,
+		ap_main = sym ap_main,
+	);
+
+	/// Waits for approximately `us` microseconds.
+	fn delay(us: usize) {
+		for _ in 0..us {
+			// Writing to the POST port takes about one microsecond
+			unsafe {
+				outb(0x80, 0);
+			}
+		}
+	}
+
+	/// Entry point of a core in the kernel, after the trampoline.
+	extern "C" fn ap_main(core: usize) -> ! {
+		scheduler::init_core_local(core);
+		KERNEL_VMEM.lock().bind();
+		x86::enable_sse();
+		paging::prepare();
+		gdt::init();
+		tss::init();
+		idt::load();
+		apic::init_core();
+		scheduler::set_online(core);
+		unsafe { idle_task() }
+	}
+
+	/// Starts the core with ID `core`, which has the Local APIC ID `apic_id`.
+	///
+	/// The function returns `false` if the core did not come online in time.
+	fn start(core: usize, apic_id: u8) -> AllocResult<bool> {
+		let stack = scheduler::init_run_queue(core)?;
+		APIC_IDS[core].store(apic_id, Relaxed);
+		AP_STACK.store(stack.as_ptr() as _, Relaxed);
+		AP_CORE.store(core, Release);
+		let page = (TRAMPOLINE_ADDR.0 / PAGE_SIZE) as u8;
+		apic::send_init(apic_id);
+		delay(10000);
+		// The Start-Up IPI is sent twice, since the first one may be missed
+		for _ in 0..2 {
+			apic::send_startup(apic_id, page);
+			delay(200);
+		}
+		for _ in 0..START_TIMEOUT {
+			if scheduler::online_cpus() & (1 << core) != 0 {
+				return Ok(true);
+			}
+			delay(1000);
+			hint::spin_loop();
+		}
+		Ok(false)
+	}
+
+	/// Starts the other cores of the system, as listed by ACPI.
+	pub(super) fn start_cores() -> AllocResult<()> {
+		let bsp = apic::id();
+		APIC_IDS[0].store(bsp, Relaxed);
+		let ids = acpi::local_apic_ids()?;
+		if ids.len() <= 1 {
+			return Ok(());
+		}
+		// Copy the trampoline
+		unsafe {
+			let begin = addr_of!(ap_trampoline);
+			let len = addr_of!(ap_trampoline_end).offset_from(begin) as usize;
+			let dst = TRAMPOLINE_ADDR.kernel_to_virtual().unwrap().as_ptr();
+			ptr::copy_nonoverlapping(begin, dst, len);
+		}
+		let cr3 = VirtAddr::from(KERNEL_VMEM.lock().inner() as *const _)
+			.kernel_to_physical()
+			.unwrap();
+		AP_CR3.store(cr3.0, Relaxed);
+		let others = ids.iter().filter(|id| **id != bsp);
+		for (core, apic_id) in (1..scheduler::MAX_CPUS).zip(others) {
+			if !start(core, *apic_id)? {
+				println!("SMP: the core with APIC ID {apic_id} did not start");
+				break;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Registers IPI handlers, then starts the other cores of the system.
+///
+/// This function must be called once, on the bootstrap core, after the Local APIC is initialized.
+pub(crate) fn init() -> AllocResult<()> {
+	let _ = ManuallyDrop::new(event::register_callback(
+		IPI_TLB_SHOOTDOWN as _,
+		|_, _, _, _| {
+			handle_shootdown();
+			CallbackResult::Continue
+		},
+	)?);
+	if !apic::is_present() {
+		return Ok(());
+	}
+	#[cfg(target_arch = "x86_64")]
+	boot::start_cores()?;
+	Ok(())
+}
@@ -26,8 +26,15 @@
 //! The structure has to be registered into the GDT into the TSS segment, and must be loaded using
 //! instruction `ltr`.
 
-use crate::arch::x86::gdt;
-use core::{arch::asm, mem, ptr::addr_of};
+use crate::{
+	arch::x86::gdt,
+	process::scheduler::{MAX_CPUS, core_id},
+};
+use core::{
+	arch::asm,
+	mem,
+	ptr::{addr_of, addr_of_mut},
+};
 
 /// Task State Segment.
 #[repr(C)]
@@ -85,14 +92,13 @@ pub struct Tss {
 	pub iopb: u16,
 }
 
-/// The Task State Segment.
-#[unsafe(no_mangle)]
-static mut TSS: Tss = unsafe { mem::zeroed() };
+/// The Task State Segment of each core.
+static mut TSS: [Tss; MAX_CPUS] = unsafe { mem::zeroed() };
 
-/// Initializes the TSS.
+/// Initializes the TSS of the current core.
 pub(crate) fn init() {
 	let [gdt_entry_low, gdt_entry_high] = gdt::Entry::new64(
-		addr_of!(TSS) as u64,
+		unsafe { addr_of!(TSS[core_id()]) } as u64,
 		size_of::<Tss>() as u32 - 1,
 		0b10001001,
 		0,
@@ -109,20 +115,26 @@ pub(crate) fn init() {
 	}
 }
 
-/// Sets the kernel stack pointer on the TSS.
+/// Returns the address of the TSS of the current core.
+pub fn current() -> *const Tss {
+	unsafe { addr_of!(TSS[core_id()]) }
+}
+
+/// Sets the kernel stack pointer on the TSS of the current core.
 ///
 /// # Safety
 ///
 /// This function is **not** reentrant.
 pub unsafe fn set_kernel_stack(kernel_stack: *mut u8) {
+	let tss = &mut *addr_of_mut!(TSS[core_id()]);
 	#[cfg(target_arch = "x86")]
 	{
-		TSS.esp0 = kernel_stack as _;
-		TSS.ss0 = gdt::KERNEL_DS as _;
-		TSS.ss = gdt::USER_DS as _;
+		tss.esp0 = kernel_stack as _;
+		tss.ss0 = gdt::KERNEL_DS as _;
+		tss.ss = gdt::USER_DS as _;
 	}
 	#[cfg(target_arch = "x86_64")]
 	{
-		TSS.rsp0 = kernel_stack as _;
+		tss.rsp0 = kernel_stack as _;
 	}
 }
@@ -0,0 +1,644 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Intel VMX (Virtual Machine Extensions), allowing to run hardware-virtualized guests.
+//!
+//! A core enters VMX operation with `vmxon`. Each virtual CPU is then described by a Virtual
+//! Machine Control Structure (VMCS), which is made current on the core with `vmptrld`, then
+//! accessed with `vmread` and `vmwrite`.
+//!
+//! The host state of the VMCS, restored on VM exit, is written before each entry, so that a
+//! virtual CPU can be run on any core.
+
+use crate::{
+	arch::x86::{cpuid, rdmsr, tss, wrmsr},
+	memory::PhysAddr,
+	register_get, register_set,
+};
+use core::arch::asm;
+
+/// MSR: feature control
+const IA32_FEATURE_CONTROL: u32 = 0x3a;
+/// MSR: `SYSENTER` code segment
+pub const IA32_SYSENTER_CS: u32 = 0x174;
+/// MSR: `SYSENTER` stack pointer
+pub const IA32_SYSENTER_ESP: u32 = 0x175;
+/// MSR: `SYSENTER` instruction pointer
+pub const IA32_SYSENTER_EIP: u32 = 0x176;
+/// MSR: basic VMX information
+const IA32_VMX_BASIC: u32 = 0x480;
+/// MSR: bits of CR0 fixed to `1` in VMX operation
+const IA32_VMX_CR0_FIXED0: u32 = 0x486;
+/// MSR: bits of CR0 fixed to `0` in VMX operation, when clear
+const IA32_VMX_CR0_FIXED1: u32 = 0x487;
+/// MSR: bits of CR4 fixed to `1` in VMX operation
+const IA32_VMX_CR4_FIXED0: u32 = 0x488;
+/// MSR: bits of CR4 fixed to `0` in VMX operation, when clear
+const IA32_VMX_CR4_FIXED1: u32 = 0x489;
+/// MSR: allowed secondary processor-based controls
+const IA32_VMX_PROCBASED_CTLS2: u32 = 0x48b;
+/// MSR: EPT and VPID capabilities
+const IA32_VMX_EPT_VPID_CAP: u32 = 0x48c;
+/// MSR: allowed pin-based controls
+const IA32_VMX_TRUE_PINBASED_CTLS: u32 = 0x48d;
+/// MSR: allowed primary processor-based controls
+const IA32_VMX_TRUE_PROCBASED_CTLS: u32 = 0x48e;
+/// MSR: allowed VM-exit controls
+const IA32_VMX_TRUE_EXIT_CTLS: u32 = 0x48f;
+/// MSR: allowed VM-entry controls
+const IA32_VMX_TRUE_ENTRY_CTLS: u32 = 0x490;
+/// MSR: extended feature enable
+pub const IA32_EFER: u32 = 0xc0000080;
+
+/// Feature control: the MSR is locked
+const FEATURE_CONTROL_LOCKED: u64 = 1 << 0;
+/// Feature control: VMX is allowed outside of SMX operation
+const FEATURE_CONTROL_VMXON: u64 = 1 << 2;
+
+/// CR4: VMX enable
+const CR4_VMXE: usize = 1 << 13;
+
+// VMCS fields: guest state
+
+/// VMCS field: guest ES selector. The other segments follow, every `2` encodings, in the order
+/// ES, CS, SS, DS, FS, GS, LDTR and TR.
+pub const GUEST_ES_SELECTOR: u32 = 0x0800;
+/// VMCS field: VMCS link pointer
+pub const VMCS_LINK_POINTER: u32 = 0x2800;
+/// VMCS field: guest `IA32_DEBUGCTL`
+pub const GUEST_IA32_DEBUGCTL: u32 = 0x2802;
+/// VMCS field: guest `IA32_EFER`
+pub const GUEST_IA32_EFER: u32 = 0x2806;
+/// VMCS field: guest ES limit. The other segments follow, as for [`GUEST_ES_SELECTOR`].
+pub const GUEST_ES_LIMIT: u32 = 0x4800;
+/// VMCS field: guest GDTR limit
+pub const GUEST_GDTR_LIMIT: u32 = 0x4810;
+/// VMCS field: guest IDTR limit
+pub const GUEST_IDTR_LIMIT: u32 = 0x4812;
+/// VMCS field: guest ES access rights. The other segments follow, as for
+/// [`GUEST_ES_SELECTOR`].
+pub const GUEST_ES_AR_BYTES: u32 = 0x4814;
+/// VMCS field: guest interruptibility state
+pub const GUEST_INTERRUPTIBILITY_INFO: u32 = 0x4824;
+/// VMCS field: guest activity state
+pub const GUEST_ACTIVITY_STATE: u32 = 0x4826;
+/// VMCS field: guest `IA32_SYSENTER_CS`
+pub const GUEST_SYSENTER_CS: u32 = 0x482a;
+/// VMCS field: guest CR0
+pub const GUEST_CR0: u32 = 0x6800;
+/// VMCS field: guest CR3
+pub const GUEST_CR3: u32 = 0x6802;
+/// VMCS field: guest CR4
+pub const GUEST_CR4: u32 = 0x6804;
+/// VMCS field: guest ES base. The other segments follow, as for [`GUEST_ES_SELECTOR`].
+pub const GUEST_ES_BASE: u32 = 0x6806;
+/// VMCS field: guest GDTR base
+pub const GUEST_GDTR_BASE: u32 = 0x6816;
+/// VMCS field: guest IDTR base
+pub const GUEST_IDTR_BASE: u32 = 0x6818;
+/// VMCS field: guest DR7
+pub const GUEST_DR7: u32 = 0x681a;
+/// VMCS field: guest RSP
+pub const GUEST_RSP: u32 = 0x681c;
+/// VMCS field: guest RIP
+pub const GUEST_RIP: u32 = 0x681e;
+/// VMCS field: guest RFLAGS
+pub const GUEST_RFLAGS: u32 = 0x6820;
+/// VMCS field: guest pending debug exceptions
+pub const GUEST_PENDING_DBG_EXCEPTIONS: u32 = 0x6822;
+/// VMCS field: guest `IA32_SYSENTER_ESP`
+pub const GUEST_SYSENTER_ESP: u32 = 0x6824;
+/// VMCS field: guest `IA32_SYSENTER_EIP`
+pub const GUEST_SYSENTER_EIP: u32 = 0x6826;
+
+// VMCS fields: host state
+
+/// VMCS field: host ES selector
+const HOST_ES_SELECTOR: u32 = 0x0c00;
+/// VMCS field: host CS selector
+const HOST_CS_SELECTOR: u32 = 0x0c02;
+/// VMCS field: host SS selector
+const HOST_SS_SELECTOR: u32 = 0x0c04;
+/// VMCS field: host DS selector
+const HOST_DS_SELECTOR: u32 = 0x0c06;
+/// VMCS field: host FS selector
+const HOST_FS_SELECTOR: u32 = 0x0c08;
+/// VMCS field: host GS selector
+const HOST_GS_SELECTOR: u32 = 0x0c0a;
+/// VMCS field: host TR selector
+const HOST_TR_SELECTOR: u32 = 0x0c0c;
+/// VMCS field: host `IA32_EFER`
+const HOST_IA32_EFER: u32 = 0x2c02;
+/// VMCS field: host `IA32_SYSENTER_CS`
+const HOST_IA32_SYSENTER_CS: u32 = 0x4c00;
+/// VMCS field: host CR0
+const HOST_CR0: u32 = 0x6c00;
+/// VMCS field: host CR3
+const HOST_CR3: u32 = 0x6c02;
+/// VMCS field: host CR4
+const HOST_CR4: u32 = 0x6c04;
+/// VMCS field: host FS base
+const HOST_FS_BASE: u32 = 0x6c06;
+/// VMCS field: host GS base
+const HOST_GS_BASE: u32 = 0x6c08;
+/// VMCS field: host TR base
+const HOST_TR_BASE: u32 = 0x6c0a;
+/// VMCS field: host GDTR base
+const HOST_GDTR_BASE: u32 = 0x6c0c;
+/// VMCS field: host IDTR base
+const HOST_IDTR_BASE: u32 = 0x6c0e;
+/// VMCS field: host `IA32_SYSENTER_ESP`
+const HOST_IA32_SYSENTER_ESP: u32 = 0x6c10;
+/// VMCS field: host `IA32_SYSENTER_EIP`
+const HOST_IA32_SYSENTER_EIP: u32 = 0x6c12;
+/// VMCS field: host RSP
+const HOST_RSP: u32 = 0x6c14;
+/// VMCS field: host RIP
+const HOST_RIP: u32 = 0x6c16;
+
+// VMCS fields: controls
+
+/// VMCS field: EPT pointer
+pub const EPT_POINTER: u32 = 0x201a;
+/// VMCS field: pin-based VM-execution controls
+pub const PIN_BASED_VM_EXEC_CONTROL: u32 = 0x4000;
+/// VMCS field: primary processor-based VM-execution controls
+pub const CPU_BASED_VM_EXEC_CONTROL: u32 = 0x4002;
+/// VMCS field: exception bitmap
+pub const EXCEPTION_BITMAP: u32 = 0x4004;
+/// VMCS field: VM-exit controls
+pub const VM_EXIT_CONTROLS: u32 = 0x400c;
+/// VMCS field: VM-entry controls
+pub const VM_ENTRY_CONTROLS: u32 = 0x4012;
+/// VMCS field: VM-entry interruption information
+pub const VM_ENTRY_INTR_INFO_FIELD: u32 = 0x4016;
+/// VMCS field: VM-entry exception error code
+pub const VM_ENTRY_EXCEPTION_ERROR_CODE: u32 = 0x4018;
+/// VMCS field: secondary processor-based VM-execution controls
+pub const SECONDARY_VM_EXEC_CONTROL: u32 = 0x401e;
+/// VMCS field: CR0 guest/host mask
+pub const CR0_GUEST_HOST_MASK: u32 = 0x6000;
+/// VMCS field: CR4 guest/host mask
+pub const CR4_GUEST_HOST_MASK: u32 = 0x6002;
+/// VMCS field: CR0 read shadow
+pub const CR0_READ_SHADOW: u32 = 0x6004;
+/// VMCS field: CR4 read shadow
+pub const CR4_READ_SHADOW: u32 = 0x6006;
+
+// VMCS fields: exit information
+
+/// VMCS field: guest-physical address
+pub const GUEST_PHYSICAL_ADDRESS: u32 = 0x2400;
+/// VMCS field: VM-instruction error
+pub const VM_INSTRUCTION_ERROR: u32 = 0x4400;
+/// VMCS field: exit reason
+pub const VM_EXIT_REASON: u32 = 0x4402;
+/// VMCS field: VM-exit instruction length
+pub const VM_EXIT_INSTRUCTION_LEN: u32 = 0x440c;
+/// VMCS field: exit qualification
+pub const EXIT_QUALIFICATION: u32 = 0x6400;
+
+/// Pin-based control: external interrupts cause VM exits
+const PIN_EXTERNAL_INTERRUPT_EXITING: u32 = 1 << 0;
+/// Primary processor-based control: `hlt` causes VM exits
+const CPU_HLT_EXITING: u32 = 1 << 7;
+/// Primary processor-based control: I/O instructions cause VM exits
+const CPU_UNCOND_IO_EXITING: u32 = 1 << 24;
+/// Primary processor-based control: activate the secondary controls
+const CPU_ACTIVATE_SECONDARY_CONTROLS: u32 = 1 << 31;
+/// Secondary processor-based control: enable EPT
+const CPU2_ENABLE_EPT: u32 = 1 << 1;
+/// Secondary processor-based control: the guest may run in real mode or without paging
+const CPU2_UNRESTRICTED_GUEST: u32 = 1 << 7;
+/// VM-exit control: the host runs in 64 bit mode
+const EXIT_HOST_ADDR_SPACE_SIZE: u32 = 1 << 9;
+/// VM-exit control: save the guest's `IA32_EFER`
+const EXIT_SAVE_IA32_EFER: u32 = 1 << 20;
+/// VM-exit control: load the host's `IA32_EFER`
+const EXIT_LOAD_IA32_EFER: u32 = 1 << 21;
+/// VM-entry control: the guest runs in IA-32e mode
+pub const ENTRY_IA32E_MODE: u32 = 1 << 9;
+/// VM-entry control: load the guest's `IA32_EFER`
+const ENTRY_LOAD_IA32_EFER: u32 = 1 << 15;
+
+/// EPT capability: `invept` is supported
+const EPT_CAP_INVEPT: u64 = 1 << 20;
+/// EPT capability: all-context `invept` is supported
+const EPT_CAP_INVEPT_ALL_CONTEXT: u64 = 1 << 26;
+
+/// Exit reason: exception or NMI
+pub const EXIT_REASON_EXCEPTION_NMI: u32 = 0;
+/// Exit reason: external interrupt
+pub const EXIT_REASON_EXTERNAL_INTERRUPT: u32 = 1;
+/// Exit reason: triple fault
+pub const EXIT_REASON_TRIPLE_FAULT: u32 = 2;
+/// Exit reason: `cpuid`
+pub const EXIT_REASON_CPUID: u32 = 10;
+/// Exit reason: `hlt`
+pub const EXIT_REASON_HLT: u32 = 12;
+/// Exit reason: control register access
+pub const EXIT_REASON_CR_ACCESS: u32 = 28;
+/// Exit reason: I/O instruction
+pub const EXIT_REASON_IO_INSTRUCTION: u32 = 30;
+/// Exit reason: `rdmsr`
+pub const EXIT_REASON_MSR_READ: u32 = 31;
+/// Exit reason: `wrmsr`
+pub const EXIT_REASON_MSR_WRITE: u32 = 32;
+/// Exit reason: EPT violation
+pub const EXIT_REASON_EPT_VIOLATION: u32 = 48;
+/// Exit reason flag: the VM entry failed
+pub const EXIT_REASON_FAILED_ENTRY: u32 = 1 << 31;
+
+/// The general-purpose registers of a guest, which are not held by the VMCS.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(missing_docs)]
+pub struct GuestRegs {
+	pub rax: u64,
+	pub rbx: u64,
+	pub rcx: u64,
+	pub rdx: u64,
+	pub rsi: u64,
+	pub rdi: u64,
+	pub rbp: u64,
+	pub r8: u64,
+	pub r9: u64,
+	pub r10: u64,
+	pub r11: u64,
+	pub r12: u64,
+	pub r13: u64,
+	pub r14: u64,
+	pub r15: u64,
+	pub cr2: u64,
+}
+
+/// The VMX features of the processor, used to set up a VMCS.
+#[derive(Clone, Copy, Debug)]
+pub struct Caps {
+	/// The revision identifier to write at the beginning of the VMXON region and of VMCSs.
+	pub revision_id: u32,
+	/// Pin-based VM-execution controls.
+	pub pin_based: u32,
+	/// Primary processor-based VM-execution controls.
+	pub cpu_based: u32,
+	/// Secondary processor-based VM-execution controls.
+	pub cpu_based2: u32,
+	/// VM-exit controls.
+	pub exit: u32,
+	/// VM-entry controls, without [`ENTRY_IA32E_MODE`].
+	pub entry: u32,
+	/// Bits of CR0 which must be set in VMX operation.
+	pub cr0_fixed0: u64,
+	/// Bits of CR4 which must be set in VMX operation.
+	pub cr4_fixed0: u64,
+}
+
+/// Returns the value of the controls whose allowed settings are given by `msr`, with the bits of
+/// `wanted` set.
+///
+/// If the processor does not allow the bits of `wanted` to be set, the function returns `None`.
+fn adjust_controls(msr: u32, wanted: u32) -> Option<u32> {
+	let allowed = rdmsr(msr);
+	let val = (wanted | allowed as u32) & (allowed >> 32) as u32;
+	(val & wanted == wanted).then_some(val)
+}
+
+/// Returns the VMX features of the processor.
+///
+/// If the processor does not support VMX with EPT and unrestricted guests, or if VMX has been
+/// disabled by the firmware, the function returns `None`.
+pub fn probe() -> Option<Caps> {
+	let (_, _, ecx, _) = cpuid(1, 0, 0, 0);
+	if ecx & (1 << 5) == 0 {
+		return None;
+	}
+	let feature_control = rdmsr(IA32_FEATURE_CONTROL);
+	if feature_control & FEATURE_CONTROL_LOCKED != 0
+		&& feature_control & FEATURE_CONTROL_VMXON == 0
+	{
+		return None;
+	}
+	let basic = rdmsr(IA32_VMX_BASIC);
+	// The "true" controls are required to disable CR3 accesses exiting
+	if basic & (1 << 55) == 0 {
+		return None;
+	}
+	let cpu_based = adjust_controls(
+		IA32_VMX_TRUE_PROCBASED_CTLS,
+		CPU_HLT_EXITING | CPU_UNCOND_IO_EXITING | CPU_ACTIVATE_SECONDARY_CONTROLS,
+	)?;
+	let cpu_based2 = adjust_controls(
+		IA32_VMX_PROCBASED_CTLS2,
+		CPU2_ENABLE_EPT | CPU2_UNRESTRICTED_GUEST,
+	)?;
+	let ept_cap = rdmsr(IA32_VMX_EPT_VPID_CAP);
+	if ept_cap & (EPT_CAP_INVEPT | EPT_CAP_INVEPT_ALL_CONTEXT)
+		!= EPT_CAP_INVEPT | EPT_CAP_INVEPT_ALL_CONTEXT
+	{
+		return None;
+	}
+	// Protected mode and paging may be disabled in unrestricted guests
+	let pe_pg = (1 << 0) | (1 << 31);
+	Some(Caps {
+		revision_id: basic as u32 & 0x7fffffff,
+		pin_based: adjust_controls(IA32_VMX_TRUE_PINBASED_CTLS, PIN_EXTERNAL_INTERRUPT_EXITING)?,
+		cpu_based,
+		cpu_based2,
+		exit: adjust_controls(
+			IA32_VMX_TRUE_EXIT_CTLS,
+			EXIT_HOST_ADDR_SPACE_SIZE | EXIT_SAVE_IA32_EFER | EXIT_LOAD_IA32_EFER,
+		)?,
+		entry: adjust_controls(IA32_VMX_TRUE_ENTRY_CTLS, ENTRY_LOAD_IA32_EFER)?
+			& !ENTRY_IA32E_MODE,
+		cr0_fixed0: rdmsr(IA32_VMX_CR0_FIXED0) & rdmsr(IA32_VMX_CR0_FIXED1) & !pe_pg,
+		cr4_fixed0: rdmsr(IA32_VMX_CR4_FIXED0) & rdmsr(IA32_VMX_CR4_FIXED1),
+	})
+}
+
+/// Executes an instruction taking a physical address operand, returning `false` if it failed.
+macro_rules! vmx_insn {
+	($insn:literal, $addr:expr) => {{
+		let addr: u64 = $addr.0 as _;
+		let failed: u8;
+		asm!(
+			concat!($insn, " [{}]"),
+			"setna {}",
+			in(reg) &addr,
+			out(reg_byte) failed,
+			options(nostack)
+		);
+		failed == 0
+	}};
+}
+
+/// Makes the current core enter VMX operation, using the VMXON region at `region`.
+///
+/// On success, the function returns the previous values of CR0 and CR4, to be given to [`off`].
+///
+/// # Safety
+///
+/// Interrupts must be disabled until [`off`] is called. The region must be initialized with the
+/// revision identifier, and must not be used by another core at the same time.
+pub unsafe fn on(region: PhysAddr) -> Option<(usize, usize)> {
+	let feature_control = rdmsr(IA32_FEATURE_CONTROL);
+	if feature_control & FEATURE_CONTROL_LOCKED == 0 {
+		wrmsr(
+			IA32_FEATURE_CONTROL,
+			feature_control | FEATURE_CONTROL_LOCKED | FEATURE_CONTROL_VMXON,
+		);
+	}
+	let cr0 = register_get!("cr0");
+	let cr4 = register_get!("cr4");
+	let cr0_fixed0 = rdmsr(IA32_VMX_CR0_FIXED0) as usize;
+	let cr0_fixed1 = rdmsr(IA32_VMX_CR0_FIXED1) as usize;
+	let cr4_fixed0 = rdmsr(IA32_VMX_CR4_FIXED0) as usize;
+	let cr4_fixed1 = rdmsr(IA32_VMX_CR4_FIXED1) as usize;
+	register_set!("cr0", (cr0 | cr0_fixed0) & cr0_fixed1);
+	register_set!("cr4", (cr4 | cr4_fixed0 | CR4_VMXE) & cr4_fixed1);
+	if !vmx_insn!("vmxon", region) {
+		register_set!("cr4", cr4);
+		register_set!("cr0", cr0);
+		return None;
+	}
+	Some((cr0, cr4))
+}
+
+/// Makes the current core leave VMX operation, restoring CR0 and CR4 to `cr`, as returned by
+/// [`on`].
+///
+/// # Safety
+///
+/// The core must be in VMX operation.
+pub unsafe fn off(cr: (usize, usize)) {
+	asm!("vmxoff", options(nostack));
+	register_set!("cr4", cr.1);
+	register_set!("cr0", cr.0);
+}
+
+/// Clears the VMCS at `vmcs`, writing its state to memory, so that it can be made current on any
+/// core.
+///
+/// # Safety
+///
+/// The core must be in VMX operation.
+pub unsafe fn vmclear(vmcs: PhysAddr) -> bool {
+	vmx_insn!("vmclear", vmcs)
+}
+
+/// Makes the VMCS at `vmcs` current on the current core.
+///
+/// # Safety
+///
+/// The core must be in VMX operation, and the VMCS must not be current on another core.
+pub unsafe fn vmptrld(vmcs: PhysAddr) -> bool {
+	vmx_insn!("vmptrld", vmcs)
+}
+
+/// Reads the field `field` of the current VMCS.
+///
+/// # Safety
+///
+/// A VMCS must be current on the core.
+pub unsafe fn vmread(field: u32) -> u64 {
+	let val: u64;
+	asm!("vmread {}, {}", out(reg) val, in(reg) field as u64, options(nostack));
+	val
+}
+
+/// Writes `val` to the field `field` of the current VMCS.
+///
+/// # Safety
+///
+/// A VMCS must be current on the core.
+pub unsafe fn vmwrite(field: u32, val: u64) {
+	asm!("vmwrite {}, {}", in(reg) field as u64, in(reg) val, options(nostack));
+}
+
+/// Invalidates the translations derived from every EPT on the current core.
+///
+/// # Safety
+///
+/// The core must be in VMX operation.
+pub unsafe fn invept_all() {
+	let desc = [0u64; 2];
+	asm!("invept {}, [{}]", in(reg) 2u64, in(reg) &desc, options(nostack));
+}
+
+/// A descriptor table register.
+#[repr(C, packed)]
+#[derive(Default)]
+struct DescTable {
+	/// The size of the table in bytes, minus `1`.
+	limit: u16,
+	/// The address of the table.
+	base: u64,
+}
+
+/// Writes the host state to the current VMCS, then runs the guest until the next VM exit.
+///
+/// `regs` holds the general-purpose registers of the guest, which are loaded before entering the
+/// guest and saved back on exit. The guest is entered with `vmlaunch`, so the VMCS must have been
+/// cleared beforehand.
+///
+/// If the entry failed, the function returns `false`. The reason is given by the
+/// [`VM_INSTRUCTION_ERROR`] field.
+///
+/// # Safety
+///
+/// Interrupts must be disabled. The guest state of the VMCS must be valid.
+pub unsafe fn enter(regs: &mut GuestRegs) -> bool {
+	// Save the state which is not restored on VM exit, or restored with other values
+	let mut gdtr = DescTable::default();
+	let mut idtr = DescTable::default();
+	asm!("sgdt [{}]", in(reg) &mut gdtr, options(nostack));
+	asm!("sidt [{}]", in(reg) &mut idtr, options(nostack));
+	let (cs, ss, ds, es, fs, gs, tr): (u16, u16, u16, u16, u16, u16, u16);
+	asm!(
+		"mov {0:x}, cs",
+		"mov {1:x}, ss",
+		"mov {2:x}, ds",
+		"mov {3:x}, es",
+		"mov {4:x}, fs",
+		"mov {5:x}, gs",
+		"str {6:x}",
+		out(reg) cs,
+		out(reg) ss,
+		out(reg) ds,
+		out(reg) es,
+		out(reg) fs,
+		out(reg) gs,
+		out(reg) tr,
+		options(nomem, nostack)
+	);
+	let fs_base = rdmsr(super::IA32_FS_BASE);
+	let gs_base = rdmsr(super::IA32_GS_BASE);
+	// Host state
+	vmwrite(HOST_CR0, register_get!("cr0") as _);
+	vmwrite(HOST_CR3, register_get!("cr3") as _);
+	vmwrite(HOST_CR4, register_get!("cr4") as _);
+	vmwrite(HOST_CS_SELECTOR, cs as _);
+	vmwrite(HOST_SS_SELECTOR, ss as _);
+	// Data segments are restored below
+	vmwrite(HOST_DS_SELECTOR, 0);
+	vmwrite(HOST_ES_SELECTOR, 0);
+	vmwrite(HOST_FS_SELECTOR, 0);
+	vmwrite(HOST_GS_SELECTOR, 0);
+	vmwrite(HOST_TR_SELECTOR, tr as _);
+	vmwrite(HOST_FS_BASE, fs_base);
+	vmwrite(HOST_GS_BASE, gs_base);
+	vmwrite(HOST_TR_BASE, tss::current() as _);
+	vmwrite(HOST_GDTR_BASE, gdtr.base);
+	vmwrite(HOST_IDTR_BASE, idtr.base);
+	vmwrite(HOST_IA32_SYSENTER_CS, rdmsr(IA32_SYSENTER_CS));
+	vmwrite(HOST_IA32_SYSENTER_ESP, rdmsr(IA32_SYSENTER_ESP));
+	vmwrite(HOST_IA32_SYSENTER_EIP, rdmsr(IA32_SYSENTER_EIP));
+	vmwrite(HOST_IA32_EFER, rdmsr(IA32_EFER));
+	let failed: u64;
+	asm!(
+		"push rbp",
+		"push rbx",
+		"push rdi",
+		// The host resumes right after the entry, with the current stack
+		"mov rax, {host_rsp}",
+		"vmwrite rax, rsp",
+		"mov rax, {host_rip}",
+		"lea rdx, [rip + 2f]",
+		"vmwrite rax, rdx",
+		// Load the guest's registers
+		"mov rax, [rdi + 120]",
+		"mov cr2, rax",
+		"mov rax, [rdi]",
+		"mov rbx, [rdi + 8]",
+		"mov rcx, [rdi + 16]",
+		"mov rdx, [rdi + 24]",
+		"mov rsi, [rdi + 32]",
+		"mov rbp, [rdi + 48]",
+		"mov r8, [rdi + 56]",
+		"mov r9, [rdi + 64]",
+		"mov r10, [rdi + 72]",
+		"mov r11, [rdi + 80]",
+		"mov r12, [rdi + 88]",
+		"mov r13, [rdi + 96]",
+		"mov r14, [rdi + 104]",
+		"mov r15, [rdi + 112]",
+		"mov rdi, [rdi + 40]",
+		"vmlaunch",
+		// The entry failed
+		"pop rdi",
+		"mov rax, 1",
+		"jmp 3f",
+		// VM exit: save the guest's registers
+		"2:",
+		"xchg rdi, [rsp]",
+		"mov [rdi], rax",
+		"mov [rdi + 8], rbx",
+		"mov [rdi + 16], rcx",
+		"mov [rdi + 24], rdx",
+		"mov [rdi + 32], rsi",
+		"mov [rdi + 48], rbp",
+		"mov [rdi + 56], r8",
+		"mov [rdi + 64], r9",
+		"mov [rdi + 72], r10",
+		"mov [rdi + 80], r11",
+		"mov [rdi + 88], r12",
+		"mov [rdi + 96], r13",
+		"mov [rdi + 104], r14",
+		"mov [rdi + 112], r15",
+		"pop rax",
+		"mov [rdi + 40], rax",
+		"mov rax, cr2",
+		"mov [rdi + 120], rax",
+		"xor eax, eax",
+		"3:",
+		"pop rbx",
+		"pop rbp",
+		host_rsp = const HOST_RSP,
+		host_rip = const HOST_RIP,
+		inout("rdi") regs as *mut GuestRegs => _,
+		out("rax") failed,
+		out("rcx") _,
+		out("rdx") _,
+		out("rsi") _,
+		out("r8") _,
+		out("r9") _,
+		out("r10") _,
+		out("r11") _,
+		out("r12") _,
+		out("r13") _,
+		out("r14") _,
+		out("r15") _,
+	);
+	// VM exits set the limits of the descriptor tables to their maximum
+	asm!("lgdt [{}]", in(reg) &gdtr, options(nostack));
+	asm!("lidt [{}]", in(reg) &idtr, options(nostack));
+	// Loading the segments overwrites the bases of FS and GS
+	asm!(
+		"mov ds, {0:x}",
+		"mov es, {1:x}",
+		"mov fs, {2:x}",
+		"mov gs, {3:x}",
+		in(reg) ds,
+		in(reg) es,
+		in(reg) fs,
+		in(reg) gs,
+		options(nostack)
+	);
+	wrmsr(super::IA32_FS_BASE, fs_base);
+	wrmsr(super::IA32_GS_BASE, gs_base);
+	failed == 0
+}
@@ -18,7 +18,13 @@
 
 //! Boot-time kernel command line arguments parsing.
 
-use crate::tty::vga;
+use crate::{
+	console::MAX_CONSOLES,
+	device::storage::verity::VerityTable,
+	net::{ipconfig::IpConfig, netconsole::NetConsoleConfig},
+	profile::MAX_SHIFT,
+	tty::vga,
+};
 use core::{cmp::min, fmt, str};
 use utils::DisplayableStr;
 
@@ -29,6 +35,27 @@ fn parse_nbr(slice: &[u8]) -> Option<u32> {
 	str::from_utf8(slice).ok().and_then(|s| s.parse().ok())
 }
 
+/// Parses the size or address represented by the string in the given slice.
+///
+/// The number may be hexadecimal if prefixed with `0x`, and may be followed by one of the `K`, `M`
+/// or `G` suffixes.
+///
+/// If the slice doesn't contain a valid number, the function returns `None`.
+fn parse_size(slice: &[u8]) -> Option<usize> {
+	let (slice, shift) = match slice.last()? {
+		b'K' | b'k' => (&slice[..slice.len() - 1], 10),
+		b'M' | b'm' => (&slice[..slice.len() - 1], 20),
+		b'G' | b'g' => (&slice[..slice.len() - 1], 30),
+		_ => (slice, 0),
+	};
+	let s = str::from_utf8(slice).ok()?;
+	let n = match s.strip_prefix("0x") {
+		Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+		None => s.parse().ok()?,
+	};
+	n.checked_mul(1 << shift)
+}
+
 /// Structure representing a command line parsing error.
 #[derive(Debug)]
 pub struct ParseError<'s> {
@@ -127,10 +154,30 @@ impl<'s> Iterator for TokenIterator<'s> {
 pub struct ArgsParser<'s> {
 	/// The root device major and minor numbers.
 	root: Option<(u32, u32)>,
+	/// Whether the root filesystem is mounted in read-only.
+	readonly: bool,
 	/// The path to the init binary, if specified.
 	init: Option<&'s [u8]>,
 	/// Whether the kernel boots silently.
 	silent: bool,
+	/// The size of the memory to reserve for the crash kernel, if specified.
+	crashkernel: Option<usize>,
+	/// The physical address of the crashed kernel's ELF core header, if specified.
+	elfcorehdr: Option<usize>,
+	/// The static network configuration, if specified.
+	ip: Option<IpConfig<'s>>,
+	/// The configuration of the network console, if specified.
+	netconsole: Option<NetConsoleConfig<'s>>,
+	/// The parameters of the verity device, if specified.
+	verity: Option<VerityTable<'s>>,
+	/// The shift of the kernel profiler's buckets, if enabled.
+	profile: Option<u32>,
+	/// Whether the duration of each initcall is printed.
+	initcall_debug: bool,
+	/// The names of the selected consoles, in order.
+	consoles: [&'s [u8]; MAX_CONSOLES],
+	/// The number of selected consoles.
+	consoles_count: usize,
 }
 
 impl<'s> ArgsParser<'s> {
@@ -138,8 +185,18 @@ impl<'s> ArgsParser<'s> {
 	pub fn parse(cmdline: &'s [u8]) -> Result<Self, ParseError<'s>> {
 		let mut s = Self {
 			root: None,
+			readonly: false,
 			init: None,
 			silent: false,
+			crashkernel: None,
+			elfcorehdr: None,
+			ip: None,
+			netconsole: None,
+			verity: None,
+			profile: None,
+			initcall_debug: false,
+			consoles: [b""; MAX_CONSOLES],
+			consoles_count: 0,
 		};
 
 		let mut iter = TokenIterator {
@@ -190,8 +247,86 @@ impl<'s> ArgsParser<'s> {
 					s.init = Some(init.s);
 				}
 
+				b"-ro" => s.readonly = true,
+
 				b"-silent" => s.silent = true,
 
+				b"initcall_debug" => s.initcall_debug = true,
+
+				_ if token.s.starts_with(b"ip=") => {
+					s.ip = IpConfig::parse(&token.s[3..]).map_err(|err| ParseError {
+						cmdline,
+						err,
+						token: Some((token.begin, token.s.len())),
+					})?;
+				}
+
+				_ if token.s.starts_with(b"netconsole=") => {
+					let config =
+						NetConsoleConfig::parse(&token.s[11..]).map_err(|err| ParseError {
+							cmdline,
+							err,
+							token: Some((token.begin, token.s.len())),
+						})?;
+					s.netconsole = Some(config);
+				}
+
+				_ if token.s.starts_with(b"verity=") => {
+					let table = VerityTable::parse(&token.s[7..]).map_err(|err| ParseError {
+						cmdline,
+						err,
+						token: Some((token.begin, token.s.len())),
+					})?;
+					s.verity = Some(table);
+				}
+
+				_ if token.s.starts_with(b"console=") => {
+					let name = &token.s[8..];
+					if name.is_empty() || s.consoles_count >= MAX_CONSOLES {
+						return Err(ParseError {
+							cmdline,
+							err: "invalid console",
+							token: Some((token.begin, token.s.len())),
+						});
+					}
+					s.consoles[s.consoles_count] = name;
+					s.consoles_count += 1;
+				}
+
+				_ if token.s.starts_with(b"crashkernel=") => {
+					let Some(size) = parse_size(&token.s[12..]).filter(|size| *size > 0) else {
+						return Err(ParseError {
+							cmdline,
+							err: "invalid crash kernel size",
+							token: Some((token.begin, token.s.len())),
+						});
+					};
+					s.crashkernel = Some(size);
+				}
+
+				_ if token.s.starts_with(b"elfcorehdr=") => {
+					let Some(addr) = parse_size(&token.s[11..]) else {
+						return Err(ParseError {
+							cmdline,
+							err: "invalid ELF core header address",
+							token: Some((token.begin, token.s.len())),
+						});
+					};
+					s.elfcorehdr = Some(addr);
+				}
+
+				_ if token.s.starts_with(b"profile=") => {
+					let shift = parse_nbr(&token.s[8..]).filter(|shift| *shift <= MAX_SHIFT);
+					let Some(shift) = shift else {
+						return Err(ParseError {
+							cmdline,
+							err: "invalid profiler shift",
+							token: Some((token.begin, token.s.len())),
+						});
+					};
+					s.profile = Some(shift);
+				}
+
 				_ => {
 					return Err(ParseError {
 						cmdline,
@@ -210,15 +345,62 @@ impl<'s> ArgsParser<'s> {
 		self.root
 	}
 
+	/// Tells whether the root filesystem is mounted in read-only.
+	pub fn is_root_readonly(&self) -> bool {
+		self.readonly
+	}
+
 	/// Returns the init binary path if specified.
 	pub fn get_init_path(&self) -> Option<&'s [u8]> {
 		self.init
 	}
 
+	/// Returns the static network configuration if specified.
+	pub fn get_ip_config(&self) -> Option<&IpConfig<'s>> {
+		self.ip.as_ref()
+	}
+
+	/// Returns the configuration of the network console if specified.
+	pub fn get_netconsole_config(&self) -> Option<&NetConsoleConfig<'s>> {
+		self.netconsole.as_ref()
+	}
+
+	/// Returns the parameters of the verity device if specified.
+	pub fn get_verity_table(&self) -> Option<&VerityTable<'s>> {
+		self.verity.as_ref()
+	}
+
+	/// Returns the shift of the kernel profiler's buckets if the profiler is enabled.
+	pub fn get_profile_shift(&self) -> Option<u32> {
+		self.profile
+	}
+
+	/// Returns the size of the memory to reserve for the crash kernel, if specified.
+	pub fn get_crashkernel_size(&self) -> Option<usize> {
+		self.crashkernel
+	}
+
+	/// Returns the physical address of the crashed kernel's ELF core header, if specified.
+	///
+	/// When specified, the kernel is a crash kernel started after a panic.
+	pub fn get_elfcorehdr(&self) -> Option<usize> {
+		self.elfcorehdr
+	}
+
+	/// Returns the names of the selected consoles, in order.
+	pub fn get_consoles(&self) -> &[&'s [u8]] {
+		&self.consoles[..self.consoles_count]
+	}
+
 	/// If `true`, the kernel doesn't print logs while booting.
 	pub fn is_silent(&self) -> bool {
 		self.silent
 	}
+
+	/// If `true`, the kernel prints the duration of each initcall.
+	pub fn is_initcall_debug(&self) -> bool {
+		self.initcall_debug
+	}
 }
 
 #[cfg(test)]
@@ -264,4 +446,65 @@ mod test {
 	fn cmdline7() {
 		assert!(ArgsParser::parse(b"-root 1 0 -init bleh -silent").is_ok());
 	}
+
+	#[test_case]
+	fn cmdline8() {
+		assert!(
+			ArgsParser::parse(b"-root 1 0 ip=10.0.0.2::10.0.0.1:255.255.255.0:box:eth0:off")
+				.is_ok()
+		);
+	}
+
+	#[test_case]
+	fn cmdline9() {
+		assert!(ArgsParser::parse(b"-root 1 0 ip=bleh").is_err());
+	}
+
+	#[test_case]
+	fn cmdline10() {
+		assert!(ArgsParser::parse(b"-root 253 0 -ro").is_ok());
+	}
+
+	#[test_case]
+	fn cmdline11() {
+		assert!(ArgsParser::parse(b"-root 253 0 verity=8:1,8:2").is_err());
+	}
+
+	#[test_case]
+	fn cmdline12() {
+		let args = ArgsParser::parse(b"-root 1 0 profile=2").unwrap();
+		assert_eq!(args.get_profile_shift(), Some(2));
+		assert!(ArgsParser::parse(b"-root 1 0 profile=").is_err());
+		assert!(ArgsParser::parse(b"-root 1 0 profile=32").is_err());
+	}
+
+	#[test_case]
+	fn cmdline13() {
+		let args = ArgsParser::parse(b"-root 1 0 initcall_debug").unwrap();
+		assert!(args.is_initcall_debug());
+	}
+
+	#[test_case]
+	fn cmdline14() {
+		let args = ArgsParser::parse(b"-root 1 0 console=ttyS0 console=tty0").unwrap();
+		assert_eq!(args.get_consoles(), &[b"ttyS0".as_slice(), b"tty0"]);
+		assert!(ArgsParser::parse(b"-root 1 0 console=").is_err());
+	}
+
+	#[test_case]
+	fn cmdline15() {
+		let args = ArgsParser::parse(b"-root 1 0 netconsole=@/eth0,@10.0.0.1/").unwrap();
+		assert!(args.get_netconsole_config().is_some());
+		assert!(ArgsParser::parse(b"-root 1 0 netconsole=bleh").is_err());
+	}
+
+	#[test_case]
+	fn cmdline16() {
+		let args = ArgsParser::parse(b"-root 1 0 crashkernel=64M elfcorehdr=0x1000k").unwrap();
+		assert_eq!(args.get_crashkernel_size(), Some(64 << 20));
+		assert_eq!(args.get_elfcorehdr(), Some(0x1000 << 10));
+		assert!(ArgsParser::parse(b"-root 1 0 crashkernel=0").is_err());
+		assert!(ArgsParser::parse(b"-root 1 0 crashkernel=M").is_err());
+		assert!(ArgsParser::parse(b"-root 1 0 elfcorehdr=0xg").is_err());
+	}
 }
@@ -0,0 +1,224 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kernel consoles.
+//!
+//! A console is a sink receiving the kernel's logs and the output of the TTY. Several consoles
+//! can be registered, each being identified by a name (`tty0` for the VGA text mode, `ttyS<n>`
+//! for serial ports, ...).
+//!
+//! Consoles can be selected with the `console=<name>` command line argument, which may be
+//! repeated. Output is then sent to each selected console, in the order in which they appear on
+//! the command line. If no console is selected, output is sent to the default consoles. Some
+//! consoles, such as the network console, receive output regardless of the selection.
+//!
+//! Consoles may be registered at any time, for example by a module. A console registered after
+//! boot receives output as soon as it is registered, if selected.
+
+use crate::{device::serial, sync::mutex::IntMutex, tty};
+use core::hint::unlikely;
+use utils::{errno, errno::EResult};
+
+/// The maximum number of registered or selected consoles.
+pub const MAX_CONSOLES: usize = 8;
+
+/// A sink for the kernel's output.
+pub trait Console: Sync {
+	/// Returns the name of the console.
+	fn name(&self) -> &[u8];
+
+	/// Tells whether the console is enabled when none is selected on the command line.
+	fn is_default(&self) -> bool {
+		false
+	}
+
+	/// Tells whether the console receives output regardless of the selection on the command line.
+	fn is_always_enabled(&self) -> bool {
+		false
+	}
+
+	/// Writes `buf` to the console.
+	fn write(&self, buf: &[u8]);
+}
+
+/// The registry of consoles.
+struct Registry {
+	/// Registered consoles, in order of registration.
+	consoles: [Option<&'static dyn Console>; MAX_CONSOLES],
+	/// The names of the selected consoles, in order.
+	selected: [&'static [u8]; MAX_CONSOLES],
+	/// The number of selected consoles.
+	selected_count: usize,
+}
+
+impl Registry {
+	/// Returns the registered console with the given name.
+	fn get(&self, name: &[u8]) -> Option<&'static dyn Console> {
+		self.consoles
+			.iter()
+			.flatten()
+			.find(|c| c.name() == name)
+			.copied()
+	}
+
+	/// Tells whether output is sent to `console`.
+	fn is_enabled(&self, console: &dyn Console) -> bool {
+		if console.is_always_enabled() {
+			true
+		} else if self.selected_count == 0 {
+			console.is_default()
+		} else {
+			self.selected[..self.selected_count].contains(&console.name())
+		}
+	}
+}
+
+/// The list of consoles.
+///
+/// Built-in consoles are registered statically so that output is available as early as possible.
+static CONSOLES: IntMutex<Registry> = IntMutex::new(Registry {
+	consoles: [
+		Some(&tty::VGA_CONSOLE),
+		Some(&serial::CONSOLES[0]),
+		Some(&serial::CONSOLES[1]),
+		Some(&serial::CONSOLES[2]),
+		Some(&serial::CONSOLES[3]),
+		None,
+		None,
+		None,
+	],
+	selected: [b""; MAX_CONSOLES],
+	selected_count: 0,
+});
+
+/// Selects the consoles to send output to, in order.
+///
+/// Names that do not match any registered console are kept, so that the corresponding consoles
+/// are used once registered.
+///
+/// If `names` is empty, the default consoles are used.
+///
+/// Names beyond [`MAX_CONSOLES`] are ignored.
+pub fn select(names: &[&'static [u8]]) {
+	let mut reg = CONSOLES.lock();
+	let count = names.len().min(MAX_CONSOLES);
+	reg.selected[..count].copy_from_slice(&names[..count]);
+	reg.selected_count = count;
+}
+
+/// Registers a new console.
+///
+/// If a console with the same name is already registered, the function returns
+/// [`errno::EEXIST`]. If there is no room left for a new console, it returns [`errno::ENOSPC`].
+pub fn register(console: &'static dyn Console) -> EResult<()> {
+	let mut reg = CONSOLES.lock();
+	if unlikely(reg.get(console.name()).is_some()) {
+		return Err(errno!(EEXIST));
+	}
+	let slot = reg
+		.consoles
+		.iter_mut()
+		.find(|c| c.is_none())
+		.ok_or_else(|| errno!(ENOSPC))?;
+	*slot = Some(console);
+	Ok(())
+}
+
+/// Unregisters the console with the given name.
+///
+/// If no console with this name is registered, the function returns [`errno::ENOENT`].
+pub fn unregister(name: &[u8]) -> EResult<()> {
+	let mut reg = CONSOLES.lock();
+	let slot = reg
+		.consoles
+		.iter_mut()
+		.find(|c| c.is_some_and(|c| c.name() == name))
+		.ok_or_else(|| errno!(ENOENT))?;
+	*slot = None;
+	Ok(())
+}
+
+/// Calls `f` for each registered console, along with a boolean telling whether it is enabled.
+pub fn for_each<E, F: FnMut(&dyn Console, bool) -> Result<(), E>>(mut f: F) -> Result<(), E> {
+	let reg = CONSOLES.lock();
+	for c in reg.consoles.iter().flatten() {
+		f(*c, reg.is_enabled(*c))?;
+	}
+	Ok(())
+}
+
+/// Writes `buf` to every enabled console.
+pub fn write(buf: &[u8]) {
+	let reg = CONSOLES.lock();
+	if reg.selected_count == 0 {
+		for c in reg
+			.consoles
+			.iter()
+			.flatten()
+			.filter(|c| reg.is_enabled(**c))
+		{
+			c.write(buf);
+		}
+	} else {
+		for name in &reg.selected[..reg.selected_count] {
+			if let Some(c) = reg.get(name) {
+				c.write(buf);
+			}
+		}
+		let selected = &reg.selected[..reg.selected_count];
+		for c in reg.consoles.iter().flatten() {
+			if c.is_always_enabled() && !selected.contains(&c.name()) {
+				c.write(buf);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	struct Dummy;
+
+	impl Console for Dummy {
+		fn name(&self) -> &[u8] {
+			b"dummy"
+		}
+
+		fn write(&self, _buf: &[u8]) {}
+	}
+
+	#[test_case]
+	fn console_register() {
+		static DUMMY: Dummy = Dummy;
+		register(&DUMMY).unwrap();
+		assert!(register(&DUMMY).is_err());
+		let mut found = false;
+		for_each::<(), _>(|c, enabled| {
+			if c.name() == b"dummy" {
+				found = true;
+				assert!(!enabled);
+			}
+			Ok(())
+		})
+		.unwrap();
+		assert!(found);
+		unregister(b"dummy").unwrap();
+		assert!(unregister(b"dummy").is_err());
+	}
+}
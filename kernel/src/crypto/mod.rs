@@ -18,13 +18,17 @@
 
 //! Cryptographic algorithms and tools.
 
+use crate::initcall;
 use utils::errno::AllocResult;
 
 pub mod chacha20;
 pub mod checksum;
 pub mod rand;
+pub mod sha256;
 
 /// Initializes cryptographic features.
-pub(crate) fn init() -> AllocResult<()> {
+fn init() -> AllocResult<()> {
 	rand::init()
 }
+
+initcall!(subsys, crypto, |_| Ok(init()?));
@@ -0,0 +1,194 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Implementation of the SHA-256 hash function, as specified in FIPS 180-4.
+
+/// The size of a digest, in bytes.
+pub const DIGEST_SIZE: usize = 32;
+/// The size of a block, in bytes.
+const BLOCK_SIZE: usize = 64;
+
+/// Round constants.
+const K: [u32; 64] = [
+	0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+	0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+	0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+	0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+	0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+	0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+	0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+	0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+	0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+	0xc67178f2,
+];
+
+/// Initial hash value.
+const H0: [u32; 8] = [
+	0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hasher.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+	/// The current hash value.
+	state: [u32; 8],
+	/// Buffer for the incomplete block.
+	buf: [u8; BLOCK_SIZE],
+	/// The number of bytes in `buf`.
+	buf_len: usize,
+	/// The total number of bytes hashed so far.
+	len: u64,
+}
+
+impl Default for Sha256 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Sha256 {
+	/// Creates a new hasher.
+	pub const fn new() -> Self {
+		Self {
+			state: H0,
+			buf: [0; BLOCK_SIZE],
+			buf_len: 0,
+			len: 0,
+		}
+	}
+
+	/// Processes a single block.
+	fn compress(&mut self, block: &[u8; BLOCK_SIZE]) {
+		let mut w = [0u32; 64];
+		for (w, b) in w.iter_mut().zip(block.chunks_exact(4)) {
+			*w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+		}
+		for i in 16..64 {
+			let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+			let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+			w[i] = w[i - 16]
+				.wrapping_add(s0)
+				.wrapping_add(w[i - 7])
+				.wrapping_add(s1);
+		}
+		let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+		for i in 0..64 {
+			let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+			let ch = (e & f) ^ (!e & g);
+			let t1 = h
+				.wrapping_add(s1)
+				.wrapping_add(ch)
+				.wrapping_add(K[i])
+				.wrapping_add(w[i]);
+			let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+			let maj = (a & b) ^ (a & c) ^ (b & c);
+			let t2 = s0.wrapping_add(maj);
+			h = g;
+			g = f;
+			f = e;
+			e = d.wrapping_add(t1);
+			d = c;
+			c = b;
+			b = a;
+			a = t1.wrapping_add(t2);
+		}
+		for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+			*s = s.wrapping_add(v);
+		}
+	}
+
+	/// Feeds `data` to the hasher.
+	pub fn update(&mut self, mut data: &[u8]) {
+		self.len = self.len.wrapping_add(data.len() as u64);
+		// Complete the pending block
+		if self.buf_len > 0 {
+			let n = (BLOCK_SIZE - self.buf_len).min(data.len());
+			self.buf[self.buf_len..(self.buf_len + n)].copy_from_slice(&data[..n]);
+			self.buf_len += n;
+			data = &data[n..];
+			if self.buf_len < BLOCK_SIZE {
+				return;
+			}
+			let block = self.buf;
+			self.compress(&block);
+			self.buf_len = 0;
+		}
+		let mut blocks = data.chunks_exact(BLOCK_SIZE);
+		for block in &mut blocks {
+			self.compress(block.try_into().unwrap());
+		}
+		let rem = blocks.remainder();
+		self.buf[..rem.len()].copy_from_slice(rem);
+		self.buf_len = rem.len();
+	}
+
+	/// Finishes the computation and returns the digest.
+	pub fn finalize(mut self) -> [u8; DIGEST_SIZE] {
+		let bits = self.len.wrapping_mul(8);
+		// Padding: a `1` bit, then zeros up to the length field
+		self.update(&[0x80]);
+		while self.buf_len != BLOCK_SIZE - 8 {
+			self.update(&[0]);
+		}
+		self.update(&bits.to_be_bytes());
+		let mut digest = [0; DIGEST_SIZE];
+		for (d, s) in digest.chunks_exact_mut(4).zip(self.state) {
+			d.copy_from_slice(&s.to_be_bytes());
+		}
+		digest
+	}
+}
+
+/// Computes the SHA-256 digest of `data`.
+pub fn digest(data: &[u8]) -> [u8; DIGEST_SIZE] {
+	let mut hasher = Sha256::new();
+	hasher.update(data);
+	hasher.finalize()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn sha256_empty() {
+		assert_eq!(
+			digest(b""),
+			[
+				0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99,
+				0x6f, 0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95,
+				0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55
+			]
+		);
+	}
+
+	#[test_case]
+	fn sha256_two_blocks() {
+		let mut hasher = Sha256::new();
+		hasher.update(b"abcdbcdecdefdefgefghfghighij");
+		hasher.update(b"hijkijkljklmklmnlmnomnopnopq");
+		assert_eq!(
+			hasher.finalize(),
+			[
+				0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c,
+				0x3e, 0x60, 0x39, 0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec,
+				0xed, 0xd4, 0x19, 0xdb, 0x06, 0xc1
+			]
+		);
+	}
+}
@@ -129,7 +129,7 @@ impl FileOps for KMsgDeviceHandle {
 
 /// Creates the default devices.
 pub(super) fn create() -> EResult<()> {
-	let _first_major = ManuallyDrop::new(id::alloc_major(DeviceType::Char, Some(1))?);
+	let _first_major = ManuallyDrop::new(id::alloc_major(DeviceType::Char, b"mem", Some(1))?);
 	register_char(CharDev::new(
 		DeviceID {
 			major: 1,
@@ -176,7 +176,7 @@ pub(super) fn create() -> EResult<()> {
 		KMsgDeviceHandle,
 	)?)?;
 
-	let _fifth_major = ManuallyDrop::new(id::alloc_major(DeviceType::Char, Some(5))?);
+	let _fifth_major = ManuallyDrop::new(id::alloc_major(DeviceType::Char, b"/dev/tty", Some(5))?);
 	register_char(CharDev::new(
 		DeviceID {
 			major: 5,
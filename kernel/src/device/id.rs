@@ -17,16 +17,82 @@
  */
 
 //! This module handles minor/major numbers, including their allocation.
+//!
+//! Major numbers are registered with a name, listed in `/proc/devices`. A driver either claims a
+//! specific major number, or requests one from the dynamic ranges.
+//!
+//! Major numbers assigned by Linux to standard devices are reserved: they are never returned by
+//! dynamic allocation, so that userspace relying on them keeps working when the corresponding
+//! driver is loaded later.
 
 use crate::{device::DeviceType, sync::mutex::Mutex};
-use core::cell::OnceCell;
-use utils::{collections::id_allocator::IDAllocator, errno::AllocResult};
+use core::ops::RangeInclusive;
+use utils::{
+	collections::{btreemap::BTreeMap, id_allocator::IDAllocator, string::String},
+	errno,
+	errno::{AllocResult, EResult},
+};
 
 /// The number of major numbers.
-const MAJOR_COUNT: u32 = 256;
+const MAJOR_COUNT: u32 = 512;
 /// The number of minor numbers.
 const MINORS_COUNT: u32 = 256;
 
+/// Major numbers of standard devices, reserved from dynamic allocation.
+///
+/// Each entry contains the device type, the range of major numbers and the name of the device.
+const STANDARD_MAJORS: &[(DeviceType, RangeInclusive<u32>, &str)] = &[
+	(DeviceType::Char, 1..=1, "mem"),
+	(DeviceType::Char, 4..=4, "tty"),
+	(DeviceType::Char, 5..=5, "/dev/tty"),
+	(DeviceType::Char, 7..=7, "vcs"),
+	(DeviceType::Char, 10..=10, "misc"),
+	(DeviceType::Char, 13..=13, "input"),
+	(DeviceType::Char, 29..=29, "fb"),
+	(DeviceType::Char, 116..=116, "alsa"),
+	(DeviceType::Char, 128..=143, "pty"),
+	(DeviceType::Char, 180..=180, "usb"),
+	(DeviceType::Char, 189..=189, "usb_device"),
+	(DeviceType::Char, 226..=226, "drm"),
+	(DeviceType::Block, 1..=1, "ramdisk"),
+	(DeviceType::Block, 7..=7, "loop"),
+	(DeviceType::Block, 8..=8, "sd"),
+	(DeviceType::Block, 9..=9, "md"),
+	(DeviceType::Block, 11..=11, "sr"),
+	(DeviceType::Block, 65..=71, "sd"),
+	(DeviceType::Block, 128..=135, "sd"),
+	(DeviceType::Block, 179..=179, "mmc"),
+	(DeviceType::Block, 253..=253, "device-mapper"),
+	(DeviceType::Block, 259..=259, "blkext"),
+];
+
+/// Returns the ranges of major numbers for dynamic allocation, in order of preference.
+fn dynamic_ranges(device_type: DeviceType) -> &'static [RangeInclusive<u32>] {
+	// Same ranges as Linux, searched from the top
+	match device_type {
+		DeviceType::Block => &[240..=254],
+		DeviceType::Char => &[234..=254, 384..=511],
+	}
+}
+
+/// Tells whether the major number `major` is reserved for a standard device of type
+/// `device_type`.
+pub fn is_reserved(device_type: DeviceType, major: u32) -> bool {
+	STANDARD_MAJORS
+		.iter()
+		.any(|(t, range, _)| *t == device_type && range.contains(&major))
+}
+
+/// Returns the major number of the standard device of type `device_type` with the name `name`.
+///
+/// If the device has several major numbers, the first one is returned.
+pub fn standard_major(device_type: DeviceType, name: &[u8]) -> Option<u32> {
+	STANDARD_MAJORS
+		.iter()
+		.find(|(t, _, n)| *t == device_type && n.as_bytes() == name)
+		.map(|(_, range, _)| *range.start())
+}
+
 /// Returns the major number from a device number.
 pub fn major(dev: u64) -> u32 {
 	(((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff)) as _
@@ -96,39 +162,73 @@ impl MajorBlock {
 
 impl Drop for MajorBlock {
 	fn drop(&mut self) {
-		let mut major_allocator = match self.device_type {
-			DeviceType::Block => BLOCK_MAJOR_ALLOCATOR.lock(),
-			DeviceType::Char => CHAR_MAJOR_ALLOCATOR.lock(),
-		};
-		if let Some(major_allocator) = major_allocator.get_mut() {
-			major_allocator.free(self.major);
-		}
+		registry(self.device_type).lock().remove(&self.major);
 	}
 }
 
-/// The major numbers allocator.
-static BLOCK_MAJOR_ALLOCATOR: Mutex<OnceCell<IDAllocator>> = Mutex::new(OnceCell::new());
-/// The major numbers allocator.
-static CHAR_MAJOR_ALLOCATOR: Mutex<OnceCell<IDAllocator>> = Mutex::new(OnceCell::new());
+/// Registered block major numbers, with their names.
+static BLOCK_MAJORS: Mutex<BTreeMap<u32, String>> = Mutex::new(BTreeMap::new());
+/// Registered char major numbers, with their names.
+static CHAR_MAJORS: Mutex<BTreeMap<u32, String>> = Mutex::new(BTreeMap::new());
+
+/// Returns the registry of major numbers for the given device type.
+fn registry(device_type: DeviceType) -> &'static Mutex<BTreeMap<u32, String>> {
+	match device_type {
+		DeviceType::Block => &BLOCK_MAJORS,
+		DeviceType::Char => &CHAR_MAJORS,
+	}
+}
 
-/// Allocates a major number.
+/// Allocates a major number with the name `name`.
 ///
 /// `device_type` is the type of device for the major block to be allocated.
 ///
 /// If `major` is not `None`, the function shall allocate the specific given major
-/// number.
+/// number. Else, a number is picked from the dynamic ranges, skipping reserved numbers.
 ///
-/// If the allocation fails, the function returns an `Err`.
-pub fn alloc_major(device_type: DeviceType, major: Option<u32>) -> AllocResult<MajorBlock> {
-	let mut major_allocator = match device_type {
-		DeviceType::Block => BLOCK_MAJOR_ALLOCATOR.lock(),
-		DeviceType::Char => CHAR_MAJOR_ALLOCATOR.lock(),
+/// Errors:
+/// - [`errno::EINVAL`]: `major` is out of bounds
+/// - [`errno::EBUSY`]: `major` is already allocated, or no dynamic major number is available
+pub fn alloc_major(
+	device_type: DeviceType,
+	name: &[u8],
+	major: Option<u32>,
+) -> EResult<MajorBlock> {
+	let mut majors = registry(device_type).lock();
+	let major = match major {
+		Some(major) if major >= MAJOR_COUNT => return Err(errno!(EINVAL)),
+		Some(major) if majors.contains_key(&major) => return Err(errno!(EBUSY)),
+		Some(major) => major,
+		None => dynamic_ranges(device_type)
+			.iter()
+			.flat_map(|range| range.clone().rev())
+			.find(|major| !majors.contains_key(major) && !is_reserved(device_type, *major))
+			.ok_or_else(|| errno!(EBUSY))?,
 	};
-	major_allocator.get_or_try_init(|| IDAllocator::new(MAJOR_COUNT))?;
-	// FIXME: remove unwrap (wait until `get_mut_or_try_init` or equivalent is available)
-	let major_allocator = major_allocator.get_mut().unwrap();
-	let major = major_allocator.alloc(major)?;
-	MajorBlock::new(device_type, major)
+	let block = MajorBlock::new(device_type, major)?;
+	majors.insert(major, String::try_from(name)?)?;
+	Ok(block)
+}
+
+/// Returns the major number registered with the name `name` for the device type `device_type`.
+pub fn get_major_by_name(device_type: DeviceType, name: &[u8]) -> Option<u32> {
+	registry(device_type)
+		.lock()
+		.iter()
+		.find(|(_, n)| n.as_bytes() == name)
+		.map(|(major, _)| *major)
+}
+
+/// Calls `f` for each registered major number of type `device_type`, in increasing order, with
+/// the major number and its name.
+pub fn for_each_major<E, F: FnMut(u32, &[u8]) -> Result<(), E>>(
+	device_type: DeviceType,
+	mut f: F,
+) -> Result<(), E> {
+	for (major, name) in registry(device_type).lock().iter() {
+		f(*major, name.as_bytes())?;
+	}
+	Ok(())
 }
 
 #[cfg(test)]
@@ -145,4 +245,20 @@ mod test {
 			}
 		}
 	}
+
+	#[test_case]
+	fn major_dynamic() {
+		let block = alloc_major(DeviceType::Char, b"test", None).unwrap();
+		let major = block.get_major();
+		assert!(
+			dynamic_ranges(DeviceType::Char)
+				.iter()
+				.any(|range| range.contains(&major))
+		);
+		assert!(!is_reserved(DeviceType::Char, major));
+		assert_eq!(get_major_by_name(DeviceType::Char, b"test"), Some(major));
+		assert!(alloc_major(DeviceType::Char, b"test2", Some(major)).is_err());
+		drop(block);
+		assert_eq!(get_major_by_name(DeviceType::Char, b"test"), None);
+	}
 }
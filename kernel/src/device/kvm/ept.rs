@@ -0,0 +1,120 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Extended Page Tables (EPT), translating guest-physical addresses to host-physical addresses.
+//!
+//! Pages are mapped lazily, when the guest first accesses them.
+
+use crate::{
+	memory::{
+		PhysAddr,
+		cache::{FrameOwner, RcFrame},
+	},
+	sync::mutex::Mutex,
+};
+use core::slice;
+use utils::{
+	collections::{btreemap::BTreeMap, vec::Vec},
+	errno::AllocResult,
+};
+
+/// Entry flag: read access
+const EPT_READ: u64 = 1 << 0;
+/// Entry flag: write access
+const EPT_WRITE: u64 = 1 << 1;
+/// Entry flag: execute access
+const EPT_EXEC: u64 = 1 << 2;
+/// Leaf entry: write-back memory type
+const EPT_MEMTYPE_WB: u64 = 6 << 3;
+/// The mask of the physical address in an entry.
+const EPT_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// EPT pointer: write-back memory type for the tables
+const EPTP_MEMTYPE_WB: u64 = 6;
+/// EPT pointer: 4 levels of tables
+const EPTP_4_LEVELS: u64 = 3 << 3;
+
+/// The number of levels of tables.
+const LEVELS: usize = 4;
+/// The number of entries in a table.
+const ENTRIES: usize = 512;
+
+/// The frames referenced by an [`Ept`].
+#[derive(Debug, Default)]
+struct EptFrames {
+	/// The tables below the root.
+	tables: Vec<RcFrame>,
+	/// The pages mapped in the guest, by guest frame number.
+	pages: BTreeMap<u64, RcFrame>,
+}
+
+/// A hierarchy of extended page tables.
+///
+/// The frames referenced by the tables are kept alive until the hierarchy is dropped.
+#[derive(Debug)]
+pub struct Ept {
+	/// The root table.
+	root: RcFrame,
+	/// The frames referenced by the tables.
+	frames: Mutex<EptFrames>,
+}
+
+impl Ept {
+	/// Creates a new hierarchy, without any page mapped.
+	pub fn new() -> AllocResult<Self> {
+		Ok(Self {
+			root: RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?,
+			frames: Default::default(),
+		})
+	}
+
+	/// Returns the value of the EPT pointer to use this hierarchy.
+	pub fn pointer(&self) -> u64 {
+		self.root.phys_addr().0 as u64 | EPTP_4_LEVELS | EPTP_MEMTYPE_WB
+	}
+
+	/// Maps `frame` at the guest frame number `gfn`, replacing the previous mapping, if any.
+	///
+	/// If `writable` is `false`, the guest is not allowed to write to the page.
+	pub fn map(&self, gfn: u64, frame: RcFrame, writable: bool) -> AllocResult<()> {
+		let mut frames = self.frames.lock();
+		let mut table: &mut [u64] = unsafe { self.root.slice_mut() };
+		for level in (1..LEVELS).rev() {
+			let index = (gfn >> (9 * level)) as usize % ENTRIES;
+			let entry = table[index];
+			let next = if entry & EPT_READ != 0 {
+				PhysAddr((entry & EPT_ADDR_MASK) as usize)
+			} else {
+				let next = RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?;
+				let addr = next.phys_addr();
+				frames.tables.push(next)?;
+				table[index] = addr.0 as u64 | EPT_READ | EPT_WRITE | EPT_EXEC;
+				addr
+			};
+			let next = next.kernel_to_virtual().unwrap();
+			table = unsafe { slice::from_raw_parts_mut(next.as_ptr(), ENTRIES) };
+		}
+		let mut flags = EPT_READ | EPT_EXEC | EPT_MEMTYPE_WB;
+		if writable {
+			flags |= EPT_WRITE;
+		}
+		table[gfn as usize % ENTRIES] = frame.phys_addr().0 as u64 | flags;
+		frames.pages.insert(gfn, frame)?;
+		Ok(())
+	}
+}
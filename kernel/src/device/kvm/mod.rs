@@ -0,0 +1,308 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Hardware-assisted virtualization, through a subset of the KVM interface.
+//!
+//! `/dev/kvm` allows to create virtual machines, each having its own file descriptor. The memory
+//! of a virtual machine is made of slots, each mapping a range of the memory of the process which
+//! created it at a guest-physical address. VCPUs are created on a virtual machine, each with its
+//! own file descriptor, then run with `KVM_RUN` until the guest needs the intervention of
+//! userspace.
+//!
+//! Only Intel VMX is supported, with EPT and unrestricted guests.
+
+mod ept;
+mod vcpu;
+
+use crate::{
+	arch::x86::vmx,
+	device::{CharDev, DeviceID, DeviceType, id, register_char},
+	file::{File, FileType, O_RDWR, Stat, fd::FD_CLOEXEC, fs::FileOps},
+	initcall,
+	memory::user::UserPtr,
+	process::{Process, mem_space::MemSpace},
+	sync::mutex::Mutex,
+	syscall::{FromSyscallArg, ioctl},
+};
+use core::{ffi::c_void, hint::unlikely, mem::ManuallyDrop};
+use ept::Ept;
+use utils::{
+	collections::{path::PathBuf, vec::Vec},
+	errno,
+	errno::EResult,
+	limits::PAGE_SIZE,
+	ptr::arc::Arc,
+};
+use vcpu::Vcpu;
+
+/// The version of the KVM API.
+const KVM_API_VERSION: u32 = 12;
+/// The major number of the device.
+const KVM_MAJOR: u32 = 10;
+/// The minor number of the device.
+const KVM_MINOR: u32 = 232;
+
+/// Capability: memory slots are mapped from userspace memory
+const KVM_CAP_USER_MEMORY: u32 = 3;
+/// Capability: the recommended maximum number of VCPUs
+const KVM_CAP_NR_VCPUS: u32 = 9;
+/// Capability: the maximum number of memory slots
+const KVM_CAP_NR_MEMSLOTS: u32 = 10;
+/// Capability: the maximum number of VCPUs
+const KVM_CAP_MAX_VCPUS: u32 = 66;
+/// Capability: `immediate_exit` is supported
+const KVM_CAP_IMMEDIATE_EXIT: u32 = 136;
+
+/// The maximum number of memory slots of a virtual machine.
+const MAX_MEMSLOTS: u32 = 32;
+/// The maximum number of VCPUs of a virtual machine.
+const MAX_VCPUS: u32 = 16;
+/// The size of the guest-physical address space, in bytes.
+const GUEST_PHYS_SIZE: u64 = 1 << 48;
+
+/// `struct kvm_userspace_memory_region`
+#[repr(C)]
+#[derive(Debug)]
+struct KvmUserspaceMemoryRegion {
+	slot: u32,
+	flags: u32,
+	guest_phys_addr: u64,
+	memory_size: u64,
+	userspace_addr: u64,
+}
+
+/// Returns the value of the capability `cap`.
+fn check_extension(cap: u32) -> u32 {
+	match cap {
+		KVM_CAP_USER_MEMORY | KVM_CAP_IMMEDIATE_EXIT => 1,
+		KVM_CAP_NR_VCPUS | KVM_CAP_MAX_VCPUS => MAX_VCPUS,
+		KVM_CAP_NR_MEMSLOTS => MAX_MEMSLOTS,
+		_ => 0,
+	}
+}
+
+/// Creates a close-on-exec file descriptor for `ops` on the current process, returning its ID.
+fn create_fd(ops: Arc<dyn FileOps>) -> EResult<u32> {
+	let file = File::open_floating(ops, O_RDWR)?;
+	let fds = Process::current()
+		.file_descriptors
+		.get()
+		.ok_or_else(|| errno!(EBADF))?;
+	let (id, _) = fds.lock().create_fd(FD_CLOEXEC, file)?;
+	Ok(id as _)
+}
+
+/// A memory slot of a virtual machine.
+#[derive(Debug)]
+struct MemSlot {
+	/// The ID of the slot.
+	id: u32,
+	/// The guest-physical address of the beginning of the slot.
+	guest_phys_addr: u64,
+	/// The size of the slot in bytes.
+	size: u64,
+	/// The address of the memory of the slot, in the memory space of the virtual machine.
+	userspace_addr: u64,
+}
+
+/// A virtual machine.
+#[derive(Debug)]
+pub struct Vm {
+	/// The VMX features of the processor.
+	caps: vmx::Caps,
+	/// The memory space in which the memory slots are mapped.
+	mem_space: Arc<MemSpace>,
+	/// The memory slots.
+	slots: Mutex<Vec<MemSlot>>,
+	/// The guest-physical address space.
+	///
+	/// Running VCPUs hold a reference to it, so that it is replaced when the memory slots
+	/// change.
+	ept: Mutex<Arc<Ept>>,
+	/// The IDs of the VCPUs.
+	vcpus: Mutex<Vec<u32>>,
+}
+
+impl Vm {
+	/// Returns the address in the memory space of the virtual machine corresponding to the
+	/// guest-physical address `addr`.
+	///
+	/// If no memory slot contains the address, the function returns `None`.
+	fn translate(&self, addr: u64) -> Option<u64> {
+		self.slots
+			.lock()
+			.iter()
+			.find(|s| (s.guest_phys_addr..s.guest_phys_addr + s.size).contains(&addr))
+			.map(|s| s.userspace_addr + (addr - s.guest_phys_addr))
+	}
+
+	/// Creates, modifies or deletes a memory slot.
+	fn set_user_memory_region(&self, region: &KvmUserspaceMemoryRegion) -> EResult<()> {
+		if unlikely(region.flags != 0 || region.slot >= MAX_MEMSLOTS) {
+			return Err(errno!(EINVAL));
+		}
+		let aligned = [
+			region.guest_phys_addr,
+			region.memory_size,
+			region.userspace_addr,
+		]
+		.iter()
+		.all(|n| n % PAGE_SIZE as u64 == 0);
+		if unlikely(!aligned) {
+			return Err(errno!(EINVAL));
+		}
+		let guest_end = region
+			.guest_phys_addr
+			.checked_add(region.memory_size)
+			.ok_or_else(|| errno!(EINVAL))?;
+		if unlikely(guest_end > GUEST_PHYS_SIZE) {
+			return Err(errno!(EINVAL));
+		}
+		region
+			.userspace_addr
+			.checked_add(region.memory_size)
+			.ok_or_else(|| errno!(EINVAL))?;
+		let mut slots = self.slots.lock();
+		let overlap = slots.iter().any(|s| {
+			s.id != region.slot
+				&& s.guest_phys_addr < guest_end
+				&& region.guest_phys_addr < s.guest_phys_addr + s.size
+		});
+		if unlikely(region.memory_size > 0 && overlap) {
+			return Err(errno!(EEXIST));
+		}
+		// Allocate first, so that the slots are left untouched on failure
+		let ept = Arc::new(Ept::new()?)?;
+		let slot = MemSlot {
+			id: region.slot,
+			guest_phys_addr: region.guest_phys_addr,
+			size: region.memory_size,
+			userspace_addr: region.userspace_addr,
+		};
+		match slots.iter().position(|s| s.id == region.slot) {
+			Some(i) if region.memory_size == 0 => {
+				slots.remove(i);
+			}
+			Some(i) => slots[i] = slot,
+			None if region.memory_size == 0 => {}
+			None => slots.push(slot)?,
+		}
+		// Guest pages are mapped again on access
+		*self.ept.lock() = ept;
+		Ok(())
+	}
+}
+
+/// The file operations of a virtual machine.
+#[derive(Debug)]
+struct VmFile(Arc<Vm>);
+
+impl FileOps for VmFile {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			ioctl::KVM_CHECK_EXTENSION => Ok(check_extension(argp as _)),
+			ioctl::KVM_CREATE_VCPU => {
+				let id = argp as usize;
+				if unlikely(id >= MAX_VCPUS as usize) {
+					return Err(errno!(EINVAL));
+				}
+				let id = id as u32;
+				let mut vcpus = self.0.vcpus.lock();
+				if unlikely(vcpus.contains(&id)) {
+					return Err(errno!(EEXIST));
+				}
+				let vcpu = Arc::new(Vcpu::new(self.0.clone(), id)?)?;
+				let fd = create_fd(vcpu)?;
+				vcpus.push(id)?;
+				Ok(fd)
+			}
+			ioctl::KVM_SET_USER_MEMORY_REGION => {
+				let region_ptr = UserPtr::<KvmUserspaceMemoryRegion>::from_ptr(argp as usize);
+				let region = region_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+				self.0.set_user_memory_region(&region)?;
+				Ok(0)
+			}
+			// Unrestricted guests run real mode code without a TSS
+			ioctl::KVM_SET_TSS_ADDR => Ok(0),
+			_ => Err(errno!(EINVAL)),
+		}
+	}
+}
+
+/// The file operations of `/dev/kvm`.
+#[derive(Debug)]
+struct KvmDevice {
+	/// The VMX features of the processor.
+	caps: vmx::Caps,
+}
+
+impl FileOps for KvmDevice {
+	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			ioctl::KVM_GET_API_VERSION => Ok(KVM_API_VERSION),
+			ioctl::KVM_CREATE_VM => {
+				// Only the default machine type is supported
+				if unlikely(!argp.is_null()) {
+					return Err(errno!(EINVAL));
+				}
+				let mem_space = Process::current()
+					.mem_space
+					.as_ref()
+					.cloned()
+					.ok_or_else(|| errno!(EINVAL))?;
+				let vm = Arc::new(Vm {
+					caps: self.caps,
+					mem_space,
+					slots: Default::default(),
+					ept: Mutex::new(Arc::new(Ept::new()?)?),
+					vcpus: Default::default(),
+				})?;
+				create_fd(Arc::new(VmFile(vm))?)
+			}
+			ioctl::KVM_CHECK_EXTENSION => Ok(check_extension(argp as _)),
+			ioctl::KVM_GET_VCPU_MMAP_SIZE => Ok(vcpu::MMAP_SIZE as _),
+			_ => Err(errno!(EINVAL)),
+		}
+	}
+}
+
+initcall!(device, kvm, |_| {
+	let Some(caps) = vmx::probe() else {
+		return Ok(());
+	};
+	let _major = ManuallyDrop::new(id::alloc_major(DeviceType::Char, b"misc", Some(KVM_MAJOR))?);
+	register_char(CharDev::new(
+		DeviceID {
+			major: KVM_MAJOR,
+			minor: KVM_MINOR,
+		},
+		PathBuf::try_from(b"/dev/kvm")?,
+		0o666,
+		KvmDevice {
+			caps,
+		},
+	)?)?;
+	Ok(())
+});
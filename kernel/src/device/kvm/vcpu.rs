@@ -0,0 +1,949 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Virtual CPUs.
+
+use super::{Vm, ept::Ept};
+use crate::{
+	arch::x86::{
+		DEFAULT_FCW, DEFAULT_MXCSR, FxState, IA32_FS_BASE, IA32_GS_BASE, IA32_KERNEL_GS_BASE, cli,
+		cpuid, fxrstor, fxsave, is_interrupt_enabled, rdmsr, sti, vmx, vmx::*, wrmsr,
+	},
+	file::{File, FileType, Stat, fs::FileOps},
+	memory::{
+		VirtAddr,
+		cache::{FrameOwner, RcFrame},
+		user::UserPtr,
+	},
+	process::Process,
+	sync::mutex::Mutex,
+	syscall::{FromSyscallArg, ioctl},
+};
+use core::{ffi::c_void, fmt, hint::unlikely};
+use utils::{collections::vec::Vec, errno, errno::EResult, limits::PAGE_SIZE, ptr::arc::Arc};
+
+/// The size of the memory to map on a VCPU file descriptor: the `struct kvm_run` page, followed
+/// by the page holding the data of I/O exits.
+pub const MMAP_SIZE: usize = 2 * PAGE_SIZE;
+
+/// Exit reason: unknown, the hardware exit reason is given
+const KVM_EXIT_UNKNOWN: u32 = 0;
+/// Exit reason: I/O instruction
+const KVM_EXIT_IO: u32 = 2;
+/// Exit reason: the guest executed `hlt`
+const KVM_EXIT_HLT: u32 = 5;
+/// Exit reason: the guest triple faulted
+const KVM_EXIT_SHUTDOWN: u32 = 8;
+/// Exit reason: the guest could not be entered
+const KVM_EXIT_FAIL_ENTRY: u32 = 9;
+/// Exit reason: interrupted by a signal
+const KVM_EXIT_INTR: u32 = 10;
+
+/// I/O direction: `in`
+const KVM_EXIT_IO_IN: u64 = 0;
+/// I/O direction: `out`
+const KVM_EXIT_IO_OUT: u64 = 1;
+
+/// MSR: `syscall` segments
+const IA32_STAR: u32 = 0xc0000081;
+/// MSR: `syscall` entry point in 64 bit mode
+const IA32_LSTAR: u32 = 0xc0000082;
+/// MSR: `syscall` entry point in compatibility mode
+const IA32_CSTAR: u32 = 0xc0000083;
+/// MSR: `syscall` flags mask
+const IA32_FMASK: u32 = 0xc0000084;
+/// The MSRs which are not held by the VMCS, swapped with the host's around guest execution.
+const SWAPPED_MSRS: [u32; 5] = [
+	IA32_KERNEL_GS_BASE,
+	IA32_STAR,
+	IA32_LSTAR,
+	IA32_CSTAR,
+	IA32_FMASK,
+];
+
+/// CR0: protected mode enable
+const CR0_PE: u64 = 1 << 0;
+/// CR0: paging
+const CR0_PG: u64 = 1 << 31;
+/// EFER: `syscall` enable
+const EFER_SCE: u64 = 1 << 0;
+/// EFER: long mode enable
+const EFER_LME: u64 = 1 << 8;
+/// EFER: long mode active
+const EFER_LMA: u64 = 1 << 10;
+/// EFER: no-execute enable
+const EFER_NXE: u64 = 1 << 11;
+
+/// CPUID leaf 1, ECX: VMX
+const CPUID_VMX: u32 = 1 << 5;
+/// CPUID leaf 1, ECX: `xsave`
+const CPUID_XSAVE: u32 = 1 << 26;
+/// CPUID leaf 1, ECX: `xsave` enabled by the OS
+const CPUID_OSXSAVE: u32 = 1 << 27;
+/// CPUID leaf 1, ECX: running under a hypervisor
+const CPUID_HYPERVISOR: u32 = 1 << 31;
+
+/// Interruption information: general protection fault, as a hardware exception, valid
+const INTR_INFO_GP: u64 = 13 | (3 << 8) | (1 << 31);
+/// Interruption information: deliver an error code
+const INTR_INFO_DELIVER_CODE: u64 = 1 << 11;
+
+/// The index of the segments in the VMCS fields.
+const SEG_ES: u32 = 0;
+const SEG_CS: u32 = 1;
+const SEG_SS: u32 = 2;
+const SEG_DS: u32 = 3;
+const SEG_FS: u32 = 4;
+const SEG_GS: u32 = 5;
+const SEG_LDTR: u32 = 6;
+const SEG_TR: u32 = 7;
+
+/// `struct kvm_run`, shared with userspace.
+#[repr(C)]
+struct KvmRun {
+	request_interrupt_window: u8,
+	immediate_exit: u8,
+	padding1: [u8; 6],
+	exit_reason: u32,
+	ready_for_interrupt_injection: u8,
+	if_flag: u8,
+	flags: u16,
+	cr8: u64,
+	apic_base: u64,
+	/// Information about the exit, depending on its reason.
+	exit: [u64; 32],
+}
+
+impl KvmRun {
+	/// Sets the exit reason, along with the first two words of its information.
+	fn set_exit(&mut self, reason: u32, info: [u64; 2]) {
+		self.exit_reason = reason;
+		self.exit[..2].copy_from_slice(&info);
+	}
+}
+
+/// `struct kvm_regs`
+#[repr(C)]
+#[derive(Debug)]
+struct KvmRegs {
+	rax: u64,
+	rbx: u64,
+	rcx: u64,
+	rdx: u64,
+	rsi: u64,
+	rdi: u64,
+	rsp: u64,
+	rbp: u64,
+	r8: u64,
+	r9: u64,
+	r10: u64,
+	r11: u64,
+	r12: u64,
+	r13: u64,
+	r14: u64,
+	r15: u64,
+	rip: u64,
+	rflags: u64,
+}
+
+/// `struct kvm_segment`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct KvmSegment {
+	base: u64,
+	limit: u32,
+	selector: u16,
+	type_: u8,
+	present: u8,
+	dpl: u8,
+	db: u8,
+	s: u8,
+	l: u8,
+	g: u8,
+	avl: u8,
+	unusable: u8,
+	padding: u8,
+}
+
+impl KvmSegment {
+	/// Returns the segment at index `i` in the current VMCS.
+	unsafe fn read(i: u32) -> Self {
+		let ar = vmread(GUEST_ES_AR_BYTES + 2 * i);
+		let bit = |n: u32| ((ar >> n) & 1) as u8;
+		Self {
+			base: vmread(GUEST_ES_BASE + 2 * i),
+			limit: vmread(GUEST_ES_LIMIT + 2 * i) as _,
+			selector: vmread(GUEST_ES_SELECTOR + 2 * i) as _,
+			type_: (ar & 0xf) as _,
+			present: bit(7),
+			dpl: ((ar >> 5) & 3) as _,
+			db: bit(14),
+			s: bit(4),
+			l: bit(13),
+			g: bit(15),
+			avl: bit(12),
+			unusable: bit(16),
+			padding: 0,
+		}
+	}
+
+	/// Writes the segment at index `i` in the current VMCS.
+	unsafe fn write(&self, i: u32) {
+		let ar = if self.unusable != 0 || self.present == 0 {
+			1 << 16
+		} else {
+			(self.type_ as u64 & 0xf)
+				| (self.s as u64 & 1) << 4
+				| (self.dpl as u64 & 3) << 5
+				| (self.present as u64 & 1) << 7
+				| (self.avl as u64 & 1) << 12
+				| (self.l as u64 & 1) << 13
+				| (self.db as u64 & 1) << 14
+				| (self.g as u64 & 1) << 15
+		};
+		vmwrite(GUEST_ES_SELECTOR + 2 * i, self.selector as _);
+		vmwrite(GUEST_ES_LIMIT + 2 * i, self.limit as _);
+		vmwrite(GUEST_ES_AR_BYTES + 2 * i, ar);
+		vmwrite(GUEST_ES_BASE + 2 * i, self.base);
+	}
+}
+
+/// `struct kvm_dtable`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct KvmDtable {
+	base: u64,
+	limit: u16,
+	padding: [u16; 3],
+}
+
+/// `struct kvm_sregs`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct KvmSregs {
+	cs: KvmSegment,
+	ds: KvmSegment,
+	es: KvmSegment,
+	fs: KvmSegment,
+	gs: KvmSegment,
+	ss: KvmSegment,
+	tr: KvmSegment,
+	ldt: KvmSegment,
+	gdt: KvmDtable,
+	idt: KvmDtable,
+	cr0: u64,
+	cr2: u64,
+	cr3: u64,
+	cr4: u64,
+	cr8: u64,
+	efer: u64,
+	apic_base: u64,
+	interrupt_bitmap: [u64; 4],
+}
+
+/// The action to take after a VM exit.
+enum Exit {
+	/// Resume the guest.
+	Resume,
+	/// The guest accessed a page which is not mapped in the EPT.
+	EptViolation {
+		/// The guest-physical address of the access
+		addr: u64,
+		/// Tells whether the access is a write
+		write: bool,
+	},
+	/// Return to userspace, the exit being described in the `struct kvm_run`.
+	User,
+}
+
+/// The state of a VCPU.
+struct VcpuState {
+	/// The VMX features of the processor.
+	caps: Caps,
+	/// The region used to enter VMX operation while accessing the VCPU.
+	vmxon: RcFrame,
+	/// The VMCS of the VCPU.
+	vmcs: RcFrame,
+	/// The general-purpose registers which are not held by the VMCS.
+	regs: GuestRegs,
+	/// The FPU state.
+	fpu: FxState,
+	/// The values of [`SWAPPED_MSRS`].
+	msrs: [u64; SWAPPED_MSRS.len()],
+	/// If the guest executed an `in` instruction, the size of its operand. The instruction is
+	/// completed with the data provided by userspace on the next run.
+	pending_in: Option<usize>,
+}
+
+impl VcpuState {
+	/// Makes the VMCS of the VCPU current on the core, then calls `f`.
+	///
+	/// Interrupts are disabled while `f` runs, so that the VCPU cannot move to another core.
+	fn with_vmcs<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> EResult<R> {
+		let int = is_interrupt_enabled();
+		cli();
+		let res = unsafe {
+			match vmx::on(self.vmxon.phys_addr()) {
+				Some(cr) => {
+					let vmcs = self.vmcs.phys_addr();
+					// Clearing the VMCS allows to run it on another core next time
+					let res = if vmclear(vmcs) && vmptrld(vmcs) {
+						let res = f(self);
+						vmclear(vmcs);
+						Ok(res)
+					} else {
+						Err(errno!(EIO))
+					};
+					vmx::off(cr);
+					res
+				}
+				None => Err(errno!(EIO)),
+			}
+		};
+		if int {
+			sti();
+		}
+		res
+	}
+
+	/// Initializes the VMCS, with the state of the processor after a reset.
+	unsafe fn init(&mut self) {
+		vmwrite(VMCS_LINK_POINTER, u64::MAX);
+		vmwrite(PIN_BASED_VM_EXEC_CONTROL, self.caps.pin_based as _);
+		vmwrite(CPU_BASED_VM_EXEC_CONTROL, self.caps.cpu_based as _);
+		vmwrite(SECONDARY_VM_EXEC_CONTROL, self.caps.cpu_based2 as _);
+		vmwrite(VM_EXIT_CONTROLS, self.caps.exit as _);
+		vmwrite(VM_ENTRY_CONTROLS, self.caps.entry as _);
+		vmwrite(EXCEPTION_BITMAP, 0);
+		// Intercept writes to the bits of control registers which are fixed in VMX operation,
+		// along with the bits changing the processor mode
+		vmwrite(CR0_GUEST_HOST_MASK, self.caps.cr0_fixed0 | CR0_PE | CR0_PG);
+		vmwrite(CR4_GUEST_HOST_MASK, self.caps.cr4_fixed0);
+		self.set_cr0(0x60000010);
+		self.set_cr4(0);
+		vmwrite(GUEST_CR3, 0);
+		vmwrite(GUEST_IA32_EFER, 0);
+		vmwrite(GUEST_DR7, 0x400);
+		vmwrite(GUEST_IA32_DEBUGCTL, 0);
+		vmwrite(GUEST_RSP, 0);
+		vmwrite(GUEST_RIP, 0xfff0);
+		vmwrite(GUEST_RFLAGS, 0x2);
+		for i in [SEG_ES, SEG_SS, SEG_DS, SEG_FS, SEG_GS] {
+			vmwrite(GUEST_ES_SELECTOR + 2 * i, 0);
+			vmwrite(GUEST_ES_BASE + 2 * i, 0);
+			vmwrite(GUEST_ES_LIMIT + 2 * i, 0xffff);
+			vmwrite(GUEST_ES_AR_BYTES + 2 * i, 0x93);
+		}
+		// Execution starts 16 bytes below 4 GiB
+		vmwrite(GUEST_ES_SELECTOR + 2 * SEG_CS, 0xf000);
+		vmwrite(GUEST_ES_BASE + 2 * SEG_CS, 0xffff0000);
+		vmwrite(GUEST_ES_LIMIT + 2 * SEG_CS, 0xffff);
+		vmwrite(GUEST_ES_AR_BYTES + 2 * SEG_CS, 0x9b);
+		for (i, ar) in [(SEG_LDTR, 0x82), (SEG_TR, 0x8b)] {
+			vmwrite(GUEST_ES_SELECTOR + 2 * i, 0);
+			vmwrite(GUEST_ES_BASE + 2 * i, 0);
+			vmwrite(GUEST_ES_LIMIT + 2 * i, 0xffff);
+			vmwrite(GUEST_ES_AR_BYTES + 2 * i, ar);
+		}
+		vmwrite(GUEST_GDTR_BASE, 0);
+		vmwrite(GUEST_GDTR_LIMIT, 0xffff);
+		vmwrite(GUEST_IDTR_BASE, 0);
+		vmwrite(GUEST_IDTR_LIMIT, 0xffff);
+		vmwrite(GUEST_SYSENTER_CS, 0);
+		vmwrite(GUEST_SYSENTER_ESP, 0);
+		vmwrite(GUEST_SYSENTER_EIP, 0);
+		vmwrite(GUEST_ACTIVITY_STATE, 0);
+		vmwrite(GUEST_INTERRUPTIBILITY_INFO, 0);
+		vmwrite(GUEST_PENDING_DBG_EXCEPTIONS, 0);
+	}
+
+	/// Sets the guest's CR0 to `val`.
+	unsafe fn set_cr0(&mut self, val: u64) {
+		vmwrite(CR0_READ_SHADOW, val);
+		vmwrite(GUEST_CR0, val | self.caps.cr0_fixed0);
+		self.update_long_mode();
+	}
+
+	/// Sets the guest's CR4 to `val`.
+	unsafe fn set_cr4(&mut self, val: u64) {
+		vmwrite(CR4_READ_SHADOW, val);
+		vmwrite(GUEST_CR4, val | self.caps.cr4_fixed0);
+	}
+
+	/// Sets the guest's EFER to `val`, ignoring the LMA bit.
+	unsafe fn set_efer(&mut self, val: u64) {
+		vmwrite(GUEST_IA32_EFER, val & !EFER_LMA);
+		self.update_long_mode();
+	}
+
+	/// Activates long mode if the guest enabled it and paging, or deactivates it otherwise.
+	unsafe fn update_long_mode(&mut self) {
+		let efer = vmread(GUEST_IA32_EFER);
+		let paging = vmread(CR0_READ_SHADOW) & CR0_PG != 0;
+		let (efer, entry) = if paging && efer & EFER_LME != 0 {
+			(efer | EFER_LMA, self.caps.entry | ENTRY_IA32E_MODE)
+		} else {
+			(efer & !EFER_LMA, self.caps.entry)
+		};
+		vmwrite(GUEST_IA32_EFER, efer);
+		vmwrite(VM_ENTRY_CONTROLS, entry as _);
+	}
+
+	/// Returns a reference to the general-purpose register with the index `n`, as encoded in
+	/// instructions.
+	///
+	/// The stack pointer is held by the VMCS, so it is not available.
+	fn gpr(&mut self, n: u64) -> Option<&mut u64> {
+		let regs = &mut self.regs;
+		let reg = match n {
+			0 => &mut regs.rax,
+			1 => &mut regs.rcx,
+			2 => &mut regs.rdx,
+			3 => &mut regs.rbx,
+			5 => &mut regs.rbp,
+			6 => &mut regs.rsi,
+			7 => &mut regs.rdi,
+			8 => &mut regs.r8,
+			9 => &mut regs.r9,
+			10 => &mut regs.r10,
+			11 => &mut regs.r11,
+			12 => &mut regs.r12,
+			13 => &mut regs.r13,
+			14 => &mut regs.r14,
+			15 => &mut regs.r15,
+			_ => return None,
+		};
+		Some(reg)
+	}
+
+	/// Moves the guest to the instruction following the one which caused the VM exit.
+	unsafe fn skip_instruction(&mut self) {
+		let rip = vmread(GUEST_RIP) + vmread(VM_EXIT_INSTRUCTION_LEN);
+		vmwrite(GUEST_RIP, rip);
+	}
+
+	/// Injects a general protection fault in the guest.
+	unsafe fn inject_gp(&mut self) {
+		// Exceptions have no error code in real mode
+		if vmread(CR0_READ_SHADOW) & CR0_PE != 0 {
+			vmwrite(VM_ENTRY_EXCEPTION_ERROR_CODE, 0);
+			vmwrite(
+				VM_ENTRY_INTR_INFO_FIELD,
+				INTR_INFO_GP | INTR_INFO_DELIVER_CODE,
+			);
+		} else {
+			vmwrite(VM_ENTRY_INTR_INFO_FIELD, INTR_INFO_GP);
+		}
+	}
+
+	/// Returns the value of the guest's MSR `msr`, or `None` if not supported.
+	unsafe fn read_msr(&self, msr: u32) -> Option<u64> {
+		let val = match msr {
+			IA32_EFER => vmread(GUEST_IA32_EFER),
+			IA32_FS_BASE => vmread(GUEST_ES_BASE + 2 * SEG_FS),
+			IA32_GS_BASE => vmread(GUEST_ES_BASE + 2 * SEG_GS),
+			IA32_SYSENTER_CS => vmread(GUEST_SYSENTER_CS),
+			IA32_SYSENTER_ESP => vmread(GUEST_SYSENTER_ESP),
+			IA32_SYSENTER_EIP => vmread(GUEST_SYSENTER_EIP),
+			_ => {
+				let i = SWAPPED_MSRS.iter().position(|m| *m == msr)?;
+				self.msrs[i]
+			}
+		};
+		Some(val)
+	}
+
+	/// Sets the guest's MSR `msr` to `val`, returning `false` if not supported.
+	unsafe fn write_msr(&mut self, msr: u32, val: u64) -> bool {
+		match msr {
+			IA32_EFER => {
+				if val & !(EFER_SCE | EFER_LME | EFER_LMA | EFER_NXE) != 0 {
+					return false;
+				}
+				self.set_efer(val);
+			}
+			IA32_FS_BASE => vmwrite(GUEST_ES_BASE + 2 * SEG_FS, val),
+			IA32_GS_BASE => vmwrite(GUEST_ES_BASE + 2 * SEG_GS, val),
+			IA32_SYSENTER_CS => vmwrite(GUEST_SYSENTER_CS, val),
+			IA32_SYSENTER_ESP => vmwrite(GUEST_SYSENTER_ESP, val),
+			IA32_SYSENTER_EIP => vmwrite(GUEST_SYSENTER_EIP, val),
+			_ => {
+				let Some(i) = SWAPPED_MSRS.iter().position(|m| *m == msr) else {
+					return false;
+				};
+				self.msrs[i] = val;
+			}
+		}
+		true
+	}
+
+	/// Emulates `cpuid`, hiding the features which cannot be used by the guest.
+	unsafe fn emulate_cpuid(&mut self) {
+		let leaf = self.regs.rax as u32;
+		let (eax, ebx, mut ecx, edx) = cpuid(leaf, 0, self.regs.rcx as u32, 0);
+		if leaf == 1 {
+			ecx &= !(CPUID_VMX | CPUID_XSAVE | CPUID_OSXSAVE);
+			ecx |= CPUID_HYPERVISOR;
+		}
+		self.regs.rax = eax as _;
+		self.regs.rbx = ebx as _;
+		self.regs.rcx = ecx as _;
+		self.regs.rdx = edx as _;
+	}
+
+	/// Emulates an access to a control register.
+	///
+	/// If the access is not supported, the function returns `false`.
+	unsafe fn emulate_cr_access(&mut self) -> bool {
+		let qual = vmread(EXIT_QUALIFICATION);
+		let cr = qual & 0xf;
+		let access = (qual >> 4) & 0x3;
+		let gpr = (qual >> 8) & 0xf;
+		let val = if gpr == 4 {
+			vmread(GUEST_RSP)
+		} else {
+			self.gpr(gpr).map(|r| *r).unwrap_or(0)
+		};
+		match (access, cr) {
+			// `mov` to CR0
+			(0, 0) => self.set_cr0(val),
+			// `mov` to CR4
+			(0, 4) => self.set_cr4(val),
+			// `lmsw`, which cannot clear PE
+			(3, 0) => {
+				let data = (qual >> 16) & 0xf;
+				let cr0 = vmread(CR0_READ_SHADOW);
+				self.set_cr0((cr0 & !0xe) | data);
+			}
+			_ => return false,
+		}
+		true
+	}
+
+	/// Handles an I/O instruction, describing it in `run` and `pio`.
+	///
+	/// If the instruction is not supported, the function returns `false`.
+	unsafe fn handle_io(&mut self, run: &mut KvmRun, pio: &mut [u8]) -> bool {
+		let qual = vmread(EXIT_QUALIFICATION);
+		// String instructions are not supported
+		if qual & (1 << 4) != 0 {
+			return false;
+		}
+		let size = (qual & 0x7) as usize + 1;
+		let port = (qual >> 16) & 0xffff;
+		let dir = if qual & (1 << 3) != 0 {
+			self.pending_in = Some(size);
+			KVM_EXIT_IO_IN
+		} else {
+			pio[..size].copy_from_slice(&self.regs.rax.to_le_bytes()[..size]);
+			KVM_EXIT_IO_OUT
+		};
+		// One operation of `size` bytes, with the data in the second page
+		let io = dir | (size as u64) << 8 | port << 16 | 1 << 32;
+		run.set_exit(KVM_EXIT_IO, [io, PAGE_SIZE as _]);
+		true
+	}
+
+	/// Runs the guest with the memory of `ept` until the next VM exit, then handles it.
+	unsafe fn run_once(&mut self, ept: &Ept, run: &mut KvmRun, pio: &mut [u8]) -> Exit {
+		vmwrite(EPT_POINTER, ept.pointer());
+		invept_all();
+		// Swap the state which is not switched by the processor
+		let mut host_fpu = FxState([0; 512]);
+		fxsave(&mut host_fpu);
+		fxrstor(&self.fpu);
+		let host_msrs = SWAPPED_MSRS.map(rdmsr);
+		for (msr, val) in SWAPPED_MSRS.iter().zip(self.msrs) {
+			wrmsr(*msr, val);
+		}
+		let entered = enter(&mut self.regs);
+		for (i, msr) in SWAPPED_MSRS.iter().enumerate() {
+			self.msrs[i] = rdmsr(*msr);
+			wrmsr(*msr, host_msrs[i]);
+		}
+		fxsave(&mut self.fpu);
+		fxrstor(&host_fpu);
+		if !entered {
+			let err = vmread(VM_INSTRUCTION_ERROR);
+			run.set_exit(KVM_EXIT_FAIL_ENTRY, [err, 0]);
+			return Exit::User;
+		}
+		let reason = vmread(VM_EXIT_REASON) as u32;
+		if reason & EXIT_REASON_FAILED_ENTRY != 0 {
+			run.set_exit(KVM_EXIT_FAIL_ENTRY, [(reason & 0xffff) as _, 0]);
+			return Exit::User;
+		}
+		let reason = reason & 0xffff;
+		let handled = match reason {
+			// The interrupt is handled by the host once interrupts are enabled again
+			EXIT_REASON_EXTERNAL_INTERRUPT => return Exit::Resume,
+			EXIT_REASON_TRIPLE_FAULT => {
+				run.set_exit(KVM_EXIT_SHUTDOWN, [0; 2]);
+				return Exit::User;
+			}
+			EXIT_REASON_CPUID => {
+				self.emulate_cpuid();
+				true
+			}
+			EXIT_REASON_HLT => {
+				self.skip_instruction();
+				run.set_exit(KVM_EXIT_HLT, [0; 2]);
+				return Exit::User;
+			}
+			EXIT_REASON_CR_ACCESS => self.emulate_cr_access(),
+			EXIT_REASON_IO_INSTRUCTION => {
+				if self.handle_io(run, pio) {
+					self.skip_instruction();
+					return Exit::User;
+				}
+				false
+			}
+			EXIT_REASON_MSR_READ => {
+				let msr = self.regs.rcx as u32;
+				match self.read_msr(msr) {
+					Some(val) => {
+						self.regs.rax = val & 0xffffffff;
+						self.regs.rdx = val >> 32;
+						self.skip_instruction();
+					}
+					None => self.inject_gp(),
+				}
+				return Exit::Resume;
+			}
+			EXIT_REASON_MSR_WRITE => {
+				let msr = self.regs.rcx as u32;
+				let val = (self.regs.rdx << 32) | (self.regs.rax & 0xffffffff);
+				if self.write_msr(msr, val) {
+					self.skip_instruction();
+				} else {
+					self.inject_gp();
+				}
+				return Exit::Resume;
+			}
+			EXIT_REASON_EPT_VIOLATION => {
+				return Exit::EptViolation {
+					addr: vmread(GUEST_PHYSICAL_ADDRESS),
+					write: vmread(EXIT_QUALIFICATION) & (1 << 1) != 0,
+				};
+			}
+			_ => false,
+		};
+		if !handled {
+			run.set_exit(KVM_EXIT_UNKNOWN, [reason as _, 0]);
+			return Exit::User;
+		}
+		self.skip_instruction();
+		Exit::Resume
+	}
+}
+
+/// A virtual CPU.
+pub struct Vcpu {
+	/// The virtual machine the VCPU belongs to.
+	vm: Arc<Vm>,
+	/// The ID of the VCPU.
+	id: u32,
+	/// The page holding the `struct kvm_run`, shared with userspace.
+	run: RcFrame,
+	/// The page holding the data of I/O exits, shared with userspace.
+	pio: RcFrame,
+	/// The state of the VCPU.
+	state: Mutex<VcpuState>,
+}
+
+impl Vcpu {
+	/// Creates a VCPU on `vm`, with the ID `id`.
+	pub fn new(vm: Arc<Vm>, id: u32) -> EResult<Self> {
+		let vmxon = RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?;
+		let vmcs = RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?;
+		unsafe {
+			vmxon.slice_mut::<u32>()[0] = vm.caps.revision_id;
+			vmcs.slice_mut::<u32>()[0] = vm.caps.revision_id;
+		}
+		let mut fpu = FxState([0; 512]);
+		fpu.0[..2].copy_from_slice(&(DEFAULT_FCW as u16).to_le_bytes());
+		fpu.0[24..28].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+		let mut state = VcpuState {
+			caps: vm.caps,
+			vmxon,
+			vmcs,
+			regs: Default::default(),
+			fpu,
+			msrs: [0; SWAPPED_MSRS.len()],
+			pending_in: None,
+		};
+		state.with_vmcs(|s| unsafe { s.init() })?;
+		Ok(Self {
+			vm,
+			id,
+			run: RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?,
+			pio: RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?,
+			state: Mutex::new(state),
+		})
+	}
+
+	/// Maps the page of the guest containing the guest-physical address `addr`.
+	///
+	/// If the address is not in a memory slot, the function returns `false`.
+	fn map_guest_page(&self, ept: &Ept, addr: u64, write: bool) -> EResult<bool> {
+		let Some(host_addr) = self.vm.translate(addr) else {
+			return Ok(false);
+		};
+		let cgroup = Process::current().cgroup.lock().clone();
+		let (frame, writable) = self
+			.vm
+			.mem_space
+			.get_page(VirtAddr(host_addr as _), &cgroup)?
+			.ok_or_else(|| errno!(EFAULT))?;
+		if unlikely(write && !writable) {
+			return Err(errno!(EFAULT));
+		}
+		ept.map(addr / PAGE_SIZE as u64, frame, writable)?;
+		Ok(true)
+	}
+
+	/// Runs the VCPU until an exit must be handled by userspace.
+	fn run(&self) -> EResult<u32> {
+		let proc = Process::current();
+		// The memory slots are mapped in the memory space of the creator of the virtual machine
+		let same_mem_space = proc
+			.mem_space
+			.as_ref()
+			.is_some_and(|m| Arc::as_ptr(m) == Arc::as_ptr(&self.vm.mem_space));
+		if unlikely(!same_mem_space) {
+			return Err(errno!(EIO));
+		}
+		let mut state = self.state.lock();
+		let run = unsafe { &mut *self.run.virt_addr().as_ptr::<KvmRun>() };
+		let pio = unsafe { self.pio.slice_mut::<u8>() };
+		// Complete the pending `in` instruction
+		if let Some(size) = state.pending_in.take() {
+			let mut bytes = state.regs.rax.to_le_bytes();
+			bytes[..size].copy_from_slice(&pio[..size]);
+			// 32 bit operands are zero-extended
+			if size == 4 {
+				bytes[4..].fill(0);
+			}
+			state.regs.rax = u64::from_le_bytes(bytes);
+		}
+		loop {
+			if run.immediate_exit != 0 || proc.has_pending_signal() {
+				run.set_exit(KVM_EXIT_INTR, [0; 2]);
+				return Err(errno!(EINTR));
+			}
+			let ept = self.vm.ept.lock().clone();
+			let exit = state.with_vmcs(|s| unsafe { s.run_once(&ept, run, pio) })?;
+			match exit {
+				Exit::Resume => {}
+				Exit::EptViolation {
+					addr,
+					write,
+				} => {
+					// MMIO is not supported
+					if !self.map_guest_page(&ept, addr, write)? {
+						let reason = EXIT_REASON_EPT_VIOLATION as u64;
+						run.set_exit(KVM_EXIT_UNKNOWN, [reason, 0]);
+						return Ok(0);
+					}
+				}
+				Exit::User => return Ok(0),
+			}
+		}
+	}
+
+	/// Returns the general-purpose registers of the guest.
+	fn get_regs(&self) -> EResult<KvmRegs> {
+		self.state.lock().with_vmcs(|s| unsafe {
+			KvmRegs {
+				rax: s.regs.rax,
+				rbx: s.regs.rbx,
+				rcx: s.regs.rcx,
+				rdx: s.regs.rdx,
+				rsi: s.regs.rsi,
+				rdi: s.regs.rdi,
+				rsp: vmread(GUEST_RSP),
+				rbp: s.regs.rbp,
+				r8: s.regs.r8,
+				r9: s.regs.r9,
+				r10: s.regs.r10,
+				r11: s.regs.r11,
+				r12: s.regs.r12,
+				r13: s.regs.r13,
+				r14: s.regs.r14,
+				r15: s.regs.r15,
+				rip: vmread(GUEST_RIP),
+				rflags: vmread(GUEST_RFLAGS),
+			}
+		})
+	}
+
+	/// Sets the general-purpose registers of the guest.
+	fn set_regs(&self, regs: &KvmRegs) -> EResult<()> {
+		self.state.lock().with_vmcs(|s| unsafe {
+			s.regs = GuestRegs {
+				rax: regs.rax,
+				rbx: regs.rbx,
+				rcx: regs.rcx,
+				rdx: regs.rdx,
+				rsi: regs.rsi,
+				rdi: regs.rdi,
+				rbp: regs.rbp,
+				r8: regs.r8,
+				r9: regs.r9,
+				r10: regs.r10,
+				r11: regs.r11,
+				r12: regs.r12,
+				r13: regs.r13,
+				r14: regs.r14,
+				r15: regs.r15,
+				cr2: s.regs.cr2,
+			};
+			vmwrite(GUEST_RSP, regs.rsp);
+			vmwrite(GUEST_RIP, regs.rip);
+			// Bit 1 is reserved and always set
+			vmwrite(GUEST_RFLAGS, regs.rflags | 0x2);
+		})
+	}
+
+	/// Returns the special registers of the guest.
+	fn get_sregs(&self) -> EResult<KvmSregs> {
+		self.state.lock().with_vmcs(|s| unsafe {
+			KvmSregs {
+				cs: KvmSegment::read(SEG_CS),
+				ds: KvmSegment::read(SEG_DS),
+				es: KvmSegment::read(SEG_ES),
+				fs: KvmSegment::read(SEG_FS),
+				gs: KvmSegment::read(SEG_GS),
+				ss: KvmSegment::read(SEG_SS),
+				tr: KvmSegment::read(SEG_TR),
+				ldt: KvmSegment::read(SEG_LDTR),
+				gdt: KvmDtable {
+					base: vmread(GUEST_GDTR_BASE),
+					limit: vmread(GUEST_GDTR_LIMIT) as _,
+					padding: [0; 3],
+				},
+				idt: KvmDtable {
+					base: vmread(GUEST_IDTR_BASE),
+					limit: vmread(GUEST_IDTR_LIMIT) as _,
+					padding: [0; 3],
+				},
+				cr0: vmread(CR0_READ_SHADOW),
+				cr2: s.regs.cr2,
+				cr3: vmread(GUEST_CR3),
+				cr4: vmread(CR4_READ_SHADOW),
+				efer: vmread(GUEST_IA32_EFER),
+				..Default::default()
+			}
+		})
+	}
+
+	/// Sets the special registers of the guest.
+	///
+	/// The local APIC is not emulated, so that `cr8`, `apic_base` and `interrupt_bitmap` are
+	/// ignored.
+	fn set_sregs(&self, sregs: &KvmSregs) -> EResult<()> {
+		self.state.lock().with_vmcs(|s| unsafe {
+			sregs.cs.write(SEG_CS);
+			sregs.ds.write(SEG_DS);
+			sregs.es.write(SEG_ES);
+			sregs.fs.write(SEG_FS);
+			sregs.gs.write(SEG_GS);
+			sregs.ss.write(SEG_SS);
+			sregs.tr.write(SEG_TR);
+			sregs.ldt.write(SEG_LDTR);
+			vmwrite(GUEST_GDTR_BASE, sregs.gdt.base);
+			vmwrite(GUEST_GDTR_LIMIT, sregs.gdt.limit as _);
+			vmwrite(GUEST_IDTR_BASE, sregs.idt.base);
+			vmwrite(GUEST_IDTR_LIMIT, sregs.idt.limit as _);
+			s.regs.cr2 = sregs.cr2;
+			vmwrite(GUEST_CR3, sregs.cr3);
+			s.set_cr4(sregs.cr4);
+			// EFER first, since CR0 decides whether long mode is active
+			vmwrite(GUEST_IA32_EFER, sregs.efer & !EFER_LMA);
+			s.set_cr0(sregs.cr0);
+		})
+	}
+}
+
+impl fmt::Debug for Vcpu {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Vcpu").field("id", &self.id).finish()
+	}
+}
+
+impl FileOps for Vcpu {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			ioctl::KVM_RUN => {
+				// Only the default argument is supported
+				if unlikely(!argp.is_null()) {
+					return Err(errno!(EINVAL));
+				}
+				self.run()
+			}
+			ioctl::KVM_GET_REGS => {
+				let regs_ptr = UserPtr::<KvmRegs>::from_ptr(argp as usize);
+				regs_ptr.copy_to_user(&self.get_regs()?)?;
+				Ok(0)
+			}
+			ioctl::KVM_SET_REGS => {
+				let regs_ptr = UserPtr::<KvmRegs>::from_ptr(argp as usize);
+				let regs = regs_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+				self.set_regs(&regs)?;
+				Ok(0)
+			}
+			ioctl::KVM_GET_SREGS => {
+				let sregs_ptr = UserPtr::<KvmSregs>::from_ptr(argp as usize);
+				sregs_ptr.copy_to_user(&self.get_sregs()?)?;
+				Ok(0)
+			}
+			ioctl::KVM_SET_SREGS => {
+				let sregs_ptr = UserPtr::<KvmSregs>::from_ptr(argp as usize);
+				let sregs = sregs_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+				self.set_sregs(&sregs)?;
+				Ok(0)
+			}
+			_ => Err(errno!(EINVAL)),
+		}
+	}
+
+	fn mmap_pages(&self, _file: &File, off: u64, pages: usize) -> EResult<Option<Vec<RcFrame>>> {
+		let end = (off as usize)
+			.checked_add(pages)
+			.ok_or_else(|| errno!(EINVAL))?;
+		if unlikely(end > MMAP_SIZE / PAGE_SIZE) {
+			return Err(errno!(EINVAL));
+		}
+		let mut frames = Vec::new();
+		for frame in [&self.run, &self.pio]
+			.into_iter()
+			.take(end)
+			.skip(off as usize)
+		{
+			frames.push(frame.clone())?;
+		}
+		Ok(Some(frames))
+	}
+}
@@ -36,8 +36,11 @@ pub mod bus;
 pub mod default;
 pub mod id;
 pub mod keyboard;
+#[cfg(target_arch = "x86_64")]
+pub mod kvm;
 pub mod manager;
 pub mod serial;
+pub mod stats;
 pub mod storage;
 pub mod tty;
 
@@ -46,21 +49,34 @@ use crate::{
 	file,
 	file::{
 		File, FileType, Mode, Stat,
+		buffer::watch_queue::{
+			DEVICE_WATCHES, DeviceNotification, NOTIFY_DEVICE_ADD, NOTIFY_DEVICE_REMOVE,
+			WATCH_TYPE_DEVICE_NOTIFY,
+		},
 		fs::FileOps,
 		perm::AccessProfile,
 		vfs,
 		vfs::{ResolutionSettings, Resolved},
 	},
+	initcall,
 	memory::{
+		buddy,
 		buddy::FrameOrder,
 		cache::{FrameOwner, MappedNode, RcFrame},
-		user::UserSlice,
+		user::{UserPtr, UserSlice},
 	},
+	process::rusage,
 	sync::mutex::Mutex,
-	syscall::ioctl,
+	syscall::{FromSyscallArg, ioctl},
+};
+use core::{
+	ffi::c_void,
+	fmt,
+	hint::{likely, unlikely},
+	num::NonZeroU64,
 };
-use core::{ffi::c_void, fmt, hint::likely, num::NonZeroU64};
 use keyboard::KeyboardManager;
+use stats::{IoDir, IoStats, SECTOR_SIZE};
 use storage::StorageManager;
 use utils::{
 	boxed::Box,
@@ -188,6 +204,17 @@ pub trait BlockDeviceOps: fmt::Debug {
 	/// `off` is the offset of the frame on the device, in pages.
 	fn write_pages(&self, off: u64, buf: &[u8]) -> EResult<()>;
 
+	/// Discards `count` blocks at the offset `off` (in blocks), telling the device their content
+	/// is not used anymore (TRIM).
+	///
+	/// The content of discarded blocks is undefined until they are written again.
+	///
+	/// The default implementation returns [`errno::EOPNOTSUPP`].
+	fn discard(&self, off: u64, count: u64) -> EResult<()> {
+		let _ = (off, count);
+		Err(errno!(EOPNOTSUPP))
+	}
+
 	/// Polls the device with the given mask.
 	fn poll(&self, mask: u32) -> EResult<u32> {
 		let _ = mask;
@@ -219,6 +246,8 @@ pub struct BlkDev {
 	pub ops: Box<dyn BlockDeviceOps>,
 	/// The device as a mapped node
 	pub(crate) mapped: MappedNode,
+	/// I/O statistics
+	pub stats: IoStats,
 }
 
 impl BlkDev {
@@ -242,6 +271,7 @@ impl BlkDev {
 
 			ops,
 			mapped: Default::default(),
+			stats: Default::default(),
 		})?;
 		if likely(file::is_init()) {
 			create_file(&id, DeviceType::Block, &dev.path, mode)?;
@@ -258,14 +288,46 @@ impl BlkDev {
 		order: FrameOrder,
 		owner: FrameOwner,
 	) -> EResult<RcFrame> {
+		let read = |owner| {
+			let frame = this.read_frame_direct(off, order, owner)?;
+			rusage::account_input(buddy::get_frame_size(order));
+			Ok(frame)
+		};
 		if let Some(mapped) = owner.inner() {
-			mapped.get_or_insert_frame(off, order, || {
-				this.ops.read_frame(off, order, owner.clone())
-			})
+			mapped.get_or_insert_frame(off, order, || read(owner.clone()))
 		} else {
-			this.ops.read_frame(off, order, owner)
+			read(owner)
 		}
 	}
+
+	/// Reads a frame from the device at the offset `off`, bypassing the cache.
+	pub fn read_frame_direct(
+		&self,
+		off: u64,
+		order: FrameOrder,
+		owner: FrameOwner,
+	) -> EResult<RcFrame> {
+		let sectors = buddy::get_frame_size(order) as u64 / SECTOR_SIZE;
+		self.stats.account(IoDir::Read, sectors, || {
+			self.ops.read_frame(off, order, owner)
+		})
+	}
+
+	/// Writes pages to the device at the offset `off` (in pages).
+	pub fn write_pages(&self, off: u64, buf: &[u8]) -> EResult<()> {
+		let sectors = buf.len() as u64 / SECTOR_SIZE;
+		self.stats
+			.account(IoDir::Write, sectors, || self.ops.write_pages(off, buf))
+	}
+
+	/// Discards `count` blocks at the offset `off` (in blocks).
+	///
+	/// For details, see [`BlockDeviceOps::discard`].
+	pub fn discard(&self, off: u64, count: u64) -> EResult<()> {
+		let sectors = count * self.ops.block_size().get() / SECTOR_SIZE;
+		self.stats
+			.account(IoDir::Discard, sectors, || self.ops.discard(off, count))
+	}
 }
 
 impl Drop for BlkDev {
@@ -328,17 +390,43 @@ pub static BLK_DEVICES: Mutex<HashMap<DeviceID, Arc<BlkDev>>> = Mutex::new(HashM
 /// The list of registered character devices.
 pub static CHAR_DEVICES: Mutex<HashMap<DeviceID, Arc<CharDev>>> = Mutex::new(HashMap::new());
 
+/// Posts a notification to the watches on devices.
+///
+/// Arguments:
+/// - `dev_type` is the type of the device
+/// - `id` is the ID of the device
+/// - `removed` tells whether the device has been removed, or added
+pub(crate) fn notify(dev_type: DeviceType, id: DeviceID, removed: bool) {
+	let subtype = if removed {
+		NOTIFY_DEVICE_REMOVE
+	} else {
+		NOTIFY_DEVICE_ADD
+	};
+	let note = DeviceNotification {
+		watch: Default::default(),
+		dev_type: dev_type.to_file_type().to_mode(),
+		major: id.major,
+		minor: id.minor,
+		__reserved: 0,
+	};
+	DEVICE_WATCHES.post(WATCH_TYPE_DEVICE_NOTIFY, subtype, note);
+}
+
 /// Helper to insert a block device.
 #[inline]
 pub fn register_blk(dev: Arc<BlkDev>) -> AllocResult<()> {
-	BLK_DEVICES.lock().insert(dev.id, dev)?;
+	let id = dev.id;
+	BLK_DEVICES.lock().insert(id, dev)?;
+	notify(DeviceType::Block, id, false);
 	Ok(())
 }
 
 /// Helper to insert a character device.
 #[inline]
 pub fn register_char(dev: Arc<CharDev>) -> AllocResult<()> {
-	CHAR_DEVICES.lock().insert(dev.id, dev)?;
+	let id = dev.id;
+	CHAR_DEVICES.lock().insert(id, dev)?;
+	notify(DeviceType::Char, id, false);
 	Ok(())
 }
 
@@ -397,12 +485,35 @@ impl FileOps for BlkDevFileOps {
 
 	fn ioctl(&self, file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
 		let dev = file.as_block_device().ok_or_else(|| errno!(ENODEV))?;
-		dev.ops.ioctl(request, argp)
+		match request.get_old_format() {
+			ioctl::BLKDISCARD => {
+				if unlikely(!file.can_write()) {
+					return Err(errno!(EBADF));
+				}
+				let range_ptr = UserPtr::<[u64; 2]>::from_ptr(argp as usize);
+				let [start, len] = range_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+				// The range must be aligned on blocks and in bounds of the device
+				let blk_size = dev.ops.block_size().get();
+				let end = start.checked_add(len).ok_or_else(|| errno!(EINVAL))?;
+				if unlikely(!start.is_multiple_of(blk_size) || !len.is_multiple_of(blk_size)) {
+					return Err(errno!(EINVAL));
+				}
+				if unlikely(end > dev.ops.blocks_count() * blk_size) {
+					return Err(errno!(EINVAL));
+				}
+				// Drop cached pages that are entirely discarded
+				dev.mapped
+					.remove_range(start.div_ceil(PAGE_SIZE as u64), end / PAGE_SIZE as u64);
+				dev.discard(start / blk_size, len / blk_size)?;
+				Ok(0)
+			}
+			_ => dev.ops.ioctl(request, argp),
+		}
 	}
 }
 
 /// Initializes devices management.
-pub(crate) fn init() -> EResult<()> {
+fn init() -> EResult<()> {
 	let keyboard_manager = KeyboardManager::new();
 	manager::register(keyboard_manager)?;
 
@@ -425,6 +536,8 @@ pub(crate) fn init() -> EResult<()> {
 	Ok(())
 }
 
+initcall!(device, devices, |_| init());
+
 /// Switches to stage 2, creating device files of devices that are already registered.
 ///
 /// This function must be used only once at boot, after files management has been initialized.
@@ -20,6 +20,7 @@
 
 use crate::{
 	arch::x86::io::{inb, outb},
+	console::Console,
 	sync::mutex::Mutex,
 };
 
@@ -188,3 +189,45 @@ pub static PORTS: [Mutex<Serial>; 4] = [
 	Mutex::new(Serial::from_port(COM3)),
 	Mutex::new(Serial::from_port(COM4)),
 ];
+
+/// A console writing to a serial port.
+pub struct SerialConsole {
+	/// The name of the console.
+	name: &'static [u8],
+	/// The index of the port in [`PORTS`].
+	port: usize,
+}
+
+impl Console for SerialConsole {
+	fn name(&self) -> &[u8] {
+		self.name
+	}
+
+	fn is_default(&self) -> bool {
+		self.port == 0
+	}
+
+	fn write(&self, buf: &[u8]) {
+		PORTS[self.port].lock().write(buf);
+	}
+}
+
+/// The consoles of each serial port.
+pub static CONSOLES: [SerialConsole; 4] = [
+	SerialConsole {
+		name: b"ttyS0",
+		port: 0,
+	},
+	SerialConsole {
+		name: b"ttyS1",
+		port: 1,
+	},
+	SerialConsole {
+		name: b"ttyS2",
+		port: 2,
+	},
+	SerialConsole {
+		name: b"ttyS3",
+		port: 3,
+	},
+];
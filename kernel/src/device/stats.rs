@@ -0,0 +1,160 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! I/O statistics of block devices, exposed through `/proc/diskstats`.
+
+use crate::{
+	sync::atomic::AtomicU64,
+	time::clock::{Clock, current_time_ns},
+};
+use core::{
+	fmt,
+	sync::atomic::{
+		AtomicUsize,
+		Ordering::{AcqRel, Acquire, Relaxed, Release},
+	},
+};
+
+/// The size of a sector, the unit in which transfers are counted, in bytes.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// The direction of an I/O request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IoDir {
+	/// Reading from the device.
+	Read = 0,
+	/// Writing to the device.
+	Write = 1,
+	/// Discarding blocks of the device.
+	Discard = 2,
+}
+
+/// Counters for one direction of I/O requests.
+#[derive(Debug, Default)]
+struct DirStats {
+	/// The number of requests completed successfully.
+	ios: AtomicU64,
+	/// The number of sectors transferred.
+	sectors: AtomicU64,
+	/// The time spent on requests, in nanoseconds.
+	ticks: AtomicU64,
+}
+
+/// I/O statistics of a block device.
+#[derive(Debug, Default)]
+pub struct IoStats {
+	/// Per-direction counters, indexed by [`IoDir`].
+	dirs: [DirStats; 3],
+	/// The number of requests currently in progress.
+	in_flight: AtomicUsize,
+	/// The timestamp at which the device last became busy, in nanoseconds.
+	busy_since: AtomicU64,
+	/// The time during which at least one request was in progress, in nanoseconds.
+	io_ticks: AtomicU64,
+	/// The sum of the durations of all requests, in nanoseconds.
+	time_in_queue: AtomicU64,
+}
+
+impl IoStats {
+	/// Accounts the request performed by `f`, which transfers `sectors` sectors in the direction
+	/// `dir`.
+	///
+	/// Requests that fail are not counted as completed.
+	pub fn account<T, E>(
+		&self,
+		dir: IoDir,
+		sectors: u64,
+		f: impl FnOnce() -> Result<T, E>,
+	) -> Result<T, E> {
+		let start = current_time_ns(Clock::Monotonic);
+		if self.in_flight.fetch_add(1, AcqRel) == 0 {
+			self.busy_since.store(start, Release);
+		}
+		let res = f();
+		let end = current_time_ns(Clock::Monotonic);
+		let duration = end.saturating_sub(start);
+		if self.in_flight.fetch_sub(1, AcqRel) == 1 {
+			let busy = end.saturating_sub(self.busy_since.load(Acquire));
+			self.io_ticks.fetch_add(busy, Relaxed);
+		}
+		self.time_in_queue.fetch_add(duration, Relaxed);
+		if res.is_ok() {
+			let stats = &self.dirs[dir as usize];
+			stats.ios.fetch_add(1, Relaxed);
+			stats.sectors.fetch_add(sectors, Relaxed);
+			stats.ticks.fetch_add(duration, Relaxed);
+		}
+		res
+	}
+}
+
+/// Displays the statistics with the fields of a `/proc/diskstats` line following the device's
+/// name.
+///
+/// Merged requests are not tracked and are always reported as zero. Times are in milliseconds.
+impl fmt::Display for IoStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let ms = |c: &AtomicU64| c.load(Relaxed) / 1_000_000;
+		let [read, write, discard] = &self.dirs;
+		for (i, dir) in [read, write].into_iter().enumerate() {
+			if i > 0 {
+				write!(f, " ")?;
+			}
+			write!(
+				f,
+				"{} 0 {} {}",
+				dir.ios.load(Relaxed),
+				dir.sectors.load(Relaxed),
+				ms(&dir.ticks)
+			)?;
+		}
+		write!(
+			f,
+			" {} {} {} {} 0 {} {}",
+			self.in_flight.load(Relaxed),
+			ms(&self.io_ticks),
+			ms(&self.time_in_queue),
+			discard.ios.load(Relaxed),
+			discard.sectors.load(Relaxed),
+			ms(&discard.ticks)
+		)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use utils::format;
+
+	#[test_case]
+	fn io_stats_account() {
+		let stats = IoStats::default();
+		stats.account(IoDir::Read, 8, || Ok::<_, ()>(())).unwrap();
+		stats
+			.account(IoDir::Write, 8, || Err::<(), _>(()))
+			.unwrap_err();
+		stats
+			.account(IoDir::Discard, 16, || Ok::<_, ()>(()))
+			.unwrap();
+		// Requests are immediate, so times are zero
+		assert_eq!(
+			format!("{stats}").unwrap().as_bytes(),
+			b"1 0 8 0 0 0 0 0 0 0 0 1 0 16 0"
+		);
+	}
+}
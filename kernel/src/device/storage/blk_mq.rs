@@ -0,0 +1,419 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Multi-queue submission path, for devices able to process several requests at once (such as
+//! NVMe or virtio-blk).
+//!
+//! Unlike the [`super::elevator`], which serializes requests, requests are submitted directly to
+//! one of the device's hardware queues, chosen according to the CPU core issuing the request, so
+//! that cores do not contend on a single lock.
+//!
+//! Each hardware queue has a fixed number of slots, identified by **tags**. The driver passes the
+//! tag of a request along with it to the device, and reports its completion with
+//! [`BlkMq::complete`], usually from the queue's completion interrupt. Drivers of devices without
+//! completion interrupts (such as PATA, which uses programmed I/O) may instead process the request
+//! synchronously on submission.
+//!
+//! A request that does not complete before its deadline is aborted. If the driver cannot abort
+//! it, the whole device is reset. Failed requests are retried according to the policy of
+//! [`super::recovery`].
+
+use crate::{
+	device::{
+		BlockDeviceOps,
+		storage::{recovery, recovery::Deadline},
+	},
+	file::wait_queue::WaitQueue,
+	memory::{
+		buddy::{FrameOrder, ZONE_KERNEL},
+		cache::{FrameOwner, RcFrame},
+	},
+	process,
+	process::{
+		Process,
+		scheduler::{Scheduler, core_id, stat},
+		signal::{SIGEV_NONE, SigEvent},
+	},
+	sync::mutex::IntMutex,
+	syscall::ioctl,
+	time::{clock::Clock, timer::Timer},
+};
+use core::{ffi::c_void, fmt, hint::unlikely, num::NonZeroU64};
+use utils::{
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	limits::PAGE_SIZE,
+	vec,
+};
+
+/// An I/O request.
+#[derive(Debug)]
+pub enum Request<'b> {
+	/// Reads from the device to the buffer.
+	Read {
+		/// The offset on the device, in pages.
+		off: u64,
+		/// The buffer to read to.
+		buf: &'b mut [u8],
+	},
+	/// Writes the buffer to the device.
+	Write {
+		/// The offset on the device, in pages.
+		off: u64,
+		/// The buffer to write.
+		buf: &'b [u8],
+	},
+	/// Discards blocks on the device.
+	///
+	/// Drivers of devices that do not support discarding must fail with [`errno::EOPNOTSUPP`].
+	Discard {
+		/// The offset on the device, in blocks.
+		off: u64,
+		/// The number of blocks to discard.
+		count: u64,
+	},
+}
+
+/// The state of a request after its submission to the driver.
+#[derive(Debug)]
+pub enum Submitted {
+	/// The device is processing the request. The driver reports its completion with
+	/// [`BlkMq::complete`].
+	Pending,
+	/// The driver has processed the request synchronously, with the given result.
+	Done(EResult<()>),
+}
+
+/// Operations of a device driver supporting several hardware queues.
+pub trait MqOps: fmt::Debug {
+	/// Returns the granularity of I/O for the device, in bytes.
+	fn block_size(&self) -> NonZeroU64;
+	/// Returns the number of blocks on the device.
+	fn blocks_count(&self) -> u64;
+
+	/// Submits the request `req` to the hardware queue `hwq`, in the slot `tag`.
+	///
+	/// If the device processes requests asynchronously, the function must not wait for the
+	/// request to complete and returns [`Submitted::Pending`]. The driver then reports its
+	/// completion with [`BlkMq::complete`]. Until then, the buffer of the request remains valid.
+	///
+	/// If the function returns an error, the request is considered as not submitted.
+	fn submit(&self, hwq: usize, tag: usize, req: &mut Request) -> EResult<Submitted>;
+
+	/// Aborts the request in the slot `tag` of the hardware queue `hwq`, which has timed out.
+	///
+	/// On success, the device must not access the buffer of the request anymore, and the
+	/// request must not be reported as completed.
+	///
+	/// The default implementation returns an error, which leads to a reset of the device.
+	fn abort(&self, hwq: usize, tag: usize) -> EResult<()> {
+		let _ = (hwq, tag);
+		Err(errno!(EIO))
+	}
+
+	/// Resets the device (for example, a port reset for AHCI or a controller reset for NVMe),
+	/// cancelling all requests in flight.
+	///
+	/// If the device cannot be reset, it is considered as dead and all requests fail.
+	///
+	/// The default implementation returns an error.
+	fn reset(&self) -> EResult<()> {
+		Err(errno!(EIO))
+	}
+
+	/// Performs an ioctl operation on the device.
+	///
+	/// Arguments:
+	/// - `request` is the ID of the request to perform
+	/// - `argp` is a pointer to the argument
+	fn ioctl(&self, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		let _ = (request, argp);
+		Err(errno!(EINVAL))
+	}
+}
+
+/// The state of a slot in a hardware queue.
+#[derive(Clone, Copy, Debug)]
+enum Slot {
+	/// The slot is available.
+	Free,
+	/// A request is being processed in the slot.
+	InFlight,
+	/// The request in the slot has completed with the given result.
+	Done(EResult<()>),
+}
+
+/// A hardware queue of a device.
+#[derive(Debug)]
+struct HwQueue {
+	/// The slots of the queue, by tag.
+	slots: IntMutex<Vec<Slot>>,
+	/// Processes waiting for a slot to be free, or for a request to complete.
+	wait_queue: WaitQueue,
+}
+
+impl HwQueue {
+	/// Makes the current process wait until `f` returns `Some`, without being interruptible by
+	/// signals.
+	///
+	/// If `deadline` is set and passes, the function returns [`errno::ETIMEDOUT`].
+	fn wait<T, F: FnMut(&mut Vec<Slot>) -> Option<T>>(
+		&self,
+		deadline: Option<&Deadline>,
+		mut f: F,
+	) -> EResult<T> {
+		// Fast path, which does not require a process to exist (during boot)
+		if let Some(val) = f(&mut self.slots.lock()) {
+			return Ok(val);
+		}
+		let proc = Process::current();
+		// Timer waking the process up on timeout
+		let _timer = deadline
+			.map(|deadline| {
+				let t = Timer::new(
+					Clock::Monotonic,
+					proc.get_pid(),
+					SigEvent {
+						sigev_notify: SIGEV_NONE,
+						..Default::default()
+					},
+				)?;
+				t.set_time(0, deadline.remaining().max(1))?;
+				EResult::Ok(t)
+			})
+			.transpose()?;
+		let _iowait = stat::io_wait();
+		loop {
+			self.wait_queue.register()?;
+			proc.set_state(process::State::Sleeping);
+			if let Some(val) = f(&mut self.slots.lock()) {
+				proc.set_state(process::State::Running);
+				self.wait_queue.unregister();
+				return Ok(val);
+			}
+			if deadline.is_some_and(Deadline::expired) {
+				proc.set_state(process::State::Running);
+				self.wait_queue.unregister();
+				return Err(errno!(ETIMEDOUT));
+			}
+			Scheduler::tick();
+			self.wait_queue.unregister();
+		}
+	}
+
+	/// Fails all requests in flight with [`errno::EIO`], after the device has been reset.
+	fn fail_all(&self) {
+		for slot in self.slots.lock().iter_mut() {
+			if matches!(slot, Slot::InFlight) {
+				*slot = Slot::Done(Err(errno!(EIO)));
+			}
+		}
+		self.wait_queue.wake_all();
+	}
+}
+
+/// Multi-queue submission interface for a device, implementing [`BlockDeviceOps`] on top of a
+/// driver's [`MqOps`].
+#[derive(Debug)]
+pub struct BlkMq<O: MqOps> {
+	/// The device driver.
+	pub ops: O,
+	/// The hardware queues of the device.
+	hw_queues: Vec<HwQueue>,
+}
+
+impl<O: MqOps> BlkMq<O> {
+	/// Creates a new instance.
+	///
+	/// Arguments:
+	/// - `ops` is the device driver
+	/// - `hw_queues` is the number of hardware queues of the device
+	/// - `depth` is the number of requests each hardware queue can process at once
+	pub fn new(ops: O, hw_queues: usize, depth: usize) -> AllocResult<Self> {
+		let mut queues = Vec::with_capacity(hw_queues.max(1))?;
+		for _ in 0..hw_queues.max(1) {
+			queues.push(HwQueue {
+				slots: IntMutex::new(vec![Slot::Free; depth.max(1)]?),
+				wait_queue: WaitQueue::new(),
+			})?;
+		}
+		Ok(Self {
+			ops,
+			hw_queues: queues,
+		})
+	}
+
+	/// Returns the number of hardware queues.
+	pub fn hw_queues_count(&self) -> usize {
+		self.hw_queues.len()
+	}
+
+	/// Returns the hardware queue to which the software queue of the CPU core `cpu` is mapped.
+	pub fn map_queue(&self, cpu: usize) -> usize {
+		cpu % self.hw_queues.len()
+	}
+
+	/// Submits the request `req` and waits for its completion, retrying on failure.
+	pub fn execute(&self, mut req: Request) -> EResult<()> {
+		// Recovery is performed when the request fails
+		recovery::with_retry("blk-mq", || self.execute_once(&mut req), || Ok(()))
+	}
+
+	/// Submits the request `req` once and waits for its completion.
+	fn execute_once(&self, req: &mut Request) -> EResult<()> {
+		let hwq = self.map_queue(core_id());
+		let queue = &self.hw_queues[hwq];
+		// Get a free slot
+		let tag = queue.wait(None, |slots| {
+			let tag = slots.iter().position(|s| matches!(s, Slot::Free))?;
+			slots[tag] = Slot::InFlight;
+			Some(tag)
+		})?;
+		let free = || {
+			queue.slots.lock()[tag] = Slot::Free;
+			// Wake processes waiting for a slot
+			queue.wait_queue.wake_all();
+		};
+		match self.ops.submit(hwq, tag, req) {
+			Ok(Submitted::Pending) => {}
+			Ok(Submitted::Done(res)) => {
+				free();
+				// The device is idle, so it can be reset right away
+				if matches!(res, Err(e) if recovery::is_transient(e)) {
+					let _ = self.ops.reset();
+				}
+				return res;
+			}
+			Err(e) => {
+				free();
+				return Err(e);
+			}
+		}
+		// Wait for completion
+		let deadline = Deadline::new();
+		let res = queue.wait(Some(&deadline), |slots| match slots[tag] {
+			Slot::Done(res) => Some(res),
+			_ => None,
+		});
+		match res {
+			Ok(res) => {
+				free();
+				res
+			}
+			Err(e) => {
+				self.recover(hwq, tag);
+				free();
+				Err(e)
+			}
+		}
+	}
+
+	/// Recovers from the timeout of the request in slot `tag` of the hardware queue `hwq`.
+	///
+	/// The request is aborted. If this is not possible, the device is reset.
+	fn recover(&self, hwq: usize, tag: usize) {
+		if self.ops.abort(hwq, tag).is_ok() {
+			return;
+		}
+		// Even if the reset fails, the device is not usable anymore, so requests have to fail
+		let _ = self.ops.reset();
+		for queue in &self.hw_queues {
+			queue.fail_all();
+		}
+	}
+
+	/// Reports the completion of the request with tag `tag` on the hardware queue `hwq`, with
+	/// the result `res`.
+	///
+	/// This function may be called from an interrupt handler.
+	pub fn complete(&self, hwq: usize, tag: usize, res: EResult<()>) {
+		let Some(queue) = self.hw_queues.get(hwq) else {
+			return;
+		};
+		{
+			let mut slots = queue.slots.lock();
+			let Some(slot @ Slot::InFlight) = slots.get_mut(tag) else {
+				// Spurious completion
+				return;
+			};
+			*slot = Slot::Done(res);
+		}
+		queue.wait_queue.wake_all();
+	}
+
+	/// Checks that `len` bytes at the offset `off` (in pages) are in bounds of the device.
+	fn check_bounds(&self, off: u64, len: usize) -> EResult<()> {
+		let size = self.ops.block_size().get() * self.ops.blocks_count();
+		let end = off
+			.checked_mul(PAGE_SIZE as u64)
+			.and_then(|off| off.checked_add(len as u64))
+			.ok_or_else(|| errno!(EOVERFLOW))?;
+		if unlikely(end > size) {
+			return Err(errno!(EOVERFLOW));
+		}
+		Ok(())
+	}
+}
+
+impl<O: MqOps> BlockDeviceOps for BlkMq<O> {
+	fn block_size(&self) -> NonZeroU64 {
+		self.ops.block_size()
+	}
+
+	fn blocks_count(&self) -> u64 {
+		self.ops.blocks_count()
+	}
+
+	fn read_frame(&self, off: u64, order: FrameOrder, owner: FrameOwner) -> EResult<RcFrame> {
+		let frame = RcFrame::new(order, ZONE_KERNEL, owner, off)?;
+		let buf = unsafe { frame.slice_mut() };
+		self.check_bounds(off, buf.len())?;
+		self.execute(Request::Read {
+			off,
+			buf,
+		})?;
+		Ok(frame)
+	}
+
+	fn write_pages(&self, off: u64, buf: &[u8]) -> EResult<()> {
+		if unlikely(!buf.len().is_multiple_of(PAGE_SIZE)) {
+			return Err(errno!(EINVAL));
+		}
+		self.check_bounds(off, buf.len())?;
+		self.execute(Request::Write {
+			off,
+			buf,
+		})
+	}
+
+	fn discard(&self, off: u64, count: u64) -> EResult<()> {
+		let end = off.checked_add(count).ok_or_else(|| errno!(EOVERFLOW))?;
+		if unlikely(end > self.ops.blocks_count()) {
+			return Err(errno!(EOVERFLOW));
+		}
+		self.execute(Request::Discard {
+			off,
+			count,
+		})
+	}
+
+	fn ioctl(&self, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		self.ops.ioctl(request, argp)
+	}
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The elevator orders requests to a storage device according to the I/O priority of the
+//! processes issuing them.
+//!
+//! An I/O priority is made of a scheduling class and of a level inside of that class. Classes are
+//! served in order: real-time first, then best-effort, then idle. Inside of a class, lower
+//! levels are served first. Requests with the same priority are served in order of arrival.
+
+use crate::{
+	process,
+	process::{Process, pid::Pid, scheduler::Scheduler},
+	sync::mutex::IntMutex,
+};
+use core::sync::atomic::Ordering::Relaxed;
+use utils::{collections::vec::Vec, errno::AllocResult};
+
+/// I/O scheduling class: no class has been set, the process is treated as best-effort.
+pub const IOPRIO_CLASS_NONE: u16 = 0;
+/// I/O scheduling class: real-time, served before any other class.
+pub const IOPRIO_CLASS_RT: u16 = 1;
+/// I/O scheduling class: best-effort, the default.
+pub const IOPRIO_CLASS_BE: u16 = 2;
+/// I/O scheduling class: idle, served only when no other request is pending.
+pub const IOPRIO_CLASS_IDLE: u16 = 3;
+
+/// The number of priority levels in the real-time and best-effort classes.
+pub const IOPRIO_NR_LEVELS: u16 = 8;
+/// The level of best-effort processes that have no class set.
+const IOPRIO_NORM: u16 = 4;
+
+/// The shift of the class in an I/O priority value.
+const IOPRIO_CLASS_SHIFT: u16 = 13;
+
+/// Returns the class of the I/O priority `ioprio`.
+#[inline]
+pub fn ioprio_class(ioprio: u16) -> u16 {
+	ioprio >> IOPRIO_CLASS_SHIFT
+}
+
+/// Returns the level of the I/O priority `ioprio`.
+#[inline]
+pub fn ioprio_level(ioprio: u16) -> u16 {
+	ioprio & ((1 << IOPRIO_CLASS_SHIFT) - 1)
+}
+
+/// Returns the effective `(class, level)` of the I/O priority `ioprio`, ordered so that lower is
+/// served first.
+pub fn ioprio_key(ioprio: u16) -> (u16, u16) {
+	match ioprio_class(ioprio) {
+		IOPRIO_CLASS_NONE => (IOPRIO_CLASS_BE, IOPRIO_NORM),
+		IOPRIO_CLASS_IDLE => (IOPRIO_CLASS_IDLE, 0),
+		class => (class, ioprio_level(ioprio)),
+	}
+}
+
+/// A request waiting for the device.
+#[derive(Debug)]
+struct Waiter {
+	/// The effective priority of the request.
+	key: (u16, u16),
+	/// The order of arrival of the request.
+	seq: u64,
+	/// The process issuing the request.
+	pid: Pid,
+}
+
+/// The state of an [`Elevator`].
+#[derive(Debug, Default)]
+struct State {
+	/// Tells whether a request is being processed by the device.
+	busy: bool,
+	/// Requests waiting for the device.
+	waiters: Vec<Waiter>,
+	/// The sequence number of the next request.
+	next_seq: u64,
+}
+
+/// Serializes requests to a device, dispatching pending requests by order of I/O priority.
+#[derive(Debug, Default)]
+pub struct Elevator(IntMutex<State>);
+
+impl Elevator {
+	/// Waits until the device is available for a request from the current process, then returns
+	/// a guard which hands the device to the next request when dropped.
+	pub fn lock(&self) -> AllocResult<ElevatorGuard<'_>> {
+		let seq = {
+			let mut state = self.0.lock();
+			// Fast path, which does not require a process to exist (during boot)
+			if !state.busy {
+				state.busy = true;
+				return Ok(ElevatorGuard(self));
+			}
+			let proc = Process::current();
+			let seq = state.next_seq;
+			state.next_seq += 1;
+			state.waiters.push(Waiter {
+				key: ioprio_key(proc.ioprio.load(Relaxed)),
+				seq,
+				pid: proc.get_pid(),
+			})?;
+			seq
+		};
+		// Requests to a device cannot be interrupted, so signals are ignored
+		let proc = Process::current();
+		loop {
+			proc.set_state(process::State::Sleeping);
+			// If the device has been handed to us in between, do not sleep
+			if !self.0.lock().waiters.iter().any(|w| w.seq == seq) {
+				proc.set_state(process::State::Running);
+				return Ok(ElevatorGuard(self));
+			}
+			Scheduler::tick();
+		}
+	}
+}
+
+/// Guard of the device, returned by [`Elevator::lock`].
+#[derive(Debug)]
+pub struct ElevatorGuard<'e>(&'e Elevator);
+
+impl Drop for ElevatorGuard<'_> {
+	fn drop(&mut self) {
+		loop {
+			let pid = {
+				let mut state = self.0.0.lock();
+				let next = state
+					.waiters
+					.iter()
+					.enumerate()
+					.min_by_key(|(_, w)| (w.key, w.seq))
+					.map(|(i, _)| i);
+				match next {
+					// The device remains busy, handed to the next request
+					Some(i) => state.waiters.remove(i).pid,
+					None => {
+						state.busy = false;
+						return;
+					}
+				}
+			};
+			if let Some(proc) = Process::get_by_pid(pid) {
+				proc.wake();
+				break;
+			}
+			// The process does not exist anymore, try the next request
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn ioprio_order() {
+		let rt = (IOPRIO_CLASS_RT << IOPRIO_CLASS_SHIFT) | 7;
+		let be = IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT;
+		let idle = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+		assert!(ioprio_key(rt) < ioprio_key(be));
+		assert!(ioprio_key(be) < ioprio_key(0));
+		assert!(ioprio_key(0) < ioprio_key(idle));
+	}
+}
@@ -23,7 +23,7 @@ use crate::device::{
 	BlockDeviceOps,
 	bar::BAR,
 	bus::pci,
-	storage::{PhysicalDevice, pata::PATAInterface},
+	storage::{PhysicalDevice, blk_mq::BlkMq, pata, pata::PATAInterface},
 };
 use utils::{boxed::Box, errno::AllocResult};
 
@@ -178,6 +178,10 @@ impl Controller {
 			})
 			// TODO log errors?
 			.filter_map(|(channel, slave)| PATAInterface::new(channel, slave).ok())
-			.map(|i| Box::new(i).map(|a| a as Box<dyn BlockDeviceOps>))
+			// A channel has a single hardware queue
+			.map(|i| {
+				let mq = BlkMq::new(i, 1, pata::QUEUE_DEPTH)?;
+				Box::new(mq).map(|a| a as Box<dyn BlockDeviceOps>)
+			})
 	}
 }
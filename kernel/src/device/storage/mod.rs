@@ -18,9 +18,14 @@
 
 //! Storage management implementation.
 
+pub mod blk_mq;
+pub mod elevator;
 pub mod ide;
 pub mod partition;
+pub mod passthrough;
 pub mod pata;
+pub mod recovery;
+pub mod verity;
 
 use crate::{
 	device,
@@ -112,7 +117,16 @@ impl BlockDeviceOps for PartitionOps {
 
 	fn write_pages(&self, off: u64, buf: &[u8]) -> EResult<()> {
 		if off < self.partition.size {
-			self.dev.ops.write_pages(self.partition.offset + off, buf)
+			self.dev.write_pages(self.partition.offset + off, buf)
+		} else {
+			Err(errno!(EINVAL))
+		}
+	}
+
+	fn discard(&self, off: u64, count: u64) -> EResult<()> {
+		let end = off.checked_add(count).ok_or_else(|| errno!(EINVAL))?;
+		if end <= self.partition.size {
+			self.dev.discard(self.partition.offset + off, count)
 		} else {
 			Err(errno!(EINVAL))
 		}
@@ -157,7 +171,8 @@ impl BlockDeviceOps for PartitionOps {
 				size_ptr.copy_to_user(&size)?;
 				Ok(0)
 			}
-			_ => Err(errno!(ENOTTY)),
+			// Commands addressed to the underlying disk
+			_ => self.dev.ops.ioctl(request, argp),
 		}
 	}
 }
@@ -176,7 +191,7 @@ impl StorageManager {
 	/// Creates a new instance.
 	pub fn new() -> EResult<Self> {
 		Ok(Self {
-			major_block: id::alloc_major(DeviceType::Block, Some(STORAGE_MAJOR))?,
+			major_block: id::alloc_major(DeviceType::Block, b"sd", Some(STORAGE_MAJOR))?,
 			interfaces: Vec::new(),
 		})
 	}
@@ -230,10 +245,13 @@ impl StorageManager {
 	pub fn clear_partitions(major: u32) -> EResult<()> {
 		let mut blk_devices = BLK_DEVICES.lock();
 		for i in 1..MAX_PARTITIONS {
-			blk_devices.remove(&DeviceID {
+			let id = DeviceID {
 				major,
 				minor: i as _,
-			});
+			};
+			if blk_devices.remove(&id).is_some() {
+				device::notify(DeviceType::Block, id, true);
+			}
 		}
 		Ok(())
 	}
@@ -0,0 +1,463 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Passthrough of raw commands to disks, allowing userspace tools (such as `smartctl` or
+//! `hdparm`) to send commands the kernel does not know about.
+//!
+//! Two interfaces are supported:
+//! - `HDIO_DRIVE_CMD`, which sends an ATA command
+//! - `SG_IO`, which sends a SCSI command. ATA disks only support the `ATA PASS-THROUGH` commands
+//!   (which wrap an ATA command) and `INQUIRY`
+
+use crate::{
+	file::perm::CAP_SYS_RAWIO,
+	memory::user::{UserPtr, UserSlice},
+	process::Process,
+	syscall::FromSyscallArg,
+};
+use core::{
+	ffi::{c_int, c_uchar, c_uint, c_ushort, c_void},
+	hint::unlikely,
+};
+use utils::{errno, errno::EResult, vec};
+
+/// The size of a sector transferred by ATA commands, in bytes.
+pub const ATA_SECTOR_SIZE: usize = 512;
+/// The maximum size of a data transfer, in bytes.
+const MAX_TRANSFER: usize = 256 * ATA_SECTOR_SIZE;
+
+/// ATA status: an error occurred.
+pub const ATA_STATUS_ERR: u8 = 0x01;
+
+/// ATA command: identify device.
+const ATA_CMD_IDENTIFY: u8 = 0xec;
+/// ATA command: SMART.
+const ATA_CMD_SMART: u8 = 0xb0;
+/// The value of the LBA mid register required by SMART commands.
+const SMART_LBAM_PASS: u64 = 0x4f;
+/// The value of the LBA high register required by SMART commands.
+const SMART_LBAH_PASS: u64 = 0xc2;
+
+/// The data transfer of an ATA command.
+#[derive(Debug)]
+pub enum AtaData<'b> {
+	/// No data is transferred.
+	None,
+	/// Data is read from the device (PIO Data-In).
+	In(&'b mut [u8]),
+	/// Data is written to the device (PIO Data-Out).
+	Out(&'b [u8]),
+}
+
+/// The registers of an ATA command.
+///
+/// After the execution of the command, the structure contains the registers returned by the
+/// device.
+#[derive(Debug, Default)]
+pub struct Taskfile {
+	/// Tells whether the command uses 48-bit registers.
+	pub lba48: bool,
+	/// The features register (error register on output).
+	pub feature: u16,
+	/// The sector count register.
+	pub nsect: u16,
+	/// The LBA registers.
+	pub lba: u64,
+	/// The device register.
+	pub device: u8,
+	/// The command register (status register on output).
+	pub command: u8,
+}
+
+/// A disk able to execute raw ATA commands.
+pub trait AtaDevice {
+	/// Executes the ATA command described by `tf`, transferring `data`.
+	///
+	/// If the device reports an error, the function succeeds: the error is reported through the
+	/// registers in `tf`. The function returns an error only if the command could not be executed.
+	fn exec_command(&self, tf: &mut Taskfile, data: AtaData) -> EResult<()>;
+}
+
+/// Checks the current process is allowed to send raw commands to devices.
+fn check_privileges() -> EResult<()> {
+	if Process::current()
+		.fs
+		.lock()
+		.access_profile
+		.has_capability(CAP_SYS_RAWIO)
+	{
+		Ok(())
+	} else {
+		Err(errno!(EACCES))
+	}
+}
+
+/// Performs the `HDIO_DRIVE_CMD` ioctl.
+///
+/// `argp` points to 4 bytes containing the command, the sector number (or the sector count),
+/// the features and the sector count. Read data follows.
+pub fn drive_cmd(dev: &dyn AtaDevice, argp: *const c_void) -> EResult<u32> {
+	check_privileges()?;
+	if unlikely(argp.is_null()) {
+		return Err(errno!(EINVAL));
+	}
+	let args_ptr = UserSlice::<u8>::from_user(argp as _, 4)?;
+	let mut args = [0u8; 4];
+	args_ptr.copy_from_user(0, &mut args)?;
+	let mut buf = vec![0u8; args[3] as usize * ATA_SECTOR_SIZE]?;
+	let mut tf = Taskfile {
+		feature: args[2] as _,
+		command: args[0],
+		..Default::default()
+	};
+	if args[0] == ATA_CMD_SMART {
+		tf.nsect = args[3] as _;
+		tf.lba = args[1] as u64 | (SMART_LBAM_PASS << 8) | (SMART_LBAH_PASS << 16);
+	} else {
+		tf.nsect = args[1] as _;
+	}
+	let data = if !buf.is_empty() {
+		AtaData::In(&mut buf)
+	} else {
+		AtaData::None
+	};
+	dev.exec_command(&mut tf, data)?;
+	// Write back registers, then data
+	args[0] = tf.command;
+	args[1] = tf.feature as _;
+	args[2] = tf.nsect as _;
+	args_ptr.copy_to_user(0, &args)?;
+	if !buf.is_empty() {
+		let buf_ptr = UserSlice::<u8>::from_user((argp as *mut u8).wrapping_add(4), buf.len())?;
+		buf_ptr.copy_to_user(0, &buf)?;
+	}
+	if tf.command & ATA_STATUS_ERR != 0 {
+		return Err(errno!(EIO));
+	}
+	Ok(0)
+}
+
+/// `SG_IO` request header.
+#[derive(Debug)]
+#[repr(C)]
+struct SgIoHdr {
+	/// Must be `'S'`.
+	interface_id: c_int,
+	/// The direction of the data transfer.
+	dxfer_direction: c_int,
+	/// The length of the SCSI command.
+	cmd_len: c_uchar,
+	/// The maximum length of the sense buffer.
+	mx_sb_len: c_uchar,
+	/// The number of elements of the scatter-gather list. Zero if the buffer is flat.
+	iovec_count: c_ushort,
+	/// The length of the data transfer.
+	dxfer_len: c_uint,
+	/// The data buffer.
+	dxferp: *mut c_void,
+	/// The SCSI command.
+	cmdp: *mut c_uchar,
+	/// The sense buffer.
+	sbp: *mut c_uchar,
+	/// The timeout, in milliseconds.
+	timeout: c_uint,
+	/// Request flags.
+	flags: c_uint,
+	/// Request identifier, unused.
+	pack_id: c_int,
+	/// User data, unused.
+	usr_ptr: *mut c_void,
+	/// The SCSI status.
+	status: c_uchar,
+	/// The SCSI status, shifted.
+	masked_status: c_uchar,
+	/// Messaging level data, unused.
+	msg_status: c_uchar,
+	/// The number of bytes written to the sense buffer.
+	sb_len_wr: c_uchar,
+	/// Host adapter errors.
+	host_status: c_ushort,
+	/// Driver errors.
+	driver_status: c_ushort,
+	/// The number of bytes that have not been transferred.
+	resid: c_int,
+	/// The duration of the command, in milliseconds.
+	duration: c_uint,
+	/// Auxiliary information.
+	info: c_uint,
+}
+
+/// `SG_IO` transfer direction: no data.
+const SG_DXFER_NONE: c_int = -1;
+/// `SG_IO` transfer direction: to the device.
+const SG_DXFER_TO_DEV: c_int = -2;
+/// `SG_IO` transfer direction: from the device.
+const SG_DXFER_FROM_DEV: c_int = -3;
+
+/// SCSI command: INQUIRY.
+const SCSI_INQUIRY: u8 = 0x12;
+/// SCSI command: ATA PASS-THROUGH (12).
+const SCSI_ATA_12: u8 = 0xa1;
+/// SCSI command: ATA PASS-THROUGH (16).
+const SCSI_ATA_16: u8 = 0x85;
+
+/// SCSI status: the command succeeded.
+const SAM_STAT_GOOD: u8 = 0x00;
+/// SCSI status: sense data is available.
+const SAM_STAT_CHECK_CONDITION: u8 = 0x02;
+/// Driver status: sense data is available.
+const DRIVER_SENSE: c_ushort = 0x08;
+/// Info flag: the command did not complete normally.
+const SG_INFO_CHECK: c_uint = 0x1;
+
+/// Sense key: recovered error.
+const SENSE_RECOVERED_ERROR: u8 = 0x01;
+/// Sense key: illegal request.
+const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+/// Sense key: aborted command.
+const SENSE_ABORTED_COMMAND: u8 = 0x0b;
+
+/// ATA protocol: non-data.
+const ATA_PROT_NON_DATA: u8 = 3;
+/// ATA protocol: PIO Data-In.
+const ATA_PROT_PIO_IN: u8 = 4;
+/// ATA protocol: PIO Data-Out.
+const ATA_PROT_PIO_OUT: u8 = 5;
+
+/// The result of a SCSI command.
+struct ScsiResult {
+	/// The SCSI status.
+	status: u8,
+	/// The sense data.
+	sense: [u8; 22],
+	/// The length of the sense data.
+	sense_len: usize,
+	/// The number of bytes transferred.
+	transferred: usize,
+}
+
+impl ScsiResult {
+	/// Returns a successful result, with `transferred` bytes transferred.
+	fn good(transferred: usize) -> Self {
+		Self {
+			status: SAM_STAT_GOOD,
+			sense: [0; 22],
+			sense_len: 0,
+			transferred,
+		}
+	}
+
+	/// Returns a result with sense data in descriptor format, with the given key and additional
+	/// sense code.
+	fn check(key: u8, asc: u8, ascq: u8) -> Self {
+		let mut res = Self {
+			status: SAM_STAT_CHECK_CONDITION,
+			sense: [0; 22],
+			sense_len: 8,
+			transferred: 0,
+		};
+		res.sense[..4].copy_from_slice(&[0x72, key, asc, ascq]);
+		res
+	}
+}
+
+/// Executes an `ATA PASS-THROUGH` command described by `cdb`, with the data buffer `buf`.
+fn ata_passthrough(dev: &dyn AtaDevice, cdb: &[u8], dir: c_int, buf: &mut [u8]) -> ScsiResult {
+	let invalid = || ScsiResult::check(SENSE_ILLEGAL_REQUEST, 0x24, 0x00);
+	let ata16 = cdb[0] == SCSI_ATA_16;
+	if unlikely(cdb.len() < if ata16 { 16 } else { 12 }) {
+		return invalid();
+	}
+	let protocol = (cdb[1] >> 1) & 0xf;
+	let ck_cond = cdb[2] & (1 << 5) != 0;
+	let mut tf = if ata16 {
+		let extend = cdb[1] & 1 != 0;
+		let hob = |i: usize| if extend { cdb[i] as u64 } else { 0 };
+		Taskfile {
+			lba48: extend,
+			feature: ((hob(3) << 8) | cdb[4] as u64) as _,
+			nsect: ((hob(5) << 8) | cdb[6] as u64) as _,
+			lba: cdb[8] as u64
+				| ((cdb[10] as u64) << 8)
+				| ((cdb[12] as u64) << 16)
+				| (hob(7) << 24)
+				| (hob(9) << 32)
+				| (hob(11) << 40),
+			device: cdb[13],
+			command: cdb[14],
+		}
+	} else {
+		Taskfile {
+			lba48: false,
+			feature: cdb[3] as _,
+			nsect: cdb[4] as _,
+			lba: cdb[5] as u64 | ((cdb[6] as u64) << 8) | ((cdb[7] as u64) << 16),
+			device: cdb[8],
+			command: cdb[9],
+		}
+	};
+	let len = buf.len();
+	let data = match (protocol, dir) {
+		(ATA_PROT_NON_DATA, _) => AtaData::None,
+		(ATA_PROT_PIO_IN, SG_DXFER_FROM_DEV) => AtaData::In(buf),
+		(ATA_PROT_PIO_OUT, SG_DXFER_TO_DEV) => AtaData::Out(buf),
+		// DMA protocols and inconsistent directions are not supported
+		_ => return invalid(),
+	};
+	let transferred = if matches!(data, AtaData::None) {
+		0
+	} else {
+		len
+	};
+	if dev.exec_command(&mut tf, data).is_err() {
+		return ScsiResult::check(SENSE_ABORTED_COMMAND, 0x00, 0x00);
+	}
+	let error = tf.command & ATA_STATUS_ERR != 0;
+	if !error && !ck_cond {
+		return ScsiResult::good(transferred);
+	}
+	// Return the registers in an ATA Status Return sense descriptor
+	let mut res = if error {
+		ScsiResult::check(SENSE_ABORTED_COMMAND, 0x00, 0x00)
+	} else {
+		// ATA PASS-THROUGH INFORMATION AVAILABLE
+		ScsiResult::check(SENSE_RECOVERED_ERROR, 0x00, 0x1d)
+	};
+	res.sense[7] = 14;
+	res.sense[8..].copy_from_slice(&[
+		0x09,
+		0x0c,
+		tf.lba48 as u8,
+		tf.feature as u8,
+		(tf.nsect >> 8) as u8,
+		tf.nsect as u8,
+		(tf.lba >> 24) as u8,
+		tf.lba as u8,
+		(tf.lba >> 32) as u8,
+		(tf.lba >> 8) as u8,
+		(tf.lba >> 40) as u8,
+		(tf.lba >> 16) as u8,
+		tf.device,
+		tf.command,
+	]);
+	res.sense_len = res.sense.len();
+	res.transferred = transferred;
+	res
+}
+
+/// Executes an `INQUIRY` command, returning the standard inquiry data built from the device's
+/// identification.
+fn inquiry(dev: &dyn AtaDevice, cdb: &[u8], buf: &mut [u8]) -> ScsiResult {
+	// Vital product data pages are not supported
+	if cdb.len() < 6 || cdb[1] & 1 != 0 {
+		return ScsiResult::check(SENSE_ILLEGAL_REQUEST, 0x24, 0x00);
+	}
+	let mut id = [0u8; ATA_SECTOR_SIZE];
+	let mut tf = Taskfile {
+		command: ATA_CMD_IDENTIFY,
+		..Default::default()
+	};
+	if dev.exec_command(&mut tf, AtaData::In(&mut id)).is_err() || tf.command & ATA_STATUS_ERR != 0
+	{
+		return ScsiResult::check(SENSE_ABORTED_COMMAND, 0x00, 0x00);
+	}
+	// Strings in the identification data are made of byte-swapped words
+	let ata_string = |dst: &mut [u8], start: usize| {
+		for (i, b) in dst.iter_mut().enumerate() {
+			*b = id[start + (i ^ 1)];
+		}
+	};
+	let mut data = [0u8; 36];
+	// Direct access block device, SPC-3, response data format 2
+	data[..5].copy_from_slice(&[0x00, 0x00, 0x05, 0x02, 31]);
+	data[8..16].copy_from_slice(b"ATA     ");
+	// Model number: words 27 to 46
+	ata_string(&mut data[16..32], 27 * 2);
+	// Firmware revision: words 23 to 26
+	ata_string(&mut data[32..36], 23 * 2);
+	let len = buf.len().min(data.len());
+	buf[..len].copy_from_slice(&data[..len]);
+	ScsiResult::good(len)
+}
+
+/// Performs the `SG_IO` ioctl.
+pub fn sg_io(dev: &dyn AtaDevice, argp: *const c_void) -> EResult<u32> {
+	check_privileges()?;
+	let hdr_ptr = UserPtr::<SgIoHdr>::from_ptr(argp as usize);
+	let mut hdr = hdr_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	if unlikely(hdr.interface_id != b'S' as c_int) {
+		return Err(errno!(EINVAL));
+	}
+	// TODO support scatter-gather lists
+	if unlikely(hdr.iovec_count != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let cmd_len = hdr.cmd_len as usize;
+	if unlikely(!(6..=16).contains(&cmd_len)) {
+		return Err(errno!(EINVAL));
+	}
+	let mut cdb = [0u8; 16];
+	UserSlice::from_user(hdr.cmdp, cmd_len)?.copy_from_user(0, &mut cdb[..cmd_len])?;
+	let cdb = &cdb[..cmd_len];
+	// Get data
+	let dir = hdr.dxfer_direction;
+	let len = match dir {
+		SG_DXFER_NONE => 0,
+		SG_DXFER_TO_DEV | SG_DXFER_FROM_DEV => hdr.dxfer_len as usize,
+		_ => return Err(errno!(EINVAL)),
+	};
+	if unlikely(len > MAX_TRANSFER) {
+		return Err(errno!(ENOMEM));
+	}
+	let data_ptr = UserSlice::<u8>::from_user(hdr.dxferp as _, len)?;
+	let mut buf = vec![0u8; len]?;
+	if dir == SG_DXFER_TO_DEV {
+		data_ptr.copy_from_user(0, &mut buf)?;
+	}
+	// Execute
+	let res = match cdb[0] {
+		SCSI_ATA_12 | SCSI_ATA_16 if len.is_multiple_of(ATA_SECTOR_SIZE) => {
+			ata_passthrough(dev, cdb, dir, &mut buf)
+		}
+		SCSI_ATA_12 | SCSI_ATA_16 => ScsiResult::check(SENSE_ILLEGAL_REQUEST, 0x24, 0x00),
+		SCSI_INQUIRY if dir == SG_DXFER_FROM_DEV => inquiry(dev, cdb, &mut buf),
+		// INVALID COMMAND OPERATION CODE
+		_ => ScsiResult::check(SENSE_ILLEGAL_REQUEST, 0x20, 0x00),
+	};
+	// Write results
+	if dir == SG_DXFER_FROM_DEV {
+		data_ptr.copy_to_user(0, &buf[..res.transferred])?;
+	}
+	let sb_len = res.sense_len.min(hdr.mx_sb_len as usize);
+	UserSlice::from_user(hdr.sbp, sb_len)?.copy_to_user(0, &res.sense[..sb_len])?;
+	hdr.status = res.status;
+	hdr.masked_status = res.status >> 1;
+	hdr.msg_status = 0;
+	hdr.sb_len_wr = sb_len as _;
+	hdr.host_status = 0;
+	hdr.driver_status = if res.sense_len > 0 { DRIVER_SENSE } else { 0 };
+	hdr.resid = (len - res.transferred) as _;
+	hdr.duration = 0;
+	hdr.info = if res.status != SAM_STAT_GOOD {
+		SG_INFO_CHECK
+	} else {
+		0
+	};
+	hdr_ptr.copy_to_user(&hdr)?;
+	Ok(0)
+}
@@ -31,15 +31,22 @@
 
 use crate::{
 	arch::x86::io::inb,
-	device::{BlockDeviceOps, storage::ide},
-	memory::{
-		buddy::{FrameOrder, ZONE_KERNEL},
-		cache::{FrameOwner, RcFrame},
+	device::storage::{
+		blk_mq::{MqOps, Request, Submitted},
+		elevator::Elevator,
+		ide, passthrough,
+		passthrough::{ATA_SECTOR_SIZE, AtaData, AtaDevice, Taskfile},
+		recovery::Deadline,
 	},
-	sync::mutex::Mutex,
+	syscall::ioctl,
+};
+use core::{ffi::c_void, hint::unlikely, num::NonZeroU64};
+use utils::{
+	bytes::{slice_from_bytes, slice_from_bytes_mut},
+	errno,
+	errno::EResult,
+	limits::PAGE_SIZE,
 };
-use core::{hint::unlikely, num::NonZeroU64};
-use utils::{bytes::slice_from_bytes, errno, errno::EResult, limits::PAGE_SIZE};
 
 /// Offset to the data register
 const DATA_REGISTER_OFFSET: u16 = 0;
@@ -108,11 +115,20 @@ const STATUS_RDY: u8 = 0b01000000;
 /// Indicates the drive is preparing to send/receive data
 const STATUS_BSY: u8 = 0b10000000;
 
+/// Control register: read the high order bytes of LBA48 registers.
+const CONTROL_HOB: u8 = 0b10000000;
+
 /// The size of a sector in bytes
 const SECTOR_SIZE: u64 = 512;
 /// The number of sectors per page of memory
 const SECTOR_PER_PAGE: u64 = PAGE_SIZE as u64 / SECTOR_SIZE;
 
+/// The number of requests that can be submitted to a disk at once.
+///
+/// A channel processes one command at a time, so submitted requests wait for the disk in the order
+/// of their I/O priority.
+pub const QUEUE_DEPTH: usize = 32;
+
 /// Applies a delay. `n` determines the amount to wait.
 ///
 /// This function is a dirty hack and the actual delay is approximate but
@@ -147,8 +163,8 @@ pub struct PATAInterface {
 	/// The number of sectors on the disk.
 	sectors_count: u64,
 
-	/// Mutex preventing data race on read/write operations.
-	lock: Mutex<()>,
+	/// Queue of read/write operations, preventing data races.
+	queue: Elevator,
 }
 
 impl PATAInterface {
@@ -167,7 +183,7 @@ impl PATAInterface {
 			lba48: false,
 			sectors_count: 0,
 
-			lock: Default::default(),
+			queue: Default::default(),
 		};
 		s.identify()?;
 		Ok(s)
@@ -240,11 +256,16 @@ impl PATAInterface {
 	/// Waits until the drive is not busy anymore.
 	///
 	/// If the drive wasn't busy, the function doesn't do anything.
-	fn wait_busy(&self) {
+	///
+	/// If the drive is still busy after `deadline`, the function returns [`errno::ETIMEDOUT`].
+	fn wait_busy(&self, deadline: &Deadline) -> EResult<()> {
 		if self.is_floating() {
-			return;
+			return Ok(());
 		}
-		while self.get_status() & STATUS_BSY != 0 {}
+		while self.get_status() & STATUS_BSY != 0 {
+			deadline.check()?;
+		}
+		Ok(())
 	}
 
 	/// Sends the given command on the bus.
@@ -276,13 +297,13 @@ impl PATAInterface {
 	}
 
 	/// Flushes the drive's cache. The device is assumed to be selected.
-	fn cache_flush(&self, lba48: bool) {
+	fn cache_flush(&self, lba48: bool, deadline: &Deadline) -> EResult<()> {
 		if lba48 {
 			self.send_command(COMMAND_CACHE_FLUSH_EXT);
 		} else {
 			self.send_command(COMMAND_CACHE_FLUSH);
 		}
-		self.wait_busy();
+		self.wait_busy(deadline)
 	}
 
 	/// Resets both master and slave devices.
@@ -319,7 +340,8 @@ impl PATAInterface {
 		if status == 0 {
 			return Err("Drive doesn't exist");
 		}
-		self.wait_busy();
+		self.wait_busy(&Deadline::new())
+			.map_err(|_| "Drive timed out")?;
 
 		let lba_mid = self.inb(PortOffset::Ata(LBA_MID_REGISTER_OFFSET));
 		let lba_hi = self.inb(PortOffset::Ata(LBA_HI_REGISTER_OFFSET));
@@ -418,7 +440,9 @@ impl PATAInterface {
 	/// Waits for the drive to be ready for IO operation.
 	///
 	/// The device is assumed to be selected.
-	fn wait_io(&self) -> EResult<()> {
+	///
+	/// If the drive is not ready after `deadline`, the function returns [`errno::ETIMEDOUT`].
+	fn wait_io(&self, deadline: &Deadline) -> EResult<()> {
 		loop {
 			let status = self.get_status();
 			if (status & STATUS_BSY == 0) && (status & STATUS_DRQ != 0) {
@@ -427,36 +451,22 @@ impl PATAInterface {
 			if (status & STATUS_ERR != 0) || (status & STATUS_DF != 0) {
 				return Err(errno!(EIO));
 			}
+			deadline.check()?;
 		}
 	}
-}
 
-impl BlockDeviceOps for PATAInterface {
-	fn block_size(&self) -> NonZeroU64 {
-		SECTOR_SIZE.try_into().unwrap()
-	}
-
-	fn blocks_count(&self) -> u64 {
-		self.sectors_count
+	/// Resets the drive after a failed request, so that the request can be retried.
+	fn recover(&self) -> EResult<()> {
+		self.reset();
+		self.select(true);
+		self.wait_busy(&Deadline::new())
 	}
 
-	fn read_frame(&self, off: u64, order: FrameOrder, owner: FrameOwner) -> EResult<RcFrame> {
-		let frame = RcFrame::new(order, ZONE_KERNEL, owner, off)?;
-		let off = off
-			.checked_mul(SECTOR_PER_PAGE)
-			.ok_or_else(|| errno!(EOVERFLOW))?;
-		let size = frame.pages_count() as u64 * SECTOR_PER_PAGE;
-		// If the offset and size are out of bounds of the disk, return an error
-		let end = off.checked_add(size).ok_or_else(|| errno!(EOVERFLOW))?;
-		if end > self.sectors_count {
-			return Err(errno!(EOVERFLOW));
-		}
-		// Avoid data race
-		let _guard = self.lock.lock();
-		// Select disk
-		self.select(false);
-		// Read
-		let buf = unsafe { frame.slice_mut() };
+	/// Reads `size` sectors at the sector offset `off` into `buf`.
+	///
+	/// The device is assumed to be selected.
+	fn read_sectors(&self, off: u64, size: u64, buf: &mut [u16]) -> EResult<()> {
+		let deadline = Deadline::new();
 		let mut i = 0;
 		while i < size {
 			let off = off + i;
@@ -465,7 +475,7 @@ impl BlockDeviceOps for PATAInterface {
 			let start = i as usize;
 			let end = start + count as usize;
 			for j in start..end {
-				self.wait_io()?;
+				self.wait_io(&deadline)?;
 				for k in 0..256 {
 					let index = j * 256 + k;
 					buf[index] = self.inw(PortOffset::Ata(DATA_REGISTER_OFFSET));
@@ -473,28 +483,14 @@ impl BlockDeviceOps for PATAInterface {
 			}
 			i += count as u64;
 		}
-		Ok(frame)
+		Ok(())
 	}
 
-	fn write_pages(&self, off: u64, buf: &[u8]) -> EResult<()> {
-		if unlikely(buf.len() % PAGE_SIZE != 0) {
-			return Err(errno!(EINVAL));
-		}
-		let off = off
-			.checked_mul(SECTOR_PER_PAGE)
-			.ok_or_else(|| errno!(EOVERFLOW))?;
-		let size = buf.len() as u64 / SECTOR_SIZE;
-		// If the offset and size are out of bounds of the disk, return an error
-		let end = off.checked_add(size).ok_or_else(|| errno!(EOVERFLOW))?;
-		if end > self.sectors_count {
-			return Err(errno!(EOVERFLOW));
-		}
-		// Avoid data race
-		let _guard = self.lock.lock();
-		// Select disk
-		self.select(false);
-		// Write
-		let buf = slice_from_bytes::<u16>(buf).unwrap();
+	/// Writes `size` sectors from `buf` at the sector offset `off`.
+	///
+	/// The device is assumed to be selected.
+	fn write_sectors(&self, off: u64, size: u64, buf: &[u16]) -> EResult<()> {
+		let deadline = Deadline::new();
 		let mut i = 0;
 		while i < size {
 			let off = off + i;
@@ -503,15 +499,192 @@ impl BlockDeviceOps for PATAInterface {
 			let start = i as usize;
 			let end = start + count as usize;
 			for j in start..end {
-				self.wait_io()?;
+				self.wait_io(&deadline)?;
 				for k in 0..256 {
 					let index = j * 256 + k;
 					self.outw(PortOffset::Ata(DATA_REGISTER_OFFSET), buf[index])
 				}
 			}
-			self.cache_flush(lba48);
+			self.cache_flush(lba48, &deadline)?;
 			i += count as u64;
 		}
 		Ok(())
 	}
 }
+
+impl MqOps for PATAInterface {
+	fn block_size(&self) -> NonZeroU64 {
+		SECTOR_SIZE.try_into().unwrap()
+	}
+
+	fn blocks_count(&self) -> u64 {
+		self.sectors_count
+	}
+
+	fn submit(&self, _hwq: usize, _tag: usize, req: &mut Request) -> EResult<Submitted> {
+		// Bounds have been checked on submission
+		let res = match req {
+			Request::Read {
+				off,
+				buf,
+			} => {
+				let off = *off * SECTOR_PER_PAGE;
+				let size = buf.len() as u64 / SECTOR_SIZE;
+				let buf = slice_from_bytes_mut::<u16>(buf).unwrap();
+				// Avoid data race
+				let _guard = self.queue.lock()?;
+				self.select(false);
+				self.read_sectors(off, size, buf)
+			}
+			Request::Write {
+				off,
+				buf,
+			} => {
+				let off = *off * SECTOR_PER_PAGE;
+				let size = buf.len() as u64 / SECTOR_SIZE;
+				let buf = slice_from_bytes::<u16>(buf).unwrap();
+				// Avoid data race
+				let _guard = self.queue.lock()?;
+				self.select(false);
+				self.write_sectors(off, size, buf)
+			}
+			Request::Discard {
+				..
+			} => return Err(errno!(EOPNOTSUPP)),
+		};
+		// Programmed I/O completes synchronously
+		Ok(Submitted::Done(res))
+	}
+
+	fn reset(&self) -> EResult<()> {
+		// Avoid resetting the drive while another request is in progress
+		let _guard = self.queue.lock()?;
+		self.recover()
+	}
+
+	fn ioctl(&self, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			ioctl::HDIO_DRIVE_CMD => passthrough::drive_cmd(self, argp),
+			ioctl::SG_IO => passthrough::sg_io(self, argp),
+			_ => Err(errno!(ENOTTY)),
+		}
+	}
+}
+
+impl AtaDevice for PATAInterface {
+	fn exec_command(&self, tf: &mut Taskfile, mut data: AtaData) -> EResult<()> {
+		let len = match &data {
+			AtaData::None => 0,
+			AtaData::In(buf) => buf.len(),
+			AtaData::Out(buf) => buf.len(),
+		};
+		if unlikely(!len.is_multiple_of(ATA_SECTOR_SIZE)) {
+			return Err(errno!(EINVAL));
+		}
+		let deadline = Deadline::new();
+		// Avoid data race
+		let _guard = self.queue.lock()?;
+		// Select disk, keeping the LBA bits requested by the command
+		let mut drive = (tf.device & !(1 << 4)) | SELECT_MASTER;
+		if self.slave {
+			drive |= 1 << 4;
+		}
+		self.outb(PortOffset::Ata(DRIVE_REGISTER_OFFSET), drive);
+		delay(420);
+		self.wait_busy(&deadline)?;
+		// Write registers
+		if tf.lba48 {
+			self.outb(
+				PortOffset::Ata(FEATURES_REGISTER_OFFSET),
+				(tf.feature >> 8) as u8,
+			);
+			self.outb(
+				PortOffset::Ata(SECTORS_COUNT_REGISTER_OFFSET),
+				(tf.nsect >> 8) as u8,
+			);
+			self.outb(
+				PortOffset::Ata(LBA_LO_REGISTER_OFFSET),
+				(tf.lba >> 24) as u8,
+			);
+			self.outb(
+				PortOffset::Ata(LBA_MID_REGISTER_OFFSET),
+				(tf.lba >> 32) as u8,
+			);
+			self.outb(
+				PortOffset::Ata(LBA_HI_REGISTER_OFFSET),
+				(tf.lba >> 40) as u8,
+			);
+		}
+		self.outb(PortOffset::Ata(FEATURES_REGISTER_OFFSET), tf.feature as u8);
+		self.outb(
+			PortOffset::Ata(SECTORS_COUNT_REGISTER_OFFSET),
+			tf.nsect as u8,
+		);
+		self.outb(PortOffset::Ata(LBA_LO_REGISTER_OFFSET), tf.lba as u8);
+		self.outb(
+			PortOffset::Ata(LBA_MID_REGISTER_OFFSET),
+			(tf.lba >> 8) as u8,
+		);
+		self.outb(
+			PortOffset::Ata(LBA_HI_REGISTER_OFFSET),
+			(tf.lba >> 16) as u8,
+		);
+		self.send_command(tf.command);
+		delay(420);
+		// Transfer data, one sector at a time
+		for i in 0..len / ATA_SECTOR_SIZE {
+			let status = loop {
+				let status = self.get_status();
+				if status & STATUS_BSY == 0 {
+					break status;
+				}
+				deadline.check()?;
+			};
+			// The device refused the command or did not request data
+			if status & (STATUS_ERR | STATUS_DF) != 0 || status & STATUS_DRQ == 0 {
+				break;
+			}
+			let sector = i * ATA_SECTOR_SIZE..(i + 1) * ATA_SECTOR_SIZE;
+			match &mut data {
+				AtaData::None => {}
+				AtaData::In(buf) => {
+					for word in buf[sector].chunks_exact_mut(2) {
+						let val = self.inw(PortOffset::Ata(DATA_REGISTER_OFFSET));
+						word.copy_from_slice(&val.to_le_bytes());
+					}
+				}
+				AtaData::Out(buf) => {
+					for word in buf[sector].chunks_exact(2) {
+						let val = u16::from_le_bytes([word[0], word[1]]);
+						self.outw(PortOffset::Ata(DATA_REGISTER_OFFSET), val);
+					}
+				}
+			}
+		}
+		self.wait_busy(&deadline)?;
+		// Read back registers
+		let read_regs = || {
+			[
+				self.inb(PortOffset::Ata(ERROR_REGISTER_OFFSET)),
+				self.inb(PortOffset::Ata(SECTORS_COUNT_REGISTER_OFFSET)),
+				self.inb(PortOffset::Ata(LBA_LO_REGISTER_OFFSET)),
+				self.inb(PortOffset::Ata(LBA_MID_REGISTER_OFFSET)),
+				self.inb(PortOffset::Ata(LBA_HI_REGISTER_OFFSET)),
+			]
+		};
+		let [error, nsect, lbal, lbam, lbah] = read_regs();
+		tf.feature = error as _;
+		tf.nsect = nsect as _;
+		tf.lba = lbal as u64 | ((lbam as u64) << 8) | ((lbah as u64) << 16);
+		tf.device = self.inb(PortOffset::Ata(DRIVE_REGISTER_OFFSET));
+		if tf.lba48 {
+			self.outb(PortOffset::Control(0), CONTROL_HOB);
+			let [_, nsect, lbal, lbam, lbah] = read_regs();
+			self.outb(PortOffset::Control(0), 0);
+			tf.nsect |= (nsect as u16) << 8;
+			tf.lba |= ((lbal as u64) << 24) | ((lbam as u64) << 32) | ((lbah as u64) << 40);
+		}
+		tf.command = self.get_status();
+		Ok(())
+	}
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Timeout and error recovery policy for requests to storage devices.
+//!
+//! A request that does not complete before its deadline fails with [`errno::ETIMEDOUT`]. When a
+//! request fails with a transient error, the device is reset and the request is retried a limited
+//! number of times. If it still fails, the error is reported to the caller as [`errno::EIO`], so
+//! that a faulty device cannot hang the processes using it.
+
+use crate::{
+	println,
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::Timestamp,
+	},
+};
+use utils::{
+	errno,
+	errno::{EResult, Errno},
+};
+
+/// The maximum duration of a request, in nanoseconds.
+pub const REQUEST_TIMEOUT: Timestamp = 30_000_000_000;
+/// The number of times a failing request is retried.
+pub const MAX_RETRIES: u32 = 3;
+
+/// The instant after which a request is considered as timed out.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Timestamp);
+
+impl Deadline {
+	/// Returns the deadline of a request starting now.
+	pub fn new() -> Self {
+		Self(current_time_ns(Clock::Monotonic).saturating_add(REQUEST_TIMEOUT))
+	}
+
+	/// Returns the remaining duration before the deadline, in nanoseconds.
+	pub fn remaining(&self) -> Timestamp {
+		self.0.saturating_sub(current_time_ns(Clock::Monotonic))
+	}
+
+	/// Tells whether the deadline has passed.
+	pub fn expired(&self) -> bool {
+		current_time_ns(Clock::Monotonic) >= self.0
+	}
+
+	/// Returns an error if the deadline has passed.
+	pub fn check(&self) -> EResult<()> {
+		if self.expired() {
+			Err(errno!(ETIMEDOUT))
+		} else {
+			Ok(())
+		}
+	}
+}
+
+impl Default for Deadline {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Tells whether a request failing with `e` may succeed if retried after a reset.
+pub fn is_transient(e: Errno) -> bool {
+	matches!(e.as_int(), errno::EIO | errno::ETIMEDOUT)
+}
+
+/// Executes the request `f`, resetting the device with `reset` and retrying on transient errors.
+///
+/// `name` is the name of the device, used in messages.
+///
+/// If the request still fails after [`MAX_RETRIES`] retries, the function returns
+/// [`errno::EIO`]. Errors that are not transient are returned directly.
+pub fn with_retry<T, F: FnMut() -> EResult<T>, R: FnMut() -> EResult<()>>(
+	name: &str,
+	mut f: F,
+	mut reset: R,
+) -> EResult<T> {
+	let mut retries = 0;
+	loop {
+		match f() {
+			Err(e) if is_transient(e) => {
+				if retries >= MAX_RETRIES {
+					println!("{name}: request failed after {MAX_RETRIES} retries ({e})");
+					return Err(errno!(EIO));
+				}
+				retries += 1;
+				println!("{name}: request failed ({e}), resetting device");
+				// If the device cannot be reset, there is no point in retrying
+				if reset().is_err() {
+					return Err(errno!(EIO));
+				}
+			}
+			res => return res,
+		}
+	}
+}
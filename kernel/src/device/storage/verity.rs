@@ -0,0 +1,361 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Read-only block device target verifying the integrity of the data it reads, compatible with
+//! Linux's dm-verity.
+//!
+//! The hash of each block of the data device is stored on the hash device, in a Merkle tree: the
+//! blocks of each level of the tree contain the hashes of the blocks of the level below, up to a
+//! single block whose hash is the **root digest**.
+//!
+//! The root digest is given on the kernel command line, so that the content of the device cannot
+//! be altered without being detected. Reading a block whose hash does not match the tree fails
+//! with [`errno::EIO`].
+//!
+//! Only the version 1 of the format is supported, with SHA-256 and blocks of the size of a page,
+//! which are the defaults of `veritysetup`.
+
+use crate::{
+	crypto::sha256::{DIGEST_SIZE, Sha256},
+	device,
+	device::{BLK_DEVICES, BlkDev, BlockDeviceOps, DeviceID, DeviceType, id},
+	file::Mode,
+	initcall,
+	memory::{
+		buddy::FrameOrder,
+		cache::{FrameOwner, RcFrame},
+	},
+	println,
+};
+use core::{hint::unlikely, mem::ManuallyDrop, num::NonZeroU64, str};
+use utils::{
+	boxed::Box,
+	collections::{path::PathBuf, vec::Vec},
+	errno,
+	errno::EResult,
+	limits::PAGE_SIZE,
+	ptr::arc::Arc,
+};
+
+/// The major number of verity devices.
+const VERITY_MAJOR: u32 = 253;
+/// The mode of the device file of a verity device.
+const VERITY_MODE: Mode = 0o440;
+/// The maximum size of the salt, in bytes.
+const MAX_SALT_SIZE: usize = 256;
+/// The log2 of the number of hashes in a hash block.
+const HASHES_PER_BLOCK_BITS: u32 = (PAGE_SIZE / DIGEST_SIZE).ilog2();
+
+/// Decodes the hexadecimal string `s` into `buf`.
+///
+/// If the string is invalid or does not have the size of `buf`, the function returns `None`.
+fn decode_hex(s: &[u8], buf: &mut [u8]) -> Option<()> {
+	if s.len() != buf.len() * 2 {
+		return None;
+	}
+	for (b, digits) in buf.iter_mut().zip(s.chunks_exact(2)) {
+		let digits = str::from_utf8(digits).ok()?;
+		*b = u8::from_str_radix(digits, 16).ok()?;
+	}
+	Some(())
+}
+
+/// Parses a device ID, in the form `major:minor`.
+fn parse_dev(s: &[u8]) -> Option<DeviceID> {
+	let s = str::from_utf8(s).ok()?;
+	let (major, minor) = s.split_once(':')?;
+	Some(DeviceID {
+		major: major.parse().ok()?,
+		minor: minor.parse().ok()?,
+	})
+}
+
+/// The parameters of a verity device.
+#[derive(Debug)]
+pub struct VerityTable<'s> {
+	/// The device containing the data.
+	pub data_dev: DeviceID,
+	/// The device containing the hash tree.
+	pub hash_dev: DeviceID,
+	/// The number of blocks on the data device.
+	pub data_blocks: u64,
+	/// The offset of the hash tree on the hash device, in blocks.
+	pub hash_start: u64,
+	/// The hash of the top block of the tree.
+	pub root_digest: [u8; DIGEST_SIZE],
+	/// The salt prepended to each block before hashing it, in hexadecimal.
+	pub salt: &'s [u8],
+}
+
+impl<'s> VerityTable<'s> {
+	/// Parses the value of the `verity=` command line argument.
+	///
+	/// The format is `<data dev>,<hash dev>,<data blocks>,<hash start>,<root digest>[,<salt>]`,
+	/// where devices are given as `major:minor`, and the root digest and salt in hexadecimal.
+	///
+	/// On error, the function returns a message describing the problem.
+	pub fn parse(s: &'s [u8]) -> Result<Self, &'static str> {
+		let mut fields = s.split(|c| *c == b',');
+		let mut next = || fields.next().filter(|f| !f.is_empty());
+		let (data_dev, hash_dev, data_blocks, hash_start, root_digest, salt) =
+			(next(), next(), next(), next(), next(), next());
+		if next().is_some() {
+			return Err("too many verity parameters");
+		}
+		let data_dev = data_dev
+			.and_then(parse_dev)
+			.ok_or("invalid verity data device")?;
+		let hash_dev = hash_dev
+			.and_then(parse_dev)
+			.ok_or("invalid verity hash device")?;
+		let data_blocks = data_blocks
+			.and_then(|n| str::from_utf8(n).ok()?.parse().ok())
+			.filter(|n| *n > 0)
+			.ok_or("invalid verity data blocks count")?;
+		let hash_start = hash_start
+			.and_then(|n| str::from_utf8(n).ok()?.parse().ok())
+			.ok_or("invalid verity hash start")?;
+		let mut digest = [0; DIGEST_SIZE];
+		root_digest
+			.and_then(|d| decode_hex(d, &mut digest))
+			.ok_or("invalid verity root digest")?;
+		let salt = salt.unwrap_or_default();
+		if salt.len() > MAX_SALT_SIZE * 2
+			|| decode_hex(salt, &mut [0; MAX_SALT_SIZE][..salt.len() / 2]).is_none()
+		{
+			return Err("invalid verity salt");
+		}
+		Ok(Self {
+			data_dev,
+			hash_dev,
+			data_blocks,
+			hash_start,
+			root_digest: digest,
+			salt,
+		})
+	}
+}
+
+/// Block device operations of a verity device.
+#[derive(Debug)]
+pub struct VerityOps {
+	/// The device containing the data.
+	data: Arc<BlkDev>,
+	/// The device containing the hash tree.
+	hash: Arc<BlkDev>,
+	/// The number of blocks on the data device.
+	data_blocks: u64,
+	/// The salt prepended to each block before hashing it.
+	salt: Vec<u8>,
+	/// The hash of the top block of the tree.
+	root_digest: [u8; DIGEST_SIZE],
+	/// For each level of the tree, starting from the bottom, the offset of its first block on
+	/// the hash device.
+	levels: Vec<u64>,
+}
+
+impl VerityOps {
+	/// Creates a new instance from the parameters `table`.
+	pub fn new(table: &VerityTable) -> EResult<Self> {
+		let get_dev = |id| {
+			BLK_DEVICES
+				.lock()
+				.get(&id)
+				.cloned()
+				.ok_or_else(|| errno!(ENODEV))
+		};
+		let data = get_dev(table.data_dev)?;
+		let hash = get_dev(table.hash_dev)?;
+		let dev_pages = |dev: &BlkDev| dev.ops.block_size().get() * dev.ops.blocks_count();
+		let data_len = table
+			.data_blocks
+			.checked_mul(PAGE_SIZE as u64)
+			.ok_or_else(|| errno!(EINVAL))?;
+		if unlikely(data_len > dev_pages(&data)) {
+			return Err(errno!(EINVAL));
+		}
+		let mut salt = Vec::new();
+		salt.resize(table.salt.len() / 2, 0)?;
+		decode_hex(table.salt, &mut salt).ok_or_else(|| errno!(EINVAL))?;
+		// Compute the layout of the tree. Levels are stored from the top to the bottom
+		let mut levels_count = 0;
+		while HASHES_PER_BLOCK_BITS * levels_count < u64::BITS
+			&& (table.data_blocks - 1) >> (HASHES_PER_BLOCK_BITS * levels_count) != 0
+		{
+			levels_count += 1;
+		}
+		let mut levels = Vec::new();
+		levels.resize(levels_count as usize, 0)?;
+		let mut pos = table.hash_start;
+		for (i, level) in levels.iter_mut().enumerate().rev() {
+			*level = pos;
+			let shift = HASHES_PER_BLOCK_BITS * (i as u32 + 1);
+			let count = (table.data_blocks - 1).checked_shr(shift).unwrap_or(0) + 1;
+			pos = pos.checked_add(count).ok_or_else(|| errno!(EINVAL))?;
+		}
+		let hash_len = pos
+			.checked_mul(PAGE_SIZE as u64)
+			.ok_or_else(|| errno!(EINVAL))?;
+		if unlikely(hash_len > dev_pages(&hash)) {
+			return Err(errno!(EINVAL));
+		}
+		Ok(Self {
+			data,
+			hash,
+			data_blocks: table.data_blocks,
+			salt,
+			root_digest: table.root_digest,
+			levels,
+		})
+	}
+
+	/// Returns the salted hash of `block`.
+	fn digest(&self, block: &[u8]) -> [u8; DIGEST_SIZE] {
+		let mut hasher = Sha256::new();
+		hasher.update(&self.salt);
+		hasher.update(block);
+		hasher.finalize()
+	}
+
+	/// Verifies the block `buf`, at the offset `off` on the data device, against the tree.
+	///
+	/// If the block is corrupted, the function returns [`errno::EIO`].
+	fn verify(&self, off: u64, buf: &[u8]) -> EResult<()> {
+		let mut digest = self.digest(buf);
+		for (i, start) in self.levels.iter().enumerate() {
+			// The index of the hash in the level
+			let index = off >> (HASHES_PER_BLOCK_BITS * i as u32);
+			let hash_off = start + (index >> HASHES_PER_BLOCK_BITS);
+			let hash_block = BlkDev::read_frame(
+				&self.hash,
+				hash_off,
+				0,
+				FrameOwner::BlkDev(self.hash.clone()),
+			)?;
+			let hash_block = hash_block.slice::<u8>();
+			let pos = (index & ((1 << HASHES_PER_BLOCK_BITS) - 1)) as usize * DIGEST_SIZE;
+			if unlikely(hash_block[pos..(pos + DIGEST_SIZE)] != digest) {
+				println!("verity: block {off} is corrupted (hash block {hash_off})");
+				return Err(errno!(EIO));
+			}
+			digest = self.digest(hash_block);
+		}
+		if unlikely(digest != self.root_digest) {
+			println!("verity: block {off} is corrupted (root digest mismatch)");
+			return Err(errno!(EIO));
+		}
+		Ok(())
+	}
+}
+
+impl BlockDeviceOps for VerityOps {
+	fn block_size(&self) -> NonZeroU64 {
+		NonZeroU64::new(PAGE_SIZE as _).unwrap()
+	}
+
+	fn blocks_count(&self) -> u64 {
+		self.data_blocks
+	}
+
+	fn read_frame(&self, off: u64, order: FrameOrder, owner: FrameOwner) -> EResult<RcFrame> {
+		let end = off
+			.checked_add(1 << order)
+			.ok_or_else(|| errno!(EOVERFLOW))?;
+		if unlikely(end > self.data_blocks) {
+			return Err(errno!(EOVERFLOW));
+		}
+		// Read from the driver directly, to make sure the data comes from the device
+		let frame = self.data.read_frame_direct(off, order, owner)?;
+		for (i, block) in frame.slice::<u8>().chunks_exact(PAGE_SIZE).enumerate() {
+			self.verify(off + i as u64, block)?;
+		}
+		Ok(frame)
+	}
+
+	fn write_pages(&self, _off: u64, _buf: &[u8]) -> EResult<()> {
+		Err(errno!(EROFS))
+	}
+
+	fn discard(&self, _off: u64, _count: u64) -> EResult<()> {
+		Err(errno!(EROFS))
+	}
+}
+
+/// Creates the verity device `/dev/dm-0` with the parameters `table`.
+pub fn create(table: &VerityTable) -> EResult<()> {
+	let _major = ManuallyDrop::new(id::alloc_major(
+		DeviceType::Block,
+		b"device-mapper",
+		Some(VERITY_MAJOR),
+	)?);
+	let ops = VerityOps::new(table)?;
+	let dev = BlkDev::new(
+		DeviceID {
+			major: VERITY_MAJOR,
+			minor: 0,
+		},
+		PathBuf::try_from(b"/dev/dm-0")?,
+		VERITY_MODE,
+		Box::new(ops)?,
+	)?;
+	device::register_blk(dev)?;
+	Ok(())
+}
+
+initcall!(late, verity, |args| {
+	if let Some(table) = args.get_verity_table() {
+		println!("Setting up verity device...");
+		create(table)?;
+	}
+	Ok(())
+});
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn verity_table() {
+		let table = VerityTable::parse(
+			b"8:1,8:2,1024,1,\
+			0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef,00ff",
+		)
+		.unwrap();
+		assert_eq!(
+			table.data_dev,
+			DeviceID {
+				major: 8,
+				minor: 1,
+			}
+		);
+		assert_eq!(table.data_blocks, 1024);
+		assert_eq!(table.hash_start, 1);
+		assert_eq!(table.root_digest[..2], [0x01, 0x23]);
+		assert_eq!(table.salt, b"00ff");
+		// Missing root digest
+		assert!(VerityTable::parse(b"8:1,8:2,1024,1").is_err());
+		// Invalid salt
+		assert!(
+			VerityTable::parse(
+				b"8:1,8:2,1024,1,\
+				0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef,0xff",
+			)
+			.is_err()
+		);
+	}
+}
@@ -20,7 +20,8 @@
 //! communicate with it.
 
 use crate::{
-	file::{File, fs::FileOps},
+	console,
+	file::{File, fs::FileOps, wait_queue::WaitQueue},
 	memory::user::{UserPtr, UserSlice},
 	process::{
 		Process,
@@ -34,7 +35,10 @@ use crate::{
 	tty::{TTY, TTYDisplay, WinSize, termios, termios::Termios},
 };
 use core::ffi::c_void;
-use utils::{errno, errno::EResult};
+use utils::{
+	errno,
+	errno::{AllocResult, EResult},
+};
 
 /// A TTY device's handle.
 #[derive(Debug)]
@@ -103,6 +107,18 @@ impl FileOps for TTYDeviceHandle {
 		Ok(res)
 	}
 
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(TTY.get_rd_queue())?;
+		}
+		Ok(true)
+	}
+
 	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
 		let mut tty = TTY.display.lock();
 		match request.get_old_format() {
@@ -157,14 +173,13 @@ impl FileOps for TTYDeviceHandle {
 	}
 
 	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
-		let mut tty = TTY.display.lock();
-		self.check_sigttou(&tty)?;
+		self.check_sigttou(&TTY.display.lock())?;
 		// Write
 		let mut i = 0;
 		let mut b: [u8; 128] = [0; 128];
 		while i < buf.len() {
 			let l = buf.copy_from_user(i, &mut b)?;
-			tty.write(&b[..l]);
+			console::write(&b[..l]);
 			i += l;
 		}
 		Ok(buf.len())
@@ -83,6 +83,8 @@ pub const EM_860: u16 = 7;
 pub const EM_MIPS: u16 = 8;
 /// Required architecture: MIPS RS4000 Big-Endian.
 pub const EM_MIPS_RS4_BE: u16 = 10;
+/// Required architecture: AMD x86-64.
+pub const EM_X86_64: u16 = 62;
 
 /// Program header type: Ignored.
 pub const PT_NULL: u32 = 0;
@@ -100,6 +102,10 @@ pub const PT_SHLIB: u32 = 5;
 pub const PT_PHDR: u32 = 6;
 /// Program header type: Thread-Local Storage (TLS).
 pub const PT_TLS: u32 = 7;
+/// Program header type: Stack permissions (GNU extension).
+pub const PT_GNU_STACK: u32 = 0x6474e551;
+/// Program header type: Range to be made read-only after relocation (GNU extension).
+pub const PT_GNU_RELRO: u32 = 0x6474e552;
 
 /// Segment flag: Execute.
 pub const PF_X: u32 = 0x1;
@@ -108,6 +114,15 @@ pub const PF_W: u32 = 0x2;
 /// Segment flag: Read.
 pub const PF_R: u32 = 0x4;
 
+/// Dynamic entry tag: End of the dynamic array.
+pub const DT_NULL: i64 = 0;
+/// Dynamic entry tag: Address of the relocation table with explicit addends.
+pub const DT_RELA: i64 = 7;
+/// Dynamic entry tag: Size in bytes of the `DT_RELA` table.
+pub const DT_RELASZ: i64 = 8;
+/// Dynamic entry tag: Size in bytes of one entry of the `DT_RELA` table.
+pub const DT_RELAENT: i64 = 9;
+
 /// The section header is inactive.
 pub const SHT_NULL: u32 = 0x0;
 /// The section holds information defined by the program.
@@ -163,6 +178,15 @@ pub const STT_FILE: u8 = 4;
 /// Thread-Local Storage (TLS) symbol.
 pub const STT_TLS: u8 = 6;
 
+/// Local symbols are not visible outside the object file containing their
+/// definition.
+pub const STB_LOCAL: u8 = 0;
+/// Global symbols are visible to all object files being combined.
+pub const STB_GLOBAL: u8 = 1;
+/// Weak symbols resemble global symbols, but their definitions have lower
+/// precedence.
+pub const STB_WEAK: u8 = 2;
+
 /// 32 bit ELF header.
 #[derive(AnyRepr, Clone, Debug)]
 #[repr(C)]
@@ -400,6 +424,17 @@ pub struct ELF64Rela {
 	pub r_addend: i64,
 }
 
+/// 64 bit ELF dynamic array entry.
+#[cfg(target_pointer_width = "64")]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ELF64Dyn {
+	/// The type of the entry.
+	pub d_tag: i64,
+	/// The value or address of the entry, interpreted according to the tag.
+	pub d_val: u64,
+}
+
 /// The hash function for an ELF hash table.
 pub fn hash_sym_name(name: &[u8]) -> u32 {
 	let res = name.iter().fold(0u32, |mut res, c| {
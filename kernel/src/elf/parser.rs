@@ -491,11 +491,8 @@ fn iter<'data, T: 'data + Parse>(
 pub struct ELFParser<'data>(&'data [u8]);
 
 impl<'data> ELFParser<'data> {
-	/// Creates a new instance for the given image.
-	///
-	/// The function checks if the image is valid. If not, the function returns
-	/// an error.
-	pub fn new(image: &'data [u8]) -> EResult<Self> {
+	/// Checks the identification and file header of `image`, then returns the file header.
+	fn check_hdr(image: &[u8]) -> EResult<FileHeader> {
 		// Check signature
 		if unlikely(image.len() < EI_NIDENT) {
 			return Err(errno!(EINVAL));
@@ -515,12 +512,14 @@ impl<'data> ELFParser<'data> {
 		}
 		// Get full header
 		let ehdr = FileHeader::parse(image, class).ok_or_else(|| errno!(EINVAL))?;
-		// Check machine type
-		let valid = match ehdr.e_machine {
-			// x86 | Intel 80860
-			0x3 | 0x7 => cfg!(target_arch = "x86") || cfg!(target_arch = "x86_64"),
-			// AMD x86_64
-			0x3e => cfg!(target_arch = "x86_64"),
+		// Check machine type. A 64-bit image can only target x86_64, while 32-bit images are run
+		// in compatibility mode
+		let valid = match (class, ehdr.e_machine) {
+			(Class::Bit32, EM_386 | EM_860) => {
+				cfg!(target_arch = "x86") || cfg!(target_arch = "x86_64")
+			}
+			#[cfg(target_pointer_width = "64")]
+			(Class::Bit64, EM_X86_64) => cfg!(target_arch = "x86_64"),
 			_ => false,
 		};
 		if unlikely(!valid) {
@@ -538,9 +537,30 @@ impl<'data> ELFParser<'data> {
 		if unlikely(ehdr.e_shstrndx >= ehdr.e_shnum) {
 			return Err(errno!(EINVAL));
 		}
-		let p = Self(image);
-		p.try_iter_segments()
-			.try_for_each(|phdr| phdr?.is_valid(image.len() as _))?;
+		Ok(ehdr)
+	}
+
+	/// Returns the number of bytes at the beginning of the image required to parse its file
+	/// header and program headers table.
+	///
+	/// `image` is the beginning of the image, which must at least contain the file header.
+	pub fn headers_len(image: &[u8]) -> EResult<usize> {
+		let ehdr = Self::check_hdr(image)?;
+		let phdrs_len = ehdr.e_phnum as u64 * ehdr.e_phentsize as u64;
+		let end = ehdr
+			.e_phoff
+			.checked_add(phdrs_len)
+			.ok_or_else(|| errno!(EINVAL))?;
+		let end = end.max(ehdr.e_ehsize as u64);
+		end.try_into().map_err(|_| errno!(EINVAL))
+	}
+
+	/// Creates a new instance for the given image.
+	///
+	/// The function checks if the image is valid. If not, the function returns
+	/// an error.
+	pub fn new(image: &'data [u8]) -> EResult<Self> {
+		let p = Self::new_headers(image, image.len() as _)?;
 		p.try_iter_sections()
 			.try_for_each(|shdr| shdr?.is_valid(image.len() as _))?;
 		// TODO check symbols
@@ -548,6 +568,20 @@ impl<'data> ELFParser<'data> {
 		Ok(p)
 	}
 
+	/// Creates a new instance for the beginning of an image, containing its file header and
+	/// program headers table (see [`Self::headers_len`]).
+	///
+	/// `size` is the size of the whole image. Segments are checked against it.
+	///
+	/// Since sections are not checked, they are not to be accessed on the returned instance.
+	pub fn new_headers(image: &'data [u8], size: u64) -> EResult<Self> {
+		Self::check_hdr(image)?;
+		let p = Self(image);
+		p.try_iter_segments()
+			.try_for_each(|phdr| phdr?.is_valid(size))?;
+		Ok(p)
+	}
+
 	/// Returns a slice to the raw ELF data.
 	pub fn as_slice(&self) -> &[u8] {
 		self.0
@@ -570,7 +604,7 @@ impl<'data> ELFParser<'data> {
 	/// If a section is out of bounds, the iterator returns an error.
 	fn try_iter_segments(&self) -> impl Iterator<Item = EResult<ProgramHeader>> + use<'data> {
 		let ehdr = self.hdr();
-		let table = &self.0[ehdr.e_phoff as usize..];
+		let table = self.0.get(ehdr.e_phoff as usize..).unwrap_or_default();
 		iter(
 			table,
 			self.class(),
@@ -589,7 +623,7 @@ impl<'data> ELFParser<'data> {
 	/// If a section is out of bounds, the iterator returns an error.
 	fn try_iter_sections(&self) -> impl Iterator<Item = EResult<SectionHeader>> + use<'data> {
 		let ehdr = self.hdr();
-		let table = &self.0[ehdr.e_shoff as usize..];
+		let table = self.0.get(ehdr.e_shoff as usize..).unwrap_or_default();
 		iter(
 			table,
 			self.class(),
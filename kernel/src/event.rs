@@ -19,13 +19,15 @@
 //! Interrupt callback register interface.
 
 use crate::{
-	arch::x86::{idt, idt::IntFrame, pic},
+	arch::x86::{apic, idt, idt::IntFrame, pic, smp},
 	crypto::rand,
 	memory::user::UserSlice,
 	process,
+	process::scheduler::core_local,
+	softirq,
 	sync::mutex::IntMutex,
 };
-use core::ptr;
+use core::{ptr, sync::atomic::Ordering::Relaxed};
 use utils::{bytes::as_bytes, collections::vec::Vec, errno::AllocResult};
 
 /// The list of interrupt error messages ordered by index of the corresponding
@@ -162,6 +164,14 @@ extern "C" fn interrupt_handler(frame: &mut IntFrame) {
 	let id = frame.int as u32;
 	let ring = (frame.cs & 0b11) as u8;
 	let code = frame.code as u32;
+	// Interrupts from the Local APIC. Send EOI first, since the callbacks may switch context
+	if id >= smp::IPI_RESCHEDULE as u32 {
+		// Spurious interrupts must not be acknowledged
+		if id == apic::SPURIOUS_VECTOR as u32 {
+			return;
+		}
+		apic::end_of_interrupt();
+	}
 	// Call corresponding callbacks
 	let callbacks = &CALLBACKS[id as usize];
 	let mut i = 0;
@@ -182,7 +192,12 @@ extern "C" fn interrupt_handler(frame: &mut IntFrame) {
 	}
 	// If not a hardware exception, send EOI
 	if let Some(irq) = id.checked_sub(ERROR_MESSAGES.len() as u32) {
-		pic::end_of_interrupt(irq as _);
+		core_local().stat.intr.fetch_add(1, Relaxed);
+		if irq < 16 {
+			pic::end_of_interrupt(irq as _);
+		}
+		// Run the bottom halves of interrupt handlers
+		softirq::run();
 	}
 	process::yield_current(ring, frame);
 }
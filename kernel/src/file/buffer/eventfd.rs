@@ -0,0 +1,185 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An eventfd is a 64 bits counter used as an event notification mechanism.
+//!
+//! Writing adds a value to the counter. Reading returns the counter and resets it to zero, or, in
+//! semaphore mode, returns `1` and decrements it.
+
+use crate::{
+	file::{File, FileType, O_NONBLOCK, Stat, fs::FileOps, wait_queue::WaitQueue},
+	memory::user::UserSlice,
+	sync::mutex::Mutex,
+	syscall::select::{POLLIN, POLLOUT},
+};
+use core::hint::unlikely;
+use utils::{
+	errno,
+	errno::{AllocResult, EResult},
+};
+
+/// The maximum value of the counter.
+const MAX: u64 = u64::MAX - 1;
+
+/// An eventfd counter.
+#[derive(Debug)]
+pub struct EventFd {
+	/// The counter.
+	counter: Mutex<u64>,
+	/// If set, reads decrement the counter by one instead of resetting it.
+	semaphore: bool,
+	/// The queue of processes waiting for the counter to be non-zero.
+	rd_queue: WaitQueue,
+	/// The queue of processes waiting for space in the counter.
+	wr_queue: WaitQueue,
+}
+
+impl EventFd {
+	/// Creates a new instance.
+	///
+	/// Arguments:
+	/// - `initval` is the initial value of the counter
+	/// - `semaphore` tells whether the counter works in semaphore mode
+	pub fn new(initval: u64, semaphore: bool) -> Self {
+		Self {
+			counter: Mutex::new(initval),
+			semaphore,
+			rd_queue: WaitQueue::new(),
+			wr_queue: WaitQueue::new(),
+		}
+	}
+
+	/// Takes a value from the counter.
+	///
+	/// If the counter is zero, the function blocks unless `nonblock` is set, in which case it
+	/// returns [`errno::EAGAIN`].
+	pub fn take(&self, nonblock: bool) -> EResult<u64> {
+		self.rd_queue.wait_until(|| {
+			let mut counter = self.counter.lock();
+			if *counter == 0 {
+				return nonblock.then_some(Err(errno!(EAGAIN)));
+			}
+			let val = if self.semaphore { 1 } else { *counter };
+			*counter -= val;
+			self.wr_queue.wake_all();
+			Some(Ok(val))
+		})?
+	}
+
+	/// Adds `val` to the counter.
+	///
+	/// If the counter would exceed its maximum value, the function blocks unless `nonblock` is
+	/// set, in which case it returns [`errno::EAGAIN`].
+	pub fn add(&self, val: u64, nonblock: bool) -> EResult<()> {
+		if unlikely(val > MAX) {
+			return Err(errno!(EINVAL));
+		}
+		self.wr_queue.wait_until(|| {
+			let mut counter = self.counter.lock();
+			if *counter > MAX - val {
+				return nonblock.then_some(Err(errno!(EAGAIN)));
+			}
+			*counter += val;
+			if val > 0 {
+				self.rd_queue.wake_all();
+			}
+			Some(Ok(()))
+		})?
+	}
+}
+
+impl FileOps for EventFd {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let counter = *self.counter.lock();
+		let mut events = 0;
+		if counter > 0 {
+			events |= POLLIN;
+		}
+		if counter < MAX {
+			events |= POLLOUT;
+		}
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(&self.rd_queue)?;
+		}
+		if mask & POLLOUT != 0 {
+			f(&self.wr_queue)?;
+		}
+		Ok(true)
+	}
+
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		if unlikely(buf.len() < size_of::<u64>()) {
+			return Err(errno!(EINVAL));
+		}
+		let val = self.take(file.get_flags() & O_NONBLOCK != 0)?;
+		buf.copy_to_user(0, &val.to_ne_bytes())?;
+		Ok(size_of::<u64>())
+	}
+
+	fn write(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		if unlikely(buf.len() < size_of::<u64>()) {
+			return Err(errno!(EINVAL));
+		}
+		let mut val = [0; size_of::<u64>()];
+		buf.copy_from_user(0, &mut val)?;
+		self.add(u64::from_ne_bytes(val), file.get_flags() & O_NONBLOCK != 0)?;
+		Ok(size_of::<u64>())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn eventfd_counter() {
+		let efd = EventFd::new(0, false);
+		assert_eq!(efd.take(true), Err(errno!(EAGAIN)));
+		efd.add(3, true).unwrap();
+		efd.add(4, true).unwrap();
+		assert_eq!(efd.take(true), Ok(7));
+		assert_eq!(efd.take(true), Err(errno!(EAGAIN)));
+		assert_eq!(efd.add(u64::MAX, true), Err(errno!(EINVAL)));
+		efd.add(MAX, true).unwrap();
+		assert_eq!(efd.add(1, true), Err(errno!(EAGAIN)));
+	}
+
+	#[test_case]
+	fn eventfd_semaphore() {
+		let efd = EventFd::new(2, true);
+		assert_eq!(efd.take(true), Ok(1));
+		assert_eq!(efd.take(true), Ok(1));
+		assert_eq!(efd.take(true), Err(errno!(EAGAIN)));
+	}
+}
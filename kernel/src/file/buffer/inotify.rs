@@ -0,0 +1,396 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An inotify instance allows to monitor filesystem events by reading from a file descriptor.
+//!
+//! Events are reported by the VFS through [`notify`] and [`notify_child`]. Each watch is bound to
+//! a file, identified by its device and inode number.
+
+use crate::{
+	file::{
+		File, FileType, INode, O_NONBLOCK, Stat, fs::FileOps, vfs::node::Node,
+		wait_queue::WaitQueue,
+	},
+	memory::user::UserSlice,
+	sync::mutex::Mutex,
+	syscall::select::POLLIN,
+};
+use core::{
+	ffi::c_int,
+	hint::unlikely,
+	ptr,
+	sync::atomic::{AtomicU32, Ordering::Relaxed},
+};
+use utils::{
+	bytes::as_bytes,
+	collections::{string::String, vec::Vec},
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// Event: the file was accessed.
+pub const IN_ACCESS: u32 = 0x1;
+/// Event: the file was modified.
+pub const IN_MODIFY: u32 = 0x2;
+/// Event: the file's metadata changed.
+pub const IN_ATTRIB: u32 = 0x4;
+/// Event: a file opened for writing was closed.
+pub const IN_CLOSE_WRITE: u32 = 0x8;
+/// Event: a file not opened for writing was closed.
+pub const IN_CLOSE_NOWRITE: u32 = 0x10;
+/// Event: the file was opened.
+pub const IN_OPEN: u32 = 0x20;
+/// Event: a file was moved out of the watched directory.
+pub const IN_MOVED_FROM: u32 = 0x40;
+/// Event: a file was moved into the watched directory.
+pub const IN_MOVED_TO: u32 = 0x80;
+/// Event: a file was created in the watched directory.
+pub const IN_CREATE: u32 = 0x100;
+/// Event: a file was deleted from the watched directory.
+pub const IN_DELETE: u32 = 0x200;
+/// Event: the watched file was deleted.
+pub const IN_DELETE_SELF: u32 = 0x400;
+/// Event: the watched file was moved.
+pub const IN_MOVE_SELF: u32 = 0x800;
+/// Mask of all the events above.
+pub const IN_ALL_EVENTS: u32 = 0xfff;
+
+/// Event: the event queue overflowed.
+pub const IN_Q_OVERFLOW: u32 = 0x4000;
+/// Event: the watch has been removed.
+pub const IN_IGNORED: u32 = 0x8000;
+/// Event flag: the subject of the event is a directory.
+pub const IN_ISDIR: u32 = 0x40000000;
+
+/// Watch flag: only watch the file if it is a directory.
+pub const IN_ONLYDIR: u32 = 0x1000000;
+/// Watch flag: do not follow symbolic links.
+pub const IN_DONT_FOLLOW: u32 = 0x2000000;
+/// Watch flag: fail if the file is already watched by the instance.
+pub const IN_MASK_CREATE: u32 = 0x10000000;
+/// Watch flag: add events to the mask of an existing watch instead of replacing it.
+pub const IN_MASK_ADD: u32 = 0x20000000;
+/// Watch flag: remove the watch after the first event.
+pub const IN_ONESHOT: u32 = 0x80000000;
+
+/// The maximum number of queued events per instance.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// The header of an event, as read from an inotify instance.
+///
+/// The header is followed by the name of the file, padded with zeros to a multiple of the size
+/// of the header.
+#[repr(C)]
+struct InotifyEvent {
+	/// Watch descriptor.
+	wd: c_int,
+	/// Mask of events.
+	mask: u32,
+	/// Cookie binding related events together.
+	cookie: u32,
+	/// The length of the name, including padding.
+	len: u32,
+}
+
+/// A queued event.
+#[derive(Debug)]
+struct Event {
+	/// Watch descriptor.
+	wd: c_int,
+	/// Mask of events.
+	mask: u32,
+	/// Cookie binding related events together.
+	cookie: u32,
+	/// The name of the file, if the event concerns an entry of a watched directory.
+	name: Option<String>,
+}
+
+impl Event {
+	/// Returns the length of the name, including padding.
+	fn name_len(&self) -> usize {
+		self.name
+			.as_ref()
+			.map(|name| (name.len() + 1).next_multiple_of(size_of::<InotifyEvent>()))
+			.unwrap_or(0)
+	}
+}
+
+/// The events queue of an instance.
+#[derive(Debug, Default)]
+struct Queue {
+	/// Queued events.
+	events: Mutex<Vec<Event>>, // TODO use a VecDeque
+	/// The queue of processes waiting for an event.
+	wait_queue: WaitQueue,
+}
+
+impl Queue {
+	/// Pushes an event on the queue.
+	///
+	/// If the queue is full, the event is dropped and an [`IN_Q_OVERFLOW`] is queued instead.
+	fn push(&self, wd: c_int, mask: u32, cookie: u32, name: Option<&[u8]>) {
+		let mut events = self.events.lock();
+		let event = if events.len() < MAX_QUEUED_EVENTS - 1 {
+			let name = name.map(String::try_from).transpose();
+			name.ok().map(|name| Event {
+				wd,
+				mask,
+				cookie,
+				name,
+			})
+		} else {
+			None
+		};
+		let event = event.unwrap_or(Event {
+			wd: -1,
+			mask: IN_Q_OVERFLOW,
+			cookie: 0,
+			name: None,
+		});
+		if event.mask == IN_Q_OVERFLOW && events.last().is_some_and(|e| e.mask == IN_Q_OVERFLOW) {
+			return;
+		}
+		if events.push(event).is_ok() {
+			self.wait_queue.wake_all();
+		}
+	}
+}
+
+/// A watch on a file.
+#[derive(Debug)]
+struct Watch {
+	/// The queue of the instance the watch belongs to.
+	queue: Arc<Queue>,
+	/// The watch descriptor.
+	wd: c_int,
+	/// The watched file, identified by its device and inode number.
+	file: (u64, INode),
+	/// The mask of events to report, along with watch flags.
+	mask: u32,
+}
+
+/// The list of watches of all instances.
+static WATCHES: Mutex<Vec<Watch>> = Mutex::new(Vec::new());
+/// The next cookie to be used to bind rename events together.
+static COOKIE: AtomicU32 = AtomicU32::new(1);
+
+/// Returns the identifier of a node for watches.
+fn file_id(node: &Node) -> (u64, INode) {
+	(node.fs.dev, node.inode)
+}
+
+/// Reports an event to the watches on `file`.
+///
+/// If the event concerns an entry of the watched directory, `name` is the name of the entry.
+fn notify_impl(file: (u64, INode), name: Option<&[u8]>, mask: u32, cookie: u32) {
+	let mut watches = WATCHES.lock();
+	watches.retain(|w| {
+		if w.file != file {
+			return true;
+		}
+		let reported = w.mask & mask & IN_ALL_EVENTS != 0;
+		if reported {
+			w.queue.push(w.wd, mask, cookie, name);
+		}
+		// The watch is removed if the file is gone, or after the first event in oneshot mode
+		let remove = mask & IN_DELETE_SELF != 0 || (reported && w.mask & IN_ONESHOT != 0);
+		if remove {
+			w.queue.push(w.wd, IN_IGNORED, 0, None);
+		}
+		!remove
+	});
+}
+
+/// Returns a new cookie, used to bind the [`IN_MOVED_FROM`] and [`IN_MOVED_TO`] events of a
+/// rename together.
+pub fn next_cookie() -> u32 {
+	COOKIE.fetch_add(1, Relaxed)
+}
+
+/// Reports the events in `mask` to the watches on `node` itself.
+pub fn notify(node: &Node, mask: u32) {
+	let mask = if node.get_type() == Some(FileType::Directory) {
+		mask | IN_ISDIR
+	} else {
+		mask
+	};
+	notify_impl(file_id(node), None, mask, 0);
+}
+
+/// Reports the events in `mask` to the watches on the directory `dir`, for its entry `name`
+/// pointing to `node`.
+///
+/// `cookie` binds related events together. If not relevant, it is zero.
+pub fn notify_child(dir: &Node, name: &[u8], node: &Node, mask: u32, cookie: u32) {
+	let mask = if node.get_type() == Some(FileType::Directory) {
+		mask | IN_ISDIR
+	} else {
+		mask
+	};
+	notify_impl(file_id(dir), Some(name), mask, cookie);
+}
+
+/// An inotify instance.
+#[derive(Debug)]
+pub struct Inotify {
+	/// The events queue.
+	queue: Arc<Queue>,
+	/// The next watch descriptor to be allocated.
+	next_wd: Mutex<c_int>,
+}
+
+impl Inotify {
+	/// Creates a new instance.
+	pub fn new() -> AllocResult<Self> {
+		Ok(Self {
+			queue: Arc::new(Queue::default())?,
+			next_wd: Mutex::new(1),
+		})
+	}
+
+	/// Tells whether the watch `w` belongs to the instance.
+	fn owns(&self, w: &Watch) -> bool {
+		ptr::eq(Arc::as_ptr(&w.queue), Arc::as_ptr(&self.queue))
+	}
+
+	/// Watches `node` for the events in `mask`.
+	///
+	/// If the instance is already watching the file, the existing watch is updated.
+	///
+	/// On success, the function returns the watch descriptor.
+	pub fn add_watch(&self, node: &Node, mask: u32) -> EResult<c_int> {
+		if unlikely(mask & IN_ALL_EVENTS == 0) {
+			return Err(errno!(EINVAL));
+		}
+		if unlikely(mask & IN_MASK_ADD != 0 && mask & IN_MASK_CREATE != 0) {
+			return Err(errno!(EINVAL));
+		}
+		if mask & IN_ONLYDIR != 0 && node.get_type() != Some(FileType::Directory) {
+			return Err(errno!(ENOTDIR));
+		}
+		let file = file_id(node);
+		let mut watches = WATCHES.lock();
+		let existing = watches.iter_mut().find(|w| self.owns(w) && w.file == file);
+		if let Some(watch) = existing {
+			if mask & IN_MASK_CREATE != 0 {
+				return Err(errno!(EEXIST));
+			}
+			if mask & IN_MASK_ADD != 0 {
+				watch.mask |= mask;
+			} else {
+				watch.mask = mask;
+			}
+			return Ok(watch.wd);
+		}
+		let mut next_wd = self.next_wd.lock();
+		let wd = *next_wd;
+		watches.push(Watch {
+			queue: self.queue.clone(),
+			wd,
+			file,
+			mask,
+		})?;
+		*next_wd += 1;
+		Ok(wd)
+	}
+
+	/// Removes the watch with descriptor `wd`.
+	pub fn rm_watch(&self, wd: c_int) -> EResult<()> {
+		let mut watches = WATCHES.lock();
+		let i = watches
+			.iter()
+			.position(|w| self.owns(w) && w.wd == wd)
+			.ok_or_else(|| errno!(EINVAL))?;
+		watches.remove(i);
+		self.queue.push(wd, IN_IGNORED, 0, None);
+		Ok(())
+	}
+}
+
+impl FileOps for Inotify {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn release(&self, _file: &File) {
+		WATCHES.lock().retain(|w| !self.owns(w));
+	}
+
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let events = if self.queue.events.lock().is_empty() {
+			0
+		} else {
+			POLLIN
+		};
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(&self.queue.wait_queue)?;
+		}
+		Ok(true)
+	}
+
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		const HDR_SIZE: usize = size_of::<InotifyEvent>();
+		let nonblock = file.get_flags() & O_NONBLOCK != 0;
+		let mut events = self.queue.wait_queue.wait_until(|| {
+			let events = self.queue.events.lock();
+			if events.is_empty() {
+				return nonblock.then_some(Err(errno!(EAGAIN)));
+			}
+			Some(Ok(events))
+		})??;
+		let mut off = 0;
+		while let Some(event) = events.first() {
+			let name_len = event.name_len();
+			if off + HDR_SIZE + name_len > buf.len() {
+				break;
+			}
+			let hdr = InotifyEvent {
+				wd: event.wd,
+				mask: event.mask,
+				cookie: event.cookie,
+				len: name_len as _,
+			};
+			buf.copy_to_user(off, as_bytes(&hdr))?;
+			if let Some(name) = &event.name {
+				const ZEROS: [u8; HDR_SIZE] = [0; HDR_SIZE];
+				buf.copy_to_user(off + HDR_SIZE, name.as_bytes())?;
+				buf.copy_to_user(off + HDR_SIZE + name.len(), &ZEROS[..name_len - name.len()])?;
+			}
+			off += HDR_SIZE + name_len;
+			events.remove(0);
+		}
+		// The buffer is too small for the first event
+		if unlikely(off == 0) {
+			return Err(errno!(EINVAL));
+		}
+		Ok(off)
+	}
+}
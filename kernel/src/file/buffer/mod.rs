@@ -0,0 +1,303 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Buffers backing special files.
+//!
+//! [`StreamBuffer`] is a unidirectional byte stream buffer, shared by pipes and stream sockets.
+//!
+//! Each end of the stream can be closed independently:
+//! - once the write end is closed, readers get the remaining data, then end-of-file
+//! - once the read end is closed, writers get [`errno::EPIPE`]
+//!
+//! Raising `SIGPIPE` on [`errno::EPIPE`] is left to the caller through [`sigpipe`], since some
+//! interfaces (such as `send` with `MSG_NOSIGNAL`) must not generate it.
+
+pub mod eventfd;
+pub mod inotify;
+pub mod signalfd;
+pub mod timerfd;
+pub mod watch_queue;
+
+use crate::{
+	file::wait_queue::WaitQueue,
+	memory::{ring_buffer::RingBuffer, user::UserSlice},
+	process::{Process, signal::Signal},
+	sync::mutex::Mutex,
+	syscall::select::{POLLERR, POLLIN, POLLOUT, POLLRDHUP},
+};
+use core::{hint::unlikely, num::NonZeroUsize};
+use utils::{
+	errno,
+	errno::{AllocResult, EResult},
+};
+
+#[derive(Debug)]
+struct StreamInner {
+	/// The buffered data.
+	buffer: RingBuffer,
+	/// Tells whether the read end is open.
+	read_open: bool,
+	/// Tells whether the write end is open.
+	write_open: bool,
+}
+
+/// Sends `SIGPIPE` to the current process if `res` is [`errno::EPIPE`], then returns `res`.
+///
+/// If the signal is blocked or ignored by the process, only the error is returned.
+pub fn sigpipe<T>(res: EResult<T>) -> EResult<T> {
+	if matches!(&res, Err(e) if e.as_int() == errno::EPIPE) {
+		Process::current().kill(Signal::SIGPIPE);
+	}
+	res
+}
+
+/// A unidirectional byte stream.
+#[derive(Debug)]
+pub struct StreamBuffer {
+	/// Inner with locking.
+	inner: Mutex<StreamInner>,
+	/// The queue of processing waiting to read from the stream.
+	rd_queue: WaitQueue,
+	/// The queue of processing waiting to write to the stream.
+	wr_queue: WaitQueue,
+}
+
+impl StreamBuffer {
+	/// Creates a new instance with both ends open.
+	///
+	/// `capacity` is the size of the buffer in bytes.
+	pub fn new(capacity: NonZeroUsize) -> AllocResult<Self> {
+		Ok(Self {
+			inner: Mutex::new(StreamInner {
+				buffer: RingBuffer::new(capacity)?,
+				read_open: true,
+				write_open: true,
+			}),
+			rd_queue: WaitQueue::default(),
+			wr_queue: WaitQueue::default(),
+		})
+	}
+
+	/// Returns the length of the data waiting to be read, in bytes.
+	pub fn get_data_len(&self) -> usize {
+		self.inner.lock().buffer.get_data_len()
+	}
+
+	/// Tells whether the read end is open.
+	pub fn is_read_open(&self) -> bool {
+		self.inner.lock().read_open
+	}
+
+	/// Tells whether the write end is open.
+	pub fn is_write_open(&self) -> bool {
+		self.inner.lock().write_open
+	}
+
+	/// Opens or closes the read end.
+	///
+	/// Closing the read end wakes up writers so that they fail with [`errno::EPIPE`].
+	pub fn set_read_open(&self, open: bool) {
+		self.inner.lock().read_open = open;
+		if !open {
+			self.wr_queue.wake_all();
+		}
+	}
+
+	/// Opens or closes the write end.
+	///
+	/// Closing the write end wakes up readers so that they get end-of-file once the remaining data
+	/// has been read.
+	pub fn set_write_open(&self, open: bool) {
+		self.inner.lock().write_open = open;
+		if !open {
+			self.rd_queue.wake_all();
+		}
+	}
+
+	/// Returns the read events available on the stream.
+	///
+	/// If a read would return end-of-file, the function returns [`POLLIN`] and [`POLLRDHUP`].
+	pub fn poll_read(&self) -> u32 {
+		let inner = self.inner.lock();
+		if !inner.read_open || !inner.write_open {
+			POLLIN | POLLRDHUP
+		} else if !inner.buffer.is_empty() {
+			POLLIN
+		} else {
+			0
+		}
+	}
+
+	/// Returns the write events available on the stream.
+	///
+	/// If a write would fail with [`errno::EPIPE`], the function returns [`POLLOUT`] and
+	/// [`POLLERR`].
+	pub fn poll_write(&self) -> u32 {
+		let inner = self.inner.lock();
+		if !inner.read_open || !inner.write_open {
+			POLLOUT | POLLERR
+		} else if !inner.buffer.is_full() {
+			POLLOUT
+		} else {
+			0
+		}
+	}
+
+	/// Calls `f` on the wait queues woken up when an event in `mask` may have occurred on the
+	/// stream.
+	pub fn poll_wait(
+		&self,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<()> {
+		if mask & (POLLIN | POLLRDHUP) != 0 {
+			f(&self.rd_queue)?;
+		}
+		if mask & POLLOUT != 0 {
+			f(&self.wr_queue)?;
+		}
+		Ok(())
+	}
+
+	/// Reads data from the stream into `buf`.
+	///
+	/// If no data is available, the function blocks unless `nonblock` is set, in which case it
+	/// returns [`errno::EAGAIN`].
+	///
+	/// If either end is closed and no data remains, the function returns `0` (end-of-file).
+	pub fn read(&self, buf: UserSlice<u8>, nonblock: bool) -> EResult<usize> {
+		self.read_impl(buf, nonblock, false)
+	}
+
+	/// Same as [`Self::read`], except the data is not removed from the stream.
+	pub fn peek(&self, buf: UserSlice<u8>, nonblock: bool) -> EResult<usize> {
+		self.read_impl(buf, nonblock, true)
+	}
+
+	fn read_impl(&self, buf: UserSlice<u8>, nonblock: bool, peek: bool) -> EResult<usize> {
+		if unlikely(buf.is_empty()) {
+			return Ok(0);
+		}
+		self.rd_queue.wait_until(|| {
+			let mut inner = self.inner.lock();
+			let res = if peek {
+				inner.buffer.peek(buf)
+			} else {
+				inner.buffer.read(buf)
+			};
+			let len = match res {
+				Ok(l) => l,
+				Err(e) => return Some(Err(e)),
+			};
+			if len > 0 {
+				if !peek {
+					self.wr_queue.wake_all();
+				}
+				return Some(Ok(len));
+			}
+			// Nothing to read
+			if !inner.read_open || !inner.write_open {
+				return Some(Ok(0));
+			}
+			if nonblock {
+				Some(Err(errno!(EAGAIN)))
+			} else {
+				None
+			}
+		})?
+	}
+
+	/// Writes data from `buf` to the stream.
+	///
+	/// If no space is available, the function blocks unless `nonblock` is set, in which case it
+	/// returns [`errno::EAGAIN`].
+	///
+	/// If either end is closed, the function returns [`errno::EPIPE`]. `SIGPIPE` is **not**
+	/// raised, see [`sigpipe`].
+	pub fn write(&self, buf: UserSlice<u8>, nonblock: bool) -> EResult<usize> {
+		if unlikely(buf.is_empty()) {
+			return Ok(0);
+		}
+		self.wr_queue.wait_until(|| {
+			let mut inner = self.inner.lock();
+			if !inner.read_open || !inner.write_open {
+				return Some(Err(errno!(EPIPE)));
+			}
+			let len = match inner.buffer.write(buf) {
+				Ok(l) => l,
+				Err(e) => return Some(Err(e)),
+			};
+			if len > 0 {
+				self.rd_queue.wake_all();
+				return Some(Ok(len));
+			}
+			// No space left to write
+			if nonblock {
+				Some(Err(errno!(EAGAIN)))
+			} else {
+				None
+			}
+		})?
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn stream_eof_after_write_shutdown() {
+		let stream = StreamBuffer::new(NonZeroUsize::new(16).unwrap()).unwrap();
+		assert_eq!(stream.poll_read(), 0);
+		let mut buf: [u8; 4] = [42; 4];
+		let len = stream
+			.write(UserSlice::from_slice_mut(&mut buf), true)
+			.unwrap();
+		assert_eq!(len, 4);
+		stream.set_write_open(false);
+		assert_eq!(stream.poll_read(), POLLIN | POLLRDHUP);
+		assert_eq!(stream.poll_write(), POLLOUT | POLLERR);
+		let res = stream.write(UserSlice::from_slice_mut(&mut buf), true);
+		assert_eq!(res, Err(errno!(EPIPE)));
+		// Remaining data is readable before end-of-file
+		buf.fill(0);
+		let len = stream
+			.peek(UserSlice::from_slice_mut(&mut buf[..2]), true)
+			.unwrap();
+		assert_eq!(len, 2);
+		assert_eq!(stream.get_data_len(), 4);
+		let len = stream
+			.read(UserSlice::from_slice_mut(&mut buf), true)
+			.unwrap();
+		assert_eq!(len, 4);
+		assert_eq!(buf, [42; 4]);
+		let len = stream
+			.read(UserSlice::from_slice_mut(&mut buf), true)
+			.unwrap();
+		assert_eq!(len, 0);
+	}
+
+	#[test_case]
+	fn stream_nonblock_empty() {
+		let stream = StreamBuffer::new(NonZeroUsize::new(16).unwrap()).unwrap();
+		let mut buf: [u8; 4] = [0; 4];
+		let res = stream.read(UserSlice::from_slice_mut(&mut buf), true);
+		assert_eq!(res, Err(errno!(EAGAIN)));
+		assert_eq!(stream.poll_write(), POLLOUT);
+	}
+}
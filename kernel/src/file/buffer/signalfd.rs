@@ -0,0 +1,189 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A signalfd allows a process to receive signals by reading from a file descriptor.
+//!
+//! Reading consumes the pending signals of the **reading** process that are in the file's mask.
+//! Those signals are usually blocked, so that they are not delivered to a handler.
+
+use crate::{
+	file::{File, FileType, O_NONBLOCK, Stat, fs::FileOps, wait_queue::WaitQueue},
+	memory::user::UserSlice,
+	process::{
+		Process,
+		signal::{SigSet, Signal},
+	},
+	sync::mutex::Mutex,
+	syscall::select::POLLIN,
+};
+use core::hint::unlikely;
+use utils::{
+	bytes::as_bytes,
+	errno,
+	errno::{AllocResult, EResult},
+};
+
+/// Information about a signal, as read from a signalfd.
+#[repr(C)]
+#[derive(Default)]
+pub struct SignalfdSiginfo {
+	/// Signal number.
+	ssi_signo: u32,
+	/// Error number (unused).
+	ssi_errno: i32,
+	/// Signal code.
+	ssi_code: i32,
+	/// PID of the sender.
+	ssi_pid: u32,
+	/// Real UID of the sender.
+	ssi_uid: u32,
+	/// File descriptor (`SIGIO`).
+	ssi_fd: i32,
+	/// Kernel timer ID (POSIX timers).
+	ssi_tid: u32,
+	/// Band event (`SIGIO`).
+	ssi_band: u32,
+	/// POSIX timer overrun count.
+	ssi_overrun: u32,
+	/// Trap number that caused the signal.
+	ssi_trapno: u32,
+	/// Exit status or signal (`SIGCHLD`).
+	ssi_status: i32,
+	/// Integer sent by `sigqueue`.
+	ssi_int: i32,
+	/// Pointer sent by `sigqueue`.
+	ssi_ptr: u64,
+	/// User CPU time consumed (`SIGCHLD`).
+	ssi_utime: u64,
+	/// System CPU time consumed (`SIGCHLD`).
+	ssi_stime: u64,
+	/// Address that generated the signal (for hardware-generated signals).
+	ssi_addr: u64,
+	/// Least significant bit of address (`SIGBUS`).
+	ssi_addr_lsb: u16,
+	/// Padding.
+	__pad2: u16,
+	/// System call number (`SIGSYS`).
+	ssi_syscall: i32,
+	/// System call address (`SIGSYS`).
+	ssi_call_addr: u64,
+	/// System call architecture (`SIGSYS`).
+	ssi_arch: u32,
+	/// Padding to 128 bytes.
+	__pad: [u8; 28],
+}
+
+/// A file receiving signals.
+#[derive(Debug)]
+pub struct SignalFd {
+	/// The set of signals to be read from the file.
+	mask: Mutex<SigSet>,
+}
+
+impl SignalFd {
+	/// Creates a new instance.
+	///
+	/// `mask` is the set of signals to be read from the file.
+	pub fn new(mask: SigSet) -> Self {
+		Self {
+			mask: Mutex::new(Self::sanitize(mask)),
+		}
+	}
+
+	/// Removes the signals that cannot be received through a signalfd from `mask`.
+	fn sanitize(mut mask: SigSet) -> SigSet {
+		mask.clear(Signal::SIGKILL as _);
+		mask.clear(Signal::SIGSTOP as _);
+		mask
+	}
+
+	/// Replaces the set of signals to be read from the file.
+	pub fn set_mask(&self, mask: SigSet) {
+		*self.mask.lock() = Self::sanitize(mask);
+	}
+
+	/// Takes the next pending signal of the current process that is in the file's mask.
+	///
+	/// If no such signal is pending, the function blocks unless `nonblock` is set, in which case
+	/// it returns [`errno::EAGAIN`].
+	fn take(&self, nonblock: bool) -> EResult<Signal> {
+		let proc = Process::current();
+		proc.signal_queue.wait_until(|| {
+			let mask = *self.mask.lock();
+			match proc.signal.lock().take_pending_in(mask) {
+				Some(sig) => Some(Ok(sig)),
+				None => nonblock.then_some(Err(errno!(EAGAIN))),
+			}
+		})?
+	}
+}
+
+impl FileOps for SignalFd {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let sigmask = *self.mask.lock();
+		let events = if Process::current().signal.lock().is_pending_in(sigmask) {
+			POLLIN
+		} else {
+			0
+		};
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(&Process::current().signal_queue)?;
+		}
+		Ok(true)
+	}
+
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		const SIZE: usize = size_of::<SignalfdSiginfo>();
+		if unlikely(buf.len() < SIZE) {
+			return Err(errno!(EINVAL));
+		}
+		let nonblock = file.get_flags() & O_NONBLOCK != 0;
+		// Block only for the first signal
+		let mut off = 0;
+		while off + SIZE <= buf.len() {
+			let sig = match self.take(nonblock || off > 0) {
+				Ok(sig) => sig,
+				Err(e) if off > 0 && e.as_int() == errno::EAGAIN => break,
+				Err(e) => return Err(e),
+			};
+			let info = SignalfdSiginfo {
+				ssi_signo: sig as _,
+				..Default::default()
+			};
+			buf.copy_to_user(off, as_bytes(&info))?;
+			off += SIZE;
+		}
+		Ok(off)
+	}
+}
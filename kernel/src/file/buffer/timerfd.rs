@@ -0,0 +1,153 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A timerfd is a timer whose expirations are read from a file descriptor.
+//!
+//! Reading returns the number of expirations since the timer has been set or last read, then
+//! resets it to zero.
+
+use crate::{
+	file::{File, FileType, O_NONBLOCK, Stat, fs::FileOps, wait_queue::WaitQueue},
+	memory::user::UserSlice,
+	syscall::select::POLLIN,
+	time::{
+		clock::{Clock, current_time_ns},
+		timer::Timer,
+		unit::Timestamp,
+	},
+};
+use core::hint::unlikely;
+use utils::{
+	errno,
+	errno::{AllocResult, EResult},
+};
+
+/// A timer readable through a file descriptor.
+#[derive(Debug)]
+pub struct TimerFd {
+	/// The underlying timer.
+	timer: Timer,
+}
+
+impl TimerFd {
+	/// Creates a new unarmed timer.
+	///
+	/// `clock` is the clock to use.
+	pub fn new(clock: Clock) -> EResult<Self> {
+		Ok(Self {
+			timer: Timer::new_waitable(clock)?,
+		})
+	}
+
+	/// Returns the current state of the timer, in nanoseconds.
+	///
+	/// The function returns the interval between two expirations, and the remaining time until
+	/// the next one. If the timer is unarmed, the remaining time is zero.
+	pub fn get_time(&self) -> (Timestamp, Timestamp) {
+		self.timer.get_time_ns()
+	}
+
+	/// Arms or disarms the timer, and resets the number of expirations.
+	///
+	/// Arguments:
+	/// - `interval` is the interval between two expirations, in nanoseconds. If zero, the timer
+	///   expires only once
+	/// - `value` is the time of the first expiration, in nanoseconds. If zero, the timer is
+	///   disarmed
+	/// - `abs` tells whether `value` is an absolute time on the timer's clock, instead of a time
+	///   relative to now
+	pub fn set_time(&self, interval: Timestamp, value: Timestamp, abs: bool) -> AllocResult<()> {
+		let value = if abs && value > 0 {
+			// If the time is already elapsed, expire as soon as possible
+			value
+				.saturating_sub(current_time_ns(self.timer.get_clock()))
+				.max(1)
+		} else {
+			value
+		};
+		self.timer.set_time(interval, value)
+	}
+
+	/// Takes the number of expirations.
+	///
+	/// If the timer has not expired, the function blocks unless `nonblock` is set, in which case
+	/// it returns [`errno::EAGAIN`].
+	pub fn take(&self, nonblock: bool) -> EResult<u64> {
+		self.timer.get_queue().wait_until(|| {
+			let val = self.timer.take_expirations();
+			if val == 0 {
+				return nonblock.then_some(Err(errno!(EAGAIN)));
+			}
+			Some(Ok(val))
+		})?
+	}
+}
+
+impl FileOps for TimerFd {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let events = if self.timer.get_expirations() > 0 {
+			POLLIN
+		} else {
+			0
+		};
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(self.timer.get_queue())?;
+		}
+		Ok(true)
+	}
+
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		if unlikely(buf.len() < size_of::<u64>()) {
+			return Err(errno!(EINVAL));
+		}
+		let val = self.take(file.get_flags() & O_NONBLOCK != 0)?;
+		buf.copy_to_user(0, &val.to_ne_bytes())?;
+		Ok(size_of::<u64>())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn timerfd_unarmed() {
+		let tfd = TimerFd::new(Clock::Monotonic).unwrap();
+		assert_eq!(tfd.get_time(), (0, 0));
+		assert_eq!(tfd.take(true), Err(errno!(EAGAIN)));
+		tfd.set_time(1_000_000, 0, false).unwrap();
+		assert_eq!(tfd.get_time(), (1_000_000, 0));
+		assert_eq!(tfd.take(true), Err(errno!(EAGAIN)));
+	}
+}
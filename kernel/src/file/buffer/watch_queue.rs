@@ -0,0 +1,586 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A watch queue is a pipe into which the kernel posts notifications, created with `pipe2` and
+//! the [`O_NOTIFICATION_PIPE`] flag.
+//!
+//! Sources of notifications (such as mount namespaces or the list of devices) hold a
+//! [`WatchList`]. A queue is subscribed to a source with [`ioctl::IOC_WATCH_QUEUE_WATCH`], giving
+//! an ID which is reported in each notification coming from it.
+//!
+//! Each notification is read as a whole, and begins with a [`WatchNotification`] header.
+
+use crate::{
+	file::{
+		File, FileType, O_EXCL, O_NONBLOCK, Stat, fs::FileOps, vfs::mountpoint::MountNamespace,
+		wait_queue::WaitQueue,
+	},
+	memory::user::{UserPtr, UserSlice},
+	process::Process,
+	sync::mutex::Mutex,
+	syscall::{FromSyscallArg, ioctl, select::POLLIN},
+};
+use core::{
+	ffi::{c_int, c_void},
+	hint::unlikely,
+	ptr,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{Acquire, Release},
+	},
+};
+use utils::{
+	bytes::{AnyRepr, as_bytes},
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// `pipe2` flag: create a watch queue instead of a regular pipe.
+pub const O_NOTIFICATION_PIPE: c_int = O_EXCL;
+
+/// Notification type: notifications about the queue itself.
+pub const WATCH_TYPE_META: u32 = 0;
+/// Notification type: changes to keys. Keys are not implemented, so this type is never posted.
+pub const WATCH_TYPE_KEY_NOTIFY: u32 = 1;
+/// Notification type: changes to the mountpoints of a mount namespace.
+pub const WATCH_TYPE_MOUNT_NOTIFY: u32 = 2;
+/// Notification type: devices being added or removed.
+pub const WATCH_TYPE_DEVICE_NOTIFY: u32 = 3;
+
+/// Meta notification subtype: the watch has been removed.
+pub const WATCH_META_REMOVAL_NOTIFICATION: u32 = 0;
+/// Meta notification subtype: notifications have been lost because the queue was full.
+pub const WATCH_META_LOSS_NOTIFICATION: u32 = 1;
+
+/// Mount notification subtype: a filesystem has been mounted.
+pub const NOTIFY_MOUNT_NEW_MOUNT: u32 = 0;
+/// Mount notification subtype: a filesystem has been unmounted.
+pub const NOTIFY_MOUNT_UNMOUNT: u32 = 1;
+/// Mount notification subtype: a mountpoint has been moved.
+pub const NOTIFY_MOUNT_MOVE: u32 = 2;
+
+/// Device notification subtype: a device has been added.
+pub const NOTIFY_DEVICE_ADD: u32 = 0;
+/// Device notification subtype: a device has been removed.
+pub const NOTIFY_DEVICE_REMOVE: u32 = 1;
+
+/// Mask of the length of the notification in bytes, in [`WatchNotification::info`].
+pub const WATCH_INFO_LENGTH: u32 = 0x7f;
+/// Mask of the ID of the watch, in [`WatchNotification::info`].
+pub const WATCH_INFO_ID: u32 = 0xff00;
+/// Shift of the ID of the watch, in [`WatchNotification::info`].
+const WATCH_INFO_ID_SHIFT: u32 = 8;
+
+/// The maximum number of queued notifications.
+const MAX_NOTES: usize = 512;
+/// The maximum number of filters on a queue.
+const MAX_FILTERS: usize = 16;
+
+/// The header of a notification.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct WatchNotification {
+	/// The type of notification in the lower 24 bits, and its subtype in the upper 8 bits.
+	pub type_subtype: u32,
+	/// The length of the notification, the ID of the watch and type-specific flags.
+	pub info: u32,
+}
+
+unsafe impl AnyRepr for WatchNotification {}
+
+/// Payload of a [`WATCH_TYPE_MOUNT_NOTIFY`] notification.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MountNotification {
+	/// The header.
+	pub watch: WatchNotification,
+	/// The ID of the mountpoint, as reported by `/proc/<pid>/mountinfo`.
+	pub mnt_id: u32,
+	/// Reserved.
+	pub __reserved: u32,
+}
+
+unsafe impl AnyRepr for MountNotification {}
+
+/// Payload of a [`WATCH_TYPE_DEVICE_NOTIFY`] notification.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct DeviceNotification {
+	/// The header.
+	pub watch: WatchNotification,
+	/// The type of the device file: `S_IFBLK` or `S_IFCHR`.
+	pub dev_type: u32,
+	/// The major number of the device.
+	pub major: u32,
+	/// The minor number of the device.
+	pub minor: u32,
+	/// Reserved.
+	pub __reserved: u32,
+}
+
+unsafe impl AnyRepr for DeviceNotification {}
+
+/// A filter selecting notifications of a given type.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct WatchNotificationTypeFilter {
+	/// The type of notification the filter applies to.
+	pub type_: u32,
+	/// The value [`WatchNotification::info`] must have on the bits of `info_mask`.
+	pub info_filter: u32,
+	/// The mask of the bits of [`WatchNotification::info`] to check.
+	pub info_mask: u32,
+	/// Bitmap of accepted subtypes.
+	pub subtype_filter: [u32; 8],
+}
+
+unsafe impl AnyRepr for WatchNotificationTypeFilter {}
+
+/// The header of the argument of [`ioctl::IOC_WATCH_QUEUE_SET_FILTER`], followed by the filters.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct WatchNotificationFilter {
+	/// The number of filters.
+	pub nr_filters: u32,
+	/// Reserved, must be zero.
+	pub __reserved: u32,
+}
+
+unsafe impl AnyRepr for WatchNotificationFilter {}
+
+/// The argument of [`ioctl::IOC_WATCH_QUEUE_WATCH`] and [`ioctl::IOC_WATCH_QUEUE_UNWATCH`].
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct WatchQueueWatch {
+	/// The type of notifications to watch, which designates the source.
+	pub type_: u32,
+	/// The ID of the watch, reported in notifications.
+	pub id: u32,
+}
+
+unsafe impl AnyRepr for WatchQueueWatch {}
+
+impl WatchNotificationTypeFilter {
+	/// Tells whether the notification `n` passes the filter.
+	fn matches(&self, n: &WatchNotification) -> bool {
+		let type_ = n.type_subtype & 0xffffff;
+		let subtype = n.type_subtype >> 24;
+		type_ == self.type_
+			&& self.subtype_filter[subtype as usize / 32] & (1 << (subtype % 32)) != 0
+			&& n.info & self.info_mask == self.info_filter
+	}
+}
+
+/// Returns the header of a notification.
+///
+/// `len` is the length of the notification in bytes, including the header.
+fn header(type_: u32, subtype: u32, id: u8, len: usize) -> WatchNotification {
+	WatchNotification {
+		type_subtype: type_ | (subtype << 24),
+		info: (len as u32 & WATCH_INFO_LENGTH) | ((id as u32) << WATCH_INFO_ID_SHIFT),
+	}
+}
+
+/// The state of a queue.
+#[derive(Debug, Default)]
+struct QueueInner {
+	/// Queued notifications.
+	notes: Vec<Vec<u8>>, // TODO use a VecDeque
+	/// The maximum number of queued notifications. If zero, the size has not been set yet.
+	capacity: usize,
+	/// Filters applied to posted notifications. If empty, all notifications are accepted.
+	filters: Vec<WatchNotificationTypeFilter>,
+}
+
+/// The queue of a watch queue.
+#[derive(Debug, Default)]
+struct Queue {
+	/// The state of the queue.
+	state: Mutex<QueueInner>,
+	/// The queue of processes waiting for a notification.
+	wait_queue: WaitQueue,
+	/// Tells whether the read end of the queue has been closed, in which case watches on it are
+	/// removed.
+	defunct: AtomicBool,
+}
+
+impl Queue {
+	/// Pushes the notification `note` on the queue.
+	///
+	/// If the queue is full, the notification is dropped and a [`WATCH_META_LOSS_NOTIFICATION`] is
+	/// queued instead.
+	fn push(&self, note: &[u8]) {
+		let mut inner = self.state.lock();
+		let hdr = unsafe { ptr::read_unaligned(note.as_ptr() as *const WatchNotification) };
+		if !inner.filters.is_empty() && !inner.filters.iter().any(|f| f.matches(&hdr)) {
+			return;
+		}
+		let note = if inner.notes.len() + 1 < inner.capacity {
+			Vec::try_from(note).ok()
+		} else {
+			None
+		};
+		let note = match note {
+			Some(note) => note,
+			None => {
+				let lost = inner.notes.last().is_some_and(|n| is_loss(n));
+				if lost || inner.capacity == 0 {
+					return;
+				}
+				let hdr = header(
+					WATCH_TYPE_META,
+					WATCH_META_LOSS_NOTIFICATION,
+					0,
+					size_of::<WatchNotification>(),
+				);
+				let Ok(note) = Vec::try_from(as_bytes(&hdr)) else {
+					return;
+				};
+				note
+			}
+		};
+		if inner.notes.push(note).is_ok() {
+			self.wait_queue.wake_all();
+		}
+	}
+}
+
+/// Tells whether `note` is a [`WATCH_META_LOSS_NOTIFICATION`].
+fn is_loss(note: &[u8]) -> bool {
+	let hdr = unsafe { ptr::read_unaligned(note.as_ptr() as *const WatchNotification) };
+	hdr.type_subtype == WATCH_TYPE_META | (WATCH_META_LOSS_NOTIFICATION << 24)
+}
+
+/// A watch, binding a source of notifications to a queue.
+#[derive(Debug)]
+struct Watch {
+	/// The queue notifications are posted to.
+	queue: Arc<Queue>,
+	/// The ID of the watch, reported in notifications.
+	id: u8,
+}
+
+impl Watch {
+	/// Posts a [`WATCH_META_REMOVAL_NOTIFICATION`] to the queue.
+	fn removed(&self) {
+		let hdr = header(
+			WATCH_TYPE_META,
+			WATCH_META_REMOVAL_NOTIFICATION,
+			self.id,
+			size_of::<WatchNotification>(),
+		);
+		self.queue.push(as_bytes(&hdr));
+	}
+}
+
+/// The list of watches on a source of notifications.
+///
+/// When the list is dropped, a [`WATCH_META_REMOVAL_NOTIFICATION`] is posted for each watch.
+#[derive(Debug, Default)]
+pub struct WatchList(Mutex<Vec<Watch>>);
+
+impl WatchList {
+	/// Creates a new, empty list.
+	pub const fn new() -> Self {
+		Self(Mutex::new(Vec::new()))
+	}
+
+	/// Adds a watch on the list, posting notifications to `queue` with the ID `id`.
+	///
+	/// If the queue already has a watch with the same ID on the list, the function returns
+	/// [`errno::EBUSY`].
+	fn add(&self, queue: Arc<Queue>, id: u8) -> EResult<()> {
+		let mut watches = self.0.lock();
+		watches.retain(|w| !w.queue.defunct.load(Acquire));
+		let exists = watches
+			.iter()
+			.any(|w| ptr::eq(Arc::as_ptr(&w.queue), Arc::as_ptr(&queue)) && w.id == id);
+		if exists {
+			return Err(errno!(EBUSY));
+		}
+		watches.push(Watch {
+			queue,
+			id,
+		})?;
+		Ok(())
+	}
+
+	/// Removes the watch of `queue` with ID `id` from the list.
+	fn remove(&self, queue: &Arc<Queue>, id: u8) -> EResult<()> {
+		let mut watches = self.0.lock();
+		let i = watches
+			.iter()
+			.position(|w| ptr::eq(Arc::as_ptr(&w.queue), Arc::as_ptr(queue)) && w.id == id)
+			.ok_or_else(|| errno!(ENOENT))?;
+		watches.remove(i).removed();
+		Ok(())
+	}
+
+	/// Posts a notification of type `type_` and subtype `subtype` to all the watches on the list.
+	///
+	/// `note` is the notification, beginning with a [`WatchNotification`] header which is filled
+	/// by the function.
+	pub fn post<T: AnyRepr>(&self, type_: u32, subtype: u32, mut note: T) {
+		const {
+			assert!(size_of::<T>() >= size_of::<WatchNotification>());
+			assert!(size_of::<T>() <= WATCH_INFO_LENGTH as usize);
+		}
+		let mut watches = self.0.lock();
+		watches.retain(|w| !w.queue.defunct.load(Acquire));
+		for w in watches.iter() {
+			let hdr = header(type_, subtype, w.id, size_of::<T>());
+			unsafe {
+				ptr::write_unaligned(&mut note as *mut T as *mut WatchNotification, hdr);
+			}
+			w.queue.push(as_bytes(&note));
+		}
+	}
+}
+
+impl Drop for WatchList {
+	fn drop(&mut self) {
+		for w in self.0.lock().iter() {
+			w.removed();
+		}
+	}
+}
+
+/// The list of watches on devices being added or removed.
+pub static DEVICE_WATCHES: WatchList = WatchList::new();
+
+/// Returns the list of watches on the source of notifications of type `type_`, for the current
+/// process.
+///
+/// If the type has no source, the function returns [`errno::EINVAL`].
+fn watch_list(type_: u32, f: impl FnOnce(&WatchList) -> EResult<()>) -> EResult<()> {
+	match type_ {
+		WATCH_TYPE_MOUNT_NOTIFY => {
+			let ns: Arc<MountNamespace> = Process::current().fs.lock().ctx.lock().mnt_ns.clone();
+			f(&ns.watches)
+		}
+		WATCH_TYPE_DEVICE_NOTIFY => f(&DEVICE_WATCHES),
+		_ => Err(errno!(EINVAL)),
+	}
+}
+
+/// A watch queue.
+#[derive(Debug)]
+pub struct WatchQueue {
+	/// The queue of notifications.
+	queue: Arc<Queue>,
+	/// The number of open read ends.
+	readers: Mutex<usize>,
+}
+
+impl WatchQueue {
+	/// Creates a new instance.
+	pub fn new() -> AllocResult<Self> {
+		Ok(Self {
+			queue: Arc::new(Queue::default())?,
+			readers: Mutex::new(0),
+		})
+	}
+
+	/// Sets the filters of the queue from the userspace structure at `argp`.
+	fn set_filter(&self, argp: *const c_void) -> EResult<()> {
+		let hdr_ptr = UserPtr::<WatchNotificationFilter>::from_ptr(argp as usize);
+		let Some(hdr) = hdr_ptr.copy_from_user()? else {
+			// No filter
+			self.queue.state.lock().filters.clear();
+			return Ok(());
+		};
+		if unlikely(hdr.__reserved != 0 || hdr.nr_filters as usize > MAX_FILTERS) {
+			return Err(errno!(EINVAL));
+		}
+		let filters_ptr = (argp as *mut WatchNotificationFilter).wrapping_add(1);
+		let filters = UserSlice::<WatchNotificationTypeFilter>::from_user(
+			filters_ptr.cast(),
+			hdr.nr_filters as _,
+		)?
+		.copy_from_user_vec(0)?
+		.ok_or_else(|| errno!(EFAULT))?;
+		self.queue.state.lock().filters = filters;
+		Ok(())
+	}
+}
+
+impl FileOps for WatchQueue {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Fifo.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn acquire(&self, file: &File) {
+		if file.can_read() {
+			*self.readers.lock() += 1;
+		}
+	}
+
+	fn release(&self, file: &File) {
+		if file.can_read() {
+			let mut readers = self.readers.lock();
+			*readers -= 1;
+			if *readers == 0 {
+				// Watches are removed from their list the next time it is used
+				self.queue.defunct.store(true, Release);
+			}
+		}
+	}
+
+	fn poll(&self, file: &File, mask: u32) -> EResult<u32> {
+		let events = if file.can_read() && !self.queue.state.lock().notes.is_empty() {
+			POLLIN
+		} else {
+			0
+		};
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if file.can_read() && mask & POLLIN != 0 {
+			f(&self.queue.wait_queue)?;
+		}
+		Ok(true)
+	}
+
+	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			ioctl::IOC_WATCH_QUEUE_SET_SIZE => {
+				let size = argp as usize;
+				if unlikely(size == 0 || size > MAX_NOTES) {
+					return Err(errno!(EINVAL));
+				}
+				let mut inner = self.queue.state.lock();
+				if unlikely(inner.capacity != 0) {
+					return Err(errno!(EBUSY));
+				}
+				inner.capacity = size;
+			}
+			ioctl::IOC_WATCH_QUEUE_SET_FILTER => self.set_filter(argp)?,
+			ioctl::IOC_WATCH_QUEUE_WATCH => {
+				let watch = UserPtr::<WatchQueueWatch>::from_ptr(argp as usize)
+					.copy_from_user()?
+					.ok_or_else(|| errno!(EFAULT))?;
+				let id = u8::try_from(watch.id).map_err(|_| errno!(EINVAL))?;
+				// Notifications would be lost
+				if unlikely(self.queue.state.lock().capacity == 0) {
+					return Err(errno!(EINVAL));
+				}
+				watch_list(watch.type_, |list| list.add(self.queue.clone(), id))?;
+			}
+			ioctl::IOC_WATCH_QUEUE_UNWATCH => {
+				let watch = UserPtr::<WatchQueueWatch>::from_ptr(argp as usize)
+					.copy_from_user()?
+					.ok_or_else(|| errno!(EFAULT))?;
+				let id = u8::try_from(watch.id).map_err(|_| errno!(EINVAL))?;
+				watch_list(watch.type_, |list| list.remove(&self.queue, id))?;
+			}
+			_ => return Err(errno!(ENOTTY)),
+		}
+		Ok(0)
+	}
+
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let nonblock = file.get_flags() & O_NONBLOCK != 0;
+		let mut inner = self.queue.wait_queue.wait_until(|| {
+			let inner = self.queue.state.lock();
+			if inner.notes.is_empty() {
+				return nonblock.then_some(Err(errno!(EAGAIN)));
+			}
+			Some(Ok(inner))
+		})??;
+		let mut off = 0;
+		while let Some(note) = inner.notes.first() {
+			if off + note.len() > buf.len() {
+				break;
+			}
+			buf.copy_to_user(off, note)?;
+			off += note.len();
+			inner.notes.remove(0);
+		}
+		// The buffer is too small for the first notification
+		if unlikely(off == 0) {
+			return Err(errno!(ENOBUFS));
+		}
+		Ok(off)
+	}
+
+	fn write(&self, _file: &File, _off: u64, _buf: UserSlice<u8>) -> EResult<usize> {
+		// Only the kernel writes to a watch queue
+		Err(errno!(EXDEV))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn watch_queue_post() {
+		let queue = Arc::new(Queue::default()).unwrap();
+		queue.state.lock().capacity = 3;
+		let list = WatchList::new();
+		list.add(queue.clone(), 7).unwrap();
+		assert!(list.add(queue.clone(), 7).is_err());
+		let note = MountNotification {
+			watch: Default::default(),
+			mnt_id: 42,
+			__reserved: 0,
+		};
+		list.post(WATCH_TYPE_MOUNT_NOTIFY, NOTIFY_MOUNT_NEW_MOUNT, note);
+		{
+			let inner = queue.state.lock();
+			let hdr = unsafe { &*(inner.notes[0].as_ptr() as *const WatchNotification) };
+			assert_eq!(hdr.type_subtype, WATCH_TYPE_MOUNT_NOTIFY);
+			assert_eq!(hdr.info, 0x710);
+		}
+		// The last slot is taken by a loss notification, reported once
+		for _ in 0..3 {
+			list.post(WATCH_TYPE_MOUNT_NOTIFY, NOTIFY_MOUNT_UNMOUNT, note);
+		}
+		{
+			let inner = queue.state.lock();
+			assert_eq!(inner.notes.len(), 3);
+			assert!(is_loss(&inner.notes[2]));
+		}
+		// Filtered out notifications are not queued
+		let mut inner = queue.state.lock();
+		inner.notes.clear();
+		inner
+			.filters
+			.push(WatchNotificationTypeFilter {
+				type_: WATCH_TYPE_MOUNT_NOTIFY,
+				subtype_filter: [1 << NOTIFY_MOUNT_UNMOUNT, 0, 0, 0, 0, 0, 0, 0],
+				..Default::default()
+			})
+			.unwrap();
+		drop(inner);
+		list.post(WATCH_TYPE_MOUNT_NOTIFY, NOTIFY_MOUNT_NEW_MOUNT, note);
+		list.post(WATCH_TYPE_MOUNT_NOTIFY, NOTIFY_MOUNT_UNMOUNT, note);
+		assert_eq!(queue.state.lock().notes.len(), 1);
+	}
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An epoll instance monitors a list of file descriptors for I/O events.
+//!
+//! Each file descriptor of the interest list is monitored either in:
+//! - level-triggered mode (default): an event is reported as long as the file is ready
+//! - edge-triggered mode ([`EPOLLET`]): an event is reported only when the file becomes ready
+
+use crate::{
+	file::{File, FileType, Stat, fd::FileDescriptorTable, fs::FileOps, wait_queue::PollWait},
+	sync::mutex::Mutex,
+	syscall::select::{POLLERR, POLLHUP},
+};
+use core::ffi::c_int;
+use utils::{
+	collections::{btreemap::BTreeMap, vec::Vec},
+	errno,
+	errno::EResult,
+};
+
+/// `epoll_ctl` operation: add a file descriptor to the interest list.
+pub const EPOLL_CTL_ADD: c_int = 1;
+/// `epoll_ctl` operation: remove a file descriptor from the interest list.
+pub const EPOLL_CTL_DEL: c_int = 2;
+/// `epoll_ctl` operation: change the settings of a file descriptor in the interest list.
+pub const EPOLL_CTL_MOD: c_int = 3;
+
+/// Event flag: report the event only once, until the file descriptor is modified again.
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Event flag: edge-triggered mode.
+pub const EPOLLET: u32 = 1 << 31;
+
+/// An event, as passed to `epoll_ctl` and returned by `epoll_wait`.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EpollEvent {
+	/// The mask of events.
+	pub events: u32,
+	/// Userspace data, returned as-is when an event occurs.
+	pub data: u64,
+}
+
+/// An entry of the interest list.
+#[derive(Debug)]
+struct Interest {
+	/// The events to monitor, with flags.
+	events: u32,
+	/// Userspace data.
+	data: u64,
+	/// The events that were ready at the last check, used by the edge-triggered mode.
+	last: u32,
+	/// If set, the entry has been disabled by [`EPOLLONESHOT`].
+	disabled: bool,
+}
+
+/// An epoll instance.
+#[derive(Debug, Default)]
+pub struct Epoll {
+	/// The interest list, by file descriptor ID.
+	interests: Mutex<BTreeMap<c_int, Interest>>,
+}
+
+impl Epoll {
+	/// Performs the operation `op` on the file descriptor `fd`, with the event `event`.
+	///
+	/// `event` is ignored for [`EPOLL_CTL_DEL`].
+	pub fn ctl(&self, op: c_int, fd: c_int, event: EpollEvent) -> EResult<()> {
+		let mut interests = self.interests.lock();
+		match op {
+			EPOLL_CTL_ADD => {
+				if interests.get(&fd).is_some() {
+					return Err(errno!(EEXIST));
+				}
+				interests.insert(
+					fd,
+					Interest {
+						events: event.events,
+						data: event.data,
+						last: 0,
+						disabled: false,
+					},
+				)?;
+			}
+			EPOLL_CTL_MOD => {
+				let interest = interests.get_mut(&fd).ok_or_else(|| errno!(ENOENT))?;
+				*interest = Interest {
+					events: event.events,
+					data: event.data,
+					last: 0,
+					disabled: false,
+				};
+			}
+			EPOLL_CTL_DEL => {
+				interests.remove(&fd).ok_or_else(|| errno!(ENOENT))?;
+			}
+			_ => return Err(errno!(EINVAL)),
+		}
+		Ok(())
+	}
+
+	/// Polls the files of the interest list and returns the events to be reported, at most
+	/// `max`.
+	///
+	/// `fds` is the file descriptors table the interest list refers to. Entries whose file
+	/// descriptor has been closed are removed.
+	///
+	/// The current process is registered on the wait queues of the polled files through `wait`.
+	pub fn collect(
+		&self,
+		fds: &FileDescriptorTable,
+		max: usize,
+		wait: &mut PollWait,
+	) -> EResult<Vec<EpollEvent>> {
+		let mut interests = self.interests.lock();
+		let mut events = Vec::new();
+		let mut closed = Vec::new();
+		for (fd, interest) in interests.iter_mut() {
+			if events.len() >= max {
+				break;
+			}
+			let Ok(desc) = fds.get_fd(*fd) else {
+				closed.push(*fd)?;
+				continue;
+			};
+			if interest.disabled {
+				continue;
+			}
+			let file = desc.get_file();
+			// Errors and hang ups are always reported
+			let mask = (interest.events & !(EPOLLET | EPOLLONESHOT)) | POLLERR | POLLHUP;
+			wait.register(file.clone(), mask)?;
+			let ready = file.ops.poll(file, mask)? & mask;
+			let report = if interest.events & EPOLLET != 0 {
+				ready & !interest.last
+			} else {
+				ready
+			};
+			interest.last = ready;
+			if report == 0 {
+				continue;
+			}
+			if interest.events & EPOLLONESHOT != 0 {
+				interest.disabled = true;
+			}
+			events.push(EpollEvent {
+				events: report,
+				data: interest.data,
+			})?;
+		}
+		for fd in closed {
+			interests.remove(&fd);
+		}
+		Ok(events)
+	}
+}
+
+impl FileOps for Epoll {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o600,
+			..Default::default()
+		})
+	}
+
+	fn poll(&self, _file: &File, _mask: u32) -> EResult<u32> {
+		// TODO report readiness of the interest list (requires access to the file descriptors
+		// table)
+		Ok(0)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn epoll_ctl_interest_list() {
+		let ep = Epoll::default();
+		let event = EpollEvent {
+			events: POLLERR,
+			data: 42,
+		};
+		ep.ctl(EPOLL_CTL_ADD, 3, event).unwrap();
+		assert_eq!(ep.ctl(EPOLL_CTL_ADD, 3, event), Err(errno!(EEXIST)));
+		ep.ctl(EPOLL_CTL_MOD, 3, event).unwrap();
+		ep.ctl(EPOLL_CTL_DEL, 3, event).unwrap();
+		assert_eq!(ep.ctl(EPOLL_CTL_DEL, 3, event), Err(errno!(ENOENT)));
+		assert_eq!(ep.ctl(EPOLL_CTL_MOD, 3, event), Err(errno!(ENOENT)));
+		assert_eq!(ep.ctl(0, 3, event), Err(errno!(EINVAL)));
+	}
+}
@@ -21,12 +21,20 @@
 //! A file descriptor is an ID held by a process pointing to an entry in the
 //! open file description table.
 
-use crate::file::File;
-use core::{cmp::max, ffi::c_int, mem};
+use crate::{
+	file::{File, lock::LockOwner},
+	process::{Process, pid::Pid},
+};
+use core::{
+	cmp::{max, min},
+	ffi::c_int,
+	sync::atomic::{AtomicU32, Ordering::Relaxed},
+};
 use utils::{
-	collections::vec::Vec,
+	boxed::Box,
+	collections::{bitfield::Bitfield, vec::Vec},
 	errno,
-	errno::{AllocResult, CollectResult, EResult},
+	errno::{AllocResult, EResult},
 	limits::OPEN_MAX,
 	ptr::arc::Arc,
 };
@@ -35,6 +43,11 @@ use utils::{
 /// call to `execve`.
 pub const FD_CLOEXEC: i32 = 1;
 
+/// The maximum number of file descriptors a process can have (`fs.nr_open`).
+///
+/// This is the ceiling for the hard limit of `RLIMIT_NOFILE`.
+pub static NR_OPEN: AtomicU32 = AtomicU32::new(1024 * 1024);
+
 /// Constraint on a new file descriptor ID.
 #[derive(Debug)]
 pub enum NewFDConstraint {
@@ -84,19 +97,77 @@ impl FileDescriptor {
 	/// If file removal has been deferred, and this is the last reference to it, and remove fails,
 	/// then the function returns an error.
 	pub fn close(self) -> EResult<()> {
+		// The file is moved out of the `Arc` on close, so the owner of its locks is computed first
+		let owner = LockOwner::File(Arc::as_ptr(&self.file).addr());
 		// Close file if this is the last reference to it
 		let Some(file) = Arc::into_inner(self.file) else {
 			return Ok(());
 		};
+		if let Some(node) = file.node() {
+			node.locks.release(owner);
+		}
 		file.close()
 	}
+
+	/// Closes the file descriptor on behalf of the current process.
+	///
+	/// As required by POSIX, the record locks of the process on the file are released, even if
+	/// other file descriptors refer to it.
+	fn close_by_process(self) -> EResult<()> {
+		if let Some(node) = self.file.node() {
+			node.locks
+				.release(LockOwner::Process(Process::current().get_pid()));
+		}
+		self.close()
+	}
 }
 
+/// The number of file descriptors in a chunk of [`FileDescriptorTable`].
+const CHUNK_SIZE: usize = 64;
+
+/// A chunk of file descriptors, by ID.
+type Chunk = [Option<FileDescriptor>; CHUNK_SIZE];
+
 /// A table of file descriptors.
-#[derive(Default)]
-pub struct FileDescriptorTable(Vec<Option<FileDescriptor>>);
+///
+/// File descriptors are stored in chunks of [`CHUNK_SIZE`] entries, allocated when at least one
+/// of their entries is used, so that a table with a few large IDs (such as after `dup2`) remains
+/// small. A bitmap of used IDs allows to find the lowest available ID without scanning entries.
+pub struct FileDescriptorTable {
+	/// The chunks of file descriptors. A chunk with no file descriptor is not allocated.
+	chunks: Vec<Option<Box<Chunk>>>,
+	/// The bitmap of used IDs, covering all chunks.
+	used: Bitfield,
+	/// A value one greater than the maximum file descriptor ID, following `RLIMIT_NOFILE`.
+	limit: u32,
+}
+
+impl Default for FileDescriptorTable {
+	fn default() -> Self {
+		Self {
+			chunks: Vec::new(),
+			// Cannot fail since no allocation is required
+			used: Bitfield::new(0).unwrap(),
+			limit: OPEN_MAX,
+		}
+	}
+}
 
 impl FileDescriptorTable {
+	/// Returns the value one greater than the maximum file descriptor ID that can be allocated.
+	///
+	/// The value is capped by [`NR_OPEN`].
+	pub fn get_limit(&self) -> u32 {
+		min(self.limit, NR_OPEN.load(Relaxed))
+	}
+
+	/// Sets the limit on file descriptor IDs, following a change of `RLIMIT_NOFILE`.
+	///
+	/// Already open file descriptors above the limit are left untouched.
+	pub fn set_limit(&mut self, limit: u32) {
+		self.limit = limit;
+	}
+
 	/// Returns the available file descriptor with the lowest ID.
 	///
 	/// If no ID is available, the function returns an error.
@@ -104,40 +175,86 @@ impl FileDescriptorTable {
 	/// `min` is the minimum value for the file descriptor to be returned.
 	fn get_available_fd(&self, min: Option<u32>) -> EResult<u32> {
 		let min = min.unwrap_or(0) as usize;
-		// Find a hole in the table
-		let fd = if min < self.0.len() {
-			self.0[min..]
-				.iter()
-				.enumerate()
-				.find(|(_, fd)| fd.is_none())
-				.map(|(i, _)| (min + i) as u32)
-		} else {
-			None
-		};
-		match fd {
-			Some(fd) => Ok(fd),
-			// No hole found, place the new FD at the end
-			None => {
-				let id = max(self.0.len(), min) as u32;
-				if id < OPEN_MAX {
-					Ok(id)
-				} else {
-					Err(errno!(EMFILE))
-				}
+		// If no ID is free in the bitmap, place the new FD after it
+		let id = self
+			.used
+			.find_clear_from(min)
+			.unwrap_or(max(self.used.len(), min));
+		// The limit might have been lowered below the size of the table
+		match u32::try_from(id) {
+			Ok(id) if id < self.get_limit() => Ok(id),
+			_ => Err(errno!(EMFILE)),
+		}
+	}
+
+	/// Returns the slot of the file descriptor with ID `id`, allocating its chunk if necessary.
+	fn slot(&mut self, id: u32) -> AllocResult<&mut Option<FileDescriptor>> {
+		let id = id as usize;
+		let chunk_index = id / CHUNK_SIZE;
+		if chunk_index >= self.chunks.len() {
+			let len = chunk_index + 1;
+			self.used.resize(len * CHUNK_SIZE)?;
+			self.chunks.reserve(len - self.chunks.len())?;
+			while self.chunks.len() < len {
+				self.chunks.push(None)?;
 			}
 		}
+		let chunk = match &mut self.chunks[chunk_index] {
+			Some(chunk) => chunk,
+			chunk @ None => chunk.insert(Box::new([const { None }; CHUNK_SIZE])?),
+		};
+		Ok(&mut chunk[id % CHUNK_SIZE])
+	}
+
+	/// Inserts `fd` in the table with ID `id`.
+	///
+	/// The function returns the file descriptor previously at this ID, if any.
+	fn insert(&mut self, id: u32, fd: FileDescriptor) -> AllocResult<Option<FileDescriptor>> {
+		let prev = self.slot(id)?.replace(fd);
+		self.used.set(id as _);
+		Ok(prev)
 	}
 
-	/// Extends the file descriptor table if necessary so that it can fit the given ID.
+	/// Removes the file descriptor with ID `id` from the table and returns it.
 	///
-	/// If the table is already large enough, this function is a no-op.
-	fn extend(&mut self, id: u32) -> AllocResult<()> {
+	/// Chunks left empty are freed.
+	fn remove(&mut self, id: u32) -> Option<FileDescriptor> {
 		let id = id as usize;
-		// The ID fits. Do nothing
-		if id < self.0.len() {
-			return Ok(());
+		let chunk_index = id / CHUNK_SIZE;
+		let chunk = self.chunks.get_mut(chunk_index)?.as_mut()?;
+		let fd = chunk[id % CHUNK_SIZE].take()?;
+		self.used.clear(id);
+		// Free the chunk if it is empty
+		let chunk_begin = chunk_index * CHUNK_SIZE;
+		let empty = self
+			.used
+			.find_set_from(chunk_begin)
+			.is_none_or(|i| i >= chunk_begin + CHUNK_SIZE);
+		if empty {
+			self.chunks[chunk_index] = None;
+			// Shrink the table if the last chunks are not allocated
+			let len = self
+				.chunks
+				.iter()
+				.rposition(Option::is_some)
+				.map(|i| i + 1)
+				.unwrap_or(0);
+			if len < self.chunks.len() {
+				self.chunks.truncate(len);
+				// Cannot fail since the bitmap is shrunk
+				let _ = self.used.resize(len * CHUNK_SIZE);
+			}
 		}
-		self.0.resize(id + 1, None)
+		Some(fd)
+	}
+
+	/// Returns an iterator over the file descriptors of the table, with their IDs, in ascending
+	/// order of ID.
+	pub fn iter(&self) -> impl Iterator<Item = (u32, &FileDescriptor)> {
+		self.used.iter_set().filter_map(|id| {
+			let fd = self.chunks[id / CHUNK_SIZE].as_ref()?[id % CHUNK_SIZE].as_ref()?;
+			Some((id as u32, fd))
+		})
 	}
 
 	/// Creates a file descriptor.
@@ -151,9 +268,8 @@ impl FileDescriptorTable {
 		let id = self.get_available_fd(None)?;
 		let fd = FileDescriptor::new(flags, file)?;
 		// Insert the FD
-		self.extend(id)?;
-		let fd = self.0[id as usize].insert(fd);
-		Ok((id, fd))
+		self.insert(id, fd)?;
+		Ok((id, self.get_fd(id as _)?))
 	}
 
 	/// Creates a pair of file descriptors. The `flags` field is set to zero for both.
@@ -172,10 +288,10 @@ impl FileDescriptorTable {
 		let id1 = self.get_available_fd(Some(id0 + 1))?;
 		let fd0 = FileDescriptor::new(0, file0)?;
 		let fd1 = FileDescriptor::new(0, file1)?;
-		// Insert the FDs
-		self.extend(id1)?; // `id1` is always larger than `id0`
-		self.0[id0 as usize] = Some(fd0);
-		self.0[id1 as usize] = Some(fd1);
+		// Insert the FDs. Allocate the slot of `id1` first so that `id0` cannot be left alone
+		self.slot(id1)?;
+		self.insert(id0, fd0)?;
+		self.insert(id1, fd1)?;
 		Ok((id0, id1))
 	}
 
@@ -184,9 +300,10 @@ impl FileDescriptorTable {
 	/// If the file descriptor does not exist, the function returns [`errno::EBADF`].
 	pub fn get_fd(&self, id: c_int) -> EResult<&FileDescriptor> {
 		let id: usize = id.try_into().map_err(|_| errno!(EBADF))?;
-		self.0
-			.get(id)
+		self.chunks
+			.get(id / CHUNK_SIZE)
 			.and_then(Option::as_ref)
+			.and_then(|chunk| chunk[id % CHUNK_SIZE].as_ref())
 			.ok_or_else(|| errno!(EBADF))
 	}
 
@@ -195,9 +312,10 @@ impl FileDescriptorTable {
 	/// If the file descriptor does not exist, the function returns [`errno::EBADF`].
 	pub fn get_fd_mut(&mut self, id: c_int) -> EResult<&mut FileDescriptor> {
 		let id: usize = id.try_into().map_err(|_| errno!(EBADF))?;
-		self.0
-			.get_mut(id)
+		self.chunks
+			.get_mut(id / CHUNK_SIZE)
 			.and_then(Option::as_mut)
+			.and_then(|chunk| chunk[id % CHUNK_SIZE].as_mut())
 			.ok_or_else(|| errno!(EBADF))
 	}
 
@@ -219,7 +337,7 @@ impl FileDescriptorTable {
 			NewFDConstraint::None => self.get_available_fd(None)?,
 			NewFDConstraint::Fixed(id) => {
 				let id: u32 = id.try_into().map_err(|_| errno!(EBADF))?;
-				if id >= OPEN_MAX {
+				if id >= self.get_limit() {
 					return Err(errno!(EMFILE));
 				}
 				id
@@ -232,16 +350,11 @@ impl FileDescriptorTable {
 		let mut new_fd = old_fd.clone();
 		let flags = if cloexec { FD_CLOEXEC } else { 0 };
 		new_fd.flags = flags;
-		// Make sure the table is large enough
-		self.extend(new_id)?;
-		// If there was a file descriptor in the slot, close it
-		let slot = &mut self.0[new_id as usize];
-		if let Some(prev) = slot.take() {
-			let _ = prev.close();
+		// Insert the FD. If there was a file descriptor in the slot, close it
+		if let Some(prev) = self.insert(new_id, new_fd)? {
+			let _ = prev.close_by_process();
 		}
-		// Insert the FD
-		let new_fd = slot.insert(new_fd);
-		Ok((new_id, new_fd))
+		Ok((new_id, self.get_fd(new_id as _)?))
 	}
 
 	/// Duplicates the whole file descriptors table.
@@ -249,50 +362,74 @@ impl FileDescriptorTable {
 	/// `cloexec` specifies whether the cloexec flag must be taken into account. This is the case
 	/// when executing a program.
 	pub fn duplicate(&self, cloexec: bool) -> EResult<Self> {
+		let mut table = Self::default();
+		table.limit = self.limit;
+		// cloexec implies the FD's cloexec flag must be clear
 		let fds = self
-			.0
 			.iter()
-			.cloned()
-			.map(|fd| {
-				fd.filter(|fd| {
-					// cloexec implies the FD's cloexec flag must be clear
-					!cloexec || fd.flags & FD_CLOEXEC == 0
-				})
-			})
-			.collect::<CollectResult<Vec<_>>>()
-			.0?;
-		Ok(Self(fds))
+			.filter(|(_, fd)| !cloexec || fd.flags & FD_CLOEXEC == 0);
+		for (id, fd) in fds {
+			table.insert(id, fd.clone())?;
+		}
+		Ok(table)
 	}
 
 	/// Closes the file descriptor with the ID `id`.
 	///
 	/// If the file descriptor does not exist, the function returns [`errno::EBADF`].
 	pub fn close_fd(&mut self, id: c_int) -> EResult<()> {
-		let id: usize = id.try_into().map_err(|_| errno!(EBADF))?;
-		let fd = self.0.get_mut(id).ok_or_else(|| errno!(EBADF))?;
-		// Remove FD from table
-		let Some(fd) = fd.take() else {
-			return Err(errno!(EBADF));
-		};
-		// Shrink the table if necessary
-		let new_len = self
-			.0
-			.iter()
-			.enumerate()
-			.rfind(|(_, fd)| fd.is_some())
-			.map(|(i, _)| i + 1)
-			.unwrap_or(0);
-		self.0.truncate(new_len);
-		// Close FD
-		fd.close()
+		let id: u32 = id.try_into().map_err(|_| errno!(EBADF))?;
+		let fd = self.remove(id).ok_or_else(|| errno!(EBADF))?;
+		fd.close_by_process()
+	}
+
+	/// Closes all the file descriptors with an ID in the range `first..=last`.
+	///
+	/// Errors on close are ignored.
+	pub fn close_range(&mut self, first: u32, last: u32) {
+		let mut cursor = first as usize;
+		while let Some(id) = self.used.find_set_from(cursor) {
+			if id > last as usize {
+				break;
+			}
+			if let Some(fd) = self.remove(id as _) {
+				let _ = fd.close_by_process();
+			}
+			cursor = id + 1;
+		}
+	}
+
+	/// Sets the `FD_CLOEXEC` flag on all the file descriptors with an ID in the range
+	/// `first..=last`.
+	pub fn set_cloexec_range(&mut self, first: u32, last: u32) {
+		let mut cursor = first as usize;
+		while let Some(id) = self.used.find_set_from(cursor) {
+			if id > last as usize {
+				break;
+			}
+			if let Ok(fd) = self.get_fd_mut(id as _) {
+				fd.flags |= FD_CLOEXEC;
+			}
+			cursor = id + 1;
+		}
+	}
+
+	/// Releases the record locks of the process with PID `pid` on the files of the table.
+	pub fn release_locks(&self, pid: Pid) {
+		for (_, fd) in self.iter() {
+			if let Some(node) = fd.get_file().node() {
+				node.locks.release(LockOwner::Process(pid));
+			}
+		}
 	}
 }
 
 impl Drop for FileDescriptorTable {
 	fn drop(&mut self) {
-		let fds = mem::take(&mut self.0);
-		for fd in fds.into_iter().flatten() {
-			let _ = fd.close();
+		for chunk in self.chunks.iter_mut().flatten() {
+			for fd in chunk.iter_mut().filter_map(Option::take) {
+				let _ = fd.close();
+			}
 		}
 	}
 }
@@ -346,4 +483,66 @@ mod test {
 		assert!(id3 >= 8);
 		assert_ne!(id3, id2);
 	}
+
+	#[test_case]
+	fn fd_limit() {
+		let mut fds = FileDescriptorTable::default();
+		fds.set_limit(2);
+		fds.create_fd(0, dummy_file()).unwrap();
+		fds.create_fd(0, dummy_file()).unwrap();
+		assert_eq!(fds.create_fd(0, dummy_file()).unwrap_err(), errno!(EMFILE));
+		assert_eq!(
+			fds.duplicate_fd(0, NewFDConstraint::Fixed(2), false)
+				.unwrap_err(),
+			errno!(EMFILE)
+		);
+	}
+
+	#[test_case]
+	fn fd_gap() {
+		let mut fds = FileDescriptorTable::default();
+		fds.create_fd(0, dummy_file()).unwrap();
+		let (id, _) = fds
+			.duplicate_fd(0, NewFDConstraint::Fixed(1000), false)
+			.unwrap();
+		assert_eq!(id, 1000);
+		// Only the first and last chunks are allocated
+		assert_eq!(fds.chunks.iter().flatten().count(), 2);
+		let (id, _) = fds.create_fd(0, dummy_file()).unwrap();
+		assert_eq!(id, 1);
+		assert!(fds.iter().map(|(id, _)| id).eq([0, 1, 1000]));
+		fds.close_fd(1000).unwrap();
+		assert_eq!(fds.chunks.len(), 1);
+		assert_eq!(fds.close_fd(1000).unwrap_err(), errno!(EBADF));
+	}
+
+	#[test_case]
+	fn fd_close_range() {
+		let mut fds = FileDescriptorTable::default();
+		for _ in 0..8 {
+			fds.create_fd(0, dummy_file()).unwrap();
+		}
+		fds.close_range(2, 5);
+		assert!(fds.iter().map(|(id, _)| id).eq([0, 1, 6, 7]));
+		let (id, _) = fds.create_fd(0, dummy_file()).unwrap();
+		assert_eq!(id, 2);
+		fds.close_range(1, u32::MAX);
+		assert!(fds.iter().map(|(id, _)| id).eq([0]));
+	}
+
+	#[test_case]
+	fn fd_cloexec_range() {
+		let mut fds = FileDescriptorTable::default();
+		for _ in 0..4 {
+			fds.create_fd(0, dummy_file()).unwrap();
+		}
+		fds.set_cloexec_range(1, 2);
+		let cloexec = |fds: &FileDescriptorTable, id| fds.get_fd(id).unwrap().flags & FD_CLOEXEC;
+		assert_eq!(cloexec(&fds, 0), 0);
+		assert_eq!(cloexec(&fds, 1), FD_CLOEXEC);
+		assert_eq!(cloexec(&fds, 2), FD_CLOEXEC);
+		assert_eq!(cloexec(&fds, 3), 0);
+		let fds = fds.duplicate(true).unwrap();
+		assert!(fds.iter().map(|(id, _)| id).eq([0, 3]));
+	}
 }
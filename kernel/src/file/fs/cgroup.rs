@@ -0,0 +1,590 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `cgroup2` filesystem exposes the hierarchy of control groups to userspace.
+//!
+//! Each directory represents a cgroup. Creating a directory creates a child cgroup, and removing
+//! it removes the cgroup, provided it is empty. Each directory contains control files, used to
+//! read the state of the cgroup and configure its controllers.
+
+use super::{DummyOps, FileOps, Filesystem, FilesystemOps, FilesystemType, NodeOps, Statfs};
+use crate::{
+	device::BlkDev,
+	file::{
+		DirContext, DirEntry, File, FileType, Stat,
+		fs::kernfs::{EitherOps, StaticDir, StaticEntry, box_file},
+		vfs,
+		vfs::node::Node,
+	},
+	format_content,
+	memory::user::UserSlice,
+	process::{
+		Process, cgroup,
+		cgroup::{CONTROLLER_CPU, CONTROLLER_MEMORY, CONTROLLERS, Cgroup},
+		pid::Pid,
+	},
+	sync::mutex::Mutex,
+};
+use core::{any::Any, fmt, slice, str, sync::atomic::AtomicBool};
+use utils::{
+	boxed::Box,
+	collections::{path::PathBuf, vec::Vec},
+	errno,
+	errno::EResult,
+	ptr::arc::Arc,
+};
+
+/// Reads the content written by userspace to a control file into `data`, and returns it with
+/// surrounding whitespaces trimmed.
+fn read_input<'d>(buf: UserSlice<u8>, data: &'d mut [u8]) -> EResult<&'d str> {
+	let len = buf.copy_from_user(0, data)?;
+	str::from_utf8(&data[..len])
+		.map(str::trim)
+		.map_err(|_| errno!(EINVAL))
+}
+
+/// Parses a value which can be either an unsigned integer or `max`, meaning no limit.
+fn parse_max<T: str::FromStr>(s: &str) -> EResult<Option<T>> {
+	match s {
+		"max" => Ok(None),
+		s => s.parse().map(Some).map_err(|_| errno!(EINVAL)),
+	}
+}
+
+/// Displays a set of controllers as a space-separated list of names.
+struct ControllerList(u8);
+
+impl fmt::Display for ControllerList {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut names = CONTROLLERS
+			.iter()
+			.filter(|(_, bit)| self.0 & bit != 0)
+			.map(|(name, _)| name);
+		if let Some(name) = names.next() {
+			write!(f, "{name}")?;
+		}
+		for name in names {
+			write!(f, " {name}")?;
+		}
+		Ok(())
+	}
+}
+
+/// Returns the set of controllers available to `cgroup`.
+fn available_controllers(cgroup: &Cgroup) -> u8 {
+	match &cgroup.parent {
+		Some(parent) => parent.subtree_control(),
+		None => CONTROLLERS.iter().fold(0, |mask, (_, bit)| mask | bit),
+	}
+}
+
+/// The `cgroup.controllers` file, listing the controllers available to the cgroup.
+#[derive(Debug)]
+struct Controllers(Arc<Cgroup>);
+
+impl FileOps for Controllers {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let controllers = ControllerList(available_controllers(&self.0));
+		format_content!(off, buf, "{controllers}\n")
+	}
+}
+
+/// The `cgroup.subtree_control` file, listing the controllers enabled for the children of the
+/// cgroup.
+///
+/// Controllers are enabled or disabled by writing their names, prefixed with `+` or `-`.
+#[derive(Debug)]
+struct SubtreeControl(Arc<Cgroup>);
+
+impl FileOps for SubtreeControl {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let controllers = ControllerList(self.0.subtree_control());
+		format_content!(off, buf, "{controllers}\n")
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let mut data = [0u8; 64];
+		let input = read_input(buf, &mut data)?;
+		let mut enable = 0;
+		let mut disable = 0;
+		for tok in input.split_whitespace() {
+			let (mask, name) = match tok.split_at_checked(1) {
+				Some(("+", name)) => (&mut enable, name),
+				Some(("-", name)) => (&mut disable, name),
+				_ => return Err(errno!(EINVAL)),
+			};
+			let (_, bit) = CONTROLLERS
+				.iter()
+				.find(|(n, _)| *n == name)
+				.ok_or_else(|| errno!(EINVAL))?;
+			*mask |= bit;
+		}
+		self.0.set_subtree_control(enable, disable)?;
+		Ok(buf.len())
+	}
+}
+
+/// Displays a list of PIDs, one per line.
+struct PidList(Vec<Pid>);
+
+impl fmt::Display for PidList {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for pid in self.0.iter() {
+			writeln!(f, "{pid}")?;
+		}
+		Ok(())
+	}
+}
+
+/// The `cgroup.procs` file, listing the processes belonging to the cgroup.
+///
+/// Writing a PID moves the process to the cgroup. The PID `0` designates the writing process.
+#[derive(Debug)]
+struct Procs(Arc<Cgroup>);
+
+impl FileOps for Procs {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let procs = PidList(self.0.procs()?);
+		format_content!(off, buf, "{procs}")
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let mut data = [0u8; 32];
+		let pid: Pid = read_input(buf, &mut data)?
+			.parse()
+			.map_err(|_| errno!(EINVAL))?;
+		let proc = match pid {
+			0 => Process::current(),
+			pid => Process::get_by_pid(pid).ok_or_else(|| errno!(ESRCH))?,
+		};
+		Cgroup::attach(&self.0, &proc)?;
+		Ok(buf.len())
+	}
+}
+
+/// The `cpu.max` file, holding the CPU bandwidth limit of the cgroup.
+///
+/// The content is the quota (or `max`) followed by the period, both in microseconds.
+#[derive(Debug)]
+struct CpuMax(Arc<Cgroup>);
+
+impl FileOps for CpuMax {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		match self.0.cpu_max() {
+			(Some(quota), period) => format_content!(off, buf, "{quota} {period}\n"),
+			(None, period) => format_content!(off, buf, "max {period}\n"),
+		}
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let mut data = [0u8; 64];
+		let mut input = read_input(buf, &mut data)?.split_whitespace();
+		let quota = parse_max(input.next().ok_or_else(|| errno!(EINVAL))?)?;
+		let period = input
+			.next()
+			.map(|p| p.parse().map_err(|_| errno!(EINVAL)))
+			.transpose()?;
+		if input.next().is_some() {
+			return Err(errno!(EINVAL));
+		}
+		self.0.set_cpu_max(quota, period)?;
+		Ok(buf.len())
+	}
+}
+
+/// The `cpu.stat` file, giving CPU usage statistics of the cgroup.
+#[derive(Debug)]
+struct CpuStat(Arc<Cgroup>);
+
+impl FileOps for CpuStat {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let stat = self.0.cpu_stat();
+		format_content!(
+			off,
+			buf,
+			"usage_usec {}\nnr_periods {}\nnr_throttled {}\nthrottled_usec {}\n",
+			stat.usage_usec,
+			stat.nr_periods,
+			stat.nr_throttled,
+			stat.throttled_usec
+		)
+	}
+}
+
+/// The `cpu.weight` file, holding the CPU weight of the cgroup.
+#[derive(Debug)]
+struct CpuWeight(Arc<Cgroup>);
+
+impl FileOps for CpuWeight {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", self.0.cpu_weight())
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let mut data = [0u8; 32];
+		let weight = read_input(buf, &mut data)?
+			.parse()
+			.map_err(|_| errno!(EINVAL))?;
+		self.0.set_cpu_weight(weight)?;
+		Ok(buf.len())
+	}
+}
+
+/// The `memory.current` file, giving the amount of memory used by the cgroup, in bytes.
+#[derive(Debug)]
+struct MemoryCurrent(Arc<Cgroup>);
+
+impl FileOps for MemoryCurrent {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", self.0.memory_current())
+	}
+}
+
+/// The `memory.events` file, giving the number of memory events that occurred in the cgroup.
+#[derive(Debug)]
+struct MemoryEvents(Arc<Cgroup>);
+
+impl FileOps for MemoryEvents {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "max {}\n", self.0.memory_events_max())
+	}
+}
+
+/// The `memory.max` file, holding the memory limit of the cgroup in bytes, or `max`.
+#[derive(Debug)]
+struct MemoryMax(Arc<Cgroup>);
+
+impl FileOps for MemoryMax {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		match self.0.memory_max() {
+			Some(max) => format_content!(off, buf, "{max}\n"),
+			None => format_content!(off, buf, "max\n"),
+		}
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let mut data = [0u8; 32];
+		let max = parse_max(read_input(buf, &mut data)?)?;
+		self.0.set_memory_max(max);
+		Ok(buf.len())
+	}
+}
+
+/// Returns the status of a read-only control file.
+fn ro_file_stat(_: Arc<Cgroup>) -> Stat {
+	Stat {
+		mode: FileType::Regular.to_mode() | 0o444,
+		..Default::default()
+	}
+}
+
+/// Returns the status of a writable control file.
+fn rw_file_stat(_: Arc<Cgroup>) -> Stat {
+	Stat {
+		mode: FileType::Regular.to_mode() | 0o644,
+		..Default::default()
+	}
+}
+
+/// Control files present in every cgroup, sorted by name.
+static CORE_FILES: [StaticEntry<Arc<Cgroup>>; 3] = [
+	StaticEntry {
+		name: b"cgroup.controllers",
+		stat: ro_file_stat,
+		init: EitherOps::File(|cg| box_file(Controllers(cg))),
+	},
+	StaticEntry {
+		name: b"cgroup.procs",
+		stat: rw_file_stat,
+		init: EitherOps::File(|cg| box_file(Procs(cg))),
+	},
+	StaticEntry {
+		name: b"cgroup.subtree_control",
+		stat: rw_file_stat,
+		init: EitherOps::File(|cg| box_file(SubtreeControl(cg))),
+	},
+];
+
+/// Control files of controllers, sorted by name, along with the controller they belong to.
+///
+/// The root cgroup only has the statistics files, since it cannot be limited.
+static CONTROLLER_FILES: [(StaticEntry<Arc<Cgroup>>, u8, bool); 6] = [
+	(
+		StaticEntry {
+			name: b"cpu.max",
+			stat: rw_file_stat,
+			init: EitherOps::File(|cg| box_file(CpuMax(cg))),
+		},
+		CONTROLLER_CPU,
+		false,
+	),
+	(
+		StaticEntry {
+			name: b"cpu.stat",
+			stat: ro_file_stat,
+			init: EitherOps::File(|cg| box_file(CpuStat(cg))),
+		},
+		CONTROLLER_CPU,
+		true,
+	),
+	(
+		StaticEntry {
+			name: b"cpu.weight",
+			stat: rw_file_stat,
+			init: EitherOps::File(|cg| box_file(CpuWeight(cg))),
+		},
+		CONTROLLER_CPU,
+		false,
+	),
+	(
+		StaticEntry {
+			name: b"memory.current",
+			stat: ro_file_stat,
+			init: EitherOps::File(|cg| box_file(MemoryCurrent(cg))),
+		},
+		CONTROLLER_MEMORY,
+		false,
+	),
+	(
+		StaticEntry {
+			name: b"memory.events",
+			stat: ro_file_stat,
+			init: EitherOps::File(|cg| box_file(MemoryEvents(cg))),
+		},
+		CONTROLLER_MEMORY,
+		false,
+	),
+	(
+		StaticEntry {
+			name: b"memory.max",
+			stat: rw_file_stat,
+			init: EitherOps::File(|cg| box_file(MemoryMax(cg))),
+		},
+		CONTROLLER_MEMORY,
+		false,
+	),
+];
+
+/// Returns an iterator over the control files of `cgroup`.
+///
+/// The files of a controller are present only if the controller is available to the cgroup.
+fn control_files(cgroup: &Cgroup) -> impl Iterator<Item = &'static StaticEntry<Arc<Cgroup>>> {
+	let root = cgroup.is_root();
+	let available = available_controllers(cgroup);
+	let controller_files = CONTROLLER_FILES
+		.iter()
+		.filter(move |(_, controller, in_root)| {
+			if root {
+				*in_root
+			} else {
+				available & controller != 0
+			}
+		})
+		.map(|(ent, ..)| ent);
+	CORE_FILES.iter().chain(controller_files)
+}
+
+/// Returns the status of a cgroup directory.
+fn dir_stat() -> Stat {
+	Stat {
+		mode: FileType::Directory.to_mode() | 0o755,
+		..Default::default()
+	}
+}
+
+/// A cgroup directory.
+///
+/// The cgroup is `None` while the directory is being created, until it gets linked to its parent.
+#[derive(Debug, Default)]
+struct CgroupDir(Mutex<Option<Arc<Cgroup>>>);
+
+impl CgroupDir {
+	/// Returns the cgroup of the directory.
+	fn cgroup(&self) -> EResult<Arc<Cgroup>> {
+		self.0.lock().clone().ok_or_else(|| errno!(ENOENT))
+	}
+
+	/// Creates a node for the directory of `cgroup`.
+	fn new_node(fs: &Arc<Filesystem>, cgroup: Arc<Cgroup>) -> EResult<Arc<Node>> {
+		Ok(Arc::new(Node {
+			inode: 0,
+			fs: fs.clone(),
+
+			stat: Mutex::new(dir_stat()),
+			dirty: AtomicBool::new(false),
+
+			node_ops: Box::new(CgroupDir(Mutex::new(Some(cgroup))))?,
+			file_ops: Box::new(DummyOps)?,
+
+			lock: Default::default(),
+			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
+		})?)
+	}
+}
+
+impl NodeOps for CgroupDir {
+	fn lookup_entry(&self, dir: &Node, ent: &mut vfs::Entry) -> EResult<()> {
+		let cgroup = self.cgroup()?;
+		let name = ent.name.as_bytes();
+		let file = control_files(&cgroup).find(|e| e.name == name);
+		if let Some(file) = file {
+			let file_dir = StaticDir {
+				entries: slice::from_ref(file),
+				data: cgroup,
+			};
+			return file_dir.lookup_entry(dir, ent);
+		}
+		ent.node = cgroup
+			.get_child(name)
+			.map(|child| Self::new_node(&dir.fs, child))
+			.transpose()?;
+		Ok(())
+	}
+
+	fn iter_entries(&self, _dir: &Node, ctx: &mut DirContext) -> EResult<()> {
+		let off: usize = ctx.off.try_into().map_err(|_| errno!(EINVAL))?;
+		let cgroup = self.cgroup()?;
+		let children = cgroup.children()?;
+		let files = control_files(&cgroup).map(|e| (e.name, (e.stat)(cgroup.clone()).get_type()));
+		let dirs = children
+			.iter()
+			.map(|c| (c.name.as_bytes(), Some(FileType::Directory)));
+		for (name, entry_type) in files.chain(dirs).skip(off) {
+			let ent = DirEntry {
+				inode: 0,
+				entry_type,
+				name,
+			};
+			if !(ctx.write)(&ent)? {
+				break;
+			}
+			ctx.off += 1;
+		}
+		Ok(())
+	}
+
+	fn link(&self, _parent: Arc<Node>, ent: &vfs::Entry) -> EResult<()> {
+		let cgroup = self.cgroup()?;
+		let node = ent.node();
+		let dir: &CgroupDir = (&*node.node_ops as &dyn Any)
+			.downcast_ref()
+			.ok_or_else(|| errno!(EPERM))?;
+		let child = Cgroup::create_child(&cgroup, ent.name.as_bytes())?;
+		*dir.0.lock() = Some(child);
+		Ok(())
+	}
+
+	fn unlink(&self, _parent: &Node, ent: &vfs::Entry) -> EResult<()> {
+		let cgroup = self.cgroup()?;
+		let name = ent.name.as_bytes();
+		if control_files(&cgroup).any(|e| e.name == name) {
+			return Err(errno!(EPERM));
+		}
+		cgroup.remove_child(name)
+	}
+}
+
+/// The cgroup2 filesystem.
+#[derive(Debug)]
+pub struct CgroupFs;
+
+impl FilesystemOps for CgroupFs {
+	fn get_name(&self) -> &[u8] {
+		b"cgroup2"
+	}
+
+	fn cache_entries(&self) -> bool {
+		false
+	}
+
+	fn get_stat(&self) -> EResult<Statfs> {
+		Ok(Statfs {
+			f_type: 0,
+			f_bsize: 0,
+			f_blocks: 0,
+			f_bfree: 0,
+			f_bavail: 0,
+			f_files: 0,
+			f_ffree: 0,
+			f_fsid: Default::default(),
+			f_namelen: 0,
+			f_frsize: 0,
+			f_flags: 0,
+			f_spare: [0; 4],
+		})
+	}
+
+	fn root(&self, fs: &Arc<Filesystem>) -> EResult<Arc<Node>> {
+		CgroupDir::new_node(fs, cgroup::root()?)
+	}
+
+	fn create_node(
+		&self,
+		fs: &Arc<Filesystem>,
+		_parent: Option<&Node>,
+		stat: Stat,
+	) -> EResult<Arc<Node>> {
+		// Only directories can be created, and the cgroup is created when linked to its parent
+		if stat.get_type() != Some(FileType::Directory) {
+			return Err(errno!(EPERM));
+		}
+		Ok(Arc::new(Node {
+			inode: 0,
+			fs: fs.clone(),
+
+			stat: Mutex::new(dir_stat()),
+			dirty: AtomicBool::new(false),
+
+			node_ops: Box::new(CgroupDir::default())?,
+			file_ops: Box::new(DummyOps)?,
+
+			lock: Default::default(),
+			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
+		})?)
+	}
+
+	fn destroy_node(&self, _node: &Node) -> EResult<()> {
+		Ok(())
+	}
+}
+
+/// The cgroup2 filesystem type.
+pub struct CgroupFsType;
+
+impl FilesystemType for CgroupFsType {
+	fn get_name(&self) -> &'static [u8] {
+		b"cgroup2"
+	}
+
+	fn detect(&self, _dev: &Arc<BlkDev>) -> EResult<bool> {
+		Ok(false)
+	}
+
+	fn load_filesystem(
+		&self,
+		_dev: Option<Arc<BlkDev>>,
+		_mountpath: PathBuf,
+		_flags: u32,
+	) -> EResult<Arc<Filesystem>> {
+		Ok(Filesystem::new(0, Box::new(CgroupFs)?)?)
+	}
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Cache of directory listings, for filesystems on which listing a directory is slow (such as
+//! network filesystems).
+//!
+//! A filesystem opts in by returning its cache from [`super::FilesystemOps::dir_cache`] and by
+//! listing directories through [`DirCache::iter_entries`].
+//!
+//! A listing is kept for a fixed duration, after which it is fetched again. The VFS invalidates
+//! the listing of a directory when it is modified locally. Modifications made by other clients
+//! of the filesystem are only visible once the listing expires.
+//!
+//! While a listing is cached, iteration offsets are indexes in the listing.
+
+use crate::{
+	file::{DirContext, DirEntry, FileType, INode},
+	sync::mutex::Mutex,
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::Timestamp,
+	},
+};
+use utils::{
+	collections::{hashmap::HashMap, string::String, vec::Vec},
+	errno::EResult,
+};
+
+/// A cached directory entry.
+#[derive(Debug)]
+struct CachedEntry {
+	/// The entry's inode.
+	inode: INode,
+	/// The entry's type, if known.
+	entry_type: Option<FileType>,
+	/// The name of the entry.
+	name: String,
+}
+
+/// The cached listing of a directory.
+#[derive(Debug)]
+struct Listing {
+	/// The entries of the directory.
+	entries: Vec<CachedEntry>,
+	/// The timestamp at which the listing expires, in nanoseconds.
+	expires: Timestamp,
+}
+
+/// A cache of directory listings.
+#[derive(Debug)]
+pub struct DirCache {
+	/// The duration for which a listing remains valid, in nanoseconds.
+	ttl: Timestamp,
+	/// Cached listings, by directory inode.
+	listings: Mutex<HashMap<INode, Listing>>,
+}
+
+impl DirCache {
+	/// Creates a new cache, in which listings remain valid for `ttl` nanoseconds.
+	pub const fn new(ttl: Timestamp) -> Self {
+		Self {
+			ttl,
+			listings: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Iterates on the entries of the directory `dir`.
+	///
+	/// If the directory's listing is not cached or has expired, it is fetched with `fetch`, which
+	/// is the filesystem's own implementation of the iteration.
+	pub fn iter_entries<F: FnOnce(&mut DirContext) -> EResult<()>>(
+		&self,
+		dir: INode,
+		ctx: &mut DirContext,
+		fetch: F,
+	) -> EResult<()> {
+		let now = current_time_ns(Clock::Monotonic);
+		let mut listings = self.listings.lock();
+		let valid = listings.get(&dir).is_some_and(|l| now < l.expires);
+		if !valid {
+			// Fetch without holding the lock, since it may be slow
+			drop(listings);
+			let mut entries = Vec::new();
+			let mut res = Ok(());
+			fetch(&mut DirContext {
+				write: &mut |ent: &DirEntry| {
+					res = entries.push(CachedEntry {
+						inode: ent.inode,
+						entry_type: ent.entry_type,
+						name: String::try_from(ent.name)?,
+					});
+					Ok(res.is_ok())
+				},
+				off: 0,
+			})?;
+			res?;
+			listings = self.listings.lock();
+			listings.insert(
+				dir,
+				Listing {
+					entries,
+					expires: now + self.ttl,
+				},
+			)?;
+		}
+		let listing = listings.get(&dir).unwrap();
+		for ent in listing.entries.iter().skip(ctx.off as usize) {
+			let ent = DirEntry {
+				inode: ent.inode,
+				entry_type: ent.entry_type,
+				name: &ent.name,
+			};
+			if !(ctx.write)(&ent)? {
+				break;
+			}
+			ctx.off += 1;
+		}
+		Ok(())
+	}
+
+	/// Invalidates the listing of the directory `dir`, so that it is fetched again on the next
+	/// iteration.
+	pub fn invalidate(&self, dir: INode) {
+		self.listings.lock().remove(&dir);
+	}
+
+	/// Invalidates all listings.
+	pub fn clear(&self) {
+		self.listings.lock().clear();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Lists `dir` through `cache`, counting calls to the fetch function in `fetches`.
+	fn list(cache: &DirCache, dir: INode, names: &[&[u8]], fetches: &mut usize) -> usize {
+		let mut count = 0;
+		cache
+			.iter_entries(
+				dir,
+				&mut DirContext {
+					write: &mut |_| {
+						count += 1;
+						Ok(true)
+					},
+					off: 0,
+				},
+				|ctx| {
+					*fetches += 1;
+					for (i, name) in names.iter().enumerate() {
+						(ctx.write)(&DirEntry {
+							inode: i as _,
+							entry_type: None,
+							name,
+						})?;
+					}
+					Ok(())
+				},
+			)
+			.unwrap();
+		count
+	}
+
+	#[test_case]
+	fn dir_cache_invalidate() {
+		let cache = DirCache::new(u64::MAX / 2);
+		let mut fetches = 0;
+		assert_eq!(list(&cache, 1, &[b"a", b"b"], &mut fetches), 2);
+		// Served from the cache
+		assert_eq!(list(&cache, 1, &[b"a"], &mut fetches), 2);
+		assert_eq!(fetches, 1);
+		cache.invalidate(1);
+		assert_eq!(list(&cache, 1, &[b"a"], &mut fetches), 1);
+		assert_eq!(fetches, 2);
+	}
+}
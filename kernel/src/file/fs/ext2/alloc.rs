@@ -0,0 +1,387 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Allocation policies, deciding where inodes and blocks are placed on the filesystem.
+//!
+//! New directories are spread across block groups, while other inodes are placed in the group of
+//! their parent directory (Orlov allocator). This way, the files of a directory are close to each
+//! other and to the directory itself.
+//!
+//! Blocks are allocated as close as possible to a goal, which is usually the block following the
+//! previous block of the file. To keep files contiguous when several of them are written at the
+//! same time, a regular file being written gets a reservation window: a range of blocks in which
+//! other inodes do not allocate, unless the filesystem is full. Windows are only kept in memory,
+//! and grow as long as the file is written sequentially.
+
+use super::{Ext2Fs, bgd::BlockGroupDescriptor, inode::ROOT_DIRECTORY_INODE, read_block};
+use crate::memory::cache::{RcFrame, RcFrameVal};
+use core::{
+	cmp::min,
+	hint::unlikely,
+	ops::Range,
+	sync::atomic::{
+		AtomicU8,
+		Ordering::{AcqRel, Acquire, Release},
+	},
+};
+use utils::{errno, errno::EResult, limits::PAGE_SIZE};
+
+/// The default size of a reservation window, in blocks.
+const DEFAULT_WINDOW_SIZE: u32 = 8;
+/// The maximum size of a reservation window, in blocks.
+const MAX_WINDOW_SIZE: u32 = 1024;
+
+/// A range of blocks reserved for the content of an inode.
+#[derive(Debug)]
+pub(super) struct Window {
+	/// The reserved blocks
+	range: Range<u32>,
+	/// The size the window was requested with, which can be larger than `range`
+	size: u32,
+}
+
+impl Ext2Fs {
+	/// Allocates an inode in the block group `group` and returns its ID.
+	///
+	/// If the group has no free inode, the function returns `None`.
+	fn alloc_inode_in(&self, group: u32, directory: bool) -> EResult<Option<u32>> {
+		let bgd = BlockGroupDescriptor::get(group, self)?;
+		if bgd.bg_free_inodes_count.load(Acquire) == 0 {
+			return Ok(None);
+		}
+		let Some(j) = self.bitmap_alloc(bgd.bg_inode_bitmap, self.sp.s_inodes_per_group)? else {
+			return Ok(None);
+		};
+		self.sp.s_free_inodes_count.fetch_sub(1, Release);
+		bgd.bg_free_inodes_count.fetch_sub(1, Release);
+		if directory {
+			bgd.bg_used_dirs_count.fetch_add(1, Release);
+		}
+		self.sp.mark_dirty();
+		bgd.mark_dirty();
+		Ok(Some(group * self.sp.s_inodes_per_group + j + 1))
+	}
+
+	/// Returns the block group in which a new directory, child of the directory `parent`, should
+	/// be placed.
+	///
+	/// Children of the root directory are spread across the groups that have more free inodes and
+	/// blocks than average, choosing the one with the fewest directories. Other directories stay
+	/// close to their parent, unless its group is running out of space or contains too many
+	/// directories.
+	fn find_group_dir(&self, parent: u32) -> EResult<u32> {
+		let groups = self.sp.get_block_groups_count();
+		let parent_group = (parent - 1) / self.sp.s_inodes_per_group;
+		let avg_free_inodes = self.sp.s_free_inodes_count.load(Acquire) / groups;
+		let avg_free_blocks = self.sp.s_free_blocks_count.load(Acquire) / groups;
+		// Gather the state of each group
+		let mut dirs = 0;
+		for group in 0..groups {
+			let bgd = BlockGroupDescriptor::get(group, self)?;
+			dirs += bgd.bg_used_dirs_count.load(Acquire) as u32;
+		}
+		let usable = |bgd: &BlockGroupDescriptor, min_inodes: u32, min_blocks: u32| {
+			let free_inodes = bgd.bg_free_inodes_count.load(Acquire) as u32;
+			let free_blocks = bgd.bg_free_blocks_count.load(Acquire) as u32;
+			free_inodes > 0 && free_inodes >= min_inodes && free_blocks >= min_blocks
+		};
+		if parent == ROOT_DIRECTORY_INODE {
+			let mut best = None;
+			for group in 0..groups {
+				let bgd = BlockGroupDescriptor::get(group, self)?;
+				if !usable(&bgd, avg_free_inodes, avg_free_blocks) {
+					continue;
+				}
+				let group_dirs = bgd.bg_used_dirs_count.load(Acquire);
+				if best.is_none_or(|(_, best_dirs)| group_dirs < best_dirs) {
+					best = Some((group, group_dirs));
+				}
+			}
+			if let Some((group, _)) = best {
+				return Ok(group);
+			}
+		} else {
+			let max_dirs = dirs / groups + self.sp.s_inodes_per_group / 16;
+			let min_inodes = avg_free_inodes.saturating_sub(self.sp.s_inodes_per_group / 4);
+			let min_blocks = avg_free_blocks.saturating_sub(self.sp.s_blocks_per_group / 4);
+			for i in 0..groups {
+				let group = (parent_group + i) % groups;
+				let bgd = BlockGroupDescriptor::get(group, self)?;
+				if usable(&bgd, min_inodes, min_blocks)
+					&& (bgd.bg_used_dirs_count.load(Acquire) as u32) < max_dirs
+				{
+					return Ok(group);
+				}
+			}
+		}
+		// Fall back to any group with an average number of free inodes
+		for i in 0..groups {
+			let group = (parent_group + i) % groups;
+			let bgd = BlockGroupDescriptor::get(group, self)?;
+			if usable(&bgd, avg_free_inodes, 0) {
+				return Ok(group);
+			}
+		}
+		Ok(parent_group)
+	}
+
+	/// Returns the block group in which a new inode other than a directory, child of the
+	/// directory `parent`, should be placed.
+	///
+	/// This is the group of the parent if it has free inodes and blocks. Otherwise, groups are
+	/// probed quadratically from the parent's, so that the children of different directories do
+	/// not all end up in the same group.
+	fn find_group_other(&self, parent: u32) -> EResult<u32> {
+		let groups = self.sp.get_block_groups_count();
+		let parent_group = (parent - 1) / self.sp.s_inodes_per_group;
+		let has_room = |group: u32| -> EResult<bool> {
+			let bgd = BlockGroupDescriptor::get(group, self)?;
+			Ok(bgd.bg_free_inodes_count.load(Acquire) > 0
+				&& bgd.bg_free_blocks_count.load(Acquire) > 0)
+		};
+		if has_room(parent_group)? {
+			return Ok(parent_group);
+		}
+		let mut group = (parent_group + parent) % groups;
+		let mut i = 1;
+		while i < groups {
+			group = (group + i) % groups;
+			if has_room(group)? {
+				return Ok(group);
+			}
+			i <<= 1;
+		}
+		Ok(parent_group)
+	}
+
+	/// Allocates an inode and returns its ID.
+	///
+	/// Arguments:
+	/// - `parent` is the inode of the directory in which the new inode is to be created
+	/// - `directory` tells whether the inode is allocated for a directory
+	///
+	/// If no free inode can be found, the function returns an error.
+	pub fn alloc_inode(&self, parent: u32, directory: bool) -> EResult<u32> {
+		if unlikely(self.sp.s_free_inodes_count.load(Acquire) == 0) {
+			return Err(errno!(ENOSPC));
+		}
+		let start = if directory {
+			self.find_group_dir(parent)?
+		} else {
+			self.find_group_other(parent)?
+		};
+		// If the chosen group is full, use the next one that is not
+		let groups = self.sp.get_block_groups_count();
+		for i in 0..groups {
+			if let Some(ino) = self.alloc_inode_in((start + i) % groups, directory)? {
+				return Ok(ino);
+			}
+		}
+		Err(errno!(ENOSPC))
+	}
+
+	/// Marks the block at index `i` in the bitmap of the block group `group` as used, updating
+	/// counters, and returns its ID.
+	fn claim_block(
+		&self,
+		group: u32,
+		bgd: &RcFrameVal<BlockGroupDescriptor>,
+		i: u32,
+	) -> EResult<u32> {
+		let blk = group * self.sp.s_blocks_per_group + i;
+		if unlikely(blk <= 2 || blk >= self.sp.s_blocks_count) {
+			return Err(self.error(errno!(EUCLEAN)));
+		}
+		self.sp.s_free_blocks_count.fetch_sub(1, Release);
+		bgd.bg_free_blocks_count.fetch_sub(1, Release);
+		self.sp.mark_dirty();
+		bgd.mark_dirty();
+		Ok(blk)
+	}
+
+	/// Allocates the first free block in the range `range` of the bitmap of the block group
+	/// `group`, and returns its ID.
+	///
+	/// If `skip` returns `Some(end)` for a block, the search continues from the block `end`.
+	///
+	/// If no free block is found, the function returns `None`.
+	fn alloc_block_in<F: Fn(u32) -> Option<u32>>(
+		&self,
+		group: u32,
+		range: Range<u32>,
+		skip: &F,
+	) -> EResult<Option<u32>> {
+		let bgd = BlockGroupDescriptor::get(group, self)?;
+		if bgd.bg_free_blocks_count.load(Acquire) == 0 {
+			return Ok(None);
+		}
+		let bits_per_blk = self.sp.get_block_size() * 8;
+		let base = group * self.sp.s_blocks_per_group;
+		let mut bitmap: Option<(u32, RcFrame)> = None;
+		let mut i = range.start;
+		while i < range.end {
+			if let Some(end) = skip(base + i) {
+				i = end - base;
+				continue;
+			}
+			let blk_off = bgd.bg_block_bitmap + i / bits_per_blk;
+			if bitmap.as_ref().is_none_or(|(off, _)| *off != blk_off) {
+				bitmap = Some((blk_off, read_block(self, blk_off as _)?));
+			}
+			let blk = &bitmap.as_ref().unwrap().1;
+			let byte_off = (i % bits_per_blk) as usize / 8;
+			let byte = &blk.slice::<AtomicU8>()[byte_off];
+			let val = byte.load(Acquire);
+			// Skip full bytes at once
+			if val == !0 {
+				i = (i | 7) + 1;
+				continue;
+			}
+			let mask = 1 << (i % 8);
+			if val & mask == 0 && byte.fetch_or(mask, AcqRel) & mask == 0 {
+				blk.mark_page_dirty(byte_off / PAGE_SIZE);
+				return self.claim_block(group, &bgd, i).map(Some);
+			}
+			i += 1;
+		}
+		Ok(None)
+	}
+
+	/// Allocates the first free block starting from the block `goal`, wrapping around the end of
+	/// the filesystem, and returns its ID.
+	///
+	/// `skip` works the same as for [`Self::alloc_block_in`].
+	fn search_block<F: Fn(u32) -> Option<u32>>(
+		&self,
+		goal: u32,
+		skip: &F,
+	) -> EResult<Option<u32>> {
+		let groups = self.sp.get_block_groups_count();
+		let blocks_per_group = self.sp.s_blocks_per_group;
+		let goal = if goal < groups * blocks_per_group {
+			goal
+		} else {
+			0
+		};
+		let (goal_group, goal_off) = (goal / blocks_per_group, goal % blocks_per_group);
+		// The group of the goal is searched last from its start
+		for i in 0..=groups {
+			let group = (goal_group + i) % groups;
+			let range = match i {
+				0 => goal_off..blocks_per_group,
+				i if i == groups => 0..goal_off,
+				_ => 0..blocks_per_group,
+			};
+			if let Some(blk) = self.alloc_block_in(group, range, skip)? {
+				return Ok(Some(blk));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Allocates a block as close as possible to the block `goal`, and returns its ID.
+	///
+	/// `ino` is the inode for which the block is allocated. Blocks reserved for other inodes are
+	/// used only if no other block is free.
+	pub fn alloc_block(&self, goal: u32, ino: u32) -> EResult<u32> {
+		if unlikely(self.sp.s_free_blocks_count.load(Acquire) == 0) {
+			return Err(errno!(ENOSPC));
+		}
+		let reservations = self.reservations.lock();
+		let reserved = |blk: u32| {
+			reservations
+				.iter()
+				.find(|(i, win)| **i != ino && win.range.contains(&blk))
+				.map(|(_, win)| win.range.end)
+		};
+		if let Some(blk) = self.search_block(goal, &reserved)? {
+			return Ok(blk);
+		}
+		self.search_block(goal, &|_| None)?
+			.ok_or_else(|| errno!(ENOSPC))
+	}
+
+	/// Allocates a block for the content of the inode `ino`, as close as possible to the block
+	/// `goal`, and returns its ID.
+	///
+	/// The block is taken from the inode's reservation window. If the goal is outside of it, the
+	/// window is moved to the goal.
+	pub fn alloc_reserved_block(&self, ino: u32, goal: u32) -> EResult<u32> {
+		if unlikely(self.sp.s_free_blocks_count.load(Acquire) == 0) {
+			return Err(errno!(ENOSPC));
+		}
+		let blocks_per_group = self.sp.s_blocks_per_group;
+		let mut reservations = self.reservations.lock();
+		let mut size = match self.sp.s_prealloc_blocks {
+			0 => DEFAULT_WINDOW_SIZE,
+			n => n as u32,
+		};
+		if let Some(win) = reservations.remove(&ino) {
+			// Allocate in the current window
+			if win.range.contains(&goal) {
+				let group = goal / blocks_per_group;
+				let base = group * blocks_per_group;
+				let range = (goal - base)..(win.range.end - base);
+				if let Some(blk) = self.alloc_block_in(group, range, &|_| None)? {
+					let _ = reservations.insert(ino, win);
+					return Ok(blk);
+				}
+			}
+			// The file is written sequentially: grow the next window
+			if win.range.contains(&goal) || goal == win.range.end {
+				size = min(win.size * 2, MAX_WINDOW_SIZE);
+			}
+		}
+		// Open a new window, away from the windows of other inodes
+		let reserved = |blk: u32| {
+			reservations
+				.iter()
+				.find(|(_, win)| win.range.contains(&blk))
+				.map(|(_, win)| win.range.end)
+		};
+		let Some(blk) = self.search_block(goal, &reserved)? else {
+			return self
+				.search_block(goal, &|_| None)?
+				.ok_or_else(|| errno!(ENOSPC));
+		};
+		// The window cannot span several groups, nor overlap another window
+		let group_end = min(
+			(blk / blocks_per_group + 1) * blocks_per_group,
+			self.sp.s_blocks_count,
+		);
+		let end = reservations
+			.iter()
+			.map(|(_, win)| win.range.start)
+			.filter(|start| *start > blk)
+			.fold(min(blk + size, group_end), min);
+		// Reservations are only a hint, so failing to record one is not an error
+		let _ = reservations.insert(
+			ino,
+			Window {
+				range: blk..end,
+				size,
+			},
+		);
+		Ok(blk)
+	}
+
+	/// Discards the reservation window of the inode `ino`, if any.
+	pub fn discard_reservation(&self, ino: u32) {
+		self.reservations.lock().remove(&ino);
+	}
+}
@@ -111,34 +111,39 @@ impl Dirent {
 		// Init
 		ent.inode = entry_inode;
 		ent.rec_len = rec_len;
-		ent.set_type(superblock, file_type);
-		ent.name[..name_len].copy_from_slice(name);
 		ent.name_len = name_len as u8;
+		if superblock.s_feature_incompat & super::REQUIRED_FEATURE_DIRECTORY_TYPE != 0 {
+			ent.set_type(superblock, file_type);
+		} else {
+			// Without the type field, the byte holds the name length's most-significant bits
+			ent.file_type = (name_len >> 8) as u8;
+		}
+		ent.name[..name_len].copy_from_slice(name);
 		Ok(())
 	}
 
 	/// Reinterprets a slice of bytes as a directory entry.
 	///
-	/// `superblock` is the filesystem's superblock.
+	/// `fs` is the filesystem the entry belongs to.
 	///
 	/// If the entry is invalid, the function returns [`EUCLEAN`].
-	pub fn from_slice<'b>(slice: &'b mut [u8], superblock: &Superblock) -> EResult<&'b mut Self> {
+	pub fn from_slice<'b>(slice: &'b mut [u8], fs: &Ext2Fs) -> EResult<&'b mut Self> {
 		// Validation
 		if unlikely(slice.len() < NAME_OFF) {
-			return Err(errno!(EUCLEAN));
+			return Err(fs.error(errno!(EUCLEAN)));
 		}
 		// Read record's length
 		const REC_LEN_OFF: usize = offset_of!(Dirent, rec_len);
 		let rec_len = u16::from_le_bytes([slice[REC_LEN_OFF], slice[REC_LEN_OFF + 1]]) as usize;
 		// Validation
 		if unlikely(rec_len > slice.len() || rec_len < NAME_OFF || rec_len % ALIGN != 0) {
-			return Err(errno!(EUCLEAN));
+			return Err(fs.error(errno!(EUCLEAN)));
 		}
 		// Reinterpret
 		let ent = unsafe { &mut *(&mut slice[..rec_len] as *mut _ as *mut Self) };
 		// Validation
-		if unlikely(!ent.is_free() && NAME_OFF + ent.name_len(superblock) > rec_len) {
-			return Err(errno!(EUCLEAN));
+		if unlikely(!ent.is_free() && NAME_OFF + ent.name_len(&fs.sp) > rec_len) {
+			return Err(fs.error(errno!(EUCLEAN)));
 		}
 		Ok(ent)
 	}
@@ -272,7 +277,7 @@ impl<'a> DirentIterator<'a> {
 		// Safe since the node is locked
 		let blk_slice = unsafe { blk.slice_mut() };
 		// Read entry
-		let ent = Dirent::from_slice(&mut blk_slice[inner_off..], &self.fs.sp)?;
+		let ent = Dirent::from_slice(&mut blk_slice[inner_off..], self.fs)?;
 		let prev_off = self.off;
 		self.off += ent.rec_len as u64;
 		// If on the next block, ensure the offset is at the beginning
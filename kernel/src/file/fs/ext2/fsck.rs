@@ -0,0 +1,625 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A lightweight consistency check of the filesystem, run at mount with the `check` mount option.
+//!
+//! The check:
+//! - walks the directory tree from the root directory. Inodes which are in use but not reachable
+//!   are reattached in the `lost+found` directory, which is created if missing
+//! - removes directory entries referring to unused inodes
+//! - fixes link counts
+//! - frees inodes which are allocated but not in use
+//! - checks the block and inode bitmaps against the blocks and inodes in use
+//! - fixes the counters of free blocks and inodes
+//!
+//! Unlike a full `fsck`, the content of inodes is not validated. Invalid block pointers, blocks
+//! referred to several times and blocks which are allocated but not used are reported, but not
+//! repaired.
+//!
+//! If the filesystem is read-only, errors are reported without being repaired.
+
+use super::{
+	Ext2Fs, OPTIONAL_FEATURE_RESIZE, Superblock, WRITE_REQUIRED_SPARSE_SUPERBLOCKS,
+	bgd::BlockGroupDescriptor,
+	dirent::DirentIterator,
+	inode::{
+		DIRECT_BLOCKS_COUNT, Ext2INode, INODE_TYPE_DIRECTORY, ROOT_DIRECTORY_INODE,
+		SYMLINK_INLINE_LIMIT,
+	},
+	read_block,
+};
+use crate::{
+	file::FileType,
+	println,
+	time::clock::{Clock, current_time_sec},
+};
+use core::{
+	fmt,
+	sync::atomic::{
+		AtomicU8,
+		Ordering::{Acquire, Relaxed, Release},
+	},
+};
+use utils::{
+	collections::{bitfield::Bitfield, vec::Vec},
+	errno,
+	errno::EResult,
+	format,
+	limits::PAGE_SIZE,
+	vec,
+};
+
+/// The name of the directory in which unreachable inodes are reattached.
+const LOST_FOUND: &[u8] = b"lost+found";
+/// The maximum number of parent directories followed to find the top of an unreachable tree.
+const MAX_DEPTH: usize = 1024;
+
+/// Tells whether the block group `group` contains a copy of the superblock and of the block group
+/// descriptor table.
+fn has_superblock(sp: &Superblock, group: u32) -> bool {
+	let sparse =
+		sp.s_rev_level >= 1 && sp.s_feature_ro_compat & WRITE_REQUIRED_SPARSE_SUPERBLOCKS != 0;
+	if group <= 1 || !sparse {
+		return true;
+	}
+	// With sparse superblocks, copies are only in groups which are powers of `3`, `5` or `7`
+	[3u64, 5, 7].into_iter().any(|base| {
+		let mut n = base;
+		while n < group as u64 {
+			n *= base;
+		}
+		n == group as u64
+	})
+}
+
+/// Counts the clear bits among the `count` first bits of the bitmap located in the block `blk`.
+fn count_clear(fs: &Ext2Fs, blk: u32, count: u32) -> EResult<u32> {
+	let blk = read_block(fs, blk as _)?;
+	let bytes = blk.slice::<u8>();
+	let clear = (0..count as usize)
+		.filter(|i| bytes[i / 8] & (1 << (i % 8)) == 0)
+		.count();
+	Ok(clear as _)
+}
+
+/// Statistics about the errors found by a check.
+#[derive(Default)]
+struct Report {
+	/// The number of inodes in use which are not reachable from the root directory.
+	unreachable: u32,
+	/// The number of directory entries referring to unused inodes.
+	dangling: u32,
+	/// The number of inodes with a wrong link count.
+	links: u32,
+	/// The number of inodes which are allocated but not in use.
+	leaked_inodes: u32,
+	/// The number of inodes in use which are not marked in the bitmap.
+	unmarked_inodes: u32,
+	/// The number of block pointers beyond the end of the filesystem.
+	bad_blocks: u32,
+	/// The number of blocks referred to several times.
+	shared_blocks: u32,
+	/// The number of blocks in use which are not marked in the bitmap.
+	unmarked_blocks: u32,
+	/// The number of blocks which are allocated but not in use.
+	leaked_blocks: u32,
+	/// The number of wrong counters in block group descriptors and in the superblock.
+	counters: u32,
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} unreachable inodes, {} dangling entries, {} wrong link counts, {} leaked inodes, \
+			 {} unmarked inodes, {} bad block pointers, {} shared blocks, {} unmarked blocks, {} \
+			 leaked blocks, {} wrong counters",
+			self.unreachable,
+			self.dangling,
+			self.links,
+			self.leaked_inodes,
+			self.unmarked_inodes,
+			self.bad_blocks,
+			self.shared_blocks,
+			self.unmarked_blocks,
+			self.leaked_blocks,
+			self.counters
+		)
+	}
+}
+
+/// The state of a check in progress.
+struct Check<'f> {
+	/// The filesystem being checked
+	fs: &'f Ext2Fs,
+	/// Tells whether errors are repaired
+	repair: bool,
+	/// For each inode, the number of directory entries referring to it
+	links: Vec<u16>,
+	/// The directories which have been walked
+	walked: Bitfield,
+	/// The inode of `lost+found`, if known
+	lost_found: Option<u32>,
+	/// The errors found so far
+	report: Report,
+}
+
+impl Check<'_> {
+	/// Tells whether the inode `ino` is reserved for the internal use of the filesystem.
+	fn is_reserved(&self, ino: u32) -> bool {
+		ino != ROOT_DIRECTORY_INODE && ino < self.fs.sp.get_first_available_inode()
+	}
+
+	/// Returns the inode `ino` if it is valid and in use.
+	fn get_used(&self, ino: u32) -> EResult<Option<Ext2INode>> {
+		if !(1..=self.fs.sp.s_inodes_count).contains(&ino) {
+			return Ok(None);
+		}
+		let inode = Ext2INode::get_unlocked(ino, self.fs)?;
+		Ok((inode.i_links_count > 0).then(|| (*inode).clone()))
+	}
+
+	/// Walks the directory tree from the directory `ino`, counting the entries referring to each
+	/// inode.
+	///
+	/// Entries referring to unused inodes are removed.
+	fn walk(&mut self, ino: u32) -> EResult<()> {
+		let fs = self.fs;
+		let mut stack = Vec::new();
+		stack.push(ino)?;
+		while let Some(dir) = stack.pop() {
+			if self.walked.is_set(dir as _) {
+				continue;
+			}
+			self.walked.set(dir as _);
+			let mut inode = Ext2INode::get_unlocked(dir, fs)?;
+			if inode.get_type() != FileType::Directory {
+				continue;
+			}
+			let mut dangling = Vec::new();
+			let mut blk = None;
+			for ent in DirentIterator::new(fs, &inode, &mut blk, 0)? {
+				let (off, ent) = ent?;
+				if ent.is_free() {
+					continue;
+				}
+				let name = ent.get_name(&fs.sp);
+				let target = self.get_used(ent.inode)?;
+				if name == b"." || name == b".." {
+					if target.is_some() {
+						let links = &mut self.links[ent.inode as usize];
+						*links = links.saturating_add(1);
+					}
+					continue;
+				}
+				let Some(target) = target else {
+					dangling.push(off)?;
+					continue;
+				};
+				let links = &mut self.links[ent.inode as usize];
+				*links = links.saturating_add(1);
+				if target.get_type() == FileType::Directory {
+					stack.push(ent.inode)?;
+				}
+			}
+			self.report.dangling += dangling.len() as u32;
+			if self.repair && !dangling.is_empty() {
+				for off in dangling {
+					inode.set_dirent_inode(off, 0, fs)?;
+				}
+				inode.mark_dirty();
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the inode of the `lost+found` directory, creating it if it does not exist.
+	fn lost_found(&mut self) -> EResult<u32> {
+		if let Some(ino) = self.lost_found {
+			return Ok(ino);
+		}
+		let fs = self.fs;
+		let mut root = Ext2INode::get_unlocked(ROOT_DIRECTORY_INODE, fs)?;
+		let ino = match root.get_dirent(LOST_FOUND, fs)? {
+			Some((ino, _)) => {
+				let dir = self
+					.get_used(ino)?
+					.is_some_and(|inode| inode.get_type() == FileType::Directory);
+				if !dir {
+					return Err(errno!(ENOTDIR));
+				}
+				ino
+			}
+			None => {
+				let ino = fs.alloc_inode(ROOT_DIRECTORY_INODE, true)?;
+				let ts = current_time_sec(Clock::Realtime) as u32;
+				let mut inode = Ext2INode::get_unlocked(ino, fs)?;
+				*inode = Ext2INode {
+					i_mode: INODE_TYPE_DIRECTORY | 0o700,
+					i_uid: 0,
+					i_size: 0,
+					i_ctime: ts,
+					i_mtime: ts,
+					i_atime: ts,
+					i_dtime: 0,
+					i_gid: 0,
+					i_links_count: 2,
+					i_blocks: 0,
+					i_flags: 0,
+					i_osd1: 0,
+					i_block: [0; DIRECT_BLOCKS_COUNT + 3],
+					i_generation: 0,
+					i_file_acl: 0,
+					i_dir_acl: 0,
+					i_faddr: 0,
+					i_osd2: [0; 12],
+				};
+				inode.add_dirent(fs, ino, b".", FileType::Directory)?;
+				inode.add_dirent(fs, ROOT_DIRECTORY_INODE, b"..", FileType::Directory)?;
+				inode.mark_dirty();
+				root.add_dirent(fs, ino, LOST_FOUND, FileType::Directory)?;
+				root.i_links_count += 1;
+				root.mark_dirty();
+				// The root directory has already been walked
+				self.links[ino as usize] += 1;
+				self.walk(ino)?;
+				ino
+			}
+		};
+		self.lost_found = Some(ino);
+		Ok(ino)
+	}
+
+	/// Adds an entry for the inode `ino`, of type `file_type`, to `lost+found`.
+	fn attach(&mut self, ino: u32, file_type: FileType) -> EResult<()> {
+		let fs = self.fs;
+		let lost_found = self.lost_found()?;
+		let mut dir = Ext2INode::get_unlocked(lost_found, fs)?;
+		let name = format!("#{ino}")?;
+		if dir.get_dirent(name.as_bytes(), fs)?.is_some() {
+			return Ok(());
+		}
+		dir.add_dirent(fs, ino, name.as_bytes(), file_type)?;
+		self.links[ino as usize] += 1;
+		if file_type == FileType::Directory {
+			let mut inode = Ext2INode::get_unlocked(ino, fs)?;
+			match inode.get_dirent(b"..", fs)? {
+				Some((_, off)) => inode.set_dirent_inode(off, lost_found as _, fs)?,
+				None => inode.add_dirent(fs, lost_found, b"..", FileType::Directory)?,
+			}
+			inode.mark_dirty();
+			dir.i_links_count = dir.i_links_count.saturating_add(1);
+		}
+		dir.mark_dirty();
+		Ok(())
+	}
+
+	/// Reattaches the inodes in use which are not reachable from the root directory to
+	/// `lost+found`.
+	fn reattach(&mut self) -> EResult<()> {
+		let fs = self.fs;
+		let first = fs.sp.get_first_available_inode();
+		for ino in first..=fs.sp.s_inodes_count {
+			if self.links[ino as usize] > 0 || self.walked.is_set(ino as _) {
+				continue;
+			}
+			let Some(inode) = self.get_used(ino)? else {
+				continue;
+			};
+			let file_type = inode.get_type();
+			// For a directory, reattach the top of the unreachable tree instead
+			let mut top = ino;
+			if file_type == FileType::Directory {
+				for _ in 0..MAX_DEPTH {
+					let dir = Ext2INode::get_unlocked(top, fs)?;
+					let Some((parent, _)) = dir.get_dirent(b"..", fs)? else {
+						break;
+					};
+					if parent < first
+						|| parent == ino || self.links[parent as usize] > 0
+						|| self.walked.is_set(parent as _)
+					{
+						break;
+					}
+					let is_dir = self
+						.get_used(parent)?
+						.is_some_and(|inode| inode.get_type() == FileType::Directory);
+					if !is_dir {
+						break;
+					}
+					top = parent;
+				}
+			}
+			self.report.unreachable += 1;
+			if self.repair {
+				self.attach(top, file_type)?;
+			}
+			self.walk(top)?;
+		}
+		Ok(())
+	}
+
+	/// Fixes the link counts of inodes, according to the entries found while walking the
+	/// directory tree.
+	fn fix_links(&mut self) -> EResult<()> {
+		let fs = self.fs;
+		for ino in 1..=fs.sp.s_inodes_count {
+			let found = self.links[ino as usize];
+			if found == 0 || self.is_reserved(ino) {
+				continue;
+			}
+			let mut inode = Ext2INode::get_unlocked(ino, fs)?;
+			if inode.i_links_count == 0 || inode.i_links_count == found {
+				continue;
+			}
+			self.report.links += 1;
+			if self.repair {
+				inode.i_links_count = found;
+				inode.mark_dirty();
+			}
+		}
+		Ok(())
+	}
+
+	/// Checks the inode bitmaps against the inodes in use.
+	///
+	/// Inodes which are allocated but not in use are freed. Their content is left untouched, since
+	/// it cannot be trusted.
+	///
+	/// The function returns the number of directories in each block group.
+	fn check_inodes(&mut self) -> EResult<Vec<u32>> {
+		let fs = self.fs;
+		let sp = &fs.sp;
+		let ts = current_time_sec(Clock::Realtime);
+		let groups = sp.s_inodes_count.div_ceil(sp.s_inodes_per_group);
+		let mut dirs = vec![0; groups as usize]?;
+		for (group, dirs) in dirs.iter_mut().enumerate() {
+			let bgd = BlockGroupDescriptor::get(group as _, fs)?;
+			let bitmap = read_block(fs, bgd.bg_inode_bitmap as _)?;
+			let bytes = bitmap.slice::<AtomicU8>();
+			for index in 0..sp.s_inodes_per_group {
+				let ino = group as u32 * sp.s_inodes_per_group + index + 1;
+				if ino > sp.s_inodes_count {
+					break;
+				}
+				let byte = &bytes[index as usize / 8];
+				let bit = 1 << (index % 8);
+				let marked = byte.load(Relaxed) & bit != 0;
+				let mut inode = Ext2INode::get_unlocked(ino, fs)?;
+				let reserved = self.is_reserved(ino);
+				let used = reserved || inode.i_links_count > 0;
+				match (marked, used) {
+					(true, false) => {
+						self.report.leaked_inodes += 1;
+						if self.repair {
+							inode.i_dtime = ts as _;
+							inode.mark_dirty();
+							let dir = inode.get_type() == FileType::Directory;
+							fs.free_inode(ino as _, dir)?;
+						}
+					}
+					(false, true) => {
+						self.report.unmarked_inodes += 1;
+						if self.repair {
+							byte.fetch_or(bit, Release);
+							bitmap.mark_page_dirty(index as usize / 8 / PAGE_SIZE);
+						}
+					}
+					_ => {}
+				}
+				if used && !reserved && inode.get_type() == FileType::Directory {
+					*dirs += 1;
+				}
+			}
+		}
+		Ok(dirs)
+	}
+
+	/// Marks the block `blk` as used in `used`, along with the blocks it refers to if it is an
+	/// indirect block with `level` levels of indirection.
+	fn mark_block(&mut self, used: &mut Bitfield, blk: u32, level: usize) -> EResult<()> {
+		if blk == 0 {
+			return Ok(());
+		}
+		if blk >= self.fs.sp.s_blocks_count {
+			self.report.bad_blocks += 1;
+			return Ok(());
+		}
+		if used.is_set(blk as _) {
+			self.report.shared_blocks += 1;
+			return Ok(());
+		}
+		used.set(blk as _);
+		if let Some(level) = level.checked_sub(1) {
+			let blk = read_block(self.fs, blk as _)?;
+			for ent in blk.slice::<u32>() {
+				self.mark_block(used, *ent, level)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Marks the blocks used by the structures of the filesystem in `used`.
+	fn mark_metadata(&self, used: &mut Bitfield, groups: u32) -> EResult<()> {
+		let sp = &self.fs.sp;
+		let blk_size = sp.get_block_size();
+		let gdt_blocks = (groups * size_of::<BlockGroupDescriptor>() as u32).div_ceil(blk_size);
+		let reserved_gdt_blocks =
+			if sp.s_rev_level >= 1 && sp.s_feature_compat & OPTIONAL_FEATURE_RESIZE != 0 {
+				sp.s_reserved_gdt_blocks as u32
+			} else {
+				0
+			};
+		let table_blocks = (sp.s_inodes_per_group * sp.get_inode_size() as u32).div_ceil(blk_size);
+		let mut mark = |start: u32, count: u32| {
+			let end = start.saturating_add(count).min(sp.s_blocks_count);
+			for blk in start..end {
+				used.set(blk as _);
+			}
+		};
+		mark(0, sp.s_first_data_block);
+		for group in 0..groups {
+			if has_superblock(sp, group) {
+				let start = sp.s_first_data_block + group * sp.s_blocks_per_group;
+				mark(start, 1 + gdt_blocks + reserved_gdt_blocks);
+			}
+			let bgd = BlockGroupDescriptor::get(group, self.fs)?;
+			mark(bgd.bg_block_bitmap, 1);
+			mark(bgd.bg_inode_bitmap, 1);
+			mark(bgd.bg_inode_table, table_blocks);
+		}
+		Ok(())
+	}
+
+	/// Checks the block bitmaps against the blocks in use.
+	fn check_blocks(&mut self, groups: u32) -> EResult<()> {
+		let fs = self.fs;
+		let sp = &fs.sp;
+		let mut used = Bitfield::new(sp.s_blocks_count as _)?;
+		self.mark_metadata(&mut used, groups)?;
+		for ino in 1..=sp.s_inodes_count {
+			let inode = Ext2INode::get_unlocked(ino, fs)?;
+			if !self.is_reserved(ino) && inode.i_links_count == 0 {
+				continue;
+			}
+			// Skip inodes using `i_block` for something else than block pointers
+			let has_blocks = match inode.get_type() {
+				FileType::Regular | FileType::Directory => true,
+				FileType::Link => inode.get_size(sp) > SYMLINK_INLINE_LIMIT,
+				_ => false,
+			};
+			if !has_blocks {
+				continue;
+			}
+			for (off, blk) in inode.i_block.iter().enumerate() {
+				let level = (off + 1).saturating_sub(DIRECT_BLOCKS_COUNT);
+				self.mark_block(&mut used, *blk, level)?;
+			}
+		}
+		for group in 0..groups {
+			let bgd = BlockGroupDescriptor::get(group, fs)?;
+			let bitmap = read_block(fs, bgd.bg_block_bitmap as _)?;
+			let bytes = bitmap.slice::<AtomicU8>();
+			let start = sp.s_first_data_block + group * sp.s_blocks_per_group;
+			let end = start
+				.saturating_add(sp.s_blocks_per_group)
+				.min(sp.s_blocks_count);
+			for blk in start..end {
+				let index = (blk - start) as usize;
+				let byte = &bytes[index / 8];
+				let bit = 1 << (index % 8);
+				let marked = byte.load(Relaxed) & bit != 0;
+				match (marked, used.is_set(blk as _)) {
+					(true, false) => self.report.leaked_blocks += 1,
+					(false, true) => {
+						self.report.unmarked_blocks += 1;
+						if self.repair {
+							byte.fetch_or(bit, Release);
+							bitmap.mark_page_dirty(index / 8 / PAGE_SIZE);
+						}
+					}
+					_ => {}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Fixes the counters of free blocks and inodes according to the bitmaps, and the counters of
+	/// directories according to `dirs`.
+	fn fix_counters(&mut self, groups: u32, dirs: &[u32]) -> EResult<()> {
+		let fs = self.fs;
+		let sp = &fs.sp;
+		let mut free_blocks = 0;
+		let mut free_inodes = 0;
+		for group in 0..groups {
+			let bgd = BlockGroupDescriptor::get(group, fs)?;
+			let start = sp.s_first_data_block + group * sp.s_blocks_per_group;
+			let blocks = sp
+				.s_blocks_count
+				.saturating_sub(start)
+				.min(sp.s_blocks_per_group);
+			let blocks = count_clear(fs, bgd.bg_block_bitmap, blocks)?;
+			let start = group * sp.s_inodes_per_group;
+			let inodes = sp
+				.s_inodes_count
+				.saturating_sub(start)
+				.min(sp.s_inodes_per_group);
+			let inodes = count_clear(fs, bgd.bg_inode_bitmap, inodes)?;
+			let used_dirs = dirs.get(group as usize).copied().unwrap_or(0);
+			let counters = [
+				(&bgd.bg_free_blocks_count, blocks),
+				(&bgd.bg_free_inodes_count, inodes),
+				(&bgd.bg_used_dirs_count, used_dirs),
+			];
+			for (counter, val) in counters {
+				let val = val as u16;
+				if counter.load(Acquire) != val {
+					self.report.counters += 1;
+					if self.repair {
+						counter.store(val, Release);
+						bgd.mark_dirty();
+					}
+				}
+			}
+			free_blocks += blocks;
+			free_inodes += inodes;
+		}
+		let counters = [
+			(&sp.s_free_blocks_count, free_blocks),
+			(&sp.s_free_inodes_count, free_inodes),
+		];
+		for (counter, val) in counters {
+			if counter.load(Acquire) != val {
+				self.report.counters += 1;
+				if self.repair {
+					counter.store(val, Release);
+					sp.mark_dirty();
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Ext2Fs {
+	/// Checks the consistency of the filesystem, repairing errors unless it is read-only.
+	///
+	/// This function must be called at mount, before the filesystem is used.
+	pub fn check(&self) -> EResult<()> {
+		let sp = &self.sp;
+		let inodes = sp.s_inodes_count as usize + 1;
+		let mut check = Check {
+			fs: self,
+			repair: !self.is_readonly(),
+			links: vec![0; inodes]?,
+			walked: Bitfield::new(inodes)?,
+			lost_found: None,
+			report: Report::default(),
+		};
+		let groups = (sp.s_blocks_count - sp.s_first_data_block).div_ceil(sp.s_blocks_per_group);
+		check.walk(ROOT_DIRECTORY_INODE)?;
+		check.reattach()?;
+		check.fix_links()?;
+		let dirs = check.check_inodes()?;
+		check.check_blocks(groups)?;
+		check.fix_counters(groups, &dirs)?;
+		println!("ext2: checked {}: {}", self.dev.path, check.report);
+		Ok(())
+	}
+}
@@ -0,0 +1,715 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Hashed directory indexes (htree).
+//!
+//! On a filesystem with the [`OPTIONAL_FEATURE_HASH_INDEX`] feature, the entries of a directory
+//! with the [`INODE_FLAG_HASH_INDEXED`] flag are spread among leaf blocks according to the hash of
+//! their name. A tree of index blocks maps ranges of hashes to leaf blocks, so that looking for
+//! an entry only requires to scan one leaf block.
+//!
+//! The root of the tree is stored in the first block of the directory, after the `.` and `..`
+//! entries. The `..` entry covers the rest of the block, and the internal nodes of the tree start
+//! with a free entry covering the whole block, so that the directory can still be read linearly.
+//!
+//! A directory is converted to an indexed one when its first block is full.
+
+use super::{
+	Ext2Fs, OPTIONAL_FEATURE_HASH_INDEX, dirent,
+	dirent::Dirent,
+	inode::{
+		Ext2INode, INODE_FLAG_HASH_INDEXED, INodeWrap, fill_free_entries, find_free_run,
+		insert_dirent,
+	},
+	read_block,
+};
+use crate::{file::FileType, memory::cache::RcFrame};
+use core::hint::unlikely;
+use utils::{collections::vec::Vec, errno, errno::EResult};
+
+/// Hash version: legacy
+const HASH_LEGACY: u8 = 0;
+/// Hash version: half MD4
+const HASH_HALF_MD4: u8 = 1;
+/// Hash version: TEA
+const HASH_TEA: u8 = 2;
+/// Offset from a hash version to its variant treating characters as unsigned.
+const HASH_UNSIGNED: u8 = 3;
+
+/// `s_flags`: Directory hashes treat characters as unsigned
+const FLAG_UNSIGNED_HASH: u32 = 0x2;
+
+/// The offset of the root information in the root block.
+const ROOT_INFO_OFF: usize = 24;
+/// The size of the root information.
+const ROOT_INFO_LEN: u8 = 8;
+/// The offset of the index entries in the root block.
+const ROOT_ENTRIES_OFF: usize = ROOT_INFO_OFF + ROOT_INFO_LEN as usize;
+/// The offset of the index entries in an internal node.
+const NODE_ENTRIES_OFF: usize = dirent::NAME_OFF;
+/// The size of an index entry.
+const ENTRY_SIZE: usize = 8;
+/// The maximum number of levels of the tree, including the root.
+const MAX_LEVELS: usize = 2;
+
+/// Returns the character `c` as an integer, sign-extended unless `unsigned` is set.
+fn char_val(c: u8, unsigned: bool) -> u32 {
+	if unsigned { c as u32 } else { c as i8 as u32 }
+}
+
+/// The legacy hash function.
+fn legacy_hash(name: &[u8], unsigned: bool) -> u32 {
+	let mut hash0: u32 = 0x12a3fe2d;
+	let mut hash1: u32 = 0x37abe8f9;
+	for c in name {
+		let mut hash = hash1.wrapping_add(hash0 ^ char_val(*c, unsigned).wrapping_mul(7152373));
+		if hash & 0x80000000 != 0 {
+			hash = hash.wrapping_sub(0x7fffffff);
+		}
+		hash1 = hash0;
+		hash0 = hash;
+	}
+	hash0 << 1
+}
+
+/// Fills `buf` with words made from the beginning of `msg`, to be used as input for a hash
+/// transform.
+fn str_to_hash_buf(msg: &[u8], unsigned: bool, buf: &mut [u32]) {
+	let mut pad = msg.len() as u32 | ((msg.len() as u32) << 8);
+	pad |= pad << 16;
+	let mut val = pad;
+	let len = buf.len() * 4;
+	let mut words = buf.iter_mut();
+	for (i, c) in msg.iter().take(len).enumerate() {
+		val = char_val(*c, unsigned).wrapping_add(val << 8);
+		if i % 4 == 3 {
+			*words.next().unwrap() = val;
+			val = pad;
+		}
+	}
+	if let Some(word) = words.next() {
+		*word = val;
+	}
+	words.for_each(|word| *word = pad);
+}
+
+/// The half MD4 transform.
+fn half_md4_transform(buf: &mut [u32; 4], data: &[u32; 8]) {
+	type Round = (fn(u32, u32, u32) -> u32, u32, [usize; 8], [u32; 4]);
+	const ROUNDS: [Round; 3] = [
+		(
+			|x, y, z| z ^ (x & (y ^ z)),
+			0,
+			[0, 1, 2, 3, 4, 5, 6, 7],
+			[3, 7, 11, 19],
+		),
+		(
+			|x, y, z| (x & y).wrapping_add((x ^ y) & z),
+			0o13240474631,
+			[1, 3, 5, 7, 0, 2, 4, 6],
+			[3, 5, 9, 13],
+		),
+		(
+			|x, y, z| x ^ y ^ z,
+			0o15666365641,
+			[3, 7, 2, 6, 1, 5, 0, 4],
+			[3, 9, 11, 15],
+		),
+	];
+	let mut state = *buf;
+	for (f, k, order, shifts) in ROUNDS {
+		for (i, word) in order.into_iter().enumerate() {
+			// The updated word rotates through `a`, `d`, `c` and `b`
+			let t = [0, 3, 2, 1][i % 4];
+			let x = f(state[(t + 1) % 4], state[(t + 2) % 4], state[(t + 3) % 4]);
+			state[t] = state[t]
+				.wrapping_add(x)
+				.wrapping_add(data[word].wrapping_add(k))
+				.rotate_left(shifts[i % 4]);
+		}
+	}
+	for (b, s) in buf.iter_mut().zip(state) {
+		*b = b.wrapping_add(s);
+	}
+}
+
+/// The TEA transform.
+fn tea_transform(buf: &mut [u32; 4], data: &[u32; 4]) {
+	const DELTA: u32 = 0x9e3779b9;
+	let [a, b, c, d] = *data;
+	let mut b0 = buf[0];
+	let mut b1 = buf[1];
+	let mut sum: u32 = 0;
+	for _ in 0..16 {
+		sum = sum.wrapping_add(DELTA);
+		b0 = b0.wrapping_add(
+			((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+		);
+		b1 = b1.wrapping_add(
+			((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+		);
+	}
+	buf[0] = buf[0].wrapping_add(b0);
+	buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// Computes the hash of `name` with the hash `version` and the given `seed`.
+///
+/// The lowest bit of the hash is always clear, since it is used to mark collisions in the index.
+fn hash(name: &[u8], version: u8, seed: &[u32; 4]) -> u32 {
+	let mut buf = if seed.iter().any(|w| *w != 0) {
+		*seed
+	} else {
+		[0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476]
+	};
+	let unsigned = version >= HASH_UNSIGNED;
+	let hash = match version % HASH_UNSIGNED {
+		HASH_LEGACY => legacy_hash(name, unsigned),
+		HASH_HALF_MD4 => {
+			for off in (0..name.len()).step_by(32) {
+				let mut data = [0; 8];
+				str_to_hash_buf(&name[off..], unsigned, &mut data);
+				half_md4_transform(&mut buf, &data);
+			}
+			buf[1]
+		}
+		_ => {
+			for off in (0..name.len()).step_by(16) {
+				let mut data = [0; 4];
+				str_to_hash_buf(&name[off..], unsigned, &mut data);
+				tea_transform(&mut buf, &data);
+			}
+			buf[0]
+		}
+	};
+	let hash = hash & !1;
+	// This value is reserved to mark the end of a directory
+	if hash == 0x7fffffff << 1 {
+		(0x7fffffff - 1) << 1
+	} else {
+		hash
+	}
+}
+
+/// Tells whether the filesystem allows directories to be indexed.
+fn has_feature(fs: &Ext2Fs) -> bool {
+	// A free entry cannot cover a whole block of 64 KiB
+	fs.sp.s_feature_compat & OPTIONAL_FEATURE_HASH_INDEX != 0
+		&& fs.sp.get_block_size() <= u16::MAX as u32
+}
+
+/// Reads the block at the file block offset `off` of the directory `dir`.
+fn read_dir_block(dir: &Ext2INode, fs: &Ext2Fs, off: u32) -> EResult<RcFrame> {
+	let blk = dir
+		.translate_blk_off(off, fs)?
+		.ok_or_else(|| fs.error(errno!(EUCLEAN)))?;
+	read_block(fs, blk.get() as _)
+}
+
+/// Allocates a new block at the end of the directory `dir`.
+///
+/// The function returns the file block offset of the block, along with the block itself.
+fn append_dir_block(dir: &mut INodeWrap, fs: &Ext2Fs) -> EResult<(u32, RcFrame)> {
+	let blk_size = fs.sp.get_block_size() as u64;
+	let off = dir.get_blocks(&fs.sp);
+	let blk = dir.alloc_content_blk(off, fs)?;
+	dir.set_size(&fs.sp, (off as u64 + 1) * blk_size, false);
+	Ok((off, read_block(fs, blk as _)?))
+}
+
+/// A block of the index, either the root or an internal node.
+#[derive(Clone)]
+struct IndexBlock {
+	/// The block
+	blk: RcFrame,
+	/// The offset of the entries in the block, in words
+	start: usize,
+}
+
+impl IndexBlock {
+	/// Reads the internal node at the file block offset `off` of the directory `dir`.
+	fn read_node(dir: &Ext2INode, fs: &Ext2Fs, off: u32) -> EResult<Self> {
+		let blk = Self {
+			blk: read_dir_block(dir, fs, off)?,
+			start: NODE_ENTRIES_OFF / 4,
+		};
+		blk.check(fs)?;
+		Ok(blk)
+	}
+
+	/// Checks the header of the block is valid.
+	fn check(&self, fs: &Ext2Fs) -> EResult<()> {
+		let limit = (fs.sp.get_block_size() as usize - self.start * 4) / ENTRY_SIZE;
+		let count = self.count();
+		if unlikely(self.limit() != limit || count == 0 || count > limit) {
+			return Err(fs.error(errno!(EUCLEAN)));
+		}
+		Ok(())
+	}
+
+	/// Returns the words of the block, starting at the entries.
+	fn words(&self) -> &[u32] {
+		&self.blk.slice()[self.start..]
+	}
+
+	/// Returns the words of the block, starting at the entries.
+	///
+	/// # Safety
+	///
+	/// The directory must be locked.
+	#[allow(clippy::mut_from_ref)]
+	unsafe fn words_mut(&self) -> &mut [u32] {
+		unsafe { &mut self.blk.slice_mut()[self.start..] }
+	}
+
+	/// Returns the maximum number of entries in the block.
+	fn limit(&self) -> usize {
+		(self.words()[0] & 0xffff) as usize
+	}
+
+	/// Returns the number of entries in the block.
+	fn count(&self) -> usize {
+		(self.words()[0] >> 16) as usize
+	}
+
+	/// Sets the number of entries in the block.
+	fn set_count(&self, count: usize) {
+		// Safe since the directory is locked
+		let words = unsafe { self.words_mut() };
+		words[0] = (words[0] & 0xffff) | ((count as u32) << 16);
+		self.blk.mark_dirty();
+	}
+
+	/// Returns the hash and the file block offset of the entry at `i`.
+	///
+	/// The hash of the first entry is always `0`.
+	fn get(&self, i: usize) -> (u32, u32) {
+		let words = self.words();
+		let hash = if i > 0 { words[i * 2] } else { 0 };
+		// The highest bits are reserved
+		(hash, words[i * 2 + 1] & 0x0fffffff)
+	}
+
+	/// Returns the position of the entry to follow to find the hash `hash`.
+	fn find(&self, hash: u32) -> usize {
+		let words = self.words();
+		// Look for the last entry with a hash lower or equal to `hash`
+		let mut low = 1;
+		let mut high = self.count();
+		while low < high {
+			let mid = (low + high) / 2;
+			if words[mid * 2] <= hash {
+				low = mid + 1;
+			} else {
+				high = mid;
+			}
+		}
+		low - 1
+	}
+
+	/// Inserts an entry at `i`, with the given `hash` and file block offset `off`.
+	///
+	/// `i` must not be `0`, and the block must not be full.
+	fn insert(&self, i: usize, hash: u32, off: u32) {
+		let count = self.count();
+		// Safe since the directory is locked
+		let words = unsafe { self.words_mut() };
+		words.copy_within(i * 2..count * 2, i * 2 + 2);
+		words[i * 2] = hash;
+		words[i * 2 + 1] = off;
+		self.set_count(count + 1);
+	}
+}
+
+/// For each level of the index, starting from the root, the block and the position of the entry
+/// that is followed.
+type Path = Vec<(IndexBlock, usize)>;
+
+/// The hash index of a directory.
+pub struct Index {
+	/// The hash version, including the unsigned offset if applicable
+	version: u8,
+	/// The number of levels of internal nodes below the root
+	levels: usize,
+	/// The root block
+	root: IndexBlock,
+}
+
+impl Index {
+	/// Returns the index of the directory `dir`.
+	///
+	/// If the directory is not indexed, the function returns `None`.
+	pub fn open(dir: &Ext2INode, fs: &Ext2Fs) -> EResult<Option<Self>> {
+		if !has_feature(fs) || dir.i_flags & INODE_FLAG_HASH_INDEXED == 0 {
+			return Ok(None);
+		}
+		let blk = read_dir_block(dir, fs, 0)?;
+		let info = &blk.slice::<u8>()[ROOT_INFO_OFF..ROOT_ENTRIES_OFF];
+		let version = info[4];
+		let levels = info[6] as usize;
+		if unlikely(version > HASH_TEA || info[5] != ROOT_INFO_LEN || levels >= MAX_LEVELS) {
+			return Err(fs.error(errno!(EUCLEAN)));
+		}
+		let root = IndexBlock {
+			blk,
+			start: ROOT_ENTRIES_OFF / 4,
+		};
+		root.check(fs)?;
+		let unsigned = fs.sp.s_flags & FLAG_UNSIGNED_HASH != 0;
+		Ok(Some(Self {
+			version: version + if unsigned { HASH_UNSIGNED } else { 0 },
+			levels,
+			root,
+		}))
+	}
+
+	/// Converts the directory `dir`, which must have a single block, to an indexed directory.
+	///
+	/// The entries of the first block are moved to a new leaf block.
+	///
+	/// If the directory cannot be indexed, the function returns `None`.
+	pub fn create(dir: &mut INodeWrap, fs: &Ext2Fs) -> EResult<Option<Self>> {
+		if !has_feature(fs) || dir.get_blocks(&fs.sp) != 1 {
+			return Ok(None);
+		}
+		let blk_size = fs.sp.get_block_size() as usize;
+		let root = read_dir_block(dir, fs, 0)?;
+		// Safe since the directory is locked
+		let buf = unsafe { root.slice_mut::<u8>() };
+		// The block must start with the `.` and `..` entries, leaving room for the index
+		let dot = Dirent::from_slice(buf, fs)?;
+		if dot.is_free()
+			|| dot.get_name(&fs.sp) != b"."
+			|| dot.rec_len as usize != ROOT_INFO_OFF / 2
+		{
+			return Ok(None);
+		}
+		let dotdot = Dirent::from_slice(&mut buf[ROOT_INFO_OFF / 2..], fs)?;
+		if dotdot.is_free() || dotdot.get_name(&fs.sp) != b".." {
+			return Ok(None);
+		}
+		let entries_off = ROOT_INFO_OFF / 2 + dotdot.rec_len as usize;
+		// Move the other entries to a new leaf block
+		let (leaf_off, leaf) = append_dir_block(dir, fs)?;
+		// Safe since the directory is locked
+		let leaf_buf = unsafe { leaf.slice_mut::<u8>() };
+		let len = blk_size - entries_off;
+		leaf_buf[..len].copy_from_slice(&buf[entries_off..]);
+		fill_free_entries(&mut leaf_buf[len..], &fs.sp)?;
+		leaf.mark_dirty();
+		// Make `..` cover the index
+		let dotdot = Dirent::from_slice(&mut buf[ROOT_INFO_OFF / 2..], fs)?;
+		dotdot.rec_len = (blk_size - ROOT_INFO_OFF / 2) as u16;
+		buf[ROOT_INFO_OFF..].fill(0);
+		let version = match fs.sp.s_def_hash_version {
+			v @ HASH_LEGACY..=HASH_TEA => v,
+			_ => HASH_HALF_MD4,
+		};
+		buf[ROOT_INFO_OFF + 4] = version;
+		buf[ROOT_INFO_OFF + 5] = ROOT_INFO_LEN;
+		let root = IndexBlock {
+			blk: root,
+			start: ROOT_ENTRIES_OFF / 4,
+		};
+		// Safe since the directory is locked
+		let words = unsafe { root.words_mut() };
+		words[0] = ((blk_size - ROOT_ENTRIES_OFF) / ENTRY_SIZE) as u32 | (1 << 16);
+		words[1] = leaf_off;
+		root.blk.mark_dirty();
+		dir.i_flags |= INODE_FLAG_HASH_INDEXED;
+		let unsigned = fs.sp.s_flags & FLAG_UNSIGNED_HASH != 0;
+		Ok(Some(Self {
+			version: version + if unsigned { HASH_UNSIGNED } else { 0 },
+			levels: 0,
+			root,
+		}))
+	}
+
+	/// Computes the hash of `name`.
+	fn hash(&self, name: &[u8], fs: &Ext2Fs) -> u32 {
+		hash(name, self.version, &fs.sp.s_hash_seed)
+	}
+
+	/// Walks down the index to the leaf block which may contain entries with the hash `hash`.
+	fn probe(&self, dir: &Ext2INode, fs: &Ext2Fs, hash: u32) -> EResult<Path> {
+		let mut path = Vec::with_capacity(self.levels + 1)?;
+		let mut blk = self.root.clone();
+		loop {
+			let pos = blk.find(hash);
+			let (_, child) = blk.get(pos);
+			path.push((blk, pos))?;
+			if path.len() > self.levels {
+				break;
+			}
+			blk = IndexBlock::read_node(dir, fs, child)?;
+		}
+		Ok(path)
+	}
+
+	/// Returns the file block offset of the leaf block pointed to by `path`.
+	fn leaf(path: &Path) -> u32 {
+		let (blk, pos) = path.last().unwrap();
+		blk.get(*pos).1
+	}
+
+	/// Moves `path` to the next leaf block, if it may contain entries with the hash `hash`, in
+	/// case of collisions.
+	///
+	/// If the next leaf cannot contain such entries, the function returns `false`.
+	fn next_leaf(dir: &Ext2INode, fs: &Ext2Fs, path: &mut Path, hash: u32) -> EResult<bool> {
+		// Find the deepest level which has a next entry
+		let Some(level) = path.iter().rposition(|(blk, pos)| pos + 1 < blk.count()) else {
+			return Ok(false);
+		};
+		let (blk, pos) = &mut path[level];
+		*pos += 1;
+		let (next_hash, mut child) = blk.get(*pos);
+		if next_hash & !1 != hash {
+			return Ok(false);
+		}
+		// Go down to the leftmost leaf
+		for (blk, pos) in &mut path[level + 1..] {
+			*blk = IndexBlock::read_node(dir, fs, child)?;
+			*pos = 0;
+			child = blk.get(0).1;
+		}
+		Ok(true)
+	}
+
+	/// Looks for the entry with the name `name` in the directory `dir`.
+	///
+	/// On success, the function returns the inode of the entry and its offset in the directory.
+	pub fn lookup(
+		&self,
+		dir: &Ext2INode,
+		fs: &Ext2Fs,
+		name: &[u8],
+	) -> EResult<Option<(u32, u64)>> {
+		let blk_size = fs.sp.get_block_size() as u64;
+		let hash = self.hash(name, fs);
+		let mut path = self.probe(dir, fs, hash)?;
+		loop {
+			let off = Self::leaf(&path);
+			let blk = read_dir_block(dir, fs, off)?;
+			// Safe since the directory is locked
+			let buf = unsafe { blk.slice_mut::<u8>() };
+			let mut inner_off = 0;
+			while inner_off < buf.len() {
+				let ent = Dirent::from_slice(&mut buf[inner_off..], fs)?;
+				if !ent.is_free() && ent.get_name(&fs.sp) == name {
+					return Ok(Some((ent.inode, off as u64 * blk_size + inner_off as u64)));
+				}
+				inner_off += ent.rec_len as usize;
+			}
+			if !Self::next_leaf(dir, fs, &mut path, hash)? {
+				return Ok(None);
+			}
+		}
+	}
+
+	/// Allocates a new internal node at the end of the directory `dir`.
+	///
+	/// The function returns the file block offset of the node, along with the node itself.
+	fn new_node(dir: &mut INodeWrap, fs: &Ext2Fs) -> EResult<(u32, IndexBlock)> {
+		let blk_size = fs.sp.get_block_size() as usize;
+		let (off, blk) = append_dir_block(dir, fs)?;
+		// Safe since the directory is locked
+		let buf = unsafe { blk.slice_mut::<u8>() };
+		// A free entry hides the node from linear reads
+		Dirent::write_new(buf, &fs.sp, 0, blk_size as _, None, b"")?;
+		let node = IndexBlock {
+			blk,
+			start: NODE_ENTRIES_OFF / 4,
+		};
+		// Safe since the directory is locked
+		let words = unsafe { node.words_mut() };
+		words[0] = ((blk_size - NODE_ENTRIES_OFF) / ENTRY_SIZE) as u32;
+		Ok((off, node))
+	}
+
+	/// Makes room for a new entry in the deepest index block of `path`, splitting it or adding a
+	/// level to the tree if necessary.
+	///
+	/// If the index is full, the function returns [`ENOSPC`].
+	fn make_room(&mut self, dir: &mut INodeWrap, fs: &Ext2Fs, path: &mut Path) -> EResult<()> {
+		let (blk, pos) = path.last().unwrap();
+		let count = blk.count();
+		if count < blk.limit() {
+			return Ok(());
+		}
+		let (blk, pos) = (blk.clone(), *pos);
+		if path.len() == 1 {
+			// The root is full: move its entries to a new node, below the root
+			if unlikely(self.levels + 1 >= MAX_LEVELS) {
+				return Err(errno!(ENOSPC));
+			}
+			let (node_off, node) = Self::new_node(dir, fs)?;
+			// Safe since the directory is locked
+			let words = unsafe { node.words_mut() };
+			words[1..count * 2].copy_from_slice(&blk.words()[1..count * 2]);
+			node.set_count(count);
+			self.levels += 1;
+			// Safe since the directory is locked
+			unsafe {
+				blk.blk.slice_mut::<u8>()[ROOT_INFO_OFF + 6] = self.levels as u8;
+			}
+			// Safe since the directory is locked
+			let words = unsafe { blk.words_mut() };
+			words[1] = node_off;
+			blk.set_count(1);
+			path[0].1 = 0;
+			path.push((node, pos))?;
+			return Ok(());
+		}
+		// Split the node in two, if its parent has room for the new node
+		let parent_level = path.len() - 2;
+		let (parent, parent_pos) = &mut path[parent_level];
+		if unlikely(parent.count() >= parent.limit()) {
+			return Err(errno!(ENOSPC));
+		}
+		let (node_off, node) = Self::new_node(dir, fs)?;
+		let half = count / 2;
+		let (split_hash, _) = blk.get(half);
+		// Safe since the directory is locked
+		let words = unsafe { node.words_mut() };
+		words[1..(count - half) * 2].copy_from_slice(&blk.words()[half * 2 + 1..count * 2]);
+		node.set_count(count - half);
+		blk.set_count(half);
+		parent.insert(*parent_pos + 1, split_hash, node_off);
+		if pos >= half {
+			*parent_pos += 1;
+			*path.last_mut().unwrap() = (node, pos - half);
+		}
+		Ok(())
+	}
+
+	/// Splits the leaf block `leaf` in two, moving half of its entries to a new leaf block.
+	///
+	/// The function returns the hash of the first entry of the new leaf block, along with its file
+	/// block offset and the block itself. If entries with this hash remain in `leaf`, the lowest
+	/// bit of the hash is set.
+	fn split_leaf(
+		&self,
+		dir: &mut INodeWrap,
+		fs: &Ext2Fs,
+		leaf: &RcFrame,
+	) -> EResult<(u32, u32, RcFrame)> {
+		// Safe since the directory is locked
+		let buf = unsafe { leaf.slice_mut::<u8>() };
+		let mut copy = Vec::new();
+		copy.extend_from_slice(buf)?;
+		// Sort entries by hash
+		let mut ents = Vec::new();
+		let mut off = 0;
+		while off < copy.len() {
+			let ent = Dirent::from_slice(&mut copy[off..], fs)?;
+			if !ent.is_free() {
+				ents.push((self.hash(ent.get_name(&fs.sp), fs), off))?;
+			}
+			off += ent.rec_len as usize;
+		}
+		if unlikely(ents.len() < 2) {
+			return Err(errno!(ENOSPC));
+		}
+		ents.sort_unstable();
+		let mid = ents.len() / 2;
+		let split_hash = ents[mid].0 | (ents[mid - 1].0 == ents[mid].0) as u32;
+		let (new_off, new) = append_dir_block(dir, fs)?;
+		// Safe since the directory is locked
+		let new_buf = unsafe { new.slice_mut::<u8>() };
+		write_entries(buf, &mut copy, &ents[..mid], fs)?;
+		write_entries(new_buf, &mut copy, &ents[mid..], fs)?;
+		leaf.mark_dirty();
+		new.mark_dirty();
+		Ok((split_hash, new_off, new))
+	}
+
+	/// Adds an entry to the directory `dir`.
+	///
+	/// Arguments:
+	/// - `entry_inode` is the inode of the entry
+	/// - `name` is the name of the entry
+	/// - `file_type` is the type of the entry
+	/// - `rec_len` is the minimum size of the entry
+	pub fn add(
+		&mut self,
+		dir: &mut INodeWrap,
+		fs: &Ext2Fs,
+		entry_inode: u32,
+		name: &[u8],
+		file_type: FileType,
+		rec_len: u16,
+	) -> EResult<()> {
+		let hash = self.hash(name, fs);
+		let mut path = self.probe(dir, fs, hash)?;
+		let leaf = read_dir_block(dir, fs, Self::leaf(&path))?;
+		// Safe since the directory is locked
+		let buf = unsafe { leaf.slice_mut::<u8>() };
+		if let Some(range) = find_free_run(buf, fs, rec_len)? {
+			insert_dirent(buf, range, rec_len, &fs.sp, entry_inode, name, file_type)?;
+			leaf.mark_dirty();
+			return Ok(());
+		}
+		// The leaf is full: split it
+		self.make_room(dir, fs, &mut path)?;
+		let (split_hash, new_off, new) = self.split_leaf(dir, fs, &leaf)?;
+		let (blk, pos) = path.last().unwrap();
+		blk.insert(pos + 1, split_hash, new_off);
+		let leaf = if hash >= split_hash { new } else { leaf };
+		// Safe since the directory is locked
+		let buf = unsafe { leaf.slice_mut::<u8>() };
+		let range = find_free_run(buf, fs, rec_len)?.ok_or_else(|| errno!(ENOSPC))?;
+		insert_dirent(buf, range, rec_len, &fs.sp, entry_inode, name, file_type)?;
+		leaf.mark_dirty();
+		Ok(())
+	}
+}
+
+/// Writes the entries of `src` at the offsets given by `ents` to the directory block `buf`.
+///
+/// The remaining space is covered with free entries.
+fn write_entries(
+	buf: &mut [u8],
+	src: &mut [u8],
+	ents: &[(u32, usize)],
+	fs: &Ext2Fs,
+) -> EResult<()> {
+	let mut off = 0;
+	let mut last = 0;
+	for (_, src_off) in ents {
+		let ent = Dirent::from_slice(&mut src[*src_off..], fs)?;
+		let name = ent.get_name(&fs.sp);
+		let rec_len = (dirent::NAME_OFF + name.len()).next_multiple_of(dirent::ALIGN);
+		Dirent::write_new(
+			&mut buf[off..],
+			&fs.sp,
+			ent.inode,
+			rec_len as _,
+			ent.get_type(&fs.sp),
+			name,
+		)?;
+		last = off;
+		off += rec_len;
+	}
+	// If the remaining space cannot fit an entry, extend the last one
+	if buf.len() - off < dirent::NAME_OFF {
+		Dirent::from_slice(&mut buf[last..], fs)?.rec_len += (buf.len() - off) as u16;
+		return Ok(());
+	}
+	fill_free_entries(&mut buf[off..], &fs.sp)
+}
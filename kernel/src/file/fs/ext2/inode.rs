@@ -19,7 +19,8 @@
 //! An inode represents a file in the filesystem.
 
 use super::{
-	Ext2Fs, Superblock, bgd::BlockGroupDescriptor, dirent, dirent::Dirent, read_block, zero_block,
+	Ext2Fs, Superblock, bgd::BlockGroupDescriptor, dirent, dirent::Dirent, htree::Index,
+	read_block, zero_block,
 };
 use crate::{
 	file::{FileType, INode, Mode, Stat, fs::ext2::dirent::DirentIterator, vfs::node::Node},
@@ -30,7 +31,7 @@ use core::{
 	hint::unlikely,
 	mem,
 	num::NonZeroU32,
-	ops::{Deref, DerefMut},
+	ops::{Deref, DerefMut, Range},
 	sync::atomic::{AtomicU32, Ordering::Relaxed},
 };
 use macros::AnyRepr;
@@ -76,7 +77,7 @@ const INODE_FLAG_NODUMP: u32 = 0x00040;
 /// `s_flags`: Last accessed time should not be updated
 const INODE_FLAG_ATIME_NOUPDATE: u32 = 0x00080;
 /// `s_flags`: Hash indexed directory
-const INODE_FLAG_HASH_INDEXED: u32 = 0x10000;
+pub const INODE_FLAG_HASH_INDEXED: u32 = 0x10000;
 /// `s_flags`: AFS directory
 const INODE_FLAG_AFS_DIRECTORY: u32 = 0x20000;
 /// `s_flags`: Journal file data
@@ -94,7 +95,9 @@ pub const ROOT_DIRECTORY_INODE: u32 = 2;
 
 /// Container for an inode, locking its associated mutex to avoid concurrency issues
 pub(super) struct INodeWrap<'n> {
-	_guard: MutexGuard<'n, (), true>,
+	_guard: Option<MutexGuard<'n, (), true>>,
+	/// The number of the inode
+	pub ino: u32,
 	inode: RcFrameVal<Ext2INode>,
 }
 
@@ -104,6 +107,129 @@ impl INodeWrap<'_> {
 	pub fn mark_dirty(&self) {
 		self.inode.mark_dirty()
 	}
+
+	/// Returns the block around which the content block at the file block offset `off` should be
+	/// allocated.
+	///
+	/// This is the block following the previous content block, or else the first block of the
+	/// inode's block group.
+	fn find_goal(&self, off: u32, fs: &Ext2Fs) -> EResult<u32> {
+		if let Some(prev) = off.checked_sub(1)
+			&& let Some(blk) = self.translate_blk_off(prev, fs)?
+		{
+			return Ok(blk.get() + 1);
+		}
+		let group = (self.ino - 1) / fs.sp.s_inodes_per_group;
+		Ok(group * fs.sp.s_blocks_per_group)
+	}
+
+	/// Allocates a block for the node's content block at the given file block offset `off`.
+	///
+	/// The content of the allocated block is **not** initialized.
+	///
+	/// If a block is already allocated, the function does nothing.
+	///
+	/// **Note**: the function assumes the inode is locked.
+	///
+	/// On success, the function returns the allocated disk block offset.
+	pub fn alloc_content_blk(&mut self, off: u32, fs: &Ext2Fs) -> EResult<u32> {
+		let mut offsets: [usize; 4] = [0; 4];
+		let depth = indirections_offsets(off, fs.sp.get_entries_per_block_log(), &mut offsets)?;
+		let goal = self.find_goal(off, fs)?;
+		// Only the content of regular files uses a reservation window
+		let reserve = self.get_type() == FileType::Regular;
+		let ino = self.ino;
+		let alloc = |data: bool| {
+			if data && reserve {
+				fs.alloc_reserved_block(ino, goal)
+			} else {
+				fs.alloc_block(goal, ino)
+			}
+		};
+		// Allocate the first level if needed
+		let blk_off = &mut self.i_block[offsets[0]];
+		if *blk_off == 0 {
+			*blk_off = alloc(depth == 1)?;
+			zero_block(fs, *blk_off as _)?;
+		}
+		// Perform indirections
+		let mut blk_off = *blk_off;
+		for (i, off) in offsets[1..depth].iter().enumerate() {
+			let blk = read_block(fs, blk_off as _)?;
+			let ent = &blk.slice::<AtomicU32>()[*off];
+			// Allocate block if needed (two atomic operations are fine here since the node is
+			// locked)
+			let mut b = ent.load(Relaxed);
+			if b == 0 {
+				let new = alloc(i + 2 == depth)?;
+				zero_block(fs, new as _)?;
+				ent.store(new, Relaxed);
+				blk.mark_page_dirty(*off / (PAGE_SIZE / size_of::<AtomicU32>()));
+				b = new;
+			}
+			blk_off = b;
+		}
+		Ok(blk_off)
+	}
+
+	/// Adds a new entry to the current directory.
+	///
+	/// Arguments:
+	/// - `entry_inode` is the inode of the entry
+	/// - `name` is the name of the entry
+	/// - `file_type` is the type of the entry
+	///
+	/// If the block allocation fails or if the entry name is already used, the
+	/// function returns an error.
+	///
+	/// If the file is not a directory, the behaviour is undefined.
+	pub fn add_dirent(
+		&mut self,
+		fs: &Ext2Fs,
+		entry_inode: u32,
+		name: &[u8],
+		file_type: FileType,
+	) -> EResult<()> {
+		debug_assert_eq!(self.get_type(), FileType::Directory);
+		// If the name is too long, error
+		if unlikely(name.len() > NAME_MAX) {
+			return Err(errno!(ENAMETOOLONG));
+		}
+		let rec_len = (dirent::NAME_OFF + name.len()).next_multiple_of(dirent::ALIGN) as u16;
+		// If the entry is too large, error
+		let blk_size = fs.sp.get_block_size();
+		if unlikely(rec_len as u32 > blk_size) {
+			return Err(errno!(ENAMETOOLONG));
+		}
+		if let Some(mut index) = Index::open(self, fs)? {
+			return index.add(self, fs, entry_inode, name, file_type, rec_len);
+		}
+		// Adding entries without updating the index would break it
+		self.i_flags &= !INODE_FLAG_HASH_INDEXED;
+		if let Some((blk, range)) = self.find_suitable_slot(fs, rec_len)? {
+			// Safe since the inode is locked
+			let buf = unsafe { blk.slice_mut() };
+			insert_dirent(buf, range, rec_len, &fs.sp, entry_inode, name, file_type)?;
+			blk.mark_dirty();
+			return Ok(());
+		}
+		// No suitable free entry: index the directory once its first block is full
+		if let Some(mut index) = Index::create(self, fs)? {
+			return index.add(self, fs, entry_inode, name, file_type, rec_len);
+		}
+		// Fill a new block
+		let blocks = self.get_blocks(&fs.sp);
+		let blk_off = self.alloc_content_blk(blocks, fs)?;
+		let blk = read_block(fs, blk_off as _)?;
+		// Safe since the inode is locked
+		let buf = unsafe { blk.slice_mut() };
+		buf.fill(0);
+		let len = buf.len();
+		insert_dirent(buf, 0..len, rec_len, &fs.sp, entry_inode, name, file_type)?;
+		self.set_size(&fs.sp, (blocks as u64 + 1) * blk_size as u64, false);
+		blk.mark_dirty();
+		Ok(())
+	}
 }
 
 impl Deref for INodeWrap<'_> {
@@ -128,7 +254,7 @@ impl DerefMut for INodeWrap<'_> {
 ///
 /// On success, the function returns the number of offsets.
 ///
-/// If the offset is out of bounds, the function returns [`EOVERFLOW`].
+/// If the offset is out of bounds, the function returns [`errno::EFBIG`].
 fn indirections_offsets(
 	mut off: u32,
 	ent_per_blk_log: u32,
@@ -154,31 +280,31 @@ fn indirections_offsets(
 		return Ok(3);
 	}
 	off -= ent_per_blk * ent_per_blk;
-	if off < ent_per_blk * ent_per_blk * ent_per_blk {
+	if (off as u64) < (ent_per_blk as u64).pow(3) {
 		offsets[0] = DIRECT_BLOCKS_COUNT + 2;
 		offsets[1] = (off >> (ent_per_blk_log * 2)) as _;
-		offsets[2] = ((off >> ent_per_blk_log) & (ent_per_blk_log - 1)) as _;
+		offsets[2] = ((off >> ent_per_blk_log) & (ent_per_blk - 1)) as _;
 		offsets[3] = (off & (ent_per_blk - 1)) as _;
 		return Ok(4);
 	}
-	Err(errno!(EOVERFLOW))
+	Err(errno!(EFBIG))
 }
 
 /// Checks for an invalid block number.
 ///
 /// If the block number is zero, the function returns `None`.
-pub fn check_blk_off(blk: u32, sp: &Superblock) -> EResult<Option<NonZeroU32>> {
-	if unlikely(blk >= sp.s_blocks_count) {
-		return Err(errno!(EUCLEAN));
+pub fn check_blk_off(blk: u32, fs: &Ext2Fs) -> EResult<Option<NonZeroU32>> {
+	if unlikely(blk >= fs.sp.s_blocks_count) {
+		return Err(fs.error(errno!(EUCLEAN)));
 	}
 	Ok(NonZeroU32::new(blk))
 }
 
 /// Tells whether the block contains only free directory entries.
-fn is_block_empty(blk: &mut [u8], sp: &Superblock) -> EResult<bool> {
+fn is_block_empty(blk: &mut [u8], fs: &Ext2Fs) -> EResult<bool> {
 	let mut off = 0;
 	while off < blk.len() {
-		let ent = Dirent::from_slice(&mut blk[off..], sp)?;
+		let ent = Dirent::from_slice(&mut blk[off..], fs)?;
 		if !ent.is_free() {
 			return Ok(false);
 		}
@@ -193,7 +319,7 @@ fn is_block_empty(blk: &mut [u8], sp: &Superblock) -> EResult<bool> {
 /// [`dirent::ALIGN`].
 ///
 /// If an entry could not be created, the associated error is returned.
-fn fill_free_entries(buf: &mut [u8], sp: &Superblock) -> EResult<()> {
+pub(super) fn fill_free_entries(buf: &mut [u8], sp: &Superblock) -> EResult<()> {
 	const MIN: usize = dirent::NAME_OFF;
 	const MAX: usize = u16::MAX as usize;
 	const SPECIAL_CASE_END: usize = MAX + MIN;
@@ -218,6 +344,64 @@ fn fill_free_entries(buf: &mut [u8], sp: &Superblock) -> EResult<()> {
 	Ok(())
 }
 
+/// Looks in the directory block `buf` for a sequence of free entries large enough to fit an entry
+/// of `min_size` bytes.
+///
+/// On success, the function returns the range of the sequence in the block.
+pub(super) fn find_free_run(
+	buf: &mut [u8],
+	fs: &Ext2Fs,
+	min_size: u16,
+) -> EResult<Option<Range<usize>>> {
+	let mut start = 0;
+	let mut off = 0;
+	while off < buf.len() {
+		let ent = Dirent::from_slice(&mut buf[off..], fs)?;
+		let end = off + ent.rec_len as usize;
+		if !ent.is_free() {
+			// If the entry is used, start a new sequence after it
+			start = end;
+		} else if end - start >= min_size as usize {
+			return Ok(Some(start..end));
+		}
+		off = end;
+	}
+	Ok(None)
+}
+
+/// Writes a new entry onto the sequence of free entries at `range` in the directory block `buf`.
+///
+/// Arguments:
+/// - `rec_len` is the minimum size of the entry
+/// - `entry_inode` is the inode of the entry
+/// - `name` is the name of the entry
+/// - `file_type` is the type of the entry
+///
+/// The remaining space in the sequence is covered with free entries.
+pub(super) fn insert_dirent(
+	buf: &mut [u8],
+	range: Range<usize>,
+	mut rec_len: u16,
+	sp: &Superblock,
+	entry_inode: u32,
+	name: &[u8],
+	file_type: FileType,
+) -> EResult<()> {
+	// If not enough space is left to fit another entry, use the remaining space
+	if range.start + rec_len as usize + dirent::NAME_OFF >= range.end {
+		rec_len = range.len() as u16;
+	}
+	Dirent::write_new(
+		&mut buf[range.clone()],
+		sp,
+		entry_inode,
+		rec_len,
+		Some(file_type),
+		name,
+	)?;
+	fill_free_entries(&mut buf[(range.start + rec_len as usize)..range.end], sp)
+}
+
 /// An inode represents a file in the filesystem.
 ///
 /// The name of the file is not included in the inode but in the directory entry associated with it
@@ -267,6 +451,28 @@ impl Ext2INode {
 	/// Returns the `i`th inode on the filesystem.
 	pub fn get<'n>(node: &'n Node, fs: &Ext2Fs) -> EResult<INodeWrap<'n>> {
 		let i: u32 = node.inode.try_into().map_err(|_| errno!(EOVERFLOW))?;
+		let inode = Self::read(i, fs)?;
+		Ok(INodeWrap {
+			_guard: Some(node.lock.lock()),
+			ino: i,
+			inode,
+		})
+	}
+
+	/// Returns the `i`th inode on the filesystem, without locking it.
+	///
+	/// This is meant for inodes which are not associated with a [`Node`], such as when checking
+	/// the filesystem at mount. The caller must ensure the inode is not modified concurrently.
+	pub fn get_unlocked(i: u32, fs: &Ext2Fs) -> EResult<INodeWrap<'static>> {
+		Ok(INodeWrap {
+			_guard: None,
+			ino: i,
+			inode: Self::read(i, fs)?,
+		})
+	}
+
+	/// Reads the `i`th inode from the inode table.
+	fn read(i: u32, fs: &Ext2Fs) -> EResult<RcFrameVal<Self>> {
 		// Check the index is correct
 		let Some(i) = i.checked_sub(1) else {
 			return Err(errno!(EINVAL));
@@ -285,10 +491,14 @@ impl Ext2INode {
 		let off = i as u64 % (blk_size / inode_size);
 		// Adapt to the size of an inode
 		let off = off * (inode_size / 128);
-		Ok(INodeWrap {
-			_guard: node.lock.lock(),
-			inode: RcFrameVal::new(blk, off as _),
-		})
+		Ok(RcFrameVal::new(blk, off as _))
+	}
+
+	/// Tells whether no directory entry refers to the inode anymore, not counting the `.` entry
+	/// of a directory.
+	pub fn is_unlinked(&self) -> bool {
+		self.i_links_count == 0
+			|| (self.get_type() == FileType::Directory && self.i_links_count <= 1)
 	}
 
 	/// Returns the file's status.
@@ -376,13 +586,13 @@ impl Ext2INode {
 	pub fn translate_blk_off(&self, off: u32, fs: &Ext2Fs) -> EResult<Option<NonZeroU32>> {
 		let mut offsets: [usize; 4] = [0; 4];
 		let depth = indirections_offsets(off, fs.sp.get_entries_per_block_log(), &mut offsets)?;
-		let Some(mut blk_off) = check_blk_off(self.i_block[offsets[0]], &fs.sp)? else {
+		let Some(mut blk_off) = check_blk_off(self.i_block[offsets[0]], fs)? else {
 			return Ok(None);
 		};
 		// Perform indirections
 		for off in &offsets[1..depth] {
 			let blk = read_block(fs, blk_off.get() as _)?;
-			let Some(b) = check_blk_off(blk.slice()[*off], &fs.sp)? else {
+			let Some(b) = check_blk_off(blk.slice()[*off], fs)? else {
 				return Ok(None);
 			};
 			blk_off = b;
@@ -390,44 +600,6 @@ impl Ext2INode {
 		Ok(Some(blk_off))
 	}
 
-	/// Allocates a block for the node's content block at the given file block offset `off`.
-	///
-	/// The content of the allocated block is **not** initialized.
-	///
-	/// If a block is already allocated, the function does nothing.
-	///
-	/// **Note**: the function assumes the inode is locked.
-	///
-	/// On success, the function returns the allocated disk block offset.
-	pub fn alloc_content_blk(&mut self, off: u32, fs: &Ext2Fs) -> EResult<u32> {
-		let mut offsets: [usize; 4] = [0; 4];
-		let depth = indirections_offsets(off, fs.sp.get_entries_per_block_log(), &mut offsets)?;
-		// Allocate the first level if needed
-		let blk_off = &mut self.i_block[offsets[0]];
-		if *blk_off == 0 {
-			*blk_off = fs.alloc_block()?;
-			zero_block(fs, *blk_off as _)?;
-		}
-		// Perform indirections
-		let mut blk_off = *blk_off;
-		for off in &offsets[1..depth] {
-			let blk = read_block(fs, blk_off as _)?;
-			let ent = &blk.slice::<AtomicU32>()[*off];
-			// Allocate block if needed (two atomic operations are fine here since the node is
-			// locked)
-			let mut b = ent.load(Relaxed);
-			if b == 0 {
-				let new = fs.alloc_block()?;
-				zero_block(fs, new as _)?;
-				ent.store(new, Relaxed);
-				blk.mark_page_dirty(*off / (PAGE_SIZE / size_of::<AtomicU32>()));
-				b = new;
-			}
-			blk_off = b;
-		}
-		Ok(blk_off)
-	}
-
 	fn free_content_blk_impl(blk: u32, offsets: &[usize], fs: &Ext2Fs) -> EResult<bool> {
 		let Some(off) = offsets.first() else {
 			return Ok(true);
@@ -455,7 +627,7 @@ impl Ext2INode {
 		let mut offsets: [usize; 4] = [0; 4];
 		let depth = indirections_offsets(off, fs.sp.get_entries_per_block_log(), &mut offsets)?;
 		let blk = &mut self.i_block[offsets[0]];
-		if check_blk_off(*blk, &fs.sp)?.is_none() {
+		if check_blk_off(*blk, fs)?.is_none() {
 			return Ok(());
 		}
 		if Self::free_content_blk_impl(*blk, &offsets[1..depth], fs)? {
@@ -471,7 +643,7 @@ impl Ext2INode {
 	fn indirect_free_all(blk_off: u32, level: usize, fs: &Ext2Fs) -> EResult<()> {
 		let blk = read_block(fs, blk_off as _)?;
 		for blk in blk.slice() {
-			let Some(blk) = check_blk_off(*blk, &fs.sp)? else {
+			let Some(blk) = check_blk_off(*blk, fs)? else {
 				continue;
 			};
 			if let Some(next_level) = level.checked_sub(1) {
@@ -493,10 +665,10 @@ impl Ext2INode {
 		self.set_size(&fs.sp, 0, false);
 		// Free blocks
 		for (off, blk) in self.i_block.iter().enumerate() {
-			let Some(blk) = check_blk_off(*blk, &fs.sp)? else {
+			let Some(blk) = check_blk_off(*blk, fs)? else {
 				continue;
 			};
-			let depth = off.saturating_sub(DIRECT_BLOCKS_COUNT);
+			let depth = (off + 1).saturating_sub(DIRECT_BLOCKS_COUNT);
 			if let Some(depth) = depth.checked_sub(1) {
 				Self::indirect_free_all(blk.get(), depth, fs)?;
 			}
@@ -520,7 +692,13 @@ impl Ext2INode {
 		if self.get_type() != FileType::Directory {
 			return Ok(None);
 		}
-		// TODO If the hash index is enabled, use it
+		// The `.` and `..` entries are not indexed
+		if name != b"."
+			&& name != b".."
+			&& let Some(index) = Index::open(self, fs)?
+		{
+			return index.lookup(self, fs, name);
+		}
 		// Linear lookup
 		let mut blk = None;
 		for ent in DirentIterator::new(fs, self, &mut blk, 0)? {
@@ -547,107 +725,28 @@ impl Ext2INode {
 		Ok(true)
 	}
 
-	/// Looks for a sequence of free entries large enough to fit a chunk with at least `min_size`
-	/// bytes, and returns the block containing it, with the offset to its beginning.
-	///
-	/// Arguments:
-	/// - `buf` is the block buffer
-	/// - `min_size` is the minimum size of the new entry in bytes
+	/// Looks for a sequence of free entries large enough to fit an entry of `min_size` bytes, and
+	/// returns the block containing it, with the range of the sequence in the block.
 	///
 	/// If no suitable sequence is found, the function returns `None`.
-	fn find_suitable_slot(&self, fs: &Ext2Fs, min_size: u16) -> EResult<Option<(RcFrame, u64)>> {
-		let blk_size = fs.sp.get_block_size() as u64;
-		let mut free_length = 0;
-		let mut blk = None;
-		for ent in DirentIterator::new(fs, self, &mut blk, 0)? {
-			let (off, ent) = ent?;
-			// If the entry is used, reset counter
-			if !ent.is_free() {
-				free_length = 0;
-				continue;
-			}
-			// If a sequence large enough has been found, stop
-			if (free_length + ent.rec_len as usize) >= min_size as usize {
-				let begin = off - free_length as u64;
-				return Ok(Some((blk.unwrap(), begin)));
-			}
-			// If the next entry is on the next block, reset counter
-			let next = (off % blk_size + ent.rec_len as u64) >= blk_size;
-			if next {
-				free_length = 0;
-			} else {
-				free_length += ent.rec_len as usize;
-			}
-		}
-		Ok(None)
-	}
-
-	/// Adds a new entry to the current directory.
-	///
-	/// Arguments:
-	/// - `entry_inode` is the inode of the entry
-	/// - `name` is the name of the entry
-	/// - `file_type` is the type of the entry
-	///
-	/// If the block allocation fails or if the entry name is already used, the
-	/// function returns an error.
-	///
-	/// If the file is not a directory, the behaviour is undefined.
-	pub fn add_dirent(
-		&mut self,
+	fn find_suitable_slot(
+		&self,
 		fs: &Ext2Fs,
-		entry_inode: u32,
-		name: &[u8],
-		file_type: FileType,
-	) -> EResult<()> {
-		debug_assert_eq!(self.get_type(), FileType::Directory);
-		// If the name is too long, error
-		if unlikely(name.len() > NAME_MAX) {
-			return Err(errno!(ENAMETOOLONG));
-		}
-		let mut rec_len = (dirent::NAME_OFF + name.len()).next_multiple_of(dirent::ALIGN) as u16;
-		// If the entry is too large, error
-		let blk_size = fs.sp.get_block_size();
-		if unlikely(rec_len as u32 > blk_size) {
-			return Err(errno!(ENAMETOOLONG));
-		}
-		if let Some((blk, off)) = self.find_suitable_slot(fs, rec_len)? {
+		min_size: u16,
+	) -> EResult<Option<(RcFrame, Range<usize>)>> {
+		for off in 0..self.get_blocks(&fs.sp) {
+			// If reaching a zero block, stop
+			let Some(blk) = self.translate_blk_off(off, fs)? else {
+				break;
+			};
+			let blk = read_block(fs, blk.get() as _)?;
 			// Safe since the inode is locked
 			let buf = unsafe { blk.slice_mut() };
-			// Create entry
-			let inner_off = (off % buf.len() as u64) as usize;
-			// If not enough space is left on the block to fit another entry, use the remaining
-			// space
-			if inner_off + rec_len as usize + dirent::NAME_OFF >= buf.len() {
-				rec_len = (buf.len() - inner_off) as u16;
+			if let Some(range) = find_free_run(buf, fs, min_size)? {
+				return Ok(Some((blk, range)));
 			}
-			Dirent::write_new(
-				&mut buf[inner_off..],
-				&fs.sp,
-				entry_inode as _,
-				rec_len,
-				Some(file_type),
-				name,
-			)?;
-			// Create free entries to cover remaining free space
-			fill_free_entries(&mut buf[(inner_off + rec_len as usize)..], &fs.sp)?;
-			blk.mark_dirty();
-		} else {
-			// No suitable free entry: Fill a new block
-			let blocks = self.get_blocks(&fs.sp);
-			let blk_off = self.alloc_content_blk(blocks, fs)?;
-			let blk = read_block(fs, blk_off as _)?;
-			// Safe since the inode is locked
-			let buf = unsafe { blk.slice_mut() };
-			buf.fill(0);
-			// Create used entry
-			Dirent::write_new(buf, &fs.sp, entry_inode, rec_len, Some(file_type), name)?;
-			// Create free entries to cover remaining free space
-			fill_free_entries(&mut buf[rec_len as usize..], &fs.sp)?;
-			self.set_size(&fs.sp, (blocks as u64 + 1) * blk_size as u64, false);
-			blk.mark_dirty();
 		}
-		Ok(())
+		Ok(None)
 	}
 
 	/// Changes the inode associated with a directory entry.
@@ -672,11 +771,13 @@ impl Ext2INode {
 		let blk = read_block(fs, disk_blk_off.get() as _)?;
 		// Read and free entry
 		let slice = unsafe { blk.slice_mut() };
-		let ent = Dirent::from_slice(&mut slice[inner_off..], &fs.sp)?;
+		let ent = Dirent::from_slice(&mut slice[inner_off..], fs)?;
 		ent.inode = inode as _;
 		blk.mark_dirty();
-		// If the block is now empty, free it
-		if inode == 0 && is_block_empty(slice, &fs.sp)? {
+		// If the block is now empty, free it. The blocks of an indexed directory are referenced by
+		// the index
+		let indexed = self.i_flags & INODE_FLAG_HASH_INDEXED != 0;
+		if inode == 0 && !indexed && is_block_empty(slice, fs)? {
 			// If this is the last block, update the file's size
 			if file_blk_off as u32 + 1 >= self.get_blocks(&fs.sp) {
 				self.set_size(&fs.sp, file_blk_off * blk_size as u64, false);
@@ -46,29 +46,36 @@
 // TODO Take into account user's UID/GID when allocating block/inode to handle
 // reserved blocks/inodes
 
+mod alloc;
 mod bgd;
 mod dirent;
+mod fsck;
+mod htree;
 mod inode;
+mod orphan;
 
 use crate::{
 	device::BlkDev,
 	file::{
 		DirContext, DirEntry, File, FileType, INode, Stat,
 		fs::{
-			FileOps, Filesystem, FilesystemOps, FilesystemType, NodeOps, Statfs, downcast_fs,
+			ErrorPolicy, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FileOps, Filesystem,
+			FilesystemOps, FilesystemType, NodeOps, Statfs, downcast_fs,
 			ext2::{dirent::DirentIterator, inode::ROOT_DIRECTORY_INODE},
 			generic_file_read, generic_file_write,
 		},
 		vfs,
-		vfs::node::Node,
+		vfs::{mountpoint, node::Node},
 	},
 	memory::{
 		cache::{FrameOwner, RcFrame, RcFrameVal},
 		user::UserSlice,
 	},
+	println,
 	sync::mutex::Mutex,
 	time::clock::{Clock, current_time_sec},
 };
+use alloc::Window;
 use bgd::BlockGroupDescriptor;
 use core::{
 	cmp::max,
@@ -83,9 +90,10 @@ use macros::AnyRepr;
 use utils::{
 	boxed::Box,
 	bytes,
-	collections::path::PathBuf,
+	collections::{btreemap::BTreeMap, path::PathBuf},
 	errno,
-	errno::EResult,
+	errno::{EResult, Errno},
+	fortify,
 	limits::{NAME_MAX, PAGE_SIZE, SYMLINK_MAX},
 	math,
 	ptr::arc::Arc,
@@ -159,6 +167,23 @@ fn zero_block(fs: &Ext2Fs, off: u64) -> EResult<()> {
 	Ok(())
 }
 
+/// Zeros the bytes in the range `start..end` of the content of `node`, through the page cache.
+///
+/// The range must be contained in a single page.
+fn zero_range(node: &Arc<Node>, start: u64, end: u64) -> EResult<()> {
+	if start >= end {
+		return Ok(());
+	}
+	let page = node.node_ops.read_page(node, start / PAGE_SIZE as u64)?;
+	let inner_off = start as usize % PAGE_SIZE;
+	unsafe {
+		let page_ptr = page.virt_addr().as_ptr::<u8>().add(inner_off);
+		fortify::memset_chk(page_ptr, 0, (end - start) as usize, PAGE_SIZE - inner_off);
+	}
+	page.mark_dirty();
+	Ok(())
+}
+
 /// Finds a `0` bit in the given block, sets it atomically, then returns its offset.
 ///
 /// If no bit is found, the function returns `None`.
@@ -212,6 +237,8 @@ impl NodeOps for Ext2NodeOps {
 
 						lock: Default::default(),
 						mapped: Default::default(),
+						write_access: Default::default(),
+						locks: Default::default(),
 					};
 					let stat = Ext2INode::get(&node, fs)?.stat(&fs.sp);
 					node.stat = Mutex::new(stat);
@@ -249,7 +276,7 @@ impl NodeOps for Ext2NodeOps {
 
 	fn link(&self, parent: Arc<Node>, ent: &vfs::Entry) -> EResult<()> {
 		let fs = downcast_fs::<Ext2Fs>(&*parent.fs.ops);
-		if unlikely(fs.readonly) {
+		if unlikely(fs.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		// Check the parent file is a directory
@@ -287,7 +314,7 @@ impl NodeOps for Ext2NodeOps {
 
 	fn unlink(&self, parent: &Node, ent: &vfs::Entry) -> EResult<()> {
 		let fs = downcast_fs::<Ext2Fs>(&*parent.fs.ops);
-		if unlikely(fs.readonly) {
+		if unlikely(fs.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		if ent.name == "." || ent.name == ".." {
@@ -320,6 +347,10 @@ impl NodeOps for Ext2NodeOps {
 				parent.stat.lock().nlink = parent_.i_links_count;
 			}
 		}
+		// The inode is freed when not used anymore, keep track of it until then
+		if target.is_unlinked() {
+			fs.orphan_add(ent.node().inode as _, &mut target);
+		}
 		parent_.mark_dirty();
 		target.mark_dirty();
 		Ok(())
@@ -333,7 +364,7 @@ impl NodeOps for Ext2NodeOps {
 		}
 		let size = inode_.get_size(&fs.sp);
 		if unlikely(size > SYMLINK_MAX as u64) {
-			return Err(errno!(EUCLEAN));
+			return Err(fs.error(errno!(EUCLEAN)));
 		}
 		if size <= inode::SYMLINK_INLINE_LIMIT {
 			// The target is stored inline in the inode
@@ -342,8 +373,8 @@ impl NodeOps for Ext2NodeOps {
 			Ok(len)
 		} else {
 			// The target is stored like in regular files
-			let blk =
-				inode::check_blk_off(inode_.i_block[0], &fs.sp)?.ok_or_else(|| errno!(EUCLEAN))?;
+			let blk = inode::check_blk_off(inode_.i_block[0], fs)?
+				.ok_or_else(|| fs.error(errno!(EUCLEAN)))?;
 			let blk = read_block(fs, blk.get() as _)?;
 			let len = buf.copy_to_user(0, &blk.slice()[..size as usize])?;
 			Ok(len)
@@ -387,7 +418,7 @@ impl NodeOps for Ext2NodeOps {
 	fn rename(&self, entry: &vfs::Entry, new_parent: &vfs::Entry, new_name: &[u8]) -> EResult<()> {
 		let entry_node = entry.node();
 		let fs = downcast_fs::<Ext2Fs>(&*entry_node.fs.ops);
-		if unlikely(fs.readonly) {
+		if unlikely(fs.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		// Create new entry
@@ -407,7 +438,7 @@ impl NodeOps for Ext2NodeOps {
 				}
 				let (_, off) = inode
 					.get_dirent(b"..", fs)?
-					.ok_or_else(|| errno!(EUCLEAN))?;
+					.ok_or_else(|| fs.error(errno!(EUCLEAN)))?;
 				inode.set_dirent_inode(off, new_parent_node.inode, fs)?;
 				// Update links count
 				new_parent_inode.i_links_count += 1;
@@ -438,24 +469,37 @@ impl NodeOps for Ext2NodeOps {
 	fn read_page(&self, node: &Arc<Node>, off: u64) -> EResult<RcFrame> {
 		node.mapped.get_or_insert_frame(off, 0, || {
 			let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
-			let inode = Ext2INode::get(node, fs)?;
+			let mut inode = Ext2INode::get(node, fs)?;
 			let off: u32 = off.try_into().map_err(|_| errno!(EOVERFLOW))?;
-			let blk_off = inode
-				.translate_blk_off(off, fs)?
-				.ok_or_else(|| errno!(EOVERFLOW))?;
+			let blk_size = fs.sp.get_block_size() as u64;
+			let blk_count = inode.get_size(&fs.sp).div_ceil(blk_size);
+			let blk_off = match inode.translate_blk_off(off, fs)? {
+				Some(blk_off) => blk_off.get(),
+				// Frames in cache must be backed by a block, so holes left by punching are
+				// allocated when accessed
+				None if !fs.is_readonly() && (off as u64) < blk_count => {
+					let blk_off = inode.alloc_content_blk(off, fs)?;
+					inode.mark_dirty();
+					blk_off
+				}
+				None => return Err(errno!(EOVERFLOW)),
+			};
 			fs.dev
-				.ops
-				.read_frame(blk_off.get() as _, 0, FrameOwner::Node(node.clone()))
+				.read_frame_direct(blk_off as _, 0, FrameOwner::Node(node.clone()))
 		})
 	}
 
 	fn write_frame(&self, node: &Node, frame: &RcFrame) -> EResult<()> {
 		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
-		fs.dev.ops.write_pages(frame.dev_offset(), frame.slice())
+		fs.dev.write_pages(frame.dev_offset(), frame.slice())
 	}
 
 	fn sync_stat(&self, node: &Node) -> EResult<()> {
 		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
+		// Changes are not written back to a read-only filesystem
+		if unlikely(fs.is_readonly()) {
+			return Ok(());
+		}
 		let stat = node.stat.lock().clone();
 		let mut inode_ = Ext2INode::get(node, fs)?;
 		inode_.set_permissions(stat.mode);
@@ -491,7 +535,7 @@ impl FileOps for Ext2FileOps {
 	fn write(&self, file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
 		let node = file.node().unwrap();
 		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
-		if unlikely(fs.readonly) {
+		if unlikely(fs.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		// TODO replace by filetype-specific FileOps
@@ -505,10 +549,17 @@ impl FileOps for Ext2FileOps {
 		generic_file_write(file, off, buf)
 	}
 
+	fn release(&self, file: &File) {
+		// Blocks reserved for the file are not needed once it is not written anymore
+		let node = file.node().unwrap();
+		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
+		fs.discard_reservation(node.inode as _);
+	}
+
 	fn truncate(&self, file: &File, size: u64) -> EResult<()> {
 		let node = file.node().unwrap();
 		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
-		if unlikely(fs.readonly) {
+		if unlikely(fs.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		let mut inode_ = Ext2INode::get(node, fs)?;
@@ -516,6 +567,9 @@ impl FileOps for Ext2FileOps {
 		if inode_.get_type() != FileType::Regular {
 			return Err(errno!(EINVAL));
 		}
+		if unlikely(size > fs.sp.get_max_file_size()) {
+			return Err(errno!(EFBIG));
+		}
 		// The size of a block
 		let blk_size = fs.sp.get_block_size();
 		let old_size = inode_.get_size(&fs.sp);
@@ -526,6 +580,7 @@ impl FileOps for Ext2FileOps {
 			for off in start..end {
 				inode_.free_content_blk(off, fs)?;
 			}
+			fs.discard_reservation(node.inode as _);
 			// Clear cache
 			node.mapped.truncate(start as _);
 		} else {
@@ -541,6 +596,61 @@ impl FileOps for Ext2FileOps {
 		node.stat.lock().size = size;
 		Ok(())
 	}
+
+	fn fallocate(&self, file: &File, mode: u32, off: u64, len: u64) -> EResult<()> {
+		let node = file.node().unwrap();
+		let fs = downcast_fs::<Ext2Fs>(&*node.fs.ops);
+		if unlikely(fs.is_readonly()) {
+			return Err(errno!(EROFS));
+		}
+		// TODO replace by filetype-specific FileOps
+		{
+			let inode_ = Ext2INode::get(node, fs)?;
+			if inode_.get_type() != FileType::Regular {
+				return Err(errno!(ENODEV));
+			}
+		}
+		let end = off.checked_add(len).ok_or_else(|| errno!(EFBIG))?;
+		if unlikely(end > fs.sp.get_max_file_size()) {
+			return Err(errno!(EFBIG));
+		}
+		let blk_size = fs.sp.get_block_size() as u64;
+		if mode & FALLOC_FL_PUNCH_HOLE != 0 {
+			let end = end.min(node.stat.lock().size);
+			if off >= end {
+				return Ok(());
+			}
+			// Zero the blocks partially covered by the hole
+			let start_blk = off.div_ceil(blk_size);
+			let end_blk = end / blk_size;
+			if start_blk > end_blk {
+				return zero_range(node, off, end);
+			}
+			zero_range(node, off, start_blk * blk_size)?;
+			zero_range(node, end_blk * blk_size, end)?;
+			// Remove from cache first, so that the freed blocks cannot be written back
+			node.mapped.remove_range(start_blk, end_blk);
+			let mut inode_ = Ext2INode::get(node, fs)?;
+			for off in start_blk..end_blk {
+				inode_.free_content_blk(off as _, fs)?;
+			}
+			inode_.mark_dirty();
+			return Ok(());
+		}
+		// Extending the file allocates the new blocks
+		if mode & FALLOC_FL_KEEP_SIZE == 0 && end > node.stat.lock().size {
+			self.truncate(file, end)?;
+		}
+		// Allocate the holes in the range. Blocks beyond the end of the file are not
+		// preallocated
+		let end = end.min(node.stat.lock().size);
+		let mut inode_ = Ext2INode::get(node, fs)?;
+		for off in (off / blk_size)..end.div_ceil(blk_size) {
+			inode_.alloc_content_blk(off as _, fs)?;
+		}
+		inode_.mark_dirty();
+		Ok(())
+	}
 }
 
 /// The ext2 superblock structure.
@@ -580,7 +690,7 @@ pub struct Superblock {
 	/// The ext2 signature.
 	s_magic: u16,
 	/// The filesystem's state.
-	s_state: u16,
+	s_state: AtomicU16,
 	/// The action to perform when an error is detected.
 	s_errors: u16,
 	/// The minor version.
@@ -623,8 +733,8 @@ pub struct Superblock {
 	s_prealloc_blocks: u8,
 	/// The number of blocks to preallocate for directories.
 	s_prealloc_dir_blocks: u8,
-	/// Unused.
-	_pad: u16,
+	/// The number of blocks reserved after the block group descriptor table, for its growth.
+	s_reserved_gdt_blocks: u16,
 	/// The journal ID.
 	s_journal_uuid: [u8; 16],
 	/// The journal inode.
@@ -632,9 +742,17 @@ pub struct Superblock {
 	/// The journal device.
 	s_journal_dev: u32,
 	/// The head of orphan inodes list.
-	s_last_orphan: u32,
+	s_last_orphan: AtomicU32,
+	/// The seed of the hash used by directory indexes.
+	s_hash_seed: [u32; 4],
+	/// The default hash version used by directory indexes.
+	s_def_hash_version: u8,
+
+	_padding0: [u8; 99],
+	/// Miscellaneous flags.
+	s_flags: u32,
 
-	_padding: [u8; 788],
+	_padding1: [u8; 668],
 }
 
 impl Superblock {
@@ -660,6 +778,27 @@ impl Superblock {
 		self.s_log_block_size + 10 - 2
 	}
 
+	/// Returns the maximum size of a regular file, in bytes.
+	///
+	/// Without the [`WRITE_REQUIRED_64_BITS`] feature, sizes are limited to 32 bit signed values.
+	/// Otherwise, the size is limited by the number of blocks an inode can reference and count.
+	pub fn get_max_file_size(&self) -> u64 {
+		let has_version = self.s_rev_level >= 1;
+		let has_feature = self.s_feature_ro_compat & WRITE_REQUIRED_64_BITS != 0;
+		if !has_version || !has_feature {
+			return i32::MAX as _;
+		}
+		let ent_per_blk = 1u64 << self.get_entries_per_block_log();
+		let blocks = inode::DIRECT_BLOCKS_COUNT as u64
+			+ ent_per_blk
+			+ ent_per_blk * ent_per_blk
+			+ ent_per_blk * ent_per_blk * ent_per_blk;
+		// `i_blocks` is a 32 bit count of 512 bytes sectors
+		let blk_size = self.get_block_size();
+		let blocks = blocks.min((u32::MAX / (blk_size / 512)) as _);
+		blocks * blk_size as u64
+	}
+
 	/// Returns the number of block groups.
 	fn get_block_groups_count(&self) -> u32 {
 		self.s_blocks_count / self.s_blocks_per_group
@@ -696,11 +835,45 @@ struct Ext2Fs {
 	dev: Arc<BlkDev>,
 	/// The filesystem's superblock
 	sp: RcFrameVal<Superblock>,
-	/// Tells whether the filesystem is mounted as read-only
-	readonly: bool,
+	/// Tells whether the filesystem is read-only, either from mount or after an error
+	readonly: AtomicBool,
+	/// The action to perform when an error is detected
+	errors: ErrorPolicy,
+	/// Lock for modifications of the orphan inodes list
+	orphans: Mutex<()>,
+	/// The reservation windows of the inodes being written, by inode
+	reservations: Mutex<BTreeMap<u32, Window>>,
 }
 
 impl Ext2Fs {
+	/// Tells whether the filesystem is read-only.
+	fn is_readonly(&self) -> bool {
+		self.readonly.load(Acquire)
+	}
+
+	/// Handles the error `err`, detected in the on-disk structures of the filesystem.
+	///
+	/// The error is recorded in the superblock, then the filesystem's error policy is applied.
+	/// The function returns `err` so that it can be propagated.
+	#[cold]
+	fn error(&self, err: Errno) -> Errno {
+		println!("ext2: error on {}: {err}", self.dev.path);
+		// Record the error so that the next consistency check repairs the filesystem
+		if !self.is_readonly() && self.sp.s_state.swap(FS_STATE_ERROR, Relaxed) != FS_STATE_ERROR {
+			self.sp.mark_dirty();
+		}
+		match self.errors {
+			ErrorPolicy::Continue => {}
+			ErrorPolicy::RemountRo => {
+				if !self.readonly.swap(true, Release) {
+					println!("ext2: remounting {} read-only", self.dev.path);
+				}
+			}
+			ErrorPolicy::Panic => panic!("ext2: unrecoverable error on {}", self.dev.path),
+		}
+		err
+	}
+
 	/// Finds a free element in the given bitmap, allocates it, and returns its index.
 	///
 	/// Arguments:
@@ -738,34 +911,6 @@ impl Ext2Fs {
 		Ok(prev & (1 << bitmap_bit_index) != 0)
 	}
 
-	/// Allocates an inode and returns its ID.
-	///
-	/// `directory` tells whether the inode is allocated for a directory.
-	///
-	/// If no free inode can be found, the function returns an error.
-	pub fn alloc_inode(&self, directory: bool) -> EResult<u32> {
-		if unlikely(self.sp.s_free_inodes_count.load(Acquire) == 0) {
-			return Err(errno!(ENOSPC));
-		}
-		for group in 0..self.sp.get_block_groups_count() {
-			let bgd = BlockGroupDescriptor::get(group, self)?;
-			if bgd.bg_free_inodes_count.load(Acquire) == 0 {
-				continue;
-			}
-			if let Some(j) = self.bitmap_alloc(bgd.bg_inode_bitmap, self.sp.s_inodes_per_group)? {
-				self.sp.s_free_inodes_count.fetch_sub(1, Release);
-				bgd.bg_free_inodes_count.fetch_sub(1, Release);
-				if directory {
-					bgd.bg_used_dirs_count.fetch_add(1, Release);
-				}
-				self.sp.mark_dirty();
-				bgd.mark_dirty();
-				return Ok(group * self.sp.s_inodes_per_group + j + 1);
-			}
-		}
-		Err(errno!(ENOSPC))
-	}
-
 	/// Marks the inode `inode` available on the filesystem.
 	///
 	/// If `inode` is zero, the function does nothing.
@@ -798,40 +943,13 @@ impl Ext2Fs {
 		Ok(())
 	}
 
-	/// Returns the ID of a free block in the filesystem.
-	pub fn alloc_block(&self) -> EResult<u32> {
-		if unlikely(self.sp.s_free_inodes_count.load(Acquire) == 0) {
-			return Err(errno!(ENOSPC));
-		}
-		for i in 0..self.sp.get_block_groups_count() {
-			let bgd = BlockGroupDescriptor::get(i as _, self)?;
-			if bgd.bg_free_blocks_count.load(Acquire) == 0 {
-				continue;
-			}
-			let Some(j) = self.bitmap_alloc(bgd.bg_block_bitmap, self.sp.s_blocks_per_group)?
-			else {
-				continue;
-			};
-			let blk_index = i * self.sp.s_blocks_per_group + j;
-			if unlikely(blk_index <= 2 || blk_index >= self.sp.s_blocks_count) {
-				return Err(errno!(EUCLEAN));
-			}
-			self.sp.s_free_blocks_count.fetch_sub(1, Release);
-			bgd.bg_free_blocks_count.fetch_sub(1, Release);
-			self.sp.mark_dirty();
-			bgd.mark_dirty();
-			return Ok(blk_index);
-		}
-		Err(errno!(ENOSPC))
-	}
-
 	/// Marks the block `blk` available on the filesystem.
 	///
 	/// If `blk` is zero, the function does nothing.
 	pub fn free_block(&self, blk: u32) -> EResult<()> {
 		// Validation
 		if unlikely(blk <= 2 || blk >= self.sp.s_blocks_count) {
-			return Err(errno!(EUCLEAN));
+			return Err(self.error(errno!(EUCLEAN)));
 		}
 		// Get block group
 		let group = blk / self.sp.s_blocks_per_group;
@@ -845,9 +963,19 @@ impl Ext2Fs {
 			bgd.bg_free_blocks_count.fetch_add(1, Release);
 			self.sp.mark_dirty();
 			bgd.mark_dirty();
+			self.discard_block(blk);
 		}
 		Ok(())
 	}
+
+	/// Tells the device the content of the block `blk` is not used anymore.
+	///
+	/// Discarding is only a hint, so errors (including devices not supporting it) are ignored.
+	fn discard_block(&self, blk: u32) {
+		let dev_blk_size = self.dev.ops.block_size().get();
+		let count = self.sp.get_block_size() as u64 / dev_blk_size;
+		let _ = self.dev.discard(blk as u64 * count, count);
+	}
 }
 
 // TODO Update the write timestamp when the fs is written (take mount flags into
@@ -861,10 +989,14 @@ impl FilesystemOps for Ext2Fs {
 		true
 	}
 
+	fn is_readonly(&self) -> bool {
+		Ext2Fs::is_readonly(self)
+	}
+
 	fn get_stat(&self) -> EResult<Statfs> {
 		Ok(Statfs {
 			f_type: EXT2_MAGIC as _,
-			f_bsize: self.sp.get_block_size(),
+			f_bsize: self.sp.get_block_size() as _,
 			f_blocks: self.sp.s_blocks_count as _,
 			f_bfree: self.sp.s_free_blocks_count.load(Relaxed) as _,
 			// TODO Subtract blocks for superuser
@@ -873,8 +1005,9 @@ impl FilesystemOps for Ext2Fs {
 			f_ffree: self.sp.s_free_inodes_count.load(Relaxed) as _,
 			f_fsid: Default::default(),
 			f_namelen: NAME_MAX as _,
-			f_frsize: math::pow2(self.sp.s_log_frag_size + 10),
+			f_frsize: math::pow2(self.sp.s_log_frag_size + 10) as _,
 			f_flags: 0, // TODO
+			f_spare: [0; 4],
 		})
 	}
 
@@ -892,6 +1025,8 @@ impl FilesystemOps for Ext2Fs {
 
 				lock: Default::default(),
 				mapped: Default::default(),
+				write_access: Default::default(),
+				locks: Default::default(),
 			};
 			let stat = Ext2INode::get(&node, self)?.stat(&self.sp);
 			node.stat = Mutex::new(stat);
@@ -899,13 +1034,21 @@ impl FilesystemOps for Ext2Fs {
 		})
 	}
 
-	fn create_node(&self, fs: &Arc<Filesystem>, stat: Stat) -> EResult<Arc<Node>> {
-		if unlikely(self.readonly) {
+	fn create_node(
+		&self,
+		fs: &Arc<Filesystem>,
+		parent: Option<&Node>,
+		stat: Stat,
+	) -> EResult<Arc<Node>> {
+		if unlikely(self.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
 		let file_type = stat.get_type().ok_or_else(|| errno!(EINVAL))?;
 		// Allocate an inode
-		let inode_index = self.alloc_inode(file_type == FileType::Directory)?;
+		let parent = parent
+			.map(|p| p.inode as u32)
+			.unwrap_or(ROOT_DIRECTORY_INODE);
+		let inode_index = self.alloc_inode(parent, file_type == FileType::Directory)?;
 		// Create inode
 		let mut node = Node {
 			inode: inode_index as _,
@@ -919,6 +1062,8 @@ impl FilesystemOps for Ext2Fs {
 
 			lock: Default::default(),
 			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
 		};
 		let mut inode = Ext2INode::get(&node, self)?;
 		*inode = Ext2INode {
@@ -965,10 +1110,12 @@ impl FilesystemOps for Ext2Fs {
 	}
 
 	fn destroy_node(&self, node: &Node) -> EResult<()> {
-		if unlikely(self.readonly) {
+		if unlikely(self.is_readonly()) {
 			return Err(errno!(EROFS));
 		}
+		self.discard_reservation(node.inode as _);
 		let mut inode = Ext2INode::get(node, self)?;
+		self.orphan_remove(node.inode as _, &mut inode)?;
 		// Remove the inode
 		inode.i_links_count = 0;
 		let ts = current_time_sec(Clock::Monotonic);
@@ -1001,8 +1148,9 @@ impl FilesystemType for Ext2FsType {
 		&self,
 		dev: Option<Arc<BlkDev>>,
 		_mountpath: PathBuf,
-		readonly: bool,
+		flags: u32,
 	) -> EResult<Arc<Filesystem>> {
+		let readonly = flags & mountpoint::FLAG_RDONLY != 0;
 		let dev = dev.ok_or_else(|| errno!(ENODEV))?;
 		let sp = Superblock::read(&dev)?;
 		if unlikely(!sp.is_valid()) {
@@ -1045,17 +1193,39 @@ impl FilesystemType for Ext2FsType {
 		let len = min(mountpath_bytes.len(), sp.s_last_mounted.len());
 		sp.s_last_mounted[..len].copy_from_slice(&mountpath_bytes[..len]);
 		sp.s_last_mounted[len..].fill(0);*/
-		// Set the last mount timestamp
-		sp.s_mtime.store(ts as _, Relaxed);
-		sp.s_mnt_count.fetch_add(1, Relaxed);
-		sp.mark_dirty();
+		// Set the last mount timestamp. A read-only device must not be written to
+		if !readonly {
+			sp.s_mtime.store(ts as _, Relaxed);
+			sp.s_mnt_count.fetch_add(1, Relaxed);
+			sp.mark_dirty();
+		}
+		// The policy given at mount takes precedence over the one in the superblock
+		let errors = ErrorPolicy::from_flags(flags).unwrap_or(match sp.s_errors {
+			ERR_ACTION_READ_ONLY => ErrorPolicy::RemountRo,
+			ERR_ACTION_KERNEL_PANIC => ErrorPolicy::Panic,
+			_ => ErrorPolicy::Continue,
+		});
+		let fs = Ext2Fs {
+			dev,
+			sp,
+			readonly: AtomicBool::new(readonly),
+			errors,
+			orphans: Mutex::new(()),
+			reservations: Mutex::new(BTreeMap::new()),
+		};
+		// Free the inodes that were still in use when the filesystem was last unmounted
+		if !readonly {
+			let count = fs.release_orphans()?;
+			if count > 0 {
+				println!("ext2: released {count} orphan inodes on {}", fs.dev.path);
+			}
+		}
+		if flags & mountpoint::FLAG_CHECK != 0 {
+			fs.check()?;
+		}
 		Ok(Filesystem::new(
-			dev.id.get_device_number(),
-			Box::new(Ext2Fs {
-				dev,
-				sp,
-				readonly,
-			})?,
+			fs.dev.id.get_device_number(),
+			Box::new(fs)?,
 		)?)
 	}
 }
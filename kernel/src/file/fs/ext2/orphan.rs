@@ -0,0 +1,140 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Orphan inodes are inodes which have been unlinked while still in use.
+//!
+//! Such inodes can only be freed once they are not used anymore. To avoid leaking them if the
+//! system stops before, they are kept in a list on the disk, which is processed at the next mount.
+//!
+//! The head of the list is stored in the superblock, and each orphan inode stores the next one in
+//! place of its deletion time.
+
+use super::{Ext2Fs, inode::Ext2INode};
+use crate::{
+	file::FileType,
+	time::clock::{Clock, current_time_sec},
+};
+use core::{
+	hint::unlikely,
+	sync::atomic::Ordering::{Acquire, Release},
+};
+use utils::{errno, errno::EResult};
+
+impl Ext2Fs {
+	/// Tells whether the filesystem supports the orphan inodes list.
+	///
+	/// The list is located in the extended superblock, which does not exist on revision `0`.
+	fn has_orphan_list(&self) -> bool {
+		self.sp.s_rev_level >= 1
+	}
+
+	/// Adds the inode `ino` to the orphan inodes list.
+	///
+	/// `inode` is the inode's content.
+	pub fn orphan_add(&self, ino: u32, inode: &mut Ext2INode) {
+		if !self.has_orphan_list() {
+			return;
+		}
+		let _guard = self.orphans.lock();
+		inode.i_dtime = self.sp.s_last_orphan.swap(ino, Release);
+		self.sp.mark_dirty();
+	}
+
+	/// Removes the inode `ino` from the orphan inodes list.
+	///
+	/// `inode` is the inode's content.
+	///
+	/// If the inode is not in the list, the function does nothing.
+	pub fn orphan_remove(&self, ino: u32, inode: &mut Ext2INode) -> EResult<()> {
+		if !self.has_orphan_list() {
+			return Ok(());
+		}
+		let _guard = self.orphans.lock();
+		let next = inode.i_dtime;
+		if self.sp.s_last_orphan.load(Acquire) == ino {
+			self.sp.s_last_orphan.store(next, Release);
+			self.sp.mark_dirty();
+			inode.i_dtime = 0;
+			return Ok(());
+		}
+		// Look for the previous element. The number of iterations is bounded in case the list has
+		// a cycle
+		let mut cur = self.sp.s_last_orphan.load(Acquire);
+		for _ in 0..self.sp.s_inodes_count {
+			if cur == 0 {
+				break;
+			}
+			if unlikely(cur > self.sp.s_inodes_count) {
+				return Err(self.error(errno!(EUCLEAN)));
+			}
+			let mut prev = Ext2INode::get_unlocked(cur, self)?;
+			if prev.i_dtime == ino {
+				prev.i_dtime = next;
+				prev.mark_dirty();
+				inode.i_dtime = 0;
+				break;
+			}
+			cur = prev.i_dtime;
+		}
+		Ok(())
+	}
+
+	/// Frees the inodes of the orphan inodes list, which have been left by a previous mount.
+	///
+	/// The function returns the number of freed inodes.
+	///
+	/// This function must be called at mount, before the filesystem is used.
+	pub fn release_orphans(&self) -> EResult<u32> {
+		if !self.has_orphan_list() {
+			return Ok(0);
+		}
+		let ts = current_time_sec(Clock::Realtime);
+		let mut count = 0;
+		let mut cur = self.sp.s_last_orphan.swap(0, Release);
+		if cur != 0 {
+			self.sp.mark_dirty();
+		}
+		// The number of iterations is bounded in case the list has a cycle
+		for _ in 0..self.sp.s_inodes_count {
+			if cur == 0 {
+				break;
+			}
+			if unlikely(cur < self.sp.get_first_available_inode() || cur > self.sp.s_inodes_count)
+			{
+				return Err(self.error(errno!(EUCLEAN)));
+			}
+			let mut inode = Ext2INode::get_unlocked(cur, self)?;
+			let next = inode.i_dtime;
+			if inode.is_unlinked() {
+				let dir = inode.get_type() == FileType::Directory;
+				inode.free_content(self)?;
+				inode.i_links_count = 0;
+				inode.i_dtime = ts as _;
+				inode.mark_dirty();
+				self.free_inode(cur as _, dir)?;
+				count += 1;
+			} else {
+				// The inode has been linked again
+				inode.i_dtime = 0;
+				inode.mark_dirty();
+			}
+			cur = next;
+		}
+		Ok(count)
+	}
+}
@@ -299,6 +299,8 @@ impl<T: 'static + Clone + Debug> NodeOps for StaticDir<T> {
 
 					lock: Default::default(),
 					mapped: Default::default(),
+					write_access: Default::default(),
+					locks: Default::default(),
 				})
 			})
 			.transpose()?;
@@ -19,6 +19,8 @@
 //! A filesystem is the representation of the file hierarchy on a storage
 //! device.
 
+pub mod cgroup;
+pub mod dir_cache;
 pub mod ext2;
 pub mod initramfs;
 pub mod kernfs;
@@ -29,10 +31,11 @@ use super::{
 	DirContext, File, INode, Mode, Stat,
 	perm::{Gid, Uid},
 	vfs,
+	vfs::mountpoint,
 };
 use crate::{
 	device::BlkDev,
-	file::vfs::node::Node,
+	file::{fs::dir_cache::DirCache, vfs::node::Node, wait_queue::WaitQueue},
 	memory::{cache::RcFrame, user::UserSlice},
 	sync::mutex::Mutex,
 	syscall::ioctl,
@@ -42,7 +45,7 @@ use core::{
 	any::Any,
 	borrow::Borrow,
 	cmp::min,
-	ffi::{c_int, c_void},
+	ffi::{c_int, c_long, c_void},
 	fmt,
 	fmt::{Debug, Formatter},
 	hash::{Hash, Hasher},
@@ -50,19 +53,53 @@ use core::{
 };
 use utils::{
 	boxed::Box,
-	collections::{hashmap::HashMap, hashset::HashSet, path::PathBuf, string::String},
+	collections::{hashmap::HashMap, hashset::HashSet, path::PathBuf, string::String, vec::Vec},
 	errno,
 	errno::{AllocResult, EResult},
 	limits::PAGE_SIZE,
 	ptr::arc::Arc,
 };
 
+/// `fallocate` mode: do not change the size of the file.
+pub const FALLOC_FL_KEEP_SIZE: u32 = 0x01;
+/// `fallocate` mode: deallocate the range, leaving a hole that reads as zeros.
+pub const FALLOC_FL_PUNCH_HOLE: u32 = 0x02;
+
+/// The action to perform when a filesystem detects an error in its on-disk structures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorPolicy {
+	/// Ignore the error and keep the filesystem writable.
+	Continue,
+	/// Switch the filesystem to read-only, so that further writes fail with
+	/// [`errno::EROFS`].
+	RemountRo,
+	/// Trigger a kernel panic.
+	Panic,
+}
+
+impl ErrorPolicy {
+	/// Returns the error policy selected by the `errors=` option in the mount flags `flags`.
+	///
+	/// If no policy is selected, the function returns `None`.
+	pub fn from_flags(flags: u32) -> Option<Self> {
+		if flags & mountpoint::FLAG_ERRORS_PANIC != 0 {
+			Some(Self::Panic)
+		} else if flags & mountpoint::FLAG_ERRORS_REMOUNT_RO != 0 {
+			Some(Self::RemountRo)
+		} else if flags & mountpoint::FLAG_ERRORS_CONTINUE != 0 {
+			Some(Self::Continue)
+		} else {
+			None
+		}
+	}
+}
+
 /// Used in the f_fsid field of [`Statfs`].
 ///
 /// It is currently unused.
 #[repr(C)]
 #[derive(Debug, Default)]
-struct Fsid {
+pub struct Fsid {
 	/// Unused.
 	_val: [c_int; 2],
 }
@@ -72,27 +109,29 @@ struct Fsid {
 #[derive(Debug)]
 pub struct Statfs {
 	/// Type of filesystem.
-	f_type: u32,
+	pub f_type: c_long,
 	/// Optimal transfer block size.
-	f_bsize: u32,
+	pub f_bsize: c_long,
 	/// Total data blocks in filesystem.
-	f_blocks: i64,
+	pub f_blocks: i64,
 	/// Free blocks in filesystem.
-	f_bfree: i64,
+	pub f_bfree: i64,
 	/// Free blocks available to unprivileged user.
-	f_bavail: i64,
+	pub f_bavail: i64,
 	/// Total inodes in filesystem.
-	f_files: i64,
+	pub f_files: i64,
 	/// Free inodes in filesystem.
-	f_ffree: i64,
+	pub f_ffree: i64,
 	/// Filesystem ID.
-	f_fsid: Fsid,
+	pub f_fsid: Fsid,
 	/// Maximum length of filenames.
-	f_namelen: u32,
+	pub f_namelen: c_long,
 	/// Fragment size.
-	f_frsize: u32,
+	pub f_frsize: c_long,
 	/// Mount flags of filesystem.
-	f_flags: u32,
+	pub f_flags: c_long,
+	/// Reserved.
+	pub f_spare: [c_long; 4],
 }
 
 /// A set of attributes to modify on a file's status.
@@ -189,6 +228,17 @@ pub trait NodeOps: Any + Debug {
 		Err(errno!(EINVAL))
 	}
 
+	/// Returns the entry the symbolic link refers to, if it is a magic link.
+	///
+	/// Magic links do not point to a path. Following them gives the entry directly, even if it is
+	/// not reachable from the root directory of the process anymore, or has been removed.
+	///
+	/// The default implementation of this function returns `None`.
+	fn follow_link(&self, node: &Node) -> EResult<Option<Arc<vfs::Entry>>> {
+		let _ = node;
+		Ok(None)
+	}
+
 	/// Writes the path the symbolic link points to and writes it into `buf`.
 	///
 	/// If the node is not a symbolic link, the function returns [`errno::EINVAL`].
@@ -286,6 +336,24 @@ pub trait FileOps: Any + Debug {
 		Err(errno!(EINVAL))
 	}
 
+	/// Calls `f` on each wait queue whose processes are woken up when an event in `mask` may
+	/// have occurred on the file.
+	///
+	/// This allows to sleep until an event occurs on one of several files. If the file does not
+	/// provide such queues, the function returns `false` and the file has to be polled
+	/// periodically.
+	///
+	/// The default implementation returns `false`.
+	fn poll_wait(
+		&self,
+		file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		let _ = (file, mask, f);
+		Ok(false)
+	}
+
 	/// Performs an ioctl operation on the device file.
 	///
 	/// Arguments:
@@ -337,6 +405,31 @@ pub trait FileOps: Any + Debug {
 		let _ = (file, size);
 		Err(errno!(EINVAL))
 	}
+
+	/// Manipulates the space allocated for the range of `len` bytes at offset `off` in the
+	/// content of `file`.
+	///
+	/// `mode` is a combination of `FALLOC_FL_*` flags. If zero, blocks are allocated for the
+	/// range, extending the file if necessary.
+	///
+	/// The default implementation of this function returns [`errno::EOPNOTSUPP`].
+	fn fallocate(&self, file: &File, mode: u32, off: u64, len: u64) -> EResult<()> {
+		let _ = (file, mode, off, len);
+		Err(errno!(EOPNOTSUPP))
+	}
+
+	/// Returns the `pages` pages to be mapped in memory for `file`, starting at the offset `off`
+	/// in pages.
+	///
+	/// This allows files whose content is not in the page cache to be mapped with `mmap`. The
+	/// pages are shared with every mapping of the file.
+	///
+	/// The default implementation returns `None`, meaning the file is mapped through the page
+	/// cache, if at all.
+	fn mmap_pages(&self, file: &File, off: u64, pages: usize) -> EResult<Option<Vec<RcFrame>>> {
+		let _ = (file, off, pages);
+		Ok(None)
+	}
 }
 
 /// Generic implementation for [`FileOps::read`] on regular files.
@@ -414,13 +507,29 @@ pub trait FilesystemOps: Any + Debug {
 	/// Returns statistics about the filesystem.
 	fn get_stat(&self) -> EResult<Statfs>;
 
+	/// Tells whether the filesystem has been switched to read-only after being mounted, for
+	/// example because of an error.
+	///
+	/// The default implementation of this function returns `false`.
+	fn is_readonly(&self) -> bool {
+		false
+	}
+
 	/// Returns the root node.
 	///
 	/// If the node does not exist, the function returns [`errno::ENOENT`].
 	fn root(&self, fs: &Arc<Filesystem>) -> EResult<Arc<Node>>;
 
 	/// Creates a node on the filesystem.
-	fn create_node(&self, fs: &Arc<Filesystem>, stat: Stat) -> EResult<Arc<Node>>;
+	///
+	/// `parent` is the directory in which the node is to be linked, if any. The filesystem may use
+	/// it to place the node close to its parent.
+	fn create_node(
+		&self,
+		fs: &Arc<Filesystem>,
+		parent: Option<&Node>,
+		stat: Stat,
+	) -> EResult<Arc<Node>>;
 
 	/// Removes `node` from the filesystem.
 	///
@@ -433,6 +542,15 @@ pub trait FilesystemOps: Any + Debug {
 	fn sync_fs(&self) -> EResult<()> {
 		Ok(())
 	}
+
+	/// Returns the cache of directory listings of the filesystem, if it uses one.
+	///
+	/// The VFS uses it to invalidate the listing of directories modified locally.
+	///
+	/// The default implementation of this function returns `None`.
+	fn dir_cache(&self) -> Option<&DirCache> {
+		None
+	}
 }
 
 /// Downcasts the given `fs` into `F`.
@@ -581,12 +699,12 @@ pub trait FilesystemType {
 	/// Arguments:
 	/// - `dev` is the mounted device
 	/// - `mountpath` is the path on which the filesystem is mounted
-	/// - `readonly` tells whether the filesystem is mounted in read-only
+	/// - `flags` are the mount flags, including the ones set from mount options
 	fn load_filesystem(
 		&self,
 		dev: Option<Arc<BlkDev>>,
 		mountpath: PathBuf,
-		readonly: bool,
+		flags: u32,
 	) -> EResult<Arc<Filesystem>>;
 }
 
@@ -630,6 +748,7 @@ pub fn register_defaults() -> EResult<()> {
 	register(ext2::Ext2FsType)?;
 	register(tmp::TmpFsType)?;
 	register(proc::ProcFsType)?;
+	register(cgroup::CgroupFsType)?;
 	// TODO sysfs
 	Ok(())
 }
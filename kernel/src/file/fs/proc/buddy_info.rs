@@ -0,0 +1,48 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `buddyinfo` file gives, for each memory zone, the number of free frames of each order in
+//! the buddy allocator.
+
+use crate::{
+	file::{File, fs::FileOps},
+	format_content,
+	memory::{buddy, buddy::ZONES_COUNT, user::UserSlice},
+};
+use core::fmt;
+use utils::errno::EResult;
+
+/// The `buddyinfo` file.
+#[derive(Debug, Default)]
+pub struct BuddyInfo;
+
+impl FileOps for BuddyInfo {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let disp = fmt::from_fn(|f| {
+			for zone in 0..ZONES_COUNT {
+				write!(f, "Node 0, zone {:>8}", buddy::ZONE_NAMES[zone])?;
+				for count in buddy::free_frames_count(zone) {
+					write!(f, " {count:6}")?;
+				}
+				writeln!(f)?;
+			}
+			Ok(())
+		});
+		format_content!(off, buf, "{disp}")
+	}
+}
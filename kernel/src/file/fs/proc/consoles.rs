@@ -0,0 +1,48 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `consoles` file lists the registered consoles.
+//!
+//! Like on Linux, each line contains the name of a console, followed by its flags. Since consoles
+//! are output-only, the `W` flag is always set. The `E` flag tells whether the console is
+//! enabled.
+
+use crate::{
+	console,
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+};
+use core::fmt;
+use utils::{DisplayableStr, errno::EResult};
+
+/// The `consoles` file.
+#[derive(Debug, Default)]
+pub struct Consoles;
+
+impl FileOps for Consoles {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let disp = fmt::from_fn(|f| {
+			console::for_each(|c, enabled| {
+				let flags = if enabled { 'E' } else { ' ' };
+				writeln!(f, "{:<21} -W- ({flags})", DisplayableStr(c.name()))
+			})
+		});
+		format_content!(off, buf, "{disp}")
+	}
+}
@@ -0,0 +1,49 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `devices` file lists the major numbers registered for each type of device.
+
+use crate::{
+	device::{DeviceType, id},
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+};
+use core::fmt;
+use utils::{DisplayableStr, errno::EResult};
+
+/// The `devices` file.
+#[derive(Debug, Default)]
+pub struct Devices;
+
+impl FileOps for Devices {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let disp = fmt::from_fn(|f| {
+			let list = |f: &mut fmt::Formatter, device_type| {
+				id::for_each_major(device_type, |major, name| {
+					writeln!(f, "{major:3} {}", DisplayableStr(name))
+				})
+			};
+			writeln!(f, "Character devices:")?;
+			list(f, DeviceType::Char)?;
+			writeln!(f, "\nBlock devices:")?;
+			list(f, DeviceType::Block)
+		});
+		format_content!(off, buf, "{disp}")
+	}
+}
@@ -0,0 +1,63 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `diskstats` file exposes I/O statistics for each block device.
+
+use crate::{
+	device::BLK_DEVICES,
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+};
+use core::fmt;
+use utils::{
+	DisplayableStr,
+	collections::vec::Vec,
+	errno::{CollectResult, EResult},
+};
+
+/// The `diskstats` file.
+#[derive(Debug, Default)]
+pub struct DiskStats;
+
+impl FileOps for DiskStats {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let CollectResult(devs) = BLK_DEVICES
+			.lock()
+			.iter()
+			.map(|(_, dev)| dev.clone())
+			.collect();
+		let mut devs: Vec<_> = devs?;
+		devs.sort_unstable_by_key(|dev| (dev.id.major, dev.id.minor));
+		let disp = fmt::from_fn(|f| {
+			for dev in &devs {
+				let name = dev.path.file_name().unwrap_or_default();
+				writeln!(
+					f,
+					"{:4} {:7} {} {}",
+					dev.id.major,
+					dev.id.minor,
+					DisplayableStr(name),
+					dev.stats
+				)?;
+			}
+			Ok(())
+		});
+		format_content!(off, buf, "{disp}")
+	}
+}
@@ -0,0 +1,62 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `kallsyms` file lists the kernel's function symbols along with their addresses, allowing
+//! to resolve the samples of `profile`.
+
+use crate::{
+	elf,
+	elf::{STB_LOCAL, STT_FUNC, kernel::get_symbol_name},
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+};
+use core::fmt;
+use utils::{DisplayableStr, errno::EResult};
+
+/// The `kallsyms` file.
+#[derive(Debug, Default)]
+pub struct Kallsyms;
+
+impl FileOps for Kallsyms {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let disp = fmt::from_fn(|f| {
+			let funcs = elf::kernel::symbols().filter(|sym| sym.st_info & 0xf == STT_FUNC);
+			for sym in funcs {
+				let Some(name) = get_symbol_name(&sym).filter(|name| !name.is_empty()) else {
+					continue;
+				};
+				// Like on Linux, local symbols are in lowercase
+				let ty = if sym.st_info >> 4 == STB_LOCAL {
+					't'
+				} else {
+					'T'
+				};
+				writeln!(
+					f,
+					"{addr:0width$x} {ty} {name}",
+					addr = sym.st_value,
+					width = size_of::<usize>() * 2,
+					name = DisplayableStr(name)
+				)?;
+			}
+			Ok(())
+		});
+		format_content!(off, buf, "{disp}")
+	}
+}
@@ -0,0 +1,76 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `stat` file exposes statistics about the activity of the kernel since boot.
+
+use crate::{
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+	process::{
+		rusage::ns_to_ticks,
+		scheduler,
+		scheduler::{stat, stat::CpuStat},
+	},
+	time::clock::{Clock, current_time_sec},
+};
+use core::{fmt, sync::atomic::Ordering::Relaxed};
+use utils::errno::EResult;
+
+/// Writes the line of `stat`, named `name`, with times in clock ticks.
+fn write_cpu(f: &mut fmt::Formatter<'_>, name: impl fmt::Display, stat: &CpuStat) -> fmt::Result {
+	// Interrupt handlers, steal and guest times are not measured
+	writeln!(
+		f,
+		"{name} {user} {nice} {system} {idle} {iowait} 0 {softirq} 0 0 0",
+		user = ns_to_ticks(stat.user.load(Relaxed)),
+		nice = ns_to_ticks(stat.nice.load(Relaxed)),
+		system = ns_to_ticks(stat.system.load(Relaxed)),
+		idle = ns_to_ticks(stat.idle.load(Relaxed)),
+		iowait = ns_to_ticks(stat.iowait.load(Relaxed)),
+		softirq = ns_to_ticks(stat.softirq.load(Relaxed)),
+	)
+}
+
+/// The `stat` file.
+#[derive(Debug, Default)]
+pub struct KernelStat;
+
+impl fmt::Display for KernelStat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let total = stat::total();
+		write_cpu(f, "cpu ", &total)?;
+		for (i, stat) in stat::iter_cores().enumerate() {
+			write_cpu(f, format_args!("cpu{i}"), stat)?;
+		}
+		let btime = current_time_sec(Clock::Realtime) - current_time_sec(Clock::Boottime);
+		let running = scheduler::nr_running();
+		writeln!(f, "intr {}", total.intr.load(Relaxed))?;
+		writeln!(f, "ctxt {}", total.ctxt.load(Relaxed))?;
+		writeln!(f, "btime {btime}")?;
+		writeln!(f, "processes {}", stat::FORKS.load(Relaxed))?;
+		writeln!(f, "procs_running {running}")?;
+		writeln!(f, "procs_blocked {}", stat::nr_iowait())
+	}
+}
+
+impl FileOps for KernelStat {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{self}")
+	}
+}
@@ -22,7 +22,7 @@
 use crate::{
 	file::{File, fs::FileOps},
 	format_content, memory,
-	memory::user::UserSlice,
+	memory::{hugetlb, hugetlb::HUGE_PAGE_SIZE, user::UserSlice},
 };
 use utils::errno::EResult;
 
@@ -33,6 +33,14 @@ pub struct MemInfo;
 impl FileOps for MemInfo {
 	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
 		let mem_info = memory::stats::MEM_INFO.lock().clone();
-		format_content!(off, buf, "{}", mem_info)
+		format_content!(
+			off,
+			buf,
+			"{}HugePages_Total: {}\nHugePages_Free: {}\nHugepagesize: {} kB\n",
+			mem_info,
+			hugetlb::total(),
+			hugetlb::free(),
+			HUGE_PAGE_SIZE / 1024
+		)
 	}
 }
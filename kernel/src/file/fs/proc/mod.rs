@@ -19,12 +19,22 @@
 //! The `procfs` is a virtual filesystem which provides information about
 //! processes.
 
+mod buddy_info;
+mod consoles;
+mod devices;
+mod disk_stats;
+mod kallsyms;
+mod kernel_stat;
 mod mem_info;
+mod net_dir;
 mod proc_dir;
+mod profile;
 mod self_link;
 mod sys_dir;
+mod syscall_stats;
 mod uptime;
 mod version;
+mod vmcore;
 
 use super::{DummyOps, Filesystem, FilesystemOps, FilesystemType, NodeOps};
 use crate::{
@@ -45,24 +55,41 @@ use crate::{
 	process::{Process, pid::Pid, scheduler::SCHEDULER},
 	sync::mutex::Mutex,
 };
-use core::sync::atomic::AtomicBool;
+use buddy_info::BuddyInfo;
+use consoles::Consoles;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use devices::Devices;
+use disk_stats::DiskStats;
+use kallsyms::Kallsyms;
+use kernel_stat::KernelStat;
 use mem_info::MemInfo;
+use net_dir::Arp;
+pub use proc_dir::ns::{IpcNs, MntNs, NetNs, UtsNs};
 use proc_dir::{
-	cmdline::Cmdline, cwd::Cwd, exe::Exe, mounts::Mounts, stat::StatNode, status::Status,
+	cgroup::CgroupNode, cmdline::Cmdline, cwd::Cwd, exe::Exe, fd::FdDir, io::Io,
+	mountinfo::MountInfo, mounts::Mounts, stat::StatNode, status::Status,
+	syscall_stats::SyscallStatsNode,
 };
+use profile::Profile;
 use self_link::SelfNode;
-use sys_dir::OsRelease;
+use sys_dir::{
+	BinfmtRegister, ExecFdAudit, FileMax, FileNr, MmapMinAddr, NrHugepages, NrOpen, OsRelease,
+	RandomizeVaSpace,
+};
+use syscall_stats::SyscallStats;
 use uptime::Uptime;
 use utils::{
 	boxed::Box, collections::path::PathBuf, errno, errno::EResult, format, ptr::arc::Arc,
 };
 use version::Version;
+use vmcore::Vmcore;
 
 /// Returns the user ID and group ID of the process with the given PID.
 ///
-/// If the process does not exist, the function returns `(0, 0)`.
+/// If the process does not exist or is not dumpable, the function returns `(0, 0)`.
 fn get_proc_owner(pid: Pid) -> (Uid, Gid) {
 	Process::get_by_pid(pid)
+		.filter(|proc| proc.dumpable.load(Relaxed))
 		.map(|proc| {
 			let fs = proc.fs.lock();
 			(fs.access_profile.euid, fs.access_profile.egid)
@@ -92,6 +119,46 @@ impl RootDir {
 	/// processes.
 	const STATIC: StaticDir = StaticDir {
 		entries: &[
+			StaticEntry {
+				name: b"buddyinfo",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(BuddyInfo)),
+			},
+			StaticEntry {
+				name: b"consoles",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(Consoles)),
+			},
+			StaticEntry {
+				name: b"devices",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(Devices)),
+			},
+			StaticEntry {
+				name: b"diskstats",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(DiskStats)),
+			},
+			StaticEntry {
+				name: b"kallsyms",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(Kallsyms)),
+			},
 			StaticEntry {
 				name: b"meminfo",
 				stat: |_| Stat {
@@ -108,6 +175,32 @@ impl RootDir {
 				},
 				init: EitherOps::Node(|_| box_node(StaticLink(b"self/mounts"))),
 			},
+			StaticEntry {
+				name: b"net",
+				stat: |_| static_dir_stat(),
+				init: EitherOps::Node(|_| {
+					box_node(StaticDir {
+						entries: &[StaticEntry {
+							name: b"arp",
+							stat: |_| Stat {
+								mode: FileType::Regular.to_mode() | 0o444,
+								..Default::default()
+							},
+							init: EitherOps::File(|_| box_file(Arp)),
+						}],
+						data: (),
+					})
+				}),
+			},
+			StaticEntry {
+				name: b"profile",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o644,
+					size: profile::size(),
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(Profile)),
+			},
 			StaticEntry {
 				name: b"self",
 				stat: |_| Stat {
@@ -116,29 +209,149 @@ impl RootDir {
 				},
 				init: EitherOps::Node(|_| box_node(SelfNode)),
 			},
+			StaticEntry {
+				name: b"stat",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o444,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(KernelStat)),
+			},
 			StaticEntry {
 				name: b"sys",
 				stat: |_| static_dir_stat(),
 				init: EitherOps::Node(|_| {
 					box_node(StaticDir {
-						entries: &[(StaticEntry {
-							name: b"kernel",
-							stat: |_| static_dir_stat(),
-							init: EitherOps::Node(|_| {
-								box_node(StaticDir {
-									entries: &[StaticEntry {
-										name: b"osrelease",
-										stat: |_| static_dir_stat(),
-										init: EitherOps::File(|_| box_file(OsRelease)),
-									}],
-									data: (),
-								})
-							}),
-						})],
+						entries: &[
+							StaticEntry {
+								name: b"fs",
+								stat: |_| static_dir_stat(),
+								init: EitherOps::Node(|_| {
+									box_node(StaticDir {
+										entries: &[
+											StaticEntry {
+												name: b"binfmt_misc",
+												stat: |_| static_dir_stat(),
+												init: EitherOps::Node(|_| {
+													box_node(StaticDir {
+														entries: &[StaticEntry {
+															name: b"register",
+															stat: |_| Stat {
+																mode: FileType::Regular.to_mode()
+																	| 0o200,
+																..Default::default()
+															},
+															init: EitherOps::File(|_| {
+																box_file(BinfmtRegister)
+															}),
+														}],
+														data: (),
+													})
+												}),
+											},
+											StaticEntry {
+												name: b"exec_fd_audit",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(ExecFdAudit)),
+											},
+											StaticEntry {
+												name: b"file-max",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(FileMax)),
+											},
+											StaticEntry {
+												name: b"file-nr",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o444,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(FileNr)),
+											},
+											StaticEntry {
+												name: b"nr_open",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(NrOpen)),
+											},
+										],
+										data: (),
+									})
+								}),
+							},
+							StaticEntry {
+								name: b"kernel",
+								stat: |_| static_dir_stat(),
+								init: EitherOps::Node(|_| {
+									box_node(StaticDir {
+										entries: &[
+											StaticEntry {
+												name: b"osrelease",
+												stat: |_| static_dir_stat(),
+												init: EitherOps::File(|_| box_file(OsRelease)),
+											},
+											StaticEntry {
+												name: b"randomize_va_space",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| {
+													box_file(RandomizeVaSpace)
+												}),
+											},
+										],
+										data: (),
+									})
+								}),
+							},
+							StaticEntry {
+								name: b"vm",
+								stat: |_| static_dir_stat(),
+								init: EitherOps::Node(|_| {
+									box_node(StaticDir {
+										entries: &[
+											StaticEntry {
+												name: b"mmap_min_addr",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(MmapMinAddr)),
+											},
+											StaticEntry {
+												name: b"nr_hugepages",
+												stat: |_| Stat {
+													mode: FileType::Regular.to_mode() | 0o644,
+													..Default::default()
+												},
+												init: EitherOps::File(|_| box_file(NrHugepages)),
+											},
+										],
+										data: (),
+									})
+								}),
+							},
+						],
 						data: (),
 					})
 				}),
 			},
+			StaticEntry {
+				name: b"syscall_stats",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o644,
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(SyscallStats)),
+			},
 			StaticEntry {
 				name: b"uptime",
 				stat: |_| Stat {
@@ -155,6 +368,15 @@ impl RootDir {
 				},
 				init: EitherOps::File(|_| box_file(Version)),
 			},
+			StaticEntry {
+				name: b"vmcore",
+				stat: |_| Stat {
+					mode: FileType::Regular.to_mode() | 0o400,
+					size: vmcore::size(),
+					..Default::default()
+				},
+				init: EitherOps::File(|_| box_file(Vmcore)),
+			},
 		],
 		data: (),
 	};
@@ -188,6 +410,13 @@ impl NodeOps for RootDir {
 
 					node_ops: Box::new(StaticDir {
 						entries: &[
+							StaticEntry {
+								name: b"cgroup",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Regular.to_mode() | 0o444)
+								},
+								init: EitherOps::File(|pid| box_file(CgroupNode(pid))),
+							},
 							StaticEntry {
 								name: b"cmdline",
 								stat: |pid| {
@@ -212,6 +441,27 @@ impl NodeOps for RootDir {
 								stat: |pid| proc_file_stat(pid, FileType::Link.to_mode() | 0o444),
 								init: EitherOps::Node(|pid| box_node(Exe(pid))),
 							},
+							StaticEntry {
+								name: b"fd",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Directory.to_mode() | 0o500)
+								},
+								init: EitherOps::Node(|pid| box_node(FdDir(pid))),
+							},
+							StaticEntry {
+								name: b"io",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Regular.to_mode() | 0o400)
+								},
+								init: EitherOps::File(|pid| box_file(Io(pid))),
+							},
+							StaticEntry {
+								name: b"mountinfo",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Regular.to_mode() | 0o444)
+								},
+								init: EitherOps::File(|pid| box_file(MountInfo(pid))),
+							},
 							StaticEntry {
 								name: b"mounts",
 								stat: |pid| {
@@ -219,6 +469,67 @@ impl NodeOps for RootDir {
 								},
 								init: EitherOps::File(|pid| box_file(Mounts(pid))),
 							},
+							StaticEntry {
+								name: b"ns",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Directory.to_mode() | 0o511)
+								},
+								init: EitherOps::Node(|pid| {
+									box_node(StaticDir {
+										entries: &[
+											StaticEntry {
+												name: b"ipc",
+												stat: |pid| {
+													proc_file_stat(
+														pid,
+														FileType::Regular.to_mode() | 0o444,
+													)
+												},
+												init: EitherOps::File(|pid| {
+													box_file(IpcNs::new(pid))
+												}),
+											},
+											StaticEntry {
+												name: b"mnt",
+												stat: |pid| {
+													proc_file_stat(
+														pid,
+														FileType::Regular.to_mode() | 0o444,
+													)
+												},
+												init: EitherOps::File(|pid| {
+													box_file(MntNs::new(pid))
+												}),
+											},
+											StaticEntry {
+												name: b"net",
+												stat: |pid| {
+													proc_file_stat(
+														pid,
+														FileType::Regular.to_mode() | 0o444,
+													)
+												},
+												init: EitherOps::File(|pid| {
+													box_file(NetNs::new(pid))
+												}),
+											},
+											StaticEntry {
+												name: b"uts",
+												stat: |pid| {
+													proc_file_stat(
+														pid,
+														FileType::Regular.to_mode() | 0o444,
+													)
+												},
+												init: EitherOps::File(|pid| {
+													box_file(UtsNs::new(pid))
+												}),
+											},
+										],
+										data: pid,
+									})
+								}),
+							},
 							StaticEntry {
 								name: b"stat",
 								stat: |pid| {
@@ -233,6 +544,13 @@ impl NodeOps for RootDir {
 								},
 								init: EitherOps::File(|pid| box_file(Status(pid))),
 							},
+							StaticEntry {
+								name: b"syscall_stats",
+								stat: |pid| {
+									proc_file_stat(pid, FileType::Regular.to_mode() | 0o444)
+								},
+								init: EitherOps::File(|pid| box_file(SyscallStatsNode(pid))),
+							},
 						],
 						data: pid,
 					})?,
@@ -240,6 +558,8 @@ impl NodeOps for RootDir {
 
 					lock: Default::default(),
 					mapped: Default::default(),
+					write_access: Default::default(),
+					locks: Default::default(),
 				})
 			})
 			.transpose()?;
@@ -308,6 +628,7 @@ impl FilesystemOps for ProcFS {
 			f_namelen: 0,
 			f_frsize: 0,
 			f_flags: 0,
+			f_spare: [0; 4],
 		})
 	}
 
@@ -324,10 +645,17 @@ impl FilesystemOps for ProcFS {
 
 			lock: Default::default(),
 			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
 		})?)
 	}
 
-	fn create_node(&self, _fs: &Arc<Filesystem>, _stat: Stat) -> EResult<Arc<Node>> {
+	fn create_node(
+		&self,
+		_fs: &Arc<Filesystem>,
+		_parent: Option<&Node>,
+		_stat: Stat,
+	) -> EResult<Arc<Node>> {
 		Err(errno!(EINVAL))
 	}
 
@@ -352,7 +680,7 @@ impl FilesystemType for ProcFsType {
 		&self,
 		_dev: Option<Arc<BlkDev>>,
 		_mountpath: PathBuf,
-		_readonly: bool,
+		_flags: u32,
 	) -> EResult<Arc<Filesystem>> {
 		Ok(Filesystem::new(0, Box::new(ProcFS)?)?)
 	}
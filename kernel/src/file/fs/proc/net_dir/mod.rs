@@ -0,0 +1,47 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `net` directory contains information about the network stack.
+
+use crate::{
+	file::{File, FileType, Stat, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+	net::arp::ArpTable,
+	process::Process,
+};
+use utils::errno::EResult;
+
+/// The `arp` file, listing the entries of the ARP neighbor table of the network namespace of the
+/// reading process.
+#[derive(Debug, Default)]
+pub struct Arp;
+
+impl FileOps for Arp {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o444,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let ns = Process::current().ns.lock().net.clone();
+		format_content!(off, buf, "{}", ArpTable(&ns))
+	}
+}
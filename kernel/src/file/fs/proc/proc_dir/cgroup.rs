@@ -0,0 +1,40 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `cgroup` node gives the path of the control group the process belongs to.
+
+use crate::{
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+	process::{Process, pid::Pid},
+};
+use utils::{errno, errno::EResult};
+
+/// The cgroup node of the proc.
+#[derive(Clone, Debug)]
+pub struct CgroupNode(pub Pid);
+
+impl FileOps for CgroupNode {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
+		let cgroup = proc.cgroup.lock().clone();
+		// Only the unified (v2) hierarchy is supported, which has the ID `0`
+		format_content!(off, buf, "0::{cgroup}\n")
+	}
+}
@@ -34,8 +34,10 @@ pub struct Cwd(pub Pid);
 impl NodeOps for Cwd {
 	fn readlink(&self, _node: &Node, buf: UserSlice<u8>) -> EResult<usize> {
 		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
-		let fs = proc.fs.lock();
-		let cwd = vfs::Entry::get_path(&fs.cwd)?;
+		let cwd = proc.fs.lock().ctx.lock().cwd.clone();
+		// The path is relative to the root directory of the reader
+		let root = Process::current().fs.lock().ctx.lock().chroot.clone();
+		let cwd = vfs::Entry::get_path_in(&cwd, &root)?;
 		format_content!(0, buf, "{cwd}")
 	}
 }
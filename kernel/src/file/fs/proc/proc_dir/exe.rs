@@ -16,7 +16,7 @@
  * Maestro. If not, see <https://www.gnu.org/licenses/>.
  */
 
-//! Implementation of the `exe` node, which is a link to the executable
+//! Implementation of the `exe` node, which is a magic link to the executable
 //! file of the process.
 
 use crate::{
@@ -25,7 +25,7 @@ use crate::{
 	memory::user::UserSlice,
 	process::{Process, pid::Pid},
 };
-use utils::{errno, errno::EResult};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
 
 /// The `exe` node.
 #[derive(Debug)]
@@ -34,12 +34,20 @@ pub struct Exe(pub Pid);
 impl NodeOps for Exe {
 	fn readlink(&self, _node: &Node, buf: UserSlice<u8>) -> EResult<usize> {
 		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
+		// The path is relative to the root directory of the reader
+		let root = Process::current().fs.lock().ctx.lock().chroot.clone();
 		let path = proc
 			.mem_space
 			.as_ref()
-			.map(|mem_space| vfs::Entry::get_path(&mem_space.exe_info.exe))
+			.map(|mem_space| vfs::Entry::get_path_in(&mem_space.exe_info.exe, &root))
 			.transpose()?
 			.unwrap_or_default();
 		format_content!(0, buf, "{path}")
 	}
+
+	fn follow_link(&self, _node: &Node) -> EResult<Option<Arc<vfs::Entry>>> {
+		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
+		let mem_space = proc.mem_space.as_ref().ok_or_else(|| errno!(ENOENT))?;
+		Ok(Some(mem_space.exe_info.exe.clone()))
+	}
 }
@@ -0,0 +1,133 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Implementation of the `fd` directory, which contains a magic link for each open file
+//! descriptor of the process.
+
+use crate::{
+	file::{
+		DirContext, DirEntry, File, FileType,
+		fs::{DummyOps, NodeOps, proc::proc_file_stat},
+		vfs,
+		vfs::node::Node,
+	},
+	format_content,
+	memory::user::UserSlice,
+	process::{Process, pid::Pid},
+	sync::mutex::Mutex,
+};
+use core::sync::atomic::AtomicBool;
+use utils::{boxed::Box, errno, errno::EResult, format, ptr::arc::Arc};
+
+/// Returns the file open with the file descriptor `id` on the process with PID `pid`.
+fn get_file(pid: Pid, id: u32) -> EResult<Arc<File>> {
+	let proc = Process::get_by_pid(pid).ok_or_else(|| errno!(ENOENT))?;
+	let fds = proc.file_descriptors.get().ok_or_else(|| errno!(ENOENT))?;
+	let fds = fds.lock();
+	let fd = fds.get_fd(id as _).map_err(|_| errno!(ENOENT))?;
+	Ok(fd.get_file().clone())
+}
+
+/// The `fd` directory.
+#[derive(Debug)]
+pub struct FdDir(pub Pid);
+
+impl NodeOps for FdDir {
+	fn lookup_entry(&self, dir: &Node, ent: &mut vfs::Entry) -> EResult<()> {
+		let id = core::str::from_utf8(&ent.name)
+			.ok()
+			.and_then(|s| s.parse::<u32>().ok());
+		let Some(id) = id else {
+			return Ok(());
+		};
+		if get_file(self.0, id).is_err() {
+			return Ok(());
+		}
+		ent.node = Some(Arc::new(Node {
+			inode: 0,
+			fs: dir.fs.clone(),
+
+			stat: Mutex::new(proc_file_stat(self.0, FileType::Link.to_mode() | 0o700)),
+			dirty: AtomicBool::new(false),
+
+			node_ops: Box::new(FdLink {
+				pid: self.0,
+				id,
+			})?,
+			file_ops: Box::new(DummyOps)?,
+
+			lock: Default::default(),
+			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
+		})?);
+		Ok(())
+	}
+
+	fn iter_entries(&self, _dir: &Node, ctx: &mut DirContext) -> EResult<()> {
+		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
+		let Some(fds) = proc.file_descriptors.get() else {
+			return Ok(());
+		};
+		let fds = fds.lock();
+		// The offset is the ID following the last listed file descriptor, so that listing is not
+		// disturbed by file descriptors being closed
+		let start = ctx.off;
+		let iter = fds.iter().skip_while(|(id, _)| (*id as u64) < start);
+		for (id, _) in iter {
+			let name = format!("{id}")?;
+			let ent = DirEntry {
+				inode: 0,
+				entry_type: Some(FileType::Link),
+				name: name.as_bytes(),
+			};
+			if !(ctx.write)(&ent)? {
+				break;
+			}
+			ctx.off = id as u64 + 1;
+		}
+		Ok(())
+	}
+}
+
+/// A magic link to the file open with a file descriptor.
+#[derive(Debug)]
+struct FdLink {
+	/// The PID of the process.
+	pid: Pid,
+	/// The ID of the file descriptor.
+	id: u32,
+}
+
+impl NodeOps for FdLink {
+	fn readlink(&self, _node: &Node, buf: UserSlice<u8>) -> EResult<usize> {
+		let file = get_file(self.pid, self.id)?;
+		// The path is relative to the root directory of the reader
+		let root = Process::current().fs.lock().ctx.lock().chroot.clone();
+		let desc = file.describe(&root)?;
+		format_content!(0, buf, "{desc}")
+	}
+
+	fn follow_link(&self, _node: &Node) -> EResult<Option<Arc<vfs::Entry>>> {
+		let file = get_file(self.pid, self.id)?;
+		file.vfs_entry
+			.clone()
+			.map(Some)
+			.ok_or_else(|| errno!(ENOENT))
+	}
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `io` node exposes the I/O statistics of the process.
+
+use crate::{
+	file::{File, fs::FileOps},
+	format_content,
+	memory::user::UserSlice,
+	process::{Process, pid::Pid, rusage::IO_BLOCK_SIZE},
+};
+use core::sync::atomic::Ordering::Relaxed;
+use utils::{errno, errno::EResult};
+
+/// The `io` node of the proc.
+#[derive(Debug)]
+pub struct Io(pub Pid);
+
+impl FileOps for Io {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let proc = Process::get_by_pid(self.0).ok_or_else(|| errno!(ENOENT))?;
+		let rusage = &proc.rusage;
+		format_content!(
+			off,
+			buf,
+			"rchar: {}\nwchar: {}\nsyscr: {}\nsyscw: {}\nread_bytes: {}\nwrite_bytes: {}\n\
+cancelled_write_bytes: 0\n",
+			rusage.rchar.load(Relaxed),
+			rusage.wchar.load(Relaxed),
+			rusage.syscr.load(Relaxed),
+			rusage.syscw.load(Relaxed),
+			rusage.inblock.load(Relaxed) * IO_BLOCK_SIZE,
+			rusage.oublock.load(Relaxed) * IO_BLOCK_SIZE,
+		)
+	}
+}
@@ -24,13 +24,19 @@ use crate::{
 };
 use utils::{collections::vec::Vec, errno::AllocResult, ptr::arc::Arc, vec};
 
+pub mod cgroup;
 pub mod cmdline;
 pub mod cwd;
 pub mod environ;
 pub mod exe;
+pub mod fd;
+pub mod io;
+pub mod mountinfo;
 pub mod mounts;
+pub mod ns;
 pub mod stat;
 pub mod status;
+pub mod syscall_stats;
 
 /// Reads a range of memory from `mem_space` and writes it to `f`.
 ///
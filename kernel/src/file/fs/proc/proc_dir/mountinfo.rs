@@ -0,0 +1,95 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Implementation of the `mountinfo` node, which describes the tree of mountpoints.
+
+use crate::{
+	device::id,
+	file::{
+		File,
+		fs::FileOps,
+		vfs,
+		vfs::mountpoint::{DisplayOptions, PER_MOUNT_FLAGS},
+	},
+	format_content,
+	memory::user::UserSlice,
+	process::{Process, pid::Pid},
+};
+use core::{fmt, fmt::Formatter, iter};
+use utils::{DisplayableStr, errno::EResult};
+
+/// The `mountinfo` node.
+#[derive(Debug)]
+pub struct MountInfo(pub Pid);
+
+impl FileOps for MountInfo {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{self}")
+	}
+}
+
+impl fmt::Display for MountInfo {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let Some(proc) = Process::get_by_pid(self.0) else {
+			return Ok(());
+		};
+		let (root, ns) = {
+			let fs = proc.fs.lock();
+			let ctx = fs.ctx.lock();
+			(ctx.chroot.clone(), ctx.mnt_ns.clone())
+		};
+		let mps = ns.mount_points.lock();
+		// List in order of creation, so that parents come before their children
+		let mut last_id = 0;
+		while let Some(mp) = mps
+			.iter()
+			.map(|(_, mp)| mp)
+			.filter(|mp| mp.id > last_id)
+			.min_by_key(|mp| mp.id)
+		{
+			last_id = mp.id;
+			// Mountpoints outside of the process's root directory are not visible
+			if !mp.root_entry.is_in(&root) {
+				continue;
+			}
+			let Ok(target) = vfs::Entry::get_path_in(&mp.root_entry, &root) else {
+				continue;
+			};
+			// The root mountpoint is its own parent
+			let parent_id =
+				iter::successors(mp.root_entry.parent.as_deref(), |e| e.parent.as_deref())
+					.find_map(|e| mps.get(&(e as *const _)))
+					.map(|parent| parent.id)
+					.unwrap_or(mp.id);
+			// Propagation is not supported, so there is no optional field
+			writeln!(
+				f,
+				"{id} {parent_id} {major}:{minor} / {target} {mount_opts} - {fs_type} {source} \
+				 {super_opts}",
+				id = mp.id,
+				major = id::major(mp.fs.dev),
+				minor = id::minor(mp.fs.dev),
+				mount_opts = DisplayOptions(mp.flags & PER_MOUNT_FLAGS),
+				fs_type = DisplayableStr(mp.fs.ops.get_name()),
+				source = mp.source,
+				super_opts = DisplayOptions(mp.get_flags() & !PER_MOUNT_FLAGS),
+			)?;
+		}
+		Ok(())
+	}
+}
@@ -22,7 +22,7 @@ use crate::{
 	file::{File, fs::FileOps, vfs, vfs::mountpoint},
 	format_content,
 	memory::user::UserSlice,
-	process::pid::Pid,
+	process::{Process, pid::Pid},
 };
 use core::{fmt, fmt::Formatter};
 use utils::{DisplayableStr, errno::EResult};
@@ -39,19 +39,31 @@ impl FileOps for Mounts {
 
 impl fmt::Display for Mounts {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-		let mps = mountpoint::MOUNT_POINTS.lock();
+		let Some(proc) = Process::get_by_pid(self.0) else {
+			return Ok(());
+		};
+		let (root, ns) = {
+			let fs = proc.fs.lock();
+			let ctx = fs.ctx.lock();
+			(ctx.chroot.clone(), ctx.mnt_ns.clone())
+		};
+		let mps = ns.mount_points.lock();
 		for (_, mp) in mps.iter() {
-			let Ok(target) = vfs::Entry::get_path(&mp.root_entry) else {
+			// Mountpoints outside of the process's root directory are not visible
+			if !mp.root_entry.is_in(&root) {
+				continue;
+			}
+			let Ok(target) = vfs::Entry::get_path_in(&mp.root_entry, &root) else {
 				continue;
 			};
 			let fs_type = mp.fs.ops.get_name();
-			let flags = "TODO"; // TODO
 			writeln!(
 				f,
 				"{source} {target} {fs_type} {flags} 0 0",
 				source = mp.source,
 				target = target,
-				fs_type = DisplayableStr(fs_type)
+				fs_type = DisplayableStr(fs_type),
+				flags = mountpoint::DisplayOptions(mp.get_flags())
 			)?;
 		}
 		Ok(())
@@ -0,0 +1,103 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Implementation of the `ns` directory, which contains handles to the namespaces of the
+//! process.
+
+use crate::{
+	file::{
+		fs::FileOps,
+		vfs::{mountpoint, mountpoint::MountNamespace},
+	},
+	net::NetNamespace,
+	process::{
+		Process,
+		ns::{IpcNamespace, UtsNamespace},
+		pid::Pid,
+	},
+};
+use utils::ptr::arc::Arc;
+
+/// The `mnt` node, referring to the mount namespace of the process.
+///
+/// The namespace is the one of the process at the time the node is looked up. An open file
+/// description of this node can be passed to `setns`.
+#[derive(Debug)]
+pub struct MntNs(pub Option<Arc<MountNamespace>>);
+
+impl MntNs {
+	/// Creates a handle to the mount namespace of the process with PID `pid`.
+	pub fn new(pid: Pid) -> Self {
+		Self(Process::get_by_pid(pid).map(|proc| proc.fs.lock().ctx.lock().mnt_ns.clone()))
+	}
+}
+
+impl FileOps for MntNs {}
+
+impl Drop for MntNs {
+	fn drop(&mut self) {
+		if let Some(ns) = &self.0 {
+			mountpoint::put_namespace(ns);
+		}
+	}
+}
+
+/// The `uts` node, referring to the UTS namespace of the process.
+///
+/// Like [`MntNs`], the namespace is the one at the time the node is looked up.
+#[derive(Debug)]
+pub struct UtsNs(pub Option<Arc<UtsNamespace>>);
+
+impl UtsNs {
+	/// Creates a handle to the UTS namespace of the process with PID `pid`.
+	pub fn new(pid: Pid) -> Self {
+		Self(Process::get_by_pid(pid).map(|proc| proc.ns.lock().uts.clone()))
+	}
+}
+
+impl FileOps for UtsNs {}
+
+/// The `ipc` node, referring to the IPC namespace of the process.
+///
+/// Like [`MntNs`], the namespace is the one at the time the node is looked up.
+#[derive(Debug)]
+pub struct IpcNs(pub Option<Arc<IpcNamespace>>);
+
+impl IpcNs {
+	/// Creates a handle to the IPC namespace of the process with PID `pid`.
+	pub fn new(pid: Pid) -> Self {
+		Self(Process::get_by_pid(pid).map(|proc| proc.ns.lock().ipc.clone()))
+	}
+}
+
+impl FileOps for IpcNs {}
+
+/// The `net` node, referring to the network namespace of the process.
+///
+/// Like [`MntNs`], the namespace is the one at the time the node is looked up.
+#[derive(Debug)]
+pub struct NetNs(pub Option<Arc<NetNamespace>>);
+
+impl NetNs {
+	/// Creates a handle to the network namespace of the process with PID `pid`.
+	pub fn new(pid: Pid) -> Self {
+		Self(Process::get_by_pid(pid).map(|proc| proc.ns.lock().net.clone()))
+	}
+}
+
+impl FileOps for NetNs {}
@@ -23,7 +23,8 @@ use crate::{
 	file::{File, fs::FileOps},
 	format_content,
 	memory::{VirtAddr, user::UserSlice},
-	process::{Process, pid::Pid},
+	process::{Process, kthread, pid::Pid, rusage::ns_to_ticks},
+	time::unit::TimeUnit,
 };
 use core::fmt;
 use utils::{DisplayableStr, errno, errno::EResult};
@@ -41,12 +42,19 @@ impl FileOps for StatNode {
 				.as_ref()
 				.map(|m| (m.exe_info.exe.name.as_bytes(), m.get_vmem_usage()))
 				.unwrap_or_default();
+			let name = match kthread::get(self.0) {
+				Some(thread) => thread.name.as_bytes(),
+				None => name,
+			};
 			let user_regs = proc.user_regs();
+			let rusage = proc.get_rusage();
+			let children = proc.children_rusage.lock().clone();
 			// TODO Fill every fields with process's data
 			write!(
 				f,
 				"{pid} ({name}) {state_char} {ppid} {pgid} {sid} TODO TODO 0 \
-0 0 0 0 {user_jiffies} {kernel_jiffies} TODO TODO {priority} {nice} {num_threads} 0 {vmem_usage} \
+{minflt} {cminflt} {majflt} {cmajflt} {user_jiffies} {kernel_jiffies} {cuser_jiffies} \
+{ckernel_jiffies} {priority} {nice} {num_threads} 0 {vmem_usage} \
 TODO TODO TODO TODO {sp:?} {pc:?} TODO TODO TODO TODO 0 0 0 TODO TODO TODO TODO TODO TODO TODO TODO \
 TODO TODO TODO TODO TODO TODO TODO TODO TODO",
 				pid = self.0,
@@ -54,12 +62,18 @@ TODO TODO TODO TODO TODO TODO TODO TODO TODO",
 				state_char = proc.get_state().as_char(),
 				ppid = proc.get_parent_pid(),
 				pgid = proc.get_pgid(),
-				sid = 0,            // TODO
-				user_jiffies = 0,   // TODO
-				kernel_jiffies = 0, // TODO
-				priority = 0,       // TODO
-				nice = 0,           // TODO
-				num_threads = 1,    // TODO
+				sid = 0, // TODO
+				minflt = rusage.ru_minflt,
+				cminflt = children.ru_minflt,
+				majflt = rusage.ru_majflt,
+				cmajflt = children.ru_majflt,
+				user_jiffies = ns_to_ticks(rusage.ru_utime.to_nano()),
+				kernel_jiffies = ns_to_ticks(rusage.ru_stime.to_nano()),
+				cuser_jiffies = ns_to_ticks(children.ru_utime.to_nano()),
+				ckernel_jiffies = ns_to_ticks(children.ru_stime.to_nano()),
+				priority = 0,    // TODO
+				nice = 0,        // TODO
+				num_threads = 1, // TODO
 				sp = VirtAddr(user_regs.get_stack_address() as _),
 				pc = VirtAddr(user_regs.get_program_counter() as _),
 			)
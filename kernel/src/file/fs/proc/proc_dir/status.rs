@@ -20,13 +20,13 @@
 //! status of the process.
 
 use crate::{
-	file::{File, fs::FileOps},
+	file::{File, fs::FileOps, perm::CAP_FULL_SET},
 	format_content,
 	memory::user::UserSlice,
-	process::{Process, pid::Pid},
+	process::{Process, kthread, pid::Pid},
 };
-use core::fmt;
-use utils::{DisplayableStr, errno, errno::EResult};
+use core::{fmt, sync::atomic::Ordering::Relaxed};
+use utils::{DisplayableStr, errno, errno::EResult, limits::PAGE_SIZE};
 
 /// The `status` node of the proc.
 #[derive(Debug)]
@@ -40,9 +40,16 @@ impl FileOps for Status {
 				.mem_space
 				.as_ref()
 				.map(|m| m.exe_info.exe.name.as_bytes())
+				.or_else(|| kthread::get(self.0).map(|t| t.name.as_bytes()))
+				.unwrap_or_default();
+			let (rss, max_rss) = proc
+				.mem_space
+				.as_ref()
+				.map(|m| (m.get_rss(), m.get_max_rss()))
 				.unwrap_or_default();
 			let state = proc.get_state();
 			let fs = proc.fs.lock();
+			let seccomp = proc.seccomp.lock().clone();
 			// TODO Fill every fields with process's data
 			writeln!(
 				f,
@@ -66,8 +73,8 @@ VmPeak: TODO kB
 VmSize: TODO kB
 VmLck: TODO kB
 VmPin: TODO kB
-VmHWM: TODO kB
-VmRSS: TODO kB
+VmHWM: {max_rss} kB
+VmRSS: {rss} kB
 RssAnon: TODO kB
 RssFile: TODO kB
 RssShmem: TODO kB
@@ -87,22 +94,22 @@ ShdPnd: 0000000000000000
 SigBlk: 0000000000000000
 SigIgn: 0000000000000000
 SigCgt: 0000000000000000
-CapInh: 0000000000000000
-CapPrm: 0000000000000000
-CapEff: 0000000000000000
-CapBnd: 000001ffffffffff
+CapInh: {cap_inh:016x}
+CapPrm: {cap_prm:016x}
+CapEff: {cap_eff:016x}
+CapBnd: {cap_bnd:016x}
 CapAmb: 0000000000000000
-NoNewPrivs: 0
-Seccomp: 0
-Seccomp_filters: 0
+NoNewPrivs: {no_new_privs}
+Seccomp: {seccomp}
+Seccomp_filters: {seccomp_filters}
 Speculation_Store_Bypass: thread vulnerable
 SpeculationIndirectBranch: conditional enabled
 Cpus_allowed: ff
 Cpus_allowed_list: 0-7
 Mems_allowed: 00000001
 Mems_allowed_list: 0
-voluntary_ctxt_switches: 0
-nonvoluntary_ctxt_switches: 0",
+voluntary_ctxt_switches: {nvcsw}
+nonvoluntary_ctxt_switches: {nivcsw}",
 				name = DisplayableStr(name),
 				umask = fs.umask(),
 				state_char = state.as_char(),
@@ -117,6 +124,17 @@ nonvoluntary_ctxt_switches: 0",
 				egid = fs.access_profile.egid,
 				sgid = fs.access_profile.sgid,
 				rgid = fs.access_profile.gid,
+				cap_inh = fs.access_profile.cap_inheritable,
+				cap_prm = fs.access_profile.cap_permitted,
+				cap_eff = fs.access_profile.cap_effective,
+				cap_bnd = CAP_FULL_SET,
+				no_new_privs = proc.no_new_privs.load(Relaxed) as u8,
+				seccomp = seccomp.mode(),
+				seccomp_filters = seccomp.filters_count(),
+				max_rss = max_rss * PAGE_SIZE / 1024,
+				rss = rss * PAGE_SIZE / 1024,
+				nvcsw = proc.rusage.nvcsw.load(Relaxed),
+				nivcsw = proc.rusage.nivcsw.load(Relaxed),
 			)
 		});
 		format_content!(off, buf, "{disp}")
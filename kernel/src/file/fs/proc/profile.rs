@@ -0,0 +1,75 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `profile` file exposes the histogram of the kernel's sampling profiler.
+//!
+//! Writing to the file resets the counters.
+
+use crate::{
+	file::{File, fs::FileOps},
+	memory::user::UserSlice,
+	profile,
+};
+use core::sync::atomic::Ordering::Relaxed;
+use utils::{errno, errno::EResult};
+
+/// The size of a word in the file.
+const WORD_SIZE: usize = size_of::<u32>();
+
+/// Returns the size of the file's content, in bytes.
+pub fn size() -> u64 {
+	profile::get()
+		.map(|profile| ((profile.buckets().len() + 1) * WORD_SIZE) as _)
+		.unwrap_or(0)
+}
+
+/// The `profile` file.
+#[derive(Debug, Default)]
+pub struct Profile;
+
+impl FileOps for Profile {
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let Some(profile) = profile::get() else {
+			return Ok(0);
+		};
+		let mut off: usize = off.try_into().map_err(|_| errno!(EOVERFLOW))?;
+		let mut len = 0;
+		while len < buf.len() {
+			// The first word is the shift, followed by the counters
+			let i = off / WORD_SIZE;
+			let word = match i {
+				0 => profile.shift(),
+				_ => match profile.buckets().get(i - 1) {
+					Some(b) => b.load(Relaxed),
+					None => break,
+				},
+			};
+			let bytes = word.to_ne_bytes();
+			let l = buf.copy_to_user(len, &bytes[(off % WORD_SIZE)..])?;
+			len += l;
+			off += l;
+		}
+		Ok(len)
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let profile = profile::get().ok_or_else(|| errno!(EINVAL))?;
+		profile.reset();
+		Ok(buf.len())
+	}
+}
@@ -19,11 +19,30 @@
 //! TODO doc
 
 use crate::{
-	file::{File, FileType, Stat, fs::FileOps},
+	file::{
+		FILE_MAX, File, FileType, Stat, fd::NR_OPEN, fs::FileOps, open_files_count,
+		vfs::ResolutionSettings,
+	},
 	format_content,
-	memory::user::UserSlice,
+	memory::{hugetlb, user::UserSlice},
+	process::{
+		Process,
+		exec::{EXEC_FD_AUDIT, misc},
+		mem_space::{MMAP_MIN_ADDR, RANDOMIZE_VA_SPACE},
+	},
 };
-use utils::errno::EResult;
+use core::{str, sync::atomic::Ordering::Relaxed};
+use utils::{errno, errno::EResult, limits::PAGE_SIZE};
+
+/// Parses the unsigned integer written by userspace to a tunable file.
+fn parse_uint<T: str::FromStr>(buf: UserSlice<u8>) -> EResult<T> {
+	let mut data = [0u8; 32];
+	let len = buf.copy_from_user(0, &mut data)?;
+	str::from_utf8(&data[..len])
+		.ok()
+		.and_then(|s| s.trim().parse().ok())
+		.ok_or_else(|| errno!(EINVAL))
+}
 
 /// The `osrelease` file.
 #[derive(Debug, Default)]
@@ -41,3 +60,204 @@ impl FileOps for OsRelease {
 		format_content!(off, buf, "{}\n", crate::VERSION)
 	}
 }
+
+/// The `kernel/randomize_va_space` file, setting the address space layout randomization policy.
+#[derive(Debug, Default)]
+pub struct RandomizeVaSpace;
+
+impl FileOps for RandomizeVaSpace {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", RANDOMIZE_VA_SPACE.load(Relaxed))
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let val: u8 = parse_uint(buf)?;
+		if val > 2 {
+			return Err(errno!(EINVAL));
+		}
+		RANDOMIZE_VA_SPACE.store(val, Relaxed);
+		Ok(buf.len())
+	}
+}
+
+/// The `fs/exec_fd_audit` file, enabling the logging of file descriptors inherited on execution.
+#[derive(Debug, Default)]
+pub struct ExecFdAudit;
+
+impl FileOps for ExecFdAudit {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", EXEC_FD_AUDIT.load(Relaxed) as u8)
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let val: u8 = parse_uint(buf)?;
+		if val > 1 {
+			return Err(errno!(EINVAL));
+		}
+		EXEC_FD_AUDIT.store(val != 0, Relaxed);
+		Ok(buf.len())
+	}
+}
+
+/// The `fs/file-nr` file, giving the number of open file descriptions.
+#[derive(Debug, Default)]
+pub struct FileNr;
+
+impl FileOps for FileNr {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o444,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		// The second field is always zero since Linux 2.6
+		format_content!(
+			off,
+			buf,
+			"{}\t0\t{}\n",
+			open_files_count(),
+			FILE_MAX.load(Relaxed)
+		)
+	}
+}
+
+/// The `fs/file-max` file, setting the maximum number of open file descriptions on the system.
+#[derive(Debug, Default)]
+pub struct FileMax;
+
+impl FileOps for FileMax {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", FILE_MAX.load(Relaxed))
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		FILE_MAX.store(parse_uint(buf)?, Relaxed);
+		Ok(buf.len())
+	}
+}
+
+/// The `fs/nr_open` file, setting the maximum number of file descriptors of a process.
+#[derive(Debug, Default)]
+pub struct NrOpen;
+
+impl FileOps for NrOpen {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", NR_OPEN.load(Relaxed))
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let val: u32 = parse_uint(buf)?;
+		// Cannot go below the size of the default table
+		if val < 64 {
+			return Err(errno!(EINVAL));
+		}
+		NR_OPEN.store(val, Relaxed);
+		Ok(buf.len())
+	}
+}
+
+/// The `fs/binfmt_misc/register` file, registering an executable format described by the
+/// written string.
+#[derive(Debug, Default)]
+pub struct BinfmtRegister;
+
+impl FileOps for BinfmtRegister {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o200,
+			..Default::default()
+		})
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		// Limit taken from Linux
+		if buf.len() > 1920 {
+			return Err(errno!(EINVAL));
+		}
+		let desc = buf.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
+		let rs = ResolutionSettings::for_process(&Process::current(), true);
+		misc::register(&desc, &rs)?;
+		Ok(buf.len())
+	}
+}
+
+/// The `vm/nr_hugepages` file, setting the number of huge pages reserved in the pool.
+#[derive(Debug, Default)]
+pub struct NrHugepages;
+
+impl FileOps for NrHugepages {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", hugetlb::total())
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		hugetlb::resize(parse_uint(buf)?)?;
+		Ok(buf.len())
+	}
+}
+
+/// The `vm/mmap_min_addr` file, setting the lowest address at which unprivileged processes can
+/// map memory.
+#[derive(Debug, Default)]
+pub struct MmapMinAddr;
+
+impl FileOps for MmapMinAddr {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | 0o644,
+			..Default::default()
+		})
+	}
+
+	fn read(&self, _file: &File, off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		format_content!(off, buf, "{}\n", MMAP_MIN_ADDR.load(Relaxed))
+	}
+
+	fn write(&self, _file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let val: usize = parse_uint(buf)?;
+		// The first page is never mappable anyway
+		let val = val
+			.max(PAGE_SIZE)
+			.checked_next_multiple_of(PAGE_SIZE)
+			.ok_or_else(|| errno!(EINVAL))?;
+		MMAP_MIN_ADDR.store(val, Relaxed);
+		Ok(buf.len())
+	}
+}
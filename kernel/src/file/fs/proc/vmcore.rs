@@ -0,0 +1,55 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `vmcore` file exposes the memory of a crashed kernel, as an ELF core file.
+//!
+//! The file is empty if the kernel is not a crash kernel.
+
+use crate::{
+	file::{File, fs::FileOps},
+	memory::{user::UserSlice, vmcore},
+};
+use utils::errno::EResult;
+
+/// Returns the size of the file's content, in bytes.
+pub fn size() -> u64 {
+	vmcore::get().map(|vmcore| vmcore.size()).unwrap_or(0)
+}
+
+/// The `vmcore` file.
+#[derive(Debug, Default)]
+pub struct Vmcore;
+
+impl FileOps for Vmcore {
+	fn read(&self, _file: &File, mut off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let Some(vmcore) = vmcore::get() else {
+			return Ok(0);
+		};
+		let mut len = 0;
+		while len < buf.len() {
+			let content = vmcore.content_at(off);
+			if content.is_empty() {
+				break;
+			}
+			let l = buf.copy_to_user(len, content)?;
+			len += l;
+			off += l as u64;
+		}
+		Ok(len)
+	}
+}
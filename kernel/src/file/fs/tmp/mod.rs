@@ -23,6 +23,21 @@
 
 // TODO count memory usage to enforce quota
 
+/// Seal: further calls to `fcntl` with `F_ADD_SEALS` fail.
+pub const F_SEAL_SEAL: u32 = 1;
+/// Seal: the size of the file cannot be reduced.
+pub const F_SEAL_SHRINK: u32 = 2;
+/// Seal: the size of the file cannot be increased.
+pub const F_SEAL_GROW: u32 = 4;
+/// Seal: the content of the file cannot be modified.
+pub const F_SEAL_WRITE: u32 = 8;
+/// Seal: the content of the file cannot be modified through new writes or writable shared
+/// mappings, while existing shared mappings remain writable.
+pub const F_SEAL_FUTURE_WRITE: u32 = 16;
+/// All the seals.
+const SEALS_ALL: u32 =
+	F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_FUTURE_WRITE;
+
 use crate::{
 	device::BlkDev,
 	file::{
@@ -33,7 +48,7 @@ use crate::{
 		},
 		perm::{ROOT_GID, ROOT_UID},
 		vfs,
-		vfs::node::Node,
+		vfs::{mountpoint, node::Node},
 	},
 	memory::{
 		cache::{FrameOwner, RcFrame},
@@ -41,11 +56,18 @@ use crate::{
 	},
 	sync::mutex::Mutex,
 };
-use core::{any::Any, hint::unlikely, sync::atomic::AtomicBool};
+use core::{
+	any::Any,
+	hint::unlikely,
+	sync::atomic::{
+		AtomicBool, AtomicU32,
+		Ordering::{AcqRel, Acquire, Release},
+	},
+};
 use utils::{
 	TryClone, TryToOwned,
 	boxed::Box,
-	collections::{path::PathBuf, vec::Vec},
+	collections::{path::PathBuf, string::String, vec::Vec},
 	errno,
 	errno::{AllocResult, EResult},
 	limits::{NAME_MAX, PAGE_SIZE},
@@ -130,7 +152,15 @@ impl DirInner {
 #[derive(Debug)]
 enum NodeContent {
 	/// Regular file content
-	Regular(Mutex<Vec<RcFrame>>),
+	Regular {
+		/// The file's pages.
+		pages: Mutex<Vec<RcFrame>>,
+		/// The file's seals (`F_SEAL_*`).
+		///
+		/// Regular files are created with [`F_SEAL_SEAL`], so that only anonymous files can be
+		/// sealed.
+		seals: AtomicU32,
+	},
 	/// Directory entries
 	Directory(Mutex<DirInner>),
 	// TODO we could avoid having a mutex here since the path is set only when the link is
@@ -316,7 +346,10 @@ impl NodeOps for NodeContent {
 
 	fn read_page(&self, _node: &Arc<Node>, off: u64) -> EResult<RcFrame> {
 		let i: usize = off.try_into().map_err(|_| errno!(EOVERFLOW))?;
-		let NodeContent::Regular(pages) = self else {
+		let NodeContent::Regular {
+			pages, ..
+		} = self
+		else {
 			return Err(errno!(EINVAL));
 		};
 		pages.lock().get(i).cloned().ok_or_else(|| errno!(EINVAL))
@@ -342,16 +375,36 @@ impl FileOps for TmpFSFile {
 		if unlikely(fs.readonly) {
 			return Err(errno!(EROFS));
 		}
+		let seals = get_seals(node).unwrap_or(0);
+		if unlikely(seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0) {
+			return Err(errno!(EPERM));
+		}
+		let end = off.saturating_add(buf.len() as _);
+		if unlikely(seals & F_SEAL_GROW != 0 && end > node.stat.lock().size) {
+			return Err(errno!(EPERM));
+		}
 		generic_file_write(file, off, buf)
 	}
 
 	fn truncate(&self, file: &File, size: u64) -> EResult<()> {
 		let node = file.node().unwrap();
-		let pages = NodeContent::from_ops(&*node.node_ops);
-		let NodeContent::Regular(pages) = pages else {
+		let content = NodeContent::from_ops(&*node.node_ops);
+		let NodeContent::Regular {
+			pages,
+			seals,
+		} = content
+		else {
 			return Err(errno!(EINVAL));
 		};
 		// Validation
+		let seals = seals.load(Acquire);
+		let cur_size = node.stat.lock().size;
+		if unlikely(seals & F_SEAL_SHRINK != 0 && size < cur_size) {
+			return Err(errno!(EPERM));
+		}
+		if unlikely(seals & F_SEAL_GROW != 0 && size > cur_size) {
+			return Err(errno!(EPERM));
+		}
 		let size: usize = size.try_into().map_err(|_| errno!(EOVERFLOW))?;
 		let new_pages_count = size.div_ceil(PAGE_SIZE);
 		let mut pages = pages.lock();
@@ -413,6 +466,7 @@ impl FilesystemOps for TmpFS {
 			f_namelen: NAME_MAX as _,
 			f_frsize: 0,
 			f_flags: 0,
+			f_spare: [0; 4],
 		})
 	}
 
@@ -420,14 +474,22 @@ impl FilesystemOps for TmpFS {
 		self.nodes.lock().get_node(kernfs::ROOT_INODE).cloned()
 	}
 
-	fn create_node(&self, fs: &Arc<Filesystem>, stat: Stat) -> EResult<Arc<Node>> {
+	fn create_node(
+		&self,
+		fs: &Arc<Filesystem>,
+		_parent: Option<&Node>,
+		stat: Stat,
+	) -> EResult<Arc<Node>> {
 		if unlikely(self.readonly) {
 			return Err(errno!(EROFS));
 		}
 		// Prepare content
 		let file_type = stat.get_type().ok_or_else(|| errno!(EINVAL))?;
 		let content = match file_type {
-			FileType::Regular => NodeContent::Regular(Default::default()),
+			FileType::Regular => NodeContent::Regular {
+				pages: Default::default(),
+				seals: AtomicU32::new(F_SEAL_SEAL),
+			},
 			FileType::Directory => NodeContent::Directory(Default::default()),
 			FileType::Link => NodeContent::Link(Default::default()),
 			_ => NodeContent::None,
@@ -447,6 +509,8 @@ impl FilesystemOps for TmpFS {
 
 			lock: Default::default(),
 			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
 		})?;
 		*slot = Some(node.clone());
 		Ok(node)
@@ -477,12 +541,12 @@ impl FilesystemType for TmpFsType {
 		&self,
 		_dev: Option<Arc<BlkDev>>,
 		_mountpath: PathBuf,
-		readonly: bool,
+		flags: u32,
 	) -> EResult<Arc<Filesystem>> {
 		let fs = Filesystem::new(
 			0,
 			Box::new(TmpFS {
-				readonly,
+				readonly: flags & mountpoint::FLAG_RDONLY != 0,
 				nodes: Mutex::new(NodeStorage::new()?),
 			})?,
 		)?;
@@ -510,6 +574,8 @@ impl FilesystemType for TmpFsType {
 
 			lock: Default::default(),
 			mapped: Default::default(),
+			write_access: Default::default(),
+			locks: Default::default(),
 		})?;
 		// Insert node
 		downcast_fs::<TmpFS>(&*fs.ops)
@@ -533,3 +599,75 @@ impl FilesystemType for TmpFsType {
 		Ok(fs)
 	}
 }
+
+/// Returns the seals of `node`.
+///
+/// If the node is not a regular file of a tmpfs, the function returns `None`.
+pub fn get_seals(node: &Node) -> Option<u32> {
+	if !(&*node.fs.ops as &dyn Any).is::<TmpFS>() {
+		return None;
+	}
+	match NodeContent::from_ops(&*node.node_ops) {
+		NodeContent::Regular {
+			seals, ..
+		} => Some(seals.load(Acquire)),
+		_ => None,
+	}
+}
+
+/// Adds `new_seals` to the seals of `node`.
+///
+/// Errors:
+/// - The node is not a regular file of a tmpfs, or `new_seals` is invalid: [`errno::EINVAL`]
+/// - The node has the [`F_SEAL_SEAL`] seal: [`errno::EPERM`]
+pub fn add_seals(node: &Node, new_seals: u32) -> EResult<()> {
+	if unlikely(new_seals & !SEALS_ALL != 0) {
+		return Err(errno!(EINVAL));
+	}
+	if !(&*node.fs.ops as &dyn Any).is::<TmpFS>() {
+		return Err(errno!(EINVAL));
+	}
+	let NodeContent::Regular {
+		seals, ..
+	} = NodeContent::from_ops(&*node.node_ops)
+	else {
+		return Err(errno!(EINVAL));
+	};
+	seals
+		.fetch_update(AcqRel, Acquire, |seals| {
+			(seals & F_SEAL_SEAL == 0).then_some(seals | new_seals)
+		})
+		.map_err(|_| errno!(EPERM))?;
+	Ok(())
+}
+
+/// The tmpfs instance holding anonymous files.
+static ANON_FS: Mutex<Option<Arc<Filesystem>>> = Mutex::new(None);
+
+/// Creates an anonymous regular file, which is not linked to any directory and is removed once
+/// closed.
+///
+/// Arguments:
+/// - `name` is the name of the file's entry, for display purposes only
+/// - `stat` is the status of the file
+/// - `seals` are the initial seals of the file
+pub fn create_anonymous(name: String, stat: Stat, seals: u32) -> EResult<Arc<vfs::Entry>> {
+	let fs = {
+		let mut anon_fs = ANON_FS.lock();
+		match &*anon_fs {
+			Some(fs) => fs.clone(),
+			None => {
+				let fs = TmpFsType.load_filesystem(None, PathBuf::root()?, 0)?;
+				anon_fs.insert(fs).clone()
+			}
+		}
+	};
+	let node = fs.ops.create_node(&fs, None, stat)?;
+	if let NodeContent::Regular {
+		seals: s, ..
+	} = NodeContent::from_ops(&*node.node_ops)
+	{
+		s.store(seals, Release);
+	}
+	Ok(vfs::Entry::new(name, None, Some(node)).link_parent()?)
+}
@@ -0,0 +1,366 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Advisory record locks, placed on byte ranges of a file with `fcntl`.
+//!
+//! A lock is owned either by a process (POSIX locks), or by an open file description (OFD
+//! locks). Locks of the same owner never conflict
+//! with each other: a new lock replaces the previous locks of its owner on its range, and is
+//! merged with the adjacent ones of the same type.
+//!
+//! The locks of a file are stored in an interval tree, so that conflicts are found without
+//! scanning all the locks of the file.
+
+use crate::{file::wait_queue::WaitQueue, process::pid::Pid, sync::mutex::Mutex};
+use core::{
+	cmp::{max, min},
+	ffi::{c_int, c_short},
+	ops::Range,
+};
+use utils::{
+	collections::{interval_tree::IntervalTree, vec::Vec},
+	errno,
+	errno::{AllocResult, CollectResult, EResult},
+};
+
+/// The `flock` structure, describing a lock on a byte range of a file.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(missing_docs)]
+pub struct Flock {
+	pub l_type: c_short,
+	pub l_whence: c_short,
+	pub l_start: i64,
+	pub l_len: i64,
+	pub l_pid: c_int,
+}
+
+/// Compatibility version of [`Flock`], with 32 bit offsets.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(missing_docs)]
+pub struct CompatFlock {
+	pub l_type: c_short,
+	pub l_whence: c_short,
+	pub l_start: i32,
+	pub l_len: i32,
+	pub l_pid: c_int,
+}
+
+impl From<Flock> for CompatFlock {
+	fn from(fl: Flock) -> Self {
+		Self {
+			l_type: fl.l_type,
+			l_whence: fl.l_whence,
+			l_start: fl.l_start as _,
+			l_len: fl.l_len as _,
+			l_pid: fl.l_pid,
+		}
+	}
+}
+
+impl From<CompatFlock> for Flock {
+	fn from(fl: CompatFlock) -> Self {
+		Self {
+			l_type: fl.l_type,
+			l_whence: fl.l_whence,
+			l_start: fl.l_start as _,
+			l_len: fl.l_len as _,
+			l_pid: fl.l_pid,
+		}
+	}
+}
+
+/// Compatibility version of [`Flock`], with 64 bit offsets aligned on 4 bytes, used by the `*64`
+/// and OFD commands.
+#[repr(C, packed(4))]
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(missing_docs)]
+pub struct CompatFlock64 {
+	pub l_type: c_short,
+	pub l_whence: c_short,
+	pub l_start: i64,
+	pub l_len: i64,
+	pub l_pid: c_int,
+}
+
+impl From<Flock> for CompatFlock64 {
+	fn from(fl: Flock) -> Self {
+		Self {
+			l_type: fl.l_type,
+			l_whence: fl.l_whence,
+			l_start: fl.l_start,
+			l_len: fl.l_len,
+			l_pid: fl.l_pid,
+		}
+	}
+}
+
+impl From<CompatFlock64> for Flock {
+	fn from(fl: CompatFlock64) -> Self {
+		Self {
+			l_type: fl.l_type,
+			l_whence: fl.l_whence,
+			l_start: fl.l_start,
+			l_len: fl.l_len,
+			l_pid: fl.l_pid,
+		}
+	}
+}
+
+/// The type of a lock.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockType {
+	/// Shared lock, allowing other owners to place read locks.
+	Read,
+	/// Exclusive lock.
+	Write,
+}
+
+/// The owner of a lock.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockOwner {
+	/// A process, identified by its PID.
+	Process(Pid),
+	/// An open file description, identified by its address.
+	File(usize),
+}
+
+/// A lock on a byte range of a file.
+#[derive(Clone, Copy, Debug)]
+pub struct Lock {
+	/// The owner of the lock.
+	pub owner: LockOwner,
+	/// The type of the lock.
+	pub lock_type: LockType,
+	/// The PID of the process which placed the lock, reported to other processes.
+	pub pid: Pid,
+}
+
+impl Lock {
+	/// Tells whether the lock conflicts with `other`, placed on an overlapping range.
+	fn conflicts(&self, other: &Self) -> bool {
+		self.owner != other.owner
+			&& (self.lock_type == LockType::Write || other.lock_type == LockType::Write)
+	}
+}
+
+/// Returns the first lock in `locks` conflicting with `lock` on `range`.
+fn find_conflict(
+	locks: &IntervalTree<u64, Lock>,
+	range: Range<u64>,
+	lock: &Lock,
+) -> Option<(Range<u64>, Lock)> {
+	locks
+		.overlapping(range)
+		.find(|(_, l)| l.conflicts(lock))
+		.map(|(r, l)| (r.clone(), *l))
+}
+
+/// Places `lock` on `range` for `owner`, or removes the locks of `owner` on `range` if `None`.
+///
+/// On failure, `locks` is left unchanged.
+fn apply(
+	locks: &mut IntervalTree<u64, Lock>,
+	mut range: Range<u64>,
+	owner: LockOwner,
+	lock: Option<Lock>,
+) -> AllocResult<()> {
+	// The locks of the owner overlapping or adjacent to the range
+	let lo = range.start.saturating_sub(1);
+	let hi = range.end.saturating_add(1);
+	let old = locks
+		.overlapping(lo..hi)
+		.filter(|(_, l)| l.owner == owner)
+		.map(|(r, l)| (r.clone(), *l))
+		.collect::<CollectResult<Vec<_>>>()
+		.0?;
+	let mut remove = Vec::new();
+	let mut insert = Vec::new();
+	for (r, l) in old {
+		if lock.is_some_and(|lock| lock.lock_type == l.lock_type) {
+			// Merge with the new lock
+			range = min(range.start, r.start)..max(range.end, r.end);
+		} else if r.end > range.start && r.start < range.end {
+			// Keep the parts outside of the range
+			if r.start < range.start {
+				insert.push((r.start..range.start, l))?;
+			}
+			if r.end > range.end {
+				insert.push((range.end..r.end, l))?;
+			}
+		} else {
+			// Adjacent lock of another type
+			continue;
+		}
+		remove.push((r, l.lock_type))?;
+	}
+	if let Some(lock) = lock {
+		// If the lock is unchanged, there is nothing to do
+		let i = remove
+			.iter()
+			.position(|(r, t)| *r == range && *t == lock.lock_type);
+		match i {
+			Some(i) => {
+				remove.remove(i);
+			}
+			None => insert.push((range, lock))?,
+		}
+	}
+	// Insert first, since it may fail
+	for (i, (r, l)) in insert.iter().enumerate() {
+		if let Err(e) = locks.insert(r.clone(), *l) {
+			for (r, l) in &insert[..i] {
+				locks.remove_if(&r.start, |r2, l2| {
+					r2 == r && l2.owner == l.owner && l2.lock_type == l.lock_type
+				});
+			}
+			return Err(e);
+		}
+	}
+	for (r, t) in remove {
+		locks.remove_if(&r.start, |r2, l| {
+			*r2 == r && l.owner == owner && l.lock_type == t
+		});
+	}
+	Ok(())
+}
+
+/// The set of record locks placed on a file.
+#[derive(Debug, Default)]
+pub struct FileLocks {
+	/// The locks, by byte range.
+	locks: Mutex<IntervalTree<u64, Lock>>,
+	/// The queue of processes waiting for conflicting locks to be released.
+	queue: WaitQueue,
+}
+
+impl FileLocks {
+	/// Returns the first lock conflicting with `lock` on `range`, with its range.
+	pub fn get_conflict(&self, range: Range<u64>, lock: &Lock) -> Option<(Range<u64>, Lock)> {
+		find_conflict(&self.locks.lock(), range, lock)
+	}
+
+	/// Places `lock` on `range`.
+	///
+	/// If a lock of another owner conflicts, the function waits for it to be released if `wait`
+	/// is set, else it returns [`errno::EAGAIN`].
+	///
+	/// Deadlocks between processes waiting for each other are not detected.
+	pub fn lock(&self, range: Range<u64>, lock: Lock, wait: bool) -> EResult<()> {
+		self.queue.wait_until(|| {
+			let mut locks = self.locks.lock();
+			if find_conflict(&locks, range.clone(), &lock).is_some() {
+				return (!wait).then(|| Err(errno!(EAGAIN)));
+			}
+			Some(apply(&mut locks, range.clone(), lock.owner, Some(lock)).map_err(Into::into))
+		})??;
+		// A write lock may have been converted to a read lock
+		self.queue.wake_all();
+		Ok(())
+	}
+
+	/// Removes the locks of `owner` on `range`.
+	pub fn unlock(&self, range: Range<u64>, owner: LockOwner) -> EResult<()> {
+		apply(&mut self.locks.lock(), range, owner, None)?;
+		self.queue.wake_all();
+		Ok(())
+	}
+
+	/// Removes all the locks of `owner`.
+	pub fn release(&self, owner: LockOwner) {
+		let mut locks = self.locks.lock();
+		let mut released = false;
+		while let Some(start) = locks
+			.iter()
+			.find(|(_, l)| l.owner == owner)
+			.map(|(r, _)| r.start)
+		{
+			locks.remove_if(&start, |_, l| l.owner == owner);
+			released = true;
+		}
+		if released {
+			self.queue.wake_all();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Returns a lock of type `lock_type` for the owner `owner`.
+	fn lock(owner: usize, lock_type: LockType) -> Lock {
+		Lock {
+			owner: LockOwner::File(owner),
+			lock_type,
+			pid: 0,
+		}
+	}
+
+	#[test_case]
+	fn file_lock_conflict() {
+		let locks = FileLocks::default();
+		locks.lock(0..10, lock(0, LockType::Read), false).unwrap();
+		locks.lock(5..20, lock(1, LockType::Read), false).unwrap();
+		assert!(
+			locks
+				.get_conflict(0..100, &lock(2, LockType::Read))
+				.is_none()
+		);
+		let (range, l) = locks
+			.get_conflict(8..100, &lock(2, LockType::Write))
+			.unwrap();
+		assert_eq!(range, 0..10);
+		assert_eq!(l.owner, LockOwner::File(0));
+		assert_eq!(
+			locks
+				.lock(15..16, lock(0, LockType::Write), false)
+				.unwrap_err()
+				.as_int(),
+			errno::EAGAIN
+		);
+		locks.release(LockOwner::File(1));
+		locks.lock(15..16, lock(0, LockType::Write), false).unwrap();
+	}
+
+	#[test_case]
+	fn file_lock_split_merge() {
+		let locks = FileLocks::default();
+		let owner = LockOwner::File(0);
+		locks.lock(0..10, lock(0, LockType::Read), false).unwrap();
+		locks.lock(10..20, lock(0, LockType::Read), false).unwrap();
+		locks.lock(5..8, lock(0, LockType::Write), false).unwrap();
+		locks.unlock(15..u64::MAX, owner).unwrap();
+		let tree = locks.locks.lock();
+		let ranges = tree
+			.iter()
+			.map(|(r, l)| (r.clone(), l.lock_type))
+			.collect::<CollectResult<Vec<_>>>()
+			.0
+			.unwrap();
+		assert_eq!(
+			ranges.as_slice(),
+			&[
+				(0..5, LockType::Read),
+				(5..8, LockType::Write),
+				(8..15, LockType::Read)
+			]
+		);
+	}
+}
@@ -24,8 +24,11 @@
 //! The root filesystem is passed to the kernel as an argument on boot.
 //! Other filesystems are mounted into subdirectories.
 
+pub mod buffer;
+pub mod epoll;
 pub mod fd;
 pub mod fs;
+pub mod lock;
 pub mod perm;
 pub mod pipe;
 pub mod socket;
@@ -37,25 +40,35 @@ use crate::{
 	device::{BLK_DEVICES, BlkDev, BlkDevFileOps, CHAR_DEVICES, DeviceID, DeviceType},
 	file::{
 		fs::FileOps,
-		perm::{Gid, Uid},
+		perm::{CapSet, Gid, Uid},
 		pipe::PipeBuffer,
 		socket::Socket,
 		vfs::node::Node,
 	},
 	memory::user::UserSlice,
 	net::{SocketDesc, SocketDomain, SocketType},
+	process::Process,
 	sync::{atomic::AtomicU64, mutex::Mutex, once::OnceInit},
 	time::{
 		clock::{Clock, current_time_sec},
 		unit::Timestamp,
 	},
 };
-use core::{any::Any, fmt::Debug, ops::Deref, ptr::NonNull};
+use core::{
+	any::Any,
+	fmt::Debug,
+	ops::Deref,
+	ptr,
+	ptr::NonNull,
+	sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+use lock::LockOwner;
 use perm::AccessProfile;
 use utils::{
 	collections::{string::String, vec::Vec},
 	errno,
 	errno::EResult,
+	format,
 	ptr::arc::Arc,
 	vec,
 };
@@ -73,6 +86,16 @@ pub type INode = u64;
 /// - UNIX type (regular, directory, etc...), represented by the remaining bits.
 pub type Mode = u32;
 
+/// The maximum number of open file descriptions on the system (`fs.file-max`).
+pub static FILE_MAX: AtomicUsize = AtomicUsize::new(65536);
+/// The number of open file descriptions on the system.
+static OPEN_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of open file descriptions on the system.
+pub fn open_files_count() -> usize {
+	OPEN_FILES.load(Relaxed)
+}
+
 /// File type: socket
 pub const S_IFSOCK: Mode = 0o140000;
 /// File type: symbolic link
@@ -352,6 +375,21 @@ pub struct File {
 }
 
 impl File {
+	/// Accounts for a new open file description in the system-wide counter.
+	///
+	/// The slot is given back when the [`File`] is dropped, so the structure must be created
+	/// right after calling this function.
+	///
+	/// If the limit set by [`FILE_MAX`] is reached, the function returns [`errno::ENFILE`].
+	fn reserve() -> EResult<()> {
+		OPEN_FILES
+			.fetch_update(Relaxed, Relaxed, |n| {
+				(n < FILE_MAX.load(Relaxed)).then_some(n + 1)
+			})
+			.map_err(|_| errno!(ENFILE))?;
+		Ok(())
+	}
+
 	/// Opens a file from a [`vfs::Entry`].
 	///
 	/// Arguments:
@@ -369,11 +407,15 @@ impl File {
 			}
 			Some(FileType::Socket) => {
 				FileOpsWrapper::Owned(node.fs.buffer_get_or_insert(node.inode, || {
-					Socket::new(SocketDesc {
-						domain: SocketDomain::AfUnix,
-						type_: SocketType::SockStream,
-						protocol: 0,
-					})
+					let net_ns = Process::current().ns.lock().net.clone();
+					Socket::new(
+						SocketDesc {
+							domain: SocketDomain::AfUnix,
+							type_: SocketType::SockStream,
+							protocol: 0,
+						},
+						net_ns,
+					)
 				})?)
 			}
 			Some(FileType::BlockDevice) => FileOpsWrapper::Owned(Arc::new(BlkDevFileOps)?),
@@ -389,6 +431,16 @@ impl File {
 			}
 			_ => FileOpsWrapper::Borrowed(NonNull::from(node.file_ops.as_ref())),
 		};
+		// A file cannot be written to while it is being executed
+		let write = matches!(flags & 0b11, O_WRONLY | O_RDWR);
+		if write {
+			node.get_write_access()?;
+		}
+		Self::reserve().inspect_err(|_| {
+			if write {
+				node.put_write_access();
+			}
+		})?;
 		let file = Self {
 			vfs_entry: Some(entry),
 			ops,
@@ -401,6 +453,7 @@ impl File {
 
 	/// Open a file with no associated VFS entry.
 	pub fn open_floating(ops: Arc<dyn FileOps>, flags: i32) -> EResult<Arc<Self>> {
+		Self::reserve()?;
 		let file = Self {
 			vfs_entry: None,
 			ops: FileOpsWrapper::Owned(ops),
@@ -480,6 +533,23 @@ impl File {
 		FileType::from_mode(stat.mode).ok_or_else(|| errno!(EUCLEAN))
 	}
 
+	/// Returns a description of the file, as shown by the links of `/proc/<pid>/fd`.
+	///
+	/// For files on a filesystem, this is their path, relative to `root`. Other files are
+	/// described by their type.
+	pub fn describe(&self, root: &Arc<vfs::Entry>) -> EResult<String> {
+		if let Some(ent) = &self.vfs_entry {
+			let path = vfs::Entry::get_path_in(ent, root)?;
+			return Ok(format!("{path}")?);
+		}
+		let desc: &[u8] = match self.get_type()? {
+			FileType::Fifo => b"pipe:",
+			FileType::Socket => b"socket:",
+			_ => b"anon_inode:",
+		};
+		Ok(String::try_from(desc)?)
+	}
+
 	/// Reads the content of the file into a buffer.
 	///
 	/// **Caution**: the function reads until EOF, meaning the caller should not call this function
@@ -520,21 +590,61 @@ impl File {
 		Ok(buf)
 	}
 
+	/// Gives back the right to write to the node, acquired when opening the file.
+	fn release_write_access(&self) {
+		if self.can_write()
+			&& let Some(node) = self.vfs_entry.as_ref().and_then(|ent| ent.node.as_ref())
+		{
+			node.put_write_access();
+		}
+	}
+
 	/// Closes the file, removing the underlying node if no link remain and this was the last
 	/// use of it.
-	pub fn close(self) -> EResult<()> {
+	pub fn close(mut self) -> EResult<()> {
 		self.ops.release(&self);
-		if let Some(ent) = self.vfs_entry {
+		self.release_write_access();
+		if let Some(ent) = self.vfs_entry.take() {
 			vfs::Entry::release(ent)?;
 		}
 		Ok(())
 	}
 }
 
+impl Drop for File {
+	fn drop(&mut self) {
+		// If the file has not been closed, it is dropped in place and still owns its locks
+		if let Some(node) = self.node() {
+			node.locks
+				.release(LockOwner::File(ptr::from_ref(self).addr()));
+		}
+		self.release_write_access();
+		OPEN_FILES.fetch_sub(1, Relaxed);
+	}
+}
+
 impl AccessProfile {
-	fn check_read_access_impl(uid: Uid, gid: Gid, stat: &Stat) -> bool {
-		// If root, bypass checks
-		if uid == perm::ROOT_UID || gid == perm::ROOT_GID {
+	/// Returns the IDs and capabilities to use for access checks.
+	///
+	/// `effective` tells whether to use effective IDs. If not, real IDs are used, and
+	/// capabilities are kept only if the real user is root.
+	fn access_ids(&self, effective: bool) -> (Uid, Gid, CapSet) {
+		if effective {
+			(self.euid, self.egid, self.cap_effective)
+		} else {
+			let caps = if self.uid == perm::ROOT_UID {
+				self.cap_permitted
+			} else {
+				0
+			};
+			(self.uid, self.gid, caps)
+		}
+	}
+
+	fn check_read_access_impl(uid: Uid, gid: Gid, caps: CapSet, stat: &Stat) -> bool {
+		// Bypass checks if capable
+		let bypass = (1 << perm::CAP_DAC_OVERRIDE) | (1 << perm::CAP_DAC_READ_SEARCH);
+		if caps & bypass != 0 {
 			return true;
 		}
 		// Check permissions
@@ -551,12 +661,8 @@ impl AccessProfile {
 	///
 	/// `effective` tells whether to use effective IDs. If not, real IDs are used.
 	pub fn check_read_access(&self, stat: &Stat, effective: bool) -> bool {
-		let (uid, gid) = if effective {
-			(self.euid, self.egid)
-		} else {
-			(self.uid, self.gid)
-		};
-		Self::check_read_access_impl(uid, gid, stat)
+		let (uid, gid, caps) = self.access_ids(effective);
+		Self::check_read_access_impl(uid, gid, caps, stat)
 	}
 
 	/// Tells whether the agent can read a file with the given status.
@@ -573,9 +679,9 @@ impl AccessProfile {
 		self.can_read_file(stat)
 	}
 
-	fn check_write_access_impl(uid: Uid, gid: Gid, stat: &Stat) -> bool {
-		// If root, bypass checks
-		if uid == perm::ROOT_UID || gid == perm::ROOT_GID {
+	fn check_write_access_impl(uid: Uid, gid: Gid, caps: CapSet, stat: &Stat) -> bool {
+		// Bypass checks if capable
+		if caps & (1 << perm::CAP_DAC_OVERRIDE) != 0 {
 			return true;
 		}
 		// Check permissions
@@ -592,12 +698,8 @@ impl AccessProfile {
 	///
 	/// `effective` tells whether to use effective IDs. If not, real IDs are used.
 	pub fn check_write_access(&self, stat: &Stat, effective: bool) -> bool {
-		let (uid, gid) = if effective {
-			(self.euid, self.egid)
-		} else {
-			(self.uid, self.gid)
-		};
-		Self::check_write_access_impl(uid, gid, stat)
+		let (uid, gid, caps) = self.access_ids(effective);
+		Self::check_write_access_impl(uid, gid, caps, stat)
 	}
 
 	/// Tells whether the agent can write a file with the given status.
@@ -612,11 +714,14 @@ impl AccessProfile {
 		self.can_write_file(stat) && self.can_execute_file(stat)
 	}
 
-	fn check_execute_access_impl(uid: Uid, gid: Gid, stat: &Stat) -> bool {
-		// If root, bypass checks (unless the file is a regular file)
-		if stat.get_type() != Some(FileType::Regular)
-			&& (uid == perm::ROOT_UID || gid == perm::ROOT_GID)
-		{
+	fn check_execute_access_impl(uid: Uid, gid: Gid, caps: CapSet, stat: &Stat) -> bool {
+		// Bypass checks if capable (unless the file is a regular file)
+		let bypass = if stat.get_type() == Some(FileType::Directory) {
+			(1 << perm::CAP_DAC_OVERRIDE) | (1 << perm::CAP_DAC_READ_SEARCH)
+		} else {
+			1 << perm::CAP_DAC_OVERRIDE
+		};
+		if stat.get_type() != Some(FileType::Regular) && caps & bypass != 0 {
 			return true;
 		}
 		// Check permissions
@@ -633,12 +738,8 @@ impl AccessProfile {
 	///
 	/// `effective` tells whether to use effective IDs. If not, real IDs are used.
 	pub fn check_execute_access(&self, stat: &Stat, effective: bool) -> bool {
-		let (uid, gid) = if effective {
-			(self.euid, self.egid)
-		} else {
-			(self.uid, self.gid)
-		};
-		Self::check_execute_access_impl(uid, gid, stat)
+		let (uid, gid, caps) = self.access_ids(effective);
+		Self::check_execute_access_impl(uid, gid, caps, stat)
 	}
 
 	/// Tells whether the agent can execute a file with the given status.
@@ -655,14 +756,16 @@ impl AccessProfile {
 
 	/// Tells whether the agent can set permissions for a file with the given status.
 	pub fn can_set_file_permissions(&self, stat: &Stat) -> bool {
-		self.euid == perm::ROOT_UID || self.euid == stat.uid
+		self.has_capability(perm::CAP_FOWNER) || self.euid == stat.uid
 	}
 }
 
 /// Initializes files management.
 ///
-/// `root` is the set of major and minor numbers of the root device. If `None`, a tmpfs is used.
-pub(crate) fn init(root: Option<(u32, u32)>) -> EResult<()> {
+/// Arguments:
+/// - `root` is the set of major and minor numbers of the root device. If `None`, a tmpfs is used
+/// - `readonly` tells whether the root filesystem is mounted in read-only
+pub(crate) fn init(root: Option<(u32, u32)>, readonly: bool) -> EResult<()> {
 	fs::register_defaults()?;
 	// Create the root mountpoint
 	let source = match root {
@@ -672,7 +775,8 @@ pub(crate) fn init(root: Option<(u32, u32)>) -> EResult<()> {
 		}),
 		None => MountSource::NoDev(String::try_from(b"tmpfs")?),
 	};
-	let root = mountpoint::create(source, None, 0, None)?;
+	let flags = if readonly { mountpoint::FLAG_RDONLY } else { 0 };
+	let root = mountpoint::create(source, None, flags, None)?;
 	// Init the VFS's root entry.
 	unsafe {
 		OnceInit::init(&vfs::ROOT, root);
@@ -682,5 +786,5 @@ pub(crate) fn init(root: Option<(u32, u32)>) -> EResult<()> {
 
 /// Tells whether files management has been initialized.
 pub(crate) fn is_init() -> bool {
-	!mountpoint::MOUNT_POINTS.lock().is_empty()
+	!mountpoint::NAMESPACES.lock().is_empty()
 }
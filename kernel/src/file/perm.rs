@@ -19,6 +19,10 @@
 //! UNIX permissions are detailed in the POSIX specification.
 //!
 //! This module implements management of such permissions.
+//!
+//! Privileged operations are not granted to the root user directly, but to agents holding the
+//! corresponding **capability** in their effective set. Capabilities are granted to processes
+//! running as root, and dropped when they switch to another user, in the same way as Linux.
 
 use super::Mode;
 use utils::{errno, errno::EResult};
@@ -64,6 +68,99 @@ pub const S_ISGID: Mode = 0o2000;
 /// Sticky bit.
 pub const S_ISVTX: Mode = 0o1000;
 
+/// A capability, identifying a set of privileged operations.
+pub type Capability = u8;
+/// A set of capabilities, as a bitfield.
+pub type CapSet = u64;
+
+/// Change the owner and group of files.
+pub const CAP_CHOWN: Capability = 0;
+/// Bypass file read, write and execute permission checks.
+pub const CAP_DAC_OVERRIDE: Capability = 1;
+/// Bypass file read, and directory read and search permission checks.
+pub const CAP_DAC_READ_SEARCH: Capability = 2;
+/// Bypass permission checks on operations requiring to own the file.
+pub const CAP_FOWNER: Capability = 3;
+/// Keep set-user-ID and set-group-ID bits when modifying a file.
+pub const CAP_FSETID: Capability = 4;
+/// Bypass permission checks for sending signals.
+pub const CAP_KILL: Capability = 5;
+/// Make arbitrary changes to group IDs.
+pub const CAP_SETGID: Capability = 6;
+/// Make arbitrary changes to user IDs.
+pub const CAP_SETUID: Capability = 7;
+/// Transfer capabilities.
+pub const CAP_SETPCAP: Capability = 8;
+/// Set the immutable and append-only flags on files.
+pub const CAP_LINUX_IMMUTABLE: Capability = 9;
+/// Bind a socket to a privileged port (below `1024`).
+pub const CAP_NET_BIND_SERVICE: Capability = 10;
+/// Make socket broadcasts and listen to multicasts.
+pub const CAP_NET_BROADCAST: Capability = 11;
+/// Perform network administration operations.
+pub const CAP_NET_ADMIN: Capability = 12;
+/// Use raw and packet sockets.
+pub const CAP_NET_RAW: Capability = 13;
+/// Lock memory.
+pub const CAP_IPC_LOCK: Capability = 14;
+/// Bypass permission checks on IPC objects.
+pub const CAP_IPC_OWNER: Capability = 15;
+/// Load and unload kernel modules.
+pub const CAP_SYS_MODULE: Capability = 16;
+/// Perform raw I/O operations on devices.
+pub const CAP_SYS_RAWIO: Capability = 17;
+/// Use `chroot`.
+pub const CAP_SYS_CHROOT: Capability = 18;
+/// Trace arbitrary processes.
+pub const CAP_SYS_PTRACE: Capability = 19;
+/// Use `acct`.
+pub const CAP_SYS_PACCT: Capability = 20;
+/// Perform system administration operations.
+pub const CAP_SYS_ADMIN: Capability = 21;
+/// Reboot the system.
+pub const CAP_SYS_BOOT: Capability = 22;
+/// Raise the priority of processes, and change the priority of other processes.
+pub const CAP_SYS_NICE: Capability = 23;
+/// Override resource limits.
+pub const CAP_SYS_RESOURCE: Capability = 24;
+/// Set the system clock.
+pub const CAP_SYS_TIME: Capability = 25;
+/// Use `vhangup` and privileged operations on virtual terminals.
+pub const CAP_SYS_TTY_CONFIG: Capability = 26;
+/// Create special files with `mknod`.
+pub const CAP_MKNOD: Capability = 27;
+/// Establish leases on arbitrary files.
+pub const CAP_LEASE: Capability = 28;
+/// Write records to the kernel's auditing log.
+pub const CAP_AUDIT_WRITE: Capability = 29;
+/// Configure the kernel's auditing.
+pub const CAP_AUDIT_CONTROL: Capability = 30;
+/// Set capabilities on files.
+pub const CAP_SETFCAP: Capability = 31;
+/// Override Mandatory Access Control.
+pub const CAP_MAC_OVERRIDE: Capability = 32;
+/// Configure Mandatory Access Control.
+pub const CAP_MAC_ADMIN: Capability = 33;
+/// Perform privileged operations on the kernel's log.
+pub const CAP_SYSLOG: Capability = 34;
+/// Trigger something that will wake up the system.
+pub const CAP_WAKE_ALARM: Capability = 35;
+/// Block system suspend.
+pub const CAP_BLOCK_SUSPEND: Capability = 36;
+/// Read the kernel's auditing log.
+pub const CAP_AUDIT_READ: Capability = 37;
+/// Perform performance monitoring operations.
+pub const CAP_PERFMON: Capability = 38;
+/// Perform privileged BPF operations.
+pub const CAP_BPF: Capability = 39;
+/// Perform checkpoint and restore operations.
+pub const CAP_CHECKPOINT_RESTORE: Capability = 40;
+/// The last valid capability.
+pub const CAP_LAST_CAP: Capability = CAP_CHECKPOINT_RESTORE;
+
+/// The set containing every capability.
+pub const CAP_FULL_SET: CapSet = (1 << (CAP_LAST_CAP + 1)) - 1;
+
 /// A set of informations determining whether an agent (example: a process) can access a resource.
 ///
 /// Implementations of this structure may contain functions to check access to an object. Custom
@@ -98,6 +195,13 @@ pub struct AccessProfile {
 	pub suid: Uid,
 	/// The saved group ID.
 	pub sgid: Gid,
+
+	/// The capabilities used for permission checks.
+	pub cap_effective: CapSet,
+	/// The capabilities the agent may enable in its effective set.
+	pub cap_permitted: CapSet,
+	/// The capabilities preserved across program execution.
+	pub cap_inheritable: CapSet,
 }
 
 impl AccessProfile {
@@ -111,10 +215,17 @@ impl AccessProfile {
 
 		suid: 0,
 		sgid: 0,
+
+		cap_effective: CAP_FULL_SET,
+		cap_permitted: CAP_FULL_SET,
+		cap_inheritable: 0,
 	};
 
 	/// Creates a profile from the given IDs.
+	///
+	/// The root user is granted every capability.
 	pub fn new(uid: Uid, gid: Gid) -> Self {
+		let caps = if uid == ROOT_UID { CAP_FULL_SET } else { 0 };
 		Self {
 			uid,
 			gid,
@@ -124,38 +235,86 @@ impl AccessProfile {
 
 			suid: uid,
 			sgid: gid,
+
+			cap_effective: caps,
+			cap_permitted: caps,
+			cap_inheritable: 0,
+		}
+	}
+
+	/// Tells whether the agent has the capability `cap` in its effective set.
+	pub fn has_capability(&self, cap: Capability) -> bool {
+		self.cap_effective & (1 << cap) != 0
+	}
+
+	/// Updates capabilities after a change of user IDs, `old` being the profile before the change.
+	///
+	/// Like on Linux:
+	/// - when no user ID is root anymore, permitted and effective capabilities are cleared
+	/// - when the effective user ID switches from root, effective capabilities are cleared
+	/// - when the effective user ID switches to root, permitted capabilities become effective
+	pub fn update_capabilities(&mut self, old: &AccessProfile) {
+		let was_root = [old.uid, old.euid, old.suid].contains(&ROOT_UID);
+		let is_root = [self.uid, self.euid, self.suid].contains(&ROOT_UID);
+		if was_root && !is_root {
+			self.cap_permitted = 0;
+			self.cap_effective = 0;
+		}
+		if old.euid == ROOT_UID && self.euid != ROOT_UID {
+			self.cap_effective = 0;
+		} else if old.euid != ROOT_UID && self.euid == ROOT_UID {
+			self.cap_effective = self.cap_permitted;
 		}
 	}
 
-	/// Tells whether the agent is privileged (root).
-	pub fn is_privileged(&self) -> bool {
-		self.euid == ROOT_UID || self.egid == ROOT_GID
+	/// Computes capabilities for the execution of a new program, after user IDs have been updated
+	/// for set-user-ID programs.
+	///
+	/// Since file capabilities are not supported, programs executed by root, or set-user-ID root,
+	/// are granted every capability. Other programs lose all of them.
+	pub fn exec_capabilities(&mut self) {
+		if self.uid == ROOT_UID || self.euid == ROOT_UID {
+			self.cap_permitted = CAP_FULL_SET;
+		} else {
+			self.cap_permitted = 0;
+		}
+		if self.euid == ROOT_UID {
+			self.cap_effective = self.cap_permitted;
+		} else {
+			self.cap_effective = 0;
+		}
 	}
 
-	/// Sets the user ID in the same way the `setgid` system call does.
+	/// Sets the user ID in the same way the `setuid` system call does.
 	///
 	/// If the agent is not privileged enough to make the change, the function returns an error.
 	pub fn set_uid(&mut self, uid: Uid) -> EResult<()> {
-		if self.euid == ROOT_UID {
-			// privileged
+		let old = *self;
+		if self.has_capability(CAP_SETUID) {
 			self.uid = uid;
 			self.euid = uid;
 			self.suid = uid;
-			Ok(())
 		} else if uid == self.uid || uid == self.euid || uid == self.suid {
 			self.euid = uid;
-			Ok(())
 		} else {
-			Err(errno!(EPERM))
+			return Err(errno!(EPERM));
 		}
+		self.update_capabilities(&old);
+		Ok(())
 	}
 
 	/// Sets the effective user ID.
 	///
 	/// If the agent is not privileged enough to make the change, the function returns an error.
 	pub fn set_euid(&mut self, uid: Uid) -> EResult<()> {
-		if uid == ROOT_UID || uid == self.uid || uid == self.euid || uid == self.suid {
+		if self.has_capability(CAP_SETUID)
+			|| uid == self.uid
+			|| uid == self.euid
+			|| uid == self.suid
+		{
+			let old = *self;
 			self.euid = uid;
+			self.update_capabilities(&old);
 			Ok(())
 		} else {
 			Err(errno!(EPERM))
@@ -166,7 +325,7 @@ impl AccessProfile {
 	///
 	/// If the agent is not privileged enough to make the change, the function returns an error.
 	pub fn set_gid(&mut self, gid: Gid) -> EResult<()> {
-		if self.egid == ROOT_GID {
+		if self.has_capability(CAP_SETGID) {
 			// privileged
 			self.gid = gid;
 			self.egid = gid;
@@ -184,7 +343,11 @@ impl AccessProfile {
 	///
 	/// If the agent is not privileged enough to make the change, the function returns an error.
 	pub fn set_egid(&mut self, gid: Uid) -> EResult<()> {
-		if gid == ROOT_GID || gid == self.gid || gid == self.egid || gid == self.sgid {
+		if self.has_capability(CAP_SETGID)
+			|| gid == self.gid
+			|| gid == self.egid
+			|| gid == self.sgid
+		{
 			self.egid = gid;
 			Ok(())
 		} else {
@@ -192,3 +355,24 @@ impl AccessProfile {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn capabilities_setuid() {
+		let mut ap = AccessProfile::new(ROOT_UID, 0);
+		assert!(ap.has_capability(CAP_CHOWN));
+		// Dropping root as effective user ID only clears effective capabilities
+		ap.set_euid(1000).unwrap();
+		assert!(!ap.has_capability(CAP_CHOWN));
+		assert_eq!(ap.cap_permitted, CAP_FULL_SET);
+		ap.set_euid(ROOT_UID).unwrap();
+		assert!(ap.has_capability(CAP_CHOWN));
+		// Dropping root entirely clears all capabilities
+		ap.set_uid(1000).unwrap();
+		assert_eq!(ap.cap_permitted, 0);
+		assert!(ap.set_uid(ROOT_UID).is_err());
+	}
+}
@@ -20,18 +20,21 @@
 //! and another writing, with a buffer in between.
 
 use crate::{
-	file::{File, FileType, O_NONBLOCK, Stat, fs::FileOps, wait_queue::WaitQueue},
-	memory::{
-		ring_buffer::RingBuffer,
-		user::{UserPtr, UserSlice},
+	file::{
+		File, FileType, O_NONBLOCK, Stat,
+		buffer::{StreamBuffer, sigpipe},
+		fs::FileOps,
+		wait_queue::WaitQueue,
 	},
-	process::{Process, signal::Signal},
+	memory::user::{UserPtr, UserSlice},
 	sync::mutex::Mutex,
-	syscall::{FromSyscallArg, ioctl},
+	syscall::{
+		FromSyscallArg, ioctl,
+		select::{POLLHUP, POLLIN, POLLOUT, POLLRDHUP},
+	},
 };
 use core::{
 	ffi::{c_int, c_void},
-	hint::unlikely,
 	num::NonZeroUsize,
 };
 use utils::{
@@ -42,8 +45,6 @@ use utils::{
 
 #[derive(Debug)]
 struct PipeInner {
-	/// The pipe's buffer.
-	buffer: RingBuffer,
 	/// The number of readers on the pipe.
 	readers: usize,
 	/// The number of writers on the pipe.
@@ -53,25 +54,25 @@ struct PipeInner {
 /// Representing a FIFO buffer.
 #[derive(Debug)]
 pub struct PipeBuffer {
+	/// The pipe's buffer.
+	buffer: StreamBuffer,
 	/// Inner with locking.
 	inner: Mutex<PipeInner>,
-	/// The queue of processing waiting to read from the pipe.
-	rd_queue: WaitQueue,
-	/// The queue of processing waiting to write to the pipe.
-	wr_queue: WaitQueue,
 }
 
 impl PipeBuffer {
 	/// Creates a new instance.
 	pub fn new() -> AllocResult<Self> {
+		let buffer = StreamBuffer::new(NonZeroUsize::new(PIPE_BUF).unwrap())?;
+		// Ends are opened when files are acquired
+		buffer.set_read_open(false);
+		buffer.set_write_open(false);
 		Ok(Self {
+			buffer,
 			inner: Mutex::new(PipeInner {
-				buffer: RingBuffer::new(NonZeroUsize::new(PIPE_BUF).unwrap())?,
 				readers: 0,
 				writers: 0,
 			}),
-			rd_queue: WaitQueue::default(),
-			wr_queue: WaitQueue::default(),
 		})
 	}
 
@@ -93,9 +94,11 @@ impl FileOps for PipeBuffer {
 		let mut inner = self.inner.lock();
 		if file.can_read() {
 			inner.readers += 1;
+			self.buffer.set_read_open(true);
 		}
 		if file.can_write() {
 			inner.writers += 1;
+			self.buffer.set_write_open(true);
 		}
 	}
 
@@ -103,24 +106,54 @@ impl FileOps for PipeBuffer {
 		let mut inner = self.inner.lock();
 		if file.can_read() {
 			inner.readers -= 1;
+			if inner.readers == 0 {
+				self.buffer.set_read_open(false);
+			}
 		}
 		if file.can_write() {
 			inner.writers -= 1;
+			if inner.writers == 0 {
+				self.buffer.set_write_open(false);
+			}
 		}
-		if (inner.readers == 0) != (inner.writers == 0) {
-			self.rd_queue.wake_all();
-			self.wr_queue.wake_all();
+	}
+
+	fn poll(&self, file: &File, mask: u32) -> EResult<u32> {
+		let mut events = 0;
+		if file.can_read() {
+			events |= self.buffer.poll_read();
+			// The other end of the pipe has been closed
+			if !self.buffer.is_write_open() {
+				events = (events & !POLLRDHUP) | POLLHUP;
+			}
+		}
+		if file.can_write() {
+			events |= self.buffer.poll_write();
 		}
+		Ok(events & (mask | !(POLLIN | POLLOUT)))
 	}
 
-	fn poll(&self, _file: &File, _mask: u32) -> EResult<u32> {
-		todo!()
+	fn poll_wait(
+		&self,
+		file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		let mut mask = mask;
+		if !file.can_read() {
+			mask &= !POLLIN;
+		}
+		if !file.can_write() {
+			mask &= !POLLOUT;
+		}
+		self.buffer.poll_wait(mask, f)?;
+		Ok(true)
 	}
 
 	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
 		match request.get_old_format() {
 			ioctl::FIONREAD => {
-				let len = self.inner.lock().buffer.get_data_len() as c_int;
+				let len = self.buffer.get_data_len() as c_int;
 				let count_ptr = UserPtr::from_ptr(argp as usize);
 				count_ptr.copy_to_user(&len)?;
 			}
@@ -130,57 +163,10 @@ impl FileOps for PipeBuffer {
 	}
 
 	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
-		if unlikely(buf.is_empty()) {
-			return Ok(0);
-		}
-		let len = self.rd_queue.wait_until(|| {
-			let mut inner = self.inner.lock();
-			let len = match inner.buffer.read(buf) {
-				Ok(l) => l,
-				Err(e) => return Some(Err(e)),
-			};
-			if len > 0 {
-				self.wr_queue.wake_next();
-				return Some(Ok(len));
-			}
-			// Nothing to read
-			if inner.writers == 0 {
-				return Some(Ok(0));
-			}
-			if file.get_flags() & O_NONBLOCK != 0 {
-				Some(Err(errno!(EAGAIN)))
-			} else {
-				None
-			}
-		})??;
-		Ok(len)
+		self.buffer.read(buf, file.get_flags() & O_NONBLOCK != 0)
 	}
 
 	fn write(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
-		if unlikely(buf.is_empty()) {
-			return Ok(0);
-		}
-		let len = self.wr_queue.wait_until(|| {
-			let mut inner = self.inner.lock();
-			if inner.readers == 0 {
-				Process::current().kill(Signal::SIGPIPE);
-				return Some(Err(errno!(EPIPE)));
-			}
-			let len = match inner.buffer.write(buf) {
-				Ok(l) => l,
-				Err(e) => return Some(Err(e)),
-			};
-			if len > 0 {
-				self.rd_queue.wake_next();
-				return Some(Ok(len));
-			}
-			// No space left to write
-			if file.get_flags() & O_NONBLOCK != 0 {
-				Some(Err(errno!(EAGAIN)))
-			} else {
-				None
-			}
-		})??;
-		Ok(len)
+		sigpipe(self.buffer.write(buf, file.get_flags() & O_NONBLOCK != 0))
 	}
 }
@@ -19,21 +19,38 @@
 //! This file implements sockets.
 
 use crate::{
-	file::{File, FileType, Stat, fs::FileOps, wait_queue::WaitQueue},
-	memory::{ring_buffer::RingBuffer, user::UserSlice},
-	net::{SocketDesc, osi},
+	file::{
+		File, FileType, O_NONBLOCK, Stat,
+		buffer::{StreamBuffer, sigpipe},
+		fs::FileOps,
+		wait_queue::WaitQueue,
+	},
+	memory::{cache::RcFrame, user::UserSlice},
+	net::{
+		NetNamespace, SocketDesc, SocketDomain, arp, bpf,
+		bpf::{Program, SockFilter},
+		netlink, osi, packet,
+		packet::PacketRings,
+		udp,
+	},
 	sync::mutex::Mutex,
-	syscall::ioctl,
+	syscall::{
+		ioctl,
+		select::{POLLHUP, POLLIN, POLLOUT, POLLRDHUP},
+	},
 };
 use core::{
+	cmp::min,
 	ffi::{c_int, c_void},
 	num::NonZeroUsize,
+	ptr,
 	sync::{atomic, atomic::AtomicUsize},
 };
 use utils::{
 	collections::vec::Vec,
 	errno,
 	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
 };
 
 /// The maximum size of a socket's buffers.
@@ -42,11 +59,27 @@ const BUFFER_SIZE: usize = 65536;
 /// Socket option level: Socket
 const SOL_SOCKET: c_int = 1;
 
+/// Socket option: attach a filter program
+const SO_ATTACH_FILTER: c_int = 26;
+/// Socket option: detach the filter program
+const SO_DETACH_FILTER: c_int = 27;
+
+/// A datagram waiting to be received.
+#[derive(Debug)]
+struct Datagram {
+	/// The content of the datagram.
+	data: Vec<u8>,
+	/// The address of the sender. If empty, the sender is unknown.
+	addr: Vec<u8>,
+}
+
 /// A UNIX socket.
 #[derive(Debug)]
 pub struct Socket {
 	/// The socket's stack descriptor.
 	desc: SocketDesc,
+	/// The network namespace the socket belongs to.
+	net_ns: Arc<NetNamespace>,
 	/// The socket's network stack corresponding to the descriptor.
 	stack: Option<osi::Stack>,
 	/// The number of entities owning a reference to the socket. When this count reaches zero, the
@@ -55,37 +88,47 @@ pub struct Socket {
 
 	/// The address the socket is bound to.
 	sockname: Mutex<Vec<u8>>,
+	/// The address of the peer the socket is connected to. Empty if not connected.
+	peername: Mutex<Vec<u8>>,
+
+	/// The buffer containing received data. Its read end is closed when reception is shutdown,
+	/// and its write end when the peer stops transmitting.
+	rx_buff: StreamBuffer,
+	/// The buffer containing data to be transmitted. Its write end is closed when transmission is
+	/// shutdown.
+	tx_buff: StreamBuffer,
+	/// The queue of received datagrams, for sockets preserving message boundaries.
+	rx_datagrams: Mutex<Vec<Datagram>>,
 
-	/// The buffer containing received data. If `None`, reception has been shutdown.
-	rx_buff: Mutex<Option<RingBuffer>>,
-	/// The buffer containing data to be transmitted. If `None`, transmission has been shutdown.
-	tx_buff: Mutex<Option<RingBuffer>>,
+	/// The filter program run on received packets, if any.
+	filter: Mutex<Option<Program>>,
+	/// The rings of frames shared with userspace, for packet sockets.
+	packet_rings: Mutex<PacketRings>,
 
-	/// Receive wait queue.
+	/// Receive wait queue, for datagrams.
 	rx_queue: WaitQueue,
-	/// Transmit wait queue.
-	tx_queue: WaitQueue,
 }
 
 impl Socket {
-	/// Creates a new instance.
-	pub fn new(desc: SocketDesc) -> AllocResult<Self> {
+	/// Creates a new instance, in the network namespace `net_ns`.
+	pub fn new(desc: SocketDesc, net_ns: Arc<NetNamespace>) -> AllocResult<Self> {
 		Ok(Self {
 			desc,
+			net_ns,
 			stack: None,
 			open_count: AtomicUsize::new(0),
 
 			sockname: Default::default(),
+			peername: Default::default(),
+
+			rx_buff: StreamBuffer::new(NonZeroUsize::new(BUFFER_SIZE).unwrap())?,
+			tx_buff: StreamBuffer::new(NonZeroUsize::new(BUFFER_SIZE).unwrap())?,
+			rx_datagrams: Default::default(),
 
-			rx_buff: Mutex::new(Some(RingBuffer::new(
-				NonZeroUsize::new(BUFFER_SIZE).unwrap(),
-			)?)),
-			tx_buff: Mutex::new(Some(RingBuffer::new(
-				NonZeroUsize::new(BUFFER_SIZE).unwrap(),
-			)?)),
+			filter: Default::default(),
+			packet_rings: Default::default(),
 
 			rx_queue: WaitQueue::new(),
-			tx_queue: WaitQueue::new(),
 		})
 	}
 
@@ -95,20 +138,45 @@ impl Socket {
 		&self.desc
 	}
 
+	/// Returns the network namespace the socket belongs to.
+	#[inline(always)]
+	pub fn net_ns(&self) -> &Arc<NetNamespace> {
+		&self.net_ns
+	}
+
+	/// Tells whether received data is queued as datagrams, rather than in the stream buffer.
+	fn is_datagram_queued(&self) -> bool {
+		matches!(
+			self.desc.domain,
+			SocketDomain::AfPacket | SocketDomain::AfNetlink
+		) || udp::is_udp(&self.desc)
+	}
+
 	/// Returns the socket's network stack.
 	#[inline(always)]
 	pub fn stack(&self) -> Option<&osi::Stack> {
 		self.stack.as_ref()
 	}
 
+	/// Returns the rings of frames shared with userspace, for packet sockets.
+	#[inline(always)]
+	pub fn packet_rings(&self) -> &Mutex<PacketRings> {
+		&self.packet_rings
+	}
+
 	/// Reads the given socket option.
 	///
 	/// Arguments:
 	/// - `level` is the level (protocol) at which the option is located.
 	/// - `optname` is the name of the option.
-	pub fn get_opt(&self, _level: c_int, _optname: c_int) -> EResult<&[u8]> {
-		// TODO
-		todo!()
+	pub fn get_opt(&self, level: c_int, optname: c_int) -> EResult<Vec<u8>> {
+		match level {
+			packet::SOL_PACKET if self.desc.domain == SocketDomain::AfPacket => {
+				packet::get_opt(self, optname)
+			}
+			// TODO
+			_ => Err(errno!(ENOPROTOOPT)),
+		}
 	}
 
 	/// Writes the given socket option.
@@ -119,8 +187,42 @@ impl Socket {
 	/// - `optval` is the value of the option.
 	///
 	/// The function returns a value to be returned by the syscall on success.
-	pub fn set_opt(&self, _level: c_int, _optname: c_int, _optval: &[u8]) -> EResult<c_int> {
-		// TODO
+	pub fn set_opt(&self, level: c_int, optname: c_int, optval: &[u8]) -> EResult<c_int> {
+		match (level, optname) {
+			(SOL_SOCKET, SO_ATTACH_FILTER) => {
+				// `struct sock_fprog`. The layout depends on the size of pointers
+				let (len, ptr) = match optval.len() {
+					8 => (
+						u16::from_ne_bytes([optval[0], optval[1]]),
+						u32::from_ne_bytes(optval[4..8].try_into().unwrap()) as usize,
+					),
+					16 => (
+						u16::from_ne_bytes([optval[0], optval[1]]),
+						u64::from_ne_bytes(optval[8..16].try_into().unwrap()) as usize,
+					),
+					_ => return Err(errno!(EINVAL)),
+				};
+				let len = len as usize;
+				if len == 0 || len > bpf::MAX_INSNS {
+					return Err(errno!(EINVAL));
+				}
+				let insns = UserSlice::<SockFilter>::from_user(
+					ptr::with_exposed_provenance_mut(ptr),
+					len,
+				)?
+				.copy_from_user_vec(0)?
+				.ok_or_else(|| errno!(EFAULT))?;
+				*self.filter.lock() = Some(Program::new(insns)?);
+			}
+			(SOL_SOCKET, SO_DETACH_FILTER) => {
+				self.filter.lock().take().ok_or_else(|| errno!(ENOENT))?;
+			}
+			(packet::SOL_PACKET, _) if self.desc.domain == SocketDomain::AfPacket => {
+				packet::set_opt(self, optname, optval)?;
+			}
+			// TODO
+			_ => {}
+		}
 		Ok(0)
 	}
 
@@ -129,6 +231,11 @@ impl Socket {
 		&self.sockname
 	}
 
+	/// Returns the name of the peer the socket is connected to.
+	pub fn get_peername(&self) -> &Mutex<Vec<u8>> {
+		&self.peername
+	}
+
 	/// Binds the socket to the given address.
 	///
 	/// `sockaddr` is the new socket name.
@@ -136,6 +243,12 @@ impl Socket {
 	/// If the socket is already bound, or if the address is invalid, or if the address is already
 	/// in used, the function returns an error.
 	pub fn bind(&self, sockaddr: &[u8]) -> EResult<()> {
+		if self.desc.domain == SocketDomain::AfNetlink {
+			return netlink::bind(self, sockaddr);
+		}
+		if udp::is_udp(&self.desc) {
+			return udp::bind(self, sockaddr);
+		}
 		let mut sockname = self.sockname.lock();
 		if !sockname.is_empty() {
 			return Err(errno!(EINVAL));
@@ -148,15 +261,277 @@ impl Socket {
 		Ok(())
 	}
 
+	/// Connects the socket to the address `sockaddr`.
+	///
+	/// On a datagram socket, this sets the default destination and filters received datagrams.
+	///
+	/// If the socket does not support connections, the function returns
+	/// [`errno::EOPNOTSUPP`].
+	pub fn connect(&self, sockaddr: &[u8]) -> EResult<()> {
+		if udp::is_udp(&self.desc) {
+			return udp::connect(self, sockaddr);
+		}
+		// TODO connection-mode sockets
+		Err(errno!(EOPNOTSUPP))
+	}
+
+	/// Runs `data` through the socket's filter, returning the length of the data to keep.
+	///
+	/// If the data is rejected, the function returns zero.
+	fn filter_len(&self, data: &[u8]) -> usize {
+		match &*self.filter.lock() {
+			Some(prog) => min(prog.run(data) as usize, data.len()),
+			None => data.len(),
+		}
+	}
+
+	/// Queues the received datagram `data`, after running it through the socket's filter.
+	///
+	/// `addr` is the address of the sender. If empty, the sender is unknown.
+	///
+	/// If the datagram is rejected by the filter, or if there is not enough space left in the
+	/// receive queue, the datagram is dropped.
+	pub fn push_datagram(&self, data: &[u8], addr: &[u8]) -> AllocResult<()> {
+		let len = self.filter_len(data);
+		if len == 0 {
+			return Ok(());
+		}
+		// If reception has been shutdown, drop
+		if !self.rx_buff.is_read_open() {
+			return Ok(());
+		}
+		{
+			let mut datagrams = self.rx_datagrams.lock();
+			let size: usize = datagrams.iter().map(|d| d.data.len()).sum();
+			if size + len > BUFFER_SIZE {
+				return Ok(());
+			}
+			datagrams.push(Datagram {
+				data: Vec::try_from(&data[..len])?,
+				addr: Vec::try_from(addr)?,
+			})?;
+		}
+		self.rx_queue.wake_all();
+		Ok(())
+	}
+
+	/// Queues `frame`, of packet type `pkttype`, which went through the interface with index
+	/// `ifindex`, on a packet socket. The data given to userspace begins at the offset `off` in
+	/// the frame.
+	///
+	/// If a reception ring is set up, the frame is written to it. Otherwise, it is queued as a
+	/// datagram.
+	pub fn push_packet(
+		&self,
+		ifindex: u32,
+		pkttype: u8,
+		frame: &[u8],
+		off: usize,
+	) -> AllocResult<()> {
+		{
+			let mut rings = self.packet_rings.lock();
+			if rings.has_rx() {
+				let len = self.filter_len(&frame[off..]);
+				if len > 0
+					&& self.rx_buff.is_read_open()
+					&& rings.receive(ifindex, pkttype, frame, off, len)
+				{
+					self.rx_queue.wake_all();
+				}
+				return Ok(());
+			}
+		}
+		// TODO pass the sender's address
+		self.push_datagram(&frame[off..], &[])
+	}
+
+	/// Reads data from the stream into `buf`.
+	///
+	/// If `waitall` is set, the function blocks until `buf` is full or the stream ends. Otherwise,
+	/// it blocks only until some data is available. In both cases, it does not block if `block`
+	/// is not set.
+	///
+	/// If an error occurs after some data has been read, the function returns the length read.
+	fn read_stream(&self, buf: UserSlice<u8>, block: bool, waitall: bool) -> EResult<usize> {
+		let mut off = 0;
+		while off < buf.len() {
+			let nonblock = !block || (off > 0 && !waitall);
+			match self.rx_buff.read(buf.skip(off), nonblock) {
+				Ok(0) => break,
+				Ok(len) => off += len,
+				Err(_) if off > 0 => break,
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(off)
+	}
+
+	/// Receives data from the socket, scattering it into the buffers `iov` in order.
+	///
+	/// Arguments:
+	/// - `iov` is the list of destination buffers.
+	/// - `peek` tells whether the data is to be left in the receive queue.
+	/// - `nonblock` tells whether the function must return [`errno::EAGAIN`] instead of blocking
+	///   when no data is available.
+	/// - `waitall` tells whether the function must block until `iov` is full, on stream sockets.
+	///
+	/// On success, the function returns the number of bytes written to `iov`, followed by the
+	/// length of the received message, and the address of its sender (empty if unknown). If the
+	/// message did not fit in `iov`, it is truncated and the second value is the greater. For
+	/// stream sockets, both values are equal.
+	///
+	/// If reception has been shutdown and no data remains, the function returns `(0, 0)`.
+	pub fn recv(
+		&self,
+		iov: &[UserSlice<u8>],
+		peek: bool,
+		nonblock: bool,
+		waitall: bool,
+	) -> EResult<(usize, usize, Vec<u8>)> {
+		if self.is_datagram_queued() {
+			return self.rx_queue.wait_until(|| {
+				let mut datagrams = self.rx_datagrams.lock();
+				if let Some(dgram) = datagrams.first() {
+					// Copy directly from the queue to avoid an intermediate buffer
+					let copied = match scatter(iov, &dgram.data) {
+						Ok(copied) => copied,
+						Err(e) => return Some(Err(e)),
+					};
+					let len = dgram.data.len();
+					let addr = if peek {
+						match Vec::try_from(dgram.addr.as_slice()) {
+							Ok(addr) => addr,
+							Err(e) => return Some(Err(e.into())),
+						}
+					} else {
+						datagrams.remove(0).addr
+					};
+					return Some(Ok((copied, len, addr)));
+				}
+				if !self.rx_buff.is_read_open() {
+					return Some(Ok((0, 0, Vec::new())));
+				}
+				if nonblock {
+					Some(Err(errno!(EAGAIN)))
+				} else {
+					None
+				}
+			})?;
+		}
+		if !self.desc.type_.is_stream() {
+			// TODO receive datagrams on other sockets
+			return Err(errno!(EOPNOTSUPP));
+		}
+		let mut total = 0;
+		for (i, buf) in iov.iter().enumerate() {
+			// Unless all the data is requested, only the first read may block, since data has
+			// been received afterwards
+			let len = if peek {
+				// TODO peek past the first buffer
+				if i > 0 {
+					break;
+				}
+				self.rx_buff.peek(*buf, nonblock)?
+			} else {
+				let block = !nonblock && (waitall || total == 0);
+				match self.read_stream(*buf, block, waitall) {
+					Ok(len) => len,
+					Err(_) if total > 0 => break,
+					Err(e) => return Err(e),
+				}
+			};
+			total += len;
+			if len < buf.len() {
+				break;
+			}
+		}
+		Ok((total, total, Vec::new()))
+	}
+
+	/// Sends the data in `buf` on the socket.
+	///
+	/// If `nonblock` is set and the data cannot be sent immediately, the function returns
+	/// [`errno::EAGAIN`].
+	///
+	/// If transmission has been shutdown, the function returns [`errno::EPIPE`] without raising
+	/// `SIGPIPE`.
+	pub fn send(&self, buf: UserSlice<u8>, nonblock: bool) -> EResult<usize> {
+		if self.is_datagram_queued() {
+			let msg = buf.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
+			return self.send_datagram(&msg, None);
+		}
+		if self.desc.type_.is_stream() {
+			// TODO transmit buffered data through the stack
+			return self.tx_buff.write(buf, nonblock);
+		}
+		// A destination address is required
+		if self.stack.is_none() {
+			return Err(errno!(EDESTADDRREQ));
+		}
+		// TODO transmit through the stack
+		Err(errno!(EOPNOTSUPP))
+	}
+
+	/// Sends the datagram `msg` on a packet, netlink or UDP socket.
+	///
+	/// `dest` is the destination address. If `None`, the default destination of the socket is
+	/// used.
+	///
+	/// For other sockets, the function returns [`errno::EOPNOTSUPP`].
+	///
+	/// If transmission has been shutdown, the function returns [`errno::EPIPE`] without raising
+	/// `SIGPIPE`.
+	pub fn send_datagram(&self, msg: &[u8], dest: Option<&[u8]>) -> EResult<usize> {
+		if !self.tx_buff.is_write_open() {
+			return Err(errno!(EPIPE));
+		}
+		match self.desc.domain {
+			// TODO send to the given address
+			SocketDomain::AfPacket => packet::send(self, msg),
+			// Netlink messages always go to the kernel
+			SocketDomain::AfNetlink => netlink::send(self, msg),
+			_ if udp::is_udp(&self.desc) => udp::send(self, msg, dest),
+			_ => Err(errno!(EOPNOTSUPP)),
+		}
+	}
+
 	/// Shuts down the reception side of the socket.
+	///
+	/// Subsequent reads return end-of-file once buffered data has been consumed.
 	pub fn shutdown_reception(&self) {
-		*self.rx_buff.lock() = None;
+		self.rx_buff.set_read_open(false);
+		self.rx_queue.wake_all();
 	}
 
 	/// Shuts down the transmit side of the socket.
+	///
+	/// Subsequent writes fail with [`errno::EPIPE`].
 	pub fn shutdown_transmit(&self) {
-		*self.tx_buff.lock() = None;
+		// TODO send a FIN to the peer once buffered data has been transmitted
+		self.tx_buff.set_write_open(false);
+	}
+
+	/// Handles the reception of a FIN from the peer, meaning it will not transmit anymore.
+	///
+	/// Subsequent reads return end-of-file once buffered data has been consumed.
+	pub fn peer_shutdown(&self) {
+		self.rx_buff.set_write_open(false);
+	}
+}
+
+/// Copies `data` into the buffers `iov`, in order.
+///
+/// The function returns the number of bytes copied, which is lower than the length of `data` if
+/// the buffers are too small.
+fn scatter(iov: &[UserSlice<u8>], data: &[u8]) -> EResult<usize> {
+	let mut off = 0;
+	for buf in iov {
+		if off >= data.len() {
+			break;
+		}
+		off += buf.copy_to_user(0, &data[off..])?;
 	}
+	Ok(off)
 }
 
 impl FileOps for Socket {
@@ -173,31 +548,78 @@ impl FileOps for Socket {
 
 	fn release(&self, _file: &File) {
 		let cnt = self.open_count.fetch_sub(1, atomic::Ordering::Release);
-		if cnt == 0 {
-			// TODO close the socket
+		if cnt > 1 {
+			return;
 		}
+		if self.desc.domain == SocketDomain::AfPacket {
+			packet::unregister(self);
+		}
+		if udp::is_udp(&self.desc) {
+			udp::unregister(self);
+		}
+		// TODO close the socket
 	}
 
-	fn poll(&self, _file: &File, _mask: u32) -> EResult<u32> {
-		todo!()
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let mut events = if self.is_datagram_queued() {
+			let datagrams = !self.rx_datagrams.lock().is_empty();
+			let ring = self.packet_rings.lock().rx_ready();
+			if datagrams || ring || !self.rx_buff.is_read_open() {
+				POLLIN
+			} else {
+				0
+			}
+		} else {
+			self.rx_buff.poll_read()
+		};
+		events |= self.tx_buff.poll_write();
+		// Both directions are shutdown
+		if events & POLLRDHUP != 0 && !self.tx_buff.is_write_open() {
+			events |= POLLHUP;
+		}
+		Ok(events & (mask | !(POLLIN | POLLOUT | POLLRDHUP)))
 	}
 
-	fn ioctl(&self, _file: &File, _request: ioctl::Request, _argp: *const c_void) -> EResult<u32> {
-		todo!()
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if self.is_datagram_queued() {
+			if mask & POLLIN != 0 {
+				f(&self.rx_queue)?;
+			}
+		} else {
+			self.rx_buff.poll_wait(mask & !POLLOUT, f)?;
+		}
+		self.tx_buff.poll_wait(mask & POLLOUT, f)?;
+		Ok(true)
 	}
 
-	fn read(&self, _file: &File, _off: u64, _buf: UserSlice<u8>) -> EResult<usize> {
-		if !self.desc.type_.is_stream() {
-			// TODO error
+	fn ioctl(&self, _file: &File, request: ioctl::Request, argp: *const c_void) -> EResult<u32> {
+		match request.get_old_format() {
+			req @ (ioctl::SIOCGARP | ioctl::SIOCSARP | ioctl::SIOCDARP) => {
+				arp::ioctl(&self.net_ns, req, argp)
+			}
+			_ => Err(errno!(ENOTTY)),
 		}
-		todo!()
 	}
 
-	fn write(&self, _file: &File, _off: u64, _buf: UserSlice<u8>) -> EResult<usize> {
-		// A destination address is required
-		let Some(_stack) = self.stack.as_ref() else {
-			return Err(errno!(EDESTADDRREQ));
-		};
-		todo!()
+	fn read(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		let nonblock = file.get_flags() & O_NONBLOCK != 0;
+		let (len, ..) = self.recv(&[buf], false, nonblock, false)?;
+		Ok(len)
+	}
+
+	fn write(&self, file: &File, _off: u64, buf: UserSlice<u8>) -> EResult<usize> {
+		sigpipe(self.send(buf, file.get_flags() & O_NONBLOCK != 0))
+	}
+
+	fn mmap_pages(&self, _file: &File, off: u64, pages: usize) -> EResult<Option<Vec<RcFrame>>> {
+		if self.desc.domain != SocketDomain::AfPacket {
+			return Ok(None);
+		}
+		packet::mmap_pages(self, off, pages).map(Some)
 	}
 }
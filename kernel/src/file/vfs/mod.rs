@@ -34,11 +34,20 @@ pub mod mountpoint;
 pub mod node;
 
 use super::{
-	FileType, Stat, perm,
+	DirContext, FileType, Stat, perm,
 	perm::{AccessProfile, S_ISVTX},
 };
 use crate::{
-	file::fs::StatSet,
+	file::{
+		buffer::{
+			inotify,
+			inotify::{
+				IN_ATTRIB, IN_CREATE, IN_DELETE, IN_DELETE_SELF, IN_MOVE_SELF, IN_MOVED_FROM,
+				IN_MOVED_TO,
+			},
+		},
+		fs::StatSet,
+	},
 	process::Process,
 	sync::{mutex::Mutex, once::OnceInit},
 };
@@ -46,6 +55,7 @@ use core::{
 	borrow::Borrow,
 	hash::{Hash, Hasher},
 	hint::unlikely,
+	iter, ptr,
 	sync::atomic::Ordering::Release,
 };
 use node::Node;
@@ -159,13 +169,29 @@ impl Entry {
 
 	/// Returns the absolute path to reach the entry.
 	pub fn get_path(this: &Arc<Self>) -> EResult<PathBuf> {
-		if this.parent.is_none() {
-			return Ok(PathBuf::root()?);
-		}
+		Self::get_path_impl(this, None)
+	}
+
+	/// Returns the path to reach the entry from the root directory `root`.
+	///
+	/// If the entry is not `root` or one of its descendants, the path starts from the root of the
+	/// VFS and is prefixed with `(unreachable)`, like on Linux.
+	pub fn get_path_in(this: &Arc<Self>, root: &Arc<Self>) -> EResult<PathBuf> {
+		Self::get_path_impl(this, Some(root))
+	}
+
+	/// Implementation of [`Self::get_path`] and [`Self::get_path_in`].
+	fn get_path_impl(this: &Arc<Self>, root: Option<&Arc<Self>>) -> EResult<PathBuf> {
 		let mut buf = vec![0u8; PATH_MAX]?;
 		let mut off = PATH_MAX;
 		let mut cur = this;
-		while let Some(parent) = &cur.parent {
+		let reachable = loop {
+			if root.is_some_and(|root| Arc::as_ptr(cur) == Arc::as_ptr(root)) {
+				break true;
+			}
+			let Some(parent) = &cur.parent else {
+				break root.is_none();
+			};
 			let len = cur.name.len();
 			off = off
 				.checked_sub(len + 1)
@@ -173,21 +199,47 @@ impl Entry {
 			buf[off] = b'/';
 			buf[(off + 1)..(off + len + 1)].copy_from_slice(&cur.name);
 			cur = parent;
+		};
+		if off == PATH_MAX {
+			off -= 1;
+			buf[off] = b'/';
+		}
+		if !reachable {
+			const PREFIX: &[u8] = b"(unreachable)";
+			off = off
+				.checked_sub(PREFIX.len())
+				.ok_or_else(|| errno!(ENAMETOOLONG))?;
+			buf[off..(off + PREFIX.len())].copy_from_slice(PREFIX);
 		}
 		buf.rotate_left(off);
 		buf.truncate(buf.len() - off);
 		Ok(PathBuf::new_unchecked(String::from(buf)))
 	}
 
+	/// Tells whether the entry is `root` or one of its descendants.
+	pub fn is_in(&self, root: &Self) -> bool {
+		iter::successors(Some(self), |e| e.parent.as_deref()).any(|e| ptr::eq(e, root))
+	}
+
 	/// Makes `self` a child of its parent, if any. The entry is also inserted in the LRU.
 	///
+	/// If a negative entry with the same name was cached, it is replaced.
+	///
 	/// The function returns `self` wrapped into an [`Arc`].
 	pub fn link_parent(self) -> AllocResult<Arc<Self>> {
 		let entry = Arc::new(self)?;
-		if let Some(parent) = &entry.parent {
-			parent.children.lock().insert(EntryChild(entry.clone()))?;
+		let old = match &entry.parent {
+			Some(parent) => parent.children.lock().insert(EntryChild(entry.clone()))?,
+			None => None,
+		};
+		let mut lru = LRU.lock();
+		if let Some(old) = old {
+			// Cached children are always in the LRU
+			unsafe {
+				lru.remove(&old.0);
+			}
 		}
-		LRU.lock().insert_front(entry.clone());
+		lru.insert_front(entry.clone());
 		Ok(entry)
 	}
 
@@ -239,10 +291,17 @@ pub fn shrink_entries() -> bool {
 			continue;
 		};
 		let mut parent_children = parent.children.lock();
-		if Arc::strong_count(&entry) > 3 {
+		// The entry may have been removed from the cache (unlink or rename) while still being
+		// referenced, in which case another entry may now have the same name
+		let cached = parent_children
+			.get(&*entry.name)
+			.is_some_and(|c| Arc::as_ptr(&c.0) == Arc::as_ptr(&entry));
+		if Arc::strong_count(&entry) > 2 + cached as usize {
 			continue;
 		}
-		parent_children.remove(&*entry.name);
+		if cached {
+			parent_children.remove(&*entry.name);
+		}
 		cursor.remove();
 		let Some(entry) = Arc::into_inner(entry) else {
 			continue;
@@ -310,9 +369,10 @@ impl ResolutionSettings {
 	/// `follow_link` tells whether symbolic links are followed.
 	pub fn for_process(proc: &Process, follow_link: bool) -> Self {
 		let fs = proc.fs.lock();
+		let ctx = fs.ctx.lock();
 		Self {
-			root: fs.chroot.clone(),
-			cwd: Some(fs.cwd.clone()),
+			root: ctx.chroot.clone(),
+			cwd: Some(ctx.cwd.clone()),
 
 			access_profile: fs.access_profile,
 
@@ -339,10 +399,34 @@ pub enum Resolved<'s> {
 	},
 }
 
+/// Looks for an entry in the directory `dir` whose name matches `name` with ASCII case folding.
+///
+/// If found, the function returns the actual name of the entry.
+fn casefold_lookup(dir: &Node, name: &[u8]) -> EResult<Option<String>> {
+	let mut found = None;
+	let mut ctx = DirContext {
+		write: &mut |ent| {
+			if !ent.name.eq_ignore_ascii_case(name) {
+				return Ok(true);
+			}
+			found = Some(String::try_from(ent.name)?);
+			Ok(false)
+		},
+		off: 0,
+	};
+	dir.node_ops.iter_entries(dir, &mut ctx)?;
+	Ok(found)
+}
+
 /// Resolves an entry with the given `name`, in the given `lookup_dir`.
 ///
 /// If the entry does not exist in cache or on the filesystem, the function returns a negative
-/// entry.
+/// entry. If the filesystem allows caching, the negative entry is cached so that subsequent
+/// lookups of the same non-existent file do not reach the filesystem.
+///
+/// If `lookup_dir` belongs to a mountpoint with [`mountpoint::FLAG_CASEFOLD`], a name that is not
+/// found as is gets matched case-insensitively. In this case, negative entries are not cached
+/// since the creation of a file with a different case would not invalidate them.
 fn resolve_entry(lookup_dir: &Arc<Entry>, name: &[u8]) -> EResult<Arc<Entry>> {
 	let mut children = lookup_dir.children.lock();
 	// Try to get from cache first
@@ -361,8 +445,18 @@ fn resolve_entry(lookup_dir: &Arc<Entry>, name: &[u8]) -> EResult<Arc<Entry>> {
 	lookup_dir_node
 		.node_ops
 		.lookup_entry(lookup_dir_node, &mut entry)?;
+	let casefold = entry.is_negative()
+		&& mountpoint::of_entry(lookup_dir)
+			.is_some_and(|mp| mp.flags & mountpoint::FLAG_CASEFOLD != 0);
+	if casefold {
+		let real_name = casefold_lookup(lookup_dir_node, name)?;
+		if let Some(real_name) = real_name.filter(|n| n.as_bytes() != name) {
+			drop(children);
+			return resolve_entry(lookup_dir, real_name.as_bytes());
+		}
+	}
 	let entry = Arc::new(entry)?;
-	if lookup_dir_node.fs.ops.cache_entries() {
+	if lookup_dir_node.fs.ops.cache_entries() && !casefold {
 		// Insert in cache. Do not use `link_parent` to keep `children` locked
 		children.insert(EntryChild(entry.clone()))?;
 		drop(children);
@@ -371,6 +465,17 @@ fn resolve_entry(lookup_dir: &Arc<Entry>, name: &[u8]) -> EResult<Arc<Entry>> {
 	Ok(entry)
 }
 
+/// Tells whether an entry with the given `name` in the directory `dir` is in cache and exists.
+///
+/// This allows failing early with [`errno::EEXIST`] when creating a file, without reaching the
+/// filesystem.
+fn is_cached_positive(dir: &Entry, name: &[u8]) -> bool {
+	dir.children
+		.lock()
+		.get(name)
+		.is_some_and(|ent| !ent.0.is_negative())
+}
+
 /// Resolves the symbolic link `link` and returns the target.
 ///
 /// Arguments:
@@ -391,7 +496,11 @@ fn resolve_link(
 	if unlikely(symlink_rec + 1 > SYMLOOP_MAX) {
 		return Err(errno!(ELOOP));
 	}
-	let target = link.node().readlink()?;
+	let node = link.node();
+	if let Some(target) = node.node_ops.follow_link(node)? {
+		return Ok(target);
+	}
+	let target = node.readlink()?;
 	// Resolve link
 	let rs = ResolutionSettings {
 		root,
@@ -408,6 +517,15 @@ fn resolve_link(
 	Ok(target)
 }
 
+/// Returns the parent of `entry`, or `None` if `entry` is the root directory `root` or the root of
+/// the VFS.
+fn parent_in(entry: &Arc<Entry>, root: &Arc<Entry>) -> Option<Arc<Entry>> {
+	if Arc::as_ptr(entry) == Arc::as_ptr(root) {
+		return None;
+	}
+	entry.parent.clone()
+}
+
 /// Implementation of [`resolve_path`].
 ///
 /// `symlink_rec` is the number of recursions due to symbolic links resolution.
@@ -438,8 +556,9 @@ fn resolve_path_impl<'p>(
 		// Get the name of the next entry
 		let name = match comp {
 			Component::ParentDir => {
-				if let Some(parent) = &lookup_dir.parent {
-					lookup_dir = parent.clone();
+				// Resolution cannot go above the root directory
+				if let Some(parent) = parent_in(&lookup_dir, &settings.root) {
+					lookup_dir = parent;
 				}
 				continue;
 			}
@@ -474,8 +593,8 @@ fn resolve_path_impl<'p>(
 			return Ok(Resolved::Found(lookup_dir));
 		}
 		Component::ParentDir => {
-			if let Some(parent) = &lookup_dir.parent {
-				lookup_dir = parent.clone();
+			if let Some(parent) = parent_in(&lookup_dir, &settings.root) {
+				lookup_dir = parent;
 			}
 			return Ok(Resolved::Found(lookup_dir));
 		}
@@ -558,8 +677,9 @@ pub fn get_file_from_path(
 	get_file_from_path_opt(path, resolution_settings)?.ok_or_else(|| errno!(ENOENT))
 }
 
-/// Updates status of a node.
-pub fn set_stat(node: &Node, set: &StatSet) -> EResult<()> {
+/// Updates status of the node of `entry`.
+pub fn set_stat(entry: &Entry, set: &StatSet) -> EResult<()> {
+	let node = entry.node();
 	let mut stat = node.stat.lock();
 	if let Some(mode) = set.mode {
 		stat.mode = (stat.mode & !0o7777) | (mode & 0o7777);
@@ -579,7 +699,9 @@ pub fn set_stat(node: &Node, set: &StatSet) -> EResult<()> {
 	if let Some(atime) = set.atime {
 		stat.atime = atime;
 	}
+	drop(stat);
 	node.dirty.store(true, Release);
+	notify_entry(entry, IN_ATTRIB);
 	Ok(())
 }
 
@@ -614,6 +736,9 @@ pub fn create_file(
 	if !ap.can_write_directory(&parent_stat) {
 		return Err(errno!(EACCES));
 	}
+	if is_cached_positive(&parent, name) {
+		return Err(errno!(EEXIST));
+	}
 	stat.nlink = 0;
 	stat.uid = ap.euid;
 	stat.gid = if parent_stat.mode & perm::S_ISGID != 0 {
@@ -625,11 +750,17 @@ pub fn create_file(
 	};
 	// Add file to filesystem
 	let parent_node = parent.node();
-	let node = parent_node.fs.ops.create_node(&parent_node.fs, stat)?;
+	let node = parent_node
+		.fs
+		.ops
+		.create_node(&parent_node.fs, Some(parent_node), stat)?;
 	// Add link to filesystem
 	let ent = Entry::new(String::try_from(name)?, Some(parent.clone()), Some(node));
 	parent_node.node_ops.link(parent_node.clone(), &ent)?;
-	Ok(ent.link_parent()?)
+	let ent = ent.link_parent()?;
+	invalidate_listing(parent_node);
+	inotify::notify_child(parent_node, name, ent.node(), IN_CREATE, 0);
+	Ok(ent)
 }
 
 /// Creates a new hard link to the given target file.
@@ -672,13 +803,38 @@ pub fn link(
 	if !parent.node().is_same_fs(&target) {
 		return Err(errno!(EXDEV));
 	}
+	if is_cached_positive(parent, &name) {
+		return Err(errno!(EEXIST));
+	}
 	// Add link to the filesystem
 	let ent = Entry::new(name, Some(parent.clone()), Some(target));
 	parent.node().node_ops.link(parent.node().clone(), &ent)?;
-	ent.link_parent()?;
+	let ent = ent.link_parent()?;
+	invalidate_listing(parent.node());
+	inotify::notify(ent.node(), IN_ATTRIB);
+	inotify::notify_child(parent.node(), &ent.name, ent.node(), IN_CREATE, 0);
 	Ok(())
 }
 
+/// Invalidates the cached listing of the directory `dir` after it has been modified, if the
+/// filesystem caches listings.
+fn invalidate_listing(dir: &Node) {
+	if let Some(cache) = dir.fs.ops.dir_cache() {
+		cache.invalidate(dir.inode);
+	}
+}
+
+/// Reports the events in `mask` to the watches on `entry`'s file and on its parent directory.
+fn notify_entry(entry: &Entry, mask: u32) {
+	let Some(node) = &entry.node else {
+		return;
+	};
+	inotify::notify(node, mask);
+	if let Some(parent) = &entry.parent {
+		inotify::notify_child(parent.node(), &entry.name, node, mask, 0);
+	}
+}
+
 /// Removes a hard link to a file.
 ///
 /// Arguments:
@@ -725,6 +881,15 @@ pub fn unlink(entry: Arc<Entry>, ap: &AccessProfile) -> EResult<()> {
 	children.remove(entry.name.as_bytes());
 	// Drop to avoid deadlock
 	drop(children);
+	invalidate_listing(dir_node);
+	let node = entry.node();
+	inotify::notify_child(dir_node, &entry.name, node, IN_DELETE, 0);
+	// The file itself is gone with its last link
+	if node.stat.lock().nlink == 0 {
+		inotify::notify(node, IN_DELETE_SELF);
+	} else {
+		inotify::notify(node, IN_ATTRIB);
+	}
 	// Remove the underlying node if this was the last reference to it
 	Entry::release(entry)?;
 	Ok(())
@@ -758,6 +923,9 @@ pub fn symlink(
 	if !ap.can_write_directory(&parent_stat) {
 		return Err(errno!(EACCES));
 	}
+	if is_cached_positive(parent, name) {
+		return Err(errno!(EEXIST));
+	}
 	stat.mode = FileType::Link.to_mode() | 0o777;
 	stat.nlink = 0;
 	stat.uid = ap.euid;
@@ -770,12 +938,17 @@ pub fn symlink(
 	};
 	// Create node
 	let parent_node = parent.node();
-	let node = parent_node.fs.ops.create_node(&parent_node.fs, stat)?;
+	let node = parent_node
+		.fs
+		.ops
+		.create_node(&parent_node.fs, Some(parent_node), stat)?;
 	node.node_ops.writelink(&node, target)?;
 	// Add link to the filesystem
 	let ent = Entry::new(String::try_from(name)?, Some(parent.clone()), Some(node));
 	parent_node.node_ops.link(parent_node.clone(), &ent)?;
-	ent.link_parent()?;
+	let ent = ent.link_parent()?;
+	invalidate_listing(parent_node);
+	inotify::notify_child(parent_node, name, ent.node(), IN_CREATE, 0);
 	Ok(())
 }
 
@@ -840,5 +1013,44 @@ pub fn rename(
 	// Invalidate cache
 	old_parent.children.lock().remove(&*old.name);
 	new_parent.children.lock().remove(new_name);
+	invalidate_listing(old_parent.node());
+	invalidate_listing(new_parent.node());
+	// Notify, binding both sides of the move with the same cookie
+	let node = old.node();
+	let cookie = inotify::next_cookie();
+	inotify::notify_child(old_parent.node(), &old.name, node, IN_MOVED_FROM, cookie);
+	inotify::notify_child(new_parent.node(), new_name, node, IN_MOVED_TO, cookie);
+	inotify::notify(node, IN_MOVE_SELF);
+	// The replaced file is gone with its last link
+	if let Some(replaced) = &new.node {
+		if replaced.stat.lock().nlink == 0 {
+			inotify::notify(replaced, IN_DELETE_SELF);
+		}
+	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn vfs_path_in_root() {
+		let new = |name: &[u8], parent| {
+			Arc::new(Entry::new(String::try_from(name).unwrap(), parent, None)).unwrap()
+		};
+		let root = new(b"", None);
+		let a = new(b"a", Some(root.clone()));
+		let b = new(b"b", Some(a.clone()));
+		assert_eq!(Entry::get_path(&b).unwrap().as_bytes(), b"/a/b");
+		assert_eq!(Entry::get_path_in(&b, &a).unwrap().as_bytes(), b"/b");
+		assert_eq!(Entry::get_path_in(&a, &a).unwrap().as_bytes(), b"/");
+		assert_eq!(
+			Entry::get_path_in(&root, &a).unwrap().as_bytes(),
+			b"(unreachable)/"
+		);
+		assert!(b.is_in(&a));
+		assert!(!root.is_in(&a));
+		assert!(parent_in(&a, &a).is_none());
+	}
+}
@@ -21,20 +21,30 @@
 use crate::{
 	device::{BLK_DEVICES, DeviceID},
 	file::{
-		FileType, fs,
+		FileType,
+		buffer::watch_queue::{
+			MountNotification, NOTIFY_MOUNT_MOVE, NOTIFY_MOUNT_NEW_MOUNT, NOTIFY_MOUNT_UNMOUNT,
+			WATCH_TYPE_MOUNT_NOTIFY, WatchList,
+		},
+		fs,
 		fs::{Filesystem, FilesystemType},
+		perm::AccessProfile,
 		vfs,
 		vfs::{EntryChild, ResolutionSettings},
 	},
 	sync::mutex::Mutex,
 };
-use core::fmt;
+use core::{
+	fmt, iter, mem,
+	sync::atomic::{AtomicU32, Ordering::Relaxed},
+};
 use utils::{
 	TryClone,
 	collections::{
 		hashmap::HashMap,
 		path::{Path, PathBuf},
 		string::String,
+		vec::Vec,
 	},
 	errno,
 	errno::{AllocResult, ENOENT, EResult},
@@ -66,6 +76,67 @@ pub const FLAG_SILENT: u32 = 0b001000000000;
 pub const FLAG_STRICTATIME: u32 = 0b010000000000;
 /// Makes writes on this filesystem synchronous.
 pub const FLAG_SYNCHRONOUS: u32 = 0b100000000000;
+/// Directory lookups on the filesystem are case-insensitive, with ASCII case folding.
+///
+/// This flag is set from the `casefold` mount option, not from mount flags.
+pub const FLAG_CASEFOLD: u32 = 0b1000000000000;
+/// Errors detected on the filesystem are ignored.
+///
+/// This flag is set from the `errors=continue` mount option, not from mount flags.
+pub const FLAG_ERRORS_CONTINUE: u32 = 0b10000000000000;
+/// Errors detected on the filesystem switch it to read-only.
+///
+/// This flag is set from the `errors=remount-ro` mount option, not from mount flags.
+pub const FLAG_ERRORS_REMOUNT_RO: u32 = 0b100000000000000;
+/// Errors detected on the filesystem trigger a kernel panic.
+///
+/// This flag is set from the `errors=panic` mount option, not from mount flags.
+pub const FLAG_ERRORS_PANIC: u32 = 0b1000000000000000;
+/// All the flags selecting an error policy.
+pub const FLAG_ERRORS: u32 = FLAG_ERRORS_CONTINUE | FLAG_ERRORS_REMOUNT_RO | FLAG_ERRORS_PANIC;
+/// The consistency of the filesystem is checked at mount.
+///
+/// This flag is set from the `check` mount option, not from mount flags.
+pub const FLAG_CHECK: u32 = 0b10000000000000000;
+
+/// Flags applying to the mountpoint itself, as opposed to the filesystem.
+pub const PER_MOUNT_FLAGS: u32 =
+	FLAG_NOSUID | FLAG_NODEV | FLAG_NOEXEC | FLAG_NOATIME | FLAG_NODIRATIME | FLAG_RELATIME;
+
+/// Displays the read-only flag and the options in a set of mount flags, as a comma-separated
+/// list.
+pub struct DisplayOptions(pub u32);
+
+impl fmt::Display for DisplayOptions {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		const OPTIONS: [(u32, &str); 13] = [
+			(FLAG_NOSUID, "nosuid"),
+			(FLAG_NODEV, "nodev"),
+			(FLAG_NOEXEC, "noexec"),
+			(FLAG_SYNCHRONOUS, "sync"),
+			(FLAG_MANDLOCK, "mand"),
+			(FLAG_NOATIME, "noatime"),
+			(FLAG_NODIRATIME, "nodiratime"),
+			(FLAG_RELATIME, "relatime"),
+			(FLAG_CASEFOLD, "casefold"),
+			(FLAG_ERRORS_CONTINUE, "errors=continue"),
+			(FLAG_ERRORS_REMOUNT_RO, "errors=remount-ro"),
+			(FLAG_ERRORS_PANIC, "errors=panic"),
+			(FLAG_CHECK, "check"),
+		];
+		f.write_str(if self.0 & FLAG_RDONLY != 0 {
+			"ro"
+		} else {
+			"rw"
+		})?;
+		for (flag, name) in OPTIONS {
+			if self.0 & flag != 0 {
+				write!(f, ",{name}")?;
+			}
+		}
+		Ok(())
+	}
+}
 
 /// Value specifying the device from which a filesystem is mounted.
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -134,12 +205,12 @@ pub static FILESYSTEMS: Mutex<HashMap<DeviceID, Arc<Filesystem>>> = Mutex::new(H
 /// - `source` is the source of the mountpoint.
 /// - `fs_type` is the filesystem type. If `None`, the function tries to detect it automatically.
 /// - `target_path` is the path at which the filesystem is to be mounted.
-/// - `readonly` tells whether the filesystem is mount in readonly.
+/// - `flags` are the mount flags.
 fn get_fs(
 	source: &MountSource,
 	fs_type: Option<Arc<dyn FilesystemType>>,
 	target_path: PathBuf,
-	readonly: bool,
+	flags: u32,
 ) -> EResult<Arc<Filesystem>> {
 	match source {
 		MountSource::Device(dev_id) => {
@@ -158,7 +229,7 @@ fn get_fs(
 				Some(f) => f,
 				None => fs::detect(&dev)?,
 			};
-			let fs = fs_type.load_filesystem(Some(dev), target_path, readonly)?;
+			let fs = fs_type.load_filesystem(Some(dev), target_path, flags)?;
 			filesystems.insert(*dev_id, fs.clone())?;
 			Ok(fs)
 		}
@@ -167,7 +238,7 @@ fn get_fs(
 				Some(f) => f,
 				None => fs::get_type(name).ok_or_else(|| errno!(ENODEV))?,
 			};
-			fs_type.load_filesystem(None, target_path, readonly)
+			fs_type.load_filesystem(None, target_path, flags)
 		}
 	}
 }
@@ -175,6 +246,8 @@ fn get_fs(
 /// A mount point, allowing to attach a filesystem to a directory on the VFS.
 #[derive(Debug)]
 pub struct MountPoint {
+	/// The ID of the mountpoint.
+	pub id: u32,
 	/// Mount flags.
 	pub flags: u32,
 	/// The source of the mountpoint.
@@ -185,6 +258,18 @@ pub struct MountPoint {
 	pub root_entry: Arc<vfs::Entry>,
 }
 
+impl MountPoint {
+	/// Returns the mount flags, with [`FLAG_RDONLY`] set if the filesystem has been switched to
+	/// read-only since it was mounted.
+	pub fn get_flags(&self) -> u32 {
+		if self.fs.ops.is_readonly() {
+			self.flags | FLAG_RDONLY
+		} else {
+			self.flags
+		}
+	}
+}
+
 impl Drop for MountPoint {
 	fn drop(&mut self) {
 		// If not associated with a device, stop
@@ -206,10 +291,186 @@ impl Drop for MountPoint {
 	}
 }
 
-/// The list of mountpoints with their respective ID.
-pub static MOUNT_POINTS: Mutex<HashMap<*const vfs::Entry, Arc<MountPoint>>> =
+/// The ID of the next mountpoint to be created.
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// A mount namespace, holding its own table of mountpoints.
+///
+/// Each namespace has its own tree of VFS entries. The namespace an entry belongs to is found by
+/// walking up to the top of its tree.
+#[derive(Debug)]
+pub struct MountNamespace {
+	/// The top of the namespace's tree of entries.
+	top: Arc<vfs::Entry>,
+	/// The root directory of the namespace.
+	///
+	/// This is the same as `top`, unless the root has been changed with `pivot_root`.
+	pub root: Mutex<Arc<vfs::Entry>>,
+	/// The mountpoints of the namespace, indexed by their root entry.
+	pub mount_points: Mutex<HashMap<*const vfs::Entry, Arc<MountPoint>>>,
+	/// Watches on changes to the mountpoints of the namespace.
+	pub watches: WatchList,
+}
+
+impl MountNamespace {
+	/// Returns the entry in the namespace at the same path as `ent` in its own namespace.
+	///
+	/// If the path does not exist in the namespace, the function returns the namespace's root
+	/// directory.
+	pub fn translate(&self, ent: &Arc<vfs::Entry>) -> Arc<vfs::Entry> {
+		let root = self.root.lock().clone();
+		let Ok(path) = vfs::Entry::get_path(ent) else {
+			return root;
+		};
+		let rs = ResolutionSettings {
+			root: self.top.clone(),
+			cwd: None,
+
+			access_profile: AccessProfile::KERNEL,
+
+			create: false,
+			follow_link: false,
+		};
+		vfs::get_file_from_path(&path, &rs).unwrap_or(root)
+	}
+}
+
+/// The list of mount namespaces, indexed by the top of their tree.
+pub static NAMESPACES: Mutex<HashMap<*const vfs::Entry, Arc<MountNamespace>>> =
 	Mutex::new(HashMap::new());
 
+/// Returns the mount namespace the entry `ent` belongs to.
+pub fn namespace_of(ent: &vfs::Entry) -> Option<Arc<MountNamespace>> {
+	let top = iter::successors(Some(ent), |e| e.parent.as_deref()).last()?;
+	NAMESPACES.lock().get(&(top as _)).cloned()
+}
+
+/// Returns the mount namespace of the root of the VFS, which is the one of the init process.
+pub fn init_namespace() -> Arc<MountNamespace> {
+	namespace_of(&vfs::ROOT).expect("files management is not initialized")
+}
+
+/// Copies the mount namespace `ns`.
+///
+/// The mountpoints visible from the top of the namespace are re-created in the new namespace,
+/// with new IDs. Subsequent mounts and unmounts in either namespace do not affect the other.
+pub fn copy_namespace(ns: &MountNamespace) -> EResult<Arc<MountNamespace>> {
+	// Mountpoints are sorted by depth so that the mountpoints containing a directory are created
+	// before the ones mounted on it
+	let mut mps = Vec::new();
+	for mp in ns.mount_points.lock().iter().map(|(_, mp)| mp) {
+		let depth = iter::successors(Some(&*mp.root_entry), |e| e.parent.as_deref()).count();
+		mps.push((depth, mp.clone()))?;
+	}
+	mps.sort_unstable_by_key(|(depth, mp)| (*depth, mp.id));
+	let top = Arc::new(vfs::Entry::new(String::new(), None, ns.top.node.clone()))?;
+	let new = Arc::new(MountNamespace {
+		top: top.clone(),
+		root: Mutex::new(top.clone()),
+		mount_points: Default::default(),
+		watches: Default::default(),
+	})?;
+	NAMESPACES.lock().insert(Arc::as_ptr(&top), new.clone())?;
+	let res = (|| {
+		for (_, mp) in mps {
+			if !is_visible(ns, &mp.root_entry) {
+				continue;
+			}
+			let target = new.translate(&mp.root_entry);
+			let root_entry = match &mp.root_entry.parent {
+				Some(_) => {
+					// The mountpoint's parent does not exist in the new tree
+					if target.parent.is_none() {
+						continue;
+					}
+					Arc::new(vfs::Entry::new(
+						target.name.try_clone()?,
+						target.parent.clone(),
+						mp.root_entry.node.clone(),
+					))?
+				}
+				None => top.clone(),
+			};
+			let mountpoint = Arc::new(MountPoint {
+				id: NEXT_ID.fetch_add(1, Relaxed),
+				flags: mp.flags,
+				source: mp.source.try_clone()?,
+				fs: mp.fs.clone(),
+				root_entry: root_entry.clone(),
+			})?;
+			new.mount_points
+				.lock()
+				.insert(Arc::as_ptr(&root_entry), mountpoint)?;
+			if let Some(parent) = &root_entry.parent {
+				parent
+					.children
+					.lock()
+					.insert(EntryChild(root_entry.clone()))?;
+			}
+		}
+		let root = ns.root.lock().clone();
+		let root = new.translate(&root);
+		*new.root.lock() = root;
+		Ok(())
+	})();
+	if let Err(e) = res {
+		put_namespace(&new);
+		return Err(e);
+	}
+	Ok(new)
+}
+
+/// Tells whether the mountpoint whose root entry is `ent` is reachable from the top of the
+/// namespace `ns`, that is if it is not detached nor hidden by another mountpoint.
+fn is_visible(ns: &MountNamespace, ent: &Arc<vfs::Entry>) -> bool {
+	let mut cur = ent;
+	while let Some(parent) = &cur.parent {
+		// Path resolution locks mountpoints while holding a directory's children, so do not nest
+		// the locks the other way
+		let is_mountpoint = ns.mount_points.lock().get(&Arc::as_ptr(cur)).is_some();
+		if is_mountpoint {
+			let attached = parent
+				.children
+				.lock()
+				.get(cur.name.as_bytes())
+				.is_some_and(|c| Arc::as_ptr(&c.0) == Arc::as_ptr(cur));
+			if !attached {
+				return false;
+			}
+		}
+		cur = parent;
+	}
+	Arc::as_ptr(cur) == Arc::as_ptr(&ns.top)
+}
+
+/// Releases a reference to the mount namespace `ns`.
+///
+/// If the caller holds the last reference, the namespace is destroyed. The mount namespace of
+/// the root of the VFS is never destroyed.
+pub fn put_namespace(ns: &Arc<MountNamespace>) {
+	{
+		let mut namespaces = NAMESPACES.lock();
+		// The caller's reference + the one held by `NAMESPACES` = `2`
+		if Arc::strong_count(ns) > 2 || Arc::as_ptr(&ns.top) == Arc::as_ptr(&vfs::ROOT) {
+			return;
+		}
+		namespaces.remove(&Arc::as_ptr(&ns.top));
+	}
+	// Detach mountpoints from the tree to break reference cycles
+	let mps = mem::take(&mut *ns.mount_points.lock());
+	for (_, mp) in mps.iter() {
+		if let Some(parent) = &mp.root_entry.parent {
+			let mut children = parent.children.lock();
+			let cached = children
+				.get(mp.root_entry.name.as_bytes())
+				.is_some_and(|c| Arc::as_ptr(&c.0) == Arc::as_ptr(&mp.root_entry));
+			if cached {
+				children.remove(mp.root_entry.name.as_bytes());
+			}
+		}
+	}
+}
+
 /// Creates a new mountpoint.
 ///
 /// If a mountpoint is already present at the same path, the function fails with [`errno::EINVAL`].
@@ -218,7 +479,8 @@ pub static MOUNT_POINTS: Mutex<HashMap<*const vfs::Entry, Arc<MountPoint>>> =
 /// - `source` is the source of the mountpoint
 /// - `fs_type` is the filesystem type. If `None`, the function tries to detect it automatically
 /// - `flags` are the mount flags
-/// - `target` is the target directory. If `None`, the mountpoint is root
+/// - `target` is the target directory. If `None`, the mountpoint is the root of a new mount
+///   namespace
 ///
 /// The function returns the root VFS entry of the mountpoint.
 pub fn create(
@@ -228,23 +490,41 @@ pub fn create(
 	target: Option<Arc<vfs::Entry>>,
 ) -> EResult<Arc<vfs::Entry>> {
 	// Get filesystem
-	let (target_path, name, parent) = match target {
+	let (target_path, name, parent, ns) = match target {
 		Some(target) => (
 			vfs::Entry::get_path(&target)?,
 			target.name.try_clone()?,
 			target.parent.clone(),
+			Some(namespace_of(&target).ok_or_else(|| errno!(EINVAL))?),
 		),
-		None => (PathBuf::root()?, String::new(), None),
+		None => (PathBuf::root()?, String::new(), None, None),
 	};
-	let fs = get_fs(&source, fs_type, target_path, flags & FLAG_RDONLY != 0)?;
-	let mut mps = MOUNT_POINTS.lock();
+	let fs = get_fs(&source, fs_type, target_path, flags)?;
 	// TODO get root node from cache if present instead
 	// Get filesystem root node
 	let root = fs.ops.root(&fs)?;
 	// Create an entry for the root of the mountpoint
 	let root_entry = Arc::new(vfs::Entry::new(name, parent.clone(), Some(root)))?;
+	let ns = match ns {
+		Some(ns) => ns,
+		None => {
+			let ns = Arc::new(MountNamespace {
+				top: root_entry.clone(),
+				root: Mutex::new(root_entry.clone()),
+				mount_points: Default::default(),
+				watches: Default::default(),
+			})?;
+			NAMESPACES
+				.lock()
+				.insert(Arc::as_ptr(&root_entry), ns.clone())?;
+			ns
+		}
+	};
+	let mut mps = ns.mount_points.lock();
 	// Create mountpoint
+	let id = NEXT_ID.fetch_add(1, Relaxed);
 	let mountpoint = Arc::new(MountPoint {
+		id,
 		flags,
 		source,
 		fs,
@@ -252,6 +532,9 @@ pub fn create(
 	})?;
 	// If the next insertion fails, this will be undone by the implementation of `Drop`
 	mps.insert(Arc::as_ptr(&root_entry), mountpoint)?;
+	// Path resolution locks mountpoints while holding a directory's children, so do not nest
+	// the locks the other way
+	drop(mps);
 	// Replace `target` with the mountpoint's root in the tree
 	if let Some(target_parent) = &parent {
 		target_parent
@@ -259,9 +542,21 @@ pub fn create(
 			.lock()
 			.insert(EntryChild(root_entry.clone()))?;
 	}
+	notify(&ns, NOTIFY_MOUNT_NEW_MOUNT, id);
 	Ok(root_entry)
 }
 
+/// Posts a notification of subtype `subtype` about the mountpoint with ID `mnt_id` to the watches
+/// on the namespace `ns`.
+fn notify(ns: &MountNamespace, subtype: u32, mnt_id: u32) {
+	let note = MountNotification {
+		watch: Default::default(),
+		mnt_id,
+		__reserved: 0,
+	};
+	ns.watches.post(WATCH_TYPE_MOUNT_NOTIFY, subtype, note);
+}
+
 /// Removes the mountpoint at the given `target` entry.
 ///
 /// Data is synchronized to the associated storage device, if any, before removing the mountpoint.
@@ -279,13 +574,87 @@ pub fn remove(target: Arc<vfs::Entry>) -> EResult<()> {
 	};
 	parent.children.lock().remove(target.name.as_bytes());
 	// TODO release node and children
-	MOUNT_POINTS.lock().remove(&Arc::as_ptr(&target));
+	if let Some(ns) = namespace_of(&target) {
+		let mp = ns.mount_points.lock().remove(&Arc::as_ptr(&target));
+		if let Some(mp) = mp {
+			notify(&ns, NOTIFY_MOUNT_UNMOUNT, mp.id);
+		}
+	}
 	Ok(())
 }
 
+/// Moves the mountpoint whose root entry is `source` onto the `target` directory.
+///
+/// The entries under `source` are not moved, they are looked up again from the new location.
+/// Mountpoints under `source` are not moved either.
+///
+/// If `source` is not a mountpoint, the function returns [`errno::EINVAL`].
+///
+/// The function returns the new root VFS entry of the mountpoint.
+pub fn move_to(source: &Arc<vfs::Entry>, target: &Arc<vfs::Entry>) -> EResult<Arc<vfs::Entry>> {
+	let ns = namespace_of(source).ok_or_else(|| errno!(EINVAL))?;
+	let mut mps = ns.mount_points.lock();
+	let old = mps
+		.get(&Arc::as_ptr(source))
+		.ok_or_else(|| errno!(EINVAL))?
+		.clone();
+	let root_entry = Arc::new(vfs::Entry::new(
+		target.name.try_clone()?,
+		target.parent.clone(),
+		Some(source.node().clone()),
+	))?;
+	let mountpoint = Arc::new(MountPoint {
+		id: old.id,
+		flags: old.flags,
+		source: old.source.try_clone()?,
+		fs: old.fs.clone(),
+		root_entry: root_entry.clone(),
+	})?;
+	mps.insert(Arc::as_ptr(&root_entry), mountpoint)?;
+	mps.remove(&Arc::as_ptr(source));
+	drop(mps);
+	// Detach the mountpoint from its previous location, then attach it to the new one
+	if let Some(parent) = &source.parent {
+		parent.children.lock().remove(source.name.as_bytes());
+	}
+	if let Some(parent) = &target.parent {
+		parent
+			.children
+			.lock()
+			.insert(EntryChild(root_entry.clone()))?;
+	}
+	notify(&ns, NOTIFY_MOUNT_MOVE, old.id);
+	Ok(root_entry)
+}
+
 /// Returns the mountpoint for the root entry `ent`.
 ///
 /// If `ent` is not associated to a mountpoint, the function returns `None`.
 pub fn from_entry(ent: &vfs::Entry) -> Option<Arc<MountPoint>> {
-	MOUNT_POINTS.lock().get(&(ent as _)).cloned()
+	namespace_of(ent)?
+		.mount_points
+		.lock()
+		.get(&(ent as _))
+		.cloned()
+}
+
+/// Returns the mountpoint containing the entry `ent`.
+///
+/// If no mountpoint is found (which should not happen for an entry of the VFS), the function
+/// returns `None`.
+pub fn of_entry(ent: &vfs::Entry) -> Option<Arc<MountPoint>> {
+	let ns = namespace_of(ent)?;
+	let mps = ns.mount_points.lock();
+	let mut cur = ent;
+	loop {
+		if let Some(mp) = mps.get(&(cur as _)) {
+			break Some(mp.clone());
+		}
+		cur = cur.parent.as_deref()?;
+	}
+}
+
+/// Tells whether the mountpoint containing the entry `ent` has all the flags in `flags` set.
+pub fn entry_has_flags(ent: &vfs::Entry, flags: u32) -> bool {
+	of_entry(ent).is_some_and(|mp| mp.flags & flags == flags)
 }
@@ -22,17 +22,22 @@ use crate::{
 	file::{
 		FileType, INode, Stat,
 		fs::{FileOps, Filesystem, NodeOps},
+		lock::FileLocks,
 	},
 	memory::{cache::MappedNode, user::UserSlice},
 	sync::mutex::Mutex,
 };
 use core::{
 	ptr,
-	sync::atomic::{AtomicBool, Ordering::Acquire},
+	sync::atomic::{
+		AtomicBool, AtomicIsize,
+		Ordering::{Acquire, Relaxed},
+	},
 };
 use utils::{
 	boxed::Box,
 	collections::{path::PathBuf, string::String},
+	errno,
 	errno::EResult,
 	limits::SYMLINK_MAX,
 	ptr::arc::Arc,
@@ -61,6 +66,11 @@ pub struct Node {
 	pub lock: Mutex<()>,
 	/// The node as mapped
 	pub mapped: MappedNode,
+	/// If positive, the number of open files allowing to write to the node. If negative, the
+	/// number of programs being executed from the node
+	pub write_access: AtomicIsize,
+	/// The record locks placed on the node
+	pub locks: FileLocks,
 }
 
 impl Node {
@@ -83,6 +93,36 @@ impl Node {
 		ptr::eq(self.fs.as_ref(), other.fs.as_ref())
 	}
 
+	/// Acquires the right to write to the node.
+	///
+	/// If the node is being executed, the function returns [`errno::ETXTBSY`].
+	pub fn get_write_access(&self) -> EResult<()> {
+		self.write_access
+			.fetch_update(Relaxed, Relaxed, |n| (n >= 0).then_some(n + 1))
+			.map_err(|_| errno!(ETXTBSY))?;
+		Ok(())
+	}
+
+	/// Gives back the right acquired with [`Self::get_write_access`].
+	pub fn put_write_access(&self) {
+		self.write_access.fetch_sub(1, Relaxed);
+	}
+
+	/// Prevents writing to the node while it is being executed.
+	///
+	/// If the node is open for writing, the function returns [`errno::ETXTBSY`].
+	pub fn deny_write_access(&self) -> EResult<()> {
+		self.write_access
+			.fetch_update(Relaxed, Relaxed, |n| (n <= 0).then_some(n - 1))
+			.map_err(|_| errno!(ETXTBSY))?;
+		Ok(())
+	}
+
+	/// Cancels a previous call to [`Self::deny_write_access`].
+	pub fn allow_write_access(&self) {
+		self.write_access.fetch_add(1, Relaxed);
+	}
+
 	/// Reads the symbolic link.
 	pub fn readlink(&self) -> EResult<PathBuf> {
 		const INCREMENT: usize = 64;
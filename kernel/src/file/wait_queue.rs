@@ -20,12 +20,28 @@
 //! the resource is available.
 
 use crate::{
+	file::File,
 	process,
-	process::{Process, pid::Pid, scheduler::Scheduler},
+	process::{
+		Process,
+		pid::Pid,
+		scheduler::Scheduler,
+		signal::{SIGEV_NONE, SigEvent},
+	},
 	sync::mutex::{IntMutex, Mutex},
+	time::{
+		clock::{Clock, current_time_ns},
+		timer::Timer,
+		unit::Timestamp,
+	},
 };
 use core::mem;
-use utils::{collections::vec::Vec, errno, errno::EResult};
+use utils::{
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
 
 /// A queue of processes waiting on a resource.
 ///
@@ -44,14 +60,44 @@ impl WaitQueue {
 	/// Makes the current process wait until the given closure returns `Some`.
 	///
 	/// If waiting is interrupted by a signal handler, the function returns [`errno::EINTR`].
-	pub fn wait_until<F: FnMut() -> Option<T>, T>(&self, mut f: F) -> EResult<T> {
+	pub fn wait_until<F: FnMut() -> Option<T>, T>(&self, f: F) -> EResult<T> {
+		self.wait_until_timeout(f, None)
+	}
+
+	/// Same as [`Self::wait_until`], except the function returns [`errno::ETIMEDOUT`] once
+	/// `timeout` nanoseconds have elapsed.
+	///
+	/// If `timeout` is `None`, the function waits indefinitely.
+	pub fn wait_until_timeout<F: FnMut() -> Option<T>, T>(
+		&self,
+		mut f: F,
+		timeout: Option<Timestamp>,
+	) -> EResult<T> {
+		let end = timeout.map(|t| current_time_ns(Clock::Monotonic) + t);
+		// Timer waking the process up on timeout
+		let mut timer = None;
 		loop {
 			if let Some(val) = f() {
 				break Ok(val);
 			}
+			if end.is_some_and(|end| current_time_ns(Clock::Monotonic) >= end) {
+				return Err(errno!(ETIMEDOUT));
+			}
 			// Queue
 			{
 				let proc = Process::current();
+				if let (Some(timeout), None) = (timeout, &timer) {
+					let t = Timer::new(
+						Clock::Monotonic,
+						proc.get_pid(),
+						SigEvent {
+							sigev_notify: SIGEV_NONE,
+							..Default::default()
+						},
+					)?;
+					t.set_time(0, timeout)?;
+					timer = Some(t);
+				}
 				self.0.lock().push(proc.get_pid())?;
 				proc.set_state(process::State::Sleeping);
 			}
@@ -68,6 +114,25 @@ impl WaitQueue {
 		}
 	}
 
+	/// Adds the current process to the queue, without putting it to sleep.
+	///
+	/// This allows a process to wait on several queues at once. The caller is responsible for
+	/// putting the process to sleep, then for calling [`Self::unregister`] once woken up.
+	pub fn register(&self) -> AllocResult<()> {
+		let pid = Process::current().get_pid();
+		let mut pids = self.0.lock();
+		if !pids.contains(&pid) {
+			pids.push(pid)?;
+		}
+		Ok(())
+	}
+
+	/// Removes the current process from the queue, if present.
+	pub fn unregister(&self) {
+		let pid = Process::current().get_pid();
+		self.0.lock().retain(|p| *p != pid);
+	}
+
 	/// Wakes the next process in queue.
 	pub fn wake_next(&self) {
 		let proc = loop {
@@ -101,3 +166,50 @@ impl WaitQueue {
 		}
 	}
 }
+
+/// The set of files whose wait queues the current process is registered on, while waiting for
+/// events on several files at once (`select`, `poll` and `epoll`).
+///
+/// When dropped, the process is removed from all the queues.
+#[derive(Default)]
+pub struct PollWait {
+	/// The registered files, with the mask of events.
+	files: Vec<(Arc<File>, u32)>,
+	/// If set, at least one of the files does not provide wait queues, so that the process
+	/// cannot sleep and has to poll periodically.
+	busy: bool,
+}
+
+impl PollWait {
+	/// Registers the current process on the wait queues of `file` for the events in `mask`.
+	pub fn register(&mut self, file: Arc<File>, mask: u32) -> AllocResult<()> {
+		if file.ops.poll_wait(&file, mask, &mut WaitQueue::register)? {
+			self.files.push((file, mask))?;
+		} else {
+			self.busy = true;
+		}
+		Ok(())
+	}
+
+	/// Tells whether the process may sleep until woken up by one of the registered files.
+	pub fn can_sleep(&self) -> bool {
+		!self.busy
+	}
+
+	/// Removes the current process from all the wait queues it has been registered on.
+	pub fn clear(&mut self) {
+		for (file, mask) in mem::take(&mut self.files) {
+			let _ = file.ops.poll_wait(&file, mask, &mut |queue| {
+				queue.unregister();
+				Ok(())
+			});
+		}
+		self.busy = false;
+	}
+}
+
+impl Drop for PollWait {
+	fn drop(&mut self) {
+		self.clear();
+	}
+}
@@ -0,0 +1,161 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Boot-time initialization calls (initcalls).
+//!
+//! Subsystems register their initialization function with [`initcall!`], at a given [`Level`].
+//! Initcalls are placed in a dedicated section of the kernel's image by the linker, grouped by
+//! level, in the order of [`Level`].
+//!
+//! At boot, the kernel runs every initcall, level by level. Initcalls of the same level run in an
+//! unspecified order, so they must not depend on each other.
+//!
+//! The duration of each initcall is measured in cycles of the Time Stamp Counter, since clocks
+//! may not be running yet. With the `initcall_debug` command line argument, the kernel prints it.
+
+use crate::{arch::x86::rdtsc, cmdline::ArgsParser, println};
+use core::{fmt, slice};
+use utils::errno::EResult;
+
+/// The level of an initcall, in order of execution.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Level {
+	/// Facilities required by everything else, which do not depend on anything.
+	Early,
+	/// Architecture-specific hardware, such as clocks.
+	Arch,
+	/// Subsystems cores, which drivers register to.
+	Subsys,
+	/// Devices detection and drivers.
+	Device,
+	/// Everything that requires devices to be ready.
+	Late,
+}
+
+impl fmt::Display for Level {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Self::Early => "early",
+			Self::Arch => "arch",
+			Self::Subsys => "subsys",
+			Self::Device => "device",
+			Self::Late => "late",
+		};
+		fmt.write_str(name)
+	}
+}
+
+/// An initialization function called at boot.
+#[derive(Debug)]
+pub struct Initcall {
+	/// The name of the initcall.
+	pub name: &'static str,
+	/// The level at which the initcall runs.
+	pub level: Level,
+	/// The function to call, with the kernel's command line arguments.
+	pub func: fn(&ArgsParser) -> EResult<()>,
+}
+
+/// Registers an initcall.
+///
+/// Arguments:
+/// - the level, in lowercase: `early`, `arch`, `subsys`, `device` or `late`
+/// - the name of the initcall
+/// - the function to call, taking the kernel's command line arguments
+///
+/// Example:
+///
+/// ```ignore
+/// initcall!(subsys, crypto, |_| Ok(init()?));
+/// ```
+#[macro_export]
+macro_rules! initcall {
+	(early, $name:ident, $func:expr) => {
+		$crate::initcall!(@register ".initcall.early", Early, $name, $func);
+	};
+	(arch, $name:ident, $func:expr) => {
+		$crate::initcall!(@register ".initcall.arch", Arch, $name, $func);
+	};
+	(subsys, $name:ident, $func:expr) => {
+		$crate::initcall!(@register ".initcall.subsys", Subsys, $name, $func);
+	};
+	(device, $name:ident, $func:expr) => {
+		$crate::initcall!(@register ".initcall.device", Device, $name, $func);
+	};
+	(late, $name:ident, $func:expr) => {
+		$crate::initcall!(@register ".initcall.late", Late, $name, $func);
+	};
+	(@register $section:literal, $level:ident, $name:ident, $func:expr) => {
+		const _: () = {
+			#[used]
+			#[unsafe(link_section = $section)]
+			static INITCALL: $crate::initcall::Initcall = $crate::initcall::Initcall {
+				name: stringify!($name),
+				level: $crate::initcall::Level::$level,
+				func: $func,
+			};
+		};
+	};
+}
+
+unsafe extern "C" {
+	/// The beginning of the initcalls section, defined by the linker script.
+	static __initcall_start: u8;
+	/// The end of the initcalls section, defined by the linker script.
+	static __initcall_end: u8;
+}
+
+/// Returns the list of registered initcalls, in order of execution.
+pub fn list() -> &'static [Initcall] {
+	unsafe {
+		let begin = (&raw const __initcall_start).cast::<Initcall>();
+		let end = (&raw const __initcall_end).cast::<Initcall>();
+		slice::from_raw_parts(begin, end.offset_from(begin) as usize)
+	}
+}
+
+/// Runs every registered initcall, with the kernel's command line arguments `args`.
+///
+/// If an initcall fails, the kernel panics.
+pub(crate) fn run(args: &ArgsParser) {
+	let debug = args.is_initcall_debug();
+	for initcall in list() {
+		let begin = rdtsc();
+		let res = (initcall.func)(args);
+		let cycles = rdtsc().wrapping_sub(begin);
+		if debug {
+			println!(
+				"initcall {} ({}) returned after {cycles} cycles",
+				initcall.name, initcall.level
+			);
+		}
+		if let Err(e) = res {
+			panic!("Initcall `{}` failed! ({e})", initcall.name);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn initcall_order() {
+		assert!(list().is_sorted_by_key(|initcall| initcall.level));
+	}
+}
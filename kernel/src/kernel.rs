@@ -37,7 +37,6 @@
 #![feature(likely_unlikely)]
 #![feature(negative_impls)]
 #![feature(offset_of_enum)]
-#![feature(once_cell_try)]
 #![feature(pointer_is_aligned_to)]
 #![feature(ptr_metadata)]
 #![feature(strict_provenance_lints)]
@@ -55,12 +54,14 @@ pub mod acpi;
 pub mod arch;
 mod boot;
 pub mod cmdline;
+pub mod console;
 pub mod crypto;
 pub mod debug;
 pub mod device;
 pub mod elf;
 pub mod event;
 pub mod file;
+pub mod initcall;
 pub mod logger;
 pub mod memory;
 pub mod module;
@@ -72,29 +73,37 @@ pub mod power;
 #[macro_use]
 pub mod print;
 pub mod process;
+pub mod profile;
 pub mod selftest;
+pub mod softirq;
+pub mod stack_protector;
 pub mod sync;
 pub mod syscall;
 pub mod time;
 pub mod tty;
+pub mod uapi;
 
 use crate::{
-	arch::x86::{enable_sse, has_sse, idt, idt::IntFrame},
+	arch::x86::{apic, enable_sse, gdt, has_sse, idt, idt::IntFrame, smp},
 	file::{fs::initramfs, vfs, vfs::ResolutionSettings},
 	logger::LOGGER,
 	memory::{cache, vmem},
 	process::{
 		Process, exec,
 		exec::{ExecInfo, exec},
-		scheduler::{SCHEDULER, switch, switch::idle_task},
+		kthread,
+		mem_space::ksm,
+		scheduler,
+		scheduler::{switch, switch::idle_task, with_run_queue},
+		workqueue,
 	},
-	sync::mutex::Mutex,
 	tty::TTY,
 };
 use core::{ffi::c_void, hint::unlikely};
 pub use utils;
 use utils::{
-	collections::{path::Path, string::String, vec::Vec},
+	TryClone,
+	collections::{path::Path, string::String},
 	errno::EResult,
 	vec,
 };
@@ -107,9 +116,6 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// The path to the init process binary.
 const INIT_PATH: &[u8] = b"/sbin/init";
 
-/// The current hostname of the system.
-pub static HOSTNAME: Mutex<Vec<u8>> = Mutex::new(Vec::new());
-
 /// Launches the init process.
 ///
 /// `init_path` is the path to the init program.
@@ -125,7 +131,8 @@ fn init(init_path: String) -> EResult<IntFrame> {
 			ent,
 			ExecInfo {
 				path_resolution: &rs,
-				argv: vec![init_path]?,
+				path: init_path.as_bytes(),
+				argv: vec![init_path.try_clone()?]?,
 				envp: vec![
 					b"PATH=/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin:/usr/local/sbin"
 						.try_into()?,
@@ -135,17 +142,20 @@ fn init(init_path: String) -> EResult<IntFrame> {
 		)?;
 		let proc = Process::init()?;
 		exec(&proc, &mut frame, program_image)?;
-		SCHEDULER.lock().swap_current_process(proc);
+		with_run_queue(|rq| rq.swap_current_process(proc));
 	}
 	Ok(frame)
 }
 
 /// An inner function is required to ensure everything in scope is dropped before idle.
 fn kernel_main_inner(magic: u32, multiboot_ptr: *const c_void) {
+	// The bootstrap core is the core with ID `0`
+	scheduler::init_core_local(0);
 	// Initialize TTY
 	TTY.display.lock().show();
 	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 	{
+		gdt::init();
 		// Ensure the CPU has SSE
 		if !has_sse() {
 			panic!("SSE support is required to run this kernel :(");
@@ -161,8 +171,21 @@ fn kernel_main_inner(magic: u32, multiboot_ptr: *const c_void) {
 	}
 	let boot_info = unsafe { multiboot::read(multiboot_ptr) };
 
+	// Parse bootloader command line arguments. This is done before initializing memory
+	// management, since some arguments reserve memory
+	let cmdline = boot_info.cmdline.unwrap_or_default();
+	let args_parser = match cmdline::ArgsParser::parse(cmdline) {
+		Ok(p) => p,
+		Err(e) => {
+			println!("{e}");
+			power::halt();
+		}
+	};
+	LOGGER.lock().silent = args_parser.is_silent();
+	console::select(args_parser.get_consoles());
+
 	// Initialize memory management
-	memory::memmap::init(boot_info);
+	memory::memmap::init(boot_info, args_parser.get_crashkernel_size());
 	#[cfg(debug_assertions)]
 	memory::memmap::print_entries();
 	memory::alloc::init();
@@ -179,35 +202,18 @@ fn kernel_main_inner(magic: u32, multiboot_ptr: *const c_void) {
 	#[cfg(test)]
 	kernel_selftest();
 
-	// Parse bootloader command line arguments
-	let cmdline = boot_info.cmdline.unwrap_or_default();
-	let args_parser = match cmdline::ArgsParser::parse(cmdline) {
-		Ok(p) => p,
-		Err(e) => {
-			println!("{e}");
-			power::halt();
-		}
-	};
-	LOGGER.lock().silent = args_parser.is_silent();
-
 	println!("Booting Maestro kernel version {VERSION}");
 
 	// FIXME
 	//println!("Initializing ACPI...");
 	//acpi::init();
 
-	println!("Initializing time management...");
-	time::init().unwrap_or_else(|e| panic!("Failed to initialize time management! ({e})"));
-
-	println!("Initializing devices management...");
-	device::init().unwrap_or_else(|e| panic!("Failed to initialize devices management! ({e})"));
-	net::osi::init().unwrap_or_else(|e| panic!("Failed to initialize network! ({e})"));
-	crypto::init()
-		.unwrap_or_else(|_| panic!("Failed to initialize cryptography! (out of memory)"));
+	initcall::run(&args_parser);
 
 	let root = args_parser.get_root_dev();
 	println!("Initializing files management...");
-	file::init(root).unwrap_or_else(|e| panic!("Failed to initialize files management! ({e})"));
+	file::init(root, args_parser.is_root_readonly())
+		.unwrap_or_else(|e| panic!("Failed to initialize files management! ({e})"));
 	if let Some(initramfs) = boot_info.initramfs {
 		println!("Initializing initramfs...");
 		initramfs::load(initramfs)
@@ -224,8 +230,22 @@ fn kernel_main_inner(magic: u32, multiboot_ptr: *const c_void) {
 	let init_frame =
 		init(init_path).unwrap_or_else(|e| panic!("Cannot execute init process: {e}"));
 
-	Process::new_kthread(None, cache::flush_task, true)
+	kthread::spawn("cache_flush", cache::flush_task)
 		.unwrap_or_else(|e| panic!("Cannot launch the cache flush task: {e}"));
+	kthread::spawn("readahead", cache::readahead_task)
+		.unwrap_or_else(|e| panic!("Cannot launch the read ahead task: {e}"));
+	kthread::spawn("ksmd", ksm::ksmd_task)
+		.unwrap_or_else(|e| panic!("Cannot launch the samepage merging task: {e}"));
+	workqueue::init().unwrap_or_else(|e| panic!("Cannot create the system workqueue: {e}"));
+	softirq::init().unwrap_or_else(|e| panic!("Cannot launch the softirq task: {e}"));
+
+	// Start the other cores last, so that they cannot take the init process before it runs
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	{
+		println!("Starting CPU cores...");
+		apic::init().unwrap_or_else(|e| panic!("Failed to initialize the Local APIC! ({e})"));
+		smp::init().unwrap_or_else(|e| panic!("Failed to start CPU cores! ({e})"));
+	}
 
 	unsafe {
 		switch::init_ctx(&init_frame);
@@ -243,6 +263,8 @@ fn kernel_main_inner(magic: u32, multiboot_ptr: *const c_void) {
 /// - `multiboot_ptr` is the pointer to the Multiboot booting information structure.
 #[unsafe(no_mangle)]
 pub extern "C" fn kernel_main(magic: u32, multiboot_ptr: *const c_void) -> ! {
+	// Done here since this function never returns
+	stack_protector::init();
 	kernel_main_inner(magic, multiboot_ptr);
 	unsafe {
 		idle_task();
@@ -18,10 +18,12 @@
 
 //! Kernel logging
 //!
-//! If the logger is set as silent, logs will not show up on screen, but will be kept in memory
+//! Logs are sent to every enabled console (see [`crate::console`]).
+//!
+//! If the logger is set as silent, logs will not show up on consoles, but will be kept in memory
 //! anyway.
 
-use crate::{sync::mutex::IntMutex, tty::TTY};
+use crate::{console, sync::mutex::IntMutex};
 use core::{
 	cmp::{Ordering, min},
 	fmt,
@@ -126,7 +128,7 @@ impl Write for Logger {
 	fn write_str(&mut self, s: &str) -> fmt::Result {
 		self.push(s.as_bytes());
 		if !self.silent {
-			TTY.display.lock().write(s.as_bytes());
+			console::write(s.as_bytes());
 		}
 		Ok(())
 	}
@@ -51,6 +51,9 @@ pub const MAX_ORDER: FrameOrder = 17;
 /// The number of memory zones.
 pub const ZONES_COUNT: usize = 3;
 
+/// The names of the memory zones, by index.
+pub const ZONE_NAMES: [&str; ZONES_COUNT] = ["User", "MMIO", "Kernel"];
+
 /// The mask for the zone ID in buddy allocator flags.
 const ZONE_TYPE_MASK: Flags = 0b11;
 
@@ -528,6 +531,19 @@ pub unsafe fn free_kernel(ptr: *mut u8, order: FrameOrder) {
 	free(addr, order);
 }
 
+/// Returns the number of free frames of each order in the zone at index `zone`.
+pub fn free_frames_count(zone: usize) -> [usize; (MAX_ORDER + 1) as usize] {
+	let zones = ZONES.lock();
+	let mut counts = [0; (MAX_ORDER + 1) as usize];
+	for (count, mut cur) in counts.iter_mut().zip(zones[zone].free_list) {
+		while let Some(frame) = cur {
+			*count += 1;
+			cur = unsafe { frame.as_ref() }.next;
+		}
+	}
+	counts
+}
+
 /// Returns the total number of pages allocated by the buddy allocator.
 pub fn allocated_pages_count() -> usize {
 	let zones = ZONES.lock();
@@ -26,15 +26,15 @@
 //!   reclaimed at anytime
 
 use crate::{
-	arch::x86::sti,
 	device::BlkDev,
-	file::vfs::node::Node,
+	file::{vfs::node::Node, wait_queue::WaitQueue},
 	memory::{
 		PhysAddr, VirtAddr, buddy,
-		buddy::{Flags, FrameOrder, Page, ZONE_KERNEL},
+		buddy::{Flags, FrameOrder, Page, ZONE_KERNEL, ZONE_USER},
 		stats::MEM_INFO,
 	},
 	println,
+	process::{cgroup::Cgroup, kthread, rusage},
 	sync::mutex::IntMutex,
 	time::{
 		clock::{Clock, current_time_ms},
@@ -57,7 +57,7 @@ use core::{
 };
 use utils::{
 	bytes::AnyRepr,
-	collections::{btreemap::BTreeMap, list::ListNode},
+	collections::{btreemap::BTreeMap, list::ListNode, vec::Vec},
 	errno::{AllocResult, EResult},
 	limits::PAGE_SIZE,
 	list, list_type,
@@ -68,6 +68,10 @@ use utils::{
 // TODO must be configurable
 /// The timeout, in milliseconds, after which a dirty page may be written back to disk.
 const WRITEBACK_TIMEOUT: u64 = 100;
+/// The number of pages read ahead after a page fault on a mapped file.
+pub const READAHEAD_PAGES: u64 = 16;
+/// The maximum number of pending read ahead requests. Further requests are dropped.
+const READAHEAD_MAX: usize = 64;
 
 /// The node from which the data of a [`RcFrame`] comes from.
 #[derive(Clone, Debug)]
@@ -107,10 +111,16 @@ struct RcFrameInner {
 	map_count: AtomicUsize,
 	/// The node for the cache LRU
 	lru: ListNode,
+
+	/// The cgroup the frame is charged to, if any
+	memcg: Option<Arc<Cgroup>>,
 }
 
 impl Drop for RcFrameInner {
 	fn drop(&mut self) {
+		if let Some(memcg) = &self.memcg {
+			memcg.uncharge_memory(pow2(self.order as usize));
+		}
 		unsafe {
 			buddy::free(self.addr, self.order);
 		}
@@ -149,9 +159,33 @@ impl RcFrame {
 
 			map_count: Default::default(),
 			lru: Default::default(),
+
+			memcg: None,
 		})?))
 	}
 
+	/// Allocates a new, *uninitialized* anonymous page in the user zone, charged to the memory
+	/// controller of `cgroup`.
+	///
+	/// If the memory limit of the cgroup is reached, the function fails.
+	pub fn new_user(cgroup: &Arc<Cgroup>) -> AllocResult<Self> {
+		cgroup.charge_memory(1)?;
+		let addr = buddy::alloc(0, ZONE_USER).inspect_err(|_| cgroup.uncharge_memory(1))?;
+		let inner = Arc::new(RcFrameInner {
+			addr,
+			order: 0,
+
+			owner: FrameOwner::Anon,
+			dev_off: 0,
+
+			map_count: Default::default(),
+			lru: Default::default(),
+
+			memcg: Some(cgroup.clone()),
+		})?;
+		Ok(Self(inner))
+	}
+
 	/// Allocates a new, zeroed page in the kernel zone.
 	///
 	/// Arguments:
@@ -245,7 +279,10 @@ impl RcFrame {
 
 	/// Marks the `n`th page as dirty.
 	pub fn mark_page_dirty(&self, n: usize) {
-		self.get_page(n).dirty.store(true, Release);
+		let was_dirty = self.get_page(n).dirty.swap(true, Release);
+		if !was_dirty {
+			rusage::account_output(PAGE_SIZE);
+		}
 	}
 
 	/// Marks all pages on the frame as dirty.
@@ -278,7 +315,7 @@ impl RcFrame {
 			// Write page
 			match &self.0.owner {
 				FrameOwner::Anon => {}
-				FrameOwner::BlkDev(blk) => blk.ops.write_pages(self.dev_offset(), self.slice())?,
+				FrameOwner::BlkDev(blk) => blk.write_pages(self.dev_offset(), self.slice())?,
 				FrameOwner::Node(node) => node.node_ops.write_frame(node, self)?,
 			}
 			// Update write timestamp
@@ -289,6 +326,12 @@ impl RcFrame {
 		Ok(())
 	}
 
+	/// Tells whether the frame belongs to the page cache of a node.
+	#[inline]
+	pub fn is_cached(&self) -> bool {
+		self.0.owner.inner().is_some()
+	}
+
 	/// Returns a reference to the map counter.
 	#[inline]
 	pub fn map_counter(&self) -> &AtomicUsize {
@@ -300,6 +343,12 @@ impl RcFrame {
 	pub fn is_shared(&self) -> bool {
 		self.0.map_count.load(Acquire) > 1
 	}
+
+	/// Returns the number of references to the frame.
+	#[inline]
+	pub fn ref_count(&self) -> usize {
+		Arc::strong_count(&self.0)
+	}
 }
 
 /// A view over an object on a frame, where the frame is considered as an array of this object
@@ -421,6 +470,20 @@ impl MappedNode {
 		Ok(())
 	}
 
+	/// Removes, without flushing, the pages in the range `start..end`.
+	pub fn remove_range(&self, start: u64, end: u64) {
+		let mut lru = LRU.lock();
+		self.cache.lock().retain(|o, frame| {
+			let retain = !(start..end).contains(o);
+			if !retain {
+				unsafe {
+					lru.remove(&frame.0);
+				}
+			}
+			retain
+		});
+	}
+
 	/// Removes, without flushing, all the pages after the offset `off` (included).
 	pub fn truncate(&self, off: u64) {
 		let mut lru = LRU.lock();
@@ -465,10 +528,10 @@ fn flush_task_inner(cur_ts: Timestamp) {
 	}
 }
 
-/// The entry point of the kernel task flushing cached memory back to disk.
-pub(crate) fn flush_task() -> ! {
-	sti();
-	loop {
+/// The entry point of the kernel thread flushing cached memory back to disk.
+pub(crate) fn flush_task() {
+	while !kthread::should_stop() {
+		kthread::parkme();
 		let cur_ts = current_time_ms(Clock::Boottime);
 		flush_task_inner(cur_ts);
 		// Sleep
@@ -477,6 +540,79 @@ pub(crate) fn flush_task() -> ! {
 	}
 }
 
+/// A request to read pages of a node ahead, into its page cache.
+struct Readahead {
+	/// The node to read from.
+	node: Arc<Node>,
+	/// The offset of the first page to read.
+	start: u64,
+	/// The offset of the end of the range to read.
+	end: u64,
+}
+
+/// Pending read ahead requests, in order.
+static READAHEAD: IntMutex<Vec<Readahead>> = IntMutex::new(Vec::new());
+/// The queue on which the read ahead task waits for requests.
+static READAHEAD_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Requests the pages in the range `start..end` of `node` to be read into the page cache,
+/// asynchronously.
+///
+/// The range is truncated to the end of the node when the request is processed. If too many
+/// requests are pending, the function does nothing.
+///
+/// This function does not lock the node, so that it can be called while handling a page fault.
+pub fn readahead(node: &Arc<Node>, start: u64, end: u64) {
+	if start >= end {
+		return;
+	}
+	{
+		let mut reqs = READAHEAD.lock();
+		let dup = reqs
+			.iter()
+			.any(|r| Arc::as_ptr(&r.node) == Arc::as_ptr(node) && r.start == start);
+		if dup || reqs.len() >= READAHEAD_MAX {
+			return;
+		}
+		let res = reqs.push(Readahead {
+			node: node.clone(),
+			start,
+			end,
+		});
+		// Reading ahead is only an optimization
+		if res.is_err() {
+			return;
+		}
+	}
+	READAHEAD_QUEUE.wake_next();
+}
+
+/// The entry point of the kernel thread reading pages ahead into the page cache.
+pub(crate) fn readahead_task() {
+	while !kthread::should_stop() {
+		kthread::parkme();
+		let res = READAHEAD_QUEUE.wait_until(|| {
+			let mut reqs = READAHEAD.lock();
+			(!reqs.is_empty()).then(|| reqs.remove(0))
+		});
+		let Ok(req) = res else {
+			continue;
+		};
+		let end = req
+			.end
+			.min(req.node.stat.lock().size.div_ceil(PAGE_SIZE as u64));
+		for off in req.start..end {
+			if req.node.mapped.get(off).is_some() {
+				continue;
+			}
+			// On failure, the page is read again when accessed
+			if req.node.node_ops.read_page(&req.node, off).is_err() {
+				break;
+			}
+		}
+	}
+}
+
 /// Attempts to shrink the page cache.
 ///
 /// If the cache cannot shrink, the function returns `false`.
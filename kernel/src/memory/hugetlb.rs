@@ -0,0 +1,113 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Huge pages are physically contiguous frames of [`HUGE_PAGE_PAGES`] pages, backing the
+//! mappings created with `MAP_HUGETLB`.
+//!
+//! Since large contiguous frames become hard to allocate once memory is fragmented, huge pages
+//! are reserved in advance in a pool, whose size is set through `/proc/sys/vm/nr_hugepages`.
+//! Frames in the pool remain allocated, even when no mapping uses them.
+
+use crate::{
+	arch::x86::paging::ENTRIES_PER_TABLE,
+	memory::{
+		buddy::{FrameOrder, ZONE_USER},
+		cache::{FrameOwner, RcFrame},
+	},
+	sync::mutex::Mutex,
+};
+use utils::{collections::vec::Vec, errno::AllocResult, limits::PAGE_SIZE};
+
+/// The number of pages in a huge page.
+pub const HUGE_PAGE_PAGES: usize = ENTRIES_PER_TABLE;
+/// The buddy allocator order of a huge page.
+pub const HUGE_PAGE_ORDER: FrameOrder = HUGE_PAGE_PAGES.ilog2() as _;
+/// The size of a huge page in bytes.
+pub const HUGE_PAGE_SIZE: usize = HUGE_PAGE_PAGES * PAGE_SIZE;
+
+/// The pool of reserved huge pages.
+///
+/// A huge page is free when the pool holds the only reference to it.
+static POOL: Mutex<Vec<RcFrame>> = Mutex::new(Vec::new());
+
+/// Returns the number of huge pages in the pool.
+pub fn total() -> usize {
+	POOL.lock().len()
+}
+
+/// Returns the number of huge pages in the pool that are not used by any mapping.
+pub fn free() -> usize {
+	POOL.lock().iter().filter(|f| f.ref_count() == 1).count()
+}
+
+/// Sets the number of huge pages in the pool to `count`.
+///
+/// If not enough memory is available, the pool grows as much as possible. Huge pages in use
+/// cannot be released, so the pool may also remain larger than `count`.
+///
+/// The function fails only if the pool itself cannot grow.
+pub fn resize(count: usize) -> AllocResult<()> {
+	let mut pool = POOL.lock();
+	// Release free huge pages, starting from the end
+	let mut i = pool.len();
+	while pool.len() > count && i > 0 {
+		i -= 1;
+		if pool[i].ref_count() == 1 {
+			pool.remove(i);
+		}
+	}
+	// Reserve new huge pages
+	while pool.len() < count {
+		let Ok(frame) = RcFrame::new(HUGE_PAGE_ORDER, ZONE_USER, FrameOwner::Anon, 0) else {
+			break;
+		};
+		pool.push(frame)?;
+	}
+	Ok(())
+}
+
+/// Returns a free huge page from the pool, or `None` if none is available.
+///
+/// The content of the returned frame is *uninitialized*.
+pub fn alloc() -> Option<RcFrame> {
+	let pool = POOL.lock();
+	pool.iter().find(|f| f.ref_count() == 1).cloned()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn hugetlb_pool() {
+		resize(2).unwrap();
+		assert_eq!(total(), 2);
+		assert_eq!(free(), 2);
+		let frame = alloc().unwrap();
+		assert_eq!(free(), 1);
+		// A huge page in use cannot be released
+		resize(0).unwrap();
+		assert_eq!(total(), 1);
+		assert_eq!(free(), 0);
+		assert!(alloc().is_none());
+		drop(frame);
+		assert_eq!(free(), 1);
+		resize(0).unwrap();
+		assert_eq!(total(), 0);
+	}
+}
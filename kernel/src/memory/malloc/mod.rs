@@ -20,13 +20,16 @@
 
 mod block;
 mod chunk;
+#[cfg(config_debug_malloc_poison)]
+mod poison;
 
 use crate::{memory, memory::malloc::ptr::NonNull, sync::mutex::IntMutex};
 use block::Block;
 use chunk::Chunk;
+#[cfg(not(config_debug_malloc_poison))]
+use core::cmp::Ordering;
 use core::{
 	alloc::{AllocError, Layout},
-	cmp::Ordering,
 	hint::unlikely,
 	num::NonZeroUsize,
 	ptr,
@@ -39,6 +42,8 @@ static MUTEX: IntMutex<()> = IntMutex::new(());
 
 unsafe fn alloc(n: NonZeroUsize) -> AllocResult<NonNull<u8>> {
 	let _ = MUTEX.lock();
+	#[cfg(config_debug_malloc_poison)]
+	let (n, requested) = (poison::outer_size(n)?, n.get());
 	// Get free chunk
 	let free_chunk = chunk::get_available_chunk(n)?;
 	free_chunk.chunk.split(n.get());
@@ -51,6 +56,8 @@ unsafe fn alloc(n: NonZeroUsize) -> AllocResult<NonNull<u8>> {
 	let ptr = chunk.get_ptr_mut();
 	debug_assert!(ptr.is_aligned_to(chunk::ALIGNMENT));
 	debug_assert!(ptr as usize >= memory::PROCESS_END.0);
+	#[cfg(config_debug_malloc_poison)]
+	let ptr = poison::on_alloc(ptr, requested);
 	#[cfg(feature = "memtrace")]
 	super::trace::sample(
 		"malloc",
@@ -61,6 +68,17 @@ unsafe fn alloc(n: NonZeroUsize) -> AllocResult<NonNull<u8>> {
 	NonNull::new(ptr).ok_or(AllocError)
 }
 
+#[cfg(config_debug_malloc_poison)]
+unsafe fn realloc(ptr: NonNull<u8>, n: NonZeroUsize) -> AllocResult<NonNull<u8>> {
+	// Always move the allocation so that accesses through the old pointer hit poisoned memory
+	let (_, size) = poison::check(ptr.as_ptr());
+	let mut new_ptr = alloc(n)?;
+	ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_mut(), size.min(n.get()));
+	free(ptr);
+	Ok(new_ptr)
+}
+
+#[cfg(not(config_debug_malloc_poison))]
 unsafe fn realloc(ptr: NonNull<u8>, n: NonZeroUsize) -> AllocResult<NonNull<u8>> {
 	let _ = MUTEX.lock();
 	// Get chunk
@@ -97,13 +115,19 @@ unsafe fn realloc(ptr: NonNull<u8>, n: NonZeroUsize) -> AllocResult<NonNull<u8>>
 	Ok(new_ptr)
 }
 
-unsafe fn free(mut ptr: NonNull<u8>) {
+unsafe fn free(ptr: NonNull<u8>) {
 	let _ = MUTEX.lock();
 	// Get chunk
-	let chunk = Chunk::from_ptr(ptr.as_mut());
+	#[cfg(config_debug_malloc_poison)]
+	let (data, _) = poison::check(ptr.as_ptr());
+	#[cfg(not(config_debug_malloc_poison))]
+	let data = ptr.as_ptr();
+	let chunk = Chunk::from_ptr(data);
 	assert!(chunk.used);
 	#[cfg(config_debug_malloc_check)]
 	chunk.check();
+	#[cfg(config_debug_malloc_poison)]
+	poison::poison(chunk);
 	// Mark as free
 	chunk.used = false;
 	let free_chunk = chunk.as_free_chunk().unwrap();
@@ -316,4 +340,19 @@ mod test {
 		}
 		assert_eq!(usage, buddy::allocated_pages_count());
 	}
+
+	#[cfg(config_debug_malloc_poison)]
+	#[test_case]
+	fn free_poison() {
+		unsafe {
+			let ptr0 = alloc(NonZeroUsize::new(32).unwrap()).unwrap();
+			slice::from_raw_parts_mut(ptr0.as_ptr(), 32).fill(!0);
+			// Keep the block alive after the first free
+			let ptr1 = alloc(NonZeroUsize::new(32).unwrap()).unwrap();
+			free(ptr0);
+			let freed = slice::from_raw_parts(ptr0.as_ptr(), 32);
+			assert!(freed.iter().all(|b| *b == poison::FREE_POISON));
+			free(ptr1);
+		}
+	}
 }
@@ -0,0 +1,159 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Allocation poisoning and redzones, used to catch use-after-free and out-of-bounds writes.
+//!
+//! When enabled, every allocation is laid out this way inside its chunk:
+//! - a [`Header`], storing the requested size and the call site of the allocation
+//! - a front redzone, filled with [`REDZONE_BYTE`]
+//! - the memory returned to the caller
+//! - a back redzone, filled with [`REDZONE_BYTE`]
+//!
+//! Redzones are verified when the allocation is freed or reallocated. Freed memory is filled
+//! with [`FREE_POISON`] so that reading it after free yields recognizable garbage instead of
+//! stale data.
+
+use super::chunk::Chunk;
+use crate::{debug, memory::VirtAddr, println, register_get};
+use core::{mem::size_of, num::NonZeroUsize, ptr, slice};
+use utils::errno::AllocResult;
+
+/// The size of each redzone, in bytes.
+const REDZONE_SIZE: usize = super::chunk::ALIGNMENT;
+/// The byte redzones are filled with.
+const REDZONE_BYTE: u8 = 0xbb;
+/// The byte freed memory is filled with.
+pub const FREE_POISON: u8 = 0x6b;
+/// The number of frames of the allocation's call site to record.
+const CALLSTACK_DEPTH: usize = 8;
+
+/// Header placed before the front redzone of each allocation.
+#[repr(C, align(16))]
+struct Header {
+	/// The size requested by the caller, in bytes.
+	size: usize,
+	/// The callstack of the allocation.
+	callstack: [VirtAddr; CALLSTACK_DEPTH],
+}
+
+/// The number of bytes added to each allocation.
+const OVERHEAD: usize = size_of::<Header>() + 2 * REDZONE_SIZE;
+
+/// Returns the callstack of the caller.
+#[inline(always)]
+fn callstack() -> [VirtAddr; CALLSTACK_DEPTH] {
+	#[cfg(target_arch = "x86")]
+	let frame = register_get!("ebp");
+	#[cfg(target_arch = "x86_64")]
+	let frame = register_get!("rbp");
+	let mut callstack = [VirtAddr::default(); CALLSTACK_DEPTH];
+	unsafe {
+		debug::get_callstack(ptr::with_exposed_provenance(frame), &mut callstack);
+	}
+	callstack
+}
+
+/// Reports a corruption of the allocation at `ptr`.
+///
+/// If the `malloc_poison_panic` option is enabled, the kernel panics. Else, the corruption is
+/// logged and execution continues.
+#[cold]
+fn report(ptr: *const u8, what: &str, alloc_callstack: Option<&[VirtAddr]>) {
+	println!("malloc: {what} (allocation at {ptr:p})");
+	if let Some(alloc_callstack) = alloc_callstack {
+		println!("--- Allocated at ---");
+		debug::print_callstack(alloc_callstack);
+	}
+	#[cfg(config_debug_malloc_poison_panic)]
+	panic!("malloc: {what}");
+	#[cfg(not(config_debug_malloc_poison_panic))]
+	{
+		println!("--- Detected at ---");
+		debug::print_callstack(&callstack());
+	}
+}
+
+/// Returns the size of the chunk to allocate to fit an allocation of `n` bytes.
+pub fn outer_size(n: NonZeroUsize) -> AllocResult<NonZeroUsize> {
+	n.checked_add(OVERHEAD).ok_or(core::alloc::AllocError)
+}
+
+/// Sets up the header and redzones of a newly allocated chunk.
+///
+/// Arguments:
+/// - `data` is the pointer to the chunk's data
+/// - `n` is the size requested by the caller
+///
+/// The function returns the pointer to give to the caller.
+///
+/// # Safety
+///
+/// `data` must point to at least `n + OVERHEAD` bytes of memory.
+#[inline(always)]
+pub unsafe fn on_alloc(data: *mut u8, n: usize) -> *mut u8 {
+	(data as *mut Header).write(Header {
+		size: n,
+		callstack: callstack(),
+	});
+	let ptr = data.add(size_of::<Header>() + REDZONE_SIZE);
+	ptr.sub(REDZONE_SIZE)
+		.write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+	ptr.add(n).write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+	ptr
+}
+
+/// Checks the redzones of the allocation at `ptr`, reporting any corruption.
+///
+/// The function returns the pointer to the chunk's data along with the size requested by the
+/// caller at allocation.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`on_alloc`].
+pub unsafe fn check(ptr: *mut u8) -> (*mut u8, usize) {
+	let data = ptr.sub(size_of::<Header>() + REDZONE_SIZE);
+	let chunk = Chunk::from_ptr(data);
+	if !chunk.used {
+		// The header has been overwritten with poison, so it cannot be trusted
+		report(ptr, "double free or free of an invalid pointer", None);
+		// Continuing would corrupt the allocator
+		panic!("malloc: double free");
+	}
+	let hdr = &*(data as *const Header);
+	if hdr.size > chunk.get_size().saturating_sub(OVERHEAD) {
+		report(ptr, "allocation header corrupted", None);
+		panic!("malloc: allocation header corrupted");
+	}
+	let front = slice::from_raw_parts(ptr.sub(REDZONE_SIZE), REDZONE_SIZE);
+	if front.iter().any(|b| *b != REDZONE_BYTE) {
+		report(ptr, "buffer underflow", Some(&hdr.callstack));
+	}
+	let back = slice::from_raw_parts(ptr.add(hdr.size), REDZONE_SIZE);
+	if back.iter().any(|b| *b != REDZONE_BYTE) {
+		report(ptr, "buffer overflow", Some(&hdr.callstack));
+	}
+	(data, hdr.size)
+}
+
+/// Fills the data of the given chunk with [`FREE_POISON`].
+pub fn poison(chunk: &mut Chunk) {
+	let size = chunk.get_size();
+	unsafe {
+		chunk.get_ptr_mut().write_bytes(FREE_POISON, size);
+	}
+}
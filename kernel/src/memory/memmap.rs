@@ -27,6 +27,9 @@ use crate::{elf::kernel::sections, multiboot, multiboot::BootInfo, sync::once::O
 use core::{cmp::min, iter};
 use utils::limits::PAGE_SIZE;
 
+/// The alignment of the memory reserved for the crash kernel, in bytes.
+const CRASH_KERNEL_ALIGN: usize = 16 * 1024 * 1024;
+
 /// Physical memory map information.
 #[derive(Debug)]
 pub struct PhysMapInfo {
@@ -44,6 +47,10 @@ pub struct PhysMapInfo {
 	pub phys_main_begin: PhysAddr,
 	/// The size of the main block of physical allocatable memory, in pages.
 	pub phys_main_pages: usize,
+
+	/// The physical address and size in pages of the memory reserved for the crash kernel, if
+	/// any.
+	pub crash_kernel: Option<(PhysAddr, usize)>,
 }
 
 /// Physical memory map information.
@@ -84,7 +91,11 @@ fn sections_end(boot_info: &BootInfo) -> PhysAddr {
 }
 
 /// Fills the memory mapping structure according to Multiboot's information.
-pub(crate) fn init(boot_info: &BootInfo) {
+///
+/// `crash_kernel_size` is the size of the memory to reserve for the crash kernel, in bytes. The
+/// memory is reserved at the end of the main block of allocatable memory, so that it is never
+/// used by the current kernel.
+pub(crate) fn init(boot_info: &BootInfo, crash_kernel_size: Option<usize>) {
 	// The end address of the loaded initramfs
 	let initramfs_end = boot_info
 		.initramfs
@@ -106,8 +117,26 @@ pub(crate) fn init(boot_info: &BootInfo) {
 		(1000 + boot_info.mem_upper as usize) / 4,
 		usize::MAX / PAGE_SIZE,
 	);
+	// Reserve memory for the crash kernel
+	let crash_kernel = crash_kernel_size.and_then(|size| {
+		let size = size.checked_next_multiple_of(PAGE_SIZE)?;
+		let begin = (memory_size * PAGE_SIZE).checked_sub(size)? & !(CRASH_KERNEL_ALIGN - 1);
+		(begin >= phys_main_begin.0).then_some((PhysAddr(begin), size / PAGE_SIZE))
+	});
+	match (crash_kernel_size, crash_kernel) {
+		(_, Some((begin, pages))) => crate::println!(
+			"Reserved {} MiB at {:#x} for the crash kernel",
+			pages * PAGE_SIZE / 1024 / 1024,
+			begin.0
+		),
+		(Some(_), None) => crate::println!("Not enough memory to reserve for the crash kernel"),
+		(None, None) => {}
+	}
+	let phys_main_end = crash_kernel
+		.map(|(begin, _)| begin.0 / PAGE_SIZE)
+		.unwrap_or(memory_size);
 	// The number of physical page available for memory allocation
-	let phys_main_pages = memory_size - phys_main_begin.0 / PAGE_SIZE;
+	let phys_main_pages = phys_main_end - phys_main_begin.0 / PAGE_SIZE;
 	// Set memory information
 	let phys_map = PhysMapInfo {
 		memory_maps_size: boot_info.memory_maps_size,
@@ -118,6 +147,8 @@ pub(crate) fn init(boot_info: &BootInfo) {
 
 		phys_main_begin,
 		phys_main_pages,
+
+		crash_kernel,
 	};
 	unsafe {
 		OnceInit::init(&PHYS_MAP, phys_map);
@@ -38,15 +38,18 @@ use core::{
 pub mod alloc;
 pub mod buddy;
 pub mod cache;
+pub mod hugetlb;
 pub mod malloc;
 pub mod memmap;
 pub mod mmio;
 pub mod oom;
 pub mod ring_buffer;
+pub mod shm;
 pub mod stats;
 #[cfg(feature = "memtrace")]
 mod trace;
 pub mod user;
+pub mod vmcore;
 pub mod vmem;
 
 /// Address of the beginning of the allocatable region in the virtual memory.
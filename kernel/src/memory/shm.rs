@@ -0,0 +1,400 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV shared memory segments.
+//!
+//! A segment is a chunk of memory identified in an IPC namespace by an ID, and optionally bound
+//! to a key. Processes attach it to their memory space with `shmat`, and detach it with `shmdt`.
+//!
+//! The memory of a segment is held by an anonymous tmpfs file, which each attachment maps as
+//! shared. Thus, the number of attachments of a segment is the number of mappings referencing the
+//! file, which accounts for mappings inherited with `fork` or split by `munmap`.
+//!
+//! A segment removed with `IPC_RMID` while still attached is only marked for destruction: it is
+//! destroyed once the last attachment is gone.
+
+use crate::{
+	file::{File, FileType, Mode, O_RDWR, Stat, fs::tmp, perm::AccessProfile},
+	process::{
+		ns::{CompatIpcPerm, IPC_PRIVATE, IpcIds, IpcObjectPerm, IpcPerm, get_access},
+		pid::Pid,
+	},
+	sync::mutex::Mutex,
+	time::{
+		clock::{Clock, current_time_sec},
+		unit::Timestamp,
+	},
+};
+use core::{
+	ffi::{c_int, c_long, c_ulong},
+	hint::unlikely,
+	ptr,
+};
+use utils::{
+	collections::{hashmap::HashMap, string::String},
+	errno,
+	errno::EResult,
+	limits::PAGE_SIZE,
+	ptr::arc::Arc,
+};
+
+/// `shmat` flag: attach the segment read-only.
+pub const SHM_RDONLY: c_int = 0o10000;
+/// `shmat` flag: round the address down to a multiple of [`SHMLBA`].
+pub const SHM_RND: c_int = 0o20000;
+/// `shmat` flag: replace existing mappings in the range of the attachment.
+pub const SHM_REMAP: c_int = 0o40000;
+/// `shmat` flag: allow executing the content of the segment.
+pub const SHM_EXEC: c_int = 0o100000;
+
+/// Flag in the mode of a segment marked for destruction.
+pub const SHM_DEST: Mode = 0o1000;
+
+/// The alignment of attachment addresses.
+pub const SHMLBA: usize = PAGE_SIZE;
+/// The minimum size of a segment, in bytes.
+pub const SHMMIN: usize = 1;
+/// The maximum size of a segment, in bytes.
+pub const SHMMAX: usize = usize::MAX - (1 << 24);
+/// The maximum number of segments in an IPC namespace.
+pub const SHMMNI: usize = 4096;
+
+/// Status of a shared memory segment.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShmidDs {
+	pub shm_perm: IpcPerm,
+	pub shm_segsz: usize,
+	pub shm_atime: c_long,
+	pub shm_dtime: c_long,
+	pub shm_ctime: c_long,
+	pub shm_cpid: c_int,
+	pub shm_lpid: c_int,
+	pub shm_nattch: c_ulong,
+	pub _unused4: c_ulong,
+	pub _unused5: c_ulong,
+}
+
+/// Compatibility version of [`ShmidDs`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompatShmidDs {
+	pub shm_perm: CompatIpcPerm,
+	pub shm_segsz: u32,
+	pub shm_atime: u32,
+	pub shm_atime_high: u32,
+	pub shm_dtime: u32,
+	pub shm_dtime_high: u32,
+	pub shm_ctime: u32,
+	pub shm_ctime_high: u32,
+	pub shm_cpid: c_int,
+	pub shm_lpid: c_int,
+	pub shm_nattch: u32,
+	pub _unused4: u32,
+	pub _unused5: u32,
+}
+
+impl From<ShmidDs> for CompatShmidDs {
+	fn from(ds: ShmidDs) -> Self {
+		let (atime, dtime, ctime) = (
+			ds.shm_atime as u64,
+			ds.shm_dtime as u64,
+			ds.shm_ctime as u64,
+		);
+		Self {
+			shm_perm: ds.shm_perm.into(),
+			shm_segsz: ds.shm_segsz as _,
+			shm_atime: atime as _,
+			shm_atime_high: (atime >> 32) as _,
+			shm_dtime: dtime as _,
+			shm_dtime_high: (dtime >> 32) as _,
+			shm_ctime: ctime as _,
+			shm_ctime_high: (ctime >> 32) as _,
+			shm_cpid: ds.shm_cpid,
+			shm_lpid: ds.shm_lpid,
+			shm_nattch: ds.shm_nattch as _,
+			..Default::default()
+		}
+	}
+}
+
+impl From<CompatShmidDs> for ShmidDs {
+	fn from(ds: CompatShmidDs) -> Self {
+		let time = |low: u32, high: u32| ((high as u64) << 32 | low as u64) as _;
+		Self {
+			shm_perm: ds.shm_perm.into(),
+			shm_segsz: ds.shm_segsz as _,
+			shm_atime: time(ds.shm_atime, ds.shm_atime_high),
+			shm_dtime: time(ds.shm_dtime, ds.shm_dtime_high),
+			shm_ctime: time(ds.shm_ctime, ds.shm_ctime_high),
+			shm_cpid: ds.shm_cpid,
+			shm_lpid: ds.shm_lpid,
+			shm_nattch: ds.shm_nattch as _,
+			..Default::default()
+		}
+	}
+}
+
+/// Mutable state of a segment.
+#[derive(Debug)]
+struct ShmState {
+	/// Ownership and permissions, along with [`SHM_DEST`] in the mode if the segment is marked
+	/// for destruction.
+	perm: IpcObjectPerm,
+
+	/// The timestamp of the last attachment, in seconds.
+	atime: Timestamp,
+	/// The timestamp of the last detachment, in seconds.
+	dtime: Timestamp,
+	/// The timestamp of the last change, in seconds.
+	ctime: Timestamp,
+	/// The PID of the last process which attached or detached the segment.
+	lpid: Pid,
+}
+
+/// A SysV shared memory segment.
+#[derive(Debug)]
+pub struct ShmSegment {
+	/// The ID of the segment in its namespace.
+	pub id: c_int,
+	/// The size of the segment in bytes.
+	pub size: usize,
+	/// The file holding the memory of the segment.
+	pub file: Arc<File>,
+
+	/// The PID of the creator.
+	cpid: Pid,
+	/// Mutable state.
+	state: Mutex<ShmState>,
+}
+
+impl ShmSegment {
+	/// Returns the number of attachments of the segment.
+	pub fn nattch(&self) -> usize {
+		// Do not count the reference held by the segment itself
+		Arc::strong_count(&self.file) - 1
+	}
+
+	/// Tells whether the segment is marked for destruction.
+	pub fn is_destroyed(&self) -> bool {
+		self.state.lock().perm.mode & SHM_DEST != 0
+	}
+
+	/// Tells whether the agent `ap` has the access `access` to the segment.
+	///
+	/// See [`IpcObjectPerm::can_access`].
+	pub fn can_access(&self, ap: &AccessProfile, access: Mode) -> bool {
+		self.state.lock().perm.can_access(ap, access)
+	}
+
+	/// Records an attachment (`attach` set) or a detachment by the process `pid`.
+	pub fn record_op(&self, pid: Pid, attach: bool) {
+		let now = current_time_sec(Clock::Realtime);
+		let mut state = self.state.lock();
+		if attach {
+			state.atime = now;
+		} else {
+			state.dtime = now;
+		}
+		state.lpid = pid;
+	}
+
+	/// Returns the status of the segment.
+	pub fn stat(&self) -> ShmidDs {
+		let state = self.state.lock();
+		ShmidDs {
+			shm_perm: state.perm.to_ipc_perm(),
+			shm_segsz: self.size,
+			shm_atime: state.atime as _,
+			shm_dtime: state.dtime as _,
+			shm_ctime: state.ctime as _,
+			shm_cpid: self.cpid as _,
+			shm_lpid: state.lpid as _,
+			shm_nattch: self.nattch() as _,
+			..Default::default()
+		}
+	}
+
+	/// Sets the owner and permissions of the segment from `perm`, on behalf of the agent `ap`.
+	///
+	/// If the agent does not own the segment, the function returns [`errno::EPERM`].
+	pub fn set(&self, ap: &AccessProfile, perm: &IpcPerm) -> EResult<()> {
+		let mut state = self.state.lock();
+		state.perm.set(ap, perm)?;
+		state.ctime = current_time_sec(Clock::Realtime);
+		Ok(())
+	}
+}
+
+/// The shared memory segments of an IPC namespace.
+#[derive(Debug, Default)]
+pub struct ShmIds {
+	/// Identifiers and keys of segments.
+	ids: IpcIds,
+	/// Segments, by ID.
+	segments: HashMap<c_int, Arc<ShmSegment>>,
+}
+
+impl ShmIds {
+	/// Removes the segments which are marked for destruction and no longer attached.
+	///
+	/// Since detachments can happen outside of `shmdt` (`munmap`, process exit), this is done
+	/// lazily on each operation on the namespace.
+	pub fn purge(&mut self) {
+		self.segments
+			.retain(|_, seg| !seg.is_destroyed() || seg.nattch() > 0);
+	}
+
+	/// Returns the ID of the segment bound to `key`, creating it if necessary, as `shmget` does.
+	///
+	/// Arguments:
+	/// - `size` is the size of the segment in bytes
+	/// - `flags` are the `shmget` flags, along with the permissions of a new segment
+	/// - `ap` is the access profile of the calling process
+	/// - `pid` is the PID of the calling process
+	pub fn get(
+		&mut self,
+		key: c_int,
+		size: usize,
+		flags: c_int,
+		ap: &AccessProfile,
+		pid: Pid,
+	) -> EResult<c_int> {
+		self.purge();
+		if let Some(id) = self.ids.get(key, flags)? {
+			let seg = &self.segments[id];
+			if unlikely(size > seg.size) {
+				return Err(errno!(EINVAL));
+			}
+			if unlikely(!seg.can_access(ap, get_access(flags))) {
+				return Err(errno!(EACCES));
+			}
+			return Ok(id);
+		}
+		// Create the segment
+		if unlikely(!(SHMMIN..=SHMMAX).contains(&size)) {
+			return Err(errno!(EINVAL));
+		}
+		if unlikely(self.segments.len() >= SHMMNI) {
+			return Err(errno!(ENOSPC));
+		}
+		let mode = (flags & 0o777) as Mode;
+		let now = current_time_sec(Clock::Realtime);
+		let stat = Stat {
+			mode: FileType::Regular.to_mode() | mode,
+			uid: ap.euid,
+			gid: ap.egid,
+			ctime: now,
+			mtime: now,
+			atime: now,
+			..Default::default()
+		};
+		let ent = tmp::create_anonymous(String::try_from(b"SYSV")?, stat, tmp::F_SEAL_SEAL)?;
+		let file = File::open_entry(ent, O_RDWR)?;
+		file.ops.truncate(&file, size as _)?;
+		let id = self.ids.alloc(key)?;
+		let seg = Arc::new(ShmSegment {
+			id,
+			size,
+			file,
+
+			cpid: pid,
+			state: Mutex::new(ShmState {
+				perm: IpcObjectPerm::new(key, ap, mode),
+
+				atime: 0,
+				dtime: 0,
+				ctime: now,
+				lpid: 0,
+			}),
+		});
+		let res = seg.and_then(|seg| self.segments.insert(id, seg));
+		if let Err(e) = res {
+			self.ids.remove(key);
+			return Err(e.into());
+		}
+		Ok(id)
+	}
+
+	/// Returns the segment with ID `id`.
+	///
+	/// If the segment does not exist, the function returns [`errno::EINVAL`].
+	pub fn get_segment(&mut self, id: c_int) -> EResult<Arc<ShmSegment>> {
+		self.purge();
+		self.segments
+			.get(&id)
+			.cloned()
+			.ok_or_else(|| errno!(EINVAL))
+	}
+
+	/// Marks the segment with ID `id` for destruction, on behalf of the agent `ap`.
+	///
+	/// The key of the segment is released immediately, while the segment itself is removed once
+	/// it is no longer attached.
+	pub fn remove(&mut self, id: c_int, ap: &AccessProfile) -> EResult<()> {
+		let seg = self.get_segment(id)?;
+		{
+			let mut state = seg.state.lock();
+			let perm = &mut state.perm;
+			if unlikely(!perm.is_owner(ap)) {
+				return Err(errno!(EPERM));
+			}
+			if perm.mode & SHM_DEST == 0 && perm.key != IPC_PRIVATE {
+				self.ids.remove(perm.key);
+			}
+			perm.key = IPC_PRIVATE;
+			perm.mode |= SHM_DEST;
+		}
+		self.purge();
+		Ok(())
+	}
+
+	/// Returns the segment whose memory is held by `file`, if any.
+	pub fn get_by_file(&self, file: &File) -> Option<Arc<ShmSegment>> {
+		self.segments
+			.iter()
+			.map(|(_, seg)| seg)
+			.find(|seg| ptr::eq(Arc::as_ptr(&seg.file), file))
+			.cloned()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	#[cfg(target_arch = "x86_64")]
+	fn shmid_ds_compat() {
+		let ds = ShmidDs {
+			shm_segsz: 0x2000,
+			shm_atime: 0x1_0000_0002,
+			shm_nattch: 3,
+			..Default::default()
+		};
+		let compat = CompatShmidDs::from(ds);
+		assert_eq!(compat.shm_atime, 2);
+		assert_eq!(compat.shm_atime_high, 1);
+		let ds = ShmidDs::from(compat);
+		assert_eq!(ds.shm_segsz, 0x2000);
+		assert_eq!(ds.shm_atime, 0x1_0000_0002);
+		assert_eq!(ds.shm_nattch, 3);
+	}
+}
@@ -211,6 +211,19 @@ impl<'a, T: Sized + fmt::Debug> UserSlice<'a, T> {
 		self.len == 0
 	}
 
+	/// Returns the slice without its first `n` elements.
+	///
+	/// If `n` is greater than the length of the slice, the returned slice is empty.
+	pub fn skip(&self, n: usize) -> Self {
+		let n = n.min(self.len);
+		Self {
+			ptr: self.ptr.map(|p| unsafe { p.add(n) }),
+			len: self.len - n,
+
+			phantom: PhantomData,
+		}
+	}
+
 	/// Same as [`Self::copy_from_user`], with a pointer `ptr` and length `len` instead of a slice.
 	///
 	/// # Safety
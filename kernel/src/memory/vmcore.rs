@@ -0,0 +1,241 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Crash dump of a previous kernel.
+//!
+//! After a panic, a crash kernel can be started in the memory reserved with the `crashkernel=`
+//! command line argument. The crash kernel receives the physical address of an ELF core header
+//! describing the memory of the crashed kernel, through the `elfcorehdr=` command line argument,
+//! as prepared by `kexec-tools`.
+//!
+//! The crashed kernel's memory is then exposed as an ELF core file through `/proc/vmcore`, so that
+//! it can be saved and analyzed.
+//!
+//! In the core header, the `p_offset` field of each segment contains the physical address of its
+//! content. When building the dump, notes are trimmed to their actual size, and segments are laid
+//! out contiguously after the headers.
+
+use super::{KERNELSPACE_SIZE, PhysAddr, VirtAddr, memmap::PHYS_MAP};
+#[cfg(target_pointer_width = "32")]
+use crate::elf::{
+	ELF32ELFHeader as ElfHeader, ELF32ProgramHeader as ProgramHeader, ELFCLASS32 as ELFCLASS,
+};
+#[cfg(target_pointer_width = "64")]
+use crate::elf::{
+	ELF64ELFHeader as ElfHeader, ELF64ProgramHeader as ProgramHeader, ELFCLASS64 as ELFCLASS,
+};
+use crate::{
+	elf::{EI_CLASS, ET_CORE, PT_LOAD, PT_NOTE},
+	initcall, println,
+	sync::once::OnceInit,
+};
+use core::{cmp::min, mem, slice};
+use utils::{
+	bytes::{as_bytes, as_bytes_mut},
+	collections::vec::Vec,
+	errno,
+	errno::EResult,
+	limits::PAGE_SIZE,
+};
+
+/// The size of the header of a note.
+const NOTE_HDR_SIZE: usize = 12;
+
+/// Returns the crashed kernel's memory in the physical range starting at `addr`, of `len` bytes.
+///
+/// If the range is not mapped in kernelspace, the function returns [`errno::EFAULT`].
+fn old_memory(addr: u64, len: u64) -> EResult<&'static [u8]> {
+	let mapped = min(PHYS_MAP.memory_size, KERNELSPACE_SIZE / PAGE_SIZE) * PAGE_SIZE;
+	let end = addr.checked_add(len).ok_or_else(|| errno!(EFAULT))?;
+	if end > mapped as u64 {
+		return Err(errno!(EFAULT));
+	}
+	let virt = PhysAddr(addr as _).kernel_to_virtual().unwrap();
+	Ok(unsafe { slice::from_raw_parts(virt.as_ptr(), len as _) })
+}
+
+/// Returns the size of the notes in `notes`, ignoring the padding at the end.
+///
+/// The crashed kernel allocates room for notes before knowing their actual size, so the segment
+/// is usually followed by zeros.
+fn notes_size(notes: &[u8]) -> usize {
+	let mut off = 0;
+	while let Some(hdr) = notes.get(off..(off + NOTE_HDR_SIZE)) {
+		let namesz = u32::from_ne_bytes(hdr[0..4].try_into().unwrap()) as usize;
+		let descsz = u32::from_ne_bytes(hdr[4..8].try_into().unwrap()) as usize;
+		if namesz == 0 {
+			break;
+		}
+		let len = NOTE_HDR_SIZE + namesz.next_multiple_of(4) + descsz.next_multiple_of(4);
+		if off + len > notes.len() {
+			break;
+		}
+		off += len;
+	}
+	off
+}
+
+/// A segment of the crashed kernel's memory, in the dump.
+#[derive(Debug)]
+struct Segment {
+	/// The offset of the segment in the dump.
+	off: u64,
+	/// The address of the segment's content in kernelspace.
+	addr: VirtAddr,
+	/// The size of the segment, in bytes.
+	size: u64,
+}
+
+/// The crash dump.
+#[derive(Debug)]
+pub struct Vmcore {
+	/// The ELF header, followed by program headers and notes.
+	headers: Vec<u8>,
+	/// The segments of memory, following the headers.
+	segments: Vec<Segment>,
+}
+
+impl Vmcore {
+	/// Builds the dump from the ELF core header at the physical address `addr`.
+	fn new(addr: u64) -> EResult<Self> {
+		let ehdr_bytes = old_memory(addr, size_of::<ElfHeader>() as _)?;
+		// The content is valid for any bytes
+		let mut ehdr: ElfHeader = unsafe { mem::zeroed() };
+		as_bytes_mut(&mut ehdr).copy_from_slice(ehdr_bytes);
+		let valid = ehdr.e_ident.starts_with(b"\x7fELF")
+			&& ehdr.e_ident[EI_CLASS] == ELFCLASS
+			&& ehdr.e_type == ET_CORE
+			&& ehdr.e_phentsize as usize == size_of::<ProgramHeader>()
+			&& ehdr.e_phnum > 0;
+		if !valid {
+			return Err(errno!(EINVAL));
+		}
+		let phnum = ehdr.e_phnum as usize;
+		let phdrs_bytes = old_memory(
+			addr + ehdr.e_phoff as u64,
+			(phnum * size_of::<ProgramHeader>()) as _,
+		)?;
+		let mut phdrs = Vec::with_capacity(phnum)?;
+		for _ in 0..phnum {
+			phdrs.push(unsafe { mem::zeroed::<ProgramHeader>() })?;
+		}
+		as_bytes_mut(phdrs.as_mut_slice()).copy_from_slice(phdrs_bytes);
+		// Lay out the content of segments after the headers
+		let mut off = size_of::<ElfHeader>() + phnum * size_of::<ProgramHeader>();
+		let mut notes = Vec::new();
+		for phdr in phdrs.iter_mut().filter(|phdr| phdr.p_type == PT_NOTE) {
+			let content = old_memory(phdr.p_offset as _, phdr.p_filesz as _)?;
+			let content = &content[..notes_size(content)];
+			notes.extend_from_slice(content)?;
+			phdr.p_offset = off as _;
+			phdr.p_filesz = content.len() as _;
+			phdr.p_memsz = content.len() as _;
+			off += content.len();
+		}
+		let mut segments = Vec::new();
+		for phdr in phdrs.iter_mut().filter(|phdr| phdr.p_type == PT_LOAD) {
+			let content = old_memory(phdr.p_offset as _, phdr.p_filesz as _)?;
+			segments.push(Segment {
+				off: off as _,
+				addr: VirtAddr::from(content.as_ptr()),
+				size: content.len() as _,
+			})?;
+			phdr.p_offset = off as _;
+			off += content.len();
+		}
+		// Section headers are not included
+		ehdr.e_phoff = size_of::<ElfHeader>() as _;
+		ehdr.e_shoff = 0;
+		ehdr.e_shnum = 0;
+		ehdr.e_shstrndx = 0;
+		let mut headers = Vec::try_from(as_bytes(&ehdr))?;
+		headers.extend_from_slice(as_bytes(phdrs.as_slice()))?;
+		headers.extend_from_slice(&notes)?;
+		Ok(Self {
+			headers,
+			segments,
+		})
+	}
+
+	/// Returns the size of the dump, in bytes.
+	pub fn size(&self) -> u64 {
+		self.segments
+			.last()
+			.map(|seg| seg.off + seg.size)
+			.unwrap_or(self.headers.len() as _)
+	}
+
+	/// Returns the content of the dump at the offset `off`, up to the end of the header or
+	/// segment it is in.
+	///
+	/// If `off` is beyond the end of the dump, the function returns an empty slice.
+	pub fn content_at(&self, off: u64) -> &[u8] {
+		if let Some(content) = self.headers.get(off as usize..) {
+			return content;
+		}
+		let Some(seg) = self
+			.segments
+			.iter()
+			.find(|seg| (seg.off..(seg.off + seg.size)).contains(&off))
+		else {
+			return &[];
+		};
+		let inner_off = (off - seg.off) as usize;
+		// Checked on creation
+		let content = unsafe { slice::from_raw_parts(seg.addr.as_ptr(), seg.size as _) };
+		&content[inner_off..]
+	}
+}
+
+/// The crash dump, if the kernel is a crash kernel.
+static VMCORE: OnceInit<Option<Vmcore>> = unsafe { OnceInit::new() };
+
+initcall!(subsys, vmcore, |args| {
+	let vmcore = args.get_elfcorehdr().and_then(|addr| {
+		// The system can still boot to let the user investigate
+		Vmcore::new(addr as _)
+			.inspect_err(|e| println!("Cannot load the crash dump: {e}"))
+			.ok()
+	});
+	unsafe {
+		OnceInit::init(&VMCORE, vmcore);
+	}
+	Ok(())
+});
+
+/// Returns the crash dump, or `None` if the kernel is not a crash kernel.
+pub fn get() -> Option<&'static Vmcore> {
+	VMCORE.as_ref()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn vmcore_notes_size() {
+		let mut notes = [0u8; 64];
+		// A note with a 5 bytes name and a 6 bytes descriptor
+		notes[0..4].copy_from_slice(&5u32.to_ne_bytes());
+		notes[4..8].copy_from_slice(&6u32.to_ne_bytes());
+		notes[12..17].copy_from_slice(b"CORE\0");
+		assert_eq!(notes_size(&notes), 12 + 8 + 8);
+		assert_eq!(notes_size(&notes[..20]), 0);
+		assert_eq!(notes_size(&[]), 0);
+	}
+}
@@ -22,16 +22,22 @@
 use crate::{
 	arch::{
 		x86,
-		x86::paging::{
-			FLAG_CACHE_DISABLE, FLAG_GLOBAL, FLAG_USER, FLAG_WRITE, FLAG_WRITE_THROUGH,
+		x86::{
+			paging::{FLAG_CACHE_DISABLE, FLAG_GLOBAL, FLAG_USER, FLAG_WRITE, FLAG_WRITE_THROUGH},
+			smp,
 		},
 	},
 	elf, memory,
 	memory::{KERNELSPACE_SIZE, PhysAddr, VirtAddr, buddy, memmap::PHYS_MAP},
+	process::{scheduler, scheduler::core_local},
 	sync::{mutex::Mutex, once::OnceInit},
 	tty::vga,
 };
-use core::{cmp::min, ptr::NonNull, sync::atomic::Ordering::Release};
+use core::{
+	cmp::min,
+	ptr::NonNull,
+	sync::atomic::Ordering::{Relaxed, Release},
+};
 use utils::limits::PAGE_SIZE;
 
 /// A virtual memory context.
@@ -93,6 +99,7 @@ impl VMem {
 			x86::paging::map(self.inner_mut(), physaddr, virtaddr, flags);
 		}
 		invalidate_page_current(virtaddr);
+		self.shootdown(virtaddr, 1);
 	}
 
 	/// Like [`Self::map`] but on a range of several pages.
@@ -108,8 +115,13 @@ impl VMem {
 		for i in 0..pages {
 			let physaddr = physaddr + i * PAGE_SIZE;
 			let virtaddr = virtaddr + i * PAGE_SIZE;
-			self.map(physaddr, virtaddr, flags);
+			#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+			unsafe {
+				x86::paging::map(self.inner_mut(), physaddr, virtaddr, flags);
+			}
+			invalidate_page_current(virtaddr);
 		}
+		self.shootdown(virtaddr, pages);
 	}
 
 	/// Unmaps a single page of virtual memory at `virtaddr`.
@@ -120,6 +132,7 @@ impl VMem {
 			x86::paging::unmap(self.inner_mut(), virtaddr);
 		}
 		invalidate_page_current(virtaddr);
+		self.shootdown(virtaddr, 1);
 	}
 
 	/// Like [`Self::unmap`] but on a range of several pages.
@@ -128,8 +141,30 @@ impl VMem {
 	pub fn unmap_range(&mut self, virtaddr: VirtAddr, pages: usize) {
 		for i in 0..pages {
 			let virtaddr = virtaddr + i * PAGE_SIZE;
-			self.unmap(virtaddr);
+			#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+			unsafe {
+				x86::paging::unmap(self.inner_mut(), virtaddr);
+			}
+			invalidate_page_current(virtaddr);
 		}
+		self.shootdown(virtaddr, pages);
+	}
+
+	/// Invalidates the range of `pages` pages starting at `addr` on the other cores using the
+	/// context.
+	///
+	/// Kernelspace is shared by all contexts, so modifications to it are propagated to every core.
+	fn shootdown(&self, addr: VirtAddr, pages: usize) {
+		let online = scheduler::online_cpus();
+		let targets = if addr >= memory::KERNEL_BEGIN {
+			online
+		} else {
+			let table = self.table.as_ptr() as usize;
+			scheduler::iter_cpus(online)
+				.filter(|core| scheduler::core_local_at(*core).vmem.load(Relaxed) == table)
+				.fold(0, |mask, core| mask | (1 << core))
+		};
+		smp::shootdown(targets, addr, pages);
 	}
 
 	/// Polls the dirty flags on the range of `pages` pages starting at `addr`, clearing them
@@ -155,6 +190,10 @@ impl VMem {
 			#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 			x86::paging::bind(phys_addr);
 		}
+		// Allow other cores to find which cores use the context
+		core_local()
+			.vmem
+			.store(self.table.as_ptr() as usize, Relaxed);
 	}
 
 	/// Tells whether the context is bound to the current CPU.
@@ -302,6 +341,12 @@ mod test {
 		}
 	}
 
+	#[test_case]
+	fn vmem_null_page() {
+		// Dereferencing a null pointer in the kernel must fault
+		assert_eq!(KERNEL_VMEM.lock().translate(VirtAddr(0)), None);
+	}
+
 	#[test_case]
 	fn vmem_map0() {
 		let mut vmem = unsafe { VMem::new() };
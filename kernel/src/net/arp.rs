@@ -0,0 +1,260 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The Address Resolution Protocol (ARP) neighbor table, associating IPv4 addresses to MAC
+//! addresses. Each network namespace has its own table.
+//!
+//! Entries can be configured by administrators through the `SIOCSARP` and `SIOCDARP` ioctls, or
+//! through `RTM_NEWNEIGH` and `RTM_DELNEIGH` Netlink requests (see [`super::netlink`]).
+
+use super::{Address, IFNAMSIZ, MAC, NetNamespace, ifname};
+use crate::{
+	file::perm::CAP_NET_ADMIN,
+	memory::user::UserPtr,
+	process::Process,
+	syscall::{
+		FromSyscallArg,
+		ioctl::{SIOCDARP, SIOCGARP, SIOCSARP},
+	},
+};
+use core::{
+	ffi::{c_int, c_ulong, c_void},
+	fmt,
+	fmt::Formatter,
+};
+use utils::{
+	DisplayableStr,
+	collections::btreemap::BTreeMap,
+	errno,
+	errno::{AllocResult, EResult},
+	format,
+};
+
+/// Address family: IPv4
+const AF_INET: u16 = 2;
+/// ARP hardware type: Ethernet
+const ARPHRD_ETHER: u16 = 1;
+
+/// Entry flag: the entry is complete.
+pub const ATF_COM: c_int = 0x02;
+/// Entry flag: the entry is permanent, it is not removed when the table is flushed.
+pub const ATF_PERM: c_int = 0x04;
+/// Entry flag: publish the entry.
+pub const ATF_PUBL: c_int = 0x08;
+
+/// Generic socket address (`struct sockaddr`).
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct RawSockAddr {
+	/// The address family.
+	pub sa_family: u16,
+	/// The address, whose format depends on the family.
+	pub sa_data: [u8; 14],
+}
+
+/// ARP ioctl request (`struct arpreq`).
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct ArpReq {
+	/// Protocol address.
+	pub arp_pa: RawSockAddr,
+	/// Hardware address.
+	pub arp_ha: RawSockAddr,
+	/// Flags.
+	pub arp_flags: c_int,
+	/// Netmask of the protocol address.
+	pub arp_netmask: RawSockAddr,
+	/// Name of the interface, NUL-terminated.
+	pub arp_dev: [u8; IFNAMSIZ],
+}
+
+/// An entry of the neighbor table.
+#[derive(Debug)]
+struct Neighbor {
+	/// The hardware address.
+	mac: MAC,
+	/// The name of the interface the neighbor is reachable through, NUL-padded.
+	dev: [u8; IFNAMSIZ],
+	/// Flags.
+	flags: c_int,
+}
+
+/// A neighbor table, by IPv4 address.
+#[derive(Debug, Default)]
+pub struct NeighborTable(BTreeMap<[u8; 4], Neighbor>);
+
+impl NeighborTable {
+	/// Inserts or replaces the entry for the IPv4 address `addr`.
+	///
+	/// Arguments:
+	/// - `mac` is the hardware address of the neighbor
+	/// - `dev` is the name of the interface the neighbor is reachable through
+	/// - `flags` are the entry's flags. [`ATF_COM`] is always set
+	pub fn insert(
+		&mut self,
+		addr: [u8; 4],
+		mac: MAC,
+		dev: &[u8],
+		flags: c_int,
+	) -> AllocResult<()> {
+		let mut buf = [0; IFNAMSIZ];
+		let len = dev.len().min(IFNAMSIZ - 1);
+		buf[..len].copy_from_slice(&dev[..len]);
+		self.0.insert(
+			addr,
+			Neighbor {
+				mac,
+				dev: buf,
+				flags: flags | ATF_COM,
+			},
+		)?;
+		Ok(())
+	}
+
+	/// Removes the entry for the IPv4 address `addr`.
+	///
+	/// If the entry does not exist, the function returns `false`.
+	pub fn remove(&mut self, addr: [u8; 4]) -> bool {
+		self.0.remove(&addr).is_some()
+	}
+
+	/// Returns the hardware address of the neighbor with the IPv4 address `addr`, if known.
+	pub fn lookup(&self, addr: [u8; 4]) -> Option<MAC> {
+		self.0.get(&addr).map(|n| n.mac)
+	}
+
+	/// Removes every entry that is not permanent.
+	pub fn flush(&mut self) {
+		self.0.retain(|_, n| n.flags & ATF_PERM != 0);
+	}
+}
+
+/// Returns the IPv4 address in the given socket address.
+fn ipv4_addr(sockaddr: &RawSockAddr) -> EResult<[u8; 4]> {
+	if sockaddr.sa_family != AF_INET {
+		return Err(errno!(EAFNOSUPPORT));
+	}
+	// Skip the port
+	Ok(sockaddr.sa_data[2..6].try_into().unwrap())
+}
+
+/// Handles an ARP ioctl `request` with argument `argp`, on the neighbor table of the network
+/// namespace `ns`.
+pub fn ioctl(ns: &NetNamespace, request: c_ulong, argp: *const c_void) -> EResult<u32> {
+	let req_ptr = UserPtr::<ArpReq>::from_ptr(argp as usize);
+	let mut req = req_ptr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let addr = ipv4_addr(&req.arp_pa)?;
+	match request {
+		SIOCGARP => {
+			let neighbors = ns.neighbors.lock();
+			let neighbor = neighbors.0.get(&addr).ok_or_else(|| errno!(ENXIO))?;
+			req.arp_ha.sa_family = ARPHRD_ETHER;
+			req.arp_ha.sa_data[..6].copy_from_slice(&neighbor.mac);
+			req.arp_flags = neighbor.flags;
+			req.arp_dev = neighbor.dev;
+			req_ptr.copy_to_user(&req)?;
+		}
+		SIOCSARP | SIOCDARP => {
+			let privileged = Process::current()
+				.fs
+				.lock()
+				.access_profile
+				.has_capability(CAP_NET_ADMIN);
+			if !privileged {
+				return Err(errno!(EPERM));
+			}
+			if request == SIOCDARP {
+				if !ns.neighbors.lock().remove(addr) {
+					return Err(errno!(ENXIO));
+				}
+				return Ok(0);
+			}
+			if req.arp_ha.sa_family != ARPHRD_ETHER {
+				return Err(errno!(EINVAL));
+			}
+			let dev = ifname(&req.arp_dev);
+			if !dev.is_empty() && ns.get_iface(dev).is_none() {
+				return Err(errno!(ENODEV));
+			}
+			let mac = req.arp_ha.sa_data[..6].try_into().unwrap();
+			ns.neighbors.lock().insert(addr, mac, dev, req.arp_flags)?;
+		}
+		_ => return Err(errno!(EINVAL)),
+	}
+	Ok(0)
+}
+
+/// Displays the neighbor table of a network namespace in the format of `/proc/net/arp`.
+pub struct ArpTable<'n>(pub &'n NetNamespace);
+
+impl fmt::Display for ArpTable<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"IP address       HW type     Flags       HW address            Mask     Device"
+		)?;
+		let neighbors = self.0.neighbors.lock();
+		for (addr, n) in neighbors.0.iter() {
+			let ip = format!("{}", Address::IPv4(*addr)).map_err(|_| fmt::Error)?;
+			// The address only contains ASCII digits and dots
+			let ip = ip.as_str().unwrap();
+			let mac = n.mac;
+			writeln!(
+				f,
+				"{ip:<16} 0x{ARPHRD_ETHER:<9x} 0x{flags:<9x} \
+				 {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}     *        {dev}",
+				mac[0],
+				mac[1],
+				mac[2],
+				mac[3],
+				mac[4],
+				mac[5],
+				flags = n.flags,
+				dev = DisplayableStr(ifname(&n.dev))
+			)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn arp_flush_keeps_permanent() {
+		let mut table = NeighborTable::default();
+		let mac = [0x02, 0, 0, 0, 0, 1];
+		table.insert([10, 0, 0, 1], mac, b"lo", 0).unwrap();
+		table.insert([10, 0, 0, 2], mac, b"lo", ATF_PERM).unwrap();
+		assert_eq!(table.lookup([10, 0, 0, 1]), Some(mac));
+		table.flush();
+		assert_eq!(table.lookup([10, 0, 0, 1]), None);
+		assert_eq!(table.lookup([10, 0, 0, 2]), Some(mac));
+		assert!(table.remove([10, 0, 0, 2]));
+		assert!(!table.remove([10, 0, 0, 2]));
+	}
+
+	#[test_case]
+	fn arp_ipv4_display() {
+		let fmt = |addr| format!("{}", Address::IPv4(addr)).unwrap();
+		assert_eq!(fmt([192, 168, 0, 1]).as_bytes(), b"192.168.0.1");
+		assert_eq!(fmt([255, 255, 255, 255]).as_bytes(), b"255.255.255.255");
+		assert_eq!(fmt([0, 10, 0, 9]).as_bytes(), b"0.10.0.9");
+	}
+}
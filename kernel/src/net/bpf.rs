@@ -0,0 +1,396 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Classic Berkeley Packet Filter (cBPF) implementation.
+//!
+//! A filter is a small program, provided by userspace, which is run on each packet to decide
+//! whether it must be accepted and how many bytes of it must be kept.
+//!
+//! Filters are also used by seccomp, to decide whether a system call is allowed. In this case,
+//! the program runs on a [`crate::uapi::seccomp::SeccompData`] structure.
+
+use utils::{collections::vec::Vec, errno, errno::EResult};
+
+/// The maximum number of instructions in a program.
+pub const MAX_INSNS: usize = 4096;
+/// The number of words in the scratch memory.
+const MEM_WORDS: usize = 16;
+
+/// Instruction class: load into `A`
+const BPF_LD: u16 = 0x00;
+/// Instruction class: load into `X`
+const BPF_LDX: u16 = 0x01;
+/// Instruction class: store `A`
+const BPF_ST: u16 = 0x02;
+/// Instruction class: store `X`
+const BPF_STX: u16 = 0x03;
+/// Instruction class: arithmetic and logic
+const BPF_ALU: u16 = 0x04;
+/// Instruction class: jump
+const BPF_JMP: u16 = 0x05;
+/// Instruction class: return
+const BPF_RET: u16 = 0x06;
+/// Instruction class: miscellaneous
+const BPF_MISC: u16 = 0x07;
+
+/// Load size: word
+const BPF_W: u16 = 0x00;
+/// Load size: half-word
+const BPF_H: u16 = 0x08;
+/// Load size: byte
+const BPF_B: u16 = 0x10;
+
+/// Load mode: immediate
+const BPF_IMM: u16 = 0x00;
+/// Load mode: absolute offset in the packet
+const BPF_ABS: u16 = 0x20;
+/// Load mode: offset in the packet relative to `X`
+const BPF_IND: u16 = 0x40;
+/// Load mode: scratch memory
+const BPF_MEM: u16 = 0x60;
+/// Load mode: length of the packet
+const BPF_LEN: u16 = 0x80;
+/// Load mode: IP header length
+const BPF_MSH: u16 = 0xa0;
+
+/// ALU operation: addition
+const BPF_ADD: u16 = 0x00;
+/// ALU operation: subtraction
+const BPF_SUB: u16 = 0x10;
+/// ALU operation: multiplication
+const BPF_MUL: u16 = 0x20;
+/// ALU operation: division
+const BPF_DIV: u16 = 0x30;
+/// ALU operation: bitwise OR
+const BPF_OR: u16 = 0x40;
+/// ALU operation: bitwise AND
+const BPF_AND: u16 = 0x50;
+/// ALU operation: left shift
+const BPF_LSH: u16 = 0x60;
+/// ALU operation: right shift
+const BPF_RSH: u16 = 0x70;
+/// ALU operation: negation
+const BPF_NEG: u16 = 0x80;
+/// ALU operation: modulo
+const BPF_MOD: u16 = 0x90;
+/// ALU operation: bitwise XOR
+const BPF_XOR: u16 = 0xa0;
+
+/// Jump operation: always
+const BPF_JA: u16 = 0x00;
+/// Jump operation: if equal
+const BPF_JEQ: u16 = 0x10;
+/// Jump operation: if greater
+const BPF_JGT: u16 = 0x20;
+/// Jump operation: if greater or equal
+const BPF_JGE: u16 = 0x30;
+/// Jump operation: if any of the bits are set
+const BPF_JSET: u16 = 0x40;
+
+/// Source operand: constant `k`
+const BPF_K: u16 = 0x00;
+/// Source operand: register `X`
+const BPF_X: u16 = 0x08;
+/// Source operand: register `A` (for `BPF_RET` only)
+const BPF_A: u16 = 0x10;
+
+/// Miscellaneous operation: copy `A` into `X`
+const BPF_TAX: u16 = 0x00;
+/// Miscellaneous operation: copy `X` into `A`
+const BPF_TXA: u16 = 0x80;
+
+/// A filter instruction, as passed by userspace (`struct sock_filter`).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SockFilter {
+	/// The operation code.
+	pub code: u16,
+	/// Jump offset if the condition is true.
+	pub jt: u8,
+	/// Jump offset if the condition is false.
+	pub jf: u8,
+	/// Generic multi-use field.
+	pub k: u32,
+}
+
+/// A validated filter program.
+#[derive(Debug)]
+pub struct Program {
+	/// The program's instructions.
+	insns: Vec<SockFilter>,
+	/// If `true`, words are loaded in native endianness instead of network endianness.
+	native_endian: bool,
+}
+
+impl Program {
+	/// Validates the given instructions and creates a program from them.
+	///
+	/// If the program is invalid, the function returns [`errno::EINVAL`].
+	pub fn new(insns: Vec<SockFilter>) -> EResult<Self> {
+		if insns.is_empty() || insns.len() > MAX_INSNS {
+			return Err(errno!(EINVAL));
+		}
+		for (pc, insn) in insns.iter().enumerate() {
+			let remaining = insns.len() - pc - 1;
+			let valid = match insn.code & 0x07 {
+				BPF_LD | BPF_LDX => match insn.code & 0xe0 {
+					BPF_MEM => (insn.k as usize) < MEM_WORDS,
+					BPF_IMM | BPF_ABS | BPF_IND | BPF_LEN | BPF_MSH => true,
+					_ => false,
+				},
+				BPF_ST | BPF_STX => (insn.k as usize) < MEM_WORDS,
+				BPF_ALU => match insn.code & 0xf0 {
+					// Reject constant division by zero
+					BPF_DIV | BPF_MOD => insn.code & BPF_X != 0 || insn.k != 0,
+					BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_LSH | BPF_RSH
+					| BPF_NEG | BPF_XOR => true,
+					_ => false,
+				},
+				// Only forward jumps are allowed, which guarantees termination
+				BPF_JMP => match insn.code & 0xf0 {
+					BPF_JA => (insn.k as usize) < remaining,
+					BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+						(insn.jt as usize) < remaining && (insn.jf as usize) < remaining
+					}
+					_ => false,
+				},
+				BPF_RET => matches!(insn.code & 0x18, BPF_K | BPF_A),
+				BPF_MISC => matches!(insn.code & 0xf8, BPF_TAX | BPF_TXA),
+				_ => false,
+			};
+			if !valid {
+				return Err(errno!(EINVAL));
+			}
+		}
+		// The program must end with a return
+		if insns.last().unwrap().code & 0x07 != BPF_RET {
+			return Err(errno!(EINVAL));
+		}
+		Ok(Self {
+			insns,
+			native_endian: false,
+		})
+	}
+
+	/// Validates the given instructions and creates a seccomp filter from them.
+	///
+	/// `data_len` is the size of the data the program runs on. Loads from the data must be
+	/// aligned 32 bits words within this size, and are performed in native endianness.
+	///
+	/// If the program is invalid, the function returns [`errno::EINVAL`].
+	pub fn new_seccomp(insns: Vec<SockFilter>, data_len: usize) -> EResult<Self> {
+		let valid = insns.iter().all(|insn| match insn.code & 0x07 {
+			// Only word loads are allowed (`BPF_W` being zero, the size bits must be clear)
+			BPF_LD => match insn.code & 0xf8 {
+				BPF_ABS => (insn.k as usize) < data_len && insn.k.is_multiple_of(4),
+				BPF_IMM | BPF_MEM | BPF_LEN => true,
+				_ => false,
+			},
+			BPF_LDX => matches!(insn.code & 0xe0, BPF_IMM | BPF_MEM | BPF_LEN),
+			_ => true,
+		});
+		if !valid {
+			return Err(errno!(EINVAL));
+		}
+		let mut prog = Self::new(insns)?;
+		prog.native_endian = true;
+		Ok(prog)
+	}
+
+	/// Runs the program on `data`.
+	///
+	/// The function returns the value returned by the program. For socket filters, this is the
+	/// number of bytes of the packet to keep (zero meaning the packet is dropped).
+	///
+	/// Out of bounds accesses to `data` make the program return zero.
+	pub fn run(&self, data: &[u8]) -> u32 {
+		let load = |off: u32, size: u16| -> Option<u32> {
+			let off = off as usize;
+			let len = match size {
+				BPF_W => 4,
+				BPF_H => 2,
+				_ => 1,
+			};
+			let bytes = data.get(off..off.checked_add(len)?)?;
+			if self.native_endian {
+				return Some(u32::from_ne_bytes(bytes.try_into().ok()?));
+			}
+			Some(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u32))
+		};
+		let mut a: u32 = 0;
+		let mut x: u32 = 0;
+		let mut mem = [0u32; MEM_WORDS];
+		let mut pc = 0;
+		loop {
+			let insn = &self.insns[pc];
+			pc += 1;
+			let src = if insn.code & BPF_X != 0 { x } else { insn.k };
+			match insn.code & 0x07 {
+				BPF_LD => {
+					a = match insn.code & 0xe0 {
+						BPF_IMM => insn.k,
+						BPF_ABS => match load(insn.k, insn.code & 0x18) {
+							Some(v) => v,
+							None => return 0,
+						},
+						BPF_IND => match load(x.wrapping_add(insn.k), insn.code & 0x18) {
+							Some(v) => v,
+							None => return 0,
+						},
+						BPF_MEM => mem[insn.k as usize],
+						BPF_LEN => data.len() as u32,
+						_ => return 0,
+					};
+				}
+				BPF_LDX => {
+					x = match insn.code & 0xe0 {
+						BPF_IMM => insn.k,
+						BPF_MEM => mem[insn.k as usize],
+						BPF_LEN => data.len() as u32,
+						BPF_MSH => match load(insn.k, BPF_B) {
+							Some(v) => (v & 0xf) << 2,
+							None => return 0,
+						},
+						_ => return 0,
+					};
+				}
+				BPF_ST => mem[insn.k as usize] = a,
+				BPF_STX => mem[insn.k as usize] = x,
+				BPF_ALU => {
+					a = match insn.code & 0xf0 {
+						BPF_ADD => a.wrapping_add(src),
+						BPF_SUB => a.wrapping_sub(src),
+						BPF_MUL => a.wrapping_mul(src),
+						BPF_DIV => match a.checked_div(src) {
+							Some(v) => v,
+							None => return 0,
+						},
+						BPF_MOD => match a.checked_rem(src) {
+							Some(v) => v,
+							None => return 0,
+						},
+						BPF_OR => a | src,
+						BPF_AND => a & src,
+						BPF_LSH => a.checked_shl(src).unwrap_or(0),
+						BPF_RSH => a.checked_shr(src).unwrap_or(0),
+						BPF_NEG => a.wrapping_neg(),
+						BPF_XOR => a ^ src,
+						_ => return 0,
+					};
+				}
+				BPF_JMP => {
+					let jump = match insn.code & 0xf0 {
+						BPF_JA => {
+							pc += insn.k as usize;
+							continue;
+						}
+						BPF_JEQ => a == src,
+						BPF_JGT => a > src,
+						BPF_JGE => a >= src,
+						BPF_JSET => a & src != 0,
+						_ => return 0,
+					};
+					pc += if jump { insn.jt } else { insn.jf } as usize;
+				}
+				BPF_RET => {
+					return if insn.code & 0x18 == BPF_A { a } else { insn.k };
+				}
+				BPF_MISC => {
+					if insn.code & 0xf8 == BPF_TAX {
+						x = a;
+					} else {
+						a = x;
+					}
+				}
+				_ => return 0,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use utils::vec;
+
+	fn insn(code: u16, jt: u8, jf: u8, k: u32) -> SockFilter {
+		SockFilter {
+			code,
+			jt,
+			jf,
+			k,
+		}
+	}
+
+	#[test_case]
+	fn bpf_validate() {
+		// Empty program
+		assert!(Program::new(Vec::new()).is_err());
+		// No return at the end
+		assert!(Program::new(vec![insn(BPF_LD | BPF_IMM, 0, 0, 1)].unwrap()).is_err());
+		// Jump out of bounds
+		let insns = vec![
+			insn(BPF_JMP | BPF_JA, 0, 0, 1),
+			insn(BPF_RET | BPF_K, 0, 0, 0),
+		]
+		.unwrap();
+		assert!(Program::new(insns).is_err());
+		// Constant division by zero
+		let insns = vec![
+			insn(BPF_ALU | BPF_DIV | BPF_K, 0, 0, 0),
+			insn(BPF_RET | BPF_K, 0, 0, 0),
+		]
+		.unwrap();
+		assert!(Program::new(insns).is_err());
+	}
+
+	#[test_case]
+	fn bpf_ethertype() {
+		// Accept IPv4 packets only
+		let insns = vec![
+			insn(BPF_LD | BPF_H | BPF_ABS, 0, 0, 12),
+			insn(BPF_JMP | BPF_JEQ | BPF_K, 0, 1, 0x0800),
+			insn(BPF_RET | BPF_K, 0, 0, 0xffff),
+			insn(BPF_RET | BPF_K, 0, 0, 0),
+		]
+		.unwrap();
+		let prog = Program::new(insns).unwrap();
+		let mut pkt = [0u8; 20];
+		pkt[12] = 0x08;
+		assert_eq!(prog.run(&pkt), 0xffff);
+		pkt[13] = 0x06;
+		assert_eq!(prog.run(&pkt), 0);
+		// Truncated packet
+		assert_eq!(prog.run(&pkt[..10]), 0);
+	}
+
+	#[test_case]
+	fn bpf_alu() {
+		let insns = vec![
+			insn(BPF_LD | BPF_W | BPF_LEN, 0, 0, 0),
+			insn(BPF_ALU | BPF_MUL | BPF_K, 0, 0, 3),
+			insn(BPF_MISC | BPF_TAX, 0, 0, 0),
+			insn(BPF_LD | BPF_IMM, 0, 0, 1),
+			insn(BPF_ALU | BPF_ADD | BPF_X, 0, 0, 0),
+			insn(BPF_RET | BPF_A, 0, 0, 0),
+		]
+		.unwrap();
+		let prog = Program::new(insns).unwrap();
+		assert_eq!(prog.run(&[0; 4]), 13);
+	}
+}
@@ -19,6 +19,7 @@
 //! TODO doc
 
 use core::ptr::NonNull;
+use utils::{collections::vec::Vec, errno::AllocResult};
 
 /// A linked-list of buffers representing a packet being built.
 ///
@@ -51,6 +52,17 @@ impl<'b> BuffList<'b> {
 		self.b.len() + self.next_len
 	}
 
+	/// Copies the content of the list into a contiguous buffer.
+	pub fn to_vec(&self) -> AllocResult<Vec<u8>> {
+		let mut vec = Vec::with_capacity(self.len())?;
+		let mut cur = Some(self);
+		while let Some(buff) = cur {
+			vec.extend_from_slice(buff.b)?;
+			cur = buff.next.map(|next| unsafe { next.as_ref() });
+		}
+		Ok(vec)
+	}
+
 	/// Pushes another buffer at the front of the current list.
 	///
 	/// The function returns the new head of the list (which is the given `front`).
@@ -18,11 +18,24 @@
 
 //! This module implements the IP protocol.
 
-use super::{buff::BuffList, osi::Layer};
+use super::{
+	Address, Interface, LOOPBACK_INDEX,
+	buff::BuffList,
+	netfilter,
+	netfilter::{Hook, HookContext, Verdict},
+	osi::Layer,
+	udp,
+};
 use crate::crypto::checksum;
 use core::mem::size_of;
 use macros::AnyRepr;
-use utils::{boxed::Box, bytes::as_bytes, errno::EResult};
+use utils::{
+	boxed::Box,
+	bytes::{as_bytes, from_bytes},
+	collections::vec::Vec,
+	errno,
+	errno::EResult,
+};
 
 /// The default TTL value.
 const DEFAULT_TTL: u8 = 128;
@@ -83,6 +96,126 @@ impl IPv4Header {
 	}
 }
 
+/// The length of the IPv4 header, without options.
+pub const IPV4_HDR_LEN: usize = size_of::<IPv4Header>();
+
+/// Returns the IPv4 header, without options, of a datagram carrying `payload_len` bytes of the
+/// protocol `protocol` from `src_addr` to `dst_addr`.
+///
+/// The header is in network byte order and its checksum is filled.
+pub fn build_header(
+	protocol: u8,
+	src_addr: [u8; 4],
+	dst_addr: [u8; 4],
+	payload_len: u16,
+) -> [u8; IPV4_HDR_LEN] {
+	let mut hdr = IPv4Header {
+		version_ihl: (4 << 4) | (IPV4_HDR_LEN / 4) as u8,
+		type_of_service: 0,
+		total_length: (IPV4_HDR_LEN as u16 + payload_len).to_be(),
+
+		identification: 0,
+		flags_fragment_offset: ((FLAG_DF as u16) << 13).to_be(),
+
+		ttl: DEFAULT_TTL,
+		protocol,
+		hdr_checksum: 0,
+
+		src_addr,
+		dst_addr,
+	};
+	hdr.compute_checksum();
+	as_bytes(&hdr).try_into().unwrap()
+}
+
+/// Passes the packet made of `hdr` followed by `payload` through the hooks `hooks`.
+///
+/// If a hook drops the packet, the function returns [`errno::EPERM`].
+fn run_output_hooks(hooks: &[Hook], hdr: &[u8], payload: &BuffList) -> EResult<()> {
+	let mut packet = None;
+	for hook in hooks.iter().copied() {
+		if !netfilter::is_hooked(hook) {
+			continue;
+		}
+		// Build the contiguous packet only if needed
+		let packet = match &mut packet {
+			Some(p) => p,
+			None => {
+				let mut p = Vec::try_from(hdr)?;
+				p.extend_from_slice(&payload.to_vec()?)?;
+				packet.insert(p)
+			}
+		};
+		let ctx = HookContext {
+			hook,
+			in_iface: None,
+			// TODO pass the interface once routing is done in this layer
+			out_iface: None,
+		};
+		if netfilter::run(&ctx, packet) == Verdict::Drop {
+			return Err(errno!(EPERM));
+		}
+	}
+	Ok(())
+}
+
+/// Handles the IP packet `packet`, received on `iface` in the network namespace with ID `ns_id`.
+///
+/// The packet goes through the [`Hook::PreRouting`] hook, then through [`Hook::LocalIn`] if it is
+/// destined to the local system, or [`Hook::Forward`] and [`Hook::PostRouting`] otherwise.
+/// Packets received on the loopback interface are always destined to the local system.
+///
+/// Malformed and dropped packets are discarded silently.
+pub fn receive(ns_id: u64, iface: &dyn Interface, packet: &[u8]) {
+	let Some(hdr) = from_bytes::<IPv4Header>(packet) else {
+		return;
+	};
+	// TODO IPv6
+	if hdr.version_ihl >> 4 != 4 || !hdr.check_checksum() {
+		return;
+	}
+	let hdr_len = (hdr.version_ihl & 0xf) as usize * 4;
+	let total_len = u16::from_be(hdr.total_length) as usize;
+	let Some(payload) = packet.get(hdr_len..total_len) else {
+		return;
+	};
+	// TODO reassemble fragments
+	let fragment = u16::from_be(hdr.flags_fragment_offset);
+	if fragment & ((FLAG_MF as u16) << 13) != 0 || fragment & 0x1fff != 0 {
+		return;
+	}
+	let mut ctx = HookContext {
+		hook: Hook::PreRouting,
+		in_iface: Some(iface.get_name()),
+		out_iface: None,
+	};
+	if netfilter::run(&ctx, packet) == Verdict::Drop {
+		return;
+	}
+	let src_addr = hdr.src_addr;
+	let dst_addr = hdr.dst_addr;
+	let local = iface.get_index() == LOOPBACK_INDEX
+		|| iface
+			.get_addresses()
+			.iter()
+			.any(|a| a.addr == Address::IPv4(dst_addr));
+	if local {
+		ctx.hook = Hook::LocalIn;
+		if netfilter::run(&ctx, packet) == Verdict::Accept && hdr.protocol == PROTO_UDP {
+			udp::receive(ns_id, src_addr, dst_addr, payload);
+		}
+	} else {
+		ctx.hook = Hook::Forward;
+		if netfilter::run(&ctx, packet) == Verdict::Drop {
+			return;
+		}
+		ctx.hook = Hook::PostRouting;
+		if netfilter::run(&ctx, packet) == Verdict::Accept {
+			// TODO forward the packet
+		}
+	}
+}
+
 /// The IPv6 header (RFC 8200).
 #[repr(C, packed)]
 struct IPv6Header {
@@ -141,6 +274,7 @@ impl Layer for IPv4Layer {
 		};
 		hdr.compute_checksum();
 		let hdr_buff = as_bytes(&hdr);
+		run_output_hooks(&[Hook::LocalOut, Hook::PostRouting], hdr_buff, &buff)?;
 		buff.push_front(hdr_buff.into());
 		next(buff)
 	}
@@ -0,0 +1,232 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Static network configuration at boot, from the `ip=` command line argument.
+//!
+//! The argument has the following format:
+//!
+//! ```text
+//! ip=<client-ip>:<server-ip>:<gw-ip>:<netmask>:<hostname>:<device>:<autoconf>
+//! ```
+//!
+//! Every field is optional. Only static configuration is supported, so `autoconf` must be either
+//! empty, `off` or `none`. `ip=off` and `ip=none` disable the configuration altogether.
+//!
+//! This allows setups such as NFS-root or netboot to have a working network before init runs.
+
+use super::{Address, BindAddress, Route};
+use crate::{initcall, println, process::ns};
+use utils::{
+	TryClone,
+	collections::{string::String, vec::Vec},
+	errno,
+	errno::EResult,
+	ptr::arc::Arc,
+};
+
+/// Parses the IPv4 address in dotted-decimal notation in `s`.
+///
+/// If the address is invalid, the function returns `None`.
+pub(super) fn parse_ipv4(s: &[u8]) -> Option<[u8; 4]> {
+	let mut addr = [0; 4];
+	let mut parts = s.split(|c| *c == b'.');
+	for b in &mut addr {
+		let part = parts.next()?;
+		*b = str::from_utf8(part).ok()?.parse().ok()?;
+	}
+	parts.next().is_none().then_some(addr)
+}
+
+/// Returns the prefix length corresponding to the subnet mask `mask`.
+///
+/// If the mask is not contiguous, the function returns `None`.
+fn mask_to_prefix(mask: [u8; 4]) -> Option<u8> {
+	let mask = u32::from_be_bytes(mask);
+	let prefix = mask.leading_ones();
+	(mask.checked_shl(prefix).unwrap_or(0) == 0).then_some(prefix as _)
+}
+
+/// Returns the default prefix length for the address `addr`, according to its class.
+fn default_prefix(addr: [u8; 4]) -> u8 {
+	match addr[0] {
+		0..128 => 8,
+		128..192 => 16,
+		_ => 24,
+	}
+}
+
+/// A static network configuration.
+#[derive(Debug)]
+pub struct IpConfig<'s> {
+	/// The address of the client.
+	pub client: [u8; 4],
+	/// The address of the server, used by network boot setups.
+	pub server: Option<[u8; 4]>,
+	/// The address of the gateway, if any.
+	pub gateway: Option<[u8; 4]>,
+	/// The subnet prefix length.
+	pub prefix: u8,
+	/// The hostname of the client, if any.
+	pub hostname: Option<&'s [u8]>,
+	/// The name of the interface to configure. If `None`, the first interface other than the
+	/// loopback is used.
+	pub device: Option<&'s [u8]>,
+}
+
+impl<'s> IpConfig<'s> {
+	/// Parses the value of the `ip=` command line argument.
+	///
+	/// If the configuration is disabled, the function returns `None`.
+	///
+	/// On error, the function returns a message describing the problem.
+	pub fn parse(s: &'s [u8]) -> Result<Option<Self>, &'static str> {
+		if matches!(s, b"off" | b"none") {
+			return Ok(None);
+		}
+		let mut fields = s.split(|c| *c == b':');
+		let mut next = || fields.next().filter(|f| !f.is_empty());
+		let (client, server, gateway, netmask, hostname, device, autoconf) =
+			(next(), next(), next(), next(), next(), next(), next());
+		if !matches!(autoconf, None | Some(b"off" | b"none")) {
+			return Err("unsupported network autoconfiguration");
+		}
+		let client = client
+			.ok_or("missing client address")
+			.and_then(|a| parse_ipv4(a).ok_or("invalid client address"))?;
+		let server = server
+			.map(|a| parse_ipv4(a).ok_or("invalid server address"))
+			.transpose()?;
+		let gateway = gateway
+			.map(|a| parse_ipv4(a).ok_or("invalid gateway address"))
+			.transpose()?;
+		let prefix = match netmask {
+			Some(mask) => parse_ipv4(mask)
+				.and_then(mask_to_prefix)
+				.ok_or("invalid netmask")?,
+			None => default_prefix(client),
+		};
+		Ok(Some(Self {
+			client,
+			server,
+			gateway,
+			prefix,
+			hostname,
+			device,
+		}))
+	}
+
+	/// Applies the configuration to the initial namespaces: binds the client address to the
+	/// interface, adds the routes and sets the hostname.
+	///
+	/// If the interface does not exist, the function returns [`errno::ENODEV`].
+	pub fn apply(&self) -> EResult<()> {
+		let ns = ns::init_namespaces()?;
+		let (name, iface) = {
+			let interfaces = ns.net.interfaces.lock();
+			let iface = match self.device {
+				Some(name) => interfaces.get(name).map(|iface| (name, iface)),
+				None => interfaces
+					.iter()
+					.filter(|(name, _)| name.as_bytes() != b"lo")
+					.min_by_key(|(_, iface)| iface.lock().get_index())
+					.map(|(name, iface)| (name.as_bytes(), iface)),
+			};
+			let (name, iface) = iface.ok_or_else(|| errno!(ENODEV))?;
+			(String::try_from(name)?, Arc::clone(iface))
+		};
+		iface.lock().add_address(BindAddress {
+			addr: Address::IPv4(self.client),
+			subnet_mask: self.prefix,
+		})?;
+		{
+			let mut routing_table = ns.net.routes.lock();
+			// The local subnet, directly reachable
+			let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+			let subnet = u32::from_be_bytes(self.client) & mask;
+			routing_table.push(Route {
+				dst: Some(BindAddress {
+					addr: Address::IPv4(subnet.to_be_bytes()),
+					subnet_mask: self.prefix,
+				}),
+				iface: name.try_clone()?,
+				gateway: Address::IPv4([0; 4]),
+				metric: 0,
+			})?;
+			if let Some(gateway) = self.gateway {
+				routing_table.push(Route {
+					dst: None,
+					iface: name,
+					gateway: Address::IPv4(gateway),
+					metric: 0,
+				})?;
+			}
+		}
+		if let Some(hostname) = self.hostname {
+			*ns.uts.hostname.lock() = Vec::try_from(hostname)?;
+		}
+		Ok(())
+	}
+}
+
+initcall!(late, ipconfig, |args| {
+	if let Some(ip_config) = args.get_ip_config() {
+		println!("Configuring network...");
+		// The system can still boot without network
+		if let Err(e) = ip_config.apply() {
+			println!("Failed to configure network: {e}");
+		}
+	}
+	Ok(())
+});
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn ipconfig_parse_full() {
+		let conf = IpConfig::parse(b"10.0.0.2::10.0.0.1:255.255.255.0:box:eth0:off")
+			.unwrap()
+			.unwrap();
+		assert_eq!(conf.client, [10, 0, 0, 2]);
+		assert_eq!(conf.server, None);
+		assert_eq!(conf.gateway, Some([10, 0, 0, 1]));
+		assert_eq!(conf.prefix, 24);
+		assert_eq!(conf.hostname, Some(&b"box"[..]));
+		assert_eq!(conf.device, Some(&b"eth0"[..]));
+	}
+
+	#[test_case]
+	fn ipconfig_parse_defaults() {
+		let conf = IpConfig::parse(b"172.16.0.5").unwrap().unwrap();
+		assert_eq!(conf.gateway, None);
+		assert_eq!(conf.prefix, 16);
+		assert_eq!(conf.hostname, None);
+		assert_eq!(conf.device, None);
+		assert!(IpConfig::parse(b"off").unwrap().is_none());
+	}
+
+	#[test_case]
+	fn ipconfig_parse_invalid() {
+		assert!(IpConfig::parse(b"").is_err());
+		assert!(IpConfig::parse(b"10.0.0").is_err());
+		assert!(IpConfig::parse(b"10.0.0.256").is_err());
+		assert!(IpConfig::parse(b"10.0.0.2::10.0.0.1:255.0.255.0").is_err());
+		assert!(IpConfig::parse(b"10.0.0.2:::::eth0:dhcp").is_err());
+	}
+}
@@ -18,17 +18,60 @@
 
 //! This module implements the local loopback.
 
-use super::{Address, BindAddress, Interface, MAC, buff::BuffList};
-use utils::errno::EResult;
+use super::{
+	Address, BindAddress, ETH_HDR_LEN, ETH_P_IP, Interface, LOOPBACK_INDEX, MAC, RxQueue,
+	buff::BuffList, ip, packet, packet::PACKET_HOST,
+};
+use utils::{
+	collections::vec::Vec,
+	errno::{AllocResult, EResult},
+	vec,
+};
 
 /// Local loopback interfaces allows the system to write data to itself.
-pub struct LocalLoopback {}
+///
+/// Each network namespace has its own loopback interface.
+pub struct LocalLoopback {
+	/// The ID of the network namespace the interface belongs to.
+	ns_id: u64,
+	/// The addresses bound to the interface.
+	addresses: Vec<BindAddress>,
+	/// The frames written to the interface, waiting to be read back.
+	rx: RxQueue,
+}
+
+impl LocalLoopback {
+	/// Creates the loopback interface of the network namespace with ID `ns_id`.
+	pub fn new(ns_id: u64) -> AllocResult<Self> {
+		Ok(Self {
+			ns_id,
+			addresses: vec![
+				BindAddress {
+					addr: Address::IPv4([127, 0, 0, 1]),
+					subnet_mask: 8,
+				},
+				BindAddress {
+					addr: Address::IPv6([
+						0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+						0x00, 0x00, 0x00, 0x01,
+					]),
+					subnet_mask: 128,
+				},
+			]?,
+			rx: RxQueue::default(),
+		})
+	}
+}
 
 impl Interface for LocalLoopback {
 	fn get_name(&self) -> &[u8] {
 		b"lo"
 	}
 
+	fn get_index(&self) -> u32 {
+		LOOPBACK_INDEX
+	}
+
 	fn is_up(&self) -> bool {
 		true
 	}
@@ -38,28 +81,25 @@ impl Interface for LocalLoopback {
 	}
 
 	fn get_addresses(&self) -> &[BindAddress] {
-		&[
-			BindAddress {
-				addr: Address::IPv4([127, 0, 0, 1]),
-				subnet_mask: 8,
-			},
-			BindAddress {
-				addr: Address::IPv6([
-					0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-					0x00, 0x00, 0x01,
-				]),
-				subnet_mask: 128,
-			},
-		]
+		&self.addresses
+	}
+
+	fn add_address(&mut self, addr: BindAddress) -> EResult<()> {
+		self.addresses.push(addr)?;
+		Ok(())
 	}
 
-	fn read(&mut self, _buff: &mut [u8]) -> EResult<u64> {
-		// TODO Write to ring buffer
-		todo!();
+	fn read(&mut self, buff: &mut [u8]) -> EResult<u64> {
+		self.rx.pop(buff)
 	}
 
-	fn write(&mut self, _buff: &BuffList<'_>) -> EResult<u64> {
-		// TODO Read from ring buffer
-		todo!();
+	fn write(&mut self, buff: &BuffList<'_>) -> EResult<u64> {
+		let frame = buff.to_vec()?;
+		packet::deliver(self.ns_id, LOOPBACK_INDEX, PACKET_HOST, &frame);
+		if frame.get(12..ETH_HDR_LEN) == Some(&ETH_P_IP.to_be_bytes()) {
+			ip::receive(self.ns_id, self, &frame[ETH_HDR_LEN..]);
+		}
+		self.rx.push(frame)?;
+		Ok(buff.len() as _)
 	}
 }
@@ -18,31 +18,69 @@
 
 //! Network stack implementation.
 
+pub mod arp;
+pub mod bpf;
 pub mod buff;
 pub mod icmp;
 pub mod ip;
+pub mod ipconfig;
 pub mod lo;
+pub mod netconsole;
+pub mod netfilter;
+pub mod netlink;
 pub mod osi;
+pub mod packet;
 pub mod sockaddr;
 pub mod tcp;
+pub mod udp;
+pub mod veth;
 
 use crate::{
-	file::perm::AccessProfile,
-	net::sockaddr::{SockAddrIn, SockAddrIn6},
-	sync::mutex::Mutex,
+	file::perm::{AccessProfile, CAP_NET_BIND_SERVICE, CAP_NET_RAW},
+	net::{
+		arp::NeighborTable,
+		lo::LocalLoopback,
+		sockaddr::{SockAddrIn, SockAddrIn6},
+	},
+	sync::{atomic::AtomicU64, mutex::Mutex},
 };
 use buff::BuffList;
-use core::{cmp::Ordering, mem::size_of};
+use core::{
+	cmp::{Ordering, min},
+	fmt, mem,
+	mem::size_of,
+	sync::atomic::{AtomicU32, Ordering::Relaxed},
+};
 use utils::{
 	collections::{hashmap::HashMap, string::String, vec::Vec},
 	errno,
-	errno::{EResult, Errno},
+	errno::{AllocResult, EResult, Errno},
 	ptr::arc::Arc,
 };
 
 /// Type representing a Media Access Control (MAC) address.
 pub type MAC = [u8; 6];
 
+/// The maximum length of an interface name, including the terminating NUL byte.
+pub const IFNAMSIZ: usize = 16;
+
+/// Index of the loopback interface, in every network namespace.
+pub const LOOPBACK_INDEX: u32 = 1;
+
+/// The length of the Ethernet header.
+pub const ETH_HDR_LEN: usize = 14;
+/// EtherType: IPv4
+pub const ETH_P_IP: u16 = 0x0800;
+
+/// The maximum number of received frames a virtual interface keeps until they are read.
+const RX_QUEUE_LEN: usize = 64;
+
+/// Returns the interface name stored in the NUL-padded buffer `buf`.
+pub fn ifname(buf: &[u8; IFNAMSIZ]) -> &[u8] {
+	let len = buf.iter().position(|b| *b == 0).unwrap_or(IFNAMSIZ);
+	&buf[..len]
+}
+
 // TODO allow implementation of custom protocols
 
 /// An enumeration of network address types.
@@ -54,6 +92,23 @@ pub enum Address {
 	IPv6([u8; 16]),
 }
 
+impl fmt::Display for Address {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::IPv4([a, b, c, d]) => write!(f, "{a}.{b}.{c}.{d}"),
+			Self::IPv6(addr) => {
+				for (i, group) in addr.array_chunks::<2>().enumerate() {
+					if i > 0 {
+						f.write_str(":")?;
+					}
+					write!(f, "{:x}", u16::from_be_bytes(*group))?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
 /// An address/subnet mask pair to be bound to an interface.
 #[derive(Debug)]
 pub struct BindAddress {
@@ -95,6 +150,11 @@ pub trait Interface {
 	/// Returns the name of the interface.
 	fn get_name(&self) -> &[u8];
 
+	/// Returns the index of the interface.
+	///
+	/// The index is a non-zero number uniquely identifying the interface on the system.
+	fn get_index(&self) -> u32;
+
 	/// Tells whether the interface is UP.
 	fn is_up(&self) -> bool;
 
@@ -104,6 +164,18 @@ pub trait Interface {
 	/// Returns the list of addresses bound to the interface.
 	fn get_addresses(&self) -> &[BindAddress];
 
+	/// Tells whether the interface can be deleted by userspace.
+	fn is_removable(&self) -> bool {
+		false
+	}
+
+	/// Called when the interface is removed from its network namespace, either explicitly or
+	/// because the namespace is destroyed.
+	fn unregister(&mut self) {}
+
+	/// Binds the address `addr` to the interface.
+	fn add_address(&mut self, addr: BindAddress) -> EResult<()>;
+
 	/// Reads data from the network interface and writes it into `buff`.
 	///
 	/// The function returns the number of bytes read.
@@ -115,6 +187,22 @@ pub trait Interface {
 	fn write(&mut self, buff: &BuffList<'_>) -> EResult<u64>;
 }
 
+/// Transmits the frame `buff` on the interface `iface` of the network namespace `ns`.
+///
+/// A copy of the frame is first handed to the packet sockets of the namespace (see
+/// [`packet::deliver`]).
+///
+/// The function returns the number of bytes written.
+pub fn transmit(
+	ns: &NetNamespace,
+	iface: &mut dyn Interface,
+	buff: &BuffList<'_>,
+) -> EResult<u64> {
+	let frame = buff.to_vec()?;
+	packet::deliver(ns.id(), iface.get_index(), packet::PACKET_OUTGOING, &frame);
+	iface.write(buff)
+}
+
 /// An entry in the routing table.
 pub struct Route {
 	/// The destination address. If `None`, this is the default destination.
@@ -182,47 +270,161 @@ impl Route {
 	}
 }
 
-/// The list of network interfaces.
-pub static INTERFACES: Mutex<HashMap<String, Arc<Mutex<dyn Interface>>>> =
-	Mutex::new(HashMap::new());
-/// The routing table.
-pub static ROUTING_TABLE: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+/// Queue of the frames received by a virtual interface, waiting to be read.
+#[derive(Default)]
+pub struct RxQueue(Vec<Vec<u8>>);
+
+impl RxQueue {
+	/// Pushes `frame` at the end of the queue.
+	///
+	/// If the queue is full, the frame is dropped.
+	pub fn push(&mut self, frame: Vec<u8>) -> AllocResult<()> {
+		if self.0.len() < RX_QUEUE_LEN {
+			self.0.push(frame)?;
+		}
+		Ok(())
+	}
+
+	/// Removes the oldest frame from the queue and copies it into `buf`.
+	///
+	/// If the frame does not fit in `buf`, it is truncated.
+	///
+	/// The function returns the number of bytes copied. If the queue is empty, it returns
+	/// [`errno::EAGAIN`].
+	pub fn pop(&mut self, buf: &mut [u8]) -> EResult<u64> {
+		if self.0.is_empty() {
+			return Err(errno!(EAGAIN));
+		}
+		let frame = self.0.remove(0);
+		let len = min(frame.len(), buf.len());
+		buf[..len].copy_from_slice(&frame[..len]);
+		Ok(len as _)
+	}
+}
 
-/// Registers the given network interface.
+/// The ID of the next network namespace.
+static NEXT_NS_ID: AtomicU64 = AtomicU64::new(0);
+/// The index of the next network interface, except loopback interfaces.
+static NEXT_IFINDEX: AtomicU32 = AtomicU32::new(LOOPBACK_INDEX + 1);
+
+/// Allocates an index for a new network interface.
 ///
-/// Arguments:
-/// - `name` is the name of the interface.
-/// - `iface` is the interface to register.
-pub fn register_iface<I: 'static + Interface>(name: String, iface: I) -> EResult<()> {
-	let mut interfaces = INTERFACES.lock();
+/// Indexes are unique on the system, so that an interface keeps the same one in any namespace.
+pub fn alloc_ifindex() -> u32 {
+	NEXT_IFINDEX.fetch_add(1, Relaxed)
+}
 
-	let i = Arc::new(Mutex::new(iface))?;
-	interfaces.insert(name, i)?;
+/// The network interfaces of a namespace, by name.
+pub type IfaceTable = Mutex<HashMap<String, Arc<Mutex<dyn Interface>>>>;
 
-	Ok(())
+/// A network namespace.
+///
+/// Each namespace has its own network interfaces, including a loopback interface, along with its
+/// own routing and neighbor tables. Sockets belong to the namespace of the process that created
+/// them, and only see the interfaces of that namespace.
+pub struct NetNamespace {
+	/// The ID of the namespace, unique on the system.
+	id: u64,
+	/// The network interfaces.
+	pub interfaces: Arc<IfaceTable>,
+	/// The routing table.
+	pub routes: Mutex<Vec<Route>>,
+	/// The ARP neighbor table.
+	pub neighbors: Mutex<NeighborTable>,
 }
 
-/// Unregisters the network interface with the given name.
-pub fn unregister_iface(name: &[u8]) {
-	let mut interfaces = INTERFACES.lock();
-	interfaces.remove(name);
+impl NetNamespace {
+	/// Creates a new namespace, holding only a loopback interface.
+	pub fn new() -> AllocResult<Arc<Self>> {
+		let id = NEXT_NS_ID.fetch_add(1, Relaxed);
+		let mut interfaces = HashMap::new();
+		let lo: Arc<Mutex<dyn Interface>> = Arc::new(Mutex::new(LocalLoopback::new(id)?))?;
+		interfaces.insert(String::try_from(b"lo")?, lo)?;
+		Arc::new(Self {
+			id,
+			interfaces: Arc::new(Mutex::new(interfaces))?,
+			routes: Mutex::new(Vec::new()),
+			neighbors: Mutex::new(NeighborTable::default()),
+		})
+	}
+
+	/// Returns the ID of the namespace.
+	#[inline]
+	pub fn id(&self) -> u64 {
+		self.id
+	}
+
+	/// Registers the given network interface, under its own name.
+	///
+	/// If an interface with the same name already exists, the function returns
+	/// [`errno::EEXIST`].
+	pub fn register_iface<I: 'static + Interface>(&self, iface: I) -> EResult<()> {
+		let name = String::try_from(iface.get_name())?;
+		let mut interfaces = self.interfaces.lock();
+		if interfaces.contains_key(&name) {
+			return Err(errno!(EEXIST));
+		}
+		let i = Arc::new(Mutex::new(iface))?;
+		interfaces.insert(name, i)?;
+		Ok(())
+	}
+
+	/// Unregisters the network interface with the given name, and returns it.
+	///
+	/// If the interface doesn't exist, the function returns `None`.
+	pub fn unregister_iface(&self, name: &[u8]) -> Option<Arc<Mutex<dyn Interface>>> {
+		let iface = self.interfaces.lock().remove(name)?;
+		iface.lock().unregister();
+		Some(iface)
+	}
+
+	/// Returns the network interface with the given name.
+	///
+	/// If the interface doesn't exist, the function returns `None`.
+	pub fn get_iface(&self, name: &[u8]) -> Option<Arc<Mutex<dyn Interface>>> {
+		self.interfaces.lock().get(name).cloned()
+	}
+
+	/// Returns the network interface with the given index.
+	///
+	/// If the interface doesn't exist, the function returns `None`.
+	pub fn get_iface_by_index(&self, index: u32) -> Option<Arc<Mutex<dyn Interface>>> {
+		self.interfaces
+			.lock()
+			.iter()
+			.map(|(_, iface)| iface)
+			.find(|iface| iface.lock().get_index() == index)
+			.cloned()
+	}
+
+	/// Returns the network interface to be used to transmit a packet to the given destination
+	/// address.
+	pub fn get_iface_for(&self, addr: Address) -> Option<Arc<Mutex<dyn Interface>>> {
+		let routing_table = self.routes.lock();
+		let route = routing_table
+			.iter()
+			.filter(|route| route.is_matching(&addr))
+			.max_by(|a, b| a.cmp_for(b, &addr))?;
+		self.get_iface(&route.iface)
+	}
 }
 
-/// Returns the network interface with the given name.
-///
-/// If the interface doesn't exist, thhe function returns `None`.
-pub fn get_iface(name: &[u8]) -> Option<Arc<Mutex<dyn Interface>>> {
-	INTERFACES.lock().get(name).cloned()
+impl Drop for NetNamespace {
+	fn drop(&mut self) {
+		// Interfaces may remove others from the table when unregistered, so it is emptied first
+		let interfaces = mem::take(&mut *self.interfaces.lock());
+		for (_, iface) in interfaces.iter() {
+			iface.lock().unregister();
+		}
+	}
 }
 
-/// Returns the network interface to be used to transmit a packet to the given destination address.
-pub fn get_iface_for(addr: Address) -> Option<Arc<Mutex<dyn Interface>>> {
-	let routing_table = ROUTING_TABLE.lock();
-	let route = routing_table
-		.iter()
-		.filter(|route| route.is_matching(&addr))
-		.max_by(|a, b| a.cmp_for(b, &addr))?;
-	get_iface(&route.iface)
+impl fmt::Debug for NetNamespace {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("NetNamespace")
+			.field("id", &self.id)
+			.finish_non_exhaustive()
+	}
 }
 
 /// Enumeration of socket domains.
@@ -283,7 +485,24 @@ impl AccessProfile {
 	/// Tells whether the agent has the permission to use the socket domain.
 	pub fn can_use_sock_domain(&self, domain: &SocketDomain) -> bool {
 		match domain {
-			SocketDomain::AfPacket => self.is_privileged(),
+			SocketDomain::AfPacket => self.has_capability(CAP_NET_RAW),
+			_ => true,
+		}
+	}
+
+	/// Tells whether the agent has the permission to bind a socket of the domain `domain` to the
+	/// address `sockaddr`.
+	pub fn can_bind(&self, domain: &SocketDomain, sockaddr: &[u8]) -> bool {
+		// The port is at the same offset for both IPv4 and IPv6
+		let port = match domain {
+			SocketDomain::AfInet | SocketDomain::AfInet6 => sockaddr
+				.get(2..4)
+				.map(|port| u16::from_be_bytes([port[0], port[1]])),
+			_ => None,
+		};
+		// Ports below `1024` are privileged
+		match port {
+			Some(1..1024) => self.has_capability(CAP_NET_BIND_SERVICE),
 			_ => true,
 		}
 	}
@@ -339,7 +558,7 @@ impl AccessProfile {
 	/// Tells whether the agent has the permission to use the socket type.
 	pub fn can_use_sock_type(&self, sock_type: &SocketType) -> bool {
 		match sock_type {
-			SocketType::SockRaw => self.is_privileged(),
+			SocketType::SockRaw => self.has_capability(CAP_NET_RAW),
 			_ => true,
 		}
 	}
@@ -0,0 +1,305 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Network console, sending the kernel's output over UDP to a remote collector.
+//!
+//! The console is configured with the `netconsole=` command line argument, which has the
+//! following format:
+//!
+//! ```text
+//! netconsole=[src-port]@[src-ip]/[dev],[tgt-port]@<tgt-ip>/[tgt-mac]
+//! ```
+//!
+//! Only the target address is mandatory. Fields default to:
+//! - `src-port`: `6665`
+//! - `src-ip`: the first IPv4 address bound to the interface
+//! - `dev`: the interface routing to the target
+//! - `tgt-port`: `6666`
+//! - `tgt-mac`: the address in the neighbor table, or the broadcast address
+//!
+//! Output is dropped until the interface is up and has an address, so that logs are sent as soon
+//! as the network is configured (for example with `ip=`). Output can be received on the
+//! collector with `nc -u -l 6666`.
+
+use super::{Address, MAC, buff::BuffList, ip, ip::IPV4_HDR_LEN, ipconfig::parse_ipv4};
+use crate::{console, console::Console, initcall, process, sync::mutex::Mutex};
+use core::{
+	str,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{Acquire, Release},
+	},
+};
+use utils::collections::string::String;
+
+/// The default source port.
+const DEFAULT_SRC_PORT: u16 = 6665;
+/// The default target port.
+const DEFAULT_TGT_PORT: u16 = 6666;
+
+/// The length of the Ethernet header.
+const ETH_HDR_LEN: usize = 14;
+/// EtherType: IPv4
+const ETH_P_IP: u16 = 0x0800;
+/// The length of the UDP header.
+const UDP_HDR_LEN: usize = 8;
+/// The maximum size of the payload of a datagram, so that frames fit in the Ethernet MTU.
+const MAX_PAYLOAD: usize = 1500 - IPV4_HDR_LEN - UDP_HDR_LEN;
+
+/// Parses the MAC address `s`, made of six hexadecimal bytes separated by colons.
+///
+/// If the address is invalid, the function returns `None`.
+fn parse_mac(s: &str) -> Option<MAC> {
+	let mut mac = [0; 6];
+	let mut parts = s.split(':');
+	for b in &mut mac {
+		let part = parts.next().filter(|p| p.len() == 2)?;
+		*b = u8::from_str_radix(part, 16).ok()?;
+	}
+	parts.next().is_none().then_some(mac)
+}
+
+/// Parses the endpoint `s`, in the form `[port]@[ip]/[suffix]`.
+///
+/// The function returns the port, the address and the suffix, each being `None` if empty.
+fn parse_endpoint(s: &str) -> Option<(Option<&str>, Option<&str>, Option<&str>)> {
+	let (port, s) = s.split_once('@')?;
+	let (addr, suffix) = s.split_once('/').unwrap_or((s, ""));
+	let [port, addr, suffix] = [port, addr, suffix].map(|s| (!s.is_empty()).then_some(s));
+	Some((port, addr, suffix))
+}
+
+/// The configuration of the network console.
+#[derive(Debug)]
+pub struct NetConsoleConfig<'s> {
+	/// The UDP port output is sent from.
+	pub src_port: u16,
+	/// The address output is sent from. If `None`, the interface's first IPv4 address is used.
+	pub src_addr: Option<[u8; 4]>,
+	/// The name of the interface to send output through. If `None`, the interface routing to the
+	/// target is used.
+	pub device: Option<&'s [u8]>,
+	/// The UDP port of the collector.
+	pub tgt_port: u16,
+	/// The address of the collector.
+	pub tgt_addr: [u8; 4],
+	/// The MAC address of the collector, or of the gateway routing to it. If `None`, the neighbor
+	/// table is used, and the broadcast address if the collector is not in it.
+	pub tgt_mac: Option<MAC>,
+}
+
+impl<'s> NetConsoleConfig<'s> {
+	/// Parses the value of the `netconsole=` command line argument.
+	///
+	/// On error, the function returns a message describing the problem.
+	pub fn parse(s: &'s [u8]) -> Result<Self, &'static str> {
+		let s = str::from_utf8(s).map_err(|_| "invalid netconsole configuration")?;
+		let (local, remote) = s
+			.split_once(',')
+			.ok_or("invalid netconsole configuration")?;
+		let (src_port, src_addr, device) =
+			parse_endpoint(local).ok_or("invalid netconsole source")?;
+		let (tgt_port, tgt_addr, tgt_mac) =
+			parse_endpoint(remote).ok_or("invalid netconsole target")?;
+		let src_port = src_port
+			.map(|p| p.parse().map_err(|_| "invalid netconsole source port"))
+			.transpose()?
+			.unwrap_or(DEFAULT_SRC_PORT);
+		let src_addr = src_addr
+			.map(|a| parse_ipv4(a.as_bytes()).ok_or("invalid netconsole source address"))
+			.transpose()?;
+		let tgt_port = tgt_port
+			.map(|p| p.parse().map_err(|_| "invalid netconsole target port"))
+			.transpose()?
+			.unwrap_or(DEFAULT_TGT_PORT);
+		let tgt_addr = tgt_addr
+			.ok_or("missing netconsole target address")
+			.and_then(|a| parse_ipv4(a.as_bytes()).ok_or("invalid netconsole target address"))?;
+		let tgt_mac = tgt_mac
+			.map(|m| parse_mac(m).ok_or("invalid netconsole target MAC address"))
+			.transpose()?;
+		Ok(Self {
+			src_port,
+			src_addr,
+			device: device.map(str::as_bytes),
+			tgt_port,
+			tgt_addr,
+			tgt_mac,
+		})
+	}
+}
+
+/// The destination of the network console's output.
+struct Target {
+	/// The UDP port output is sent from.
+	src_port: u16,
+	/// The address output is sent from, if forced.
+	src_addr: Option<[u8; 4]>,
+	/// The name of the interface to send output through, if forced.
+	device: Option<String>,
+	/// The UDP port of the collector.
+	tgt_port: u16,
+	/// The address of the collector.
+	tgt_addr: [u8; 4],
+	/// The MAC address of the collector, if forced.
+	tgt_mac: Option<MAC>,
+}
+
+impl Target {
+	/// Sends `buf` to the collector, split into as many datagrams as necessary.
+	///
+	/// If the interface is not available yet, the output is dropped.
+	fn send(&self, buf: &[u8]) {
+		// The interface is looked up on each write, so that it can be registered or configured
+		// after the console
+		// The console always sends through the initial network namespace
+		let Ok(ns) = process::ns::init_namespaces() else {
+			return;
+		};
+		let iface = match &self.device {
+			Some(name) => ns.net.get_iface(name),
+			None => ns.net.get_iface_for(Address::IPv4(self.tgt_addr)),
+		};
+		let Some(iface) = iface else {
+			return;
+		};
+		let mut iface = iface.lock();
+		if !iface.is_up() {
+			return;
+		}
+		let src_addr = self.src_addr.or_else(|| {
+			iface.get_addresses().iter().find_map(|a| match a.addr {
+				Address::IPv4(addr) => Some(addr),
+				_ => None,
+			})
+		});
+		let Some(src_addr) = src_addr else {
+			return;
+		};
+		let dst_mac = self
+			.tgt_mac
+			.or_else(|| ns.net.neighbors.lock().lookup(self.tgt_addr))
+			.unwrap_or([0xff; 6]);
+		let mut eth_hdr = [0; ETH_HDR_LEN];
+		eth_hdr[..6].copy_from_slice(&dst_mac);
+		eth_hdr[6..12].copy_from_slice(iface.get_mac());
+		eth_hdr[12..].copy_from_slice(&ETH_P_IP.to_be_bytes());
+		for chunk in buf.chunks(MAX_PAYLOAD) {
+			let udp_len = (UDP_HDR_LEN + chunk.len()) as u16;
+			let ip_hdr = ip::build_header(ip::PROTO_UDP, src_addr, self.tgt_addr, udp_len);
+			// The checksum is optional over IPv4, and left to zero
+			let mut udp_hdr = [0; UDP_HDR_LEN];
+			udp_hdr[..2].copy_from_slice(&self.src_port.to_be_bytes());
+			udp_hdr[2..4].copy_from_slice(&self.tgt_port.to_be_bytes());
+			udp_hdr[4..6].copy_from_slice(&udp_len.to_be_bytes());
+			let mut payload = BuffList::from(chunk);
+			let mut udp = payload.push_front(udp_hdr.as_slice().into());
+			let mut ip = udp.push_front(ip_hdr.as_slice().into());
+			let frame = ip.push_front(eth_hdr.as_slice().into());
+			// Like Linux's netpoll, frames bypass packet sockets, since messages may be logged
+			// while packet sockets are locked. There is nowhere to report errors to
+			let _ = iface.write(&frame);
+		}
+	}
+}
+
+/// The network console.
+pub struct NetConsole {
+	/// The destination of the output, if configured.
+	target: Mutex<Option<Target>>,
+	/// Tells whether output is being sent, to drop logs emitted while sending.
+	busy: AtomicBool,
+}
+
+impl Console for NetConsole {
+	fn name(&self) -> &[u8] {
+		b"netcon0"
+	}
+
+	fn is_always_enabled(&self) -> bool {
+		true
+	}
+
+	fn write(&self, buf: &[u8]) {
+		if self.busy.swap(true, Acquire) {
+			return;
+		}
+		if let Some(target) = &*self.target.lock() {
+			target.send(buf);
+		}
+		self.busy.store(false, Release);
+	}
+}
+
+/// The network console, registered if configured on the command line.
+pub static NETCONSOLE: NetConsole = NetConsole {
+	target: Mutex::new(None),
+	busy: AtomicBool::new(false),
+};
+
+initcall!(late, netconsole, |args| {
+	let Some(config) = args.get_netconsole_config() else {
+		return Ok(());
+	};
+	*NETCONSOLE.target.lock() = Some(Target {
+		src_port: config.src_port,
+		src_addr: config.src_addr,
+		device: config.device.map(String::try_from).transpose()?,
+		tgt_port: config.tgt_port,
+		tgt_addr: config.tgt_addr,
+		tgt_mac: config.tgt_mac,
+	});
+	console::register(&NETCONSOLE)
+});
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn netconsole_parse_full() {
+		let conf = NetConsoleConfig::parse(b"4444@10.0.0.2/eth0,9353@10.0.0.1/12:34:56:78:9a:bc")
+			.unwrap();
+		assert_eq!(conf.src_port, 4444);
+		assert_eq!(conf.src_addr, Some([10, 0, 0, 2]));
+		assert_eq!(conf.device, Some(&b"eth0"[..]));
+		assert_eq!(conf.tgt_port, 9353);
+		assert_eq!(conf.tgt_addr, [10, 0, 0, 1]);
+		assert_eq!(conf.tgt_mac, Some([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]));
+	}
+
+	#[test_case]
+	fn netconsole_parse_defaults() {
+		let conf = NetConsoleConfig::parse(b"@/,@10.0.0.1/").unwrap();
+		assert_eq!(conf.src_port, DEFAULT_SRC_PORT);
+		assert_eq!(conf.src_addr, None);
+		assert_eq!(conf.device, None);
+		assert_eq!(conf.tgt_port, DEFAULT_TGT_PORT);
+		assert_eq!(conf.tgt_mac, None);
+		assert!(NetConsoleConfig::parse(b"@,@10.0.0.1").is_ok());
+	}
+
+	#[test_case]
+	fn netconsole_parse_invalid() {
+		assert!(NetConsoleConfig::parse(b"").is_err());
+		assert!(NetConsoleConfig::parse(b"@/,@/").is_err());
+		assert!(NetConsoleConfig::parse(b"@/,10.0.0.1").is_err());
+		assert!(NetConsoleConfig::parse(b"70000@/,@10.0.0.1/").is_err());
+		assert!(NetConsoleConfig::parse(b"@/,@10.0.0.1/12:34:56").is_err());
+	}
+}
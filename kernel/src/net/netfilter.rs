@@ -0,0 +1,170 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Hook points in the IP stack, on which packet filtering and address translation can be built.
+//!
+//! A packet goes through the hooks in the following order:
+//!
+//! ```text
+//! received --> PreRouting --+--> LocalIn --> local process
+//!                           |
+//!                           +--> Forward --+--> PostRouting --> transmitted
+//!                                          |
+//! local process --> LocalOut --------------+
+//! ```
+
+use crate::sync::mutex::Mutex;
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use utils::{collections::vec::Vec, errno::AllocResult};
+
+/// A hook point in the IP stack.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hook {
+	/// Received packets, before the routing decision.
+	PreRouting,
+	/// Received packets destined to the local system.
+	LocalIn,
+	/// Received packets to be forwarded to another host.
+	Forward,
+	/// Packets emitted by the local system.
+	LocalOut,
+	/// Packets about to be transmitted, after the routing decision.
+	PostRouting,
+}
+
+/// The decision taken by a hook on a packet.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+	/// Let the packet through, to the next hook.
+	Accept,
+	/// Discard the packet.
+	Drop,
+}
+
+/// Information about a packet going through a hook.
+#[derive(Debug)]
+pub struct HookContext<'a> {
+	/// The hook point.
+	pub hook: Hook,
+	/// The name of the interface the packet has been received on, if any.
+	pub in_iface: Option<&'a [u8]>,
+	/// The name of the interface the packet is to be transmitted on, if known.
+	pub out_iface: Option<&'a [u8]>,
+}
+
+/// A function called on each packet going through a hook.
+///
+/// The function receives the packet, starting at the network layer header.
+pub type HookFn = fn(ctx: &HookContext, packet: &[u8]) -> Verdict;
+
+/// Description of a hook to register.
+#[derive(Clone, Copy, Debug)]
+pub struct HookOps {
+	/// The hook point.
+	pub hook: Hook,
+	/// The priority of the hook. Hooks with the lowest values are called first.
+	pub priority: i32,
+	/// The function to call.
+	pub func: HookFn,
+}
+
+/// Identifier of a registered hook, used to unregister it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HookId(u32);
+
+/// The list of registered hooks, sorted by priority.
+static HOOKS: Mutex<Vec<(HookId, HookOps)>> = Mutex::new(Vec::new());
+/// The ID of the next registered hook.
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Registers the given hook.
+///
+/// Hooks with the same priority are called in registration order.
+pub fn register(ops: HookOps) -> AllocResult<HookId> {
+	let id = HookId(NEXT_ID.fetch_add(1, Relaxed));
+	let mut hooks = HOOKS.lock();
+	let index = hooks
+		.iter()
+		.position(|(_, o)| o.priority > ops.priority)
+		.unwrap_or(hooks.len());
+	hooks.insert(index, (id, ops))?;
+	Ok(id)
+}
+
+/// Unregisters the hook with the given ID.
+///
+/// If the hook does not exist, the function does nothing.
+pub fn unregister(id: HookId) {
+	HOOKS.lock().retain(|(i, _)| *i != id);
+}
+
+/// Tells whether at least one hook is registered on `hook`.
+///
+/// This allows to avoid building a contiguous copy of a packet when nothing would look at it.
+pub fn is_hooked(hook: Hook) -> bool {
+	HOOKS.lock().iter().any(|(_, ops)| ops.hook == hook)
+}
+
+/// Passes `packet` through the hooks registered on `ctx.hook`, in priority order.
+///
+/// The first verdict other than [`Verdict::Accept`] is returned.
+///
+/// Hooks are called with the hooks list locked, so they must not register or unregister hooks.
+pub fn run(ctx: &HookContext, packet: &[u8]) -> Verdict {
+	HOOKS
+		.lock()
+		.iter()
+		.filter(|(_, ops)| ops.hook == ctx.hook)
+		.map(|(_, ops)| (ops.func)(ctx, packet))
+		.find(|v| *v != Verdict::Accept)
+		.unwrap_or(Verdict::Accept)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn drop_short(_ctx: &HookContext, packet: &[u8]) -> Verdict {
+		if packet.len() < 20 {
+			Verdict::Drop
+		} else {
+			Verdict::Accept
+		}
+	}
+
+	#[test_case]
+	fn netfilter_register() {
+		let ctx = HookContext {
+			hook: Hook::Forward,
+			in_iface: None,
+			out_iface: None,
+		};
+		assert_eq!(run(&ctx, &[0; 4]), Verdict::Accept);
+		let id = register(HookOps {
+			hook: Hook::Forward,
+			priority: 0,
+			func: drop_short,
+		})
+		.unwrap();
+		assert!(is_hooked(Hook::Forward));
+		assert_eq!(run(&ctx, &[0; 4]), Verdict::Drop);
+		assert_eq!(run(&ctx, &[0; 20]), Verdict::Accept);
+		unregister(id);
+		assert_eq!(run(&ctx, &[0; 4]), Verdict::Accept);
+	}
+}
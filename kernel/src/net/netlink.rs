@@ -0,0 +1,489 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Netlink sockets (`AF_NETLINK`), through which userspace configures the network stack.
+//!
+//! Only the routing protocol (`NETLINK_ROUTE`) is supported, with the following requests:
+//! - `RTM_NEWLINK`: creates a link. Only veth pairs (see [`super::veth`]) can be created, and
+//!   existing links cannot be changed
+//! - `RTM_DELLINK`: deletes a link
+//! - `RTM_NEWNEIGH`: adds or replaces an entry of the ARP neighbor table (see [`super::arp`])
+//! - `RTM_DELNEIGH`: deletes an entry of the neighbor table. Without a destination address, every
+//!   entry that is not permanent is flushed
+//!
+//! Requests apply to the network namespace of the socket, unless they designate another one. A
+//! request is answered with an `NLMSG_ERROR` message if it fails, or if it has the `NLM_F_ACK`
+//! flag.
+
+use super::{
+	NetNamespace,
+	arp::{ATF_PERM, ATF_PUBL},
+	veth,
+};
+use crate::{
+	file::{fs::proc::NetNs, perm::CAP_NET_ADMIN, socket::Socket},
+	process::Process,
+};
+use core::{ffi::c_int, iter, mem::size_of};
+use macros::AnyRepr;
+use utils::{
+	bytes::{as_bytes, from_bytes},
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// Netlink protocol: routing and link configuration
+pub const NETLINK_ROUTE: c_int = 0;
+
+/// Address family: IPv4
+const AF_INET: u8 = 2;
+/// Address family: Netlink
+const AF_NETLINK: u16 = 16;
+
+/// Message type: error or acknowledgement
+const NLMSG_ERROR: u16 = 2;
+
+/// Message flag: the message is a request
+const NLM_F_REQUEST: u16 = 0x1;
+/// Message flag: the request must be acknowledged
+const NLM_F_ACK: u16 = 0x4;
+/// Acknowledgement flag: the request is not included past its header
+const NLM_F_CAPPED: u16 = 0x100;
+/// `RTM_NEW*` flag: fail if the object already exists
+const NLM_F_EXCL: u16 = 0x200;
+/// `RTM_NEW*` flag: create the object if it does not exist
+const NLM_F_CREATE: u16 = 0x400;
+
+/// Request: create a link
+const RTM_NEWLINK: u16 = 16;
+/// Request: delete a link
+const RTM_DELLINK: u16 = 17;
+/// Request: add a neighbor
+const RTM_NEWNEIGH: u16 = 28;
+/// Request: delete a neighbor
+const RTM_DELNEIGH: u16 = 29;
+
+/// Link attribute: the name of the interface
+const IFLA_IFNAME: u16 = 3;
+/// Link attribute: the kind of link, along with its specific attributes
+const IFLA_LINKINFO: u16 = 18;
+/// Link attribute: a PID whose network namespace the link belongs to
+const IFLA_NET_NS_PID: u16 = 19;
+/// Link attribute: a file descriptor referring to the network namespace the link belongs to
+const IFLA_NET_NS_FD: u16 = 28;
+
+/// `IFLA_LINKINFO` attribute: the kind of link
+const IFLA_INFO_KIND: u16 = 1;
+/// `IFLA_LINKINFO` attribute: attributes specific to the kind of link
+const IFLA_INFO_DATA: u16 = 2;
+
+/// Neighbor attribute: the protocol address
+const NDA_DST: u16 = 1;
+/// Neighbor attribute: the hardware address
+const NDA_LLADDR: u16 = 2;
+
+/// Neighbor state: the entry is permanent
+const NUD_PERMANENT: u16 = 0x80;
+/// Neighbor flag: the entry is a proxy entry
+const NTF_PROXY: u8 = 0x08;
+
+/// veth attribute: a link message describing the peer
+const VETH_INFO_PEER: u16 = 1;
+
+/// Mask of the type of an attribute, without its flags.
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+/// Netlink socket address (`struct sockaddr_nl`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+pub struct SockAddrNl {
+	/// Always `AF_NETLINK`.
+	pub nl_family: u16,
+	/// Padding.
+	pub nl_pad: u16,
+	/// The port ID. Zero designates the kernel.
+	pub nl_pid: u32,
+	/// Multicast groups mask.
+	pub nl_groups: u32,
+}
+
+impl SockAddrNl {
+	/// Returns the address with the port ID `pid`.
+	pub fn new(pid: u32) -> Self {
+		Self {
+			nl_family: AF_NETLINK,
+			nl_pad: 0,
+			nl_pid: pid,
+			nl_groups: 0,
+		}
+	}
+}
+
+/// Netlink message header (`struct nlmsghdr`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+struct NlMsgHdr {
+	/// The length of the message, including the header.
+	nlmsg_len: u32,
+	/// The type of the message.
+	nlmsg_type: u16,
+	/// Flags.
+	nlmsg_flags: u16,
+	/// Sequence number.
+	nlmsg_seq: u32,
+	/// The port ID of the sender.
+	nlmsg_pid: u32,
+}
+
+/// Link message (`struct ifinfomsg`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+struct IfInfoMsg {
+	/// The address family.
+	ifi_family: u8,
+	/// Padding.
+	_pad: u8,
+	/// The device type.
+	ifi_type: u16,
+	/// The interface index. Zero if unspecified.
+	ifi_index: c_int,
+	/// Device flags.
+	ifi_flags: u32,
+	/// Mask of the flags to change.
+	ifi_change: u32,
+}
+
+/// Neighbor message (`struct ndmsg`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+struct NdMsg {
+	/// The address family.
+	ndm_family: u8,
+	/// Padding.
+	_pad1: u8,
+	/// Padding.
+	_pad2: u16,
+	/// The index of the interface the neighbor is reachable through. Zero if unspecified.
+	ndm_ifindex: c_int,
+	/// The state of the entry.
+	ndm_state: u16,
+	/// Flags.
+	ndm_flags: u8,
+	/// The type of the entry.
+	ndm_type: u8,
+}
+
+/// Error message (`struct nlmsgerr`), followed by the header of the request.
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+struct NlMsgErr {
+	/// The header of the message.
+	hdr: NlMsgHdr,
+	/// The negated errno, or zero for an acknowledgement.
+	error: c_int,
+	/// The header of the request.
+	msg: NlMsgHdr,
+}
+
+/// Rounds `len` up to the alignment of messages and attributes.
+fn align(len: usize) -> usize {
+	len.next_multiple_of(4)
+}
+
+/// Returns an iterator over the attributes in `buf`, as pairs of type and payload.
+///
+/// Iteration stops at the first malformed attribute.
+fn attrs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+	iter::from_fn(move || {
+		let len = u16::from_ne_bytes([*buf.first()?, *buf.get(1)?]) as usize;
+		let type_ = u16::from_ne_bytes([*buf.get(2)?, *buf.get(3)?]);
+		let payload = buf.get(4..len)?;
+		buf = buf.get(align(len)..).unwrap_or_default();
+		Some((type_ & NLA_TYPE_MASK, payload))
+	})
+}
+
+/// Returns the payload of the attribute of type `type_` in `buf`, if present.
+fn attr(buf: &[u8], type_: u16) -> Option<&[u8]> {
+	attrs(buf).find(|(t, _)| *t == type_).map(|(_, p)| p)
+}
+
+/// Returns the string in the payload of an attribute, without its terminating NUL byte.
+fn attr_str(payload: &[u8]) -> &[u8] {
+	let len = payload
+		.iter()
+		.position(|b| *b == 0)
+		.unwrap_or(payload.len());
+	&payload[..len]
+}
+
+/// Returns the integer in the payload of an attribute.
+fn attr_u32(payload: &[u8]) -> EResult<u32> {
+	let buf = payload.get(..4).ok_or_else(|| errno!(EINVAL))?;
+	Ok(u32::from_ne_bytes(buf.try_into().unwrap()))
+}
+
+/// Splits the link message `payload` into its header and attributes.
+fn parse_link(payload: &[u8]) -> EResult<(&IfInfoMsg, &[u8])> {
+	let len = size_of::<IfInfoMsg>();
+	let info = payload
+		.get(..len)
+		.and_then(from_bytes::<IfInfoMsg>)
+		.ok_or_else(|| errno!(EINVAL))?;
+	Ok((info, &payload[len..]))
+}
+
+/// Returns the network namespace designated by the link attributes `attrs`, or `default` if they
+/// do not designate any.
+fn target_ns(attrs: &[u8], default: &Arc<NetNamespace>) -> EResult<Arc<NetNamespace>> {
+	if let Some(fd) = attr(attrs, IFLA_NET_NS_FD) {
+		let fd = attr_u32(fd)? as c_int;
+		let fds = Process::current()
+			.file_descriptors
+			.get()
+			.ok_or_else(|| errno!(EBADF))?;
+		let file = fds.lock().get_fd(fd)?.get_file().clone();
+		let NetNs(ns) = file.get_buffer::<NetNs>().ok_or_else(|| errno!(EINVAL))?;
+		return ns.clone().ok_or_else(|| errno!(EINVAL));
+	}
+	if let Some(pid) = attr(attrs, IFLA_NET_NS_PID) {
+		let pid = attr_u32(pid)?.try_into().map_err(|_| errno!(ESRCH))?;
+		let proc = Process::get_by_pid(pid).ok_or_else(|| errno!(ESRCH))?;
+		return Ok(proc.ns.lock().net.clone());
+	}
+	Ok(default.clone())
+}
+
+/// Handles a `RTM_NEWLINK` request with flags `flags`, on a socket of the namespace `ns`.
+fn new_link(ns: &Arc<NetNamespace>, flags: u16, payload: &[u8]) -> EResult<()> {
+	let (_, attrs) = parse_link(payload)?;
+	let name = attr(attrs, IFLA_IFNAME).map(attr_str);
+	let link_ns = target_ns(attrs, ns)?;
+	if let Some(name) = name
+		&& link_ns.get_iface(name).is_some()
+	{
+		// Changing existing links is not supported
+		return if flags & NLM_F_EXCL != 0 {
+			Err(errno!(EEXIST))
+		} else {
+			Err(errno!(EOPNOTSUPP))
+		};
+	}
+	if flags & NLM_F_CREATE == 0 {
+		return Err(errno!(ENODEV));
+	}
+	let info = attr(attrs, IFLA_LINKINFO).ok_or_else(|| errno!(EINVAL))?;
+	if attr(info, IFLA_INFO_KIND).map(attr_str) != Some(b"veth") {
+		return Err(errno!(EOPNOTSUPP));
+	}
+	// The peer is described by a link message, and is in the namespace of the socket by default
+	let peer = attr(info, IFLA_INFO_DATA).and_then(|data| attr(data, VETH_INFO_PEER));
+	let (peer_name, peer_ns) = match peer {
+		Some(peer) => {
+			let (_, peer_attrs) = parse_link(peer)?;
+			let peer_name = attr(peer_attrs, IFLA_IFNAME).map(attr_str);
+			(peer_name, target_ns(peer_attrs, ns)?)
+		}
+		None => (None, ns.clone()),
+	};
+	veth::create(&link_ns, name, &peer_ns, peer_name)
+}
+
+/// Handles a `RTM_DELLINK` request on a socket of the namespace `ns`.
+fn del_link(ns: &NetNamespace, payload: &[u8]) -> EResult<()> {
+	let (info, attrs) = parse_link(payload)?;
+	let iface = if info.ifi_index > 0 {
+		ns.get_iface_by_index(info.ifi_index as _)
+	} else {
+		let name = attr(attrs, IFLA_IFNAME).ok_or_else(|| errno!(EINVAL))?;
+		ns.get_iface(attr_str(name))
+	};
+	let iface = iface.ok_or_else(|| errno!(ENODEV))?;
+	let name = {
+		let iface = iface.lock();
+		if !iface.is_removable() {
+			return Err(errno!(EOPNOTSUPP));
+		}
+		Vec::try_from(iface.get_name())?
+	};
+	ns.unregister_iface(&name);
+	Ok(())
+}
+
+/// Splits the neighbor message `payload` into its header and attributes.
+///
+/// Only IPv4 neighbors are supported.
+fn parse_neigh(payload: &[u8]) -> EResult<(&NdMsg, &[u8])> {
+	let len = size_of::<NdMsg>();
+	let msg = payload
+		.get(..len)
+		.and_then(from_bytes::<NdMsg>)
+		.ok_or_else(|| errno!(EINVAL))?;
+	if msg.ndm_family != AF_INET {
+		return Err(errno!(EAFNOSUPPORT));
+	}
+	Ok((msg, &payload[len..]))
+}
+
+/// Returns the IPv4 address in the payload of a `NDA_DST` attribute.
+fn neigh_dst(payload: &[u8]) -> EResult<[u8; 4]> {
+	payload.try_into().map_err(|_| errno!(EINVAL))
+}
+
+/// Handles a `RTM_NEWNEIGH` request with flags `flags`, on a socket of the namespace `ns`.
+fn new_neigh(ns: &NetNamespace, flags: u16, payload: &[u8]) -> EResult<()> {
+	let (msg, attrs) = parse_neigh(payload)?;
+	let addr = neigh_dst(attr(attrs, NDA_DST).ok_or_else(|| errno!(EINVAL))?)?;
+	let mac = attr(attrs, NDA_LLADDR)
+		.and_then(|mac| mac.try_into().ok())
+		.ok_or_else(|| errno!(EINVAL))?;
+	let iface = ns
+		.get_iface_by_index(msg.ndm_ifindex.try_into().map_err(|_| errno!(EINVAL))?)
+		.ok_or_else(|| errno!(ENODEV))?;
+	let mut arp_flags = 0;
+	if msg.ndm_state & NUD_PERMANENT != 0 {
+		arp_flags |= ATF_PERM;
+	}
+	if msg.ndm_flags & NTF_PROXY != 0 {
+		arp_flags |= ATF_PUBL;
+	}
+	// Interfaces are locked before the neighbor table
+	let iface = iface.lock();
+	let mut neighbors = ns.neighbors.lock();
+	let exists = neighbors.lookup(addr).is_some();
+	if exists && flags & NLM_F_EXCL != 0 {
+		return Err(errno!(EEXIST));
+	}
+	if !exists && flags & NLM_F_CREATE == 0 {
+		return Err(errno!(ENOENT));
+	}
+	neighbors.insert(addr, mac, iface.get_name(), arp_flags)?;
+	Ok(())
+}
+
+/// Handles a `RTM_DELNEIGH` request on a socket of the namespace `ns`.
+fn del_neigh(ns: &NetNamespace, payload: &[u8]) -> EResult<()> {
+	let (_, attrs) = parse_neigh(payload)?;
+	let mut neighbors = ns.neighbors.lock();
+	match attr(attrs, NDA_DST) {
+		Some(dst) => {
+			if !neighbors.remove(neigh_dst(dst)?) {
+				return Err(errno!(ENOENT));
+			}
+		}
+		None => neighbors.flush(),
+	}
+	Ok(())
+}
+
+/// Handles the request with header `hdr` and payload `payload`, on a socket of the namespace
+/// `ns`.
+fn handle_request(ns: &Arc<NetNamespace>, hdr: &NlMsgHdr, payload: &[u8]) -> EResult<()> {
+	let privileged = Process::current()
+		.fs
+		.lock()
+		.access_profile
+		.has_capability(CAP_NET_ADMIN);
+	match hdr.nlmsg_type {
+		RTM_NEWLINK | RTM_DELLINK | RTM_NEWNEIGH | RTM_DELNEIGH if !privileged => {
+			Err(errno!(EPERM))
+		}
+		RTM_NEWLINK => new_link(ns, hdr.nlmsg_flags, payload),
+		RTM_DELLINK => del_link(ns, payload),
+		RTM_NEWNEIGH => new_neigh(ns, hdr.nlmsg_flags, payload),
+		RTM_DELNEIGH => del_neigh(ns, payload),
+		_ => Err(errno!(EOPNOTSUPP)),
+	}
+}
+
+/// Returns the port ID of the socket `sock`.
+///
+/// If the socket is not bound, it is bound to the PID of the current process.
+pub fn port_id(sock: &Socket) -> AllocResult<u32> {
+	let mut name = sock.get_sockname().lock();
+	if let Some(addr) = from_bytes::<SockAddrNl>(&name) {
+		return Ok(addr.nl_pid);
+	}
+	let pid = Process::current().get_pid() as u32;
+	*name = Vec::try_from(as_bytes(&SockAddrNl::new(pid)))?;
+	Ok(pid)
+}
+
+/// Binds the socket `sock` to the address `sockaddr`.
+///
+/// If the given port ID is zero, the PID of the current process is used instead.
+pub fn bind(sock: &Socket, sockaddr: &[u8]) -> EResult<()> {
+	let addr = sockaddr
+		.get(..size_of::<SockAddrNl>())
+		.and_then(from_bytes::<SockAddrNl>)
+		.ok_or_else(|| errno!(EINVAL))?;
+	if addr.nl_family != AF_NETLINK {
+		return Err(errno!(EINVAL));
+	}
+	let pid = match addr.nl_pid {
+		0 => Process::current().get_pid() as u32,
+		pid => pid,
+	};
+	let mut name = sock.get_sockname().lock();
+	if !name.is_empty() {
+		return Err(errno!(EINVAL));
+	}
+	*name = Vec::try_from(as_bytes(&SockAddrNl::new(pid)))?;
+	Ok(())
+}
+
+/// Handles the messages in `buf`, sent on the socket `sock`, and queues the replies on it.
+///
+/// The function returns the number of bytes consumed.
+pub fn send(sock: &Socket, buf: &[u8]) -> EResult<usize> {
+	let pid = port_id(sock)?;
+	let mut off = 0;
+	while let Some(hdr) = buf
+		.get(off..off + size_of::<NlMsgHdr>())
+		.and_then(from_bytes::<NlMsgHdr>)
+	{
+		let len = hdr.nlmsg_len as usize;
+		let Some(payload) = buf.get(off + size_of::<NlMsgHdr>()..off + len) else {
+			break;
+		};
+		if hdr.nlmsg_flags & NLM_F_REQUEST != 0 {
+			let res = handle_request(sock.net_ns(), hdr, payload);
+			if res.is_err() || hdr.nlmsg_flags & NLM_F_ACK != 0 {
+				let error = res.err().map(|e| -e.as_int()).unwrap_or(0);
+				let reply = NlMsgErr {
+					hdr: NlMsgHdr {
+						nlmsg_len: size_of::<NlMsgErr>() as _,
+						nlmsg_type: NLMSG_ERROR,
+						nlmsg_flags: NLM_F_CAPPED,
+						nlmsg_seq: hdr.nlmsg_seq,
+						nlmsg_pid: pid,
+					},
+					error,
+					msg: *hdr,
+				};
+				// Replies are sent by the kernel
+				let src = SockAddrNl::new(0);
+				sock.push_datagram(as_bytes(&reply), as_bytes(&src))?;
+			}
+		}
+		off += align(len);
+	}
+	Ok(buf.len())
+}
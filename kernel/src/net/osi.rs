@@ -19,7 +19,7 @@
 //! The Open Systems Interconnection (OSI) model defines the architecure of a network stack.
 
 use super::{SocketDesc, SocketDomain, SocketType, buff::BuffList, ip};
-use crate::sync::mutex::Mutex;
+use crate::{initcall, sync::mutex::Mutex};
 use core::fmt::Debug;
 use utils::{boxed::Box, collections::hashmap::HashMap, errno, errno::EResult};
 
@@ -103,7 +103,7 @@ impl Stack {
 }
 
 /// Registers default domains/types/protocols.
-pub(crate) fn init() -> EResult<()> {
+fn init() -> EResult<()> {
 	let domains = HashMap::try_from([
 		// TODO unix
 		(
@@ -140,3 +140,5 @@ pub(crate) fn init() -> EResult<()> {
 
 	Ok(())
 }
+
+initcall!(subsys, net, |_| init());
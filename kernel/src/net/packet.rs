@@ -0,0 +1,678 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Packet sockets (`AF_PACKET`) give access to frames at the device level.
+//!
+//! Every frame going through a network interface is passed to [`deliver`], which hands a copy of
+//! it to each matching packet socket. Outgoing frames are delivered by [`super::transmit`], and
+//! incoming frames by the interface receiving them. A packet socket only sees the interfaces of
+//! its network namespace.
+//!
+//! Instead of using a system call for each frame, a packet socket can exchange frames with
+//! userspace through rings of frames mapped with `mmap` (`PACKET_RX_RING` and `PACKET_TX_RING`).
+//! The ownership of each frame of a ring is given by its status word, switched back and forth
+//! between the kernel and userspace.
+
+use crate::{
+	file::socket::Socket,
+	memory::cache::{FrameOwner, RcFrame},
+	net::{SocketDomain, SocketType},
+	sync::mutex::Mutex,
+	time::clock::{Clock, current_time_ns},
+};
+use core::{
+	cmp::min,
+	ffi::c_int,
+	ptr,
+	sync::atomic::{
+		AtomicU32,
+		Ordering::{Acquire, Release},
+	},
+};
+use macros::AnyRepr;
+use utils::{
+	bytes::{as_bytes, from_bytes},
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	limits::PAGE_SIZE,
+	ptr::arc::Arc,
+};
+
+/// Protocol: every protocol
+pub const ETH_P_ALL: u16 = 0x0003;
+
+/// Socket option level: packet sockets
+pub const SOL_PACKET: c_int = 263;
+
+/// Packet socket option: set up the reception ring
+const PACKET_RX_RING: c_int = 5;
+/// Packet socket option: the version of the headers of ring frames
+const PACKET_VERSION: c_int = 10;
+/// Packet socket option: set up the transmission ring
+const PACKET_TX_RING: c_int = 13;
+
+/// Ring frame header version: `struct tpacket_hdr`
+const TPACKET_V1: u32 = 0;
+/// Ring frame header version: `struct tpacket2_hdr`
+const TPACKET_V2: u32 = 1;
+
+/// The alignment of the parts of a ring frame.
+const TPACKET_ALIGNMENT: usize = 16;
+/// The offset of the address in a ring frame, right after the header, for both versions.
+const TPACKET_ADDR_OFF: usize = 32;
+/// The size of the header and address at the beginning of a ring frame.
+const TPACKET_HDRLEN: usize = TPACKET_ADDR_OFF + size_of::<SockAddrLl>();
+/// The offset of the network header in a received ring frame.
+const TPACKET_NET_OFF: usize = (TPACKET_HDRLEN + 16).next_multiple_of(TPACKET_ALIGNMENT);
+
+/// Reception frame status: the frame belongs to the kernel
+const TP_STATUS_KERNEL: u32 = 0;
+/// Reception frame status: the frame belongs to userspace
+const TP_STATUS_USER: u32 = 1;
+/// Reception frame status: the frame has been truncated
+const TP_STATUS_COPY: u32 = 2;
+/// Reception frame status: frames have been dropped since the previous one
+const TP_STATUS_LOSING: u32 = 4;
+
+/// Transmission frame status: the frame is available to userspace
+const TP_STATUS_AVAILABLE: u32 = 0;
+/// Transmission frame status: the frame is to be sent by the kernel
+const TP_STATUS_SEND_REQUEST: u32 = 1;
+/// Transmission frame status: the frame is being sent
+const TP_STATUS_SENDING: u32 = 2;
+/// Transmission frame status: the frame is invalid
+const TP_STATUS_WRONG_FORMAT: u32 = 4;
+
+/// ARP hardware type: Ethernet
+const ARPHRD_ETHER: u16 = 1;
+
+/// Packet type: the frame is addressed to the local host
+pub const PACKET_HOST: u8 = 0;
+/// Packet type: the frame is sent by the local host
+pub const PACKET_OUTGOING: u8 = 4;
+
+/// The size of the Ethernet header, stripped from frames on `SOCK_DGRAM` packet sockets.
+const ETH_HDR_LEN: usize = 14;
+
+/// Device level socket address (`struct sockaddr_ll`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+pub struct SockAddrLl {
+	/// Always `AF_PACKET`.
+	pub sll_family: u16,
+	/// Physical-layer protocol, in network byte order.
+	pub sll_protocol: u16,
+	/// Interface index. Zero matches any interface.
+	pub sll_ifindex: c_int,
+	/// ARP hardware type.
+	pub sll_hatype: u16,
+	/// Packet type.
+	pub sll_pkttype: u8,
+	/// Length of the address.
+	pub sll_halen: u8,
+	/// Physical-layer address.
+	pub sll_addr: [u8; 8],
+}
+
+/// The layout of a ring (`struct tpacket_req`).
+#[repr(C)]
+#[derive(AnyRepr, Clone, Copy, Debug)]
+pub struct TpacketReq {
+	/// The size of a block, in bytes. Frames do not cross blocks.
+	pub tp_block_size: u32,
+	/// The number of blocks.
+	pub tp_block_nr: u32,
+	/// The size of a frame, in bytes.
+	pub tp_frame_size: u32,
+	/// The number of frames.
+	pub tp_frame_nr: u32,
+}
+
+/// A ring of frames, shared with userspace.
+#[derive(Debug)]
+struct Ring {
+	/// The pages of the ring, in order.
+	pages: Vec<RcFrame>,
+	/// The size of a block, in bytes.
+	block_size: usize,
+	/// The size of a frame, in bytes.
+	frame_size: usize,
+	/// The number of frames.
+	frame_nr: usize,
+	/// The index of the next frame to be used by the kernel.
+	head: usize,
+}
+
+impl Ring {
+	/// Creates a ring with the given layout.
+	///
+	/// If the layout describes an empty ring, the function returns `None`.
+	fn new(req: &TpacketReq) -> EResult<Option<Self>> {
+		let block_size = req.tp_block_size as usize;
+		let frame_size = req.tp_frame_size as usize;
+		let frame_nr = req.tp_frame_nr as usize;
+		if req.tp_block_nr == 0 {
+			if frame_nr != 0 {
+				return Err(errno!(EINVAL));
+			}
+			return Ok(None);
+		}
+		if block_size == 0 || !block_size.is_multiple_of(PAGE_SIZE) {
+			return Err(errno!(EINVAL));
+		}
+		if frame_size < TPACKET_HDRLEN || !frame_size.is_multiple_of(TPACKET_ALIGNMENT) {
+			return Err(errno!(EINVAL));
+		}
+		let frames_per_block = block_size / frame_size;
+		if frames_per_block == 0
+			|| frames_per_block.checked_mul(req.tp_block_nr as usize) != Some(frame_nr)
+		{
+			return Err(errno!(EINVAL));
+		}
+		let pages_count = (block_size / PAGE_SIZE)
+			.checked_mul(req.tp_block_nr as usize)
+			.ok_or_else(|| errno!(EINVAL))?;
+		let mut pages = Vec::with_capacity(pages_count)?;
+		for _ in 0..pages_count {
+			pages.push(RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?)?;
+		}
+		Ok(Some(Self {
+			pages,
+			block_size,
+			frame_size,
+			frame_nr,
+			head: 0,
+		}))
+	}
+
+	/// Returns the offset of the frame `i` in the ring, in bytes.
+	fn frame_off(&self, i: usize) -> usize {
+		let frames_per_block = self.block_size / self.frame_size;
+		(i / frames_per_block) * self.block_size + (i % frames_per_block) * self.frame_size
+	}
+
+	/// Returns the status word of the frame `i`.
+	///
+	/// With [`TPACKET_V1`], the status is an `unsigned long`, whose low bits come first.
+	fn status(&self, i: usize) -> &AtomicU32 {
+		let off = self.frame_off(i);
+		let page = &self.pages[off / PAGE_SIZE];
+		// The offset is aligned, so the word does not cross pages
+		unsafe { AtomicU32::from_ptr(page.virt_addr().as_ptr::<u32>().byte_add(off % PAGE_SIZE)) }
+	}
+
+	/// Writes `data` at the offset `off` in the ring.
+	///
+	/// The caller must own the frame being written.
+	fn write(&self, mut off: usize, mut data: &[u8]) {
+		while !data.is_empty() {
+			let inner_off = off % PAGE_SIZE;
+			let len = min(PAGE_SIZE - inner_off, data.len());
+			let page = unsafe { self.pages[off / PAGE_SIZE].slice_mut::<u8>() };
+			page[inner_off..(inner_off + len)].copy_from_slice(&data[..len]);
+			off += len;
+			data = &data[len..];
+		}
+	}
+
+	/// Reads from the offset `off` in the ring, to fill `buf`.
+	///
+	/// The caller must own the frame being read.
+	fn read(&self, mut off: usize, mut buf: &mut [u8]) {
+		while !buf.is_empty() {
+			let inner_off = off % PAGE_SIZE;
+			let len = min(PAGE_SIZE - inner_off, buf.len());
+			let page = self.pages[off / PAGE_SIZE].slice::<u8>();
+			buf[..len].copy_from_slice(&page[inner_off..(inner_off + len)]);
+			off += len;
+			buf = &mut buf[len..];
+		}
+	}
+
+	/// Tells whether the ring is mapped in memory.
+	fn is_mapped(&self) -> bool {
+		// The ring holds a reference, and each mapping another one
+		self.pages.iter().any(|p| p.ref_count() > 1)
+	}
+}
+
+/// The rings of a packet socket.
+#[derive(Debug, Default)]
+pub struct PacketRings {
+	/// The version of the headers of frames.
+	version: u32,
+	/// The reception ring.
+	rx: Option<Ring>,
+	/// The transmission ring.
+	tx: Option<Ring>,
+	/// Tells whether a received frame has been dropped because the reception ring was full.
+	losing: bool,
+}
+
+impl PacketRings {
+	/// Tells whether a reception ring is set up.
+	pub fn has_rx(&self) -> bool {
+		self.rx.is_some()
+	}
+
+	/// Tells whether one of the rings is mapped in memory.
+	fn is_mapped(&self) -> bool {
+		self.rx.iter().chain(self.tx.iter()).any(Ring::is_mapped)
+	}
+
+	/// Tells whether frames received in the reception ring are waiting for userspace.
+	pub fn rx_ready(&self) -> bool {
+		self.rx.as_ref().is_some_and(|ring| {
+			let last = (ring.head + ring.frame_nr - 1) % ring.frame_nr;
+			ring.status(last).load(Acquire) != TP_STATUS_KERNEL
+		})
+	}
+
+	/// Writes a frame received on the interface with index `ifindex` to the reception ring.
+	///
+	/// Arguments:
+	/// - `frame` is the frame, including the Ethernet header
+	/// - `off` is the offset of the data given to userspace in `frame`
+	/// - `snaplen` is the length of the data to copy, as returned by the socket's filter
+	///
+	/// If the ring is full, the frame is dropped and the function returns `false`.
+	pub fn receive(
+		&mut self,
+		ifindex: u32,
+		pkttype: u8,
+		frame: &[u8],
+		off: usize,
+		snaplen: usize,
+	) -> bool {
+		let version = self.version;
+		let Some(ring) = &mut self.rx else {
+			return false;
+		};
+		let status = ring.status(ring.head);
+		if status.load(Acquire) != TP_STATUS_KERNEL {
+			self.losing = true;
+			return false;
+		}
+		let frame_off = ring.frame_off(ring.head);
+		let len = frame.len() - off;
+		// Align the network header, as Linux does
+		let mac_off = TPACKET_NET_OFF + off - ETH_HDR_LEN;
+		let snaplen = min(snaplen, ring.frame_size.saturating_sub(mac_off));
+		let mut st = TP_STATUS_USER;
+		if snaplen < len {
+			st |= TP_STATUS_COPY;
+		}
+		if self.losing {
+			st |= TP_STATUS_LOSING;
+			self.losing = false;
+		}
+		// Header, without the status word
+		let ts = current_time_ns(Clock::Realtime);
+		let sec = (ts / 1_000_000_000) as u32;
+		let nsec = (ts % 1_000_000_000) as u32;
+		// `TPACKET_V1` gives microseconds
+		let sub_sec = match version {
+			TPACKET_V1 => nsec / 1000,
+			_ => nsec,
+		};
+		let hdr_off = status_size(version);
+		let mut hdr = [0u8; TPACKET_ADDR_OFF];
+		let mut cursor = hdr_off;
+		let mut put = |field: &[u8]| {
+			hdr[cursor..(cursor + field.len())].copy_from_slice(field);
+			cursor += field.len();
+		};
+		put(&(len as u32).to_ne_bytes());
+		put(&(snaplen as u32).to_ne_bytes());
+		put(&(mac_off as u16).to_ne_bytes());
+		put(&(TPACKET_NET_OFF as u16).to_ne_bytes());
+		put(&sec.to_ne_bytes());
+		put(&sub_sec.to_ne_bytes());
+		let proto = frame.get(12..ETH_HDR_LEN).unwrap_or(&[0, 0]);
+		let mut addr = SockAddrLl {
+			sll_family: SocketDomain::AfPacket.get_id() as _,
+			sll_protocol: u16::from_ne_bytes([proto[0], proto[1]]),
+			sll_ifindex: ifindex as _,
+			sll_hatype: ARPHRD_ETHER,
+			sll_pkttype: pkttype,
+			sll_halen: 6,
+			sll_addr: [0; 8],
+		};
+		if let Some(src) = frame.get(6..12) {
+			addr.sll_addr[..6].copy_from_slice(src);
+		}
+		ring.write(frame_off + hdr_off, &hdr[hdr_off..]);
+		ring.write(frame_off + TPACKET_ADDR_OFF, as_bytes(&addr));
+		ring.write(frame_off + mac_off, &frame[off..(off + snaplen)]);
+		// Hand the frame over to userspace
+		status.store(st, Release);
+		ring.head = (ring.head + 1) % ring.frame_nr;
+		true
+	}
+}
+
+/// Returns the size of the status word at the beginning of ring frames with headers of version
+/// `version`.
+fn status_size(version: u32) -> usize {
+	match version {
+		TPACKET_V1 => size_of::<usize>(),
+		_ => size_of::<u32>(),
+	}
+}
+
+/// Returns the `u32` at the beginning of `optval`.
+fn read_u32(optval: &[u8]) -> EResult<u32> {
+	optval
+		.get(..4)
+		.map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+		.ok_or_else(|| errno!(EINVAL))
+}
+
+/// Writes the packet socket option `optname` on `sock`, with the value `optval`.
+pub fn set_opt(sock: &Socket, optname: c_int, optval: &[u8]) -> EResult<()> {
+	let mut rings = sock.packet_rings().lock();
+	match optname {
+		PACKET_VERSION => {
+			let version = read_u32(optval)?;
+			if version != TPACKET_V1 && version != TPACKET_V2 {
+				return Err(errno!(EINVAL));
+			}
+			if rings.rx.is_some() || rings.tx.is_some() {
+				return Err(errno!(EBUSY));
+			}
+			rings.version = version;
+		}
+		PACKET_RX_RING | PACKET_TX_RING => {
+			if optval.len() < size_of::<TpacketReq>() {
+				return Err(errno!(EINVAL));
+			}
+			let field = |i: usize| read_u32(&optval[(i * 4)..]);
+			let req = TpacketReq {
+				tp_block_size: field(0)?,
+				tp_block_nr: field(1)?,
+				tp_frame_size: field(2)?,
+				tp_frame_nr: field(3)?,
+			};
+			if rings.is_mapped() {
+				return Err(errno!(EBUSY));
+			}
+			let ring = Ring::new(&req)?;
+			if optname == PACKET_RX_RING {
+				rings.rx = ring;
+			} else {
+				rings.tx = ring;
+			}
+		}
+		_ => return Err(errno!(ENOPROTOOPT)),
+	}
+	Ok(())
+}
+
+/// Reads the packet socket option `optname` on `sock`.
+pub fn get_opt(sock: &Socket, optname: c_int) -> EResult<Vec<u8>> {
+	match optname {
+		PACKET_VERSION => {
+			let version = sock.packet_rings().lock().version;
+			Ok(Vec::try_from(&(version as c_int).to_ne_bytes()[..])?)
+		}
+		_ => Err(errno!(ENOPROTOOPT)),
+	}
+}
+
+/// Returns the `pages` pages to be mapped for the rings of `sock`, starting at the offset `off`
+/// in pages.
+///
+/// The reception ring is followed by the transmission ring. Like on Linux, both rings must be
+/// mapped as a whole.
+pub fn mmap_pages(sock: &Socket, off: u64, pages: usize) -> EResult<Vec<RcFrame>> {
+	let rings = sock.packet_rings().lock();
+	let ring_pages = rings
+		.rx
+		.iter()
+		.chain(rings.tx.iter())
+		.flat_map(|r| r.pages.iter());
+	let total = ring_pages.clone().count();
+	if total == 0 || off != 0 || pages != total {
+		return Err(errno!(EINVAL));
+	}
+	let mut frames = Vec::with_capacity(total)?;
+	for page in ring_pages {
+		frames.push(page.clone())?;
+	}
+	Ok(frames)
+}
+
+/// Sends the frames userspace requested to send in the transmission ring of `sock`.
+///
+/// On success, the function returns the number of bytes sent. If no transmission ring is set
+/// up, the function returns `None`.
+fn flush_tx_ring(sock: &Socket) -> EResult<Option<usize>> {
+	let mut total = 0;
+	loop {
+		// Take the next frame, without holding the lock while transmitting, since the frame may be
+		// delivered back to the socket
+		let (i, data) = {
+			let mut rings = sock.packet_rings().lock();
+			let version = rings.version;
+			let Some(ring) = &mut rings.tx else {
+				return Ok(None);
+			};
+			let i = ring.head;
+			let status = ring.status(i);
+			if status.load(Acquire) != TP_STATUS_SEND_REQUEST {
+				break;
+			}
+			let frame_off = ring.frame_off(i);
+			let mut len = [0u8; 4];
+			ring.read(frame_off + status_size(version), &mut len);
+			let len = u32::from_ne_bytes(len) as usize;
+			if len > ring.frame_size - TPACKET_ADDR_OFF {
+				status.store(TP_STATUS_WRONG_FORMAT, Release);
+				ring.head = (i + 1) % ring.frame_nr;
+				return Err(errno!(EINVAL));
+			}
+			let mut data = Vec::new();
+			data.resize(len, 0)?;
+			// The data follows the header, in place of the address
+			ring.read(frame_off + TPACKET_ADDR_OFF, &mut data);
+			status.store(TP_STATUS_SENDING, Release);
+			ring.head = (i + 1) % ring.frame_nr;
+			(i, data)
+		};
+		let res = transmit(sock, &data);
+		// Give the frame back to userspace
+		if let Some(ring) = &sock.packet_rings().lock().tx {
+			let status = if res.is_ok() {
+				TP_STATUS_AVAILABLE
+			} else {
+				TP_STATUS_WRONG_FORMAT
+			};
+			ring.status(i).store(status, Release);
+		}
+		total += res?;
+	}
+	Ok(Some(total))
+}
+
+/// Sends `msg` on the packet socket `sock`.
+///
+/// If a transmission ring is set up, `msg` is ignored and the frames of the ring requested to be
+/// sent are transmitted instead.
+pub fn send(sock: &Socket, msg: &[u8]) -> EResult<usize> {
+	match flush_tx_ring(sock)? {
+		Some(len) => Ok(len),
+		None => transmit(sock, msg),
+	}
+}
+
+/// The list of open packet sockets.
+static SOCKETS: Mutex<Vec<Arc<Socket>>> = Mutex::new(Vec::new());
+
+/// Registers a newly created packet socket so that it receives frames.
+pub fn register(sock: Arc<Socket>) -> AllocResult<()> {
+	SOCKETS.lock().push(sock)
+}
+
+/// Unregisters the given packet socket.
+///
+/// If the socket is not registered, the function does nothing.
+pub fn unregister(sock: &Socket) {
+	SOCKETS
+		.lock()
+		.retain(|s| !ptr::eq(Arc::as_ptr(s), sock as *const _));
+}
+
+/// Tells whether the socket bound with `addr` and protocol `proto` (in network byte order) shall
+/// receive `frame` from the interface with index `ifindex`.
+fn is_matching(addr: Option<&SockAddrLl>, proto: u16, ifindex: u32, frame: &[u8]) -> bool {
+	// Binding with a zero protocol keeps the one given at creation
+	let proto = addr
+		.map(|a| a.sll_protocol)
+		.filter(|p| *p != 0)
+		.unwrap_or(proto);
+	let bound_ifindex = addr.map(|a| a.sll_ifindex as u32).unwrap_or(0);
+	if bound_ifindex != 0 && bound_ifindex != ifindex {
+		return false;
+	}
+	let proto = u16::from_be(proto);
+	if proto == ETH_P_ALL {
+		return true;
+	}
+	frame
+		.get(12..ETH_HDR_LEN)
+		.map(|t| u16::from_be_bytes([t[0], t[1]]) == proto)
+		.unwrap_or(false)
+}
+
+/// Hands a copy of `frame`, which went through the interface with index `ifindex` in the network
+/// namespace with ID `ns_id`, to every matching packet socket of that namespace.
+///
+/// `pkttype` is the type of the frame, either [`PACKET_HOST`] or [`PACKET_OUTGOING`].
+///
+/// Frames are filtered by the socket's bound interface and protocol, then by the socket's filter
+/// program if one is attached. Sockets whose receive queue is full drop the frame.
+pub fn deliver(ns_id: u64, ifindex: u32, pkttype: u8, frame: &[u8]) {
+	let sockets = SOCKETS.lock();
+	for sock in sockets.iter() {
+		if sock.net_ns().id() != ns_id {
+			continue;
+		}
+		let matching = {
+			let name = sock.get_sockname().lock();
+			let addr = from_bytes::<SockAddrLl>(&name);
+			is_matching(addr, sock.desc().protocol as _, ifindex, frame)
+		};
+		if !matching {
+			continue;
+		}
+		let off = match sock.desc().type_ {
+			SocketType::SockDgram => min(ETH_HDR_LEN, frame.len()),
+			_ => 0,
+		};
+		// Errors are not reported since the frame is simply dropped for this socket
+		let _ = sock.push_packet(ifindex, pkttype, frame, off);
+	}
+}
+
+/// Transmits `frame` on the interface the packet socket `sock` is bound to.
+///
+/// If the socket is not bound to an interface, the function returns [`errno::ENXIO`].
+pub fn transmit(sock: &Socket, frame: &[u8]) -> EResult<usize> {
+	let ifindex = {
+		let name = sock.get_sockname().lock();
+		let addr = from_bytes::<SockAddrLl>(&name).ok_or_else(|| errno!(ENXIO))?;
+		addr.sll_ifindex as u32
+	};
+	let iface = sock
+		.net_ns()
+		.get_iface_by_index(ifindex)
+		.ok_or_else(|| errno!(ENXIO))?;
+	let mut iface = iface.lock();
+	if !iface.is_up() {
+		return Err(errno!(ENETDOWN));
+	}
+	let len = crate::net::transmit(sock.net_ns(), &mut *iface, &frame.into())?;
+	Ok(len as _)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn packet_match_protocol() {
+		let mut frame = [0u8; 60];
+		frame[12] = 0x08;
+		frame[13] = 0x06;
+		assert!(is_matching(None, ETH_P_ALL.to_be(), 1, &frame));
+		assert!(is_matching(None, 0x0806u16.to_be(), 1, &frame));
+		assert!(!is_matching(None, 0x0800u16.to_be(), 1, &frame));
+		let addr = SockAddrLl {
+			sll_family: 17,
+			sll_protocol: ETH_P_ALL.to_be(),
+			sll_ifindex: 2,
+			sll_hatype: 0,
+			sll_pkttype: 0,
+			sll_halen: 0,
+			sll_addr: [0; 8],
+		};
+		assert!(is_matching(Some(&addr), 0, 2, &frame));
+		assert!(!is_matching(Some(&addr), 0, 1, &frame));
+	}
+
+	#[test_case]
+	fn packet_rx_ring() {
+		let req = TpacketReq {
+			tp_block_size: PAGE_SIZE as _,
+			tp_block_nr: 1,
+			tp_frame_size: (PAGE_SIZE / 2) as _,
+			tp_frame_nr: 2,
+		};
+		let mut rings = PacketRings {
+			version: TPACKET_V2,
+			rx: Ring::new(&req).unwrap(),
+			..Default::default()
+		};
+		let mut frame = [0u8; 60];
+		frame[59] = 0xff;
+		assert!(!rings.rx_ready());
+		assert!(rings.receive(1, PACKET_HOST, &frame, 0, frame.len()));
+		assert!(rings.receive(1, PACKET_HOST, &frame, ETH_HDR_LEN, 10));
+		// The ring is full
+		assert!(!rings.receive(1, PACKET_HOST, &frame, 0, frame.len()));
+		assert!(rings.rx_ready());
+		let ring = rings.rx.as_ref().unwrap();
+		assert_eq!(ring.status(0).load(Acquire), TP_STATUS_USER);
+		assert_eq!(
+			ring.status(1).load(Acquire),
+			TP_STATUS_USER | TP_STATUS_COPY
+		);
+		let mut last = [0u8];
+		ring.read(TPACKET_NET_OFF - ETH_HDR_LEN + 59, &mut last);
+		assert_eq!(last[0], 0xff);
+		// Give the first frame back to the kernel
+		ring.status(0).store(TP_STATUS_KERNEL, Release);
+		assert!(rings.receive(1, PACKET_HOST, &frame, 0, frame.len()));
+		let ring = rings.rx.as_ref().unwrap();
+		assert_eq!(
+			ring.status(0).load(Acquire),
+			TP_STATUS_USER | TP_STATUS_LOSING
+		);
+	}
+}
@@ -21,19 +21,20 @@
 
 use super::Address;
 use core::ffi::c_short;
+use macros::AnyRepr;
 
 /// Structure providing connection informations for sockets with IPv4.
 #[repr(C)]
-#[derive(Clone)]
+#[derive(AnyRepr, Clone)]
 pub struct SockAddrIn {
 	/// The family of the socket.
-	sin_family: c_short,
+	pub sin_family: c_short,
 	/// The port on which the connection is to be opened.
-	sin_port: c_short,
+	pub sin_port: c_short,
 	/// The destination address of the connection.
-	sin_addr: u32,
+	pub sin_addr: u32,
 	/// Padding.
-	sin_zero: [u8; 8],
+	pub sin_zero: [u8; 8],
 }
 
 /// Structure representing an IPv6 address.
@@ -50,15 +51,15 @@ pub union In6Addr {
 #[derive(Clone)]
 pub struct SockAddrIn6 {
 	/// The family of the socket.
-	sin6_family: c_short,
+	pub sin6_family: c_short,
 	/// The port on which the connection is to be opened.
-	sin6_port: c_short,
+	pub sin6_port: c_short,
 	/// TODO doc
-	sin6_flowinfo: u32,
+	pub sin6_flowinfo: u32,
 	/// The destination address of the connection.
-	sin6_addr: In6Addr,
+	pub sin6_addr: In6Addr,
 	/// TODO doc
-	sin6_scope_id: u32,
+	pub sin6_scope_id: u32,
 }
 
 /// A unified structure which contains data passed from userspace.
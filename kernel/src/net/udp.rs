@@ -0,0 +1,361 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The User Datagram Protocol (UDP, RFC 768) transmits datagrams over IP, without any guarantee
+//! of delivery or ordering.
+//!
+//! A socket is bound to a local port, either explicitly or on first use, in which case an
+//! ephemeral port is allocated. A connected socket sends to its peer by default, and only
+//! receives the datagrams coming from it.
+
+use super::{
+	Address, ETH_HDR_LEN, ETH_P_IP, Interface, NetNamespace, SocketDesc, SocketDomain, SocketType,
+	buff::BuffList, ip, ip::IPV4_HDR_LEN, sockaddr::SockAddrIn, transmit,
+};
+use crate::{file::socket::Socket, sync::mutex::Mutex};
+use core::{ops::Range, ptr};
+use utils::{
+	bytes::{as_bytes, from_bytes},
+	collections::vec::Vec,
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// Protocol number of UDP.
+pub const IPPROTO_UDP: i32 = 17;
+
+/// Address family: unspecified. Connecting to such an address dissolves the association.
+const AF_UNSPEC: u16 = 0;
+/// Address family: IPv4
+const AF_INET: u16 = 2;
+
+/// The wildcard address, to which sockets receiving on every local address are bound.
+const INADDR_ANY: [u8; 4] = [0; 4];
+
+/// The length of the UDP header.
+const UDP_HDR_LEN: usize = 8;
+/// The maximum size of the payload of a datagram.
+const MAX_PAYLOAD: usize = u16::MAX as usize - IPV4_HDR_LEN - UDP_HDR_LEN;
+
+/// The range of ports allocated to sockets used without being bound to a port.
+const EPHEMERAL_PORTS: Range<u16> = 32768..61000;
+
+/// The list of UDP sockets.
+static SOCKETS: Mutex<Vec<Arc<Socket>>> = Mutex::new(Vec::new());
+
+/// Tells whether the socket with descriptor `desc` is a UDP socket.
+pub fn is_udp(desc: &SocketDesc) -> bool {
+	desc.domain == SocketDomain::AfInet
+		&& desc.type_ == SocketType::SockDgram
+		&& matches!(desc.protocol, 0 | IPPROTO_UDP)
+}
+
+/// Registers a newly created UDP socket so that it receives datagrams.
+pub fn register(sock: Arc<Socket>) -> AllocResult<()> {
+	SOCKETS.lock().push(sock)
+}
+
+/// Unregisters the given UDP socket.
+///
+/// If the socket is not registered, the function does nothing.
+pub fn unregister(sock: &Socket) {
+	SOCKETS
+		.lock()
+		.retain(|s| !ptr::eq(Arc::as_ptr(s), sock as *const _));
+}
+
+/// Parses the IPv4 socket address `sockaddr`, returning the address and the port.
+fn parse_addr(sockaddr: &[u8]) -> EResult<([u8; 4], u16)> {
+	let sockaddr = from_bytes::<SockAddrIn>(sockaddr).ok_or_else(|| errno!(EINVAL))?;
+	if sockaddr.sin_family as u16 != AF_INET {
+		return Err(errno!(EAFNOSUPPORT));
+	}
+	Ok((
+		sockaddr.sin_addr.to_ne_bytes(),
+		u16::from_be(sockaddr.sin_port as _),
+	))
+}
+
+/// Returns the IPv4 socket address for `addr` and `port`.
+fn make_addr(addr: [u8; 4], port: u16) -> AllocResult<Vec<u8>> {
+	let sockaddr = SockAddrIn {
+		sin_family: AF_INET as _,
+		sin_port: port.to_be() as _,
+		sin_addr: u32::from_ne_bytes(addr),
+		sin_zero: [0; 8],
+	};
+	Vec::try_from(as_bytes(&sockaddr))
+}
+
+/// Tells whether a socket other than `sock` in the same network namespace, among `sockets`, is
+/// bound to `port` on an address overlapping with `addr`.
+fn is_in_use(sockets: &[Arc<Socket>], sock: &Socket, addr: [u8; 4], port: u16) -> bool {
+	sockets
+		.iter()
+		.filter(|s| !ptr::eq(Arc::as_ptr(s), sock) && s.net_ns().id() == sock.net_ns().id())
+		.filter_map(|s| parse_addr(&s.get_sockname().lock()).ok())
+		.any(|(a, p)| p == port && (a == addr || a == INADDR_ANY || addr == INADDR_ANY))
+}
+
+/// Binds `sock` to the address `addr` and the port `port`, on behalf of the locked list
+/// `sockets`.
+///
+/// If `port` is zero, an ephemeral port is allocated.
+///
+/// If the socket is already bound, the function returns [`errno::EINVAL`]. If the port is already
+/// in use, or if no ephemeral port is available, the function returns [`errno::EADDRINUSE`].
+fn do_bind(sockets: &[Arc<Socket>], sock: &Socket, addr: [u8; 4], port: u16) -> EResult<()> {
+	let mut sockname = sock.get_sockname().lock();
+	if !sockname.is_empty() {
+		return Err(errno!(EINVAL));
+	}
+	let port = if port != 0 {
+		if is_in_use(sockets, sock, addr, port) {
+			return Err(errno!(EADDRINUSE));
+		}
+		port
+	} else {
+		EPHEMERAL_PORTS
+			.clone()
+			.find(|p| !is_in_use(sockets, sock, addr, *p))
+			.ok_or_else(|| errno!(EADDRINUSE))?
+	};
+	*sockname = make_addr(addr, port)?;
+	Ok(())
+}
+
+/// Binds the UDP socket `sock` to the address `sockaddr`.
+///
+/// If the port is zero, an ephemeral port is allocated.
+pub fn bind(sock: &Socket, sockaddr: &[u8]) -> EResult<()> {
+	let (addr, port) = parse_addr(sockaddr)?;
+	// TODO check the address is bound to an interface (EADDRNOTAVAIL)
+	do_bind(&SOCKETS.lock(), sock, addr, port)
+}
+
+/// Returns the local address and port of `sock`, binding it to an ephemeral port first if it is
+/// not bound yet.
+fn autobind(sock: &Socket) -> EResult<([u8; 4], u16)> {
+	// The list is locked first, in the same order as when looking for a bound port
+	let sockets = SOCKETS.lock();
+	if sock.get_sockname().lock().is_empty() {
+		do_bind(&sockets, sock, INADDR_ANY, 0)?;
+	}
+	parse_addr(&sock.get_sockname().lock())
+}
+
+/// Connects the UDP socket `sock` to the address `sockaddr`.
+///
+/// Datagrams are then sent to this address by default, and only datagrams coming from it are
+/// received. If the address family of `sockaddr` is `AF_UNSPEC`, the association is dissolved.
+pub fn connect(sock: &Socket, sockaddr: &[u8]) -> EResult<()> {
+	let family = sockaddr
+		.get(..2)
+		.map(|f| u16::from_ne_bytes([f[0], f[1]]))
+		.ok_or_else(|| errno!(EINVAL))?;
+	if family == AF_UNSPEC {
+		sock.get_peername().lock().clear();
+		return Ok(());
+	}
+	let (addr, port) = parse_addr(sockaddr)?;
+	autobind(sock)?;
+	*sock.get_peername().lock() = make_addr(addr, port)?;
+	Ok(())
+}
+
+/// Returns the interface through which datagrams to `addr` are transmitted in the network
+/// namespace `ns`.
+///
+/// Datagrams to an address of the local system go through the loopback interface.
+fn route(ns: &NetNamespace, addr: [u8; 4]) -> Option<Arc<Mutex<dyn Interface>>> {
+	let local = addr[0] == 127
+		|| ns.interfaces.lock().iter().any(|(_, iface)| {
+			iface
+				.lock()
+				.get_addresses()
+				.iter()
+				.any(|a| a.addr == Address::IPv4(addr))
+		});
+	if local {
+		ns.get_iface(b"lo")
+	} else {
+		ns.get_iface_for(Address::IPv4(addr))
+	}
+}
+
+/// Sends the datagram `msg` on the UDP socket `sock`.
+///
+/// `dest` is the destination address. If `None`, the datagram is sent to the peer the socket is
+/// connected to, or the function returns [`errno::EDESTADDRREQ`] if it is not connected.
+///
+/// If the socket is not bound, it is bound to an ephemeral port first.
+pub fn send(sock: &Socket, msg: &[u8], dest: Option<&[u8]>) -> EResult<usize> {
+	if msg.len() > MAX_PAYLOAD {
+		return Err(errno!(EMSGSIZE));
+	}
+	let (dst_addr, dst_port) = match dest {
+		Some(dest) => parse_addr(dest)?,
+		None => {
+			let peer = sock.get_peername().lock();
+			if peer.is_empty() {
+				return Err(errno!(EDESTADDRREQ));
+			}
+			parse_addr(&peer)?
+		}
+	};
+	if dst_port == 0 {
+		return Err(errno!(EINVAL));
+	}
+	let (src_addr, src_port) = autobind(sock)?;
+	let ns = sock.net_ns();
+	let iface = route(ns, dst_addr).ok_or_else(|| errno!(ENETUNREACH))?;
+	let mut iface = iface.lock();
+	if !iface.is_up() {
+		return Err(errno!(ENETDOWN));
+	}
+	// Sockets bound to the wildcard address send from the interface's address
+	let src_addr = if src_addr != INADDR_ANY {
+		Some(src_addr)
+	} else {
+		iface.get_addresses().iter().find_map(|a| match a.addr {
+			Address::IPv4(addr) => Some(addr),
+			_ => None,
+		})
+	};
+	let src_addr = src_addr.ok_or_else(|| errno!(EADDRNOTAVAIL))?;
+	let dst_mac = ns.neighbors.lock().lookup(dst_addr).unwrap_or([0xff; 6]);
+	let mut eth_hdr = [0; ETH_HDR_LEN];
+	eth_hdr[..6].copy_from_slice(&dst_mac);
+	eth_hdr[6..12].copy_from_slice(iface.get_mac());
+	eth_hdr[12..].copy_from_slice(&ETH_P_IP.to_be_bytes());
+	let udp_len = (UDP_HDR_LEN + msg.len()) as u16;
+	let ip_hdr = ip::build_header(ip::PROTO_UDP, src_addr, dst_addr, udp_len);
+	// The checksum is optional over IPv4, and left to zero
+	let mut udp_hdr = [0; UDP_HDR_LEN];
+	udp_hdr[..2].copy_from_slice(&src_port.to_be_bytes());
+	udp_hdr[2..4].copy_from_slice(&dst_port.to_be_bytes());
+	udp_hdr[4..6].copy_from_slice(&udp_len.to_be_bytes());
+	let mut payload = BuffList::from(msg);
+	let mut udp = payload.push_front(udp_hdr.as_slice().into());
+	let mut ip = udp.push_front(ip_hdr.as_slice().into());
+	let frame = ip.push_front(eth_hdr.as_slice().into());
+	transmit(ns, &mut *iface, &frame)?;
+	Ok(msg.len())
+}
+
+/// Returns the priority of `sock` to receive a datagram from `src` to `dst`, each being an
+/// address and a port, or `None` if the socket shall not receive it.
+///
+/// Connected sockets, then sockets bound to a specific address, have priority.
+fn match_priority(sock: &Socket, src: ([u8; 4], u16), dst: ([u8; 4], u16)) -> Option<u8> {
+	let (addr, port) = parse_addr(&sock.get_sockname().lock()).ok()?;
+	if port != dst.1 || (addr != INADDR_ANY && addr != dst.0) {
+		return None;
+	}
+	let peer = sock.get_peername().lock();
+	let connected = !peer.is_empty();
+	if connected && parse_addr(&peer).ok()? != src {
+		return None;
+	}
+	Some(((connected as u8) << 1) | (addr != INADDR_ANY) as u8)
+}
+
+/// Hands the UDP datagram `segment`, received from `src_addr` to `dst_addr` in the network
+/// namespace with ID `ns_id`, to the socket bound to its destination.
+///
+/// Malformed datagrams, and datagrams for which no socket is bound, are discarded silently.
+pub fn receive(ns_id: u64, src_addr: [u8; 4], dst_addr: [u8; 4], segment: &[u8]) {
+	let Some(hdr) = segment.get(..UDP_HDR_LEN) else {
+		return;
+	};
+	let src_port = u16::from_be_bytes([hdr[0], hdr[1]]);
+	let dst_port = u16::from_be_bytes([hdr[2], hdr[3]]);
+	let len = u16::from_be_bytes([hdr[4], hdr[5]]) as usize;
+	// TODO verify the checksum
+	let Some(payload) = segment.get(UDP_HDR_LEN..len) else {
+		return;
+	};
+	let sock = SOCKETS
+		.lock()
+		.iter()
+		.filter(|s| s.net_ns().id() == ns_id)
+		.filter_map(|s| {
+			let prio = match_priority(s, (src_addr, src_port), (dst_addr, dst_port))?;
+			Some((prio, s))
+		})
+		.max_by_key(|(prio, _)| *prio)
+		.map(|(_, s)| s.clone());
+	let Some(sock) = sock else {
+		return;
+	};
+	// Errors are not reported since the datagram is simply dropped
+	let Ok(addr) = make_addr(src_addr, src_port) else {
+		return;
+	};
+	let _ = sock.push_datagram(payload, &addr);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::memory::user::UserSlice;
+
+	#[test_case]
+	fn udp_connected() {
+		let ns = NetNamespace::new().unwrap();
+		let new_sock = || {
+			let desc = SocketDesc {
+				domain: SocketDomain::AfInet,
+				type_: SocketType::SockDgram,
+				protocol: 0,
+			};
+			let sock = Arc::new(Socket::new(desc, ns.clone()).unwrap()).unwrap();
+			register(sock.clone()).unwrap();
+			sock
+		};
+		let (a, b, c) = (new_sock(), new_sock(), new_sock());
+		let a_addr = make_addr([127, 0, 0, 1], 5000).unwrap();
+		bind(&a, &a_addr).unwrap();
+		assert_eq!(bind(&c, &a_addr).unwrap_err().as_int(), errno::EADDRINUSE);
+		assert_eq!(
+			send(&b, b"hello", None).unwrap_err().as_int(),
+			errno::EDESTADDRREQ
+		);
+		// The default destination of `b` is `a`
+		connect(&b, &a_addr).unwrap();
+		send(&b, b"hello", None).unwrap();
+		let mut buf = [0; 16];
+		let iov = [UserSlice::from_slice_mut(&mut buf)];
+		let (len, _, src) = a.recv(&iov, false, true, false).unwrap();
+		assert_eq!(len, 5);
+		let b_port = parse_addr(&b.get_sockname().lock()).unwrap().1;
+		assert_eq!(parse_addr(&src).unwrap(), ([127, 0, 0, 1], b_port));
+		// Once connected to `b`, `a` does not receive from `c`
+		connect(&a, &src).unwrap();
+		send(&c, b"hello", Some(&a_addr)).unwrap();
+		let err = a.recv(&iov, false, true, false).unwrap_err();
+		assert_eq!(err.as_int(), errno::EAGAIN);
+		// Dissolve the association
+		connect(&b, &AF_UNSPEC.to_ne_bytes()).unwrap();
+		assert!(b.get_peername().lock().is_empty());
+		for sock in [a, b, c] {
+			unregister(&sock);
+		}
+	}
+}
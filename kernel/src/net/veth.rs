@@ -0,0 +1,294 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Virtual Ethernet (veth) interfaces, created in pairs.
+//!
+//! A frame written to one end of a pair is received by the other end. The two ends can be in
+//! different network namespaces, which allows connecting them.
+//!
+//! Pairs are created and deleted through netlink (see [`super::netlink`]). Deleting either end
+//! deletes the pair, as does destroying the namespace of either end.
+
+use super::{
+	BindAddress, IFNAMSIZ, IfaceTable, Interface, MAC, NetNamespace, RxQueue, alloc_ifindex,
+	buff::BuffList, packet, packet::PACKET_HOST,
+};
+use crate::sync::mutex::Mutex;
+use core::sync::atomic::{
+	AtomicBool,
+	Ordering::{AcqRel, Acquire, Release},
+};
+use utils::{
+	collections::{string::String, vec::Vec},
+	errno,
+	errno::{AllocResult, EResult},
+	format,
+	ptr::arc::Arc,
+};
+
+/// One end of a pair, as seen by its peer.
+struct End {
+	/// The name of the interface.
+	name: String,
+	/// The index of the interface.
+	index: u32,
+	/// The ID of the network namespace the end is in.
+	ns_id: u64,
+	/// The interfaces of the network namespace the end is in.
+	table: Arc<IfaceTable>,
+}
+
+/// The state shared by both ends of a pair.
+struct Link {
+	/// The ends of the pair.
+	ends: [End; 2],
+	/// For each end, the frames it received.
+	rx: [Mutex<RxQueue>; 2],
+	/// Tells whether the pair is up. This is the case from the registration of both ends, until
+	/// either of them is unregistered.
+	up: AtomicBool,
+}
+
+/// One end of a veth pair.
+pub struct Veth {
+	/// The hardware address of the interface.
+	mac: MAC,
+	/// The addresses bound to the interface.
+	addresses: Vec<BindAddress>,
+
+	/// The state shared with the peer.
+	link: Arc<Link>,
+	/// The index of this end in the link.
+	end: usize,
+}
+
+impl Veth {
+	/// Returns the locally administered hardware address of the interface with index `index`.
+	fn mac(index: u32) -> MAC {
+		let [a, b, c, d] = index.to_be_bytes();
+		[0x02, 0x00, a, b, c, d]
+	}
+}
+
+impl Interface for Veth {
+	fn get_name(&self) -> &[u8] {
+		self.link.ends[self.end].name.as_bytes()
+	}
+
+	fn get_index(&self) -> u32 {
+		self.link.ends[self.end].index
+	}
+
+	fn is_up(&self) -> bool {
+		self.link.up.load(Acquire)
+	}
+
+	fn get_mac(&self) -> &MAC {
+		&self.mac
+	}
+
+	fn get_addresses(&self) -> &[BindAddress] {
+		&self.addresses
+	}
+
+	fn is_removable(&self) -> bool {
+		true
+	}
+
+	fn unregister(&mut self) {
+		// The first end to be unregistered removes its peer
+		if !self.link.up.swap(false, AcqRel) {
+			return;
+		}
+		let peer = &self.link.ends[1 - self.end];
+		// Dropped after releasing the lock
+		let _peer = peer.table.lock().remove(peer.name.as_bytes());
+	}
+
+	fn add_address(&mut self, addr: BindAddress) -> EResult<()> {
+		self.addresses.push(addr)?;
+		Ok(())
+	}
+
+	fn read(&mut self, buff: &mut [u8]) -> EResult<u64> {
+		self.link.rx[self.end].lock().pop(buff)
+	}
+
+	fn write(&mut self, buff: &BuffList<'_>) -> EResult<u64> {
+		if !self.is_up() {
+			return Err(errno!(ENETDOWN));
+		}
+		let frame = buff.to_vec()?;
+		let peer = 1 - self.end;
+		let End {
+			ns_id,
+			index,
+			..
+		} = self.link.ends[peer];
+		packet::deliver(ns_id, index, PACKET_HOST, &frame);
+		self.link.rx[peer].lock().push(frame)?;
+		Ok(buff.len() as _)
+	}
+}
+
+/// Tells whether `name` is a valid interface name.
+fn is_valid_name(name: &[u8]) -> bool {
+	!name.is_empty()
+		&& name.len() < IFNAMSIZ
+		&& name != b"."
+		&& name != b".."
+		&& !name
+			.iter()
+			.any(|c| matches!(c, b'/' | b':') || c.is_ascii_whitespace())
+}
+
+/// Returns the two ends of a new pair, named `name` in the namespace `ns` and `peer_name` in
+/// `peer_ns`.
+///
+/// The pair is down until it is brought up explicitly.
+fn new_pair(
+	ns: &NetNamespace,
+	name: String,
+	peer_ns: &NetNamespace,
+	peer_name: String,
+) -> AllocResult<(Veth, Veth)> {
+	let index = alloc_ifindex();
+	let peer_index = alloc_ifindex();
+	let link = Arc::new(Link {
+		ends: [
+			End {
+				name,
+				index,
+				ns_id: ns.id(),
+				table: ns.interfaces.clone(),
+			},
+			End {
+				name: peer_name,
+				index: peer_index,
+				ns_id: peer_ns.id(),
+				table: peer_ns.interfaces.clone(),
+			},
+		],
+		rx: Default::default(),
+		up: AtomicBool::new(false),
+	})?;
+	let end = Veth {
+		mac: Veth::mac(index),
+		addresses: Vec::new(),
+
+		link: link.clone(),
+		end: 0,
+	};
+	let peer = Veth {
+		mac: Veth::mac(peer_index),
+		addresses: Vec::new(),
+
+		link,
+		end: 1,
+	};
+	Ok((end, peer))
+}
+
+/// Returns the first name of the form `vethN` that is free in the namespace `ns`, and different
+/// from `taken`.
+fn free_name(ns: &NetNamespace, taken: Option<&[u8]>) -> AllocResult<String> {
+	let mut n = 0usize;
+	loop {
+		let name = format!("veth{n}")?;
+		if ns.get_iface(name.as_bytes()).is_none() && taken != Some(name.as_bytes()) {
+			return Ok(name);
+		}
+		n += 1;
+	}
+}
+
+/// Creates a veth pair, with one end named `name` in the namespace `ns`, and the other named
+/// `peer_name` in the namespace `peer_ns`.
+///
+/// If a name is not given, the first free name of the form `vethN` is used.
+///
+/// Errors:
+/// - [`errno::EINVAL`]: a name is invalid, or both ends have the same name in the same namespace
+/// - [`errno::EEXIST`]: an interface with the name of an end already exists in its namespace
+pub fn create(
+	ns: &NetNamespace,
+	name: Option<&[u8]>,
+	peer_ns: &NetNamespace,
+	peer_name: Option<&[u8]>,
+) -> EResult<()> {
+	let name = match name {
+		Some(name) => String::try_from(name)?,
+		None => free_name(ns, None)?,
+	};
+	let same_ns = ns.id() == peer_ns.id();
+	let peer_name = match peer_name {
+		Some(peer_name) => String::try_from(peer_name)?,
+		None => free_name(peer_ns, same_ns.then_some(name.as_bytes()))?,
+	};
+	if !is_valid_name(name.as_bytes()) || !is_valid_name(peer_name.as_bytes()) {
+		return Err(errno!(EINVAL));
+	}
+	if same_ns && name == peer_name {
+		return Err(errno!(EINVAL));
+	}
+	let (end, peer) = new_pair(ns, name, peer_ns, peer_name)?;
+	let link = end.link.clone();
+	ns.register_iface(end)?;
+	if let Err(e) = peer_ns.register_iface(peer) {
+		// The pair is down, so this does not remove the interface that has the peer's name
+		ns.unregister_iface(link.ends[0].name.as_bytes());
+		return Err(e);
+	}
+	link.up.store(true, Release);
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn veth_pair() {
+		let ns = NetNamespace::new().unwrap();
+		let peer_ns = NetNamespace::new().unwrap();
+		create(&ns, Some(b"veth0"), &peer_ns, Some(b"veth1")).unwrap();
+		assert!(create(&ns, Some(b"veth0"), &peer_ns, Some(b"veth2")).is_err());
+		assert!(peer_ns.get_iface(b"veth2").is_none());
+		let end = ns.get_iface(b"veth0").unwrap();
+		let peer = peer_ns.get_iface(b"veth1").unwrap();
+		let frame = [0xffu8; 60];
+		end.lock().write(&frame.as_slice().into()).unwrap();
+		let mut buf = [0; 64];
+		assert_eq!(peer.lock().read(&mut buf).unwrap(), 60);
+		assert!(end.lock().read(&mut buf).is_err());
+		// Deleting an end deletes the pair
+		drop(ns.unregister_iface(b"veth0"));
+		assert!(!peer.lock().is_up());
+		assert!(peer_ns.get_iface(b"veth1").is_none());
+		assert!(end.lock().write(&frame.as_slice().into()).is_err());
+		// Free names are picked for unnamed ends
+		create(&ns, None, &ns, None).unwrap();
+		assert!(ns.get_iface(b"veth0").is_some());
+		assert!(ns.get_iface(b"veth1").is_some());
+		// Destroying a namespace deletes the pairs it holds an end of
+		create(&ns, Some(b"veth2"), &peer_ns, Some(b"veth0")).unwrap();
+		drop(peer_ns);
+		assert!(ns.get_iface(b"veth2").is_none());
+		assert!(ns.get_iface(b"veth0").is_some());
+	}
+}
@@ -0,0 +1,526 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Control groups (cgroups) organize processes in a hierarchy, in order to account and limit the
+//! resources they use.
+//!
+//! Each process belongs to exactly one cgroup, inherited from its parent. The hierarchy is exposed
+//! to userspace by the `cgroup2` filesystem.
+//!
+//! Two controllers are implemented:
+//! - `cpu`: the time slice of processes is scaled by the weight of their cgroup, and the CPU time
+//!   of a cgroup can be limited to a quota over each period
+//! - `memory`: the amount of user memory allocated by the processes of a cgroup can be limited
+//!
+//! Limits apply hierarchically: the resources used by a cgroup are also charged to its ancestors.
+
+use crate::{
+	process::{Process, pid::Pid, scheduler::SCHEDULER},
+	sync::mutex::{IntMutex, Mutex},
+	time::unit::Timestamp,
+};
+use core::{
+	alloc::AllocError,
+	fmt,
+	hint::unlikely,
+	ptr,
+	sync::atomic::{
+		AtomicBool, AtomicU8, AtomicU32, AtomicUsize,
+		Ordering::{Acquire, Relaxed, Release},
+	},
+};
+use utils::{
+	collections::{hashmap::HashMap, string::String, vec::Vec},
+	errno,
+	errno::{AllocResult, CollectResult, EResult},
+	limits::PAGE_SIZE,
+	ptr::arc::Arc,
+};
+
+/// Controller bit: `cpu`.
+pub const CONTROLLER_CPU: u8 = 0b01;
+/// Controller bit: `memory`.
+pub const CONTROLLER_MEMORY: u8 = 0b10;
+/// The names of controllers, along with their bit.
+pub const CONTROLLERS: [(&str, u8); 2] = [("cpu", CONTROLLER_CPU), ("memory", CONTROLLER_MEMORY)];
+
+/// The minimum CPU weight.
+pub const CPU_WEIGHT_MIN: u32 = 1;
+/// The default CPU weight.
+pub const CPU_WEIGHT_DEFAULT: u32 = 100;
+/// The maximum CPU weight.
+pub const CPU_WEIGHT_MAX: u32 = 10000;
+/// The default period of the CPU bandwidth limit, in microseconds.
+pub const CPU_PERIOD_DEFAULT: u64 = 100_000;
+/// The minimum period or quota of the CPU bandwidth limit, in microseconds.
+pub const CPU_PERIOD_MIN: u64 = 1000;
+/// The maximum period of the CPU bandwidth limit, in microseconds.
+pub const CPU_PERIOD_MAX: u64 = 1_000_000;
+
+/// CPU bandwidth state and statistics of a cgroup.
+#[derive(Debug)]
+struct CpuState {
+	/// The CPU time the cgroup can use in each period, in microseconds. If `None`, there is no
+	/// limit.
+	quota: Option<u64>,
+	/// The length of a period, in microseconds.
+	period: u64,
+
+	/// The beginning of the current period, in nanoseconds.
+	period_start: Timestamp,
+	/// The CPU time used during the current period, in nanoseconds.
+	runtime: Timestamp,
+	/// The timestamp at which the cgroup has been throttled during the current period, if any.
+	throttled_since: Option<Timestamp>,
+
+	/// The total CPU time used, in nanoseconds.
+	usage: Timestamp,
+	/// The number of elapsed periods.
+	nr_periods: u64,
+	/// The number of periods during which the cgroup has been throttled.
+	nr_throttled: u64,
+	/// The total time the cgroup has been throttled, in nanoseconds.
+	throttled_time: Timestamp,
+}
+
+impl CpuState {
+	/// Moves to the period containing the timestamp `now`, if the current period is over.
+	fn refresh(&mut self, now: Timestamp) {
+		let period = self.period * 1000;
+		let end = self.period_start + period;
+		if now < end {
+			return;
+		}
+		if let Some(since) = self.throttled_since.take() {
+			self.throttled_time += end.saturating_sub(since);
+		}
+		let elapsed = (now - self.period_start) / period;
+		self.nr_periods += elapsed;
+		self.period_start += elapsed * period;
+		self.runtime = 0;
+	}
+}
+
+/// Statistics of the CPU controller of a cgroup.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuStat {
+	/// The total CPU time used, in microseconds.
+	pub usage_usec: u64,
+	/// The number of elapsed periods.
+	pub nr_periods: u64,
+	/// The number of periods during which the cgroup has been throttled.
+	pub nr_throttled: u64,
+	/// The total time the cgroup has been throttled, in microseconds.
+	pub throttled_usec: u64,
+}
+
+/// A control group.
+#[derive(Debug)]
+pub struct Cgroup {
+	/// The name of the cgroup. Empty for the root.
+	pub name: String,
+	/// The parent cgroup. `None` for the root.
+	pub parent: Option<Arc<Cgroup>>,
+	/// Child cgroups, by name.
+	children: Mutex<HashMap<String, Arc<Cgroup>>>,
+	/// Tells whether the cgroup has been removed.
+	dead: AtomicBool,
+	/// The set of controllers enabled for the children of the cgroup.
+	subtree_control: AtomicU8,
+
+	/// The CPU weight.
+	cpu_weight: AtomicU32,
+	/// CPU bandwidth state.
+	cpu: IntMutex<CpuState>,
+
+	/// The number of user memory pages charged to the cgroup.
+	memory_current: AtomicUsize,
+	/// The maximum number of user memory pages that can be charged to the cgroup.
+	memory_max: AtomicUsize,
+	/// The number of allocations that failed because the limit has been reached.
+	memory_events_max: AtomicUsize,
+}
+
+impl Cgroup {
+	/// Creates a new cgroup.
+	fn new(name: String, parent: Option<Arc<Cgroup>>) -> Self {
+		Self {
+			name,
+			parent,
+			children: Default::default(),
+			dead: AtomicBool::new(false),
+			subtree_control: AtomicU8::new(0),
+
+			cpu_weight: AtomicU32::new(CPU_WEIGHT_DEFAULT),
+			cpu: IntMutex::new(CpuState {
+				quota: None,
+				period: CPU_PERIOD_DEFAULT,
+
+				period_start: 0,
+				runtime: 0,
+				throttled_since: None,
+
+				usage: 0,
+				nr_periods: 0,
+				nr_throttled: 0,
+				throttled_time: 0,
+			}),
+
+			memory_current: AtomicUsize::new(0),
+			memory_max: AtomicUsize::new(usize::MAX),
+			memory_events_max: AtomicUsize::new(0),
+		}
+	}
+
+	/// Tells whether the cgroup is the root of the hierarchy.
+	#[inline]
+	pub fn is_root(&self) -> bool {
+		self.parent.is_none()
+	}
+
+	/// Returns an iterator over the cgroup and its ancestors, up to the root.
+	pub fn ancestors(&self) -> impl Iterator<Item = &Cgroup> {
+		let mut cur = Some(self);
+		core::iter::from_fn(move || {
+			let cg = cur?;
+			cur = cg.parent.as_deref();
+			Some(cg)
+		})
+	}
+
+	/// Creates a child cgroup named `name`.
+	///
+	/// If a child with the same name already exists, the function returns [`errno::EEXIST`].
+	pub fn create_child(this: &Arc<Self>, name: &[u8]) -> EResult<Arc<Self>> {
+		if unlikely(this.dead.load(Acquire)) {
+			return Err(errno!(ENOENT));
+		}
+		let mut children = this.children.lock();
+		if children.get(name).is_some() {
+			return Err(errno!(EEXIST));
+		}
+		let child = Arc::new(Self::new(String::try_from(name)?, Some(this.clone())))?;
+		children.insert(String::try_from(name)?, child.clone())?;
+		Ok(child)
+	}
+
+	/// Returns the child cgroup named `name`, if any.
+	pub fn get_child(&self, name: &[u8]) -> Option<Arc<Self>> {
+		self.children.lock().get(name).cloned()
+	}
+
+	/// Returns the list of child cgroups, in arbitrary order.
+	pub fn children(&self) -> AllocResult<Vec<Arc<Self>>> {
+		self.children
+			.lock()
+			.iter()
+			.map(|(_, c)| c.clone())
+			.collect::<CollectResult<_>>()
+			.0
+	}
+
+	/// Removes the child cgroup named `name`.
+	///
+	/// If the child has children or processes, the function returns [`errno::EBUSY`].
+	pub fn remove_child(&self, name: &[u8]) -> EResult<()> {
+		let mut children = self.children.lock();
+		let child = children.get(name).ok_or_else(|| errno!(ENOENT))?;
+		if !child.children.lock().is_empty() || child.is_populated() {
+			return Err(errno!(EBUSY));
+		}
+		child.dead.store(true, Release);
+		children.remove(name);
+		Ok(())
+	}
+
+	/// Tells whether `proc` belongs to the cgroup.
+	fn contains(&self, proc: &Process) -> bool {
+		ptr::eq(Arc::as_ptr(&proc.cgroup.lock()), self)
+	}
+
+	/// Tells whether at least one process belongs to the cgroup.
+	pub fn is_populated(&self) -> bool {
+		SCHEDULER
+			.lock()
+			.iter_process()
+			.any(|(_, proc)| self.contains(proc))
+	}
+
+	/// Returns the PIDs of the processes belonging to the cgroup, in increasing order.
+	pub fn procs(&self) -> AllocResult<Vec<Pid>> {
+		SCHEDULER
+			.lock()
+			.iter_process()
+			.filter(|(_, proc)| self.contains(proc))
+			.map(|(pid, _)| *pid)
+			.collect::<CollectResult<_>>()
+			.0
+	}
+
+	/// Moves the process `proc` to the cgroup.
+	///
+	/// If the cgroup has been removed, the function returns [`errno::ENOENT`].
+	pub fn attach(this: &Arc<Self>, proc: &Process) -> EResult<()> {
+		if unlikely(this.dead.load(Acquire)) {
+			return Err(errno!(ENOENT));
+		}
+		*proc.cgroup.lock() = this.clone();
+		Ok(())
+	}
+
+	/// Returns the set of controllers enabled for the children of the cgroup.
+	#[inline]
+	pub fn subtree_control(&self) -> u8 {
+		self.subtree_control.load(Relaxed)
+	}
+
+	/// Enables the controllers of `enable` and disables the ones of `disable` for the children of
+	/// the cgroup.
+	///
+	/// A controller can be enabled only if it is enabled in the parent.
+	pub fn set_subtree_control(&self, enable: u8, disable: u8) -> EResult<()> {
+		let available = self
+			.parent
+			.as_ref()
+			.map_or(u8::MAX, |p| p.subtree_control());
+		if unlikely(enable & !available != 0) {
+			return Err(errno!(ENOENT));
+		}
+		let _ = self
+			.subtree_control
+			.fetch_update(Relaxed, Relaxed, |c| Some((c | enable) & !disable));
+		Ok(())
+	}
+
+	/// Returns the CPU weight of the cgroup.
+	#[inline]
+	pub fn cpu_weight(&self) -> u32 {
+		self.cpu_weight.load(Relaxed)
+	}
+
+	/// Sets the CPU weight of the cgroup.
+	pub fn set_cpu_weight(&self, weight: u32) -> EResult<()> {
+		if unlikely(!(CPU_WEIGHT_MIN..=CPU_WEIGHT_MAX).contains(&weight)) {
+			return Err(errno!(ERANGE));
+		}
+		self.cpu_weight.store(weight, Relaxed);
+		Ok(())
+	}
+
+	/// Scales the time slice `slice` of a process of the cgroup according to the CPU weights of
+	/// the cgroup and its ancestors.
+	pub fn scale_timeslice(&self, slice: Timestamp) -> Timestamp {
+		self.ancestors()
+			.filter(|cg| !cg.is_root())
+			.fold(slice, |slice, cg| {
+				slice * cg.cpu_weight() as Timestamp / CPU_WEIGHT_DEFAULT as Timestamp
+			})
+			.max(1_000_000)
+	}
+
+	/// Returns the CPU bandwidth limit of the cgroup, in microseconds: the quota (`None` if
+	/// unlimited) and the period.
+	pub fn cpu_max(&self) -> (Option<u64>, u64) {
+		let cpu = self.cpu.lock();
+		(cpu.quota, cpu.period)
+	}
+
+	/// Sets the CPU bandwidth limit of the cgroup, in microseconds.
+	///
+	/// If `period` is `None`, it is left unchanged.
+	pub fn set_cpu_max(&self, quota: Option<u64>, period: Option<u64>) -> EResult<()> {
+		let valid_quota = quota.is_none_or(|q| q >= CPU_PERIOD_MIN);
+		let valid_period = period.is_none_or(|p| (CPU_PERIOD_MIN..=CPU_PERIOD_MAX).contains(&p));
+		if unlikely(!valid_quota || !valid_period) {
+			return Err(errno!(EINVAL));
+		}
+		let mut cpu = self.cpu.lock();
+		cpu.quota = quota;
+		if let Some(period) = period {
+			cpu.period = period;
+		}
+		Ok(())
+	}
+
+	/// Returns the CPU statistics of the cgroup.
+	pub fn cpu_stat(&self) -> CpuStat {
+		let cpu = self.cpu.lock();
+		CpuStat {
+			usage_usec: cpu.usage / 1000,
+			nr_periods: cpu.nr_periods,
+			nr_throttled: cpu.nr_throttled,
+			throttled_usec: cpu.throttled_time / 1000,
+		}
+	}
+
+	/// Charges `delta` nanoseconds of CPU time to the cgroup and its ancestors, `now` being the
+	/// current timestamp.
+	pub fn account_cpu(&self, delta: Timestamp, now: Timestamp) {
+		for cg in self.ancestors() {
+			let mut cpu = cg.cpu.lock();
+			cpu.refresh(now);
+			cpu.usage += delta;
+			cpu.runtime += delta;
+		}
+	}
+
+	/// Tells whether the processes of the cgroup must not run until the next period, because the
+	/// cgroup or one of its ancestors has exhausted its CPU quota.
+	pub fn cpu_throttled(&self, now: Timestamp) -> bool {
+		let mut throttled = false;
+		for cg in self.ancestors() {
+			let mut cpu = cg.cpu.lock();
+			cpu.refresh(now);
+			let Some(quota) = cpu.quota else {
+				continue;
+			};
+			if cpu.runtime < quota * 1000 {
+				continue;
+			}
+			if cpu.throttled_since.is_none() {
+				cpu.throttled_since = Some(now);
+				cpu.nr_throttled += 1;
+			}
+			throttled = true;
+		}
+		throttled
+	}
+
+	/// Returns the amount of user memory charged to the cgroup, in bytes.
+	pub fn memory_current(&self) -> usize {
+		self.memory_current.load(Relaxed) * PAGE_SIZE
+	}
+
+	/// Returns the memory limit of the cgroup in bytes. If `None`, there is no limit.
+	pub fn memory_max(&self) -> Option<usize> {
+		let max = self.memory_max.load(Relaxed);
+		(max != usize::MAX).then(|| max * PAGE_SIZE)
+	}
+
+	/// Sets the memory limit of the cgroup in bytes, rounded down to the page size.
+	///
+	/// If `None`, the limit is removed.
+	pub fn set_memory_max(&self, max: Option<usize>) {
+		let pages = max.map(|max| max / PAGE_SIZE).unwrap_or(usize::MAX);
+		self.memory_max.store(pages, Relaxed);
+	}
+
+	/// Returns the number of allocations that failed because the memory limit of the cgroup has
+	/// been reached.
+	pub fn memory_events_max(&self) -> usize {
+		self.memory_events_max.load(Relaxed)
+	}
+
+	/// Charges `pages` pages of user memory to the cgroup and its ancestors.
+	///
+	/// If the charge would exceed the memory limit of one of them, nothing is charged and the
+	/// function returns an error.
+	pub fn charge_memory(&self, pages: usize) -> AllocResult<()> {
+		for cg in self.ancestors() {
+			let current = cg.memory_current.fetch_add(pages, Relaxed) + pages;
+			if unlikely(current > cg.memory_max.load(Relaxed)) {
+				cg.memory_events_max.fetch_add(1, Relaxed);
+				// Rollback
+				for c in self.ancestors() {
+					c.memory_current.fetch_sub(pages, Relaxed);
+					if ptr::eq(c, cg) {
+						break;
+					}
+				}
+				return Err(AllocError);
+			}
+		}
+		Ok(())
+	}
+
+	/// Uncharges `pages` pages of user memory from the cgroup and its ancestors.
+	pub fn uncharge_memory(&self, pages: usize) {
+		for cg in self.ancestors() {
+			cg.memory_current.fetch_sub(pages, Relaxed);
+		}
+	}
+}
+
+impl fmt::Display for Cgroup {
+	/// Writes the path of the cgroup, relative to the root of the hierarchy.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.parent {
+			None => write!(f, "/"),
+			Some(parent) if parent.is_root() => write!(f, "/{}", self.name),
+			Some(parent) => write!(f, "{parent}/{}", self.name),
+		}
+	}
+}
+
+/// The root cgroup, created on first use.
+static ROOT: Mutex<Option<Arc<Cgroup>>> = Mutex::new(None);
+
+/// Returns the root cgroup, which every process belongs to unless moved.
+pub fn root() -> AllocResult<Arc<Cgroup>> {
+	let mut root = ROOT.lock();
+	if let Some(root) = &*root {
+		return Ok(root.clone());
+	}
+	let cg = Arc::new(Cgroup::new(String::new(), None))?;
+	*root = Some(cg.clone());
+	Ok(cg)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use utils::format;
+
+	#[test_case]
+	fn cgroup_memory_charge() {
+		let root = Arc::new(Cgroup::new(String::new(), None)).unwrap();
+		let parent = Cgroup::create_child(&root, b"parent").unwrap();
+		let child = Cgroup::create_child(&parent, b"child").unwrap();
+		assert!(Cgroup::create_child(&parent, b"child").is_err());
+		parent.set_memory_max(Some(4 * PAGE_SIZE));
+		child.charge_memory(3).unwrap();
+		assert_eq!(parent.memory_current(), 3 * PAGE_SIZE);
+		assert_eq!(root.memory_current(), 3 * PAGE_SIZE);
+		// The limit of an ancestor applies, and a failed charge is rolled back
+		assert!(child.charge_memory(2).is_err());
+		assert_eq!(child.memory_current(), 3 * PAGE_SIZE);
+		assert_eq!(parent.memory_events_max(), 1);
+		child.uncharge_memory(3);
+		assert_eq!(root.memory_current(), 0);
+		assert_eq!(format!("{child}").unwrap().as_bytes(), b"/parent/child");
+		assert!(parent.remove_child(b"child").is_ok());
+	}
+
+	#[test_case]
+	fn cgroup_cpu_quota() {
+		let root = Arc::new(Cgroup::new(String::new(), None)).unwrap();
+		let cg = Cgroup::create_child(&root, b"cg").unwrap();
+		cg.set_cpu_max(Some(10_000), Some(100_000)).unwrap();
+		cg.account_cpu(5_000_000, 1_000_000);
+		assert!(!cg.cpu_throttled(6_000_000));
+		cg.account_cpu(5_000_000, 6_000_000);
+		assert!(cg.cpu_throttled(11_000_000));
+		// The quota is replenished at the next period
+		assert!(!cg.cpu_throttled(100_000_000));
+		let stat = cg.cpu_stat();
+		assert_eq!(stat.usage_usec, 10_000);
+		assert_eq!(stat.nr_throttled, 1);
+		assert_eq!(stat.nr_periods, 1);
+		assert_eq!(root.cpu_stat().usage_usec, 10_000);
+	}
+}
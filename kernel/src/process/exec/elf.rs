@@ -21,30 +21,61 @@
 use super::vdso;
 use crate::{
 	arch::x86,
+	crypto::rand,
 	elf,
 	elf::{
 		ET_DYN,
 		parser::{Class, ELFParser, ProgramHeader},
 	},
-	file::{File, FileType, O_RDONLY, vfs},
-	memory::{VirtAddr, vmem},
+	file::{
+		File, FileType, O_RDONLY,
+		perm::{AccessProfile, Gid, Uid},
+		vfs,
+		vfs::ResolutionSettings,
+	},
+	memory::{VirtAddr, user::UserSlice, vmem},
 	process,
 	process::{
-		exec::{ExecInfo, Executor, ProgramImage, vdso::MappedVDSO},
+		exec,
+		exec::{ExecInfo, Executor, Loaded, ProgramImage, vdso::MappedVDSO},
 		mem_space,
-		mem_space::{MAP_ANONYMOUS, MAP_PRIVATE, MapConstraint, MemSpace, PROT_READ, PROT_WRITE},
+		mem_space::{
+			MAP_ANONYMOUS, MAP_GROWSDOWN, MAP_PRIVATE, MapConstraint, MemSpace, PROT_EXEC,
+			PROT_READ, PROT_WRITE,
+		},
+		rusage::USER_HZ,
 	},
 };
 use core::{cmp::max, hint::unlikely, num::NonZeroUsize, ptr, slice};
 use utils::{
-	collections::{string::String, vec::Vec},
+	collections::{path::Path, string::String, vec::Vec},
 	errno,
 	errno::{AllocResult, EResult},
-	limits::PAGE_SIZE,
+	limits::{PAGE_SIZE, PATH_MAX},
 	ptr::arc::Arc,
 	vec,
 };
 
+/// The maximum size of the file header and program headers table of an executable.
+const MAX_HEADERS_LEN: usize = 65536;
+
+/// The lowest base address of position-independent executables.
+#[cfg(target_arch = "x86_64")]
+const ET_DYN_BASE: usize = 0x555555554000;
+/// The number of random bits in the page number of the base address of position-independent
+/// executables.
+#[cfg(target_arch = "x86_64")]
+const ET_DYN_RND_BITS: u32 = 28;
+/// The lowest base address of position-independent executables, in compatibility mode.
+const COMPAT_ET_DYN_BASE: usize = 0x400000;
+/// The number of random bits in the page number of the base address of position-independent
+/// executables, in compatibility mode.
+const COMPAT_ET_DYN_RND_BITS: u32 = 16;
+
+/// Relocation type: adjust by the load base.
+#[cfg(target_arch = "x86_64")]
+const R_X86_64_RELATIVE: u32 = 8;
+
 /// Used to define the end of the entries list.
 const AT_NULL: i32 = 0;
 /// Entry with no meaning, to be ignored.
@@ -103,6 +134,8 @@ const AT_SYSINFO_EHDR: i32 = 33;
 /// initialization.
 #[derive(Debug)]
 struct ELFLoadInfo {
+	/// The base address at which the ELF is loaded
+	load_base: VirtAddr,
 	/// The pointer to the end of loaded segments
 	load_end: *mut u8,
 
@@ -118,34 +151,41 @@ struct ELFLoadInfo {
 }
 
 /// Enumeration of possible values for an auxiliary vector entry.
-enum AuxEntryDescValue {
+enum AuxEntryDescValue<'s> {
 	/// A single number.
 	Number(usize),
 	/// A string of bytes.
-	String(&'static [u8]),
+	String(&'s [u8]),
 }
 
 /// An auxiliary vector entry.
-struct AuxEntryDesc {
+struct AuxEntryDesc<'s> {
 	/// The entry's type.
 	pub a_type: i32,
 	/// The entry's value.
-	pub a_val: AuxEntryDescValue,
+	pub a_val: AuxEntryDescValue<'s>,
 }
 
 /// Builds an auxiliary vector.
 ///
 /// Arguments:
 /// - `exec_info` is the set of execution information.
-/// - `load_base` is the base address at which the ELF is loaded.
 /// - `load_info` is the set of ELF load information.
+/// - `interp` is the set of load information of the interpreter, if any.
 /// - `vdso` is the set of vDSO information.
-fn build_auxiliary(
-	exec_info: &ExecInfo,
-	load_base: *mut u8,
+/// - `ids` is the effective user ID and group ID of the process after execution.
+/// - `random` is the set of random bytes to pass to the program.
+fn build_auxiliary<'s>(
+	exec_info: &ExecInfo<'s>,
 	load_info: &ELFLoadInfo,
+	interp: Option<&ELFLoadInfo>,
 	vdso: &MappedVDSO,
-) -> AllocResult<Vec<AuxEntryDesc>> {
+	(euid, egid): (Uid, Gid),
+	random: &'s [u8; 16],
+) -> AllocResult<Vec<AuxEntryDesc<'s>>> {
+	let ap = &exec_info.path_resolution.access_profile;
+	// The program runs in secure mode if it gains privileges
+	let secure = euid != ap.uid || egid != ap.gid;
 	let mut vec = vec![
 		AuxEntryDesc {
 			a_type: AT_PHDR,
@@ -165,7 +205,11 @@ fn build_auxiliary(
 		},
 		AuxEntryDesc {
 			a_type: AT_BASE,
-			a_val: AuxEntryDescValue::Number(load_base as _),
+			a_val: AuxEntryDescValue::Number(interp.map(|i| i.load_base.0).unwrap_or(0)),
+		},
+		AuxEntryDesc {
+			a_type: AT_ENTRY,
+			a_val: AuxEntryDescValue::Number(load_info.entry_point.0),
 		},
 		AuxEntryDesc {
 			a_type: AT_NOTELF,
@@ -173,19 +217,19 @@ fn build_auxiliary(
 		},
 		AuxEntryDesc {
 			a_type: AT_UID,
-			a_val: AuxEntryDescValue::Number(exec_info.path_resolution.access_profile.uid as _),
+			a_val: AuxEntryDescValue::Number(ap.uid as _),
 		},
 		AuxEntryDesc {
 			a_type: AT_EUID,
-			a_val: AuxEntryDescValue::Number(exec_info.path_resolution.access_profile.euid as _),
+			a_val: AuxEntryDescValue::Number(euid as _),
 		},
 		AuxEntryDesc {
 			a_type: AT_GID,
-			a_val: AuxEntryDescValue::Number(exec_info.path_resolution.access_profile.gid as _),
+			a_val: AuxEntryDescValue::Number(ap.gid as _),
 		},
 		AuxEntryDesc {
 			a_type: AT_EGID,
-			a_val: AuxEntryDescValue::Number(exec_info.path_resolution.access_profile.egid as _),
+			a_val: AuxEntryDescValue::Number(egid as _),
 		},
 		AuxEntryDesc {
 			a_type: AT_PLATFORM,
@@ -195,9 +239,13 @@ fn build_auxiliary(
 			a_type: AT_HWCAP,
 			a_val: AuxEntryDescValue::Number(x86::get_hwcap() as _),
 		},
+		AuxEntryDesc {
+			a_type: AT_CLKTCK,
+			a_val: AuxEntryDescValue::Number(USER_HZ as _),
+		},
 		AuxEntryDesc {
 			a_type: AT_SECURE,
-			a_val: AuxEntryDescValue::Number(0), // TODO
+			a_val: AuxEntryDescValue::Number(secure as _),
 		},
 		AuxEntryDesc {
 			a_type: AT_BASE_PLATFORM,
@@ -205,11 +253,11 @@ fn build_auxiliary(
 		},
 		AuxEntryDesc {
 			a_type: AT_RANDOM,
-			a_val: AuxEntryDescValue::String(&[0; 16]), // TODO
+			a_val: AuxEntryDescValue::String(random),
 		},
 		AuxEntryDesc {
 			a_type: AT_EXECFN,
-			a_val: AuxEntryDescValue::String(b"TODO\0"), // TODO
+			a_val: AuxEntryDescValue::String(exec_info.path),
 		},
 		AuxEntryDesc {
 			a_type: AT_SYSINFO_EHDR,
@@ -230,6 +278,135 @@ fn build_auxiliary(
 	Ok(vec)
 }
 
+/// Reads at most `len` bytes at offset `off` in `file`.
+fn read_at(file: &File, off: u64, len: usize) -> EResult<Vec<u8>> {
+	let mut buf = vec![0u8; len]?;
+	let mut i = 0;
+	while i < len {
+		let slice = UserSlice::from_slice_mut(&mut buf[i..]);
+		let l = file.ops.read(file, off + i as u64, slice)?;
+		// Reached EOF
+		if l == 0 {
+			break;
+		}
+		i += l;
+	}
+	buf.truncate(i);
+	Ok(buf)
+}
+
+/// Reads the file header and program headers table of the executable `file`.
+fn read_headers(file: &File) -> EResult<Vec<u8>> {
+	let hdr = read_at(file, 0, PAGE_SIZE)?;
+	let len = ELFParser::headers_len(&hdr)?;
+	if unlikely(len > MAX_HEADERS_LEN) {
+		return Err(errno!(EINVAL));
+	}
+	if len > hdr.len() {
+		read_at(file, 0, len)
+	} else {
+		Ok(hdr)
+	}
+}
+
+/// Reads the path to the interpreter of the executable `file`, parsed by `elf`.
+///
+/// If the executable does not require an interpreter, the function returns `None`.
+fn read_interp_path(file: &File, elf: &ELFParser) -> EResult<Option<Vec<u8>>> {
+	let Some(seg) = elf.iter_segments().find(|seg| seg.p_type == elf::PT_INTERP) else {
+		return Ok(None);
+	};
+	// The path must be non-empty and end with a nul byte
+	if unlikely(!(2..=PATH_MAX as u64).contains(&seg.p_filesz)) {
+		return Err(errno!(ENOEXEC));
+	}
+	let mut path = read_at(file, seg.p_offset, seg.p_filesz as _)?;
+	if unlikely(path.len() as u64 != seg.p_filesz || path.pop() != Some(b'\0')) {
+		return Err(errno!(ENOEXEC));
+	}
+	Ok(Some(path))
+}
+
+/// Returns a randomized base address at which a position-independent executable is loaded.
+///
+/// `compat` indicates whether userspace runs in compatibility mode.
+fn dyn_base(compat: bool) -> usize {
+	#[cfg(target_arch = "x86_64")]
+	let (base, bits) = if compat {
+		(COMPAT_ET_DYN_BASE, COMPAT_ET_DYN_RND_BITS)
+	} else {
+		(ET_DYN_BASE, ET_DYN_RND_BITS)
+	};
+	#[cfg(not(target_arch = "x86_64"))]
+	let (base, bits) = {
+		let _ = compat;
+		(COMPAT_ET_DYN_BASE, COMPAT_ET_DYN_RND_BITS)
+	};
+	base + mem_space::random_pages(bits, 1) * PAGE_SIZE
+}
+
+/// Applies the relative relocations of the position-independent executable parsed by `elf`,
+/// loaded at `load_base` in the current memory space.
+///
+/// Relocations are found through the `PT_DYNAMIC` segment, and other types of relocations are
+/// left to the program. Since relative relocations with an addend only depend on the load base,
+/// static PIE programs may still apply them from their startup code.
+///
+/// 32 bit programs use relocations without an addend, which their startup code expects to find
+/// unapplied, so they are not handled here.
+#[cfg(target_arch = "x86_64")]
+fn relocate(elf: &ELFParser, load_base: *mut u8) -> EResult<()> {
+	use crate::{
+		elf::{ELF64Dyn, ELF64Rela},
+		memory::user::UserPtr,
+	};
+	use core::{fmt, ptr::NonNull};
+	/// Reads a value of type `T` at `addr` in the current memory space.
+	fn read_user<T: fmt::Debug>(addr: *mut u8) -> EResult<T> {
+		UserPtr(NonNull::new(addr.cast()))
+			.copy_from_user()?
+			.ok_or_else(|| errno!(EFAULT))
+	}
+	let Some(dynamic) = elf
+		.iter_segments()
+		.find(|seg| seg.p_type == elf::PT_DYNAMIC)
+	else {
+		return Ok(());
+	};
+	// Find the relocation table
+	let mut rela = None;
+	let mut rela_sz = 0;
+	let mut rela_ent = size_of::<ELF64Rela>() as u64;
+	let dyn_addr = load_base.wrapping_add(dynamic.p_vaddr as usize);
+	for i in 0..(dynamic.p_memsz as usize / size_of::<ELF64Dyn>()) {
+		let ent: ELF64Dyn = read_user(dyn_addr.wrapping_add(i * size_of::<ELF64Dyn>()))?;
+		match ent.d_tag {
+			elf::DT_NULL => break,
+			elf::DT_RELA => rela = Some(ent.d_val),
+			elf::DT_RELASZ => rela_sz = ent.d_val,
+			elf::DT_RELAENT => rela_ent = ent.d_val,
+			_ => {}
+		}
+	}
+	let Some(rela) = rela else {
+		return Ok(());
+	};
+	if unlikely(rela_ent != size_of::<ELF64Rela>() as u64) {
+		return Err(errno!(EINVAL));
+	}
+	let rela_addr = load_base.wrapping_add(rela as usize);
+	for i in 0..(rela_sz / rela_ent) as usize {
+		let rel: ELF64Rela = read_user(rela_addr.wrapping_add(i * size_of::<ELF64Rela>()))?;
+		if rel.r_info as u32 != R_X86_64_RELATIVE {
+			continue;
+		}
+		let val = (load_base as u64).wrapping_add_signed(rel.r_addend);
+		let addr = load_base.wrapping_add(rel.r_offset as usize);
+		UserPtr(NonNull::new(addr.cast::<u64>())).copy_to_user(&val)?;
+	}
+	Ok(())
+}
+
 /// Maps the segment `seg` in memory.
 ///
 /// If the segment is not loadable, the function does nothing.
@@ -240,6 +417,9 @@ fn build_auxiliary(
 /// - `load_base` is the base address at which the executable is loaded
 /// - `seg` is the segment for which the memory is allocated
 ///
+/// Pages containing data from the file are mapped from it, and loaded on first access. The
+/// remaining pages of the segment are mapped anonymously.
+///
 /// If loaded, the function return the pointer to the end of the segment in
 /// virtual memory.
 fn map_segment(
@@ -259,10 +439,10 @@ fn map_segment(
 	let addr = load_base.wrapping_add(page_start);
 	let size = seg.p_memsz as usize + page_off;
 	let off = seg.p_offset - page_off as u64;
-	if let Some(pages) = NonZeroUsize::new(size.div_ceil(PAGE_SIZE)) {
-		let addr = VirtAddr::from(addr);
+	let file_pages = (seg.p_filesz as usize + page_off).div_ceil(PAGE_SIZE);
+	if let Some(pages) = NonZeroUsize::new(file_pages) {
 		mem_space.map(
-			MapConstraint::Fixed(addr),
+			MapConstraint::Fixed(VirtAddr::from(addr)),
 			pages,
 			seg.mmap_prot(),
 			MAP_PRIVATE,
@@ -270,6 +450,17 @@ fn map_segment(
 			off,
 		)?;
 	}
+	if let Some(pages) = NonZeroUsize::new(size.div_ceil(PAGE_SIZE) - file_pages) {
+		let addr = VirtAddr::from(addr) + file_pages * PAGE_SIZE;
+		mem_space.map(
+			MapConstraint::Fixed(addr),
+			pages,
+			seg.mmap_prot(),
+			MAP_PRIVATE | MAP_ANONYMOUS,
+			None,
+			0,
+		)?;
+	}
 	// The pointer to the end of the virtual memory chunk
 	let mem_end = addr.wrapping_add(size);
 	Ok(Some(mem_end))
@@ -282,13 +473,17 @@ fn map_segment(
 /// - `elf` is the ELF image
 /// - `mem_space` is the memory space
 /// - `load_base` is the base address at which the ELF is loaded
+/// - `ap` is the access profile of the process
 fn load_elf(
 	file: &Arc<File>,
 	elf: &ELFParser,
 	mem_space: &Arc<MemSpace>,
 	load_base: *mut u8,
+	ap: &AccessProfile,
 ) -> EResult<ELFLoadInfo> {
 	let ehdr = elf.hdr();
+	#[cfg(target_arch = "x86_64")]
+	let dynamically_linked = elf.iter_segments().any(|seg| seg.p_type == elf::PT_INTERP);
 	let mut load_end = load_base;
 	let mut phdr_addr = 0;
 	unsafe {
@@ -307,26 +502,52 @@ fn load_elf(
 						load_base as usize + (ehdr.e_phoff - seg.p_offset + seg.p_vaddr) as usize;
 				}
 			}
-			// Zero the end of segments when needed
+			// Zero the end of the last page mapped from the file for each segment. Following
+			// pages are anonymous, thus already zeroed
 			vmem::write_ro(|| {
 				vmem::smap_disable(|| {
 					for seg in elf.iter_segments() {
 						if seg.p_type != elf::PT_LOAD {
 							continue;
 						}
-						if let Some(len) = seg.p_memsz.checked_sub(seg.p_filesz) {
-							let begin =
-								load_base.add(seg.p_vaddr as usize + seg.p_filesz as usize);
-							let slice = slice::from_raw_parts_mut(begin, len as usize);
+						let begin = seg.p_vaddr as usize + seg.p_filesz as usize;
+						let end = (seg.p_vaddr as usize + seg.p_memsz as usize)
+							.min(begin.next_multiple_of(PAGE_SIZE));
+						if let Some(len) = end.checked_sub(begin) {
+							let slice = slice::from_raw_parts_mut(load_base.add(begin), len);
 							slice.fill(0);
 						}
 					}
 				});
 			});
+			// Dynamically linked programs are relocated by their interpreter
+			#[cfg(target_arch = "x86_64")]
+			if ehdr.e_type == ET_DYN && elf.class() == Class::Bit64 && !dynamically_linked {
+				relocate(elf, load_base)?;
+			}
 			Ok(())
 		})?;
 	}
+	// Make the relocated data read-only. Programs with a dynamic section relocate themselves at
+	// startup, so they protect it by themselves afterwards
+	let dynamic = elf.iter_segments().any(|seg| seg.p_type == elf::PT_DYNAMIC);
+	let relro = elf
+		.iter_segments()
+		.find(|seg| seg.p_type == elf::PT_GNU_RELRO);
+	if let Some(relro) = relro
+		&& !dynamic
+	{
+		let begin = load_base.wrapping_add(relro.p_vaddr as usize);
+		let end = begin.wrapping_add(relro.p_memsz as usize);
+		// Pages partially in the range are left writable
+		let begin = begin.wrapping_sub(begin as usize % PAGE_SIZE);
+		let end = end.wrapping_sub(end as usize % PAGE_SIZE);
+		if end > begin {
+			mem_space.set_prot(begin.cast(), end as usize - begin as usize, PROT_READ, ap)?;
+		}
+	}
 	Ok(ELFLoadInfo {
+		load_base: VirtAddr::from(load_base),
 		load_end,
 
 		phdr: VirtAddr(phdr_addr),
@@ -337,6 +558,57 @@ fn load_elf(
 	})
 }
 
+/// Loads the interpreter at `path` into the memory space `mem_space`.
+///
+/// Arguments:
+/// - `path` is the path to the interpreter
+/// - `rs` is the path resolution settings
+/// - `mem_space` is the memory space
+/// - `class` is the class of the program, which the interpreter must match
+fn load_interp(
+	path: &Path,
+	rs: &ResolutionSettings,
+	mem_space: &Arc<MemSpace>,
+	class: Class,
+) -> EResult<ELFLoadInfo> {
+	let ent = vfs::get_file_from_path(path, rs)?;
+	let stat = ent.stat();
+	if unlikely(stat.get_type() != Some(FileType::Regular)) {
+		return Err(errno!(EACCES));
+	}
+	if unlikely(!rs.access_profile.can_execute_file(&stat)) {
+		return Err(errno!(EACCES));
+	}
+	let file = File::open_entry(ent, O_RDONLY)?;
+	let hdr = read_headers(&file)?;
+	let parser = ELFParser::new_headers(&hdr, stat.size)?;
+	if unlikely(parser.class() != class) {
+		return Err(errno!(ELIBBAD));
+	}
+	// Reserve a range large enough for all the segments, which are then mapped over it
+	let load_base = if parser.hdr().e_type == ET_DYN {
+		let end = parser
+			.iter_segments()
+			.filter(|seg| seg.p_type == elf::PT_LOAD)
+			.map(|seg| seg.p_vaddr.saturating_add(seg.p_memsz))
+			.max()
+			.unwrap_or(0);
+		let end = usize::try_from(end).map_err(|_| errno!(ENOMEM))?;
+		let pages = NonZeroUsize::new(end.div_ceil(PAGE_SIZE)).ok_or_else(|| errno!(ELIBBAD))?;
+		mem_space.map(
+			MapConstraint::None,
+			pages,
+			0,
+			MAP_PRIVATE | MAP_ANONYMOUS,
+			None,
+			0,
+		)?
+	} else {
+		ptr::null_mut()
+	};
+	load_elf(&file, &parser, mem_space, load_base, &rs.access_profile)
+}
+
 /// Computes the size of the initial data on the stack.
 ///
 /// `compat` indicates whether userspace runs in compatibility mode.
@@ -465,63 +737,98 @@ unsafe fn init_stack(
 }
 
 /// The program executor for ELF files.
-pub struct ELFExecutor<'s>(pub ExecInfo<'s>);
+pub struct ELFExecutor;
+
+impl Executor for ELFExecutor {
+	fn get_name(&self) -> &[u8] {
+		b"elf"
+	}
+
+	fn detect(&self, hdr: &[u8]) -> bool {
+		hdr.starts_with(b"\x7fELF")
+	}
 
-impl Executor for ELFExecutor<'_> {
-	// TODO Handle suid and sgid
-	fn build_image(&self, ent: Arc<vfs::Entry>) -> EResult<ProgramImage> {
+	fn load(&self, ent: Arc<vfs::Entry>, _hdr: &[u8], info: &mut ExecInfo) -> EResult<Loaded> {
 		// Check that the file can be executed by the user
 		let stat = ent.stat();
 		if unlikely(stat.get_type() != Some(FileType::Regular)) {
 			return Err(errno!(EACCES));
 		}
-		if unlikely(
-			!self
-				.0
-				.path_resolution
-				.access_profile
-				.can_execute_file(&stat),
-		) {
+		if unlikely(!info.path_resolution.access_profile.can_execute_file(&stat)) {
 			return Err(errno!(EACCES));
 		}
 		// Open file
 		let file = File::open_entry(ent.clone(), O_RDONLY)?;
-		// Read and parse file
-		let image = file.read_all()?;
-		let parser = ELFParser::new(&image)?;
+		// Read and parse headers. Segments are read from the page cache when accessed
+		let hdr = read_headers(&file)?;
+		let parser = ELFParser::new_headers(&hdr, stat.size)?;
 		let compat = parser.class() == Class::Bit32;
 		// Initialize memory space
 		let mut mem_space = MemSpace::new(ent)?;
+		// Cannot fail since no one else holds a reference
+		Arc::as_mut(&mut mem_space)
+			.unwrap()
+			.randomize_mmap_base(compat);
 		let load_base = if parser.hdr().e_type == ET_DYN {
-			// TODO ASLR
-			PAGE_SIZE
+			dyn_base(compat)
 		} else {
 			0
 		};
 		let load_base = VirtAddr(load_base).as_ptr();
-		let load_info = load_elf(&file, &parser, &mem_space, load_base)?;
+		let ap = &info.path_resolution.access_profile;
+		let load_info = load_elf(&file, &parser, &mem_space, load_base, ap)?;
+		// Load the interpreter of dynamically linked programs
+		let interp = read_interp_path(&file, &parser)?
+			.map(|path| {
+				load_interp(
+					Path::new(&path)?,
+					info.path_resolution,
+					&mem_space,
+					parser.class(),
+				)
+			})
+			.transpose()?;
+		let vdso = vdso::map(&mem_space, compat)?;
+		let ids = exec::exec_ids(
+			&mem_space.exe_info.exe,
+			&info.path_resolution.access_profile,
+		);
+		let mut random = [0u8; 16];
+		let _ = rand::getrandom(UserSlice::from_slice_mut(&mut random), 0);
+		let aux = build_auxiliary(info, &load_info, interp.as_ref(), &vdso, ids, &random)?;
+		let (_, init_stack_size) = get_init_stack_size(&info.argv, &info.envp, &aux, compat);
+		// Map the stack at the top of the memory space, so that it has room to grow downward.
+		// The initial mapping must at least fit the initial data
+		let stack_size = process::USER_STACK_SIZE.max(init_stack_size.div_ceil(PAGE_SIZE) + 1);
+		let stack_begin = mem_space::random_stack_top(compat) - stack_size * PAGE_SIZE;
+		// The stack is not executable, unless the program requires it
+		let exec_stack = parser
+			.iter_segments()
+			.any(|seg| seg.p_type == elf::PT_GNU_STACK && seg.p_flags & elf::PF_X != 0);
+		let mut stack_prot = PROT_READ | PROT_WRITE;
+		if exec_stack {
+			stack_prot |= PROT_EXEC;
+		}
 		let user_stack = mem_space
 			.map(
-				MapConstraint::None,
-				process::USER_STACK_SIZE.try_into().unwrap(),
-				PROT_READ | PROT_WRITE,
-				MAP_PRIVATE | MAP_ANONYMOUS,
+				MapConstraint::Hint(stack_begin),
+				stack_size.try_into().unwrap(),
+				stack_prot,
+				MAP_PRIVATE | MAP_ANONYMOUS | MAP_GROWSDOWN,
 				None,
 				0,
 			)?
-			.wrapping_add(process::USER_STACK_SIZE * PAGE_SIZE);
-		let vdso = vdso::map(&mem_space, compat)?;
+			.wrapping_add(stack_size * PAGE_SIZE);
 		// Initialize the userspace stack
-		let aux = build_auxiliary(&self.0, load_base, &load_info, &vdso)?;
-		let (_, init_stack_size) = get_init_stack_size(&self.0.argv, &self.0.envp, &aux, compat);
 		let mut exe_info = mem_space.exe_info.clone();
+		exe_info.sigreturn = vdso.sigreturn.unwrap_or_default();
 		unsafe {
 			MemSpace::switch(&mem_space, |_| {
 				vmem::smap_disable(|| -> EResult<()> {
 					init_stack(
 						user_stack,
-						&self.0.argv,
-						&self.0.envp,
+						&info.argv,
+						&info.envp,
 						&aux,
 						&mut exe_info,
 						compat,
@@ -533,13 +840,16 @@ impl Executor for ELFExecutor<'_> {
 		// Set immutable fields
 		let m = Arc::as_mut(&mut mem_space).unwrap(); // Cannot fail since no one else hold a reference
 		m.exe_info = exe_info;
-		m.set_brk_init(VirtAddr::from(load_info.load_end).align_to(PAGE_SIZE));
-		Ok(ProgramImage {
+		m.set_brk_init(mem_space::random_brk(
+			VirtAddr::from(load_info.load_end).align_to(PAGE_SIZE),
+		));
+		Ok(Loaded::Image(ProgramImage {
 			mem_space,
 			compat,
 
-			entry_point: load_info.entry_point,
+			// Execution starts in the interpreter, which then jumps to the program
+			entry_point: interp.unwrap_or(load_info).entry_point,
 			user_stack: VirtAddr::from(user_stack) - init_stack_size,
-		})
+		}))
 	}
 }
@@ -0,0 +1,232 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Executors registered by userspace, in the manner of Linux's `binfmt_misc`.
+//!
+//! A format is described by a string of the form `:name:type:offset:magic:mask:interpreter:flags`,
+//! where `:` can be replaced by any separator, given by the first character. Programs whose file
+//! contains `magic` at `offset`, after applying `mask`, are run by `interpreter`.
+//!
+//! Only the `M` type (matching by magic bytes) is supported. Supported flags are:
+//! - `P`: the original first argument is preserved, instead of being replaced by the path of the
+//!   program
+//! - `F`: the interpreter is opened when the format is registered, instead of when programs are
+//!   executed
+
+use crate::{
+	file::{vfs, vfs::ResolutionSettings},
+	process::{
+		exec,
+		exec::{ExecInfo, Executor, HEADER_MAX, Loaded},
+	},
+};
+use core::str;
+use utils::{
+	collections::{path::PathBuf, string::String, vec::Vec},
+	errno,
+	errno::EResult,
+	ptr::arc::Arc,
+};
+
+/// Decodes the `\xHH` and `\\` escape sequences in `s`.
+fn unescape(s: &[u8]) -> EResult<Vec<u8>> {
+	let mut out = Vec::new();
+	let mut i = 0;
+	while i < s.len() {
+		let b = match s[i..] {
+			[b'\\', b'\\', ..] => {
+				i += 2;
+				b'\\'
+			}
+			[b'\\', b'x', h, l, ..] => {
+				i += 4;
+				let digit = |c: u8| (c as char).to_digit(16).ok_or_else(|| errno!(EINVAL));
+				(digit(h)? * 16 + digit(l)?) as u8
+			}
+			[b'\\', ..] => return Err(errno!(EINVAL)),
+			[b, ..] => {
+				i += 1;
+				b
+			}
+			[] => unreachable!(),
+		};
+		out.push(b)?;
+	}
+	Ok(out)
+}
+
+/// An executor for a format registered by userspace.
+pub struct MiscExecutor {
+	/// The name of the format.
+	name: String,
+	/// The offset of the magic bytes in the file.
+	offset: usize,
+	/// The magic bytes identifying the format.
+	magic: Vec<u8>,
+	/// The mask applied to the file's bytes before comparing them with the magic bytes.
+	mask: Option<Vec<u8>>,
+	/// The path to the interpreter.
+	interp: PathBuf,
+	/// The interpreter, if opened at registration.
+	interp_file: Option<Arc<vfs::Entry>>,
+	/// Tells whether the original first argument is preserved.
+	preserve_argv0: bool,
+}
+
+impl MiscExecutor {
+	/// Parses the format description `desc`.
+	///
+	/// `rs` is the resolution settings used to open the interpreter if the `F` flag is set.
+	pub fn parse(desc: &[u8], rs: &ResolutionSettings) -> EResult<Self> {
+		let desc = desc.strip_suffix(b"\n").unwrap_or(desc);
+		let [sep, desc @ ..] = desc else {
+			return Err(errno!(EINVAL));
+		};
+		let mut fields = desc.split(|b| b == sep);
+		let mut next = || fields.next().ok_or_else(|| errno!(EINVAL));
+		let (name, kind, offset, magic, mask, interp, flags) = (
+			next()?,
+			next()?,
+			next()?,
+			next()?,
+			next()?,
+			next()?,
+			next()?,
+		);
+		if fields.next().is_some() {
+			return Err(errno!(EINVAL));
+		}
+		if matches!(name, b"" | b"." | b"..") || name.contains(&b'/') {
+			return Err(errno!(EINVAL));
+		}
+		if kind != b"M" {
+			return Err(errno!(EINVAL));
+		}
+		let offset: usize = if offset.is_empty() {
+			0
+		} else {
+			str::from_utf8(offset)
+				.ok()
+				.and_then(|s| s.parse().ok())
+				.ok_or_else(|| errno!(EINVAL))?
+		};
+		let magic = unescape(magic)?;
+		let mask = (!mask.is_empty()).then(|| unescape(mask)).transpose()?;
+		let end = offset.checked_add(magic.len());
+		if magic.is_empty() || end.is_none_or(|end| end > HEADER_MAX) {
+			return Err(errno!(EINVAL));
+		}
+		if mask.as_ref().is_some_and(|m| m.len() != magic.len()) {
+			return Err(errno!(EINVAL));
+		}
+		if interp.is_empty() {
+			return Err(errno!(EINVAL));
+		}
+		let mut preserve_argv0 = false;
+		let mut fix_binary = false;
+		for f in flags {
+			match f {
+				b'P' => preserve_argv0 = true,
+				b'F' => fix_binary = true,
+				_ => return Err(errno!(EINVAL)),
+			}
+		}
+		let interp = PathBuf::try_from(interp)?;
+		let interp_file = fix_binary
+			.then(|| vfs::get_file_from_path(&interp, rs))
+			.transpose()?;
+		Ok(Self {
+			name: String::try_from(name)?,
+			offset,
+			magic,
+			mask,
+			interp,
+			interp_file,
+			preserve_argv0,
+		})
+	}
+}
+
+impl Executor for MiscExecutor {
+	fn get_name(&self) -> &[u8] {
+		&self.name
+	}
+
+	fn detect(&self, hdr: &[u8]) -> bool {
+		let Some(bytes) = hdr.get(self.offset..(self.offset + self.magic.len())) else {
+			return false;
+		};
+		match &self.mask {
+			Some(mask) => bytes
+				.iter()
+				.zip(mask.iter())
+				.map(|(b, m)| b & m)
+				.eq(self.magic.iter().copied()),
+			None => bytes == &*self.magic,
+		}
+	}
+
+	fn load(&self, _file: Arc<vfs::Entry>, _hdr: &[u8], info: &mut ExecInfo) -> EResult<Loaded> {
+		let ent = match &self.interp_file {
+			Some(ent) => ent.clone(),
+			None => vfs::get_file_from_path(&self.interp, info.path_resolution)?,
+		};
+		if !self.preserve_argv0 && !info.argv.is_empty() {
+			info.argv.remove(0);
+		}
+		info.argv.insert(0, String::try_from(info.path)?)?;
+		info.argv
+			.insert(0, String::try_from(self.interp.as_bytes())?)?;
+		Ok(Loaded::Interp(ent))
+	}
+}
+
+/// Registers the format described by `desc`, as written to `/proc/sys/fs/binfmt_misc/register`.
+///
+/// Formats registered by userspace are probed before the ones implemented inside the kernel.
+///
+/// `rs` is the resolution settings of the registering process.
+pub fn register(desc: &[u8], rs: &ResolutionSettings) -> EResult<()> {
+	exec::register_first(MiscExecutor::parse(desc, rs)?)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn binfmt_misc_parse() {
+		let rs = ResolutionSettings::kernel_follow();
+		let exe =
+			MiscExecutor::parse(b":test:M:2:\\x7fW\\\\:\\xff\\xdf\\xff:/bin/interp:P\n", &rs)
+				.unwrap();
+		assert_eq!(exe.get_name(), b"test");
+		assert!(exe.preserve_argv0);
+		assert!(exe.detect(b"..\x7fw\\"));
+		assert!(!exe.detect(b".\x7fW\\"));
+		assert!(!exe.detect(b"..\x7fW"));
+		// Invalid descriptions
+		let rs = &rs;
+		assert!(MiscExecutor::parse(b":test:E::sh::/bin/sh:", rs).is_err());
+		assert!(MiscExecutor::parse(b":a/b:M::x::/bin/sh:", rs).is_err());
+		assert!(MiscExecutor::parse(b":test:M::\\x7::/bin/sh:", rs).is_err());
+		assert!(MiscExecutor::parse(b":test:M::ab:\\xff:/bin/sh:", rs).is_err());
+		assert!(MiscExecutor::parse(b":test:M:256:x::/bin/sh:", rs).is_err());
+		assert!(MiscExecutor::parse(b":test:M::x::/bin/sh", rs).is_err());
+	}
+}
@@ -23,27 +23,65 @@
 //! - Parse the program
 //! - Build the memory image according to the program
 //! - Replace the process's memory with the newly created image to run it
+//!
+//! The format of the program is detected from the first bytes of the file, which are probed by
+//! each registered [`Executor`] in turn.
 
 pub mod elf;
+pub mod misc;
+pub mod script;
 pub mod vdso;
 
 use crate::{
-	arch::x86::{idt::IntFrame, tss},
-	file::{vfs, vfs::ResolutionSettings},
-	memory::VirtAddr,
-	process::{Process, mem_space::MemSpace},
+	arch::x86::{idt, idt::IntFrame, tss},
+	file::{
+		File, O_RDONLY, O_RDWR,
+		fd::FileDescriptorTable,
+		perm::{AccessProfile, Gid, S_ISGID, S_ISUID, S_IXGRP, Uid},
+		vfs,
+		vfs::{
+			ResolutionSettings, mountpoint,
+			mountpoint::{FLAG_NOEXEC, FLAG_NOSUID},
+		},
+	},
+	initcall,
+	memory::{VirtAddr, user::UserSlice},
+	process::{
+		Process,
+		mem_space::MemSpace,
+		rlimit::{DEFAULT_STACK_LIMIT, RLIMIT_STACK},
+		signal::Signal,
+	},
 	sync::mutex::Mutex,
 };
+use core::{
+	cmp::min,
+	fmt,
+	hint::unlikely,
+	ptr,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{Relaxed, Release},
+	},
+};
 use utils::{
-	collections::{string::String, vec::Vec},
+	collections::{path::Path, string::String, vec::Vec},
+	errno,
 	errno::EResult,
 	ptr::arc::Arc,
 };
 
+/// The number of bytes read at the beginning of a program to detect its format.
+pub const HEADER_MAX: usize = 256;
+/// The maximum number of interpreters that can be used recursively for an execution.
+const INTERP_MAX: usize = 4;
+
 /// Information to prepare a program image to be executed.
 pub struct ExecInfo<'s> {
 	/// Path resolution settings.
 	pub path_resolution: &'s ResolutionSettings,
+	/// The path of the program, as given to `execve`.
+	pub path: &'s [u8],
 	/// The list of arguments.
 	pub argv: Vec<String>,
 	/// The list of environment variables.
@@ -63,25 +101,258 @@ pub struct ProgramImage {
 	user_stack: VirtAddr,
 }
 
+impl ProgramImage {
+	/// Returns the file of the executed program.
+	///
+	/// For a program run by an interpreter, this is the interpreter's file.
+	pub fn exe(&self) -> &Arc<vfs::Entry> {
+		&self.mem_space.exe_info.exe
+	}
+}
+
+/// The result of loading a program with an [`Executor`].
+pub enum Loaded {
+	/// The program image, ready to be executed.
+	Image(ProgramImage),
+	/// The program is run by an interpreter, whose file is loaded in turn.
+	///
+	/// The executor has already updated the arguments to be passed to the interpreter.
+	Interp(Arc<vfs::Entry>),
+}
+
 /// A program executor, whose role is to load a program and to prepare it for execution.
 pub trait Executor {
-	/// Builds a program image.
+	/// Returns the name of the executable format.
+	fn get_name(&self) -> &[u8];
+
+	/// Tells whether the executor handles the program whose file begins with `hdr`.
+	///
+	/// `hdr` contains at most [`HEADER_MAX`] bytes.
+	fn detect(&self, hdr: &[u8]) -> bool;
+
+	/// Loads the program.
 	///
-	/// `file` is the program's VFS entry.
-	fn build_image(&self, file: Arc<vfs::Entry>) -> EResult<ProgramImage>;
+	/// Arguments:
+	/// - `file` is the program's VFS entry
+	/// - `hdr` is the beginning of the program's file
+	/// - `info` is the execution information for the program
+	fn load(&self, file: Arc<vfs::Entry>, hdr: &[u8], info: &mut ExecInfo) -> EResult<Loaded>;
+}
+
+/// The list of registered executors, in the order in which they are probed.
+static EXECUTORS: Mutex<Vec<Arc<dyn Executor>>> = Mutex::new(Vec::new());
+
+/// If set, the file descriptors inherited by each executed program are written to the kernel log.
+pub static EXEC_FD_AUDIT: AtomicBool = AtomicBool::new(false);
+
+/// Inserts `executor` in the list of registered executors.
+///
+/// If `first` is set, the executor is probed before the others.
+///
+/// If an executor with the same name is already registered, the function returns
+/// [`errno::EEXIST`].
+fn insert<E: 'static + Executor>(executor: E, first: bool) -> EResult<()> {
+	let mut executors = EXECUTORS.lock();
+	if executors
+		.iter()
+		.any(|e| e.get_name() == executor.get_name())
+	{
+		return Err(errno!(EEXIST));
+	}
+	let executor = Arc::new(executor)?;
+	if first {
+		executors.insert(0, executor)?;
+	} else {
+		executors.push(executor)?;
+	}
+	Ok(())
+}
+
+/// Registers a new executor, probed after the already registered ones.
+///
+/// If an executor with the same name is already registered, the function returns
+/// [`errno::EEXIST`].
+pub fn register<E: 'static + Executor>(executor: E) -> EResult<()> {
+	insert(executor, false)
+}
+
+/// Registers a new executor, probed before the already registered ones.
+///
+/// If an executor with the same name is already registered, the function returns
+/// [`errno::EEXIST`].
+pub fn register_first<E: 'static + Executor>(executor: E) -> EResult<()> {
+	insert(executor, true)
+}
+
+/// Unregisters the executor with the given name.
+///
+/// If the executor doesn't exist, the function does nothing.
+pub fn unregister(name: &[u8]) {
+	EXECUTORS.lock().retain(|e| e.get_name() != name);
+}
+
+/// Returns the executor handling the program whose file begins with `hdr`.
+///
+/// If no executor handles the program, the function returns [`errno::ENOEXEC`].
+fn detect(hdr: &[u8]) -> EResult<Arc<dyn Executor>> {
+	EXECUTORS
+		.lock()
+		.iter()
+		.find(|e| e.detect(hdr))
+		.cloned()
+		.ok_or_else(|| errno!(ENOEXEC))
+}
+
+/// Registers the executors that are implemented inside the kernel itself.
+fn register_defaults() -> EResult<()> {
+	register(elf::ELFExecutor)?;
+	register(script::ScriptExecutor)?;
+	Ok(())
+}
+
+initcall!(subsys, binfmt, |_| register_defaults());
+
+/// Returns the effective user ID and group ID a process with the access profile `ap` gets when
+/// executing the program `ent`.
+///
+/// The set-user-ID and set-group-ID bits of `ent` are ignored if it is on a filesystem mounted
+/// with `nosuid`, or if the current process has `no_new_privs` set.
+pub fn exec_ids(ent: &vfs::Entry, ap: &AccessProfile) -> (Uid, Gid) {
+	let stat = ent.stat();
+	let nosuid = mountpoint::entry_has_flags(ent, FLAG_NOSUID)
+		|| Process::current().no_new_privs.load(Relaxed);
+	let euid = if !nosuid && stat.mode & S_ISUID != 0 {
+		stat.uid
+	} else {
+		ap.euid
+	};
+	let egid = if !nosuid && stat.mode & S_ISGID != 0 && stat.mode & S_IXGRP != 0 {
+		stat.gid
+	} else {
+		ap.egid
+	};
+	(euid, egid)
+}
+
+/// Opens `/dev/null` on the standard file descriptors of `proc` that are closed.
+fn open_std_fds(proc: &Process) -> EResult<()> {
+	let Some(fds) = proc.file_descriptors.get() else {
+		return Ok(());
+	};
+	let mut fds = fds.lock();
+	let mut null = None;
+	for id in 0..3 {
+		if fds.get_fd(id).is_ok() {
+			continue;
+		}
+		let file = match &null {
+			Some(file) => file,
+			None => {
+				let path = Path::new(b"/dev/null")?;
+				let ent = vfs::get_file_from_path(path, &ResolutionSettings::kernel_follow())?;
+				null.insert(File::open_entry(ent, O_RDWR)?)
+			}
+		};
+		// Lower file descriptors are open, so the lowest available is `id`
+		fds.create_fd(0, file.clone())?;
+	}
+	Ok(())
+}
+
+/// Sets the credentials of `proc` after it executed a new program with [`exec`].
+///
+/// `euid` and `egid` are the effective IDs returned by [`exec_ids`] for the program.
+///
+/// If the effective IDs differ from the real ones (secure execution), the process is made
+/// non-dumpable, and the state inherited from the caller is sanitized so that it cannot alter
+/// the behaviour of the program:
+/// - the soft limit of the stack size is lowered to its default value
+/// - the standard file descriptors that are closed are opened on `/dev/null`, so that files opened
+///   by the program are not mistaken for them
+pub fn set_credentials(proc: &Process, euid: Uid, egid: Gid) {
+	let no_new_privs = proc.no_new_privs.load(Relaxed);
+	let secure = {
+		let mut fs = proc.fs.lock();
+		let ap = &mut fs.access_profile;
+		ap.euid = euid;
+		ap.egid = egid;
+		ap.suid = ap.euid;
+		ap.sgid = ap.egid;
+		let old_permitted = ap.cap_permitted;
+		ap.exec_capabilities();
+		if no_new_privs {
+			ap.cap_permitted &= old_permitted;
+			ap.cap_effective &= old_permitted;
+		}
+		ap.euid != ap.uid || ap.egid != ap.gid
+	};
+	proc.dumpable.store(!secure, Relaxed);
+	if !secure {
+		return;
+	}
+	{
+		let mut rlimits = proc.rlimits.lock();
+		let stack = &mut rlimits[RLIMIT_STACK as usize];
+		stack.rlim_cur = min(stack.rlim_cur, DEFAULT_STACK_LIMIT);
+	}
+	// The program is already loaded, so it cannot be prevented from running anymore
+	if open_std_fds(proc).is_err() {
+		proc.kill(Signal::SIGKILL);
+	}
 }
 
 /// Builds a program image from the given executable file.
 ///
+/// If the program is run by an interpreter, the interpreter is loaded instead, with the
+/// arguments updated accordingly.
+///
 /// Arguments:
 /// - `file` is the program's file
 /// - `info` is the set execution information for the program
 ///
 /// The function returns a memory space containing the program image and the
 /// pointer to the entry point.
-pub fn build_image(file: Arc<vfs::Entry>, info: ExecInfo) -> EResult<ProgramImage> {
-	// TODO Support other formats than ELF (wasm?)
-	elf::ELFExecutor(info).build_image(file)
+pub fn build_image(mut file: Arc<vfs::Entry>, mut info: ExecInfo) -> EResult<ProgramImage> {
+	for _ in 0..INTERP_MAX {
+		// Check permission
+		let stat = file.stat();
+		let ap = &info.path_resolution.access_profile;
+		if unlikely(!ap.can_read_file(&stat) || !ap.can_execute_file(&stat)) {
+			return Err(errno!(EACCES));
+		}
+		if unlikely(mountpoint::entry_has_flags(&file, FLAG_NOEXEC)) {
+			return Err(errno!(EACCES));
+		}
+		// Read header
+		let mut hdr = [0u8; HEADER_MAX];
+		let len = {
+			let f = File::open_entry(file.clone(), O_RDONLY)?;
+			f.ops.read(&f, 0, UserSlice::from_slice_mut(&mut hdr))?
+		};
+		let hdr = &hdr[..len];
+		match detect(hdr)?.load(file, hdr, &mut info)? {
+			Loaded::Image(image) => return Ok(image),
+			Loaded::Interp(interp) => file = interp,
+		}
+	}
+	// The limit of interpreters has been reached
+	Err(errno!(ELOOP))
+}
+
+/// Writes the file descriptors of `fds`, inherited by `proc` executing `image`, to the kernel
+/// log.
+fn audit_fds(proc: &Process, image: &ProgramImage, fds: &FileDescriptorTable) {
+	let exe = vfs::Entry::get_path(image.exe());
+	let exe = exe.as_ref().map(|p| p as &dyn fmt::Display).unwrap_or(&"?");
+	for (id, fd) in fds.iter() {
+		match fd.get_file().describe(&vfs::ROOT) {
+			Ok(desc) => println!(
+				"exec: pid {} ({exe}) inherits fd {id}: {desc}",
+				proc.get_pid()
+			),
+			Err(e) => println!("exec: pid {} ({exe}) inherits fd {id}: {e}", proc.get_pid()),
+		}
+	}
 }
 
 /// Executes the program image `image` on the process `proc`.
@@ -90,21 +361,35 @@ pub fn build_image(file: Arc<vfs::Entry>, info: ExecInfo) -> EResult<ProgramImag
 /// for each register so that the execution beings when the interrupt handler returns.
 pub fn exec(proc: &Process, frame: &mut IntFrame, image: ProgramImage) -> EResult<()> {
 	// Preform all fallible operations first before touching the process
+	// The table is copied while locked, so that file descriptors are either inherited or closed
+	// according to their `FD_CLOEXEC` flag at a single point in time, even if other threads share
+	// the table
 	let fds = proc
 		.file_descriptors
-		.as_ref()
+		.get()
 		.map(|fds_mutex| -> EResult<_> {
 			let fds = fds_mutex.lock();
 			let new_fds = fds.duplicate(true)?;
 			Ok(Arc::new(Mutex::new(new_fds))?)
 		})
 		.transpose()?;
+	if EXEC_FD_AUDIT.load(Relaxed)
+		&& let Some(fds) = &fds
+	{
+		audit_fds(proc, &image, &fds.lock());
+	}
 	let signal_handlers = Arc::new(Default::default())?;
 	// All fallible operations succeeded, flush to process
+	// Keep the maximum resident set size reached by the previous program
+	if let Some(mem_space) = proc.mem_space.as_ref() {
+		let max_rss = mem_space.get_max_rss();
+		proc.rusage.maxrss.fetch_max(max_rss, Relaxed);
+	}
 	MemSpace::bind(&image.mem_space);
+	// Swap atomically, so that other processes never see a partially updated table
+	proc.file_descriptors.swap(fds);
 	// Safe because no other thread can execute this function at the same time for the same process
 	unsafe {
-		*proc.file_descriptors.get_mut() = fds;
 		*proc.mem_space.get_mut() = Some(image.mem_space);
 	}
 	// Reset signals
@@ -112,38 +397,43 @@ pub fn exec(proc: &Process, frame: &mut IntFrame, image: ProgramImage) -> EResul
 		let mut signal_manager = proc.signal.lock();
 		signal_manager.handlers = signal_handlers;
 		signal_manager.sigpending = Default::default();
+		signal_manager.altstack = Default::default();
 	}
 	proc.vfork_wake();
 	*proc.tls.lock() = Default::default();
-	// Set TSS here for the first process to be executed
-	unsafe {
-		tss::set_kernel_stack(proc.kernel_stack.top().as_ptr());
-	}
+	proc.clear_child_tid.store(ptr::null_mut(), Release);
 	// Set the process's registers
 	IntFrame::exec(frame, image.entry_point.0, image.user_stack.0, image.compat);
-	#[cfg(target_arch = "x86_64")]
-	{
-		use crate::{arch::x86, process::scheduler::core_local};
-		use core::{arch::asm, sync::atomic::Ordering::Relaxed};
-		// Preserve GS base
-		let gs_base = x86::rdmsr(x86::IA32_GS_BASE);
-		// Reset segment selector
+	// Disable interrupts so that the current core cannot change meanwhile
+	idt::wrap_disable_interrupts(|| {
+		// Set TSS here for the first process to be executed
 		unsafe {
-			asm!(
-				"xor {tmp}, {tmp}",
-				"mov fs, {tmp}",
-				"mov gs, {tmp}",
-				tmp = out(reg) _
-			);
+			tss::set_kernel_stack(proc.kernel_stack.top().as_ptr());
 		}
-		// Reset MSR
-		x86::wrmsr(x86::IA32_FS_BASE, 0);
-		x86::wrmsr(x86::IA32_GS_BASE, gs_base);
-		x86::wrmsr(x86::IA32_KERNEL_GS_BASE, 0);
-		// Update user stack
-		core_local()
-			.user_stack
-			.store(image.user_stack.0 as _, Relaxed);
-	}
+		#[cfg(target_arch = "x86_64")]
+		{
+			use crate::{arch::x86, process::scheduler::core_local};
+			use core::arch::asm;
+			// Preserve GS base
+			let gs_base = x86::rdmsr(x86::IA32_GS_BASE);
+			// Reset segment selector
+			unsafe {
+				asm!(
+					"xor {tmp}, {tmp}",
+					"mov fs, {tmp}",
+					"mov gs, {tmp}",
+					tmp = out(reg) _
+				);
+			}
+			// Reset MSR
+			x86::wrmsr(x86::IA32_FS_BASE, 0);
+			x86::wrmsr(x86::IA32_GS_BASE, gs_base);
+			x86::wrmsr(x86::IA32_KERNEL_GS_BASE, 0);
+			// Update user stack
+			core_local()
+				.user_stack
+				.store(image.user_stack.0 as _, Relaxed);
+		}
+	});
 	Ok(())
 }
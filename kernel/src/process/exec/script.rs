@@ -0,0 +1,104 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Execution of scripts, which begin with a shebang (`#!`) giving the path to their interpreter.
+//!
+//! The interpreter is run with the path given by the shebang as first argument, followed by the
+//! optional argument given by the shebang, then by the arguments of the script.
+
+use crate::{
+	file::vfs,
+	process::exec::{ExecInfo, Executor, HEADER_MAX, Loaded},
+};
+use utils::{
+	collections::{path::Path, string::String},
+	errno,
+	errno::EResult,
+	ptr::arc::Arc,
+};
+
+/// Tells whether `b` separates the interpreter from its argument in a shebang.
+fn is_blank(b: &u8) -> bool {
+	matches!(b, b' ' | b'\t')
+}
+
+/// Parses the shebang at the beginning of `hdr`, returning the path to the interpreter and its
+/// optional argument.
+///
+/// If the line does not fit in the header or contains no interpreter, the function returns
+/// [`errno::ENOEXEC`].
+fn parse(hdr: &[u8]) -> EResult<(&[u8], Option<&[u8]>)> {
+	let line = hdr.strip_prefix(b"#!").ok_or_else(|| errno!(ENOEXEC))?;
+	let end = match line.iter().position(|b| *b == b'\n') {
+		Some(end) => end,
+		// The file ends on the shebang
+		None if hdr.len() < HEADER_MAX => line.len(),
+		None => return Err(errno!(ENOEXEC)),
+	};
+	let line = line[..end].trim_ascii();
+	let interp_end = line.iter().position(is_blank).unwrap_or(line.len());
+	let (interp, arg) = line.split_at(interp_end);
+	if interp.is_empty() {
+		return Err(errno!(ENOEXEC));
+	}
+	let arg = arg.trim_ascii();
+	Ok((interp, (!arg.is_empty()).then_some(arg)))
+}
+
+/// The program executor for scripts.
+pub struct ScriptExecutor;
+
+impl Executor for ScriptExecutor {
+	fn get_name(&self) -> &[u8] {
+		b"script"
+	}
+
+	fn detect(&self, hdr: &[u8]) -> bool {
+		hdr.starts_with(b"#!")
+	}
+
+	fn load(&self, _file: Arc<vfs::Entry>, hdr: &[u8], info: &mut ExecInfo) -> EResult<Loaded> {
+		let (interp, arg) = parse(hdr)?;
+		let ent = vfs::get_file_from_path(Path::new(interp)?, info.path_resolution)?;
+		if let Some(arg) = arg {
+			info.argv.insert(0, String::try_from(arg)?)?;
+		}
+		info.argv.insert(0, String::try_from(interp)?)?;
+		Ok(Loaded::Interp(ent))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn shebang_parse() {
+		assert_eq!(parse(b"#!/bin/sh\n").unwrap(), (&b"/bin/sh"[..], None));
+		assert_eq!(
+			parse(b"#! /usr/bin/env  python3 -u \necho\n").unwrap(),
+			(&b"/usr/bin/env"[..], Some(&b"python3 -u"[..]))
+		);
+		assert_eq!(parse(b"#!/bin/sh").unwrap(), (&b"/bin/sh"[..], None));
+		assert!(parse(b"#!\n").is_err());
+		// Truncated line
+		let mut hdr = [b'a'; HEADER_MAX];
+		hdr[..2].copy_from_slice(b"#!");
+		assert!(parse(&hdr).is_err());
+	}
+}
@@ -18,18 +18,25 @@
 
 //! The vDSO (virtual dynamic shared object) is a small shared library that the kernel
 //! automatically maps into the memory space of all userspace programs.
+//!
+//! The image is preceded by a read-only data page, through which the kernel shares the values
+//! of clocks so that the vDSO can read them without performing a system call.
 
 use crate::{
-	elf::parser::ELFParser,
+	elf::parser::{ELFParser, Sym},
+	file::perm::AccessProfile,
 	memory::{
 		VirtAddr,
 		buddy::ZONE_KERNEL,
 		cache::{FrameOwner, RcFrame},
 	},
-	process::mem_space::{MAP_ANONYMOUS, MAP_PRIVATE, MemSpace, PROT_EXEC, PROT_READ, Page},
+	process::mem_space::{
+		MAP_ANONYMOUS, MAP_PRIVATE, MapConstraint, MemSpace, PROT_EXEC, PROT_READ, Page,
+	},
 	sync::once::OnceInit,
+	time::clock,
 };
-use core::{cmp::min, num::NonZeroUsize, ptr::NonNull};
+use core::{cmp::min, iter, num::NonZeroUsize, ptr::NonNull};
 use utils::{
 	collections::vec::Vec,
 	errno::{AllocResult, CollectResult, EResult},
@@ -39,10 +46,13 @@ use utils::{
 
 /// Information on the vDSO ELF image.
 struct Vdso {
-	/// The list of pages on which the image is loaded.
+	/// The list of pages to be mapped: the data page followed by the pages on which the image is
+	/// loaded.
 	pages: Vec<RcFrame>,
 	/// The offset of the vDSO's entry.
 	entry_off: Option<NonZeroUsize>,
+	/// The offset of the signal return trampoline.
+	sigreturn_off: Option<NonZeroUsize>,
 }
 
 /// Information about the mapped vDSO.
@@ -51,37 +61,59 @@ pub struct MappedVDSO {
 	pub begin: VirtAddr,
 	/// The pointer to the entry point of the vDSO
 	pub entry: Option<NonNull<u8>>,
+	/// The address of the signal return trampoline, used when a signal handler does not provide
+	/// its own
+	pub sigreturn: Option<VirtAddr>,
 }
 
+/// The name of the signal return trampoline symbol in the main image.
+#[cfg(target_arch = "x86")]
+const SIGRETURN: &[u8] = b"__kernel_sigreturn";
+/// The name of the signal return trampoline symbol in the main image.
+#[cfg(target_arch = "x86_64")]
+const SIGRETURN: &[u8] = b"__kernel_rt_sigreturn";
+
 /// The info of the vDSO. If `None`, the vDSO is not loaded yet.
 static VDSO: OnceInit<Vdso> = unsafe { OnceInit::new() };
 /// Same as [`VDSO`], except for the compat image.
 #[cfg(target_arch = "x86_64")]
 static VDSO_COMPAT: OnceInit<Vdso> = unsafe { OnceInit::new() };
 
+/// The data page shared by all vDSO images. If `None`, the vDSO is not loaded yet.
+static DATA: OnceInit<RcFrame> = unsafe { OnceInit::new() };
+
 /// Loads the vDSO in memory and returns the image.
-fn load_image(elf: &[u8]) -> EResult<Vdso> {
+///
+/// Arguments:
+/// - `elf` is the ELF image
+/// - `sigreturn` is the name of the symbol of the signal return trampoline
+fn load_image(elf: &[u8], sigreturn: &[u8]) -> EResult<Vdso> {
 	let parser = ELFParser::new(elf)?;
 	// Load image into pages
 	let pages_count = elf.len().div_ceil(PAGE_SIZE);
-	let pages = (0..pages_count)
-		.map(|i| {
-			let off = i * PAGE_SIZE;
-			let len = min(PAGE_SIZE, elf.len() - off);
-			// Alloc page
-			let page = RcFrame::new(0, ZONE_KERNEL, FrameOwner::Anon, 0)?;
-			let virtaddr = unsafe { &mut *page.virt_addr().as_ptr::<Page>() };
-			// Copy data
-			let src = &elf[off..(off + len)];
-			virtaddr[..src.len()].copy_from_slice(src);
-			virtaddr[src.len()..].fill(0);
-			Ok(page)
-		})
+	let image = (0..pages_count).map(|i| {
+		let off = i * PAGE_SIZE;
+		let len = min(PAGE_SIZE, elf.len() - off);
+		// Alloc page
+		let page = RcFrame::new(0, ZONE_KERNEL, FrameOwner::Anon, 0)?;
+		let virtaddr = unsafe { &mut *page.virt_addr().as_ptr::<Page>() };
+		// Copy data
+		let src = &elf[off..(off + len)];
+		virtaddr[..src.len()].copy_from_slice(src);
+		virtaddr[src.len()..].fill(0);
+		Ok(page)
+	});
+	let pages = iter::once(Ok(DATA.clone()))
+		.chain(image)
 		.collect::<AllocResult<CollectResult<_>>>()?
 		.0?;
 	Ok(Vdso {
 		pages,
 		entry_off: NonZeroUsize::new(parser.hdr().e_entry as usize),
+		sigreturn_off: parser
+			.get_symbol_by_name(sigreturn)
+			.filter(Sym::is_defined)
+			.and_then(|sym| NonZeroUsize::new(sym.st_value as usize)),
 	})
 }
 
@@ -96,31 +128,44 @@ pub fn map(mem_space: &MemSpace, compat: bool) -> EResult<MappedVDSO> {
 	let vdso = &*VDSO;
 	#[cfg(target_arch = "x86_64")]
 	let vdso = { if !compat { &*VDSO } else { &*VDSO_COMPAT } };
-	let begin = mem_space.map_special(
+	let data = mem_space.map_special(
+		MapConstraint::None,
 		PROT_READ | PROT_EXEC,
 		MAP_PRIVATE | MAP_ANONYMOUS,
 		&vdso.pages,
 	)?;
+	// The data page must not be executable
+	mem_space.set_prot(data.cast(), PAGE_SIZE, PROT_READ, &AccessProfile::KERNEL)?;
+	let begin = data.wrapping_add(PAGE_SIZE);
 	Ok(MappedVDSO {
 		begin: begin.into(),
 		entry: vdso
 			.entry_off
 			.and_then(|off| NonNull::new(begin.wrapping_add(off.get()))),
+		sigreturn: vdso
+			.sigreturn_off
+			.map(|off| VirtAddr::from(begin) + off.get()),
 	})
 }
 
 /// Loads the vDSO.
 pub(crate) fn init() -> EResult<()> {
+	// Data page
+	let data = RcFrame::new_zeroed(0, FrameOwner::Anon, 0)?;
+	clock::share(&data);
+	unsafe {
+		OnceInit::init(&DATA, data);
+	}
 	// Main image
 	unsafe {
 		static ELF: &[u8] = include_bytes_aligned!(usize, env!("VDSO_PATH"));
-		OnceInit::init(&VDSO, load_image(ELF)?);
+		OnceInit::init(&VDSO, load_image(ELF, SIGRETURN)?);
 	}
 	// 32 bit image for backward compat
 	#[cfg(target_arch = "x86_64")]
 	unsafe {
 		static ELF: &[u8] = include_bytes_aligned!(usize, env!("VDSO_COMPAT_PATH"));
-		OnceInit::init(&VDSO_COMPAT, load_image(ELF)?);
+		OnceInit::init(&VDSO_COMPAT, load_image(ELF, b"__kernel_sigreturn")?);
 	}
 	Ok(())
 }
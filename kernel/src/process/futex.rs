@@ -0,0 +1,175 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Futexes (Fast Userspace muTEXes) allow userspace to sleep until another thread signals a
+//! change on a word of memory.
+//!
+//! A futex is identified by the memory space and the address of its word. As such, futexes are
+//! shared between threads, but not between processes sharing a mapping.
+
+use crate::{
+	process,
+	process::{
+		Process,
+		mem_space::MemSpace,
+		pid::Pid,
+		scheduler::Scheduler,
+		signal::{SIGEV_NONE, SigEvent},
+	},
+	sync::mutex::IntMutex,
+	time::{
+		clock::{Clock, current_time_ns},
+		timer::Timer,
+		unit::Timestamp,
+	},
+};
+use utils::{collections::vec::Vec, errno, errno::EResult, ptr::arc::Arc};
+
+/// Identifier of a futex: the address of the memory space and the address of the word.
+type Key = (usize, usize);
+
+/// A process waiting on a futex.
+#[derive(Debug)]
+struct Waiter {
+	/// The futex being waited on.
+	key: Key,
+	/// The waiting process.
+	pid: Pid,
+}
+
+/// The list of processes waiting on a futex, in order of arrival.
+static WAITERS: IntMutex<Vec<Waiter>> = IntMutex::new(Vec::new());
+
+/// Returns the key of the futex at `addr` in `mem_space`.
+fn key(mem_space: &Arc<MemSpace>, addr: usize) -> Key {
+	(Arc::as_ptr(mem_space) as usize, addr)
+}
+
+/// Removes the process with the given PID from the list of waiters of `key`.
+///
+/// If the process was not waiting (because it has been woken up), the function returns `false`.
+fn dequeue(key: Key, pid: Pid) -> bool {
+	let mut waiters = WAITERS.lock();
+	let i = waiters.iter().position(|w| w.key == key && w.pid == pid);
+	if let Some(i) = i {
+		waiters.remove(i);
+	}
+	i.is_some()
+}
+
+/// Makes the current process wait on the futex at `addr` in `mem_space`, until it is woken up by
+/// [`wake`].
+///
+/// `load` returns the current value of the futex's word. If it is not equal to `val`, the
+/// function returns immediately with [`errno::EAGAIN`].
+///
+/// `timeout` is the maximum duration to wait for, in nanoseconds. If elapsed, the function
+/// returns [`errno::ETIMEDOUT`].
+///
+/// If waiting is interrupted by a signal, the function returns [`errno::EINTR`].
+pub fn wait<F: FnOnce() -> EResult<u32>>(
+	mem_space: &Arc<MemSpace>,
+	addr: usize,
+	val: u32,
+	load: F,
+	timeout: Option<Timestamp>,
+) -> EResult<()> {
+	let key = key(mem_space, addr);
+	let proc = Process::current();
+	let pid = proc.get_pid();
+	// Queue before reading the value, so that a wake happening right after a change of the value
+	// cannot be missed
+	WAITERS.lock().push(Waiter {
+		key,
+		pid,
+	})?;
+	let cur = load();
+	if cur.as_ref().map_or(true, |cur| *cur != val) {
+		dequeue(key, pid);
+		return cur.and(Err(errno!(EAGAIN)));
+	}
+	let end = timeout.map(|t| current_time_ns(Clock::Monotonic) + t);
+	// Timer waking the process up on timeout
+	let timer = timeout
+		.map(|timeout| {
+			let t = Timer::new(
+				Clock::Monotonic,
+				pid,
+				SigEvent {
+					sigev_notify: SIGEV_NONE,
+					..Default::default()
+				},
+			)?;
+			t.set_time(0, timeout)?;
+			Ok(t)
+		})
+		.transpose();
+	let _timer = match timer {
+		Ok(timer) => timer,
+		Err(e) => {
+			dequeue(key, pid);
+			return Err(e);
+		}
+	};
+	loop {
+		proc.set_state(process::State::Sleeping);
+		// If woken up in between, do not sleep
+		if !WAITERS.lock().iter().any(|w| w.key == key && w.pid == pid) {
+			proc.set_state(process::State::Running);
+			return Ok(());
+		}
+		Scheduler::tick();
+		if proc.has_pending_signal() {
+			return if dequeue(key, pid) {
+				Err(errno!(EINTR))
+			} else {
+				Ok(())
+			};
+		}
+		if end.is_some_and(|end| current_time_ns(Clock::Monotonic) >= end) {
+			return if dequeue(key, pid) {
+				Err(errno!(ETIMEDOUT))
+			} else {
+				Ok(())
+			};
+		}
+	}
+}
+
+/// Wakes up at most `count` processes waiting on the futex at `addr` in `mem_space`, in order of
+/// arrival.
+///
+/// The function returns the number of processes that have been woken up.
+pub fn wake(mem_space: &Arc<MemSpace>, addr: usize, count: usize) -> usize {
+	let key = key(mem_space, addr);
+	let mut woken = 0;
+	while woken < count {
+		let pid = {
+			let mut waiters = WAITERS.lock();
+			let Some(i) = waiters.iter().position(|w| w.key == key) else {
+				break;
+			};
+			waiters.remove(i).pid
+		};
+		if let Some(proc) = Process::get_by_pid(pid) {
+			proc.wake();
+		}
+		woken += 1;
+	}
+	woken
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kernel threads are tasks running in kernelspace, managed by the scheduler like processes.
+//!
+//! They have no userspace memory space, and are used for background work such as writing back
+//! the page cache, processing received network packets or servicing devices.
+//!
+//! A thread can be asked to park, in which case it sleeps at its next call to [`parkme`] until it
+//! is unparked, or to stop, in which case [`should_stop`] returns `true` and the thread is
+//! expected to return from its function.
+
+use crate::{
+	arch::x86::{cli, sti},
+	file::wait_queue::WaitQueue,
+	process::{
+		Process, State,
+		pid::Pid,
+		scheduler::{SCHEDULER, Scheduler},
+	},
+	sync::mutex::{IntMutex, Mutex},
+};
+use core::sync::atomic::{
+	AtomicBool,
+	Ordering::{Acquire, Release},
+};
+use utils::{boxed::Box, collections::btreemap::BTreeMap, errno::EResult, ptr::arc::Arc};
+
+/// A function to be run once by a kernel thread.
+trait ThreadFn {
+	/// Runs the function, if it has not been run yet.
+	fn run(&mut self);
+}
+
+impl<F: FnOnce()> ThreadFn for Option<F> {
+	fn run(&mut self) {
+		if let Some(f) = self.take() {
+			f();
+		}
+	}
+}
+
+/// Kernel threads, by PID.
+static THREADS: IntMutex<BTreeMap<Pid, Arc<KThread>>> = IntMutex::new(BTreeMap::new());
+
+/// A kernel thread.
+pub struct KThread {
+	/// The name of the thread.
+	pub name: &'static str,
+	/// The process structure of the thread.
+	proc: Arc<Process>,
+	/// The function run by the thread, taken when it starts.
+	func: Mutex<Option<Box<dyn ThreadFn>>>,
+
+	/// Tells whether the thread has been asked to park.
+	should_park: AtomicBool,
+	/// Tells whether the thread has been asked to stop.
+	should_stop: AtomicBool,
+	/// Tells whether the thread is parked.
+	parked: AtomicBool,
+	/// Tells whether the thread has returned from its function.
+	exited: AtomicBool,
+	/// Queue of processes waiting for the thread to park or exit.
+	events: WaitQueue,
+}
+
+impl KThread {
+	/// Returns the PID of the thread.
+	#[inline]
+	pub fn get_pid(&self) -> Pid {
+		self.proc.get_pid()
+	}
+
+	/// Wakes up the thread if it is sleeping.
+	///
+	/// A parked thread is not woken up by this function.
+	pub fn wake(&self) {
+		if !self.parked.load(Acquire) {
+			self.proc.wake();
+		}
+	}
+
+	/// Asks the thread to park, then waits until it is parked.
+	///
+	/// This function must not be called from the thread itself.
+	pub fn park(&self) {
+		self.should_park.store(true, Release);
+		self.proc.wake();
+		while !self.parked.load(Acquire) && !self.exited.load(Acquire) {
+			let _ = self.events.wait_until(|| {
+				(self.parked.load(Acquire) || self.exited.load(Acquire)).then_some(())
+			});
+		}
+	}
+
+	/// Lets a parked thread resume.
+	pub fn unpark(&self) {
+		self.should_park.store(false, Release);
+		self.proc.wake();
+	}
+
+	/// Asks the thread to stop, then waits until it has returned from its function.
+	///
+	/// The thread is then removed from the scheduler. This is the only way to release the
+	/// resources of a kernel thread.
+	///
+	/// This function must not be called from the thread itself.
+	pub fn stop(&self) {
+		self.should_stop.store(true, Release);
+		self.proc.wake();
+		while !self.exited.load(Acquire) {
+			let _ = self
+				.events
+				.wait_until(|| self.exited.load(Acquire).then_some(()));
+		}
+		// Wait for the thread to switch away for the last time
+		while self.proc.get_state() != State::Zombie {
+			Scheduler::tick();
+		}
+		let pid = self.get_pid();
+		SCHEDULER.lock().remove_process(pid);
+		self.proc.wait_off_cpu();
+		THREADS.lock().remove(&pid);
+	}
+}
+
+/// The entry point of kernel threads created with [`spawn`].
+fn entry() -> ! {
+	sti();
+	// Use a scope to release references before exiting, since the last `tick` never returns
+	{
+		let thread = current().unwrap();
+		let func = thread.func.lock().take();
+		if let Some(mut func) = func {
+			func.run();
+		}
+		thread.exited.store(true, Release);
+		thread.events.wake_all();
+		// Prevent preemption until the references are released
+		cli();
+		thread.proc.exit(0);
+	}
+	loop {
+		Scheduler::tick();
+	}
+}
+
+/// Creates a kernel thread named `name`, running `f`, and places it into the scheduler's queue.
+///
+/// When `f` returns, the thread exits. Its resources are released by [`KThread::stop`].
+pub fn spawn<F: 'static + FnOnce()>(name: &'static str, f: F) -> EResult<Arc<KThread>> {
+	let proc = Process::new_kthread(None, entry, false)?;
+	let func: Box<dyn ThreadFn> = Box::new(Some(f))?;
+	let thread = Arc::new(KThread {
+		name,
+		proc: proc.clone(),
+		func: Mutex::new(Some(func)),
+
+		should_park: AtomicBool::new(false),
+		should_stop: AtomicBool::new(false),
+		parked: AtomicBool::new(false),
+		exited: AtomicBool::new(false),
+		events: WaitQueue::new(),
+	})?;
+	// Register the thread before it can be scheduled
+	let pid = proc.get_pid();
+	THREADS.lock().insert(pid, thread.clone())?;
+	if let Err(e) = SCHEDULER.lock().add_process(proc) {
+		THREADS.lock().remove(&pid);
+		return Err(e.into());
+	}
+	Ok(thread)
+}
+
+/// Returns the kernel thread with PID `pid`, if any.
+pub fn get(pid: Pid) -> Option<Arc<KThread>> {
+	THREADS.lock().get(&pid).cloned()
+}
+
+/// Returns the current kernel thread, or `None` if the current process is not one.
+pub fn current() -> Option<Arc<KThread>> {
+	get(Process::current().get_pid())
+}
+
+/// Tells whether the current kernel thread has been asked to stop.
+///
+/// If the current process is not a kernel thread, the function returns `false`.
+pub fn should_stop() -> bool {
+	current().is_some_and(|t| t.should_stop.load(Acquire))
+}
+
+/// Parks the current kernel thread if it has been asked to, until it is unparked or asked to stop.
+///
+/// If the current process is not a kernel thread, the function does nothing.
+pub fn parkme() {
+	let Some(thread) = current() else {
+		return;
+	};
+	let must_park = || thread.should_park.load(Acquire) && !thread.should_stop.load(Acquire);
+	while must_park() {
+		// Sleep first, so that a wake up happening after the check is not lost
+		thread.proc.set_state(State::Sleeping);
+		if !must_park() {
+			thread.proc.set_state(State::Running);
+			break;
+		}
+		thread.parked.store(true, Release);
+		thread.events.wake_all();
+		Scheduler::tick();
+	}
+	thread.parked.store(false, Release);
+}
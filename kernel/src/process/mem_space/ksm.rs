@@ -0,0 +1,237 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kernel samepage merging (KSM) merges identical anonymous pages of mappings marked with
+//! `madvise(MADV_MERGEABLE)`, to save memory when many similar processes run.
+//!
+//! The `ksmd` kernel thread periodically scans mergeable pages, a batch at a time. A page whose
+//! checksum did not change since the previous full scan is considered stable: it is
+//! write-protected, then merged with an identical *KSM page* if any, or becomes a KSM page
+//! otherwise. KSM pages are shared Copy-On-Write, so writing to one gives the writer its own copy
+//! again.
+//!
+//! At the end of each full scan, KSM pages that are no longer mapped anywhere are released.
+
+use super::{
+	MAP_HUGETLB, MAP_MERGEABLE, MAP_SHARED, MemSpace,
+	mapping::{MappedFrame, MemMapping},
+};
+use crate::{
+	file::wait_queue::WaitQueue,
+	memory::{PhysAddr, VirtAddr, cache::RcFrame, vmem::VMem},
+	process::{kthread, pid::Pid, scheduler::SCHEDULER},
+	time::{clock::Clock, sleep_for},
+};
+use core::{
+	mem,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{Acquire, Release},
+	},
+};
+use utils::{
+	collections::{hashmap::HashMap, vec::Vec},
+	errno::AllocResult,
+	limits::PAGE_SIZE,
+};
+
+/// The number of pages scanned in a batch.
+const PAGES_TO_SCAN: usize = 100;
+/// The duration `ksmd` sleeps for between two batches, in milliseconds.
+const SLEEP_MS: u64 = 20;
+
+/// Tells whether a mapping has ever been made mergeable. Until then, `ksmd` does not scan.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The queue on which `ksmd` waits for a mapping to be made mergeable.
+static ENABLED_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Lets `ksmd` start scanning, since a mapping has been made mergeable.
+pub(super) fn enable() {
+	if !ENABLED.swap(true, Release) {
+		ENABLED_QUEUE.wake_all();
+	}
+}
+
+/// Tells whether the pages of `mapping` can be merged.
+fn is_mergeable(mapping: &MemMapping) -> bool {
+	mapping.flags & MAP_MERGEABLE != 0
+		&& mapping.flags & (MAP_SHARED | MAP_HUGETLB) == 0
+		&& mapping.file.is_none()
+}
+
+/// Computes the checksum of the content of `frame`.
+fn checksum(frame: &RcFrame) -> u64 {
+	// FNV-1a, on words
+	frame
+		.slice::<u64>()
+		.iter()
+		.fold(0xcbf29ce484222325, |h, w| {
+			(h ^ w).wrapping_mul(0x100000001b3)
+		})
+}
+
+/// The state of the scan of memory spaces.
+#[derive(Default)]
+struct Scanner {
+	/// The PID of the process whose memory space is being scanned.
+	pid: Pid,
+	/// The address at which the scan resumes in the memory space.
+	addr: VirtAddr,
+
+	/// The checksums of the pages seen during the previous full scan, by physical address.
+	prev_checksums: HashMap<PhysAddr, u64>,
+	/// The checksums of the pages seen during the current full scan, by physical address.
+	checksums: HashMap<PhysAddr, u64>,
+	/// KSM pages, by checksum.
+	ksm_pages: HashMap<u64, Vec<MappedFrame>>,
+}
+
+impl Scanner {
+	/// Scans the page at offset `off` of `mapping`, whose memory space has the virtual memory
+	/// context `vmem`.
+	fn scan_page(
+		&mut self,
+		mapping: &mut MemMapping,
+		off: usize,
+		vmem: &mut VMem,
+	) -> AllocResult<()> {
+		let Some(page) = &mapping.pages[off] else {
+			return Ok(());
+		};
+		// Already merged, or pending Copy-On-Write
+		if page.is_shared() {
+			return Ok(());
+		}
+		let phys_addr = page.phys_addr();
+		let sum = checksum(page);
+		// A page which changed since the previous scan is likely to be written again soon
+		if self.prev_checksums.get(&phys_addr) != Some(&sum) {
+			self.checksums.insert(phys_addr, sum)?;
+			return Ok(());
+		}
+		// Write-protect the page so that its content cannot change anymore
+		vmem.unmap(VirtAddr::from(mapping.addr) + off * PAGE_SIZE);
+		let ksm_pages = self.ksm_pages.entry(sum).or_insert(Vec::new())?;
+		let same = ksm_pages
+			.iter()
+			.find(|p| p.slice::<u8>() == page.slice::<u8>())
+			.cloned();
+		match same {
+			// The page is released, unless someone else holds a reference to it
+			Some(ksm_page) => mapping.pages[off] = Some(ksm_page),
+			// The page becomes a KSM page, shared Copy-On-Write from now on
+			None => ksm_pages.push(page.clone())?,
+		}
+		Ok(())
+	}
+
+	/// Scans the mergeable pages of `mem_space`, from the current address, decrementing `budget`
+	/// for each page.
+	///
+	/// The function returns `true` if the end of the memory space has been reached.
+	fn scan_space(&mut self, mem_space: &MemSpace, budget: &mut usize) -> AllocResult<bool> {
+		while *budget > 0 {
+			let mut state = mem_space.state.lock();
+			let mut vmem = mem_space.vmem.lock();
+			// The next mapping to scan
+			let mapping = state
+				.mappings
+				.overlapping_mut(self.addr.as_ptr()..)
+				.map(|(_, m)| m)
+				.find(|m| is_mergeable(m));
+			let Some(mapping) = mapping else {
+				return Ok(true);
+			};
+			let begin = VirtAddr::from(mapping.addr);
+			let start = self.addr.0.saturating_sub(begin.0) / PAGE_SIZE;
+			let end = mapping.size.get().min(start + *budget);
+			for off in start..end {
+				self.scan_page(mapping, off, &mut vmem)?;
+			}
+			*budget -= end - start;
+			self.addr = begin + end * PAGE_SIZE;
+		}
+		Ok(false)
+	}
+
+	/// Ends a full scan, then starts the next one.
+	fn end_scan(&mut self) {
+		self.pid = 0;
+		self.addr = VirtAddr(0);
+		self.prev_checksums = mem::take(&mut self.checksums);
+		// Release KSM pages which are no longer mapped anywhere
+		self.ksm_pages.retain(|_, pages| {
+			pages.retain(|p| p.is_shared());
+			!pages.is_empty()
+		});
+	}
+
+	/// Scans a batch of pages, resuming from where the previous batch stopped.
+	fn scan_batch(&mut self) -> AllocResult<()> {
+		let mut budget = PAGES_TO_SCAN;
+		while budget > 0 {
+			let proc = SCHEDULER
+				.lock()
+				.iter_process()
+				.map(|(_, p)| p)
+				.find(|p| p.get_pid() >= self.pid)
+				.cloned();
+			let Some(proc) = proc else {
+				self.end_scan();
+				break;
+			};
+			// The process being scanned may have exited
+			if proc.get_pid() != self.pid {
+				self.pid = proc.get_pid();
+				self.addr = VirtAddr(0);
+			}
+			let mem_space = proc.mem_space.as_ref().cloned();
+			if let Some(mem_space) = mem_space
+				&& !self.scan_space(&mem_space, &mut budget)?
+			{
+				break;
+			}
+			// Move on to the next process
+			let Some(next) = self.pid.checked_add(1) else {
+				self.end_scan();
+				break;
+			};
+			self.pid = next;
+			self.addr = VirtAddr(0);
+		}
+		Ok(())
+	}
+}
+
+/// The entry point of the kernel thread merging identical pages.
+pub(crate) fn ksmd_task() {
+	let mut scanner = Scanner::default();
+	while !kthread::should_stop() {
+		kthread::parkme();
+		if ENABLED_QUEUE
+			.wait_until(|| ENABLED.load(Acquire).then_some(()))
+			.is_err()
+		{
+			continue;
+		}
+		// On allocation failure, the batch is scanned again later
+		let _ = scanner.scan_batch();
+		let mut remain = 0;
+		let _ = sleep_for(Clock::Monotonic, SLEEP_MS * 1_000_000, &mut remain);
+	}
+}
@@ -24,22 +24,32 @@
 use super::gap::MemGap;
 use crate::{
 	arch::x86::paging,
-	file::File,
+	file::{File, vfs::node::Node},
 	memory::{
-		PhysAddr, VirtAddr,
-		buddy::ZONE_USER,
-		cache::{FrameOwner, RcFrame},
+		PhysAddr, VirtAddr, cache,
+		cache::RcFrame,
+		hugetlb,
+		hugetlb::HUGE_PAGE_PAGES,
 		vmem::{VMem, write_ro},
 	},
-	process::mem_space::{
-		COPY_BUFFER, MAP_ANONYMOUS, MAP_PRIVATE, MAP_SHARED, PROT_EXEC, PROT_WRITE, Page,
+	process::{
+		cgroup::Cgroup,
+		mem_space::{
+			COPY_BUFFER, MAP_ANONYMOUS, MAP_DENYWRITE, MAP_HUGETLB, MAP_PRIVATE, MAP_SHARED,
+			PROT_EXEC, PROT_WRITE, Page,
+		},
 	},
 	time::clock::{Clock, current_time_ms},
 };
-use core::{num::NonZeroUsize, ops::Deref, sync::atomic::Ordering::Release};
+use core::{
+	num::NonZeroUsize,
+	ops::{Deref, Range},
+	sync::atomic::Ordering::{Acquire, Release},
+};
 use utils::{
 	TryClone,
 	collections::vec::Vec,
+	errno,
 	errno::{AllocResult, EResult},
 	limits::PAGE_SIZE,
 	ptr::arc::Arc,
@@ -114,30 +124,28 @@ fn vmem_flags(prot: u8, cow: bool) -> usize {
 	flags
 }
 
-// FIXME: SMAP and mapping the page to userspace before init (potential data leak to userspace)
-/// Initializes a new page and maps it at `dst`.
+/// Maps the physical page at `dst_phys` at `dst`, then initializes it.
 ///
 /// Arguments:
 /// - `vmem` is the transaction on which the page mapping takes place
-/// - `prot` is the memory protection for the newly mapped page
-/// - `src` is the page containing the data to initialize the new page with. If `None`, the new
+/// - `src` is the physical page containing the data to initialize the page with. If `None`, the
 ///   page is initialized with zeros
-/// - `dst` is the virtual address at which the new page is mapped
-fn init_page(
+/// - `dst_phys` is the physical address of the page to initialize
+/// - `dst` is the virtual address at which the page is mapped
+/// - `flags` is the virtual memory flags of the mapping
+fn copy_page(
 	vmem: &mut VMem,
-	prot: u8,
-	src: Option<&RcFrame>,
+	src: Option<PhysAddr>,
+	dst_phys: PhysAddr,
 	dst: VirtAddr,
-) -> AllocResult<RcFrame> {
-	// Allocate destination page
-	let new_page = RcFrame::new(0, ZONE_USER, FrameOwner::Anon, 0)?;
+	flags: usize,
+) {
 	// Map source page to copy buffer if any
 	if let Some(src) = src {
-		vmem.map(src.phys_addr(), COPY_BUFFER, 0);
+		vmem.map(src, COPY_BUFFER, 0);
 	}
 	// Map destination page
-	let flags = vmem_flags(prot, false);
-	vmem.map(new_page.phys_addr(), dst, flags);
+	vmem.map(dst_phys, dst, flags);
 	// Copy or zero
 	unsafe {
 		// Required since the copy buffer is mapped without write permission
@@ -151,9 +159,46 @@ fn init_page(
 			}
 		});
 	}
+}
+
+// FIXME: SMAP and mapping the page to userspace before init (potential data leak to userspace)
+/// Initializes a new page and maps it at `dst`.
+///
+/// Arguments:
+/// - `vmem` is the transaction on which the page mapping takes place
+/// - `cgroup` is the cgroup the new page is charged to
+/// - `prot` is the memory protection for the newly mapped page
+/// - `src` is the page containing the data to initialize the new page with. If `None`, the new
+///   page is initialized with zeros
+/// - `dst` is the virtual address at which the new page is mapped
+fn init_page(
+	vmem: &mut VMem,
+	cgroup: &Arc<Cgroup>,
+	prot: u8,
+	src: Option<&RcFrame>,
+	dst: VirtAddr,
+) -> AllocResult<RcFrame> {
+	let new_page = RcFrame::new_user(cgroup)?;
+	let flags = vmem_flags(prot, false);
+	copy_page(
+		vmem,
+		src.map(RcFrame::phys_addr),
+		new_page.phys_addr(),
+		dst,
+		flags,
+	);
 	Ok(new_page)
 }
 
+/// Returns the physical address of the page of the huge page `frame` which is mapped at `addr`.
+///
+/// Pages of a huge page are mapped in the order of their virtual address, so that the page
+/// mapped at an address does not depend on the mapping containing it.
+#[inline]
+fn huge_sub_page(frame: &RcFrame, addr: VirtAddr) -> PhysAddr {
+	frame.phys_addr() + (addr.0 / PAGE_SIZE % HUGE_PAGE_PAGES) * PAGE_SIZE
+}
+
 /// A mapping in a memory space.
 #[derive(Debug)]
 pub struct MemMapping {
@@ -167,7 +212,7 @@ pub struct MemMapping {
 	pub(super) flags: u8,
 
 	/// The mapped file, if any
-	file: Option<Arc<File>>,
+	pub(super) file: Option<Arc<File>>,
 	/// The offset in the mapped file. If no file is mapped, this field is not relevant
 	off: u64,
 
@@ -187,6 +232,9 @@ impl MemMapping {
 	/// - `flags` the mapping's flags
 	/// - `file` is the mapped file. If `None`, no file is mapped
 	/// - `off` is the offset in `file`, if applicable
+	///
+	/// If `flags` contains [`MAP_DENYWRITE`] and `file` is open for writing, the function returns
+	/// [`utils::errno::ETXTBSY`].
 	pub fn new(
 		addr: *mut u8,
 		size: NonZeroUsize,
@@ -194,10 +242,13 @@ impl MemMapping {
 		flags: u8,
 		file: Option<Arc<File>>,
 		off: u64,
-	) -> AllocResult<Self> {
+	) -> EResult<Self> {
 		debug_assert!(addr.is_aligned_to(PAGE_SIZE));
 		let mut pages = Vec::new();
 		pages.resize(size.get(), None)?;
+		if let Some(node) = Self::denied_node(flags, file.as_ref()) {
+			node.deny_write_access()?;
+		}
 		Ok(Self {
 			addr,
 			size,
@@ -211,43 +262,80 @@ impl MemMapping {
 		})
 	}
 
+	/// Returns the node to which writing is denied by a mapping with the given `flags` and `file`,
+	/// if any.
+	fn denied_node(flags: u8, file: Option<&Arc<File>>) -> Option<&Arc<Node>> {
+		if flags & MAP_DENYWRITE == 0 {
+			return None;
+		}
+		file?.node()
+	}
+
+	/// Denies writing to the mapped file again, for a new mapping created from the current one.
+	fn dup_deny_write(&self) {
+		if let Some(node) = Self::denied_node(self.flags, self.file.as_ref()) {
+			// Cannot fail since `self` already denies writing
+			node.deny_write_access().unwrap();
+		}
+	}
+
 	/// Maps the page at the offset `offset` of the mapping, onto `vmem`.
 	///
 	/// `write` tells whether the page has to be mapped for writing.
 	///
+	/// Newly allocated pages are charged to `cgroup`.
+	///
 	/// If no underlying physical memory exist for this offset, the function might allocate it.
 	///
 	/// **Note**: it is assumed the associated virtual memory is bound.
 	///
 	/// If a file is mapped, the function uses the page cache's content (potentially populating it
-	/// by reading from the disk).
+	/// by reading from the disk). Private mappings share the page cache's pages until written to.
+	///
+	/// The function returns `true` if the page had to be read from the disk (major fault).
 	///
 	/// Upon allocation failure, or failure to read a page from the disk, the function returns an
 	/// error.
-	pub fn map(&mut self, offset: usize, vmem: &mut VMem, write: bool) -> EResult<()> {
+	pub fn map(
+		&mut self,
+		offset: usize,
+		vmem: &mut VMem,
+		cgroup: &Arc<Cgroup>,
+		write: bool,
+	) -> EResult<bool> {
+		if self.flags & MAP_HUGETLB != 0 {
+			self.map_huge(offset, vmem)?;
+			return Ok(false);
+		}
 		let virtaddr = VirtAddr::from(self.addr) + offset * PAGE_SIZE;
 		if let Some(page) = &self.pages[offset] {
 			// A page is already present, use it
 			let mut phys_addr = page.phys_addr();
-			let pending_cow = self.flags & MAP_SHARED == 0 && page.is_shared();
+			// A private mapping never writes to the page cache
+			let pending_cow =
+				self.flags & MAP_SHARED == 0 && (page.is_shared() || page.is_cached());
+			if pending_cow && !write {
+				// Reading does not require a copy, map the shared page in read-only
+				vmem.map(phys_addr, virtaddr, vmem_flags(self.prot, true));
+				return Ok(false);
+			}
 			if pending_cow {
-				// The page cannot be shared: we need our own copy (regardless of whether we are
-				// reading or writing)
-				let page = init_page(vmem, self.prot, Some(page), virtaddr)?;
+				// The page cannot be shared: we need our own copy
+				let page = init_page(vmem, cgroup, self.prot, Some(page), virtaddr)?;
 				phys_addr = page.phys_addr();
 				self.pages[offset] = Some(MappedFrame::new(page));
 			}
 			// Map the page
 			let flags = vmem_flags(self.prot, false);
 			vmem.map(phys_addr, virtaddr, flags);
-			return Ok(());
+			return Ok(false);
 		}
 		// Else, Allocate a page
 		match &self.file {
 			// Anonymous mapping
 			None => {
 				let phys_addr = if write {
-					let page = init_page(vmem, self.prot, None, virtaddr)?;
+					let page = init_page(vmem, cgroup, self.prot, None, virtaddr)?;
 					let phys_addr = page.phys_addr();
 					self.pages[offset] = Some(MappedFrame::new(page));
 					phys_addr
@@ -264,21 +352,110 @@ impl MemMapping {
 				// Get page from file
 				let node = file.node().unwrap();
 				let file_off = self.off / PAGE_SIZE as u64 + offset as u64;
+				let major = node.mapped.get(file_off).is_none();
 				let mut page = node.node_ops.read_page(node, file_off)?;
-				// If the mapping is private, we need our own copy
-				if self.flags & MAP_PRIVATE != 0 {
-					page = init_page(vmem, self.prot, Some(&page), virtaddr)?;
+				// Sequential accesses are likely to follow, read the next pages in the background
+				if major {
+					let end = self.off / PAGE_SIZE as u64 + self.size.get() as u64;
+					let ra_end = end.min(file_off + 1 + cache::READAHEAD_PAGES);
+					cache::readahead(node, file_off + 1, ra_end);
+				}
+				// If the mapping is private, we need our own copy to write. Until then, the page
+				// cache's page is mapped read-only
+				if write && self.flags & MAP_PRIVATE != 0 {
+					page = init_page(vmem, cgroup, self.prot, Some(&page), virtaddr)?;
 				}
 				let phys_addr = page.phys_addr();
 				self.pages[offset] = Some(MappedFrame::new(page));
 				// Map
 				let flags = vmem_flags(self.prot, !write);
 				vmem.map(phys_addr, virtaddr, flags);
+				return Ok(major);
 			}
 		}
+		Ok(false)
+	}
+
+	/// Returns the range of pages of the mapping which are allocated at once when the page at
+	/// `offset` is accessed for the first time.
+	///
+	/// Only the accessed page is allocated, unless the mapping is backed by huge pages, in which
+	/// case the whole huge page is.
+	pub fn fault_range(&self, offset: usize) -> Range<usize> {
+		if self.flags & MAP_HUGETLB == 0 {
+			return offset..(offset + 1);
+		}
+		let virtaddr = VirtAddr::from(self.addr) + offset * PAGE_SIZE;
+		let sub = virtaddr.0 / PAGE_SIZE % HUGE_PAGE_PAGES;
+		let end = offset + HUGE_PAGE_PAGES - sub;
+		offset.saturating_sub(sub)..end.min(self.size.get())
+	}
+
+	/// Maps the page at the offset `offset` of a mapping backed by huge pages, onto `vmem`.
+	///
+	/// Huge pages are taken from the pool of reserved huge pages. If the pool is empty, the
+	/// function returns [`utils::errno::ENOMEM`].
+	fn map_huge(&mut self, offset: usize, vmem: &mut VMem) -> EResult<()> {
+		let virtaddr = VirtAddr::from(self.addr) + offset * PAGE_SIZE;
+		let flags = vmem_flags(self.prot, false);
+		let range = self.fault_range(offset);
+		if let Some(page) = &self.pages[offset] {
+			// Each page of the huge page holds a reference to it. If there are more references
+			// than pages in this mapping, the huge page is shared
+			let local = self.pages[range.clone()]
+				.iter()
+				.flatten()
+				.filter(|p| p.phys_addr() == page.phys_addr())
+				.count();
+			let pending_cow =
+				self.flags & MAP_SHARED == 0 && page.map_counter().load(Acquire) > local;
+			if !pending_cow {
+				vmem.map(huge_sub_page(page, virtaddr), virtaddr, flags);
+				return Ok(());
+			}
+		}
+		// Allocate a huge page, then copy or zero each of its pages in the mapping
+		let frame = hugetlb::alloc().ok_or_else(|| errno!(ENOMEM))?;
+		let begin = VirtAddr::from(self.addr) + range.start * PAGE_SIZE;
+		for (i, page) in self.pages[range].iter_mut().enumerate() {
+			let dst = begin + i * PAGE_SIZE;
+			let src = page.as_deref().map(|p| huge_sub_page(p, dst));
+			copy_page(vmem, src, huge_sub_page(&frame, dst), dst, flags);
+			*page = Some(MappedFrame::new(frame.clone()));
+		}
 		Ok(())
 	}
 
+	/// Returns the range of virtual addresses covered by the mapping.
+	pub fn range(&self) -> Range<*mut u8> {
+		self.addr..self.addr.wrapping_add(self.size.get() * PAGE_SIZE)
+	}
+
+	/// Returns the number of pages of the mapping that are backed by physical memory.
+	pub fn resident_pages(&self) -> usize {
+		self.pages.iter().filter(|p| p.is_some()).count()
+	}
+
+	/// Returns a new mapping for the `size` pages of the current mapping, starting at the page
+	/// `begin`.
+	///
+	/// Physical pages are shared with the current mapping.
+	pub fn sub_mapping(&self, begin: usize, size: NonZeroUsize) -> AllocResult<Self> {
+		let pages = Vec::try_from(&self.pages[begin..begin + size.get()])?;
+		self.dup_deny_write();
+		Ok(Self {
+			addr: self.addr.wrapping_add(begin * PAGE_SIZE),
+			size,
+			prot: self.prot,
+			flags: self.flags,
+
+			file: self.file.clone(),
+			off: self.off + (begin * PAGE_SIZE) as u64,
+
+			pages,
+		})
+	}
+
 	/// Splits the current mapping, creating up to two new mappings and one gap.
 	///
 	/// Arguments:
@@ -298,19 +475,7 @@ impl MemMapping {
 		size: usize,
 	) -> AllocResult<(Option<Self>, Option<MemGap>, Option<Self>)> {
 		let prev = NonZeroUsize::new(begin)
-			.map(|size| {
-				Ok(MemMapping {
-					addr: self.addr,
-					size,
-					prot: self.prot,
-					flags: self.flags,
-
-					file: self.file.clone(),
-					off: self.off,
-
-					pages: Vec::try_from(&self.pages[..size.get()])?,
-				})
-			})
+			.map(|size| self.sub_mapping(0, size))
 			.transpose()?;
 		let gap = NonZeroUsize::new(size).map(|size| {
 			let addr = VirtAddr::from(self.addr) + begin * PAGE_SIZE;
@@ -323,19 +488,7 @@ impl MemMapping {
 			.get()
 			.checked_sub(end)
 			.and_then(NonZeroUsize::new)
-			.map(|size| {
-				Ok(Self {
-					addr: self.addr.wrapping_add(end * PAGE_SIZE),
-					size,
-					prot: self.prot,
-					flags: self.flags,
-
-					file: self.file.clone(),
-					off: self.off + end as u64,
-
-					pages: Vec::try_from(&self.pages[end..])?,
-				})
-			})
+			.map(|size| self.sub_mapping(end, size))
 			.transpose()?;
 		Ok((prev, gap, next))
 	}
@@ -373,6 +526,8 @@ impl MemMapping {
 
 impl TryClone for MemMapping {
 	fn try_clone(&self) -> AllocResult<Self> {
+		let pages = self.pages.try_clone()?;
+		self.dup_deny_write();
 		Ok(Self {
 			addr: self.addr,
 			size: self.size,
@@ -382,7 +537,15 @@ impl TryClone for MemMapping {
 			file: self.file.clone(),
 			off: self.off,
 
-			pages: self.pages.try_clone()?,
+			pages,
 		})
 	}
 }
+
+impl Drop for MemMapping {
+	fn drop(&mut self) {
+		if let Some(node) = Self::denied_node(self.flags, self.file.as_ref()) {
+			node.allow_write_access();
+		}
+	}
+}
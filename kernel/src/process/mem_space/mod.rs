@@ -24,6 +24,7 @@
 //! - Gap: A chunk of virtual memory that is available to be allocated
 
 mod gap;
+pub mod ksm;
 mod mapping;
 mod transaction;
 
@@ -32,21 +33,36 @@ use crate::{
 		idt,
 		paging::{PAGE_FAULT_INSTRUCTION, PAGE_FAULT_WRITE},
 	},
-	file::{File, perm::AccessProfile, vfs},
+	crypto::rand,
+	file::{
+		File,
+		perm::{AccessProfile, CAP_SYS_RAWIO},
+		vfs,
+	},
 	memory,
-	memory::{PROCESS_END, VirtAddr, cache::RcFrame, vmem::VMem},
-	process::{mem_space::mapping::MappedFrame, scheduler::core_local},
+	memory::{
+		PROCESS_END, VirtAddr, cache::RcFrame, hugetlb::HUGE_PAGE_PAGES, user::UserSlice,
+		vmem::VMem,
+	},
+	process::{cgroup::Cgroup, mem_space::mapping::MappedFrame, scheduler::core_local},
 	sync::mutex::IntMutex,
 };
 use core::{
-	alloc::AllocError, cmp::min, ffi::c_void, fmt, hint::unlikely, mem, num::NonZeroUsize,
+	alloc::AllocError,
+	cmp::min,
+	ffi::c_void,
+	fmt,
+	hint::unlikely,
+	mem,
+	num::NonZeroUsize,
+	sync::atomic::{AtomicU8, AtomicUsize, Ordering::Relaxed},
 };
 use gap::MemGap;
 use mapping::MemMapping;
 use transaction::MemSpaceTransaction;
 use utils::{
 	TryClone,
-	collections::{btreemap::BTreeMap, vec::Vec},
+	collections::{btreemap::BTreeMap, interval_tree::IntervalTree, vec::Vec},
 	errno,
 	errno::{AllocResult, CollectResult, EResult},
 	limits::PAGE_SIZE,
@@ -69,6 +85,26 @@ pub const MAP_PRIVATE: u8 = 0x2;
 pub const MAP_FIXED: u8 = 0x10;
 /// The mapping is not backed by any file
 pub const MAP_ANONYMOUS: u8 = 0x20;
+/// The mapping is a stack, growing downward when memory right below it is accessed.
+///
+/// Linux's value for this flag does not fit in the flags of a mapping, so `mmap` translates it.
+pub const MAP_GROWSDOWN: u8 = 0x80;
+/// Writing to the mapped file is denied as long as the mapping exists.
+///
+/// Linux's value for this flag does not fit in the flags of a mapping, so `mmap` translates it.
+pub const MAP_DENYWRITE: u8 = 0x8;
+/// The mapping is backed by huge pages, taken from the pool of reserved huge pages.
+///
+/// Linux's value for this flag does not fit in the flags of a mapping, so `mmap` translates it.
+pub const MAP_HUGETLB: u8 = 0x4;
+/// The pages of the mapping can be merged with identical pages, by [`ksm`].
+///
+/// This flag is set by `madvise` with `MADV_MERGEABLE`, not by `mmap`.
+pub const MAP_MERGEABLE: u8 = 0x40;
+
+/// The minimum number of pages by which a stack grows, to limit the number of page faults and
+/// mappings.
+const STACK_GROW_STEP: usize = 16;
 
 /// The virtual address of the buffer used to map pages for copy.
 const COPY_BUFFER: VirtAddr = VirtAddr(PROCESS_END.0 - PAGE_SIZE);
@@ -76,12 +112,102 @@ const COPY_BUFFER: VirtAddr = VirtAddr(PROCESS_END.0 - PAGE_SIZE);
 /// Type representing a memory page.
 pub type Page = [u8; PAGE_SIZE];
 
+/// The number of random bits in the page offset of the base of the region where mappings are
+/// placed.
+const MMAP_RND_BITS: u32 = 28;
+/// The number of random bits in the page offset of the base of the region where mappings are
+/// placed, in compatibility mode.
+const COMPAT_MMAP_RND_BITS: u32 = 8;
+/// The number of random bits in the page offset of the top of the main stack.
+const STACK_RND_BITS: u32 = 22;
+/// The number of random bits in the page offset of the top of the main stack, in compatibility
+/// mode.
+const COMPAT_STACK_RND_BITS: u32 = 11;
+/// The number of random bits in the page offset of the beginning of the heap.
+const BRK_RND_BITS: u32 = 13;
+
+/// The address space layout randomization policy, set through
+/// `/proc/sys/kernel/randomize_va_space`:
+/// - `0`: no randomization
+/// - `1`: the main stack, the base of mappings (including the vDSO) and position-independent
+///   executables are placed at random addresses
+/// - `2`: the beginning of the heap is randomized as well
+pub static RANDOMIZE_VA_SPACE: AtomicU8 = AtomicU8::new(2);
+
+/// The lowest address at which a process is allowed to place a mapping, unless it has the
+/// [`CAP_SYS_RAWIO`] capability, set through `/proc/sys/vm/mmap_min_addr`.
+///
+/// Keeping the bottom of the memory space unmapped ensures dereferencing a null pointer faults,
+/// including from the kernel.
+pub static MMAP_MIN_ADDR: AtomicUsize = AtomicUsize::new(0x10000);
+
+/// Tells whether the agent with the access profile `ap` is allowed to map memory at `addr`.
+///
+/// The first page can never be mapped.
+pub fn mmap_addr_allowed(ap: &AccessProfile, addr: VirtAddr) -> bool {
+	addr.0 >= PAGE_SIZE
+		&& (addr.0 >= MMAP_MIN_ADDR.load(Relaxed) || ap.has_capability(CAP_SYS_RAWIO))
+}
+
+/// Returns a random number of pages, below `2^bits`, by which a region of a memory space is
+/// offset.
+///
+/// If the randomization policy is below `level` (see [`RANDOMIZE_VA_SPACE`]), the function
+/// returns `0`.
+pub fn random_pages(bits: u32, level: u8) -> usize {
+	if RANDOMIZE_VA_SPACE.load(Relaxed) < level {
+		return 0;
+	}
+	let mut buf = [0u8; size_of::<usize>()];
+	// If the entropy pool is not initialized yet, the region is not randomized
+	let _ = rand::getrandom(UserSlice::from_slice_mut(&mut buf), 0);
+	usize::from_ne_bytes(buf) & ((1 << bits) - 1)
+}
+
+/// Selects the number of random bits to use, depending on `compat`.
+fn rnd_bits(compat: bool, bits: u32, compat_bits: u32) -> u32 {
+	#[cfg(target_arch = "x86_64")]
+	if !compat {
+		return bits;
+	}
+	let _ = (compat, bits);
+	compat_bits
+}
+
 /// Tells whether the address is in bound of the userspace.
 pub fn bound_check(addr: usize, n: usize) -> bool {
 	addr >= PAGE_SIZE && addr.saturating_add(n) <= COPY_BUFFER.0
 }
 
-// TODO Add a variant for ASLR
+/// Returns the address of the top of the main stack of a program.
+///
+/// `compat` tells whether the program runs in 32 bit mode, in which case the stack must remain
+/// in the addressable memory.
+pub fn stack_top(compat: bool) -> VirtAddr {
+	if compat {
+		min(COPY_BUFFER, VirtAddr(0xc0000000 - PAGE_SIZE))
+	} else {
+		COPY_BUFFER
+	}
+}
+
+/// Returns a randomized address of the top of the main stack of a program, below
+/// [`stack_top`].
+///
+/// `compat` tells whether the program runs in 32 bit mode.
+pub fn random_stack_top(compat: bool) -> VirtAddr {
+	let bits = rnd_bits(compat, STACK_RND_BITS, COMPAT_STACK_RND_BITS);
+	stack_top(compat) - random_pages(bits, 1) * PAGE_SIZE
+}
+
+/// Returns a randomized base address for the beginning of the heap of a program, whose image
+/// ends at `end`.
+///
+/// `end` MUST be page-aligned.
+pub fn random_brk(end: VirtAddr) -> VirtAddr {
+	end + random_pages(BRK_RND_BITS, 2) * PAGE_SIZE
+}
+
 /// Enumeration of constraints for the selection of the virtual address for a memory mapping.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MapConstraint {
@@ -110,8 +236,9 @@ impl MapConstraint {
 			// Checking the address is within userspace is required because `Fixed` allocations can
 			// take place *outside of gaps* but *not inside the kernelspace*
 			MapConstraint::Fixed(addr) => {
-				// The copy buffer is located right before the kernelspace
-				addr < COPY_BUFFER && addr.is_aligned_to(PAGE_SIZE)
+				// The copy buffer is located right before the kernelspace. The first page remains
+				// unmapped so that null pointers are never valid
+				addr.0 >= PAGE_SIZE && addr < COPY_BUFFER && addr.is_aligned_to(PAGE_SIZE)
 			}
 			MapConstraint::Hint(addr) => addr.is_aligned_to(PAGE_SIZE),
 			_ => true,
@@ -174,10 +301,14 @@ struct MemSpaceState {
 	/// The collection is sorted by pointer to the beginning of the mapping on the virtual
 	/// memory.
 	gaps: BTreeMap<VirtAddr, MemGap>,
-	/// Binary tree storing the list of memory mappings.
+	/// Interval tree storing the list of memory mappings.
 	///
-	/// Sorted by pointer to the beginning of the mapping on the virtual memory.
-	mappings: BTreeMap<*mut u8, MemMapping>,
+	/// Sorted by pointer to the beginning of the mapping on the virtual memory. Mappings never
+	/// overlap.
+	mappings: IntervalTree<*mut u8, MemMapping>,
+
+	/// The address above which mappings are placed first, when no address is requested.
+	mmap_base: VirtAddr,
 
 	/// The initial pointer of the `[s]brk` system calls.
 	brk_init: VirtAddr,
@@ -186,19 +317,30 @@ struct MemSpaceState {
 
 	/// The number of used virtual memory pages.
 	vmem_usage: usize,
+	/// The number of pages of physical memory used by mappings (resident set size).
+	rss: usize,
+	/// The highest value reached by `rss`.
+	max_rss: usize,
 }
 
 impl MemSpaceState {
-	/// Returns a reference to a gap with at least size `size`.
+	/// Returns a gap in which a mapping of `size` pages fits at an address aligned to `align`
+	/// pages, along with the offset of this address in the gap, in pages.
 	///
-	/// `size` is the minimum size of the gap to be returned.
+	/// Gaps above the base of mappings are searched first.
 	///
 	/// If no gap large enough is available, the function returns `None`.
-	fn get_gap(&self, size: NonZeroUsize) -> Option<&MemGap> {
-		self.gaps
-			.iter()
-			.map(|(_, g)| g)
-			.find(|g| g.get_size() >= size)
+	fn get_aligned_gap(&self, size: NonZeroUsize, align: usize) -> Option<(&MemGap, usize)> {
+		let find = |min: usize| {
+			self.gaps.iter().map(|(_, g)| g).find_map(|g| {
+				let gap_begin = g.get_begin().0 / PAGE_SIZE;
+				let begin = gap_begin.max(min);
+				let off = begin.next_multiple_of(align) - gap_begin;
+				let end = off.checked_add(size.get())?;
+				(end <= g.get_size().get()).then_some((g, off))
+			})
+		};
+		find(self.mmap_base.0 / PAGE_SIZE).or_else(|| find(0))
 	}
 
 	/// Returns a reference to the gap containing the given virtual address.
@@ -214,8 +356,7 @@ impl MemSpaceState {
 	///
 	/// If no mapping contains the address, the function returns `None`.
 	pub fn get_mapping_for_addr(&self, addr: VirtAddr) -> Option<&MemMapping> {
-		self.mappings
-			.cmp_get(|key, value| range_cmp(*key as usize, value.size.get() * PAGE_SIZE, addr.0))
+		self.mappings.get_containing(addr.as_ptr()).map(|(_, m)| m)
 	}
 
 	/// Returns a mutable reference to the memory mapping containing the given virtual
@@ -223,14 +364,15 @@ impl MemSpaceState {
 	///
 	/// If no mapping contains the address, the function returns `None`.
 	pub fn get_mut_mapping_for_addr(&mut self, addr: VirtAddr) -> Option<&mut MemMapping> {
-		self.mappings.cmp_get_mut(|key, value| {
-			range_cmp(*key as usize, value.size.get() * PAGE_SIZE, addr.0)
-		})
+		self.mappings
+			.get_containing_mut(addr.as_ptr())
+			.map(|(_, m)| m)
 	}
 }
 
 /// Executable program information.
-#[derive(Clone)]
+///
+/// The program cannot be written to as long as the structure exists.
 pub struct ExeInfo {
 	/// The VFS entry of the program loaded on this memory space.
 	pub exe: Arc<vfs::Entry>,
@@ -243,6 +385,30 @@ pub struct ExeInfo {
 	pub envp_begin: VirtAddr,
 	/// Address to the end of program environment.
 	pub envp_end: VirtAddr,
+	/// Address of the signal return trampoline in the vDSO. If null, there is none.
+	pub sigreturn: VirtAddr,
+}
+
+impl Clone for ExeInfo {
+	fn clone(&self) -> Self {
+		// Cannot fail since `self` already denies writing
+		self.exe.node().deny_write_access().unwrap();
+		Self {
+			exe: self.exe.clone(),
+
+			argv_begin: self.argv_begin,
+			argv_end: self.argv_end,
+			envp_begin: self.envp_begin,
+			envp_end: self.envp_end,
+			sigreturn: self.sigreturn,
+		}
+	}
+}
+
+impl Drop for ExeInfo {
+	fn drop(&mut self) {
+		self.exe.node().allow_write_access();
+	}
 }
 
 /// A virtual memory space.
@@ -262,8 +428,10 @@ pub struct MemSpace {
 impl MemSpace {
 	/// Creates a new virtual memory object.
 	///
-	/// `exe` is the VFS entry of the program loaded on the memory space.
-	pub fn new(exe: Arc<vfs::Entry>) -> AllocResult<Arc<Self>> {
+	/// `exe` is the VFS entry of the program loaded on the memory space. If it is open for
+	/// writing, the function returns [`errno::ETXTBSY`].
+	pub fn new(exe: Arc<vfs::Entry>) -> EResult<Arc<Self>> {
+		exe.node().deny_write_access()?;
 		let s = Self {
 			state: Default::default(),
 			vmem: IntMutex::new(unsafe { VMem::new() }),
@@ -275,6 +443,7 @@ impl MemSpace {
 				argv_end: Default::default(),
 				envp_begin: Default::default(),
 				envp_end: Default::default(),
+				sigreturn: Default::default(),
 			},
 		};
 		// Create the default gap of memory which is present at the beginning
@@ -284,7 +453,7 @@ impl MemSpace {
 		let mut transaction = MemSpaceTransaction::new(&s);
 		transaction.insert_gap(gap)?;
 		transaction.commit();
-		Arc::new(s)
+		Ok(Arc::new(s)?)
 	}
 
 	/// Returns the number of virtual memory pages in the memory space.
@@ -293,6 +462,18 @@ impl MemSpace {
 		self.state.lock().vmem_usage
 	}
 
+	/// Returns the number of physical memory pages used by the memory space.
+	#[inline]
+	pub fn get_rss(&self) -> usize {
+		self.state.lock().rss
+	}
+
+	/// Returns the highest number of physical memory pages used by the memory space.
+	#[inline]
+	pub fn get_max_rss(&self) -> usize {
+		self.state.lock().max_rss
+	}
+
 	fn map_impl(
 		transaction: &mut MemSpaceTransaction,
 		map_constraint: MapConstraint,
@@ -305,6 +486,12 @@ impl MemSpace {
 		if !map_constraint.is_valid() {
 			return Err(errno!(ENOMEM));
 		}
+		// Mappings backed by huge pages must be aligned on them
+		let align = if flags & MAP_HUGETLB != 0 {
+			HUGE_PAGE_PAGES
+		} else {
+			1
+		};
 		// Get suitable gap for the given constraint
 		let (gap, gap_off) = match map_constraint {
 			MapConstraint::Fixed(addr) => {
@@ -330,15 +517,18 @@ impl MemSpace {
 					})
 					// Hint cannot be satisfied. Get a large enough gap
 					.or_else(|| {
-						let gap = transaction.state.get_gap(size)?;
-						Some((gap.clone(), 0))
+						let (gap, off) = transaction.state.get_aligned_gap(size, align)?;
+						Some((gap.clone(), off))
 					})
 					.ok_or(AllocError)?
 					.clone()
 			}
 			MapConstraint::None => {
-				let gap = transaction.state.get_gap(size).ok_or(AllocError)?.clone();
-				(gap, 0)
+				let (gap, off) = transaction
+					.state
+					.get_aligned_gap(size, align)
+					.ok_or(AllocError)?;
+				(gap.clone(), off)
 			}
 		};
 		let addr = (gap.get_begin() + gap_off * PAGE_SIZE).as_ptr();
@@ -352,7 +542,7 @@ impl MemSpace {
 			transaction.insert_gap(new_gap)?;
 		}
 		// Create the mapping
-		Ok(MemMapping::new(addr, size, prot, flags, file, off)?)
+		MemMapping::new(addr, size, prot, flags, file, off)
 	}
 
 	/// Maps a chunk of memory.
@@ -399,21 +589,19 @@ impl MemSpace {
 	}
 
 	/// Maps a chunk of memory population with the given static pages.
-	pub fn map_special(&self, prot: u8, flags: u8, pages: &[RcFrame]) -> AllocResult<*mut u8> {
+	pub fn map_special(
+		&self,
+		map_constraint: MapConstraint,
+		prot: u8,
+		flags: u8,
+		pages: &[RcFrame],
+	) -> AllocResult<*mut u8> {
 		let Some(len) = NonZeroUsize::new(pages.len()) else {
 			return Err(AllocError);
 		};
 		let mut transaction = MemSpaceTransaction::new(self);
-		let mut map = Self::map_impl(
-			&mut transaction,
-			MapConstraint::None,
-			len,
-			prot,
-			flags,
-			None,
-			0,
-		)
-		.map_err(|_| AllocError)?;
+		let mut map = Self::map_impl(&mut transaction, map_constraint, len, prot, flags, None, 0)
+			.map_err(|_| AllocError)?;
 		// Populate
 		map.pages
 			.iter_mut()
@@ -518,6 +706,53 @@ impl MemSpace {
 		Ok(())
 	}
 
+	/// Returns the file mapped by the mapping beginning at `addr`, along with the size of the
+	/// mapping in pages.
+	///
+	/// If no mapping begins at `addr`, or if it does not map a file, the function returns `None`.
+	pub fn get_mapped_file(&self, addr: VirtAddr) -> Option<(Arc<File>, NonZeroUsize)> {
+		let state = self.state.lock();
+		let mapping = state
+			.get_mapping_for_addr(addr)
+			.filter(|m| VirtAddr::from(m.addr) == addr)?;
+		Some((mapping.file.clone()?, mapping.size))
+	}
+
+	/// Returns the frame backing the page at `addr`, allocating it first if necessary, along with
+	/// a boolean telling whether the page is writable.
+	///
+	/// A page pending Copy-On-Write is copied, so that the frame is not shared with other memory
+	/// spaces. Newly allocated pages are charged to `cgroup`.
+	///
+	/// The memory space must be bound. If `addr` is not mapped, the function returns `None`.
+	pub fn get_page(
+		&self,
+		addr: VirtAddr,
+		cgroup: &Arc<Cgroup>,
+	) -> EResult<Option<(RcFrame, bool)>> {
+		let mut state = self.state.lock();
+		let mut vmem = self.vmem.lock();
+		let Some(mapping) = state.get_mut_mapping_for_addr(addr) else {
+			return Ok(None);
+		};
+		// Guest memory is mapped page by page
+		if unlikely(mapping.flags & MAP_HUGETLB != 0) {
+			return Err(errno!(EINVAL));
+		}
+		let page_offset = (addr.0 - mapping.addr as usize) / PAGE_SIZE;
+		let resident = mapping.pages[page_offset].is_some();
+		mapping.map(page_offset, &mut vmem, cgroup, true)?;
+		let writable = mapping.prot & PROT_WRITE != 0;
+		let Some(frame) = mapping.pages[page_offset].as_deref().cloned() else {
+			return Ok(None);
+		};
+		if !resident {
+			state.rss += 1;
+			state.max_rss = state.max_rss.max(state.rss);
+		}
+		Ok(Some((frame, writable)))
+	}
+
 	/// Binds the memory space to the current kernel.
 	pub fn bind(this: &Arc<Self>) {
 		this.vmem.lock().bind();
@@ -569,10 +804,14 @@ impl MemSpace {
 				gaps: state.gaps.try_clone()?,
 				mappings,
 
+				mmap_base: state.mmap_base,
+
 				brk_init: state.brk_init,
 				brk: state.brk,
 
 				vmem_usage: state.vmem_usage,
+				rss: state.rss,
+				max_rss: state.rss,
 			}),
 			vmem: IntMutex::new(unsafe { VMem::new() }),
 
@@ -580,6 +819,68 @@ impl MemSpace {
 		})
 	}
 
+	/// Updates the mappings on the range of `len` bytes starting at `addr`, splitting them at the
+	/// bounds of the range.
+	///
+	/// For each mapping in the range, `update` returns the new protection and flags of the part
+	/// of the mapping in the range. If it returns `None`, the mapping is left untouched.
+	///
+	/// If a page in the range is not mapped, the function returns [`errno::ENOMEM`].
+	fn update_range<F: FnMut(&MemMapping) -> EResult<Option<(u8, u8)>>>(
+		&self,
+		addr: VirtAddr,
+		len: usize,
+		mut update: F,
+	) -> EResult<()> {
+		let Some(size) = NonZeroUsize::new(len.div_ceil(PAGE_SIZE)) else {
+			return Ok(());
+		};
+		let mut transaction = MemSpaceTransaction::new(self);
+		let mut i = 0;
+		while i < size.get() {
+			// The current page's beginning
+			let page_addr = addr + i * PAGE_SIZE;
+			// The mapping containing the page
+			let mapping = transaction
+				.state
+				.get_mapping_for_addr(page_addr)
+				.ok_or_else(|| errno!(ENOMEM))?;
+			// The pointer to the beginning of the mapping
+			let mapping_begin = mapping.addr;
+			// The offset in the mapping to the beginning of pages to modify
+			let inner_off = (page_addr.0 - mapping_begin as usize) / PAGE_SIZE;
+			// The number of pages to modify in the mapping
+			let pages = min(size.get() - i, mapping.size.get() - inner_off);
+			i += pages;
+			let Some((prot, flags)) = update(mapping)? else {
+				continue;
+			};
+			// Split the mapping around the modified pages
+			let end = inner_off + pages;
+			let prev = NonZeroUsize::new(inner_off)
+				.map(|size| mapping.sub_mapping(0, size))
+				.transpose()?;
+			// Cannot fail since `pages` is never zero
+			let mut cur = mapping.sub_mapping(inner_off, NonZeroUsize::new(pages).unwrap())?;
+			cur.prot = prot;
+			cur.flags = flags;
+			let next = NonZeroUsize::new(mapping.size.get() - end)
+				.map(|size| mapping.sub_mapping(end, size))
+				.transpose()?;
+			// Replace the old mapping. Pages are mapped again with the new protection on fault
+			transaction.remove_mapping(mapping_begin)?;
+			if let Some(m) = prev {
+				transaction.insert_mapping(m)?;
+			}
+			transaction.insert_mapping(cur)?;
+			if let Some(m) = next {
+				transaction.insert_mapping(m)?;
+			}
+		}
+		transaction.commit();
+		Ok(())
+	}
+
 	/// Sets protection for the given range of memory.
 	///
 	/// Arguments:
@@ -588,24 +889,62 @@ impl MemSpace {
 	/// - `prot` is a set of mapping flags
 	/// - `access_profile` is the access profile to check permissions
 	///
-	/// If a mapping to be modified is associated with a file, and the file doesn't have the
-	/// matching permissions, the function returns an error.
+	/// If a shared mapping to be made writable is associated with a file, and the file cannot be
+	/// written, the function returns [`errno::EACCES`].
+	///
+	/// If a page in the range is not mapped, the function returns [`errno::ENOMEM`].
 	pub fn set_prot(
 		&self,
-		_addr: *mut c_void,
-		_len: usize,
-		_prot: u8,
-		_access_profile: &AccessProfile,
+		addr: *mut c_void,
+		len: usize,
+		prot: u8,
+		access_profile: &AccessProfile,
 	) -> EResult<()> {
-		// TODO Iterate on mappings in the range:
-		//		If the mapping is shared and associated to a file, check file permissions match
-		// `prot` (only write)
-		//		Split the mapping if needed
-		//		Set permissions
-		//		Update vmem
+		self.update_range(VirtAddr::from(addr), len, |mapping| {
+			if prot & PROT_WRITE != 0
+				&& mapping.flags & MAP_SHARED != 0
+				&& let Some(file) = &mapping.file
+			{
+				let stat = file.stat()?;
+				if unlikely(!access_profile.can_write_file(&stat)) {
+					return Err(errno!(EACCES));
+				}
+			}
+			Ok((mapping.prot != prot).then_some((prot, mapping.flags)))
+		})
+	}
+
+	/// Tells whether the pages in the range of `len` bytes starting at `addr` can be merged with
+	/// identical pages, by [`ksm`].
+	///
+	/// Pages which are already merged remain so until they are written.
+	///
+	/// If a page in the range is not mapped, the function returns [`errno::ENOMEM`].
+	pub fn set_mergeable(&self, addr: VirtAddr, len: usize, mergeable: bool) -> EResult<()> {
+		self.update_range(addr, len, |mapping| {
+			let flags = if mergeable {
+				mapping.flags | MAP_MERGEABLE
+			} else {
+				mapping.flags & !MAP_MERGEABLE
+			};
+			Ok((mapping.flags != flags).then_some((mapping.prot, flags)))
+		})?;
+		if mergeable {
+			ksm::enable();
+		}
 		Ok(())
 	}
 
+	/// Sets a randomized base for the region where mappings are placed.
+	///
+	/// This function MUST be called *only once*, before any mapping is created.
+	///
+	/// `compat` tells whether the program runs in 32 bit mode.
+	pub fn randomize_mmap_base(&mut self, compat: bool) {
+		let bits = rnd_bits(compat, MMAP_RND_BITS, COMPAT_MMAP_RND_BITS);
+		self.state.lock().mmap_base = memory::ALLOC_BEGIN + random_pages(bits, 1) * PAGE_SIZE;
+	}
+
 	/// Sets the initial pointer for the `brk` syscall.
 	///
 	/// This function MUST be called *only once*, before the program starts.
@@ -670,6 +1009,21 @@ impl MemSpace {
 		addr
 	}
 
+	/// Tells whether all the pages in the range of `pages` pages starting at `addr` are mapped.
+	pub fn is_mapped(&self, addr: VirtAddr, pages: usize) -> bool {
+		let state = self.state.lock();
+		let mut i = 0;
+		while i < pages {
+			let page_addr = addr + i * PAGE_SIZE;
+			let Some(mapping) = state.get_mapping_for_addr(page_addr) else {
+				return false;
+			};
+			let inner_off = (page_addr.0 - mapping.addr as usize) / PAGE_SIZE;
+			i += mapping.size.get() - inner_off;
+		}
+		true
+	}
+
 	/// Synchronizes memory to the backing storage on the given range.
 	///
 	/// Arguments:
@@ -689,6 +1043,69 @@ impl MemSpace {
 		Ok(())
 	}
 
+	/// Grows the stack located right above `addr`, so that it contains `addr`.
+	///
+	/// A stack is made of adjacent mappings with the [`MAP_GROWSDOWN`] flag. It grows by
+	/// inserting a new mapping below it, as long as its total size does not exceed
+	/// `limit`, in bytes, and at least one page of guard remains between it and the
+	/// mapping below.
+	///
+	/// If the stack has been grown, the function returns `true`.
+	fn grow_stack<F: FnOnce() -> usize>(&self, addr: VirtAddr, limit: F) -> EResult<bool> {
+		let mut transaction = MemSpaceTransaction::new(self);
+		let Some(gap) = transaction.state.get_gap_for_addr(addr).cloned() else {
+			return Ok(false);
+		};
+		// Compute the current size of the stack above the gap
+		let mut size = 0;
+		let mut prot = 0;
+		loop {
+			let next = gap.get_end() + size * PAGE_SIZE;
+			match transaction.state.mappings.get(&next.as_ptr()) {
+				Some(m) if m.flags & MAP_GROWSDOWN != 0 => {
+					// The new mapping has the same protection as the bottom of the stack
+					if size == 0 {
+						prot = m.prot;
+					}
+					size += m.size.get();
+				}
+				_ => break,
+			}
+		}
+		if size == 0 {
+			return Ok(false);
+		}
+		// Keep a guard page below the stack
+		let lowest = gap.get_begin() + PAGE_SIZE;
+		let page = addr.down_align_to(PAGE_SIZE);
+		if page < lowest {
+			return Ok(false);
+		}
+		let max_pages = limit() / PAGE_SIZE;
+		let min_grow = (gap.get_end().0 - page.0) / PAGE_SIZE;
+		if size + min_grow > max_pages {
+			return Ok(false);
+		}
+		let grow = min_grow
+			.next_multiple_of(STACK_GROW_STEP)
+			.min(max_pages - size)
+			.min((gap.get_end().0 - lowest.0) / PAGE_SIZE);
+		let begin = gap.get_end() - grow * PAGE_SIZE;
+		let map = Self::map_impl(
+			&mut transaction,
+			MapConstraint::Fixed(begin),
+			// Cannot fail since `page` is below the end of the gap
+			NonZeroUsize::new(grow).unwrap(),
+			prot,
+			MAP_PRIVATE | MAP_ANONYMOUS | MAP_GROWSDOWN,
+			None,
+			0,
+		)?;
+		transaction.insert_mapping(map)?;
+		transaction.commit();
+		Ok(true)
+	}
+
 	/// Function called whenever the CPU triggered a page fault for the context.
 	///
 	/// This function determines whether the process should continue or not.
@@ -699,9 +1116,22 @@ impl MemSpace {
 	/// Arguments:
 	/// - `addr` is the virtual address of the wrong memory access that caused the fault.
 	/// - `code` is the error code given along with the error.
+	/// - `cgroup` is the cgroup newly allocated pages are charged to.
+	/// - `stack_limit` returns the maximum size of a stack in bytes, in case it has to grow.
 	///
 	/// If the process should continue, the function returns `true`, else `false`.
-	pub fn handle_page_fault(&self, addr: VirtAddr, code: u32) -> EResult<bool> {
+	pub fn handle_page_fault<F: FnOnce() -> usize>(
+		&self,
+		addr: VirtAddr,
+		code: u32,
+		cgroup: &Arc<Cgroup>,
+		stack_limit: F,
+	) -> EResult<bool> {
+		// An access right below a stack makes it grow
+		let mapped = self.state.lock().get_mapping_for_addr(addr).is_some();
+		if !mapped && !self.grow_stack(addr, stack_limit)? {
+			return Ok(false);
+		}
 		let mut state = self.state.lock();
 		let mut vmem = self.vmem.lock();
 		let Some(mapping) = state.get_mut_mapping_for_addr(addr) else {
@@ -717,7 +1147,19 @@ impl MemSpace {
 		}
 		// Map the accessed page
 		let page_offset = (addr.0 - mapping.addr as usize) / PAGE_SIZE;
-		mapping.map(page_offset, &mut vmem, write)?;
+		let resident = mapping.pages[page_offset].is_some();
+		let major = mapping.map(page_offset, &mut vmem, cgroup, write)?;
+		if !resident && mapping.pages[page_offset].is_some() {
+			state.rss += mapping.fault_range(page_offset).len();
+			state.max_rss = state.max_rss.max(state.rss);
+		}
+		// Statistics
+		let rusage = &core_local().rusage;
+		if major {
+			rusage.majflt.fetch_add(1, Relaxed);
+		} else {
+			rusage.minflt.fetch_add(1, Relaxed);
+		}
 		Ok(true)
 	}
 }
@@ -28,21 +28,54 @@ use utils::{
 	collections::{
 		btreemap::BTreeMap,
 		hashmap::{Entry, HashMap},
+		interval_tree::IntervalTree,
 	},
 	errno::{AllocResult, EResult},
 };
 
+/// A collection of values sorted by key, on which operations can be rolled back.
+trait Collection<K, V> {
+	/// Inserts `value` at `key`, returning the previous value, if any.
+	///
+	/// Replacing a value must not allocate memory.
+	fn insert(&mut self, key: K, value: V) -> AllocResult<Option<V>>;
+
+	/// Removes the value at `key`, and returns it.
+	fn remove(&mut self, key: &K) -> Option<V>;
+}
+
+impl<K: Ord, V> Collection<K, V> for BTreeMap<K, V> {
+	fn insert(&mut self, key: K, value: V) -> AllocResult<Option<V>> {
+		BTreeMap::insert(self, key, value)
+	}
+
+	fn remove(&mut self, key: &K) -> Option<V> {
+		BTreeMap::remove(self, key)
+	}
+}
+
+impl Collection<*mut u8, MemMapping> for IntervalTree<*mut u8, MemMapping> {
+	fn insert(&mut self, _key: *mut u8, mapping: MemMapping) -> AllocResult<Option<MemMapping>> {
+		let old = self.replace(mapping.range(), mapping)?;
+		Ok(old.map(|(_, m)| m))
+	}
+
+	fn remove(&mut self, key: &*mut u8) -> Option<MemMapping> {
+		IntervalTree::remove(self, key).map(|(_, m)| m)
+	}
+}
+
 /// Applies the difference in `complement` to rollback operations.
 ///
 /// If the complement does not correspond to `on`, the function might panic.
-fn rollback<K: Ord + Hash, V>(on: &mut BTreeMap<K, V>, complement: HashMap<K, Option<V>>) {
+fn rollback<K: Hash + Eq, V>(on: &mut impl Collection<K, V>, complement: HashMap<K, Option<V>>) {
 	for (key, value) in complement {
 		rollback_impl(on, key, value);
 	}
 }
 
 #[cold]
-fn rollback_impl<K: Ord + Hash, V>(on: &mut BTreeMap<K, V>, key: K, value: Option<V>) {
+fn rollback_impl<K, V>(on: &mut impl Collection<K, V>, key: K, value: Option<V>) {
 	let _ = match value {
 		// Insertion cannot fail since `on` is guaranteed to already contain the key
 		Some(value) => on.insert(key, value).unwrap(),
@@ -50,16 +83,16 @@ fn rollback_impl<K: Ord + Hash, V>(on: &mut BTreeMap<K, V>, key: K, value: Optio
 	};
 }
 
-/// Insert an element in the [`BTreeMap`] `on`, together with rollback data.
+/// Insert an element in the collection `on`, together with rollback data.
 ///
 /// `complement` is the complement used for rollback.
 ///
 /// The `discard` list is also updated to avoid discarding an element that is being replaced by the
 /// insertion.
-fn insert<K: Clone + Ord + Hash, V>(
+fn insert<K: Clone + Hash + Eq, V>(
 	key: K,
 	value: V,
-	on: &mut BTreeMap<K, V>,
+	on: &mut impl Collection<K, V>,
 	complement: &mut HashMap<K, Option<V>>,
 	discard: &mut HashMap<K, ()>,
 ) -> AllocResult<()> {
@@ -108,6 +141,8 @@ pub(super) struct MemSpaceTransaction<'m> {
 
 	/// The new value for the `vmem_usage` field.
 	vmem_usage: usize,
+	/// The new value for the `rss` field.
+	rss: usize,
 }
 
 impl<'m> MemSpaceTransaction<'m> {
@@ -116,6 +151,7 @@ impl<'m> MemSpaceTransaction<'m> {
 		let state = mem_space.state.lock();
 		let vmem = mem_space.vmem.lock();
 		let vmem_usage = state.vmem_usage;
+		let rss = state.rss;
 		Self {
 			vmem,
 			state,
@@ -127,6 +163,7 @@ impl<'m> MemSpaceTransaction<'m> {
 			mappings_discard: Default::default(),
 
 			vmem_usage,
+			rss,
 		}
 	}
 
@@ -159,6 +196,7 @@ impl<'m> MemSpaceTransaction<'m> {
 	/// On failure, the transaction is dropped and rolled back.
 	pub fn insert_mapping(&mut self, mapping: MemMapping) -> AllocResult<()> {
 		let size = mapping.size.get();
+		let resident = mapping.resident_pages();
 		insert(
 			mapping.addr,
 			mapping,
@@ -167,6 +205,7 @@ impl<'m> MemSpaceTransaction<'m> {
 			&mut self.mappings_discard,
 		)?;
 		self.vmem_usage += size;
+		self.rss += resident;
 		Ok(())
 	}
 
@@ -183,6 +222,7 @@ impl<'m> MemSpaceTransaction<'m> {
 				.unmap_range(VirtAddr::from(mapping.addr), mapping.size.get());
 			// Update usage
 			self.vmem_usage -= mapping.size.get();
+			self.rss -= mapping.resident_pages();
 		}
 		Ok(())
 	}
@@ -202,6 +242,8 @@ impl<'m> MemSpaceTransaction<'m> {
 		}
 		// Update vmem
 		self.state.vmem_usage = self.vmem_usage;
+		self.state.rss = self.rss;
+		self.state.max_rss = self.state.max_rss.max(self.rss);
 	}
 }
 
@@ -22,13 +22,23 @@
 //! several processes to run at the same time by sharing the CPU resources using
 //! a scheduler.
 
+pub mod cgroup;
 pub mod exec;
+pub mod futex;
+pub mod kthread;
 pub mod mem_space;
+pub mod mqueue;
+pub mod msg;
+pub mod ns;
 pub mod pid;
+pub mod rlimit;
 pub mod rusage;
 pub mod scheduler;
+pub mod seccomp;
+pub mod sem;
 pub mod signal;
 pub mod user_desc;
+pub mod workqueue;
 
 use crate::{
 	arch::x86::{FxState, cli, gdt, idt, idt::IntFrame, tss},
@@ -38,41 +48,58 @@ use crate::{
 	file::{
 		File, O_RDWR,
 		fd::{FileDescriptorTable, NewFDConstraint},
-		perm::AccessProfile,
+		perm::{AccessProfile, CAP_KILL},
 		vfs,
-		vfs::ResolutionSettings,
+		vfs::{ResolutionSettings, mountpoint, mountpoint::MountNamespace},
+		wait_queue::WaitQueue,
 	},
 	memory::{VirtAddr, buddy, buddy::FrameOrder, oom, user, user::UserPtr},
 	process::{
+		cgroup::Cgroup,
+		ns::Namespaces,
 		pid::{IDLE_PID, INIT_PID, PidHandle},
-		rusage::Rusage,
+		rlimit::{RLIMIT_STACK, RLimits},
+		rusage::{Rusage, RusageCounters},
 		scheduler::{
-			SCHEDULER, Scheduler, core_local, switch,
+			CpuMask, MAX_CPUS, SCHED_OTHER, SCHEDULER, Scheduler, core_local, lock_run_queue_of,
+			stat, switch,
 			switch::{KThreadEntry, idle_task},
+			with_run_queue,
 		},
+		seccomp::Seccomp,
+		sem::SemSet,
 		signal::SigSet,
 	},
 	register_get,
-	sync::mutex::Mutex,
-	syscall::FromSyscallArg,
-	time::timer::TimerManager,
+	sync::{
+		atomic::AtomicU64,
+		mutex::{IntMutex, Mutex},
+		rcu::RcuOptionArc,
+	},
+	syscall::{FromSyscallArg, stats::SyscallStats},
+	time::{
+		timer::TimerManager,
+		unit::{TimeUnit, Timeval},
+	},
 };
 use core::{
 	ffi::c_int,
 	fmt,
 	fmt::Formatter,
+	hint,
 	hint::unlikely,
 	mem,
 	mem::ManuallyDrop,
+	ptr,
 	ptr::NonNull,
 	sync::atomic::{
-		AtomicBool, AtomicPtr, AtomicU8, AtomicU32,
+		AtomicBool, AtomicI8, AtomicPtr, AtomicU8, AtomicU16, AtomicUsize,
 		Ordering::{Acquire, Relaxed, Release, SeqCst},
 	},
 };
 use mem_space::MemSpace;
 use pid::Pid;
-use signal::{Signal, SignalHandler};
+use signal::{SA_NOCLDSTOP, SigStack, Signal, SignalHandler};
 use utils::{
 	collections::{
 		path::{Path, PathBuf},
@@ -80,6 +107,7 @@ use utils::{
 	},
 	errno,
 	errno::{AllocResult, EResult},
+	limits::PAGE_SIZE,
 	ptr::arc::Arc,
 	unsafe_mut::UnsafeMut,
 };
@@ -93,8 +121,10 @@ const TTY_DEVICE_PATH: &str = "/dev/tty";
 /// The default file creation mask.
 const DEFAULT_UMASK: file::Mode = 0o022;
 
-/// The size of the userspace stack of a process in number of pages.
-const USER_STACK_SIZE: usize = 2048;
+/// The initial size of the userspace stack of a process in number of pages.
+///
+/// The stack then grows on demand, up to the process's `RLIMIT_STACK` limit.
+const USER_STACK_SIZE: usize = 32;
 /// The size of the kernelspace stack of a process in number of pages.
 const KERNEL_STACK_ORDER: FrameOrder = 4;
 
@@ -179,6 +209,14 @@ pub struct ForkOptions {
 	/// If `true`, the parent and child processes both share the same signal
 	/// handlers table.
 	pub share_sighand: bool,
+	/// If `true`, the parent and child processes both share the same umask, working directory,
+	/// root directory and mount namespace.
+	pub share_fs: bool,
+	/// The filesystem information of the child process. If `None`, it is copied from the
+	/// parent, according to `share_fs`.
+	pub fs: Option<ProcessFs>,
+	/// The namespaces of the child process. If `None`, they are shared with the parent.
+	pub ns: Option<Namespaces>,
 }
 
 /// Wrapper for the kernel stack, allowing to free it on drop.
@@ -220,38 +258,116 @@ pub struct ProcessLinks {
 	pub process_group: Vec<Pid>,
 }
 
-/// A process's filesystem access information.
-pub struct ProcessFs {
-	/// The process's access profile, containing user and group IDs.
-	pub access_profile: AccessProfile,
-	/// The process's current umask.
-	pub umask: AtomicU32,
+/// Filesystem information which can be shared between processes, with `CLONE_FS`.
+#[derive(Debug)]
+pub struct FsContext {
+	/// The current umask.
+	pub umask: file::Mode,
 	/// Current working directory
 	///
 	/// The field contains both the path and the directory.
 	pub cwd: Arc<vfs::Entry>,
-	/// Current root path used by the process
+	/// Current root path
 	pub chroot: Arc<vfs::Entry>,
+	/// The mount namespace
+	pub mnt_ns: Arc<MountNamespace>,
 }
 
-impl ProcessFs {
-	/// Returns the current umask.
-	pub fn umask(&self) -> file::Mode {
-		self.umask.load(Acquire)
+impl FsContext {
+	/// Moves the context to the mount namespace `ns`.
+	///
+	/// The current working directory and root directory are set to the root of the namespace.
+	pub fn set_mnt_ns(&mut self, ns: Arc<MountNamespace>) {
+		let root = ns.root.lock().clone();
+		self.cwd = root.clone();
+		self.chroot = root;
+		let old = mem::replace(&mut self.mnt_ns, ns);
+		mountpoint::put_namespace(&old);
+	}
+
+	/// Moves the context to a private copy of its mount namespace.
+	///
+	/// The current working directory and root directory are looked up again in the new
+	/// namespace.
+	pub fn unshare_mnt_ns(&mut self) -> EResult<()> {
+		let ns = mountpoint::copy_namespace(&self.mnt_ns)?;
+		self.cwd = ns.translate(&self.cwd);
+		self.chroot = ns.translate(&self.chroot);
+		let old = mem::replace(&mut self.mnt_ns, ns);
+		mountpoint::put_namespace(&old);
+		Ok(())
 	}
 }
 
-impl Clone for ProcessFs {
+impl Clone for FsContext {
 	fn clone(&self) -> Self {
 		Self {
-			access_profile: self.access_profile,
-			umask: AtomicU32::new(self.umask.load(Acquire)),
+			umask: self.umask,
 			cwd: self.cwd.clone(),
 			chroot: self.chroot.clone(),
+			mnt_ns: self.mnt_ns.clone(),
 		}
 	}
 }
 
+impl Drop for FsContext {
+	fn drop(&mut self) {
+		mountpoint::put_namespace(&self.mnt_ns);
+	}
+}
+
+/// A process's filesystem access information.
+#[derive(Debug)]
+pub struct ProcessFs {
+	/// The process's access profile, containing user and group IDs.
+	pub access_profile: AccessProfile,
+	/// The umask, working directory, root directory and mount namespace of the process.
+	///
+	/// The context is shared with the processes created with `CLONE_FS`.
+	pub ctx: Arc<Mutex<FsContext>>,
+}
+
+impl ProcessFs {
+	/// Creates a new instance with a private context.
+	pub fn new(access_profile: AccessProfile, ctx: FsContext) -> AllocResult<Self> {
+		Ok(Self {
+			access_profile,
+			ctx: Arc::new(Mutex::new(ctx))?,
+		})
+	}
+
+	/// Returns the current umask.
+	pub fn umask(&self) -> file::Mode {
+		self.ctx.lock().umask
+	}
+
+	/// Returns a copy of the filesystem information.
+	///
+	/// If `share` is set, the context is shared with the copy. Else, the copy gets a private
+	/// one.
+	pub fn copy(&self, share: bool) -> AllocResult<Self> {
+		if share {
+			Ok(Self {
+				access_profile: self.access_profile,
+				ctx: self.ctx.clone(),
+			})
+		} else {
+			Self::new(self.access_profile, self.ctx.lock().clone())
+		}
+	}
+
+	/// Stops sharing the context with other processes, by replacing it with a private copy.
+	///
+	/// If the context is not shared, the function does nothing.
+	pub fn unshare(&mut self) -> AllocResult<()> {
+		if Arc::strong_count(&self.ctx) > 1 {
+			let ctx = self.ctx.lock().clone();
+			self.ctx = Arc::new(Mutex::new(ctx))?;
+		}
+		Ok(())
+	}
+}
+
 /// A process's signal management information.
 pub struct ProcessSignal {
 	/// The list of signal handlers.
@@ -260,6 +376,12 @@ pub struct ProcessSignal {
 	pub sigmask: SigSet,
 	/// A bitfield storing the set of pending signals.
 	sigpending: SigSet,
+	/// The signal mask to restore once the next signal has been handled.
+	///
+	/// This is set by system calls that temporarily replace the signal mask, such as `ppoll`.
+	pub saved_sigmask: Option<SigSet>,
+	/// The alternate signal stack.
+	pub altstack: SigStack,
 
 	/// The exit status of the process after exiting.
 	pub exit_status: ExitStatus,
@@ -274,12 +396,49 @@ impl ProcessSignal {
 			handlers: Arc::new(Default::default())?,
 			sigmask: Default::default(),
 			sigpending: Default::default(),
+			saved_sigmask: None,
+			altstack: Default::default(),
 
 			exit_status: 0,
 			termsig: 0,
 		})
 	}
 
+	/// Returns the signal mask to restore once the signal handler about to be executed returns.
+	///
+	/// If the mask has been temporarily replaced (see [`Self::saved_sigmask`]), the saved mask is
+	/// returned and cleared, so that it gets restored by the handler's return.
+	pub fn context_sigmask(&mut self) -> SigSet {
+		self.saved_sigmask.take().unwrap_or(self.sigmask)
+	}
+
+	/// Tells whether the stack pointer `sp` is on the alternate signal stack.
+	///
+	/// If the stack is disarmed while in use (see [`signal::SS_AUTODISARM`]), the function
+	/// always returns `false`.
+	pub fn on_altstack(&self, sp: usize) -> bool {
+		if self.altstack.ss_flags & signal::SS_AUTODISARM != 0 {
+			return false;
+		}
+		sp > self.altstack.ss_sp && sp - self.altstack.ss_sp <= self.altstack.ss_size
+	}
+
+	/// Returns the description of the alternate signal stack as seen by userspace, with `sp`
+	/// the current stack pointer.
+	pub fn altstack_info(&self, sp: usize) -> SigStack {
+		let state = if self.altstack.ss_size == 0 {
+			signal::SS_DISABLE
+		} else if self.on_altstack(sp) {
+			signal::SS_ONSTACK
+		} else {
+			0
+		};
+		SigStack {
+			ss_flags: state | (self.altstack.ss_flags & signal::SS_AUTODISARM),
+			..self.altstack
+		}
+	}
+
 	/// Tells whether the given signal is blocked by the process.
 	pub fn is_signal_blocked(&self, sig: Signal) -> bool {
 		self.sigmask.is_set(sig as _)
@@ -295,9 +454,7 @@ impl ProcessSignal {
 		let sig = self
 			.sigpending
 			.iter()
-			.enumerate()
-			.filter(|(_, b)| *b)
-			.filter_map(|(i, _)| {
+			.filter_map(|i| {
 				let s = Signal::try_from(i as c_int).ok()?;
 				(!s.can_catch() || !self.sigmask.is_set(i)).then_some(s)
 			})
@@ -307,6 +464,25 @@ impl ProcessSignal {
 		}
 		sig
 	}
+
+	/// Tells whether a signal in `mask` is pending, regardless of the signal mask.
+	pub fn is_pending_in(&self, mask: SigSet) -> bool {
+		self.sigpending.0 & mask.0 != 0
+	}
+
+	/// Returns the next pending signal in `mask`, clearing it from the pending signals mask.
+	///
+	/// Contrary to [`Self::next_signal`], the signal mask is not taken into account, so that
+	/// blocked signals can be consumed (for example, by a signalfd).
+	///
+	/// If no signal in `mask` is pending, the function returns `None`.
+	pub fn take_pending_in(&mut self, mask: SigSet) -> Option<Signal> {
+		let sig = SigSet(self.sigpending.0 & mask.0)
+			.iter()
+			.find_map(|i| Signal::try_from(i as c_int).ok())?;
+		self.sigpending.clear(sig as _);
+		Some(sig)
+	}
 }
 
 /// The **Process Control Block** (PCB). This structure stores all the information
@@ -332,20 +508,82 @@ pub struct Process {
 	fpu: Mutex<FxState>,
 	/// TLS entries.
 	pub tls: Mutex<[gdt::Entry; TLS_ENTRIES_COUNT]>, // TODO rwlock
+	/// Userspace address of the thread ID word to clear when the thread exits, in order to wake
+	/// up threads waiting on it (`clear_child_tid`).
+	///
+	/// If null, nothing is done on exit.
+	pub clear_child_tid: AtomicPtr<c_int>,
+	/// The semaphore sets on which the process performed operations flagged with `SEM_UNDO`.
+	///
+	/// The adjustments of the process are applied to these sets when it exits.
+	pub sem_undo: Mutex<Vec<Arc<SemSet>>>,
+	/// The I/O priority of the process, used to order its requests to storage devices.
+	pub ioprio: AtomicU16,
+	/// The set of CPU cores the process is allowed to run on.
+	pub cpu_mask: AtomicU64,
+	/// The ID of the core whose run queue holds the process.
+	pub cpu: AtomicUsize,
+	/// Tells whether the process is being executed by a core, or has not finished switching away
+	/// from it.
+	pub on_cpu: AtomicBool,
+	/// The scheduling policy of the process.
+	///
+	/// This field must be modified only through [`Scheduler::set_scheduler`].
+	pub sched_policy: AtomicU8,
+	/// The real-time priority of the process. Zero if the scheduling policy is not real-time.
+	///
+	/// This field must be modified only through [`Scheduler::set_scheduler`].
+	pub rt_priority: AtomicU8,
+	/// The nice value of the process, weighting its share of CPU time when it is not real-time.
+	pub nice: AtomicI8,
+	/// The virtual runtime of the process, in nanoseconds: its CPU time, scaled down by its
+	/// scheduling weight.
+	///
+	/// This field must be modified only by the run queue of the process.
+	pub vruntime: AtomicU64,
 
 	/// The virtual memory of the process.
 	pub mem_space: UnsafeMut<Option<Arc<MemSpace>>>,
 	/// Filesystem access information.
 	pub fs: Mutex<ProcessFs>, // TODO rwlock
+	/// The UTS and IPC namespaces of the process.
+	pub ns: Mutex<Namespaces>,
+	/// The control group the process belongs to.
+	pub cgroup: IntMutex<Arc<Cgroup>>,
 	/// The list of open file descriptors with their respective ID.
-	pub file_descriptors: UnsafeMut<Option<Arc<Mutex<FileDescriptorTable>>>>,
+	///
+	/// The table is replaced atomically, so that other processes (such as readers of `/proc`)
+	/// always see either the previous or the new table.
+	pub file_descriptors: RcuOptionArc<Mutex<FileDescriptorTable>>,
 	/// Process's timers, shared between all threads of the same process.
 	pub timer_manager: Arc<Mutex<TimerManager>>,
 	/// The process's signal management structure.
 	pub signal: Mutex<ProcessSignal>, // TODO rwlock
+	/// The queue of processes waiting for a signal to become pending on the process, through a
+	/// signalfd.
+	pub signal_queue: WaitQueue,
 
 	/// The process's resources usage.
-	pub rusage: Mutex<Rusage>,
+	pub rusage: RusageCounters,
+	/// The resources used by terminated children that have been waited for.
+	pub children_rusage: Mutex<Rusage>,
+	/// Statistics of the system calls made by the process.
+	pub syscall_stats: SyscallStats,
+	/// The CPU time spent in userspace, in nanoseconds.
+	utime: AtomicU64,
+	/// The CPU time spent in kernelspace, in nanoseconds.
+	stime: AtomicU64,
+	/// The process's resource limits.
+	pub rlimits: IntMutex<RLimits>,
+	/// The process's seccomp state.
+	pub seccomp: Mutex<Seccomp>,
+	/// If `true`, `execve` cannot grant privileges the process does not already have.
+	pub no_new_privs: AtomicBool,
+	/// If `true`, the process adopts its orphaned descendants instead of the init process.
+	pub child_subreaper: AtomicBool,
+	/// If `false`, the process runs with privileges its caller did not have, and cannot be
+	/// inspected by unprivileged processes. Its directory in `/proc` then belongs to root.
+	pub dumpable: AtomicBool,
 }
 
 /// Initializes processes system. This function must be called only once, at
@@ -397,7 +635,12 @@ pub(crate) fn init() -> EResult<()> {
 			return CallbackResult::Panic;
 		};
 		// Check access
-		let sig = mem_space.handle_page_fault(accessed_addr, code);
+		let proc = Process::current();
+		let cgroup = proc.cgroup.lock().clone();
+		let sig = mem_space.handle_page_fault(accessed_addr, code, &cgroup, || {
+			let limit = proc.rlimits.lock()[RLIMIT_STACK as usize].rlim_cur;
+			limit.try_into().unwrap_or(usize::MAX)
+		});
 		match sig {
 			Ok(true) => {}
 			Ok(false) => {
@@ -410,10 +653,12 @@ pub(crate) fn init() -> EResult<()> {
 						return CallbackResult::Panic;
 					}
 				} else {
-					Process::current().kill(Signal::SIGSEGV);
+					proc.kill(Signal::SIGSEGV);
 				}
 			}
-			Err(_) => Process::current().kill(Signal::SIGBUS),
+			// The memory limit of the cgroup has been reached
+			Err(e) if e == errno!(ENOMEM) => proc.kill(Signal::SIGKILL),
+			Err(_) => proc.kill(Signal::SIGBUS),
 		}
 		CallbackResult::Continue
 	};
@@ -445,7 +690,7 @@ impl Process {
 
 	/// Returns the current running process.
 	pub fn current() -> Arc<Self> {
-		SCHEDULER.lock().get_current_process()
+		with_run_queue(|rq| rq.get_current_process().clone())
 	}
 
 	/// Creates a kernel thread.
@@ -478,20 +723,45 @@ impl Process {
 			kernel_sp: AtomicPtr::new(kernel_sp),
 			fpu: Mutex::new(FxState([0; 512])),
 			tls: Default::default(),
+			clear_child_tid: Default::default(),
+			sem_undo: Default::default(),
+			ioprio: Default::default(),
+			cpu_mask: AtomicU64::new(CpuMask::MAX),
+			cpu: AtomicUsize::new(0),
+			on_cpu: AtomicBool::new(false),
+			sched_policy: AtomicU8::new(SCHED_OTHER),
+			rt_priority: AtomicU8::new(0),
+			nice: AtomicI8::new(0),
+			vruntime: AtomicU64::new(0),
 
 			// TODO this is not needed. find a way to avoid init
 			mem_space: Default::default(),
-			fs: Mutex::new(ProcessFs {
-				access_profile: AccessProfile::KERNEL,
-				umask: Default::default(),
-				cwd: vfs::ROOT.clone(),
-				chroot: vfs::ROOT.clone(),
-			}),
+			fs: Mutex::new(ProcessFs::new(
+				AccessProfile::KERNEL,
+				FsContext {
+					umask: Default::default(),
+					cwd: vfs::ROOT.clone(),
+					chroot: vfs::ROOT.clone(),
+					mnt_ns: mountpoint::init_namespace(),
+				},
+			)?),
+			ns: Mutex::new(ns::init_namespaces()?),
+			cgroup: IntMutex::new(cgroup::root()?),
 			file_descriptors: Default::default(),
 			timer_manager: Arc::new(Mutex::new(TimerManager::new(0)?))?,
 			signal: Mutex::new(ProcessSignal::new()?),
+			signal_queue: WaitQueue::new(),
 
 			rusage: Default::default(),
+			children_rusage: Default::default(),
+			syscall_stats: Default::default(),
+			utime: Default::default(),
+			stime: Default::default(),
+			rlimits: IntMutex::new(rlimit::default_limits()),
+			seccomp: Default::default(),
+			no_new_privs: AtomicBool::new(false),
+			child_subreaper: AtomicBool::new(false),
+			dumpable: AtomicBool::new(true),
 		})?;
 		if queue {
 			SCHEDULER.lock().add_process(thread.clone())?;
@@ -546,26 +816,53 @@ impl Process {
 			kernel_sp: AtomicPtr::default(),
 			fpu: Mutex::new(FxState([0; 512])),
 			tls: Default::default(),
+			clear_child_tid: Default::default(),
+			sem_undo: Default::default(),
+			ioprio: Default::default(),
+			cpu_mask: AtomicU64::new(CpuMask::MAX),
+			cpu: AtomicUsize::new(0),
+			on_cpu: AtomicBool::new(false),
+			sched_policy: AtomicU8::new(SCHED_OTHER),
+			rt_priority: AtomicU8::new(0),
+			nice: AtomicI8::new(0),
+			vruntime: AtomicU64::new(0),
 
 			mem_space: UnsafeMut::new(None),
-			fs: Mutex::new(ProcessFs {
-				access_profile: rs.access_profile,
-				umask: AtomicU32::new(DEFAULT_UMASK),
-				cwd: root_dir.clone(),
-				chroot: root_dir,
-			}),
-			file_descriptors: UnsafeMut::new(Some(Arc::new(Mutex::new(file_descriptors))?)),
+			fs: Mutex::new(ProcessFs::new(
+				rs.access_profile,
+				FsContext {
+					umask: DEFAULT_UMASK,
+					cwd: root_dir.clone(),
+					chroot: root_dir,
+					mnt_ns: mountpoint::init_namespace(),
+				},
+			)?),
+			ns: Mutex::new(ns::init_namespaces()?),
+			cgroup: IntMutex::new(cgroup::root()?),
+			file_descriptors: RcuOptionArc::new(Some(Arc::new(Mutex::new(file_descriptors))?)),
 			timer_manager: Arc::new(Mutex::new(TimerManager::new(INIT_PID)?))?,
 			signal: Mutex::new(ProcessSignal {
 				handlers: Arc::new(Default::default())?,
 				sigmask: Default::default(),
 				sigpending: Default::default(),
+				saved_sigmask: None,
+				altstack: Default::default(),
 
 				exit_status: 0,
 				termsig: 0,
 			}),
+			signal_queue: WaitQueue::new(),
 
 			rusage: Default::default(),
+			children_rusage: Default::default(),
+			syscall_stats: Default::default(),
+			utime: Default::default(),
+			stime: Default::default(),
+			rlimits: IntMutex::new(rlimit::default_limits()),
+			seccomp: Default::default(),
+			no_new_privs: AtomicBool::new(false),
+			child_subreaper: AtomicBool::new(false),
+			dumpable: AtomicBool::new(true),
 		})?;
 		SCHEDULER.lock().add_process(proc.clone())?;
 		Ok(proc)
@@ -588,6 +885,11 @@ impl Process {
 		*self.pid == INIT_PID
 	}
 
+	/// Tells whether the process is allowed to run on the CPU core with ID `core`.
+	pub fn can_run_on(&self, core: usize) -> bool {
+		core < MAX_CPUS && self.cpu_mask.load(Relaxed) & (1 << core) != 0
+	}
+
 	/// Returns the process group ID.
 	pub fn get_pgid(&self) -> Pid {
 		self.links
@@ -688,6 +990,9 @@ impl Process {
 		// Disable interruptions to ensure the function can finish before the scheduler switches
 		// context (and thus never resume if the new state is `Zombie`)
 		idt::wrap_disable_interrupts(|| {
+			// Lock the run queue so that the state and the number of running processes change
+			// together
+			let mut rq = lock_run_queue_of(self);
 			let Ok(old_state) = self.state.fetch_update(Release, Acquire, |old_state| {
 				let old_state = State::from_id(old_state);
 				let valid = matches!(
@@ -710,22 +1015,24 @@ impl Process {
 			);
 			// Update the number of running processes
 			if new_state == State::Running {
-				SCHEDULER.lock().increment_running();
+				rq.increment_running(self);
 			} else if old_state == State::Running {
-				SCHEDULER.lock().decrement_running();
+				rq.decrement_running(self);
 			}
+			drop(rq);
 			if new_state == State::Zombie {
 				if self.is_init() {
 					panic!("Terminated init process!");
 				}
 				// Remove the memory space and file descriptors table to reclaim memory
-				unsafe {
-					//self.mem_space = None; // TODO Handle the case where the memory space is
-					// bound
-					*self.file_descriptors.get_mut() = None;
+				//self.mem_space = None; // TODO Handle the case where the memory space is
+				// bound
+				if let Some(fds) = self.file_descriptors.get() {
+					fds.lock().release_locks(self.get_pid());
 				}
-				// Attach every child to the init process
-				let init_proc = Process::get_by_pid(INIT_PID).unwrap();
+				self.file_descriptors.swap(None);
+				// Attach every child to the reaper
+				let reaper = self.find_reaper();
 				let children = mem::take(&mut self.links.lock().children);
 				for child_pid in children {
 					// Check just in case
@@ -733,32 +1040,115 @@ impl Process {
 						continue;
 					}
 					// TODO do the same for process group members
-					if let Some(child) = Process::get_by_pid(child_pid) {
-						child.links.lock().parent = Some(init_proc.clone());
-						oom::wrap(|| init_proc.add_child(child_pid));
+					let Some(child) = Process::get_by_pid(child_pid) else {
+						continue;
+					};
+					child.links.lock().parent = Some(reaper.clone());
+					oom::wrap(|| reaper.add_child(child_pid));
+					// Let the reaper collect children that have already exited
+					if child.get_state() == State::Zombie {
+						reaper.kill(Signal::SIGCHLD);
+					}
+				}
+				// Clear the thread ID and wake up a thread waiting for the exit (`pthread_join`)
+				let clear_child_tid = self.clear_child_tid.swap(ptr::null_mut(), Acquire);
+				if let (Some(ptr), Some(mem_space)) =
+					(NonNull::new(clear_child_tid), self.mem_space.as_ref())
+				{
+					// Safety: the kernel stack is mapped in every memory space
+					unsafe {
+						MemSpace::switch(mem_space, |mem_space| {
+							let _ = UserPtr(Some(ptr)).copy_to_user(&0);
+							futex::wake(mem_space, ptr.as_ptr() as _, 1);
+						});
 					}
 				}
+				// Revert the semaphore operations flagged with `SEM_UNDO`
+				let sets = mem::take(&mut *self.sem_undo.lock());
+				sem::exit(self.get_pid(), sets);
 				// Set vfork as done just in case
 				self.vfork_wake();
 			}
-			// Send SIGCHLD
-			if matches!(new_state, State::Running | State::Stopped | State::Zombie) {
-				let links = self.links.lock();
-				if let Some(parent) = &links.parent {
-					parent.kill(Signal::SIGCHLD);
-				}
-			}
+			self.notify_parent(old_state, new_state);
 		});
 	}
 
+	/// Returns the process adopting the children of the process when it exits.
+	///
+	/// This is the nearest ancestor marked as child subreaper that has not exited, or else the
+	/// init process.
+	fn find_reaper(&self) -> Arc<Process> {
+		let mut ancestor = self.links.lock().parent.clone();
+		while let Some(proc) = ancestor {
+			if proc.child_subreaper.load(Relaxed) && proc.get_state() != State::Zombie {
+				return proc;
+			}
+			ancestor = proc.links.lock().parent.clone();
+		}
+		Process::get_by_pid(INIT_PID).unwrap()
+	}
+
+	/// Sends `SIGCHLD` to the parent of the process if the change of state from `old_state` to
+	/// `new_state` has to be reported.
+	fn notify_parent(&self, old_state: State, new_state: State) {
+		let links = self.links.lock();
+		let Some(parent) = &links.parent else {
+			return;
+		};
+		let notify = match (old_state, new_state) {
+			(_, State::Zombie) => true,
+			// Stops and resumptions are not reported if the parent asked so
+			(_, State::Stopped) | (State::Stopped, State::Running) => {
+				let handlers = parent.signal.lock().handlers.clone();
+				let action = handlers.lock()[Signal::SIGCHLD as usize].get_action();
+				action.sa_flags & SA_NOCLDSTOP == 0
+			}
+			_ => false,
+		};
+		if notify {
+			parent.kill(Signal::SIGCHLD);
+		}
+	}
+
 	/// Tells whether there is a pending signal on the process.
 	pub fn has_pending_signal(&self) -> bool {
 		let signal = self.signal.lock();
 		signal.sigpending.0 & !signal.sigmask.0 != 0
 	}
 
+	/// Adds `delta` nanoseconds to the CPU time consumed by the process.
+	///
+	/// `user` tells whether the time has been spent in userspace.
+	pub fn account_cpu_time(&self, delta: u64, user: bool) {
+		let counter = if user { &self.utime } else { &self.stime };
+		counter.fetch_add(delta, Relaxed);
+	}
+
+	/// Returns the CPU time consumed by the process in userspace and kernelspace, in
+	/// nanoseconds.
+	pub fn get_cpu_time(&self) -> (u64, u64) {
+		(self.utime.load(Relaxed), self.stime.load(Relaxed))
+	}
+
+	/// Returns the resources used by the process.
+	///
+	/// Resources used on the current CPU core which have not been accounted to the process yet are
+	/// not included.
+	pub fn get_rusage(&self) -> Rusage {
+		let mut rusage = self.rusage.to_rusage();
+		let (utime, stime) = self.get_cpu_time();
+		rusage.ru_utime = Timeval::from_nano(utime);
+		rusage.ru_stime = Timeval::from_nano(stime);
+		if let Some(mem_space) = self.mem_space.as_ref() {
+			let maxrss = (mem_space.get_max_rss() * (PAGE_SIZE / 1024)) as i64;
+			rusage.ru_maxrss = rusage.ru_maxrss.max(maxrss);
+		}
+		rusage
+	}
+
 	/// Wakes up the process if in [`State::Sleeping`] state.
 	pub fn wake(&self) {
+		let mut rq = lock_run_queue_of(self);
 		// TODO make sure the ordering is right
 		let res = self.state.fetch_update(SeqCst, SeqCst, |old_state| {
 			(old_state == State::Sleeping as _).then_some(State::Running as _)
@@ -772,7 +1162,17 @@ impl Process {
 		);
 		// Update the number of running processes
 		if res.is_ok() {
-			SCHEDULER.lock().increment_running();
+			rq.increment_running(self);
+		}
+	}
+
+	/// Waits until the process is not executed by any core anymore.
+	///
+	/// This function must be called before releasing a process which has been removed from the
+	/// scheduler, since it may still be switching away from its core.
+	pub fn wait_off_cpu(&self) {
+		while self.on_cpu.load(Acquire) {
+			hint::spin_loop();
 		}
 	}
 
@@ -831,10 +1231,10 @@ impl Process {
 		};
 		// Clone file descriptors
 		let file_descriptors = if fork_options.share_fd {
-			this.file_descriptors.get().clone()
+			this.file_descriptors.get()
 		} else {
 			this.file_descriptors
-				.as_ref()
+				.get()
 				.map(|fds| -> EResult<_> {
 					let fds = fds.lock();
 					let new_fds = fds.duplicate(false)?;
@@ -843,15 +1243,27 @@ impl Process {
 				.transpose()?
 		};
 		// Clone signal handlers
-		let signal_handlers = {
+		let (signal_handlers, altstack) = {
 			let signal_manager = this.signal.lock();
-			if fork_options.share_sighand {
+			let handlers = if fork_options.share_sighand {
 				signal_manager.handlers.clone()
 			} else {
 				let handlers = signal_manager.handlers.lock().clone();
 				Arc::new(Mutex::new(handlers))?
-			}
+			};
+			// A thread cannot use the same alternate stack as its parent
+			let altstack = if fork_options.share_memory {
+				Default::default()
+			} else {
+				signal_manager.altstack
+			};
+			(handlers, altstack)
+		};
+		let fs = match fork_options.fs {
+			Some(fs) => fs,
+			None => this.fs.lock().copy(fork_options.share_fs)?,
 		};
+		let ns = fork_options.ns.unwrap_or_else(|| this.ns.lock().clone());
 		let group_leader = this
 			.links
 			.lock()
@@ -874,22 +1286,46 @@ impl Process {
 			kernel_sp: AtomicPtr::default(),
 			fpu: Mutex::new(this.fpu.lock().clone()),
 			tls: Mutex::new(*this.tls.lock()),
+			clear_child_tid: Default::default(),
+			sem_undo: Default::default(),
+			ioprio: AtomicU16::new(this.ioprio.load(Relaxed)),
+			cpu_mask: AtomicU64::new(this.cpu_mask.load(Relaxed)),
+			cpu: AtomicUsize::new(0),
+			on_cpu: AtomicBool::new(false),
+			sched_policy: AtomicU8::new(this.sched_policy.load(Relaxed)),
+			rt_priority: AtomicU8::new(this.rt_priority.load(Relaxed)),
+			nice: AtomicI8::new(this.nice.load(Relaxed)),
+			vruntime: AtomicU64::new(this.vruntime.load(Relaxed)),
 
 			mem_space: UnsafeMut::new(Some(mem_space)),
-			fs: Mutex::new(this.fs.lock().clone()),
-			file_descriptors: UnsafeMut::new(file_descriptors),
+			fs: Mutex::new(fs),
+			ns: Mutex::new(ns),
+			cgroup: IntMutex::new(this.cgroup.lock().clone()),
+			file_descriptors: RcuOptionArc::new(file_descriptors),
 			// TODO if creating a thread: timer_manager: this.timer_manager.clone(),
 			timer_manager: Arc::new(Mutex::new(TimerManager::new(pid_int)?))?,
 			signal: Mutex::new(ProcessSignal {
 				handlers: signal_handlers,
 				sigmask: this.signal.lock().sigmask,
 				sigpending: Default::default(),
+				saved_sigmask: None,
+				altstack,
 
 				exit_status: 0,
 				termsig: 0,
 			}),
+			signal_queue: WaitQueue::new(),
 
 			rusage: Default::default(),
+			children_rusage: Default::default(),
+			syscall_stats: Default::default(),
+			utime: Default::default(),
+			stime: Default::default(),
+			rlimits: IntMutex::new(*this.rlimits.lock()),
+			seccomp: Mutex::new(this.seccomp.lock().clone()),
+			no_new_privs: AtomicBool::new(this.no_new_privs.load(Relaxed)),
+			child_subreaper: AtomicBool::new(false),
+			dumpable: AtomicBool::new(this.dumpable.load(Relaxed)),
 		})?;
 		// TODO on failure, must undo
 		this.add_child(pid_int)?;
@@ -900,6 +1336,7 @@ impl Process {
 			}
 		}
 		SCHEDULER.lock().add_process(proc.clone())?;
+		stat::FORKS.fetch_add(1, Relaxed);
 		Ok(proc)
 	}
 
@@ -909,19 +1346,20 @@ impl Process {
 	/// executed.
 	pub fn kill(&self, sig: Signal) {
 		let mut signal_manager = self.signal.lock();
-		// Ignore blocked signals
-		if sig.can_catch() && signal_manager.sigmask.is_set(sig as _) {
-			return;
-		}
 		// Statistics
-		self.rusage.lock().ru_nsignals += 1;
+		self.rusage.nsignals.fetch_add(1, Relaxed);
 		#[cfg(feature = "strace")]
 		println!(
 			"[strace {pid}] received signal `{sig}`",
 			pid = self.get_pid(),
 			sig = sig as c_int
 		);
+		// Blocked signals remain pending until unblocked, or consumed by a signalfd
 		signal_manager.sigpending.set(sig as _);
+		drop(signal_manager);
+		self.signal_queue.wake_all();
+		// Interrupt sleeping, so that the signal can be handled
+		self.wake();
 	}
 
 	/// Kills every process in the process group.
@@ -960,8 +1398,8 @@ impl fmt::Debug for Process {
 impl AccessProfile {
 	/// Tells whether the agent can kill the process.
 	pub fn can_kill(&self, proc: &Process) -> bool {
-		// if privileged
-		if self.is_privileged() {
+		// if capable
+		if self.has_capability(CAP_KILL) {
 			return true;
 		}
 		// if sender's `uid` or `euid` equals receiver's `uid` or `suid`
@@ -995,6 +1433,10 @@ fn yield_current_impl(frame: &mut IntFrame) -> bool {
 	let (sig, handler) = {
 		let mut signal_manager = proc.signal.lock();
 		let Some(sig) = signal_manager.next_signal() else {
+			// No signal to handle, restore the signal mask if it was temporarily replaced
+			if let Some(sigmask) = signal_manager.saved_sigmask.take() {
+				signal_manager.sigmask = sigmask;
+			}
 			return true;
 		};
 		let handler = signal_manager.handlers.lock()[sig as usize].clone();
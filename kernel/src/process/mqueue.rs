@@ -0,0 +1,448 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! POSIX message queues.
+//!
+//! A message queue holds messages ordered by priority, and is identified in an IPC namespace by a
+//! name. `mq_open` returns a file descriptor referring to a queue, on which `mq_timedsend` and
+//! `mq_timedreceive` operate. Unlike SysV message queues, a queue removed with `mq_unlink`
+//! remains usable through the descriptors referring to it.
+
+use crate::{
+	file::{
+		File, FileType, Mode, Stat,
+		fs::FileOps,
+		perm::{AccessProfile, CAP_SYS_RESOURCE},
+		wait_queue::WaitQueue,
+	},
+	process::{
+		Process,
+		ns::{IPC_PRIVATE, IpcObjectPerm},
+		pid::Pid,
+		signal::{SIGEV_SIGNAL, SigEvent, Signal},
+	},
+	sync::mutex::Mutex,
+	syscall::select::{POLLIN, POLLOUT},
+	time::unit::Timestamp,
+};
+use core::{ffi::c_long, hint::unlikely};
+use utils::{
+	collections::{hashmap::HashMap, string::String, vec::Vec},
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// The maximum priority of a message, exclusive.
+pub const MQ_PRIO_MAX: u32 = 32768;
+
+/// The default maximum number of messages in a queue.
+pub const DFLT_MAXMSG: usize = 10;
+/// The default maximum size of a message, in bytes.
+pub const DFLT_MSGSIZE: usize = 8192;
+/// The maximum number of messages in a queue, without the `CAP_SYS_RESOURCE` capability.
+pub const MSG_MAX: usize = 10;
+/// The maximum size of a message, without the `CAP_SYS_RESOURCE` capability.
+pub const MSGSIZE_MAX: usize = 8192;
+/// The maximum number of messages in a queue.
+pub const HARD_MSGMAX: usize = 65536;
+/// The maximum size of a message.
+pub const HARD_MSGSIZEMAX: usize = 16 * 1024 * 1024;
+/// The maximum number of queues in an IPC namespace, without the `CAP_SYS_RESOURCE` capability.
+pub const QUEUES_MAX: usize = 256;
+
+/// Attributes of a message queue.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MqAttr {
+	pub mq_flags: c_long,
+	pub mq_maxmsg: c_long,
+	pub mq_msgsize: c_long,
+	pub mq_curmsgs: c_long,
+	pub _reserved: [c_long; 4],
+}
+
+/// Compatibility version of [`MqAttr`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompatMqAttr {
+	pub mq_flags: i32,
+	pub mq_maxmsg: i32,
+	pub mq_msgsize: i32,
+	pub mq_curmsgs: i32,
+	pub _reserved: [i32; 4],
+}
+
+impl From<MqAttr> for CompatMqAttr {
+	fn from(attr: MqAttr) -> Self {
+		Self {
+			mq_flags: attr.mq_flags as _,
+			mq_maxmsg: attr.mq_maxmsg as _,
+			mq_msgsize: attr.mq_msgsize as _,
+			mq_curmsgs: attr.mq_curmsgs as _,
+			..Default::default()
+		}
+	}
+}
+
+impl From<CompatMqAttr> for MqAttr {
+	fn from(attr: CompatMqAttr) -> Self {
+		Self {
+			mq_flags: attr.mq_flags as _,
+			mq_maxmsg: attr.mq_maxmsg as _,
+			mq_msgsize: attr.mq_msgsize as _,
+			mq_curmsgs: attr.mq_curmsgs as _,
+			..Default::default()
+		}
+	}
+}
+
+/// A message.
+#[derive(Debug)]
+pub struct MqMessage {
+	/// The priority of the message, lower than [`MQ_PRIO_MAX`].
+	pub prio: u32,
+	/// The content of the message.
+	pub data: Vec<u8>,
+}
+
+/// Returns the index at which a message with priority `prio` is inserted in `messages`, after
+/// the messages with the same or a higher priority.
+fn insert_pos(messages: &[MqMessage], prio: u32) -> usize {
+	messages
+		.iter()
+		.position(|m| m.prio < prio)
+		.unwrap_or(messages.len())
+}
+
+/// A registration for notification, made with `mq_notify`.
+#[derive(Debug)]
+struct Notify {
+	/// The process to notify.
+	pid: Pid,
+	/// The notification to send.
+	sevp: SigEvent,
+}
+
+/// Mutable state of a message queue.
+#[derive(Debug)]
+struct MqState {
+	/// Ownership and permissions.
+	perm: IpcObjectPerm,
+	/// The messages, by decreasing priority, then in order of arrival.
+	messages: Vec<MqMessage>,
+	/// The number of processes sleeping until a message is available.
+	receivers: usize,
+	/// The process to notify when a message arrives on the empty queue.
+	notify: Option<Notify>,
+}
+
+/// A POSIX message queue.
+#[derive(Debug)]
+pub struct Mqueue {
+	/// The maximum number of messages in the queue.
+	pub maxmsg: usize,
+	/// The maximum size of a message, in bytes.
+	pub msgsize: usize,
+	/// Mutable state.
+	state: Mutex<MqState>,
+	/// Processes waiting for a message to be sent.
+	rd_queue: WaitQueue,
+	/// Processes waiting for a message to be received.
+	wr_queue: WaitQueue,
+}
+
+impl Mqueue {
+	/// Returns the attributes of the queue.
+	///
+	/// The `mq_flags` field is left to the caller, since it belongs to the open file description.
+	pub fn attr(&self) -> MqAttr {
+		MqAttr {
+			mq_maxmsg: self.maxmsg as _,
+			mq_msgsize: self.msgsize as _,
+			mq_curmsgs: self.state.lock().messages.len() as _,
+			..Default::default()
+		}
+	}
+
+	/// Inserts the message `msg` in the queue, as `mq_timedsend` does.
+	///
+	/// If the queue is full, the function sleeps until a message is received, unless `nonblock`
+	/// is set, in which case it returns [`errno::EAGAIN`]. If `timeout` (in nanoseconds) elapses
+	/// first, the function returns [`errno::ETIMEDOUT`].
+	pub fn send(&self, msg: MqMessage, nonblock: bool, timeout: Option<Timestamp>) -> EResult<()> {
+		if unlikely(msg.data.len() > self.msgsize) {
+			return Err(errno!(EMSGSIZE));
+		}
+		let mut msg = Some(msg);
+		let notify = self.wr_queue.wait_until_timeout(
+			|| {
+				let mut state = self.state.lock();
+				if state.messages.len() >= self.maxmsg {
+					return nonblock.then(|| Err(errno!(EAGAIN)));
+				}
+				let i = insert_pos(&state.messages, msg.as_ref().unwrap().prio);
+				if let Err(e) = state.messages.insert(i, msg.take().unwrap()) {
+					return Some(Err(e.into()));
+				}
+				// The registration is consumed only if no process is waiting for the message
+				let notify = if state.messages.len() == 1 && state.receivers == 0 {
+					state.notify.take()
+				} else {
+					None
+				};
+				Some(Ok(notify))
+			},
+			timeout,
+		)??;
+		self.rd_queue.wake_all();
+		if let Some(notify) = notify {
+			notify.fire();
+		}
+		Ok(())
+	}
+
+	/// Removes the message with the highest priority from the queue, as `mq_timedreceive` does.
+	///
+	/// `size` is the size of the buffer receiving the message. If it is lower than the maximum
+	/// size of a message, the function returns [`errno::EMSGSIZE`].
+	///
+	/// If the queue is empty, the function sleeps until a message is sent, unless `nonblock` is
+	/// set, in which case it returns [`errno::EAGAIN`]. If `timeout` (in nanoseconds) elapses
+	/// first, the function returns [`errno::ETIMEDOUT`].
+	pub fn receive(
+		&self,
+		size: usize,
+		nonblock: bool,
+		timeout: Option<Timestamp>,
+	) -> EResult<MqMessage> {
+		if unlikely(size < self.msgsize) {
+			return Err(errno!(EMSGSIZE));
+		}
+		self.state.lock().receivers += 1;
+		let res = self.rd_queue.wait_until_timeout(
+			|| {
+				let mut state = self.state.lock();
+				if state.messages.is_empty() {
+					return nonblock.then(|| Err(errno!(EAGAIN)));
+				}
+				Some(Ok(state.messages.remove(0)))
+			},
+			timeout,
+		);
+		self.state.lock().receivers -= 1;
+		let msg = res??;
+		self.wr_queue.wake_all();
+		Ok(msg)
+	}
+
+	/// Registers the process `pid` to be notified with `sevp` when a message arrives on the
+	/// empty queue, as `mq_notify` does.
+	///
+	/// If `sevp` is `None`, the registration of the process is removed.
+	///
+	/// If another process is already registered, the function returns [`errno::EBUSY`].
+	pub fn set_notify(&self, pid: Pid, sevp: Option<SigEvent>) -> EResult<()> {
+		let mut state = self.state.lock();
+		match (sevp, &state.notify) {
+			(Some(_), Some(_)) => return Err(errno!(EBUSY)),
+			(Some(sevp), None) => {
+				state.notify = Some(Notify {
+					pid,
+					sevp,
+				});
+			}
+			(None, Some(notify)) if notify.pid == pid => state.notify = None,
+			(None, _) => {}
+		}
+		Ok(())
+	}
+}
+
+impl Notify {
+	/// Sends the notification.
+	fn fire(self) {
+		// With `SIGEV_NONE`, the registration is only removed
+		if self.sevp.sigev_notify != SIGEV_SIGNAL {
+			return;
+		}
+		let Ok(signal) = Signal::try_from(self.sevp.sigev_signo) else {
+			return;
+		};
+		if let Some(proc) = Process::get_by_pid(self.pid) {
+			proc.kill(signal);
+		}
+	}
+}
+
+impl FileOps for Mqueue {
+	fn get_stat(&self, _file: &File) -> EResult<Stat> {
+		let state = self.state.lock();
+		Ok(Stat {
+			mode: FileType::Regular.to_mode() | state.perm.mode,
+			uid: state.perm.uid,
+			gid: state.perm.gid,
+			..Default::default()
+		})
+	}
+
+	fn poll(&self, _file: &File, mask: u32) -> EResult<u32> {
+		let len = self.state.lock().messages.len();
+		let mut events = 0;
+		if len > 0 {
+			events |= POLLIN;
+		}
+		if len < self.maxmsg {
+			events |= POLLOUT;
+		}
+		Ok(events & mask)
+	}
+
+	fn poll_wait(
+		&self,
+		_file: &File,
+		mask: u32,
+		f: &mut dyn FnMut(&WaitQueue) -> AllocResult<()>,
+	) -> AllocResult<bool> {
+		if mask & POLLIN != 0 {
+			f(&self.rd_queue)?;
+		}
+		if mask & POLLOUT != 0 {
+			f(&self.wr_queue)?;
+		}
+		Ok(true)
+	}
+}
+
+/// The POSIX message queues of an IPC namespace.
+#[derive(Debug, Default)]
+pub struct MqIds {
+	/// Queues, by name.
+	queues: HashMap<String, Arc<Mqueue>>,
+}
+
+impl MqIds {
+	/// Returns the queue with the name `name`, creating it if necessary, as `mq_open` does.
+	///
+	/// Arguments:
+	/// - `create` tells whether the queue is created if it does not exist (`O_CREAT`)
+	/// - `excl` tells whether the function fails if the queue exists (`O_EXCL`)
+	/// - `access` is the access requested to an existing queue, as a combination of
+	///   [`crate::file::perm::S_IROTH`] and [`crate::file::perm::S_IWOTH`]
+	/// - `mode` is the permissions of a new queue
+	/// - `attr` is the attributes of a new queue. If `None`, default values are used
+	/// - `ap` is the access profile of the calling process
+	///
+	/// The creator of a queue has access to it, regardless of its permissions.
+	pub fn open(
+		&mut self,
+		name: String,
+		(create, excl): (bool, bool),
+		access: Mode,
+		mode: Mode,
+		attr: Option<MqAttr>,
+		ap: &AccessProfile,
+	) -> EResult<Arc<Mqueue>> {
+		if let Some(queue) = self.queues.get(name.as_bytes()) {
+			if unlikely(create && excl) {
+				return Err(errno!(EEXIST));
+			}
+			if unlikely(!queue.state.lock().perm.can_access(ap, access)) {
+				return Err(errno!(EACCES));
+			}
+			return Ok(queue.clone());
+		}
+		if unlikely(!create) {
+			return Err(errno!(ENOENT));
+		}
+		let privileged = ap.has_capability(CAP_SYS_RESOURCE);
+		if unlikely(self.queues.len() >= QUEUES_MAX && !privileged) {
+			return Err(errno!(ENOSPC));
+		}
+		let (maxmsg, msgsize) = match attr {
+			Some(attr) => {
+				let (maxmsg, msgsize) = (attr.mq_maxmsg, attr.mq_msgsize);
+				if unlikely(maxmsg <= 0 || msgsize <= 0) {
+					return Err(errno!(EINVAL));
+				}
+				let (maxmsg, msgsize) = (maxmsg as usize, msgsize as usize);
+				let (max, size_max) = if privileged {
+					(HARD_MSGMAX, HARD_MSGSIZEMAX)
+				} else {
+					(MSG_MAX, MSGSIZE_MAX)
+				};
+				if unlikely(maxmsg > max || msgsize > size_max) {
+					return Err(errno!(EINVAL));
+				}
+				(maxmsg, msgsize)
+			}
+			None => (DFLT_MAXMSG, DFLT_MSGSIZE),
+		};
+		let queue = Arc::new(Mqueue {
+			maxmsg,
+			msgsize,
+			state: Mutex::new(MqState {
+				perm: IpcObjectPerm::new(IPC_PRIVATE, ap, mode),
+				messages: Vec::new(),
+				receivers: 0,
+				notify: None,
+			}),
+			rd_queue: WaitQueue::new(),
+			wr_queue: WaitQueue::new(),
+		})?;
+		self.queues.insert(name, queue.clone())?;
+		Ok(queue)
+	}
+
+	/// Removes the name `name`, on behalf of the agent `ap`, as `mq_unlink` does.
+	///
+	/// The queue is destroyed once no file descriptor refers to it anymore.
+	pub fn unlink(&mut self, name: &[u8], ap: &AccessProfile) -> EResult<()> {
+		let queue = self.queues.get(name).ok_or_else(|| errno!(ENOENT))?;
+		if unlikely(!queue.state.lock().perm.is_owner(ap)) {
+			return Err(errno!(EACCES));
+		}
+		self.queues.remove(name);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn mqueue_insert_pos() {
+		let messages = [5, 1, 1]
+			.into_iter()
+			.map(|prio| MqMessage {
+				prio,
+				data: Vec::new(),
+			})
+			.collect::<utils::errno::CollectResult<Vec<_>>>()
+			.0
+			.unwrap();
+		assert_eq!(insert_pos(&messages, 6), 0);
+		assert_eq!(insert_pos(&messages, 5), 1);
+		assert_eq!(insert_pos(&messages, 1), 3);
+		assert_eq!(insert_pos(&messages, 0), 3);
+		assert_eq!(insert_pos(&[], 0), 0);
+	}
+}
@@ -0,0 +1,455 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV message queues.
+//!
+//! A message queue holds typed messages, and is identified in an IPC namespace by an ID and
+//! optionally bound to a key. `msgsnd` appends a message to a queue, sleeping while the queue is
+//! full, and `msgrcv` removes a message selected by its type, sleeping while there is none.
+
+use crate::{
+	file::{
+		Mode,
+		perm::{AccessProfile, CAP_SYS_RESOURCE, S_IROTH, S_IWOTH},
+		wait_queue::WaitQueue,
+	},
+	process::{
+		ns::{CompatIpcPerm, IPC_NOWAIT, IPC_PRIVATE, IpcIds, IpcObjectPerm, IpcPerm, get_access},
+		pid::Pid,
+	},
+	sync::mutex::{Mutex, MutexGuard},
+	time::{
+		clock::{Clock, current_time_sec},
+		unit::Timestamp,
+	},
+};
+use core::{
+	ffi::{c_int, c_long, c_ulong},
+	hint::unlikely,
+};
+use utils::{
+	collections::{hashmap::HashMap, vec::Vec},
+	errno,
+	errno::EResult,
+	ptr::arc::Arc,
+};
+
+/// `msgrcv` flag: truncate messages larger than the buffer instead of failing.
+pub const MSG_NOERROR: c_int = 0o10000;
+/// `msgrcv` flag: receive the first message whose type is not the given one.
+pub const MSG_EXCEPT: c_int = 0o20000;
+
+/// The maximum size of a message, in bytes.
+pub const MSGMAX: usize = 8192;
+/// The default maximum number of bytes in a queue.
+pub const MSGMNB: usize = 16384;
+/// The maximum number of message queues in an IPC namespace.
+pub const MSGMNI: usize = 32000;
+
+/// Status of a message queue.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsqidDs {
+	pub msg_perm: IpcPerm,
+	pub msg_stime: c_long,
+	pub msg_rtime: c_long,
+	pub msg_ctime: c_long,
+	pub msg_cbytes: c_ulong,
+	pub msg_qnum: c_ulong,
+	pub msg_qbytes: c_ulong,
+	pub msg_lspid: c_int,
+	pub msg_lrpid: c_int,
+	pub _unused4: c_ulong,
+	pub _unused5: c_ulong,
+}
+
+/// Compatibility version of [`MsqidDs`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompatMsqidDs {
+	pub msg_perm: CompatIpcPerm,
+	pub msg_stime: u32,
+	pub msg_stime_high: u32,
+	pub msg_rtime: u32,
+	pub msg_rtime_high: u32,
+	pub msg_ctime: u32,
+	pub msg_ctime_high: u32,
+	pub msg_cbytes: u32,
+	pub msg_qnum: u32,
+	pub msg_qbytes: u32,
+	pub msg_lspid: c_int,
+	pub msg_lrpid: c_int,
+	pub _unused4: u32,
+	pub _unused5: u32,
+}
+
+impl From<MsqidDs> for CompatMsqidDs {
+	fn from(ds: MsqidDs) -> Self {
+		let (stime, rtime, ctime) = (
+			ds.msg_stime as u64,
+			ds.msg_rtime as u64,
+			ds.msg_ctime as u64,
+		);
+		Self {
+			msg_perm: ds.msg_perm.into(),
+			msg_stime: stime as _,
+			msg_stime_high: (stime >> 32) as _,
+			msg_rtime: rtime as _,
+			msg_rtime_high: (rtime >> 32) as _,
+			msg_ctime: ctime as _,
+			msg_ctime_high: (ctime >> 32) as _,
+			msg_cbytes: ds.msg_cbytes as _,
+			msg_qnum: ds.msg_qnum as _,
+			msg_qbytes: ds.msg_qbytes as _,
+			msg_lspid: ds.msg_lspid,
+			msg_lrpid: ds.msg_lrpid,
+			..Default::default()
+		}
+	}
+}
+
+impl From<CompatMsqidDs> for MsqidDs {
+	fn from(ds: CompatMsqidDs) -> Self {
+		let time = |low: u32, high: u32| ((high as u64) << 32 | low as u64) as _;
+		Self {
+			msg_perm: ds.msg_perm.into(),
+			msg_stime: time(ds.msg_stime, ds.msg_stime_high),
+			msg_rtime: time(ds.msg_rtime, ds.msg_rtime_high),
+			msg_ctime: time(ds.msg_ctime, ds.msg_ctime_high),
+			msg_cbytes: ds.msg_cbytes as _,
+			msg_qnum: ds.msg_qnum as _,
+			msg_qbytes: ds.msg_qbytes as _,
+			msg_lspid: ds.msg_lspid,
+			msg_lrpid: ds.msg_lrpid,
+			..Default::default()
+		}
+	}
+}
+
+/// A message.
+#[derive(Debug)]
+pub struct Message {
+	/// The type of the message, strictly positive.
+	pub mtype: c_long,
+	/// The content of the message.
+	pub data: Vec<u8>,
+}
+
+/// Returns the index of the message to receive in `messages`, for the type `mtype`:
+/// - if zero, the first message
+/// - if positive, the first message of type `mtype`, or of another type if `except` is set
+/// - if negative, the first message with the lowest type not greater than the absolute value of
+///   `mtype`
+fn select(messages: &[Message], mtype: c_long, except: bool) -> Option<usize> {
+	let mut iter = messages.iter().enumerate();
+	match mtype {
+		0 => iter.next(),
+		1.. => iter.find(|(_, msg)| (msg.mtype == mtype) != except),
+		_ => iter
+			.filter(|(_, msg)| msg.mtype.unsigned_abs() <= mtype.unsigned_abs())
+			.min_by_key(|(_, msg)| msg.mtype),
+	}
+	.map(|(i, _)| i)
+}
+
+/// Mutable state of a message queue.
+#[derive(Debug)]
+struct MsgState {
+	/// Ownership and permissions.
+	perm: IpcObjectPerm,
+	/// The messages, in order of arrival.
+	messages: Vec<Message>,
+	/// The number of bytes in the messages of the queue.
+	cbytes: usize,
+	/// The maximum number of bytes in the messages of the queue.
+	qbytes: usize,
+
+	/// The timestamp of the last `msgsnd`, in seconds.
+	stime: Timestamp,
+	/// The timestamp of the last `msgrcv`, in seconds.
+	rtime: Timestamp,
+	/// The timestamp of the last change, in seconds.
+	ctime: Timestamp,
+	/// The PID of the last process which sent a message.
+	lspid: Pid,
+	/// The PID of the last process which received a message.
+	lrpid: Pid,
+	/// Tells whether the queue has been removed.
+	removed: bool,
+}
+
+/// A SysV message queue.
+#[derive(Debug)]
+pub struct MsgQueue {
+	/// The ID of the queue in its namespace.
+	pub id: c_int,
+	/// Mutable state.
+	state: Mutex<MsgState>,
+	/// Processes waiting for a message to be sent.
+	rd_queue: WaitQueue,
+	/// Processes waiting for a message to be received.
+	wr_queue: WaitQueue,
+}
+
+impl MsgQueue {
+	/// Locks the state of the queue, checking the agent `ap` has the access `access` to it.
+	///
+	/// Errors:
+	/// - [`errno::EIDRM`]: the queue has been removed
+	/// - [`errno::EACCES`]: the agent does not have the requested access
+	fn lock(&self, ap: &AccessProfile, access: Mode) -> EResult<MutexGuard<'_, MsgState, true>> {
+		let state = self.state.lock();
+		if unlikely(state.removed) {
+			return Err(errno!(EIDRM));
+		}
+		if unlikely(!state.perm.can_access(ap, access)) {
+			return Err(errno!(EACCES));
+		}
+		Ok(state)
+	}
+
+	/// Appends the message `msg` to the queue, as `msgsnd` does, on behalf of the agent `ap` in
+	/// the process `pid`.
+	///
+	/// If the queue is full, the function sleeps until enough messages are received, unless
+	/// [`IPC_NOWAIT`] is set in `flags`, in which case it returns [`errno::EAGAIN`].
+	pub fn send(&self, ap: &AccessProfile, pid: Pid, msg: Message, flags: c_int) -> EResult<()> {
+		if unlikely(msg.mtype <= 0) {
+			return Err(errno!(EINVAL));
+		}
+		self.lock(ap, S_IWOTH)?;
+		let mut msg = Some(msg);
+		self.wr_queue.wait_until(|| {
+			let mut state = self.state.lock();
+			if state.removed {
+				return Some(Err(errno!(EIDRM)));
+			}
+			let len = msg.as_ref().unwrap().data.len();
+			// As on Linux, the number of messages is limited by the size of the queue, so that
+			// empty messages cannot fill the memory
+			let full = state.cbytes + len > state.qbytes || state.messages.len() >= state.qbytes;
+			if full {
+				return (flags & IPC_NOWAIT != 0).then(|| Err(errno!(EAGAIN)));
+			}
+			if let Err(e) = state.messages.reserve(1) {
+				return Some(Err(e.into()));
+			}
+			// Cannot fail since memory has been reserved
+			let _ = state.messages.push(msg.take().unwrap());
+			state.cbytes += len;
+			state.lspid = pid;
+			state.stime = current_time_sec(Clock::Realtime);
+			Some(Ok(()))
+		})??;
+		self.rd_queue.wake_all();
+		Ok(())
+	}
+
+	/// Removes a message from the queue, as `msgrcv` does, on behalf of the agent `ap` in the
+	/// process `pid`.
+	///
+	/// Arguments:
+	/// - `size` is the maximum size of the message, in bytes
+	/// - `mtype` is the type of the message to receive (see `msgrcv(2)`)
+	/// - `flags` are the `msgrcv` flags
+	///
+	/// If there is no matching message, the function sleeps until one is sent, unless
+	/// [`IPC_NOWAIT`] is set in `flags`, in which case it returns [`errno::ENOMSG`].
+	///
+	/// If the message is larger than `size`, the function returns [`errno::E2BIG`], unless
+	/// [`MSG_NOERROR`] is set in `flags`, in which case the message is truncated.
+	pub fn receive(
+		&self,
+		ap: &AccessProfile,
+		pid: Pid,
+		size: usize,
+		mtype: c_long,
+		flags: c_int,
+	) -> EResult<Message> {
+		self.lock(ap, S_IROTH)?;
+		let msg = self.rd_queue.wait_until(|| {
+			let mut state = self.state.lock();
+			if state.removed {
+				return Some(Err(errno!(EIDRM)));
+			}
+			let Some(i) = select(&state.messages, mtype, flags & MSG_EXCEPT != 0) else {
+				return (flags & IPC_NOWAIT != 0).then(|| Err(errno!(ENOMSG)));
+			};
+			if state.messages[i].data.len() > size && flags & MSG_NOERROR == 0 {
+				return Some(Err(errno!(E2BIG)));
+			}
+			let mut msg = state.messages.remove(i);
+			state.cbytes -= msg.data.len();
+			state.lrpid = pid;
+			state.rtime = current_time_sec(Clock::Realtime);
+			msg.data.truncate(size);
+			Some(Ok(msg))
+		})??;
+		self.wr_queue.wake_all();
+		Ok(msg)
+	}
+
+	/// Returns the status of the queue, on behalf of the agent `ap`.
+	pub fn stat(&self, ap: &AccessProfile) -> EResult<MsqidDs> {
+		let state = self.lock(ap, S_IROTH)?;
+		Ok(MsqidDs {
+			msg_perm: state.perm.to_ipc_perm(),
+			msg_stime: state.stime as _,
+			msg_rtime: state.rtime as _,
+			msg_ctime: state.ctime as _,
+			msg_cbytes: state.cbytes as _,
+			msg_qnum: state.messages.len() as _,
+			msg_qbytes: state.qbytes as _,
+			msg_lspid: state.lspid as _,
+			msg_lrpid: state.lrpid as _,
+			..Default::default()
+		})
+	}
+
+	/// Sets the owner, permissions and maximum size of the queue from `ds`, on behalf of the
+	/// agent `ap`.
+	///
+	/// Errors:
+	/// - [`errno::EPERM`]: the agent does not own the queue, or raises its maximum size above
+	///   [`MSGMNB`] without the `CAP_SYS_RESOURCE` capability
+	pub fn set(&self, ap: &AccessProfile, ds: &MsqidDs) -> EResult<()> {
+		let qbytes = ds.msg_qbytes as usize;
+		{
+			let mut state = self.state.lock();
+			if unlikely(state.removed) {
+				return Err(errno!(EIDRM));
+			}
+			if unlikely(qbytes > MSGMNB && !ap.has_capability(CAP_SYS_RESOURCE)) {
+				return Err(errno!(EPERM));
+			}
+			state.perm.set(ap, &ds.msg_perm)?;
+			state.qbytes = qbytes;
+			state.ctime = current_time_sec(Clock::Realtime);
+		}
+		// The queue may have more room
+		self.wr_queue.wake_all();
+		Ok(())
+	}
+}
+
+/// The message queues of an IPC namespace.
+#[derive(Debug, Default)]
+pub struct MsgIds {
+	/// Identifiers and keys of queues.
+	ids: IpcIds,
+	/// Queues, by ID.
+	queues: HashMap<c_int, Arc<MsgQueue>>,
+}
+
+impl MsgIds {
+	/// Returns the ID of the queue bound to `key`, creating it if necessary, as `msgget` does.
+	///
+	/// Arguments:
+	/// - `flags` are the `msgget` flags, along with the permissions of a new queue
+	/// - `ap` is the access profile of the calling process
+	pub fn get(&mut self, key: c_int, flags: c_int, ap: &AccessProfile) -> EResult<c_int> {
+		if let Some(id) = self.ids.get(key, flags)? {
+			self.queues[id].lock(ap, get_access(flags))?;
+			return Ok(id);
+		}
+		// Create the queue
+		if unlikely(self.queues.len() >= MSGMNI) {
+			return Err(errno!(ENOSPC));
+		}
+		let id = self.ids.alloc(key)?;
+		let queue = Arc::new(MsgQueue {
+			id,
+			state: Mutex::new(MsgState {
+				perm: IpcObjectPerm::new(key, ap, flags as _),
+				messages: Vec::new(),
+				cbytes: 0,
+				qbytes: MSGMNB,
+
+				stime: 0,
+				rtime: 0,
+				ctime: current_time_sec(Clock::Realtime),
+				lspid: 0,
+				lrpid: 0,
+				removed: false,
+			}),
+			rd_queue: WaitQueue::new(),
+			wr_queue: WaitQueue::new(),
+		});
+		let res = queue.and_then(|queue| self.queues.insert(id, queue));
+		if let Err(e) = res {
+			self.ids.remove(key);
+			return Err(e.into());
+		}
+		Ok(id)
+	}
+
+	/// Returns the queue with ID `id`.
+	///
+	/// If the queue does not exist, the function returns [`errno::EINVAL`].
+	pub fn get_queue(&self, id: c_int) -> EResult<Arc<MsgQueue>> {
+		self.queues.get(&id).cloned().ok_or_else(|| errno!(EINVAL))
+	}
+
+	/// Removes the queue with ID `id`, on behalf of the agent `ap`.
+	///
+	/// Processes waiting on the queue are woken up, and fail with [`errno::EIDRM`].
+	pub fn remove(&mut self, id: c_int, ap: &AccessProfile) -> EResult<()> {
+		let queue = self.get_queue(id)?;
+		{
+			let mut state = queue.state.lock();
+			if unlikely(!state.perm.is_owner(ap)) {
+				return Err(errno!(EPERM));
+			}
+			if state.perm.key != IPC_PRIVATE {
+				self.ids.remove(state.perm.key);
+			}
+			state.removed = true;
+			state.messages.clear();
+		}
+		self.queues.remove(&id);
+		queue.rd_queue.wake_all();
+		queue.wr_queue.wake_all();
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn msg_select() {
+		let messages = [3, 1, 2, 1]
+			.into_iter()
+			.map(|mtype| Message {
+				mtype,
+				data: Vec::new(),
+			})
+			.collect::<utils::errno::CollectResult<Vec<_>>>()
+			.0
+			.unwrap();
+		assert_eq!(select(&messages, 0, false), Some(0));
+		assert_eq!(select(&messages, 2, false), Some(2));
+		assert_eq!(select(&messages, 3, true), Some(1));
+		assert_eq!(select(&messages, 4, false), None);
+		assert_eq!(select(&messages, -2, false), Some(1));
+		assert_eq!(select(&[], 0, false), None);
+	}
+}
@@ -0,0 +1,386 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! UTS, IPC and network namespaces.
+//!
+//! A namespace isolates a global resource, so that processes in different namespaces see
+//! different instances of it:
+//! - the UTS namespace holds the hostname and the domain name
+//! - the IPC namespace holds the identifier spaces of SysV IPC objects, and the names of POSIX
+//!   message queues
+//! - the network namespace holds the network interfaces, routes and sockets (see [`NetNamespace`])
+//!
+//! Mount namespaces are handled by [`crate::file::vfs::mountpoint`].
+
+use crate::{
+	file::{
+		FileType, Mode, Stat,
+		perm::{AccessProfile, CAP_IPC_OWNER, Gid, S_IROTH, S_IWOTH, S_IXOTH, Uid},
+	},
+	memory::shm::ShmIds,
+	net::NetNamespace,
+	process::{mqueue::MqIds, msg::MsgIds, sem::SemIds},
+	sync::mutex::Mutex,
+};
+use core::{
+	ffi::{c_int, c_ulong},
+	hint::unlikely,
+};
+use utils::{
+	TryClone,
+	collections::{hashmap::HashMap, vec::Vec},
+	errno,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// Key creating a SysV IPC object that cannot be looked up by key.
+pub const IPC_PRIVATE: c_int = 0;
+
+/// `*get` flag: create the object if it does not exist.
+pub const IPC_CREAT: c_int = 0o1000;
+/// `*get` flag: with [`IPC_CREAT`], fail if the object already exists.
+pub const IPC_EXCL: c_int = 0o2000;
+/// Operation flag: fail with [`errno::EAGAIN`] instead of blocking.
+pub const IPC_NOWAIT: c_int = 0o4000;
+
+/// `*ctl` command: remove the object.
+pub const IPC_RMID: c_int = 0;
+/// `*ctl` command: set the owner and permissions of the object.
+pub const IPC_SET: c_int = 1;
+/// `*ctl` command: get the status of the object.
+pub const IPC_STAT: c_int = 2;
+/// `*ctl` command flag: use the 64 bit version of structures.
+pub const IPC_64: c_int = 0x100;
+
+/// Ownership and permissions of a SysV IPC object.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IpcPerm {
+	pub key: c_int,
+	pub uid: u32,
+	pub gid: u32,
+	pub cuid: u32,
+	pub cgid: u32,
+	pub mode: u32,
+	pub seq: u16,
+	pub _pad: u16,
+	pub _unused1: c_ulong,
+	pub _unused2: c_ulong,
+}
+
+/// Compatibility version of [`IpcPerm`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompatIpcPerm {
+	pub key: c_int,
+	pub uid: u32,
+	pub gid: u32,
+	pub cuid: u32,
+	pub cgid: u32,
+	pub mode: u32,
+	pub seq: u16,
+	pub _pad: u16,
+	pub _unused1: u32,
+	pub _unused2: u32,
+}
+
+impl From<IpcPerm> for CompatIpcPerm {
+	fn from(perm: IpcPerm) -> Self {
+		Self {
+			key: perm.key,
+			uid: perm.uid,
+			gid: perm.gid,
+			cuid: perm.cuid,
+			cgid: perm.cgid,
+			mode: perm.mode,
+			seq: perm.seq,
+			..Default::default()
+		}
+	}
+}
+
+impl From<CompatIpcPerm> for IpcPerm {
+	fn from(perm: CompatIpcPerm) -> Self {
+		Self {
+			key: perm.key,
+			uid: perm.uid,
+			gid: perm.gid,
+			cuid: perm.cuid,
+			cgid: perm.cgid,
+			mode: perm.mode,
+			seq: perm.seq,
+			..Default::default()
+		}
+	}
+}
+
+/// Returns the access requested by the flags `flags` of a `*get` system call, as a combination
+/// of [`S_IROTH`], [`S_IWOTH`] and [`S_IXOTH`].
+pub fn get_access(flags: c_int) -> Mode {
+	let flags = flags as Mode;
+	(flags >> 6 | flags >> 3 | flags) & 0o7
+}
+
+/// Ownership and permissions of a SysV IPC object.
+#[derive(Debug)]
+pub struct IpcObjectPerm {
+	/// The key the object was created with, or [`IPC_PRIVATE`] once removed.
+	pub key: c_int,
+	/// The user ID of the owner.
+	pub uid: Uid,
+	/// The group ID of the owner.
+	pub gid: Gid,
+	/// The user ID of the creator.
+	pub cuid: Uid,
+	/// The group ID of the creator.
+	pub cgid: Gid,
+	/// The permissions, along with flags specific to the kind of object.
+	pub mode: Mode,
+}
+
+impl IpcObjectPerm {
+	/// Returns the permissions of an object created with `key` by the agent `ap`, with the
+	/// permissions in `mode`.
+	pub fn new(key: c_int, ap: &AccessProfile, mode: Mode) -> Self {
+		Self {
+			key,
+			uid: ap.euid,
+			gid: ap.egid,
+			cuid: ap.euid,
+			cgid: ap.egid,
+			mode: mode & 0o777,
+		}
+	}
+
+	/// Tells whether the agent `ap` owns the object, or has the capability to manage it.
+	pub fn is_owner(&self, ap: &AccessProfile) -> bool {
+		ap.euid == self.uid || ap.euid == self.cuid || ap.has_capability(CAP_IPC_OWNER)
+	}
+
+	/// Tells whether the agent `ap` has the access `access` to the object.
+	///
+	/// `access` is a combination of [`S_IROTH`], [`S_IWOTH`] and [`S_IXOTH`].
+	pub fn can_access(&self, ap: &AccessProfile, access: Mode) -> bool {
+		if ap.has_capability(CAP_IPC_OWNER) {
+			return true;
+		}
+		let stat = Stat {
+			mode: FileType::Regular.to_mode() | (self.mode & 0o777),
+			uid: self.uid,
+			gid: self.gid,
+			..Default::default()
+		};
+		(access & S_IROTH == 0 || ap.can_read_file(&stat))
+			&& (access & S_IWOTH == 0 || ap.can_write_file(&stat))
+			&& (access & S_IXOTH == 0 || ap.can_execute_file(&stat))
+	}
+
+	/// Returns the userspace representation of the permissions.
+	pub fn to_ipc_perm(&self) -> IpcPerm {
+		IpcPerm {
+			key: self.key,
+			uid: self.uid as _,
+			gid: self.gid as _,
+			cuid: self.cuid as _,
+			cgid: self.cgid as _,
+			mode: self.mode,
+			..Default::default()
+		}
+	}
+
+	/// Sets the owner and permissions from `perm`, on behalf of the agent `ap`.
+	///
+	/// If the agent does not own the object, the function returns [`errno::EPERM`].
+	pub fn set(&mut self, ap: &AccessProfile, perm: &IpcPerm) -> EResult<()> {
+		if unlikely(!self.is_owner(ap)) {
+			return Err(errno!(EPERM));
+		}
+		self.uid = perm.uid.try_into().map_err(|_| errno!(EINVAL))?;
+		self.gid = perm.gid.try_into().map_err(|_| errno!(EINVAL))?;
+		self.mode = (self.mode & !0o777) | (perm.mode & 0o777);
+		Ok(())
+	}
+}
+
+/// A UTS namespace.
+#[derive(Debug, Default)]
+pub struct UtsNamespace {
+	/// The hostname.
+	pub hostname: Mutex<Vec<u8>>,
+	/// The NIS domain name.
+	pub domainname: Mutex<Vec<u8>>,
+}
+
+/// Identifiers of one kind of SysV IPC objects (message queues, semaphore sets or shared memory
+/// segments).
+#[derive(Debug, Default)]
+pub struct IpcIds {
+	/// The identifiers of objects bound to a key, by key.
+	keys: HashMap<c_int, c_int>,
+	/// The next identifier to allocate.
+	next_id: c_int,
+}
+
+impl IpcIds {
+	/// Returns the identifier of the object bound to `key`, if any.
+	pub fn lookup(&self, key: c_int) -> Option<c_int> {
+		self.keys.get(&key).cloned()
+	}
+
+	/// Looks up the object bound to `key`, as the `*get` system calls do with the flags `flags`.
+	///
+	/// If a new object has to be created, the function returns `None`.
+	///
+	/// Errors:
+	/// - [`errno::EEXIST`]: the object exists, while [`IPC_CREAT`] and [`IPC_EXCL`] are set
+	/// - [`errno::ENOENT`]: the object does not exist, while [`IPC_CREAT`] is not set
+	pub fn get(&self, key: c_int, flags: c_int) -> EResult<Option<c_int>> {
+		if key == IPC_PRIVATE {
+			return Ok(None);
+		}
+		match self.lookup(key) {
+			Some(_) if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL => {
+				Err(errno!(EEXIST))
+			}
+			Some(id) => Ok(Some(id)),
+			None if flags & IPC_CREAT == 0 => Err(errno!(ENOENT)),
+			None => Ok(None),
+		}
+	}
+
+	/// Allocates an identifier for a new object, bound to `key`.
+	///
+	/// If `key` is [`IPC_PRIVATE`], the object is not bound to any key.
+	///
+	/// If an object is already bound to `key`, the function returns [`errno::EEXIST`].
+	pub fn alloc(&mut self, key: c_int) -> EResult<c_int> {
+		if key != IPC_PRIVATE && self.keys.contains_key(&key) {
+			return Err(errno!(EEXIST));
+		}
+		let id = self.next_id;
+		if key != IPC_PRIVATE {
+			self.keys.insert(key, id)?;
+		}
+		self.next_id = self.next_id.checked_add(1).ok_or_else(|| errno!(ENOSPC))?;
+		Ok(id)
+	}
+
+	/// Unbinds `key`, after its object has been removed.
+	pub fn remove(&mut self, key: c_int) {
+		self.keys.remove(&key);
+	}
+}
+
+/// An IPC namespace.
+#[derive(Debug, Default)]
+pub struct IpcNamespace {
+	/// Message queues.
+	pub msg: Mutex<MsgIds>,
+	/// Semaphore sets.
+	pub sem: Mutex<SemIds>,
+	/// Shared memory segments.
+	pub shm: Mutex<ShmIds>,
+	/// POSIX message queues.
+	pub mq: Mutex<MqIds>,
+}
+
+/// The set of namespaces a process belongs to, besides its mount namespace.
+#[derive(Clone, Debug)]
+pub struct Namespaces {
+	/// The UTS namespace.
+	pub uts: Arc<UtsNamespace>,
+	/// The IPC namespace.
+	pub ipc: Arc<IpcNamespace>,
+	/// The network namespace.
+	pub net: Arc<NetNamespace>,
+}
+
+impl Namespaces {
+	/// Returns a copy of the set of namespaces, in which the UTS, IPC and network namespaces are
+	/// replaced with new ones if `uts`, `ipc` and `net` are respectively set.
+	///
+	/// A new UTS namespace starts with a copy of the hostname and domain name, while a new IPC
+	/// namespace starts empty and a new network namespace only has a loopback interface.
+	pub fn unshare(&self, uts: bool, ipc: bool, net: bool) -> AllocResult<Self> {
+		let uts = if uts {
+			Arc::new(UtsNamespace {
+				hostname: Mutex::new(self.uts.hostname.lock().try_clone()?),
+				domainname: Mutex::new(self.uts.domainname.lock().try_clone()?),
+			})?
+		} else {
+			self.uts.clone()
+		};
+		let ipc = if ipc {
+			Arc::new(IpcNamespace::default())?
+		} else {
+			self.ipc.clone()
+		};
+		let net = if net {
+			NetNamespace::new()?
+		} else {
+			self.net.clone()
+		};
+		Ok(Self {
+			uts,
+			ipc,
+			net,
+		})
+	}
+}
+
+/// The namespaces of the init process, created on first use.
+static INIT: Mutex<Option<Namespaces>> = Mutex::new(None);
+
+/// Returns the namespaces of the init process, which are the ones of processes that never
+/// unshared them.
+pub fn init_namespaces() -> AllocResult<Namespaces> {
+	let mut init = INIT.lock();
+	if let Some(ns) = &*init {
+		return Ok(ns.clone());
+	}
+	let ns = Namespaces {
+		uts: Arc::new(UtsNamespace::default())?,
+		ipc: Arc::new(IpcNamespace::default())?,
+		net: NetNamespace::new()?,
+	};
+	*init = Some(ns.clone());
+	Ok(ns)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn ipc_ids() {
+		let mut ids = IpcIds::default();
+		let id = ids.alloc(42).unwrap();
+		assert_eq!(ids.lookup(42), Some(id));
+		assert!(ids.alloc(42).is_err());
+		// Private objects get distinct identifiers and are not bound to a key
+		let private = ids.alloc(IPC_PRIVATE).unwrap();
+		assert_ne!(private, id);
+		assert_ne!(ids.alloc(IPC_PRIVATE).unwrap(), private);
+		assert_eq!(ids.lookup(IPC_PRIVATE), None);
+		ids.remove(42);
+		assert_eq!(ids.lookup(42), None);
+	}
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Resource limits of processes.
+
+use super::{Process, signal::Signal};
+use crate::file::fd::NR_OPEN;
+use core::{ffi::c_int, sync::atomic::Ordering::Relaxed};
+use utils::limits::OPEN_MAX;
+
+/// The amount of seconds of CPU time the process can consume.
+pub const RLIMIT_CPU: c_int = 0;
+/// The maximum size of a file the process may create, in bytes.
+pub const RLIMIT_FSIZE: c_int = 1;
+/// The maximum size of the process's data segment in bytes, rounded down to the
+/// page size.
+pub const RLIMIT_DATA: c_int = 2;
+/// The maximum size of the process stack, in bytes.
+pub const RLIMIT_STACK: c_int = 3;
+/// The maximum size of a kernel file the process may dump in bytes.
+pub const RLIMIT_CORE: c_int = 4;
+/// A limit on the process's resident set (the number of virtual pages resident in RAM).
+pub const RLIMIT_RSS: c_int = 5;
+/// The limit on the number of threads for the real user ID of the calling process.
+pub const RLIMIT_NPROC: c_int = 6;
+/// A value one greater than the maximum number of file descriptors that can be
+/// open by the process.
+pub const RLIMIT_NOFILE: c_int = 7;
+/// The maximum number of butes of memory that may be locked into RAM.
+pub const RLIMIT_MEMLOCK: c_int = 8;
+/// The maximum size of the memory space in bytes, rounded down to the page
+/// size.
+pub const RLIMIT_AS: c_int = 9;
+/// The limit on the combined number of flock(2) locks and fcntl(2) leases the
+/// process may establish.
+pub const RLIMIT_LOCKS: c_int = 10;
+/// The limit on the number of signals that may be queued for the real user ID of the calling
+/// process.
+pub const RLIMIT_SIGPENDING: c_int = 11;
+/// The limit on the number of bytes that can be allocated for POSIX message queues for the real
+/// user IF of the calling process.
+pub const RLIMIT_MSGQUEUE: c_int = 12;
+/// The ceiling to which the process's nice value can be raised.
+pub const RLIMIT_NICE: c_int = 13;
+/// The ceiling on the real-time priority that may be set for this process.
+pub const RLIMIT_RTPRIO: c_int = 14;
+/// The limit (in microseconds) on the amount of CPU that a process scheduled under a real-time
+/// scheduling policy may consume without masking a blocking system call.
+pub const RLIMIT_RTTIME: c_int = 15;
+/// The number of resource limits.
+pub const RLIMIT_NLIMITS: usize = 16;
+
+/// Value of a resource limit meaning there is no limit.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// The default soft limit for the size of the stack, in bytes.
+pub const DEFAULT_STACK_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// A resource limit.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RLimit {
+	/// Soft limit
+	pub rlim_cur: u64,
+	/// Hard limit (ceiling for [`rlim_cur`])
+	pub rlim_max: u64,
+}
+
+impl Default for RLimit {
+	fn default() -> Self {
+		Self {
+			rlim_cur: RLIM_INFINITY,
+			rlim_max: RLIM_INFINITY,
+		}
+	}
+}
+
+/// The resource limits of a process, by resource ID.
+pub type RLimits = [RLimit; RLIMIT_NLIMITS];
+
+/// Returns the default resource limits of a process.
+pub fn default_limits() -> RLimits {
+	let mut limits = [RLimit::default(); RLIMIT_NLIMITS];
+	limits[RLIMIT_NOFILE as usize] = RLimit {
+		rlim_cur: OPEN_MAX as _,
+		rlim_max: NR_OPEN.load(Relaxed) as _,
+	};
+	limits[RLIMIT_STACK as usize] = RLimit {
+		rlim_cur: DEFAULT_STACK_LIMIT,
+		rlim_max: RLIM_INFINITY,
+	};
+	// Raising the scheduling priority requires privileges by default
+	limits[RLIMIT_NICE as usize] = RLimit {
+		rlim_cur: 0,
+		rlim_max: 0,
+	};
+	limits[RLIMIT_RTPRIO as usize] = RLimit {
+		rlim_cur: 0,
+		rlim_max: 0,
+	};
+	limits
+}
+
+/// Checks the CPU time consumed by `proc` against its [`RLIMIT_CPU`] limit.
+///
+/// When the soft limit is reached, `SIGXCPU` is sent and the soft limit is raised by one second,
+/// so that the signal is sent again for each additional second of CPU time. When the hard limit
+/// is reached, `SIGKILL` is sent.
+///
+/// `cpu_time` is the total CPU time consumed by the process, in nanoseconds.
+pub fn check_cpu_limit(proc: &Process, cpu_time: u64) {
+	let secs = cpu_time / 1_000_000_000;
+	let sig = {
+		let mut rlimits = proc.rlimits.lock();
+		let limit = &mut rlimits[RLIMIT_CPU as usize];
+		if secs >= limit.rlim_max {
+			Signal::SIGKILL
+		} else if secs >= limit.rlim_cur {
+			limit.rlim_cur = (secs + 1).min(limit.rlim_max);
+			Signal::SIGXCPU
+		} else {
+			return;
+		}
+	};
+	proc.kill(sig);
+}
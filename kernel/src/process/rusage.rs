@@ -18,12 +18,26 @@
 
 //! Monitoring of the resource usage of processes.
 
-use crate::time::unit::Timeval;
+use crate::{
+	process::scheduler::core_local,
+	time::unit::{TimeUnit, Timeval},
+};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use utils::limits::PAGE_SIZE;
 
-// TODO Place calls in kernel's code to update usage
+/// The number of clock ticks per second, used to report CPU times to userspace.
+pub const USER_HZ: u64 = 100;
+/// The size of the blocks counted by [`Rusage::ru_inblock`] and [`Rusage::ru_oublock`], in bytes.
+pub const IO_BLOCK_SIZE: usize = 512;
+
+/// Converts the duration `ns`, in nanoseconds, to clock ticks.
+pub fn ns_to_ticks(ns: u64) -> u64 {
+	ns / (1_000_000_000 / USER_HZ)
+}
 
 /// Usage of each resource by a process.
 #[derive(Clone, Debug, Default)]
+#[repr(C)]
 pub struct Rusage {
 	/// User CPU time used.
 	pub ru_utime: Timeval,
@@ -58,3 +72,143 @@ pub struct Rusage {
 	/// Involuntary context switches.
 	pub ru_nivcsw: i64,
 }
+
+impl Rusage {
+	/// Adds the resources used by `other`, a terminated child process, to `self`.
+	///
+	/// The maximum resident set size is the largest of both.
+	pub fn accumulate(&mut self, other: &Self) {
+		self.ru_utime = Timeval::from_nano(self.ru_utime.to_nano() + other.ru_utime.to_nano());
+		self.ru_stime = Timeval::from_nano(self.ru_stime.to_nano() + other.ru_stime.to_nano());
+		self.ru_maxrss = self.ru_maxrss.max(other.ru_maxrss);
+		self.ru_minflt += other.ru_minflt;
+		self.ru_majflt += other.ru_majflt;
+		self.ru_inblock += other.ru_inblock;
+		self.ru_oublock += other.ru_oublock;
+		self.ru_nsignals += other.ru_nsignals;
+		self.ru_nvcsw += other.ru_nvcsw;
+		self.ru_nivcsw += other.ru_nivcsw;
+	}
+}
+
+/// Counters of resources used, updated as a process runs.
+///
+/// Counters are also kept for each CPU core, so that resources can be accounted from contexts
+/// where the current process cannot be retrieved (such as a page fault). They are moved to the
+/// current process by the scheduler.
+#[derive(Debug, Default)]
+pub struct RusageCounters {
+	/// Maximum resident set size, in pages.
+	pub maxrss: AtomicUsize,
+	/// Page reclaims (soft page faults).
+	pub minflt: AtomicUsize,
+	/// Page faults (hard page faults).
+	pub majflt: AtomicUsize,
+	/// Block input operations.
+	pub inblock: AtomicUsize,
+	/// Block output operations.
+	pub oublock: AtomicUsize,
+	/// Signals received.
+	pub nsignals: AtomicUsize,
+	/// Voluntary context switches.
+	pub nvcsw: AtomicUsize,
+	/// Involuntary context switches.
+	pub nivcsw: AtomicUsize,
+	/// Bytes read by system calls.
+	pub rchar: AtomicUsize,
+	/// Bytes written by system calls.
+	pub wchar: AtomicUsize,
+	/// Read system calls.
+	pub syscr: AtomicUsize,
+	/// Write system calls.
+	pub syscw: AtomicUsize,
+}
+
+impl RusageCounters {
+	/// Creates a new instance with all counters set to zero.
+	pub const fn new() -> Self {
+		Self {
+			maxrss: AtomicUsize::new(0),
+			minflt: AtomicUsize::new(0),
+			majflt: AtomicUsize::new(0),
+			inblock: AtomicUsize::new(0),
+			oublock: AtomicUsize::new(0),
+			nsignals: AtomicUsize::new(0),
+			nvcsw: AtomicUsize::new(0),
+			nivcsw: AtomicUsize::new(0),
+			rchar: AtomicUsize::new(0),
+			wchar: AtomicUsize::new(0),
+			syscr: AtomicUsize::new(0),
+			syscw: AtomicUsize::new(0),
+		}
+	}
+
+	/// Moves the counters of `self` to `dst`, resetting them to zero.
+	///
+	/// The maximum resident set size is not moved.
+	pub fn drain_into(&self, dst: &Self) {
+		let counters = [
+			(&self.minflt, &dst.minflt),
+			(&self.majflt, &dst.majflt),
+			(&self.inblock, &dst.inblock),
+			(&self.oublock, &dst.oublock),
+			(&self.nsignals, &dst.nsignals),
+			(&self.nvcsw, &dst.nvcsw),
+			(&self.nivcsw, &dst.nivcsw),
+			(&self.rchar, &dst.rchar),
+			(&self.wchar, &dst.wchar),
+			(&self.syscr, &dst.syscr),
+			(&self.syscw, &dst.syscw),
+		];
+		for (src, dst) in counters {
+			let val = src.swap(0, Relaxed);
+			if val > 0 {
+				dst.fetch_add(val, Relaxed);
+			}
+		}
+	}
+
+	/// Returns the resource usage represented by the counters.
+	///
+	/// CPU times are left to zero.
+	pub fn to_rusage(&self) -> Rusage {
+		let get = |c: &AtomicUsize| c.load(Relaxed) as i64;
+		Rusage {
+			ru_maxrss: get(&self.maxrss) * (PAGE_SIZE / 1024) as i64,
+			ru_minflt: get(&self.minflt),
+			ru_majflt: get(&self.majflt),
+			ru_inblock: get(&self.inblock),
+			ru_oublock: get(&self.oublock),
+			ru_nsignals: get(&self.nsignals),
+			ru_nvcsw: get(&self.nvcsw),
+			ru_nivcsw: get(&self.nivcsw),
+			..Default::default()
+		}
+	}
+}
+
+/// Accounts `len` bytes read from a storage device on behalf of the current process.
+pub fn account_input(len: usize) {
+	let blocks = len / IO_BLOCK_SIZE;
+	core_local().rusage.inblock.fetch_add(blocks, Relaxed);
+}
+
+/// Accounts `len` bytes to be written to a storage device on behalf of the current process.
+pub fn account_output(len: usize) {
+	let blocks = len / IO_BLOCK_SIZE;
+	core_local().rusage.oublock.fetch_add(blocks, Relaxed);
+}
+
+/// Accounts a read system call that returned `len` bytes, on behalf of the current process.
+pub fn account_read(len: usize) {
+	let rusage = &core_local().rusage;
+	rusage.rchar.fetch_add(len, Relaxed);
+	rusage.syscr.fetch_add(1, Relaxed);
+}
+
+/// Accounts a write system call that wrote `len` bytes, on behalf of the current process.
+pub fn account_write(len: usize) {
+	let rusage = &core_local().rusage;
+	rusage.wchar.fetch_add(len, Relaxed);
+	rusage.syscw.fetch_add(1, Relaxed);
+}
@@ -18,22 +18,40 @@
 
 //! The role of the process scheduler is to interrupt the currently running
 //! process periodically to switch to another process that is in running state.
+//!
+//! Each CPU core has its own [`RunQueue`], holding the processes it executes. New processes are
+//! placed on the queue of the core creating them, then moved between cores to balance the load.
 
+pub mod run_queue;
+pub mod stat;
 pub mod switch;
 
 use crate::{
-	arch::x86::{cli, idt::IntFrame, pic},
+	arch::x86::{cli, idt, idt::IntFrame, pic, smp},
 	event,
 	event::{CallbackHook, CallbackResult},
-	process::{Process, State, mem_space::MemSpace, pid::Pid, scheduler::switch::switch},
-	sync::{atomic::AtomicU64, mutex::IntMutex, once::OnceInit},
+	process::{
+		Process,
+		mem_space::MemSpace,
+		pid::Pid,
+		rlimit,
+		rusage::RusageCounters,
+		scheduler::{run_queue::RunQueue, stat::CpuStat, switch::switch},
+	},
+	profile, softirq,
+	sync::{
+		atomic::AtomicU64,
+		mutex::{IntMutex, MutexGuard},
+		once::OnceInit,
+	},
 	time,
+	time::unit::Timestamp,
 };
 use core::{
-	mem,
-	sync::{
-		atomic,
-		atomic::{AtomicUsize, Ordering::Release},
+	ptr::NonNull,
+	sync::atomic::{
+		AtomicUsize,
+		Ordering::{Acquire, Relaxed, Release},
 	},
 };
 use utils::{
@@ -44,29 +62,192 @@ use utils::{
 
 /// The process scheduler.
 pub static SCHEDULER: OnceInit<IntMutex<Scheduler>> = unsafe { OnceInit::new() };
-/// Core-local storage.
-static CORE_LOCAL: CoreLocal = CoreLocal {
-	kernel_stack: AtomicUsize::new(0),
-	user_stack: AtomicUsize::new(0),
-
-	mem_space: RelaxedArcCell::new(),
+/// Core-local storage, for each core.
+static CORE_LOCALS: [CoreLocal; MAX_CPUS] = {
+	let mut locals = [const { CoreLocal::new() }; MAX_CPUS];
+	// TODO use for loop when stabilized
+	let mut i = 0;
+	while i < MAX_CPUS {
+		locals[i].id = i;
+		i += 1;
+	}
+	locals
 };
+/// The set of online cores.
+static ONLINE: AtomicU64 = AtomicU64::new(0);
+/// The number of processes in running state, on all cores.
+static NR_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of ticks between two load balancing passes on a core.
+const BALANCE_INTERVAL: u64 = 16;
 
 /// Initializes schedulers.
+///
+/// The current core is the bootstrap core, with ID `0`.
 pub fn init() -> AllocResult<()> {
+	init_run_queue(0)?;
 	unsafe {
 		OnceInit::init(&SCHEDULER, IntMutex::new(Scheduler::new()?));
 	}
-	// Set GS base on the current core
+	set_online(0);
+	Ok(())
+}
+
+/// Sets up the core-local storage of the current core, which has ID `id`.
+///
+/// This function must be called once on each core, before anything else.
+pub(crate) fn init_core_local(id: usize) {
 	#[cfg(target_arch = "x86_64")]
 	{
 		use crate::arch::x86;
 		use core::ptr::addr_of;
 		// Set to `IA32_GS_BASE` instead of `IA32_KERNEL_GS_BASE` since it will get swapped
 		// when switching to userspace
-		x86::wrmsr(x86::IA32_GS_BASE, addr_of!(CORE_LOCAL) as u64);
+		x86::wrmsr(x86::IA32_GS_BASE, addr_of!(CORE_LOCALS[id]) as u64);
 	}
-	Ok(())
+	#[cfg(not(target_arch = "x86_64"))]
+	let _ = id;
+}
+
+/// Creates the idle task and the run queue of the core with ID `core`.
+///
+/// The function returns the top of the idle task's kernel stack, on which the core starts.
+pub(crate) fn init_run_queue(core: usize) -> AllocResult<NonNull<u8>> {
+	let idle = Process::idle_task()?;
+	// The idle task is the first task executed by the core
+	idle.cpu.store(core, Relaxed);
+	idle.on_cpu.store(true, Relaxed);
+	let stack = idle.kernel_stack.top();
+	let rq = RunQueue::new(core, idle);
+	unsafe {
+		OnceInit::init(&CORE_LOCALS[core].run_queue, IntMutex::new(rq));
+	}
+	Ok(stack)
+}
+
+/// A set of CPU cores, where bit `n` represents the core with ID `n`.
+pub type CpuMask = u64;
+/// The maximum number of CPU cores that can be represented in a [`CpuMask`].
+pub const MAX_CPUS: usize = CpuMask::BITS as usize;
+
+/// Returns the ID of the current core.
+#[inline]
+pub fn core_id() -> usize {
+	#[cfg(target_arch = "x86_64")]
+	{
+		use core::{arch::asm, mem::offset_of};
+		let id: usize;
+		unsafe {
+			asm!(
+				"mov {id}, gs:[{off}]",
+				id = out(reg) id,
+				off = const offset_of!(CoreLocal, id),
+				options(nostack, readonly, preserves_flags)
+			);
+		}
+		id
+	}
+	// SMP is supported only on `x86_64`
+	#[cfg(not(target_arch = "x86_64"))]
+	0
+}
+
+/// Returns the set of online cores.
+pub fn online_cpus() -> CpuMask {
+	ONLINE.load(Acquire)
+}
+
+/// Marks the core with ID `core` as online.
+pub(crate) fn set_online(core: usize) {
+	ONLINE.fetch_or(1 << core, Release);
+}
+
+/// Returns an iterator over the IDs of the cores in `mask`.
+pub fn iter_cpus(mask: CpuMask) -> impl Iterator<Item = usize> {
+	(0..MAX_CPUS).filter(move |core| mask & (1 << core) != 0)
+}
+
+/// Returns the number of processes in running state, on all cores.
+#[inline]
+pub fn nr_running() -> usize {
+	NR_RUNNING.load(Relaxed)
+}
+
+/// Updates the number of processes in running state, on all cores, then adapts the ticking
+/// frequency to it.
+///
+/// `increment` tells whether a process started or stopped running.
+fn update_running(increment: bool) {
+	// Lock first so that the frequency follows the order of updates
+	let mut clocks = time::hw::CLOCKS.lock();
+	let running = if increment {
+		NR_RUNNING.fetch_add(1, Relaxed) + 1
+	} else {
+		NR_RUNNING.fetch_sub(1, Relaxed) - 1
+	};
+	let pit = clocks.get_mut(b"pit".as_slice()).unwrap();
+	if running == 0 {
+		pit.set_enabled(false);
+	} else {
+		pit.set_frequency((10 * running) as _);
+		pit.set_enabled(true);
+	}
+}
+
+/// Scheduling policy: default time-sharing.
+pub const SCHED_OTHER: u8 = 0;
+/// Scheduling policy: real-time, first-in first-out.
+pub const SCHED_FIFO: u8 = 1;
+/// Scheduling policy: real-time, round-robin.
+pub const SCHED_RR: u8 = 2;
+/// Scheduling policy: CPU-intensive batch processes.
+pub const SCHED_BATCH: u8 = 3;
+/// Scheduling policy: very low priority background processes.
+pub const SCHED_IDLE: u8 = 5;
+
+/// The lowest priority of real-time processes.
+pub const RT_PRIO_MIN: u8 = 1;
+/// The highest priority of real-time processes.
+pub const RT_PRIO_MAX: u8 = 99;
+/// The time slice of [`SCHED_RR`] processes, in nanoseconds.
+pub const RR_TIMESLICE: Timestamp = 100_000_000;
+
+/// The lowest nice value, which has the highest priority.
+pub const NICE_MIN: i8 = -20;
+/// The highest nice value, which has the lowest priority.
+pub const NICE_MAX: i8 = 19;
+
+/// The scheduling weight of a process with a nice value of `0`.
+pub const NICE_0_WEIGHT: u32 = 1024;
+/// The scheduling weight of [`SCHED_IDLE`] processes.
+const IDLE_WEIGHT: u32 = 3;
+
+/// Scheduling weights by nice value, from [`NICE_MIN`] to [`NICE_MAX`].
+///
+/// Each step changes the share of CPU time of a process by about 10% relative to others.
+const NICE_WEIGHTS: [u32; 40] = [
+	88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+	3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110,
+	87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Returns the scheduling weight of a process with the scheduling policy `policy` and the nice
+/// value `nice`.
+///
+/// The weight of a process determines its share of CPU time relative to the other processes that
+/// are not real-time.
+pub fn sched_weight(policy: u8, nice: i8) -> u32 {
+	if policy == SCHED_IDLE {
+		return IDLE_WEIGHT;
+	}
+	let nice = nice.clamp(NICE_MIN, NICE_MAX);
+	NICE_WEIGHTS[(nice - NICE_MIN) as usize]
+}
+
+/// Tells whether `policy` is a real-time scheduling policy.
+#[inline]
+pub fn is_rt_policy(policy: u8) -> bool {
+	matches!(policy, SCHED_FIFO | SCHED_RR)
 }
 
 /// Kernel core-local storage.
@@ -76,75 +257,206 @@ pub struct CoreLocal {
 	pub kernel_stack: AtomicUsize,
 	/// The stashed user stack
 	pub user_stack: AtomicUsize,
+	/// The ID of the core
+	pub id: usize,
 
 	/// Attached memory space.
 	///
 	/// The pointer stored by this field is returned by [`Arc::into_raw`].
 	pub mem_space: RelaxedArcCell<MemSpace>,
+	/// The address of the virtual memory context bound on the core.
+	pub vmem: AtomicUsize,
+
+	/// Resources used on the core, not yet accounted to the current process.
+	pub rusage: RusageCounters,
+	/// Usage statistics of the core.
+	pub stat: CpuStat,
+
+	/// The run queue of the core.
+	run_queue: OnceInit<IntMutex<RunQueue>>,
+}
+
+impl CoreLocal {
+	/// Creates a new instance, for the core with ID `0`.
+	const fn new() -> Self {
+		Self {
+			kernel_stack: AtomicUsize::new(0),
+			user_stack: AtomicUsize::new(0),
+			id: 0,
+
+			mem_space: RelaxedArcCell::new(),
+			vmem: AtomicUsize::new(0),
+
+			rusage: RusageCounters::new(),
+			stat: CpuStat::new(),
+
+			run_queue: unsafe { OnceInit::new() },
+		}
+	}
+
+	/// Returns the run queue of the core.
+	#[inline]
+	pub fn run_queue(&self) -> &IntMutex<RunQueue> {
+		&self.run_queue
+	}
 }
 
 /// Returns the core-local structure for the current core.
 #[inline]
 pub fn core_local() -> &'static CoreLocal {
-	// TODO use `gs`
-	&CORE_LOCAL
+	&CORE_LOCALS[core_id()]
+}
+
+/// Returns the core-local structure for the core with ID `core`.
+#[inline]
+pub fn core_local_at(core: usize) -> &'static CoreLocal {
+	&CORE_LOCALS[core]
+}
+
+/// Executes `f` with the run queue of the current core locked.
+///
+/// Interrupts are disabled so that the current process cannot be moved to another core meanwhile.
+pub fn with_run_queue<F: FnOnce(&mut RunQueue) -> T, T>(f: F) -> T {
+	idt::wrap_disable_interrupts(|| f(&mut core_local().run_queue().lock()))
+}
+
+/// Locks and returns the run queue holding `proc`.
+pub fn lock_run_queue_of(proc: &Process) -> MutexGuard<'static, RunQueue, false> {
+	loop {
+		let core = proc.cpu.load(Acquire);
+		let rq = core_local_at(core).run_queue().lock();
+		// The process may have been moved while waiting for the lock
+		if proc.cpu.load(Acquire) == core {
+			break rq;
+		}
+	}
+}
+
+/// Locks the run queues of the cores `a` and `b`, always in the same order to avoid deadlocks.
+///
+/// Interrupts must be disabled, since guards are not released in reverse order of locking.
+fn lock_pair(
+	a: usize,
+	b: usize,
+) -> (
+	MutexGuard<'static, RunQueue, false>,
+	MutexGuard<'static, RunQueue, false>,
+) {
+	let rq_a = core_local_at(a).run_queue();
+	let rq_b = core_local_at(b).run_queue();
+	if a < b {
+		let a = rq_a.lock();
+		(a, rq_b.lock())
+	} else {
+		let b = rq_b.lock();
+		(rq_a.lock(), b)
+	}
+}
+
+/// Moves processes from other cores to the current core, to balance the load.
+///
+/// Processes which are not allowed to run on their core are moved first. Then, if another core
+/// has at least two more processes in running state than the current core, one of them is
+/// moved.
+///
+/// Interrupts must be disabled.
+fn load_balance() {
+	let core = core_id();
+	let others = online_cpus() & !(1 << core);
+	let mut busiest: Option<(usize, usize)> = None;
+	for src in iter_cpus(others) {
+		let (mut src_rq, mut dst_rq) = lock_pair(src, core);
+		if let Some(pid) = src_rq.find_migratable(core, true) {
+			src_rq.migrate(pid, &mut dst_rq);
+		}
+		let load = src_rq.get_running_count();
+		if busiest.is_none_or(|(_, max)| load > max) {
+			busiest = Some((src, load));
+		}
+	}
+	let Some((src, _)) = busiest else {
+		return;
+	};
+	let (mut src_rq, mut dst_rq) = lock_pair(src, core);
+	if src_rq.get_running_count() <= dst_rq.get_running_count() + 1 {
+		return;
+	}
+	if let Some(pid) = src_rq.find_migratable(core, false) {
+		src_rq.migrate(pid, &mut dst_rq);
+	}
+}
+
+/// Called at a regular interval to make the scheduler work.
+///
+/// The PIT interrupts only the bootstrap core, which forwards the tick to the other cores with
+/// [`smp::IPI_RESCHEDULE`].
+fn tick_callback(id: u32, _code: u32, frame: &mut IntFrame, ring: u8) -> CallbackResult {
+	if id != smp::IPI_RESCHEDULE as u32 {
+		smp::send_ipi_others(smp::IPI_RESCHEDULE);
+	}
+	let user = ring >= 3;
+	if !user {
+		profile::tick(frame.get_program_counter());
+	}
+	let (proc, balance) = {
+		let mut rq = core_local().run_queue().lock();
+		let ticks = rq.tick_clock(user);
+		let balance = rq.is_idle() || ticks.is_multiple_of(BALANCE_INTERVAL);
+		(rq.get_current_process().clone(), balance)
+	};
+	// Enforce the CPU time limit. This is done only when interrupting userspace, so that no lock
+	// can be held by the process
+	if user {
+		let (utime, stime) = proc.get_cpu_time();
+		rlimit::check_cpu_limit(&proc, utime + stime);
+	}
+	drop(proc);
+	if balance {
+		load_balance();
+	}
+	// Softirqs interrupted by the clock must complete first
+	if core_local().run_queue().lock().must_preempt() && !softirq::in_softirq() {
+		Scheduler::tick();
+	}
+	CallbackResult::Continue
 }
 
-/// A process scheduler.
+/// The process scheduler.
 ///
-/// Each CPU core has its own scheduler.
+/// The scheduler holds every process of the system. Each process is also assigned to the
+/// [`RunQueue`] of the core executing it.
+///
+/// Run queues may be locked while the scheduler is locked, but not the other way around.
 pub struct Scheduler {
 	/// The ticking callback hook, called at a regular interval to make the
 	/// scheduler work.
 	tick_callback_hook: CallbackHook,
-	/// The total number of ticks since the instantiation of the scheduler.
-	total_ticks: AtomicU64,
+	/// The hook of the callback receiving the ticks forwarded by the bootstrap core.
+	ipi_callback_hook: CallbackHook,
 
-	/// A binary tree containing all processes registered to the current
-	/// scheduler.
+	/// A binary tree containing all processes registered to the scheduler.
 	processes: BTreeMap<Pid, Arc<Process>>,
-	/// The process currently being executed by the scheduler's core.
-	curr_proc: Arc<Process>,
-	/// The current number of processes in running state.
-	running_procs: usize,
-
-	/// The task used to idle.
-	idle_task: Arc<Process>,
 }
 
 impl Scheduler {
 	/// Creates a new instance of scheduler.
 	pub(super) fn new() -> AllocResult<Self> {
-		// Register tick callback
-		let mut clocks = time::hw::CLOCKS.lock();
-		let pit = clocks.get_mut(b"pit".as_slice()).unwrap();
-		let tick_callback_hook = event::register_callback(
-			pit.get_interrupt_vector(),
-			|_: u32, _: u32, _: &mut IntFrame, _: u8| {
-				Scheduler::tick();
-				CallbackResult::Continue
-			},
-		)?
-		.unwrap();
-		let idle_task = Process::idle_task()?;
+		// Register tick callbacks
+		let tick_callback_hook = {
+			let mut clocks = time::hw::CLOCKS.lock();
+			let pit = clocks.get_mut(b"pit".as_slice()).unwrap();
+			event::register_callback(pit.get_interrupt_vector(), tick_callback)?.unwrap()
+		};
+		let ipi_callback_hook =
+			event::register_callback(smp::IPI_RESCHEDULE as _, tick_callback)?.unwrap();
 		Ok(Self {
 			tick_callback_hook,
-			total_ticks: AtomicU64::new(0),
+			ipi_callback_hook,
 
 			processes: BTreeMap::new(),
-			curr_proc: idle_task.clone(),
-			running_procs: 0,
-
-			idle_task,
 		})
 	}
 
-	/// Returns the total number of ticks since the instantiation of the
-	/// scheduler.
-	pub fn get_total_ticks(&self) -> u64 {
-		self.total_ticks.load(atomic::Ordering::Relaxed)
-	}
-
 	/// Returns an iterator on the scheduler's processes.
 	pub fn iter_process(&self) -> MapIterator<'_, Pid, Arc<Process>> {
 		self.processes.iter()
@@ -164,89 +476,48 @@ impl Scheduler {
 		todo!()
 	}
 
-	/// Returns the current running process.
-	pub fn get_current_process(&self) -> Arc<Process> {
-		self.curr_proc.clone()
-	}
-
-	/// Swaps the current running process for `new`, returning the previous.
-	pub fn swap_current_process(&mut self, new: Arc<Process>) -> Arc<Process> {
-		core_local()
-			.kernel_stack
-			.store(new.kernel_stack.top().as_ptr() as _, Release);
-		mem::replace(&mut self.curr_proc, new)
-	}
-
-	/// Adds a process to the scheduler.
+	/// Adds a process to the scheduler, on the run queue of the current core.
 	pub fn add_process(&mut self, proc: Arc<Process>) -> AllocResult<()> {
-		if proc.get_state() == State::Running {
-			self.increment_running();
+		self.processes.insert(*proc.pid, proc.clone())?;
+		let res = with_run_queue(|rq| rq.enqueue(proc.clone()));
+		if let Err(e) = res {
+			self.processes.remove(&proc.get_pid());
+			return Err(e);
 		}
-		self.processes.insert(*proc.pid, proc)?;
 		Ok(())
 	}
 
-	/// Removes the process with the given pid `pid`.
+	/// Removes the process with the given pid `pid`, returning it.
 	///
-	/// If the process is not attached to this scheduler, the function does nothing.
-	pub fn remove_process(&mut self, pid: Pid) {
-		let proc = self.processes.remove(&pid);
-		if let Some(proc) = proc {
-			if proc.get_state() == State::Running {
-				self.decrement_running();
-			}
-		}
-	}
-
-	/// Returns the current ticking frequency of the scheduler.
-	pub fn get_ticking_frequency(&self) -> u32 {
-		(10 * self.running_procs) as _
-	}
-
-	/// Increments the number of running processes.
-	pub fn increment_running(&mut self) {
-		self.running_procs += 1;
-		let mut clocks = time::hw::CLOCKS.lock();
-		let pit = clocks.get_mut(b"pit".as_slice()).unwrap();
-		if self.running_procs >= 1 {
-			pit.set_frequency(self.get_ticking_frequency());
-			pit.set_enabled(true);
-		}
+	/// If the process is not attached to this scheduler, the function returns `None`.
+	///
+	/// The process may still be switching away from its core. Before releasing it, the caller must
+	/// wait for it with [`Process::wait_off_cpu`], after unlocking the scheduler.
+	pub fn remove_process(&mut self, pid: Pid) -> Option<Arc<Process>> {
+		let proc = self.processes.remove(&pid)?;
+		lock_run_queue_of(&proc).dequeue(pid);
+		Some(proc)
 	}
 
-	/// Decrements the number of running processes.
-	pub fn decrement_running(&mut self) {
-		self.running_procs -= 1;
-		let mut clocks = time::hw::CLOCKS.lock();
-		let pit = clocks.get_mut(b"pit".as_slice()).unwrap();
-		if self.running_procs == 0 {
-			pit.set_enabled(false);
-		} else {
-			pit.set_frequency(self.get_ticking_frequency());
-		}
+	/// Sets the scheduling policy and real-time priority of `proc`.
+	///
+	/// If the process is real-time, it is placed after the other processes with the same
+	/// priority.
+	pub fn set_scheduler(proc: &Arc<Process>, policy: u8, priority: u8) -> AllocResult<()> {
+		lock_run_queue_of(proc).set_scheduler(proc, policy, priority)
 	}
 
-	/// Returns the next process to run with its PID.
-	fn get_next_process(&self) -> Option<Arc<Process>> {
-		// Get the current process, or take the first process in the list if no
-		// process is running
-		let curr_pid = self.curr_proc.get_pid();
-		let process_filter =
-			|(_, proc): &(&Pid, &Arc<Process>)| matches!(proc.get_state(), State::Running);
-		self.processes
-			.range((curr_pid + 1)..)
-			.find(process_filter)
-			.or_else(|| {
-				// If no suitable process is found, go back to the beginning to check processes
-				// located before the previous process (looping)
-				self.processes.range(..=curr_pid).find(process_filter)
-			})
-			.map(|(_, proc)| proc.clone())
+	/// Makes the current process give up the CPU to other processes with the same priority.
+	///
+	/// The scheduler must be ticked afterwards for the change to take effect.
+	pub fn yield_current() {
+		with_run_queue(RunQueue::yield_current);
 	}
 
 	/// Ticking the scheduler.
 	///
-	/// The function looks for the next process to run, then switches context to it.
+	/// The function looks for the next process to run on the current core, then switches context
+	/// to it.
 	///
 	/// If no process is ready to run, the scheduler halts the current core until a process becomes
 	/// runnable.
@@ -254,23 +525,19 @@ impl Scheduler {
 		// Disable interrupts so that no interrupt can occur before switching to the next process
 		cli();
 		let (prev, next) = {
-			let mut sched = SCHEDULER.lock();
-			sched.total_ticks.fetch_add(1, atomic::Ordering::Relaxed);
-			// Find the next process to run
-			let next = sched
-				.get_next_process()
-				.unwrap_or_else(|| sched.idle_task.clone());
-			// If the process to run is the current, do nothing
-			if next.get_pid() == sched.curr_proc.get_pid() {
+			let mut rq = core_local().run_queue().lock();
+			let Some((prev, next)) = rq.switch_next() else {
 				return;
-			}
-			// Swap current running process. We use pointers to avoid cloning the Arc
-			let next_ptr = Arc::as_ptr(&next);
-			let prev = sched.swap_current_process(next);
-			(Arc::as_ptr(&prev), next_ptr)
+			};
+			// We use pointers to avoid cloning the Arc. Both processes are kept alive: the next
+			// by the run queue, the previous by whoever releases it after it switched away
+			(Arc::as_ptr(&prev), Arc::as_ptr(&next))
 		};
-		// Send end of interrupt, so that the next tick can be received
-		pic::end_of_interrupt(0);
+		// The PIT interrupts only the bootstrap core. Send end of interrupt, so that the next tick
+		// can be received
+		if core_id() == 0 {
+			pic::end_of_interrupt(0);
+		}
 		unsafe {
 			switch(prev, next);
 		}
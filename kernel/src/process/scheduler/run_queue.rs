@@ -0,0 +1,582 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Run queue of a CPU core.
+//!
+//! Each core has its own run queue, holding the processes it executes. Processes are moved
+//! between run queues by load balancing.
+//!
+//! Real-time processes run by order of priority. The other processes share the CPU time
+//! according to their weight: each accumulates a virtual runtime, which is its CPU time scaled
+//! down by its weight, and the process with the lowest virtual runtime runs next.
+
+use crate::{
+	arch::x86::smp,
+	process::{
+		Process, State,
+		pid::Pid,
+		scheduler::{
+			NICE_0_WEIGHT, RR_TIMESLICE, SCHED_BATCH, SCHED_IDLE, SCHED_RR, core_id, core_local,
+			is_rt_policy, sched_weight, stat, update_running,
+		},
+	},
+	softirq,
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::Timestamp,
+	},
+};
+use core::{
+	mem, ptr,
+	sync::atomic::Ordering::{Acquire, Relaxed, Release},
+};
+use utils::{
+	collections::{btreemap::BTreeMap, vec::Vec},
+	errno::AllocResult,
+	ptr::arc::Arc,
+};
+
+/// The period in which every runnable process that is not real-time runs once, in nanoseconds,
+/// unless there are too many of them.
+const SCHED_LATENCY: Timestamp = 24_000_000;
+/// The minimum time slice of a process that is not real-time, in nanoseconds.
+const MIN_GRANULARITY: Timestamp = 3_000_000;
+/// The difference of virtual runtime from which a runnable process preempts the current one, in
+/// nanoseconds.
+const WAKEUP_GRANULARITY: Timestamp = 4_000_000;
+
+/// Returns the scheduling weight of `proc`.
+fn weight(proc: &Process) -> u32 {
+	sched_weight(proc.sched_policy.load(Relaxed), proc.nice.load(Relaxed))
+}
+
+/// The run queue of a CPU core.
+pub struct RunQueue {
+	/// The ID of the core the queue belongs to.
+	core: usize,
+
+	/// The processes assigned to the core, by PID.
+	processes: BTreeMap<Pid, Arc<Process>>,
+	/// The number of processes of the queue in running state.
+	running: usize,
+	/// Real-time processes, by decreasing priority. Processes with the same priority are in
+	/// first-in first-out order.
+	rt_queue: Vec<Arc<Process>>,
+	/// Runnable processes that are not real-time, except the current process, by virtual runtime,
+	/// along with their weight at insertion.
+	fair_queue: BTreeMap<(u64, Pid), (Arc<Process>, u32)>,
+	/// The sum of the weights of the processes in `fair_queue`.
+	fair_load: u64,
+	/// The lowest virtual runtime of the runnable processes of the queue. It never decreases.
+	min_vruntime: u64,
+
+	/// The process currently being executed by the core.
+	curr: Arc<Process>,
+	/// The task used to idle.
+	idle: Arc<Process>,
+
+	/// The total number of ticks since the core started.
+	total_ticks: u64,
+	/// The timestamp of the last CPU time accounting, in nanoseconds.
+	last_account: Timestamp,
+	/// The timestamp at which the time slice of the current process ends, in nanoseconds.
+	slice_end: Timestamp,
+}
+
+impl RunQueue {
+	/// Creates the run queue of the core with ID `core`, with its idle task `idle`.
+	pub(super) fn new(core: usize, idle: Arc<Process>) -> Self {
+		Self {
+			core,
+
+			processes: BTreeMap::new(),
+			running: 0,
+			rt_queue: Vec::new(),
+			fair_queue: BTreeMap::new(),
+			fair_load: 0,
+			min_vruntime: 0,
+
+			curr: idle.clone(),
+			idle,
+
+			total_ticks: 0,
+			last_account: 0,
+			slice_end: 0,
+		}
+	}
+
+	/// Returns the total number of ticks since the core started.
+	#[inline]
+	pub fn get_total_ticks(&self) -> u64 {
+		self.total_ticks
+	}
+
+	/// Returns the number of processes of the queue in running state.
+	#[inline]
+	pub fn get_running_count(&self) -> usize {
+		self.running
+	}
+
+	/// Returns the process currently being executed by the core.
+	#[inline]
+	pub fn get_current_process(&self) -> &Arc<Process> {
+		&self.curr
+	}
+
+	/// Tells whether the core is idle.
+	#[inline]
+	pub fn is_idle(&self) -> bool {
+		Arc::as_ptr(&self.curr) == Arc::as_ptr(&self.idle)
+	}
+
+	/// Tells whether `proc` is the process currently being executed by the core.
+	#[inline]
+	fn is_current(&self, proc: &Process) -> bool {
+		ptr::eq(proc, Arc::as_ptr(&self.curr))
+	}
+
+	/// Swaps the current running process for `new`, returning the previous.
+	///
+	/// This function must be called on the core the queue belongs to.
+	pub fn swap_current_process(&mut self, new: Arc<Process>) -> Arc<Process> {
+		core_local()
+			.kernel_stack
+			.store(new.kernel_stack.top().as_ptr() as _, Release);
+		new.on_cpu.store(true, Release);
+		mem::replace(&mut self.curr, new)
+	}
+
+	/// Adds `proc` to the queue.
+	///
+	/// On failure, the queue is left unchanged.
+	pub(super) fn enqueue(&mut self, proc: Arc<Process>) -> AllocResult<()> {
+		if is_rt_policy(proc.sched_policy.load(Relaxed)) {
+			self.rt_enqueue(proc.clone())?;
+		}
+		if let Err(e) = self.processes.insert(proc.get_pid(), proc.clone()) {
+			self.rt_queue.retain(|p| p.get_pid() != proc.get_pid());
+			return Err(e);
+		}
+		proc.cpu.store(self.core, Release);
+		if proc.get_state() == State::Running {
+			self.increment_running(&proc);
+		}
+		Ok(())
+	}
+
+	/// Removes the process with PID `pid` from the queue, returning it.
+	///
+	/// If the process is not in the queue, the function returns `None`.
+	pub(super) fn dequeue(&mut self, pid: Pid) -> Option<Arc<Process>> {
+		self.rt_queue.retain(|p| p.get_pid() != pid);
+		let proc = self.processes.remove(&pid)?;
+		self.fair_remove(proc.vruntime.load(Relaxed), pid);
+		if proc.get_state() == State::Running {
+			self.running -= 1;
+			update_running(false);
+		}
+		Some(proc)
+	}
+
+	/// Inserts the real-time process `proc` in the real-time queue, after the processes with the
+	/// same priority.
+	fn rt_enqueue(&mut self, proc: Arc<Process>) -> AllocResult<()> {
+		let prio = proc.rt_priority.load(Relaxed);
+		let i = self
+			.rt_queue
+			.iter()
+			.position(|p| p.rt_priority.load(Relaxed) < prio)
+			.unwrap_or(self.rt_queue.len());
+		self.rt_queue.insert(i, proc)
+	}
+
+	/// Moves the real-time process with PID `pid` after the other processes with the same
+	/// priority.
+	///
+	/// If the process is not in the real-time queue, the function does nothing.
+	fn rt_requeue(&mut self, pid: Pid) {
+		let Some(start) = self.rt_queue.iter().position(|p| p.get_pid() == pid) else {
+			return;
+		};
+		let prio = self.rt_queue[start].rt_priority.load(Relaxed);
+		let len = self.rt_queue[start..]
+			.iter()
+			.take_while(|p| p.rt_priority.load(Relaxed) == prio)
+			.count();
+		self.rt_queue[start..(start + len)].rotate_left(1);
+	}
+
+	/// Inserts `proc` in the queue of processes that are not real-time.
+	///
+	/// On allocation failure, the process is not inserted, but it can still be selected by
+	/// [`Self::get_next_process`], which looks for runnable processes outside the queue when none
+	/// of the queue can run.
+	fn fair_insert(&mut self, proc: Arc<Process>) -> AllocResult<()> {
+		let weight = weight(&proc);
+		let key = (proc.vruntime.load(Relaxed), proc.get_pid());
+		self.fair_queue.insert(key, (proc, weight))?;
+		self.fair_load += weight as u64;
+		Ok(())
+	}
+
+	/// Removes the process with PID `pid`, inserted with the virtual runtime `vruntime`, from the
+	/// queue of processes that are not real-time.
+	///
+	/// If the process is not in the queue, the function does nothing.
+	fn fair_remove(&mut self, vruntime: u64, pid: Pid) {
+		if let Some((_, weight)) = self.fair_queue.remove(&(vruntime, pid)) {
+			self.fair_load -= weight as u64;
+		}
+	}
+
+	/// Returns the process with the lowest virtual runtime which can run on the core, if any.
+	fn pick_fair(&self) -> Option<&Arc<Process>> {
+		let now = self.last_account;
+		self.fair_queue
+			.iter()
+			.map(|(_, (proc, _))| proc)
+			.find(|proc| {
+				matches!(proc.get_state(), State::Running)
+					&& proc.can_run_on(self.core)
+					&& !proc.cgroup.lock().cpu_throttled(now)
+			})
+	}
+
+	/// Updates the lowest virtual runtime of the queue.
+	fn update_min_vruntime(&mut self) {
+		let mut min = self
+			.fair_queue
+			.first_key_value()
+			.map(|((vruntime, _), _)| *vruntime);
+		let curr = &self.curr;
+		if !self.is_idle()
+			&& !is_rt_policy(curr.sched_policy.load(Relaxed))
+			&& curr.get_state() == State::Running
+		{
+			let vruntime = curr.vruntime.load(Relaxed);
+			min = Some(min.map_or(vruntime, |min| min.min(vruntime)));
+		}
+		if let Some(min) = min {
+			self.min_vruntime = self.min_vruntime.max(min);
+		}
+	}
+
+	/// Returns the time slice of the process `proc`, which is not in the queue of processes that
+	/// are not real-time, in nanoseconds.
+	///
+	/// The time slice of a process that is not real-time is its share of the scheduling period,
+	/// according to its weight and to the ones of the other runnable processes.
+	fn timeslice(&self, proc: &Process) -> Timestamp {
+		match proc.sched_policy.load(Relaxed) {
+			SCHED_RR => RR_TIMESLICE,
+			_ => {
+				let weight = weight(proc) as u64;
+				let count = self.fair_queue.len() as u64 + 1;
+				let period = SCHED_LATENCY.max(count * MIN_GRANULARITY);
+				let slice = (period * weight / (self.fair_load + weight)).max(MIN_GRANULARITY);
+				proc.cgroup.lock().scale_timeslice(slice)
+			}
+		}
+	}
+
+	/// Sets the scheduling policy and real-time priority of `proc`, which must be in the queue.
+	///
+	/// If the process is real-time, it is placed after the other processes with the same
+	/// priority.
+	pub(super) fn set_scheduler(
+		&mut self,
+		proc: &Arc<Process>,
+		policy: u8,
+		priority: u8,
+	) -> AllocResult<()> {
+		// Allocate beforehand so that the process cannot be lost on failure
+		self.rt_queue.reserve(1)?;
+		self.rt_queue.retain(|p| p.get_pid() != proc.get_pid());
+		self.fair_remove(proc.vruntime.load(Relaxed), proc.get_pid());
+		proc.sched_policy.store(policy, Relaxed);
+		proc.rt_priority.store(priority, Relaxed);
+		if is_rt_policy(policy) {
+			self.rt_enqueue(proc.clone())?;
+		} else if proc.get_state() == State::Running && !self.is_current(proc) {
+			let _ = self.fair_insert(proc.clone());
+		}
+		Ok(())
+	}
+
+	/// Makes the current process give up the CPU to other processes with the same priority.
+	///
+	/// The scheduler must be ticked afterwards for the change to take effect.
+	pub fn yield_current(&mut self) {
+		let pid = self.curr.get_pid();
+		self.rt_requeue(pid);
+		// Place a process that is not real-time after the other runnable processes
+		if !self.is_idle()
+			&& !is_rt_policy(self.curr.sched_policy.load(Relaxed))
+			&& let Some(((last, _), _)) = self.fair_queue.iter().last()
+		{
+			let vruntime = self.curr.vruntime.load(Relaxed).max(*last);
+			self.curr.vruntime.store(vruntime, Relaxed);
+		}
+		self.slice_end = 0;
+	}
+
+	/// Tells whether the current process must be preempted, because its time slice has expired or
+	/// a process with a higher priority is runnable.
+	pub(super) fn must_preempt(&self) -> bool {
+		let curr = &self.curr;
+		if self.is_idle() || curr.get_state() != State::Running || !curr.can_run_on(self.core) {
+			return true;
+		}
+		let policy = curr.sched_policy.load(Relaxed);
+		let prio = if is_rt_policy(policy) {
+			curr.rt_priority.load(Relaxed)
+		} else {
+			0
+		};
+		let higher_prio = self.rt_queue.iter().any(|proc| {
+			proc.rt_priority.load(Relaxed) > prio
+				&& matches!(proc.get_state(), State::Running)
+				&& proc.can_run_on(self.core)
+		});
+		if higher_prio {
+			return true;
+		}
+		if is_rt_policy(policy) {
+			// First-in first-out processes have no time slice
+			return policy == SCHED_RR && self.last_account >= self.slice_end;
+		}
+		// A process whose cgroup exhausted its CPU quota must leave the CPU
+		if curr.cgroup.lock().cpu_throttled(self.last_account) {
+			return true;
+		}
+		if self.last_account >= self.slice_end {
+			return true;
+		}
+		// Let a process which ran much less than the current one run first, such as a process
+		// which just woke up
+		self.pick_fair().is_some_and(|next| {
+			let next_policy = next.sched_policy.load(Relaxed);
+			if policy == SCHED_IDLE {
+				return next_policy != SCHED_IDLE;
+			}
+			next_policy != SCHED_BATCH
+				&& next.vruntime.load(Relaxed) + WAKEUP_GRANULARITY < curr.vruntime.load(Relaxed)
+		})
+	}
+
+	/// Increments the number of running processes, when `proc` becomes runnable.
+	///
+	/// If the process is not in the queue, the function does nothing.
+	pub(crate) fn increment_running(&mut self, proc: &Process) {
+		if !self.processes.contains_key(&proc.get_pid()) {
+			return;
+		}
+		self.running += 1;
+		update_running(true);
+		if !is_rt_policy(proc.sched_policy.load(Relaxed)) && !self.is_current(proc) {
+			// Do not let a process which slept for a long time monopolize the CPU, while giving it
+			// an advantage over the processes which kept running
+			let vruntime = proc
+				.vruntime
+				.load(Relaxed)
+				.max(self.min_vruntime.saturating_sub(SCHED_LATENCY / 2));
+			proc.vruntime.store(vruntime, Relaxed);
+			if let Some(proc) = self.processes.get(&proc.get_pid()).cloned() {
+				let _ = self.fair_insert(proc);
+			}
+		}
+		// Wake the core up if it is waiting for something to do
+		if self.core != core_id() && self.is_idle() {
+			smp::send_ipi(self.core, smp::IPI_RESCHEDULE);
+		}
+	}
+
+	/// Decrements the number of running processes, when `proc` stops being runnable.
+	///
+	/// If the process is not in the queue, the function does nothing.
+	pub(crate) fn decrement_running(&mut self, proc: &Process) {
+		if !self.processes.contains_key(&proc.get_pid()) {
+			return;
+		}
+		self.running -= 1;
+		update_running(false);
+		self.fair_remove(proc.vruntime.load(Relaxed), proc.get_pid());
+	}
+
+	/// Accounts the CPU time elapsed since the last accounting, and the resources used on the
+	/// core, to the current process.
+	///
+	/// `user` tells whether the time has been spent in userspace.
+	fn account_cpu_time(&mut self, user: bool) {
+		let now = current_time_ns(Clock::Monotonic);
+		let delta = now.saturating_sub(self.last_account);
+		self.last_account = now;
+		self.curr.account_cpu_time(delta, user);
+		core_local().rusage.drain_into(&self.curr.rusage);
+		let idle = self.is_idle();
+		if !idle {
+			self.curr.cgroup.lock().account_cpu(delta, now);
+			if !is_rt_policy(self.curr.sched_policy.load(Relaxed)) {
+				let delta = delta * NICE_0_WEIGHT as u64 / weight(&self.curr) as u64;
+				self.curr.vruntime.fetch_add(delta, Relaxed);
+			}
+		}
+		self.update_min_vruntime();
+		// Update the core's statistics
+		let stat = &core_local().stat;
+		let counter = if user {
+			if self.curr.nice.load(Relaxed) > 0 {
+				&stat.nice
+			} else {
+				&stat.user
+			}
+		} else if softirq::in_softirq() {
+			&stat.softirq
+		} else if idle {
+			if stat::nr_iowait() > 0 {
+				&stat.iowait
+			} else {
+				&stat.idle
+			}
+		} else {
+			&stat.system
+		};
+		counter.fetch_add(delta, Relaxed);
+	}
+
+	/// Handles a clock tick on the core, accounting the CPU time elapsed since the last
+	/// accounting.
+	///
+	/// `user` tells whether the time has been spent in userspace.
+	///
+	/// The function returns the total number of ticks received by the core.
+	pub(super) fn tick_clock(&mut self, user: bool) -> u64 {
+		self.account_cpu_time(user);
+		self.total_ticks += 1;
+		self.total_ticks
+	}
+
+	/// Returns the next process to run.
+	fn get_next_process(&self) -> Option<Arc<Process>> {
+		let core = self.core;
+		// Real-time processes take precedence, by order of priority
+		let rt_proc = self
+			.rt_queue
+			.iter()
+			.find(|proc| matches!(proc.get_state(), State::Running) && proc.can_run_on(core));
+		if let Some(proc) = rt_proc {
+			return Some(proc.clone());
+		}
+		if let Some(proc) = self.pick_fair() {
+			return Some(proc.clone());
+		}
+		// Look for processes which could not be inserted in the queue on allocation failure
+		let now = self.last_account;
+		self.processes
+			.iter()
+			.map(|(_, proc)| proc)
+			.find(|proc| {
+				matches!(proc.get_state(), State::Running)
+					&& proc.can_run_on(core)
+					&& !is_rt_policy(proc.sched_policy.load(Relaxed))
+					&& !proc.cgroup.lock().cpu_throttled(now)
+			})
+			.cloned()
+	}
+
+	/// Selects the next process to run and makes it the current process.
+	///
+	/// The function returns the previous and the next process. If the current process keeps
+	/// running, the function returns `None`.
+	pub(super) fn switch_next(&mut self) -> Option<(Arc<Process>, Arc<Process>)> {
+		self.account_cpu_time(false);
+		// If the time slice of the current round-robin process has expired, let other processes
+		// with the same priority run
+		let now = self.last_account;
+		let curr_policy = self.curr.sched_policy.load(Relaxed);
+		if curr_policy == SCHED_RR && now >= self.slice_end {
+			let pid = self.curr.get_pid();
+			self.rt_requeue(pid);
+			self.slice_end = now + RR_TIMESLICE;
+		}
+		// Put the current process back with the other runnable processes
+		if !self.is_idle() && !is_rt_policy(curr_policy) && self.curr.get_state() == State::Running
+		{
+			let _ = self.fair_insert(self.curr.clone());
+		}
+		// Find the next process to run
+		let next = self.get_next_process().unwrap_or_else(|| self.idle.clone());
+		self.fair_remove(next.vruntime.load(Relaxed), next.get_pid());
+		// If the process to run is the current, it keeps running
+		if Arc::as_ptr(&next) == Arc::as_ptr(&self.curr) {
+			if now >= self.slice_end {
+				self.slice_end = now + self.timeslice(&next);
+			}
+			return None;
+		}
+		// The switch is voluntary if the process stopped running by itself
+		let prev_usage = &self.curr.rusage;
+		if matches!(self.curr.get_state(), State::Running) {
+			prev_usage.nivcsw.fetch_add(1, Relaxed);
+		} else {
+			prev_usage.nvcsw.fetch_add(1, Relaxed);
+		}
+		core_local().stat.ctxt.fetch_add(1, Relaxed);
+		// Start the time slice of the next process
+		self.slice_end = now + self.timeslice(&next);
+		let prev = self.swap_current_process(next.clone());
+		Some((prev, next))
+	}
+
+	/// Returns a process of the queue which can be moved to the core `dest`, if any.
+	///
+	/// If `misplaced` is `true`, only processes which are not allowed to run on the core of the
+	/// queue are considered.
+	pub(super) fn find_migratable(&self, dest: usize, misplaced: bool) -> Option<Pid> {
+		self.processes
+			.iter()
+			.find(|(_, proc)| {
+				matches!(proc.get_state(), State::Running)
+					&& Arc::as_ptr(proc) != Arc::as_ptr(&self.curr)
+					// The process must have finished switching away from its core
+					&& !proc.on_cpu.load(Acquire)
+					&& proc.can_run_on(dest)
+					&& (!misplaced || !proc.can_run_on(self.core))
+			})
+			.map(|(pid, _)| *pid)
+	}
+
+	/// Moves the process with PID `pid` from this queue to `dest`.
+	///
+	/// If the process cannot be inserted in `dest`, it stays in this queue.
+	pub(super) fn migrate(&mut self, pid: Pid, dest: &mut RunQueue) {
+		let Some(proc) = self.processes.get(&pid).cloned() else {
+			return;
+		};
+		// The virtual runtime is relative to the run queue
+		let vruntime = proc.vruntime.load(Relaxed);
+		let moved = vruntime.saturating_sub(self.min_vruntime) + dest.min_vruntime;
+		proc.vruntime.store(moved, Relaxed);
+		if dest.enqueue(proc.clone()).is_err() {
+			proc.vruntime.store(vruntime, Relaxed);
+			return;
+		}
+		self.fair_remove(vruntime, pid);
+		self.dequeue(pid);
+	}
+}
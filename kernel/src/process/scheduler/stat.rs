@@ -0,0 +1,131 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! CPU usage statistics.
+//!
+//! Each core has its own counters, only updated by the core itself, so that no lock is required.
+//! They are summed when read, to be reported in `/proc/stat`.
+
+use crate::{
+	process::scheduler::{core_local_at, iter_cpus, online_cpus},
+	sync::atomic::AtomicU64,
+};
+use core::sync::atomic::{
+	AtomicUsize,
+	Ordering::{Relaxed, Release},
+};
+
+/// Statistics of the usage of a CPU core.
+///
+/// Times are in nanoseconds.
+#[derive(Debug, Default)]
+pub struct CpuStat {
+	/// Time spent in userspace.
+	pub user: AtomicU64,
+	/// Time spent in userspace by processes with a positive nice value.
+	pub nice: AtomicU64,
+	/// Time spent in kernelspace.
+	pub system: AtomicU64,
+	/// Time spent idle, with no process waiting for I/O.
+	pub idle: AtomicU64,
+	/// Time spent idle, while processes are waiting for I/O.
+	pub iowait: AtomicU64,
+	/// Time spent running softirqs.
+	pub softirq: AtomicU64,
+
+	/// The number of context switches.
+	pub ctxt: AtomicU64,
+	/// The number of hardware interrupts handled.
+	pub intr: AtomicU64,
+}
+
+impl CpuStat {
+	/// Creates a new instance with all counters set to zero.
+	pub const fn new() -> Self {
+		Self {
+			user: AtomicU64::new(0),
+			nice: AtomicU64::new(0),
+			system: AtomicU64::new(0),
+			idle: AtomicU64::new(0),
+			iowait: AtomicU64::new(0),
+			softirq: AtomicU64::new(0),
+
+			ctxt: AtomicU64::new(0),
+			intr: AtomicU64::new(0),
+		}
+	}
+
+	/// Adds the counters of `other` to `self`.
+	pub fn add(&self, other: &Self) {
+		let counters = [
+			(&self.user, &other.user),
+			(&self.nice, &other.nice),
+			(&self.system, &other.system),
+			(&self.idle, &other.idle),
+			(&self.iowait, &other.iowait),
+			(&self.softirq, &other.softirq),
+			(&self.ctxt, &other.ctxt),
+			(&self.intr, &other.intr),
+		];
+		for (dst, src) in counters {
+			dst.fetch_add(src.load(Relaxed), Relaxed);
+		}
+	}
+}
+
+/// The number of processes created since boot.
+pub static FORKS: AtomicU64 = AtomicU64::new(0);
+/// The number of processes waiting for I/O to complete.
+static NR_IOWAIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns an iterator over the statistics of each core.
+pub fn iter_cores() -> impl Iterator<Item = &'static CpuStat> {
+	iter_cpus(online_cpus()).map(|core| &core_local_at(core).stat)
+}
+
+/// Returns the sum of the statistics of all cores.
+pub fn total() -> CpuStat {
+	let total = CpuStat::new();
+	for stat in iter_cores() {
+		total.add(stat);
+	}
+	total
+}
+
+/// Returns the number of processes waiting for I/O to complete.
+#[inline]
+pub fn nr_iowait() -> usize {
+	NR_IOWAIT.load(Relaxed)
+}
+
+/// Guard marking the current process as waiting for I/O to complete, until dropped.
+///
+/// While processes are waiting for I/O, idle time is accounted as I/O wait time.
+pub struct IoWaitGuard(());
+
+/// Marks the current process as waiting for I/O to complete, until the returned guard is dropped.
+pub fn io_wait() -> IoWaitGuard {
+	NR_IOWAIT.fetch_add(1, Release);
+	IoWaitGuard(())
+}
+
+impl Drop for IoWaitGuard {
+	fn drop(&mut self) {
+		NR_IOWAIT.fetch_sub(1, Release);
+	}
+}
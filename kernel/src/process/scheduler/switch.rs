@@ -23,7 +23,7 @@ use crate::{
 	memory::vmem::KERNEL_VMEM,
 	process::{Process, mem_space::MemSpace},
 };
-use core::{arch::global_asm, mem::offset_of, ptr::NonNull};
+use core::{arch::global_asm, mem::offset_of, ptr::NonNull, sync::atomic::Ordering::Release};
 
 /// Stashes current segment values during execution of `f`, restoring them after.
 pub fn stash_segments<F: FnOnce() -> T, T>(f: F) -> T {
@@ -38,7 +38,6 @@ pub fn stash_segments<F: FnOnce() -> T, T>(f: F) -> T {
 		use core::arch::asm;
 		// Save MSR
 		let fs_base = x86::rdmsr(x86::IA32_FS_BASE);
-		let gs_base = x86::rdmsr(x86::IA32_GS_BASE);
 		let kernel_gs_base = x86::rdmsr(x86::IA32_KERNEL_GS_BASE);
 		// Save segment selectors
 		let mut fs: u16;
@@ -52,6 +51,10 @@ pub fn stash_segments<F: FnOnce() -> T, T>(f: F) -> T {
 			);
 		}
 		let res = f();
+		// The `gs` base points to the core-local storage, which is lost when reloading the
+		// selector. Since the process may have moved to another core meanwhile, keep the current
+		// one
+		let gs_base = x86::rdmsr(x86::IA32_GS_BASE);
 		// Restore segment selectors
 		unsafe {
 			asm!(
@@ -69,6 +72,44 @@ pub fn stash_segments<F: FnOnce() -> T, T>(f: F) -> T {
 	}
 }
 
+/// Reloads the bases of the `fs` and `gs` segments of the current context, when their selectors
+/// refer to an entry of `tls`.
+///
+/// Segment registers are not reloaded when returning to userspace, so this is required for a
+/// change to an entry currently in use to take effect.
+///
+/// This function must be called from kernelspace, with the userspace `gs` base swapped out.
+#[cfg(target_arch = "x86_64")]
+pub fn reload_tls(tls: &[gdt::Entry; crate::process::TLS_ENTRIES_COUNT]) {
+	use crate::arch::x86;
+	use core::arch::asm;
+	let mut fs: u16;
+	let mut gs: u16;
+	unsafe {
+		asm!(
+			"mov {fs:x}, fs",
+			"mov {gs:x}, gs",
+			fs = out(reg) fs,
+			gs = out(reg) gs
+		);
+	}
+	let tls_base = |sel: u16| {
+		// Selectors referring to the LDT are not concerned
+		if sel & 0x4 != 0 {
+			return None;
+		}
+		let off = (sel & !0x7) as usize;
+		let id = off.checked_sub(gdt::TLS_OFFSET)? / size_of::<gdt::Entry>();
+		tls.get(id).map(|ent| ent.get_base() as u64)
+	};
+	if let Some(base) = tls_base(fs) {
+		x86::wrmsr(x86::IA32_FS_BASE, base);
+	}
+	if let Some(base) = tls_base(gs) {
+		x86::wrmsr(x86::IA32_KERNEL_GS_BASE, base);
+	}
+}
+
 /// Switches context from `prev` to `next`.
 ///
 /// After returning, the execution will continue on `next`.
@@ -152,7 +193,9 @@ switch_asm:
 	mov [esp + 4], eax
 	mov [esp + 8], edx
 	jmp switch_finish
-"#, off = const offset_of!(Process, kernel_sp));
+"#,
+	off = const offset_of!(Process, kernel_sp)
+);
 
 #[cfg(target_arch = "x86_64")]
 global_asm!(r#"
@@ -196,14 +239,15 @@ switch_asm:
 	pop rbp
 
 	jmp switch_finish
-"#, off = const offset_of!(Process, kernel_sp));
+"#,
+	off = const offset_of!(Process, kernel_sp)
+);
 
 /// Finishes switching context from `prev` to `next`, that is restore everything else than
 /// general-purpose registers.
 ///
-/// This function is jumped to from [`switch`].
-#[unsafe(export_name = "switch_finish")]
-pub extern "C" fn finish(prev: &Process, next: &Process) {
+/// This function is called by [`switch_finish`], and when forking.
+pub fn finish(prev: &Process, next: &Process) {
 	// Bind the memory space
 	match next.mem_space.as_ref() {
 		Some(mem_space) => MemSpace::bind(mem_space),
@@ -229,6 +273,16 @@ pub extern "C" fn finish(prev: &Process, next: &Process) {
 	fxrstor(&next.fpu.lock());
 }
 
+/// Finishes switching context from `prev` to `next`, then releases `prev` so that it can be
+/// executed by another core.
+///
+/// This function is jumped to from [`switch`].
+#[unsafe(export_name = "switch_finish")]
+extern "C" fn switch_finish(prev: &Process, next: &Process) {
+	finish(prev, next);
+	prev.on_cpu.store(false, Release);
+}
+
 /// The entry point of a kernel thread.
 pub type KThreadEntry = fn() -> !;
 
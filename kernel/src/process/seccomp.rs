@@ -0,0 +1,314 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Secure computing mode (seccomp), restricting the system calls a process is allowed to make.
+//!
+//! In strict mode, the process may only call `read`, `write`, `exit` and `sigreturn`. Any other
+//! system call kills it with `SIGKILL`.
+//!
+//! In filter mode, each system call is described by a [`SeccompData`] structure, on which every
+//! filter attached to the process runs. Each filter returns an action, and the most restrictive
+//! one is applied.
+//!
+//! Filters are inherited by children, preserved across `execve`, and cannot be removed.
+
+use crate::{
+	arch::x86::idt::IntFrame,
+	net::bpf::Program,
+	process::{Process, signal::Signal},
+	syscall::SIGRETURN_ID,
+	uapi::seccomp::{AUDIT_ARCH_I386, AUDIT_ARCH_X86_64, SeccompData},
+};
+use core::{iter, slice};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Seccomp mode: no restriction.
+pub const SECCOMP_MODE_DISABLED: u8 = 0;
+/// Seccomp mode: only a few system calls are allowed.
+pub const SECCOMP_MODE_STRICT: u8 = 1;
+/// Seccomp mode: system calls are checked by filters.
+pub const SECCOMP_MODE_FILTER: u8 = 2;
+
+/// Filter return value: kill the process.
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000;
+/// Filter return value: kill the thread.
+pub const SECCOMP_RET_KILL_THREAD: u32 = 0x00000000;
+/// Filter return value: send `SIGSYS` to the process, without executing the system call.
+pub const SECCOMP_RET_TRAP: u32 = 0x00030000;
+/// Filter return value: return the errno in the data bits, without executing the system call.
+pub const SECCOMP_RET_ERRNO: u32 = 0x00050000;
+/// Filter return value: notify a userspace supervisor.
+pub const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000;
+/// Filter return value: notify the tracer.
+pub const SECCOMP_RET_TRACE: u32 = 0x7ff00000;
+/// Filter return value: log the system call, then execute it.
+pub const SECCOMP_RET_LOG: u32 = 0x7ffc0000;
+/// Filter return value: execute the system call.
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff0000;
+
+/// Mask of the action bits of a filter's return value.
+const SECCOMP_RET_ACTION_FULL: u32 = 0xffff0000;
+/// Mask of the data bits of a filter's return value.
+const SECCOMP_RET_DATA: u32 = 0x0000ffff;
+
+/// The largest errno a filter may return.
+const MAX_ERRNO: u32 = 4095;
+/// The maximum total number of instructions in the filters of a process, each filter counting
+/// for four additional instructions.
+const MAX_INSNS_PER_PATH: usize = 32768;
+
+/// System calls allowed in strict mode, for 32 bit processes: `read`, `write`, `exit` and
+/// `sigreturn`.
+const STRICT_SYSCALLS_32: [usize; 4] = [0x003, 0x004, 0x001, SIGRETURN_ID];
+/// System calls allowed in strict mode, for 64 bit processes: `read`, `write`, `exit` and
+/// `rt_sigreturn`.
+const STRICT_SYSCALLS_64: [usize; 4] = [0x000, 0x001, 0x03c, 0x00f];
+
+/// The action to take for a system call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+	/// Execute the system call.
+	Allow,
+	/// Return the given errno without executing the system call.
+	Errno(u16),
+	/// Send `SIGSYS` to the process without executing the system call.
+	Trap,
+	/// Terminate the process as if killed by the given signal, which cannot be caught.
+	Kill(Signal),
+}
+
+impl Action {
+	/// Returns the action corresponding to the value returned by a filter.
+	fn from_ret(ret: u32) -> Self {
+		match ret & SECCOMP_RET_ACTION_FULL {
+			SECCOMP_RET_ERRNO => Self::Errno((ret & SECCOMP_RET_DATA).min(MAX_ERRNO) as _),
+			SECCOMP_RET_TRAP => Self::Trap,
+			// There is no tracer nor supervisor
+			SECCOMP_RET_TRACE | SECCOMP_RET_USER_NOTIF => Self::Errno(errno::ENOSYS as _),
+			SECCOMP_RET_LOG | SECCOMP_RET_ALLOW => Self::Allow,
+			// Unknown actions are treated as `SECCOMP_RET_KILL_PROCESS`
+			_ => Self::Kill(Signal::SIGSYS),
+		}
+	}
+}
+
+/// Tells whether the filter return value `action` is supported.
+pub fn is_action_available(action: u32) -> bool {
+	matches!(
+		action,
+		SECCOMP_RET_KILL_PROCESS
+			| SECCOMP_RET_KILL_THREAD
+			| SECCOMP_RET_TRAP
+			| SECCOMP_RET_ERRNO
+			| SECCOMP_RET_TRACE
+			| SECCOMP_RET_LOG
+			| SECCOMP_RET_ALLOW
+	)
+}
+
+/// A filter attached to a process.
+#[derive(Debug)]
+struct Filter {
+	/// The filter's program.
+	prog: Program,
+	/// The filter attached before this one.
+	prev: Option<Arc<Filter>>,
+	/// The total number of instructions of this filter and the previous ones, counting four
+	/// additional instructions per filter.
+	path_len: usize,
+}
+
+/// The seccomp state of a process.
+#[derive(Clone, Debug, Default)]
+pub struct Seccomp {
+	/// The current mode.
+	mode: u8,
+	/// The last attached filter.
+	filter: Option<Arc<Filter>>,
+}
+
+impl Seccomp {
+	/// Returns the current mode.
+	pub fn mode(&self) -> u8 {
+		self.mode
+	}
+
+	/// Returns an iterator over attached filters, from the most recent.
+	fn filters(&self) -> impl Iterator<Item = &Filter> {
+		iter::successors(self.filter.as_deref(), |f| f.prev.as_deref())
+	}
+
+	/// Returns the number of attached filters.
+	pub fn filters_count(&self) -> usize {
+		self.filters().count()
+	}
+
+	/// Switches to the strict mode.
+	///
+	/// If filters are attached, the function returns [`errno::EINVAL`].
+	pub fn set_strict(&mut self) -> EResult<()> {
+		if self.mode == SECCOMP_MODE_FILTER {
+			return Err(errno!(EINVAL));
+		}
+		self.mode = SECCOMP_MODE_STRICT;
+		Ok(())
+	}
+
+	/// Attaches the filter `prog`, switching to the filter mode.
+	///
+	/// `len` is the number of instructions of the program.
+	///
+	/// If the process is in strict mode, the function returns [`errno::EINVAL`]. If the filters
+	/// are too large, it returns [`errno::ENOMEM`].
+	pub fn add_filter(&mut self, prog: Program, len: usize) -> EResult<()> {
+		if self.mode == SECCOMP_MODE_STRICT {
+			return Err(errno!(EINVAL));
+		}
+		let prev_len = self.filter.as_ref().map(|f| f.path_len).unwrap_or(0);
+		let path_len = prev_len + len + 4;
+		if path_len > MAX_INSNS_PER_PATH {
+			return Err(errno!(ENOMEM));
+		}
+		self.filter = Some(Arc::new(Filter {
+			prog,
+			prev: self.filter.take(),
+			path_len,
+		})?);
+		self.mode = SECCOMP_MODE_FILTER;
+		Ok(())
+	}
+
+	/// Returns the action to take for the system call `data`.
+	fn filter(&self, data: &SeccompData) -> Action {
+		let data = unsafe {
+			slice::from_raw_parts(
+				(data as *const SeccompData).cast::<u8>(),
+				size_of::<SeccompData>(),
+			)
+		};
+		// The most restrictive action wins, the actions being ordered as signed values
+		let ret = self
+			.filters()
+			.map(|f| f.prog.run(data))
+			.min_by_key(|ret| (ret & SECCOMP_RET_ACTION_FULL) as i32);
+		ret.map(Action::from_ret).unwrap_or(Action::Allow)
+	}
+}
+
+/// Returns the action to take for the system call with ID `id` made by `proc`, whose registers
+/// are in `frame`.
+pub fn check(proc: &Process, id: usize, frame: &IntFrame) -> Action {
+	let seccomp = proc.seccomp.lock();
+	match seccomp.mode {
+		SECCOMP_MODE_STRICT => {
+			let allowed = if frame.is_compat() {
+				STRICT_SYSCALLS_32.contains(&id)
+			} else {
+				STRICT_SYSCALLS_64.contains(&id)
+			};
+			if allowed {
+				Action::Allow
+			} else {
+				Action::Kill(Signal::SIGKILL)
+			}
+		}
+		SECCOMP_MODE_FILTER => {
+			let data = SeccompData {
+				nr: id as _,
+				arch: if frame.is_compat() {
+					AUDIT_ARCH_I386
+				} else {
+					AUDIT_ARCH_X86_64
+				},
+				instruction_pointer: frame.get_program_counter() as _,
+				args: [0, 1, 2, 3, 4, 5].map(|n| frame.get_syscall_arg(n) as _),
+			};
+			seccomp.filter(&data)
+		}
+		_ => Action::Allow,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::net::bpf::SockFilter;
+	use utils::vec;
+
+	/// Returns a filter allowing every system call except `nr`, for which it returns `ret`.
+	fn deny(nr: u32, ret: u32) -> Program {
+		let insns = vec![
+			// Load `nr`
+			SockFilter {
+				code: 0x20,
+				jt: 0,
+				jf: 0,
+				k: 0,
+			},
+			// Compare with `nr`
+			SockFilter {
+				code: 0x15,
+				jt: 0,
+				jf: 1,
+				k: nr,
+			},
+			// Return `ret` if equal
+			SockFilter {
+				code: 0x06,
+				jt: 0,
+				jf: 0,
+				k: ret,
+			},
+			// Allow otherwise
+			SockFilter {
+				code: 0x06,
+				jt: 0,
+				jf: 0,
+				k: SECCOMP_RET_ALLOW,
+			},
+		]
+		.unwrap();
+		Program::new_seccomp(insns, size_of::<SeccompData>()).unwrap()
+	}
+
+	#[test_case]
+	fn seccomp_filter() {
+		let mut seccomp = Seccomp::default();
+		seccomp
+			.add_filter(deny(1, SECCOMP_RET_ERRNO | 13), 4)
+			.unwrap();
+		seccomp.add_filter(deny(2, SECCOMP_RET_TRAP), 4).unwrap();
+		seccomp
+			.add_filter(deny(1, SECCOMP_RET_KILL_PROCESS), 4)
+			.unwrap();
+		assert_eq!(seccomp.filters_count(), 3);
+		assert!(seccomp.set_strict().is_err());
+		let data = |nr| SeccompData {
+			nr,
+			..Default::default()
+		};
+		assert_eq!(seccomp.filter(&data(0)), Action::Allow);
+		assert_eq!(seccomp.filter(&data(1)), Action::Kill(Signal::SIGSYS));
+		assert_eq!(seccomp.filter(&data(2)), Action::Trap);
+		// Filters are inherited
+		let mut child = seccomp.clone();
+		child.add_filter(deny(0, SECCOMP_RET_ERRNO | 1), 4).unwrap();
+		assert_eq!(child.filter(&data(0)), Action::Errno(1));
+		assert_eq!(seccomp.filter(&data(0)), Action::Allow);
+	}
+}
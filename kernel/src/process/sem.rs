@@ -0,0 +1,649 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV semaphore sets.
+//!
+//! A semaphore set is an array of counters, identified in an IPC namespace by an ID and optionally
+//! bound to a key. With `semop`, a process applies a list of operations to a set atomically: each
+//! operation adds to a semaphore, subtracts from it, or waits for it to be zero. If one of the
+//! operations cannot be performed without making a semaphore negative, none is, and the process
+//! sleeps until they all can.
+//!
+//! The effect of operations flagged with [`SEM_UNDO`] is recorded in per-process adjustments,
+//! which are applied to the semaphores when the process exits. This allows releasing a semaphore
+//! held by a process that is killed.
+
+use crate::{
+	file::{
+		Mode,
+		perm::{AccessProfile, S_IROTH, S_IWOTH},
+		wait_queue::WaitQueue,
+	},
+	process::{
+		Process,
+		ns::{CompatIpcPerm, IPC_NOWAIT, IPC_PRIVATE, IpcIds, IpcObjectPerm, IpcPerm, get_access},
+		pid::Pid,
+	},
+	sync::mutex::{Mutex, MutexGuard},
+	time::{
+		clock::{Clock, current_time_sec},
+		unit::Timestamp,
+	},
+};
+use core::{
+	ffi::{c_int, c_long, c_ulong},
+	hint::unlikely,
+};
+use utils::{
+	collections::{hashmap::HashMap, vec::Vec},
+	errno,
+	errno::{CollectResult, EResult},
+	ptr::arc::Arc,
+	vec,
+};
+
+/// `semop` flag: revert the operation when the process exits.
+pub const SEM_UNDO: i16 = 0x1000;
+
+/// `semctl` command: get the PID of the last process which operated on a semaphore.
+pub const GETPID: c_int = 11;
+/// `semctl` command: get the value of a semaphore.
+pub const GETVAL: c_int = 12;
+/// `semctl` command: get the values of all the semaphores of the set.
+pub const GETALL: c_int = 13;
+/// `semctl` command: get the number of processes waiting for a semaphore to increase.
+pub const GETNCNT: c_int = 14;
+/// `semctl` command: get the number of processes waiting for a semaphore to become zero.
+pub const GETZCNT: c_int = 15;
+/// `semctl` command: set the value of a semaphore.
+pub const SETVAL: c_int = 16;
+/// `semctl` command: set the values of all the semaphores of the set.
+pub const SETALL: c_int = 17;
+
+/// The maximum number of semaphores in a set.
+pub const SEMMSL: usize = 32000;
+/// The maximum number of semaphore sets in an IPC namespace.
+pub const SEMMNI: usize = 32000;
+/// The maximum number of operations in a single `semop` call.
+pub const SEMOPM: usize = 500;
+/// The maximum value of a semaphore.
+pub const SEMVMX: i32 = 32767;
+
+/// An operation on a semaphore, as passed to `semop`.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SemBuf {
+	pub sem_num: u16,
+	pub sem_op: i16,
+	pub sem_flg: i16,
+}
+
+/// Status of a semaphore set.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SemidDs {
+	pub sem_perm: IpcPerm,
+	pub sem_otime: c_long,
+	pub _unused1: c_ulong,
+	pub sem_ctime: c_long,
+	pub _unused2: c_ulong,
+	pub sem_nsems: c_ulong,
+	pub _unused3: c_ulong,
+	pub _unused4: c_ulong,
+}
+
+/// Compatibility version of [`SemidDs`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompatSemidDs {
+	pub sem_perm: CompatIpcPerm,
+	pub sem_otime: u32,
+	pub sem_otime_high: u32,
+	pub sem_ctime: u32,
+	pub sem_ctime_high: u32,
+	pub sem_nsems: u32,
+	pub _unused3: u32,
+	pub _unused4: u32,
+}
+
+impl From<SemidDs> for CompatSemidDs {
+	fn from(ds: SemidDs) -> Self {
+		let (otime, ctime) = (ds.sem_otime as u64, ds.sem_ctime as u64);
+		Self {
+			sem_perm: ds.sem_perm.into(),
+			sem_otime: otime as _,
+			sem_otime_high: (otime >> 32) as _,
+			sem_ctime: ctime as _,
+			sem_ctime_high: (ctime >> 32) as _,
+			sem_nsems: ds.sem_nsems as _,
+			..Default::default()
+		}
+	}
+}
+
+impl From<CompatSemidDs> for SemidDs {
+	fn from(ds: CompatSemidDs) -> Self {
+		let time = |low: u32, high: u32| ((high as u64) << 32 | low as u64) as _;
+		Self {
+			sem_perm: ds.sem_perm.into(),
+			sem_otime: time(ds.sem_otime, ds.sem_otime_high),
+			sem_ctime: time(ds.sem_ctime, ds.sem_ctime_high),
+			sem_nsems: ds.sem_nsems as _,
+			..Default::default()
+		}
+	}
+}
+
+/// A semaphore.
+#[derive(Clone, Debug, Default)]
+struct Sem {
+	/// The value of the semaphore.
+	val: i32,
+	/// The PID of the last process which operated on the semaphore.
+	pid: Pid,
+	/// The number of processes waiting for the value to increase.
+	ncnt: usize,
+	/// The number of processes waiting for the value to become zero.
+	zcnt: usize,
+}
+
+/// The adjustments to apply to the semaphores of a set when a process exits.
+#[derive(Debug)]
+struct Undo {
+	/// The PID of the process.
+	pid: Pid,
+	/// The adjustment of each semaphore.
+	adj: Vec<i32>,
+}
+
+/// The semaphore a process is blocked on: its index, and whether the process waits for it to
+/// become zero (otherwise, to increase).
+type Blocker = (usize, bool);
+
+/// Mutable state of a semaphore set.
+#[derive(Debug)]
+struct SemState {
+	/// Ownership and permissions.
+	perm: IpcObjectPerm,
+	/// The semaphores.
+	sems: Vec<Sem>,
+	/// The adjustments of processes which performed operations with [`SEM_UNDO`].
+	undo: Vec<Undo>,
+
+	/// The timestamp of the last `semop`, in seconds.
+	otime: Timestamp,
+	/// The timestamp of the last change, in seconds.
+	ctime: Timestamp,
+	/// Tells whether the set has been removed.
+	removed: bool,
+}
+
+impl SemState {
+	/// Tries to apply `ops` atomically on behalf of the process `pid`.
+	///
+	/// If an operation would block, no operation is applied and the function returns the
+	/// semaphore it would block on.
+	///
+	/// Errors:
+	/// - [`errno::ERANGE`]: an operation would make a semaphore exceed [`SEMVMX`]
+	/// - [`errno::EAGAIN`]: an operation would block, and is flagged with [`IPC_NOWAIT`]
+	fn try_apply(&mut self, ops: &[SemBuf], pid: Pid) -> EResult<Option<Blocker>> {
+		for (i, op) in ops.iter().enumerate() {
+			let num = op.sem_num as usize;
+			let sem = &mut self.sems[num];
+			let val = sem.val + op.sem_op as i32;
+			let res = match op.sem_op {
+				0 if sem.val != 0 => Err(Some((num, true))),
+				_ if val < 0 => Err(Some((num, false))),
+				_ if val > SEMVMX => Err(None),
+				_ => Ok(()),
+			};
+			let Err(blocker) = res else {
+				sem.val = val;
+				continue;
+			};
+			// Revert the operations applied so far
+			for op in ops[..i].iter().rev() {
+				self.sems[op.sem_num as usize].val -= op.sem_op as i32;
+			}
+			let Some(blocker) = blocker else {
+				return Err(errno!(ERANGE));
+			};
+			if op.sem_flg & IPC_NOWAIT as i16 != 0 {
+				return Err(errno!(EAGAIN));
+			}
+			return Ok(Some(blocker));
+		}
+		// All operations have been applied
+		let undo = self.undo.iter_mut().find(|u| u.pid == pid);
+		if let Some(undo) = undo {
+			for op in ops.iter().filter(|op| op.sem_flg & SEM_UNDO != 0) {
+				undo.adj[op.sem_num as usize] -= op.sem_op as i32;
+			}
+		}
+		for op in ops {
+			self.sems[op.sem_num as usize].pid = pid;
+		}
+		self.otime = current_time_sec(Clock::Realtime);
+		Ok(None)
+	}
+
+	/// Updates the counts of waiting processes when the current process, blocked on `*blocker`,
+	/// becomes blocked on `new`.
+	fn set_blocker(&mut self, blocker: &mut Option<Blocker>, new: Option<Blocker>) {
+		if *blocker == new {
+			return;
+		}
+		if let Some((num, zero)) = *blocker {
+			let sem = &mut self.sems[num];
+			if zero {
+				sem.zcnt -= 1;
+			} else {
+				sem.ncnt -= 1;
+			}
+		}
+		if let Some((num, zero)) = new {
+			let sem = &mut self.sems[num];
+			if zero {
+				sem.zcnt += 1;
+			} else {
+				sem.ncnt += 1;
+			}
+		}
+		*blocker = new;
+	}
+
+	/// Sets the value of the semaphore `num` to `val`, on behalf of the process `pid`.
+	///
+	/// The adjustments of all processes for this semaphore are cleared.
+	fn set_val(&mut self, num: usize, val: i32, pid: Pid) {
+		let sem = &mut self.sems[num];
+		sem.val = val;
+		sem.pid = pid;
+		for undo in self.undo.iter_mut() {
+			undo.adj[num] = 0;
+		}
+	}
+}
+
+/// A SysV semaphore set.
+#[derive(Debug)]
+pub struct SemSet {
+	/// The ID of the set in its namespace.
+	pub id: c_int,
+	/// Mutable state.
+	state: Mutex<SemState>,
+	/// The queue of processes waiting for operations on the set to be possible.
+	queue: WaitQueue,
+}
+
+impl SemSet {
+	/// Locks the state of the set, checking the agent `ap` has the access `access` to it.
+	///
+	/// Errors:
+	/// - [`errno::EIDRM`]: the set has been removed
+	/// - [`errno::EACCES`]: the agent does not have the requested access
+	fn lock(&self, ap: &AccessProfile, access: Mode) -> EResult<MutexGuard<'_, SemState, true>> {
+		let state = self.state.lock();
+		if unlikely(state.removed) {
+			return Err(errno!(EIDRM));
+		}
+		if unlikely(!state.perm.can_access(ap, access)) {
+			return Err(errno!(EACCES));
+		}
+		Ok(state)
+	}
+
+	/// Returns the number of semaphores in the set.
+	pub fn nsems(&self) -> usize {
+		self.state.lock().sems.len()
+	}
+
+	/// Applies the operations `ops` atomically on the set `set`, as `semop` does.
+	///
+	/// Arguments:
+	/// - `ap` is the access profile of the calling process
+	/// - `proc` is the calling process
+	/// - `timeout` is the maximum duration to wait for, in nanoseconds
+	///
+	/// If the operations cannot be applied before `timeout` elapses, the function returns
+	/// [`errno::EAGAIN`]. If the set is removed while waiting, the function returns
+	/// [`errno::EIDRM`].
+	pub fn semop(
+		set: &Arc<Self>,
+		ops: &[SemBuf],
+		ap: &AccessProfile,
+		proc: &Process,
+		timeout: Option<Timestamp>,
+	) -> EResult<()> {
+		let pid = proc.get_pid();
+		let alter = ops.iter().any(|op| op.sem_op != 0);
+		{
+			let mut state = set.lock(ap, if alter { S_IWOTH } else { S_IROTH })?;
+			if unlikely(ops.iter().any(|op| op.sem_num as usize >= state.sems.len())) {
+				return Err(errno!(EFBIG));
+			}
+			// Allocate the adjustments beforehand, so that applying operations cannot fail
+			let undo = ops.iter().any(|op| op.sem_flg & SEM_UNDO != 0);
+			if undo && !state.undo.iter().any(|u| u.pid == pid) {
+				let adj = vec![0; state.sems.len()]?;
+				state.undo.push(Undo {
+					pid,
+					adj,
+				})?;
+				if let Err(e) = proc.sem_undo.lock().push(set.clone()) {
+					state.undo.pop();
+					return Err(e.into());
+				}
+			}
+		}
+		let mut blocker = None;
+		let res = set.queue.wait_until_timeout(
+			|| {
+				let mut state = set.state.lock();
+				if state.removed {
+					return Some(Err(errno!(EIDRM)));
+				}
+				let res = match state.try_apply(ops, pid) {
+					Ok(Some(new)) => {
+						state.set_blocker(&mut blocker, Some(new));
+						return None;
+					}
+					Ok(None) => Ok(()),
+					Err(e) => Err(e),
+				};
+				state.set_blocker(&mut blocker, None);
+				Some(res)
+			},
+			timeout,
+		);
+		let res = match res {
+			Ok(res) => res,
+			Err(e) => {
+				let mut state = set.state.lock();
+				if !state.removed {
+					state.set_blocker(&mut blocker, None);
+				}
+				if e.as_int() == errno::ETIMEDOUT {
+					Err(errno!(EAGAIN))
+				} else {
+					Err(e)
+				}
+			}
+		};
+		if res.is_ok() && alter {
+			set.queue.wake_all();
+		}
+		res
+	}
+
+	/// Returns the value of the `semctl` command `cmd` for the semaphore `num`, on behalf of the
+	/// agent `ap`.
+	///
+	/// `cmd` is one of [`GETPID`], [`GETVAL`], [`GETNCNT`] or [`GETZCNT`].
+	pub fn get(&self, ap: &AccessProfile, num: usize, cmd: c_int) -> EResult<c_int> {
+		let state = self.lock(ap, S_IROTH)?;
+		let sem = state.sems.get(num).ok_or_else(|| errno!(EINVAL))?;
+		let val = match cmd {
+			GETPID => sem.pid as _,
+			GETVAL => sem.val,
+			GETNCNT => sem.ncnt as _,
+			GETZCNT => sem.zcnt as _,
+			_ => return Err(errno!(EINVAL)),
+		};
+		Ok(val)
+	}
+
+	/// Returns the values of all the semaphores, on behalf of the agent `ap`.
+	pub fn get_all(&self, ap: &AccessProfile) -> EResult<Vec<u16>> {
+		let state = self.lock(ap, S_IROTH)?;
+		let vals = state
+			.sems
+			.iter()
+			.map(|sem| sem.val as u16)
+			.collect::<CollectResult<_>>()
+			.0?;
+		Ok(vals)
+	}
+
+	/// Sets the value of the semaphore `num` to `val`, on behalf of the agent `ap` in the process
+	/// `pid`.
+	pub fn set_val(&self, ap: &AccessProfile, pid: Pid, num: usize, val: c_int) -> EResult<()> {
+		{
+			let mut state = self.lock(ap, S_IWOTH)?;
+			if unlikely(num >= state.sems.len()) {
+				return Err(errno!(EINVAL));
+			}
+			if unlikely(!(0..=SEMVMX).contains(&val)) {
+				return Err(errno!(ERANGE));
+			}
+			state.set_val(num, val, pid);
+			state.ctime = current_time_sec(Clock::Realtime);
+		}
+		self.queue.wake_all();
+		Ok(())
+	}
+
+	/// Sets the values of all the semaphores from `vals`, on behalf of the agent `ap` in the
+	/// process `pid`.
+	///
+	/// `vals` must contain one value for each semaphore.
+	pub fn set_all(&self, ap: &AccessProfile, pid: Pid, vals: &[u16]) -> EResult<()> {
+		{
+			let mut state = self.lock(ap, S_IWOTH)?;
+			if unlikely(vals.len() != state.sems.len()) {
+				return Err(errno!(EINVAL));
+			}
+			if unlikely(vals.iter().any(|val| *val as i32 > SEMVMX)) {
+				return Err(errno!(ERANGE));
+			}
+			for (num, val) in vals.iter().enumerate() {
+				state.set_val(num, *val as _, pid);
+			}
+			state.ctime = current_time_sec(Clock::Realtime);
+		}
+		self.queue.wake_all();
+		Ok(())
+	}
+
+	/// Returns the status of the set, on behalf of the agent `ap`.
+	pub fn stat(&self, ap: &AccessProfile) -> EResult<SemidDs> {
+		let state = self.lock(ap, S_IROTH)?;
+		Ok(SemidDs {
+			sem_perm: state.perm.to_ipc_perm(),
+			sem_otime: state.otime as _,
+			sem_ctime: state.ctime as _,
+			sem_nsems: state.sems.len() as _,
+			..Default::default()
+		})
+	}
+
+	/// Sets the owner and permissions of the set from `perm`, on behalf of the agent `ap`.
+	///
+	/// If the agent does not own the set, the function returns [`errno::EPERM`].
+	pub fn set(&self, ap: &AccessProfile, perm: &IpcPerm) -> EResult<()> {
+		let mut state = self.state.lock();
+		if unlikely(state.removed) {
+			return Err(errno!(EIDRM));
+		}
+		state.perm.set(ap, perm)?;
+		state.ctime = current_time_sec(Clock::Realtime);
+		Ok(())
+	}
+}
+
+/// The semaphore sets of an IPC namespace.
+#[derive(Debug, Default)]
+pub struct SemIds {
+	/// Identifiers and keys of sets.
+	ids: IpcIds,
+	/// Sets, by ID.
+	sets: HashMap<c_int, Arc<SemSet>>,
+}
+
+impl SemIds {
+	/// Returns the ID of the set bound to `key`, creating it if necessary, as `semget` does.
+	///
+	/// Arguments:
+	/// - `nsems` is the number of semaphores in the set
+	/// - `flags` are the `semget` flags, along with the permissions of a new set
+	/// - `ap` is the access profile of the calling process
+	pub fn get(
+		&mut self,
+		key: c_int,
+		nsems: c_int,
+		flags: c_int,
+		ap: &AccessProfile,
+	) -> EResult<c_int> {
+		let nsems: usize = nsems.try_into().map_err(|_| errno!(EINVAL))?;
+		if let Some(id) = self.ids.get(key, flags)? {
+			let set = &self.sets[id];
+			if unlikely(nsems > set.nsems()) {
+				return Err(errno!(EINVAL));
+			}
+			set.lock(ap, get_access(flags))?;
+			return Ok(id);
+		}
+		// Create the set
+		if unlikely(!(1..=SEMMSL).contains(&nsems)) {
+			return Err(errno!(EINVAL));
+		}
+		if unlikely(self.sets.len() >= SEMMNI) {
+			return Err(errno!(ENOSPC));
+		}
+		let sems = vec![Sem::default(); nsems]?;
+		let id = self.ids.alloc(key)?;
+		let set = Arc::new(SemSet {
+			id,
+			state: Mutex::new(SemState {
+				perm: IpcObjectPerm::new(key, ap, flags as _),
+				sems,
+				undo: Vec::new(),
+
+				otime: 0,
+				ctime: current_time_sec(Clock::Realtime),
+				removed: false,
+			}),
+			queue: WaitQueue::new(),
+		});
+		let res = set.and_then(|set| self.sets.insert(id, set));
+		if let Err(e) = res {
+			self.ids.remove(key);
+			return Err(e.into());
+		}
+		Ok(id)
+	}
+
+	/// Returns the set with ID `id`.
+	///
+	/// If the set does not exist, the function returns [`errno::EINVAL`].
+	pub fn get_set(&self, id: c_int) -> EResult<Arc<SemSet>> {
+		self.sets.get(&id).cloned().ok_or_else(|| errno!(EINVAL))
+	}
+
+	/// Removes the set with ID `id`, on behalf of the agent `ap`.
+	///
+	/// Processes waiting on the set are woken up, and fail with [`errno::EIDRM`].
+	pub fn remove(&mut self, id: c_int, ap: &AccessProfile) -> EResult<()> {
+		let set = self.get_set(id)?;
+		{
+			let mut state = set.state.lock();
+			if unlikely(!state.perm.is_owner(ap)) {
+				return Err(errno!(EPERM));
+			}
+			if state.perm.key != IPC_PRIVATE {
+				self.ids.remove(state.perm.key);
+			}
+			state.removed = true;
+		}
+		self.sets.remove(&id);
+		set.queue.wake_all();
+		Ok(())
+	}
+}
+
+/// Applies the adjustments of the exiting process `pid` to the semaphore sets `sets`, on which it
+/// performed operations flagged with [`SEM_UNDO`].
+pub fn exit(pid: Pid, sets: Vec<Arc<SemSet>>) {
+	for set in sets {
+		{
+			let mut state = set.state.lock();
+			let state = &mut *state;
+			if state.removed {
+				continue;
+			}
+			let Some(i) = state.undo.iter().position(|u| u.pid == pid) else {
+				continue;
+			};
+			let undo = state.undo.remove(i);
+			for (sem, adj) in state.sems.iter_mut().zip(undo.adj) {
+				if adj != 0 {
+					// Values are clamped, since other processes may have modified them
+					sem.val = (sem.val + adj).clamp(0, SEMVMX);
+					sem.pid = pid;
+				}
+			}
+		}
+		set.queue.wake_all();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn sem_try_apply() {
+		let mut state = SemState {
+			perm: IpcObjectPerm::new(IPC_PRIVATE, &AccessProfile::KERNEL, 0o600),
+			sems: Vec::try_from([Sem::default(), Sem::default()]).unwrap(),
+			undo: Vec::new(),
+
+			otime: 0,
+			ctime: 0,
+			removed: false,
+		};
+		let op = |sem_num, sem_op, sem_flg| SemBuf {
+			sem_num,
+			sem_op,
+			sem_flg,
+		};
+		assert_eq!(state.try_apply(&[op(0, 2, 0), op(1, 1, 0)], 1), Ok(None));
+		// The second operation blocks, so the first one must be reverted
+		let blocker = state.try_apply(&[op(0, -1, 0), op(1, -2, 0)], 1);
+		assert_eq!(blocker, Ok(Some((1, false))));
+		assert_eq!(state.sems[0].val, 2);
+		assert_eq!(state.try_apply(&[op(0, 0, 0)], 1), Ok(Some((0, true))));
+		assert!(
+			state
+				.try_apply(&[op(1, -2, IPC_NOWAIT as _)], 1)
+				.is_err_and(|e| e.as_int() == errno::EAGAIN)
+		);
+		assert!(
+			state
+				.try_apply(&[op(1, SEMVMX as _, 0)], 1)
+				.is_err_and(|e| e.as_int() == errno::ERANGE)
+		);
+		assert_eq!(state.try_apply(&[op(0, -2, 0), op(1, -1, 0)], 1), Ok(None));
+		assert_eq!(state.sems[0].val, 0);
+		assert_eq!(state.sems[1].val, 0);
+	}
+}
@@ -30,10 +30,9 @@ use crate::{
 };
 use core::{
 	ffi::{c_int, c_void},
+	hint::unlikely,
 	mem::{size_of, transmute},
-	ptr,
-	ptr::NonNull,
-	slice,
+	ptr, slice,
 };
 use ucontext::UContext32;
 #[cfg(target_pointer_width = "64")]
@@ -46,16 +45,30 @@ pub const SIG_IGN: usize = 0x0;
 pub const SIG_DFL: usize = 0x1;
 
 // TODO implement all flags
+/// [`SigAction`] flag: If set for `SIGCHLD`, the signal is not sent when a child process stops or
+/// resumes.
+pub const SA_NOCLDSTOP: u64 = 0x00000001;
 /// [`SigAction`] flag: If set, use `sa_sigaction` instead of `sa_handler`.
 pub const SA_SIGINFO: u64 = 0x00000004;
 /// [`SigAction`] flag: If set, use [`SigAction::sa_restorer`] as signal trampoline.
 pub const SA_RESTORER: u64 = 0x04000000;
+/// [`SigAction`] flag: If set, the handler is executed on the alternate signal stack, if any.
+pub const SA_ONSTACK: u64 = 0x08000000;
 /// [`SigAction`] flag: If set, the system call must restart after being interrupted by a signal.
 pub const SA_RESTART: u64 = 0x10000000;
 /// [`SigAction`] flag: If set, the signal is not added to the signal mask of the process when
 /// executed.
 pub const SA_NODEFER: u64 = 0x40000000;
 
+/// [`SigStack`] flag: The process is currently executing on the alternate signal stack.
+pub const SS_ONSTACK: c_int = 1;
+/// [`SigStack`] flag: The alternate signal stack is disabled.
+pub const SS_DISABLE: c_int = 2;
+/// [`SigStack`] flag: The alternate signal stack is disabled while a handler runs on it.
+pub const SS_AUTODISARM: c_int = 1 << 31;
+/// The minimum size of an alternate signal stack, in bytes.
+pub const MINSIGSTKSZ: usize = 2048;
+
 /// Notify method: generate a signal
 pub const SIGEV_SIGNAL: c_int = 0;
 /// Notify method: do nothing
@@ -152,7 +165,9 @@ pub struct SigInfo {
 	si_arch: u32,
 }
 
-/// Kernelspace signal mask.
+/// Signal mask.
+///
+/// The layout is the same as userspace's: signal `n` is represented by bit `n - 1`.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct SigSet(pub u64);
 
@@ -163,28 +178,28 @@ impl SigSet {
 		self.0 == 0
 	}
 
-	/// Tells whether the `n`th bit is set.
+	/// Tells whether signal `n` is in the set.
 	#[inline]
 	pub fn is_set(&self, n: usize) -> bool {
-		self.0 & (1 << n) != 0
+		self.0 & (1 << (n - 1)) != 0
 	}
 
-	/// Sets the `n`th bit.
+	/// Adds signal `n` to the set.
 	#[inline]
 	pub fn set(&mut self, n: usize) {
-		self.0 |= (1 << n) as u64;
+		self.0 |= 1 << (n - 1);
 	}
 
-	/// Sets the `n`th bit.
+	/// Removes signal `n` from the set.
 	#[inline]
 	pub fn clear(&mut self, n: usize) {
-		self.0 &= !((1 << n) as u64);
+		self.0 &= !(1 << (n - 1));
 	}
 
-	/// Returns an iterator over the bitset's values
+	/// Returns an iterator over the signals in the set, in increasing order.
 	#[inline]
-	pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
-		(0..64).map(|n| self.is_set(n))
+	pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+		(1..=64).filter(|n| self.is_set(*n))
 	}
 }
 
@@ -241,22 +256,80 @@ impl From<SigAction> for CompatSigAction {
 	}
 }
 
+/// Description of an alternate signal stack.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SigStack {
+	/// Pointer to the beginning of the stack.
+	pub ss_sp: usize,
+	/// The stack's flags.
+	pub ss_flags: c_int,
+	/// The size of the stack in bytes.
+	pub ss_size: usize,
+}
+
+impl Default for SigStack {
+	fn default() -> Self {
+		Self {
+			ss_sp: 0,
+			ss_flags: SS_DISABLE,
+			ss_size: 0,
+		}
+	}
+}
+
+impl From<CompatSigStack> for SigStack {
+	fn from(stack: CompatSigStack) -> Self {
+		Self {
+			ss_sp: stack.ss_sp as _,
+			ss_flags: stack.ss_flags,
+			ss_size: stack.ss_size as _,
+		}
+	}
+}
+
+/// Compatibility version of [`SigStack`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CompatSigStack {
+	pub ss_sp: u32,
+	pub ss_flags: c_int,
+	pub ss_size: u32,
+}
+
+impl From<SigStack> for CompatSigStack {
+	fn from(stack: SigStack) -> Self {
+		Self {
+			ss_sp: stack.ss_sp as _,
+			ss_flags: stack.ss_flags,
+			ss_size: stack.ss_size as _,
+		}
+	}
+}
+
+/// The size of [`SigEvent`], in bytes.
+const SIGEV_MAX_SIZE: usize = 64;
+/// The number of integers in the padding of [`SigEvent`], after the thread ID.
+const SIGEV_PAD_SIZE: usize = (SIGEV_MAX_SIZE - size_of::<SigVal>()) / size_of::<c_int>() - 3;
+
 /// Notification from asynchronous routines.
 #[repr(C)]
 #[derive(Clone, Debug, Default)]
 pub struct SigEvent {
-	/// Notification method.
-	pub sigev_notify: c_int,
+	/// Data passed with the notification.
+	pub sigev_value: SigVal,
 	/// Notification signal.
 	pub sigev_signo: c_int,
-	/// TODO doc
-	pub sigev_value: SigVal,
-	/// Function used for thread notification.
-	pub sigev_notify_function: Option<NonNull<extern "C" fn(SigVal)>>,
-	/// Data passed with notification.
-	pub sigev_notify_attributes: Option<NonNull<c_void>>,
-	/// ID of thread to signal.
-	pub sigev_notify_thread_id: Pid,
+	/// Notification method.
+	pub sigev_notify: c_int,
+	/// ID of the thread to signal.
+	///
+	/// For `SIGEV_THREAD`, this field is overlapped by the function and attributes of the
+	/// notification thread, which are only used by the C library.
+	pub sigev_notify_thread_id: c_int,
+	/// Padding.
+	pub _pad: [c_int; SIGEV_PAD_SIZE],
 }
 
 impl SigEvent {
@@ -359,12 +432,32 @@ impl SignalHandler {
 				return;
 			}
 		};
-		// TODO trigger EFAULT if SA_RESTORER is not set
+		let mem_space = process.mem_space.as_ref().unwrap();
+		// If the handler does not provide its own trampoline, use the vDSO's
+		let restorer = if action.sa_flags & SA_RESTORER != 0 {
+			action.sa_restorer
+		} else {
+			mem_space.exe_info.sigreturn.0
+		};
+		if unlikely(restorer == 0) {
+			force_sigsegv(process);
+			return;
+		}
 		// TODO handle SA_SIGINFO
-		// TODO Handle the case where an alternate stack is specified (sigaltstack + flag
-		// SA_ONSTACK)
 		// Prepare the signal handler stack
-		let stack_addr = VirtAddr(frame.get_stack_address()) - REDZONE_SIZE;
+		let sp = frame.get_stack_address();
+		let (altstack, on_altstack) = {
+			let signal_manager = process.signal.lock();
+			(signal_manager.altstack, signal_manager.on_altstack(sp))
+		};
+		// Switch to the alternate stack if requested and not already on it
+		let switch_stack =
+			action.sa_flags & SA_ONSTACK != 0 && altstack.ss_size != 0 && !on_altstack;
+		let stack_addr = if switch_stack {
+			VirtAddr(altstack.ss_sp + altstack.ss_size)
+		} else {
+			VirtAddr(sp) - REDZONE_SIZE
+		};
 		// Size of the `ucontext_t` struct and arguments *on the stack*
 		let (ctx_size, ctx_align, arg_len) = if frame.is_compat() {
 			(
@@ -384,8 +477,12 @@ impl SignalHandler {
 		};
 		let ctx_addr = (stack_addr - ctx_size).down_align_to(ctx_align);
 		let signal_sp = ctx_addr - arg_len;
+		// Do not overflow the alternate stack
+		if unlikely((switch_stack || on_altstack) && signal_sp.0 <= altstack.ss_sp) {
+			force_sigsegv(process);
+			return;
+		}
 		// Bind virtual memory
-		let mem_space = process.mem_space.as_ref().unwrap();
 		MemSpace::bind(mem_space);
 		// Write data on stack
 		if frame.is_compat() {
@@ -397,13 +494,13 @@ impl SignalHandler {
 			// Argument
 			args[1] = signal as _;
 			// Return pointer
-			args[0] = action.sa_restorer as _;
+			args[0] = restorer as _;
 		} else {
 			#[cfg(target_pointer_width = "64")]
 			unsafe {
 				ptr::write_volatile(ctx_addr.as_ptr(), UContext64::new(process, frame));
 				// Return pointer
-				ptr::write_volatile(signal_sp.as_ptr::<u64>(), action.sa_restorer as _);
+				ptr::write_volatile(signal_sp.as_ptr::<u64>(), restorer as _);
 			}
 		}
 		// Block signal from `sa_mask`
@@ -413,6 +510,10 @@ impl SignalHandler {
 			if action.sa_flags & SA_NODEFER == 0 {
 				signals_manager.sigmask.set(signal as _);
 			}
+			// The previous state is restored from the context when the handler returns
+			if switch_stack && altstack.ss_flags & SS_AUTODISARM != 0 {
+				signals_manager.altstack = Default::default();
+			}
 		}
 		// Prepare registers for the trampoline
 		frame.rbp = 0;
@@ -427,6 +528,12 @@ impl SignalHandler {
 	}
 }
 
+/// Terminates the process with the default action of `SIGSEGV`, because the handler of a signal
+/// cannot be executed.
+fn force_sigsegv(process: &Process) {
+	Signal::SIGSEGV.get_default_action().exec(process);
+}
+
 /// Enumeration of signal types.
 #[repr(i32)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -549,3 +656,21 @@ impl Signal {
 		)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn sigset_layout() {
+		let mut set = SigSet::default();
+		set.set(Signal::SIGHUP as _);
+		set.set(Signal::SIGUSR1 as _);
+		assert_eq!(set.0, (1 << 0) | (1 << 9));
+		assert!(set.is_set(Signal::SIGUSR1 as _));
+		assert!(!set.is_set(Signal::SIGKILL as _));
+		assert!(set.iter().eq([1, 10]));
+		set.clear(Signal::SIGHUP as _);
+		assert_eq!(set.0, 1 << 9);
+	}
+}
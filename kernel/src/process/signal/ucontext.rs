@@ -22,11 +22,59 @@
 
 use crate::{
 	arch::x86::{gdt, idt::IntFrame},
-	process::{Process, signal::SigSet},
+	process::{
+		Process, ProcessSignal, TLS_ENTRIES_COUNT,
+		mem_space::bound_check,
+		signal::{CompatSigStack, MINSIGSTKSZ, SS_AUTODISARM, SigSet, SigStack},
+	},
 };
+use core::hint::unlikely;
+use utils::{errno, errno::EResult};
 
 // TODO restore everything
 
+/// The flags of the `eflags`/`rflags` register that can be restored from a context: `CF`, `PF`,
+/// `AF`, `ZF`, `SF`, `TF`, `DF`, `OF`, `RF` and `AC`.
+///
+/// Other flags, such as `IF` or `IOPL`, are privileged and are kept untouched.
+const USER_FLAGS: usize = 0x50dd5;
+
+/// Returns the value of the flags register after restoring `flags` from a context, `cur` being the
+/// current value.
+fn restore_flags(cur: usize, flags: usize) -> usize {
+	(cur & !USER_FLAGS) | (flags & USER_FLAGS)
+}
+
+/// Tells whether userspace is allowed to load the data segment selector `sel`.
+///
+/// Loading an invalid selector would fault in kernelspace when returning to userspace.
+fn is_user_selector(sel: usize) -> bool {
+	let tls = gdt::TLS_OFFSET..(gdt::TLS_OFFSET + TLS_ENTRIES_COUNT * size_of::<gdt::Entry>());
+	// Null selector, or GDT selector with privilege level 3
+	sel == 0 || (sel & 0x7 == 3 && (sel == gdt::USER_DS | 3 || tls.contains(&(sel & !0x7))))
+}
+
+/// Fills the signal state of a new context: the alternate signal stack, and the signal mask.
+fn save_signal_state(process: &Process, frame: &IntFrame) -> (SigStack, SigSet) {
+	let mut signal = process.signal.lock();
+	let stack = signal.altstack_info(frame.get_stack_address());
+	(stack, signal.context_sigmask())
+}
+
+/// Restores the signal state of a context.
+///
+/// If the alternate signal stack has been disarmed when the signal was delivered (see
+/// [`SS_AUTODISARM`]), it is rearmed.
+fn restore_signal_state(signal: &mut ProcessSignal, stack: SigStack, sigmask: SigSet) {
+	signal.sigmask = sigmask;
+	if stack.ss_flags & SS_AUTODISARM != 0 && stack.ss_size >= MINSIGSTKSZ {
+		signal.altstack = SigStack {
+			ss_flags: SS_AUTODISARM,
+			..stack
+		};
+	}
+}
+
 // ------------------------------
 //    32 bit structures
 
@@ -60,7 +108,7 @@ pub enum GReg32 {
 pub struct UContext32 {
 	pub uc_flags: u32,
 	pub uc_link: u32, // 32 bit pointer
-	pub uc_stack: Stack32,
+	pub uc_stack: CompatSigStack,
 	pub uc_mcontext: MContext32,
 	pub uc_sigmask: SigSet,
 	pub __fpregs_mem: FpState32,
@@ -70,15 +118,11 @@ pub struct UContext32 {
 impl UContext32 {
 	/// Creates a context structure from the current.
 	pub fn new(process: &Process, frame: &IntFrame) -> Self {
+		let (uc_stack, uc_sigmask) = save_signal_state(process, frame);
 		Self {
 			uc_flags: 0, // TODO
 			uc_link: 0,
-			// TODO
-			uc_stack: Stack32 {
-				ss_sp: 0,
-				ss_flags: 0,
-				ss_size: 0,
-			},
+			uc_stack: uc_stack.into(),
 			uc_mcontext: MContext32 {
 				gregs: [
 					frame.gs as _,
@@ -105,7 +149,7 @@ impl UContext32 {
 				oldmask: 0, // TODO
 				cr2: 0,
 			},
-			uc_sigmask: process.signal.lock().sigmask,
+			uc_sigmask,
 			// TODO
 			__fpregs_mem: FpState32 {
 				cw: 0,
@@ -126,34 +170,46 @@ impl UContext32 {
 	}
 
 	/// Restores the context.
-	pub fn restore_regs(&self, proc: &Process, frame: &mut IntFrame) {
+	///
+	/// If the context is invalid, the function returns an error and the frame is left untouched.
+	pub fn restore_regs(&self, proc: &Process, frame: &mut IntFrame) -> EResult<()> {
+		let gregs = &self.uc_mcontext.gregs;
+		// Validate the context before modifying the frame
+		let gs = gregs[GReg32::Gs as usize] as usize;
+		let fs = gregs[GReg32::Fs as usize] as usize;
+		if unlikely(!is_user_selector(gs) || !is_user_selector(fs)) {
+			return Err(errno!(EFAULT));
+		}
+		let esp = gregs[GReg32::Esp as usize] as usize;
+		let eip = gregs[GReg32::Eip as usize] as usize;
+		if unlikely(!bound_check(esp, 0) || !bound_check(eip, 0)) {
+			return Err(errno!(EFAULT));
+		}
 		// Restore general registers
-		frame.gs = self.uc_mcontext.gregs[GReg32::Gs as usize] as _;
-		frame.fs = self.uc_mcontext.gregs[GReg32::Fs as usize] as _;
-		frame.rax = self.uc_mcontext.gregs[GReg32::Eax as usize] as _;
-		frame.rbx = self.uc_mcontext.gregs[GReg32::Ebx as usize] as _;
-		frame.rcx = self.uc_mcontext.gregs[GReg32::Ecx as usize] as _;
-		frame.rdx = self.uc_mcontext.gregs[GReg32::Edx as usize] as _;
-		frame.rsi = self.uc_mcontext.gregs[GReg32::Esi as usize] as _;
-		frame.rdi = self.uc_mcontext.gregs[GReg32::Edi as usize] as _;
-		frame.rbp = self.uc_mcontext.gregs[GReg32::Ebp as usize] as _;
-		frame.rsp = self.uc_mcontext.gregs[GReg32::Esp as usize] as _;
-		frame.rip = self.uc_mcontext.gregs[GReg32::Eip as usize] as _;
-		frame.rflags = self.uc_mcontext.gregs[GReg32::Efl as usize] as _;
+		frame.gs = gs as _;
+		frame.fs = fs as _;
+		frame.rax = gregs[GReg32::Eax as usize] as _;
+		frame.rbx = gregs[GReg32::Ebx as usize] as _;
+		frame.rcx = gregs[GReg32::Ecx as usize] as _;
+		frame.rdx = gregs[GReg32::Edx as usize] as _;
+		frame.rsi = gregs[GReg32::Esi as usize] as _;
+		frame.rdi = gregs[GReg32::Edi as usize] as _;
+		frame.rbp = gregs[GReg32::Ebp as usize] as _;
+		frame.rsp = esp as _;
+		frame.rip = eip as _;
+		// `cs` and `ss` are not restored, so that the process stays in userspace
+		let eflags = gregs[GReg32::Efl as usize] as usize;
+		frame.rflags = restore_flags(frame.rflags as _, eflags) as _;
 		// TODO restore fpstate
-		proc.signal.lock().sigmask = self.uc_sigmask;
+		restore_signal_state(
+			&mut proc.signal.lock(),
+			self.uc_stack.into(),
+			self.uc_sigmask,
+		);
+		Ok(())
 	}
 }
 
-/// 32-bit description of a signal stack.
-#[repr(C)]
-#[derive(Debug)]
-pub struct Stack32 {
-	pub ss_sp: u32, // 32 bit pointer
-	pub ss_flags: i32,
-	pub ss_size: u32,
-}
-
 /// 32-bit registers state.
 #[repr(C)]
 #[derive(Debug)]
@@ -193,9 +249,14 @@ pub struct FpReg32 {
 #[cfg(target_arch = "x86_64")]
 /// 64 bit structures.
 mod long {
+	use super::{restore_flags, restore_signal_state, save_signal_state};
 	use crate::{
 		arch::x86::idt::IntFrame,
-		process::{Process, mem_space::bound_check, signal::SigSet},
+		process::{
+			Process,
+			mem_space::bound_check,
+			signal::{SigSet, SigStack},
+		},
 	};
 	use core::hint::unlikely;
 	use utils::{errno, errno::EResult};
@@ -234,7 +295,7 @@ mod long {
 	pub struct UContext64 {
 		pub uc_flags: u64,
 		pub uc_link: u64, // 64 bit pointer
-		pub uc_stack: Stack64,
+		pub uc_stack: SigStack,
 		pub uc_mcontext: MContext64,
 		pub uc_sigmask: SigSet,
 		pub __fpregs_mem: FpState64,
@@ -244,15 +305,11 @@ mod long {
 	impl UContext64 {
 		/// Creates a context structure from the current.
 		pub fn new(process: &Process, frame: &IntFrame) -> Self {
+			let (uc_stack, uc_sigmask) = save_signal_state(process, frame);
 			Self {
 				uc_flags: 0, // TODO
 				uc_link: 0,
-				// TODO
-				uc_stack: Stack64 {
-					ss_sp: 0,
-					ss_flags: 0,
-					ss_size: 0,
-				},
+				uc_stack,
 				uc_mcontext: MContext64 {
 					gregs: [
 						frame.r8,
@@ -282,7 +339,7 @@ mod long {
 					fpregs: 0, // TODO
 					__reserved1: [0; 8],
 				},
-				uc_sigmask: process.signal.lock().sigmask,
+				uc_sigmask,
 				// TODO
 				__fpregs_mem: FpState64 {
 					cwd: 0,
@@ -308,47 +365,44 @@ mod long {
 		}
 
 		/// Restores the context.
+		///
+		/// If the context is invalid, the function returns an error and the frame is left
+		/// untouched.
 		pub fn restore_regs(&self, proc: &Process, frame: &mut IntFrame) -> EResult<()> {
-			// Restore general registers
-			frame.rax = self.uc_mcontext.gregs[GReg64::Rax as usize] as _;
-			frame.rbx = self.uc_mcontext.gregs[GReg64::Rbx as usize] as _;
-			frame.rcx = self.uc_mcontext.gregs[GReg64::Rcx as usize] as _;
-			frame.rdx = self.uc_mcontext.gregs[GReg64::Rdx as usize] as _;
-			frame.rsi = self.uc_mcontext.gregs[GReg64::Rsi as usize] as _;
-			frame.rdi = self.uc_mcontext.gregs[GReg64::Rdi as usize] as _;
-			frame.rbp = self.uc_mcontext.gregs[GReg64::Rbp as usize] as _;
-			frame.r8 = self.uc_mcontext.gregs[GReg64::R8 as usize] as _;
-			frame.r9 = self.uc_mcontext.gregs[GReg64::R9 as usize] as _;
-			frame.r10 = self.uc_mcontext.gregs[GReg64::R10 as usize] as _;
-			frame.r11 = self.uc_mcontext.gregs[GReg64::R11 as usize] as _;
-			frame.r12 = self.uc_mcontext.gregs[GReg64::R12 as usize] as _;
-			frame.r13 = self.uc_mcontext.gregs[GReg64::R13 as usize] as _;
-			frame.r14 = self.uc_mcontext.gregs[GReg64::R14 as usize] as _;
-			frame.r15 = self.uc_mcontext.gregs[GReg64::R15 as usize] as _;
+			let gregs = &self.uc_mcontext.gregs;
 			// Check addresses to avoid GPF because of non-canonical addresses
-			let rsp = self.uc_mcontext.gregs[GReg64::Rsp as usize] as _;
-			let rip = self.uc_mcontext.gregs[GReg64::Rip as usize] as _;
+			let rsp = gregs[GReg64::Rsp as usize];
+			let rip = gregs[GReg64::Rip as usize];
 			if unlikely(!bound_check(rsp as _, 0) || !bound_check(rip as _, 0)) {
 				return Err(errno!(EFAULT));
 			}
+			// Restore general registers
+			frame.rax = gregs[GReg64::Rax as usize];
+			frame.rbx = gregs[GReg64::Rbx as usize];
+			frame.rcx = gregs[GReg64::Rcx as usize];
+			frame.rdx = gregs[GReg64::Rdx as usize];
+			frame.rsi = gregs[GReg64::Rsi as usize];
+			frame.rdi = gregs[GReg64::Rdi as usize];
+			frame.rbp = gregs[GReg64::Rbp as usize];
+			frame.r8 = gregs[GReg64::R8 as usize];
+			frame.r9 = gregs[GReg64::R9 as usize];
+			frame.r10 = gregs[GReg64::R10 as usize];
+			frame.r11 = gregs[GReg64::R11 as usize];
+			frame.r12 = gregs[GReg64::R12 as usize];
+			frame.r13 = gregs[GReg64::R13 as usize];
+			frame.r14 = gregs[GReg64::R14 as usize];
+			frame.r15 = gregs[GReg64::R15 as usize];
 			frame.rsp = rsp;
 			frame.rip = rip;
-			frame.rflags = self.uc_mcontext.gregs[GReg64::Efl as usize] as _;
+			// `cs` and `ss` are not restored, so that the process stays in userspace
+			let rflags = gregs[GReg64::Efl as usize];
+			frame.rflags = restore_flags(frame.rflags as _, rflags as _) as _;
 			// TODO restore fpstate
-			proc.signal.lock().sigmask = self.uc_sigmask;
+			restore_signal_state(&mut proc.signal.lock(), self.uc_stack, self.uc_sigmask);
 			Ok(())
 		}
 	}
 
-	/// 64-bit description of a signal stack.
-	#[repr(C)]
-	#[derive(Debug)]
-	pub struct Stack64 {
-		pub ss_sp: u64, // 64 bit pointer
-		pub ss_flags: i32,
-		pub ss_size: usize,
-	}
-
 	/// 64-bit registers state.
 	#[repr(C)]
 	#[derive(Debug)]
@@ -394,3 +448,24 @@ mod long {
 
 #[cfg(target_arch = "x86_64")]
 pub use long::*;
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn context_sanitize() {
+		assert!(is_user_selector(0));
+		assert!(is_user_selector(gdt::USER_DS | 3));
+		assert!(is_user_selector(gdt::TLS_OFFSET | 3));
+		// Kernel segments, wrong privilege level and LDT selectors are rejected
+		assert!(!is_user_selector(gdt::KERNEL_DS));
+		assert!(!is_user_selector(gdt::KERNEL_DS | 3));
+		assert!(!is_user_selector(gdt::USER_DS));
+		assert!(!is_user_selector(gdt::TLS_OFFSET | 7));
+		assert!(!is_user_selector(gdt::TSS_OFFSET | 3));
+		// `IF` is kept and `IOPL` cannot be raised, while arithmetic flags are restored
+		assert_eq!(restore_flags(0x202, 0x3001), 0x203);
+		assert_eq!(restore_flags(0x202, 0), 0x202);
+	}
+}
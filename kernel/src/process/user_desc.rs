@@ -80,6 +80,13 @@ impl UserDesc {
 		(self.0[12] & 0b1) != 0
 	}
 
+	/// Returns the type of the segment's content: `0` for data, `1` for stack, `2` and `3` for
+	/// code.
+	#[inline(always)]
+	pub fn get_contents(&self) -> u8 {
+		((self.0[12] >> 1) & 0b11) as _
+	}
+
 	/// Tells whether the segment is writable.
 	#[inline(always)]
 	pub fn is_read_exec_only(&self) -> bool {
@@ -104,16 +111,71 @@ impl UserDesc {
 		(self.0[12] & 0b1000000) != 0
 	}
 
+	/// Tells whether the descriptor requests the entry to be cleared.
+	///
+	/// This is the case either if every field is zero, or if the segment is non-present,
+	/// read-only and has a null base and limit.
+	pub fn is_empty(&self) -> bool {
+		let flags = self.0[12] as u8 & 0b1111111;
+		let zero = self.get_base_addr().is_null() && self.get_limit() == 0;
+		zero && (flags == 0 || flags == 0b101000)
+	}
+
+	/// Tells whether the descriptor can be used for a TLS segment.
+	///
+	/// Only 32 bit data or stack segments are allowed.
+	pub fn is_valid_tls(&self) -> bool {
+		self.is_empty() || (self.is_32bits() && self.get_contents() <= 1)
+	}
+
+	/// Creates a descriptor from the GDT entry `ent`, with the entry number `entry_number`.
+	pub fn from_descriptor(entry_number: i32, ent: &gdt::Entry) -> Self {
+		let access_byte = ent.get_access_byte();
+		let flags = ent.get_flags();
+		let mut bits = 0u8;
+		if flags & (1 << 2) != 0 {
+			bits |= 0b1;
+		}
+		bits |= ((access_byte >> 2) & 0b11) << 1;
+		if access_byte & (1 << 1) == 0 {
+			bits |= 0b1000;
+		}
+		if flags & (1 << 3) != 0 {
+			bits |= 0b10000;
+		}
+		if !ent.is_present() {
+			bits |= 0b100000;
+		}
+		if flags & 0b1 != 0 {
+			bits |= 0b1000000;
+		}
+		let mut buf = [0u8; USER_DESC_SIZE];
+		buf[0..4].copy_from_slice(&entry_number.to_ne_bytes());
+		buf[4..8].copy_from_slice(&ent.get_base().to_ne_bytes());
+		buf[8..12].copy_from_slice(&ent.get_limit().to_ne_bytes());
+		buf[12] = bits;
+		Self(buf.map(|b| b as _))
+	}
+
 	/// Converts the current descriptor to a GDT entry.
+	///
+	/// If the descriptor is empty, the returned entry is null.
 	pub fn to_descriptor(&self) -> gdt::Entry {
-		let mut access_byte = 0b01110010;
-		if self.is_present() && self.is_usable() {
+		if self.is_empty() {
+			return gdt::Entry::default();
+		}
+		// Accessed data segment, usable from ring 3
+		let mut access_byte = 0b01110001 | (self.get_contents() << 2);
+		if self.is_present() {
 			access_byte |= 1 << 7;
 		}
-		if self.is_read_exec_only() {
-			access_byte |= 1 << 3;
+		if !self.is_read_exec_only() {
+			access_byte |= 1 << 1;
 		}
 		let mut flags = 0b0000;
+		if self.is_usable() {
+			flags |= 1;
+		}
 		if self.is_32bits() {
 			flags |= 1 << 2;
 		}
@@ -136,6 +198,7 @@ impl fmt::Debug for UserDesc {
 			.field("base_addr", &self.get_base_addr())
 			.field("limit", &self.get_limit())
 			.field("seg_32bit", &self.is_32bits())
+			.field("contents", &self.get_contents())
 			.field("read_exec_only", &self.is_read_exec_only())
 			.field("limit_in_pages", &self.is_limit_in_pages())
 			.field("seg_not_present", &!self.is_present())
@@ -143,3 +206,33 @@ impl fmt::Debug for UserDesc {
 			.finish()
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn user_desc_descriptor() {
+		// A glibc-like TLS descriptor: 32 bit, limit in pages, usable
+		let mut buf = [0u8; USER_DESC_SIZE];
+		buf[0..4].copy_from_slice(&12i32.to_ne_bytes());
+		buf[4..8].copy_from_slice(&0xf7f00000u32.to_ne_bytes());
+		buf[8..12].copy_from_slice(&0xfffffu32.to_ne_bytes());
+		buf[12] = 0b1010001;
+		let desc = UserDesc(buf.map(|b| b as _));
+		assert!(desc.is_valid_tls());
+		let ent = desc.to_descriptor();
+		assert!(ent.is_present());
+		assert_eq!(ent.get_base(), 0xf7f00000);
+		assert_eq!(ent.get_limit(), 0xfffff);
+		let back = UserDesc::from_descriptor(12, &ent);
+		assert_eq!(back.0, desc.0);
+		// An empty descriptor clears the entry, and reads back as empty
+		let empty = UserDesc::from_descriptor(12, &gdt::Entry::default());
+		assert!(empty.is_empty());
+		assert_eq!(empty.to_descriptor().0, 0);
+		// Code segments are rejected
+		buf[12] = 0b1010101;
+		assert!(!UserDesc(buf.map(|b| b as _)).is_valid_tls());
+	}
+}
@@ -0,0 +1,283 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Workqueues run deferred work items on kernel threads.
+//!
+//! Interrupt handlers and other code that cannot sleep can queue work to a workqueue, which runs
+//! it later in process context, where it is allowed to sleep or to perform I/O.
+//!
+//! Each workqueue has its own kernel thread. The system workqueue is shared, and is accessed
+//! through [`queue_work`] and [`queue_delayed_work`]. Subsystems whose work may block for a long
+//! time should create a dedicated workqueue with [`Workqueue::new`].
+
+use crate::{
+	file::wait_queue::WaitQueue,
+	process::{kthread, kthread::KThread},
+	sync::{
+		mutex::{IntMutex, Mutex},
+		once::OnceInit,
+	},
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::Timestamp,
+	},
+};
+use core::{
+	mem,
+	sync::atomic::{
+		AtomicBool,
+		Ordering::{AcqRel, Acquire, Release},
+	},
+};
+use utils::{
+	boxed::Box,
+	collections::vec::Vec,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// A work item, holding a function to be run by a workqueue.
+///
+/// A work item can be queued again once it has started running.
+pub struct Work {
+	/// The function to run.
+	func: Box<dyn Fn()>,
+	/// Tells whether the work is queued and has not started running yet.
+	pending: AtomicBool,
+}
+
+impl Work {
+	/// Creates a work item running `func`.
+	pub fn new<F: 'static + Fn()>(func: F) -> AllocResult<Arc<Self>> {
+		Arc::new(Self {
+			func: Box::new(func)?,
+			pending: AtomicBool::new(false),
+		})
+	}
+
+	/// Tells whether the work is queued and has not started running yet.
+	#[inline]
+	pub fn is_pending(&self) -> bool {
+		self.pending.load(Acquire)
+	}
+}
+
+/// A queue of work items, run in order by a dedicated kernel thread.
+pub struct Workqueue {
+	/// The name of the workqueue, which is also the name of its thread.
+	pub name: &'static str,
+	/// Work ready to be run, in order.
+	queue: IntMutex<Vec<Arc<Work>>>,
+	/// Delayed work, with the time at which it is due, in nanoseconds on the monotonic clock.
+	delayed: IntMutex<Vec<(Timestamp, Arc<Work>)>>,
+	/// Tells whether the thread is running a work item.
+	running: AtomicBool,
+	/// The queue on which the thread waits for work.
+	work_queue: WaitQueue,
+	/// The queue on which processes wait for work to complete.
+	idle_queue: WaitQueue,
+	/// The thread running the work.
+	thread: Mutex<Option<Arc<KThread>>>,
+}
+
+impl Workqueue {
+	/// Creates a workqueue named `name`, along with its thread.
+	pub fn new(name: &'static str) -> EResult<Arc<Self>> {
+		let wq = Arc::new(Self {
+			name,
+			queue: IntMutex::new(Vec::new()),
+			delayed: IntMutex::new(Vec::new()),
+			running: AtomicBool::new(false),
+			work_queue: WaitQueue::new(),
+			idle_queue: WaitQueue::new(),
+			thread: Mutex::new(None),
+		})?;
+		let w = wq.clone();
+		let thread = kthread::spawn(name, move || w.worker())?;
+		*wq.thread.lock() = Some(thread);
+		Ok(wq)
+	}
+
+	/// Queues `work` to be run as soon as possible.
+	///
+	/// If the work is already pending, the function does nothing and returns `false`.
+	///
+	/// This function does not sleep, so it can be called from interrupt context.
+	pub fn queue_work(&self, work: &Arc<Work>) -> AllocResult<bool> {
+		if work.pending.swap(true, AcqRel) {
+			return Ok(false);
+		}
+		if let Err(e) = self.queue.lock().push(work.clone()) {
+			work.pending.store(false, Release);
+			return Err(e);
+		}
+		self.work_queue.wake_next();
+		Ok(true)
+	}
+
+	/// Queues `work` to be run once `delay` nanoseconds have elapsed.
+	///
+	/// If the work is already pending, the function does nothing and returns `false`.
+	///
+	/// This function does not sleep, so it can be called from interrupt context.
+	pub fn queue_delayed_work(&self, work: &Arc<Work>, delay: Timestamp) -> AllocResult<bool> {
+		if delay == 0 {
+			return self.queue_work(work);
+		}
+		if work.pending.swap(true, AcqRel) {
+			return Ok(false);
+		}
+		let due = current_time_ns(Clock::Monotonic) + delay;
+		if let Err(e) = self.delayed.lock().push((due, work.clone())) {
+			work.pending.store(false, Release);
+			return Err(e);
+		}
+		// Let the thread compute its new timeout
+		self.work_queue.wake_next();
+		Ok(true)
+	}
+
+	/// Removes `work` from the queue if it is pending.
+	///
+	/// If the work was pending, the function returns `true`. If it is already running, it is not
+	/// waited for.
+	pub fn cancel_work(&self, work: &Arc<Work>) -> bool {
+		let is_work = |w: &Arc<Work>| Arc::as_ptr(w) == Arc::as_ptr(work);
+		let mut found = false;
+		self.queue.lock().retain(|w| {
+			let m = is_work(w);
+			found |= m;
+			!m
+		});
+		self.delayed.lock().retain(|(_, w)| {
+			let m = is_work(w);
+			found |= m;
+			!m
+		});
+		if found {
+			work.pending.store(false, Release);
+		}
+		found
+	}
+
+	/// Waits until all the work ready to be run has completed. Delayed work which is not due
+	/// yet is not waited for.
+	///
+	/// This function must not be called from the workqueue's thread.
+	pub fn flush(&self) -> EResult<()> {
+		self.idle_queue.wait_until(|| {
+			let idle = self.queue.lock().is_empty() && !self.running.load(Acquire);
+			idle.then_some(())
+		})
+	}
+
+	/// Stops the workqueue's thread, releasing it. Pending work is not run.
+	///
+	/// This function must not be called from the workqueue's thread.
+	pub fn destroy(&self) {
+		let thread = self.thread.lock().take();
+		if let Some(thread) = thread {
+			thread.stop();
+		}
+		let queue = mem::take(&mut *self.queue.lock());
+		let delayed = mem::take(&mut *self.delayed.lock());
+		for work in queue {
+			work.pending.store(false, Release);
+		}
+		for (_, work) in delayed {
+			work.pending.store(false, Release);
+		}
+	}
+
+	/// Moves due delayed work to the queue, then returns the next work to run, if any.
+	///
+	/// If no work is ready, the function also returns the time until the next delayed work is
+	/// due, in nanoseconds.
+	fn next_work(&self) -> (Option<Arc<Work>>, Option<Timestamp>) {
+		let now = current_time_ns(Clock::Monotonic);
+		let mut queue = self.queue.lock();
+		let mut delayed = self.delayed.lock();
+		let mut i = 0;
+		while i < delayed.len() {
+			if delayed[i].0 <= now && queue.push(delayed[i].1.clone()).is_ok() {
+				delayed.remove(i);
+			} else {
+				i += 1;
+			}
+		}
+		if !queue.is_empty() {
+			self.running.store(true, Release);
+			return (Some(queue.remove(0)), None);
+		}
+		let timeout = delayed
+			.iter()
+			.map(|(due, _)| due.saturating_sub(now).max(1))
+			.min();
+		(None, timeout)
+	}
+
+	/// The function run by the workqueue's thread.
+	fn worker(&self) {
+		while !kthread::should_stop() {
+			kthread::parkme();
+			let (work, timeout) = self.next_work();
+			let Some(work) = work else {
+				self.idle_queue.wake_all();
+				// Sleep until work is queued, delayed work is due or the thread is stopped
+				let _ = self.work_queue.wait_until_timeout(
+					|| {
+						let ready = !self.queue.lock().is_empty() || kthread::should_stop();
+						ready.then_some(())
+					},
+					timeout,
+				);
+				continue;
+			};
+			// Clear first, so that the work can queue itself again
+			work.pending.store(false, Release);
+			(work.func)();
+			self.running.store(false, Release);
+		}
+	}
+}
+
+/// The system workqueue, created after the init process.
+static SYSTEM_WQ: OnceInit<Arc<Workqueue>> = unsafe { OnceInit::new() };
+
+/// Queues `work` on the system workqueue, to be run as soon as possible.
+///
+/// See [`Workqueue::queue_work`]. This function must not be called before [`init`].
+pub fn queue_work(work: &Arc<Work>) -> AllocResult<bool> {
+	SYSTEM_WQ.queue_work(work)
+}
+
+/// Queues `work` on the system workqueue, to be run once `delay` nanoseconds have elapsed.
+///
+/// See [`Workqueue::queue_delayed_work`]. This function must not be called before [`init`].
+pub fn queue_delayed_work(work: &Arc<Work>, delay: Timestamp) -> AllocResult<bool> {
+	SYSTEM_WQ.queue_delayed_work(work, delay)
+}
+
+/// Creates the system workqueue.
+pub(crate) fn init() -> EResult<()> {
+	let wq = Workqueue::new("events")?;
+	unsafe {
+		OnceInit::init(&SYSTEM_WQ, wq);
+	}
+	Ok(())
+}
@@ -0,0 +1,124 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Minimal sampling profiler for the kernel.
+//!
+//! When enabled with the `profile=<shift>` command line argument, every timer tick interrupting
+//! kernelspace records the interrupted instruction pointer in a histogram covering the kernel's
+//! code. Each bucket of the histogram covers `2^shift` bytes.
+//!
+//! The histogram is exposed through `/proc/profile`, with the same layout as on Linux: the shift
+//! comes first, followed by the counter of each bucket, all as native-endian 32 bits words.
+//! Addresses can then be resolved using `/proc/kallsyms`.
+
+use crate::{elf, initcall, sync::once::OnceInit};
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use utils::{collections::vec::Vec, errno::AllocResult};
+
+/// The maximum accepted shift.
+pub const MAX_SHIFT: u32 = 31;
+
+/// A histogram of samples.
+#[derive(Debug)]
+pub struct Profile {
+	/// The address of the beginning of the kernel's code.
+	begin: usize,
+	/// The binary logarithm of the number of bytes covered by each bucket.
+	shift: u32,
+	/// The number of samples in each bucket.
+	buckets: Vec<AtomicU32>,
+}
+
+impl Profile {
+	/// Returns the binary logarithm of the number of bytes covered by each bucket.
+	pub fn shift(&self) -> u32 {
+		self.shift
+	}
+
+	/// Returns the address of the beginning of the kernel's code, corresponding to the first
+	/// bucket.
+	pub fn begin(&self) -> usize {
+		self.begin
+	}
+
+	/// Returns the number of samples in each bucket.
+	pub fn buckets(&self) -> &[AtomicU32] {
+		&self.buckets
+	}
+
+	/// Records a sample at the instruction pointer `pc`.
+	///
+	/// Samples outside the kernel's code are accounted in the last bucket.
+	fn sample(&self, pc: usize) {
+		let i = pc.wrapping_sub(self.begin) >> self.shift;
+		let i = i.min(self.buckets.len() - 1);
+		self.buckets[i].fetch_add(1, Relaxed);
+	}
+
+	/// Resets all counters to zero.
+	pub fn reset(&self) {
+		for b in self.buckets.iter() {
+			b.store(0, Relaxed);
+		}
+	}
+}
+
+/// The profiler's histogram, if enabled.
+static PROFILE: OnceInit<Option<Profile>> = unsafe { OnceInit::new() };
+
+/// Initializes the profiler.
+///
+/// If `shift` is `None`, the profiler is disabled.
+fn init(shift: Option<u32>) -> AllocResult<()> {
+	let profile = shift
+		.map(|shift| {
+			// `.text` MUST be present
+			let text = elf::kernel::get_section_by_name(b".text").unwrap();
+			let count = (text.sh_size as usize >> shift) + 1;
+			let mut buckets = Vec::with_capacity(count)?;
+			for _ in 0..count {
+				buckets.push(AtomicU32::new(0))?;
+			}
+			AllocResult::Ok(Profile {
+				begin: text.sh_addr as usize,
+				shift,
+				buckets,
+			})
+		})
+		.transpose()?;
+	unsafe {
+		OnceInit::init(&PROFILE, profile);
+	}
+	Ok(())
+}
+
+initcall!(early, profile, |args| Ok(init(args.get_profile_shift())?));
+
+/// Returns the profiler's histogram, or `None` if the profiler is disabled.
+pub fn get() -> Option<&'static Profile> {
+	PROFILE.as_ref()
+}
+
+/// Records a sample at the instruction pointer `pc`, if the profiler is enabled.
+///
+/// This function is meant to be called on timer ticks interrupting kernelspace.
+pub fn tick(pc: usize) {
+	if let Some(profile) = get() {
+		profile.sample(pc);
+	}
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Softirqs are the bottom halves of interrupt handlers.
+//!
+//! An interrupt handler (the top half) only performs the work that cannot wait, such as
+//! acknowledging the hardware, then raises a softirq to defer the rest. Pending softirqs are run
+//! when returning from the interrupt, with interruptions enabled, so that other interrupts are
+//! not delayed by long handlers.
+//!
+//! If softirqs keep being raised while they run, the remaining work is deferred to the
+//! `ksoftirqd` kernel thread, so that interrupted processes are not starved.
+//!
+//! Tasklets are functions scheduled to run once in softirq context, for code which does not
+//! deserve a softirq of its own.
+
+use crate::{
+	arch::x86::{cli, sti},
+	file::wait_queue::WaitQueue,
+	process::kthread,
+	sync::mutex::IntMutex,
+};
+use core::{
+	mem,
+	sync::atomic::{
+		AtomicBool, AtomicU32,
+		Ordering::{AcqRel, Acquire, Release},
+	},
+};
+use utils::{
+	boxed::Box,
+	collections::vec::Vec,
+	errno::{AllocResult, EResult},
+	ptr::arc::Arc,
+};
+
+/// The maximum number of times pending softirqs are run again on return from an interrupt,
+/// before deferring them to `ksoftirqd`.
+const MAX_RESTART: usize = 10;
+
+/// A softirq, by decreasing order of priority.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum SoftIrq {
+	/// Expiration of timers.
+	Timer = 0,
+	/// Transmission of network packets.
+	NetTx,
+	/// Reception of network packets.
+	NetRx,
+	/// Completion of block device requests.
+	Block,
+	/// Execution of tasklets.
+	Tasklet,
+}
+
+/// The number of softirqs.
+const SOFTIRQ_COUNT: usize = SoftIrq::Tasklet as usize + 1;
+
+/// The handler of a softirq.
+type Handler = fn();
+
+/// The handler of each softirq.
+static HANDLERS: IntMutex<[Option<Handler>; SOFTIRQ_COUNT]> = IntMutex::new([None; SOFTIRQ_COUNT]);
+/// The bitmask of raised softirqs.
+static PENDING: AtomicU32 = AtomicU32::new(0);
+/// Tells whether softirqs are being run.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// The queue on which `ksoftirqd` waits for deferred softirqs.
+static KSOFTIRQD_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Sets the handler of the softirq `irq`, replacing the previous one.
+///
+/// Handlers run with interruptions enabled, but must not sleep.
+pub fn open(irq: SoftIrq, handler: Handler) {
+	HANDLERS.lock()[irq as usize] = Some(handler);
+}
+
+/// Marks the softirq `irq` as pending.
+///
+/// It is run at the latest when returning from the next interrupt.
+#[inline]
+pub fn raise(irq: SoftIrq) {
+	PENDING.fetch_or(1 << irq as usize, Release);
+}
+
+/// Tells whether softirqs are being run.
+///
+/// While this is the case, the current process must not be preempted.
+#[inline]
+pub fn in_softirq() -> bool {
+	RUNNING.load(Acquire)
+}
+
+/// Runs the handlers of pending softirqs, at most `restart` times in a row.
+///
+/// If softirqs are still pending afterwards, the function returns `false`.
+///
+/// The caller must have set [`RUNNING`].
+fn run_pending(mut restart: usize) -> bool {
+	loop {
+		let pending = PENDING.swap(0, AcqRel);
+		if pending == 0 {
+			return true;
+		}
+		for i in 0..SOFTIRQ_COUNT {
+			if pending & (1 << i) == 0 {
+				continue;
+			}
+			// Not holding the lock while running the handler
+			let handler = HANDLERS.lock()[i];
+			if let Some(handler) = handler {
+				handler();
+			}
+		}
+		restart -= 1;
+		if restart == 0 {
+			return PENDING.load(Acquire) == 0;
+		}
+	}
+}
+
+/// Runs pending softirqs with interruptions enabled, on return from an interrupt.
+///
+/// If softirqs are already being run, the function returns immediately: they are run by the
+/// interrupted context.
+///
+/// Interruptions are disabled again when the function returns.
+pub(crate) fn run() {
+	if PENDING.load(Acquire) == 0 || RUNNING.swap(true, AcqRel) {
+		return;
+	}
+	sti();
+	let done = run_pending(MAX_RESTART);
+	cli();
+	RUNNING.store(false, Release);
+	if !done {
+		KSOFTIRQD_QUEUE.wake_next();
+	}
+}
+
+/// The entry point of the `ksoftirqd` kernel thread, running softirqs deferred on return from
+/// interrupts.
+fn ksoftirqd_task() {
+	while !kthread::should_stop() {
+		kthread::parkme();
+		let _ = KSOFTIRQD_QUEUE
+			.wait_until(|| (PENDING.load(Acquire) != 0 || kthread::should_stop()).then_some(()));
+		if RUNNING.swap(true, AcqRel) {
+			continue;
+		}
+		run_pending(1);
+		RUNNING.store(false, Release);
+	}
+}
+
+/// A function scheduled to run once in softirq context.
+///
+/// A tasklet can be scheduled again once it has started running.
+pub struct Tasklet {
+	/// The function to run.
+	func: Box<dyn Fn()>,
+	/// Tells whether the tasklet is scheduled and has not started running yet.
+	scheduled: AtomicBool,
+}
+
+/// Scheduled tasklets, in order.
+static TASKLETS: IntMutex<Vec<Arc<Tasklet>>> = IntMutex::new(Vec::new());
+
+impl Tasklet {
+	/// Creates a tasklet running `func`.
+	pub fn new<F: 'static + Fn()>(func: F) -> AllocResult<Arc<Self>> {
+		Arc::new(Self {
+			func: Box::new(func)?,
+			scheduled: AtomicBool::new(false),
+		})
+	}
+
+	/// Schedules the tasklet to run in softirq context.
+	///
+	/// If the tasklet is already scheduled, the function does nothing and returns `false`.
+	pub fn schedule(this: &Arc<Self>) -> AllocResult<bool> {
+		if this.scheduled.swap(true, AcqRel) {
+			return Ok(false);
+		}
+		if let Err(e) = TASKLETS.lock().push(this.clone()) {
+			this.scheduled.store(false, Release);
+			return Err(e);
+		}
+		raise(SoftIrq::Tasklet);
+		Ok(true)
+	}
+}
+
+/// The handler of [`SoftIrq::Tasklet`].
+fn tasklet_action() {
+	let tasklets = mem::take(&mut *TASKLETS.lock());
+	for tasklet in tasklets {
+		// Clear first, so that the tasklet can schedule itself again
+		tasklet.scheduled.store(false, Release);
+		(tasklet.func)();
+	}
+}
+
+/// Initializes softirqs.
+pub(crate) fn init() -> EResult<()> {
+	open(SoftIrq::Tasklet, tasklet_action);
+	kthread::spawn("ksoftirqd", ksoftirqd_task)?;
+	Ok(())
+}
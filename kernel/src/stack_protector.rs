@@ -0,0 +1,76 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Stack smashing protection.
+//!
+//! The kernel is compiled with `-Zstack-protector=strong`: functions with local buffers place a
+//! canary between these buffers and their return address, and check it has not been
+//! overwritten before returning. On mismatch, [`__stack_chk_fail`] is called.
+//!
+//! The compiler reads the expected value of the canary from the global [`__stack_chk_guard`].
+//! Since this global is shared by all CPU cores, the canary cannot be changed on context switch:
+//! a function running on another core would fail its check. It is thus chosen once at boot, and
+//! shared by all processes.
+//!
+//! A per-core (and thus per-process) canary would require the compiler to read it relative to the
+//! `gs` segment, from the core-local storage. rustc has no option selecting the guard's location,
+//! and LLVM only uses a segment-relative guard for Linux targets, at a fixed offset in `fs`
+//! (`gs` requires the kernel code model, which the kernel cannot use since it is not linked in
+//! the last 2 GiB of the address space).
+
+use crate::{arch::x86::rdtsc, crypto::rand, memory::user::UserSlice};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// Mask applied to canaries.
+///
+/// Like Linux, the lowest byte is always zero, so that a string operation overrunning a buffer
+/// cannot read nor write the canary.
+const CANARY_MASK: usize = !0xff;
+
+/// The canary, read by the code generated by the compiler.
+#[allow(non_upper_case_globals)]
+#[unsafe(no_mangle)]
+pub static __stack_chk_guard: AtomicUsize = AtomicUsize::new(0x595e9fbd94fda700u64 as usize);
+
+/// Called by the code generated by the compiler when a canary has been overwritten.
+#[unsafe(no_mangle)]
+pub extern "C" fn __stack_chk_fail() -> ! {
+	panic!("stack smashing detected");
+}
+
+/// Returns a new random canary.
+///
+/// This function must not be inlined, since it has a buffer on its stack: if a caller changes
+/// the canary in use, the check at the end of the caller would fail.
+#[inline(never)]
+fn random_canary() -> usize {
+	let mut buf = [0u8; size_of::<usize>()];
+	// If the entropy pool is not initialized yet, the buffer is left untouched
+	let _ = rand::getrandom(UserSlice::from_slice_mut(&mut buf), 0);
+	let tsc = (rdtsc() as usize).wrapping_mul(0x9e3779b97f4a7c15u64 as usize);
+	(usize::from_ne_bytes(buf) ^ tsc) & CANARY_MASK
+}
+
+/// Initializes the canary, before the first process is run and before other cores are started.
+///
+/// This function must be called from a function which never returns, since the canary on its
+/// stack would not match anymore.
+#[inline(always)]
+pub fn init() {
+	__stack_chk_guard.store(random_canary(), Relaxed);
+}
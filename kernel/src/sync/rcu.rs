@@ -107,6 +107,12 @@ impl<T> RcuOptionArc<T> {
 	}
 }
 
+impl<T> Default for RcuOptionArc<T> {
+	fn default() -> Self {
+		Self::new(None)
+	}
+}
+
 unsafe impl<T> Send for RcuOptionArc<T> {}
 
 unsafe impl<T> Sync for RcuOptionArc<T> {}
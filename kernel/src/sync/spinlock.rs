@@ -18,6 +18,7 @@
 
 //! Spinlock implementation.
 
+use crate::arch::x86::smp;
 use core::{
 	hint,
 	sync::{atomic, atomic::AtomicBool},
@@ -43,6 +44,8 @@ impl Spinlock {
 	#[inline(always)]
 	pub fn lock(&mut self) {
 		while self.0.swap(true, atomic::Ordering::Acquire) {
+			// The holder may be waiting for this core to flush its TLB
+			smp::handle_shootdown();
 			hint::spin_loop();
 		}
 	}
@@ -24,45 +24,15 @@ use crate::{
 	memory::user::UserSlice,
 	sync::mutex::Mutex,
 	syscall::Args,
+	uapi::dirent::{LinuxDirent, LinuxDirent64},
 };
 use core::{
-	ffi::{c_int, c_uint},
+	ffi::{c_int, c_uint, c_ulong},
 	mem::{offset_of, size_of},
 	sync::atomic,
 };
 use utils::{bytes::as_bytes, errno, errno::EResult, ptr::arc::Arc};
 
-/// A Linux directory entry.
-#[repr(C)]
-struct LinuxDirent {
-	/// Inode number.
-	d_ino: u32,
-	/// Offset to the next entry.
-	d_off: u32,
-	/// Length of this entry.
-	d_reclen: u16,
-	/// Filename (nul-terminated).
-	///
-	/// The filename is immediately followed by a zero padding byte, then a byte
-	/// indicating the type of the entry.
-	d_name: [u8; 0],
-}
-
-/// A Linux directory entry with 64 bits offsets.
-#[repr(C)]
-struct LinuxDirent64 {
-	/// 64-bit inode number.
-	d_ino: u64,
-	/// 64-bit offset to next entry.
-	d_off: u64,
-	/// Size of this dirent.
-	d_reclen: u16,
-	/// File type.
-	d_type: u8,
-	/// Filename (nul-terminated).
-	d_name: [u8; 0],
-}
-
 fn do_getdents<F: FnMut(&DirEntry) -> EResult<bool>>(
 	fd: c_int,
 	fds: Arc<Mutex<FileDescriptorTable>>,
@@ -95,7 +65,11 @@ pub fn getdents(
 	let mut buf_off = 0;
 	do_getdents(fd, fds, |entry| {
 		// Skip entries whose inode cannot fit in the structure
-		if entry.inode > u32::MAX as _ {
+		if entry
+			.inode
+			.checked_shr(c_ulong::BITS)
+			.is_some_and(|high| high != 0)
+		{
 			return Ok(true);
 		}
 		let reclen = (size_of::<LinuxDirent>() + entry.name.len() + 2)
@@ -124,11 +98,13 @@ pub fn getdents(
 		dirp.copy_to_user(buf_off, as_bytes(&ent))?;
 		// Copy file name
 		dirp.copy_to_user(buf_off + offset_of!(LinuxDirent, d_name), entry.name)?;
-		// Write nul byte and entry type
+		// Write nul byte
 		dirp.copy_to_user(
 			buf_off + offset_of!(LinuxDirent, d_name) + entry.name.len(),
-			&[b'\0', d_type],
+			b"\0",
 		)?;
+		// The entry type is the last byte of the record
+		dirp.copy_to_user(buf_off + reclen - 1, &[d_type])?;
 		buf_off += reclen;
 		Ok(true)
 	})?;
@@ -0,0 +1,119 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The epoll system calls allow to wait for I/O events on a large number of file descriptors.
+
+use crate::{
+	file,
+	file::{
+		File,
+		epoll::{EPOLL_CTL_DEL, Epoll, EpollEvent},
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+	},
+	memory::user::{UserPtr, UserSlice},
+	sync::mutex::Mutex,
+	syscall::{Args, select::wait_events},
+	time::unit::Timestamp,
+};
+use core::{ffi::c_int, hint::unlikely};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const EPOLL_CLOEXEC: c_int = file::O_CLOEXEC;
+
+fn do_epoll_create(flags: c_int, fds: Arc<Mutex<FileDescriptorTable>>) -> EResult<usize> {
+	if unlikely(flags & !EPOLL_CLOEXEC != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let ops = Arc::new(Epoll::default())?;
+	let file = File::open_floating(ops, file::O_RDWR)?;
+	let fd_flags = if flags & EPOLL_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.lock().create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
+
+pub fn epoll_create(
+	Args(size): Args<c_int>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	// The size is ignored, but must be positive for compatibility
+	if unlikely(size <= 0) {
+		return Err(errno!(EINVAL));
+	}
+	do_epoll_create(0, fds)
+}
+
+pub fn epoll_create1(
+	Args(flags): Args<c_int>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_epoll_create(flags, fds)
+}
+
+pub fn epoll_ctl(
+	Args((epfd, op, fd, event)): Args<(c_int, c_int, c_int, UserPtr<EpollEvent>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let (ep_file, file) = {
+		let fds = fds.lock();
+		let ep_file = fds.get_fd(epfd)?.get_file().clone();
+		let file = fds.get_fd(fd)?.get_file().clone();
+		(ep_file, file)
+	};
+	let ep: &Epoll = ep_file.get_buffer().ok_or_else(|| errno!(EINVAL))?;
+	if unlikely(fd == epfd) {
+		return Err(errno!(EINVAL));
+	}
+	// The file must support polling
+	if let Err(e) = file.ops.poll(&file, 0) {
+		if e.as_int() == errno::EINVAL {
+			return Err(errno!(EPERM));
+		}
+		return Err(e);
+	}
+	let event = if op == EPOLL_CTL_DEL {
+		EpollEvent::default()
+	} else {
+		event.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?
+	};
+	ep.ctl(op, fd, event)?;
+	Ok(0)
+}
+
+pub fn epoll_wait(
+	Args((epfd, events, maxevents, timeout)): Args<(c_int, *mut EpollEvent, c_int, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	if unlikely(maxevents <= 0) {
+		return Err(errno!(EINVAL));
+	}
+	let events = UserSlice::from_user(events, maxevents as _)?;
+	let ep_file = fds.lock().get_fd(epfd)?.get_file().clone();
+	let ep: &Epoll = ep_file.get_buffer().ok_or_else(|| errno!(EINVAL))?;
+	// The timeout. `None` means no timeout
+	let timeout = (timeout >= 0).then(|| timeout as Timestamp * 1_000_000);
+	wait_events(timeout, |wait| {
+		let ready = ep.collect(&fds.lock(), maxevents as _, wait)?;
+		events.copy_to_user(0, &ready)?;
+		Ok(ready.len())
+	})
+}
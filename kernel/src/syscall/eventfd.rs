@@ -0,0 +1,75 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `eventfd` system calls create a file descriptor used for event notification.
+
+use crate::{
+	file,
+	file::{
+		File,
+		buffer::eventfd::EventFd,
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+	},
+	sync::mutex::Mutex,
+	syscall::Args,
+};
+use core::{
+	ffi::{c_int, c_uint},
+	hint::unlikely,
+};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: reads decrement the counter by one instead of resetting it.
+const EFD_SEMAPHORE: c_int = 1;
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const EFD_CLOEXEC: c_int = file::O_CLOEXEC;
+/// Flag: set the non-blocking flag on the new open file description.
+const EFD_NONBLOCK: c_int = file::O_NONBLOCK;
+
+fn do_eventfd(
+	initval: c_uint,
+	flags: c_int,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	if unlikely(flags & !(EFD_SEMAPHORE | EFD_CLOEXEC | EFD_NONBLOCK) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let ops = Arc::new(EventFd::new(initval as _, flags & EFD_SEMAPHORE != 0))?;
+	let file = File::open_floating(ops, file::O_RDWR | (flags & EFD_NONBLOCK))?;
+	let fd_flags = if flags & EFD_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.lock().create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
+
+pub fn eventfd(
+	Args(initval): Args<c_uint>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_eventfd(initval, 0, fds)
+}
+
+pub fn eventfd2(
+	Args((initval, flags)): Args<(c_uint, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_eventfd(initval, flags, fds)
+}
@@ -19,14 +19,29 @@
 //! The `fcntl` syscall call allows to manipulate a file descriptor.
 
 use crate::{
+	arch::x86::idt::IntFrame,
 	file::{
+		File, O_RDONLY,
 		fd::{FileDescriptorTable, NewFDConstraint},
+		fs::tmp,
+		lock::{CompatFlock, CompatFlock64, Flock, Lock, LockOwner, LockType},
 		pipe::PipeBuffer,
 	},
+	memory::user::UserPtr,
+	process::Process,
 	sync::mutex::Mutex,
-	syscall::Args,
+	syscall::{
+		Args, FromSyscallArg,
+		fd::{SEEK_CUR, SEEK_END, SEEK_SET},
+	},
+	uapi::UserRepr,
+};
+use core::{
+	ffi::{c_int, c_void},
+	hint::unlikely,
+	ops::Range,
+	sync::atomic::Ordering::Acquire,
 };
-use core::ffi::{c_int, c_void};
 use utils::{errno, errno::EResult, ptr::arc::Arc};
 
 /// Duplicate the file descriptor using the lowest numbered available file descriptor greater than
@@ -40,11 +55,11 @@ const F_SETFD: c_int = 2;
 const F_GETFL: c_int = 3;
 /// Set the file status flag.
 const F_SETFL: c_int = 4;
-/// TODO doc
+/// Return a lock conflicting with the given one, if any.
 const F_GETLK: c_int = 5;
-/// TODO doc
+/// Acquire or release a lock, failing if a conflicting lock is held.
 const F_SETLK: c_int = 6;
-/// TODO doc
+/// Like `F_SETLK`, but wait for conflicting locks to be released.
 const F_SETLKW: c_int = 7;
 /// Set the process ID or process group ID that will receive `SIGIO` and `SIGURG` signals for
 /// events on the file descriptor.
@@ -56,22 +71,22 @@ const F_GETOWN: c_int = 9;
 const F_SETSIG: c_int = 10;
 /// Return the signal sent when input or output becomes possible.
 const F_GETSIG: c_int = 11;
-/// TODO doc
+/// Like `F_GETLK`, with 64 bit offsets on 32 bit userspace.
 const F_GETLK64: c_int = 12;
-/// TODO doc
+/// Like `F_SETLK`, with 64 bit offsets on 32 bit userspace.
 const F_SETLK64: c_int = 13;
-/// TODO doc
+/// Like `F_SETLKW`, with 64 bit offsets on 32 bit userspace.
 const F_SETLKW64: c_int = 14;
 /// Similar to `F_SETOWN`, except it allows to specifiy a thread ID using the `f_owner_ex`
 /// structure.
 const F_SETOWN_EX: c_int = 15;
 /// Return the setting defined by `F_SETOWN_EX`.
 const F_GETOWN_EX: c_int = 16;
-/// TODO doc
+/// Like `F_GETLK`, for locks owned by the open file description.
 const F_OFD_GETLK: c_int = 36;
-/// TODO doc
+/// Like `F_SETLK`, for locks owned by the open file description.
 const F_OFD_SETLK: c_int = 37;
-/// TODO doc
+/// Like `F_SETLKW`, for locks owned by the open file description.
 const F_OFD_SETLKW: c_int = 38;
 /// Set or remove a file lease.
 const F_SETLEASE: c_int = 1024;
@@ -105,14 +120,11 @@ const F_GET_FILE_RW_HINT: c_int = 1037;
 /// descriptor.
 const F_SET_FILE_RW_HINT: c_int = 1038;
 
-/// TODO doc
-const F_SEAL_FUTURE_WRITE: c_int = 16;
-
-/// Take out a read lease.
+/// Take out a read lease, or a read lock.
 const F_RDLCK: c_int = 0;
-/// Take out a write lease.
+/// Take out a write lease, or a write lock.
 const F_WRLCK: c_int = 1;
-/// Remove our lease from the file.
+/// Remove our lease or lock from the file.
 const F_UNLCK: c_int = 2;
 
 /// Send the signal to the process group whose ID is specified.
@@ -122,23 +134,132 @@ const F_OWNER_PID: c_int = 1;
 /// Send the signal to the thread whose thread ID is specified.
 const F_OWNER_TID: c_int = 0;
 
-/// If this seal is set, the size of the file in question cannot be increased.
-const F_SEAL_GROW: c_int = 4;
-/// If this seal is set, any further call to `fcntl` with `F_ADD_SEALS` fails.
-const F_SEAL_SEAL: c_int = 1;
-/// If this seal is set, the size of the file in question cannot be reduced.
-const F_SEAL_SHRINK: c_int = 2;
-/// If this seal is set, you cannot modify the contents of the file.
-const F_SEAL_WRITE: c_int = 8;
+/// Returns the range of bytes of `file` described by `fl`.
+///
+/// A range extending to the end of the file, whatever its size, ends at [`u64::MAX`].
+fn lock_range(file: &File, fl: &Flock) -> EResult<Range<u64>> {
+	let base = match fl.l_whence as u32 {
+		SEEK_SET => 0,
+		SEEK_CUR => file.off.load(Acquire),
+		SEEK_END => file.stat()?.size,
+		_ => return Err(errno!(EINVAL)),
+	};
+	let base: i64 = base.try_into().map_err(|_| errno!(EOVERFLOW))?;
+	let start = base
+		.checked_add(fl.l_start)
+		.ok_or_else(|| errno!(EOVERFLOW))?;
+	let (start, end) = match fl.l_len {
+		0 => (start, None),
+		// A negative length designates the bytes before `start`
+		len @ ..0 => (
+			start.checked_add(len).ok_or_else(|| errno!(EINVAL))?,
+			Some(start),
+		),
+		len => (
+			start,
+			Some(start.checked_add(len).ok_or_else(|| errno!(EOVERFLOW))?),
+		),
+	};
+	if unlikely(start < 0) {
+		return Err(errno!(EINVAL));
+	}
+	let end = end.map(|end| end as u64).unwrap_or(u64::MAX);
+	Ok(start as u64..end)
+}
+
+/// Performs a lock command on the file descriptor `fd`, with the `flock` structure at `arg` in
+/// the userspace representation `F`.
+///
+/// Arguments:
+/// - `cmd` is `F_GETLK`, `F_SETLK` or `F_SETLKW`
+/// - `ofd` tells whether the lock is owned by the open file description instead of the process
+fn do_lock<F: UserRepr<Flock>>(
+	fd: c_int,
+	cmd: c_int,
+	arg: *mut c_void,
+	ofd: bool,
+	fds: &FileDescriptorTable,
+) -> EResult<usize> {
+	let file = fds.get_fd(fd)?.get_file();
+	let node = file.node().ok_or_else(|| errno!(EINVAL))?;
+	let arg = UserPtr::<F>::from_ptr(arg as usize);
+	let mut fl: Flock = arg.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?.into();
+	if unlikely(ofd && fl.l_pid != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let range = lock_range(file, &fl)?;
+	let lock_type = match fl.l_type as c_int {
+		F_RDLCK => Some(LockType::Read),
+		F_WRLCK => Some(LockType::Write),
+		F_UNLCK => None,
+		_ => return Err(errno!(EINVAL)),
+	};
+	let pid = Process::current().get_pid();
+	let owner = if ofd {
+		LockOwner::File(Arc::as_ptr(file).addr())
+	} else {
+		LockOwner::Process(pid)
+	};
+	match (cmd, lock_type) {
+		(F_GETLK, Some(lock_type)) => {
+			let lock = Lock {
+				owner,
+				lock_type,
+				pid,
+			};
+			match node.locks.get_conflict(range, &lock) {
+				Some((range, lock)) => {
+					fl.l_type = match lock.lock_type {
+						LockType::Read => F_RDLCK as _,
+						LockType::Write => F_WRLCK as _,
+					};
+					fl.l_whence = SEEK_SET as _;
+					fl.l_start = range.start as _;
+					fl.l_len = match range.end {
+						u64::MAX => 0,
+						end => (end - range.start) as _,
+					};
+					fl.l_pid = match lock.owner {
+						LockOwner::Process(_) => lock.pid as _,
+						LockOwner::File(_) => -1,
+					};
+				}
+				None => fl.l_type = F_UNLCK as _,
+			}
+			arg.copy_to_user(&fl.into())?;
+		}
+		(F_SETLK | F_SETLKW, Some(lock_type)) => {
+			let allowed = match lock_type {
+				LockType::Read => file.can_read(),
+				LockType::Write => file.can_write(),
+			};
+			if unlikely(!allowed) {
+				return Err(errno!(EBADF));
+			}
+			let lock = Lock {
+				owner,
+				lock_type,
+				pid,
+			};
+			node.locks.lock(range, lock, cmd == F_SETLKW)?;
+		}
+		(F_SETLK | F_SETLKW, None) => node.locks.unlock(range, owner)?,
+		_ => return Err(errno!(EINVAL)),
+	}
+	Ok(0)
+}
 
 /// Performs the fcntl system call.
 ///
-/// `fcntl64` tells whether this is the `fcntl64` system call.
+/// Arguments:
+/// - `fcntl64` tells whether this is the `fcntl64` system call
+/// - `compat` tells whether the caller is a 32 bit process
 pub fn do_fcntl(
 	fd: c_int,
 	cmd: c_int,
 	arg: *mut c_void,
-	_fcntl64: bool,
+	fcntl64: bool,
+	compat: bool,
 	fds: &mut FileDescriptorTable,
 ) -> EResult<usize> {
 	match cmd {
@@ -160,21 +281,27 @@ pub fn do_fcntl(
 			fds.get_fd(fd)?.get_file().set_flags(arg as _, true);
 			Ok(0)
 		}
-		F_GETLK => todo!(),
-		F_SETLK => todo!(),
-		F_SETLKW => todo!(),
+		F_GETLK | F_SETLK | F_SETLKW if compat => do_lock::<CompatFlock>(fd, cmd, arg, false, fds),
+		F_GETLK | F_SETLK | F_SETLKW => do_lock::<Flock>(fd, cmd, arg, false, fds),
 		F_SETOWN => todo!(),
 		F_GETOWN => todo!(),
 		F_SETSIG => todo!(),
 		F_GETSIG => todo!(),
-		F_GETLK64 => todo!(),
-		F_SETLK64 => todo!(),
-		F_SETLKW64 => todo!(),
+		// 64 bit offsets are only available through `fcntl64` on 32 bit userspace
+		F_GETLK64 | F_SETLK64 | F_SETLKW64 if compat && fcntl64 => {
+			let cmd = cmd - F_GETLK64 + F_GETLK;
+			do_lock::<CompatFlock64>(fd, cmd, arg, false, fds)
+		}
 		F_SETOWN_EX => todo!(),
 		F_GETOWN_EX => todo!(),
-		F_OFD_GETLK => todo!(),
-		F_OFD_SETLK => todo!(),
-		F_OFD_SETLKW => todo!(),
+		F_OFD_GETLK | F_OFD_SETLK | F_OFD_SETLKW if !compat || fcntl64 => {
+			let cmd = cmd - F_OFD_GETLK + F_GETLK;
+			if compat {
+				do_lock::<CompatFlock64>(fd, cmd, arg, true, fds)
+			} else {
+				do_lock::<Flock>(fd, cmd, arg, true, fds)
+			}
+		}
 		F_SETLEASE => todo!(),
 		F_GETLEASE => todo!(),
 		F_NOTIFY => todo!(),
@@ -190,8 +317,21 @@ pub fn do_fcntl(
 				_ => Ok(0),
 			}
 		}
-		F_ADD_SEALS => todo!(),
-		F_GET_SEALS => todo!(),
+		F_ADD_SEALS => {
+			let file = fds.get_fd(fd)?.get_file();
+			if file.get_flags() & 0b11 == O_RDONLY {
+				return Err(errno!(EPERM));
+			}
+			let node = file.node().ok_or_else(|| errno!(EINVAL))?;
+			tmp::add_seals(node, arg as _)?;
+			Ok(0)
+		}
+		F_GET_SEALS => {
+			let file = fds.get_fd(fd)?.get_file();
+			let node = file.node().ok_or_else(|| errno!(EINVAL))?;
+			let seals = tmp::get_seals(node).ok_or_else(|| errno!(EINVAL))?;
+			Ok(seals as _)
+		}
 		F_GET_RW_HINT => todo!(),
 		F_SET_RW_HINT => todo!(),
 		F_GET_FILE_RW_HINT => todo!(),
@@ -203,13 +343,15 @@ pub fn do_fcntl(
 pub fn fcntl(
 	Args((fd, cmd, arg)): Args<(c_int, c_int, *mut c_void)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
+	frame: &mut IntFrame,
 ) -> EResult<usize> {
-	do_fcntl(fd, cmd, arg, false, &mut fds.lock())
+	do_fcntl(fd, cmd, arg, false, frame.is_compat(), &mut fds.lock())
 }
 
 pub fn fcntl64(
 	Args((fd, cmd, arg)): Args<(c_int, c_int, *mut c_void)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
+	frame: &mut IntFrame,
 ) -> EResult<usize> {
-	do_fcntl(fd, cmd, arg, true, &mut fds.lock())
+	do_fcntl(fd, cmd, arg, true, frame.is_compat(), &mut fds.lock())
 }
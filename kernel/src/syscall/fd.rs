@@ -20,12 +20,16 @@
 
 use crate::{
 	file::{
-		FileType,
+		File, FileType,
 		fd::{FileDescriptorTable, NewFDConstraint},
 	},
 	memory::user::{UserIOVec, UserPtr, UserSlice},
+	process::{Process, rusage},
 	sync::mutex::Mutex,
-	syscall::Args,
+	syscall::{
+		Args,
+		select::{POLLIN, POLLOUT},
+	},
 };
 use core::{
 	cmp::min,
@@ -36,11 +40,51 @@ use core::{
 use utils::{errno, errno::EResult, limits::IOV_MAX, ptr::arc::Arc};
 
 /// Sets the offset from the given value.
-const SEEK_SET: u32 = 0;
+pub(super) const SEEK_SET: u32 = 0;
 /// Sets the offset relative to the current offset.
-const SEEK_CUR: u32 = 1;
+pub(super) const SEEK_CUR: u32 = 1;
 /// Sets the offset relative to the end of the file.
-const SEEK_END: u32 = 2;
+pub(super) const SEEK_END: u32 = 2;
+
+/// `preadv2`/`pwritev2` flag: high priority request. Ignored.
+const RWF_HIPRI: i32 = 0x1;
+/// `pwritev2` flag: synchronize the written data to the disk, as `fdatasync` does.
+const RWF_DSYNC: i32 = 0x2;
+/// `pwritev2` flag: synchronize the written data and metadata to the disk, as `fsync` does.
+const RWF_SYNC: i32 = 0x4;
+/// `preadv2`/`pwritev2` flag: fail with [`errno::EAGAIN`] instead of blocking.
+const RWF_NOWAIT: i32 = 0x8;
+
+/// `close_range` flag: unshare the file descriptors table before closing file descriptors.
+const CLOSE_RANGE_UNSHARE: c_uint = 0x2;
+/// `close_range` flag: set the `FD_CLOEXEC` flag instead of closing file descriptors.
+const CLOSE_RANGE_CLOEXEC: c_uint = 0x4;
+
+/// Checks the flags given to `preadv2` or `pwritev2`.
+fn check_rwf_flags(flags: Option<i32>) -> EResult<i32> {
+	let flags = flags.unwrap_or(0);
+	if unlikely(flags & !(RWF_HIPRI | RWF_DSYNC | RWF_SYNC | RWF_NOWAIT) != 0) {
+		return Err(errno!(EOPNOTSUPP));
+	}
+	Ok(flags)
+}
+
+/// Tells whether an I/O operation waiting for the events in `mask` would block on `file`.
+///
+/// Files that cannot be polled are assumed not to block.
+// TODO for regular files, fail if the data is not in cache
+fn would_block(file: &File, mask: u32) -> bool {
+	file.ops
+		.poll(file, mask)
+		.is_ok_and(|events| events & mask == 0)
+}
+
+/// Builds a 64-bit file offset from the two halves given to a system call.
+///
+/// On 64-bit userspace, `low` already holds the whole offset, and `high` holds its upper half.
+fn offset_from_halves(low: usize, high: usize) -> i64 {
+	(((high as u64) << 32) | (low as u32 as u64)) as i64
+}
 
 pub fn read(
 	Args((fd, buf, count)): Args<(c_int, *mut u8, usize)>,
@@ -59,6 +103,7 @@ pub fn read(
 	// Read
 	let off = file.off.load(atomic::Ordering::Acquire);
 	let len = file.ops.read(&file, off, buf)?;
+	rusage::account_read(len);
 	// Update offset
 	let new_off = off.saturating_add(len as u64);
 	file.off.store(new_off, atomic::Ordering::Release);
@@ -78,8 +123,8 @@ pub fn do_readv(
 	fd: c_int,
 	iov: UserIOVec,
 	iovcnt: c_int,
-	offset: Option<isize>,
-	_flags: Option<i32>,
+	offset: Option<i64>,
+	flags: Option<i32>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
 	// Validation
@@ -91,7 +136,7 @@ pub fn do_readv(
 		None | Some(-1) => None,
 		Some(..-1) => return Err(errno!(EINVAL)),
 	};
-	// TODO Handle flags
+	let flags = check_rwf_flags(flags)?;
 	let file = fds.lock().get_fd(fd)?.get_file().clone();
 	if file.get_type()? == FileType::Link {
 		return Err(errno!(EINVAL));
@@ -103,6 +148,12 @@ pub fn do_readv(
 		// The size to read. This is limited to avoid an overflow on the total length
 		let max_len = min(i.iov_len, i32::MAX as usize - off);
 		let buf = UserSlice::<u8>::from_user(i.iov_base, max_len)?;
+		if flags & RWF_NOWAIT != 0 && would_block(&file, POLLIN) {
+			if off == 0 {
+				return Err(errno!(EAGAIN));
+			}
+			break;
+		}
 		// Read
 		let len = if let Some(offset) = offset {
 			let file_off = offset + off as u64;
@@ -120,6 +171,7 @@ pub fn do_readv(
 			break;
 		}
 	}
+	rusage::account_read(off);
 	Ok(off)
 }
 
@@ -135,20 +187,28 @@ pub fn preadv(
 		c_int,
 		UserIOVec,
 		c_int,
-		isize,
-		isize,
+		usize,
+		usize,
 	)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	#[allow(arithmetic_overflow)]
-	let offset = offset_low | (offset_high << 32);
+	let offset = offset_from_halves(offset_low, offset_high);
 	do_readv(fd, iov, iovcnt, Some(offset), None, fds)
 }
 
+#[allow(clippy::type_complexity)]
 pub fn preadv2(
-	Args((fd, iov, iovcnt, offset, flags)): Args<(c_int, UserIOVec, c_int, isize, c_int)>,
+	Args((fd, iov, iovcnt, offset_low, offset_high, flags)): Args<(
+		c_int,
+		UserIOVec,
+		c_int,
+		usize,
+		usize,
+		c_int,
+	)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
+	let offset = offset_from_halves(offset_low, offset_high);
 	do_readv(fd, iov, iovcnt, Some(offset), Some(flags), fds)
 }
 
@@ -169,6 +229,7 @@ pub fn write(
 	// Write
 	let off = file.off.load(atomic::Ordering::Acquire);
 	let len = file.ops.write(&file, off, buf)?;
+	rusage::account_write(len);
 	// Update offset
 	let new_off = off.saturating_add(len as u64);
 	file.off.store(new_off, atomic::Ordering::Release);
@@ -188,8 +249,8 @@ pub fn do_writev(
 	fd: i32,
 	iov: UserIOVec,
 	iovcnt: i32,
-	offset: Option<isize>,
-	_flags: Option<i32>,
+	offset: Option<i64>,
+	flags: Option<i32>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
 	// Validation
@@ -201,6 +262,7 @@ pub fn do_writev(
 		None | Some(-1) => None,
 		Some(..-1) => return Err(errno!(EINVAL)),
 	};
+	let flags = check_rwf_flags(flags)?;
 	// Get file
 	let file = fds.lock().get_fd(fd)?.get_file().clone();
 	if file.get_type()? == FileType::Link {
@@ -213,6 +275,12 @@ pub fn do_writev(
 		// The size to write. This is limited to avoid an overflow on the total length
 		let len = min(i.iov_len, i32::MAX as usize - off);
 		let buf = UserSlice::<u8>::from_user(i.iov_base, len)?;
+		if flags & RWF_NOWAIT != 0 && would_block(&file, POLLOUT) {
+			if off == 0 {
+				return Err(errno!(EAGAIN));
+			}
+			break;
+		}
 		let len = if let Some(offset) = offset {
 			let file_off = offset + off as u64;
 			file.ops.write(&file, file_off, buf)?
@@ -226,6 +294,13 @@ pub fn do_writev(
 		};
 		off += len;
 	}
+	rusage::account_write(off);
+	// Write-through
+	if flags & (RWF_DSYNC | RWF_SYNC) != 0
+		&& let Some(node) = file.node()
+	{
+		node.sync(flags & RWF_SYNC != 0)?;
+	}
 	Ok(off)
 }
 
@@ -241,46 +316,77 @@ pub fn pwritev(
 		c_int,
 		UserIOVec,
 		c_int,
-		isize,
-		isize,
+		usize,
+		usize,
 	)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	#[allow(arithmetic_overflow)]
-	let offset = offset_low | (offset_high << 32);
+	let offset = offset_from_halves(offset_low, offset_high);
 	do_writev(fd, iov, iovcnt, Some(offset), None, fds)
 }
 
+#[allow(clippy::type_complexity)]
 pub fn pwritev2(
-	Args((fd, iov, iovcnt, offset, flags)): Args<(c_int, UserIOVec, c_int, isize, c_int)>,
+	Args((fd, iov, iovcnt, offset_low, offset_high, flags)): Args<(
+		c_int,
+		UserIOVec,
+		c_int,
+		usize,
+		usize,
+		c_int,
+	)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
+	let offset = offset_from_halves(offset_low, offset_high);
 	do_writev(fd, iov, iovcnt, Some(offset), Some(flags), fds)
 }
 
+/// Performs the `lseek` operation.
+///
+/// Arguments:
+/// - `fd` is the file descriptor
+/// - `offset` is the offset, relative to `whence`
+/// - `whence` is the base of the offset
+/// - `max` is the maximum resulting offset that can be returned to the caller
+///
+/// The function returns the new offset.
+///
+/// If the resulting offset is negative, the function returns [`errno::EINVAL`]. If it is larger
+/// than `max`, the offset is not changed and the function returns [`errno::EOVERFLOW`].
 fn do_lseek(
 	fds_mutex: Arc<Mutex<FileDescriptorTable>>,
 	fd: c_uint,
-	offset: u64,
-	result: Option<UserPtr<u64>>,
+	offset: i64,
 	whence: c_uint,
-) -> EResult<usize> {
+	max: i64,
+) -> EResult<u64> {
 	let fds = fds_mutex.lock();
 	let file = fds.get_fd(fd as _)?.get_file();
 	// Compute the offset
 	let base = match whence {
 		SEEK_SET => 0,
 		SEEK_CUR => file.off.load(atomic::Ordering::Acquire),
-		SEEK_END => file.stat()?.size,
+		// The size of a block device is the size of the device itself
+		SEEK_END => match file.as_block_device() {
+			Some(dev) => dev
+				.ops
+				.block_size()
+				.get()
+				.saturating_mul(dev.ops.blocks_count()),
+			None => file.stat()?.size,
+		},
 		_ => return Err(errno!(EINVAL)),
 	};
+	let base: i64 = base.try_into().map_err(|_| errno!(EOVERFLOW))?;
 	let offset = base.checked_add(offset).ok_or_else(|| errno!(EOVERFLOW))?;
-	if let Some(result) = result {
-		// Write the result to the userspace
-		result.copy_to_user(&offset)?;
+	if unlikely(offset < 0) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(offset > max) {
+		return Err(errno!(EOVERFLOW));
 	}
 	// Set the new offset
-	file.off.store(offset, atomic::Ordering::Release);
+	file.off.store(offset as _, atomic::Ordering::Release);
 	Ok(offset as _)
 }
 
@@ -294,16 +400,28 @@ pub fn _llseek(
 	)>,
 	fds_mutex: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	let offset = ((offset_high as u64) << 32) | (offset_low as u64);
-	do_lseek(fds_mutex, fd, offset, Some(result), whence)?;
+	let offset = (((offset_high as u64) << 32) | (offset_low as u64)) as i64;
+	let offset = do_lseek(fds_mutex, fd, offset, whence, i64::MAX)?;
+	// Write the result to the userspace
+	result.copy_to_user(&offset)?;
 	Ok(0)
 }
 
+pub fn compat_lseek(
+	Args((fd, offset, whence)): Args<(c_uint, i32, c_uint)>,
+	fds_mutex: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	// The resulting offset must fit in the 32 bit return value
+	let offset = do_lseek(fds_mutex, fd, offset as _, whence, i32::MAX as _)?;
+	Ok(offset as _)
+}
+
 pub fn lseek(
-	Args((fd, offset, whence)): Args<(c_uint, u64, c_uint)>,
+	Args((fd, offset, whence)): Args<(c_uint, i64, c_uint)>,
 	fds_mutex: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	do_lseek(fds_mutex, fd, offset, None, whence)
+	let offset = do_lseek(fds_mutex, fd, offset, whence, i64::MAX)?;
+	Ok(offset as _)
 }
 
 pub fn dup(Args(oldfd): Args<c_int>, fds: Arc<Mutex<FileDescriptorTable>>) -> EResult<usize> {
@@ -327,3 +445,29 @@ pub fn close(Args(fd): Args<c_int>, fds: Arc<Mutex<FileDescriptorTable>>) -> ERe
 	fds.lock().close_fd(fd as _)?;
 	Ok(0)
 }
+
+pub fn close_range(
+	Args((first, last, flags)): Args<(c_uint, c_uint, c_uint)>,
+	proc: Arc<Process>,
+) -> EResult<usize> {
+	if unlikely(flags & !(CLOSE_RANGE_UNSHARE | CLOSE_RANGE_CLOEXEC) != 0 || first > last) {
+		return Err(errno!(EINVAL));
+	}
+	let Some(mut fds) = proc.file_descriptors.get() else {
+		return Ok(0);
+	};
+	// Give the process its own copy of the table, so that other processes sharing it are not
+	// affected
+	if flags & CLOSE_RANGE_UNSHARE != 0 {
+		let new_fds = fds.lock().duplicate(false)?;
+		fds = Arc::new(Mutex::new(new_fds))?;
+		proc.file_descriptors.swap(Some(fds.clone()));
+	}
+	let mut fds = fds.lock();
+	if flags & CLOSE_RANGE_CLOEXEC != 0 {
+		fds.set_cloexec_range(first, last);
+	} else {
+		fds.close_range(first, last);
+	}
+	Ok(0)
+}
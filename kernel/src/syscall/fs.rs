@@ -19,16 +19,17 @@
 //! Files handling system calls.
 
 use crate::{
+	arch::x86::idt::IntFrame,
 	device::id,
 	file,
 	file::{
-		File, FileType, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL, O_NOCTTY, O_NOFOLLOW, O_RDONLY,
-		O_RDWR, O_TRUNC, O_WRONLY, Stat,
+		File, FileType, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL, O_LARGEFILE, O_NOCTTY,
+		O_NOFOLLOW, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, Stat,
 		fd::{FD_CLOEXEC, FileDescriptorTable},
-		fs::StatSet,
-		perm::AccessProfile,
+		fs::{FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, StatSet},
+		perm::{AccessProfile, CAP_CHOWN, CAP_MKNOD, CAP_SYS_CHROOT},
 		vfs,
-		vfs::{ResolutionSettings, Resolved},
+		vfs::{ResolutionSettings, Resolved, mountpoint, mountpoint::FLAG_NODEV},
 	},
 	memory::user::{UserPtr, UserSlice, UserString},
 	process::Process,
@@ -45,7 +46,7 @@ use crate::{
 		unit::{TimeUnit, Timespec},
 	},
 };
-use core::{ffi::c_int, hint::unlikely, ops::Deref, sync::atomic};
+use core::{ffi::c_int, hint::unlikely, mem};
 use utils::{
 	collections::path::{Path, PathBuf},
 	errno,
@@ -68,8 +69,23 @@ const RENAME_NOREPLACE: c_int = 1;
 /// `rename` flag: Exchanges old and new paths atomically.
 const RENAME_EXCHANGE: c_int = 2;
 
-pub fn creat(Args((pathname, mode)): Args<(UserString, c_int)>) -> EResult<usize> {
-	do_openat(AT_FDCWD, pathname, O_CREAT | O_WRONLY | O_TRUNC, mode as _)
+/// Returns the open flags `flags`, with `O_LARGEFILE` added if the caller is 64 bit.
+///
+/// 64 bit userspace is not limited in the size of the files it opens.
+fn force_largefile(flags: c_int, frame: &IntFrame) -> c_int {
+	if frame.is_compat() {
+		flags
+	} else {
+		flags | O_LARGEFILE
+	}
+}
+
+pub fn creat(
+	Args((pathname, mode)): Args<(UserString, c_int)>,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	let flags = force_largefile(O_CREAT | O_WRONLY | O_TRUNC, frame);
+	do_openat(AT_FDCWD, pathname, flags, mode as _)
 }
 
 pub fn mkdir(
@@ -118,7 +134,7 @@ pub fn mknod(
 	// Check file type and permissions
 	let mode = mode & !umask.0;
 	let file_type = FileType::from_mode(mode).ok_or(errno!(EPERM))?;
-	let privileged = rs.access_profile.is_privileged();
+	let privileged = rs.access_profile.has_capability(CAP_MKNOD);
 	match (file_type, privileged) {
 		(FileType::Regular | FileType::Fifo | FileType::Socket, _) => {}
 		(FileType::BlockDevice | FileType::CharDevice, true) => {}
@@ -279,8 +295,9 @@ pub fn readlink(
 
 pub fn open(
 	Args((pathname, flags, mode)): Args<(UserString, c_int, file::Mode)>,
+	frame: &mut IntFrame,
 ) -> EResult<usize> {
-	do_openat(AT_FDCWD, pathname, flags, mode)
+	do_openat(AT_FDCWD, pathname, force_largefile(flags, frame), mode)
 }
 
 // TODO Implement all flags
@@ -294,7 +311,8 @@ pub fn open(
 /// - `mode` is the set of permissions to use if the file needs to be created.
 ///
 /// If the file doesn't exist and the `O_CREAT` flag is set, the file is created,
-/// then the function returns it.
+/// then the function returns it. If the file exists and both `O_CREAT` and `O_EXCL` are set, the
+/// function returns [`errno::EEXIST`].
 ///
 /// If the flag is not set, the function returns an error with the appropriate errno.
 ///
@@ -309,6 +327,9 @@ fn get_file(
 ) -> EResult<Arc<vfs::Entry>> {
 	let resolved = at::get_file(fds, rs.clone(), dirfd, path, flags)?;
 	match resolved {
+		Resolved::Found(_) if flags & (O_CREAT | O_EXCL) == (O_CREAT | O_EXCL) => {
+			Err(errno!(EEXIST))
+		}
 		Resolved::Found(file) => Ok(file),
 		Resolved::Creatable {
 			parent,
@@ -340,7 +361,9 @@ pub fn do_openat(
 ) -> EResult<usize> {
 	let (rs, pathname, fds_mutex, mode) = {
 		let proc = Process::current();
-		let follow_link = flags & O_NOFOLLOW == 0;
+		let excl = flags & (O_CREAT | O_EXCL) == (O_CREAT | O_EXCL);
+		// With `O_EXCL`, a symbolic link at the end of the path is never followed
+		let follow_link = flags & O_NOFOLLOW == 0 && !excl;
 		let rs = ResolutionSettings {
 			create: flags & O_CREAT != 0,
 			..ResolutionSettings::for_process(&proc, follow_link)
@@ -349,7 +372,7 @@ pub fn do_openat(
 			.copy_from_user()?
 			.map(PathBuf::try_from)
 			.ok_or_else(|| errno!(EFAULT))??;
-		let fds_mutex = proc.file_descriptors.deref().clone().unwrap();
+		let fds_mutex = proc.file_descriptors.get().unwrap();
 		let mode = mode & !proc.fs.lock().umask();
 		(rs, pathname, fds_mutex, mode)
 	};
@@ -373,10 +396,26 @@ pub fn do_openat(
 		return Err(errno!(EACCES));
 	}
 	let file_type = stat.get_type();
+	// Device files cannot be opened on a `nodev` mountpoint
+	if matches!(
+		file_type,
+		Some(FileType::BlockDevice | FileType::CharDevice)
+	) && mountpoint::entry_has_flags(&file, FLAG_NODEV)
+	{
+		return Err(errno!(EACCES));
+	}
 	// If `O_DIRECTORY` is set and the file is not a directory, return an error
 	if flags & O_DIRECTORY != 0 && file_type != Some(FileType::Directory) {
 		return Err(errno!(ENOTDIR));
 	}
+	// Without `O_LARGEFILE`, the size of the file must fit in a 32 bit offset
+	if unlikely(
+		flags & O_LARGEFILE == 0
+			&& file_type == Some(FileType::Regular)
+			&& stat.size > i32::MAX as u64,
+	) {
+		return Err(errno!(EOVERFLOW));
+	}
 	// Open file
 	const FLAGS_MASK: i32 =
 		!(O_CLOEXEC | O_CREAT | O_DIRECTORY | O_EXCL | O_NOCTTY | O_NOFOLLOW | O_TRUNC);
@@ -396,8 +435,9 @@ pub fn do_openat(
 
 pub fn openat(
 	Args((dirfd, pathname, flags, mode)): Args<(c_int, UserString, c_int, file::Mode)>,
+	frame: &mut IntFrame,
 ) -> EResult<usize> {
-	do_openat(dirfd, pathname, flags, mode)
+	do_openat(dirfd, pathname, force_largefile(flags, frame), mode)
 }
 
 /// Performs the access operation.
@@ -510,7 +550,7 @@ pub fn fchmod(
 		return Err(errno!(EPERM));
 	}
 	vfs::set_stat(
-		file.node(),
+		&file,
 		&StatSet {
 			mode: Some(mode),
 			..Default::default()
@@ -540,7 +580,7 @@ pub fn fchmodat(
 		return Err(errno!(EPERM));
 	}
 	vfs::set_stat(
-		file.node(),
+		&file,
 		&StatSet {
 			mode: Some(mode),
 			..Default::default()
@@ -565,11 +605,11 @@ pub fn do_chown(
 	// Get file
 	let ent = vfs::get_file_from_path(&path, &rs)?;
 	// TODO allow changing group to any group whose owner is member
-	if !rs.access_profile.is_privileged() {
+	if !rs.access_profile.has_capability(CAP_CHOWN) {
 		return Err(errno!(EPERM));
 	}
 	vfs::set_stat(
-		ent.node(),
+		&ent,
 		&StatSet {
 			uid: (owner > -1).then_some(owner as _),
 			gid: (group > -1).then_some(group as _),
@@ -603,7 +643,11 @@ pub fn lchown(
 
 pub fn getcwd(Args((buf, size)): Args<(*mut u8, usize)>, proc: Arc<Process>) -> EResult<usize> {
 	let buf = UserSlice::from_user(buf, size)?;
-	let cwd = vfs::Entry::get_path(&proc.fs.lock().cwd)?;
+	let cwd = {
+		let fs = proc.fs.lock();
+		let ctx = fs.ctx.lock();
+		vfs::Entry::get_path_in(&ctx.cwd, &ctx.chroot)?
+	};
 	if unlikely(size < cwd.len() + 1) {
 		return Err(errno!(ERANGE));
 	}
@@ -630,7 +674,7 @@ pub fn chdir(
 		return Err(errno!(EACCES));
 	}
 	// Set new cwd
-	proc.fs.lock().cwd = dir;
+	proc.fs.lock().ctx.lock().cwd = dir;
 	Ok(0)
 }
 
@@ -640,21 +684,21 @@ pub fn chroot(
 	rs: ResolutionSettings,
 ) -> EResult<usize> {
 	// Check permission
-	if !rs.access_profile.is_privileged() {
+	if !rs.access_profile.has_capability(CAP_SYS_CHROOT) {
 		return Err(errno!(EPERM));
 	}
 	let path = path.copy_from_user()?.ok_or(errno!(EFAULT))?;
 	let path = PathBuf::try_from(path)?;
-	let rs = ResolutionSettings {
-		root: vfs::ROOT.clone(),
-		..rs
-	};
-	// Get file
+	// Get file, relative to the current root
 	let ent = vfs::get_file_from_path(&path, &rs)?;
-	if ent.get_type()? != FileType::Directory {
+	let stat = ent.stat();
+	if stat.get_type() != Some(FileType::Directory) {
 		return Err(errno!(ENOTDIR));
 	}
-	proc.fs.lock().chroot = ent;
+	if !rs.access_profile.can_search_directory(&stat) {
+		return Err(errno!(EACCES));
+	}
+	proc.fs.lock().ctx.lock().chroot = ent;
 	Ok(0)
 }
 
@@ -679,16 +723,13 @@ pub fn fchdir(
 	if !ap.can_list_directory(&stat) {
 		return Err(errno!(EACCES));
 	}
-	proc.fs.lock().cwd = file;
+	proc.fs.lock().ctx.lock().cwd = file;
 	Ok(0)
 }
 
 pub fn umask(Args(mask): Args<file::Mode>, proc: Arc<Process>) -> EResult<usize> {
-	let prev = proc
-		.fs
-		.lock()
-		.umask
-		.swap(mask & 0o777, atomic::Ordering::Relaxed);
+	let fs = proc.fs.lock();
+	let prev = mem::replace(&mut fs.ctx.lock().umask, mask & 0o777);
 	Ok(prev as _)
 }
 
@@ -720,7 +761,7 @@ pub fn utimensat(
 	};
 	// Update timestamps
 	vfs::set_stat(
-		file.node(),
+		&file,
 		&StatSet {
 			atime: Some(atime / 1_000_000_000),
 			mtime: Some(mtime / 1_000_000_000),
@@ -799,7 +840,11 @@ pub fn renameat2(
 	do_renameat2(olddirfd, oldpath, newdirfd, newpath, flags, fds, rs)
 }
 
-pub fn truncate(Args((path, length)): Args<(UserString, usize)>) -> EResult<usize> {
+/// Performs the `truncate` system call.
+///
+/// If `length` is negative, the function returns [`errno::EINVAL`].
+fn do_truncate(path: UserString, length: i64) -> EResult<usize> {
+	let length: u64 = length.try_into().map_err(|_| errno!(EINVAL))?;
 	let proc = Process::current();
 	let rs = ResolutionSettings::for_process(&proc, true);
 	let path = path.copy_from_user()?.ok_or(errno!(EFAULT))?;
@@ -811,10 +856,109 @@ pub fn truncate(Args((path, length)): Args<(UserString, usize)>) -> EResult<usiz
 	}
 	// Truncate
 	let file = File::open_entry(ent, O_WRONLY)?;
-	file.ops.truncate(&file, length as _)?;
+	file.ops.truncate(&file, length)?;
+	Ok(0)
+}
+
+pub fn truncate(Args((path, length)): Args<(UserString, isize)>) -> EResult<usize> {
+	do_truncate(path, length as _)
+}
+
+pub fn truncate64(
+	Args((path, length_low, length_high)): Args<(UserString, u32, u32)>,
+) -> EResult<usize> {
+	let length = ((length_high as u64) << 32) | length_low as u64;
+	do_truncate(path, length as _)
+}
+
+/// Performs the `ftruncate` system call.
+///
+/// If `length` is negative, or if the file is not a regular file open for writing, the function
+/// returns [`errno::EINVAL`].
+fn do_ftruncate(fd: c_int, length: i64, fds: &Mutex<FileDescriptorTable>) -> EResult<usize> {
+	let length: u64 = length.try_into().map_err(|_| errno!(EINVAL))?;
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	let writable = matches!(file.get_flags() & 0b11, O_WRONLY | O_RDWR);
+	if unlikely(!writable || file.get_type()? != FileType::Regular) {
+		return Err(errno!(EINVAL));
+	}
+	file.ops.truncate(&file, length)?;
+	Ok(0)
+}
+
+pub fn ftruncate(
+	Args((fd, length)): Args<(c_int, isize)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_ftruncate(fd, length as _, &fds)
+}
+
+pub fn ftruncate64(
+	Args((fd, length_low, length_high)): Args<(c_int, u32, u32)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let length = ((length_high as u64) << 32) | length_low as u64;
+	do_ftruncate(fd, length as _, &fds)
+}
+
+/// Performs the `fallocate` system call.
+fn do_fallocate(
+	fd: c_int,
+	mode: c_int,
+	offset: i64,
+	len: i64,
+	fds: &Mutex<FileDescriptorTable>,
+) -> EResult<usize> {
+	let offset: u64 = offset.try_into().map_err(|_| errno!(EINVAL))?;
+	let len: u64 = len.try_into().map_err(|_| errno!(EINVAL))?;
+	if unlikely(len == 0) {
+		return Err(errno!(EINVAL));
+	}
+	let mode = mode as u32;
+	if unlikely(mode & !(FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE) != 0) {
+		return Err(errno!(EOPNOTSUPP));
+	}
+	// Punching a hole never changes the size of the file
+	if unlikely(mode & FALLOC_FL_PUNCH_HOLE != 0 && mode & FALLOC_FL_KEEP_SIZE == 0) {
+		return Err(errno!(EOPNOTSUPP));
+	}
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	if unlikely(!file.can_write()) {
+		return Err(errno!(EBADF));
+	}
+	match file.get_type()? {
+		FileType::Regular => {}
+		FileType::Directory => return Err(errno!(EISDIR)),
+		FileType::Fifo | FileType::Socket => return Err(errno!(ESPIPE)),
+		_ => return Err(errno!(ENODEV)),
+	}
+	file.ops.fallocate(&file, mode, offset, len)?;
 	Ok(0)
 }
 
+pub fn fallocate(
+	Args((fd, mode, offset, len)): Args<(c_int, c_int, isize, isize)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_fallocate(fd, mode, offset as _, len as _, &fds)
+}
+
+pub fn fallocate32(
+	Args((fd, mode, offset_low, offset_high, len_low, len_high)): Args<(
+		c_int,
+		c_int,
+		u32,
+		u32,
+		u32,
+		u32,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let offset = ((offset_high as u64) << 32) | offset_low as u64;
+	let len = ((len_high as u64) << 32) | len_low as u64;
+	do_fallocate(fd, mode, offset as _, len as _, &fds)
+}
+
 pub fn unlink(
 	Args(pathname): Args<UserString>,
 	rs: ResolutionSettings,
@@ -0,0 +1,80 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `futex` system call allows to wait on, and to wake up processes waiting on, a word of
+//! memory.
+
+use crate::{
+	memory::user::UserPtr,
+	process::{futex, mem_space::MemSpace},
+	syscall::Args,
+	time::unit::{TimeUnit, Timespec, Timespec32, Timestamp},
+};
+use core::{ffi::c_int, hint::unlikely};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Operation: wait until the futex is woken up, if its value is the expected one.
+const FUTEX_WAIT: c_int = 0;
+/// Operation: wake up processes waiting on the futex.
+const FUTEX_WAKE: c_int = 1;
+
+/// Flag: the futex is private to the process. Futexes are always private to the memory space.
+const FUTEX_PRIVATE_FLAG: c_int = 128;
+/// Flag: the timeout is measured against the realtime clock.
+const FUTEX_CLOCK_REALTIME: c_int = 256;
+
+fn do_futex<F: FnOnce() -> EResult<Option<Timestamp>>>(
+	mem_space: Arc<MemSpace>,
+	uaddr: UserPtr<u32>,
+	op: c_int,
+	val: u32,
+	timeout: F,
+) -> EResult<usize> {
+	let addr = uaddr.as_ptr() as usize;
+	if unlikely(!addr.is_multiple_of(size_of::<u32>())) {
+		return Err(errno!(EINVAL));
+	}
+	match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+		FUTEX_WAIT => {
+			let timeout = timeout()?;
+			let load = || uaddr.copy_from_user()?.ok_or_else(|| errno!(EFAULT));
+			futex::wait(&mem_space, addr, val, load, timeout)?;
+			Ok(0)
+		}
+		FUTEX_WAKE => Ok(futex::wake(&mem_space, addr, val as _)),
+		_ => Err(errno!(ENOSYS)),
+	}
+}
+
+pub fn futex32(
+	Args((uaddr, op, val, timeout)): Args<(UserPtr<u32>, c_int, u32, UserPtr<Timespec32>)>,
+	mem_space: Arc<MemSpace>,
+) -> EResult<usize> {
+	do_futex(mem_space, uaddr, op, val, || {
+		Ok(timeout.copy_from_user()?.map(|t| t.to_nano()))
+	})
+}
+
+pub fn futex64(
+	Args((uaddr, op, val, timeout)): Args<(UserPtr<u32>, c_int, u32, UserPtr<Timespec>)>,
+	mem_space: Arc<MemSpace>,
+) -> EResult<usize> {
+	do_futex(mem_space, uaddr, op, val, || {
+		Ok(timeout.copy_from_user()?.map(|t| t.to_nano()))
+	})
+}
@@ -21,19 +21,20 @@
 use crate::{
 	NAME, VERSION,
 	arch::ARCH,
-	file::perm::AccessProfile,
+	file::perm::{AccessProfile, CAP_SYS_ADMIN, CAP_SYS_BOOT},
 	memory::user::{UserPtr, UserSlice},
 	power,
+	process::Process,
 	syscall::Args,
+	uapi::utsname::{UTSNAME_LENGTH, Utsname},
 };
 use core::{
 	ffi::{c_int, c_void},
 	hint::unlikely,
 };
-use utils::{errno, errno::EResult, limits::HOST_NAME_MAX, slice_copy};
-
-/// The length of a field of the utsname structure.
-const UTSNAME_LENGTH: usize = 65;
+use utils::{
+	collections::vec::Vec, errno, errno::EResult, limits::HOST_NAME_MAX, ptr::arc::Arc, slice_copy,
+};
 
 /// First magic number.
 const MAGIC: c_int = 0xde145e83u32 as _;
@@ -49,55 +50,57 @@ const CMD_HALT: c_int = 2;
 /// Command to suspend the system.
 const CMD_SUSPEND: c_int = 3;
 
-/// Userspace structure storing uname information.
-#[repr(C)]
-#[derive(Debug)]
-pub struct Utsname {
-	/// Operating system name.
-	sysname: [u8; UTSNAME_LENGTH],
-	/// Network node hostname.
-	nodename: [u8; UTSNAME_LENGTH],
-	/// Operating system release.
-	release: [u8; UTSNAME_LENGTH],
-	/// Operating system version.
-	version: [u8; UTSNAME_LENGTH],
-	/// Hardware identifier.
-	machine: [u8; UTSNAME_LENGTH],
-}
-
-pub fn uname(Args(buf): Args<UserPtr<Utsname>>) -> EResult<usize> {
+pub fn uname(Args(buf): Args<UserPtr<Utsname>>, proc: Arc<Process>) -> EResult<usize> {
 	let mut utsname = Utsname {
 		sysname: [0; UTSNAME_LENGTH],
 		nodename: [0; UTSNAME_LENGTH],
 		release: [0; UTSNAME_LENGTH],
 		version: [0; UTSNAME_LENGTH],
 		machine: [0; UTSNAME_LENGTH],
+		domainname: [0; UTSNAME_LENGTH],
 	};
+	let uts = proc.ns.lock().uts.clone();
 	slice_copy(NAME.as_bytes(), &mut utsname.sysname);
-	slice_copy(&crate::HOSTNAME.lock(), &mut utsname.nodename);
+	slice_copy(&uts.hostname.lock(), &mut utsname.nodename);
 	slice_copy(VERSION.as_bytes(), &mut utsname.release);
 	slice_copy(&[], &mut utsname.version);
 	slice_copy(ARCH.as_bytes(), &mut utsname.machine);
+	slice_copy(&uts.domainname.lock(), &mut utsname.domainname);
 	buf.copy_to_user(&utsname)?;
 	Ok(0)
 }
 
-pub fn sethostname(
-	Args((name, len)): Args<(*mut u8, usize)>,
-	ap: AccessProfile,
-) -> EResult<usize> {
-	// Check the size of the hostname is in bounds
+/// Reads a name of length `len` from `name`, for `sethostname` and `setdomainname`.
+fn read_name(name: *mut u8, len: usize, ap: &AccessProfile) -> EResult<Vec<u8>> {
+	// Check the size of the name is in bounds
 	if unlikely(len > HOST_NAME_MAX) {
 		return Err(errno!(EINVAL));
 	}
 	// Check permission
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SYS_ADMIN) {
 		return Err(errno!(EPERM));
 	}
-	// Copy
 	let name = UserSlice::from_user(name, len)?;
-	let mut hostname = crate::HOSTNAME.lock();
-	*hostname = name.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))?;
+	name.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))
+}
+
+pub fn sethostname(
+	Args((name, len)): Args<(*mut u8, usize)>,
+	ap: AccessProfile,
+	proc: Arc<Process>,
+) -> EResult<usize> {
+	let name = read_name(name, len, &ap)?;
+	*proc.ns.lock().uts.hostname.lock() = name;
+	Ok(0)
+}
+
+pub fn setdomainname(
+	Args((name, len)): Args<(*mut u8, usize)>,
+	ap: AccessProfile,
+	proc: Arc<Process>,
+) -> EResult<usize> {
+	let name = read_name(name, len, &ap)?;
+	*proc.ns.lock().uts.domainname.lock() = name;
 	Ok(0)
 }
 
@@ -109,7 +112,7 @@ pub fn reboot(
 	if magic != MAGIC || magic2 != MAGIC2 {
 		return Err(errno!(EINVAL));
 	}
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SYS_BOOT) {
 		return Err(errno!(EPERM));
 	}
 	// Debug commands: shutdown with QEMU
@@ -0,0 +1,93 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `inotify` system calls allow to monitor filesystem events.
+
+use crate::{
+	file,
+	file::{
+		File,
+		buffer::inotify::{IN_DONT_FOLLOW, Inotify},
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+		vfs,
+		vfs::ResolutionSettings,
+	},
+	memory::user::UserString,
+	sync::mutex::Mutex,
+	syscall::Args,
+};
+use core::{ffi::c_int, hint::unlikely};
+use utils::{collections::path::PathBuf, errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const IN_CLOEXEC: c_int = file::O_CLOEXEC;
+/// Flag: set the non-blocking flag on the new open file description.
+const IN_NONBLOCK: c_int = file::O_NONBLOCK;
+
+pub fn inotify_init(fds: Arc<Mutex<FileDescriptorTable>>) -> EResult<usize> {
+	inotify_init1(Args(0), fds)
+}
+
+pub fn inotify_init1(
+	Args(flags): Args<c_int>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	if unlikely(flags & !(IN_CLOEXEC | IN_NONBLOCK) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let ops = Arc::new(Inotify::new()?)?;
+	let file = File::open_floating(ops, file::O_RDONLY | (flags & IN_NONBLOCK))?;
+	let fd_flags = if flags & IN_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.lock().create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
+
+pub fn inotify_add_watch(
+	Args((fd, pathname, mask)): Args<(c_int, UserString, u32)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+	rs: ResolutionSettings,
+) -> EResult<usize> {
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	let inotify: &Inotify = file.get_buffer().ok_or_else(|| errno!(EINVAL))?;
+	let path = pathname.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let path = PathBuf::try_from(path)?;
+	let rs = ResolutionSettings {
+		follow_link: mask & IN_DONT_FOLLOW == 0,
+		..rs
+	};
+	let ent = vfs::get_file_from_path(&path, &rs)?;
+	if !rs.access_profile.can_read_file(&ent.stat()) {
+		return Err(errno!(EACCES));
+	}
+	let wd = inotify.add_watch(ent.node(), mask)?;
+	Ok(wd as _)
+}
+
+pub fn inotify_rm_watch(
+	Args((fd, wd)): Args<(c_int, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	let inotify: &Inotify = file.get_buffer().ok_or_else(|| errno!(EINVAL))?;
+	inotify.rm_watch(wd)?;
+	Ok(0)
+}
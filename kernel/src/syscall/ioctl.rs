@@ -26,6 +26,8 @@ use utils::{errno::EResult, ptr::arc::Arc};
 
 /// ioctl request: get device geometry.
 pub const HDIO_GETGEO: c_ulong = 0x00000301;
+/// ioctl request: execute an ATA command.
+pub const HDIO_DRIVE_CMD: c_ulong = 0x0000031f;
 
 // ioctl requests: storage
 
@@ -35,6 +37,13 @@ pub const BLKRRPART: c_ulong = 0x0000125f;
 pub const BLKSSZGET: c_ulong = 0x00001268;
 /// ioctl request: get storage size in bytes.
 pub const BLKGETSIZE64: c_ulong = 0x00001272;
+/// ioctl request: discard a range of bytes on the storage.
+pub const BLKDISCARD: c_ulong = 0x00001277;
+
+// ioctl requests: SCSI generic
+
+/// ioctl request: execute a SCSI command.
+pub const SG_IO: c_ulong = 0x00002285;
 
 // ioctl requests: TTY
 
@@ -60,6 +69,53 @@ pub const TIOCSWINSZ: c_ulong = 0x00005414;
 /// ioctl request: Returns the number of bytes available on the file descriptor.
 pub const FIONREAD: c_ulong = 0x0000541b;
 
+// ioctl requests: watch queues
+
+/// ioctl request: sets the maximum number of notifications of a watch queue.
+pub const IOC_WATCH_QUEUE_SET_SIZE: c_ulong = 0x00005760;
+/// ioctl request: sets the filters of a watch queue.
+pub const IOC_WATCH_QUEUE_SET_FILTER: c_ulong = 0x00005761;
+/// ioctl request: subscribes a watch queue to a source of notifications.
+pub const IOC_WATCH_QUEUE_WATCH: c_ulong = 0x00005762;
+/// ioctl request: unsubscribes a watch queue from a source of notifications.
+pub const IOC_WATCH_QUEUE_UNWATCH: c_ulong = 0x00005763;
+
+// ioctl requests: sockets
+
+/// ioctl request: Deletes an ARP table entry.
+pub const SIOCDARP: c_ulong = 0x00008953;
+/// ioctl request: Returns an ARP table entry.
+pub const SIOCGARP: c_ulong = 0x00008954;
+/// ioctl request: Sets an ARP table entry.
+pub const SIOCSARP: c_ulong = 0x00008955;
+
+// ioctl requests: KVM
+
+/// ioctl request: Returns the version of the KVM API.
+pub const KVM_GET_API_VERSION: c_ulong = 0x0000ae00;
+/// ioctl request: Creates a virtual machine.
+pub const KVM_CREATE_VM: c_ulong = 0x0000ae01;
+/// ioctl request: Returns the value of a KVM capability.
+pub const KVM_CHECK_EXTENSION: c_ulong = 0x0000ae03;
+/// ioctl request: Returns the size of the memory to map on a VCPU file descriptor.
+pub const KVM_GET_VCPU_MMAP_SIZE: c_ulong = 0x0000ae04;
+/// ioctl request: Creates a VCPU on a virtual machine.
+pub const KVM_CREATE_VCPU: c_ulong = 0x0000ae41;
+/// ioctl request: Creates, modifies or deletes a memory slot of a virtual machine.
+pub const KVM_SET_USER_MEMORY_REGION: c_ulong = 0x0000ae46;
+/// ioctl request: Sets the address of the TSS used to emulate real mode.
+pub const KVM_SET_TSS_ADDR: c_ulong = 0x0000ae47;
+/// ioctl request: Runs a VCPU.
+pub const KVM_RUN: c_ulong = 0x0000ae80;
+/// ioctl request: Returns the general-purpose registers of a VCPU.
+pub const KVM_GET_REGS: c_ulong = 0x0000ae81;
+/// ioctl request: Sets the general-purpose registers of a VCPU.
+pub const KVM_SET_REGS: c_ulong = 0x0000ae82;
+/// ioctl request: Returns the special registers of a VCPU.
+pub const KVM_GET_SREGS: c_ulong = 0x0000ae83;
+/// ioctl request: Sets the special registers of a VCPU.
+pub const KVM_SET_SREGS: c_ulong = 0x0000ae84;
+
 /// IO directions for ioctl requests.
 #[derive(Eq, PartialEq)]
 pub enum Direction {
@@ -121,7 +177,7 @@ pub(super) fn ioctl(
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
 	let request = Request::from(request);
-	let fds = fds.lock();
-	let file = fds.get_fd(fd)?.get_file();
-	file.ops.ioctl(file, request, argp).map(|v| v as _)
+	// Release the table, since the request may create file descriptors
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	file.ops.ioctl(&file, request, argp).map(|v| v as _)
 }
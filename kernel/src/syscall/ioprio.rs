@@ -0,0 +1,94 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `ioprio_get` and `ioprio_set` system calls allow to get and set the I/O scheduling class
+//! and priority of processes.
+
+use crate::{
+	device::storage::elevator::{
+		IOPRIO_CLASS_BE, IOPRIO_CLASS_IDLE, IOPRIO_CLASS_NONE, IOPRIO_CLASS_RT, IOPRIO_NR_LEVELS,
+		ioprio_class, ioprio_key, ioprio_level,
+	},
+	file::perm::{AccessProfile, CAP_SYS_ADMIN, CAP_SYS_NICE},
+	process::Process,
+	syscall::{Args, sched, sched::Target},
+};
+use core::{ffi::c_int, sync::atomic::Ordering::Relaxed};
+use utils::{collections::vec::Vec, errno, errno::EResult, ptr::arc::Arc};
+
+/// Target: a single process.
+const IOPRIO_WHO_PROCESS: c_int = 1;
+/// Target: a process group.
+const IOPRIO_WHO_PGRP: c_int = 2;
+/// Target: all processes of a user.
+const IOPRIO_WHO_USER: c_int = 3;
+
+/// Returns the list of processes targeted by `which` and `who`.
+///
+/// If no process matches, the function returns [`errno::ESRCH`].
+fn get_targets(which: c_int, who: c_int) -> EResult<Vec<Arc<Process>>> {
+	let target = match which {
+		IOPRIO_WHO_PROCESS => Target::Process(who),
+		IOPRIO_WHO_PGRP => Target::Pgrp(who),
+		IOPRIO_WHO_USER => Target::User(who),
+		_ => return Err(errno!(EINVAL)),
+	};
+	sched::get_targets(target)
+}
+
+pub fn ioprio_get(Args((which, who)): Args<(c_int, c_int)>) -> EResult<usize> {
+	// If several processes match, return the highest priority
+	let ioprio = get_targets(which, who)?
+		.iter()
+		.map(|proc| proc.ioprio.load(Relaxed))
+		.min_by_key(|ioprio| ioprio_key(*ioprio))
+		.unwrap();
+	Ok(ioprio as _)
+}
+
+pub fn ioprio_set(
+	Args((which, who, ioprio)): Args<(c_int, c_int, c_int)>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let ioprio: u16 = ioprio.try_into().map_err(|_| errno!(EINVAL))?;
+	match ioprio_class(ioprio) {
+		IOPRIO_CLASS_NONE | IOPRIO_CLASS_IDLE => {}
+		IOPRIO_CLASS_RT => {
+			if !ap.has_capability(CAP_SYS_NICE) && !ap.has_capability(CAP_SYS_ADMIN) {
+				return Err(errno!(EPERM));
+			}
+			if ioprio_level(ioprio) >= IOPRIO_NR_LEVELS {
+				return Err(errno!(EINVAL));
+			}
+		}
+		IOPRIO_CLASS_BE => {
+			if ioprio_level(ioprio) >= IOPRIO_NR_LEVELS {
+				return Err(errno!(EINVAL));
+			}
+		}
+		_ => return Err(errno!(EINVAL)),
+	}
+	for proc in get_targets(which, who)? {
+		let uid = proc.fs.lock().access_profile.uid;
+		if !ap.has_capability(CAP_SYS_NICE) && ap.uid != uid && ap.euid != uid {
+			return Err(errno!(EPERM));
+		}
+		proc.ioprio.store(ioprio, Relaxed);
+	}
+	Ok(0)
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `ipc` system call, multiplexing SysV IPC operations on 32 bit processes.
+
+use super::{
+	msg::{do_msgctl, do_msgrcv, do_msgsnd, msgget},
+	sem::{do_semctl, do_semtimedop, semget},
+	shm::{do_shmat, do_shmctl, shmdt, shmget},
+};
+use crate::{
+	file::perm::AccessProfile,
+	memory::{VirtAddr, shm::CompatShmidDs, user::UserPtr},
+	process::{Process, mem_space::MemSpace, msg::CompatMsqidDs, sem::CompatSemidDs},
+	syscall::{Args, FromSyscallArg},
+	time::unit::{TimeUnit, Timespec32},
+};
+use core::{
+	ffi::{c_int, c_uint},
+	ptr,
+};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Operation: `semop`.
+const SEMOP: c_uint = 1;
+/// Operation: `semget`.
+const SEMGET: c_uint = 2;
+/// Operation: `semctl`.
+const SEMCTL: c_uint = 3;
+/// Operation: `semtimedop`.
+const SEMTIMEDOP: c_uint = 4;
+/// Operation: `msgsnd`.
+const MSGSND: c_uint = 11;
+/// Operation: `msgrcv`.
+const MSGRCV: c_uint = 12;
+/// Operation: `msgget`.
+const MSGGET: c_uint = 13;
+/// Operation: `msgctl`.
+const MSGCTL: c_uint = 14;
+/// Operation: `shmat`.
+const SHMAT: c_uint = 21;
+/// Operation: `shmdt`.
+const SHMDT: c_uint = 22;
+/// Operation: `shmget`.
+const SHMGET: c_uint = 23;
+/// Operation: `shmctl`.
+const SHMCTL: c_uint = 24;
+
+/// Arguments of `msgrcv` passed by pointer, with version zero of the `ipc` system call.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct IpcKludge {
+	/// Pointer to the message buffer.
+	msgp: u32,
+	/// The type of the message to receive.
+	msgtyp: i32,
+}
+
+pub fn compat_ipc(
+	Args((call, first, second, third, ptr, fifth)): Args<(
+		c_uint,
+		c_int,
+		usize,
+		usize,
+		usize,
+		usize,
+	)>,
+	proc: Arc<Process>,
+	mem_space: Arc<MemSpace>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	// The upper half holds a version number
+	let version = call >> 16;
+	match call & 0xffff {
+		SEMOP => do_semtimedop(
+			first,
+			ptr::with_exposed_provenance_mut(ptr),
+			second,
+			None,
+			&proc,
+			&ap,
+		),
+		SEMGET => semget(Args((first, second as _, third as _)), proc, ap),
+		SEMCTL => {
+			// `ptr` points to the `semun` union
+			let arg = UserPtr::<u32>::from_syscall_arg(ptr, true)
+				.copy_from_user()?
+				.ok_or_else(|| errno!(EINVAL))?;
+			do_semctl::<CompatSemidDs>(first, second as _, third as _, arg as _, &proc, &ap)
+		}
+		SEMTIMEDOP => {
+			let timeout = UserPtr::<Timespec32>::from_syscall_arg(fifth, true)
+				.copy_from_user()?
+				.map(|t| t.to_nano());
+			do_semtimedop(
+				first,
+				ptr::with_exposed_provenance_mut(ptr),
+				second,
+				timeout,
+				&proc,
+				&ap,
+			)
+		}
+		MSGSND => do_msgsnd(first, ptr, second, third as _, true, &proc, &ap),
+		MSGRCV => {
+			let (msgp, msgtyp) = if version == 0 {
+				let kludge = UserPtr::<IpcKludge>::from_syscall_arg(ptr, true)
+					.copy_from_user()?
+					.ok_or_else(|| errno!(EINVAL))?;
+				(kludge.msgp as usize, kludge.msgtyp)
+			} else {
+				(ptr, fifth as i32)
+			};
+			do_msgrcv(
+				first,
+				msgp,
+				second,
+				msgtyp as _,
+				third as _,
+				true,
+				&proc,
+				&ap,
+			)
+		}
+		MSGGET => msgget(Args((first, second as _)), proc, ap),
+		MSGCTL => {
+			let buf = UserPtr::<CompatMsqidDs>::from_syscall_arg(ptr, true);
+			do_msgctl(first, second as _, buf, &proc, &ap)
+		}
+		SHMAT => {
+			let addr = do_shmat(first, VirtAddr(ptr), second as _, &proc, &mem_space, &ap)?;
+			// The address is returned through the pointer in `third`
+			let raddr = UserPtr::<u32>::from_syscall_arg(third, true);
+			raddr.copy_to_user(&(addr.0 as _))?;
+			Ok(0)
+		}
+		SHMDT => shmdt(Args(VirtAddr(ptr)), proc, mem_space),
+		SHMGET => shmget(Args((first, second, third as _)), proc, ap),
+		SHMCTL => {
+			let buf = UserPtr::<CompatShmidDs>::from_syscall_arg(ptr, true);
+			do_shmctl(first, second as _, buf, &proc, &ap)
+		}
+		_ => Err(errno!(ENOSYS)),
+	}
+}
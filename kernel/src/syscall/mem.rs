@@ -19,23 +19,71 @@
 //! Memory management system calls.
 
 use crate::{
-	file::{FileType, fd::FileDescriptorTable, perm::AccessProfile},
+	file::{
+		FileType,
+		fd::FileDescriptorTable,
+		fs::{
+			tmp,
+			tmp::{F_SEAL_FUTURE_WRITE, F_SEAL_WRITE},
+		},
+		perm::{AccessProfile, CAP_SYS_NICE},
+		vfs::{mountpoint, mountpoint::FLAG_NOEXEC},
+	},
 	memory,
-	memory::VirtAddr,
+	memory::{VirtAddr, hugetlb::HUGE_PAGE_SIZE, user::UserPtr},
 	process::{
 		mem_space,
-		mem_space::{MAP_ANONYMOUS, MAP_FIXED, MemSpace, PROT_EXEC, PROT_READ, PROT_WRITE},
+		mem_space::{
+			MAP_ANONYMOUS, MAP_DENYWRITE, MAP_FIXED, MAP_GROWSDOWN, MAP_HUGETLB, MAP_MERGEABLE,
+			MAP_SHARED, MemSpace, PROT_EXEC, PROT_READ, PROT_WRITE,
+		},
 	},
 	sync::mutex::Mutex,
 	syscall::{Args, mem::mem_space::MapConstraint},
 };
 use core::{
-	ffi::{c_int, c_void},
+	ffi::{c_int, c_uint, c_void},
 	hint::unlikely,
 	num::NonZeroUsize,
 };
 use utils::{errno, errno::EResult, limits::PAGE_SIZE, ptr::arc::Arc};
 
+/// Value of the `MAP_GROWSDOWN` flag of `mmap`.
+const LINUX_MAP_GROWSDOWN: i32 = 0x100;
+/// Value of the `MAP_DENYWRITE` flag of `mmap`.
+const LINUX_MAP_DENYWRITE: i32 = 0x800;
+/// Value of the `MAP_HUGETLB` flag of `mmap`.
+const LINUX_MAP_HUGETLB: i32 = 0x40000;
+/// The offset of the bits of the `mmap` flags giving the base-2 logarithm of the huge page size.
+const MAP_HUGE_SHIFT: i32 = 26;
+
+/// `madvise` advice: the pages in the range can be merged with identical pages.
+const MADV_MERGEABLE: c_int = 12;
+/// `madvise` advice: the pages in the range cannot be merged with identical pages anymore.
+const MADV_UNMERGEABLE: c_int = 13;
+
+/// Memory policy: default policy of the process.
+const MPOL_DEFAULT: c_int = 0;
+/// Memory policy: allocate on the preferred node if possible.
+const MPOL_PREFERRED: c_int = 1;
+/// Memory policy: allocate only on the given nodes.
+const MPOL_BIND: c_int = 2;
+/// Memory policy: interleave allocations on the given nodes.
+const MPOL_INTERLEAVE: c_int = 3;
+/// Memory policy: allocate on the node of the CPU triggering the allocation.
+const MPOL_LOCAL: c_int = 4;
+/// Memory policy mode flag: nodes are not remapped when the allowed nodes change.
+const MPOL_F_STATIC_NODES: c_int = 1 << 15;
+/// Memory policy mode flag: nodes are relative to the allowed nodes.
+const MPOL_F_RELATIVE_NODES: c_int = 1 << 14;
+
+/// `mbind` flag: fail if pages do not follow the policy.
+const MPOL_MF_STRICT: c_uint = 1 << 0;
+/// `mbind` flag: move the pages of the process to follow the policy.
+const MPOL_MF_MOVE: c_uint = 1 << 1;
+/// `mbind` flag: move all pages to follow the policy, even if shared with other processes.
+const MPOL_MF_MOVE_ALL: c_uint = 1 << 2;
+
 /// Performs the `mmap` system call.
 #[allow(clippy::too_many_arguments)]
 pub fn do_mmap(
@@ -53,6 +101,33 @@ pub fn do_mmap(
 	if !addr.is_aligned_to(PAGE_SIZE) || length == 0 {
 		return Err(errno!(EINVAL));
 	}
+	let hugetlb = flags & LINUX_MAP_HUGETLB != 0;
+	let (addr, length) = if hugetlb {
+		// Only anonymous mappings of the default huge page size are supported
+		let size_shift = (flags >> MAP_HUGE_SHIFT) & 0x3f;
+		let default_shift = HUGE_PAGE_SIZE.ilog2() as i32;
+		if unlikely(flags & MAP_ANONYMOUS as i32 == 0) {
+			return Err(errno!(EINVAL));
+		}
+		if unlikely(size_shift != 0 && size_shift != default_shift) {
+			return Err(errno!(EINVAL));
+		}
+		if unlikely(flags & MAP_FIXED as i32 != 0 && !addr.is_aligned_to(HUGE_PAGE_SIZE)) {
+			return Err(errno!(EINVAL));
+		}
+		// A hint which is not aligned cannot be used
+		let addr = if addr.is_aligned_to(HUGE_PAGE_SIZE) {
+			addr
+		} else {
+			VirtAddr(0)
+		};
+		let length = length
+			.checked_next_multiple_of(HUGE_PAGE_SIZE)
+			.ok_or_else(|| errno!(ENOMEM))?;
+		(addr, length)
+	} else {
+		(addr, length)
+	};
 	// The length in number of pages
 	let pages = length.div_ceil(PAGE_SIZE);
 	let Some(pages) = NonZeroUsize::new(pages) else {
@@ -63,15 +138,30 @@ pub fn do_mmap(
 		return Err(errno!(EINVAL));
 	}
 	let prot = prot as u8;
-	let flags = flags as u8;
+	let flags = {
+		let mut f = flags as u8 & !(MAP_GROWSDOWN | MAP_DENYWRITE | MAP_HUGETLB | MAP_MERGEABLE);
+		if flags & LINUX_MAP_GROWSDOWN != 0 {
+			f |= MAP_GROWSDOWN;
+		}
+		if hugetlb {
+			f |= MAP_HUGETLB;
+		}
+		// Only meaningful for file mappings
+		if flags & LINUX_MAP_DENYWRITE != 0 && flags & MAP_ANONYMOUS as i32 == 0 {
+			f |= MAP_DENYWRITE;
+		}
+		f
+	};
 	let constraint = {
-		if !addr.is_null() {
-			if flags & MAP_FIXED != 0 {
-				MapConstraint::Fixed(addr)
-			} else {
-				MapConstraint::Hint(addr)
+		if flags & MAP_FIXED != 0 {
+			if unlikely(!mem_space::mmap_addr_allowed(&ap, addr)) {
+				return Err(errno!(EPERM));
 			}
+			MapConstraint::Fixed(addr)
+		} else if mem_space::mmap_addr_allowed(&ap, addr) {
+			MapConstraint::Hint(addr)
 		} else {
+			// A hint below the minimum address is ignored
 			MapConstraint::None
 		}
 	};
@@ -85,6 +175,15 @@ pub fn do_mmap(
 		}
 		// Get file
 		let file = fds.lock().get_fd(fd)?.get_file().clone();
+		// Files whose content is not in the page cache provide their own pages
+		let off = offset / PAGE_SIZE as u64;
+		if let Some(frames) = file.ops.mmap_pages(&file, off, pages.get())? {
+			if unlikely(flags & MAP_SHARED == 0) {
+				return Err(errno!(EINVAL));
+			}
+			let ptr = mem_space.map_special(constraint, prot, flags, &frames)?;
+			return Ok(ptr as _);
+		}
 		// Check permissions
 		let stat = file.stat()?;
 		if stat.get_type() != Some(FileType::Regular) {
@@ -99,6 +198,24 @@ pub fn do_mmap(
 		if prot & PROT_EXEC != 0 && !ap.can_execute_file(&stat) {
 			return Err(errno!(EPERM));
 		}
+		let noexec = file
+			.vfs_entry
+			.as_ref()
+			.is_some_and(|ent| mountpoint::entry_has_flags(ent, FLAG_NOEXEC));
+		if prot & PROT_EXEC != 0 && noexec {
+			return Err(errno!(EPERM));
+		}
+		// A sealed file cannot be mapped for shared writing
+		let seals = file
+			.node()
+			.and_then(|node| tmp::get_seals(node))
+			.unwrap_or(0);
+		if flags & MAP_SHARED != 0
+			&& prot & PROT_WRITE != 0
+			&& seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0
+		{
+			return Err(errno!(EPERM));
+		}
 		Some(file)
 	} else {
 		None
@@ -176,9 +293,72 @@ pub fn brk(Args(addr): Args<VirtAddr>, mem_space: Arc<MemSpace>) -> EResult<usiz
 }
 
 pub fn madvise(
-	Args((_addr, _length, _advice)): Args<(*mut c_void, usize, c_int)>,
+	Args((addr, length, advice)): Args<(VirtAddr, usize, c_int)>,
+	mem_space: Arc<MemSpace>,
 ) -> EResult<usize> {
-	// TODO
+	if unlikely(!addr.is_aligned_to(PAGE_SIZE)) {
+		return Err(errno!(EINVAL));
+	}
+	match advice {
+		MADV_MERGEABLE => mem_space.set_mergeable(addr, length, true)?,
+		MADV_UNMERGEABLE => mem_space.set_mergeable(addr, length, false)?,
+		// TODO
+		_ => {}
+	}
+	Ok(0)
+}
+
+pub fn mbind(
+	Args((addr, len, mode, nodemask, maxnode, flags)): Args<(
+		VirtAddr,
+		usize,
+		c_int,
+		UserPtr<u32>,
+		usize,
+		c_uint,
+	)>,
+	mem_space: Arc<MemSpace>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	if unlikely(!addr.is_aligned_to(PAGE_SIZE)) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(flags & !(MPOL_MF_STRICT | MPOL_MF_MOVE | MPOL_MF_MOVE_ALL) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(flags & MPOL_MF_MOVE_ALL != 0 && !ap.has_capability(CAP_SYS_NICE)) {
+		return Err(errno!(EPERM));
+	}
+	let mode_flags = mode & (MPOL_F_STATIC_NODES | MPOL_F_RELATIVE_NODES);
+	if unlikely(mode_flags == MPOL_F_STATIC_NODES | MPOL_F_RELATIVE_NODES) {
+		return Err(errno!(EINVAL));
+	}
+	// The system has a single node, so only the first bit of the mask matters. For historical
+	// reasons, the last bit given by `maxnode` is ignored
+	let nodes = if maxnode > 1 {
+		nodemask.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?
+	} else {
+		0
+	};
+	let empty = nodes == 0;
+	let node0 = nodes & 1 != 0;
+	let valid = match mode & !mode_flags {
+		MPOL_DEFAULT | MPOL_LOCAL => empty && mode_flags == 0,
+		MPOL_PREFERRED => empty || node0,
+		MPOL_BIND | MPOL_INTERLEAVE => node0,
+		_ => false,
+	};
+	if unlikely(!valid) {
+		return Err(errno!(EINVAL));
+	}
+	let pages = len.div_ceil(PAGE_SIZE);
+	if unlikely(addr.0.checked_add(pages * PAGE_SIZE).is_none()) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(!mem_space.is_mapped(addr, pages)) {
+		return Err(errno!(EFAULT));
+	}
+	// All memory is located on the only node, so every policy is already satisfied
 	Ok(0)
 }
 
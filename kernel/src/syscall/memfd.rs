@@ -0,0 +1,98 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `memfd_create` system call creates an anonymous file living in memory.
+
+use crate::{
+	file::{
+		File, FileType, O_RDWR, Stat,
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+		fs::tmp,
+		perm::AccessProfile,
+	},
+	memory::user::UserString,
+	sync::mutex::Mutex,
+	syscall::Args,
+	time::clock::{Clock, current_time_sec},
+};
+use core::{ffi::c_uint, hint::unlikely};
+use utils::{collections::string::String, errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const MFD_CLOEXEC: c_uint = 1;
+/// Flag: allow seals to be added to the file.
+const MFD_ALLOW_SEALING: c_uint = 2;
+/// Flag: back the file with huge pages.
+const MFD_HUGETLB: c_uint = 4;
+/// Flag: the file is not executable.
+const MFD_NOEXEC_SEAL: c_uint = 8;
+/// Flag: the file is executable.
+const MFD_EXEC: c_uint = 0x10;
+
+/// The maximum length of the name, excluding the `memfd:` prefix.
+const NAME_MAX: usize = 249;
+
+pub fn memfd_create(
+	Args((name, flags)): Args<(UserString, c_uint)>,
+	ap: AccessProfile,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	if unlikely(flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING | MFD_NOEXEC_SEAL | MFD_EXEC) != 0) {
+		// Huge pages are not supported
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(flags & (MFD_NOEXEC_SEAL | MFD_EXEC) == MFD_NOEXEC_SEAL | MFD_EXEC) {
+		return Err(errno!(EINVAL));
+	}
+	let name = name.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	if unlikely(name.len() > NAME_MAX) {
+		return Err(errno!(EINVAL));
+	}
+	let mut full_name = String::try_from(b"memfd:")?;
+	full_name.push_str(name)?;
+	let perms = if flags & MFD_NOEXEC_SEAL != 0 {
+		0o666
+	} else {
+		0o777
+	};
+	let ts = current_time_sec(Clock::Realtime);
+	let stat = Stat {
+		mode: FileType::Regular.to_mode() | perms,
+		uid: ap.euid,
+		gid: ap.egid,
+		ctime: ts,
+		mtime: ts,
+		atime: ts,
+		..Default::default()
+	};
+	// Sealing implies being able to add seals
+	let seals = if flags & (MFD_ALLOW_SEALING | MFD_NOEXEC_SEAL) != 0 {
+		0
+	} else {
+		tmp::F_SEAL_SEAL
+	};
+	let ent = tmp::create_anonymous(full_name, stat, seals)?;
+	let file = File::open_entry(ent, O_RDWR)?;
+	let fd_flags = if flags & MFD_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.lock().create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
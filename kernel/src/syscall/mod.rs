@@ -22,24 +22,40 @@
 //! command: `man 2 <syscall>`
 
 mod dirent;
+mod epoll;
+mod eventfd;
 mod execve;
 mod fcntl;
 mod fd;
 mod fs;
+mod futex;
 mod getrandom;
 mod host;
+mod inotify;
 pub mod ioctl;
+mod ioprio;
+mod ipc;
 mod mem;
+mod memfd;
 mod module;
 mod mount;
+mod mqueue;
+mod msg;
+mod ns;
 mod pipe;
 mod process;
+mod sched;
 pub mod select;
+mod sem;
+mod shm;
 mod signal;
+mod signalfd;
 mod socket;
 mod stat;
+pub mod stats;
 mod sync;
 mod time;
+mod timerfd;
 mod user;
 mod util;
 mod wait;
@@ -47,60 +63,88 @@ mod wait;
 use crate::{
 	arch::x86::idt::IntFrame,
 	file::{Mode, fd::FileDescriptorTable, perm::AccessProfile, vfs::ResolutionSettings},
-	process::{Process, mem_space::MemSpace, signal::Signal, yield_current},
+	process::{
+		Process, mem_space::MemSpace, seccomp, seccomp::Action, signal::Signal, yield_current,
+	},
 	sync::mutex::Mutex,
 	syscall::{
 		dirent::{getdents, getdents64},
+		epoll::{epoll_create, epoll_create1, epoll_ctl, epoll_wait},
+		eventfd::{eventfd, eventfd2},
 		execve::execve,
 		fcntl::{fcntl, fcntl64},
 		fd::{
-			_llseek, close, dup, dup2, lseek, preadv, preadv2, pwritev, pwritev2, read, readv,
-			write, writev,
+			_llseek, close, close_range, compat_lseek, dup, dup2, lseek, preadv, preadv2, pwritev,
+			pwritev2, read, readv, write, writev,
 		},
 		fs::{
 			access, chdir, chmod, chown, chroot, creat, faccessat, faccessat2, fadvise64_64,
-			fchdir, fchmod, fchmodat, getcwd, lchown, link, linkat, mkdir, mknod, open, openat,
-			readlink, rename, renameat2, rmdir, symlink, symlinkat, truncate, umask, unlink,
-			unlinkat, utimensat,
+			fallocate, fallocate32, fchdir, fchmod, fchmodat, ftruncate, ftruncate64, getcwd,
+			lchown, link, linkat, mkdir, mknod, open, openat, readlink, rename, renameat2, rmdir,
+			symlink, symlinkat, truncate, truncate64, umask, unlink, unlinkat, utimensat,
 		},
+		futex::{futex32, futex64},
 		getrandom::getrandom,
-		host::{reboot, sethostname, uname},
+		host::{reboot, setdomainname, sethostname, uname},
+		inotify::{inotify_add_watch, inotify_init, inotify_init1, inotify_rm_watch},
 		ioctl::ioctl,
-		mem::{brk, madvise, mmap, mmap2, mprotect, munmap},
+		ioprio::{ioprio_get, ioprio_set},
+		ipc::compat_ipc,
+		mem::{brk, madvise, mbind, mmap, mmap2, mprotect, munmap},
+		memfd::memfd_create,
 		module::{delete_module, finit_module, init_module},
-		mount::{mount, umount, umount2},
+		mount::{mount, pivot_root, umount, umount2},
+		mqueue::{
+			compat_mq_getsetattr, compat_mq_open, mq_getsetattr, mq_notify, mq_open,
+			mq_timedreceive32, mq_timedreceive64, mq_timedsend32, mq_timedsend64, mq_unlink,
+		},
+		msg::{compat_msgctl, msgctl, msgget, msgrcv, msgsnd},
+		ns::{setns, unshare},
 		pipe::{pipe, pipe2},
 		process::{
-			_exit, arch_prctl, clone, compat_clone, exit_group, fork, getpgid, getpid, getppid,
-			getrusage, gettid, prlimit64, sched_yield, set_thread_area, set_tid_address, setpgid,
-			vfork,
+			_exit, arch_prctl, clone, compat_clone, exit_group, fork, get_thread_area, getpgid,
+			getpid, getppid, getrusage, gettid, prctl, prlimit64, sched_yield, seccomp,
+			set_thread_area, set_tid_address, setpgid, times, vfork,
+		},
+		sched::{
+			getpriority, nice, sched_get_priority_max, sched_get_priority_min, sched_getaffinity,
+			sched_getparam, sched_getscheduler, sched_setaffinity, sched_setparam,
+			sched_setscheduler, setpriority,
 		},
-		select::{_newselect, poll, pselect6, select},
+		select::{_newselect, poll, ppoll, pselect6, select},
+		sem::{compat_semctl, semctl, semget, semop, semtimedop},
+		shm::{compat_shmctl, shmat, shmctl, shmdt, shmget},
 		signal::{
-			compat_rt_sigaction, kill, rt_sigaction, rt_sigprocmask, rt_sigreturn, signal,
-			sigreturn, tkill,
+			compat_rt_sigaction, compat_sigaltstack, kill, rt_sigaction, rt_sigprocmask,
+			rt_sigreturn, sigaltstack, signal, sigreturn, tkill,
 		},
+		signalfd::{signalfd, signalfd4},
 		socket::{
-			bind, connect, getsockname, getsockopt, sendto, setsockopt, shutdown, socket,
-			socketpair,
+			bind, compat_recvmsg, compat_sendmsg, connect, getpeername, getsockname, getsockopt,
+			recvfrom, recvmsg, sendmsg, sendto, setsockopt, shutdown, socket, socketpair,
 		},
 		stat::{
-			fstat, fstat64, fstatfs, fstatfs64, lstat, lstat64, stat, stat64, statfs, statfs64,
-			statx,
+			compat_fstat64, compat_lstat64, compat_stat64, fstat, fstat64, fstatfs, fstatfs64,
+			lstat, lstat64, stat, stat64, statfs, statfs64, statx,
 		},
 		sync::{fdatasync, fsync, msync, sync, syncfs},
 		time::{
-			clock_gettime, clock_gettime64, nanosleep32, nanosleep64, time32, time64,
+			clock_getres32, clock_getres64, clock_gettime, clock_gettime32, clock_gettime64,
+			gettimeofday32, gettimeofday64, nanosleep32, nanosleep64, time32, time64,
 			timer_create, timer_delete, timer_settime,
 		},
+		timerfd::{
+			timerfd_create, timerfd_gettime32, timerfd_gettime64, timerfd_settime32,
+			timerfd_settime64,
+		},
 		user::{
-			getegid, geteuid, getgid, getresgid, getresuid, getuid, setgid, setregid, setresgid,
-			setresuid, setreuid, setuid,
+			capget, capset, getegid, geteuid, getgid, getresgid, getresuid, getuid, setgid,
+			setregid, setresgid, setresuid, setreuid, setuid,
 		},
 		wait::{wait4, waitpid},
 	},
 };
-use core::{fmt, hint::unlikely, ops::Deref, ptr};
+use core::{fmt, hint::unlikely, ptr};
 use utils::{
 	errno,
 	errno::{ENOSYS, EResult},
@@ -207,7 +251,7 @@ impl FromSyscall for Arc<MemSpace> {
 impl FromSyscall for Arc<Mutex<FileDescriptorTable>> {
 	#[inline]
 	fn from_syscall(_frame: &IntFrame) -> Self {
-		Process::current().file_descriptors.deref().clone().unwrap()
+		Process::current().file_descriptors.get().unwrap()
 	}
 }
 
@@ -329,7 +373,9 @@ impl<T> FromSyscallArg for *mut T {
 /// Syscall declaration.
 macro_rules! syscall {
 	($name:ident, $frame:expr) => {
-		SyscallHandler::call($name, stringify!($name), $frame)
+		stats::measure(stringify!($name), || {
+			SyscallHandler::call($name, stringify!($name), $frame)
+		})
 	};
 }
 
@@ -354,7 +400,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x010 => syscall!(lchown, frame),
 		// 0x011: unimplemented (break)
 		// TODO 0x012 => syscall!(oldstat, frame),
-		// TODO 0x013 => syscall!(lseek, frame),
+		0x013 => syscall!(compat_lseek, frame),
 		0x014 => syscall!(getpid, frame),
 		0x015 => syscall!(mount, frame),
 		0x016 => syscall!(umount, frame),
@@ -369,7 +415,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// 0x01f: unimplemented (stty),
 		// 0x020: unimplemented_syscall (gtty)
 		0x021 => syscall!(access, frame),
-		// TODO 0x022 => syscall!(nice, frame),
+		0x022 => syscall!(nice, frame),
 		// 0x023: unimplemented (ftime),
 		0x024 => syscall!(sync, frame),
 		0x025 => syscall!(kill, frame),
@@ -378,7 +424,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x028 => syscall!(rmdir, frame),
 		0x029 => syscall!(dup, frame),
 		0x02a => syscall!(pipe, frame),
-		// TODO 0x02b => syscall!(times, frame),
+		0x02b => syscall!(times, frame),
 		// 0x02c: unimplemented (prof),
 		0x02d => syscall!(brk, frame),
 		0x02e => syscall!(setgid, frame),
@@ -413,7 +459,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x04b => syscall!(setrlimit, frame),
 		// TODO 0x04c => syscall!(getrlimit, frame),
 		0x04d => syscall!(getrusage, frame),
-		// TODO 0x04e => syscall!(gettimeofday, frame),
+		0x04e => syscall!(gettimeofday32, frame),
 		// TODO 0x04f => syscall!(settimeofday, frame),
 		// TODO 0x050 => syscall!(getgroups, frame),
 		// TODO 0x051 => syscall!(setgroups, frame),
@@ -428,11 +474,11 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x05a => syscall!(mmap, frame),
 		0x05b => syscall!(munmap, frame),
 		0x05c => syscall!(truncate, frame),
-		// TODO 0x05d => syscall!(ftruncate, frame),
+		0x05d => syscall!(ftruncate, frame),
 		0x05e => syscall!(fchmod, frame),
 		// TODO 0x05f => syscall!(fchown, frame),
-		// TODO 0x060 => syscall!(getpriority, frame),
-		// TODO 0x061 => syscall!(setpriority, frame),
+		0x060 => syscall!(getpriority, frame),
+		0x061 => syscall!(setpriority, frame),
 		// 0x062: unimplemented (profil),
 		0x063 => syscall!(statfs, frame),
 		0x064 => syscall!(fstatfs, frame),
@@ -452,11 +498,11 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x072 => syscall!(wait4, frame),
 		// TODO 0x073 => syscall!(swapoff, frame),
 		// TODO 0x074 => syscall!(sysinfo, frame),
-		// TODO 0x075 => syscall!(ipc, frame),
+		0x075 => syscall!(compat_ipc, frame),
 		0x076 => syscall!(fsync, frame),
 		SIGRETURN_ID => syscall!(sigreturn, frame),
 		0x078 => syscall!(compat_clone, frame),
-		// TODO 0x079 => syscall!(setdomainname, frame),
+		0x079 => syscall!(setdomainname, frame),
 		0x07a => syscall!(uname, frame),
 		// TODO 0x07c => syscall!(adjtimex, frame),
 		0x07d => syscall!(mprotect, frame),
@@ -487,13 +533,13 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x097 => syscall!(munlock, frame),
 		// TODO 0x098 => syscall!(mlockall, frame),
 		// TODO 0x099 => syscall!(munlockall, frame),
-		// TODO 0x09a => syscall!(sched_setparam, frame),
-		// TODO 0x09b => syscall!(sched_getparam, frame),
-		// TODO 0x09c => syscall!(sched_setscheduler, frame),
-		// TODO 0x09d => syscall!(sched_getscheduler, frame),
+		0x09a => syscall!(sched_setparam, frame),
+		0x09b => syscall!(sched_getparam, frame),
+		0x09c => syscall!(sched_setscheduler, frame),
+		0x09d => syscall!(sched_getscheduler, frame),
 		0x09e => syscall!(sched_yield, frame),
-		// TODO 0x09f => syscall!(sched_get_priority_max, frame),
-		// TODO 0x0a0 => syscall!(sched_get_priority_min, frame),
+		0x09f => syscall!(sched_get_priority_max, frame),
+		0x0a0 => syscall!(sched_get_priority_min, frame),
 		// TODO 0x0a1 => syscall!(sched_rr_get_interval, frame),
 		0x0a2 => syscall!(nanosleep32, frame),
 		// TODO 0x0a3 => syscall!(mremap, frame),
@@ -505,7 +551,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0a9 => syscall!(nfsservctl, frame),
 		0x0aa => syscall!(setresgid, frame),
 		0x0ab => syscall!(getresgid, frame),
-		// TODO 0x0ac => syscall!(prctl, frame),
+		0x0ac => syscall!(prctl, frame),
 		0x0ad => syscall!(sigreturn, frame),
 		0x0ae => syscall!(compat_rt_sigaction, frame),
 		0x0af => syscall!(rt_sigprocmask, frame),
@@ -517,20 +563,20 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0b5 => syscall!(pwrite64, frame),
 		0x0b6 => syscall!(chown, frame),
 		0x0b7 => syscall!(getcwd, frame),
-		// TODO 0x0b8 => syscall!(capget, frame),
-		// TODO 0x0b9 => syscall!(capset, frame),
-		// TODO 0x0ba => syscall!(sigaltstack, frame),
+		0x0b8 => syscall!(capget, frame),
+		0x0b9 => syscall!(capset, frame),
+		0x0ba => syscall!(compat_sigaltstack, frame),
 		// TODO 0x0bb => syscall!(sendfile, frame),
 		// 0x0bc: unimplemented (getpmsg),
 		// 0x0bd: unimplemented (putpmsg),
 		0x0be => syscall!(vfork, frame),
 		// TODO 0x0bf => syscall!(ugetrlimit, frame),
 		0x0c0 => syscall!(mmap2, frame),
-		// TODO 0x0c1 => syscall!(truncate64, frame),
-		// TODO 0x0c2 => syscall!(ftruncate64, frame),
-		0x0c3 => syscall!(stat64, frame),
-		0x0c4 => syscall!(lstat64, frame),
-		0x0c5 => syscall!(fstat64, frame),
+		0x0c1 => syscall!(truncate64, frame),
+		0x0c2 => syscall!(ftruncate64, frame),
+		0x0c3 => syscall!(compat_stat64, frame),
+		0x0c4 => syscall!(compat_lstat64, frame),
+		0x0c5 => syscall!(compat_fstat64, frame),
 		// TODO 0x0c6 => syscall!(lchown32, frame),
 		0x0c7 => syscall!(getuid, frame),   // getuid32
 		0x0c8 => syscall!(getgid, frame),   // getgid32
@@ -550,7 +596,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x0d6 => syscall!(setgid, frame),    // setgid32
 		// TODO 0x0d7 => syscall!(setfsuid32, frame),
 		// TODO 0x0d8 => syscall!(setfsgid32, frame),
-		// TODO 0x0d9 => syscall!(pivot_root, frame),
+		0x0d9 => syscall!(pivot_root, frame),
 		// TODO 0x0da => syscall!(mincore, frame),
 		0x0db => syscall!(madvise, frame),
 		0x0dc => syscall!(getdents64, frame),
@@ -571,11 +617,11 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0ed => syscall!(fremovexattr, frame),
 		0x0ee => syscall!(tkill, frame),
 		// TODO 0x0ef => syscall!(sendfile64, frame),
-		// TODO 0x0f0 => syscall!(futex, frame),
-		// TODO 0x0f1 => syscall!(sched_setaffinity, frame),
-		// TODO 0x0f2 => syscall!(sched_getaffinity, frame),
+		0x0f0 => syscall!(futex32, frame),
+		0x0f1 => syscall!(sched_setaffinity, frame),
+		0x0f2 => syscall!(sched_getaffinity, frame),
 		0x0f3 => syscall!(set_thread_area, frame),
-		// TODO 0x0f4 => syscall!(get_thread_area, frame),
+		0x0f4 => syscall!(get_thread_area, frame),
 		// TODO 0x0f5 => syscall!(io_setup, frame),
 		// TODO 0x0f6 => syscall!(io_destroy, frame),
 		// TODO 0x0f7 => syscall!(io_getevents, frame),
@@ -584,9 +630,9 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0fa => syscall!(fadvise64, frame),
 		0x0fc => syscall!(exit_group, frame),
 		// TODO 0x0fd => syscall!(lookup_dcookie, frame),
-		// TODO 0x0fe => syscall!(epoll_create, frame),
-		// TODO 0x0ff => syscall!(epoll_ctl, frame),
-		// TODO 0x100 => syscall!(epoll_wait, frame),
+		0x0fe => syscall!(epoll_create, frame),
+		0x0ff => syscall!(epoll_ctl, frame),
+		0x100 => syscall!(epoll_wait, frame),
 		// TODO 0x101 => syscall!(remap_file_pages, frame),
 		0x102 => syscall!(set_tid_address, frame),
 		0x103 => syscall!(timer_create, frame),
@@ -595,8 +641,8 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x106 => syscall!(timer_getoverrun, frame),
 		0x107 => syscall!(timer_delete, frame),
 		// TODO 0x108 => syscall!(clock_settime, frame),
-		0x109 => syscall!(clock_gettime, frame),
-		// TODO 0x10a => syscall!(clock_getres, frame),
+		0x109 => syscall!(clock_gettime32, frame),
+		0x10a => syscall!(clock_getres32, frame),
 		// TODO 0x10b => syscall!(clock_nanosleep, frame),
 		0x10c => syscall!(statfs64, frame),
 		0x10d => syscall!(fstatfs64, frame),
@@ -604,25 +650,25 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x10f => syscall!(utimes, frame),
 		0x110 => syscall!(fadvise64_64, frame),
 		// 0x111: unimplemented (vserver),
-		// TODO 0x112 => syscall!(mbind, frame),
+		0x112 => syscall!(mbind, frame),
 		// TODO 0x113 => syscall!(get_mempolicy, frame),
 		// TODO 0x114 => syscall!(set_mempolicy, frame),
-		// TODO 0x115 => syscall!(mq_open, frame),
-		// TODO 0x116 => syscall!(mq_unlink, frame),
-		// TODO 0x117 => syscall!(mq_timedsend, frame),
-		// TODO 0x118 => syscall!(mq_timedreceive, frame),
-		// TODO 0x119 => syscall!(mq_notify, frame),
-		// TODO 0x11a => syscall!(mq_getsetattr, frame),
+		0x115 => syscall!(compat_mq_open, frame),
+		0x116 => syscall!(mq_unlink, frame),
+		0x117 => syscall!(mq_timedsend32, frame),
+		0x118 => syscall!(mq_timedreceive32, frame),
+		0x119 => syscall!(mq_notify, frame),
+		0x11a => syscall!(compat_mq_getsetattr, frame),
 		// TODO 0x11b => syscall!(kexec_load, frame),
 		// TODO 0x11c => syscall!(waitid, frame),
 		// TODO 0x11e => syscall!(add_key, frame),
 		// TODO 0x11f => syscall!(request_key, frame),
 		// TODO 0x120 => syscall!(keyctl, frame),
-		// TODO 0x121 => syscall!(ioprio_set, frame),
-		// TODO 0x122 => syscall!(ioprio_get, frame),
-		// TODO 0x123 => syscall!(inotify_init, frame),
-		// TODO 0x124 => syscall!(inotify_add_watch, frame),
-		// TODO 0x125 => syscall!(inotify_rm_watch, frame),
+		0x121 => syscall!(ioprio_set, frame),
+		0x122 => syscall!(ioprio_get, frame),
+		0x123 => syscall!(inotify_init, frame),
+		0x124 => syscall!(inotify_add_watch, frame),
+		0x125 => syscall!(inotify_rm_watch, frame),
 		// TODO 0x126 => syscall!(migrate_pages, frame),
 		0x127 => syscall!(openat, frame),
 		// TODO 0x128 => syscall!(mkdirat, frame),
@@ -638,8 +684,8 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x132 => syscall!(fchmodat, frame),
 		0x133 => syscall!(faccessat, frame),
 		0x134 => syscall!(pselect6, frame),
-		// TODO 0x135 => syscall!(ppoll, frame),
-		// TODO 0x136 => syscall!(unshare, frame),
+		0x135 => syscall!(ppoll, frame),
+		0x136 => syscall!(unshare, frame),
 		// TODO 0x137 => syscall!(set_robust_list, frame),
 		// TODO 0x138 => syscall!(get_robust_list, frame),
 		// TODO 0x139 => syscall!(splice, frame),
@@ -650,18 +696,18 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x13e => syscall!(getcpu, frame),
 		// TODO 0x13f => syscall!(epoll_pwait, frame),
 		0x140 => syscall!(utimensat, frame),
-		// TODO 0x141 => syscall!(signalfd, frame),
-		// TODO 0x142 => syscall!(timerfd_create, frame),
-		// TODO 0x143 => syscall!(eventfd, frame),
-		// TODO 0x144 => syscall!(fallocate, frame),
-		// TODO 0x145 => syscall!(timerfd_settime, frame),
-		// TODO 0x146 => syscall!(timerfd_gettime, frame),
-		// TODO 0x147 => syscall!(signalfd4, frame),
-		// TODO 0x148 => syscall!(eventfd2, frame),
-		// TODO 0x149 => syscall!(epoll_create1, frame),
+		0x141 => syscall!(signalfd, frame),
+		0x142 => syscall!(timerfd_create, frame),
+		0x143 => syscall!(eventfd, frame),
+		0x144 => syscall!(fallocate32, frame),
+		0x145 => syscall!(timerfd_settime32, frame),
+		0x146 => syscall!(timerfd_gettime32, frame),
+		0x147 => syscall!(signalfd4, frame),
+		0x148 => syscall!(eventfd2, frame),
+		0x149 => syscall!(epoll_create1, frame),
 		// TODO 0x14a => syscall!(dup3, frame),
 		0x14b => syscall!(pipe2, frame),
-		// TODO 0x14c => syscall!(inotify_init1, frame),
+		0x14c => syscall!(inotify_init1, frame),
 		0x14d => syscall!(preadv, frame),
 		0x14e => syscall!(pwritev, frame),
 		// TODO 0x14f => syscall!(rt_tgsigqueueinfo, frame),
@@ -675,7 +721,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x157 => syscall!(clock_adjtime, frame),
 		0x158 => syscall!(syncfs, frame),
 		// TODO 0x159 => syscall!(sendmmsg, frame),
-		// TODO 0x15a => syscall!(setns, frame),
+		0x15a => syscall!(setns, frame),
 		// TODO 0x15b => syscall!(process_vm_readv, frame),
 		// TODO 0x15c => syscall!(process_vm_writev, frame),
 		// TODO 0x15d => syscall!(kcmp, frame),
@@ -683,9 +729,9 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x15f => syscall!(sched_setattr, frame),
 		// TODO 0x160 => syscall!(sched_getattr, frame),
 		0x161 => syscall!(renameat2, frame),
-		// TODO 0x162 => syscall!(seccomp, frame),
+		0x162 => syscall!(seccomp, frame),
 		0x163 => syscall!(getrandom, frame),
-		// TODO 0x164 => syscall!(memfd_create, frame),
+		0x164 => syscall!(memfd_create, frame),
 		// TODO 0x165 => syscall!(bpf, frame),
 		// TODO 0x166 => syscall!(execveat, frame),
 		0x167 => syscall!(socket, frame),
@@ -697,11 +743,11 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x16d => syscall!(getsockopt, frame),
 		0x16e => syscall!(setsockopt, frame),
 		0x16f => syscall!(getsockname, frame),
-		// TODO 0x170 => syscall!(getpeername, frame),
+		0x170 => syscall!(getpeername, frame),
 		0x171 => syscall!(sendto, frame),
-		// TODO 0x172 => syscall!(sendmsg, frame),
-		// TODO 0x173 => syscall!(recvfrom, frame),
-		// TODO 0x174 => syscall!(recvmsg, frame),
+		0x172 => syscall!(compat_sendmsg, frame),
+		0x173 => syscall!(recvfrom, frame),
+		0x174 => syscall!(compat_recvmsg, frame),
 		0x175 => syscall!(shutdown, frame),
 		// TODO 0x176 => syscall!(userfaultfd, frame),
 		// TODO 0x177 => syscall!(membarrier, frame),
@@ -716,35 +762,35 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x180 => syscall!(arch_prctl, frame),
 		// TODO 0x181 => syscall!(io_pgetevents, frame),
 		// TODO 0x182 => syscall!(rseq, frame),
-		// TODO 0x189 => syscall!(semget, frame),
-		// TODO 0x18a => syscall!(semctl, frame),
-		// TODO 0x18b => syscall!(shmget, frame),
-		// TODO 0x18c => syscall!(shmctl, frame),
-		// TODO 0x18d => syscall!(shmat, frame),
-		// TODO 0x18e => syscall!(shmdt, frame),
-		// TODO 0x18f => syscall!(msgget, frame),
-		// TODO 0x190 => syscall!(msgsnd, frame),
-		// TODO 0x191 => syscall!(msgrcv, frame),
-		// TODO 0x192 => syscall!(msgctl, frame),
+		0x189 => syscall!(semget, frame),
+		0x18a => syscall!(compat_semctl, frame),
+		0x18b => syscall!(shmget, frame),
+		0x18c => syscall!(compat_shmctl, frame),
+		0x18d => syscall!(shmat, frame),
+		0x18e => syscall!(shmdt, frame),
+		0x18f => syscall!(msgget, frame),
+		0x190 => syscall!(msgsnd, frame),
+		0x191 => syscall!(msgrcv, frame),
+		0x192 => syscall!(compat_msgctl, frame),
 		0x193 => syscall!(clock_gettime64, frame),
 		// TODO 0x194 => syscall!(clock_settime64, frame),
 		// TODO 0x195 => syscall!(clock_adjtime64, frame),
-		// TODO 0x196 => syscall!(clock_getres_time64, frame),
+		0x196 => syscall!(clock_getres64, frame),
 		// TODO 0x197 => syscall!(clock_nanosleep_time64, frame),
 		// TODO 0x198 => syscall!(timer_gettime64, frame),
 		// TODO 0x199 => syscall!(timer_settime64, frame),
-		// TODO 0x19a => syscall!(timerfd_gettime64, frame),
-		// TODO 0x19b => syscall!(timerfd_settime64, frame),
+		0x19a => syscall!(timerfd_gettime64, frame),
+		0x19b => syscall!(timerfd_settime64, frame),
 		// TODO 0x19c => syscall!(utimensat_time64, frame),
 		// TODO 0x19d => syscall!(pselect6_time64, frame),
 		// TODO 0x19e => syscall!(ppoll_time64, frame),
 		// TODO 0x1a0 => syscall!(io_pgetevents_time64, frame),
 		// TODO 0x1a1 => syscall!(recvmmsg_time64, frame),
-		// TODO 0x1a2 => syscall!(mq_timedsend_time64, frame),
-		// TODO 0x1a3 => syscall!(mq_timedreceive_time64, frame),
-		// TODO 0x1a4 => syscall!(semtimedop_time64, frame),
+		0x1a2 => syscall!(mq_timedsend64, frame),
+		0x1a3 => syscall!(mq_timedreceive64, frame),
+		0x1a4 => syscall!(semtimedop, frame),
 		// TODO 0x1a5 => syscall!(rt_sigtimedwait_time64, frame),
-		// TODO 0x1a6 => syscall!(futex_time64, frame),
+		0x1a6 => syscall!(futex64, frame),
 		// TODO 0x1a7 => syscall!(sched_rr_get_interval_time64, frame),
 		// TODO 0x1a8 => syscall!(pidfd_send_signal, frame),
 		// TODO 0x1a9 => syscall!(io_uring_setup, frame),
@@ -758,7 +804,7 @@ fn do_syscall32(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x1b1 => syscall!(fspick, frame),
 		// TODO 0x1b2 => syscall!(pidfd_open, frame),
 		// TODO 0x1b3 => syscall!(clone3, frame),
-		// TODO 0x1b4 => syscall!(close_range, frame),
+		0x1b4 => syscall!(close_range, frame),
 		// TODO 0x1b5 => syscall!(openat2, frame),
 		// TODO 0x1b6 => syscall!(pidfd_getfd, frame),
 		0x1b7 => syscall!(faccessat2, frame),
@@ -810,9 +856,9 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x01a => syscall!(msync, frame),
 		// TODO 0x01b => syscall!(mincore, frame),
 		0x01c => syscall!(madvise, frame),
-		// TODO 0x01d => syscall!(shmget, frame),
-		// TODO 0x01e => syscall!(shmat, frame),
-		// TODO 0x01f => syscall!(shmctl, frame),
+		0x01d => syscall!(shmget, frame),
+		0x01e => syscall!(shmat, frame),
+		0x01f => syscall!(shmctl, frame),
 		0x020 => syscall!(dup, frame),
 		0x021 => syscall!(dup2, frame),
 		// TODO 0x022 => syscall!(pause, frame),
@@ -826,14 +872,14 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x02a => syscall!(connect, frame),
 		// TODO 0x02b => syscall!(accept, frame),
 		0x02c => syscall!(sendto, frame),
-		// TODO 0x02d => syscall!(recvfrom, frame),
-		// TODO 0x02e => syscall!(sendmsg, frame),
-		// TODO 0x02f => syscall!(recvmsg, frame),
+		0x02d => syscall!(recvfrom, frame),
+		0x02e => syscall!(sendmsg, frame),
+		0x02f => syscall!(recvmsg, frame),
 		0x030 => syscall!(shutdown, frame),
 		0x031 => syscall!(bind, frame),
 		// TODO 0x032 => syscall!(listen, frame),
 		0x033 => syscall!(getsockname, frame),
-		// TODO 0x034 => syscall!(getpeername, frame),
+		0x034 => syscall!(getpeername, frame),
 		0x035 => syscall!(socketpair, frame),
 		0x036 => syscall!(setsockopt, frame),
 		0x037 => syscall!(getsockopt, frame),
@@ -841,24 +887,24 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x039 => syscall!(fork, frame),
 		0x03a => syscall!(vfork, frame),
 		0x03b => syscall!(execve, frame),
-		// TODO 0x03c => syscall!(exit, frame),
+		0x03c => syscall!(_exit, frame),
 		0x03d => syscall!(wait4, frame),
 		0x03e => syscall!(kill, frame),
 		0x03f => syscall!(uname, frame),
-		// TODO 0x040 => syscall!(semget, frame),
-		// TODO 0x041 => syscall!(semop, frame),
-		// TODO 0x042 => syscall!(semctl, frame),
-		// TODO 0x043 => syscall!(shmdt, frame),
-		// TODO 0x044 => syscall!(msgget, frame),
-		// TODO 0x045 => syscall!(msgsnd, frame),
-		// TODO 0x046 => syscall!(msgrcv, frame),
-		// TODO 0x047 => syscall!(msgctl, frame),
+		0x040 => syscall!(semget, frame),
+		0x041 => syscall!(semop, frame),
+		0x042 => syscall!(semctl, frame),
+		0x043 => syscall!(shmdt, frame),
+		0x044 => syscall!(msgget, frame),
+		0x045 => syscall!(msgsnd, frame),
+		0x046 => syscall!(msgrcv, frame),
+		0x047 => syscall!(msgctl, frame),
 		0x048 => syscall!(fcntl, frame),
 		// TODO 0x049 => syscall!(flock, frame),
 		0x04a => syscall!(fsync, frame),
 		0x04b => syscall!(fdatasync, frame),
 		0x04c => syscall!(truncate, frame),
-		// TODO 0x04d => syscall!(ftruncate, frame),
+		0x04d => syscall!(ftruncate, frame),
 		0x04e => syscall!(getdents, frame),
 		0x04f => syscall!(getcwd, frame),
 		0x050 => syscall!(chdir, frame),
@@ -877,11 +923,11 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x05d => syscall!(fchown, frame),
 		0x05e => syscall!(lchown, frame),
 		0x05f => syscall!(umask, frame),
-		// TODO 0x060 => syscall!(gettimeofday, frame),
+		0x060 => syscall!(gettimeofday64, frame),
 		// TODO 0x061 => syscall!(getrlimit, frame),
 		0x062 => syscall!(getrusage, frame),
 		// TODO 0x063 => syscall!(sysinfo, frame),
-		// TODO 0x064 => syscall!(times, frame),
+		0x064 => syscall!(times, frame),
 		// TODO 0x065 => syscall!(ptrace, frame),
 		0x066 => syscall!(getuid, frame),
 		// TODO 0x067 => syscall!(syslog, frame),
@@ -906,13 +952,13 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x07a => syscall!(setfsuid, frame),
 		// TODO 0x07b => syscall!(setfsgid, frame),
 		// TODO 0x07c => syscall!(getsid, frame),
-		// TODO 0x07d => syscall!(capget, frame),
-		// TODO 0x07e => syscall!(capset, frame),
+		0x07d => syscall!(capget, frame),
+		0x07e => syscall!(capset, frame),
 		// TODO 0x07f => syscall!(rt_sigpending, frame),
 		// TODO 0x080 => syscall!(rt_sigtimedwait, frame),
 		// TODO 0x081 => syscall!(rt_sigqueueinfo, frame),
 		// TODO 0x082 => syscall!(rt_sigsuspend, frame),
-		// TODO 0x083 => syscall!(sigaltstack, frame),
+		0x083 => syscall!(sigaltstack, frame),
 		// TODO 0x084 => syscall!(utime, frame),
 		0x085 => syscall!(mknod, frame),
 		// TODO 0x086 => syscall!(useli, frame),
@@ -921,14 +967,14 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x089 => syscall!(statfs, frame),
 		0x08a => syscall!(fstatfs, frame),
 		// TODO 0x08b => syscall!(sysfs, frame),
-		// TODO 0x08c => syscall!(getpriority, frame),
-		// TODO 0x08d => syscall!(setpriority, frame),
-		// TODO 0x08e => syscall!(sched_setparam, frame),
-		// TODO 0x08f => syscall!(sched_getparam, frame),
-		// TODO 0x090 => syscall!(sched_setscheduler, frame),
-		// TODO 0x091 => syscall!(sched_getscheduler, frame),
-		// TODO 0x092 => syscall!(sched_get_priority_max, frame),
-		// TODO 0x093 => syscall!(sched_get_priority_min, frame),
+		0x08c => syscall!(getpriority, frame),
+		0x08d => syscall!(setpriority, frame),
+		0x08e => syscall!(sched_setparam, frame),
+		0x08f => syscall!(sched_getparam, frame),
+		0x090 => syscall!(sched_setscheduler, frame),
+		0x091 => syscall!(sched_getscheduler, frame),
+		0x092 => syscall!(sched_get_priority_max, frame),
+		0x093 => syscall!(sched_get_priority_min, frame),
 		// TODO 0x094 => syscall!(sched_rr_get_interval, frame),
 		// TODO 0x095 => syscall!(mlock, frame),
 		// TODO 0x096 => syscall!(munlock, frame),
@@ -936,9 +982,9 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x098 => syscall!(munlockall, frame),
 		// TODO 0x099 => syscall!(vhangup, frame),
 		// TODO 0x09a => syscall!(modify_ldt, frame),
-		// TODO 0x09b => syscall!(pivot_root, frame),
+		0x09b => syscall!(pivot_root, frame),
 		// TODO 0x09c => syscall!(_sysctl, frame),
-		// TODO 0x09d => syscall!(prctl, frame),
+		0x09d => syscall!(prctl, frame),
 		0x09e => syscall!(arch_prctl, frame),
 		// TODO 0x09f => syscall!(adjtimex, frame),
 		// TODO 0x0a0 => syscall!(setrlimit, frame),
@@ -952,7 +998,7 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0a8 => syscall!(swapoff, frame),
 		0x0a9 => syscall!(reboot, frame),
 		0x0aa => syscall!(sethostname, frame),
-		// TODO 0x0ab => syscall!(setdomainname, frame),
+		0x0ab => syscall!(setdomainname, frame),
 		// TODO 0x0ac => syscall!(iopl, frame),
 		// TODO 0x0ad => syscall!(ioperm, frame),
 		// TODO 0x0ae => syscall!(create_modul, frame),
@@ -983,25 +1029,25 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x0c7 => syscall!(fremovexattr, frame),
 		0x0c8 => syscall!(tkill, frame),
 		0x0c9 => syscall!(time64, frame),
-		// TODO 0x0ca => syscall!(futex, frame),
-		// TODO 0x0cb => syscall!(sched_setaffinity, frame),
-		// TODO 0x0cc => syscall!(sched_getaffinity, frame),
-		// TODO 0x0cd => syscall!(set_thread_are, frame),
+		0x0ca => syscall!(futex64, frame),
+		0x0cb => syscall!(sched_setaffinity, frame),
+		0x0cc => syscall!(sched_getaffinity, frame),
+		0x0cd => syscall!(set_thread_area, frame),
 		// TODO 0x0ce => syscall!(io_setup, frame),
 		// TODO 0x0cf => syscall!(io_destroy, frame),
 		// TODO 0x0d0 => syscall!(io_getevents, frame),
 		// TODO 0x0d1 => syscall!(io_submit, frame),
 		// TODO 0x0d2 => syscall!(io_cancel, frame),
-		// TODO 0x0d3 => syscall!(get_thread_are, frame),
+		0x0d3 => syscall!(get_thread_area, frame),
 		// TODO 0x0d4 => syscall!(lookup_dcooki, frame),
-		// TODO 0x0d5 => syscall!(epoll_create, frame),
+		0x0d5 => syscall!(epoll_create, frame),
 		// TODO 0x0d6 => syscall!(epoll_ctl_ol, frame),
 		// TODO 0x0d7 => syscall!(epoll_wait_ol, frame),
 		// TODO 0x0d8 => syscall!(remap_file_pages, frame),
 		0x0d9 => syscall!(getdents64, frame),
 		0x0da => syscall!(set_tid_address, frame),
 		// TODO 0x0db => syscall!(restart_syscall, frame),
-		// TODO 0x0dc => syscall!(semtimedop, frame),
+		0x0dc => syscall!(semtimedop, frame),
 		// TODO 0x0dd => syscall!(fadvise64, frame),
 		0x0de => syscall!(timer_create, frame),
 		0x0df => syscall!(timer_settime, frame),
@@ -1010,33 +1056,33 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x0e2 => syscall!(timer_delete, frame),
 		// TODO 0x0e3 => syscall!(clock_settime, frame),
 		0x0e4 => syscall!(clock_gettime, frame),
-		// TODO 0x0e5 => syscall!(clock_getres, frame),
+		0x0e5 => syscall!(clock_getres64, frame),
 		// TODO 0x0e6 => syscall!(clock_nanosleep, frame),
 		0x0e7 => syscall!(exit_group, frame),
-		// TODO 0x0e8 => syscall!(epoll_wait, frame),
-		// TODO 0x0e9 => syscall!(epoll_ctl, frame),
+		0x0e8 => syscall!(epoll_wait, frame),
+		0x0e9 => syscall!(epoll_ctl, frame),
 		// TODO 0x0ea => syscall!(tgkill, frame),
 		// TODO 0x0eb => syscall!(utimes, frame),
 		// TODO 0x0ec => syscall!(vserve, frame),
-		// TODO 0x0ed => syscall!(mbind, frame),
+		0x0ed => syscall!(mbind, frame),
 		// TODO 0x0ee => syscall!(set_mempolicy, frame),
 		// TODO 0x0ef => syscall!(get_mempolicy, frame),
-		// TODO 0x0f0 => syscall!(mq_open, frame),
-		// TODO 0x0f1 => syscall!(mq_unlink, frame),
-		// TODO 0x0f2 => syscall!(mq_timedsend, frame),
-		// TODO 0x0f3 => syscall!(mq_timedreceive, frame),
-		// TODO 0x0f4 => syscall!(mq_notify, frame),
-		// TODO 0x0f5 => syscall!(mq_getsetattr, frame),
+		0x0f0 => syscall!(mq_open, frame),
+		0x0f1 => syscall!(mq_unlink, frame),
+		0x0f2 => syscall!(mq_timedsend64, frame),
+		0x0f3 => syscall!(mq_timedreceive64, frame),
+		0x0f4 => syscall!(mq_notify, frame),
+		0x0f5 => syscall!(mq_getsetattr, frame),
 		// TODO 0x0f6 => syscall!(kexec_load, frame),
 		// TODO 0x0f7 => syscall!(waitid, frame),
 		// TODO 0x0f8 => syscall!(add_key, frame),
 		// TODO 0x0f9 => syscall!(request_key, frame),
 		// TODO 0x0fa => syscall!(keyctl, frame),
-		// TODO 0x0fb => syscall!(ioprio_set, frame),
-		// TODO 0x0fc => syscall!(ioprio_get, frame),
-		// TODO 0x0fd => syscall!(inotify_init, frame),
-		// TODO 0x0fe => syscall!(inotify_add_watch, frame),
-		// TODO 0x0ff => syscall!(inotify_rm_watch, frame),
+		0x0fb => syscall!(ioprio_set, frame),
+		0x0fc => syscall!(ioprio_get, frame),
+		0x0fd => syscall!(inotify_init, frame),
+		0x0fe => syscall!(inotify_add_watch, frame),
+		0x0ff => syscall!(inotify_rm_watch, frame),
 		// TODO 0x100 => syscall!(migrate_pages, frame),
 		0x101 => syscall!(openat, frame),
 		// TODO 0x102 => syscall!(mkdirat, frame),
@@ -1052,8 +1098,8 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		0x10c => syscall!(fchmodat, frame),
 		0x10d => syscall!(faccessat, frame),
 		0x10e => syscall!(pselect6, frame),
-		// TODO 0x10f => syscall!(ppoll, frame),
-		// TODO 0x110 => syscall!(unshare, frame),
+		0x10f => syscall!(ppoll, frame),
+		0x110 => syscall!(unshare, frame),
 		// TODO 0x111 => syscall!(set_robust_list, frame),
 		// TODO 0x112 => syscall!(get_robust_list, frame),
 		// TODO 0x113 => syscall!(splice, frame),
@@ -1063,19 +1109,19 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x117 => syscall!(move_pages, frame),
 		0x118 => syscall!(utimensat, frame),
 		// TODO 0x119 => syscall!(epoll_pwait, frame),
-		// TODO 0x11a => syscall!(signalfd, frame),
-		// TODO 0x11b => syscall!(timerfd_create, frame),
-		// TODO 0x11c => syscall!(eventfd, frame),
-		// TODO 0x11d => syscall!(fallocate, frame),
-		// TODO 0x11e => syscall!(timerfd_settime, frame),
-		// TODO 0x11f => syscall!(timerfd_gettime, frame),
+		0x11a => syscall!(signalfd, frame),
+		0x11b => syscall!(timerfd_create, frame),
+		0x11c => syscall!(eventfd, frame),
+		0x11d => syscall!(fallocate, frame),
+		0x11e => syscall!(timerfd_settime64, frame),
+		0x11f => syscall!(timerfd_gettime64, frame),
 		// TODO 0x120 => syscall!(accept4, frame),
-		// TODO 0x121 => syscall!(signalfd4, frame),
-		// TODO 0x122 => syscall!(eventfd2, frame),
-		// TODO 0x123 => syscall!(epoll_create1, frame),
+		0x121 => syscall!(signalfd4, frame),
+		0x122 => syscall!(eventfd2, frame),
+		0x123 => syscall!(epoll_create1, frame),
 		// TODO 0x124 => syscall!(dup3, frame),
 		0x125 => syscall!(pipe2, frame),
-		// TODO 0x126 => syscall!(inotify_init1, frame),
+		0x126 => syscall!(inotify_init1, frame),
 		0x127 => syscall!(preadv, frame),
 		0x128 => syscall!(pwritev, frame),
 		// TODO 0x129 => syscall!(rt_tgsigqueueinfo, frame),
@@ -1089,7 +1135,7 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x131 => syscall!(clock_adjtime, frame),
 		0x132 => syscall!(syncfs, frame),
 		// TODO 0x133 => syscall!(sendmmsg, frame),
-		// TODO 0x134 => syscall!(setns, frame),
+		0x134 => syscall!(setns, frame),
 		// TODO 0x135 => syscall!(getcpu, frame),
 		// TODO 0x136 => syscall!(process_vm_readv, frame),
 		// TODO 0x137 => syscall!(process_vm_writev, frame),
@@ -1098,9 +1144,9 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x13a => syscall!(sched_setattr, frame),
 		// TODO 0x13b => syscall!(sched_getattr, frame),
 		0x13c => syscall!(renameat2, frame),
-		// TODO 0x13d => syscall!(seccomp, frame),
+		0x13d => syscall!(seccomp, frame),
 		0x13e => syscall!(getrandom, frame),
-		// TODO 0x13f => syscall!(memfd_create, frame),
+		0x13f => syscall!(memfd_create, frame),
 		// TODO 0x140 => syscall!(kexec_file_load, frame),
 		// TODO 0x141 => syscall!(bpf, frame),
 		// TODO 0x142 => syscall!(execveat, frame),
@@ -1128,7 +1174,7 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 		// TODO 0x1b1 => syscall!(fspick, frame),
 		// TODO 0x1b2 => syscall!(pidfd_open, frame),
 		// TODO 0x1b3 => syscall!(clone3, frame),
-		// TODO 0x1b4 => syscall!(close_range, frame),
+		0x1b4 => syscall!(close_range, frame),
 		// TODO 0x1b5 => syscall!(openat2, frame),
 		// TODO 0x1b6 => syscall!(pidfd_getfd, frame),
 		0x1b7 => syscall!(faccessat2, frame),
@@ -1157,25 +1203,37 @@ fn do_syscall64(id: usize, frame: &mut IntFrame) -> EResult<usize> {
 #[unsafe(no_mangle)]
 pub extern "C" fn syscall_handler(frame: &mut IntFrame) {
 	let id = frame.get_syscall_id();
-	#[cfg(target_arch = "x86")]
-	let res = do_syscall32(id, frame);
-	#[cfg(target_arch = "x86_64")]
-	let res = if frame.is_compat() {
-		do_syscall32(id, frame)
-	} else {
-		do_syscall64(id, frame)
-	};
-	frame.set_syscall_return(res);
-	// If the system call does not exist, kill the process with SIGSYS
-	if unlikely(matches!(res, Err(e) if e.as_int() == ENOSYS)) {
-		let proc = Process::current();
-		#[cfg(feature = "strace")]
-		crate::println!(
-			"[strace PID: {pid}] invalid syscall (ID: 0x{id:x})",
-			pid = proc.get_pid()
-		);
-		proc.kill(Signal::SIGSYS);
+	let proc = Process::current();
+	match seccomp::check(&proc, id, frame) {
+		Action::Allow => {
+			#[cfg(target_arch = "x86")]
+			let res = do_syscall32(id, frame);
+			#[cfg(target_arch = "x86_64")]
+			let res = if frame.is_compat() {
+				do_syscall32(id, frame)
+			} else {
+				do_syscall64(id, frame)
+			};
+			frame.set_syscall_return(res);
+			// If the system call does not exist, kill the process with SIGSYS
+			if unlikely(matches!(res, Err(e) if e.as_int() == ENOSYS)) {
+				#[cfg(feature = "strace")]
+				crate::println!(
+					"[strace PID: {pid}] invalid syscall (ID: 0x{id:x})",
+					pid = proc.get_pid()
+				);
+				proc.kill(Signal::SIGSYS);
+			}
+		}
+		// The system call is skipped
+		Action::Errno(errno) => frame.set_syscall_return(Ok(-(errno as isize) as _)),
+		Action::Trap => {
+			frame.set_syscall_return(Err(errno!(ENOSYS)));
+			proc.kill(Signal::SIGSYS);
+		}
+		Action::Kill(sig) => sig.get_default_action().exec(&proc),
 	}
+	drop(proc);
 	// If the process has been killed, handle it
 	yield_current(3, frame);
 }
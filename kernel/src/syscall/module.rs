@@ -19,7 +19,10 @@
 //! Kernel module system calls.
 
 use crate::{
-	file::{fd::FileDescriptorTable, perm::AccessProfile},
+	file::{
+		fd::FileDescriptorTable,
+		perm::{AccessProfile, CAP_SYS_MODULE},
+	},
 	memory::user::{UserSlice, UserString},
 	module,
 	module::Module,
@@ -37,7 +40,7 @@ pub fn init_module(
 	ap: AccessProfile,
 ) -> EResult<usize> {
 	let module_image = UserSlice::from_user(module_image, len as _)?;
-	if unlikely(!ap.is_privileged()) {
+	if unlikely(!ap.has_capability(CAP_SYS_MODULE)) {
 		return Err(errno!(EPERM));
 	}
 	let image = module_image
@@ -53,7 +56,7 @@ pub fn finit_module(
 	ap: AccessProfile,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SYS_MODULE) {
 		return Err(errno!(EPERM));
 	}
 	// Read file
@@ -68,7 +71,7 @@ pub fn delete_module(
 	Args((name, _flags)): Args<(UserString, c_uint)>,
 	ap: AccessProfile,
 ) -> EResult<usize> {
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SYS_MODULE) {
 		return Err(errno!(EPERM));
 	}
 	let name = name.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
@@ -20,26 +20,109 @@
 
 use crate::{
 	file::{
-		FileType, fs, vfs,
-		vfs::{ResolutionSettings, mountpoint, mountpoint::MountSource},
+		FileType, fs,
+		fs::FilesystemType,
+		perm::CAP_SYS_ADMIN,
+		vfs,
+		vfs::{
+			ResolutionSettings, mountpoint,
+			mountpoint::{
+				FLAG_CASEFOLD, FLAG_CHECK, FLAG_ERRORS, FLAG_ERRORS_CONTINUE, FLAG_ERRORS_PANIC,
+				FLAG_ERRORS_REMOUNT_RO, FLAG_MANDLOCK, FLAG_NOATIME, FLAG_NODEV, FLAG_NODIRATIME,
+				FLAG_NOEXEC, FLAG_NOSUID, FLAG_RDONLY, FLAG_RELATIME, FLAG_SILENT,
+				FLAG_STRICTATIME, FLAG_SYNCHRONOUS, MountSource,
+			},
+		},
 	},
-	memory::user::{UserPtr, UserString},
+	memory::user::UserString,
+	process::scheduler::SCHEDULER,
 	syscall::Args,
 };
-use core::ffi::{c_int, c_ulong, c_void};
-use utils::{collections::path::PathBuf, errno, errno::EResult};
+use core::ffi::{c_int, c_ulong};
+use utils::{collections::path::PathBuf, errno, errno::EResult, ptr::arc::Arc};
+
+/// Mount flag: mount read-only.
+const MS_RDONLY: c_ulong = 1;
+/// Mount flag: ignore set-user-ID and set-group-ID bits.
+const MS_NOSUID: c_ulong = 2;
+/// Mount flag: disallow access to device files.
+const MS_NODEV: c_ulong = 4;
+/// Mount flag: disallow program execution.
+const MS_NOEXEC: c_ulong = 8;
+/// Mount flag: writes are synchronous.
+const MS_SYNCHRONOUS: c_ulong = 16;
+/// Mount flag: allow mandatory locks.
+const MS_MANDLOCK: c_ulong = 64;
+/// Mount flag: do not update access times.
+const MS_NOATIME: c_ulong = 1024;
+/// Mount flag: do not update access times of directories.
+const MS_NODIRATIME: c_ulong = 2048;
+/// Mount flag: suppress certain warning messages.
+const MS_SILENT: c_ulong = 32768;
+/// Mount flag: update access times relative to modification times.
+const MS_RELATIME: c_ulong = 1 << 21;
+/// Mount flag: always update access times.
+const MS_STRICTATIME: c_ulong = 1 << 24;
+
+/// Converts the mount flags `mountflags` given to the `mount` system call into mountpoint flags.
+///
+/// Unsupported flags are ignored.
+fn convert_flags(mountflags: c_ulong) -> u32 {
+	const FLAGS: [(c_ulong, u32); 11] = [
+		(MS_RDONLY, FLAG_RDONLY),
+		(MS_NOSUID, FLAG_NOSUID),
+		(MS_NODEV, FLAG_NODEV),
+		(MS_NOEXEC, FLAG_NOEXEC),
+		(MS_SYNCHRONOUS, FLAG_SYNCHRONOUS),
+		(MS_MANDLOCK, FLAG_MANDLOCK),
+		(MS_NOATIME, FLAG_NOATIME),
+		(MS_NODIRATIME, FLAG_NODIRATIME),
+		(MS_SILENT, FLAG_SILENT),
+		(MS_RELATIME, FLAG_RELATIME),
+		(MS_STRICTATIME, FLAG_STRICTATIME),
+	];
+	FLAGS
+		.iter()
+		.filter(|(ms, _)| mountflags & ms != 0)
+		.fold(0, |flags, (_, flag)| flags | flag)
+}
+
+/// Parses the comma-separated list of filesystem-specific mount options `data`.
+///
+/// The function returns the mount flags corresponding to the options. Unknown options are
+/// ignored.
+fn parse_options(fs_type: &dyn FilesystemType, data: &[u8]) -> EResult<u32> {
+	let ext2 = fs_type.get_name() == b"ext2";
+	let mut flags = 0;
+	for opt in data.split(|b| *b == b',') {
+		// When several error policies are given, the last one takes precedence
+		match opt {
+			b"casefold" if ext2 => flags |= FLAG_CASEFOLD,
+			b"errors=continue" if ext2 => flags = (flags & !FLAG_ERRORS) | FLAG_ERRORS_CONTINUE,
+			b"errors=remount-ro" if ext2 => {
+				flags = (flags & !FLAG_ERRORS) | FLAG_ERRORS_REMOUNT_RO
+			}
+			b"errors=panic" if ext2 => flags = (flags & !FLAG_ERRORS) | FLAG_ERRORS_PANIC,
+			b"check" if ext2 => flags |= FLAG_CHECK,
+			b"casefold" | b"check" => return Err(errno!(EINVAL)),
+			_ if opt.starts_with(b"errors=") => return Err(errno!(EINVAL)),
+			_ => {}
+		}
+	}
+	Ok(flags)
+}
 
 pub fn mount(
-	Args((source, target, filesystemtype, mountflags, _data)): Args<(
+	Args((source, target, filesystemtype, mountflags, data)): Args<(
 		UserString,
 		UserString,
 		UserString,
 		c_ulong,
-		UserPtr<c_void>,
+		UserString,
 	)>,
 	rs: ResolutionSettings,
 ) -> EResult<usize> {
-	if !rs.access_profile.is_privileged() {
+	if !rs.access_profile.has_capability(CAP_SYS_ADMIN) {
 		return Err(errno!(EPERM));
 	}
 	// Read arguments
@@ -55,9 +138,14 @@ pub fn mount(
 	if target.get_type()? != FileType::Directory {
 		return Err(errno!(ENOTDIR));
 	}
-	// TODO Use `data`
+	let data = data.copy_from_user()?;
+	let options = parse_options(
+		&*fs_type,
+		data.as_ref().map(|d| d.as_bytes()).unwrap_or_default(),
+	)?;
+	let flags = convert_flags(mountflags) | options;
 	// Create mountpoint
-	mountpoint::create(mount_source, Some(fs_type), mountflags as _, Some(target))?;
+	mountpoint::create(mount_source, Some(fs_type), flags, Some(target))?;
 	Ok(0)
 }
 
@@ -71,7 +159,7 @@ pub fn umount2(
 ) -> EResult<usize> {
 	// TODO handle flags
 	// Check permission
-	if !rs.access_profile.is_privileged() {
+	if !rs.access_profile.has_capability(CAP_SYS_ADMIN) {
 		return Err(errno!(EPERM));
 	}
 	// Get target directory
@@ -82,3 +170,55 @@ pub fn umount2(
 	mountpoint::remove(target)?;
 	Ok(0)
 }
+
+pub fn pivot_root(
+	Args((new_root, put_old)): Args<(UserString, UserString)>,
+	rs: ResolutionSettings,
+) -> EResult<usize> {
+	if !rs.access_profile.has_capability(CAP_SYS_ADMIN) {
+		return Err(errno!(EPERM));
+	}
+	let new_root = new_root.copy_from_user()?.ok_or(errno!(EFAULT))?;
+	let new_root = vfs::get_file_from_path(&PathBuf::try_from(new_root)?, &rs)?;
+	let put_old = put_old.copy_from_user()?.ok_or(errno!(EFAULT))?;
+	let put_old = vfs::get_file_from_path(&PathBuf::try_from(put_old)?, &rs)?;
+	if new_root.get_type()? != FileType::Directory || put_old.get_type()? != FileType::Directory {
+		return Err(errno!(ENOTDIR));
+	}
+	let root = &rs.root;
+	// Both the current and the new root must be the root of a mountpoint
+	if mountpoint::from_entry(root).is_none() || mountpoint::from_entry(&new_root).is_none() {
+		return Err(errno!(EINVAL));
+	}
+	if Arc::as_ptr(&new_root) == Arc::as_ptr(root) {
+		return Err(errno!(EBUSY));
+	}
+	// `put_old` must be at or underneath the new root, itself underneath the current root
+	if !new_root.is_in(root) || !put_old.is_in(&new_root) {
+		return Err(errno!(EINVAL));
+	}
+	// Stacking the old root on top of the new root is not supported
+	if Arc::as_ptr(&put_old) == Arc::as_ptr(&new_root) {
+		return Err(errno!(EBUSY));
+	}
+	mountpoint::move_to(root, &put_old)?;
+	if let Some(ns) = mountpoint::namespace_of(&new_root) {
+		let mut ns_root = ns.root.lock();
+		if Arc::as_ptr(&ns_root) == Arc::as_ptr(root) {
+			*ns_root = new_root.clone();
+		}
+	}
+	// Move processes using the old root to the new root
+	let sched = SCHEDULER.lock();
+	for (_, proc) in sched.iter_process() {
+		let fs = proc.fs.lock();
+		let mut ctx = fs.ctx.lock();
+		if Arc::as_ptr(&ctx.chroot) == Arc::as_ptr(root) {
+			ctx.chroot = new_root.clone();
+		}
+		if Arc::as_ptr(&ctx.cwd) == Arc::as_ptr(root) {
+			ctx.cwd = new_root.clone();
+		}
+	}
+	Ok(0)
+}
@@ -0,0 +1,328 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! POSIX message queue system calls.
+
+use crate::{
+	file,
+	file::{
+		File, Mode, O_CREAT, O_EXCL, O_NONBLOCK, O_RDWR, O_WRONLY,
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+		perm::{AccessProfile, S_IROTH, S_IWOTH},
+	},
+	memory::user::{UserPtr, UserSlice, UserString},
+	process::{
+		Process,
+		mqueue::{CompatMqAttr, MQ_PRIO_MAX, MqAttr, MqMessage, Mqueue},
+		signal::{SIGEV_NONE, SIGEV_SIGNAL, SigEvent, Signal},
+	},
+	sync::mutex::Mutex,
+	syscall::{Args, Umask},
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::{TimeUnit, Timespec, Timespec32, Timestamp},
+	},
+	uapi::UserRepr,
+};
+use core::{
+	ffi::{c_int, c_long},
+	hint::unlikely,
+	ptr,
+};
+use utils::{collections::string::String, errno, errno::EResult, limits::NAME_MAX, ptr::arc::Arc};
+
+/// Reads the name of a queue from `name`.
+///
+/// The C library strips the leading slash of the name, so the name must not contain any.
+fn get_name(name: UserString) -> EResult<String> {
+	let name = name.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	if unlikely(name.is_empty()) {
+		return Err(errno!(ENOENT));
+	}
+	if unlikely(name.len() > NAME_MAX) {
+		return Err(errno!(ENAMETOOLONG));
+	}
+	if unlikely(name.as_bytes().contains(&b'/')) {
+		return Err(errno!(EACCES));
+	}
+	Ok(name)
+}
+
+/// Returns the open file description of the queue `mqdes`.
+fn get_queue(mqdes: c_int, fds: &Mutex<FileDescriptorTable>) -> EResult<Arc<File>> {
+	let file = fds.lock().get_fd(mqdes)?.get_file().clone();
+	if unlikely(file.get_buffer::<Mqueue>().is_none()) {
+		return Err(errno!(EBADF));
+	}
+	Ok(file)
+}
+
+/// Converts the absolute timeout `abs` on the realtime clock to a duration, in nanoseconds.
+fn to_duration(abs: Option<Timestamp>) -> Option<Timestamp> {
+	abs.map(|abs| abs.saturating_sub(current_time_ns(Clock::Realtime)))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn do_mq_open<A: UserRepr<MqAttr>>(
+	name: UserString,
+	oflag: c_int,
+	mode: Mode,
+	attr: UserPtr<A>,
+	proc: &Process,
+	ap: &AccessProfile,
+	fds: &Mutex<FileDescriptorTable>,
+	umask: Umask,
+) -> EResult<usize> {
+	let name = get_name(name)?;
+	let access = match oflag & (O_WRONLY | O_RDWR) {
+		file::O_RDONLY => S_IROTH,
+		O_WRONLY => S_IWOTH,
+		O_RDWR => S_IROTH | S_IWOTH,
+		_ => return Err(errno!(EINVAL)),
+	};
+	let create = oflag & O_CREAT != 0;
+	let attr = if create {
+		attr.copy_from_user()?.map(Into::into)
+	} else {
+		None
+	};
+	let ipc = proc.ns.lock().ipc.clone();
+	let queue = ipc.mq.lock().open(
+		name,
+		(create, oflag & O_EXCL != 0),
+		access,
+		mode & !umask.0,
+		attr,
+		ap,
+	)?;
+	let file = File::open_floating(queue, oflag & (O_WRONLY | O_RDWR | O_NONBLOCK))?;
+	// As on Linux, queue descriptors are always closed on `execve`
+	let (fd_id, _) = fds.lock().create_fd(FD_CLOEXEC, file)?;
+	Ok(fd_id as _)
+}
+
+pub fn mq_open(
+	Args((name, oflag, mode, attr)): Args<(UserString, c_int, Mode, UserPtr<MqAttr>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+	umask: Umask,
+) -> EResult<usize> {
+	do_mq_open(name, oflag, mode, attr, &proc, &ap, &fds, umask)
+}
+
+pub fn compat_mq_open(
+	Args((name, oflag, mode, attr)): Args<(UserString, c_int, Mode, UserPtr<CompatMqAttr>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+	umask: Umask,
+) -> EResult<usize> {
+	do_mq_open(name, oflag, mode, attr, &proc, &ap, &fds, umask)
+}
+
+pub fn mq_unlink(
+	Args(name): Args<UserString>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let name = get_name(name)?;
+	let ipc = proc.ns.lock().ipc.clone();
+	ipc.mq.lock().unlink(name.as_bytes(), &ap)?;
+	Ok(0)
+}
+
+/// Performs the `mq_timedsend` system call.
+///
+/// `abs_timeout` is the absolute timeout on the realtime clock, in nanoseconds.
+fn do_mq_timedsend(
+	mqdes: c_int,
+	msg_ptr: usize,
+	msg_len: usize,
+	msg_prio: u32,
+	abs_timeout: Option<Timestamp>,
+	fds: &Mutex<FileDescriptorTable>,
+) -> EResult<usize> {
+	let file = get_queue(mqdes, fds)?;
+	let queue: &Mqueue = file.get_buffer().unwrap();
+	if unlikely(!file.can_write()) {
+		return Err(errno!(EBADF));
+	}
+	if unlikely(msg_prio >= MQ_PRIO_MAX) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(msg_len > queue.msgsize) {
+		return Err(errno!(EMSGSIZE));
+	}
+	let data = UserSlice::from_user(ptr::with_exposed_provenance_mut(msg_ptr), msg_len)?
+		.copy_from_user_vec(0)?
+		.ok_or_else(|| errno!(EFAULT))?;
+	let msg = MqMessage {
+		prio: msg_prio,
+		data,
+	};
+	let nonblock = file.get_flags() & O_NONBLOCK != 0;
+	queue.send(msg, nonblock, to_duration(abs_timeout))?;
+	Ok(0)
+}
+
+pub fn mq_timedsend32(
+	Args((mqdes, msg_ptr, msg_len, msg_prio, abs_timeout)): Args<(
+		c_int,
+		usize,
+		usize,
+		u32,
+		UserPtr<Timespec32>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let abs_timeout = abs_timeout.copy_from_user()?.map(|t| t.to_nano());
+	do_mq_timedsend(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout, &fds)
+}
+
+pub fn mq_timedsend64(
+	Args((mqdes, msg_ptr, msg_len, msg_prio, abs_timeout)): Args<(
+		c_int,
+		usize,
+		usize,
+		u32,
+		UserPtr<Timespec>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let abs_timeout = abs_timeout.copy_from_user()?.map(|t| t.to_nano());
+	do_mq_timedsend(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout, &fds)
+}
+
+/// Performs the `mq_timedreceive` system call, returning the size of the received message.
+///
+/// `abs_timeout` is the absolute timeout on the realtime clock, in nanoseconds.
+fn do_mq_timedreceive(
+	mqdes: c_int,
+	msg_ptr: usize,
+	msg_len: usize,
+	msg_prio: UserPtr<u32>,
+	abs_timeout: Option<Timestamp>,
+	fds: &Mutex<FileDescriptorTable>,
+) -> EResult<usize> {
+	let file = get_queue(mqdes, fds)?;
+	let queue: &Mqueue = file.get_buffer().unwrap();
+	if unlikely(!file.can_read()) {
+		return Err(errno!(EBADF));
+	}
+	let buf = UserSlice::from_user(ptr::with_exposed_provenance_mut(msg_ptr), msg_len)?;
+	let nonblock = file.get_flags() & O_NONBLOCK != 0;
+	let msg = queue.receive(msg_len, nonblock, to_duration(abs_timeout))?;
+	buf.copy_to_user(0, &msg.data)?;
+	msg_prio.copy_to_user(&msg.prio)?;
+	Ok(msg.data.len())
+}
+
+#[allow(clippy::type_complexity)]
+pub fn mq_timedreceive32(
+	Args((mqdes, msg_ptr, msg_len, msg_prio, abs_timeout)): Args<(
+		c_int,
+		usize,
+		usize,
+		UserPtr<u32>,
+		UserPtr<Timespec32>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let abs_timeout = abs_timeout.copy_from_user()?.map(|t| t.to_nano());
+	do_mq_timedreceive(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout, &fds)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn mq_timedreceive64(
+	Args((mqdes, msg_ptr, msg_len, msg_prio, abs_timeout)): Args<(
+		c_int,
+		usize,
+		usize,
+		UserPtr<u32>,
+		UserPtr<Timespec>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let abs_timeout = abs_timeout.copy_from_user()?.map(|t| t.to_nano());
+	do_mq_timedreceive(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout, &fds)
+}
+
+pub fn mq_notify(
+	Args((mqdes, sevp)): Args<(c_int, UserPtr<SigEvent>)>,
+	proc: Arc<Process>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let file = get_queue(mqdes, &fds)?;
+	let queue: &Mqueue = file.get_buffer().unwrap();
+	let sevp = sevp.copy_from_user()?;
+	if let Some(sevp) = &sevp {
+		// `SIGEV_THREAD` requires netlink sockets, which are not supported
+		let valid = match sevp.sigev_notify {
+			SIGEV_NONE => true,
+			SIGEV_SIGNAL => Signal::try_from(sevp.sigev_signo).is_ok(),
+			_ => false,
+		};
+		if unlikely(!valid) {
+			return Err(errno!(EINVAL));
+		}
+	}
+	queue.set_notify(proc.get_pid(), sevp)?;
+	Ok(0)
+}
+
+/// Performs the `mq_getsetattr` system call.
+fn do_mq_getsetattr<A: UserRepr<MqAttr>>(
+	mqdes: c_int,
+	newattr: UserPtr<A>,
+	oldattr: UserPtr<A>,
+	fds: &Mutex<FileDescriptorTable>,
+) -> EResult<usize> {
+	let file = get_queue(mqdes, fds)?;
+	let queue: &Mqueue = file.get_buffer().unwrap();
+	let newattr: Option<MqAttr> = newattr.copy_from_user()?.map(Into::into);
+	if let Some(attr) = &newattr {
+		// Only `O_NONBLOCK` can be changed
+		if unlikely(attr.mq_flags & !(O_NONBLOCK as c_long) != 0) {
+			return Err(errno!(EINVAL));
+		}
+	}
+	let old = MqAttr {
+		mq_flags: (file.get_flags() & O_NONBLOCK) as _,
+		..queue.attr()
+	};
+	oldattr.copy_to_user(&old.into())?;
+	if let Some(attr) = newattr {
+		file.set_flags(attr.mq_flags as _, true);
+	}
+	Ok(0)
+}
+
+pub fn mq_getsetattr(
+	Args((mqdes, newattr, oldattr)): Args<(c_int, UserPtr<MqAttr>, UserPtr<MqAttr>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_mq_getsetattr(mqdes, newattr, oldattr, &fds)
+}
+
+pub fn compat_mq_getsetattr(
+	Args((mqdes, newattr, oldattr)): Args<(c_int, UserPtr<CompatMqAttr>, UserPtr<CompatMqAttr>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_mq_getsetattr(mqdes, newattr, oldattr, &fds)
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV message queue system calls.
+
+use crate::{
+	arch::x86::idt::IntFrame,
+	file::perm::AccessProfile,
+	memory::user::{UserPtr, UserSlice},
+	process::{
+		Process,
+		msg::{CompatMsqidDs, MSGMAX, Message, MsqidDs},
+		ns::{IPC_64, IPC_RMID, IPC_SET, IPC_STAT},
+	},
+	syscall::{Args, FromSyscallArg},
+	uapi::UserRepr,
+};
+use core::{
+	ffi::{c_int, c_long},
+	hint::unlikely,
+	ptr,
+};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Returns the size of the `mtype` field at the beginning of a message buffer.
+///
+/// `compat` tells whether the caller is a 32 bit process.
+fn mtype_size(compat: bool) -> usize {
+	if compat { 4 } else { size_of::<c_long>() }
+}
+
+pub fn msgget(
+	Args((key, flags)): Args<(c_int, c_int)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let id = ipc.msg.lock().get(key, flags, &ap)?;
+	Ok(id as _)
+}
+
+/// Performs the `msgsnd` system call.
+///
+/// `compat` tells whether the caller is a 32 bit process.
+pub(super) fn do_msgsnd(
+	msqid: c_int,
+	msgp: usize,
+	msgsz: usize,
+	flags: c_int,
+	compat: bool,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	if unlikely(msgsz > MSGMAX) {
+		return Err(errno!(EINVAL));
+	}
+	let mtype = if compat {
+		UserPtr::<i32>::from_ptr(msgp)
+			.copy_from_user()?
+			.map(|mtype| mtype as c_long)
+	} else {
+		UserPtr::<c_long>::from_ptr(msgp).copy_from_user()?
+	};
+	let mtype = mtype.ok_or_else(|| errno!(EFAULT))?;
+	let mtext = msgp + mtype_size(compat);
+	let data = UserSlice::from_user(ptr::with_exposed_provenance_mut(mtext), msgsz)?
+		.copy_from_user_vec(0)?
+		.ok_or_else(|| errno!(EFAULT))?;
+	let ipc = proc.ns.lock().ipc.clone();
+	let queue = ipc.msg.lock().get_queue(msqid)?;
+	queue.send(
+		ap,
+		proc.get_pid(),
+		Message {
+			mtype,
+			data,
+		},
+		flags,
+	)?;
+	Ok(0)
+}
+
+pub fn msgsnd(
+	Args((msqid, msgp, msgsz, flags)): Args<(c_int, usize, usize, c_int)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	do_msgsnd(msqid, msgp, msgsz, flags, frame.is_compat(), &proc, &ap)
+}
+
+/// Performs the `msgrcv` system call, returning the size of the received message.
+///
+/// `compat` tells whether the caller is a 32 bit process.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn do_msgrcv(
+	msqid: c_int,
+	msgp: usize,
+	msgsz: usize,
+	msgtyp: c_long,
+	flags: c_int,
+	compat: bool,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	if unlikely(msgsz > isize::MAX as usize) {
+		return Err(errno!(EINVAL));
+	}
+	let ipc = proc.ns.lock().ipc.clone();
+	let queue = ipc.msg.lock().get_queue(msqid)?;
+	let msg = queue.receive(ap, proc.get_pid(), msgsz, msgtyp, flags)?;
+	if compat {
+		UserPtr::<i32>::from_ptr(msgp).copy_to_user(&(msg.mtype as _))?;
+	} else {
+		UserPtr::<c_long>::from_ptr(msgp).copy_to_user(&msg.mtype)?;
+	}
+	let mtext = msgp + mtype_size(compat);
+	UserSlice::from_user(ptr::with_exposed_provenance_mut(mtext), msg.data.len())?
+		.copy_to_user(0, &msg.data)?;
+	Ok(msg.data.len())
+}
+
+pub fn msgrcv(
+	Args((msqid, msgp, msgsz, msgtyp, flags)): Args<(c_int, usize, usize, c_long, c_int)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	let compat = frame.is_compat();
+	// Arguments of 32 bit processes are not sign-extended
+	let msgtyp = if compat { msgtyp as c_int as _ } else { msgtyp };
+	do_msgrcv(msqid, msgp, msgsz, msgtyp, flags, compat, &proc, &ap)
+}
+
+/// Performs the `msgctl` system call.
+pub(super) fn do_msgctl<S: UserRepr<MsqidDs>>(
+	msqid: c_int,
+	cmd: c_int,
+	buf: UserPtr<S>,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	match cmd & !IPC_64 {
+		IPC_STAT => {
+			let queue = ipc.msg.lock().get_queue(msqid)?;
+			buf.copy_to_user(&queue.stat(ap)?.into())?;
+		}
+		IPC_SET => {
+			let queue = ipc.msg.lock().get_queue(msqid)?;
+			let ds: MsqidDs = buf.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?.into();
+			queue.set(ap, &ds)?;
+		}
+		IPC_RMID => ipc.msg.lock().remove(msqid, ap)?,
+		_ => return Err(errno!(EINVAL)),
+	}
+	Ok(0)
+}
+
+pub fn msgctl(
+	Args((msqid, cmd, buf)): Args<(c_int, c_int, UserPtr<MsqidDs>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_msgctl(msqid, cmd, buf, &proc, &ap)
+}
+
+pub fn compat_msgctl(
+	Args((msqid, cmd, buf)): Args<(c_int, c_int, UserPtr<CompatMsqidDs>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_msgctl(msqid, cmd, buf, &proc, &ap)
+}
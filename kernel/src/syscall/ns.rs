@@ -0,0 +1,122 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Namespace system calls.
+
+use crate::{
+	file::{
+		fd::FileDescriptorTable,
+		fs::proc::{IpcNs, MntNs, NetNs, UtsNs},
+		perm::{CAP_SYS_ADMIN, CAP_SYS_CHROOT},
+	},
+	process::Process,
+	sync::mutex::Mutex,
+	syscall::{
+		Args,
+		process::{CLONE_FS, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWUTS},
+	},
+};
+use core::ffi::{c_int, c_ulong};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Checks that the namespace type `nstype` passed to `setns` allows a namespace of type `flag`.
+///
+/// If `nstype` is zero, any type is allowed.
+fn check_nstype(nstype: c_ulong, flag: c_ulong) -> EResult<()> {
+	if nstype != 0 && nstype != flag {
+		return Err(errno!(EINVAL));
+	}
+	Ok(())
+}
+
+pub fn unshare(Args(flags): Args<c_int>, proc: Arc<Process>) -> EResult<usize> {
+	let flags = flags as c_ulong;
+	if flags & !(CLONE_NEWNS | CLONE_NEWUTS | CLONE_NEWIPC | CLONE_NEWNET | CLONE_FS) != 0 {
+		return Err(errno!(EINVAL));
+	}
+	if flags & (CLONE_NEWNS | CLONE_NEWUTS | CLONE_NEWIPC | CLONE_NEWNET) != 0
+		&& !proc.fs.lock().access_profile.has_capability(CAP_SYS_ADMIN)
+	{
+		return Err(errno!(EPERM));
+	}
+	// Processes sharing filesystem information cannot be in different mount namespaces, so
+	// `CLONE_NEWNS` implies `CLONE_FS`
+	if flags & (CLONE_NEWNS | CLONE_FS) != 0 {
+		let mut fs = proc.fs.lock();
+		fs.unshare()?;
+		if flags & CLONE_NEWNS != 0 {
+			fs.ctx.lock().unshare_mnt_ns()?;
+		}
+	}
+	let uts = flags & CLONE_NEWUTS != 0;
+	let ipc = flags & CLONE_NEWIPC != 0;
+	let net = flags & CLONE_NEWNET != 0;
+	if uts || ipc || net {
+		let mut ns = proc.ns.lock();
+		*ns = ns.unshare(uts, ipc, net)?;
+	}
+	Ok(0)
+}
+
+pub fn setns(
+	Args((fd, nstype)): Args<(c_int, c_int)>,
+	proc: Arc<Process>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let nstype = nstype as c_ulong;
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	if let Some(MntNs(ns)) = file.get_buffer::<MntNs>() {
+		check_nstype(nstype, CLONE_NEWNS)?;
+		let ns = ns.clone().ok_or_else(|| errno!(EINVAL))?;
+		let fs = proc.fs.lock();
+		if !fs.access_profile.has_capability(CAP_SYS_ADMIN)
+			|| !fs.access_profile.has_capability(CAP_SYS_CHROOT)
+		{
+			return Err(errno!(EPERM));
+		}
+		// The other processes sharing filesystem information would change namespace as well
+		if Arc::strong_count(&fs.ctx) > 1 {
+			return Err(errno!(EINVAL));
+		}
+		fs.ctx.lock().set_mnt_ns(ns);
+	} else if let Some(UtsNs(ns)) = file.get_buffer::<UtsNs>() {
+		check_nstype(nstype, CLONE_NEWUTS)?;
+		let ns = ns.clone().ok_or_else(|| errno!(EINVAL))?;
+		if !proc.fs.lock().access_profile.has_capability(CAP_SYS_ADMIN) {
+			return Err(errno!(EPERM));
+		}
+		proc.ns.lock().uts = ns;
+	} else if let Some(IpcNs(ns)) = file.get_buffer::<IpcNs>() {
+		check_nstype(nstype, CLONE_NEWIPC)?;
+		let ns = ns.clone().ok_or_else(|| errno!(EINVAL))?;
+		if !proc.fs.lock().access_profile.has_capability(CAP_SYS_ADMIN) {
+			return Err(errno!(EPERM));
+		}
+		proc.ns.lock().ipc = ns;
+	} else if let Some(NetNs(ns)) = file.get_buffer::<NetNs>() {
+		check_nstype(nstype, CLONE_NEWNET)?;
+		let ns = ns.clone().ok_or_else(|| errno!(EINVAL))?;
+		if !proc.fs.lock().access_profile.has_capability(CAP_SYS_ADMIN) {
+			return Err(errno!(EPERM));
+		}
+		proc.ns.lock().net = ns;
+	} else {
+		return Err(errno!(EINVAL));
+	}
+	Ok(0)
+}
@@ -20,7 +20,13 @@
 
 use crate::{
 	file,
-	file::{File, fd::FileDescriptorTable, pipe::PipeBuffer},
+	file::{
+		File,
+		buffer::watch_queue::{O_NOTIFICATION_PIPE, WatchQueue},
+		fd::FileDescriptorTable,
+		fs::FileOps,
+		pipe::PipeBuffer,
+	},
 	memory::user::UserPtr,
 	sync::mutex::Mutex,
 	syscall::Args,
@@ -45,11 +51,20 @@ pub fn pipe2(
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
 	// Validation
-	let accepted_flags = file::O_CLOEXEC | file::O_DIRECT | file::O_NONBLOCK;
+	let accepted_flags = file::O_CLOEXEC | file::O_DIRECT | file::O_NONBLOCK | O_NOTIFICATION_PIPE;
 	if flags & !accepted_flags != 0 {
 		return Err(errno!(EINVAL));
 	}
-	let ops = Arc::new(PipeBuffer::new()?)?;
+	let notification = flags & O_NOTIFICATION_PIPE != 0;
+	if notification && flags & file::O_DIRECT != 0 {
+		return Err(errno!(EINVAL));
+	}
+	let ops: Arc<dyn FileOps> = if notification {
+		Arc::new(WatchQueue::new()?)?
+	} else {
+		Arc::new(PipeBuffer::new()?)?
+	};
+	let flags = flags & !O_NOTIFICATION_PIPE;
 	let file0 = File::open_floating(ops.clone(), flags | file::O_RDONLY)?;
 	let file1 = File::open_floating(ops, flags | file::O_WRONLY)?;
 	let (fd0_id, fd1_id) = fds.lock().create_fd_pair(file0, file1)?;
@@ -23,24 +23,49 @@ use crate::{
 		x86,
 		x86::{cli, gdt, idt::IntFrame},
 	},
-	memory::user::UserPtr,
+	file::{
+		fd::NR_OPEN,
+		perm::{CAP_SYS_ADMIN, CAP_SYS_RESOURCE},
+	},
+	memory::user::{UserPtr, UserSlice},
+	net::{
+		bpf,
+		bpf::{Program, SockFilter},
+	},
 	process,
 	process::{
 		ForkOptions, Process, State,
+		mem_space::MemSpace,
 		pid::Pid,
-		rusage::Rusage,
+		rlimit::{RLIMIT_NLIMITS, RLIMIT_NOFILE, RLimit},
+		rusage::{Rusage, ns_to_ticks},
 		scheduler::{
-			SCHEDULER, Scheduler, switch,
+			Scheduler, core_local, switch,
 			switch::{fork_asm, stash_segments},
+			with_run_queue,
 		},
+		seccomp,
+		seccomp::{SECCOMP_MODE_FILTER, SECCOMP_MODE_STRICT},
 		user_desc::UserDesc,
 	},
 	syscall::{Args, FromSyscallArg},
+	time::{
+		clock::{Clock, current_time_ns},
+		unit::TimeUnit,
+	},
+	uapi::{
+		UserRepr,
+		seccomp::SeccompData,
+		socket::{CompatSockFprog, SockFprog},
+		times::Tms,
+	},
 };
 use core::{
-	ffi::{c_int, c_ulong, c_void},
+	ffi::{c_int, c_uint, c_ulong, c_void},
 	hint::unlikely,
+	ptr,
 	ptr::null_mut,
+	sync::atomic::Ordering::{Relaxed, Release},
 };
 use utils::{errno, errno::EResult, ptr::arc::Arc};
 
@@ -48,7 +73,7 @@ use utils::{errno, errno::EResult, ptr::arc::Arc};
 pub const CLONE_IO: c_ulong = -0x80000000 as _;
 /// If specified, the parent and child processes share the same memory space.
 pub const CLONE_VM: c_ulong = 0x100;
-/// TODO doc
+/// If specified, the parent and child processes share the same filesystem information.
 pub const CLONE_FS: c_ulong = 0x200;
 /// If specified, the parent and child processes share the same file descriptors
 /// table.
@@ -66,7 +91,7 @@ pub const CLONE_VFORK: c_ulong = 0x4000;
 pub const CLONE_PARENT: c_ulong = 0x8000;
 /// TODO doc
 pub const CLONE_THREAD: c_ulong = 0x10000;
-/// TODO doc
+/// If specified, the child process is placed in a new mount namespace.
 pub const CLONE_NEWNS: c_ulong = 0x20000;
 /// TODO doc
 pub const CLONE_SYSVSEM: c_ulong = 0x40000;
@@ -84,9 +109,9 @@ pub const CLONE_UNTRACED: c_ulong = 0x800000;
 pub const CLONE_CHILD_SETTID: c_ulong = 0x1000000;
 /// TODO doc
 pub const CLONE_NEWCGROUP: c_ulong = 0x2000000;
-/// TODO doc
+/// If specified, the child process is placed in a new UTS namespace.
 pub const CLONE_NEWUTS: c_ulong = 0x4000000;
-/// TODO doc
+/// If specified, the child process is placed in a new IPC namespace.
 pub const CLONE_NEWIPC: c_ulong = 0x8000000;
 /// TODO doc
 pub const CLONE_NEWUSER: c_ulong = 0x10000000;
@@ -111,53 +136,35 @@ const ARCH_GET_CPUID: c_int = 0x1011;
 /// Enable or disable cpuid instruction.
 const ARCH_SET_CPUID: c_int = 0x1012;
 
+/// `prctl` option: get the dumpable attribute.
+const PR_GET_DUMPABLE: c_int = 3;
+/// `prctl` option: set the dumpable attribute.
+const PR_SET_DUMPABLE: c_int = 4;
+/// `prctl` option: get the seccomp mode.
+const PR_GET_SECCOMP: c_int = 21;
+/// `prctl` option: set the seccomp mode.
+const PR_SET_SECCOMP: c_int = 22;
+/// `prctl` option: set the child subreaper attribute.
+const PR_SET_CHILD_SUBREAPER: c_int = 36;
+/// `prctl` option: get the child subreaper attribute.
+const PR_GET_CHILD_SUBREAPER: c_int = 37;
+/// `prctl` option: set the `no_new_privs` attribute.
+const PR_SET_NO_NEW_PRIVS: c_int = 38;
+/// `prctl` option: get the `no_new_privs` attribute.
+const PR_GET_NO_NEW_PRIVS: c_int = 39;
+
+/// `seccomp` operation: switch to the strict mode.
+const SECCOMP_SET_MODE_STRICT: c_uint = 0;
+/// `seccomp` operation: attach a filter.
+const SECCOMP_SET_MODE_FILTER: c_uint = 1;
+/// `seccomp` operation: tell whether a filter return value is supported.
+const SECCOMP_GET_ACTION_AVAIL: c_uint = 2;
+
 /// Returns the resource usage of the current process.
 const RUSAGE_SELF: i32 = 0;
 /// Returns the resource usage of the process's children.
 const RUSAGE_CHILDREN: i32 = -1;
 
-/// The amount of seconds of CPU time the process can consume.
-const RLIMIT_CPU: i32 = 0;
-/// The maximum size of a file the process may create, in bytes.
-const RLIMIT_FSIZE: i32 = 1;
-/// The maximum size of the process's data segment in bytes, rounded down to the
-/// page size.
-const RLIMIT_DATA: i32 = 2;
-/// The maximum size of the process stack, in bytes.
-const RLIMIT_STACK: i32 = 3;
-/// The maximum size of a kernel file the process may dump in bytes.
-const RLIMIT_CORE: i32 = 4;
-/// A limit on the process's resident set (the number of virtual pages resident in RAM).
-const RLIMIT_RSS: i32 = 5;
-/// The limit on the number of threads for the real user ID of the calling process.
-const RLIMIT_NPROC: i32 = 6;
-/// A value one greater than the maximum number of file descriptors that can be
-/// open by the process.
-const RLIMIT_NOFILE: i32 = 7;
-/// The maximum number of butes of memory that may be locked into RAM.
-const RLIMIT_MEMLOCK: i32 = 8;
-/// The maximum size of the memory space in bytes, rounded down to the page
-/// size.
-const RLIMIT_AS: i32 = 9;
-/// The limit on the combined number of flock(2) locks and fcntl(2) leases the
-/// process may establish.
-const RLIMIT_LOCKS: i32 = 10;
-/// The limit on the number of signals that may be queued for the real user ID of the calling
-/// process.
-const RLIMIT_SIGPENDING: i32 = 11;
-/// The limit on the number of bytes that can be allocated for POSIX message queues for the real
-/// user IF of the calling process.
-const RLIMIT_MSGQUEUE: i32 = 12;
-/// The ceiling to which the process's nice value can be raised.
-const RLIMIT_NICE: i32 = 13;
-/// The ceiling on the real-time priority that may be set for this process.
-const RLIMIT_RTPRIO: i32 = 14;
-/// The limit (in microseconds) on the amount of CPU that a process scheduled under a real-time
-/// scheduling policy may consume without masking a blocking system call.
-const RLIMIT_RTTIME: i32 = 15;
-/// TODO doc
-const RLIMIT_NLIMITS: i32 = 16;
-
 pub fn getpid(proc: Arc<Process>) -> EResult<usize> {
 	Ok(proc.get_pid() as _)
 }
@@ -202,8 +209,8 @@ pub fn gettid(proc: Arc<Process>) -> EResult<usize> {
 	Ok(proc.tid as _)
 }
 
-pub fn set_tid_address(Args(_tidptr): Args<UserPtr<c_int>>, proc: Arc<Process>) -> EResult<usize> {
-	// TODO set process's clear_child_tid
+pub fn set_tid_address(Args(tidptr): Args<UserPtr<c_int>>, proc: Arc<Process>) -> EResult<usize> {
+	proc.clear_child_tid.store(tidptr.as_ptr(), Release);
 	Ok(proc.tid as _)
 }
 
@@ -235,9 +242,18 @@ fn wait_vfork_done(child_pid: Pid) {
 	}
 }
 
+/// The thread-local storage to set on a new thread, with `CLONE_SETTLS`.
+enum CloneTls {
+	/// A TLS entry of the GDT, with its index, for 32 bit processes.
+	Entry(usize, gdt::Entry),
+	/// The base address of the `fs` segment, for 64 bit processes.
+	#[cfg(target_arch = "x86_64")]
+	FsBase(u64),
+}
+
 #[allow(clippy::type_complexity)]
 pub fn compat_clone(
-	Args((flags, stack, _parent_tid, _tls, _child_tid)): Args<(
+	Args((flags, stack, parent_tid, tls, child_tid_ptr)): Args<(
 		c_ulong,
 		*mut c_void,
 		UserPtr<c_int>,
@@ -247,6 +263,52 @@ pub fn compat_clone(
 	proc: Arc<Process>,
 	frame: &mut IntFrame,
 ) -> EResult<usize> {
+	// Copy the mount namespace before disabling interruptions, since this may require I/O
+	let fs = if flags & CLONE_NEWNS != 0 {
+		// Processes sharing filesystem information cannot be in different mount namespaces
+		if flags & CLONE_FS != 0 {
+			return Err(errno!(EINVAL));
+		}
+		let fs = proc.fs.lock().copy(false)?;
+		if !fs.access_profile.has_capability(CAP_SYS_ADMIN) {
+			return Err(errno!(EPERM));
+		}
+		fs.ctx.lock().unshare_mnt_ns()?;
+		Some(fs)
+	} else {
+		None
+	};
+	let ns = if flags & (CLONE_NEWUTS | CLONE_NEWIPC | CLONE_NEWNET) != 0 {
+		if !proc.fs.lock().access_profile.has_capability(CAP_SYS_ADMIN) {
+			return Err(errno!(EPERM));
+		}
+		let ns = proc.ns.lock().clone();
+		Some(ns.unshare(
+			flags & CLONE_NEWUTS != 0,
+			flags & CLONE_NEWIPC != 0,
+			flags & CLONE_NEWNET != 0,
+		)?)
+	} else {
+		None
+	};
+	// Read the TLS descriptor before disabling interruptions, since this may fault
+	let tls = if flags & CLONE_SETTLS == 0 {
+		None
+	} else if frame.is_compat() {
+		let info = UserPtr::<UserDesc>::from_ptr(tls as _)
+			.copy_from_user()?
+			.ok_or(errno!(EFAULT))?;
+		if unlikely(!info.is_valid_tls()) {
+			return Err(errno!(EINVAL));
+		}
+		let id = get_tls_index(info.get_entry_number())?;
+		Some(CloneTls::Entry(id, info.to_descriptor()))
+	} else {
+		#[cfg(target_arch = "x86")]
+		unreachable!();
+		#[cfg(target_arch = "x86_64")]
+		Some(CloneTls::FsBase(tls as _))
+	};
 	let (child_pid, child_tid) = {
 		// Disable interruptions so that the scheduler does not attempt to start the new process
 		cli();
@@ -256,19 +318,50 @@ pub fn compat_clone(
 				share_memory: flags & CLONE_VM != 0,
 				share_fd: flags & CLONE_FILES != 0,
 				share_sighand: flags & CLONE_SIGHAND != 0,
+				share_fs: flags & CLONE_FS != 0,
+				fs,
+				ns,
 			},
 		)?;
 		let child_pid = child.get_pid();
 		let child_tid = child.tid;
-		// Switch
+		// Like Linux, failures to write thread IDs are ignored
+		if flags & CLONE_PARENT_SETTID != 0 {
+			let _ = parent_tid.copy_to_user(&(child_tid as _));
+		}
+		if flags & CLONE_CHILD_SETTID != 0 {
+			if let Some(mem_space) = child.mem_space.as_ref() {
+				// Safety: the kernel stack is mapped in every memory space
+				unsafe {
+					MemSpace::switch(mem_space, |_| {
+						let _ = child_tid_ptr.copy_to_user(&(child_tid as _));
+					});
+				}
+			}
+		}
+		if flags & CLONE_CHILD_CLEARTID != 0 {
+			child.clear_child_tid.store(child_tid_ptr.as_ptr(), Release);
+		}
+		if let Some(CloneTls::Entry(id, entry)) = tls {
+			child.tls.lock()[id] = entry;
+		}
+		// Switch. The parent stays marked as being executed by the core until it switches away,
+		// since the child starts on its stack
 		switch::finish(&proc, &child);
-		SCHEDULER.lock().swap_current_process(child.clone());
+		with_run_queue(|rq| rq.swap_current_process(child.clone()));
 		let mut child_frame = frame.clone();
 		child_frame.rax = 0; // Return value
 		if !stack.is_null() {
 			child_frame.rsp = stack as _;
 		}
 		stash_segments(|| unsafe {
+			// Segment registers are not reloaded when returning to userspace
+			#[cfg(target_arch = "x86_64")]
+			match tls {
+				Some(CloneTls::Entry(..)) => switch::reload_tls(&child.tls.lock()),
+				Some(CloneTls::FsBase(base)) => x86::wrmsr(x86::IA32_FS_BASE, base),
+				None => {}
+			}
 			fork_asm(Arc::as_ptr(&proc), Arc::as_ptr(&child), &child_frame);
 		});
 		(child_pid, child_tid)
@@ -320,6 +413,16 @@ pub fn vfork(proc: Arc<Process>, frame: &mut IntFrame) -> EResult<usize> {
 	)
 }
 
+/// Returns the index in the TLS array of the GDT entry `entry_number`.
+fn get_tls_index(entry_number: i32) -> EResult<usize> {
+	const BEGIN_ENTRY: i32 = TLS_BEGIN_INDEX as i32;
+	const END_ENTRY: i32 = BEGIN_ENTRY + process::TLS_ENTRIES_COUNT as i32;
+	match entry_number {
+		BEGIN_ENTRY..END_ENTRY => Ok((entry_number - BEGIN_ENTRY) as usize),
+		_ => Err(errno!(EINVAL)),
+	}
+}
+
 /// Returns an entry ID for the given process and entry number.
 ///
 /// If the id is `-1`, the function shall find a free entry.
@@ -327,20 +430,10 @@ fn get_tls_entry(
 	entries: &mut [gdt::Entry; process::TLS_ENTRIES_COUNT],
 	entry_number: i32,
 ) -> EResult<(usize, &mut gdt::Entry)> {
-	const BEGIN_ENTRY: i32 = TLS_BEGIN_INDEX as i32;
-	const END_ENTRY: i32 = BEGIN_ENTRY + process::TLS_ENTRIES_COUNT as i32;
 	let id = match entry_number {
 		// Find a free entry
-		-1 => entries
-			.iter()
-			.enumerate()
-			.find(|(_, e)| !e.is_present())
-			.map(|(i, _)| i)
-			.ok_or(errno!(ESRCH))?,
-		// Valid entry index
-		BEGIN_ENTRY..END_ENTRY => (entry_number - BEGIN_ENTRY) as usize,
-		// Out of bounds
-		_ => return Err(errno!(EINVAL)),
+		-1 => entries.iter().position(|e| e.0 == 0).ok_or(errno!(ESRCH))?,
+		_ => get_tls_index(entry_number)?,
 	};
 	Ok((id, &mut entries[id]))
 }
@@ -351,6 +444,9 @@ pub fn set_thread_area(
 ) -> EResult<usize> {
 	// Read user_desc
 	let mut info = u_info.copy_from_user()?.ok_or(errno!(EFAULT))?;
+	if unlikely(!info.is_valid_tls()) {
+		return Err(errno!(EINVAL));
+	}
 	// Get the entry with its id
 	let mut entries = proc.tls.lock();
 	let (id, entry) = get_tls_entry(&mut entries, info.get_entry_number())?;
@@ -369,6 +465,18 @@ pub fn set_thread_area(
 	Ok(0)
 }
 
+pub fn get_thread_area(
+	Args(u_info): Args<UserPtr<UserDesc>>,
+	proc: Arc<Process>,
+) -> EResult<usize> {
+	let info = u_info.copy_from_user()?.ok_or(errno!(EFAULT))?;
+	let entry_number = info.get_entry_number();
+	let id = get_tls_index(entry_number)?;
+	let entry = proc.tls.lock()[id];
+	u_info.copy_to_user(&UserDesc::from_descriptor(entry_number, &entry))?;
+	Ok(0)
+}
+
 pub fn arch_prctl(Args((code, addr)): Args<(c_int, usize)>) -> EResult<usize> {
 	// For `gs`, use kernel base because it will get swapped when returning to userspace
 	match code {
@@ -396,70 +504,217 @@ pub fn arch_prctl(Args((code, addr)): Args<(c_int, usize)>) -> EResult<usize> {
 	Ok(0)
 }
 
+/// Attaches the seccomp filter pointed to by `fprog` to `proc`.
+///
+/// `compat` tells whether the filter is given by a 32 bit process.
+fn seccomp_attach_filter(proc: &Process, fprog: usize, compat: bool) -> EResult<usize> {
+	// Filters cannot be used to mislead a privileged program
+	let ap = proc.fs.lock().access_profile;
+	if unlikely(!proc.no_new_privs.load(Relaxed) && !ap.has_capability(CAP_SYS_ADMIN)) {
+		return Err(errno!(EACCES));
+	}
+	fn read<F: UserRepr<SockFprog>>(ptr: usize) -> EResult<SockFprog> {
+		let fprog = UserPtr::<F>::from_ptr(ptr)
+			.copy_from_user()?
+			.ok_or_else(|| errno!(EFAULT))?;
+		Ok(fprog.into())
+	}
+	let fprog = if compat {
+		read::<CompatSockFprog>(fprog)?
+	} else {
+		read::<SockFprog>(fprog)?
+	};
+	let len = fprog.len as usize;
+	if unlikely(len == 0 || len > bpf::MAX_INSNS) {
+		return Err(errno!(EINVAL));
+	}
+	let insns =
+		UserSlice::<SockFilter>::from_user(ptr::with_exposed_provenance_mut(fprog.filter), len)?
+			.copy_from_user_vec(0)?
+			.ok_or_else(|| errno!(EFAULT))?;
+	let prog = Program::new_seccomp(insns, size_of::<SeccompData>())?;
+	proc.seccomp.lock().add_filter(prog, len)?;
+	Ok(0)
+}
+
+pub fn seccomp(
+	Args((op, flags, args)): Args<(c_uint, c_uint, usize)>,
+	proc: Arc<Process>,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	// No flag is supported
+	if unlikely(flags != 0) {
+		return Err(errno!(EINVAL));
+	}
+	match op {
+		SECCOMP_SET_MODE_STRICT => {
+			if unlikely(args != 0) {
+				return Err(errno!(EINVAL));
+			}
+			proc.seccomp.lock().set_strict()?;
+			Ok(0)
+		}
+		SECCOMP_SET_MODE_FILTER => seccomp_attach_filter(&proc, args, frame.is_compat()),
+		SECCOMP_GET_ACTION_AVAIL => {
+			let action = UserPtr::<u32>::from_ptr(args)
+				.copy_from_user()?
+				.ok_or_else(|| errno!(EFAULT))?;
+			if !seccomp::is_action_available(action) {
+				return Err(errno!(EOPNOTSUPP));
+			}
+			Ok(0)
+		}
+		_ => Err(errno!(EINVAL)),
+	}
+}
+
+pub fn prctl(
+	Args((option, arg2, arg3, arg4, arg5)): Args<(c_int, c_ulong, c_ulong, c_ulong, c_ulong)>,
+	proc: Arc<Process>,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	match option {
+		PR_GET_DUMPABLE => Ok(proc.dumpable.load(Relaxed) as _),
+		PR_SET_DUMPABLE => {
+			if unlikely(arg2 > 1) {
+				return Err(errno!(EINVAL));
+			}
+			proc.dumpable.store(arg2 != 0, Relaxed);
+			Ok(0)
+		}
+		PR_GET_SECCOMP => Ok(proc.seccomp.lock().mode() as _),
+		PR_SET_SECCOMP => match arg2 as u8 {
+			SECCOMP_MODE_STRICT => {
+				proc.seccomp.lock().set_strict()?;
+				Ok(0)
+			}
+			SECCOMP_MODE_FILTER => seccomp_attach_filter(&proc, arg3 as _, frame.is_compat()),
+			_ => Err(errno!(EINVAL)),
+		},
+		PR_SET_CHILD_SUBREAPER => {
+			proc.child_subreaper.store(arg2 != 0, Relaxed);
+			Ok(0)
+		}
+		PR_GET_CHILD_SUBREAPER => {
+			let subreaper = proc.child_subreaper.load(Relaxed) as c_int;
+			UserPtr::<c_int>::from_ptr(arg2 as _).copy_to_user(&subreaper)?;
+			Ok(0)
+		}
+		PR_SET_NO_NEW_PRIVS => {
+			// The attribute cannot be unset
+			if unlikely(arg2 != 1 || arg3 != 0 || arg4 != 0 || arg5 != 0) {
+				return Err(errno!(EINVAL));
+			}
+			proc.no_new_privs.store(true, Relaxed);
+			Ok(0)
+		}
+		PR_GET_NO_NEW_PRIVS => {
+			if unlikely(arg2 != 0 || arg3 != 0 || arg4 != 0 || arg5 != 0) {
+				return Err(errno!(EINVAL));
+			}
+			Ok(proc.no_new_privs.load(Relaxed) as _)
+		}
+		_ => Err(errno!(EINVAL)),
+	}
+}
+
 pub fn getrusage(Args((who, usage)): Args<(c_int, UserPtr<Rusage>)>) -> EResult<usize> {
 	let proc = Process::current();
 	let rusage = match who {
-		RUSAGE_SELF => proc.rusage.lock().clone(),
-		RUSAGE_CHILDREN => {
-			// TODO Return resources of terminated children
-			Rusage::default()
+		RUSAGE_SELF => {
+			// Include resources used since the last tick
+			core_local().rusage.drain_into(&proc.rusage);
+			proc.get_rusage()
 		}
+		RUSAGE_CHILDREN => proc.children_rusage.lock().clone(),
 		_ => return Err(errno!(EINVAL)),
 	};
 	usage.copy_to_user(&rusage)?;
 	Ok(0)
 }
 
-/// A resource limit.
-#[repr(C)]
-#[derive(Debug)]
-pub struct RLimit {
-	/// Soft limit
-	rlim_cur: u64,
-	/// Hard limit (ceiling for [`rlim_cur`])
-	rlim_max: u64,
+pub fn times(Args(buf): Args<UserPtr<Tms>>) -> EResult<usize> {
+	let proc = Process::current();
+	let (utime, stime) = proc.get_cpu_time();
+	let (cutime, cstime) = {
+		let children = proc.children_rusage.lock();
+		(children.ru_utime.to_nano(), children.ru_stime.to_nano())
+	};
+	buf.copy_to_user(&Tms {
+		tms_utime: ns_to_ticks(utime) as _,
+		tms_stime: ns_to_ticks(stime) as _,
+		tms_cutime: ns_to_ticks(cutime) as _,
+		tms_cstime: ns_to_ticks(cstime) as _,
+	})?;
+	// Return the number of clock ticks elapsed since boot
+	let ticks = ns_to_ticks(current_time_ns(Clock::Monotonic));
+	Ok(ticks as _)
 }
 
 pub fn prlimit64(
-	Args((pid, resource, _new_limit, _old_limit)): Args<(
+	Args((pid, resource, new_limit, old_limit)): Args<(
 		Pid,
 		c_int,
 		UserPtr<RLimit>,
 		UserPtr<RLimit>,
 	)>,
+	proc: Arc<Process>,
 ) -> EResult<usize> {
-	// The target process. If None, the current process is the target
-	let _target_proc = if pid != 0 {
+	let privileged = proc
+		.fs
+		.lock()
+		.access_profile
+		.has_capability(CAP_SYS_RESOURCE);
+	// The target process
+	let target_proc = if pid != 0 {
 		// TODO Check permission
-		Some(Process::get_by_pid(pid).ok_or_else(|| errno!(ESRCH))?)
+		Process::get_by_pid(pid).ok_or_else(|| errno!(ESRCH))?
 	} else {
-		None
+		proc
 	};
-	// TODO Implement all
-	match resource {
-		RLIMIT_CPU => {}
-		RLIMIT_FSIZE => {}
-		RLIMIT_DATA => {}
-		RLIMIT_STACK => {}
-		RLIMIT_CORE => {}
-		RLIMIT_RSS => {}
-		RLIMIT_NPROC => {}
-		RLIMIT_NOFILE => {}
-		RLIMIT_MEMLOCK => {}
-		RLIMIT_AS => {}
-		RLIMIT_LOCKS => {}
-		RLIMIT_SIGPENDING => {}
-		RLIMIT_MSGQUEUE => {}
-		RLIMIT_NICE => {}
-		RLIMIT_RTPRIO => {}
-		RLIMIT_RTTIME => {}
-		RLIMIT_NLIMITS => {}
-		_ => return Err(errno!(EINVAL)),
+	let resource: usize = resource.try_into().map_err(|_| errno!(EINVAL))?;
+	if unlikely(resource >= RLIMIT_NLIMITS) {
+		return Err(errno!(EINVAL));
+	}
+	let new_limit = new_limit.copy_from_user()?;
+	if unlikely(new_limit.as_ref().is_some_and(|l| l.rlim_cur > l.rlim_max)) {
+		return Err(errno!(EINVAL));
+	}
+	// The number of file descriptors cannot exceed `fs.nr_open`
+	let nofile = resource == RLIMIT_NOFILE as usize;
+	if unlikely(
+		nofile
+			&& new_limit
+				.as_ref()
+				.is_some_and(|l| l.rlim_max > NR_OPEN.load(Relaxed) as u64),
+	) {
+		return Err(errno!(EPERM));
+	}
+	let old = {
+		let mut rlimits = target_proc.rlimits.lock();
+		let limit = &mut rlimits[resource];
+		let old = *limit;
+		if let Some(new_limit) = new_limit {
+			// Only a privileged process can raise the hard limit
+			if unlikely(new_limit.rlim_max > limit.rlim_max && !privileged) {
+				return Err(errno!(EPERM));
+			}
+			*limit = new_limit;
+		}
+		old
+	};
+	if let Some(new_limit) = new_limit.filter(|_| nofile) {
+		if let Some(fds) = target_proc.file_descriptors.get() {
+			let limit = new_limit.rlim_cur.try_into().unwrap_or(u32::MAX);
+			fds.lock().set_limit(limit);
+		}
 	}
+	old_limit.copy_to_user(&old)?;
 	Ok(0)
 }
 
 pub fn sched_yield() -> EResult<usize> {
+	Scheduler::yield_current();
 	Scheduler::tick();
 	Ok(0)
 }
@@ -0,0 +1,332 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Scheduling parameters system calls.
+
+use crate::{
+	file::perm::{AccessProfile, CAP_SYS_NICE, Uid},
+	memory::user::{UserPtr, UserSlice},
+	process::{
+		Process, State,
+		pid::Pid,
+		rlimit::{RLIMIT_NICE, RLIMIT_RTPRIO},
+		scheduler::{
+			CpuMask, NICE_MAX, NICE_MIN, RT_PRIO_MAX, RT_PRIO_MIN, SCHED_BATCH, SCHED_FIFO,
+			SCHED_IDLE, SCHED_OTHER, SCHED_RR, SCHEDULER, Scheduler, core_id, is_rt_policy,
+			online_cpus,
+		},
+	},
+	syscall::Args,
+	uapi::sched::SchedParam,
+};
+use core::{
+	ffi::{c_int, c_ulong},
+	hint::unlikely,
+	sync::atomic::Ordering::Relaxed,
+};
+use utils::{TryClone, collections::vec::Vec, errno, errno::EResult, ptr::arc::Arc};
+
+/// Returns the process with PID `pid`, or the current process if `pid` is zero.
+fn get_target(pid: Pid) -> EResult<Arc<Process>> {
+	if pid == 0 {
+		Ok(Process::current())
+	} else {
+		Process::get_by_pid(pid).ok_or_else(|| errno!(ESRCH))
+	}
+}
+
+/// A set of processes targeted by a system call.
+///
+/// For each variant, the value zero designates the current process, or its process group or
+/// user.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Target {
+	/// The process with the given PID.
+	Process(c_int),
+	/// The processes in the process group with the given ID.
+	Pgrp(c_int),
+	/// The processes of the user with the given ID.
+	User(c_int),
+}
+
+/// Returns the list of processes in `target`.
+///
+/// If no process matches, the function returns [`errno::ESRCH`].
+pub(super) fn get_targets(target: Target) -> EResult<Vec<Arc<Process>>> {
+	let mut targets = Vec::new();
+	match target {
+		Target::Process(who) => {
+			let proc = match who {
+				0 => Some(Process::current()),
+				_ => Pid::try_from(who).ok().and_then(Process::get_by_pid),
+			};
+			if let Some(proc) = proc {
+				targets.push(proc)?;
+			}
+		}
+		Target::Pgrp(who) => {
+			let pgid = match who {
+				0 => Some(Process::current().get_pgid()),
+				_ => Pid::try_from(who).ok(),
+			};
+			if let Some(leader) = pgid.and_then(Process::get_by_pid) {
+				let pids = leader.links.lock().process_group.try_clone()?;
+				for pid in pids {
+					if let Some(proc) = Process::get_by_pid(pid) {
+						targets.push(proc)?;
+					}
+				}
+			}
+		}
+		Target::User(who) => {
+			let uid = match who {
+				0 => Some(Process::current().fs.lock().access_profile.uid),
+				// An ID which cannot be represented matches no user
+				_ => Uid::try_from(who).ok(),
+			};
+			if let Some(uid) = uid {
+				let sched = SCHEDULER.lock();
+				for (_, proc) in sched.iter_process() {
+					if proc.fs.lock().access_profile.uid == uid {
+						targets.push(proc.clone())?;
+					}
+				}
+			}
+		}
+	}
+	targets.retain(|proc| !matches!(proc.get_state(), State::Zombie));
+	if targets.is_empty() {
+		return Err(errno!(ESRCH));
+	}
+	Ok(targets)
+}
+
+/// Checks the access profile `ap` is allowed to change the scheduling parameters of `proc`.
+fn check_permission(ap: &AccessProfile, proc: &Process) -> EResult<()> {
+	if !ap.has_capability(CAP_SYS_NICE) {
+		let target = proc.fs.lock().access_profile;
+		if ap.euid != target.uid && ap.euid != target.euid {
+			return Err(errno!(EPERM));
+		}
+	}
+	Ok(())
+}
+
+/// Returns the range of priorities for the scheduling policy `policy`.
+///
+/// If the policy is invalid, the function returns [`errno::EINVAL`].
+fn priority_range(policy: c_int) -> EResult<(u8, u8)> {
+	let policy: u8 = policy.try_into().map_err(|_| errno!(EINVAL))?;
+	match policy {
+		SCHED_FIFO | SCHED_RR => Ok((RT_PRIO_MIN, RT_PRIO_MAX)),
+		SCHED_OTHER | SCHED_BATCH | SCHED_IDLE => Ok((0, 0)),
+		_ => Err(errno!(EINVAL)),
+	}
+}
+
+/// Sets the scheduling policy and priority of the process with PID `pid`.
+///
+/// If `policy` is `None`, the current policy of the process is kept.
+fn set_scheduler(
+	pid: Pid,
+	policy: Option<c_int>,
+	param: UserPtr<SchedParam>,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	let param = param.copy_from_user()?.ok_or_else(|| errno!(EINVAL))?;
+	let proc = get_target(pid)?;
+	let policy = policy.unwrap_or_else(|| proc.sched_policy.load(Relaxed) as _);
+	let (min, max) = priority_range(policy)?;
+	let priority: u8 = param
+		.sched_priority
+		.try_into()
+		.map_err(|_| errno!(EINVAL))?;
+	if unlikely(!(min..=max).contains(&priority)) {
+		return Err(errno!(EINVAL));
+	}
+	let policy = policy as u8;
+	check_permission(ap, &proc)?;
+	// Raising the real-time priority is limited by `RLIMIT_RTPRIO`
+	if is_rt_policy(policy) && !ap.has_capability(CAP_SYS_NICE) {
+		let limit = proc.rlimits.lock()[RLIMIT_RTPRIO as usize].rlim_cur;
+		let curr = proc.rt_priority.load(Relaxed);
+		if priority as u64 > limit && priority > curr {
+			return Err(errno!(EPERM));
+		}
+	}
+	Scheduler::set_scheduler(&proc, policy, priority)?;
+	Ok(0)
+}
+
+pub fn sched_setscheduler(
+	Args((pid, policy, param)): Args<(Pid, c_int, UserPtr<SchedParam>)>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	set_scheduler(pid, Some(policy), param, &ap)
+}
+
+pub fn sched_getscheduler(Args(pid): Args<Pid>) -> EResult<usize> {
+	let proc = get_target(pid)?;
+	Ok(proc.sched_policy.load(Relaxed) as _)
+}
+
+pub fn sched_setparam(
+	Args((pid, param)): Args<(Pid, UserPtr<SchedParam>)>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	set_scheduler(pid, None, param, &ap)
+}
+
+pub fn sched_getparam(Args((pid, param)): Args<(Pid, UserPtr<SchedParam>)>) -> EResult<usize> {
+	if unlikely(param.as_ptr().is_null()) {
+		return Err(errno!(EINVAL));
+	}
+	let proc = get_target(pid)?;
+	param.copy_to_user(&SchedParam {
+		sched_priority: proc.rt_priority.load(Relaxed) as _,
+	})?;
+	Ok(0)
+}
+
+pub fn sched_get_priority_max(Args(policy): Args<c_int>) -> EResult<usize> {
+	let (_, max) = priority_range(policy)?;
+	Ok(max as _)
+}
+
+pub fn sched_get_priority_min(Args(policy): Args<c_int>) -> EResult<usize> {
+	let (min, _) = priority_range(policy)?;
+	Ok(min as _)
+}
+
+pub fn sched_setaffinity(
+	Args((pid, len, mask)): Args<(Pid, usize, *mut u8)>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	if unlikely(mask.is_null()) {
+		return Err(errno!(EFAULT));
+	}
+	let proc = get_target(pid)?;
+	check_permission(&ap, &proc)?;
+	// Bits for cores beyond the ones that can be represented are ignored
+	let mut buf = [0u8; size_of::<CpuMask>()];
+	let len = len.min(buf.len());
+	UserSlice::from_user(mask, len)?.copy_from_user(0, &mut buf[..len])?;
+	let mask = CpuMask::from_le_bytes(buf) & online_cpus();
+	if unlikely(mask == 0) {
+		return Err(errno!(EINVAL));
+	}
+	proc.cpu_mask.store(mask, Relaxed);
+	// If the current process is not allowed on the current core anymore, move it
+	if pid == 0 && !proc.can_run_on(core_id()) {
+		Scheduler::tick();
+	}
+	Ok(0)
+}
+
+pub fn sched_getaffinity(Args((pid, len, mask)): Args<(Pid, usize, *mut u8)>) -> EResult<usize> {
+	// The mask must be large enough to hold all online cores
+	let cpus_count = (CpuMask::BITS - online_cpus().leading_zeros()) as usize;
+	if unlikely(len.saturating_mul(8) < cpus_count) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(!len.is_multiple_of(size_of::<c_ulong>())) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(mask.is_null()) {
+		return Err(errno!(EFAULT));
+	}
+	let proc = get_target(pid)?;
+	let buf = (proc.cpu_mask.load(Relaxed) & online_cpus()).to_le_bytes();
+	let len = len.min(buf.len());
+	UserSlice::from_user(mask, len)?.copy_to_user(0, &buf[..len])?;
+	Ok(len)
+}
+
+/// `getpriority`/`setpriority` target: a single process.
+const PRIO_PROCESS: c_int = 0;
+/// `getpriority`/`setpriority` target: a process group.
+const PRIO_PGRP: c_int = 1;
+/// `getpriority`/`setpriority` target: all processes of a user.
+const PRIO_USER: c_int = 2;
+
+/// Tells whether the access profile `ap` allows to lower the nice value of `proc` to `nice`.
+fn can_nice(ap: &AccessProfile, proc: &Process, nice: i8) -> bool {
+	if ap.has_capability(CAP_SYS_NICE) {
+		return true;
+	}
+	// The limit is expressed in the range `1..=40`, as `20 - nice`
+	let limit = proc.rlimits.lock()[RLIMIT_NICE as usize].rlim_cur;
+	(20 - nice as i64) as u64 <= limit
+}
+
+pub fn nice(Args(inc): Args<c_int>, ap: AccessProfile) -> EResult<usize> {
+	let proc = Process::current();
+	let curr = proc.nice.load(Relaxed);
+	let nice = (curr as c_int + inc.clamp(-40, 40)).clamp(NICE_MIN as _, NICE_MAX as _) as i8;
+	if nice < curr && !can_nice(&ap, &proc, nice) {
+		return Err(errno!(EPERM));
+	}
+	proc.nice.store(nice, Relaxed);
+	Ok(0)
+}
+
+/// Returns the list of processes targeted by `which` and `who`.
+///
+/// If no process matches, the function returns [`errno::ESRCH`].
+fn get_prio_targets(which: c_int, who: c_int) -> EResult<Vec<Arc<Process>>> {
+	let target = match which {
+		PRIO_PROCESS => Target::Process(who),
+		PRIO_PGRP => Target::Pgrp(who),
+		PRIO_USER => Target::User(who),
+		_ => return Err(errno!(EINVAL)),
+	};
+	get_targets(target)
+}
+
+pub fn getpriority(Args((which, who)): Args<(c_int, c_int)>) -> EResult<usize> {
+	// If several processes match, return the highest priority
+	let nice = get_prio_targets(which, who)?
+		.iter()
+		.map(|proc| proc.nice.load(Relaxed))
+		.min()
+		.unwrap();
+	// The system call returns the value `20 - nice`, so that it cannot be negative
+	Ok((20 - nice as isize) as _)
+}
+
+pub fn setpriority(
+	Args((which, who, prio)): Args<(c_int, c_int, c_int)>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let nice = prio.clamp(NICE_MIN as _, NICE_MAX as _) as i8;
+	// Every process the caller is allowed to change is updated, even if others fail. The last
+	// error is returned
+	let mut res = Ok(0);
+	for proc in get_prio_targets(which, who)? {
+		if let Err(e) = check_permission(&ap, &proc) {
+			res = Err(e);
+			continue;
+		}
+		if nice < proc.nice.load(Relaxed) && !can_nice(&ap, &proc, nice) {
+			res = Err(errno!(EACCES));
+			continue;
+		}
+		proc.nice.store(nice, Relaxed);
+	}
+	res
+}
@@ -20,19 +20,25 @@
 //! writable or for an exception to occur.
 
 use crate::{
-	file::fd::FileDescriptorTable,
+	file::{fd::FileDescriptorTable, wait_queue::PollWait},
 	memory::user::{UserPtr, UserSlice},
-	process::scheduler::Scheduler,
+	process::{
+		Process, State,
+		scheduler::Scheduler,
+		signal::{SIGEV_NONE, SigEvent, SigSet},
+	},
 	sync::mutex::Mutex,
 	syscall::Args,
 	time::{
-		clock::{Clock, current_time_ms, current_time_ns},
+		clock::{Clock, current_time_ns},
+		timer::Timer,
 		unit::{TimeUnit, Timespec, Timestamp, Timeval},
 	},
 };
 use core::{
 	cmp::min,
 	ffi::{c_int, c_long},
+	mem,
 };
 use utils::{errno, errno::EResult, ptr::arc::Arc};
 
@@ -41,7 +47,7 @@ pub const FD_SETSIZE: usize = 1024;
 
 /// Structure representing `fd_set`.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct FDSet {
 	/// The set's bitfield.
 	fds_bits: [c_long; FD_SETSIZE / c_long::BITS as usize],
@@ -53,14 +59,12 @@ impl FDSet {
 		if fd as usize >= FD_SETSIZE {
 			return false;
 		}
-		// TODO Check correctness
 		let i = (fd as usize) / c_long::BITS as usize;
-		self.fds_bits[i] >> (fd % c_long::BITS) != 0
+		(self.fds_bits[i] >> (fd % c_long::BITS)) & 1 != 0
 	}
 
 	/// Sets or clears the bit for file descriptor `fd`.
 	fn set(&mut self, fd: u32, val: bool) {
-		// TODO Check correctness
 		let i = (fd as usize) / c_long::BITS as usize;
 		if val {
 			self.fds_bits[i] |= 1 << (fd % c_long::BITS);
@@ -70,6 +74,102 @@ impl FDSet {
 	}
 }
 
+/// Polls the file descriptor `fd` of the table `fds` for the events in `mask`.
+///
+/// The current process is registered on the wait queues of the file through `wait`.
+///
+/// If `fd` is not open, the function returns `None`.
+fn poll_fd(
+	fds: &Mutex<FileDescriptorTable>,
+	fd: c_int,
+	mask: u32,
+	wait: &mut PollWait,
+) -> EResult<Option<u32>> {
+	let file = {
+		let fds = fds.lock();
+		let Ok(fd) = fds.get_fd(fd) else {
+			return Ok(None);
+		};
+		fd.get_file().clone()
+	};
+	// Register before polling so that an event occurring in between is not missed
+	wait.register(file.clone(), mask)?;
+	file.ops.poll(&file, mask).map(Some)
+}
+
+/// Calls `check` until it reports at least one event, then returns the number of events.
+///
+/// `check` registers the current process on the wait queues of the files it polls through the
+/// given [`PollWait`]. Between calls, the process sleeps until woken up by one of the files, the
+/// timeout or a signal.
+///
+/// `timeout` is the timeout in nanoseconds, after which the function returns `0`. If `None`, the
+/// function waits indefinitely. If zero, `check` is called only once.
+///
+/// If the process is interrupted by a signal, the function returns [`errno::EINTR`].
+pub fn wait_events<F: FnMut(&mut PollWait) -> EResult<usize>>(
+	timeout: Option<Timestamp>,
+	mut check: F,
+) -> EResult<usize> {
+	let end = timeout.map(|t| current_time_ns(Clock::Monotonic) + t);
+	let mut wait = PollWait::default();
+	// Timer waking the process up on timeout
+	let mut timer = None;
+	loop {
+		let count = check(&mut wait)?;
+		if count > 0 {
+			return Ok(count);
+		}
+		// On timeout, return 0
+		if end.is_some_and(|end| current_time_ns(Clock::Monotonic) >= end) {
+			return Ok(0);
+		}
+		let proc = Process::current();
+		if proc.has_pending_signal() {
+			return Err(errno!(EINTR));
+		}
+		if wait.can_sleep() {
+			if let (Some(timeout), None) = (timeout, &timer) {
+				let t = Timer::new(
+					Clock::Monotonic,
+					proc.get_pid(),
+					SigEvent {
+						sigev_notify: SIGEV_NONE,
+						..Default::default()
+					},
+				)?;
+				t.set_time(0, timeout)?;
+				timer = Some(t);
+			}
+			proc.set_state(State::Sleeping);
+		}
+		Scheduler::tick();
+		wait.clear();
+	}
+}
+
+/// Runs `f` with the signal mask of the current process temporarily replaced by `sigmask`.
+///
+/// If `f` is interrupted by a signal, the previous mask is restored only once the signal has been
+/// handled, so that the signal is delivered with `sigmask` in effect.
+///
+/// If `sigmask` is `None`, the signal mask is left untouched.
+fn with_sigmask<F: FnOnce() -> EResult<usize>>(sigmask: Option<SigSet>, f: F) -> EResult<usize> {
+	let Some(sigmask) = sigmask else {
+		return f();
+	};
+	let proc = Process::current();
+	let old = mem::replace(&mut proc.signal.lock().sigmask, sigmask);
+	let res = f();
+	let mut signal = proc.signal.lock();
+	if matches!(&res, Err(e) if e.as_int() == errno::EINTR) {
+		signal.saved_sigmask = Some(old);
+	} else {
+		signal.sigmask = old;
+	}
+	res
+}
+
 /// Performs the select operation.
 ///
 /// Arguments:
@@ -79,7 +179,8 @@ impl FDSet {
 /// - `readfds` is the bitfield of fds to check for read operations.
 /// - `writefds` is the bitfield of fds to check for write operations.
 /// - `exceptfds` is the bitfield of fds to check for exceptional conditions.
-/// - `timeout` is the timeout after which the syscall returns.
+/// - `timeout` is the timeout after which the syscall returns. If null, the syscall waits
+///   indefinitely.
 /// - `sigmask` TODO
 pub fn do_select<T: TimeUnit>(
 	fds: Arc<Mutex<FileDescriptorTable>>,
@@ -90,37 +191,22 @@ pub fn do_select<T: TimeUnit>(
 	timeout: UserPtr<T>,
 	_sigmask: Option<*mut u8>,
 ) -> EResult<usize> {
-	let start = current_time_ns(Clock::Monotonic);
-	// Get timeout
-	let timeout = timeout
-		.copy_from_user()?
-		.map(|t| t.to_nano())
-		.unwrap_or_default();
-	// Tells whether the syscall immediately returns
-	let polling = timeout == 0;
-	// The end timestamp
-	let end = start + timeout;
+	let timeout = timeout.copy_from_user()?.map(|t| t.to_nano());
 	// Read
-	let mut readfds_set = readfds.copy_from_user()?;
-	let mut writefds_set = writefds.copy_from_user()?;
-	let mut exceptfds_set = exceptfds.copy_from_user()?;
-	let res = loop {
+	let readfds_set = readfds.copy_from_user()?;
+	let writefds_set = writefds.copy_from_user()?;
+	let exceptfds_set = exceptfds.copy_from_user()?;
+	// Results
+	let mut readfds_res = FDSet::default();
+	let mut writefds_res = FDSet::default();
+	let mut exceptfds_res = FDSet::default();
+	let is_set = |set: &Option<FDSet>, fd_id| set.as_ref().is_some_and(|fds| fds.is_set(fd_id));
+	let res = wait_events(timeout, |wait| {
 		let mut events_count = 0;
-		// Set if every bitfields are set to zero
-		let mut all_zeros = true;
 		for fd_id in 0..min(nfds, FD_SETSIZE as u32) {
-			let read = readfds_set
-				.as_ref()
-				.map(|fds| fds.is_set(fd_id))
-				.unwrap_or(false);
-			let write = writefds_set
-				.as_ref()
-				.map(|fds| fds.is_set(fd_id))
-				.unwrap_or(false);
-			let except = exceptfds_set
-				.as_ref()
-				.map(|fds| fds.is_set(fd_id))
-				.unwrap_or(false);
+			let read = is_set(&readfds_set, fd_id);
+			let write = is_set(&writefds_set, fd_id);
+			let except = is_set(&exceptfds_set, fd_id);
 			// Build event mask
 			let mut mask = 0;
 			if read {
@@ -132,61 +218,34 @@ pub fn do_select<T: TimeUnit>(
 			if except {
 				mask |= POLLPRI;
 			}
-			if mask != 0 {
-				all_zeros = false;
+			if mask == 0 {
+				continue;
 			}
 			// Poll file
-			let result = {
-				let fds = fds.lock();
-				let Ok(fd) = fds.get_fd(fd_id as _) else {
-					if mask != 0 {
-						return Err(errno!(EBADF));
-					}
-					continue;
-				};
-				let file = fd.get_file();
-				file.ops.poll(file, mask)?
-			};
+			let result = poll_fd(&fds, fd_id as _, mask, wait)?.ok_or_else(|| errno!(EBADF))?;
 			// Set results
 			let read = read && result & POLLIN != 0;
 			let write = write && result & POLLOUT != 0;
 			let except = except && result & POLLPRI != 0;
-			if let Some(fds) = &mut readfds_set {
-				fds.set(fd_id, read);
-			}
-			if let Some(fds) = &mut writefds_set {
-				fds.set(fd_id, write);
-			}
-			if let Some(fds) = &mut exceptfds_set {
-				fds.set(fd_id, except);
-			}
+			readfds_res.set(fd_id, read);
+			writefds_res.set(fd_id, write);
+			exceptfds_res.set(fd_id, except);
 			events_count += read as usize + write as usize + except as usize;
 		}
-		// If one or more events occurred, return
-		if all_zeros || polling || events_count > 0 {
-			break events_count;
-		}
-		let ts = current_time_ns(Clock::Monotonic);
-		// On timeout, return 0
-		if ts >= end {
-			break 0;
-		}
-		// TODO Make the process sleep?
-		Scheduler::tick();
-	};
+		Ok(events_count)
+	})?;
 	// Write back
-	if let Some(val) = readfds_set {
-		readfds.copy_to_user(&val)?;
+	if readfds_set.is_some() {
+		readfds.copy_to_user(&readfds_res)?;
 	}
-	if let Some(val) = writefds_set {
-		writefds.copy_to_user(&val)?;
+	if writefds_set.is_some() {
+		writefds.copy_to_user(&writefds_res)?;
 	}
-	if let Some(val) = exceptfds_set {
-		exceptfds.copy_to_user(&val)?;
+	if exceptfds_set.is_some() {
+		exceptfds.copy_to_user(&exceptfds_res)?;
 	}
 	Ok(res)
 }
-
 #[allow(clippy::type_complexity)]
 pub(super) fn select(
 	Args((nfds, readfds, writefds, exceptfds, timeout)): Args<(
@@ -267,65 +326,122 @@ pub const POLLRDHUP: u32 = 0x2000;
 #[derive(Debug)]
 pub struct PollFD {
 	/// The file descriptor.
-	fd: i32,
+	pub fd: i32,
 	/// The input mask telling which events to look for.
-	events: i16,
+	pub events: i16,
 	/// The output mask telling which events happened.
-	revents: i16,
+	pub revents: i16,
+}
+
+impl PollFD {
+	/// Polls the file descriptor and returns the events that happened on it, among the requested
+	/// ones.
+	///
+	/// If the file descriptor is negative, it is ignored and the function returns `0`. If it is
+	/// not open, the function returns [`POLLNVAL`].
+	fn poll(&self, fds: &Mutex<FileDescriptorTable>, wait: &mut PollWait) -> EResult<u32> {
+		if self.fd < 0 {
+			return Ok(0);
+		}
+		let events = self.events as u16 as u32;
+		// Errors and hang ups are always reported
+		let events = events | POLLERR | POLLHUP;
+		let mut mask = events;
+		if events & POLLRDNORM != 0 {
+			mask |= POLLIN;
+		}
+		if events & POLLWRNORM != 0 {
+			mask |= POLLOUT;
+		}
+		let Some(mut revents) = poll_fd(fds, self.fd, mask, wait)? else {
+			return Ok(POLLNVAL);
+		};
+		if revents & POLLIN != 0 {
+			revents |= POLLRDNORM;
+		}
+		if revents & POLLOUT != 0 {
+			revents |= POLLWRNORM;
+		}
+		Ok(revents & events)
+	}
+}
+
+/// Performs the poll operation.
+///
+/// Arguments:
+/// - `fds` is the process's file descriptors table.
+/// - `pollfds` is the list of file descriptors to poll.
+/// - `nfds` is the number of elements in `pollfds`.
+/// - `timeout` is the timeout in nanoseconds. If `None`, the syscall waits indefinitely.
+/// - `sigmask` is the signal mask to use while waiting. If `None`, the mask is left untouched.
+fn do_poll(
+	fds: Arc<Mutex<FileDescriptorTable>>,
+	pollfds: *mut PollFD,
+	nfds: usize,
+	timeout: Option<Timestamp>,
+	sigmask: Option<SigSet>,
+) -> EResult<usize> {
+	let pollfds = UserSlice::from_user(pollfds, nfds)?;
+	let mut pollfds_arr = pollfds
+		.copy_from_user_vec(0)?
+		.ok_or_else(|| errno!(EFAULT))?;
+	let res = with_sigmask(sigmask, || {
+		wait_events(timeout, |wait| {
+			let mut count = 0;
+			for pollfd in pollfds_arr.iter_mut() {
+				pollfd.revents = pollfd.poll(&fds, wait)? as _;
+				count += (pollfd.revents != 0) as usize;
+			}
+			Ok(count)
+		})
+	})?;
+	pollfds.copy_to_user(0, &pollfds_arr)?;
+	Ok(res)
 }
 
 pub(super) fn poll(
-	Args((fds, nfds, timeout)): Args<(*mut PollFD, usize, c_int)>,
+	Args((pollfds, nfds, timeout)): Args<(*mut PollFD, usize, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
-	let fds = UserSlice::from_user(fds, nfds)?;
 	// The timeout. `None` means no timeout
-	let to = (timeout >= 0).then_some(timeout as Timestamp);
-	let start_ts = current_time_ms(Clock::Monotonic);
-	loop {
-		// Check whether the system call timed out
-		if let Some(timeout) = to {
-			let now = current_time_ms(Clock::Monotonic);
-			if now >= start_ts + timeout {
-				return Ok(0);
-			}
-		}
-		{
-			let fds_arr = fds.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
-			// Check the file descriptors list
-			for fd in &fds_arr {
-				if fd.events as u32 & POLLIN != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLPRI != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLOUT != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLRDNORM != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLRDBAND != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLWRNORM != 0 {
-					todo!();
-				}
-				if fd.events as u32 & POLLWRBAND != 0 {
-					todo!();
-				}
-			}
-			// The number of file descriptor with at least one event
-			let fd_event_count = fds_arr.iter().filter(|fd| fd.revents != 0).count();
-			// If at least on event happened, return the number of file descriptors
-			// concerned
-			if fd_event_count > 0 {
-				fds.copy_to_user(0, &fds_arr)?;
-				return Ok(fd_event_count as _);
-			}
-		}
-		// TODO Make process sleep until an event occurs on a file descriptor in
-		// `fds`
-		Scheduler::tick();
+	let timeout = (timeout >= 0).then(|| timeout as Timestamp * 1_000_000);
+	do_poll(fds, pollfds, nfds, timeout, None)
+}
+
+#[allow(clippy::type_complexity)]
+pub(super) fn ppoll(
+	Args((pollfds, nfds, timeout, sigmask, sigsetsize)): Args<(
+		*mut PollFD,
+		usize,
+		UserPtr<Timespec>,
+		UserPtr<SigSet>,
+		usize,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let timeout = timeout.copy_from_user()?.map(|t| t.to_nano());
+	let sigmask = sigmask.copy_from_user()?;
+	if sigmask.is_some() && sigsetsize != size_of::<SigSet>() {
+		return Err(errno!(EINVAL));
+	}
+	do_poll(fds, pollfds, nfds, timeout, sigmask)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn fdset_set_clear() {
+		let mut set = FDSet::default();
+		set.set(3, true);
+		set.set(70, true);
+		assert!(set.is_set(3));
+		assert!(!set.is_set(2));
+		assert!(!set.is_set(4));
+		assert!(set.is_set(70));
+		set.set(3, false);
+		assert!(!set.is_set(3));
+		assert!(!set.is_set(FD_SETSIZE as _));
 	}
 }
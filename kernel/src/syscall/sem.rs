@@ -0,0 +1,156 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV semaphore system calls.
+
+use crate::{
+	file::perm::AccessProfile,
+	memory::user::{UserPtr, UserSlice},
+	process::{
+		Process,
+		ns::{IPC_64, IPC_RMID, IPC_SET, IPC_STAT},
+		sem::{
+			CompatSemidDs, GETALL, GETNCNT, GETPID, GETVAL, GETZCNT, SEMOPM, SETALL, SETVAL,
+			SemBuf, SemSet, SemidDs,
+		},
+	},
+	syscall::{Args, FromSyscallArg},
+	time::unit::{TimeUnit, Timespec, Timestamp},
+	uapi::UserRepr,
+};
+use core::{ffi::c_int, hint::unlikely, ptr};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+pub fn semget(
+	Args((key, nsems, flags)): Args<(c_int, c_int, c_int)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let id = ipc.sem.lock().get(key, nsems, flags, &ap)?;
+	Ok(id as _)
+}
+
+/// Performs the `semtimedop` system call.
+///
+/// `timeout` is the maximum duration to wait for, in nanoseconds.
+pub(super) fn do_semtimedop(
+	semid: c_int,
+	sops: *mut SemBuf,
+	nsops: usize,
+	timeout: Option<Timestamp>,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	if unlikely(nsops == 0) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(nsops > SEMOPM) {
+		return Err(errno!(E2BIG));
+	}
+	let ops = UserSlice::from_user(sops, nsops)?
+		.copy_from_user_vec(0)?
+		.ok_or_else(|| errno!(EFAULT))?;
+	let ipc = proc.ns.lock().ipc.clone();
+	let set = ipc.sem.lock().get_set(semid)?;
+	SemSet::semop(&set, &ops, ap, proc, timeout)?;
+	Ok(0)
+}
+
+pub fn semop(
+	Args((semid, sops, nsops)): Args<(c_int, *mut SemBuf, usize)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_semtimedop(semid, sops, nsops, None, &proc, &ap)
+}
+
+pub fn semtimedop(
+	Args((semid, sops, nsops, timeout)): Args<(c_int, *mut SemBuf, usize, UserPtr<Timespec>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let timeout = timeout.copy_from_user()?.map(|t| t.to_nano());
+	do_semtimedop(semid, sops, nsops, timeout, &proc, &ap)
+}
+
+/// Performs the `semctl` system call.
+///
+/// `arg` is the value of the `semun` union, which is either an integer or a pointer depending on
+/// `cmd`.
+pub(super) fn do_semctl<S: UserRepr<SemidDs>>(
+	semid: c_int,
+	semnum: c_int,
+	cmd: c_int,
+	arg: usize,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let cmd = cmd & !IPC_64;
+	if cmd == IPC_RMID {
+		ipc.sem.lock().remove(semid, ap)?;
+		return Ok(0);
+	}
+	let set = ipc.sem.lock().get_set(semid)?;
+	let semnum = || usize::try_from(semnum).map_err(|_| errno!(EINVAL));
+	match cmd {
+		IPC_STAT => {
+			let ds = S::from(set.stat(ap)?);
+			UserPtr::<S>::from_ptr(arg).copy_to_user(&ds)?;
+		}
+		IPC_SET => {
+			let ds: SemidDs = UserPtr::<S>::from_ptr(arg)
+				.copy_from_user()?
+				.ok_or_else(|| errno!(EFAULT))?
+				.into();
+			set.set(ap, &ds.sem_perm)?;
+		}
+		GETPID | GETVAL | GETNCNT | GETZCNT => return Ok(set.get(ap, semnum()?, cmd)? as _),
+		GETALL => {
+			let vals = set.get_all(ap)?;
+			UserSlice::from_user(ptr::with_exposed_provenance_mut(arg), vals.len())?
+				.copy_to_user(0, &vals)?;
+		}
+		SETVAL => set.set_val(ap, proc.get_pid(), semnum()?, arg as _)?,
+		SETALL => {
+			let vals = UserSlice::from_user(ptr::with_exposed_provenance_mut(arg), set.nsems())?
+				.copy_from_user_vec(0)?
+				.ok_or_else(|| errno!(EFAULT))?;
+			set.set_all(ap, proc.get_pid(), &vals)?;
+		}
+		_ => return Err(errno!(EINVAL)),
+	}
+	Ok(0)
+}
+
+pub fn semctl(
+	Args((semid, semnum, cmd, arg)): Args<(c_int, c_int, c_int, usize)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_semctl::<SemidDs>(semid, semnum, cmd, arg, &proc, &ap)
+}
+
+pub fn compat_semctl(
+	Args((semid, semnum, cmd, arg)): Args<(c_int, c_int, c_int, usize)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_semctl::<CompatSemidDs>(semid, semnum, cmd, arg, &proc, &ap)
+}
@@ -0,0 +1,184 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SysV shared memory system calls.
+
+use crate::{
+	file::perm::{AccessProfile, S_IROTH, S_IWOTH, S_IXOTH},
+	memory::{
+		VirtAddr,
+		shm::{CompatShmidDs, SHM_EXEC, SHM_RDONLY, SHM_REMAP, SHM_RND, SHMLBA, ShmidDs},
+		user::UserPtr,
+	},
+	process::{
+		Process, mem_space,
+		mem_space::{MAP_SHARED, MapConstraint, MemSpace, PROT_EXEC, PROT_READ, PROT_WRITE},
+		ns::{IPC_64, IPC_RMID, IPC_SET, IPC_STAT},
+	},
+	syscall::Args,
+	uapi::UserRepr,
+};
+use core::{ffi::c_int, hint::unlikely, num::NonZeroUsize};
+use utils::{errno, errno::EResult, limits::PAGE_SIZE, ptr::arc::Arc};
+
+pub fn shmget(
+	Args((key, size, flags)): Args<(c_int, usize, c_int)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let id = ipc.shm.lock().get(key, size, flags, &ap, proc.get_pid())?;
+	Ok(id as _)
+}
+
+/// Performs the `shmat` system call, returning the address of the attachment.
+pub(super) fn do_shmat(
+	shmid: c_int,
+	addr: VirtAddr,
+	flags: c_int,
+	proc: &Process,
+	mem_space: &MemSpace,
+	ap: &AccessProfile,
+) -> EResult<VirtAddr> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let seg = ipc.shm.lock().get_segment(shmid)?;
+	let mut access = S_IROTH;
+	let mut prot = PROT_READ;
+	if flags & SHM_RDONLY == 0 {
+		access |= S_IWOTH;
+		prot |= PROT_WRITE;
+	}
+	if flags & SHM_EXEC != 0 {
+		access |= S_IXOTH;
+		prot |= PROT_EXEC;
+	}
+	if unlikely(!seg.can_access(ap, access)) {
+		return Err(errno!(EACCES));
+	}
+	let addr = if flags & SHM_RND != 0 {
+		VirtAddr(addr.0 & !(SHMLBA - 1))
+	} else {
+		addr
+	};
+	if unlikely(!addr.is_aligned_to(SHMLBA)) {
+		return Err(errno!(EINVAL));
+	}
+	let constraint = match (addr.is_null(), flags & SHM_REMAP != 0) {
+		(true, false) => MapConstraint::None,
+		(true, true) => return Err(errno!(EINVAL)),
+		(false, false) if mem_space::mmap_addr_allowed(ap, addr) => MapConstraint::Hint(addr),
+		// A hint below the minimum address is ignored
+		(false, false) => MapConstraint::None,
+		(false, true) if mem_space::mmap_addr_allowed(ap, addr) => MapConstraint::Fixed(addr),
+		(false, true) => return Err(errno!(EPERM)),
+	};
+	let pages = NonZeroUsize::new(seg.size.div_ceil(PAGE_SIZE)).unwrap();
+	let ptr = mem_space.map(
+		constraint,
+		pages,
+		prot,
+		MAP_SHARED,
+		Some(seg.file.clone()),
+		0,
+	)?;
+	let ptr = VirtAddr::from(ptr);
+	// Without `SHM_REMAP`, the attachment must not replace existing mappings
+	if unlikely(!addr.is_null() && ptr != addr) {
+		mem_space.unmap(ptr, pages)?;
+		return Err(errno!(EINVAL));
+	}
+	seg.record_op(proc.get_pid(), true);
+	Ok(ptr)
+}
+
+pub fn shmat(
+	Args((shmid, addr, flags)): Args<(c_int, VirtAddr, c_int)>,
+	proc: Arc<Process>,
+	mem_space: Arc<MemSpace>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	let addr = do_shmat(shmid, addr, flags, &proc, &mem_space, &ap)?;
+	Ok(addr.0)
+}
+
+pub fn shmdt(
+	Args(addr): Args<VirtAddr>,
+	proc: Arc<Process>,
+	mem_space: Arc<MemSpace>,
+) -> EResult<usize> {
+	if unlikely(!addr.is_aligned_to(SHMLBA)) {
+		return Err(errno!(EINVAL));
+	}
+	let (file, pages) = mem_space
+		.get_mapped_file(addr)
+		.ok_or_else(|| errno!(EINVAL))?;
+	let ipc = proc.ns.lock().ipc.clone();
+	let mut shm = ipc.shm.lock();
+	let seg = shm.get_by_file(&file).ok_or_else(|| errno!(EINVAL))?;
+	drop(file);
+	mem_space.unmap(addr, pages)?;
+	seg.record_op(proc.get_pid(), false);
+	drop(seg);
+	// Destroy the segment if this was its last attachment
+	shm.purge();
+	Ok(0)
+}
+
+pub(super) fn do_shmctl<S: UserRepr<ShmidDs>>(
+	shmid: c_int,
+	cmd: c_int,
+	buf: UserPtr<S>,
+	proc: &Process,
+	ap: &AccessProfile,
+) -> EResult<usize> {
+	let ipc = proc.ns.lock().ipc.clone();
+	let mut shm = ipc.shm.lock();
+	match cmd & !IPC_64 {
+		IPC_STAT => {
+			let seg = shm.get_segment(shmid)?;
+			if unlikely(!seg.can_access(ap, S_IROTH)) {
+				return Err(errno!(EACCES));
+			}
+			buf.copy_to_user(&seg.stat().into())?;
+		}
+		IPC_SET => {
+			let seg = shm.get_segment(shmid)?;
+			let ds: ShmidDs = buf.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?.into();
+			seg.set(ap, &ds.shm_perm)?;
+		}
+		IPC_RMID => shm.remove(shmid, ap)?,
+		_ => return Err(errno!(EINVAL)),
+	}
+	Ok(0)
+}
+
+pub fn shmctl(
+	Args((shmid, cmd, buf)): Args<(c_int, c_int, UserPtr<ShmidDs>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_shmctl(shmid, cmd, buf, &proc, &ap)
+}
+
+pub fn compat_shmctl(
+	Args((shmid, cmd, buf)): Args<(c_int, c_int, UserPtr<CompatShmidDs>)>,
+	proc: Arc<Process>,
+	ap: AccessProfile,
+) -> EResult<usize> {
+	do_shmctl(shmid, cmd, buf, &proc, &ap)
+}
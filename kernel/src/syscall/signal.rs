@@ -27,13 +27,16 @@ use crate::{
 		Process, State,
 		pid::Pid,
 		scheduler::SCHEDULER,
-		signal::{CompatSigAction, SigAction, SigSet, Signal, SignalHandler, ucontext},
+		signal::{
+			CompatSigAction, CompatSigStack, MINSIGSTKSZ, SS_AUTODISARM, SS_DISABLE, SS_ONSTACK,
+			SigAction, SigSet, SigStack, Signal, SignalHandler, ucontext,
+		},
 	},
 	syscall::{Args, FromSyscallArg},
+	uapi::UserRepr,
 };
 use core::{
 	ffi::{c_int, c_void},
-	fmt::Debug,
 	hint::unlikely,
 	mem,
 };
@@ -59,7 +62,7 @@ pub fn signal(
 	Ok(old_handler.to_legacy() as _)
 }
 
-fn do_rt_sigaction<S: Debug + From<SigAction> + Into<SigAction>>(
+fn do_rt_sigaction<S: UserRepr<SigAction>>(
 	signum: c_int,
 	act: UserPtr<S>,
 	oldact: UserPtr<S>,
@@ -111,6 +114,9 @@ pub fn rt_sigprocmask(
 			SIG_SETMASK => signal_manager.sigmask.0 = set.0,
 			_ => return Err(errno!(EINVAL)),
 		}
+		// These signals cannot be blocked
+		signal_manager.sigmask.clear(Signal::SIGKILL as _);
+		signal_manager.sigmask.clear(Signal::SIGSTOP as _);
 	}
 	Ok(0)
 }
@@ -119,22 +125,23 @@ pub fn sigreturn(frame: &mut IntFrame) -> EResult<usize> {
 	let proc = Process::current();
 	// Retrieve and restore previous state
 	let stack_ptr = frame.get_stack_address();
-	if frame.is_compat() {
-		let ctx = UserPtr::<ucontext::UContext32>::from_ptr(stack_ptr)
-			.copy_from_user()?
-			.ok_or_else(|| errno!(EFAULT))?;
-		ctx.restore_regs(&proc, frame);
+	let res = if frame.is_compat() {
+		UserPtr::<ucontext::UContext32>::from_ptr(stack_ptr)
+			.copy_from_user()
+			.and_then(|ctx| ctx.ok_or_else(|| errno!(EFAULT)))
+			.and_then(|ctx| ctx.restore_regs(&proc, frame))
 	} else {
+		#[cfg(target_arch = "x86")]
+		unreachable!();
 		#[cfg(target_arch = "x86_64")]
-		{
-			let ctx = UserPtr::<ucontext::UContext64>::from_ptr(stack_ptr)
-				.copy_from_user()?
-				.ok_or_else(|| errno!(EFAULT))?;
-			let res = ctx.restore_regs(&proc, frame);
-			if unlikely(res.is_err()) {
-				proc.kill(Signal::SIGSEGV);
-			}
-		}
+		UserPtr::<ucontext::UContext64>::from_ptr(stack_ptr)
+			.copy_from_user()
+			.and_then(|ctx| ctx.ok_or_else(|| errno!(EFAULT)))
+			.and_then(|ctx| ctx.restore_regs(&proc, frame))
+	};
+	// An invalid signal frame cannot be returned to
+	if unlikely(res.is_err()) {
+		proc.kill(Signal::SIGSEGV);
 	}
 	// Left register untouched
 	Ok(frame.get_syscall_id())
@@ -144,6 +151,57 @@ pub fn rt_sigreturn(frame: &mut IntFrame) -> EResult<usize> {
 	sigreturn(frame)
 }
 
+fn do_sigaltstack<S: UserRepr<SigStack>>(
+	ss: UserPtr<S>,
+	old_ss: UserPtr<S>,
+	proc: Arc<Process>,
+	frame: &IntFrame,
+) -> EResult<usize> {
+	let sp = frame.get_stack_address();
+	let mut signal_manager = proc.signal.lock();
+	// Save the old structure
+	let old = signal_manager.altstack_info(sp).into();
+	// Set the new structure
+	if let Some(new) = ss.copy_from_user()? {
+		let new: SigStack = new.into();
+		// Cannot change the stack while executing on it
+		if unlikely(signal_manager.on_altstack(sp)) {
+			return Err(errno!(EPERM));
+		}
+		signal_manager.altstack = match new.ss_flags & !SS_AUTODISARM {
+			SS_DISABLE => SigStack::default(),
+			0 | SS_ONSTACK => {
+				if unlikely(new.ss_size < MINSIGSTKSZ) {
+					return Err(errno!(ENOMEM));
+				}
+				SigStack {
+					ss_flags: new.ss_flags & SS_AUTODISARM,
+					..new
+				}
+			}
+			_ => return Err(errno!(EINVAL)),
+		};
+	}
+	old_ss.copy_to_user(&old)?;
+	Ok(0)
+}
+
+pub fn sigaltstack(
+	Args((ss, old_ss)): Args<(UserPtr<SigStack>, UserPtr<SigStack>)>,
+	proc: Arc<Process>,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	do_sigaltstack(ss, old_ss, proc, frame)
+}
+
+pub fn compat_sigaltstack(
+	Args((ss, old_ss)): Args<(UserPtr<CompatSigStack>, UserPtr<CompatSigStack>)>,
+	proc: Arc<Process>,
+	frame: &mut IntFrame,
+) -> EResult<usize> {
+	do_sigaltstack(ss, old_ss, proc, frame)
+}
+
 /// Tries to kill the process with PID `pid` with the signal `sig`.
 ///
 /// If `sig` is `None`, the function doesn't send a signal, but still checks if
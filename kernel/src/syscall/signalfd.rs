@@ -0,0 +1,86 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `signalfd` system calls create a file descriptor used to receive signals.
+
+use crate::{
+	file,
+	file::{
+		File,
+		buffer::signalfd::SignalFd,
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+	},
+	memory::user::UserPtr,
+	process::signal::SigSet,
+	sync::mutex::Mutex,
+	syscall::Args,
+};
+use core::{ffi::c_int, hint::unlikely};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const SFD_CLOEXEC: c_int = file::O_CLOEXEC;
+/// Flag: set the non-blocking flag on the new open file description.
+const SFD_NONBLOCK: c_int = file::O_NONBLOCK;
+
+fn do_signalfd(
+	fd: c_int,
+	mask: UserPtr<SigSet>,
+	sizemask: usize,
+	flags: c_int,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	if unlikely(sizemask != size_of::<SigSet>()) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(flags & !(SFD_CLOEXEC | SFD_NONBLOCK) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let mask = mask.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let mut fds = fds.lock();
+	// Update an existing signalfd
+	if fd != -1 {
+		let file = fds.get_fd(fd)?.get_file();
+		let sfd: &SignalFd = file.get_buffer().ok_or_else(|| errno!(EINVAL))?;
+		sfd.set_mask(mask);
+		return Ok(fd as _);
+	}
+	let ops = Arc::new(SignalFd::new(mask))?;
+	let file = File::open_floating(ops, file::O_RDONLY | (flags & SFD_NONBLOCK))?;
+	let fd_flags = if flags & SFD_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
+
+pub fn signalfd(
+	Args((fd, mask, sizemask)): Args<(c_int, UserPtr<SigSet>, usize)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_signalfd(fd, mask, sizemask, 0, fds)
+}
+
+pub fn signalfd4(
+	Args((fd, mask, sizemask, flags)): Args<(c_int, UserPtr<SigSet>, usize, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_signalfd(fd, mask, sizemask, flags, fds)
+}
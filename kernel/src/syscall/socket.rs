@@ -20,14 +20,25 @@
 
 use crate::{
 	file,
-	file::{File, fd::FileDescriptorTable, perm::AccessProfile, socket::Socket},
-	memory::user::{UserPtr, UserSlice},
-	net::{SocketDesc, SocketDomain, SocketType},
+	file::{File, buffer::sigpipe, fd::FileDescriptorTable, perm::AccessProfile, socket::Socket},
+	memory::user::{UserIOVec, UserPtr, UserSlice},
+	net::{SocketDesc, SocketDomain, SocketType, netlink::NETLINK_ROUTE, packet, udp},
+	process::Process,
 	sync::mutex::Mutex,
-	syscall::Args,
+	syscall::{Args, FromSyscallArg},
+	uapi::{
+		UserRepr,
+		socket::{CompatMsgHdr, MsgHdr},
+	},
+};
+use core::{cmp::min, ffi::c_int, hint::unlikely, ptr};
+use utils::{
+	collections::vec::Vec,
+	errno,
+	errno::{CollectResult, EResult},
+	limits::IOV_MAX,
+	ptr::arc::Arc,
 };
-use core::{cmp::min, ffi::c_int, hint::unlikely};
-use utils::{errno, errno::EResult, ptr::arc::Arc};
 
 /// Shutdown receive side of the connection.
 const SHUT_RD: c_int = 0;
@@ -36,8 +47,22 @@ const SHUT_WR: c_int = 1;
 /// Both sides are shutdown.
 const SHUT_RDWR: c_int = 2;
 
+/// Message flag: peek at incoming data without removing it from the queue.
+const MSG_PEEK: c_int = 0x2;
+/// Message flag: return the real length of the message, even if it was truncated.
+///
+/// As an output flag, tells the message has been truncated.
+const MSG_TRUNC: c_int = 0x20;
+/// Message flag: do not block.
+const MSG_DONTWAIT: c_int = 0x40;
+/// Message flag: block until all the requested data has been received.
+const MSG_WAITALL: c_int = 0x100;
+/// Message flag: do not generate `SIGPIPE` if the connection is broken.
+const MSG_NOSIGNAL: c_int = 0x4000;
+
 pub fn socket(
 	Args((domain, r#type, protocol)): Args<(c_int, c_int, c_int)>,
+	proc: Arc<Process>,
 	ap: AccessProfile,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
@@ -47,13 +72,31 @@ pub fn socket(
 	if !ap.can_use_sock_domain(&sock_domain) || !ap.can_use_sock_type(&sock_type) {
 		return Err(errno!(EACCES));
 	}
+	// Packet and netlink sockets only exchange datagrams
+	if matches!(
+		sock_domain,
+		SocketDomain::AfPacket | SocketDomain::AfNetlink
+	) && !matches!(sock_type, SocketType::SockRaw | SocketType::SockDgram)
+	{
+		return Err(errno!(ESOCKTNOSUPPORT));
+	}
+	if sock_domain == SocketDomain::AfNetlink && protocol != NETLINK_ROUTE {
+		return Err(errno!(EPROTONOSUPPORT));
+	}
 	let desc = SocketDesc {
 		domain: sock_domain,
 		type_: sock_type,
 		protocol,
 	};
 	// Create socket
-	let sock = Arc::new(Socket::new(desc)?)?;
+	let net_ns = proc.ns.lock().net.clone();
+	let sock = Arc::new(Socket::new(desc, net_ns)?)?;
+	if sock_domain == SocketDomain::AfPacket {
+		packet::register(sock.clone())?;
+	}
+	if udp::is_udp(sock.desc()) {
+		udp::register(sock.clone())?;
+	}
 	let file = File::open_floating(sock, file::O_RDWR)?;
 	let (sock_fd_id, _) = fds.lock().create_fd(0, file)?;
 	Ok(sock_fd_id as _)
@@ -61,6 +104,7 @@ pub fn socket(
 
 pub fn socketpair(
 	Args((domain, r#type, protocol, sv)): Args<(c_int, c_int, c_int, UserPtr<[c_int; 2]>)>,
+	proc: Arc<Process>,
 	ap: AccessProfile,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
@@ -76,7 +120,8 @@ pub fn socketpair(
 		protocol,
 	};
 	// Create socket
-	let sock = Arc::new(Socket::new(desc)?)?;
+	let net_ns = proc.ns.lock().net.clone();
+	let sock = Arc::new(Socket::new(desc, net_ns)?)?;
 	let file0 = File::open_floating(sock.clone(), file::O_RDWR)?;
 	let file1 = File::open_floating(sock, file::O_RDWR)?;
 	// Create file descriptors
@@ -105,6 +150,29 @@ pub fn getsockname(
 	Ok(0)
 }
 
+pub fn getpeername(
+	Args((sockfd, addr, addrlen)): Args<(c_int, *mut u8, UserPtr<isize>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	// Get socket
+	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	// Read and check buffer length
+	let addrlen_val = addrlen.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	if addrlen_val < 0 {
+		return Err(errno!(EINVAL));
+	}
+	let name = sock.get_peername().lock();
+	if name.is_empty() {
+		return Err(errno!(ENOTCONN));
+	}
+	let len = min(name.len(), addrlen_val as _);
+	let addr = UserSlice::from_user(addr, len)?;
+	addr.copy_to_user(0, &name[..len])?;
+	addrlen.copy_to_user(&(len as _))?;
+	Ok(0)
+}
+
 pub fn getsockopt(
 	Args((sockfd, level, optname, optval, optlen)): Args<(c_int, c_int, c_int, *mut u8, usize)>,
 	fds: Arc<Mutex<FileDescriptorTable>>,
@@ -143,15 +211,16 @@ pub fn connect(
 	}
 	// Get socket
 	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
-	let _sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
 	let addr = UserSlice::from_user(addr, addrlen as _)?;
-	let _addr = addr.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
-	// TODO connect socket
-	todo!()
+	let addr = addr.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
+	sock.connect(&addr)?;
+	Ok(0)
 }
 
 pub fn bind(
 	Args((sockfd, addr, addrlen)): Args<(c_int, *mut u8, isize)>,
+	ap: AccessProfile,
 	fds: Arc<Mutex<FileDescriptorTable>>,
 ) -> EResult<usize> {
 	// Validation
@@ -163,14 +232,16 @@ pub fn bind(
 	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
 	let addr = UserSlice::from_user(addr, addrlen as _)?;
 	let addr = addr.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
+	if !ap.can_bind(&sock.desc().domain, &addr) {
+		return Err(errno!(EACCES));
+	}
 	sock.bind(&addr)?;
 	Ok(0)
 }
 
-// TODO implement flags
 #[allow(clippy::type_complexity)]
 pub fn sendto(
-	Args((sockfd, buf, len, _flags, dest_addr, addrlen)): Args<(
+	Args((sockfd, buf, len, flags, dest_addr, addrlen)): Args<(
 		c_int,
 		*mut u8,
 		usize,
@@ -188,11 +259,199 @@ pub fn sendto(
 	}
 	// Get socket
 	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
-	let _sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
-	// Get slices
-	let _buf_slice = buf.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))?;
-	let _dest_addr_slice = dest_addr.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))?;
-	todo!()
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	let nonblock = file.get_flags() & file::O_NONBLOCK != 0 || flags & MSG_DONTWAIT != 0;
+	// The destination address is ignored on connection-mode sockets
+	let res = if !sock.desc().type_.is_stream() && !dest_addr.is_empty() {
+		let dest_addr = dest_addr.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))?;
+		let msg = buf.copy_from_user_vec(0)?.ok_or(errno!(EFAULT))?;
+		sock.send_datagram(&msg, Some(&dest_addr))
+	} else {
+		sock.send(buf, nonblock)
+	};
+	if flags & MSG_NOSIGNAL != 0 {
+		res
+	} else {
+		sigpipe(res)
+	}
+}
+
+fn do_sendmsg<M: UserRepr<MsgHdr>>(
+	sockfd: c_int,
+	msg: UserPtr<M>,
+	flags: c_int,
+	compat: bool,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let hdr: MsgHdr = msg.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?.into();
+	if unlikely(hdr.msg_iovlen > IOV_MAX) {
+		return Err(errno!(EMSGSIZE));
+	}
+	let iov = UserIOVec::from_syscall_arg(hdr.msg_iov, compat)
+		.iter(hdr.msg_iovlen)
+		.map(|iov| {
+			let iov = iov?;
+			UserSlice::from_user(iov.iov_base, iov.iov_len)
+		})
+		.collect::<EResult<CollectResult<Vec<_>>>>()?
+		.0?;
+	// Get socket
+	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	let nonblock = file.get_flags() & file::O_NONBLOCK != 0 || flags & MSG_DONTWAIT != 0;
+	// TODO handle ancillary data
+	let res = if sock.desc().type_.is_stream() {
+		let mut total = 0;
+		for buf in iov {
+			// Only the first write may block, since data has been sent afterwards
+			match sock.send(buf, nonblock || total > 0) {
+				Ok(len) => total += len,
+				Err(e) if total > 0 && e.as_int() == errno::EAGAIN => break,
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(total)
+	} else {
+		// The message is sent as a whole
+		let mut data = Vec::new();
+		for buf in iov {
+			let buf = buf.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?;
+			data.extend_from_slice(&buf)?;
+		}
+		let dest = if hdr.msg_name != 0 {
+			let name = ptr::with_exposed_provenance_mut(hdr.msg_name);
+			let name = UserSlice::from_user(name, hdr.msg_namelen as _)?;
+			Some(name.copy_from_user_vec(0)?.ok_or_else(|| errno!(EFAULT))?)
+		} else {
+			None
+		};
+		sock.send_datagram(&data, dest.as_deref())
+	};
+	if flags & MSG_NOSIGNAL != 0 {
+		res
+	} else {
+		sigpipe(res)
+	}
+}
+
+pub fn sendmsg(
+	Args((sockfd, msg, flags)): Args<(c_int, UserPtr<MsgHdr>, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_sendmsg(sockfd, msg, flags, false, fds)
+}
+
+pub fn compat_sendmsg(
+	Args((sockfd, msg, flags)): Args<(c_int, UserPtr<CompatMsgHdr>, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_sendmsg(sockfd, msg, flags, true, fds)
+}
+
+/// Receives a message on the socket `sock`, opened with `file`, scattering it into `iov`.
+///
+/// On success, the function returns the length to be returned by the system call, along with the
+/// output flags and the address of the sender.
+fn do_recv(
+	file: &File,
+	sock: &Socket,
+	iov: &[UserSlice<u8>],
+	flags: c_int,
+) -> EResult<(usize, c_int, Vec<u8>)> {
+	let nonblock = file.get_flags() & file::O_NONBLOCK != 0 || flags & MSG_DONTWAIT != 0;
+	let peek = flags & MSG_PEEK != 0;
+	let (copied, len, addr) = sock.recv(iov, peek, nonblock, flags & MSG_WAITALL != 0)?;
+	let out_flags = if len > copied { MSG_TRUNC } else { 0 };
+	let len = if flags & MSG_TRUNC != 0 && !sock.desc().type_.is_stream() {
+		len
+	} else {
+		copied
+	};
+	Ok((len, out_flags, addr))
+}
+
+/// Writes the address `name` of the sender of a message to `addr`, truncated to `len` bytes.
+///
+/// The function returns the length of the address, which is zero if it is unknown.
+fn write_src_addr(name: &[u8], addr: *mut u8, len: usize) -> EResult<u32> {
+	let buf = UserSlice::from_user(addr, min(len, name.len()))?;
+	buf.copy_to_user(0, name)?;
+	Ok(name.len() as _)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn recvfrom(
+	Args((sockfd, buf, len, flags, src_addr, addrlen)): Args<(
+		c_int,
+		*mut u8,
+		usize,
+		c_int,
+		*mut u8,
+		UserPtr<u32>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let buf = UserSlice::from_user(buf, len)?;
+	// Get socket
+	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	let (len, _, name) = do_recv(&file, sock, &[buf], flags)?;
+	if !src_addr.is_null() {
+		let addrlen_val = addrlen.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+		let name_len = write_src_addr(&name, src_addr, addrlen_val as _)?;
+		addrlen.copy_to_user(&name_len)?;
+	}
+	Ok(len)
+}
+
+fn do_recvmsg<M: UserRepr<MsgHdr>>(
+	sockfd: c_int,
+	msg: UserPtr<M>,
+	flags: c_int,
+	compat: bool,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let mut hdr: MsgHdr = msg.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?.into();
+	if unlikely(hdr.msg_iovlen > IOV_MAX) {
+		return Err(errno!(EMSGSIZE));
+	}
+	let iov = UserIOVec::from_syscall_arg(hdr.msg_iov, compat)
+		.iter(hdr.msg_iovlen)
+		.map(|iov| {
+			let iov = iov?;
+			UserSlice::from_user(iov.iov_base, iov.iov_len)
+		})
+		.collect::<EResult<CollectResult<Vec<_>>>>()?
+		.0?;
+	// Get socket
+	let file = fds.lock().get_fd(sockfd)?.get_file().clone();
+	let sock: &Socket = file.get_buffer().ok_or_else(|| errno!(ENOTSOCK))?;
+	let (len, out_flags, name) = do_recv(&file, sock, &iov, flags)?;
+	hdr.msg_namelen = if hdr.msg_name != 0 {
+		let addr = ptr::with_exposed_provenance_mut(hdr.msg_name);
+		write_src_addr(&name, addr, hdr.msg_namelen as _)?
+	} else {
+		0
+	};
+	// TODO fill ancillary data
+	hdr.msg_controllen = 0;
+	hdr.msg_flags = out_flags;
+	msg.copy_to_user(&hdr.into())?;
+	Ok(len)
+}
+
+pub fn recvmsg(
+	Args((sockfd, msg, flags)): Args<(c_int, UserPtr<MsgHdr>, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_recvmsg(sockfd, msg, flags, false, fds)
+}
+
+pub fn compat_recvmsg(
+	Args((sockfd, msg, flags)): Args<(c_int, UserPtr<CompatMsgHdr>, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	do_recvmsg(sockfd, msg, flags, true, fds)
 }
 
 pub fn shutdown(
@@ -30,6 +30,7 @@ use crate::{
 	memory::user::{UserPtr, UserString},
 	sync::mutex::Mutex,
 	syscall::{Args, util::at},
+	uapi::stat::{CompatStat64, Stat32, Stat64, Statx, StatxTimestamp},
 };
 use core::{
 	ffi::{c_int, c_uint},
@@ -37,97 +38,25 @@ use core::{
 };
 use utils::{collections::path::PathBuf, errno, errno::EResult, ptr::arc::Arc};
 
-/// Status of a file, 32 bit version.
-#[derive(Debug)]
-#[repr(C)]
-pub struct Stat32 {
-	/// ID of the device containing the file
-	st_dev: u32,
-	/// The inode number
-	st_ino: u32,
-	/// File mode
-	st_mode: u16,
-	/// Link count
-	st_link: u16,
-	/// User ID of the file's owner
-	st_uid: u16,
-	/// Group ID of the file's group
-	st_gid: u16,
-	/// Device ID (if device file)
-	st_rdev: u32,
-	/// Size of file, in bytes
-	st_size: u32,
-	/// Optimal block size for I/O
-	st_blksize: u32,
-	/// Number of 512-byte blocks allocated
-	st_blocks: u32,
-	/// Timestamp of last access (seconds)
-	st_atime: u32,
-	/// Timestamp of last access (nanoseconds)
-	st_atime_nsec: u32,
-	/// Timestamp of last modification of the content (seconds)
-	st_mtime: u32,
-	/// Timestamp of last modification of the content (nanoseconds)
-	st_mtime_nsec: u32,
-	/// Timestamp of last modification of the metadata (seconds)
-	st_ctime: u32,
-	/// Timestamp of last modification of the metadata (nanoseconds)
-	st_ctime_nsec: u32,
-	/// Padding
-	padding: u64,
-}
-
-/// Status of a file, 64 bit version.
-#[derive(Debug)]
-#[repr(C)]
-pub struct Stat64 {
-	/// ID of the device containing the file
-	st_dev: u64,
-	/// The inode number
-	st_ino: u64,
-	/// Number of hard links to the file
-	st_nlink: u64,
-	/// File mode
-	st_mode: u32,
-	/// User ID of the file's owner
-	st_uid: u32,
-	/// Group ID of the file's group
-	st_gid: u32,
-	/// Padding
-	pad0: u32,
-	/// Device ID (if device file)
-	st_rdev: u64,
-	/// Size of file, in bytes
-	st_size: i64,
-	/// Optimal block size for I/O
-	st_blksize: i64,
-	/// Number of 512-byte block allocated
-	st_blocks: i64,
-	/// Timestamp of last access (seconds)
-	st_atime: u64,
-	/// Timestamp of last access (nanoseconds)
-	st_atime_nsec: u64,
-	/// Timestamp of last modification of the content (seconds)
-	st_mtime: u64,
-	/// Timestamp of last modification of the content (nanoseconds)
-	st_mtime_nsec: u64,
-	/// Timestamp of last modification of the metadata (seconds)
-	st_ctime: u64,
-	/// Timestamp of last modification of the metadata (nanoseconds)
-	st_ctime_nsec: u64,
-}
-
 /// Extract device number and inode from [`vfs::Entry`].
 fn entry_info(entry: &vfs::Entry) -> (u64, INode) {
 	let node = entry.node();
 	(node.fs.dev, node.inode)
 }
 
+/// Writes the status of a file to the legacy 32 bit structure.
+///
+/// If the size or inode number of the file cannot be represented, the function returns
+/// [`errno::EOVERFLOW`].
 fn do_stat32(stat: Stat, entry: Option<&vfs::Entry>, statbuf: UserPtr<Stat32>) -> EResult<()> {
 	let (st_dev, st_ino) = entry.map(entry_info).unwrap_or_default();
+	let st_ino = st_ino.try_into().map_err(|_| errno!(EOVERFLOW))?;
+	if unlikely(stat.size > i32::MAX as u64) {
+		return Err(errno!(EOVERFLOW));
+	}
 	statbuf.copy_to_user(&Stat32 {
 		st_dev: st_dev as _,
-		st_ino: st_ino as _,
+		st_ino,
 		st_mode: stat.mode as _,
 		st_link: stat.nlink as _,
 		st_uid: stat.uid as _,
@@ -166,6 +95,36 @@ fn do_stat64(stat: Stat, entry: Option<&vfs::Entry>, statbuf: UserPtr<Stat64>) -
 		st_mtime_nsec: 0, // TODO
 		st_ctime: stat.ctime,
 		st_ctime_nsec: 0, // TODO
+		__unused: [0; 3],
+	})
+}
+
+fn do_compat_stat64(
+	stat: Stat,
+	entry: Option<&vfs::Entry>,
+	statbuf: UserPtr<CompatStat64>,
+) -> EResult<()> {
+	let (st_dev, st_ino) = entry.map(entry_info).unwrap_or_default();
+	statbuf.copy_to_user(&CompatStat64 {
+		st_dev,
+		pad0: 0,
+		__st_ino: st_ino as _,
+		st_mode: stat.mode as _,
+		st_nlink: stat.nlink as _,
+		st_uid: stat.uid as _,
+		st_gid: stat.gid as _,
+		st_rdev: makedev(stat.dev_major, stat.dev_minor),
+		pad3: 0,
+		st_size: stat.size as _,
+		st_blksize: 512, // TODO
+		st_blocks: stat.blocks as _,
+		st_atime: stat.atime as _,
+		st_atime_nsec: 0, // TODO
+		st_mtime: stat.mtime as _,
+		st_mtime_nsec: 0, // TODO
+		st_ctime: stat.ctime as _,
+		st_ctime_nsec: 0, // TODO
+		st_ino,
 	})
 }
 
@@ -247,78 +206,43 @@ pub fn lstat64(
 	Ok(0)
 }
 
-/// A timestamp for the [`statx`] syscall.
-#[derive(Debug)]
-#[repr(C)]
-struct StatxTimestamp {
-	/// Seconds since the Epoch (UNIX time)
-	tv_sec: i64,
-	/// Nanoseconds since tv_sec
-	tv_nsec: u32,
-	/// Reserved field.
-	__reserved: i32,
+pub fn compat_stat64(
+	Args((pathname, statbuf)): Args<(UserString, UserPtr<CompatStat64>)>,
+	rs: ResolutionSettings,
+) -> EResult<usize> {
+	let pathname = pathname.copy_from_user()?.ok_or_else(|| errno!(EINVAL))?;
+	let pathname = PathBuf::try_from(pathname)?;
+	let ent = vfs::get_file_from_path(&pathname, &rs)?;
+	let stat = ent.stat();
+	do_compat_stat64(stat, Some(&ent), statbuf)?;
+	Ok(0)
+}
+
+pub fn compat_fstat64(
+	Args((fd, statbuf)): Args<(c_int, UserPtr<CompatStat64>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let fds = fds.lock();
+	let file = fds.get_fd(fd)?.get_file();
+	let stat = file.stat()?;
+	do_compat_stat64(stat, file.vfs_entry.as_deref(), statbuf)?;
+	Ok(0)
 }
 
-/// Status of a file, extended.
-#[derive(Debug)]
-#[repr(C)]
-pub struct Statx {
-	/// Mask of bits indicating filled fields
-	stx_mask: u32,
-	/// Block size for filesystem I/O
-	stx_blksize: u32,
-	/// Extra file attribute indicators
-	stx_attributes: u64,
-	/// Number of hard links
-	stx_nlink: u32,
-	/// User ID of owner
-	stx_uid: u32,
-	/// Group ID of owner
-	stx_gid: u32,
-	/// File type and mode
-	stx_mode: u16,
-	/// Padding.
-	__padding0: u16,
-	/// Inode number
-	stx_ino: u64,
-	/// Total size in bytes
-	stx_size: u64,
-	/// Number of 512B blocks allocated
-	stx_blocks: u64,
-	/// Mask to show what's supported in stx_attributes
-	stx_attributes_mask: u64,
-	/// Last access
-	stx_atime: StatxTimestamp,
-	/// Creation
-	stx_btime: StatxTimestamp,
-	/// Last status change
-	stx_ctime: StatxTimestamp,
-	/// Last modification
-	stx_mtime: StatxTimestamp,
-	/// Major ID (if the file is a device)
-	stx_rdev_major: u32,
-	/// Minor ID (if the file is a device)
-	stx_rdev_minor: u32,
-	/// Major ID of the device containing the filesystem where the file resides
-	stx_dev_major: u32,
-	/// Minor ID of the device containing the filesystem where the file resides
-	stx_dev_minor: u32,
-	/// Mount ID.
-	stx_mnt_id: u64,
-	/// Memory buffer alignment for direct I/O
-	stx_dio_mem_align: u32,
-	/// File offset alignment for direct I/O
-	stx_dio_offset_align: u32,
-	/// Subvolume identifier
-	stx_subvol: u64,
-	/// Min atomic write unit in bytes
-	stx_atomic_write_unit_min: u32,
-	/// Max atomic write unit in bytes
-	stx_atomic_write_unit_max: u32,
-	/// Max atomic write segment count
-	stx_atomic_write_segments_max: u32,
-	/// Padding
-	__padding1: [u32; 19],
+pub fn compat_lstat64(
+	Args((pathname, statbuf)): Args<(UserString, UserPtr<CompatStat64>)>,
+	rs: ResolutionSettings,
+) -> EResult<usize> {
+	let pathname = pathname.copy_from_user()?.ok_or_else(|| errno!(EINVAL))?;
+	let pathname = PathBuf::try_from(pathname)?;
+	let rs = ResolutionSettings {
+		follow_link: false,
+		..rs
+	};
+	let ent = vfs::get_file_from_path(&pathname, &rs)?;
+	let stat = ent.stat();
+	do_compat_stat64(stat, Some(&ent), statbuf)?;
+	Ok(0)
 }
 
 pub fn statx(
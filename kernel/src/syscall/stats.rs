@@ -0,0 +1,89 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Per-system call invocation counts and cumulative time, kept for each process and globally.
+//!
+//! Durations are measured in cycles of the Time Stamp Counter, since clocks do not have a fine
+//! enough resolution to time most system calls. They include the time the caller spent
+//! sleeping in the system call.
+
+use crate::{arch::x86::rdtsc, process::Process, sync::mutex::IntMutex};
+use core::{fmt, fmt::Formatter};
+use utils::{collections::hashmap::HashMap, errno::EResult};
+
+/// Statistics of a single system call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SyscallStat {
+	/// The number of invocations.
+	pub count: u64,
+	/// The cumulative duration of invocations, in cycles.
+	pub cycles: u64,
+}
+
+/// Statistics of system calls, by name.
+#[derive(Debug, Default)]
+pub struct SyscallStats(IntMutex<HashMap<&'static str, SyscallStat>>);
+
+impl SyscallStats {
+	/// Creates a new empty instance.
+	pub const fn new() -> Self {
+		Self(IntMutex::new(HashMap::new()))
+	}
+
+	/// Accounts an invocation of the system call `name`, which lasted `cycles`.
+	///
+	/// If the memory allocation fails, the invocation is not accounted.
+	pub fn add(&self, name: &'static str, cycles: u64) {
+		let mut stats = self.0.lock();
+		let Ok(stat) = stats.entry(name).or_insert(SyscallStat::default()) else {
+			return;
+		};
+		stat.count += 1;
+		stat.cycles = stat.cycles.saturating_add(cycles);
+	}
+
+	/// Resets all statistics.
+	pub fn reset(&self) {
+		self.0.lock().clear();
+	}
+}
+
+impl fmt::Display for SyscallStats {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let stats = self.0.lock();
+		for (name, stat) in stats.iter() {
+			writeln!(f, "{name} {} {}", stat.count, stat.cycles)?;
+		}
+		Ok(())
+	}
+}
+
+/// Statistics of system calls made by all processes since boot.
+pub static GLOBAL: SyscallStats = SyscallStats::new();
+
+/// Executes the system call `f`, named `name`, and accounts it to the current process and to
+/// the global statistics.
+#[inline(always)]
+pub fn measure<F: FnOnce() -> EResult<usize>>(name: &'static str, f: F) -> EResult<usize> {
+	let begin = rdtsc();
+	let res = f();
+	let cycles = rdtsc().wrapping_sub(begin);
+	GLOBAL.add(name, cycles);
+	Process::current().syscall_stats.add(name, cycles);
+	res
+}
@@ -27,9 +27,12 @@ use crate::{
 	},
 	syscall::Args,
 	time::{
-		clock::{Clock, current_time_ns, current_time_sec},
+		clock::{CPUCLOCK_VIRT, Clock, CpuClock, current_time_ns, current_time_sec},
 		sleep_for,
-		unit::{ClockIdT, ITimerspec32, TimeUnit, TimerT, Timespec, Timespec32},
+		unit::{
+			ClockIdT, ITimerspec32, TimeUnit, TimerT, Timespec, Timespec32, Timestamp, Timeval,
+			Timeval32, Timezone,
+		},
 	},
 };
 use core::ffi::c_int;
@@ -51,9 +54,50 @@ pub fn time64(Args(tloc): Args<UserPtr<u64>>) -> EResult<usize> {
 	Ok(time as _)
 }
 
+/// Returns the current value of the clock with ID `clockid`, in nanoseconds.
+fn clock_time(clockid: ClockIdT) -> EResult<Timestamp> {
+	let Some(clock) = CpuClock::from_id(clockid) else {
+		let clock = Clock::from_id(clockid).ok_or_else(|| errno!(EINVAL))?;
+		return Ok(current_time_ns(clock));
+	};
+	let proc = match clock.pid {
+		0 => Process::current(),
+		pid => Process::get_by_pid(pid).ok_or_else(|| errno!(EINVAL))?,
+	};
+	// Threads are accounted separately, so a process clock measures the given process alone
+	let (utime, stime) = proc.get_cpu_time();
+	match clock.kind {
+		CPUCLOCK_VIRT => Ok(utime),
+		_ => Ok(utime + stime),
+	}
+}
+
+pub fn gettimeofday32(
+	Args((tv, tz)): Args<(UserPtr<Timeval32>, UserPtr<Timezone>)>,
+) -> EResult<usize> {
+	tv.copy_to_user(&Timeval32::from_nano(current_time_ns(Clock::Realtime)))?;
+	tz.copy_to_user(&Timezone::default())?;
+	Ok(0)
+}
+
+pub fn gettimeofday64(
+	Args((tv, tz)): Args<(UserPtr<Timeval>, UserPtr<Timezone>)>,
+) -> EResult<usize> {
+	tv.copy_to_user(&Timeval::from_nano(current_time_ns(Clock::Realtime)))?;
+	tz.copy_to_user(&Timezone::default())?;
+	Ok(0)
+}
+
+pub fn clock_gettime32(
+	Args((clockid, tp)): Args<(ClockIdT, UserPtr<Timespec32>)>,
+) -> EResult<usize> {
+	let ts = clock_time(clockid)?;
+	tp.copy_to_user(&Timespec32::from_nano(ts))?;
+	Ok(0)
+}
+
 pub fn clock_gettime(Args((clockid, tp)): Args<(ClockIdT, UserPtr<Timespec>)>) -> EResult<usize> {
-	let clk = Clock::from_id(clockid).ok_or_else(|| errno!(EINVAL))?;
-	let ts = current_time_ns(clk);
+	let ts = clock_time(clockid)?;
 	tp.copy_to_user(&Timespec::from_nano(ts))?;
 	Ok(0)
 }
@@ -61,12 +105,29 @@ pub fn clock_gettime(Args((clockid, tp)): Args<(ClockIdT, UserPtr<Timespec>)>) -
 pub fn clock_gettime64(
 	Args((clockid, tp)): Args<(ClockIdT, UserPtr<Timespec>)>,
 ) -> EResult<usize> {
-	let clock = Clock::from_id(clockid).ok_or_else(|| errno!(EINVAL))?;
-	let ts = current_time_ns(clock);
+	let ts = clock_time(clockid)?;
 	tp.copy_to_user(&Timespec::from_nano(ts))?;
 	Ok(0)
 }
 
+pub fn clock_getres32(
+	Args((clockid, res)): Args<(ClockIdT, UserPtr<Timespec32>)>,
+) -> EResult<usize> {
+	// Check the clock exists
+	clock_time(clockid)?;
+	res.copy_to_user(&Timespec32::from_nano(1))?;
+	Ok(0)
+}
+
+pub fn clock_getres64(
+	Args((clockid, res)): Args<(ClockIdT, UserPtr<Timespec>)>,
+) -> EResult<usize> {
+	// Check the clock exists
+	clock_time(clockid)?;
+	res.copy_to_user(&Timespec::from_nano(1))?;
+	Ok(0)
+}
+
 pub fn nanosleep32(
 	Args((req, rem)): Args<(UserPtr<Timespec32>, UserPtr<Timespec32>)>,
 ) -> EResult<usize> {
@@ -113,9 +174,8 @@ pub fn timer_create(
 		sigev_notify: SIGEV_SIGNAL,
 		sigev_signo: Signal::SIGALRM as _,
 		sigev_value: timerid_val,
-		sigev_notify_function: None,
-		sigev_notify_attributes: None,
-		sigev_notify_thread_id: proc.tid,
+		sigev_notify_thread_id: proc.tid as _,
+		..Default::default()
 	});
 	let id = proc.timer_manager.lock().create_timer(clock, sevp_val)?;
 	timerid.copy_to_user(&(id as _))?;
@@ -0,0 +1,193 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `timerfd` system calls create and control timers notifying expirations through a file
+//! descriptor.
+
+use crate::{
+	file,
+	file::{
+		File,
+		buffer::timerfd::TimerFd,
+		fd::{FD_CLOEXEC, FileDescriptorTable},
+	},
+	memory::user::UserPtr,
+	sync::mutex::Mutex,
+	syscall::Args,
+	time::{
+		clock::Clock,
+		unit::{ClockIdT, ITimerspec, ITimerspec32, TimeUnit, Timespec, Timespec32, Timestamp},
+	},
+};
+use core::{ffi::c_int, hint::unlikely};
+use utils::{errno, errno::EResult, ptr::arc::Arc};
+
+/// Flag: set the close-on-exec flag on the new file descriptor.
+const TFD_CLOEXEC: c_int = file::O_CLOEXEC;
+/// Flag: set the non-blocking flag on the new open file description.
+const TFD_NONBLOCK: c_int = file::O_NONBLOCK;
+
+/// Flag: the initial expiration time is absolute.
+const TFD_TIMER_ABSTIME: c_int = 1;
+/// Flag: cancel the timer when the realtime clock is set.
+///
+/// Accepted, but has no effect since the realtime clock cannot be set.
+const TFD_TIMER_CANCEL_ON_SET: c_int = 2;
+
+pub fn timerfd_create(
+	Args((clockid, flags)): Args<(ClockIdT, c_int)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let clock = Clock::from_id(clockid).ok_or_else(|| errno!(EINVAL))?;
+	if unlikely(!matches!(
+		clock,
+		Clock::Realtime
+			| Clock::Monotonic
+			| Clock::Boottime
+			| Clock::RealtimeAlarm
+			| Clock::BoottimeAlarm
+	)) {
+		return Err(errno!(EINVAL));
+	}
+	if unlikely(flags & !(TFD_CLOEXEC | TFD_NONBLOCK) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let ops = Arc::new(TimerFd::new(clock)?)?;
+	let file = File::open_floating(ops, file::O_RDONLY | (flags & TFD_NONBLOCK))?;
+	let fd_flags = if flags & TFD_CLOEXEC != 0 {
+		FD_CLOEXEC
+	} else {
+		0
+	};
+	let (fd_id, _) = fds.lock().create_fd(fd_flags, file)?;
+	Ok(fd_id as _)
+}
+
+/// Returns the timer associated with the file descriptor `fd`.
+fn get_timer(fds: &Mutex<FileDescriptorTable>, fd: c_int) -> EResult<Arc<File>> {
+	let file = fds.lock().get_fd(fd)?.get_file().clone();
+	if file.get_buffer::<TimerFd>().is_none() {
+		return Err(errno!(EINVAL));
+	}
+	Ok(file)
+}
+
+/// Sets the timer of `fd` to `new` (interval and value), and returns its previous state.
+fn do_timerfd_settime(
+	fd: c_int,
+	flags: c_int,
+	new: (Timestamp, Timestamp),
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<(Timestamp, Timestamp)> {
+	if unlikely(flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0) {
+		return Err(errno!(EINVAL));
+	}
+	let file = get_timer(&fds, fd)?;
+	// Cannot fail since this has been checked by `get_timer`
+	let timer: &TimerFd = file.get_buffer().unwrap();
+	let old = timer.get_time();
+	timer.set_time(new.0, new.1, flags & TFD_TIMER_ABSTIME != 0)?;
+	Ok(old)
+}
+
+/// Returns the current state of the timer of `fd` (interval and value).
+fn do_timerfd_gettime(
+	fd: c_int,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<(Timestamp, Timestamp)> {
+	let file = get_timer(&fds, fd)?;
+	// Cannot fail since this has been checked by `get_timer`
+	let timer: &TimerFd = file.get_buffer().unwrap();
+	Ok(timer.get_time())
+}
+
+pub fn timerfd_settime32(
+	Args((fd, flags, new_value, old_value)): Args<(
+		c_int,
+		c_int,
+		UserPtr<ITimerspec32>,
+		UserPtr<ITimerspec32>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let new_value = new_value.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let valid_nsec = |ts: &Timespec32| ts.tv_nsec < 1_000_000_000;
+	if unlikely(!valid_nsec(&new_value.it_interval) || !valid_nsec(&new_value.it_value)) {
+		return Err(errno!(EINVAL));
+	}
+	let new = (
+		new_value.it_interval.to_nano(),
+		new_value.it_value.to_nano(),
+	);
+	let (interval, value) = do_timerfd_settime(fd, flags, new, fds)?;
+	old_value.copy_to_user(&ITimerspec32 {
+		it_interval: Timespec32::from_nano(interval),
+		it_value: Timespec32::from_nano(value),
+	})?;
+	Ok(0)
+}
+
+pub fn timerfd_settime64(
+	Args((fd, flags, new_value, old_value)): Args<(
+		c_int,
+		c_int,
+		UserPtr<ITimerspec>,
+		UserPtr<ITimerspec>,
+	)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let new_value = new_value.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let valid_nsec = |ts: &Timespec| (0..1_000_000_000).contains(&ts.tv_nsec);
+	if unlikely(!valid_nsec(&new_value.it_interval) || !valid_nsec(&new_value.it_value)) {
+		return Err(errno!(EINVAL));
+	}
+	let new = (
+		new_value.it_interval.to_nano(),
+		new_value.it_value.to_nano(),
+	);
+	let (interval, value) = do_timerfd_settime(fd, flags, new, fds)?;
+	old_value.copy_to_user(&ITimerspec {
+		it_interval: Timespec::from_nano(interval),
+		it_value: Timespec::from_nano(value),
+	})?;
+	Ok(0)
+}
+
+pub fn timerfd_gettime32(
+	Args((fd, curr_value)): Args<(c_int, UserPtr<ITimerspec32>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let (interval, value) = do_timerfd_gettime(fd, fds)?;
+	curr_value.copy_to_user(&ITimerspec32 {
+		it_interval: Timespec32::from_nano(interval),
+		it_value: Timespec32::from_nano(value),
+	})?;
+	Ok(0)
+}
+
+pub fn timerfd_gettime64(
+	Args((fd, curr_value)): Args<(c_int, UserPtr<ITimerspec>)>,
+	fds: Arc<Mutex<FileDescriptorTable>>,
+) -> EResult<usize> {
+	let (interval, value) = do_timerfd_gettime(fd, fds)?;
+	curr_value.copy_to_user(&ITimerspec {
+		it_interval: Timespec::from_nano(interval),
+		it_value: Timespec::from_nano(value),
+	})?;
+	Ok(0)
+}
@@ -19,10 +19,16 @@
 //! Users and groups system calls.
 
 use crate::{
-	file::perm::{AccessProfile, Gid, Uid},
-	memory::user::UserPtr,
-	process::Process,
+	file::perm::{
+		AccessProfile, CAP_FULL_SET, CAP_SETGID, CAP_SETPCAP, CAP_SETUID, CapSet, Gid, Uid,
+	},
+	memory::user::{UserPtr, UserSlice},
+	process::{Process, pid::Pid},
 	syscall::Args,
+	uapi::capability::{
+		CapUserData, CapUserHeader, LINUX_CAPABILITY_VERSION_1, LINUX_CAPABILITY_VERSION_2,
+		LINUX_CAPABILITY_VERSION_3,
+	},
 };
 use core::ffi::c_int;
 use utils::{errno, errno::EResult, ptr::arc::Arc};
@@ -77,7 +83,7 @@ pub fn setreuid(
 	if ruid < -1 || euid < -1 {
 		return Err(errno!(EINVAL));
 	}
-	if !ap.is_privileged() && ![-1, ap.uid as _, ap.euid as _].contains(&ruid)
+	if !ap.has_capability(CAP_SETUID) && ![-1, ap.uid as _, ap.euid as _].contains(&ruid)
 		|| ![-1, ap.uid as _, ap.euid as _, ap.suid as _].contains(&euid)
 	{
 		return Err(errno!(EPERM));
@@ -97,6 +103,7 @@ pub fn setreuid(
 	if new_ruid != ap.uid || new_euid != ap.uid {
 		fs.access_profile.suid = new_euid;
 	}
+	fs.access_profile.update_capabilities(&ap);
 	Ok(0)
 }
 
@@ -109,7 +116,7 @@ pub fn setresuid(
 	if ruid < -1 || euid < -1 || suid < -1 {
 		return Err(errno!(EINVAL));
 	}
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SETUID) {
 		let allowed = [-1, ap.uid as _, ap.euid as _, ap.suid as _];
 		if !allowed.contains(&ruid) || !allowed.contains(&euid) || !allowed.contains(&suid) {
 			return Err(errno!(EPERM));
@@ -129,6 +136,7 @@ pub fn setresuid(
 		-1 => ap.suid,
 		i => i as _,
 	};
+	fs.access_profile.update_capabilities(&ap);
 	Ok(0)
 }
 
@@ -146,7 +154,7 @@ pub fn setregid(
 	if rgid < -1 || egid < -1 {
 		return Err(errno!(EINVAL));
 	}
-	if !ap.is_privileged()
+	if !ap.has_capability(CAP_SETGID)
 		&& (![-1, ap.gid as _, ap.egid as _].contains(&rgid)
 			|| ![-1, ap.gid as _, ap.egid as _, ap.sgid as _].contains(&egid))
 	{
@@ -179,7 +187,7 @@ pub fn setresgid(
 	if rgid < -1 || egid < -1 || sgid < -1 {
 		return Err(errno!(EINVAL));
 	}
-	if !ap.is_privileged() {
+	if !ap.has_capability(CAP_SETGID) {
 		let allowed = [-1, ap.gid as _, ap.egid as _, ap.sgid as _];
 		if !allowed.contains(&rgid) || !allowed.contains(&egid) || !allowed.contains(&sgid) {
 			return Err(errno!(EPERM));
@@ -201,3 +209,93 @@ pub fn setresgid(
 	};
 	Ok(0)
 }
+
+/// Reads the header of a capabilities request, and returns it along with the number of elements
+/// in the data array for its version.
+///
+/// If the version is not supported, the function writes the preferred version to the header and
+/// returns [`errno::EINVAL`].
+fn read_cap_header(hdr: UserPtr<CapUserHeader>) -> EResult<(CapUserHeader, usize)> {
+	let header = hdr.copy_from_user()?.ok_or_else(|| errno!(EFAULT))?;
+	let len = match header.version {
+		LINUX_CAPABILITY_VERSION_1 => 1,
+		LINUX_CAPABILITY_VERSION_2 | LINUX_CAPABILITY_VERSION_3 => 2,
+		_ => {
+			hdr.copy_to_user(&CapUserHeader {
+				version: LINUX_CAPABILITY_VERSION_3,
+				pid: header.pid,
+			})?;
+			return Err(errno!(EINVAL));
+		}
+	};
+	if header.pid < 0 {
+		return Err(errno!(EINVAL));
+	}
+	Ok((header, len))
+}
+
+pub fn capget(
+	Args((hdr, data)): Args<(UserPtr<CapUserHeader>, *mut CapUserData)>,
+) -> EResult<usize> {
+	let (header, len) = read_cap_header(hdr)?;
+	// A null pointer allows to probe the supported version
+	if data.is_null() {
+		return Ok(0);
+	}
+	let proc = match header.pid {
+		0 => Process::current(),
+		pid => Process::get_by_pid(pid as Pid).ok_or_else(|| errno!(ESRCH))?,
+	};
+	let ap = proc.fs.lock().access_profile;
+	let mut buf = [CapUserData::default(); 2];
+	for (i, d) in buf.iter_mut().enumerate() {
+		let shift = i * 32;
+		d.effective = (ap.cap_effective >> shift) as _;
+		d.permitted = (ap.cap_permitted >> shift) as _;
+		d.inheritable = (ap.cap_inheritable >> shift) as _;
+	}
+	UserSlice::from_user(data, len)?.copy_to_user(0, &buf[..len])?;
+	Ok(0)
+}
+
+pub fn capset(
+	Args((hdr, data)): Args<(UserPtr<CapUserHeader>, *mut CapUserData)>,
+	proc: Arc<Process>,
+) -> EResult<usize> {
+	let (header, len) = read_cap_header(hdr)?;
+	// Only the capabilities of the current process can be changed
+	if header.pid != 0 && header.pid as Pid != proc.get_pid() {
+		return Err(errno!(EPERM));
+	}
+	let mut buf = [CapUserData::default(); 2];
+	UserSlice::from_user(data, len)?.copy_from_user(0, &mut buf[..len])?;
+	let join = |f: fn(&CapUserData) -> u32| {
+		let set = buf
+			.iter()
+			.enumerate()
+			.fold(0, |set, (i, d)| set | (f(d) as CapSet) << (i * 32));
+		set & CAP_FULL_SET
+	};
+	let effective = join(|d| d.effective);
+	let permitted = join(|d| d.permitted);
+	let inheritable = join(|d| d.inheritable);
+	let mut fs = proc.fs.lock();
+	let ap = &mut fs.access_profile;
+	// Inheritable capabilities can be added only if permitted, unless capable
+	let inheritable_limit = if ap.has_capability(CAP_SETPCAP) {
+		CAP_FULL_SET
+	} else {
+		ap.cap_inheritable | ap.cap_permitted
+	};
+	// Permitted capabilities can only be dropped, and effective capabilities must be permitted
+	if inheritable & !inheritable_limit != 0
+		|| permitted & !ap.cap_permitted != 0
+		|| effective & !permitted != 0
+	{
+		return Err(errno!(EPERM));
+	}
+	ap.cap_effective = effective;
+	ap.cap_permitted = permitted;
+	ap.cap_inheritable = inheritable;
+	Ok(0)
+}
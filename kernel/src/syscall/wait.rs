@@ -44,29 +44,31 @@ pub const WCONTINUED: i32 = 8;
 /// child.
 pub const WNOWAIT: i32 = 0x1000000;
 
-/// Returns an iterator over the IDs of the processes to be watched according to the given
-/// constraint.
-///
-/// Arguments:
-/// - `curr_proc` is the current process.
-/// - `pid` is the constraint given to the system call.
-fn iter_targets(curr_proc: &Process, pid: i32) -> impl Iterator<Item = Pid> + '_ {
+/// Returns an iterator over the IDs of the children of the current process.
+fn iter_children(curr_proc: &Process) -> impl Iterator<Item = Pid> + '_ {
 	let mut i = 0;
 	iter::from_fn(move || {
-		// FIXME: select only process that are children of `curr_proc`
-		let links = curr_proc.links.lock();
-		let res = match pid {
-			// FIXME: must wait for any child process whose pgid is equal to -pid
-			..-1 => links.process_group.get(i).cloned(),
-			-1 => links.children.get(i).cloned(),
-			0 => links.process_group.get(i).cloned(),
-			_ => (i == 0).then_some(pid as _),
-		};
+		let res = curr_proc.links.lock().children.get(i).cloned();
 		i += 1;
 		res
 	})
 }
 
+/// Tells whether the child process `proc` is to be watched according to the given constraint.
+///
+/// Arguments:
+/// - `pgid` is the process group ID of the current process.
+/// - `proc` is the child process.
+/// - `pid` is the constraint given to the system call.
+fn is_target(pgid: Pid, proc: &Process, pid: i32) -> bool {
+	match pid {
+		..-1 => proc.get_pgid() == -pid as Pid,
+		-1 => true,
+		0 => proc.get_pgid() == pgid,
+		_ => proc.get_pid() == pid as Pid,
+	}
+}
+
 /// Returns the wait status for the given process.
 fn get_wstatus(proc: &Process) -> i32 {
 	let (status, termsig) = {
@@ -103,11 +105,13 @@ fn get_waitable(
 	rusage: UserPtr<Rusage>,
 ) -> EResult<Option<Pid>> {
 	let mut empty = true;
+	let pgid = curr_proc.get_pgid();
 	let mut sched = SCHEDULER.lock();
 	// Find a waitable process
-	let proc = iter_targets(curr_proc, pid)
-		.inspect(|_| empty = false)
+	let proc = iter_children(curr_proc)
 		.filter_map(|pid| sched.get_by_pid(pid))
+		.filter(|proc| is_target(pgid, proc, pid))
+		.inspect(|_| empty = false)
 		// Select a waitable process
 		.find(|proc| {
 			let state = proc.get_state();
@@ -126,15 +130,22 @@ fn get_waitable(
 		};
 	};
 	let pid = proc.get_pid();
+	// The resources used by the child include the ones of its own terminated children
+	let mut usage = proc.get_rusage();
+	usage.accumulate(&proc.children_rusage.lock());
 	// Write values back
 	wstatus.copy_to_user(&get_wstatus(&proc))?;
-	rusage.copy_to_user(&proc.rusage.lock())?;
+	rusage.copy_to_user(&usage)?;
 	// Clear the waitable flag if requested
 	if options & WNOWAIT == 0 {
 		// If the process was a zombie, remove it
 		if matches!(proc.get_state(), State::Zombie) {
+			curr_proc.children_rusage.lock().accumulate(&usage);
 			proc.unlink();
 			sched.remove_process(pid);
+			drop(sched);
+			// The process may still be switching away from its core
+			proc.wait_off_cpu();
 		}
 	}
 	Ok(Some(pid))
@@ -19,12 +19,19 @@
 //! System clocks.
 
 use crate::{
+	memory::cache::RcFrame,
+	process::pid::Pid,
 	sync::atomic::AtomicU64,
 	time::{Timestamp, unit::ClockIdT},
 };
 use core::{
 	cmp::max,
-	sync::atomic::Ordering::{Acquire, Release},
+	ptr,
+	sync::atomic::{
+		AtomicPtr, AtomicU32,
+		Ordering::{Acquire, Relaxed, Release},
+		fence,
+	},
 };
 
 /// Available clocks
@@ -68,7 +75,70 @@ impl Clock {
 	}
 }
 
-// TODO allow accessing clocks through an address shared with userspace (vDSO)
+/// CPU-time clock kind: time spent in userspace and kernelspace.
+pub const CPUCLOCK_PROF: ClockIdT = 0;
+/// CPU-time clock kind: time spent in userspace.
+pub const CPUCLOCK_VIRT: ClockIdT = 1;
+/// CPU-time clock kind: time spent running.
+pub const CPUCLOCK_SCHED: ClockIdT = 2;
+/// Bit set in the ID of a CPU-time clock measuring a single thread instead of a process.
+pub const CPUCLOCK_PERTHREAD_MASK: ClockIdT = 4;
+/// Mask of the kind in the ID of a CPU-time clock.
+const CPUCLOCK_CLOCK_MASK: ClockIdT = 3;
+
+/// A clock measuring the CPU time consumed by a process or a thread.
+///
+/// Its ID is negative and encodes the PID of the target, as with `clock_getcpuclockid` and
+/// `pthread_getcpuclockid`. A PID of `0` designates the calling process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CpuClock {
+	/// The PID of the measured process or thread.
+	pub pid: Pid,
+	/// Tells whether the clock measures a single thread.
+	pub thread: bool,
+	/// The kind of CPU time measured, one of the `CPUCLOCK_*` constants.
+	pub kind: ClockIdT,
+}
+
+impl CpuClock {
+	/// Returns the CPU-time clock with the given ID.
+	///
+	/// [`Clock::ProcessCputimeId`] and [`Clock::ThreadCputimeId`] designate the clocks of the
+	/// calling process.
+	///
+	/// If the ID is not a CPU-time clock, the function returns `None`.
+	pub fn from_id(id: ClockIdT) -> Option<Self> {
+		match id {
+			2 | 3 => Some(Self {
+				pid: 0,
+				thread: id == 3,
+				kind: CPUCLOCK_SCHED,
+			}),
+			0.. => None,
+			_ => {
+				let kind = id & CPUCLOCK_CLOCK_MASK;
+				if kind > CPUCLOCK_SCHED {
+					return None;
+				}
+				Some(Self {
+					pid: (!(id >> 3)).try_into().ok()?,
+					thread: id & CPUCLOCK_PERTHREAD_MASK != 0,
+					kind,
+				})
+			}
+		}
+	}
+
+	/// Returns the ID of the clock.
+	pub fn to_id(&self) -> ClockIdT {
+		let thread = if self.thread {
+			CPUCLOCK_PERTHREAD_MASK
+		} else {
+			0
+		};
+		(!(self.pid as ClockIdT) << 3) | thread | self.kind
+	}
+}
 
 /// The current timestamp of the real time clock, in nanoseconds.
 static REALTIME: AtomicU64 = AtomicU64::new(0);
@@ -78,11 +148,59 @@ static MONOTONIC: AtomicU64 = AtomicU64::new(0);
 /// The time elapsed since boot time, in nanoseconds.
 static BOOTTIME: AtomicU64 = AtomicU64::new(0);
 
+/// Values of the clocks shared with userspace, read by the vDSO without a system call.
+///
+/// The layout must match the offsets used by the vDSO.
+#[repr(C)]
+struct VdsoClocks {
+	/// Sequence counter, odd while the values are being updated. Readers retry if it is odd or
+	/// has changed while reading.
+	seq: AtomicU32,
+	/// Padding.
+	_pad: u32,
+	/// The value of [`Clock::Realtime`], in nanoseconds.
+	realtime: u64,
+	/// The value of [`Clock::Monotonic`], in nanoseconds.
+	monotonic: u64,
+	/// The value of [`Clock::Boottime`], in nanoseconds.
+	boottime: u64,
+}
+
+/// The clock values shared with userspace. If null, the page is not allocated yet.
+static VDSO_CLOCKS: AtomicPtr<VdsoClocks> = AtomicPtr::new(ptr::null_mut());
+
+/// Uses the page `page` to share the values of clocks with userspace.
+///
+/// The page must remain allocated for as long as the system runs.
+pub(crate) fn share(page: &RcFrame) {
+	VDSO_CLOCKS.store(page.virt_addr().as_ptr(), Release);
+	publish();
+}
+
+/// Copies the current values of the clocks to the page shared with userspace, if any.
+fn publish() {
+	let clocks = VDSO_CLOCKS.load(Acquire);
+	let Some(clocks) = (unsafe { clocks.as_mut() }) else {
+		return;
+	};
+	// Clocks are only updated from the timer interrupt, so there is no concurrent writer
+	let seq = clocks.seq.load(Relaxed);
+	clocks.seq.store(seq.wrapping_add(1), Relaxed);
+	fence(Release);
+	unsafe {
+		ptr::write_volatile(&mut clocks.realtime, current_time_ns(Clock::Realtime));
+		ptr::write_volatile(&mut clocks.monotonic, current_time_ns(Clock::Monotonic));
+		ptr::write_volatile(&mut clocks.boottime, current_time_ns(Clock::Boottime));
+	}
+	clocks.seq.store(seq.wrapping_add(2), Release);
+}
+
 /// Updates clocks with the given delta value in nanoseconds.
 pub fn update(delta: Timestamp) {
 	REALTIME.fetch_add(delta as _, Release);
 	MONOTONIC.fetch_add(delta as _, Release);
 	BOOTTIME.fetch_add(delta as _, Release);
+	publish();
 }
 
 /// Returns the current timestamp in nanoseconds.
@@ -121,3 +239,30 @@ pub fn current_time_ms(clk: Clock) -> Timestamp {
 pub fn current_time_sec(clk: Clock) -> Timestamp {
 	current_time_ns(clk) / 1_000_000_000
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_case]
+	fn cpu_clock_id() {
+		// Value computed by `pthread_getcpuclockid` for TID `42`
+		let clock = CpuClock::from_id((-42 - 1) * 8 + 6).unwrap();
+		assert_eq!(
+			clock,
+			CpuClock {
+				pid: 42,
+				thread: true,
+				kind: CPUCLOCK_SCHED,
+			}
+		);
+		assert_eq!(CpuClock::from_id(clock.to_id()), Some(clock));
+		assert_eq!(
+			CpuClock::from_id(3).map(|c| (c.pid, c.thread)),
+			Some((0, true))
+		);
+		assert_eq!(CpuClock::from_id(Clock::Monotonic as _), None);
+		// Invalid kind
+		assert_eq!(CpuClock::from_id(!(42 << 3) | 3), None);
+	}
+}
@@ -32,11 +32,14 @@ pub mod unit;
 use crate::{
 	event,
 	event::CallbackResult,
+	initcall,
 	process::{
 		Process, State,
 		scheduler::Scheduler,
 		signal::{SIGEV_NONE, SigEvent},
 	},
+	softirq,
+	softirq::SoftIrq,
 	time::{
 		clock::{Clock, current_time_ns},
 		timer::Timer,
@@ -60,7 +63,7 @@ pub fn sleep_for(clock: Clock, delay: Timestamp, remain: &mut Timestamp) -> ERes
 	// Setup timer
 	let pid = Process::current().get_pid();
 	// FIXME: there can be allocation failures here
-	let mut timer = Timer::new(
+	let timer = Timer::new(
 		clock,
 		pid,
 		SigEvent {
@@ -90,13 +93,14 @@ pub fn sleep_for(clock: Clock, delay: Timestamp, remain: &mut Timestamp) -> ERes
 }
 
 /// Initializes time management.
-pub(crate) fn init() -> EResult<()> {
+fn init() -> EResult<()> {
 	// Initialize hardware clocks
 	let mut hw_clocks = hw::CLOCKS.lock();
 	hw_clocks.insert(b"pit".try_into()?, Box::new(hw::pit::PIT::new())?)?;
 	hw_clocks.insert(b"rtc".try_into()?, Box::new(hw::rtc::RTC::new())?)?;
 	// TODO implement HPET
 	// TODO implement APIC timer
+	softirq::open(SoftIrq::Timer, timer::tick);
 	// Link hardware clock to software clock
 	let rtc = hw_clocks.get_mut(b"rtc".as_slice()).unwrap();
 	rtc.set_frequency(FREQUENCY);
@@ -104,10 +108,12 @@ pub(crate) fn init() -> EResult<()> {
 		hw::rtc::RTC::reset();
 		// FIXME: we are loosing precision here
 		clock::update((1_000_000_000 / FREQUENCY) as _);
-		timer::tick();
+		softirq::raise(SoftIrq::Timer);
 		CallbackResult::Continue
 	})?;
 	let _ = ManuallyDrop::new(hook);
 	rtc.set_enabled(true);
 	Ok(())
 }
+
+initcall!(arch, time, |_| init());
@@ -20,19 +20,24 @@
 
 use super::unit::{ITimerspec32, TimerT};
 use crate::{
+	file::wait_queue::WaitQueue,
 	memory::oom,
 	process::{
 		Process,
 		pid::Pid,
 		signal::{SIGEV_NONE, SIGEV_SIGNAL, SIGEV_THREAD, SigEvent, Signal},
 	},
-	sync::mutex::IntMutex,
+	sync::{atomic::AtomicU64, mutex::IntMutex},
 	time::{
 		clock::{Clock, current_time_ns},
 		unit::{TimeUnit, Timespec32, Timestamp},
 	},
 };
-use core::hint::unlikely;
+use core::{
+	fmt,
+	hint::unlikely,
+	sync::atomic::Ordering::{Acquire, Relaxed},
+};
 use utils::{
 	boxed::Box,
 	collections::{btreemap::BTreeMap, hashmap::HashMap, id_allocator::IDAllocator},
@@ -53,16 +58,32 @@ struct TimerSpec {
 	next: Option<Timestamp>,
 }
 
+/// The action performed when a timer is triggered.
+enum TimerEvent {
+	/// Notifies a process.
+	SigEvent {
+		/// PID of the process to notify.
+		pid: Pid,
+		/// Definition of the action to perform.
+		sevp: SigEvent,
+	},
+	/// Wakes up the processes waiting on the timer's queue.
+	WaitQueue,
+}
+
 struct TimerInner {
 	/// The clock to user.
 	clock: Clock,
-	/// PID of the process to notify.
-	pid: Pid,
-	/// Definition of the action to perform when the timer is triggered.
-	sevp: SigEvent,
+	/// The action to perform when the timer is triggered.
+	event: TimerEvent,
 
 	/// Timer setting.
 	spec: IntMutex<TimerSpec>,
+	/// The number of expirations since the timer has been set, or since the last call to
+	/// [`Timer::take_expirations`].
+	expirations: AtomicU64,
+	/// The queue of processes waiting for the timer to expire.
+	queue: WaitQueue,
 }
 
 impl TimerInner {
@@ -86,13 +107,24 @@ impl TimerInner {
 
 	/// Fires the timer.
 	fn fire(&self) {
-		let Some(proc) = Process::get_by_pid(self.pid) else {
+		self.expirations.fetch_add(1, Relaxed);
+		let (pid, sevp) = match &self.event {
+			TimerEvent::SigEvent {
+				pid,
+				sevp,
+			} => (*pid, sevp),
+			TimerEvent::WaitQueue => {
+				self.queue.wake_all();
+				return;
+			}
+		};
+		let Some(proc) = Process::get_by_pid(pid) else {
 			return;
 		};
-		match self.sevp.sigev_notify {
+		match sevp.sigev_notify {
 			SIGEV_NONE => proc.wake(),
 			SIGEV_SIGNAL => {
-				let Ok(signal) = Signal::try_from(self.sevp.sigev_signo) else {
+				let Ok(signal) = Signal::try_from(sevp.sigev_signo) else {
 					return;
 				};
 				// TODO on sigint_t, set si_code to SI_TIMER
@@ -147,25 +179,59 @@ impl Timer {
 		if unlikely(!sevp.is_valid()) {
 			return Err(errno!(EINVAL));
 		}
+		Self::new_impl(
+			clock,
+			TimerEvent::SigEvent {
+				pid,
+				sevp,
+			},
+		)
+	}
+
+	/// Creates a timer which, instead of notifying a process, wakes up the processes waiting on
+	/// its queue.
+	///
+	/// `clock` is the clock to use.
+	pub fn new_waitable(clock: Clock) -> EResult<Self> {
+		Self::new_impl(clock, TimerEvent::WaitQueue)
+	}
+
+	fn new_impl(clock: Clock, event: TimerEvent) -> EResult<Self> {
 		Ok(Self(Box::new(TimerInner {
 			clock,
-			pid,
-			sevp,
+			event,
 
 			spec: Default::default(),
+			expirations: AtomicU64::new(0),
+			queue: WaitQueue::new(),
 		})?))
 	}
 
-	/// Returns the current state of the timer.
+	/// Returns the clock used by the timer.
 	#[inline]
-	pub fn get_time(&self) -> ITimerspec32 {
+	pub fn get_clock(&self) -> Clock {
+		self.0.clock
+	}
+
+	/// Returns the current state of the timer, in nanoseconds.
+	///
+	/// The function returns the interval between two ticks, and the remaining time until the next
+	/// tick. If the timer is unarmed, the remaining time is zero.
+	pub fn get_time_ns(&self) -> (Timestamp, Timestamp) {
 		let spec = self.0.spec.lock();
 		let value = spec
 			.next
 			.map(|next| next.saturating_sub(current_time_ns(self.0.clock)))
 			.unwrap_or(0);
+		(spec.interval, value)
+	}
+
+	/// Returns the current state of the timer.
+	#[inline]
+	pub fn get_time(&self) -> ITimerspec32 {
+		let (interval, value) = self.get_time_ns();
 		ITimerspec32 {
-			it_interval: Timespec32::from_nano(spec.interval),
+			it_interval: Timespec32::from_nano(interval),
 			it_value: Timespec32::from_nano(value),
 		}
 	}
@@ -176,8 +242,10 @@ impl Timer {
 	/// - `interval` is the interval between two timer tick
 	/// - `value` is the initial value of the timer
 	///
+	/// The expirations count is reset to zero.
+	///
 	/// On allocation error, the function returns an error.
-	pub fn set_time(&mut self, interval: Timestamp, value: Timestamp) -> AllocResult<()> {
+	pub fn set_time(&self, interval: Timestamp, value: Timestamp) -> AllocResult<()> {
 		let mut queue = TIMERS_QUEUE.lock();
 		let mut spec = self.0.spec.lock();
 		// Remove from queue
@@ -186,6 +254,7 @@ impl Timer {
 		}
 		// Update timer
 		spec.interval = interval;
+		self.0.expirations.store(0, Relaxed);
 		// Arm or disarm
 		if value == 0 {
 			spec.next = None;
@@ -205,6 +274,35 @@ impl Timer {
 	pub fn has_expired(&self, cur_ts: Timestamp) -> bool {
 		self.0.has_expired(cur_ts)
 	}
+
+	/// Returns the number of expirations since the timer has been set, or since the last call to
+	/// [`Self::take_expirations`].
+	#[inline]
+	pub fn get_expirations(&self) -> u64 {
+		self.0.expirations.load(Acquire)
+	}
+
+	/// Returns the number of expirations, then resets it to zero.
+	#[inline]
+	pub fn take_expirations(&self) -> u64 {
+		self.0.expirations.swap(0, Acquire)
+	}
+
+	/// Returns the queue of processes waiting for the timer to expire.
+	///
+	/// The queue is woken up only for timers created with [`Self::new_waitable`].
+	#[inline]
+	pub fn get_queue(&self) -> &WaitQueue {
+		&self.0.queue
+	}
+}
+
+impl fmt::Debug for Timer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Timer")
+			.field("clock", &self.0.clock)
+			.finish_non_exhaustive()
+	}
 }
 
 impl Drop for Timer {
@@ -107,6 +107,41 @@ impl PartialOrd for Timeval {
 	}
 }
 
+/// Same as [`Timeval`], but with 32 bits values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct Timeval32 {
+	/// Seconds
+	pub tv_sec: u32,
+	/// Microseconds
+	pub tv_usec: u32,
+}
+
+impl TimeUnit for Timeval32 {
+	fn from_nano(timestamp: u64) -> Self {
+		Self {
+			tv_sec: (timestamp / 1_000_000_000) as _,
+			tv_usec: ((timestamp % 1_000_000_000) / 1000) as _,
+		}
+	}
+
+	fn to_nano(&self) -> u64 {
+		(self.tv_sec as u64)
+			.wrapping_mul(1_000_000_000)
+			.wrapping_add((self.tv_usec as u64).wrapping_mul(1000))
+	}
+}
+
+/// Obsolete timezone information, as used by `gettimeofday`.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Timezone {
+	/// Minutes west of Greenwich
+	pub tz_minuteswest: c_int,
+	/// Type of DST correction
+	pub tz_dsttime: c_int,
+}
+
 /// Same as [`Timeval`], but with nanosecond precision.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 #[repr(C)]
@@ -232,6 +267,16 @@ impl PartialOrd for Timespec32 {
 /// A timer's state.
 #[derive(Clone, Copy, Debug, Default)]
 #[repr(C)]
+pub struct ITimerspec {
+	/// The interval between each firing of the timer.
+	pub it_interval: Timespec,
+	/// Start value of the timer.
+	pub it_value: Timespec,
+}
+
+/// Same as [`ITimerspec`], but with 32 bits values.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
 pub struct ITimerspec32 {
 	/// The interval between each firing of the timer.
 	pub it_interval: Timespec32,
@@ -29,7 +29,8 @@ pub mod termios;
 pub mod vga;
 
 use crate::{
-	device::serial,
+	console,
+	console::Console,
 	file::wait_queue::WaitQueue,
 	memory::{user::UserSlice, vmem},
 	process::{Process, pid::Pid, signal::Signal},
@@ -332,9 +333,6 @@ impl TTYDisplay {
 
 	/// Writes the content of `buf` to the TTY.
 	pub fn write(&mut self, buf: &[u8]) {
-		// TODO Add a compilation and/or runtime option for this
-		serial::PORTS[0].lock().write(buf);
-
 		let mut i = 0;
 		while i < buf.len() {
 			let c = buf[i];
@@ -451,6 +449,26 @@ pub static TTY: TTY = TTY {
 	rd_queue: WaitQueue::new(),
 };
 
+/// The console displaying on the TTY, using the VGA text mode.
+pub struct VgaConsole;
+
+impl Console for VgaConsole {
+	fn name(&self) -> &[u8] {
+		b"tty0"
+	}
+
+	fn is_default(&self) -> bool {
+		true
+	}
+
+	fn write(&self, buf: &[u8]) {
+		TTY.display.lock().write(buf);
+	}
+}
+
+/// The VGA console.
+pub static VGA_CONSOLE: VgaConsole = VgaConsole;
+
 impl TTY {
 	// TODO Implement IUTF8
 	/// Reads inputs from the TTY and writes it into the buffer `buf`.
@@ -507,6 +525,11 @@ impl TTY {
 		})?
 	}
 
+	/// Returns the queue of processes waiting for incoming data to read.
+	pub fn get_rd_queue(&self) -> &WaitQueue {
+		&self.rd_queue
+	}
+
 	/// Tells whether the TTY has any data available to be read.
 	pub fn has_input_available(&self) -> bool {
 		let display = self.display.lock();
@@ -534,7 +557,7 @@ impl TTY {
 
 		if termios.c_lflag & ECHO != 0 {
 			// Write onto the TTY
-			self.display.lock().write(buffer);
+			console::write(buffer);
 		}
 		// TODO If ECHO is disabled but ICANON and ECHONL are set, print newlines
 
@@ -615,7 +638,7 @@ impl TTY {
 					&& termios.c_lflag & ECHOCTL != 0
 					&& *b >= 1 && *b < 32
 				{
-					self.display.lock().write(&[b'^', b + b'A']);
+					console::write(&[b'^', b + b'A']);
 				}
 
 				// TODO Handle every special characters
@@ -630,7 +653,7 @@ impl TTY {
 			}
 		}
 
-		self.rd_queue.wake_next();
+		self.rd_queue.wake_all();
 	}
 
 	/// Erases `count` characters in TTY.
@@ -660,6 +683,6 @@ impl TTY {
 			}
 		}
 
-		self.rd_queue.wake_next();
+		self.rd_queue.wake_all();
 	}
 }
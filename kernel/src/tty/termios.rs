@@ -200,10 +200,6 @@ pub struct Termios {
 	pub c_line: CC,
 	/// Special characters
 	pub c_cc: [CC; NCCS],
-	/// Input baud rate
-	pub __c_ispeed: u32,
-	/// Output baud rate
-	pub __c_ospeed: u32,
 }
 
 impl Termios {
@@ -218,8 +214,6 @@ impl Termios {
 			c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK,
 			c_line: 0,
 			c_cc: [0; NCCS],
-			__c_ispeed: 0,
-			__c_ospeed: 0,
 		};
 		// Fill special characters
 		t.c_cc[VINTR] = 0o03;
@@ -0,0 +1,53 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Capabilities structures, used by the `capget` and `capset` system calls.
+
+use core::ffi::c_int;
+
+/// Version 1 of the capabilities interface, with 32 bit sets.
+pub const LINUX_CAPABILITY_VERSION_1: u32 = 0x19980330;
+/// Version 2 of the capabilities interface, with 64 bit sets (deprecated).
+pub const LINUX_CAPABILITY_VERSION_2: u32 = 0x20071026;
+/// Version 3 of the capabilities interface, with 64 bit sets.
+pub const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+/// The header of a capabilities request.
+#[derive(Debug)]
+#[repr(C)]
+pub struct CapUserHeader {
+	/// The version of the interface.
+	pub version: u32,
+	/// The PID of the target process. `0` designates the current process.
+	pub pid: c_int,
+}
+
+/// Capability sets of a process, split in 32 bit words.
+///
+/// With versions `2` and `3` of the interface, an array of two elements is used, the first one
+/// containing the lower half of each set.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct CapUserData {
+	/// Effective capabilities.
+	pub effective: u32,
+	/// Permitted capabilities.
+	pub permitted: u32,
+	/// Inheritable capabilities.
+	pub inheritable: u32,
+}
@@ -0,0 +1,52 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Directory entry structures, used by the `getdents` family of system calls.
+
+use core::ffi::c_ulong;
+
+/// A Linux directory entry.
+#[repr(C)]
+pub struct LinuxDirent {
+	/// Inode number.
+	pub d_ino: c_ulong,
+	/// Offset to the next entry.
+	pub d_off: c_ulong,
+	/// Length of this entry.
+	pub d_reclen: u16,
+	/// Filename (nul-terminated).
+	///
+	/// The filename is followed by a nul byte, padding, then a byte indicating the type of the
+	/// entry, which is the last byte of the record.
+	pub d_name: [u8; 0],
+}
+
+/// A Linux directory entry with 64 bits offsets.
+#[repr(C)]
+pub struct LinuxDirent64 {
+	/// 64-bit inode number.
+	pub d_ino: u64,
+	/// 64-bit offset to next entry.
+	pub d_off: u64,
+	/// Size of this dirent.
+	pub d_reclen: u16,
+	/// File type.
+	pub d_type: u8,
+	/// Filename (nul-terminated).
+	pub d_name: [u8; 0],
+}
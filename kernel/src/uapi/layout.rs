@@ -0,0 +1,246 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Compile-time checks of the layout of userspace structures.
+//!
+//! Expected values come from the structures of the Linux kernel, for each architecture.
+//!
+//! TODO: on `x86`, `Timeval`, `Timespec` and `Rusage` use 64 bit fields where the ABI
+//! expects `long`, and are not checked
+
+use super::{
+	CompatFlock, CompatFlock64, CompatIpcPerm, CompatMsqidDs, CompatSemidDs, CompatShmidDs,
+	CompatSigAction, CompatSigStack, EpollEvent, IOVec, ITimerspec32, In6Addr, IpcPerm, PollFD,
+	RLimit, SemBuf, SigEvent, SigSet, SigStack, SockAddrIn, SockAddrIn6, Statfs, Termios,
+	Timespec32, Timeval32, Timezone, WatchNotification, WatchNotificationFilter,
+	WatchNotificationTypeFilter, WinSize,
+	capability::{CapUserData, CapUserHeader},
+	dirent::{LinuxDirent, LinuxDirent64},
+	sched::SchedParam,
+	seccomp::SeccompData,
+	socket::{CompatMsgHdr, CompatSockFprog, MsgHdr, SockFprog},
+	stat::{CompatStat64, Stat32, Statx, StatxTimestamp},
+	times::Tms,
+	utsname::Utsname,
+};
+#[cfg(target_arch = "x86_64")]
+use super::{
+	Flock, ITimerspec, MsqidDs, Rusage, SemidDs, ShmidDs, SigAction, Timespec, Timeval,
+	stat::Stat64,
+};
+use core::mem::offset_of;
+
+/// Returns `x86` if compiling for the `x86` architecture, or `x86_64` otherwise.
+const fn arch(x86: usize, x86_64: usize) -> usize {
+	if cfg!(target_arch = "x86") {
+		x86
+	} else {
+		x86_64
+	}
+}
+
+/// Checks the size of the structure `$ty` is `$size`, and the offset of each `$field` is `$off`.
+macro_rules! check_layout {
+	($ty:ty, $size:expr $(, $field:ident: $off:expr)* $(,)?) => {
+		const _: () = {
+			assert!(
+				size_of::<$ty>() == $size,
+				concat!("invalid size for ", stringify!($ty))
+			);
+			$(
+				assert!(
+					offset_of!($ty, $field) == $off,
+					concat!("invalid offset for ", stringify!($ty), "::", stringify!($field))
+				);
+			)*
+		};
+	};
+}
+
+// stat
+
+check_layout!(Stat32, 64, st_ino: 4, st_mode: 8, st_rdev: 16, st_size: 20, st_atime: 32);
+#[cfg(target_arch = "x86_64")]
+check_layout!(
+	Stat64,
+	144,
+	st_nlink: 16,
+	st_mode: 24,
+	st_rdev: 40,
+	st_size: 48,
+	st_blocks: 64,
+	st_atime: 72,
+	st_ctime_nsec: 112,
+);
+check_layout!(
+	CompatStat64,
+	96,
+	__st_ino: 12,
+	st_mode: 16,
+	st_rdev: 32,
+	st_size: 44,
+	st_blksize: 52,
+	st_blocks: 56,
+	st_atime: 64,
+	st_ino: 88,
+);
+check_layout!(StatxTimestamp, 16, tv_nsec: 8);
+check_layout!(
+	Statx,
+	256,
+	stx_attributes: 8,
+	stx_mode: 28,
+	stx_ino: 32,
+	stx_atime: 64,
+	stx_rdev_major: 128,
+	stx_mnt_id: 144,
+	stx_subvol: 160,
+	__padding1: 180,
+);
+check_layout!(
+	Statfs,
+	arch(84, 120),
+	f_bsize: arch(4, 8),
+	f_blocks: arch(8, 16),
+	f_fsid: arch(48, 56),
+	f_namelen: arch(56, 64),
+	f_spare: arch(68, 88),
+);
+
+// dirent
+
+check_layout!(LinuxDirent, arch(12, 24), d_off: arch(4, 8), d_name: arch(10, 18));
+check_layout!(LinuxDirent64, arch(20, 24), d_off: 8, d_reclen: 16, d_type: 18, d_name: 19);
+
+// signal
+
+#[cfg(target_arch = "x86_64")]
+check_layout!(SigAction, 32, sa_flags: 8, sa_restorer: 16, sa_mask: 24);
+check_layout!(CompatSigAction, 20, sa_flags: 4, sa_restorer: 8, sa_mask: 12);
+check_layout!(SigSet, 8);
+check_layout!(SigStack, arch(12, 24), ss_flags: arch(4, 8), ss_size: arch(8, 16));
+check_layout!(CompatSigStack, 12, ss_flags: 4, ss_size: 8);
+check_layout!(
+	SigEvent,
+	64,
+	sigev_signo: arch(4, 8),
+	sigev_notify: arch(8, 12),
+	sigev_notify_thread_id: arch(12, 16),
+);
+
+// socket
+
+check_layout!(SockAddrIn, 16, sin_port: 2, sin_addr: 4, sin_zero: 8);
+check_layout!(In6Addr, 16);
+check_layout!(SockAddrIn6, 28, sin6_flowinfo: 4, sin6_addr: 8, sin6_scope_id: 24);
+check_layout!(
+	MsgHdr,
+	arch(28, 56),
+	msg_namelen: arch(4, 8),
+	msg_iov: arch(8, 16),
+	msg_iovlen: arch(12, 24),
+	msg_control: arch(16, 32),
+	msg_controllen: arch(20, 40),
+	msg_flags: arch(24, 48),
+);
+check_layout!(CompatMsgHdr, 28, msg_iov: 8, msg_flags: 24);
+check_layout!(IOVec, arch(8, 16), iov_len: arch(4, 8));
+check_layout!(SockFprog, arch(8, 16), filter: arch(4, 8));
+check_layout!(CompatSockFprog, 8, filter: 4);
+
+// time
+
+#[cfg(target_arch = "x86_64")]
+check_layout!(Timeval, 16, tv_usec: 8);
+check_layout!(Timeval32, 8, tv_usec: 4);
+check_layout!(Timezone, 8, tz_dsttime: 4);
+#[cfg(target_arch = "x86_64")]
+check_layout!(Timespec, 16, tv_nsec: 8);
+check_layout!(Timespec32, 8, tv_nsec: 4);
+#[cfg(target_arch = "x86_64")]
+check_layout!(ITimerspec, 32, it_value: 16);
+check_layout!(ITimerspec32, 16, it_value: 8);
+check_layout!(Tms, arch(16, 32), tms_stime: arch(4, 8), tms_cstime: arch(12, 24));
+
+// ipc
+
+check_layout!(IpcPerm, arch(36, 48), mode: 20, seq: 24, _unused1: arch(28, 32));
+check_layout!(CompatIpcPerm, 36, mode: 20, _unused1: 28);
+#[cfg(target_arch = "x86_64")]
+check_layout!(
+	ShmidDs,
+	112,
+	shm_segsz: 48,
+	shm_atime: 56,
+	shm_cpid: 80,
+	shm_nattch: 88,
+);
+check_layout!(CompatShmidDs, 84, shm_segsz: 36, shm_cpid: 64, shm_nattch: 72);
+check_layout!(SemBuf, 6, sem_op: 2, sem_flg: 4);
+#[cfg(target_arch = "x86_64")]
+check_layout!(SemidDs, 104, sem_otime: 48, sem_ctime: 64, sem_nsems: 80);
+check_layout!(CompatSemidDs, 64, sem_otime: 36, sem_ctime: 44, sem_nsems: 52);
+#[cfg(target_arch = "x86_64")]
+check_layout!(
+	MsqidDs,
+	120,
+	msg_stime: 48,
+	msg_cbytes: 72,
+	msg_qbytes: 88,
+	msg_lspid: 96,
+	_unused4: 104,
+);
+check_layout!(
+	CompatMsqidDs,
+	88,
+	msg_stime: 36,
+	msg_cbytes: 60,
+	msg_qbytes: 68,
+	msg_lspid: 72,
+);
+
+// resource
+
+check_layout!(RLimit, 16, rlim_max: 8);
+#[cfg(target_arch = "x86_64")]
+check_layout!(Rusage, 144, ru_stime: 16, ru_maxrss: 32, ru_nivcsw: 136);
+check_layout!(SchedParam, 4);
+check_layout!(CapUserHeader, 8, pid: 4);
+check_layout!(CapUserData, 12, permitted: 4, inheritable: 8);
+check_layout!(SeccompData, 64, arch: 4, instruction_pointer: 8, args: 16);
+
+// misc
+
+check_layout!(Termios, 36, c_line: 16, c_cc: 17);
+check_layout!(WinSize, 8, ws_col: 2, ws_ypixel: 6);
+check_layout!(PollFD, 8, events: 4, revents: 6);
+check_layout!(EpollEvent, 12, data: 4);
+check_layout!(Utsname, 390, nodename: 65, machine: 260, domainname: 325);
+
+// watch queue
+
+check_layout!(WatchNotification, 8, info: 4);
+check_layout!(WatchNotificationTypeFilter, 44, info_mask: 8, subtype_filter: 12);
+check_layout!(WatchNotificationFilter, 8, __reserved: 4);
+
+// record locks
+
+#[cfg(target_arch = "x86_64")]
+check_layout!(Flock, 32, l_start: 8, l_len: 16, l_pid: 24);
+check_layout!(CompatFlock, 16, l_start: 4, l_len: 8, l_pid: 12);
+check_layout!(CompatFlock64, 24, l_start: 4, l_len: 12, l_pid: 20);
@@ -0,0 +1,78 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Structures shared between the kernel and userspace.
+//!
+//! The layout of these structures is part of the ABI and must match the one expected by the C
+//! library (musl, glibc). Structures used only by system calls are defined in this module, while
+//! structures owned by a subsystem are re-exported from it, so that this module lists all of them.
+//!
+//! The size and field offsets of each structure are checked at compile time for every supported
+//! architecture, in the `layout` submodule.
+//!
+//! When the layout of a structure differs for 32 bit processes running on a 64 bit kernel, its
+//! compatibility version is prefixed with `Compat`. System calls handling both versions are
+//! generic over [`UserRepr`].
+
+pub mod capability;
+pub mod dirent;
+mod layout;
+pub mod sched;
+pub mod seccomp;
+pub mod socket;
+pub mod stat;
+pub mod times;
+pub mod utsname;
+
+pub use crate::{
+	file::{
+		buffer::watch_queue::{
+			DeviceNotification, MountNotification, WatchNotification, WatchNotificationFilter,
+			WatchNotificationTypeFilter, WatchQueueWatch,
+		},
+		epoll::EpollEvent,
+		fs::{Fsid, Statfs},
+		lock::{CompatFlock, CompatFlock64, Flock},
+	},
+	memory::{
+		shm::{CompatShmidDs, ShmidDs},
+		user::IOVec,
+	},
+	net::sockaddr::{In6Addr, SockAddrIn, SockAddrIn6},
+	process::{
+		msg::{CompatMsqidDs, MsqidDs},
+		ns::{CompatIpcPerm, IpcPerm},
+		rlimit::RLimit,
+		rusage::Rusage,
+		sem::{CompatSemidDs, SemBuf, SemidDs},
+		signal::{CompatSigAction, CompatSigStack, SigAction, SigEvent, SigSet, SigStack},
+	},
+	syscall::select::PollFD,
+	time::unit::{ITimerspec, ITimerspec32, Timespec, Timespec32, Timeval, Timeval32, Timezone},
+	tty::{WinSize, termios::Termios},
+};
+use core::fmt::Debug;
+
+/// A userspace representation of the structure `T`.
+///
+/// This is implemented by `T` itself, and by its compatibility version, which converts from and
+/// to `T`. A system call handler generic over this trait reads the structure from userspace,
+/// converts it to `T`, then converts it back before writing it.
+pub trait UserRepr<T>: Debug + From<T> + Into<T> {}
+
+impl<T, U: Debug + From<T> + Into<T>> UserRepr<T> for U {}
@@ -0,0 +1,40 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Seccomp structures.
+
+use core::ffi::c_int;
+
+/// Audit architecture identifier of `x86`.
+pub const AUDIT_ARCH_I386: u32 = 0x40000003;
+/// Audit architecture identifier of `x86_64`.
+pub const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+
+/// The description of a system call, on which seccomp filters run.
+#[repr(C)]
+#[derive(Clone, Debug, Default)]
+pub struct SeccompData {
+	/// The system call number.
+	pub nr: c_int,
+	/// The architecture of the system call convention, as an `AUDIT_ARCH_*` value.
+	pub arch: u32,
+	/// The address of the instruction that made the system call.
+	pub instruction_pointer: u64,
+	/// The arguments of the system call.
+	pub args: [u64; 6],
+}
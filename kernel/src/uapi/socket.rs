@@ -0,0 +1,120 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Socket structures.
+
+use core::ffi::c_int;
+
+/// Message header, used by `recvmsg` and `sendmsg`.
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct MsgHdr {
+	/// Pointer to the socket address.
+	pub msg_name: usize,
+	/// The length of the socket address.
+	pub msg_namelen: u32,
+	/// Pointer to the IO vector.
+	pub msg_iov: usize,
+	/// The number of elements in the IO vector.
+	pub msg_iovlen: usize,
+	/// Pointer to ancillary data.
+	pub msg_control: usize,
+	/// The length of ancillary data.
+	pub msg_controllen: usize,
+	/// Flags on the received message.
+	pub msg_flags: c_int,
+}
+
+impl From<CompatMsgHdr> for MsgHdr {
+	fn from(hdr: CompatMsgHdr) -> Self {
+		Self {
+			msg_name: hdr.msg_name as _,
+			msg_namelen: hdr.msg_namelen,
+			msg_iov: hdr.msg_iov as _,
+			msg_iovlen: hdr.msg_iovlen as _,
+			msg_control: hdr.msg_control as _,
+			msg_controllen: hdr.msg_controllen as _,
+			msg_flags: hdr.msg_flags,
+		}
+	}
+}
+
+/// Compatibility version of [`MsgHdr`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct CompatMsgHdr {
+	pub msg_name: u32,
+	pub msg_namelen: u32,
+	pub msg_iov: u32,
+	pub msg_iovlen: u32,
+	pub msg_control: u32,
+	pub msg_controllen: u32,
+	pub msg_flags: c_int,
+}
+
+impl From<MsgHdr> for CompatMsgHdr {
+	fn from(hdr: MsgHdr) -> Self {
+		Self {
+			msg_name: hdr.msg_name as _,
+			msg_namelen: hdr.msg_namelen,
+			msg_iov: hdr.msg_iov as _,
+			msg_iovlen: hdr.msg_iovlen as _,
+			msg_control: hdr.msg_control as _,
+			msg_controllen: hdr.msg_controllen as _,
+			msg_flags: hdr.msg_flags,
+		}
+	}
+}
+
+/// A filter program, used by `SO_ATTACH_FILTER` and seccomp (`struct sock_fprog`).
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct SockFprog {
+	/// The number of instructions.
+	pub len: u16,
+	/// Pointer to the instructions.
+	pub filter: usize,
+}
+
+impl From<CompatSockFprog> for SockFprog {
+	fn from(prog: CompatSockFprog) -> Self {
+		Self {
+			len: prog.len,
+			filter: prog.filter as _,
+		}
+	}
+}
+
+/// Compatibility version of [`SockFprog`].
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct CompatSockFprog {
+	pub len: u16,
+	pub filter: u32,
+}
+
+impl From<SockFprog> for CompatSockFprog {
+	fn from(prog: SockFprog) -> Self {
+		Self {
+			len: prog.len,
+			filter: prog.filter as _,
+		}
+	}
+}
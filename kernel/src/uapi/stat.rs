@@ -0,0 +1,221 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! File status structures, used by the `stat` family of system calls.
+
+/// Status of a file, 32 bit version.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Stat32 {
+	/// ID of the device containing the file
+	pub st_dev: u32,
+	/// The inode number
+	pub st_ino: u32,
+	/// File mode
+	pub st_mode: u16,
+	/// Link count
+	pub st_link: u16,
+	/// User ID of the file's owner
+	pub st_uid: u16,
+	/// Group ID of the file's group
+	pub st_gid: u16,
+	/// Device ID (if device file)
+	pub st_rdev: u32,
+	/// Size of file, in bytes
+	pub st_size: u32,
+	/// Optimal block size for I/O
+	pub st_blksize: u32,
+	/// Number of 512-byte blocks allocated
+	pub st_blocks: u32,
+	/// Timestamp of last access (seconds)
+	pub st_atime: u32,
+	/// Timestamp of last access (nanoseconds)
+	pub st_atime_nsec: u32,
+	/// Timestamp of last modification of the content (seconds)
+	pub st_mtime: u32,
+	/// Timestamp of last modification of the content (nanoseconds)
+	pub st_mtime_nsec: u32,
+	/// Timestamp of last modification of the metadata (seconds)
+	pub st_ctime: u32,
+	/// Timestamp of last modification of the metadata (nanoseconds)
+	pub st_ctime_nsec: u32,
+	/// Padding
+	pub padding: u64,
+}
+
+/// Status of a file, 64 bit version.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Stat64 {
+	/// ID of the device containing the file
+	pub st_dev: u64,
+	/// The inode number
+	pub st_ino: u64,
+	/// Number of hard links to the file
+	pub st_nlink: u64,
+	/// File mode
+	pub st_mode: u32,
+	/// User ID of the file's owner
+	pub st_uid: u32,
+	/// Group ID of the file's group
+	pub st_gid: u32,
+	/// Padding
+	pub pad0: u32,
+	/// Device ID (if device file)
+	pub st_rdev: u64,
+	/// Size of file, in bytes
+	pub st_size: i64,
+	/// Optimal block size for I/O
+	pub st_blksize: i64,
+	/// Number of 512-byte block allocated
+	pub st_blocks: i64,
+	/// Timestamp of last access (seconds)
+	pub st_atime: u64,
+	/// Timestamp of last access (nanoseconds)
+	pub st_atime_nsec: u64,
+	/// Timestamp of last modification of the content (seconds)
+	pub st_mtime: u64,
+	/// Timestamp of last modification of the content (nanoseconds)
+	pub st_mtime_nsec: u64,
+	/// Timestamp of last modification of the metadata (seconds)
+	pub st_ctime: u64,
+	/// Timestamp of last modification of the metadata (nanoseconds)
+	pub st_ctime_nsec: u64,
+	/// Reserved
+	pub __unused: [i64; 3],
+}
+
+/// Status of a file, 64 bit version for 32 bit userspace (`stat64`).
+///
+/// Contrary to [`Stat64`], 64 bit fields are only aligned on 4 bytes.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed(4))]
+pub struct CompatStat64 {
+	/// ID of the device containing the file
+	pub st_dev: u64,
+	/// Padding
+	pub pad0: u32,
+	/// The inode number, truncated to 32 bits
+	pub __st_ino: u32,
+	/// File mode
+	pub st_mode: u32,
+	/// Number of hard links to the file
+	pub st_nlink: u32,
+	/// User ID of the file's owner
+	pub st_uid: u32,
+	/// Group ID of the file's group
+	pub st_gid: u32,
+	/// Device ID (if device file)
+	pub st_rdev: u64,
+	/// Padding
+	pub pad3: u32,
+	/// Size of file, in bytes
+	pub st_size: i64,
+	/// Optimal block size for I/O
+	pub st_blksize: u32,
+	/// Number of 512-byte block allocated
+	pub st_blocks: u64,
+	/// Timestamp of last access (seconds)
+	pub st_atime: u32,
+	/// Timestamp of last access (nanoseconds)
+	pub st_atime_nsec: u32,
+	/// Timestamp of last modification of the content (seconds)
+	pub st_mtime: u32,
+	/// Timestamp of last modification of the content (nanoseconds)
+	pub st_mtime_nsec: u32,
+	/// Timestamp of last modification of the metadata (seconds)
+	pub st_ctime: u32,
+	/// Timestamp of last modification of the metadata (nanoseconds)
+	pub st_ctime_nsec: u32,
+	/// The inode number
+	pub st_ino: u64,
+}
+
+/// A timestamp for the `statx` system call.
+#[derive(Debug)]
+#[repr(C)]
+pub struct StatxTimestamp {
+	/// Seconds since the Epoch (UNIX time)
+	pub tv_sec: i64,
+	/// Nanoseconds since tv_sec
+	pub tv_nsec: u32,
+	/// Reserved field.
+	pub __reserved: i32,
+}
+
+/// Status of a file, extended.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Statx {
+	/// Mask of bits indicating filled fields
+	pub stx_mask: u32,
+	/// Block size for filesystem I/O
+	pub stx_blksize: u32,
+	/// Extra file attribute indicators
+	pub stx_attributes: u64,
+	/// Number of hard links
+	pub stx_nlink: u32,
+	/// User ID of owner
+	pub stx_uid: u32,
+	/// Group ID of owner
+	pub stx_gid: u32,
+	/// File type and mode
+	pub stx_mode: u16,
+	/// Padding.
+	pub __padding0: u16,
+	/// Inode number
+	pub stx_ino: u64,
+	/// Total size in bytes
+	pub stx_size: u64,
+	/// Number of 512B blocks allocated
+	pub stx_blocks: u64,
+	/// Mask to show what's supported in stx_attributes
+	pub stx_attributes_mask: u64,
+	/// Last access
+	pub stx_atime: StatxTimestamp,
+	/// Creation
+	pub stx_btime: StatxTimestamp,
+	/// Last status change
+	pub stx_ctime: StatxTimestamp,
+	/// Last modification
+	pub stx_mtime: StatxTimestamp,
+	/// Major ID (if the file is a device)
+	pub stx_rdev_major: u32,
+	/// Minor ID (if the file is a device)
+	pub stx_rdev_minor: u32,
+	/// Major ID of the device containing the filesystem where the file resides
+	pub stx_dev_major: u32,
+	/// Minor ID of the device containing the filesystem where the file resides
+	pub stx_dev_minor: u32,
+	/// Mount ID.
+	pub stx_mnt_id: u64,
+	/// Memory buffer alignment for direct I/O
+	pub stx_dio_mem_align: u32,
+	/// File offset alignment for direct I/O
+	pub stx_dio_offset_align: u32,
+	/// Subvolume identifier
+	pub stx_subvol: u64,
+	/// Min atomic write unit in bytes
+	pub stx_atomic_write_unit_min: u32,
+	/// Max atomic write unit in bytes
+	pub stx_atomic_write_unit_max: u32,
+	/// Max atomic write segment count
+	pub stx_atomic_write_segments_max: u32,
+	/// Padding
+	pub __padding1: [u32; 19],
+}
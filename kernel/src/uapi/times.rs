@@ -0,0 +1,35 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Process times structure, used by the `times` system call.
+
+use core::ffi::c_long;
+
+/// Process times, in clock ticks.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Tms {
+	/// User CPU time.
+	pub tms_utime: c_long,
+	/// System CPU time.
+	pub tms_stime: c_long,
+	/// User CPU time of terminated children.
+	pub tms_cutime: c_long,
+	/// System CPU time of terminated children.
+	pub tms_cstime: c_long,
+}
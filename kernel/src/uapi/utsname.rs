@@ -0,0 +1,40 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! System identification structure, used by the `uname` system call.
+
+/// The length of a field of the utsname structure.
+pub const UTSNAME_LENGTH: usize = 65;
+
+/// Userspace structure storing uname information.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Utsname {
+	/// Operating system name.
+	pub sysname: [u8; UTSNAME_LENGTH],
+	/// Network node hostname.
+	pub nodename: [u8; UTSNAME_LENGTH],
+	/// Operating system release.
+	pub release: [u8; UTSNAME_LENGTH],
+	/// Operating system version.
+	pub version: [u8; UTSNAME_LENGTH],
+	/// Hardware identifier.
+	pub machine: [u8; UTSNAME_LENGTH],
+	/// NIS domain name.
+	pub domainname: [u8; UTSNAME_LENGTH],
+}
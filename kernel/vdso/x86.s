@@ -18,10 +18,19 @@
 
 .section .text
 
+# Offsets in the data page shared with the kernel. See `VdsoClocks` in `time::clock`
+.set VDSO_SEQ, 0
+.set VDSO_REALTIME, 8
+.set VDSO_MONOTONIC, 16
+.set VDSO_BOOTTIME, 24
+
+.hidden __vdso_data
+
 .global __kernel_vsyscall
 .global __kernel_rt_sigreturn
 .global __kernel_sigreturn
 .global __vdso_clock_gettime
+.global __vdso_clock_gettime64
 .global __vdso_gettimeofday
 .global __vdso_time
 
@@ -30,21 +39,137 @@ __kernel_vsyscall:
 	ret
 
 __kernel_rt_sigreturn:
-	# TODO
-	ud2
+	movl $0xad, %eax
+	int $0x80
 
+# Pops the signal number, leaving the stack pointer on the signal context
 __kernel_sigreturn:
-	# TODO
-	ud2
+	popl %eax
+	movl $0x77, %eax
+	int $0x80
+
+# Reads the clock at offset %ecx in the data page and returns its value in nanoseconds in
+# %edx:%eax. Retries while the kernel is updating the values.
+read_clock:
+	pushl %esi
+	pushl %edi
+	call 1f
+1:
+	popl %esi
+	addl $(__vdso_data - 1b), %esi
+2:
+	movl VDSO_SEQ(%esi), %edi
+	testl $1, %edi
+	jnz 3f
+	movl (%esi, %ecx), %eax
+	movl 4(%esi, %ecx), %edx
+	cmpl VDSO_SEQ(%esi), %edi
+	jne 3f
+	popl %edi
+	popl %esi
+	ret
+3:
+	pause
+	jmp 2b
+
+# Returns in %ecx the offset of the clock with ID %eax, or -1 if it must be read by the kernel
+clock_offset:
+	movl $VDSO_REALTIME, %ecx
+	cmpl $0, %eax
+	je 1f
+	movl $VDSO_MONOTONIC, %ecx
+	cmpl $1, %eax
+	je 1f
+	movl $VDSO_BOOTTIME, %ecx
+	cmpl $7, %eax
+	je 1f
+	movl $-1, %ecx
+1:
+	ret
+
+# Reads the clock at offset %ecx and returns the seconds in %eax and the nanoseconds in %edx.
+# The number of seconds must fit in 32 bits
+read_timespec:
+	call read_clock
+	movl $1000000000, %ecx
+	divl %ecx
+	ret
 
 __vdso_clock_gettime:
-	# TODO
-	ud2
+	movl 4(%esp), %eax
+	call clock_offset
+	cmpl $-1, %ecx
+	jne 1f
+	# Other clocks are handled by the kernel
+	pushl %ebx
+	movl 8(%esp), %ebx
+	movl 12(%esp), %ecx
+	movl $0x109, %eax
+	int $0x80
+	popl %ebx
+	ret
+1:
+	call read_timespec
+	movl 8(%esp), %ecx
+	movl %eax, (%ecx)
+	movl %edx, 4(%ecx)
+	xorl %eax, %eax
+	ret
+
+__vdso_clock_gettime64:
+	movl 4(%esp), %eax
+	call clock_offset
+	cmpl $-1, %ecx
+	jne 1f
+	# Other clocks are handled by the kernel
+	pushl %ebx
+	movl 8(%esp), %ebx
+	movl 12(%esp), %ecx
+	movl $0x193, %eax
+	int $0x80
+	popl %ebx
+	ret
+1:
+	call read_timespec
+	movl 8(%esp), %ecx
+	movl %eax, (%ecx)
+	movl $0, 4(%ecx)
+	movl %edx, 8(%ecx)
+	movl $0, 12(%ecx)
+	xorl %eax, %eax
+	ret
 
 __vdso_gettimeofday:
-	# TODO
-	ud2
+	movl 4(%esp), %eax
+	testl %eax, %eax
+	jz 1f
+	movl $VDSO_REALTIME, %ecx
+	call read_timespec
+	movl 4(%esp), %ecx
+	movl %eax, (%ecx)
+	movl %edx, %eax
+	xorl %edx, %edx
+	movl $1000, %ecx
+	divl %ecx
+	movl 4(%esp), %ecx
+	movl %eax, 4(%ecx)
+1:
+	# Timezones are obsolete
+	movl 8(%esp), %ecx
+	testl %ecx, %ecx
+	jz 2f
+	movl $0, (%ecx)
+	movl $0, 4(%ecx)
+2:
+	xorl %eax, %eax
+	ret
 
 __vdso_time:
-	# TODO
-	ud2
+	movl $VDSO_MONOTONIC, %ecx
+	call read_timespec
+	movl 4(%esp), %ecx
+	testl %ecx, %ecx
+	jz 1f
+	movl %eax, (%ecx)
+1:
+	ret
@@ -18,23 +18,99 @@
 
 .section .text
 
+# Offsets in the data page shared with the kernel. See `VdsoClocks` in `time::clock`
+.set VDSO_SEQ, 0
+.set VDSO_REALTIME, 8
+.set VDSO_MONOTONIC, 16
+.set VDSO_BOOTTIME, 24
+
+.hidden __vdso_data
+
+.global __kernel_rt_sigreturn
 .global __vdso_clock_gettime
 .global __vdso_getcpu
 .global __vdso_gettimeofday
 .global __vdso_time
 
+__kernel_rt_sigreturn:
+	movq $0xf, %rax
+	syscall
+
+# Reads the clock at offset %rcx in the data page and returns its value in nanoseconds in %rax.
+# Retries while the kernel is updating the values.
+# Clobbers %rdx and %r8
+read_clock:
+	leaq __vdso_data(%rip), %r8
+1:
+	movl VDSO_SEQ(%r8), %edx
+	testl $1, %edx
+	jnz 2f
+	movq (%r8, %rcx), %rax
+	cmpl VDSO_SEQ(%r8), %edx
+	jne 2f
+	ret
+2:
+	pause
+	jmp 1b
+
 __vdso_clock_gettime:
-	# TODO
-	ud2
+	movq $VDSO_REALTIME, %rcx
+	cmpl $0, %edi
+	je 1f
+	movq $VDSO_MONOTONIC, %rcx
+	cmpl $1, %edi
+	je 1f
+	movq $VDSO_BOOTTIME, %rcx
+	cmpl $7, %edi
+	je 1f
+	# Other clocks are handled by the kernel
+	movq $0xe4, %rax
+	syscall
+	ret
+1:
+	call read_clock
+	movq $1000000000, %rcx
+	xorq %rdx, %rdx
+	divq %rcx
+	movq %rax, (%rsi)
+	movq %rdx, 8(%rsi)
+	xorq %rax, %rax
+	ret
 
 __vdso_getcpu:
     # TODO
     ud2
 
 __vdso_gettimeofday:
-	# TODO
-	ud2
+	testq %rdi, %rdi
+	jz 1f
+	movq $VDSO_REALTIME, %rcx
+	call read_clock
+	movq $1000, %rcx
+	xorq %rdx, %rdx
+	divq %rcx
+	movq $1000000, %rcx
+	xorq %rdx, %rdx
+	divq %rcx
+	movq %rax, (%rdi)
+	movq %rdx, 8(%rdi)
+1:
+	# Timezones are obsolete
+	testq %rsi, %rsi
+	jz 2f
+	movq $0, (%rsi)
+2:
+	xorq %rax, %rax
+	ret
 
 __vdso_time:
-	# TODO
-	ud2
+	movq $VDSO_MONOTONIC, %rcx
+	call read_clock
+	movq $1000000000, %rcx
+	xorq %rdx, %rdx
+	divq %rcx
+	testq %rdi, %rdi
+	jz 1f
+	movq %rax, (%rdi)
+1:
+	ret
@@ -18,14 +18,23 @@
 
 //! This module stores the Bitfield structure.
 
-use crate::{TryClone, bit_size_of, collections::vec::Vec, errno::AllocResult};
+use crate::{TryClone, collections::vec::Vec, errno::AllocResult};
+
+/// The number of bits in a unit of the bitfield.
+const UNIT_BITS: usize = usize::BITS as usize;
 
 /// A bitfield is a data structure meant to contain only boolean values.
 ///
-/// The size of the bitfield is specified at initialization.
+/// The size of the bitfield is specified at initialization, and can be changed with
+/// [`Bitfield::resize`].
+///
+/// Bits are stored in units of [`usize`], so that searching for a set or clear bit scans a whole
+/// unit at once.
 pub struct Bitfield {
 	/// The bitfield's data.
-	data: Vec<u8>,
+	///
+	/// Bits past the end of the bitfield, in the last unit, are always clear.
+	data: Vec<usize>,
 	/// The number of bits in the bitfield.
 	len: usize,
 }
@@ -33,7 +42,7 @@ pub struct Bitfield {
 impl Bitfield {
 	/// Creates a new bitfield with the given number of bits `len`.
 	pub fn new(len: usize) -> AllocResult<Self> {
-		let size = len.div_ceil(bit_size_of::<u8>());
+		let size = len.div_ceil(UNIT_BITS);
 		let bitfield = Self {
 			data: crate::vec![0; size]?,
 			len,
@@ -48,59 +57,95 @@ impl Bitfield {
 		self.len
 	}
 
-	/// Returns an immutable reference to a slice containing the bitfield.
+	/// Returns the size of the memory region of the bitfield in bytes.
 	#[inline]
-	pub fn as_slice(&self) -> &[u8] {
-		self.data.as_slice()
+	pub fn mem_size(&self) -> usize {
+		self.data.len() * size_of::<usize>()
 	}
 
-	/// Returns a mutable reference to a slice containing the bitfield.
-	#[inline]
-	pub fn as_slice_mut(&mut self) -> &mut [u8] {
-		self.data.as_mut_slice()
+	/// Changes the number of bits in the bitfield to `len`.
+	///
+	/// New bits are clear.
+	pub fn resize(&mut self, len: usize) -> AllocResult<()> {
+		self.data.resize(len.div_ceil(UNIT_BITS), 0)?;
+		self.len = len;
+		self.clear_padding();
+		Ok(())
 	}
 
-	/// Returns the size of the memory region of the bitfield in bytes.
-	#[inline]
-	pub fn mem_size(&self) -> usize {
-		self.len.div_ceil(bit_size_of::<u8>())
+	/// Clears the bits past the end of the bitfield, in the last unit.
+	fn clear_padding(&mut self) {
+		let rem = self.len % UNIT_BITS;
+		if rem != 0 {
+			if let Some(last) = self.data.last_mut() {
+				*last &= (1 << rem) - 1;
+			}
+		}
 	}
 
 	/// Tells whether bit `index` is set.
 	#[inline]
 	pub fn is_set(&self, index: usize) -> bool {
-		let unit = self.data[index / u8::BITS as usize];
-		(unit >> (index % u8::BITS as usize)) & 1 == 1
+		let unit = self.data[index / UNIT_BITS];
+		(unit >> (index % UNIT_BITS)) & 1 == 1
 	}
 
 	/// Sets bit `index`.
 	pub fn set(&mut self, index: usize) {
 		debug_assert!(index < self.len);
-
-		if !self.is_set(index) {
-			let unit = &mut self.data[index / u8::BITS as usize];
-			*unit |= 1 << (index % u8::BITS as usize);
-		}
+		self.data[index / UNIT_BITS] |= 1 << (index % UNIT_BITS);
 	}
 
 	/// Clears bit `index`.
 	pub fn clear(&mut self, index: usize) {
 		debug_assert!(index < self.len);
+		self.data[index / UNIT_BITS] &= !(1 << (index % UNIT_BITS));
+	}
 
-		if self.is_set(index) {
-			let unit = &mut self.data[index / u8::BITS as usize];
-			*unit &= !(1 << (index % u8::BITS as usize));
+	/// Returns the index of the first bit at or after `start` for which the unit, transformed
+	/// by `f`, has a bit set.
+	fn find_from<F: Fn(usize) -> usize>(&self, start: usize, f: F) -> Option<usize> {
+		if start >= self.len {
+			return None;
+		}
+		let mut i = start / UNIT_BITS;
+		// Ignore bits before `start` in the first unit
+		let mut unit = f(self.data[i]) & (!0 << (start % UNIT_BITS));
+		loop {
+			if unit != 0 {
+				let index = i * UNIT_BITS + unit.trailing_zeros() as usize;
+				return (index < self.len).then_some(index);
+			}
+			i += 1;
+			unit = f(*self.data.get(i)?);
 		}
 	}
 
+	/// Finds a set bit at or after the index `start`.
+	///
+	/// The function returns the offset to the lowest matching bit.
+	///
+	/// If none is found, the function returns `None`.
+	pub fn find_set_from(&self, start: usize) -> Option<usize> {
+		self.find_from(start, |unit| unit)
+	}
+
+	/// Finds a clear bit at or after the index `start`.
+	///
+	/// The function returns the offset to the lowest matching bit.
+	///
+	/// If none is found, the function returns `None`.
+	pub fn find_clear_from(&self, start: usize) -> Option<usize> {
+		self.find_from(start, |unit| !unit)
+	}
+
 	/// Finds a set bit.
 	///
 	/// The function returns the offset to the bit.
 	///
 	/// If none is found, the function returns `None`.
 	pub fn find_set(&self) -> Option<usize> {
-		// TODO optimize (using mask)
-		(0..self.len).find(|i| self.is_set(*i))
+		self.find_set_from(0)
 	}
 
 	/// Finds a clear bit.
@@ -109,8 +154,19 @@ impl Bitfield {
 	///
 	/// If none is found, the function returns `None`.
 	pub fn find_clear(&self) -> Option<usize> {
-		// TODO optimize (using mask)
-		(0..self.len).find(|i| !self.is_set(*i))
+		self.find_clear_from(0)
+	}
+
+	/// Returns the offset to the highest set bit.
+	///
+	/// If no bit is set, the function returns `None`.
+	pub fn last_set(&self) -> Option<usize> {
+		let (i, unit) = self
+			.data
+			.iter()
+			.enumerate()
+			.rfind(|(_, unit)| **unit != 0)?;
+		Some(i * UNIT_BITS + (UNIT_BITS - 1 - unit.leading_zeros() as usize))
 	}
 
 	/// Clears every elements in the bitfield.
@@ -118,9 +174,10 @@ impl Bitfield {
 		self.data.fill(0);
 	}
 
-	/// Clears every elements in the bitfield.
+	/// Sets every elements in the bitfield.
 	pub fn set_all(&mut self) {
 		self.data.fill(!0);
+		self.clear_padding();
 	}
 
 	/// Returns an immutable iterator over the bitfield.
@@ -130,6 +187,14 @@ impl Bitfield {
 			cursor: 0,
 		}
 	}
+
+	/// Returns an iterator over the offsets of set bits, in ascending order.
+	pub fn iter_set(&self) -> SetBitsIterator<'_> {
+		SetBitsIterator {
+			bitfield: self,
+			cursor: 0,
+		}
+	}
 }
 
 impl TryClone for Bitfield {
@@ -164,6 +229,24 @@ impl Iterator for BitfieldIterator<'_> {
 	}
 }
 
+/// An iterator over the offsets of set bits in a bitfield.
+pub struct SetBitsIterator<'b> {
+	/// The bitfield.
+	bitfield: &'b Bitfield,
+	/// The offset from which the next set bit is searched.
+	cursor: usize,
+}
+
+impl Iterator for SetBitsIterator<'_> {
+	type Item = usize;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let index = self.bitfield.find_set_from(self.cursor)?;
+		self.cursor = index + 1;
+		Some(index)
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -208,5 +291,40 @@ mod test {
 		}
 	}
 
-	// TODO Write more tests
+	#[test]
+	fn bitfield_find0() {
+		let mut bitfield = Bitfield::new(200).unwrap();
+		assert_eq!(bitfield.find_set(), None);
+		assert_eq!(bitfield.find_clear(), Some(0));
+		assert_eq!(bitfield.last_set(), None);
+		bitfield.set(3);
+		bitfield.set(64);
+		bitfield.set(130);
+		assert_eq!(bitfield.find_set(), Some(3));
+		assert_eq!(bitfield.find_set_from(4), Some(64));
+		assert_eq!(bitfield.find_set_from(65), Some(130));
+		assert_eq!(bitfield.find_set_from(131), None);
+		assert_eq!(bitfield.last_set(), Some(130));
+		assert!(bitfield.iter_set().eq([3, 64, 130]));
+		bitfield.set_all();
+		assert_eq!(bitfield.find_clear(), None);
+		bitfield.clear(199);
+		assert_eq!(bitfield.find_clear_from(100), Some(199));
+		assert_eq!(bitfield.last_set(), Some(198));
+	}
+
+	#[test]
+	fn bitfield_resize0() {
+		let mut bitfield = Bitfield::new(10).unwrap();
+		bitfield.set_all();
+		assert_eq!(bitfield.find_clear(), None);
+		bitfield.resize(100).unwrap();
+		assert_eq!(bitfield.len(), 100);
+		assert_eq!(bitfield.find_clear(), Some(10));
+		assert_eq!(bitfield.last_set(), Some(9));
+		bitfield.resize(5).unwrap();
+		assert_eq!(bitfield.last_set(), Some(4));
+		bitfield.resize(70).unwrap();
+		assert_eq!(bitfield.find_set_from(5), None);
+	}
 }
@@ -0,0 +1,866 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Implementation of the [`IntervalTree`] object.
+//!
+//! An interval tree stores values associated with half-open ranges, and allows to find the
+//! entries overlapping a given range in logarithmic time.
+//!
+//! The tree is an AVL tree sorted by the beginning of the ranges, in which each node is
+//! augmented with the greatest end of the ranges in its subtree. This allows to skip subtrees
+//! which cannot contain an overlapping range.
+
+use crate::{
+	__alloc, __dealloc, AllocError, TryClone,
+	errno::{AllocResult, CollectResult},
+};
+use core::{
+	alloc::Layout,
+	cmp::max,
+	fmt,
+	iter::FusedIterator,
+	marker::PhantomData,
+	mem,
+	ops::{Bound, Range, RangeBounds},
+	ptr::NonNull,
+};
+
+/// A node in the tree.
+struct Node<K, V> {
+	/// Pointer to the parent node
+	parent: Option<NonNull<Self>>,
+	/// Pointer to the left child
+	left: Option<NonNull<Self>>,
+	/// Pointer to the right child
+	right: Option<NonNull<Self>>,
+	/// The height of the subtree whose root is this node
+	height: u8,
+	/// The greatest end of the ranges in the subtree whose root is this node
+	max_end: K,
+
+	/// The node's range.
+	range: Range<K>,
+	/// The node's value.
+	value: V,
+}
+
+/// Returns the height of the subtree whose root is `node`.
+#[inline]
+fn height<K, V>(node: Option<NonNull<Node<K, V>>>) -> u8 {
+	node.map(|n| unsafe { n.as_ref().height }).unwrap_or(0)
+}
+
+/// Drops the node at the given pointer, returning its range and value.
+///
+/// # Safety
+///
+/// The caller must ensure the pointer points to a valid node, unlinked from the tree.
+unsafe fn drop_node<K, V>(ptr: NonNull<Node<K, V>>) -> (Range<K>, V) {
+	let node = ptr.read();
+	__dealloc(ptr.cast(), Layout::new::<Node<K, V>>());
+	(node.range, node.value)
+}
+
+/// Tells whether a range ending at `end` is after the lower bound `lo`.
+#[inline]
+fn after_lo<K: Ord>(end: &K, lo: Bound<&K>) -> bool {
+	match lo {
+		Bound::Included(lo) | Bound::Excluded(lo) => end > lo,
+		Bound::Unbounded => true,
+	}
+}
+
+/// Tells whether a range beginning at `start` is before the upper bound `hi`.
+#[inline]
+fn before_hi<K: Ord>(start: &K, hi: Bound<&K>) -> bool {
+	match hi {
+		Bound::Included(hi) => start <= hi,
+		Bound::Excluded(hi) => start < hi,
+		Bound::Unbounded => true,
+	}
+}
+
+/// Returns the first node, in order, of the subtree whose root is `node`, which ends after `lo`.
+///
+/// # Safety
+///
+/// The caller must ensure the pointer points to a valid node.
+unsafe fn first_after<K: Ord, V>(
+	mut node: NonNull<Node<K, V>>,
+	lo: Bound<&K>,
+) -> Option<NonNull<Node<K, V>>> {
+	loop {
+		let n = node.as_ref();
+		if !after_lo(&n.max_end, lo) {
+			return None;
+		}
+		if let Some(left) = n.left
+			&& after_lo(&left.as_ref().max_end, lo)
+		{
+			node = left;
+			continue;
+		}
+		if after_lo(&n.range.end, lo) {
+			return Some(node);
+		}
+		// Since the subtree contains a range ending after `lo`, it must be on the right
+		node = n.right?;
+	}
+}
+
+/// Returns the node following `node`, in order, which ends after `lo`.
+///
+/// # Safety
+///
+/// The caller must ensure the pointer points to a valid node.
+unsafe fn next_after<K: Ord, V>(
+	mut node: NonNull<Node<K, V>>,
+	lo: Bound<&K>,
+) -> Option<NonNull<Node<K, V>>> {
+	if let Some(next) = node.as_ref().right.and_then(|r| first_after(r, lo)) {
+		return Some(next);
+	}
+	// Go up until coming from a left child
+	loop {
+		let parent = node.as_ref().parent?;
+		let p = parent.as_ref();
+		if p.left == Some(node) {
+			if after_lo(&p.range.end, lo) {
+				return Some(parent);
+			}
+			if let Some(next) = p.right.and_then(|r| first_after(r, lo)) {
+				return Some(next);
+			}
+		}
+		node = parent;
+	}
+}
+
+/// A set of entries associated with half-open ranges, allowing to look for overlapping entries.
+///
+/// Several entries may begin at the same position, or overlap. Entries are sorted by the
+/// beginning of their ranges.
+pub struct IntervalTree<K: Ord + Copy, V> {
+	/// The root node of the tree.
+	root: Option<NonNull<Node<K, V>>>,
+	/// The number of entries in the tree.
+	len: usize,
+}
+
+unsafe impl<K: Ord + Copy + Send, V: Send> Send for IntervalTree<K, V> {}
+
+unsafe impl<K: Ord + Copy + Sync, V: Sync> Sync for IntervalTree<K, V> {}
+
+impl<K: Ord + Copy, V> Default for IntervalTree<K, V> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Ord + Copy, V> IntervalTree<K, V> {
+	/// Creates a new empty tree.
+	pub const fn new() -> Self {
+		Self {
+			root: None,
+			len: 0,
+		}
+	}
+
+	/// Returns the number of entries in the tree.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Tells whether the tree is empty.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Updates the height and greatest end of `node`, from its children.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointer points to a valid node.
+	unsafe fn update(mut node: NonNull<Node<K, V>>) {
+		let n = node.as_mut();
+		n.height = max(height(n.left), height(n.right)) + 1;
+		n.max_end = n.range.end;
+		for child in [n.left, n.right].into_iter().flatten() {
+			n.max_end = max(n.max_end, child.as_ref().max_end);
+		}
+	}
+
+	/// Replaces the link from the parent of `old` (or the root) to `old` with a link to `new`.
+	///
+	/// The parent of `new` is not updated.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointers point to valid nodes.
+	unsafe fn replace_link(
+		&mut self,
+		parent: Option<NonNull<Node<K, V>>>,
+		old: NonNull<Node<K, V>>,
+		new: Option<NonNull<Node<K, V>>>,
+	) {
+		match parent {
+			Some(mut p) => {
+				let p = p.as_mut();
+				if p.left == Some(old) {
+					p.left = new;
+				} else {
+					p.right = new;
+				}
+			}
+			None => self.root = new,
+		}
+	}
+
+	/// Rotates the subtree whose root is `node` to the left, returning the new root of the
+	/// subtree.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointer points to a valid node with a right child.
+	unsafe fn rotate_left(&mut self, mut node: NonNull<Node<K, V>>) -> NonNull<Node<K, V>> {
+		let mut pivot = node.as_ref().right.unwrap();
+		let parent = node.as_ref().parent;
+		node.as_mut().right = pivot.as_ref().left;
+		if let Some(mut l) = pivot.as_ref().left {
+			l.as_mut().parent = Some(node);
+		}
+		self.replace_link(parent, node, Some(pivot));
+		pivot.as_mut().parent = parent;
+		pivot.as_mut().left = Some(node);
+		node.as_mut().parent = Some(pivot);
+		Self::update(node);
+		Self::update(pivot);
+		pivot
+	}
+
+	/// Rotates the subtree whose root is `node` to the right, returning the new root of the
+	/// subtree.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointer points to a valid node with a left child.
+	unsafe fn rotate_right(&mut self, mut node: NonNull<Node<K, V>>) -> NonNull<Node<K, V>> {
+		let mut pivot = node.as_ref().left.unwrap();
+		let parent = node.as_ref().parent;
+		node.as_mut().left = pivot.as_ref().right;
+		if let Some(mut r) = pivot.as_ref().right {
+			r.as_mut().parent = Some(node);
+		}
+		self.replace_link(parent, node, Some(pivot));
+		pivot.as_mut().parent = parent;
+		pivot.as_mut().right = Some(node);
+		node.as_mut().parent = Some(pivot);
+		Self::update(node);
+		Self::update(pivot);
+		pivot
+	}
+
+	/// Updates the nodes from `node` up to the root, rebalancing the tree on the way.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointer points to a valid node.
+	unsafe fn fix_up(&mut self, mut node: Option<NonNull<Node<K, V>>>) {
+		while let Some(mut n) = node {
+			let (left, right) = (n.as_ref().left, n.as_ref().right);
+			let balance = height(left) as i16 - height(right) as i16;
+			if balance > 1 {
+				let l = left.unwrap().as_ref();
+				if height(l.left) < height(l.right) {
+					self.rotate_left(left.unwrap());
+				}
+				n = self.rotate_right(n);
+			} else if balance < -1 {
+				let r = right.unwrap().as_ref();
+				if height(r.right) < height(r.left) {
+					self.rotate_right(right.unwrap());
+				}
+				n = self.rotate_left(n);
+			} else {
+				Self::update(n);
+			}
+			node = n.as_ref().parent;
+		}
+	}
+
+	/// Returns the first node, in order, beginning at `start` or after.
+	fn lower_bound(&self, start: &K) -> Option<NonNull<Node<K, V>>> {
+		let mut cur = self.root;
+		let mut found = None;
+		while let Some(node) = cur {
+			let n = unsafe { node.as_ref() };
+			if n.range.start >= *start {
+				found = Some(node);
+				cur = n.left;
+			} else {
+				cur = n.right;
+			}
+		}
+		found
+	}
+
+	/// Returns the first node, in order, beginning at `start` whose entry matches `pred`.
+	fn find<F: FnMut(&Range<K>, &V) -> bool>(
+		&self,
+		start: &K,
+		mut pred: F,
+	) -> Option<NonNull<Node<K, V>>> {
+		let mut cur = self.lower_bound(start);
+		while let Some(node) = cur {
+			let n = unsafe { node.as_ref() };
+			if n.range.start != *start {
+				break;
+			}
+			if pred(&n.range, &n.value) {
+				return Some(node);
+			}
+			cur = unsafe { next_after(node, Bound::Unbounded) };
+		}
+		None
+	}
+
+	/// Returns a reference to the value of the first entry beginning at `start`.
+	pub fn get(&self, start: &K) -> Option<&V> {
+		self.find(start, |_, _| true)
+			.map(|n| unsafe { &n.as_ref().value })
+	}
+
+	/// Returns the first entry whose range contains `point`.
+	pub fn get_containing(&self, point: K) -> Option<(&Range<K>, &V)> {
+		self.overlapping(point..=point).next()
+	}
+
+	/// Returns the first entry whose range contains `point`, with a mutable reference to its
+	/// value.
+	pub fn get_containing_mut(&mut self, point: K) -> Option<(&Range<K>, &mut V)> {
+		self.overlapping_mut(point..=point).next()
+	}
+
+	/// Inserts an entry for `range`, with the value `value`.
+	///
+	/// Other entries beginning at the same position are kept.
+	pub fn insert(&mut self, range: Range<K>, value: V) -> AllocResult<()> {
+		let node = Node {
+			parent: None,
+			left: None,
+			right: None,
+			height: 1,
+			max_end: range.end,
+
+			range,
+			value,
+		};
+		let mut ptr = unsafe {
+			let ptr = __alloc(Layout::new::<Node<K, V>>())?.cast::<Node<K, V>>();
+			ptr.write(node);
+			ptr
+		};
+		// Look for the parent
+		let start = unsafe { ptr.as_ref().range.start };
+		let mut parent = None;
+		let mut cur = self.root;
+		while let Some(node) = cur {
+			parent = Some(node);
+			let n = unsafe { node.as_ref() };
+			cur = if start < n.range.start {
+				n.left
+			} else {
+				n.right
+			};
+		}
+		unsafe {
+			ptr.as_mut().parent = parent;
+			match parent {
+				Some(mut p) => {
+					let p = p.as_mut();
+					if start < p.range.start {
+						p.left = Some(ptr);
+					} else {
+						p.right = Some(ptr);
+					}
+				}
+				None => self.root = Some(ptr),
+			}
+			self.fix_up(parent);
+		}
+		self.len += 1;
+		Ok(())
+	}
+
+	/// Same as [`Self::insert`], except the first entry beginning at the same position, if any,
+	/// is replaced and returned.
+	///
+	/// Replacing an entry does not allocate memory.
+	pub fn replace(&mut self, range: Range<K>, value: V) -> AllocResult<Option<(Range<K>, V)>> {
+		let Some(mut node) = self.find(&range.start, |_, _| true) else {
+			self.insert(range, value)?;
+			return Ok(None);
+		};
+		let old = unsafe {
+			let n = node.as_mut();
+			let old = (
+				mem::replace(&mut n.range, range),
+				mem::replace(&mut n.value, value),
+			);
+			// The order of nodes is unchanged, but the greatest ends must be updated
+			self.fix_up(Some(node));
+			old
+		};
+		Ok(Some(old))
+	}
+
+	/// Unlinks and frees `node`, returning its entry.
+	///
+	/// # Safety
+	///
+	/// The caller must ensure the pointer points to a valid node of the tree.
+	unsafe fn remove_node(&mut self, mut node: NonNull<Node<K, V>>) -> (Range<K>, V) {
+		// If the node has two children, exchange its entry with its successor's, which has no
+		// left child, so that the order of entries remains the same
+		if let (Some(_), Some(right)) = (node.as_ref().left, node.as_ref().right) {
+			let mut succ = right;
+			while let Some(l) = succ.as_ref().left {
+				succ = l;
+			}
+			let (n, s) = (node.as_mut(), succ.as_mut());
+			mem::swap(&mut n.range, &mut s.range);
+			mem::swap(&mut n.value, &mut s.value);
+			node = succ;
+		}
+		let n = node.as_ref();
+		let parent = n.parent;
+		let child = n.left.or(n.right);
+		self.replace_link(parent, node, child);
+		if let Some(mut c) = child {
+			c.as_mut().parent = parent;
+		}
+		self.fix_up(parent);
+		self.len -= 1;
+		drop_node(node)
+	}
+
+	/// Removes the first entry beginning at `start`, and returns it.
+	pub fn remove(&mut self, start: &K) -> Option<(Range<K>, V)> {
+		self.remove_if(start, |_, _| true)
+	}
+
+	/// Removes the first entry beginning at `start` for which `pred` returns `true`, and returns
+	/// it.
+	pub fn remove_if<F: FnMut(&Range<K>, &V) -> bool>(
+		&mut self,
+		start: &K,
+		pred: F,
+	) -> Option<(Range<K>, V)> {
+		let node = self.find(start, pred)?;
+		Some(unsafe { self.remove_node(node) })
+	}
+
+	/// Removes the first entry, and returns it.
+	pub fn pop_first(&mut self) -> Option<(Range<K>, V)> {
+		let mut node = self.root?;
+		unsafe {
+			while let Some(l) = node.as_ref().left {
+				node = l;
+			}
+			Some(self.remove_node(node))
+		}
+	}
+
+	/// Returns an iterator over the entries whose ranges overlap `range`, sorted by the
+	/// beginning of their ranges.
+	pub fn overlapping<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V> {
+		let lo = range.start_bound().cloned();
+		let hi = range.end_bound().cloned();
+		Iter {
+			cur: self
+				.root
+				.and_then(|root| unsafe { first_after(root, lo.as_ref()) }),
+			lo,
+			hi,
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Same as [`Self::overlapping`], with mutable references to the values.
+	pub fn overlapping_mut<R: RangeBounds<K>>(&mut self, range: R) -> IterMut<'_, K, V> {
+		let Iter {
+			cur,
+			lo,
+			hi,
+			..
+		} = self.overlapping(range);
+		IterMut {
+			cur,
+			lo,
+			hi,
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Returns an iterator over all the entries, sorted by the beginning of their ranges.
+	pub fn iter(&self) -> Iter<'_, K, V> {
+		self.overlapping(..)
+	}
+
+	/// Returns a mutable iterator over all the entries, sorted by the beginning of their ranges.
+	pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+		self.overlapping_mut(..)
+	}
+
+	/// Removes all the entries.
+	pub fn clear(&mut self) {
+		let mut cur = self.root.take();
+		while let Some(mut node) = cur {
+			unsafe {
+				let n = node.as_mut();
+				if let Some(l) = n.left.take() {
+					cur = Some(l);
+				} else if let Some(r) = n.right.take() {
+					cur = Some(r);
+				} else {
+					cur = n.parent;
+					drop_node(node);
+				}
+			}
+		}
+		self.len = 0;
+	}
+
+	/// Checks the integrity of the tree.
+	///
+	/// If the tree is invalid, the function panics.
+	///
+	/// This function is meant to be used for debugging purposes.
+	#[cfg(test)]
+	fn check(&self) {
+		/// Checks the subtree whose root is `node`, returning its height and number of nodes.
+		unsafe fn check_node<K: Ord + Copy, V>(node: NonNull<Node<K, V>>) -> (u8, usize) {
+			let n = node.as_ref();
+			let mut max_end = n.range.end;
+			let mut count = 1;
+			let mut heights = [0; 2];
+			for (i, child) in [n.left, n.right].into_iter().enumerate() {
+				let Some(child) = child else {
+					continue;
+				};
+				let c = child.as_ref();
+				assert_eq!(c.parent, Some(node));
+				if i == 0 {
+					assert!(c.range.start <= n.range.start);
+				} else {
+					assert!(c.range.start >= n.range.start);
+				}
+				let (h, cnt) = check_node(child);
+				heights[i] = h;
+				count += cnt;
+				max_end = max(max_end, c.max_end);
+			}
+			assert!((heights[0] as i16 - heights[1] as i16).abs() <= 1);
+			assert_eq!(n.height, max(heights[0], heights[1]) + 1);
+			assert!(n.max_end == max_end);
+			(n.height, count)
+		}
+		let Some(root) = self.root else {
+			assert_eq!(self.len, 0);
+			return;
+		};
+		unsafe {
+			assert_eq!(root.as_ref().parent, None);
+			let (_, count) = check_node(root);
+			assert_eq!(count, self.len);
+		}
+	}
+}
+
+impl<K: Ord + Copy, V: TryClone<Error = E>, E: From<AllocError>> TryClone for IntervalTree<K, V> {
+	type Error = E;
+
+	fn try_clone(&self) -> Result<Self, Self::Error> {
+		Ok(self
+			.iter()
+			.map(|(range, value)| Ok((range.clone(), value.try_clone()?)))
+			.collect::<Result<CollectResult<Self>, Self::Error>>()?
+			.0?)
+	}
+}
+
+impl<K: Ord + Copy, V> FromIterator<(Range<K>, V)> for CollectResult<IntervalTree<K, V>> {
+	fn from_iter<I: IntoIterator<Item = (Range<K>, V)>>(iter: I) -> Self {
+		let res = (|| {
+			let mut tree = IntervalTree::new();
+			for (range, value) in iter {
+				tree.insert(range, value)?;
+			}
+			Ok(tree)
+		})();
+		Self(res)
+	}
+}
+
+impl<K: Ord + Copy + fmt::Debug, V: fmt::Debug> fmt::Debug for IntervalTree<K, V> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<K: Ord + Copy, V> Drop for IntervalTree<K, V> {
+	fn drop(&mut self) {
+		self.clear();
+	}
+}
+
+impl<K: Ord + Copy, V> IntoIterator for IntervalTree<K, V> {
+	type IntoIter = IntoIter<K, V>;
+	type Item = (Range<K>, V);
+
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter {
+			tree: self,
+		}
+	}
+}
+
+impl<'t, K: Ord + Copy, V> IntoIterator for &'t IntervalTree<K, V> {
+	type IntoIter = Iter<'t, K, V>;
+	type Item = (&'t Range<K>, &'t V);
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// Consuming iterator over an [`IntervalTree`].
+pub struct IntoIter<K: Ord + Copy, V> {
+	/// The tree being consumed.
+	tree: IntervalTree<K, V>,
+}
+
+impl<K: Ord + Copy, V> Iterator for IntoIter<K, V> {
+	type Item = (Range<K>, V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.tree.pop_first()
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.tree.len, Some(self.tree.len))
+	}
+}
+
+impl<K: Ord + Copy, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Ord + Copy, V> FusedIterator for IntoIter<K, V> {}
+
+/// Iterator over the entries of an [`IntervalTree`] overlapping a range.
+pub struct Iter<'t, K: Ord + Copy, V> {
+	/// The next node to return.
+	cur: Option<NonNull<Node<K, V>>>,
+	/// The lower bound of the range.
+	lo: Bound<K>,
+	/// The upper bound of the range.
+	hi: Bound<K>,
+
+	_phantom: PhantomData<&'t IntervalTree<K, V>>,
+}
+
+/// Advances the iterator whose current node is `cur`, returning the node to yield.
+///
+/// # Safety
+///
+/// The caller must ensure the pointer points to a valid node.
+unsafe fn advance<K: Ord, V>(
+	cur: &mut Option<NonNull<Node<K, V>>>,
+	lo: Bound<&K>,
+	hi: Bound<&K>,
+) -> Option<NonNull<Node<K, V>>> {
+	let node = (*cur)?;
+	// Entries are sorted, so none of the following ones can overlap
+	if !before_hi(&node.as_ref().range.start, hi) {
+		*cur = None;
+		return None;
+	}
+	*cur = next_after(node, lo);
+	Some(node)
+}
+
+impl<'t, K: Ord + Copy, V> Iterator for Iter<'t, K, V> {
+	type Item = (&'t Range<K>, &'t V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		unsafe {
+			let node = advance(&mut self.cur, self.lo.as_ref(), self.hi.as_ref())?;
+			let n = node.as_ref();
+			Some((&n.range, &n.value))
+		}
+	}
+}
+
+impl<K: Ord + Copy, V> FusedIterator for Iter<'_, K, V> {}
+
+/// Mutable iterator over the entries of an [`IntervalTree`] overlapping a range.
+pub struct IterMut<'t, K: Ord + Copy, V> {
+	/// The next node to return.
+	cur: Option<NonNull<Node<K, V>>>,
+	/// The lower bound of the range.
+	lo: Bound<K>,
+	/// The upper bound of the range.
+	hi: Bound<K>,
+
+	_phantom: PhantomData<&'t mut IntervalTree<K, V>>,
+}
+
+impl<'t, K: Ord + Copy, V> Iterator for IterMut<'t, K, V> {
+	type Item = (&'t Range<K>, &'t mut V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		unsafe {
+			let mut node = advance(&mut self.cur, self.lo.as_ref(), self.hi.as_ref())?;
+			let n = node.as_mut();
+			Some((&n.range, &mut n.value))
+		}
+	}
+}
+
+impl<K: Ord + Copy, V> FusedIterator for IterMut<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{collections::vec::Vec, math::pseudo_rand};
+
+	/// Returns the ranges of the entries yielded by `iter`.
+	fn ranges<'t, V: 't>(iter: impl Iterator<Item = (&'t Range<u32>, &'t V)>) -> Vec<Range<u32>> {
+		iter.map(|(r, _)| r.clone())
+			.collect::<CollectResult<Vec<_>>>()
+			.0
+			.unwrap()
+	}
+
+	#[test]
+	fn interval_tree_empty() {
+		let t = IntervalTree::<u32, ()>::new();
+		assert!(t.is_empty());
+		assert!(t.get(&0).is_none());
+		assert!(t.get_containing(0).is_none());
+		assert_eq!(t.iter().count(), 0);
+	}
+
+	#[test]
+	fn interval_tree_insert() {
+		let mut t = IntervalTree::new();
+		for i in 0..100 {
+			t.insert(i * 10..i * 10 + 5, i).unwrap();
+			t.check();
+		}
+		assert_eq!(t.len(), 100);
+		for i in 0..100 {
+			assert_eq!(t.get(&(i * 10)), Some(&i));
+			assert_eq!(t.get_containing(i * 10 + 4).map(|(_, v)| *v), Some(i));
+			assert!(t.get_containing(i * 10 + 5).is_none());
+		}
+		let starts: Vec<u32> = t
+			.iter()
+			.map(|(r, _)| r.start)
+			.collect::<CollectResult<_>>()
+			.0
+			.unwrap();
+		assert!(starts.is_sorted());
+	}
+
+	#[test]
+	fn interval_tree_overlapping() {
+		let mut t = IntervalTree::new();
+		t.insert(0..100, ()).unwrap();
+		t.insert(10..20, ()).unwrap();
+		t.insert(15..30, ()).unwrap();
+		t.insert(40..50, ()).unwrap();
+		t.insert(40..45, ()).unwrap();
+		t.check();
+		assert_eq!(ranges(t.overlapping(20..40)).as_slice(), &[0..100, 15..30]);
+		assert_eq!(
+			ranges(t.overlapping(20..=40)).as_slice(),
+			&[0..100, 15..30, 40..50, 40..45]
+		);
+		assert_eq!(ranges(t.overlapping(45..)).as_slice(), &[0..100, 40..50]);
+		assert_eq!(t.overlapping(100..).count(), 0);
+		assert_eq!(t.overlapping(..).count(), 5);
+	}
+
+	#[test]
+	fn interval_tree_remove() {
+		let mut t = IntervalTree::new();
+		for i in 0..100 {
+			t.insert(i..i + 1, i).unwrap();
+			t.insert(i..i + 2, i + 1000).unwrap();
+		}
+		for i in (0..100).step_by(2) {
+			assert_eq!(
+				t.remove_if(&i, |_, v| *v >= 1000),
+				Some((i..i + 2, i + 1000))
+			);
+			t.check();
+			assert_eq!(t.remove(&i), Some((i..i + 1, i)));
+			t.check();
+			assert!(t.remove(&i).is_none());
+		}
+		assert_eq!(t.len(), 100);
+		for i in (1..100).step_by(2) {
+			assert!(t.get(&i).is_some());
+		}
+	}
+
+	#[test]
+	fn interval_tree_replace() {
+		let mut t = IntervalTree::new();
+		t.insert(0..10, 0).unwrap();
+		t.insert(20..30, 1).unwrap();
+		assert_eq!(t.replace(20..100, 2).unwrap(), Some((20..30, 1)));
+		t.check();
+		assert_eq!(t.get_containing(50).map(|(_, v)| *v), Some(2));
+		assert_eq!(t.replace(50..60, 3).unwrap(), None);
+		assert_eq!(t.len(), 3);
+	}
+
+	#[test]
+	fn interval_tree_random() {
+		let mut t = IntervalTree::new();
+		let mut val = 1234;
+		for _ in 0..1000 {
+			val = pseudo_rand(val, 1664525, 1013904223, 0x1000);
+			t.insert(val..val + val % 64 + 1, ()).unwrap();
+		}
+		t.check();
+		for point in 0..0x1040 {
+			let expected = t.iter().filter(|(r, _)| r.contains(&point)).count();
+			assert_eq!(t.overlapping(point..=point).count(), expected);
+		}
+		while t.pop_first().is_some() {
+			if t.len() % 100 == 0 {
+				t.check();
+			}
+		}
+		assert!(t.is_empty());
+	}
+}
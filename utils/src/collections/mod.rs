@@ -23,6 +23,7 @@ pub mod btreemap;
 pub mod hashmap;
 pub mod hashset;
 pub mod id_allocator;
+pub mod interval_tree;
 pub mod list;
 pub mod path;
 pub mod string;
@@ -0,0 +1,118 @@
+/*
+ * Copyright 2024 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Bounds-checked memory functions, in the manner of `_FORTIFY_SOURCE`.
+//!
+//! Each function takes the size of the destination object in addition to the arguments of its
+//! unchecked counterpart. If the operation would write past the end of the object, the function
+//! panics instead of corrupting memory.
+
+use core::{hint::unlikely, ptr};
+
+/// Called when an operation would overflow its destination.
+#[cold]
+#[track_caller]
+fn chk_fail(n: usize, dst_size: usize) -> ! {
+	panic!("buffer overflow detected: writing {n} bytes to an object of {dst_size} bytes");
+}
+
+/// Copies `n` bytes from `src` to `dst`. The two regions must not overlap.
+///
+/// `dst_size` is the size of the object `dst` points to, in bytes. If `n` is greater, the
+/// function panics.
+///
+/// # Safety
+///
+/// The same requirements as [`ptr::copy_nonoverlapping`] apply, except for the size of the
+/// destination.
+#[inline]
+#[track_caller]
+pub unsafe fn memcpy_chk(dst: *mut u8, src: *const u8, n: usize, dst_size: usize) {
+	if unlikely(n > dst_size) {
+		chk_fail(n, dst_size);
+	}
+	ptr::copy_nonoverlapping(src, dst, n);
+}
+
+/// Copies `n` bytes from `src` to `dst`. The two regions may overlap.
+///
+/// `dst_size` is the size of the object `dst` points to, in bytes. If `n` is greater, the
+/// function panics.
+///
+/// # Safety
+///
+/// The same requirements as [`ptr::copy`] apply, except for the size of the destination.
+#[inline]
+#[track_caller]
+pub unsafe fn memmove_chk(dst: *mut u8, src: *const u8, n: usize, dst_size: usize) {
+	if unlikely(n > dst_size) {
+		chk_fail(n, dst_size);
+	}
+	ptr::copy(src, dst, n);
+}
+
+/// Sets `n` bytes at `dst` to `val`.
+///
+/// `dst_size` is the size of the object `dst` points to, in bytes. If `n` is greater, the
+/// function panics.
+///
+/// # Safety
+///
+/// The same requirements as [`ptr::write_bytes`] apply, except for the size of the destination.
+#[inline]
+#[track_caller]
+pub unsafe fn memset_chk(dst: *mut u8, val: u8, n: usize, dst_size: usize) {
+	if unlikely(n > dst_size) {
+		chk_fail(n, dst_size);
+	}
+	ptr::write_bytes(dst, val, n);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn memcpy_chk_in_bounds() {
+		let src = [1u8, 2, 3, 4];
+		let mut dst = [0u8; 4];
+		unsafe {
+			memcpy_chk(dst.as_mut_ptr(), src.as_ptr(), 3, dst.len());
+		}
+		assert_eq!(dst, [1, 2, 3, 0]);
+	}
+
+	#[test]
+	#[should_panic]
+	fn memcpy_chk_overflow() {
+		let src = [0u8; 8];
+		let mut dst = [0u8; 4];
+		unsafe {
+			memcpy_chk(dst.as_mut_ptr(), src.as_ptr(), src.len(), dst.len());
+		}
+	}
+
+	#[test]
+	#[should_panic]
+	fn memset_chk_overflow() {
+		let mut dst = [0u8; 4];
+		unsafe {
+			memset_chk(dst.as_mut_ptr(), 0xff, 5, dst.len());
+		}
+	}
+}
@@ -51,6 +51,7 @@ pub mod bytes;
 pub mod collections;
 pub mod cpio;
 pub mod errno;
+pub mod fortify;
 pub mod limits;
 pub mod math;
 pub mod ptr;